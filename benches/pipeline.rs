@@ -0,0 +1,96 @@
+//! Benchmarks for the per-pixel stages around inference: preprocessing,
+//! mask application, compositing and PNG encoding on a 24 MP image, the
+//! guided filter on a 12 MP image, and a whole run with and without
+//! `max_output_size` on the stub model in `tests/fixtures/models`.
+//!
+//! ```bash
+//! cargo bench --bench pipeline
+//! # Compare with the single-threaded loops
+//! cargo bench --bench pipeline --no-default-features --features ort
+//! ```
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use image::{DynamicImage, GrayImage, Luma};
+use removebg::{
+    apply_external_mask, pipeline, testing, Background, GuidedFilterOptions, Model, OutputEncoder, PngCompression, PngOptions, PngOutput,
+    RemoveBgOptions,
+};
+use std::hint::black_box;
+
+const WIDTH: u32 = 6000;
+const HEIGHT: u32 = 4000;
+
+fn image() -> DynamicImage {
+    DynamicImage::ImageRgb8(testing::synthetic_image(1, WIDTH, HEIGHT))
+}
+
+fn mask() -> DynamicImage {
+    let source = testing::synthetic_image(2, WIDTH, HEIGHT);
+    DynamicImage::ImageLuma8(GrayImage::from_fn(WIDTH, HEIGHT, |x, y| image::Luma([source.get_pixel(x, y)[0]])))
+}
+
+fn preprocess(c: &mut Criterion) {
+    let image = image();
+    c.bench_function("preprocess 24MP", |b| b.iter(|| pipeline::preprocess(black_box(&image), Model::U2net.spec())));
+}
+
+fn composite(c: &mut Criterion) {
+    let (image, mask) = (image(), mask());
+    let mut group = c.benchmark_group("apply mask 24MP");
+    group.sample_size(10);
+    let cases = [
+        ("transparent", RemoveBgOptions::default()),
+        ("premultiplied", RemoveBgOptions { premultiplied: true, ..Default::default() }),
+        ("white background", RemoveBgOptions { background: Background::Color([255, 255, 255]), ..Default::default() }),
+    ];
+    for (name, options) in cases {
+        group.bench_function(name, |b| b.iter(|| apply_external_mask(black_box(&image), &mask, &options).unwrap()));
+    }
+    group.finish();
+}
+
+fn png_encode(c: &mut Criterion) {
+    let image = image().into_rgba8();
+    let mut group = c.benchmark_group("png encode 24MP");
+    group.sample_size(10);
+    for compression in [PngCompression::Fast, PngCompression::Default] {
+        let encoder = PngOutput(PngOptions { compression, ..Default::default() });
+        let mut png = Vec::new();
+        group.bench_function(compression.to_string(), |b| {
+            b.iter(|| {
+                png.clear();
+                encoder.encode(black_box(&image), &mut png).unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+fn guided_filter(c: &mut Criterion) {
+    let image = DynamicImage::ImageRgb8(testing::synthetic_image(1, 4000, 3000));
+    let source = testing::synthetic_image(2, 4000, 3000);
+    let mask = pipeline::Gray16Image::from_fn(4000, 3000, |x, y| Luma([u16::from(source.get_pixel(x, y)[0]) * 257]));
+    let options = RemoveBgOptions { guided_filter: Some(GuidedFilterOptions::default()), ..Default::default() };
+    let mut group = c.benchmark_group("guided filter 12MP");
+    group.sample_size(10);
+    group.bench_function("radius 16", |b| {
+        b.iter_batched_ref(|| mask.clone(), |mask| pipeline::refine(mask, black_box(&image), &options).unwrap(), BatchSize::LargeInput)
+    });
+    group.finish();
+}
+
+fn max_output_size(c: &mut Criterion) {
+    let mut png = std::io::Cursor::new(Vec::new());
+    image().write_to(&mut png, image::ImageFormat::Png).unwrap();
+    let mut group = c.benchmark_group("whole run 24MP");
+    group.sample_size(10);
+    for (name, max_output_size) in [("full resolution", None), ("max output size 1500", Some(1500))] {
+        let options = RemoveBgOptions { max_output_size, ..Default::default() };
+        let remover = testing::TestModel::Brightness.remover(options).expect("stub model loads");
+        group.bench_function(name, |b| b.iter(|| remover.process_bytes(black_box(png.get_ref())).unwrap()));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, preprocess, composite, png_encode, guided_filter, max_output_size);
+criterion_main!(benches);
@@ -0,0 +1,68 @@
+//! Build script: locates the model compiled in by the `embed-u2netp` feature
+//! and generates the C header for the `capi` feature.
+
+use std::path::PathBuf;
+
+/// Variable pointing at the u2netp file to embed, relative to the crate root
+/// unless absolute.
+const EMBED_ENV: &str = "REMOVEBG_EMBED_U2NETP";
+
+/// Where the file is expected when the variable is not set.
+const DEFAULT_PATH: &str = "models/u2netp.onnx";
+
+/// Generated C header, relative to the crate root.
+#[cfg(feature = "capi")]
+const HEADER_PATH: &str = "include/removebg.h";
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    embed_model();
+    #[cfg(feature = "capi")]
+    write_header();
+}
+
+fn embed_model() {
+    println!("cargo:rerun-if-env-changed={}", EMBED_ENV);
+    if std::env::var_os("CARGO_FEATURE_EMBED_U2NETP").is_none() {
+        return;
+    }
+
+    let root = PathBuf::from(std::env::var_os("CARGO_MANIFEST_DIR").expect("set by cargo"));
+    let path = root.join(std::env::var_os(EMBED_ENV).map_or(PathBuf::from(DEFAULT_PATH), PathBuf::from));
+    if !path.is_file() {
+        panic!(
+            "the `embed-u2netp` feature needs the u2netp model at {}; download it from \
+             https://github.com/danielgatis/rembg/releases/download/v0.0.0/u2netp.onnx \
+             or set {} to its path",
+            path.display(),
+            EMBED_ENV
+        );
+    }
+
+    println!("cargo:rerun-if-changed={}", path.display());
+    println!("cargo:rustc-env=REMOVEBG_EMBEDDED_U2NETP={}", path.display());
+}
+
+/// Regenerate the C header from `src/ffi.rs`; the file is only rewritten
+/// when its contents change.
+#[cfg(feature = "capi")]
+fn write_header() {
+    let root = PathBuf::from(std::env::var_os("CARGO_MANIFEST_DIR").expect("set by cargo"));
+    let source = root.join("src/ffi.rs");
+    println!("cargo:rerun-if-changed={}", source.display());
+
+    let config = cbindgen::Config {
+        language: cbindgen::Language::C,
+        header: Some("/* Generated by cbindgen from src/ffi.rs; do not edit. */".into()),
+        include_guard: Some("REMOVEBG_H".into()),
+        cpp_compat: true,
+        usize_is_size_t: true,
+        ..Default::default()
+    };
+    cbindgen::Builder::new()
+        .with_config(config)
+        .with_src(&source)
+        .generate()
+        .expect("failed to generate the C header")
+        .write_to_file(root.join(HEADER_PATH));
+}
@@ -0,0 +1,51 @@
+//! Remove backgrounds from several images concurrently with the async API.
+//!
+//! Run with:
+//! ```text
+//! cargo run --example async_concurrent --features async -- a.jpg b.jpg c.jpg
+//! ```
+//!
+//! All tasks start against a cold process; only one of them downloads the
+//! model while the others wait for it.
+
+use removebg::{remove_background_async, RemoveBgOptions};
+
+#[tokio::main]
+async fn main() {
+    let inputs: Vec<String> = std::env::args().skip(1).collect();
+    if inputs.is_empty() {
+        eprintln!("Usage: async_concurrent <IMAGE>...");
+        std::process::exit(2);
+    }
+
+    let options = RemoveBgOptions::default();
+    let tasks: Vec<_> = inputs
+        .into_iter()
+        .map(|input| {
+            let options = options.clone();
+            tokio::spawn(async move {
+                let result = remove_background_async(&input, None, &options).await;
+                (input, result)
+            })
+        })
+        .collect();
+
+    let mut failed = false;
+    for task in tasks {
+        match task.await {
+            Ok((input, Ok(output))) => println!("{} -> {}", input, output.display()),
+            Ok((input, Err(e))) => {
+                eprintln!("{}: {}", input, e);
+                failed = true;
+            }
+            Err(e) => {
+                eprintln!("task failed: {}", e);
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        std::process::exit(1);
+    }
+}
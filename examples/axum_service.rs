@@ -0,0 +1,66 @@
+//! A bytes-in, bytes-out axum service around one shared model.
+//!
+//! Run with:
+//! ```text
+//! cargo run --release --example axum_service --features axum -- 127.0.0.1:8080
+//! curl --data-binary @photo.jpg http://127.0.0.1:8080/cutout -o cutout.png
+//! ```
+//!
+//! The remover is the router's state, shared by every request. Inference
+//! blocks, so the handler runs it on tokio's blocking pool instead of the
+//! async workers. `removebg serve` is the full server, with limits and
+//! output options.
+
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::Router;
+use removebg::{BackgroundRemover, RemoveBgError, RemoveBgOptions};
+use std::sync::Arc;
+
+/// The cutout of a posted image as PNG, or an error message.
+pub async fn cutout(State(remover): State<Arc<BackgroundRemover>>, body: Bytes) -> Response {
+    let result = tokio::task::spawn_blocking(move || remover.process_bytes(&body)).await;
+    match result {
+        Ok(Ok(png)) => ([(header::CONTENT_TYPE, "image/png")], png).into_response(),
+        Ok(Err(e)) => {
+            let status = match e.root() {
+                RemoveBgError::ImageError(_) | RemoveBgError::ImageTooSmall { .. } | RemoveBgError::UnsupportedFormat { .. } => {
+                    StatusCode::BAD_REQUEST
+                }
+                RemoveBgError::ImageTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            (status, format!("{}\n", e)).into_response()
+        }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("{}\n", e)).into_response(),
+    }
+}
+
+/// The service: `POST /cutout` with an image as the body.
+pub fn app(remover: Arc<BackgroundRemover>) -> Router {
+    Router::new().route("/cutout", post(cutout)).with_state(remover)
+}
+
+#[tokio::main]
+async fn main() {
+    let address = std::env::args().nth(1).unwrap_or_else(|| "127.0.0.1:8080".into());
+    let remover = match BackgroundRemover::new(RemoveBgOptions::default()) {
+        Ok(remover) => Arc::new(remover),
+        Err(e) => {
+            eprintln!("Failed to load the model: {}", e);
+            std::process::exit(e.exit_code());
+        }
+    };
+    let listener = tokio::net::TcpListener::bind(&address).await.unwrap_or_else(|e| {
+        eprintln!("Failed to listen on {}: {}", address, e);
+        std::process::exit(1);
+    });
+    println!("Listening on http://{}", address);
+    if let Err(e) = axum::serve(listener, app(remover)).await {
+        eprintln!("Server failed: {}", e);
+        std::process::exit(1);
+    }
+}
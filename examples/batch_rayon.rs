@@ -0,0 +1,62 @@
+//! Remove backgrounds from many files on a thread pool that shares one
+//! loaded model.
+//!
+//! Run with:
+//! ```text
+//! cargo run --release --example batch_rayon -- OUTPUT_DIR a.jpg b.jpg c.jpg
+//! ```
+//!
+//! A [`BackgroundRemover`] loads the model once and is `Sync`, so every
+//! worker of the pool runs it through the same reference instead of loading
+//! a model of its own.
+
+use rayon::prelude::*;
+use removebg::{BackgroundRemover, RemovalReport, RemoveBgOptions, Result};
+use std::path::{Path, PathBuf};
+
+/// Process `inputs` with `remover` on `threads` threads, writing the outputs
+/// to `output_dir`, and return one result per input, in order.
+pub fn remove_all(remover: &BackgroundRemover, inputs: &[PathBuf], output_dir: &Path, threads: usize) -> Vec<Result<RemovalReport>> {
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().expect("thread pool");
+    pool.install(|| {
+        inputs
+            .par_iter()
+            .map(|input| {
+                let name = Path::new(input.file_stem().unwrap_or_default()).with_extension("png");
+                remover.process_file(input, Some(&output_dir.join(name)))
+            })
+            .collect()
+    })
+}
+
+fn main() {
+    let args: Vec<PathBuf> = std::env::args_os().skip(1).map(PathBuf::from).collect();
+    let [output_dir, inputs @ ..] = &args[..] else {
+        eprintln!("Usage: batch_rayon <OUTPUT_DIR> <IMAGE>...");
+        std::process::exit(2);
+    };
+    if let Err(e) = std::fs::create_dir_all(output_dir) {
+        eprintln!("{}: {}", output_dir.display(), e);
+        std::process::exit(1);
+    }
+
+    let remover = match BackgroundRemover::new(RemoveBgOptions { overwrite: true, ..Default::default() }) {
+        Ok(remover) => remover,
+        Err(e) => {
+            eprintln!("Failed to load the model: {}", e);
+            std::process::exit(e.exit_code());
+        }
+    };
+    let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+    let mut failed = false;
+    for (input, result) in inputs.iter().zip(remove_all(&remover, inputs, output_dir, threads)) {
+        match result {
+            Ok(report) => println!("{} -> {}", input.display(), report.output_path.display()),
+            Err(e) => {
+                eprintln!("{}: {}", input.display(), e);
+                failed = true;
+            }
+        }
+    }
+    std::process::exit(if failed { 5 } else { 0 });
+}
@@ -0,0 +1,78 @@
+//! Compare per-image and batched inference on synthetic thumbnails.
+//!
+//! Run with:
+//! ```text
+//! cargo run --release --example batch_throughput -- [COUNT] [BATCH_SIZE]
+//! ```
+//!
+//! Processes COUNT (default 32) generated 256x256 images once one at a time
+//! and once packed BATCH_SIZE (default 8) per model run, and prints the
+//! throughput of both.
+
+use std::time::Instant;
+
+use image::{DynamicImage, Rgb, RgbImage};
+use removebg::{BackgroundRemover, RemoveBgOptions};
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let count = parse_arg(args.next(), 32);
+    let batch_size = parse_arg(args.next(), 8);
+
+    let images: Vec<DynamicImage> = (0..count)
+        .map(|i| {
+            let shade = (i * 37 % 200) as u8;
+            DynamicImage::ImageRgb8(RgbImage::from_fn(256, 256, |x, y| {
+                let dx = x as i32 - 128;
+                let dy = y as i32 - 128;
+                if dx * dx + dy * dy < 80 * 80 {
+                    Rgb([220, shade, 40])
+                } else {
+                    Rgb([shade, 230, 240])
+                }
+            }))
+        })
+        .collect();
+
+    for batch_size in [1, batch_size] {
+        let options = RemoveBgOptions {
+            batch_size,
+            ..Default::default()
+        };
+        let remover = match BackgroundRemover::new(options) {
+            Ok(remover) => remover,
+            Err(e) => {
+                eprintln!("failed to load the model: {}", e);
+                std::process::exit(1);
+            }
+        };
+
+        // Warm up so the first run doesn't pay for session initialization.
+        let _ = remover.process_images(&images[..1]);
+
+        let start = Instant::now();
+        let results = remover.process_images(&images);
+        let elapsed = start.elapsed();
+
+        let failed = results.iter().filter(|r| r.is_err()).count();
+        println!(
+            "batch size {:>3}: {} images in {:.2?} ({:.1} images/s, {} failed)",
+            batch_size,
+            images.len(),
+            elapsed,
+            images.len() as f64 / elapsed.as_secs_f64(),
+            failed
+        );
+    }
+}
+
+fn parse_arg(arg: Option<String>, default: usize) -> usize {
+    match arg.map(|a| a.parse::<usize>()) {
+        None => default,
+        Some(Ok(n)) if n > 0 => n,
+        Some(_) => {
+            eprintln!("Usage: batch_throughput [COUNT] [BATCH_SIZE]");
+            std::process::exit(2);
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! Replace one stage of the pipeline: fade the mask out toward the bottom of
+//! the image, as for a product photo standing on a reflective surface.
+//!
+//! Run with:
+//! ```text
+//! cargo run --example custom_postprocess -- photo.jpg faded.png [FRACTION]
+//! ```
+//!
+//! The model's mask is taken from [`pipeline::infer`] and upscaled as usual;
+//! the fade runs on the full-resolution mask before the regular refinement
+//! and compositing, so every other option still applies.
+
+use image::DynamicImage;
+use removebg::pipeline::{self, Gray16Image};
+use removebg::{BackgroundRemover, RemoveBgOptions, Result};
+
+/// Fade `mask` linearly to transparent over its bottom `fraction` of rows.
+pub fn fade_bottom(mask: &mut Gray16Image, fraction: f32) {
+    let height = mask.height();
+    let start = height - (height as f32 * fraction.clamp(0.0, 1.0)).round() as u32;
+    for (y, row) in mask.enumerate_rows_mut() {
+        if y >= start {
+            let keep = (height - y) as f32 / (height - start + 1) as f32;
+            for (_, _, value) in row {
+                value[0] = (value[0] as f32 * keep).round() as u16;
+            }
+        }
+    }
+}
+
+/// Cut out the subject of `image` with its mask faded over the bottom
+/// `fraction` of the image.
+pub fn faded_cutout(remover: &BackgroundRemover, image: &DynamicImage, fraction: f32) -> Result<DynamicImage> {
+    let options = remover.options();
+    let mask = pipeline::infer(remover, pipeline::preprocess(image, options.model.spec()))?;
+    let mut mask = pipeline::upscale_mask(&mask, (image.width(), image.height()), options.mask_filter);
+    fade_bottom(&mut mask, fraction);
+    pipeline::refine(&mut mask, image, options)?;
+    pipeline::composite(image, &mask, options)
+}
+
+fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (input, output) = match &args[..] {
+        [input, output] | [input, output, _] => (input, output),
+        _ => {
+            eprintln!("Usage: custom_postprocess <IMAGE> <OUTPUT> [FRACTION]");
+            std::process::exit(2);
+        }
+    };
+    let fraction = match args.get(2) {
+        Some(fraction) => fraction.parse()?,
+        None => 0.25,
+    };
+
+    let remover = BackgroundRemover::new(RemoveBgOptions::default())?;
+    let cutout = faded_cutout(&remover, &image::open(input)?, fraction)?;
+    cutout.save(output)?;
+    println!("Saved to: {}", output);
+    Ok(())
+}
@@ -0,0 +1,103 @@
+//! An interactive threshold slider over a cutout, in an egui window.
+//!
+//! Run with:
+//! ```text
+//! cargo run --release --example egui_preview --features egui -- photo.jpg
+//! ```
+//!
+//! The model runs once when the window opens. Moving the slider only
+//! re-thresholds the kept mask and composites the image again, which takes
+//! milliseconds, so the preview follows the slider.
+
+use eframe::egui;
+use image::DynamicImage;
+use removebg::pipeline::{self, Gray16Image};
+use removebg::{BackgroundRemover, MaskOp, RemoveBgOptions, Result};
+
+/// The model's mask of one image at full resolution, kept between previews.
+pub struct CachedMask {
+    image: DynamicImage,
+    mask: Gray16Image,
+}
+
+impl CachedMask {
+    /// Run the model of `remover` on `image`.
+    pub fn new(remover: &BackgroundRemover, image: DynamicImage) -> Result<Self> {
+        let options = remover.options();
+        let mask = pipeline::infer(remover, pipeline::preprocess(&image, options.model.spec()))?;
+        let mask = pipeline::upscale_mask(&mask, (image.width(), image.height()), options.mask_filter);
+        Ok(CachedMask { image, mask })
+    }
+
+    /// The cutout with the mask thresholded at `threshold`, as egui shows it.
+    pub fn preview(&self, threshold: f32, options: &RemoveBgOptions) -> Result<egui::ColorImage> {
+        let options = RemoveBgOptions { mask_ops: vec![MaskOp::Threshold(threshold)], ..options.clone() };
+        let mut mask = self.mask.clone();
+        pipeline::refine(&mut mask, &self.image, &options)?;
+        let cutout = pipeline::composite(&self.image, &mask, &options)?.into_rgba8();
+        let size = [cutout.width() as usize, cutout.height() as usize];
+        Ok(egui::ColorImage::from_rgba_unmultiplied(size, cutout.as_raw()))
+    }
+}
+
+struct Preview {
+    cached: CachedMask,
+    options: RemoveBgOptions,
+    threshold: f32,
+    texture: Option<egui::TextureHandle>,
+    error: Option<String>,
+}
+
+impl Preview {
+    /// Composite the preview at the current threshold into the texture.
+    fn refresh(&mut self, ctx: &egui::Context) {
+        match self.cached.preview(self.threshold, &self.options) {
+            Ok(image) => {
+                self.texture = Some(ctx.load_texture("cutout", image, egui::TextureOptions::LINEAR));
+                self.error = None;
+            }
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+}
+
+impl eframe::App for Preview {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        if self.texture.is_none() && self.error.is_none() {
+            self.refresh(ctx);
+        }
+        egui::TopBottomPanel::top("controls").show(ctx, |ui| {
+            let slider = ui.add(egui::Slider::new(&mut self.threshold, 0.0..=1.0).text("threshold"));
+            if slider.changed() {
+                self.refresh(ctx);
+            }
+            if let Some(error) = &self.error {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+        });
+        egui::CentralPanel::default().show(ctx, |ui| {
+            if let Some(texture) = &self.texture {
+                ui.add(egui::Image::new(texture).shrink_to_fit());
+            }
+        });
+    }
+}
+
+fn main() -> eframe::Result {
+    let Some(input) = std::env::args().nth(1) else {
+        eprintln!("Usage: egui_preview <image>");
+        std::process::exit(2);
+    };
+    let loaded = image::open(&input).map_err(removebg::RemoveBgError::from).and_then(|image| {
+        let remover = BackgroundRemover::new(RemoveBgOptions::default())?;
+        let cached = CachedMask::new(&remover, image)?;
+        Ok((cached, remover.options().clone()))
+    });
+    let (cached, options) = loaded.unwrap_or_else(|e| {
+        eprintln!("{}: {}", input, e);
+        std::process::exit(e.exit_code());
+    });
+
+    let preview = Preview { cached, options, threshold: 0.5, texture: None, error: None };
+    eframe::run_native("removebg preview", eframe::NativeOptions::default(), Box::new(|_| Ok(Box::new(preview))))
+}
@@ -0,0 +1,83 @@
+//! A bytes-in, bytes-out HTTP endpoint around one shared model.
+//!
+//! Run with:
+//! ```text
+//! cargo run --release --example http_service --features server -- 127.0.0.1:8080
+//! curl --data-binary @photo.jpg http://127.0.0.1:8080/ -o cutout.png
+//! ```
+//!
+//! The handler is a plain function from request to response, so it carries
+//! over to other HTTP frameworks: in an axum service it becomes a handler
+//! taking `State<Arc<BackgroundRemover>>` and `Bytes`, run through
+//! `tokio::task::spawn_blocking` since inference blocks. `removebg serve`
+//! is the full server, with limits and output options.
+
+use removebg::{BackgroundRemover, RemoveBgError, RemoveBgOptions};
+use std::sync::Arc;
+use tiny_http::{Header, Response, Server};
+
+/// Status, content type and body of a response.
+pub struct Reply {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+/// Answer a `method` request with `body`: the cutout of a posted image as
+/// PNG, or an error message.
+pub fn handle(remover: &BackgroundRemover, method: &str, body: &[u8]) -> Reply {
+    if method != "POST" {
+        return Reply { status: 405, content_type: "text/plain", body: b"POST an image\n".to_vec() };
+    }
+    match remover.process_bytes(body) {
+        Ok(png) => Reply { status: 200, content_type: "image/png", body: png },
+        Err(e) => {
+            let status = match e.root() {
+                RemoveBgError::ImageError(_) | RemoveBgError::ImageTooSmall { .. } | RemoveBgError::UnsupportedFormat { .. } => 400,
+                RemoveBgError::ImageTooLarge { .. } => 413,
+                _ => 500,
+            };
+            Reply { status, content_type: "text/plain", body: format!("{}\n", e).into_bytes() }
+        }
+    }
+}
+
+fn main() {
+    let address = std::env::args().nth(1).unwrap_or_else(|| "127.0.0.1:8080".into());
+    let remover = match BackgroundRemover::new(RemoveBgOptions::default()) {
+        Ok(remover) => Arc::new(remover),
+        Err(e) => {
+            eprintln!("Failed to load the model: {}", e);
+            std::process::exit(e.exit_code());
+        }
+    };
+    let server = Arc::new(Server::http(&address).unwrap_or_else(|e| {
+        eprintln!("Failed to listen on {}: {}", address, e);
+        std::process::exit(1);
+    }));
+    println!("Listening on http://{}", address);
+
+    // A few workers share the server and the model
+    let workers: Vec<_> = (0..4)
+        .map(|_| {
+            let (server, remover) = (Arc::clone(&server), Arc::clone(&remover));
+            std::thread::spawn(move || {
+                for mut request in server.incoming_requests() {
+                    let mut body = Vec::new();
+                    let reply = match request.as_reader().read_to_end(&mut body) {
+                        Ok(_) => handle(&remover, &request.method().to_string(), &body),
+                        Err(e) => Reply { status: 400, content_type: "text/plain", body: format!("{}\n", e).into_bytes() },
+                    };
+                    let header = Header::from_bytes("Content-Type", reply.content_type).expect("static header is valid");
+                    let response = Response::from_data(reply.body).with_status_code(reply.status).with_header(header);
+                    if let Err(e) = request.respond(response) {
+                        eprintln!("Failed to send response: {}", e);
+                    }
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        let _ = worker.join();
+    }
+}
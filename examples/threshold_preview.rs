@@ -0,0 +1,79 @@
+//! Try mask thresholds interactively without running the model again.
+//!
+//! Run with:
+//! ```text
+//! cargo run --example threshold_preview -- photo.jpg preview.png
+//! ```
+//!
+//! The model runs once; each threshold typed on stdin (0 to 1, empty to
+//! stop) is applied to the kept mask and written to the preview file, which
+//! an image viewer that reloads on change shows right away. A GUI slider
+//! works the same way: keep the mask from [`pipeline::infer`], and
+//! re-threshold and composite it on every change.
+
+use image::DynamicImage;
+use removebg::pipeline::{self, Gray16Image};
+use removebg::{BackgroundRemover, MaskOp, RemoveBgOptions, Result};
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+/// The model's mask of one image at full resolution, kept between previews.
+pub struct CachedMask {
+    image: DynamicImage,
+    mask: Gray16Image,
+}
+
+impl CachedMask {
+    /// Run the model of `remover` on `image`.
+    pub fn new(remover: &BackgroundRemover, image: DynamicImage) -> Result<Self> {
+        let options = remover.options();
+        let mask = pipeline::infer(remover, pipeline::preprocess(&image, options.model.spec()))?;
+        let mask = pipeline::upscale_mask(&mask, (image.width(), image.height()), options.mask_filter);
+        Ok(CachedMask { image, mask })
+    }
+
+    /// The cutout with the mask thresholded at `threshold`.
+    pub fn preview(&self, threshold: f32, options: &RemoveBgOptions) -> Result<DynamicImage> {
+        let options = RemoveBgOptions { mask_ops: vec![MaskOp::Threshold(threshold)], ..options.clone() };
+        let mut mask = self.mask.clone();
+        pipeline::refine(&mut mask, &self.image, &options)?;
+        pipeline::composite(&self.image, &mask, &options)
+    }
+}
+
+/// Write a preview to `output` for each threshold read from `thresholds`,
+/// one per line, until an empty line or the end of input, and return how
+/// many were written. Lines that aren't thresholds are reported on `log`.
+pub fn run(cached: &CachedMask, options: &RemoveBgOptions, thresholds: impl BufRead, output: &Path, mut log: impl Write) -> Result<usize> {
+    let mut written = 0;
+    for line in thresholds.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        match line.parse::<f32>() {
+            Ok(threshold) if (0.0..=1.0).contains(&threshold) => {
+                cached.preview(threshold, options)?.save(output)?;
+                written += 1;
+                writeln!(log, "threshold {}: {}", threshold, output.display())?;
+            }
+            _ => writeln!(log, "not a threshold from 0 to 1: {}", line)?,
+        }
+    }
+    Ok(written)
+}
+
+fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [input, output] = &args[..] else {
+        eprintln!("Usage: threshold_preview <IMAGE> <PREVIEW>");
+        std::process::exit(2);
+    };
+
+    let remover = BackgroundRemover::new(RemoveBgOptions::default())?;
+    let cached = CachedMask::new(&remover, image::open(input)?)?;
+    eprintln!("Threshold (0-1, empty to stop):");
+    run(&cached, remover.options(), std::io::stdin().lock(), Path::new(output), std::io::stderr())?;
+    Ok(())
+}
@@ -0,0 +1,121 @@
+//! Print the tracing spans of one background removal as an indented tree
+//! with the time each stage took.
+//!
+//! Run with:
+//! ```text
+//! cargo run --example tracing_spans --features tracing -- photo.jpg
+//! ```
+//!
+//! Applications usually install `tracing-subscriber` instead of a subscriber
+//! of their own; its fmt layer prints the same spans, and with its default
+//! `tracing-log` feature the crate's log messages too:
+//!
+//! ```text
+//! tracing_subscriber::fmt()
+//!     .with_env_filter("removebg=info")
+//!     .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
+//!     .init();
+//! ```
+//!
+//! An OpenTelemetry exporter (`tracing-opentelemetry`) layered onto the same
+//! registry sends the spans to a collector instead.
+
+use removebg::{remove_background_detailed, RemoveBgOptions};
+use std::collections::HashMap;
+use std::fmt::{self, Write as _};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+/// Span fields and events as `name=value` pairs.
+struct Fields(String);
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let _ = write!(self.0, " {}={:?}", field.name(), value);
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        let _ = write!(self.0, " {}={}", field.name(), value);
+    }
+}
+
+struct Open {
+    depth: usize,
+    started: Instant,
+}
+
+/// Prints each span when it closes, indented by its depth, and each event
+/// inside the span it happened in.
+#[derive(Default)]
+struct TreePrinter {
+    next_id: AtomicU64,
+    spans: Mutex<HashMap<u64, Open>>,
+    stack: Mutex<Vec<u64>>,
+}
+
+impl TreePrinter {
+    fn depth(&self) -> usize {
+        self.stack.lock().unwrap().len()
+    }
+}
+
+impl Subscriber for TreePrinter {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.target().starts_with("removebg")
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut fields = Fields(String::new());
+        span.record(&mut fields);
+        let depth = self.depth();
+        eprintln!("{}> {}{}", "  ".repeat(depth), span.metadata().name(), fields.0);
+        self.spans.lock().unwrap().insert(id, Open { depth, started: Instant::now() });
+        Id::from_u64(id)
+    }
+
+    fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = Fields(String::new());
+        event.record(&mut fields);
+        eprintln!("{}* {}{}", "  ".repeat(self.depth()), event.metadata().name(), fields.0);
+    }
+
+    fn enter(&self, span: &Id) {
+        self.stack.lock().unwrap().push(span.into_u64());
+    }
+
+    fn exit(&self, _span: &Id) {
+        self.stack.lock().unwrap().pop();
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        if let Some(open) = self.spans.lock().unwrap().remove(&span.into_u64()) {
+            eprintln!("{}< {:?}", "  ".repeat(open.depth), open.started.elapsed());
+        }
+        true
+    }
+}
+
+fn main() {
+    let Some(input) = std::env::args().nth(1) else {
+        eprintln!("Usage: tracing_spans <IMAGE>");
+        std::process::exit(2);
+    };
+    tracing::subscriber::set_global_default(TreePrinter::default()).expect("no other subscriber is installed");
+
+    match remove_background_detailed(&input, None, &RemoveBgOptions::default()) {
+        Ok(report) => println!("{} -> {}", input, report.output_path.display()),
+        Err(e) => {
+            eprintln!("{}: {}", input, e);
+            std::process::exit(e.exit_code());
+        }
+    }
+}
@@ -0,0 +1,49 @@
+//! Arbitrary bytes through the in-memory pipeline of
+//! [`remove_background_from_bytes`](removebg::remove_background_from_bytes),
+//! with the model stubbed out so only decoding, mask refinement and encoding
+//! run. Every input must give `Ok` or `Err`; a panic is a crash.
+//!
+//! The first byte picks the output format and background, so the encoders
+//! see the decoded images too.
+
+#![no_main]
+
+use image::{DynamicImage, Luma};
+use libfuzzer_sys::fuzz_target;
+use removebg::pipeline::Gray16Image;
+use removebg::{Background, BackgroundRemover, DecodeLimits, OutputFormat, RemoveBgOptions, Result, SegmentationModel};
+
+/// Keeps the brighter half of the image, so masks have edges to refine.
+struct LumaModel;
+
+impl SegmentationModel for LumaModel {
+    fn predict_mask(&self, image: &DynamicImage) -> Result<Gray16Image> {
+        let luma = image.thumbnail(64, 64).into_luma16();
+        Ok(Gray16Image::from_fn(luma.width(), luma.height(), |x, y| {
+            Luma([if luma.get_pixel(x, y)[0] > 32768 { 65535 } else { 0 }])
+        }))
+    }
+}
+
+const FORMATS: [OutputFormat; 6] =
+    [OutputFormat::Png, OutputFormat::WebP, OutputFormat::Tiff, OutputFormat::Bmp, OutputFormat::Jpeg, OutputFormat::Gif];
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&selector, data)) = data.split_first() else {
+        return;
+    };
+    let format = FORMATS[selector as usize % FORMATS.len()];
+    let background = match selector / 8 % 3 {
+        0 if format.supports_alpha() => Background::Transparent,
+        1 => Background::Auto,
+        _ => Background::Color([255, 255, 255]),
+    };
+    let options = RemoveBgOptions {
+        format: Some(format),
+        background,
+        // Keep decodes small so the fuzzer explores formats, not large images
+        limits: DecodeLimits { max_pixels: Some(1 << 20), max_memory: Some(64 << 20), ..Default::default() },
+        ..Default::default()
+    };
+    let _ = BackgroundRemover::with_model(LumaModel, options).process_bytes(data);
+});
@@ -0,0 +1,294 @@
+/* Generated by cbindgen from src/ffi.rs; do not edit. */
+
+#ifndef REMOVEBG_H
+#define REMOVEBG_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * The call succeeded.
+ */
+#define REMOVEBG_OK 0
+
+/**
+ * The input file does not exist.
+ */
+#define REMOVEBG_ERROR_FILE_NOT_FOUND 1
+
+/**
+ * The input path is not a file.
+ */
+#define REMOVEBG_ERROR_NOT_A_FILE 2
+
+/**
+ * Reading or writing a file failed.
+ */
+#define REMOVEBG_ERROR_IO 3
+
+/**
+ * The image could not be decoded or encoded, or lacks the selected page.
+ */
+#define REMOVEBG_ERROR_IMAGE 4
+
+/**
+ * The image exceeds the pixel limit.
+ */
+#define REMOVEBG_ERROR_IMAGE_TOO_LARGE 5
+
+/**
+ * Model inference failed.
+ */
+#define REMOVEBG_ERROR_MODEL 6
+
+/**
+ * A remote input could not be downloaded, or an object store input or
+ * output could not be read or written.
+ */
+#define REMOVEBG_ERROR_DOWNLOAD 7
+
+/**
+ * The model could not be downloaded or loaded.
+ */
+#define REMOVEBG_ERROR_MODEL_INIT 8
+
+/**
+ * An external mask cannot be applied.
+ */
+#define REMOVEBG_ERROR_INVALID_MASK 9
+
+/**
+ * The output format cannot be produced.
+ */
+#define REMOVEBG_ERROR_INVALID_OUTPUT_FORMAT 10
+
+/**
+ * The output file exists and overwriting is off.
+ */
+#define REMOVEBG_ERROR_OUTPUT_EXISTS 11
+
+/**
+ * The output directory does not exist.
+ */
+#define REMOVEBG_ERROR_OUTPUT_DIRECTORY_MISSING 12
+
+/**
+ * The mask's confidence is below the configured minimum, or the input
+ * looks like an earlier cutout and reprocessing is refused.
+ */
+#define REMOVEBG_ERROR_LOW_CONFIDENCE 13
+
+/**
+ * The operation was cancelled.
+ */
+#define REMOVEBG_ERROR_CANCELLED 14
+
+/**
+ * Any other processing failure.
+ */
+#define REMOVEBG_ERROR_PROCESSING 15
+
+/**
+ * The input is not an image in a supported format.
+ */
+#define REMOVEBG_ERROR_UNSUPPORTED_FORMAT 16
+
+/**
+ * The input image is smaller than 2x2 pixels.
+ */
+#define REMOVEBG_ERROR_IMAGE_TOO_SMALL 17
+
+/**
+ * Processing took longer than the configured timeout.
+ */
+#define REMOVEBG_ERROR_TIMED_OUT 18
+
+/**
+ * A raw pixel buffer's dimensions, stride or length don't fit together.
+ */
+#define REMOVEBG_ERROR_INVALID_BUFFER 19
+
+/**
+ * There isn't enough free disk space for the model download or the output.
+ */
+#define REMOVEBG_ERROR_INSUFFICIENT_DISK_SPACE 20
+
+/**
+ * Processing the image would exceed the memory budget.
+ */
+#define REMOVEBG_ERROR_MEMORY_BUDGET_EXCEEDED 21
+
+/**
+ * Raw pixels in the order red, green, blue.
+ */
+#define REMOVEBG_PIXEL_RGB8 0
+
+/**
+ * Raw pixels in the order red, green, blue, alpha.
+ */
+#define REMOVEBG_PIXEL_RGBA8 1
+
+/**
+ * Raw pixels in the order blue, green, red.
+ */
+#define REMOVEBG_PIXEL_BGR8 2
+
+/**
+ * Raw pixels in the order blue, green, red, alpha.
+ */
+#define REMOVEBG_PIXEL_BGRA8 3
+
+/**
+ * A required pointer was NULL or a string was not valid UTF-8.
+ */
+#define REMOVEBG_ERROR_INVALID_ARGUMENT -1
+
+/**
+ * removebg panicked; the handle should be freed.
+ */
+#define REMOVEBG_ERROR_PANIC -2
+
+/**
+ * A loaded model, created by [`removebg_init`].
+ */
+typedef struct RemoveBgHandle RemoveBgHandle;
+
+/**
+ * Settings for [`removebg_init`]. NULL strings and zero fields keep the
+ * defaults.
+ */
+typedef struct RemoveBgConfig {
+  /**
+   * Model name: "u2net" (default), "u2netp", "u2net-quant", "u2net-human-seg", "silueta" or "birefnet-lite".
+   */
+  const char *model;
+  /**
+   * Directory the model is cached in.
+   */
+  const char *model_dir;
+  /**
+   * Inference engine: "ort" or "tract".
+   */
+  const char *backend;
+  /**
+   * Output format, e.g. "png" (default) or "webp". Also applies to
+   * removebg_process_file unless the output path has an extension.
+   */
+  const char *format;
+  /**
+   * Non-zero to overwrite existing output files.
+   */
+  int overwrite;
+} RemoveBgConfig;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Load the model and create a handle.
+ *
+ * `config` may be NULL for the defaults. The model is downloaded on first
+ * use. Returns NULL on failure; `removebg_last_error(NULL)` then describes
+ * the error.
+ *
+ * # Safety
+ * `config` must be NULL or point to a valid [`RemoveBgConfig`] whose strings
+ * are NULL or NUL-terminated.
+ */
+struct RemoveBgHandle *removebg_init(const struct RemoveBgConfig *config);
+
+/**
+ * Remove the background from the image file `in_path` and write the cutout
+ * to `out_path`.
+ *
+ * `out_path` may be NULL to write `<input>_nobg.<format>` next to the input.
+ *
+ * # Safety
+ * `handle` must come from [`removebg_init`] and not be freed; the paths must
+ * be NULL or NUL-terminated.
+ */
+int removebg_process_file(struct RemoveBgHandle *handle, const char *in_path, const char *out_path);
+
+/**
+ * Remove the background from the encoded image in `data[0..len]` and return
+ * the encoded cutout in `*out_ptr` / `*out_len`.
+ *
+ * On success the caller owns `*out_ptr` and must release it with
+ * [`removebg_free_buffer`]. On failure `*out_ptr` is set to NULL and
+ * `*out_len` to 0.
+ *
+ * # Safety
+ * `handle` must come from [`removebg_init`] and not be freed; `data` must
+ * point to `len` readable bytes; `out_ptr` and `out_len` must be writable.
+ */
+int removebg_process_bytes(struct RemoveBgHandle *handle,
+                           const uint8_t *data,
+                           size_t len,
+                           uint8_t **out_ptr,
+                           size_t *out_len);
+
+/**
+ * Remove the background from a raw frame of `width` x `height` pixels in
+ * `format` (one of the `REMOVEBG_PIXEL_*` values), whose rows start
+ * `stride` bytes apart in `data[0..len]`, and return the cutout as 8-bit
+ * RGBA pixels, `width * 4` bytes a row, in `*out_ptr` / `*out_len`.
+ *
+ * Ownership of the result and the handling of failures are as for
+ * [`removebg_process_bytes`]. A layout that doesn't fit `len` fails with
+ * [`REMOVEBG_ERROR_INVALID_BUFFER`].
+ *
+ * # Safety
+ * `handle` must come from [`removebg_init`] and not be freed; `data` must
+ * point to `len` readable bytes; `out_ptr` and `out_len` must be writable.
+ */
+int removebg_process_raw(struct RemoveBgHandle *handle,
+                         const uint8_t *data,
+                         size_t len,
+                         uint32_t width,
+                         uint32_t height,
+                         size_t stride,
+                         int format,
+                         uint8_t **out_ptr,
+                         size_t *out_len);
+
+/**
+ * Message of the last failed call on `handle`, or of the last failed
+ * [`removebg_init`] on this thread if `handle` is NULL.
+ *
+ * Returns NULL if that call succeeded. The string is owned by removebg and
+ * stays valid until the next call with the same handle (or the next
+ * [`removebg_init`] for NULL).
+ *
+ * # Safety
+ * `handle` must be NULL or come from [`removebg_init`] and not be freed.
+ */
+const char *removebg_last_error(const struct RemoveBgHandle *handle);
+
+/**
+ * Release a handle and its model. NULL is ignored.
+ *
+ * # Safety
+ * `handle` must be NULL or come from [`removebg_init`], and must not be used
+ * afterwards.
+ */
+void removebg_free(struct RemoveBgHandle *handle);
+
+/**
+ * Release a buffer returned by [`removebg_process_bytes`] or
+ * [`removebg_process_raw`]. NULL is ignored.
+ *
+ * # Safety
+ * `ptr` and `len` must be exactly as returned by removebg, and the buffer
+ * must not be used afterwards.
+ */
+void removebg_free_buffer(uint8_t *ptr, size_t len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* REMOVEBG_H */
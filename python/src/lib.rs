@@ -0,0 +1,280 @@
+//! Python bindings for removebg, built with maturin as the `removebg_rs`
+//! extension module.
+//!
+//! The functions release the GIL while images are processed, so several
+//! Python threads can remove backgrounds in parallel.
+
+use image::{DynamicImage, GrayImage, RgbImage, RgbaImage};
+use numpy::ndarray::Array3;
+use numpy::{IntoPyArray, PyArray3, PyReadonlyArrayDyn};
+use pyo3::exceptions::{
+    PyFileExistsError, PyFileNotFoundError, PyMemoryError, PyOSError, PyPermissionError, PyRuntimeError, PyTimeoutError,
+    PyTypeError, PyValueError,
+};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+use removebg::{InferenceSize, RemoveBgError, RemoveBgOptions};
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Remove the background from an image file and save the cutout.
+///
+/// Returns the path the cutout was written to; without `output` it is
+/// `<input>_nobg.png` next to the input. Loaded models are cached per set of
+/// options, so repeated calls don't reload the model.
+#[pyfunction]
+#[pyo3(signature = (path, output=None, **options))]
+fn remove_background(
+    py: Python<'_>,
+    path: PathBuf,
+    output: Option<PathBuf>,
+    options: Option<&Bound<'_, PyDict>>,
+) -> PyResult<OsString> {
+    let options = parse_options(options)?;
+    let report = py
+        .detach(|| removebg::remove_background_detailed(&path, output.as_deref(), &options))
+        .map_err(to_py_err)?;
+    Ok(report.output_path.into_os_string())
+}
+
+/// Remove the background from an encoded image and return the encoded
+/// cutout (PNG unless `format` is given).
+#[pyfunction]
+#[pyo3(signature = (data, **options))]
+fn remove_background_bytes<'py>(
+    py: Python<'py>,
+    data: &[u8],
+    options: Option<&Bound<'_, PyDict>>,
+) -> PyResult<Bound<'py, PyBytes>> {
+    let options = parse_options(options)?;
+    let encoded = py
+        .detach(|| removebg::remove_background_from_bytes(data, &options))
+        .map_err(to_py_err)?;
+    Ok(PyBytes::new(py, &encoded))
+}
+
+/// Remove the background from a numbered image sequence, smoothing the masks
+/// over time so the cutouts don't flicker.
+///
+/// `inputs` are frame paths or patterns like `"frames/frame_*.png"`; the
+/// cutouts are written to `output_dir`. Returns a dict with the number of
+/// `frames` processed, the `scene_cuts` detected and `frames_per_second`.
+#[pyfunction]
+#[pyo3(signature = (inputs, output_dir, temporal_alpha=0.5, temporal_window=4, scene_cut_threshold=0.25, **options))]
+fn remove_background_sequence<'py>(
+    py: Python<'py>,
+    inputs: Vec<PathBuf>,
+    output_dir: PathBuf,
+    temporal_alpha: f32,
+    temporal_window: usize,
+    scene_cut_threshold: f32,
+    options: Option<&Bound<'_, PyDict>>,
+) -> PyResult<Bound<'py, PyDict>> {
+    let config = removebg::sequence::SequenceConfig {
+        temporal_alpha,
+        temporal_window,
+        scene_cut_threshold,
+        options: parse_options(options)?,
+        ..removebg::sequence::SequenceConfig::new(inputs, output_dir)
+    };
+    let summary = py.detach(|| removebg::sequence::process_sequence(config)).map_err(to_py_err)?;
+    let result = PyDict::new(py);
+    result.set_item("frames", summary.frames)?;
+    result.set_item("scene_cuts", summary.scene_cuts)?;
+    result.set_item("frames_per_second", summary.frames_per_second())?;
+    Ok(result)
+}
+
+/// A loaded model together with the options it runs with.
+///
+/// Mirrors the Rust `BackgroundRemover`: the model is loaded once in the
+/// constructor and freed with the object.
+#[pyclass(name = "BackgroundRemover", module = "removebg_rs", frozen)]
+struct PyBackgroundRemover {
+    remover: removebg::BackgroundRemover,
+}
+
+#[pymethods]
+impl PyBackgroundRemover {
+    #[new]
+    #[pyo3(signature = (**options))]
+    fn new(py: Python<'_>, options: Option<&Bound<'_, PyDict>>) -> PyResult<Self> {
+        let options = parse_options(options)?;
+        let remover = py.detach(|| removebg::BackgroundRemover::new(options)).map_err(to_py_err)?;
+        Ok(PyBackgroundRemover { remover })
+    }
+
+    /// Remove the background from an image file and return the output path.
+    #[pyo3(signature = (path, output=None))]
+    fn process_file(&self, py: Python<'_>, path: PathBuf, output: Option<PathBuf>) -> PyResult<OsString> {
+        let report = py.detach(|| self.remover.process_file(&path, output.as_deref())).map_err(to_py_err)?;
+        Ok(report.output_path.into_os_string())
+    }
+
+    /// Remove the background from an encoded image and return the encoded
+    /// cutout.
+    fn process_bytes<'py>(&self, py: Python<'py>, data: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+        let encoded = py.detach(|| self.remover.process_bytes(data)).map_err(to_py_err)?;
+        Ok(PyBytes::new(py, &encoded))
+    }
+
+    /// Remove the background from an `HxW`, `HxWx3` or `HxWx4` uint8 array
+    /// and return the cutout as an `HxWx4` uint8 array.
+    fn process_array<'py>(
+        &self,
+        py: Python<'py>,
+        array: PyReadonlyArrayDyn<'py, u8>,
+    ) -> PyResult<Bound<'py, PyArray3<u8>>> {
+        let image = image_from_array(&array)?;
+        let cutout = py.detach(|| self.remover.process_image(&image)).map_err(to_py_err)?;
+        Ok(array_from_image(cutout).into_pyarray(py))
+    }
+
+    fn __repr__(&self) -> String {
+        format!("BackgroundRemover(model='{}')", self.remover.options().model)
+    }
+}
+
+/// Build options from keyword arguments; unknown keywords are a `TypeError`.
+fn parse_options(kwargs: Option<&Bound<'_, PyDict>>) -> PyResult<RemoveBgOptions> {
+    let mut options = RemoveBgOptions::default();
+    let Some(kwargs) = kwargs else {
+        return Ok(options);
+    };
+    // The preset is the starting point whichever position it is passed in
+    if let Some(preset) = kwargs.get_item("preset")? {
+        options = RemoveBgOptions::preset(parse(&preset)?);
+    }
+    for (key, value) in kwargs.iter() {
+        let key: String = key.extract()?;
+        match key.as_str() {
+            "preset" => {}
+            "model" => options.model = parse(&value)?,
+            "model_dir" => options.model_dir = Some(value.extract()?),
+            "backend" => options.backend = parse(&value)?,
+            "device" => options.device = parse(&value)?,
+            "mmap_model" => options.session_memory.mmap_model = value.extract()?,
+            "ort_memory_limit" => options.session_memory.arena_limit = value.extract()?,
+            "format" => options.format = Some(parse(&value)?),
+            "quality" => options.quality = Some(value.extract()?),
+            "background" => options.background = parse(&value)?,
+            "max_output_size" => options.max_output_size = value.extract()?,
+            "max_memory_bytes" => options.max_memory_bytes = value.extract()?,
+            "roi" => {
+                let (x, y, width, height) = value.extract()?;
+                options.roi = Some(removebg::Rect { x, y, width, height });
+            }
+            "roi_margin" => options.roi_margin = value.extract()?,
+            "mask_filter" => options.mask_filter = parse(&value)?,
+            "trim_border" => options.trim_border = value.extract()?,
+            "two_pass" => options.two_pass = value.extract()?,
+            "inference_size" => options.inference_size = value.extract::<u32>().map(InferenceSize::Fixed).or_else(|_| parse(&value))?,
+            "auto_enhance" => options.auto_enhance = value.extract()?,
+            "keep_largest_component" => options.keep_largest_component = value.extract()?,
+            "fill_holes" => options.fill_holes = value.extract()?,
+            "external_mask" => options.external_mask = Some(value.extract()?),
+            "line_art" => options.line_art = value.extract()?,
+            "invert_mask" => options.invert_mask = value.extract()?,
+            "premultiplied" => options.premultiplied = value.extract()?,
+            "export_trimap" => options.export_trimap = Some(value.extract()?),
+            "trimap_thresholds" => (options.trimap.low, options.trimap.high) = value.extract()?,
+            "trimap_erode" => options.trimap.erode_radius = value.extract()?,
+            "split_instances" => {
+                let split: bool = value.extract()?;
+                options.split_instances = split.then(|| options.split_instances.unwrap_or_default());
+            }
+            "min_instance_area" => options.split_instances.get_or_insert_with(Default::default).min_area = value.extract()?,
+            "max_instances" => options.split_instances.get_or_insert_with(Default::default).max_instances = value.extract()?,
+            "icon_sizes" => options.icon.sizes = value.extract()?,
+            "prefix" => options.naming.prefix = value.extract()?,
+            "suffix" => options.naming.suffix = Some(value.extract()?),
+            "on_collision" => options.naming.on_collision = parse(&value)?,
+            "fix_extension" => options.naming.fix_extension = value.extract()?,
+            "overwrite" => options.overwrite = value.extract()?,
+            "create_dirs" => options.create_dirs = value.extract()?,
+            other => return Err(PyTypeError::new_err(format!("unexpected option '{}'", other))),
+        }
+    }
+    Ok(options)
+}
+
+/// Parse a string option with the same syntax as the CLI flag.
+fn parse<T: FromStr<Err = String>>(value: &Bound<'_, PyAny>) -> PyResult<T> {
+    value.extract::<String>()?.parse().map_err(PyValueError::new_err)
+}
+
+fn image_from_array(array: &PyReadonlyArrayDyn<'_, u8>) -> PyResult<DynamicImage> {
+    let view = array.as_array();
+    let pixels = || view.iter().copied().collect::<Vec<u8>>();
+    let image = match *view.shape() {
+        [height, width] => GrayImage::from_raw(width as u32, height as u32, pixels()).map(DynamicImage::ImageLuma8),
+        [height, width, 3] => RgbImage::from_raw(width as u32, height as u32, pixels()).map(DynamicImage::ImageRgb8),
+        [height, width, 4] => RgbaImage::from_raw(width as u32, height as u32, pixels()).map(DynamicImage::ImageRgba8),
+        _ => None,
+    };
+    image.ok_or_else(|| {
+        PyValueError::new_err(format!(
+            "expected an HxW, HxWx3 or HxWx4 uint8 array, got shape {:?}",
+            view.shape()
+        ))
+    })
+}
+
+fn array_from_image(image: RgbaImage) -> Array3<u8> {
+    let (width, height) = image.dimensions();
+    Array3::from_shape_vec((height as usize, width as usize, 4), image.into_raw())
+        .expect("RGBA buffer matches its dimensions")
+}
+
+/// Map a removebg error to the closest built-in Python exception.
+fn to_py_err(error: RemoveBgError) -> PyErr {
+    let message = error.to_string();
+    match error.root() {
+        RemoveBgError::FileNotFound(_) | RemoveBgError::OutputDirectoryMissing(_) => {
+            PyFileNotFoundError::new_err(message)
+        }
+        RemoveBgError::OutputExists(_) => PyFileExistsError::new_err(message),
+        RemoveBgError::TimedOut { .. } => PyTimeoutError::new_err(message),
+        RemoveBgError::AccessDenied(_) => PyPermissionError::new_err(message),
+        RemoveBgError::MemoryBudgetExceeded { .. } => PyMemoryError::new_err(message),
+        RemoveBgError::NotAFile(_)
+        | RemoveBgError::IoError(_)
+        | RemoveBgError::InsufficientDiskSpace { .. }
+        | RemoveBgError::ClipboardError(_) => PyOSError::new_err(message),
+        RemoveBgError::ImageError(_)
+        | RemoveBgError::ImageTooLarge { .. }
+        | RemoveBgError::ImageTooSmall { .. }
+        | RemoveBgError::InvalidRawLayout { .. }
+        | RemoveBgError::RawBufferTooSmall { .. }
+        | RemoveBgError::UnsupportedFormat { .. }
+        | RemoveBgError::PageOutOfRange { .. }
+        | RemoveBgError::InvalidMask(_)
+        | RemoveBgError::InvalidOutputFormat(_)
+        | RemoveBgError::OutputTooLarge { .. }
+        | RemoveBgError::InvalidManifest(_)
+        | RemoveBgError::InvalidConfig(_)
+        | RemoveBgError::ArchiveError(_)
+        | RemoveBgError::LowConfidence { .. }
+        | RemoveBgError::AlreadyProcessed { .. } => PyValueError::new_err(message),
+        RemoveBgError::ModelError(_)
+        | RemoveBgError::DownloadError(_)
+        | RemoveBgError::StorageError(_)
+        | RemoveBgError::ModelInitError(_)
+        | RemoveBgError::ModelNotCached { .. }
+        | RemoveBgError::Cancelled
+        | RemoveBgError::ProcessingError(_)
+        | RemoveBgError::Context { .. } => PyRuntimeError::new_err(message),
+    }
+}
+
+/// Background removal with U2-Net, implemented in Rust.
+#[pymodule]
+fn removebg_rs(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("__version__", removebg::VERSION)?;
+    m.add_function(wrap_pyfunction!(remove_background, m)?)?;
+    m.add_function(wrap_pyfunction!(remove_background_bytes, m)?)?;
+    m.add_function(wrap_pyfunction!(remove_background_sequence, m)?)?;
+    m.add_class::<PyBackgroundRemover>()?;
+    Ok(())
+}
@@ -0,0 +1,313 @@
+"""Tests for the removebg_rs extension module.
+
+These check the bindings: that arguments reach the options and errors map
+to the right Python exceptions. What the options do is tested on the Rust
+side, with the fixtures in tests/fixtures/inputs at the repository root.
+
+The tests that run the model use u2netp, which is downloaded into the model
+cache on the first run (set REMOVEBG_MODEL_DIR to reuse a cache).
+"""
+import os
+import struct
+import zlib
+from pathlib import Path
+
+import pytest
+
+import removebg_rs
+
+MODEL = "u2netp"
+FIXTURES = Path(__file__).parents[2] / "tests" / "fixtures" / "inputs"
+
+
+def ppm(width=8, height=8):
+    """A binary PPM with a dark left half and a light right half."""
+    pixels = bytearray()
+    for _ in range(height):
+        for x in range(width):
+            value = 30 if x < width // 2 else 220
+            pixels += bytes([value, value, value])
+    return b"P6\n%d %d\n255\n" % (width, height) + bytes(pixels)
+
+
+def gray_png(width, height, pixel):
+    """An 8-bit grayscale PNG with the values of `pixel(x, y)`."""
+
+    def chunk(kind, data):
+        return struct.pack(">I", len(data)) + kind + data + struct.pack(">I", zlib.crc32(kind + data))
+
+    rows = b"".join(b"\0" + bytes(pixel(x, y) for x in range(width)) for y in range(height))
+    header = struct.pack(">IIBBBBB", width, height, 8, 0, 0, 0, 0)
+    return b"\x89PNG\r\n\x1a\n" + chunk(b"IHDR", header) + chunk(b"IDAT", zlib.compress(rows)) + chunk(b"IEND", b"")
+
+
+def bmp_pixel(data, x, y):
+    """RGBA of a pixel in a 32-bit BMP."""
+    offset = int.from_bytes(data[10:14], "little")
+    width = int.from_bytes(data[18:22], "little", signed=True)
+    height = int.from_bytes(data[22:26], "little", signed=True)
+    row = height - 1 - y if height > 0 else y
+    blue, green, red, alpha = data[offset + (row * width + x) * 4 :][:4]
+    return red, green, blue, alpha
+
+
+def ico_entries(data):
+    """(width, height) of each entry of an ICO file, from its directory and
+    the PNG header of the entry."""
+    assert data[:4] == b"\0\0\1\0"
+    entries = []
+    for index in range(int.from_bytes(data[4:6], "little")):
+        entry = data[6 + 16 * index :][:16]
+        size = int.from_bytes(entry[8:12], "little")
+        offset = int.from_bytes(entry[12:16], "little")
+        png = data[offset : offset + size]
+        assert png.startswith(b"\x89PNG")
+        width, height = int.from_bytes(png[16:20], "big"), int.from_bytes(png[20:24], "big")
+        assert (entry[0] or 256, entry[1] or 256) == (width, height)
+        entries.append((width, height))
+    return entries
+
+
+@pytest.fixture(scope="module")
+def remover():
+    return removebg_rs.BackgroundRemover(model=MODEL)
+
+
+def test_version():
+    assert removebg_rs.__version__
+
+
+def test_remove_background_writes_output(tmp_path):
+    source = tmp_path / "photo.ppm"
+    source.write_bytes(ppm())
+    output = removebg_rs.remove_background(source, model=MODEL)
+    assert output.endswith("photo_nobg.png")
+    assert (tmp_path / "photo_nobg.png").read_bytes().startswith(b"\x89PNG")
+
+
+def test_remove_background_custom_output(tmp_path):
+    source = tmp_path / "photo.ppm"
+    source.write_bytes(ppm())
+    target = tmp_path / "cutout.webp"
+    assert removebg_rs.remove_background(str(source), str(target), model=MODEL) == str(target)
+    assert target.read_bytes()[8:12] == b"WEBP"
+
+
+def test_remove_background_bytes():
+    encoded = removebg_rs.remove_background_bytes(ppm(), model=MODEL)
+    assert isinstance(encoded, bytes)
+    assert encoded.startswith(b"\x89PNG")
+
+
+def test_missing_file_raises_file_not_found(tmp_path):
+    with pytest.raises(FileNotFoundError):
+        removebg_rs.remove_background(tmp_path / "missing.png", model=MODEL)
+
+
+def test_existing_output_raises_file_exists(tmp_path, remover):
+    source = tmp_path / "photo.ppm"
+    source.write_bytes(ppm())
+    (tmp_path / "photo_nobg.png").write_bytes(b"")
+    with pytest.raises(FileExistsError):
+        remover.process_file(source)
+
+
+def test_undecodable_bytes_raise_value_error(remover):
+    with pytest.raises(ValueError):
+        remover.process_bytes(b"not an image")
+
+
+def test_format_follows_content_not_extension(remover, tmp_path):
+    # A JPEG saved with a PNG extension, and one without an extension
+    jpeg = removebg_rs.remove_background_bytes(ppm(), model=MODEL, background="white", format="jpeg")
+    for name in ["photo.png", "download"]:
+        source = tmp_path / name
+        source.write_bytes(jpeg)
+        output = remover.process_file(source, tmp_path / (name + "_nobg.png"))
+        assert Path(output).read_bytes().startswith(b"\x89PNG")
+
+
+def test_non_images_name_what_they_look_like(remover, tmp_path):
+    contents = {
+        "empty file": b"",
+        "PDF": b"%PDF-1.7\n%\xe2\xe3\xcf\xd3\n",
+        "HTML": b"\n<!DOCTYPE html>\n<html><body>Not Found</body></html>",
+        "text": b"hello, world\n",
+        "ZIP archive": b"PK\x03\x04\x14\x00\x00\x00",
+    }
+    for detected, content in contents.items():
+        source = tmp_path / "photo.jpg"
+        source.write_bytes(content)
+        with pytest.raises(ValueError, match=r"photo\.jpg is not an image \(detected: %s\)" % detected):
+            remover.process_file(source, tmp_path / "out.png")
+        with pytest.raises(ValueError, match=r"input data is not an image \(detected: %s\)" % detected):
+            remover.process_bytes(content)
+    assert not (tmp_path / "out.png").exists()
+
+    # Unrecognized binary data has nothing to name
+    with pytest.raises(ValueError, match=r"input data is not an image$"):
+        remover.process_bytes(bytes(range(256)))
+
+
+def test_invalid_option_value_raises_value_error():
+    with pytest.raises(ValueError, match="no-such-model"):
+        removebg_rs.BackgroundRemover(model="no-such-model")
+
+
+def test_unknown_option_raises_type_error():
+    with pytest.raises(TypeError, match="colour"):
+        removebg_rs.remove_background_bytes(ppm(), colour="white")
+
+
+def test_preset_is_overridden_by_other_options():
+    assert removebg_rs.remove_background_bytes(ppm(), model=MODEL, preset="web").startswith(b"RIFF")
+    assert removebg_rs.remove_background_bytes(ppm(), format="png", model=MODEL, preset="web").startswith(b"\x89PNG")
+    with pytest.raises(ValueError, match="unknown preset"):
+        removebg_rs.remove_background_bytes(ppm(), preset="passport")
+
+
+def test_remover_process_bytes_with_background(remover):
+    flattened = removebg_rs.BackgroundRemover(model=MODEL, background="white", format="jpeg")
+    assert flattened.process_bytes(ppm()).startswith(b"\xff\xd8")
+    assert remover.process_bytes(ppm()).startswith(b"\x89PNG")
+    assert "u2netp" in repr(remover)
+
+
+def test_process_array(remover):
+    np = pytest.importorskip("numpy")
+    image = np.zeros((6, 10, 3), dtype=np.uint8)
+    image[:, 5:] = 200
+    cutout = remover.process_array(image)
+    assert cutout.shape == (6, 10, 4)
+    assert cutout.dtype == np.uint8
+    assert (cutout[:, :, :3] == image).all()
+
+    assert remover.process_array(np.zeros((6, 10, 4), dtype=np.uint8)).shape == (6, 10, 4)
+    assert remover.process_array(np.zeros((6, 10), dtype=np.uint8)).shape == (6, 10, 4)
+
+
+def test_process_array_rejects_bad_shape(remover):
+    np = pytest.importorskip("numpy")
+    with pytest.raises(ValueError, match="HxWx4"):
+        remover.process_array(np.zeros((6, 10, 2), dtype=np.uint8))
+
+
+def test_cmyk_jpeg(remover):
+    # Conversion is checked by tests/color_spaces.rs; this only checks the binding
+    assert remover.process_bytes((FIXTURES / "cmyk.jpg").read_bytes()).startswith(b"\x89PNG")
+
+
+def test_unsupported_color_space_raises_runtime_error(remover):
+    with pytest.raises(RuntimeError, match="color space"):
+        remover.process_bytes((FIXTURES / "ycbcr.tif").read_bytes())
+
+
+def test_heic_decodes_or_names_the_feature(remover):
+    try:
+        assert remover.process_bytes((FIXTURES / "rotated.heic").read_bytes()).startswith(b"\x89PNG")
+    except RuntimeError as error:
+        assert "heif" in str(error)
+
+
+def test_raw_decodes_or_names_the_feature(tmp_path):
+    source = tmp_path / "IMG_0123.dng"
+    source.write_bytes((FIXTURES / "IMG_0123.dng").read_bytes())
+    try:
+        assert removebg_rs.remove_background(source, model=MODEL).endswith("IMG_0123_nobg.png")
+    except RuntimeError as error:
+        assert "raw" in str(error)
+
+
+def test_icon_sizes():
+    data = removebg_rs.remove_background_bytes(ppm(64, 48), model=MODEL, format="ico", icon_sizes=[24, 16])
+    assert ico_entries(data) == [(16, 16), (24, 24)]
+    with pytest.raises(ValueError, match="256"):
+        removebg_rs.remove_background_bytes(ppm(), model=MODEL, format="ico", icon_sizes=[512])
+
+
+def test_sequence(tmp_path):
+    frames = tmp_path / "frames"
+    frames.mkdir()
+    for index in range(3):
+        (frames / f"frame_{index + 1}.png").write_bytes(gray_png(16, 16, lambda x, y: 200 if x < 8 else 30))
+    summary = removebg_rs.remove_background_sequence(
+        [frames / "frame_*.png"], tmp_path / "out", model=MODEL, temporal_alpha=0.5, temporal_window=2
+    )
+    assert summary["frames"] == 3 and summary["scene_cuts"] == 0
+    assert (tmp_path / "out" / "frame_3_nobg.png").exists()
+
+    with pytest.raises(FileNotFoundError):
+        removebg_rs.remove_background_sequence([tmp_path / "shot_*.png"], tmp_path / "out", model=MODEL)
+
+
+def two_subjects():
+    """A 64x32 PNG with light squares on the left and right of a dark background."""
+    return gray_png(64, 32, lambda x, y: 230 if 8 <= y < 24 and (4 <= x < 20 or 44 <= x < 60) else 20)
+
+
+def test_roi():
+    left = removebg_rs.remove_background_bytes(
+        two_subjects(), model=MODEL, format="bmp", roi=(0, 0, 32, 32), roi_margin=0.1
+    )
+    assert struct.unpack("<ii", left[18:26]) == (64, 32)
+    assert bmp_pixel(left, 52, 16)[3] == 0
+    with pytest.raises(RuntimeError, match="zero area"):
+        removebg_rs.remove_background_bytes(two_subjects(), model=MODEL, roi=(0, 0, 0, 32))
+
+
+def test_max_output_size_downscales_large_inputs():
+    small = removebg_rs.remove_background_bytes(two_subjects(), model=MODEL, format="bmp", max_output_size=16)
+    assert struct.unpack("<ii", small[18:26]) == (16, 8)
+    whole = removebg_rs.remove_background_bytes(two_subjects(), model=MODEL, format="bmp", max_output_size=64)
+    assert struct.unpack("<ii", whole[18:26]) == (64, 32)
+
+
+def test_line_art_keeps_ink_and_drops_paper():
+    drawing = gray_png(32, 16, lambda x, y: 0 if y in (7, 8) else 255)
+    output = removebg_rs.remove_background_bytes(drawing, model=MODEL, format="bmp", line_art=True)
+    assert bmp_pixel(output, 10, 7)[3] == 255
+    assert bmp_pixel(output, 10, 2)[3] == 0
+
+
+def test_export_trimap(tmp_path):
+    source, mask_file, trimap_file = tmp_path / "photo.png", tmp_path / "mask.png", tmp_path / "trimap.png"
+    source.write_bytes(gray_png(40, 20, lambda x, y: 128))
+    mask_file.write_bytes(gray_png(40, 20, lambda x, y: 255 if x < 20 else 0))
+    removebg_rs.remove_background(
+        source, tmp_path / "cutout.png", external_mask=mask_file, export_trimap=trimap_file,
+        trimap_erode=3, trimap_thresholds=(0.1, 0.9),
+    )
+    assert trimap_file.read_bytes().startswith(b"\x89PNG")
+    with pytest.raises(RuntimeError, match="low < high"):
+        removebg_rs.remove_background(
+            source, tmp_path / "other.png", external_mask=mask_file, export_trimap=trimap_file,
+            trimap_thresholds=(0.9, 0.1),
+        )
+
+
+def test_premultiplied(tmp_path):
+    mask_file = tmp_path / "mask.png"
+    mask_file.write_bytes(gray_png(40, 4, lambda x, y: min(255, x * 8)))
+    image = gray_png(40, 4, lambda x, y: 200)
+    data = removebg_rs.remove_background_bytes(image, external_mask=mask_file, format="bmp", premultiplied=True)
+    red, _, _, alpha = bmp_pixel(data, 10, 0)
+    assert alpha == 80 and abs(red - 200 * 80 / 255) <= 1
+    with pytest.raises(ValueError, match="premultiplied"):
+        removebg_rs.remove_background_bytes(
+            image, external_mask=mask_file, background="white", premultiplied=True
+        )
+
+
+def test_non_utf8_file_names_round_trip(remover, tmp_path):
+    if os.name != "posix":
+        pytest.skip("only POSIX file names can hold arbitrary bytes")
+    source = Path(os.fsdecode(bytes(tmp_path) + b"/ph\xffoto.ppm"))
+    source.write_bytes(ppm())
+    output = remover.process_file(source)
+    assert os.fsencode(output) == bytes(tmp_path) + b"/ph\xffoto_nobg.png"
+    assert Path(output).read_bytes().startswith(b"\x89PNG")
+
+
+def test_reading_the_model_matches_mapping_it(remover):
+    read = removebg_rs.BackgroundRemover(model=MODEL, mmap_model=False)
+    assert read.process_bytes(ppm()) == remover.process_bytes(ppm())
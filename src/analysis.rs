@@ -0,0 +1,688 @@
+//! Subject location statistics and confidence scoring for a mask.
+//!
+//! This module defines the result of [`analyze`](crate::analyze), which
+//! describes where the subject is instead of cutting it out, for example to
+//! drive automatic cropping, the confidence score that flags masks without a
+//! clear subject, the [`AlphaStats`] quality metrics of a cutout's edges, and
+//! the background color estimate behind
+//! [`Background::Auto`](crate::Background::Auto).
+
+use crate::core::{Channel, Gray16Image};
+use crate::error::{RemoveBgError, Result};
+use crate::matte::label_components;
+use crate::options::ConfidenceOptions;
+use crate::report::{ModelInfo, Rect, StageDurations};
+use image::{DynamicImage, GrayImage};
+
+/// One separate region of the subject.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Component {
+    /// Bounding box of the region.
+    pub bounds: Rect,
+    /// Number of pixels in the region.
+    pub area: u64,
+    /// Mean position of the region's pixels, measured to pixel centers.
+    pub centroid: (f32, f32),
+}
+
+/// Where the subject is in an image, measured on its mask.
+///
+/// Pixels whose mask alpha is at least the threshold belong to the subject.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubjectAnalysis {
+    /// Width and height of the image.
+    pub dimensions: (u32, u32),
+    /// Alpha threshold (0.0-1.0) the mask was measured at.
+    pub threshold: f32,
+    /// Bounding box of all subject pixels; `None` if there are none.
+    pub bounds: Option<Rect>,
+    /// Fraction of the image (0.0-1.0) covered by the subject.
+    pub coverage: f32,
+    /// Mean position of the subject's pixels; `None` if there are none.
+    pub centroid: Option<(f32, f32)>,
+    /// Fraction (0.0-1.0) of each quadrant covered by the subject, in the
+    /// order top-left, top-right, bottom-left, bottom-right. With an odd
+    /// width or height the middle column or row belongs to the right or
+    /// bottom quadrants.
+    pub quadrant_coverage: [f32; 4],
+    /// Separate regions of the subject (8-connected), largest first.
+    pub components: Vec<Component>,
+    /// Confidence score (0.0-1.0) of the model's mask; see
+    /// [`ConfidenceOptions`].
+    pub confidence: f32,
+    /// Quality metrics of the finished mask's alpha, independent of the
+    /// threshold.
+    pub alpha_stats: AlphaStats,
+    /// Model used to generate the mask; `None` when an external mask was used.
+    pub model: Option<ModelInfo>,
+    /// Time spent in each stage; `encode` is always zero.
+    pub durations: StageDurations,
+}
+
+/// Check that an analysis threshold is usable.
+pub(crate) fn check_threshold(threshold: f32) -> Result<()> {
+    if !(threshold > 0.0 && threshold <= 1.0) {
+        return Err(RemoveBgError::ProcessingError(format!(
+            "Subject threshold must be above 0 and at most 1, got {}",
+            threshold
+        )));
+    }
+    Ok(())
+}
+
+/// Running sums for a bounding box and centroid.
+#[derive(Default)]
+struct Extent {
+    area: u64,
+    sum_x: f64,
+    sum_y: f64,
+    bounds: Option<(u32, u32, u32, u32)>,
+}
+
+impl Extent {
+    fn add(&mut self, x: u32, y: u32) {
+        self.area += 1;
+        self.sum_x += x as f64 + 0.5;
+        self.sum_y += y as f64 + 0.5;
+        self.bounds = Some(match self.bounds {
+            Some((left, top, right, bottom)) => (left.min(x), top.min(y), right.max(x + 1), bottom.max(y + 1)),
+            None => (x, y, x + 1, y + 1),
+        });
+    }
+
+    fn rect(&self) -> Option<Rect> {
+        self.bounds.map(|(left, top, right, bottom)| Rect {
+            x: left,
+            y: top,
+            width: right - left,
+            height: bottom - top,
+        })
+    }
+
+    fn centroid(&self) -> Option<(f32, f32)> {
+        (self.area > 0).then(|| ((self.sum_x / self.area as f64) as f32, (self.sum_y / self.area as f64) as f32))
+    }
+}
+
+/// Measure the subject in a finished mask at `threshold`.
+///
+/// `confidence`, `model` and `durations` are left for the caller to fill in.
+pub(crate) fn analyze_mask(mask: &Gray16Image, threshold: f32) -> SubjectAnalysis {
+    let (width, height) = mask.dimensions();
+    let cutoff = (threshold * 65535.0).round() as u16;
+    let subject: Vec<bool> = mask.iter().map(|&alpha| alpha >= cutoff).collect();
+
+    let (labels, count) = label_components(&subject, width as usize, height as usize, true);
+    let mut total = Extent::default();
+    let mut regions: Vec<Extent> = (0..count).map(|_| Extent::default()).collect();
+    let (mid_x, mid_y) = (width / 2, height / 2);
+    let mut quadrants = [0u64; 4];
+    for (index, &label) in labels.iter().enumerate() {
+        if label == 0 {
+            continue;
+        }
+        let (x, y) = ((index % width as usize) as u32, (index / width as usize) as u32);
+        total.add(x, y);
+        regions[label as usize - 1].add(x, y);
+        quadrants[(y >= mid_y) as usize * 2 + (x >= mid_x) as usize] += 1;
+    }
+
+    let quadrant_sizes = [
+        mid_x as u64 * mid_y as u64,
+        (width - mid_x) as u64 * mid_y as u64,
+        mid_x as u64 * (height - mid_y) as u64,
+        (width - mid_x) as u64 * (height - mid_y) as u64,
+    ];
+    let quadrant_coverage =
+        std::array::from_fn(|i| if quadrant_sizes[i] > 0 { quadrants[i] as f32 / quadrant_sizes[i] as f32 } else { 0.0 });
+
+    let mut components: Vec<Component> = regions
+        .iter()
+        .filter_map(|region| {
+            Some(Component { bounds: region.rect()?, area: region.area, centroid: region.centroid()? })
+        })
+        .collect();
+    components.sort_by_key(|component| std::cmp::Reverse(component.area));
+
+    SubjectAnalysis {
+        dimensions: (width, height),
+        threshold,
+        bounds: total.rect(),
+        coverage: total.area as f32 / (width as u64 * height as u64).max(1) as f32,
+        centroid: total.centroid(),
+        quadrant_coverage,
+        components,
+        confidence: 0.0,
+        alpha_stats: measure_alpha(mask),
+        model: None,
+        durations: StageDurations::default(),
+    }
+}
+
+/// Alpha range (0.2-0.8 of full opacity, inclusive) of the pixels counted by
+/// [`AlphaStats::ambiguous`], in 16-bit steps.
+const AMBIGUOUS_ALPHA: std::ops::RangeInclusive<u16> = 13107..=52428;
+
+/// Lowest 16-bit alpha of the binarized mask behind
+/// [`AlphaStats::edge_length`] and the component counts: at least half
+/// opaque, or 128 and up in 8-bit alpha.
+const EDGE_ALPHA: u16 = 32768;
+
+/// Regions smaller than the image's pixel count divided by this (rounded
+/// up) are counted as [`AlphaStats::specks`] rather than
+/// [`AlphaStats::components`].
+const SPECK_DIVISOR: u64 = 1000;
+
+/// Quality metrics of a cutout's alpha channel, for flagging cutouts that
+/// need a human look, such as a hazy mask or stray specks.
+///
+/// Every metric is a fixed function of the alpha values, so the same mask
+/// always gives the same numbers. Alphas are compared in 16 bits; 8-bit
+/// alphas count as their 16-bit equivalent (`a * 257`), which puts them in
+/// the same histogram buckets as `a >> 4`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AlphaStats {
+    /// Pixel counts of 16 equal alpha ranges: bucket `i` holds 8-bit alphas
+    /// `16 * i` to `16 * i + 15`, so bucket 0 is (nearly) transparent and
+    /// bucket 15 (nearly) opaque.
+    pub histogram: [u64; 16],
+    /// Fraction of pixels (0.0-1.0) with an alpha from 0.2 to 0.8, neither
+    /// clearly subject nor clearly background.
+    pub ambiguous: f32,
+    /// Perimeter of the subject in pixels: the number of pairs of
+    /// horizontally or vertically adjacent pixels of which exactly one is at
+    /// least half opaque. The image's border is not counted, so a
+    /// `w`×`h` rectangle inside the image measures `2 * (w + h)`.
+    pub edge_length: u64,
+    /// Mean width in pixels of the ramp from transparent to opaque across
+    /// the edge: the number of partially transparent pixels (8-bit alpha 1
+    /// to 254) divided by [`edge_length`](Self::edge_length). 0 for hard
+    /// edges, about `k` for a linear ramp `k` pixels wide, and 0 without an
+    /// edge.
+    pub edge_softness: f32,
+    /// Separate regions (8-connected) of at least half opaque pixels with at
+    /// least [`min_component_area`](Self::min_component_area) pixels.
+    pub components: u32,
+    /// Separate regions smaller than
+    /// [`min_component_area`](Self::min_component_area).
+    pub specks: u32,
+    /// Pixel count separating components from specks: a thousandth of the
+    /// image's pixels, rounded up.
+    pub min_component_area: u64,
+}
+
+/// Measure the quality metrics of a cutout's 8-bit alpha channel, or any
+/// grayscale mask where white is the subject.
+///
+/// See [`AlphaStats`] for how each metric is defined.
+///
+/// # Examples
+/// ```
+/// use removebg::analysis::alpha_stats;
+///
+/// // A hard-edged 20x10 rectangle in a 100x100 mask
+/// let mask = image::GrayImage::from_fn(100, 100, |x, y| {
+///     image::Luma([if (40..60).contains(&x) && (45..55).contains(&y) { 255 } else { 0 }])
+/// });
+/// let stats = alpha_stats(&mask);
+/// assert_eq!((stats.histogram[0], stats.histogram[15]), (9800, 200));
+/// assert_eq!((stats.edge_length, stats.edge_softness), (60, 0.0));
+/// assert_eq!(stats.components, 1);
+/// ```
+pub fn alpha_stats(mask: &GrayImage) -> AlphaStats {
+    let mask = Gray16Image::from_fn(mask.width(), mask.height(), |x, y| image::Luma([mask.get_pixel(x, y)[0] as u16 * 257]));
+    measure_alpha(&mask)
+}
+
+/// Measure the quality metrics of a 16-bit mask; see [`AlphaStats`].
+pub(crate) fn measure_alpha(mask: &Gray16Image) -> AlphaStats {
+    let (width, height) = (mask.width() as usize, mask.height() as usize);
+    let mut histogram = [0u64; 16];
+    let (mut ambiguous, mut partial) = (0u64, 0u64);
+    for &alpha in mask.iter() {
+        histogram[(alpha >> 12) as usize] += 1;
+        ambiguous += AMBIGUOUS_ALPHA.contains(&alpha) as u64;
+        partial += (1..=254).contains(&u8::from_mask(alpha)) as u64;
+    }
+
+    let subject: Vec<bool> = mask.iter().map(|&alpha| alpha >= EDGE_ALPHA).collect();
+    let mut edge_length = 0u64;
+    for (index, &inside) in subject.iter().enumerate() {
+        let x = index % width;
+        edge_length += (x + 1 < width && subject[index + 1] != inside) as u64;
+        edge_length += (index + width < subject.len() && subject[index + width] != inside) as u64;
+    }
+
+    let (labels, count) = label_components(&subject, width, height, true);
+    let mut areas = vec![0u64; count];
+    for &label in labels.iter().filter(|&&label| label > 0) {
+        areas[label as usize - 1] += 1;
+    }
+    let pixels = (width as u64 * height as u64).max(1);
+    let min_component_area = pixels.div_ceil(SPECK_DIVISOR);
+    let components = areas.iter().filter(|&&area| area >= min_component_area).count() as u32;
+
+    AlphaStats {
+        histogram,
+        ambiguous: ambiguous as f32 / pixels as f32,
+        edge_length,
+        edge_softness: if edge_length > 0 { partial as f32 / edge_length as f32 } else { 0.0 },
+        components,
+        specks: count as u32 - components,
+        min_component_area,
+    }
+}
+
+/// Score how clearly a mask separates a subject from the background.
+///
+/// See [`ConfidenceOptions`] for how the score is computed. White pixels
+/// belong to the subject.
+///
+/// # Arguments
+/// * `mask` - Subject mask, e.g. from
+///   [`BackgroundRemover::mask`](crate::remover::BackgroundRemover::mask).
+/// * `options` - Bands and coverage range of the score.
+///
+/// # Returns
+/// The score from 0.0 (no clear subject) to 1.0.
+///
+/// # Errors
+/// * `ProcessingError` - If the bands or the coverage range are empty or outside 0-1
+///
+/// # Examples
+/// ```no_run
+/// use removebg::{mask_confidence, ConfidenceOptions};
+///
+/// let empty = image::GrayImage::new(64, 64);
+/// assert_eq!(mask_confidence(&empty, &ConfidenceOptions::default())?, 0.0);
+/// # Ok::<(), removebg::error::RemoveBgError>(())
+/// ```
+pub fn mask_confidence(mask: &GrayImage, options: &ConfidenceOptions) -> Result<f32> {
+    check_confidence(options)?;
+    Ok(confidence_score(mask.iter().map(|&alpha| alpha as f32 / 255.0), options))
+}
+
+/// Coverage below which a mask is taken to hold no subject.
+pub const MIN_SUBJECT_COVERAGE: f32 = 0.01;
+
+/// Coverage above which a mask is taken to have removed no background.
+pub const MAX_SUBJECT_COVERAGE: f32 = 0.99;
+
+/// A mask coverage that suggests the cutout is not what the user wanted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoverageWarning {
+    /// Under [`MIN_SUBJECT_COVERAGE`] of the image was kept.
+    NothingDetected,
+    /// Over [`MAX_SUBJECT_COVERAGE`] of the image was kept.
+    NoBackground,
+}
+
+impl CoverageWarning {
+    /// Check a coverage such as
+    /// [`RemovalReport::mask_coverage`](crate::RemovalReport::mask_coverage),
+    /// the fraction (0.0-1.0) of pixels the mask keeps.
+    pub fn from_coverage(coverage: f32) -> Option<Self> {
+        if coverage < MIN_SUBJECT_COVERAGE {
+            Some(CoverageWarning::NothingDetected)
+        } else if coverage > MAX_SUBJECT_COVERAGE {
+            Some(CoverageWarning::NoBackground)
+        } else {
+            None
+        }
+    }
+
+    /// Check a mask, counting every pixel with non-zero alpha as kept, as
+    /// [`RemovalReport::mask_coverage`](crate::RemovalReport::mask_coverage)
+    /// does.
+    pub fn from_mask(mask: &GrayImage) -> Option<Self> {
+        let kept = mask.iter().filter(|&&alpha| alpha > 0).count();
+        Self::from_coverage(kept as f32 / mask.len().max(1) as f32)
+    }
+
+    /// Short machine-readable name, as used in the CLI's JSON output.
+    pub fn kind(self) -> &'static str {
+        match self {
+            CoverageWarning::NothingDetected => "nothing_detected",
+            CoverageWarning::NoBackground => "no_background",
+        }
+    }
+}
+
+impl std::fmt::Display for CoverageWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            CoverageWarning::NothingDetected => "nothing was detected — output may be empty",
+            CoverageWarning::NoBackground => "no background was detected — output may be unchanged",
+        })
+    }
+}
+
+/// Mask alpha (0.0-1.0) below which a pixel is confidently background, for
+/// [`background_color`].
+const BACKGROUND_ALPHA: f32 = 0.05;
+
+/// Fraction of the image that must be confidently background for
+/// [`background_color`] to estimate a color.
+pub const MIN_BACKGROUND_SAMPLE: f32 = 0.01;
+
+/// Bits kept per channel when binning background colors.
+const COLOR_BIN_BITS: usize = 4;
+
+/// The dominant color of the pixels `mask` confidently marks as background,
+/// or `None` if under [`MIN_BACKGROUND_SAMPLE`] of the image is.
+///
+/// Colors are binned at [`COLOR_BIN_BITS`] per channel and the fullest bin
+/// wins, so subject pixels that leak in along the edges or a second,
+/// smaller backdrop cannot tint the result the way they would a mean. The
+/// estimate is the per-channel median of the samples in that bin and its
+/// neighbors, which keeps a backdrop lying across a bin boundary intact.
+pub(crate) fn background_color(image: &DynamicImage, mask: &Gray16Image) -> Option<[u8; 3]> {
+    let rgb = image.to_rgb8();
+    let threshold = (BACKGROUND_ALPHA * 65535.0) as u16;
+    let bin = |pixel: &[u8; 3]| pixel.map(|c| (c >> (8 - COLOR_BIN_BITS)) as usize);
+    let index = |pixel: &[u8; 3]| bin(pixel).iter().fold(0, |index, &c| (index << COLOR_BIN_BITS) | c);
+    let samples = || rgb.pixels().zip(mask.iter()).filter(|&(_, &alpha)| alpha < threshold).map(|(pixel, _)| &pixel.0);
+
+    let mut bins = vec![0u32; 1 << (3 * COLOR_BIN_BITS)];
+    let mut count = 0usize;
+    for pixel in samples() {
+        bins[index(pixel)] += 1;
+        count += 1;
+    }
+    if count == 0 || (count as f32) < MIN_BACKGROUND_SAMPLE * (rgb.width() * rgb.height()) as f32 {
+        return None;
+    }
+    let fullest = (0..bins.len()).max_by_key(|&i| (bins[i], std::cmp::Reverse(i)))?;
+    let levels = 1 << COLOR_BIN_BITS;
+    let mode: [usize; 3] = std::array::from_fn(|c| (fullest >> ((2 - c) * COLOR_BIN_BITS)) % levels);
+
+    let mut histograms = [[0u32; 256]; 3];
+    let mut near = 0u32;
+    for pixel in samples() {
+        if bin(pixel).iter().zip(mode).all(|(c, m)| c.abs_diff(m) <= 1) {
+            for (histogram, &c) in histograms.iter_mut().zip(pixel) {
+                histogram[c as usize] += 1;
+            }
+            near += 1;
+        }
+    }
+    Some(histograms.map(|histogram| {
+        let mut seen = 0;
+        histogram.iter().position(|&n| {
+            seen += n;
+            seen > near / 2
+        })
+        .unwrap_or(0) as u8
+    }))
+}
+
+/// Confidence score of a 16-bit mask.
+pub(crate) fn confidence(mask: &Gray16Image, options: &ConfidenceOptions) -> Result<f32> {
+    check_confidence(options)?;
+    Ok(confidence_score(mask.iter().map(|&alpha| alpha as f32 / 65535.0), options))
+}
+
+fn check_confidence(options: &ConfidenceOptions) -> Result<()> {
+    let unit = 0.0..=1.0;
+    if !(unit.contains(&options.low) && unit.contains(&options.high) && options.low < options.high) {
+        return Err(RemoveBgError::ProcessingError(format!(
+            "Confidence bands must satisfy 0 <= low < high <= 1, got low {} and high {}",
+            options.low, options.high
+        )));
+    }
+    if !(unit.contains(&options.min_coverage)
+        && unit.contains(&options.max_coverage)
+        && options.min_coverage < options.max_coverage)
+    {
+        return Err(RemoveBgError::ProcessingError(format!(
+            "Confidence coverage range must satisfy 0 <= min < max <= 1, got {} to {}",
+            options.min_coverage, options.max_coverage
+        )));
+    }
+    Ok(())
+}
+
+/// The score described on [`ConfidenceOptions`], for alpha values in 0.0-1.0.
+fn confidence_score(alphas: impl Iterator<Item = f32>, options: &ConfidenceOptions) -> f32 {
+    let (mut total, mut high, mut ambiguous, mut subject) = (0u64, 0u64, 0u64, 0u64);
+    let mut high_sum = 0.0f64;
+    for alpha in alphas {
+        total += 1;
+        if alpha >= options.high {
+            high += 1;
+            high_sum += alpha as f64;
+        } else if alpha > options.low {
+            ambiguous += 1;
+        }
+        if alpha >= 0.5 {
+            subject += 1;
+        }
+    }
+    if high == 0 {
+        return 0.0;
+    }
+
+    let strength = (high_sum / high as f64) as f32;
+    let clarity = 1.0 - ambiguous as f32 / (high + ambiguous) as f32;
+    let coverage = subject as f32 / total as f32;
+    let coverage_fit = if coverage < options.min_coverage {
+        coverage / options.min_coverage
+    } else if coverage > options.max_coverage {
+        (1.0 - coverage) / (1.0 - options.max_coverage)
+    } else {
+        1.0
+    };
+    (strength * clarity * coverage_fit).clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    /// A 100x100 mask keeping `kept` pixels, filled row by row.
+    fn mask(kept: u32, alpha: u8) -> GrayImage {
+        GrayImage::from_fn(100, 100, |x, y| Luma([if y * 100 + x < kept { alpha } else { 0 }]))
+    }
+
+    #[test]
+    fn empty_and_nearly_empty_masks_detect_nothing() {
+        assert_eq!(CoverageWarning::from_mask(&GrayImage::new(100, 100)), Some(CoverageWarning::NothingDetected));
+        assert_eq!(CoverageWarning::from_mask(&mask(99, 255)), Some(CoverageWarning::NothingDetected));
+        assert_eq!(CoverageWarning::from_mask(&mask(100, 255)), None);
+    }
+
+    #[test]
+    fn full_masks_remove_no_background() {
+        assert_eq!(CoverageWarning::from_mask(&mask(10_000, 255)), Some(CoverageWarning::NoBackground));
+        assert_eq!(CoverageWarning::from_mask(&mask(9_901, 255)), Some(CoverageWarning::NoBackground));
+        assert_eq!(CoverageWarning::from_mask(&mask(9_900, 255)), None);
+    }
+
+    #[test]
+    fn faint_alpha_counts_as_kept() {
+        assert_eq!(CoverageWarning::from_mask(&mask(5_000, 1)), None);
+        assert_eq!(CoverageWarning::from_mask(&mask(10_000, 1)), Some(CoverageWarning::NoBackground));
+    }
+
+    /// A 64x64 photo of a noisy, multicolored subject in the middle third on
+    /// a backdrop of `backdrop` with +-`noise` per channel, and its mask.
+    fn photo(backdrop: [u8; 3], noise: i32) -> (DynamicImage, Gray16Image) {
+        // A fixed hash stands in for random noise, so the tests are stable
+        let hash = |x: u32, y: u32, c: u32| (x.wrapping_mul(73_856_093) ^ y.wrapping_mul(19_349_663) ^ c.wrapping_mul(83_492_791)) % 1024;
+        let subject = |x: u32, y: u32| (22..42).contains(&x) && (22..42).contains(&y);
+        let image = image::RgbImage::from_fn(64, 64, |x, y| {
+            image::Rgb(std::array::from_fn(|c| match subject(x, y) {
+                true => hash(x, y, c as u32) as u8,
+                false => (backdrop[c] as i32 + hash(x, y, c as u32) as i32 % (2 * noise + 1) - noise).clamp(0, 255) as u8,
+            }))
+        });
+        let mask = Gray16Image::from_fn(64, 64, |x, y| Luma([if subject(x, y) { 65535 } else { 0 }]));
+        (DynamicImage::ImageRgb8(image), mask)
+    }
+
+    fn assert_near(estimate: Option<[u8; 3]>, expected: [u8; 3]) {
+        let estimate = estimate.expect("no estimate");
+        assert!(estimate.iter().zip(expected).all(|(&e, x)| e.abs_diff(x) <= 2), "{:?} vs {:?}", estimate, expected);
+    }
+
+    #[test]
+    fn background_color_finds_the_backdrop() {
+        for backdrop in [[255, 255, 255], [242, 238, 229], [30, 90, 160], [128, 128, 128]] {
+            let (image, mask) = photo(backdrop, 6);
+            assert_near(background_color(&image, &mask), backdrop);
+        }
+    }
+
+    #[test]
+    fn background_color_ignores_subject_leaking_into_the_samples() {
+        // A mask that misses the subject's bottom half puts dark, colorful
+        // pixels among the samples; a mean would drift toward them
+        let (image, mut mask) = photo([245, 240, 232], 4);
+        for y in 32..42 {
+            for x in 22..42 {
+                mask.put_pixel(x, y, Luma([0]));
+            }
+        }
+        assert_near(background_color(&image, &mask), [245, 240, 232]);
+    }
+
+    #[test]
+    fn background_color_needs_some_background() {
+        let (image, _) = photo([200, 200, 200], 4);
+        let full = Gray16Image::from_pixel(64, 64, Luma([65535]));
+        assert_eq!(background_color(&image, &full), None);
+        // Soft alpha is not confident background
+        let soft = Gray16Image::from_pixel(64, 64, Luma([20_000]));
+        assert_eq!(background_color(&image, &soft), None);
+        let mut sliver = full;
+        for x in 0..40 {
+            sliver.put_pixel(x, 0, Luma([0]));
+        }
+        assert_eq!(background_color(&image, &sliver), None);
+        sliver.put_pixel(40, 0, Luma([0]));
+        sliver.put_pixel(41, 0, Luma([0]));
+        assert_near(background_color(&image, &sliver), [200, 200, 200]);
+    }
+
+    /// A `width`×`height` mask with `alpha(x, y)` as 8-bit alpha.
+    fn alphas(width: u32, height: u32, alpha: impl Fn(u32, u32) -> u8) -> GrayImage {
+        GrayImage::from_fn(width, height, |x, y| Luma([alpha(x, y)]))
+    }
+
+    #[test]
+    fn hard_edges_have_a_perimeter_and_no_softness() {
+        // A 20x10 rectangle and a 2x2 speck, under 0.1% of 100x100
+        let stats = alpha_stats(&alphas(100, 100, |x, y| {
+            let rectangle = (10..30).contains(&x) && (20..30).contains(&y);
+            let speck = (80..82).contains(&x) && (80..82).contains(&y);
+            if rectangle || speck { 255 } else { 0 }
+        }));
+        assert_eq!(stats.histogram[15], 204);
+        assert_eq!(stats.histogram[0], 10000 - 204);
+        assert_eq!(stats.histogram[1..15].iter().sum::<u64>(), 0);
+        assert_eq!(stats.ambiguous, 0.0);
+        assert_eq!(stats.edge_length, 2 * (20 + 10) + 2 * (2 + 2));
+        assert_eq!(stats.edge_softness, 0.0);
+        assert_eq!((stats.components, stats.specks, stats.min_component_area), (1, 1, 10));
+
+        // The image's border is not an edge
+        let full = alpha_stats(&alphas(8, 6, |_, _| 255));
+        assert_eq!((full.edge_length, full.components, full.specks), (0, 1, 0));
+        let empty = alpha_stats(&alphas(8, 6, |_, _| 0));
+        assert_eq!((empty.edge_length, empty.edge_softness, empty.components), (0, 0.0, 0));
+    }
+
+    #[test]
+    fn linear_ramps_measure_their_width() {
+        // Transparent, a ramp of 10 columns from 13 to 238, then opaque
+        let ramp = |x: u32| match x {
+            0..=39 => 0,
+            40..=49 => 13 + 25 * (x - 40) as u8,
+            _ => 255,
+        };
+        let stats = alpha_stats(&alphas(100, 10, |x, _| ramp(x)));
+        assert_eq!(stats.edge_length, 10);
+        assert_eq!(stats.edge_softness, 10.0);
+        // 63, 88, 113, 138, 163 and 188 are within 0.2-0.8
+        assert!((stats.ambiguous - 0.06).abs() < 1e-6, "{}", stats.ambiguous);
+        assert_eq!(stats.histogram[0], 410);
+        assert_eq!(stats.histogram[15], 500);
+        assert_eq!(stats.histogram[14], 10);
+        assert_eq!(stats.histogram.iter().sum::<u64>(), 1000);
+        assert_eq!((stats.components, stats.specks), (1, 0));
+
+        // 16-bit alphas land in the bucket of their 8-bit equivalent
+        for alpha in [0u8, 15, 16, 127, 128, 240, 255] {
+            let stats = alpha_stats(&alphas(1, 1, |_, _| alpha));
+            assert_eq!(stats.histogram[alpha as usize / 16], 1, "{}", alpha);
+        }
+    }
+
+    fn rect(x: u32, y: u32, width: u32, height: u32) -> Rect {
+        Rect { x, y, width, height }
+    }
+
+    /// Intersection over union of two boxes.
+    fn iou(a: Rect, b: Rect) -> f32 {
+        let overlap = |a0: u32, a1: u32, b0: u32, b1: u32| a1.min(b1).saturating_sub(a0.max(b0));
+        let intersection = overlap(a.x, a.x + a.width, b.x, b.x + b.width) * overlap(a.y, a.y + a.height, b.y, b.y + b.height);
+        intersection as f32 / (a.width * a.height + b.width * b.height - intersection) as f32
+    }
+
+    #[test]
+    fn components_are_measured_separately_largest_first() {
+        // A 40x30 subject right of the middle and a 6x6 speck at the top left
+        let mask = Gray16Image::from_fn(100, 80, |x, y| {
+            let subject = (45..85).contains(&x) && (25..55).contains(&y);
+            let speck = (5..11).contains(&x) && (5..11).contains(&y);
+            Luma([if subject || speck { 60000 } else { 1000 }])
+        });
+        let analysis = analyze_mask(&mask, 0.5);
+
+        assert_eq!(analysis.bounds, Some(rect(5, 5, 80, 50)));
+        assert_eq!(analysis.coverage, 1236.0 / 8000.0);
+        assert_eq!(
+            analysis.components,
+            [
+                Component { bounds: rect(45, 25, 40, 30), area: 1200, centroid: (65.0, 40.0) },
+                Component { bounds: rect(5, 5, 6, 6), area: 36, centroid: (8.0, 8.0) },
+            ]
+        );
+        // Rows 25-39 and 40-54 of columns 50-84 on the right, 45-49 on the left
+        assert_eq!(analysis.quadrant_coverage, [(36.0 + 75.0) / 2000.0, 525.0 / 2000.0, 75.0 / 2000.0, 525.0 / 2000.0]);
+
+        let nothing = analyze_mask(&mask, 1.0);
+        assert_eq!((nothing.bounds, nothing.centroid, nothing.components.len()), (None, None, 0));
+    }
+
+    #[test]
+    fn centered_subjects_are_found_where_they_are() {
+        // A bright subject in the middle of a dark photo; the stub's mask is
+        // the image's brightness
+        let truth = rect(60, 45, 80, 60);
+        let photo = image::RgbImage::from_fn(200, 150, |x, y| {
+            let inside = (truth.x..truth.x + truth.width).contains(&x) && (truth.y..truth.y + truth.height).contains(&y);
+            image::Rgb(if inside { [240, 230, 220] } else { [20, 25, 30] })
+        });
+        let path = std::env::temp_dir().join(format!("removebg-analysis-centered-{}.png", std::process::id()));
+        photo.save(&path).unwrap();
+
+        let remover = crate::testing::TestModel::Brightness.remover(Default::default()).unwrap();
+        let analysis = remover.analyze_file(&path, 0.5).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let bounds = analysis.bounds.expect("a subject");
+        assert!(iou(bounds, truth) > 0.8, "{:?} vs {:?}", bounds, truth);
+        assert_eq!(analysis.components.len(), 1);
+        let (x, y) = analysis.centroid.unwrap();
+        assert!((x - 100.0).abs() < 2.0 && (y - 75.0).abs() < 2.0, "{:?}", (x, y));
+    }
+
+    #[test]
+    fn warnings_read_as_sentences() {
+        assert_eq!(CoverageWarning::NothingDetected.to_string(), "nothing was detected — output may be empty");
+        assert_eq!(CoverageWarning::from_coverage(0.5), None);
+        assert_eq!(CoverageWarning::from_coverage(f32::NAN), None);
+    }
+}
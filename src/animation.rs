@@ -0,0 +1,242 @@
+//! Animated GIF and APNG input.
+//!
+//! Animations are processed one frame at a time: each frame is decoded onto
+//! the full canvas (applying the source's disposal and blend rules, so delta
+//! frames are complete images), segmented, and handed to the output encoder
+//! before the next frame is read. Memory use therefore stays at a few frames
+//! regardless of the animation's length.
+
+use crate::core::{limit_output_size, limited_size, load_once, process_image};
+use crate::error::{RemoveBgError, Result};
+use crate::geometry;
+use crate::options::{Background, DecodeLimits, RemoveBgOptions};
+use crate::output::{AnimationEncoder, Metadata, OutputFormat};
+use crate::remover::BackgroundRemover;
+use crate::report::{ModelInfo, StageDurations};
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::metadata::LoopCount;
+use image::{AnimationDecoder, DynamicImage, Frames, ImageDecoder};
+use std::io::{BufRead, Seek, Write};
+use std::ops::Deref;
+use web_time::Instant;
+
+const PNG_SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+
+/// Container of an animated input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AnimationKind {
+    Gif,
+    Apng,
+}
+
+impl AnimationKind {
+    /// Output format that keeps the animation in the same container.
+    pub(crate) fn output_format(self) -> OutputFormat {
+        match self {
+            AnimationKind::Gif => OutputFormat::Gif,
+            AnimationKind::Apng => OutputFormat::Apng,
+        }
+    }
+}
+
+/// Result of processing an animation.
+pub(crate) struct Animation {
+    pub dimensions: (u32, u32),
+    /// Frame size after `max_output_size`.
+    pub output_dimensions: (u32, u32),
+    pub frames: u32,
+    /// Mean mask coverage over all frames.
+    pub mask_coverage: f32,
+    /// Lowest confidence score of any frame.
+    pub confidence: f32,
+    pub model: Option<ModelInfo>,
+    /// Solid color every frame was flattened onto, if any.
+    pub background: Option<[u8; 3]>,
+}
+
+/// Detect whether `reader` holds a GIF or APNG with more than one frame.
+///
+/// Still images, single-frame animations, and other formats return `None`, as
+/// do animations exceeding `limits` (the still image path reports those).
+pub(crate) fn detect<R: BufRead + Seek>(mut reader: R, limits: &DecodeLimits) -> Option<AnimationKind> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic).ok()?;
+    reader.rewind().ok()?;
+
+    if magic.starts_with(b"GIF8") {
+        let mut decoder = GifDecoder::new(reader).ok()?;
+        let (width, height) = decoder.dimensions();
+        limits.check(width, height, 4).ok()?;
+        decoder.set_limits(limits.to_image_limits()).ok()?;
+        (decoder.into_frames().take(2).count() == 2).then_some(AnimationKind::Gif)
+    } else if &magic == PNG_SIGNATURE {
+        let reader = png::Decoder::new(reader).read_info().ok()?;
+        let (width, height) = reader.info().size();
+        limits.check(width, height, 4).ok()?;
+        let animated = reader.info().animation_control.is_some_and(|actl| actl.num_frames > 1);
+        animated.then_some(AnimationKind::Apng)
+    } else {
+        None
+    }
+}
+
+/// Open frame decoding for an animation, enforcing `limits`.
+///
+/// Frames are decoded to 8-bit RGBA canvases.
+fn decode<'a, R: BufRead + Seek + 'a>(
+    kind: AnimationKind,
+    reader: R,
+    limits: &DecodeLimits,
+) -> Result<(Frames<'a>, (u32, u32), LoopCount)> {
+    Ok(match kind {
+        AnimationKind::Gif => {
+            let mut decoder = GifDecoder::new(reader)?;
+            let dimensions = decoder.dimensions();
+            limits.check(dimensions.0, dimensions.1, 4)?;
+            decoder.set_limits(limits.to_image_limits())?;
+            let loop_count = decoder.loop_count();
+            (decoder.into_frames(), dimensions, loop_count)
+        }
+        AnimationKind::Apng => {
+            let mut decoder = PngDecoder::new(reader)?;
+            let dimensions = decoder.dimensions();
+            limits.check(dimensions.0, dimensions.1, 4)?;
+            decoder.set_limits(limits.to_image_limits())?;
+            let decoder = decoder.apng()?;
+            let loop_count = decoder.loop_count();
+            (decoder.into_frames(), dimensions, loop_count)
+        }
+    })
+}
+
+/// Number of frames in an animation.
+///
+/// APNG stores the count in its header; GIF has to be decoded once to count.
+fn frame_count<R: BufRead + Seek>(kind: AnimationKind, reader: R, limits: &DecodeLimits) -> Result<u32> {
+    match kind {
+        AnimationKind::Apng => {
+            let reader = png::Decoder::new(reader).read_info().map_err(|e| {
+                RemoveBgError::ProcessingError(format!("Failed to read APNG header: {}", e))
+            })?;
+            Ok(reader.info().animation_control.map_or(1, |actl| actl.num_frames))
+        }
+        AnimationKind::Gif => {
+            let (frames, _, _) = decode(kind, reader, limits)?;
+            Ok(frames.count() as u32)
+        }
+    }
+}
+
+/// Remove the background from every frame of an animation.
+///
+/// `open` must return a fresh reader positioned at the start of the input
+/// each time it is called; GIF input is read twice when the output is APNG,
+/// which needs the frame count before the first frame is written. Frame delays
+/// and the loop count are carried over to the output.
+pub(crate) fn remove_animation<R, W, L>(
+    kind: AnimationKind,
+    open: impl Fn() -> Result<R>,
+    writer: W,
+    format: OutputFormat,
+    options: &RemoveBgOptions,
+    durations: &mut StageDurations,
+    load: impl FnOnce() -> Result<(L, bool)>,
+) -> Result<Animation>
+where
+    R: BufRead + Seek,
+    W: Write,
+    L: Deref<Target = BackgroundRemover>,
+{
+    if options.sticker.is_some() {
+        return Err(RemoveBgError::InvalidOutputFormat(
+            "sticker mode changes the canvas size and cannot be applied to animations".into(),
+        ));
+    }
+    if geometry::is_enabled(&options.geometry) {
+        return Err(RemoveBgError::InvalidOutputFormat(
+            "resizing, squaring and padding cannot be applied to animations".into(),
+        ));
+    }
+    if options.max_file_size.is_some() {
+        return Err(RemoveBgError::InvalidOutputFormat("a maximum file size cannot be applied to animations".into()));
+    }
+    let total = match format {
+        OutputFormat::Apng => Some(frame_count(kind, open()?, &options.limits)?),
+        _ => None,
+    };
+    let (frames, dimensions, loop_count) = decode(kind, open()?, &options.limits)?;
+
+    options.cancel.check()?;
+    let loaded = load_once(options, durations, load)?;
+
+    let output_dimensions = limited_size(dimensions, options)?;
+    let mut encoder = AnimationEncoder::new(
+        writer,
+        format,
+        output_dimensions,
+        total.unwrap_or(0),
+        loop_count,
+        options,
+        &Metadata::default(),
+    )?;
+    let mut frames = frames;
+    let mut count = 0u32;
+    let mut coverage = 0.0f32;
+    let mut confidence = 1.0f32;
+    let mut model = None;
+    // An automatic background color is picked on the first frame and kept,
+    // so the backdrop doesn't change from frame to frame
+    let mut fixed = None;
+    let mut background = None;
+
+    loop {
+        options.cancel.check()?;
+        let mut frame_durations = StageDurations::default();
+
+        let stage = Instant::now();
+        let Some(frame) = frames.next() else { break };
+        let frame = frame?;
+        let delay = frame.delay();
+        let image = limit_output_size(DynamicImage::ImageRgba8(frame.into_buffer()), options)?;
+        frame_durations.decode = stage.elapsed();
+
+        let processed = process_image(&image, fixed.as_ref().unwrap_or(options), &mut frame_durations, || match &loaded {
+            Some((remover, downloaded)) => Ok((&**remover, *downloaded)),
+            None => Err(RemoveBgError::ModelInitError("Model not loaded".into())),
+        })?;
+        coverage += processed.mask_coverage;
+        confidence = confidence.min(processed.confidence);
+        model = model.or(processed.model);
+        background = background.or(processed.background);
+        if let (Background::Auto, None, Some(color)) = (options.background, &fixed, processed.background) {
+            fixed = Some(RemoveBgOptions { background: Background::Color(color), ..options.clone() });
+        }
+
+        let stage = Instant::now();
+        encoder.write_frame(processed.image.into_rgba8(), delay)?;
+        frame_durations.encode = stage.elapsed();
+
+        count += 1;
+        match total {
+            Some(total) => log::debug!("processed frame {}/{}", count, total),
+            None => log::debug!("processed frame {}", count),
+        }
+        *durations += frame_durations;
+    }
+
+    if count == 0 {
+        return Err(RemoveBgError::ProcessingError("Animation has no frames".into()));
+    }
+    encoder.finish()?;
+
+    Ok(Animation {
+        dimensions,
+        output_dimensions,
+        frames: count,
+        mask_coverage: coverage / count as f32,
+        confidence,
+        model,
+        background,
+    })
+}
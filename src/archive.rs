@@ -0,0 +1,455 @@
+//! ZIP archives of images as inputs (`removebg photos.zip`).
+//!
+//! Photo sets often arrive zipped. [`process_zip`] reads the archive's
+//! entries one at a time, runs each image through the in-memory pipeline of
+//! [`remove_background_from_bytes`](crate::remove_background_from_bytes) and
+//! adds the cutout to an output archive, so nothing is extracted to disk and
+//! only one entry is held in memory at a time. Cutouts keep their directory
+//! inside the archive and are named like file outputs (`shoot/a.jpg`
+//! becomes `shoot/a_nobg.png`).
+//!
+//! Archives are read and written with the zip crate behind the `archive`
+//! feature; without it, [`process_zip`] fails with a message saying how to
+//! build it in. Encrypted archives are refused: there is no way to pass a
+//! password, and processing only the unencrypted entries would silently
+//! drop the rest.
+
+use crate::core;
+use crate::error::{ErrorInfo, Result};
+use crate::options::RemoveBgOptions;
+use crate::paths;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+/// How an entry of an archive ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryStatus {
+    /// The cutout was added to the output archive.
+    Done,
+    /// The entry is not an image by its extension and was left out.
+    Skipped,
+    /// The entry could not be read or processed; the run went on with the
+    /// next one.
+    Failed,
+    /// The run was cancelled before the entry was processed.
+    Cancelled,
+}
+
+impl EntryStatus {
+    /// Name used in reports: `ok`, `skipped`, `failed` or `cancelled`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EntryStatus::Done => "ok",
+            EntryStatus::Skipped => "skipped",
+            EntryStatus::Failed => "failed",
+            EntryStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// Outcome of one entry of an archive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveEntry {
+    /// Name of the entry in the input archive, like `shoot/a.jpg`.
+    pub name: String,
+    /// Name of the cutout in the output archive; `None` unless the entry is
+    /// done.
+    pub output: Option<String>,
+    /// How the entry ended.
+    pub status: EntryStatus,
+    /// Why the entry failed.
+    pub error: Option<ErrorInfo>,
+    /// Processing time; zero unless the entry is done.
+    pub elapsed: Duration,
+}
+
+/// Outcome of [`process_zip`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveSummary {
+    /// The archive written.
+    pub output: PathBuf,
+    /// Every file entry of the input archive, in archive order; directory
+    /// entries are not listed.
+    pub entries: Vec<ArchiveEntry>,
+    /// Whether the run was stopped through the cancellation token. The
+    /// output archive holds the cutouts made until then.
+    pub cancelled: bool,
+}
+
+impl ArchiveSummary {
+    /// Number of entries that ended with `status`.
+    pub fn count(&self, status: EntryStatus) -> usize {
+        self.entries.iter().filter(|entry| entry.status == status).count()
+    }
+}
+
+/// Whether `path` names a ZIP archive, by its extension.
+pub fn is_zip(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("zip"))
+}
+
+/// Where [`process_zip`] writes the cutouts of `input` when no output is
+/// given: `<stem>_nobg.zip` next to it, with the prefix and suffix of
+/// `options.naming`.
+pub fn default_output(input: &Path, options: &RemoveBgOptions) -> PathBuf {
+    let stem = input.file_stem().unwrap_or_default();
+    let prefix = options.naming.prefix.as_ref();
+    let suffix = core::output_suffix(options).as_ref();
+    input.with_file_name(paths::file_name([prefix, stem, suffix, ".zip".as_ref()]))
+}
+
+/// Remove the background from every image in a ZIP archive and write the
+/// cutouts into another.
+///
+/// Entries are images by their extension, as in a directory batch; other
+/// entries, and the `__MACOSX/` resource forks macOS adds, are skipped with a
+/// notice. The cutouts keep the directory of their entry and are named from
+/// it like file outputs, in `options.format` (PNG if unset). Entries whose
+/// cutouts would get the same name (`a.jpg` and `a.png`) fail after the
+/// first, unless [`Collision::Rename`](crate::options::Collision::Rename)
+/// numbers them (`a_nobg-1.png`). An entry that is corrupt or fails to
+/// process is recorded in its [`ArchiveEntry`] and logged; the run goes on
+/// with the next.
+///
+/// `output` defaults to [`default_output`]. It is written to a temporary
+/// file that replaces the destination only when the archive is complete, so
+/// a failed run leaves no half-written archive behind. An existing output
+/// is replaced only with `options.overwrite`, or numbered with
+/// `Collision::Rename`.
+///
+/// # Errors
+/// * `FileNotFound` - If the input archive does not exist
+/// * `ArchiveError` - If the archive is corrupt or password-protected, or
+///   removebg was built without the `archive` feature
+/// * `OutputExists` - If the output exists and overwriting was not requested
+/// * `OutputDirectoryMissing` - If the output's directory doesn't exist and
+///   `options.create_dirs` is false
+/// * `ModelInitError` - If the model cannot be loaded
+/// * `IoError` - If the output archive cannot be written
+///
+/// # Examples
+/// ```no_run
+/// use removebg::{archive, RemoveBgOptions};
+/// use std::path::Path;
+///
+/// let summary = archive::process_zip(Path::new("shoot.zip"), None, &RemoveBgOptions::default())?;
+/// println!("{} cutouts in {}", summary.count(archive::EntryStatus::Done), summary.output.display());
+/// # Ok::<(), removebg::error::RemoveBgError>(())
+/// ```
+pub fn process_zip(input: &Path, output: Option<&Path>, options: &RemoveBgOptions) -> Result<ArchiveSummary> {
+    #[cfg(feature = "archive")]
+    return zipped::process_zip(input, output, options);
+    #[cfg(not(feature = "archive"))]
+    {
+        let _ = (input, output, options);
+        Err(crate::error::RemoveBgError::ArchiveError(
+            "ZIP archives need removebg built with the `archive` feature (cargo install removebg --features archive)"
+                .into(),
+        ))
+    }
+}
+
+/// Whether an entry is processed: an image by its extension, outside the
+/// resource forks macOS stores under `__MACOSX/`.
+#[cfg_attr(not(feature = "archive"), allow(dead_code))]
+fn is_image_entry(name: &str) -> bool {
+    !name.starts_with("__MACOSX/") && crate::watch::is_image_file(Path::new(name))
+}
+
+/// The name of an entry in an archive for a relative `path`, with `/`
+/// separators whatever the platform's.
+#[cfg_attr(not(feature = "archive"), allow(dead_code))]
+fn entry_name(path: &Path) -> String {
+    let parts: Vec<_> = path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect();
+    parts.join("/")
+}
+
+/// `name`, or if `taken` holds it, the first of `<stem>-1.<ext>`,
+/// `<stem>-2.<ext>`, ... that it doesn't.
+#[cfg_attr(not(feature = "archive"), allow(dead_code))]
+fn numbered_name(name: &str, taken: &dyn Fn(&str) -> bool) -> String {
+    if !taken(name) {
+        return name.to_string();
+    }
+    let (dir, file) = name.rsplit_once('/').map_or(("", name), |(dir, file)| (dir, file));
+    let (stem, extension) = match file.rfind('.') {
+        Some(dot) if dot > 0 => file.split_at(dot),
+        _ => (file, ""),
+    };
+    (1u64..)
+        .map(|n| match dir.is_empty() {
+            true => format!("{}-{}{}", stem, n, extension),
+            false => format!("{}/{}-{}{}", dir, stem, n, extension),
+        })
+        .find(|candidate| !taken(candidate))
+        .expect("some number is free")
+}
+
+#[cfg(feature = "archive")]
+mod zipped {
+    use super::{entry_name, is_image_entry, numbered_name, ArchiveEntry, ArchiveSummary, EntryStatus};
+    use crate::core::{self, output_file_name, replaces_existing};
+    use crate::error::{self, ErrorInfo, RemoveBgError, Result};
+    use crate::options::{Collision, RemoveBgOptions};
+    use crate::output::{self, AtomicFile, OutputFormat};
+    use crate::progress::LOG_TARGET;
+    use std::collections::HashSet;
+    use std::fs::File;
+    use std::io::{BufReader, Read, Seek, Write};
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+    use web_time::Instant;
+    use zip::result::ZipError;
+    use zip::write::SimpleFileOptions;
+    use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+    /// Largest buffer reserved up front for an entry, whatever size its
+    /// header claims.
+    const MAX_RESERVE: u64 = 64 << 20;
+
+    /// What the central directory says about an entry.
+    struct Header {
+        name: String,
+        is_dir: bool,
+        /// The name as a relative path, `None` if it would escape the
+        /// archive (`../a.jpg`, `/etc/a.jpg`).
+        path: Option<PathBuf>,
+        size: u64,
+        modified: Option<zip::DateTime>,
+    }
+
+    pub(super) fn process_zip(input: &Path, output: Option<&Path>, options: &RemoveBgOptions) -> Result<ArchiveSummary> {
+        let file = match File::open(input) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(RemoveBgError::FileNotFound(input.to_path_buf()))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        // Opening reads only the central directory; entries are read when processed
+        let mut archive = ZipArchive::new(BufReader::new(file)).map_err(|e| archive_error(input, e))?;
+        let headers = (0..archive.len())
+            .map(|index| {
+                let entry = archive.by_index_raw(index).map_err(|e| archive_error(input, e))?;
+                if entry.encrypted() {
+                    return Err(password_protected(input));
+                }
+                Ok(Header {
+                    name: entry.name().to_string(),
+                    is_dir: entry.is_dir(),
+                    path: entry.enclosed_name(),
+                    size: entry.size(),
+                    modified: entry.last_modified(),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let output = match output {
+            Some(output) => output.to_path_buf(),
+            None => super::default_output(input, options),
+        };
+        let output = check_output(input, output, options)?;
+        let mut writer = ZipWriter::new(AtomicFile::create(&output)?);
+        let mut summary = ArchiveSummary { output, entries: Vec::new(), cancelled: false };
+        let mut taken = HashSet::new();
+        let format = output::selected_format(options, OutputFormat::default());
+
+        for (index, header) in headers.into_iter().enumerate() {
+            if header.is_dir {
+                continue;
+            }
+            let mut entry = ArchiveEntry {
+                name: header.name,
+                output: None,
+                status: EntryStatus::Skipped,
+                error: None,
+                elapsed: Duration::ZERO,
+            };
+            if !is_image_entry(&entry.name) {
+                log::info!("{}: skipping {}, not an image", input.display(), entry.name);
+                summary.entries.push(entry);
+                continue;
+            }
+            if summary.cancelled || options.cancel.is_cancelled() {
+                summary.cancelled = true;
+                entry.status = EntryStatus::Cancelled;
+                summary.entries.push(entry);
+                continue;
+            }
+
+            let start = Instant::now();
+            let result = cutout_name(header.path.as_deref(), format, &taken, options).and_then(|name| {
+                let data = read_entry(&mut archive, index, header.size)?;
+                let encoded = core::remove_bytes(&data, options, || core::shared_remover(options))?;
+                Ok((name, encoded))
+            });
+            match result {
+                Ok((name, encoded)) => {
+                    let mut file_options = SimpleFileOptions::default()
+                        .compression_method(compression(format))
+                        .large_file(encoded.len() as u64 >= u64::from(u32::MAX));
+                    if let Some(modified) = header.modified {
+                        file_options = file_options.last_modified_time(modified);
+                    }
+                    writer.start_file(name.as_str(), file_options).map_err(|e| archive_error(&summary.output, e))?;
+                    writer.write_all(&encoded)?;
+                    log::info!(target: LOG_TARGET, "{} -> {}", entry.name, name);
+                    taken.insert(name.clone());
+                    entry.output = Some(name);
+                    entry.status = EntryStatus::Done;
+                    entry.elapsed = start.elapsed();
+                }
+                Err(e) if matches!(e.root(), RemoveBgError::Cancelled) => {
+                    summary.cancelled = true;
+                    entry.status = EntryStatus::Cancelled;
+                }
+                Err(e) if matches!(e.root(), RemoveBgError::ModelInitError(_) | RemoveBgError::ModelNotCached { .. }) => {
+                    return Err(e)
+                }
+                Err(e) => {
+                    let e = e.for_input(Path::new(&entry.name));
+                    log::warn!("{}", error::describe(Path::new(&entry.name), &e));
+                    entry.status = EntryStatus::Failed;
+                    entry.error = Some(ErrorInfo::from(&e));
+                }
+            }
+            summary.entries.push(entry);
+        }
+
+        let file = writer.finish().map_err(|e| archive_error(&summary.output, e))?;
+        file.commit()?;
+        Ok(summary)
+    }
+
+    /// The output path to write: `output`, or a numbered one next to it if
+    /// it exists and `options.naming` asks for renaming.
+    fn check_output(input: &Path, output: PathBuf, options: &RemoveBgOptions) -> Result<PathBuf> {
+        output::prepare_output_dir(&output, options.create_dirs)?;
+        match output.exists() {
+            false => Ok(output),
+            true if options.naming.on_collision == Collision::Rename => Ok(core::free_path(output, &|_| false)),
+            true if core::is_same_file(input, &output) => Err(RemoveBgError::InvalidOutputFormat(format!(
+                "{} would be written over its input; set a prefix or suffix for output names",
+                output.display()
+            ))),
+            true if replaces_existing(options) => Ok(output),
+            true => Err(RemoveBgError::OutputExists(output)),
+        }
+    }
+
+    /// The cutout's name in the output archive for an entry at `path`.
+    fn cutout_name(
+        path: Option<&Path>,
+        format: OutputFormat,
+        taken: &HashSet<String>,
+        options: &RemoveBgOptions,
+    ) -> Result<String> {
+        let path = path.ok_or_else(|| {
+            RemoveBgError::ArchiveError("the entry's path leads out of the archive; it was not processed".into())
+        })?;
+        let stem = path.file_stem().unwrap_or_default();
+        let name = entry_name(&path.with_file_name(output_file_name(stem, format, options)));
+        match taken.contains(&name) {
+            false => Ok(name),
+            true if options.naming.on_collision == Collision::Rename => {
+                Ok(numbered_name(&name, &|candidate| taken.contains(candidate)))
+            }
+            true => Err(RemoveBgError::OutputExists(PathBuf::from(name))),
+        }
+    }
+
+    /// The uncompressed bytes of entry `index`. A damaged entry fails with
+    /// `ArchiveError`, as its checksum or compressed data is found to be
+    /// wrong.
+    fn read_entry<R: Read + Seek>(archive: &mut ZipArchive<R>, index: usize, size: u64) -> Result<Vec<u8>> {
+        let damaged = |e: &dyn std::fmt::Display| RemoveBgError::ArchiveError(format!("cannot read the entry: {}", e));
+        let mut entry = archive.by_index(index).map_err(|e| damaged(&e))?;
+        let mut data = Vec::with_capacity(size.min(MAX_RESERVE) as usize);
+        entry.read_to_end(&mut data).map_err(|e| damaged(&e))?;
+        Ok(data)
+    }
+
+    /// Stored for formats that are compressed already, deflated otherwise.
+    fn compression(format: OutputFormat) -> CompressionMethod {
+        match format {
+            OutputFormat::Tiff | OutputFormat::Bmp => CompressionMethod::Deflated,
+            _ => CompressionMethod::Stored,
+        }
+    }
+
+    fn password_protected(input: &Path) -> RemoveBgError {
+        RemoveBgError::ArchiveError(format!(
+            "{} is password-protected, which removebg can't decrypt; extract it first",
+            input.display()
+        ))
+    }
+
+    fn archive_error(path: &Path, error: ZipError) -> RemoveBgError {
+        match error {
+            ZipError::Io(e) => e.into(),
+            ZipError::UnsupportedArchive(message) if message == ZipError::PASSWORD_REQUIRED => {
+                password_protected(path)
+            }
+            error => RemoveBgError::ArchiveError(format!("{}: {}", path.display(), error)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::NamingOptions;
+
+    #[test]
+    fn zip_archives_are_told_by_extension() {
+        assert!(is_zip(Path::new("shoot.zip")));
+        assert!(is_zip(Path::new("dir/SHOOT.ZIP")));
+        assert!(!is_zip(Path::new("shoot.zip.png")));
+        assert!(!is_zip(Path::new("zip")));
+    }
+
+    #[test]
+    fn outputs_default_to_a_sibling_archive() {
+        let options = RemoveBgOptions::default();
+        assert_eq!(default_output(Path::new("in/shoot.zip"), &options), Path::new("in/shoot_nobg.zip"));
+        let naming = NamingOptions { prefix: "cut-".into(), suffix: Some(String::new()), ..Default::default() };
+        let options = RemoveBgOptions { naming, ..Default::default() };
+        assert_eq!(default_output(Path::new("shoot.zip"), &options), Path::new("cut-shoot.zip"));
+    }
+
+    #[test]
+    fn entries_are_images_outside_resource_forks() {
+        assert!(is_image_entry("a.jpg"));
+        assert!(is_image_entry("shoot/b.PNG"));
+        assert!(!is_image_entry("notes.txt"));
+        assert!(!is_image_entry("__MACOSX/shoot/._a.jpg"));
+    }
+
+    #[test]
+    fn entry_names_use_forward_slashes() {
+        assert_eq!(entry_name(&Path::new("shoot").join("day 1").join("a.png")), "shoot/day 1/a.png");
+        assert_eq!(entry_name(Path::new("a.png")), "a.png");
+    }
+
+    #[test]
+    fn taken_names_are_numbered() {
+        let taken = ["a_nobg.png", "a_nobg-1.png", "shoot/b_nobg.png"];
+        let is_taken = |name: &str| taken.contains(&name);
+        assert_eq!(numbered_name("c_nobg.png", &is_taken), "c_nobg.png");
+        assert_eq!(numbered_name("a_nobg.png", &is_taken), "a_nobg-2.png");
+        assert_eq!(numbered_name("shoot/b_nobg.png", &is_taken), "shoot/b_nobg-1.png");
+    }
+
+    #[cfg(not(feature = "archive"))]
+    #[test]
+    fn the_missing_feature_is_explained() {
+        let error = process_zip(Path::new("shoot.zip"), None, &RemoveBgOptions::default()).unwrap_err();
+        assert!(matches!(error, crate::error::RemoveBgError::ArchiveError(ref message) if message.contains("--features archive")));
+    }
+}
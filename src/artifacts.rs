@@ -0,0 +1,353 @@
+//! Additional outputs from one model run, for
+//! [`outputs`](crate::RemoveBgOptions::outputs) and `--emit`.
+//!
+//! Each [`OutputSpec`] names a kind of image (cutout, mask, flattened,
+//! overlay, thumbnail, preview) and where to write it. Paths are resolved with the main output,
+//! before any image work, so collisions and existing files are reported
+//! early. The images are derived from the finished mask and a transparent
+//! composite after the main output is saved; one that fails to encode or
+//! write is recorded in its [`Artifact`] and the rest are still written.
+
+use crate::core::{flatten_onto, Gray16Image};
+use crate::error::{RemoveBgError, Result};
+use crate::options::{Background, OutputKind, OutputSpec, RemoveBgOptions};
+use crate::output::{self, AtomicFile, Metadata, OutputFormat};
+use crate::paths;
+use crate::preview;
+use crate::report::Artifact;
+use crate::{blur, core};
+use image::{DynamicImage, ImageBuffer, Luma};
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+
+/// Longest side of thumbnails when `options.thumbnail_size` is `None`.
+const DEFAULT_THUMBNAIL_SIZE: u32 = 256;
+
+/// Template used when an [`OutputSpec`] has no path.
+const DEFAULT_TEMPLATE: &str = "{dir}/{stem}_{kind}";
+
+/// Placeholders a path template may contain.
+const PLACEHOLDERS: [&str; 3] = ["{stem}", "{dir}", "{kind}"];
+
+/// Background of flattened outputs when `options.background` has no color.
+const DEFAULT_FLATTEN_COLOR: [u8; 3] = [255, 255, 255];
+
+/// Check that `template` only uses known placeholders.
+pub(crate) fn check_template(template: &str) -> std::result::Result<(), String> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let placeholder = PLACEHOLDERS.iter().find(|p| rest[start..].starts_with(**p)).ok_or_else(|| {
+            format!("unknown placeholder in path template '{}' (expected {{stem}}, {{dir}} or {{kind}})", template)
+        })?;
+        rest = &rest[start + placeholder.len()..];
+    }
+    Ok(())
+}
+
+/// Expand `template` for one input, keeping non-UTF-8 stems and directories
+/// intact. An empty `dir` is the current directory, and `{dir}/` leaves no
+/// `./` behind.
+fn expand(template: &str, stem: &OsStr, dir: &Path, kind: OutputKind) -> Result<PathBuf> {
+    check_template(template).map_err(RemoveBgError::InvalidOutputFormat)?;
+    let current = dir.as_os_str().is_empty();
+    let dir = if current { OsStr::new(".") } else { dir.as_os_str() };
+    let mut path = OsString::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        path.push(&rest[..start]);
+        let placeholder = PLACEHOLDERS.iter().find(|p| rest[start..].starts_with(**p)).expect("checked above");
+        match *placeholder {
+            "{stem}" => path.push(stem),
+            "{dir}" => path.push(dir),
+            _ => path.push(kind.name()),
+        }
+        rest = &rest[start + placeholder.len()..];
+    }
+    path.push(rest);
+    let path = PathBuf::from(path);
+    match current {
+        true => Ok(path.strip_prefix(".").map(Path::to_path_buf).unwrap_or(path)),
+        false => Ok(path),
+    }
+}
+
+/// Where and how one [`OutputSpec`] is written for one input.
+pub(crate) struct ArtifactTarget {
+    pub kind: OutputKind,
+    pub path: PathBuf,
+    pub format: OutputFormat,
+    pub quality: Option<f32>,
+}
+
+/// Resolve the path and format of every output in `options.outputs` for
+/// `input_file`, whose main output goes to `output_path`.
+///
+/// An explicit format replaces a mismatched extension; otherwise a
+/// recognized extension selects the format, and a path without one gets the
+/// kind's default format and its extension appended.
+///
+/// # Errors
+/// * `InvalidOutputFormat` - If a template is invalid, a cutout would be
+///   written in a format without alpha, or a flattened output is combined
+///   with premultiplied alpha
+pub(crate) fn resolve(input_file: &Path, output_path: &Path, options: &RemoveBgOptions) -> Result<Vec<ArtifactTarget>> {
+    if options.outputs.is_empty() {
+        return Ok(Vec::new());
+    }
+    let stem = input_file.file_stem()
+        .ok_or_else(|| RemoveBgError::ProcessingError("Invalid input filename".into()))?;
+    let dir = output_path.parent().unwrap_or(Path::new(""));
+
+    options.outputs.iter().map(|spec| {
+        let template = spec.path.as_deref().unwrap_or(DEFAULT_TEMPLATE);
+        let path = expand(template, stem, dir, spec.kind)?;
+        let extension = path.extension().and_then(|s| s.to_str()).unwrap_or_default();
+        let (path, format) = match (spec.format, OutputFormat::from_extension(extension)) {
+            (Some(format), _) if format.matches_extension(extension) => (path, format),
+            (Some(format), Some(_)) => (path.with_extension(format.extension()), format),
+            (format, None) => {
+                let format = format.unwrap_or(spec.kind.default_format());
+                let extension = format!(".{}", format.extension());
+                (PathBuf::from(paths::file_name([path.as_os_str(), extension.as_ref()])), format)
+            }
+            (None, Some(detected)) => (path, detected),
+        };
+        check(spec, format, options)?;
+        Ok(ArtifactTarget { kind: spec.kind, path, format, quality: spec.quality.or(options.quality) })
+    })
+    .collect()
+}
+
+fn check(spec: &OutputSpec, format: OutputFormat, options: &RemoveBgOptions) -> Result<()> {
+    if matches!(spec.kind, OutputKind::Cutout | OutputKind::Thumbnail) && !format.supports_alpha() {
+        return Err(RemoveBgError::InvalidOutputFormat(format!(
+            "{} output needs a format with transparency, not {}; use a flattened output for {}",
+            spec.kind,
+            format.extension().to_ascii_uppercase(),
+            format.extension().to_ascii_uppercase()
+        )));
+    }
+    if spec.kind == OutputKind::Preview && format != OutputFormat::Gif {
+        return Err(RemoveBgError::InvalidOutputFormat(format!(
+            "preview output is an animated GIF, not {}",
+            format.extension().to_ascii_uppercase()
+        )));
+    }
+    if spec.kind == OutputKind::Flattened && options.premultiplied {
+        return Err(RemoveBgError::InvalidOutputFormat(
+            "flattened outputs cannot be combined with premultiplied alpha".into(),
+        ));
+    }
+    Ok(())
+}
+
+/// Whether `path` is named like an output of `options.outputs` next to its
+/// input: the template's file name after `{stem}`, with `{kind}` filled in,
+/// ends the name (or the stem, for templates without an extension).
+/// Templates whose file name doesn't start with `{stem}` can't be recognized.
+pub(crate) fn is_artifact_name(path: &Path, options: &RemoveBgOptions) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy().to_lowercase();
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy().to_lowercase();
+    options.outputs.iter().any(|spec| {
+        let template = spec.path.as_deref().unwrap_or(DEFAULT_TEMPLATE);
+        let file_name = template.rsplit(['/', '\\']).next().unwrap_or(template);
+        let Some(suffix) = file_name.strip_prefix("{stem}") else {
+            return false;
+        };
+        let suffix = suffix.replace("{kind}", spec.kind.name()).to_lowercase();
+        if suffix.is_empty() || suffix.contains('{') {
+            return false;
+        }
+        let has_extension = Path::new(&suffix).extension().and_then(|s| s.to_str()).and_then(OutputFormat::from_extension).is_some();
+        match has_extension {
+            true => name.ends_with(&suffix) && name.len() > suffix.len(),
+            false => stem.ends_with(&suffix) && stem.len() > suffix.len(),
+        }
+    })
+}
+
+/// Whether the main output is already the transparent cutout, so the
+/// outputs can share it instead of compositing again.
+pub(crate) fn main_is_transparent(options: &RemoveBgOptions) -> bool {
+    options.background == Background::Transparent && options.chroma_key.is_none() && !blur::keeps_background(options)
+}
+
+/// Whether `options.outputs` needs the transparent cutout.
+pub(crate) fn needs_cutout(options: &RemoveBgOptions) -> bool {
+    options.outputs.iter().any(|spec| spec.kind != OutputKind::Overlay)
+}
+
+/// Whether `options.outputs` needs the finished mask at the input's size.
+pub(crate) fn needs_mask(options: &RemoveBgOptions) -> bool {
+    options.outputs.iter().any(|spec| spec.kind == OutputKind::Overlay)
+}
+
+/// Whether `options.outputs` needs the input as processed.
+pub(crate) fn needs_input(options: &RemoveBgOptions) -> bool {
+    options.outputs.iter().any(|spec| matches!(spec.kind, OutputKind::Overlay | OutputKind::Preview))
+}
+
+/// The transparent cutout for `options.outputs`: the main output's
+/// composite without a background color, chroma key or kept background.
+pub(crate) fn transparent_cutout(image: &DynamicImage, mask: &Gray16Image, options: &RemoveBgOptions) -> Result<DynamicImage> {
+    let options = RemoveBgOptions {
+        background: Background::Transparent,
+        chroma_key: None,
+        blur_background: None,
+        grayscale_background: false,
+        ..options.clone()
+    };
+    core::compose(image, mask, &options).map(|(cutout, _)| cutout)
+}
+
+/// Images the outputs are made from.
+pub(crate) struct Sources<'a> {
+    /// The input as processed, kept when an output needs it.
+    pub input: Option<&'a DynamicImage>,
+    /// The transparent cutout; see [`transparent_cutout`].
+    pub cutout: Option<&'a DynamicImage>,
+    /// The finished mask at the input's size.
+    pub mask: Option<&'a Gray16Image>,
+    /// Solid color the main output was flattened onto, if any.
+    pub background: Option<[u8; 3]>,
+}
+
+/// Write every target, continuing past failures, and report each one.
+pub(crate) fn write(
+    targets: Vec<ArtifactTarget>,
+    sources: &Sources,
+    metadata: &Metadata,
+    options: &RemoveBgOptions,
+) -> Vec<Artifact> {
+    targets
+        .into_iter()
+        .map(|target| {
+            let error = write_one(&target, sources, metadata, options).err().map(|e| e.to_string());
+            Artifact { kind: target.kind, path: target.path, error }
+        })
+        .collect()
+}
+
+fn write_one(target: &ArtifactTarget, sources: &Sources, metadata: &Metadata, options: &RemoveBgOptions) -> Result<()> {
+    let missing = || RemoveBgError::ProcessingError(format!("no image was kept for the {} output", target.kind));
+    let image = match target.kind {
+        OutputKind::Cutout => sources.cutout.ok_or_else(missing)?.clone(),
+        OutputKind::Mask => alpha_channel(sources.cutout.ok_or_else(missing)?),
+        OutputKind::Flattened => {
+            let color = options.chroma_key.or(sources.background).unwrap_or(DEFAULT_FLATTEN_COLOR);
+            let color = color.map(|c| c as f32 / 255.0);
+            let mut image = sources.cutout.ok_or_else(missing)?.clone();
+            match &mut image {
+                DynamicImage::ImageRgba8(image) => flatten_onto(image, |_, _| color),
+                DynamicImage::ImageRgba16(image) => flatten_onto(image, |_, _| color),
+                _ => {}
+            }
+            image
+        }
+        OutputKind::Overlay => {
+            let mask = DynamicImage::ImageLuma16(sources.mask.ok_or_else(missing)?.clone()).into_luma8();
+            DynamicImage::ImageRgb8(preview::overlay(&sources.input.ok_or_else(missing)?.to_rgb8(), &mask))
+        }
+        OutputKind::Thumbnail => {
+            let size = options.thumbnail_size.unwrap_or(DEFAULT_THUMBNAIL_SIZE);
+            DynamicImage::ImageRgba8(preview::make_thumbnail(&sources.cutout.ok_or_else(missing)?.to_rgba8(), size))
+        }
+        OutputKind::Preview => {
+            let input = sources.input.ok_or_else(missing)?.to_rgba8();
+            let cutout = sources.cutout.ok_or_else(missing)?.to_rgba8();
+            let size = options.preview_size.unwrap_or(preview::DEFAULT_PREVIEW_SIZE);
+            let delay = options.preview_delay.unwrap_or(preview::DEFAULT_PREVIEW_DELAY);
+            let mut file = AtomicFile::create(&target.path)?;
+            preview::before_after_gif(&input, &cutout, size, delay, &mut file)?;
+            return file.commit();
+        }
+    };
+    // The mask is not a photo, so it doesn't carry the input's color profile
+    let stripped = Metadata::default();
+    let metadata = if target.kind == OutputKind::Mask { &stripped } else { metadata };
+    let options = RemoveBgOptions { quality: target.quality, encoder: None, ..options.clone() };
+    output::save_image(&image, &target.path, target.format, &options, metadata)
+}
+
+/// The alpha channel of an RGBA image as grayscale, at the same depth.
+fn alpha_channel(image: &DynamicImage) -> DynamicImage {
+    match image {
+        DynamicImage::ImageRgba16(image) => DynamicImage::ImageLuma16(ImageBuffer::from_fn(
+            image.width(),
+            image.height(),
+            |x, y| Luma([image.get_pixel(x, y)[3]]),
+        )),
+        image => {
+            let image = image.to_rgba8();
+            DynamicImage::ImageLuma8(ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+                Luma([image.get_pixel(x, y)[3]])
+            }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(specs: &[&str]) -> RemoveBgOptions {
+        let outputs = specs.iter().map(|spec| spec.parse().unwrap()).collect();
+        RemoveBgOptions { outputs, ..Default::default() }
+    }
+
+    fn resolved(specs: &[&str], output: &str) -> Vec<(PathBuf, OutputFormat)> {
+        resolve(Path::new("in/photo.v2.jpg"), Path::new(output), &options(specs))
+            .unwrap()
+            .into_iter()
+            .map(|target| (target.path, target.format))
+            .collect()
+    }
+
+    #[test]
+    fn default_templates_follow_the_main_output() {
+        let targets = resolved(&["cutout", "mask", "flattened", "overlay"], "out/photo.v2_nobg.png");
+        assert_eq!(
+            targets,
+            [
+                (PathBuf::from("out/photo.v2_cutout.png"), OutputFormat::Png),
+                (PathBuf::from("out/photo.v2_mask.png"), OutputFormat::Png),
+                (PathBuf::from("out/photo.v2_flattened.jpg"), OutputFormat::Jpeg),
+                (PathBuf::from("out/photo.v2_overlay.png"), OutputFormat::Png),
+            ]
+        );
+        assert_eq!(resolved(&["mask"], "photo_nobg.png"), [(PathBuf::from("photo.v2_mask.png"), OutputFormat::Png)]);
+    }
+
+    #[test]
+    fn templates_select_formats_by_extension() {
+        let targets = resolved(&["flattened@80:{dir}/white/{stem}.webp", "mask:masks/{stem}-{kind}"], "out/x.png");
+        assert_eq!(
+            targets,
+            [
+                (PathBuf::from("out/white/photo.v2.webp"), OutputFormat::WebP),
+                (PathBuf::from("masks/photo.v2-mask.png"), OutputFormat::Png),
+            ]
+        );
+        let spec: OutputSpec = "flattened@80:x.webp".parse().unwrap();
+        assert_eq!(spec.quality, Some(80.0));
+    }
+
+    #[test]
+    fn invalid_specs_are_rejected() {
+        assert!("shadow".parse::<OutputSpec>().unwrap_err().contains("invalid output kind"));
+        assert!("mask:{name}.png".parse::<OutputSpec>().unwrap_err().contains("unknown placeholder"));
+        assert!("flattened@101".parse::<OutputSpec>().unwrap_err().contains("invalid quality"));
+        let error = resolve(Path::new("a.png"), Path::new("a_nobg.png"), &options(&["cutout:{stem}.jpg"]));
+        assert!(matches!(error, Err(RemoveBgError::InvalidOutputFormat(_))));
+    }
+
+    #[test]
+    fn earlier_outputs_are_recognized() {
+        let options = options(&["mask", "flattened:{dir}/{stem}_white.jpg", "overlay:previews/{kind}.png"]);
+        assert!(is_artifact_name(Path::new("shoot/a_mask.png"), &options));
+        assert!(is_artifact_name(Path::new("shoot/a_white.JPG"), &options));
+        assert!(!is_artifact_name(Path::new("shoot/a_white.png"), &options));
+        assert!(!is_artifact_name(Path::new("shoot/overlay.png"), &options));
+        assert!(!is_artifact_name(Path::new("shoot/_mask.png"), &options));
+    }
+}
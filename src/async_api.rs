@@ -0,0 +1,99 @@
+//! Async background removal for tokio applications.
+//!
+//! Available with the `async` cargo feature. Model downloads use an async HTTP
+//! client, and the CPU-heavy decoding, inference, and encoding run on tokio's
+//! blocking thread pool so runtime workers stay responsive.
+
+use crate::core::{remove_background_from_bytes, remove_background_with_options};
+use crate::error::{RemoveBgError, Result};
+use crate::model;
+use crate::options::RemoveBgOptions;
+use std::path::{Path, PathBuf};
+
+/// Remove background from an image without blocking the async runtime.
+///
+/// Async counterpart of [`remove_background_with_options`]. Must be called
+/// from within a tokio runtime.
+///
+/// # Arguments
+/// * `input_path` - Path to the input image file.
+/// * `output_path` - Optional path to save the output image (see [`crate::remove_background`]).
+/// * `options` - Options controlling model execution.
+///
+/// # Returns
+/// The path to the output file that was created.
+///
+/// # Errors
+/// Same as [`crate::remove_background`].
+///
+/// # Examples
+/// ```no_run
+/// use removebg::{remove_background_async, RemoveBgOptions};
+///
+/// # async fn run() -> removebg::Result<()> {
+/// let output = remove_background_async("photo.jpg", None, &RemoveBgOptions::default()).await?;
+/// println!("Saved to: {}", output.display());
+/// # Ok(())
+/// # }
+/// ```
+pub async fn remove_background_async(
+    input_path: impl AsRef<Path>,
+    output_path: Option<&Path>,
+    options: &RemoveBgOptions,
+) -> Result<PathBuf> {
+    if model::embedded_model(options.model).is_none() {
+        model::ensure_model_async(
+            options.model,
+            options.model_dir.as_deref(),
+            &options.download,
+            &options.download_progress,
+            &options.cancel,
+        )
+        .await?;
+    }
+
+    let input_path = input_path.as_ref().to_path_buf();
+    let output_path = output_path.map(Path::to_path_buf);
+    let options = options.clone();
+    run_blocking(move || {
+        remove_background_with_options(&input_path, output_path.as_deref(), &options)
+    })
+    .await
+}
+
+/// Remove background from an in-memory encoded image without blocking the async runtime.
+///
+/// Async counterpart of [`remove_background_from_bytes`]. Must be called from
+/// within a tokio runtime.
+///
+/// # Errors
+/// Same as [`remove_background_from_bytes`].
+pub async fn remove_background_from_bytes_async(
+    data: Vec<u8>,
+    options: &RemoveBgOptions,
+) -> Result<Vec<u8>> {
+    if model::embedded_model(options.model).is_none() {
+        model::ensure_model_async(
+            options.model,
+            options.model_dir.as_deref(),
+            &options.download,
+            &options.download_progress,
+            &options.cancel,
+        )
+        .await?;
+    }
+
+    let options = options.clone();
+    run_blocking(move || remove_background_from_bytes(&data, &options)).await
+}
+
+/// Run a blocking closure on tokio's blocking thread pool.
+async fn run_blocking<T, F>(f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f).await.map_err(|e| {
+        RemoveBgError::ProcessingError(format!("Background removal task failed: {}", e))
+    })?
+}
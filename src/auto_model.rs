@@ -0,0 +1,132 @@
+//! Automatic model choice per image (`--model auto`, see [`AutoModel`]).
+//!
+//! The small u2netp runs first, and the shape and confidence of its mask
+//! decide whether a larger model is worth running: people get the model
+//! trained on them, and unclear masks the full general-purpose model.
+
+use crate::analysis;
+use crate::core::Gray16Image;
+use crate::error::Result;
+use crate::model::Model;
+use crate::options::{AutoModel, ConfidenceOptions};
+
+/// Model every image is segmented with first.
+pub(crate) const FIRST: Model = Model::U2netp;
+
+/// Confidence below which a mask is redone with the full model.
+const MIN_CONFIDENCE: f32 = 0.5;
+
+/// Share of the image a person's mask may cover, from a distant full-body
+/// shot to a close head-and-shoulders portrait.
+const PERSON_COVERAGE: std::ops::RangeInclusive<f32> = 0.03..=0.7;
+
+/// Largest distance of a person's horizontal center from the middle of the
+/// image, as a share of its width.
+const PERSON_OFF_CENTER: f32 = 0.2;
+
+/// What to do after running `model`.
+pub(crate) enum Decision {
+    /// Run this model, for this reason.
+    Rerun(Model, String),
+    /// Keep the mask of the last model, for this reason if there is more to
+    /// it than the reason the model was run.
+    Keep(Option<String>),
+}
+
+/// Whether `mask` is shaped like a person: a region at least as tall as it
+/// is wide, horizontally centered and cut off by the bottom edge, as people
+/// are in portraits and most photos of them.
+pub(crate) fn looks_like_person(mask: &Gray16Image) -> bool {
+    let (width, height) = mask.dimensions();
+    let (mut left, mut top, mut right, mut bottom) = (width, height, 0, 0);
+    let mut kept = 0u64;
+    for (x, y, alpha) in mask.enumerate_pixels() {
+        if alpha[0] >= u16::MAX / 2 {
+            kept += 1;
+            (left, top, right, bottom) = (left.min(x), top.min(y), right.max(x + 1), bottom.max(y + 1));
+        }
+    }
+    let coverage = kept as f32 / (width as f32 * height as f32).max(1.0);
+    if !PERSON_COVERAGE.contains(&coverage) {
+        return false;
+    }
+    // Within a pixel or two of the bottom edge, allowing for a soft border
+    let reaches_bottom = bottom + height / 50 >= height;
+    let center = (left + right) as f32 / 2.0 / width as f32;
+    reaches_bottom && (center - 0.5).abs() <= PERSON_OFF_CENTER && bottom - top >= right - left
+}
+
+/// Decide what follows the mask `mask` of `model`, the `run`th model on the
+/// image, under `auto`.
+pub(crate) fn decide(
+    model: Model,
+    mask: &Gray16Image,
+    run: u32,
+    auto: AutoModel,
+    confidence: &ConfidenceOptions,
+) -> Result<Decision> {
+    let score = analysis::confidence(mask, confidence)?;
+    let next = match model {
+        FIRST if looks_like_person(mask) => Some((Model::U2netHumanSeg, format!("{} mask looks like a person", model))),
+        Model::U2net => None,
+        _ if score < MIN_CONFIDENCE => {
+            Some((Model::U2net, format!("{} mask confidence {:.2} is below {}", model, score, MIN_CONFIDENCE)))
+        }
+        _ => None,
+    };
+    Ok(match next {
+        Some((next, reason)) if run < auto.max_models => Decision::Rerun(next, reason),
+        Some((_, reason)) => Decision::Keep(Some(format!("{}, but at most {} model(s) may run", reason, auto.max_models))),
+        None if model == FIRST => Decision::Keep(Some(format!("{} mask confidence {:.2} is high enough", model, score))),
+        None => Decision::Keep(None),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    /// A mask keeping the rectangle from (`x0`, `y0`) up to (`x1`, `y1`) of
+    /// a 100x100 image.
+    fn mask(x0: u32, y0: u32, x1: u32, y1: u32) -> Gray16Image {
+        Gray16Image::from_fn(100, 100, |x, y| Luma([if (x0..x1).contains(&x) && (y0..y1).contains(&y) { u16::MAX } else { 0 }]))
+    }
+
+    #[test]
+    fn people_are_tall_centered_and_cut_off_at_the_bottom() {
+        assert!(looks_like_person(&mask(30, 20, 70, 100)));
+        // A product floating in the middle
+        assert!(!looks_like_person(&mask(30, 30, 70, 70)));
+        // Wider than tall, or off to the side
+        assert!(!looks_like_person(&mask(10, 60, 90, 100)));
+        assert!(!looks_like_person(&mask(0, 20, 30, 100)));
+        // Filling the frame
+        assert!(!looks_like_person(&mask(5, 0, 95, 100)));
+    }
+
+    #[test]
+    fn models_run_up_to_the_limit() {
+        let auto = AutoModel::default();
+        let confidence = ConfidenceOptions::default();
+        let person = mask(30, 20, 70, 100);
+        let product = mask(30, 30, 70, 70);
+        let unclear = Gray16Image::from_pixel(100, 100, Luma([u16::MAX / 3]));
+
+        let rerun = |decision| match decision {
+            Decision::Rerun(model, _) => Some(model),
+            Decision::Keep(_) => None,
+        };
+        assert_eq!(rerun(decide(FIRST, &person, 1, auto, &confidence).unwrap()), Some(Model::U2netHumanSeg));
+        assert_eq!(rerun(decide(FIRST, &product, 1, auto, &confidence).unwrap()), None);
+        assert_eq!(rerun(decide(FIRST, &unclear, 1, auto, &confidence).unwrap()), Some(Model::U2net));
+        assert_eq!(rerun(decide(Model::U2netHumanSeg, &unclear, 2, auto, &confidence).unwrap()), None);
+        let three = AutoModel { max_models: 3 };
+        assert_eq!(rerun(decide(Model::U2netHumanSeg, &unclear, 2, three, &confidence).unwrap()), Some(Model::U2net));
+        assert_eq!(rerun(decide(Model::U2net, &unclear, 3, three, &confidence).unwrap()), None);
+        match decide(FIRST, &person, 1, AutoModel { max_models: 1 }, &confidence).unwrap() {
+            Decision::Keep(reason) => assert_eq!(reason.as_deref(), Some("u2netp mask looks like a person, but at most 1 model(s) may run")),
+            Decision::Rerun(..) => panic!("the limit allows one model"),
+        }
+    }
+}
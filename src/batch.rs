@@ -0,0 +1,397 @@
+//! Batch processing of a directory of images.
+//!
+//! Used when the CLI is given a directory as input. With `recursive`, the
+//! whole tree is walked and each cutout is written to the same relative path
+//! under the output directory, so `shoots/2024/05/a.jpg` becomes
+//! `<output>/2024/05/a_nobg.png`. The walk is streamed: each directory is
+//! read and processed before the next one is opened.
+
+use crate::artifacts;
+use crate::core::{check_naming, default_output_path, is_output_name, replaces_existing};
+use crate::error::{self, RemoveBgError, Result};
+use crate::options::{Collision, RemoveBgOptions};
+use crate::progress::{Progress, ProgressEvent, LOG_TARGET};
+use crate::remover::BackgroundRemover;
+use crate::watch::is_image_file;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Settings for [`process_directory`].
+#[derive(Debug, Clone)]
+pub struct BatchConfig {
+    /// Directory containing the input images.
+    pub input_dir: PathBuf,
+    /// Root the cutouts are written to, mirroring the input tree. `None`
+    /// writes each cutout next to its input.
+    pub output_dir: Option<PathBuf>,
+    /// Descend into subdirectories.
+    pub recursive: bool,
+    /// Skip files and directories whose names start with a dot.
+    pub skip_hidden: bool,
+    /// Which images with an existing output to skip.
+    pub skip_existing: SkipExisting,
+    /// Options used for every image. Cancelling `options.cancel` stops the
+    /// run after the current image.
+    pub options: RemoveBgOptions,
+    /// Receives each image's progress.
+    pub progress: Progress,
+}
+
+impl BatchConfig {
+    /// Process the images directly inside `input_dir`, writing cutouts next to them.
+    pub fn new(input_dir: impl Into<PathBuf>) -> Self {
+        BatchConfig {
+            input_dir: input_dir.into(),
+            output_dir: None,
+            recursive: false,
+            skip_hidden: false,
+            skip_existing: SkipExisting::Never,
+            options: RemoveBgOptions::default(),
+            progress: Progress::default(),
+        }
+    }
+}
+
+/// Which images of a batch are skipped because their output already exists.
+///
+/// The check uses only the resolved output path, so skipped images are never
+/// decoded and a fully processed tree doesn't load the model.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SkipExisting {
+    /// Process every image; existing outputs are replaced if
+    /// `options.overwrite` is set and counted as failures otherwise.
+    #[default]
+    Never,
+    /// Skip images whose output exists.
+    Always,
+    /// Skip images whose output is newer than the input; stale outputs are
+    /// replaced.
+    IfNewer,
+}
+
+/// Counts for one directory of a batch run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DirectorySummary {
+    /// Directory relative to the input directory (empty for the input directory itself).
+    pub path: PathBuf,
+    /// Images processed successfully.
+    pub processed: usize,
+    /// Images skipped because their output already existed.
+    pub skipped: usize,
+    /// Images that failed to process, including output path collisions.
+    pub failed: usize,
+}
+
+/// Outcome of a batch run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BatchSummary {
+    /// Images processed successfully.
+    pub processed: usize,
+    /// Images skipped because their output already existed.
+    pub skipped: usize,
+    /// Images that failed to process, including output path collisions.
+    pub failed: usize,
+    /// Per-directory counts, in walk order, for directories containing images.
+    pub directories: Vec<DirectorySummary>,
+    /// Whether the run was stopped through the cancellation token.
+    pub cancelled: bool,
+}
+
+/// Remove the background from every image in a directory.
+///
+/// Images are recognized by extension. Symlinked directories are followed,
+/// but a directory already visited is never entered twice, so symlink cycles
+/// terminate. The output directory is never walked when it lies inside the
+/// input tree, and when cutouts are written into the input tree itself,
+/// earlier `*_nobg` outputs (and `*_mask.png` sidecars with
+/// `options.save_mask`, and files named like `options.outputs`) are not
+/// processed again.
+///
+/// Before a directory's images are processed, their output paths, including
+/// those of `options.outputs`, are resolved; inputs that would overwrite
+/// each other (`a.jpg` and `a.png` both becoming `a_nobg.png`) are reported
+/// and counted as failed instead of being processed, unless
+/// [`Collision::Rename`] numbers the later ones (`a_nobg-1.png`). Images whose main
+/// output is written but one of `options.outputs` is not count as failed. Images with an existing output are handled according to
+/// `skip_existing`. Other failures are logged and counted; the run continues.
+///
+/// The model is loaded when the first image is found.
+///
+/// # Errors
+/// * `FileNotFound` - If the input directory does not exist
+/// * `NotAFile` - If the input path is not a directory
+/// * `ModelInitError` - If the model cannot be loaded
+/// * `IoError` - If the input directory cannot be read
+/// * `InvalidOutputFormat` - If `skip_existing` is combined with `options.overwrite` or a
+///   collision policy other than `Error`, or the output prefix or suffix contains a path separator
+pub fn process_directory(mut config: BatchConfig) -> Result<BatchSummary> {
+    if config.skip_existing != SkipExisting::Never && replaces_existing(&config.options) {
+        return Err(RemoveBgError::InvalidOutputFormat(
+            "skipping existing outputs contradicts overwriting them".into(),
+        ));
+    }
+    if config.skip_existing != SkipExisting::Never && config.options.naming.on_collision == Collision::Rename {
+        return Err(RemoveBgError::InvalidOutputFormat(
+            "skipping existing outputs contradicts renaming new ones around them".into(),
+        ));
+    }
+    check_naming(&config.options.naming)?;
+    // Stale outputs are replaced; up-to-date ones are skipped before processing
+    if config.skip_existing == SkipExisting::IfNewer {
+        config.options.overwrite = true;
+    }
+
+    let input_dir = &config.input_dir;
+    if !input_dir.exists() {
+        return Err(RemoveBgError::FileNotFound(input_dir.clone()));
+    }
+    if !input_dir.is_dir() {
+        return Err(RemoveBgError::NotAFile(input_dir.clone()));
+    }
+    let output_root = config
+        .output_dir
+        .as_ref()
+        .map(|dir| dir.canonicalize().unwrap_or_else(|_| dir.clone()));
+    // Cutouts written into the input tree itself must not be picked up again
+    let skip_outputs = output_root.is_none() || output_root == Some(input_dir.canonicalize()?);
+
+    let mut summary = BatchSummary::default();
+    let mut remover: Option<BackgroundRemover> = None;
+    let mut visited = HashSet::new();
+    // Relative paths of directories still to visit, popped depth-first
+    let mut stack = vec![PathBuf::new()];
+
+    while let Some(relative) = stack.pop() {
+        if config.options.cancel.is_cancelled() {
+            summary.cancelled = true;
+            break;
+        }
+        let dir = input_dir.join(&relative);
+        let is_root = relative.as_os_str().is_empty();
+        let listing = dir.canonicalize().map_err(RemoveBgError::from).and_then(|canonical| {
+            if !visited.insert(canonical.clone()) {
+                log::warn!("{}: directory already visited (symlink cycle?), skipping", dir.display());
+                return Ok(None);
+            }
+            if !is_root && output_root.as_ref() == Some(&canonical) {
+                return Ok(None);
+            }
+            list_directory(&dir, config.skip_hidden, skip_outputs.then_some(&config.options)).map(Some)
+        });
+        let (files, subdirs) = match listing {
+            Ok(Some(entries)) => entries,
+            Ok(None) => continue,
+            // The input directory itself must be readable; nested ones are reported
+            Err(e) if is_root => return Err(e),
+            Err(e) => {
+                log::warn!("{}: {}", dir.display(), e);
+                continue;
+            }
+        };
+
+        if config.recursive {
+            stack.extend(subdirs.into_iter().rev().map(|name| relative.join(name)));
+        }
+        if !files.is_empty() {
+            let out_dir = match &config.output_dir {
+                Some(root) => root.join(&relative),
+                None => dir.clone(),
+            };
+            let counts = process_files(&files, &out_dir, &config, &mut remover, &mut summary)?;
+            summary.directories.push(DirectorySummary { path: relative, ..counts });
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Images and subdirectory names in `dir`, both sorted by name.
+///
+/// With `outputs_of`, files named like outputs of those options are left out.
+fn list_directory(
+    dir: &Path,
+    skip_hidden: bool,
+    outputs_of: Option<&RemoveBgOptions>,
+) -> Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let mut files = Vec::new();
+    let mut subdirs = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if skip_hidden && name.to_string_lossy().starts_with('.') {
+            continue;
+        }
+        // Follows symlinks; cycles are caught by the visited set
+        let path = entry.path();
+        if path.is_dir() {
+            subdirs.push(PathBuf::from(name));
+        } else if is_image_file(&path) && !outputs_of.is_some_and(|options| is_output_name(&path, options)) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    subdirs.sort();
+    Ok((files, subdirs))
+}
+
+/// Process the images of one directory into `out_dir`.
+///
+/// Counts are added to `summary` and returned for the directory.
+fn process_files(
+    files: &[PathBuf],
+    out_dir: &Path,
+    config: &BatchConfig,
+    remover: &mut Option<BackgroundRemover>,
+    summary: &mut BatchSummary,
+) -> Result<DirectorySummary> {
+    let options = &config.options;
+    let mut counts = DirectorySummary::default();
+
+    // Resolve every output path first so collisions are caught before any work
+    // The main output comes first in each list of paths
+    let mut outputs: Vec<(&PathBuf, Result<Vec<PathBuf>>)> = Vec::new();
+    let mut claimed: HashMap<String, Vec<&PathBuf>> = HashMap::new();
+    for input in files {
+        // Renamed outputs avoid the names earlier inputs claimed
+        let taken = |path: &Path| claimed.contains_key(&path.to_string_lossy().to_lowercase());
+        let paths = default_output_path(input, out_dir, options, &taken).and_then(|output| {
+            let targets = artifacts::resolve(input, &output, options)?;
+            Ok(std::iter::once(output).chain(targets.into_iter().map(|target| target.path)).collect::<Vec<_>>())
+        });
+        for path in paths.iter().flatten() {
+            // Case-insensitive, since macOS and Windows file systems usually are
+            let inputs = claimed.entry(path.to_string_lossy().to_lowercase()).or_default();
+            // An input clashing with itself is reported when it is processed
+            if inputs.last() != Some(&input) {
+                inputs.push(input);
+            }
+        }
+        outputs.push((input, paths));
+    }
+    config.progress.report(ProgressEvent::Queued { count: outputs.len() });
+
+    // Images ready to process, run through the model in batches
+    let batch_size = options.batch_size.max(1);
+    let mut pending: Vec<(&PathBuf, PathBuf)> = Vec::new();
+    let total = outputs.len();
+    for (index, (input, paths)) in outputs.into_iter().enumerate() {
+        if options.cancel.is_cancelled() {
+            summary.cancelled = true;
+            break;
+        }
+        let ready = paths.and_then(|mut paths| {
+            for path in &paths {
+                let inputs = &claimed[&path.to_string_lossy().to_lowercase()];
+                if inputs.len() > 1 {
+                    let names: Vec<String> = inputs.iter().map(|p| file_name(p)).collect();
+                    return Err(RemoveBgError::ProcessingError(format!(
+                        "{} would all be written to {}; rename one of them",
+                        names.join(", "),
+                        path.display()
+                    )));
+                }
+            }
+            let output = paths.swap_remove(0);
+            let skip = match config.skip_existing {
+                SkipExisting::Never => false,
+                SkipExisting::Always => output.exists(),
+                SkipExisting::IfNewer => is_up_to_date(input, &output),
+            };
+            if skip {
+                log::info!(target: LOG_TARGET, "{}: {} exists, skipping", input.display(), output.display());
+                return Ok(None);
+            }
+            // Checked here too so existing outputs never load the model
+            if !replaces_existing(options) && output.exists() {
+                return Err(RemoveBgError::OutputExists(output));
+            }
+            std::fs::create_dir_all(out_dir)?;
+            Ok(Some(output))
+        });
+
+        match ready {
+            Ok(Some(output)) => pending.push((input, output)),
+            Ok(None) => {
+                config.progress.report(ProgressEvent::Skipped { input });
+                counts.skipped += 1;
+            }
+            Err(e) => {
+                log::warn!("{}", error::describe(input, &e));
+                config.progress.report(ProgressEvent::Failed { input, error: &e });
+                counts.failed += 1;
+            }
+        }
+        if pending.len() == batch_size || (index + 1 == total && !pending.is_empty()) {
+            let cancelled = process_pending(&pending, config, remover, &mut counts)?;
+            pending.clear();
+            if cancelled {
+                summary.cancelled = true;
+                break;
+            }
+        }
+    }
+
+    summary.processed += counts.processed;
+    summary.skipped += counts.skipped;
+    summary.failed += counts.failed;
+    Ok(counts)
+}
+
+/// Process a batch of images with resolved output paths, loading the model
+/// on first use, and count the outcomes in `counts`.
+///
+/// Returns whether the run was cancelled.
+fn process_pending(
+    pending: &[(&PathBuf, PathBuf)],
+    config: &BatchConfig,
+    remover: &mut Option<BackgroundRemover>,
+    counts: &mut DirectorySummary,
+) -> Result<bool> {
+    let remover = match remover {
+        Some(remover) => remover,
+        None => remover.insert(BackgroundRemover::new(config.options.clone())?),
+    };
+    let jobs: Vec<(&Path, Option<&Path>)> =
+        pending.iter().map(|(input, output)| (input.as_path(), Some(output.as_path()))).collect();
+    for (input, _) in pending {
+        config.progress.report(ProgressEvent::Started { input });
+    }
+
+    for ((input, _), result) in pending.iter().zip(remover.process_files(&jobs)) {
+        match result {
+            // The missing outputs were logged when they failed
+            Ok(report) if report.artifacts.iter().any(|artifact| artifact.error.is_some()) => {
+                let failed = report.artifacts.iter().filter(|artifact| artifact.error.is_some()).count();
+                let error = RemoveBgError::ProcessingError(format!("{} of the --emit outputs could not be written", failed));
+                config.progress.report(ProgressEvent::Failed { input, error: &error });
+                counts.failed += 1;
+            }
+            Ok(report) => {
+                log::info!(target: LOG_TARGET, "{} -> {}", input.display(), report.output_path.display());
+                config.progress.report(ProgressEvent::Processed { input, report: &report });
+                counts.processed += 1;
+            }
+            Err(RemoveBgError::Cancelled) => return Ok(true),
+            Err(e @ (RemoveBgError::ModelInitError(_) | RemoveBgError::ModelNotCached { .. })) => return Err(e),
+            Err(e) => {
+                log::warn!("{}", error::describe(input, &e));
+                config.progress.report(ProgressEvent::Failed { input, error: &e });
+                counts.failed += 1;
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Whether `output` exists and was written after `input` last changed.
+pub(crate) fn is_up_to_date(input: &Path, output: &Path) -> bool {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    match (modified(input), modified(output)) {
+        (Some(input), Some(output)) => output >= input,
+        _ => false,
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap_or_default().to_string_lossy().to_string()
+}
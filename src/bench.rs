@@ -0,0 +1,145 @@
+//! Benchmarks: time each stage of the pipeline over repeated runs on one
+//! input.
+//!
+//! Used by `removebg --bench N`. The model is downloaded before anything is
+//! timed, so no measurement includes the one-time download. Session creation
+//! is timed twice, cold and then warm (with the model file in the OS page
+//! cache), and the runs use the warm session after one untimed warm-up run,
+//! so their times show what a long-lived process pays per image.
+
+use crate::core::remove_file;
+use crate::error::{RemoveBgError, Result};
+use crate::model;
+use crate::options::{Collision, RemoveBgOptions};
+use crate::remover::BackgroundRemover;
+use crate::report::StageDurations;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Reads the time of one stage from a run's [`StageDurations`].
+pub type Stage = fn(&StageDurations) -> Duration;
+
+/// The per-image stages of the pipeline in order, with their names, for
+/// reporting [`BenchReport::stats`].
+pub const STAGES: [(&str, Stage); 8] = [
+    ("decode", |d| d.decode),
+    ("preprocess", |d| d.preprocess),
+    ("inference", |d| d.inference),
+    ("second_pass", |d| d.second_pass),
+    ("postprocess", |d| d.postprocess),
+    ("composite", |d| d.composite),
+    ("encode", |d| d.encode),
+    ("total", |d| d.total),
+];
+
+/// Results of [`run_benchmark`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchReport {
+    /// Size of the input image.
+    pub input_dimensions: (u32, u32),
+    /// Whether the model file had to be downloaded first. The download is
+    /// never part of the timings.
+    pub downloaded: bool,
+    /// Creating the inference session for the first time in this process.
+    pub cold_model_load: Duration,
+    /// Creating it again right after, with the model file cached by the OS.
+    pub warm_model_load: Duration,
+    /// Stage times of each timed run, in order.
+    pub runs: Vec<StageDurations>,
+}
+
+/// Minimum, median and maximum of one stage over the timed runs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StageStats {
+    /// Fastest run.
+    pub min: Duration,
+    /// Median run; the mean of the middle two for an even number of runs.
+    pub median: Duration,
+    /// Slowest run.
+    pub max: Duration,
+}
+
+impl BenchReport {
+    /// Statistics of the stage selected by `stage`, such as one of
+    /// [`STAGES`].
+    pub fn stats(&self, stage: impl Fn(&StageDurations) -> Duration) -> StageStats {
+        let mut times: Vec<Duration> = self.runs.iter().map(stage).collect();
+        times.sort();
+        let Some((&min, &max)) = times.first().zip(times.last()) else {
+            return StageStats::default();
+        };
+        let middle = times.len() / 2;
+        let median = match times.len() % 2 {
+            1 => times[middle],
+            _ => (times[middle - 1] + times[middle]) / 2,
+        };
+        StageStats { min, median, max }
+    }
+
+    /// Images processed per second over the timed runs.
+    pub fn images_per_second(&self) -> f64 {
+        let total: Duration = self.runs.iter().map(|run| run.total).sum();
+        self.runs.len() as f64 / total.as_secs_f64().max(f64::EPSILON)
+    }
+
+    /// Input megapixels processed per second over the timed runs.
+    pub fn megapixels_per_second(&self) -> f64 {
+        let (width, height) = self.input_dimensions;
+        self.images_per_second() * width as f64 * height as f64 / 1_000_000.0
+    }
+}
+
+/// Remove the background of `input` `iterations` times and time each stage.
+///
+/// Outputs are written as by [`remove_background`](crate::remove_background),
+/// to the same file every run: the warm-up run follows `options.overwrite`,
+/// and the timed runs then overwrite its output.
+///
+/// # Errors
+/// * `ProcessingError` - If `iterations` is zero
+/// * `ModelInitError` - If the model cannot be downloaded or loaded
+/// * `Cancelled` - If `options.cancel` is triggered
+/// * Otherwise the errors of processing a single image
+pub fn run_benchmark(
+    input: &Path,
+    output: Option<&Path>,
+    iterations: usize,
+    options: &RemoveBgOptions,
+) -> Result<BenchReport> {
+    if iterations == 0 {
+        return Err(RemoveBgError::ProcessingError("Benchmark needs at least 1 iteration".into()));
+    }
+
+    // Download first, so no timing below includes it
+    let mut downloaded = false;
+    if model::embedded_model(options.model).is_none() {
+        let model_dir = options.model_dir.as_deref();
+        downloaded = !model::model_path(options.model, model_dir)?.exists();
+        model::ensure_model(options.model, model_dir, &options.download, &options.download_progress, &options.cancel)?;
+    }
+
+    let started = Instant::now();
+    let cold = BackgroundRemover::new(options.clone())?;
+    let cold_model_load = started.elapsed();
+    drop(cold);
+    let started = Instant::now();
+    let remover = BackgroundRemover::new(options.clone())?;
+    let warm_model_load = started.elapsed();
+
+    let warm_up = remove_file(input, output, options, || Ok((&remover, false)))?;
+    log::debug!("warm-up run took {:?}", warm_up.durations.total);
+    let output = Some(warm_up.output_path.as_path());
+    let mut options = RemoveBgOptions { overwrite: true, ..options.clone() };
+    options.naming.on_collision = Collision::Overwrite;
+    let runs = (0..iterations)
+        .map(|_| remove_file(input, output, &options, || Ok((&remover, false))).map(|report| report.durations))
+        .collect::<Result<_>>()?;
+
+    Ok(BenchReport {
+        input_dimensions: warm_up.input_dimensions,
+        downloaded,
+        cold_model_load,
+        warm_model_load,
+        runs,
+    })
+}
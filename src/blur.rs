@@ -0,0 +1,266 @@
+//! Portrait-style background effects.
+//!
+//! Instead of removing the background, the subject is composited over a
+//! blurred and/or grayscale copy of the image, using the soft mask as the
+//! blend weight. Blurring the photo as-is would smear the subject's colors
+//! into a halo around it, so the subject is first painted over with the
+//! surrounding background colors and only that background plate is blurred.
+
+use crate::core::{fit_mask, flatten_onto, Gray16Image};
+use crate::error::{RemoveBgError, Result};
+use crate::options::{MaskFilter, RemoveBgOptions};
+use image::{DynamicImage, Rgb32FImage, RgbImage, RgbaImage};
+
+/// The two layers of a blurred-background image.
+///
+/// Kept apart so interactive callers can re-blend them, for example to show
+/// the sharp subject over a background blurred by a slider, without
+/// recomputing the mask.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlurLayers {
+    /// The input image with the mask as its alpha channel.
+    pub sharp: RgbaImage,
+    /// The background with the subject filled in from its surroundings, blurred.
+    pub blurred: RgbImage,
+}
+
+impl BlurLayers {
+    /// Composite the sharp layer over the blurred one into an opaque image.
+    pub fn composite(&self) -> RgbImage {
+        let mut image = self.sharp.clone();
+        flatten_onto(&mut image, |x, y| self.blurred.get_pixel(x, y).0.map(|c| c as f32 / 255.0));
+        DynamicImage::ImageRgba8(image).into_rgb8()
+    }
+}
+
+/// Split `image` into a sharp subject layer and a blurred background layer.
+///
+/// The blurred layer is computed from a copy of the image whose subject
+/// pixels are replaced with nearby background colors, so the blur doesn't
+/// pick up a halo of subject colors. [`BlurLayers::composite`] blends the
+/// two into the final picture.
+///
+/// # Arguments
+/// * `image` - Image to blur the background of.
+/// * `mask` - Subject mask (white = subject), e.g. from
+///   [`BackgroundRemover::mask`](crate::remover::BackgroundRemover::mask).
+///   A mask of a different size but the same aspect ratio is resized.
+/// * `sigma` - Standard deviation of the Gaussian blur in pixels.
+///
+/// # Returns
+/// The 8-bit sharp and blurred layers.
+///
+/// # Errors
+/// * `InvalidMask` - If the mask's aspect ratio differs from the image's by more than 1%
+/// * `ProcessingError` - If `sigma` is not a positive number
+///
+/// # Examples
+/// ```no_run
+/// use removebg::{blur_layers, remover::BackgroundRemover, RemoveBgOptions};
+///
+/// let remover = BackgroundRemover::new(RemoveBgOptions::default())?;
+/// let image = image::open("portrait.jpg")?;
+/// let mask = remover.mask(&image)?;
+/// let layers = blur_layers(&image, &image::DynamicImage::ImageLuma8(mask), 12.0)?;
+/// layers.composite().save("portrait_blurred.jpg")?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn blur_layers(image: &DynamicImage, mask: &DynamicImage, sigma: f32) -> Result<BlurLayers> {
+    check_sigma(sigma)?;
+    let mask = fit_mask(mask, image.width(), image.height(), MaskFilter::default())?;
+    let blurred = blurred_background(image, &mask, sigma);
+
+    let mut sharp = image.to_rgba8();
+    for (pixel, alpha) in sharp.pixels_mut().zip(DynamicImage::ImageLuma16(mask).into_luma8().iter()) {
+        pixel[3] = *alpha;
+    }
+    Ok(BlurLayers { sharp, blurred: DynamicImage::ImageRgb32F(blurred).into_rgb8() })
+}
+
+/// Check that a blur sigma is usable.
+pub(crate) fn check_sigma(sigma: f32) -> Result<()> {
+    if !(sigma.is_finite() && sigma > 0.0) {
+        return Err(RemoveBgError::ProcessingError(format!(
+            "Blur sigma must be a positive number, got {}",
+            sigma
+        )));
+    }
+    Ok(())
+}
+
+/// Whether `options` keep the background (blurred or grayscale) instead of
+/// removing it.
+pub(crate) fn keeps_background(options: &RemoveBgOptions) -> bool {
+    options.blur_background.is_some() || options.grayscale_background
+}
+
+/// The background layer the subject is composited over when
+/// [`keeps_background`] is true, as 0.0-1.0 RGB.
+///
+/// The image is blurred, converted to grayscale (Rec. 709 luma), and scaled
+/// by `options.background_brightness`, as enabled.
+pub(crate) fn background_layer(
+    image: &DynamicImage,
+    mask: &Gray16Image,
+    options: &RemoveBgOptions,
+) -> Result<Rgb32FImage> {
+    let mut layer = match options.blur_background {
+        Some(sigma) => {
+            check_sigma(sigma)?;
+            blurred_background(image, mask, sigma)
+        }
+        None => image.to_rgb32f(),
+    };
+    let brightness = options.background_brightness.unwrap_or(1.0);
+    if !(brightness.is_finite() && brightness >= 0.0) {
+        return Err(RemoveBgError::ProcessingError(format!(
+            "Background brightness must be a non-negative number, got {}",
+            brightness
+        )));
+    }
+    for pixel in layer.pixels_mut() {
+        if options.grayscale_background {
+            let luma = 0.2126 * pixel[0] + 0.7152 * pixel[1] + 0.0722 * pixel[2];
+            pixel.0 = [luma; 3];
+        }
+        pixel.0 = pixel.0.map(|c| (c * brightness).clamp(0.0, 1.0));
+    }
+    Ok(layer)
+}
+
+/// The background of `image` with the subject filled in, blurred with a
+/// Gaussian of `sigma` pixels.
+pub(crate) fn blurred_background(image: &DynamicImage, mask: &Gray16Image, sigma: f32) -> Rgb32FImage {
+    let plate = fill_subject(&image.to_rgb32f(), mask);
+    image::imageops::fast_blur(&plate, sigma)
+}
+
+/// Weighted colors of one level of the fill pyramid.
+struct Level {
+    width: usize,
+    height: usize,
+    /// Colors premultiplied by their weight.
+    color: Vec<[f32; 3]>,
+    /// How much background each pixel holds; above 1 on coarser levels.
+    weight: Vec<f32>,
+}
+
+impl Level {
+    /// Half-size level whose pixels are the sums of 2x2 blocks of this one.
+    fn downsample(&self) -> Level {
+        let (width, height) = (self.width.div_ceil(2), self.height.div_ceil(2));
+        let mut color = vec![[0.0; 3]; width * height];
+        let mut weight = vec![0.0; width * height];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let (from, to) = (y * self.width + x, (y / 2) * width + x / 2);
+                for (sum, value) in color[to].iter_mut().zip(self.color[from]) {
+                    *sum += value;
+                }
+                weight[to] += self.weight[from];
+            }
+        }
+        Level { width, height, color, weight }
+    }
+
+    /// Fill this level: pixels keep their own color in proportion to their
+    /// weight and take the rest from the already filled coarser level.
+    fn fill(&self, coarse: &[[f32; 3]], coarse_width: usize) -> Vec<[f32; 3]> {
+        let mut filled = vec![[0.0; 3]; self.width * self.height];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let i = y * self.width + x;
+                let weight = self.weight[i];
+                let confidence = weight.min(1.0);
+                let up = coarse[(y / 2) * coarse_width + x / 2];
+                for c in 0..3 {
+                    let own = if weight > 0.0 { self.color[i][c] / weight } else { 0.0 };
+                    filled[i][c] = own * confidence + up[c] * (1.0 - confidence);
+                }
+            }
+        }
+        filled
+    }
+}
+
+/// Replace the subject's pixels with colors pulled in from the background.
+///
+/// Push-pull inpainting: each pixel is weighted by how much of it is
+/// background (1 − mask), and weighted colors are summed down an image
+/// pyramid to a single pixel. Collapsing the pyramid again fills every pixel,
+/// in proportion to its missing weight, from the nearest coarser level that
+/// saw background.
+fn fill_subject(image: &Rgb32FImage, mask: &Gray16Image) -> Rgb32FImage {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return image.clone();
+    }
+    let weight: Vec<f32> = mask.iter().map(|&m| 1.0 - m as f32 / 65535.0).collect();
+    let color = image
+        .pixels()
+        .zip(&weight)
+        .map(|(pixel, &w)| pixel.0.map(|c| c * w))
+        .collect();
+    let mut levels = vec![Level { width: width as usize, height: height as usize, color, weight }];
+    while let Some(level) = levels.last().filter(|level| level.width > 1 || level.height > 1) {
+        let next = level.downsample();
+        levels.push(next);
+    }
+
+    // An image that is all subject has no background to pull from; use its mean color
+    let top = levels.pop().expect("pyramid has at least one level");
+    let mut filled = vec![if top.weight[0] > 0.0 {
+        top.color[0].map(|c| c / top.weight[0])
+    } else {
+        let count = image.pixels().len().max(1) as f32;
+        let mut mean = [0.0; 3];
+        for pixel in image.pixels() {
+            for c in 0..3 {
+                mean[c] += pixel[c] / count;
+            }
+        }
+        mean
+    }];
+    let mut filled_width = 1;
+    for level in levels.iter().rev() {
+        filled = level.fill(&filled, filled_width);
+        filled_width = level.width;
+    }
+
+    Rgb32FImage::from_fn(width, height, |x, y| image::Rgb(filled[(y * width + x) as usize]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::apply_external_mask;
+    use image::{GrayImage, Luma, Rgb};
+
+    /// HSV saturation of an 8-bit color.
+    fn saturation(pixel: &[u8]) -> f32 {
+        let (max, min) = (pixel[..3].iter().max().unwrap(), pixel[..3].iter().min().unwrap());
+        if *max == 0 { 0.0 } else { (max - min) as f32 / *max as f32 }
+    }
+
+    #[test]
+    fn grayscale_backgrounds_keep_the_subject_in_color() {
+        // A red background on the left, a colorful subject on the right
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(64, 32, |x, y| {
+            if x < 32 { Rgb([220, 30, 30]) } else { Rgb([40 + y as u8 * 4, 200 - x as u8, 90]) }
+        }));
+        let mask = DynamicImage::ImageLuma8(GrayImage::from_fn(64, 32, |x, _| Luma([if x < 32 { 0 } else { 255 }])));
+        for blur_background in [None, Some(4.0)] {
+            let options = RemoveBgOptions { grayscale_background: true, blur_background, ..Default::default() };
+            let pop = apply_external_mask(&image, &mask, &options).unwrap().into_rgba8();
+            for (x, y, pixel) in pop.enumerate_pixels() {
+                assert_eq!(pixel[3], 255);
+                let original = image.as_rgb8().unwrap().get_pixel(x, y);
+                if x < 32 {
+                    assert!(saturation(&pixel.0) < 0.01, "({}, {}): {:?}", x, y, pixel);
+                } else {
+                    assert!((saturation(&pixel.0) - saturation(&original.0)).abs() < 0.01, "({}, {}): {:?} for {:?}", x, y, pixel, original);
+                }
+            }
+        }
+    }
+}
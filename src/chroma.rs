@@ -0,0 +1,123 @@
+//! Chroma-key output for video editors that can't import alpha.
+//!
+//! The background is replaced by a flat key color, like a solid background
+//! color, but the subject is kept keyable: edge colors are decontaminated so
+//! the old background doesn't tint the rim, and colors inside the subject that
+//! match the key are nudged away from it so a keyer doesn't punch holes in it.
+
+use crate::core::{flatten_onto, Channel};
+use image::{DynamicImage, ImageBuffer, Pixel, Rgba};
+
+/// Alpha from which a pixel counts as solid subject and must not match the key.
+const PROTECTED_ALPHA: f32 = 0.95;
+
+/// Smallest per-channel distance (four 8-bit steps) kept between a solid
+/// subject pixel and the key color.
+const KEY_MARGIN: f32 = 4.0 / 255.0;
+
+/// Flatten an 8-bit or 16-bit RGBA cutout onto `key`, moving solid subject
+/// pixels that come within [`KEY_MARGIN`] of the key color just outside it.
+pub(crate) fn key_out(image: &mut DynamicImage, key: [u8; 3]) {
+    match image {
+        DynamicImage::ImageRgba8(image) => key_out_buffer(image, key),
+        DynamicImage::ImageRgba16(image) => key_out_buffer(image, key),
+        // Cutouts are only ever RGBA8 or RGBA16
+        _ => {}
+    }
+}
+
+fn key_out_buffer<S: Channel>(image: &mut ImageBuffer<Rgba<S>, Vec<S>>, key: [u8; 3])
+where
+    Rgba<S>: Pixel<Subpixel = S>,
+{
+    let protected: Vec<bool> = image.pixels().map(|pixel| pixel[3].to_unit() >= PROTECTED_ALPHA).collect();
+    let key = key.map(|c| c as f32 / 255.0);
+    flatten_onto(image, |_, _| key);
+
+    for (pixel, protected) in image.pixels_mut().zip(protected) {
+        if !protected {
+            continue;
+        }
+        let color = [pixel[0].to_unit(), pixel[1].to_unit(), pixel[2].to_unit()];
+        if let Some(color) = away_from_key(color, key) {
+            for c in 0..3 {
+                pixel[c] = S::from_unit(color[c]);
+            }
+        }
+    }
+}
+
+/// The nearest color at least [`KEY_MARGIN`] from `key` in every direction
+/// that moves `color` straight away from it, or `None` if `color` is already
+/// far enough away.
+fn away_from_key(color: [f32; 3], key: [f32; 3]) -> Option<[f32; 3]> {
+    let offset: [f32; 3] = std::array::from_fn(|c| color[c] - key[c]);
+    let distance = offset.iter().fold(0.0f32, |max, d| max.max(d.abs()));
+    if distance >= KEY_MARGIN {
+        return None;
+    }
+    // A color equal to the key has no direction of its own; head toward gray
+    let direction = if distance > 0.0 { offset } else { key.map(|k| 0.5 - k) };
+    let length = direction.iter().fold(0.0f32, |max, d| max.max(d.abs()));
+    Some(std::array::from_fn(|c| (key[c] + direction[c] * KEY_MARGIN / length).clamp(0.0, 1.0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    const GREEN: [u8; 3] = [0, 255, 0];
+
+    /// A cutout whose left half is background (in colors of its own), and
+    /// whose right half is solid subject, partly in the key color itself or
+    /// close to it.
+    fn cutout() -> RgbaImage {
+        RgbaImage::from_fn(16, 8, |x, y| match (x, y) {
+            (0..8, _) => Rgba([x as u8 * 30, y as u8 * 30, 90, 0]),
+            (8, _) => Rgba([0, 255, 0, 255]),
+            (9, _) => Rgba([2, 252, 1, 255]),
+            // Just over the protected alpha, on the key
+            (10, _) => Rgba([0, 255, 0, 245]),
+            _ => Rgba([200, 120, 40, 255]),
+        })
+    }
+
+    #[test]
+    fn solid_pixels_never_match_the_key() {
+        let original = cutout();
+        let mut keyed = original.clone();
+        key_out_buffer(&mut keyed, GREEN);
+        for (x, y, pixel) in keyed.enumerate_pixels() {
+            assert_eq!(pixel[3], 255);
+            if f32::from(original.get_pixel(x, y)[3]) / 255.0 >= PROTECTED_ALPHA {
+                let distance = (0..3).map(|c| pixel[c].abs_diff(GREEN[c])).max().unwrap();
+                assert!(distance >= 4, "({}, {}): {:?}", x, y, pixel);
+            }
+        }
+        // Colors away from the key are left alone
+        assert_eq!(keyed.get_pixel(12, 3), &Rgba([200, 120, 40, 255]));
+
+        // The same at 16 bits
+        let mut keyed = DynamicImage::ImageRgba8(original).into_rgba16();
+        key_out_buffer(&mut keyed, GREEN);
+        let key = GREEN.map(|c| c as u16 * 257);
+        assert!((8..11).all(|x| keyed.get_pixel(x, 0).0[..3] != key));
+    }
+
+    #[test]
+    fn the_background_is_the_key_color() {
+        let mut keyed = DynamicImage::ImageRgba8(cutout());
+        key_out(&mut keyed, GREEN);
+        let keyed = keyed.into_rgba8();
+        for x in 0..8 {
+            for y in 0..8 {
+                assert_eq!(keyed.get_pixel(x, y), &Rgba([0, 255, 0, 255]), "({}, {})", x, y);
+            }
+        }
+
+        let mut keyed = DynamicImage::ImageRgba16(ImageBuffer::from_pixel(4, 4, Rgba([1000, 2000, 3000, 0])));
+        key_out(&mut keyed, [255, 0, 255]);
+        assert!(keyed.into_rgba16().pixels().all(|pixel| pixel.0 == [65535, 0, 65535, 65535]));
+    }
+}
@@ -0,0 +1,236 @@
+//! Clipboard input and output, for `--from-clipboard` and `--to-clipboard`.
+//!
+//! The system clipboard is reached through arboard behind the `clipboard`
+//! feature; without it, [`system`] fails with a message saying how to build
+//! it in. Everything else goes through the [`Clipboard`] trait, so the flow
+//! works, and is tested, with any implementation.
+//!
+//! Images are exchanged as RGBA pixels. The platform decides how they are
+//! offered to other applications: as PNG on X11, Wayland and macOS, and as a
+//! PNG plus a bitmap on Windows. On Linux, a cutout stays on the clipboard
+//! after removebg exits only if a clipboard manager takes it over, which
+//! desktop environments usually run.
+
+use crate::core::remove_background_from_bytes;
+use crate::error::{RemoveBgError, Result};
+use crate::options::RemoveBgOptions;
+use crate::output::OutputFormat;
+use image::{ImageFormat, RgbaImage};
+use std::io::Cursor;
+
+/// A clipboard holding images.
+pub trait Clipboard {
+    /// The image on the clipboard.
+    ///
+    /// # Errors
+    /// * `ClipboardError` - If the clipboard holds no image or cannot be read
+    fn get_image(&mut self) -> Result<RgbaImage>;
+
+    /// Replace the clipboard's contents with `image`.
+    ///
+    /// # Errors
+    /// * `ClipboardError` - If the clipboard cannot be written
+    fn set_image(&mut self, image: &RgbaImage) -> Result<()>;
+}
+
+/// The system clipboard.
+#[cfg(feature = "clipboard")]
+pub struct SystemClipboard(arboard::Clipboard);
+
+#[cfg(feature = "clipboard")]
+impl SystemClipboard {
+    /// Connect to the system clipboard.
+    ///
+    /// # Errors
+    /// * `ClipboardError` - If there is no clipboard, such as on a server
+    ///   without a display
+    pub fn new() -> Result<Self> {
+        arboard::Clipboard::new().map(SystemClipboard).map_err(|e| {
+            RemoveBgError::ClipboardError(format!(
+                "no clipboard is available ({}); clipboard access needs a desktop session",
+                e.to_string().trim_end_matches('.')
+            ))
+        })
+    }
+}
+
+#[cfg(feature = "clipboard")]
+impl Clipboard for SystemClipboard {
+    fn get_image(&mut self) -> Result<RgbaImage> {
+        let image = self.0.get_image().map_err(clipboard_error)?;
+        let (width, height) = (image.width as u32, image.height as u32);
+        RgbaImage::from_raw(width, height, image.bytes.into_owned()).ok_or_else(|| {
+            RemoveBgError::ClipboardError(format!("the clipboard image has a malformed {}x{} buffer", width, height))
+        })
+    }
+
+    fn set_image(&mut self, image: &RgbaImage) -> Result<()> {
+        let data = arboard::ImageData {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            bytes: image.as_raw().into(),
+        };
+        self.0.set_image(data).map_err(clipboard_error)
+    }
+}
+
+#[cfg(feature = "clipboard")]
+fn clipboard_error(error: arboard::Error) -> RemoveBgError {
+    RemoveBgError::ClipboardError(match error {
+        arboard::Error::ContentNotAvailable => "the clipboard holds no image; copy an image first".into(),
+        error => error.to_string().trim_end_matches('.').to_string(),
+    })
+}
+
+/// Open the system clipboard.
+///
+/// # Errors
+/// * `ClipboardError` - If there is no clipboard, or removebg was built
+///   without the `clipboard` feature
+pub fn system() -> Result<Box<dyn Clipboard>> {
+    #[cfg(feature = "clipboard")]
+    return Ok(Box::new(SystemClipboard::new()?));
+    #[cfg(not(feature = "clipboard"))]
+    Err(RemoveBgError::ClipboardError(
+        "clipboard support needs removebg built with the `clipboard` feature \
+         (cargo install removebg --features clipboard)"
+            .into(),
+    ))
+}
+
+/// The image on `clipboard`, encoded as PNG for
+/// [`remove_background_from_bytes`] and the other byte-based APIs.
+///
+/// # Errors
+/// * `ClipboardError` - If the clipboard holds no image or cannot be read
+pub fn read_image(clipboard: &mut dyn Clipboard) -> Result<Vec<u8>> {
+    let image = clipboard.get_image()?;
+    let mut encoded = Cursor::new(Vec::new());
+    image.write_to(&mut encoded, ImageFormat::Png)?;
+    Ok(encoded.into_inner())
+}
+
+/// Decode `data`, an encoded image, and put it on `clipboard`.
+///
+/// # Errors
+/// * `ImageError` - If `data` cannot be decoded
+/// * `ClipboardError` - If the clipboard cannot be written
+pub fn write_image(clipboard: &mut dyn Clipboard, data: &[u8]) -> Result<()> {
+    let image = image::load_from_memory(data)?.into_rgba8();
+    clipboard.set_image(&image)
+}
+
+/// Remove the background of the image on `clipboard` and put the cutout back
+/// in its place, without touching any file.
+///
+/// All options apply as for [`remove_background_from_bytes`], except that
+/// the result is always a PNG-compatible RGBA image.
+///
+/// # Errors
+/// * `ClipboardError` - If the clipboard holds no image or cannot be accessed
+/// * `InvalidOutputFormat` - If `options.format` is set to something other than PNG
+/// * Otherwise the errors of [`remove_background_from_bytes`]
+pub fn remove_background_on_clipboard(clipboard: &mut dyn Clipboard, options: &RemoveBgOptions) -> Result<()> {
+    if options.format.is_some_and(|format| format != OutputFormat::Png) {
+        return Err(RemoveBgError::InvalidOutputFormat("clipboard output is always PNG".into()));
+    }
+    let input = read_image(clipboard)?;
+    let options = RemoveBgOptions { format: Some(OutputFormat::Png), ..options.clone() };
+    let cutout = remove_background_from_bytes(&input, &options)?;
+    write_image(clipboard, &cutout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::{Background, GeometryOptions};
+    use crate::testing;
+    use image::Rgba;
+
+    /// A clipboard in memory that counts its accesses.
+    #[derive(Default)]
+    struct MockClipboard {
+        image: Option<RgbaImage>,
+        reads: usize,
+        writes: usize,
+    }
+
+    impl Clipboard for MockClipboard {
+        fn get_image(&mut self) -> Result<RgbaImage> {
+            self.reads += 1;
+            self.image
+                .clone()
+                .ok_or_else(|| RemoveBgError::ClipboardError("the clipboard holds no image; copy an image first".into()))
+        }
+
+        fn set_image(&mut self, image: &RgbaImage) -> Result<()> {
+            self.writes += 1;
+            self.image = Some(image.clone());
+            Ok(())
+        }
+    }
+
+    fn options() -> RemoveBgOptions {
+        testing::deterministic(testing::TestModel::Brightness.options(RemoveBgOptions::default()))
+    }
+
+    /// A black left half and a white right half; the stub model keeps the
+    /// white half.
+    fn screenshot() -> RgbaImage {
+        RgbaImage::from_fn(64, 32, |x, _| if x < 32 { Rgba([0, 0, 0, 255]) } else { Rgba([255, 255, 255, 255]) })
+    }
+
+    #[test]
+    fn cutout_replaces_the_clipboard_image() {
+        let mut clipboard = MockClipboard { image: Some(screenshot()), ..Default::default() };
+        remove_background_on_clipboard(&mut clipboard, &options()).unwrap();
+
+        assert_eq!((clipboard.reads, clipboard.writes), (1, 1));
+        let cutout = clipboard.image.unwrap();
+        assert_eq!(cutout.dimensions(), (64, 32));
+        assert!(cutout.get_pixel(2, 16)[3] < 32, "background kept: {:?}", cutout.get_pixel(2, 16));
+        assert_eq!(cutout.get_pixel(60, 16)[3], 255);
+    }
+
+    #[test]
+    fn post_processing_applies() {
+        let mut clipboard = MockClipboard { image: Some(screenshot()), ..Default::default() };
+        let options = RemoveBgOptions {
+            background: Background::Color([255, 0, 0]),
+            geometry: GeometryOptions { square: true, ..Default::default() },
+            ..options()
+        };
+        remove_background_on_clipboard(&mut clipboard, &options).unwrap();
+
+        let cutout = clipboard.image.unwrap();
+        assert_eq!(cutout.dimensions(), (64, 64));
+        assert_eq!(cutout.get_pixel(2, 32), &Rgba([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn empty_clipboard_is_reported_and_left_alone() {
+        let mut clipboard = MockClipboard::default();
+        let error = remove_background_on_clipboard(&mut clipboard, &options()).unwrap_err();
+        assert!(matches!(error, RemoveBgError::ClipboardError(_)), "{:?}", error);
+        assert_eq!(error.to_string(), "Clipboard error: the clipboard holds no image; copy an image first");
+        assert_eq!(clipboard.writes, 0);
+    }
+
+    #[test]
+    fn other_formats_are_refused_before_reading() {
+        let mut clipboard = MockClipboard { image: Some(screenshot()), ..Default::default() };
+        let options = RemoveBgOptions { format: Some(OutputFormat::Jpeg), ..options() };
+        let error = remove_background_on_clipboard(&mut clipboard, &options).unwrap_err();
+        assert!(matches!(error, RemoveBgError::InvalidOutputFormat(_)), "{:?}", error);
+        assert_eq!((clipboard.reads, clipboard.writes), (0, 0));
+    }
+
+    #[test]
+    #[cfg(feature = "clipboard")]
+    #[ignore = "needs a desktop session with a clipboard"]
+    fn system_clipboard_round_trip() {
+        let mut clipboard = SystemClipboard::new().unwrap();
+        clipboard.set_image(&screenshot()).unwrap();
+        assert_eq!(clipboard.get_image().unwrap(), screenshot());
+    }
+}
@@ -0,0 +1,502 @@
+//! Object store inputs and outputs: `s3://`, `gs://` and `az://` URIs.
+//!
+//! Buckets are reached through the object_store crate behind the
+//! `object-store` feature; without it, [`remove_background_uri`] and
+//! [`process_prefix`] fail with a message saying how to build it in.
+//! Credentials come from where the cloud SDKs look for them: the `AWS_*`,
+//! `GOOGLE_*` and `AZURE_*` environment variables, and instance metadata or
+//! workload identity when those are unset.
+//!
+//! Inputs are downloaded into memory, like URL inputs. Outputs are uploaded
+//! in parts while they are encoded, so the encoded output is never held in
+//! memory next to the input.
+
+use crate::batch::{BatchSummary, DirectorySummary};
+use crate::error::{self, RemoveBgError, Result};
+use crate::options::RemoveBgOptions;
+use crate::output::{self, OutputFormat};
+use crate::progress::LOG_TARGET;
+use std::path::Path;
+
+/// URI schemes of the supported object stores.
+const SCHEMES: [&str; 3] = ["s3://", "gs://", "az://"];
+
+/// Whether an input or output argument is an `s3://`, `gs://` or `az://`
+/// URI rather than a path.
+pub fn is_object_uri(argument: &str) -> bool {
+    let lower = argument.get(..5).unwrap_or(argument).to_ascii_lowercase();
+    SCHEMES.iter().any(|scheme| lower.starts_with(scheme))
+}
+
+/// Remove the background from an image and write the result, where the input,
+/// the output or both are object store URIs; the other may be a local path.
+///
+/// When `output` is `None`, the cutout is written next to the input, named as
+/// for a local file (`s3://photos/2024/a.jpg` becomes
+/// `s3://photos/2024/a_nobg.png`). The output format is `options.format`,
+/// else the output's extension, else PNG. An existing output is replaced
+/// only with `options.overwrite`, as for files.
+///
+/// # Returns
+/// The output URI or path that was written.
+///
+/// # Errors
+/// * `FileNotFound` - If the input object or file does not exist
+/// * `AccessDenied` - If the store refuses the credentials, or none were found
+/// * `StorageError` - If the URI is malformed, the transfer fails, or removebg
+///   was built without the `object-store` feature
+/// * `DownloadError` - If the input exceeds `options.limits.max_download_size`
+/// * `OutputExists` - If the output exists and overwriting was not requested
+/// * Otherwise the same as [`remove_background_from_bytes`](crate::remove_background_from_bytes)
+///
+/// # Examples
+/// ```no_run
+/// use removebg::{cloud, RemoveBgOptions};
+///
+/// let output = cloud::remove_background_uri(
+///     "s3://shoots/2024/photo.jpg",
+///     Some("s3://cutouts/2024/photo.png"),
+///     &RemoveBgOptions::default(),
+/// )?;
+/// # Ok::<(), removebg::error::RemoveBgError>(())
+/// ```
+pub fn remove_background_uri(input: &str, output: Option<&str>, options: &RemoveBgOptions) -> Result<String> {
+    #[cfg(feature = "object-store")]
+    return store::remove_uri(input, output, options);
+    #[cfg(not(feature = "object-store"))]
+    {
+        let _ = (input, output, options);
+        Err(feature_missing())
+    }
+}
+
+/// Remove the background from every image under an object store prefix, the
+/// equivalent of [`process_directory`](crate::batch::process_directory) for
+/// a bucket.
+///
+/// `prefix` is a URI such as `s3://shoots/2024/`; with `recursive`, deeper
+/// "directories" (keys with more `/`-separated parts) are processed too.
+/// Cutouts are written under `output_dir`, a URI or a local directory,
+/// mirroring the keys below `prefix`, or next to their inputs if it is
+/// `None`, in which case earlier outputs are not processed again. Failed
+/// images are logged and counted; the run continues.
+///
+/// # Errors
+/// * `AccessDenied` - If the prefix cannot be listed with the credentials found
+/// * `StorageError` - If the prefix cannot be listed, or removebg was built
+///   without the `object-store` feature
+/// * `ModelInitError` - If the model cannot be loaded
+pub fn process_prefix(
+    prefix: &str,
+    output_dir: Option<&str>,
+    recursive: bool,
+    options: &RemoveBgOptions,
+) -> Result<BatchSummary> {
+    #[cfg(feature = "object-store")]
+    return store::process_prefix(prefix, output_dir, recursive, options);
+    #[cfg(not(feature = "object-store"))]
+    {
+        let _ = (prefix, output_dir, recursive, options);
+        Err(feature_missing())
+    }
+}
+
+#[cfg(not(feature = "object-store"))]
+fn feature_missing() -> RemoveBgError {
+    RemoveBgError::StorageError(
+        "s3://, gs:// and az:// URIs need removebg built with the `object-store` feature \
+         (cargo install removebg --features object-store)"
+            .into(),
+    )
+}
+
+/// The output URI and format for `output`, negotiated like a local output
+/// file's by [`negotiate_format`](output::negotiate_format).
+#[cfg_attr(not(feature = "object-store"), allow(dead_code))]
+fn negotiate(output: &str, options: &RemoveBgOptions) -> Result<(String, OutputFormat)> {
+    let (path, format) = output::negotiate_format(Path::new(output), OutputFormat::default(), options)?;
+    Ok((path.to_string_lossy().into_owned(), format))
+}
+
+/// `<dir>/<name>` for a URI or local directory.
+#[cfg_attr(not(feature = "object-store"), allow(dead_code))]
+fn join(dir: &str, name: &str) -> String {
+    match is_object_uri(dir) {
+        true => format!("{}/{}", dir.trim_end_matches('/'), name),
+        false => Path::new(dir).join(name).to_string_lossy().into_owned(),
+    }
+}
+
+/// Log and count the outcome of one image of a prefix batch.
+///
+/// Returns the errors that stop the run: cancellation and model loading.
+#[cfg_attr(not(feature = "object-store"), allow(dead_code))]
+fn count(input: &str, result: Result<String>, counts: &mut DirectorySummary) -> Result<()> {
+    match result {
+        Ok(output) => {
+            log::info!(target: LOG_TARGET, "{} -> {}", input, output);
+            counts.processed += 1;
+        }
+        Err(e @ (RemoveBgError::Cancelled | RemoveBgError::ModelInitError(_) | RemoveBgError::ModelNotCached { .. })) => return Err(e),
+        Err(e) => {
+            log::warn!("{}", error::describe(Path::new(input), &e));
+            counts.failed += 1;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "object-store")]
+mod store {
+    use super::{count, join, negotiate};
+    use crate::batch::{BatchSummary, DirectorySummary};
+    use crate::core::{self, is_output_name, output_file_name, replaces_existing};
+    use crate::error::{self, RemoveBgError, Result, Stage};
+    use crate::options::RemoveBgOptions;
+    use crate::output::{self, OutputFormat};
+    use crate::watch::is_image_file;
+    use object_store::aws::AmazonS3Builder;
+    use object_store::azure::MicrosoftAzureBuilder;
+    use object_store::gcp::GoogleCloudStorageBuilder;
+    use object_store::path::Path as ObjectPath;
+    use object_store::{ObjectStore, WriteMultipart};
+    use std::fs::File;
+    use std::io::{self, BufWriter, Write};
+    use std::path::{Path, PathBuf};
+    use std::sync::OnceLock;
+    use tokio::runtime::Runtime;
+
+    /// Parts uploaded at once while an output is written.
+    const PARTS_IN_FLIGHT: usize = 4;
+
+    /// An object in a store: `s3://<bucket>/<key>`.
+    struct Location {
+        store: Box<dyn ObjectStore>,
+        /// `s3://<bucket>`, without a trailing slash.
+        root: String,
+        key: ObjectPath,
+        uri: String,
+    }
+
+    impl Location {
+        fn parse(uri: &str) -> Result<Self> {
+            let (scheme, rest) = uri.split_once("://").unwrap_or_default();
+            let (bucket, key) = rest.split_once('/').unwrap_or((rest, ""));
+            if bucket.is_empty() {
+                return Err(RemoveBgError::StorageError(format!("{}: missing bucket name", uri)));
+            }
+            let root = format!("{}://{}", scheme.to_ascii_lowercase(), bucket);
+            let store: Box<dyn ObjectStore> = match scheme.to_ascii_lowercase().as_str() {
+                "s3" => Box::new(AmazonS3Builder::from_env().with_url(&root).build().map_err(|e| storage_error(uri, e))?),
+                "gs" => {
+                    Box::new(GoogleCloudStorageBuilder::from_env().with_url(&root).build().map_err(|e| storage_error(uri, e))?)
+                }
+                _ => Box::new(MicrosoftAzureBuilder::from_env().with_url(&root).build().map_err(|e| storage_error(uri, e))?),
+            };
+            Ok(Location { store, root, key: ObjectPath::from(key), uri: uri.to_string() })
+        }
+
+        /// The object `key` in the same store.
+        fn sibling(&self, key: &ObjectPath) -> String {
+            format!("{}/{}", self.root, key)
+        }
+    }
+
+    /// The runtime driving object store requests; the rest of removebg is
+    /// synchronous, so one thread is enough.
+    fn runtime() -> Result<&'static Runtime> {
+        static RUNTIME: OnceLock<std::result::Result<Runtime, String>> = OnceLock::new();
+        RUNTIME
+            .get_or_init(|| {
+                tokio::runtime::Builder::new_current_thread().enable_all().build().map_err(|e| e.to_string())
+            })
+            .as_ref()
+            .map_err(|e| RemoveBgError::StorageError(format!("cannot start the I/O runtime: {}", e)))
+    }
+
+    /// A store error in the variant telling what went wrong: a missing
+    /// object, refused credentials, or anything else, such as the network.
+    fn storage_error(uri: &str, error: object_store::Error) -> RemoveBgError {
+        match error {
+            object_store::Error::NotFound { .. } => RemoveBgError::FileNotFound(uri.into()),
+            object_store::Error::Unauthenticated { .. } | object_store::Error::PermissionDenied { .. } => {
+                RemoveBgError::AccessDenied(format!("{}: {}", uri, error))
+            }
+            error => RemoveBgError::StorageError(format!("{}: {}", uri, error)),
+        }
+    }
+
+    /// Download the object at `uri` into memory.
+    fn fetch(location: &Location, max_size: Option<u64>) -> Result<Vec<u8>> {
+        let uri = &location.uri;
+        runtime()?.block_on(async {
+            let object = location.store.get(&location.key).await.map_err(|e| storage_error(uri, e))?;
+            if let Some(limit) = max_size.filter(|&limit| object.meta.size > limit) {
+                return Err(RemoveBgError::DownloadError(format!(
+                    "{}: object of {} bytes exceeds the limit of {} bytes",
+                    uri, object.meta.size, limit
+                )));
+            }
+            let data = object.bytes().await.map_err(|e| storage_error(uri, e))?;
+            Ok(Vec::from(data))
+        })
+    }
+
+    /// Whether the object exists.
+    fn exists(location: &Location) -> Result<bool> {
+        runtime()?.block_on(async {
+            match location.store.head(&location.key).await {
+                Ok(_) => Ok(true),
+                Err(object_store::Error::NotFound { .. }) => Ok(false),
+                Err(e) => Err(storage_error(&location.uri, e)),
+            }
+        })
+    }
+
+    /// Uploads what is written to it in parts, waiting while too many parts
+    /// are in flight. The first failure is kept to be reported as such rather
+    /// than as the encoder's I/O error.
+    struct ObjectWriter {
+        upload: WriteMultipart,
+        runtime: &'static Runtime,
+        failure: Option<object_store::Error>,
+    }
+
+    impl Write for ObjectWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let upload = &mut self.upload;
+            let waited = self.runtime.block_on(async {
+                upload.wait_for_capacity(PARTS_IN_FLIGHT).await?;
+                // Spawns the part's upload once a part is full
+                upload.write(buf);
+                Ok(())
+            });
+            waited.map(|()| buf.len()).map_err(|e: object_store::Error| {
+                let message = e.to_string();
+                self.failure.get_or_insert(e);
+                io::Error::other(message)
+            })
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Run `encode` into a multipart upload of `location`, completing the
+    /// upload if it succeeds and aborting it otherwise.
+    fn upload(location: &Location, encode: impl FnOnce(&mut ObjectWriter) -> Result<()>) -> Result<()> {
+        let uri = &location.uri;
+        let runtime = runtime()?;
+        let upload = runtime.block_on(location.store.put_multipart(&location.key)).map_err(|e| storage_error(uri, e))?;
+        let mut writer = ObjectWriter { upload: WriteMultipart::new(upload), runtime, failure: None };
+        let encoded = encode(&mut writer);
+        let ObjectWriter { upload, failure, .. } = writer;
+        match (encoded, failure) {
+            (Ok(()), _) => runtime.block_on(upload.finish()).map(drop).map_err(|e| storage_error(uri, e)),
+            (Err(e), failure) => {
+                if let Err(abort) = runtime.block_on(upload.abort()) {
+                    log::debug!("{}: could not abort the upload: {}", uri, abort);
+                }
+                Err(failure.map_or(e, |failure| storage_error(uri, failure).in_stage(Stage::Encode)))
+            }
+        }
+    }
+
+    /// Create the local output file, refusing to replace an existing one
+    /// unless `options` allow it.
+    fn create_file(path: &Path, options: &RemoveBgOptions) -> Result<BufWriter<File>> {
+        if path.exists() && !replaces_existing(options) {
+            return Err(RemoveBgError::OutputExists(path.to_path_buf()));
+        }
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty() && !dir.exists()) {
+            if !options.create_dirs {
+                return Err(RemoveBgError::OutputDirectoryMissing(dir.to_path_buf()));
+            }
+            std::fs::create_dir_all(dir)?;
+        }
+        Ok(BufWriter::new(File::create(path)?))
+    }
+
+    pub(super) fn remove_uri(input: &str, output: Option<&str>, options: &RemoveBgOptions) -> Result<String> {
+        error::catch_panic(|| {
+            let source = super::is_object_uri(input).then(|| Location::parse(input)).transpose()?;
+            let (output, format) = match output {
+                Some(output) => negotiate(output, options)?,
+                None => {
+                    let stem = Path::new(input).file_stem().unwrap_or_else(|| "output".as_ref());
+                    let format = output::selected_format(options, OutputFormat::default());
+                    let name = output_file_name(stem, format, options);
+                    let name = name.to_string_lossy();
+                    let output = match &source {
+                        Some(source) => {
+                            let dir = source.key.as_ref().rsplit_once('/').map_or("", |(dir, _)| dir);
+                            source.sibling(&ObjectPath::from(join_key(dir, &name)))
+                        }
+                        None => Path::new(input).with_file_name(name.as_ref()).to_string_lossy().into_owned(),
+                    };
+                    (output, format)
+                }
+            };
+            let options = RemoveBgOptions { format: Some(format), ..options.clone() };
+            let options = &options.timed();
+            let load = || core::shared_remover(options);
+
+            // Downloaded before the output is touched, so a missing input leaves no empty object
+            let data = match &source {
+                Some(source) => {
+                    Some(fetch(source, options.limits.max_download_size).map_err(|e| e.in_stage(Stage::Download))?)
+                }
+                None => None,
+            };
+            let encode = |writer: &mut dyn Write| match &data {
+                Some(data) => core::remove_bytes_to_writer(data, &mut &mut *writer, options, load).map(|_| ()),
+                None => core::remove_to_writer(Path::new(input), &mut &mut *writer, options, load),
+            };
+
+            if super::is_object_uri(&output) {
+                let target = Location::parse(&output)?;
+                if !replaces_existing(options) && exists(&target)? {
+                    return Err(RemoveBgError::OutputExists(PathBuf::from(&output)));
+                }
+                upload(&target, |writer| encode(writer))?;
+            } else {
+                let mut file = create_file(Path::new(&output), options)?;
+                encode(&mut file)?;
+                file.flush()?;
+            }
+            Ok(output)
+        })
+        .map_err(|e| e.for_input(Path::new(input)))
+    }
+
+    pub(super) fn process_prefix(
+        prefix: &str,
+        output_dir: Option<&str>,
+        recursive: bool,
+        options: &RemoveBgOptions,
+    ) -> Result<BatchSummary> {
+        let base = Location::parse(prefix)?;
+        let runtime = runtime()?;
+        let mut summary = BatchSummary::default();
+        // Keys of the "directories" still to list, relative to the prefix, popped depth-first
+        let mut stack = vec![String::new()];
+
+        while let Some(relative) = stack.pop() {
+            if options.cancel.is_cancelled() {
+                summary.cancelled = true;
+                break;
+            }
+            let dir = base.key.parts().chain(ObjectPath::from(relative.as_str()).parts()).collect::<ObjectPath>();
+            let listing = runtime
+                .block_on(base.store.list_with_delimiter(Some(&dir)))
+                .map_err(|e| storage_error(&base.sibling(&dir), e))?;
+
+            if recursive {
+                let mut subdirs: Vec<String> = listing
+                    .common_prefixes
+                    .iter()
+                    .filter_map(|key| key.filename().map(|name| join_key(&relative, name)))
+                    .collect();
+                subdirs.sort();
+                stack.extend(subdirs.into_iter().rev());
+            }
+            let mut inputs: Vec<&ObjectPath> = listing
+                .objects
+                .iter()
+                .map(|object| &object.location)
+                .filter(|key| {
+                    let name = Path::new(key.filename().unwrap_or_default());
+                    is_image_file(name) && !(output_dir.is_none() && is_output_name(name, options))
+                })
+                .collect();
+            if inputs.is_empty() {
+                continue;
+            }
+            inputs.sort();
+
+            let out_dir = match output_dir {
+                Some(root) if relative.is_empty() => root.to_string(),
+                Some(root) => join(root, &relative),
+                None => base.sibling(&dir),
+            };
+            let mut counts = DirectorySummary { path: PathBuf::from(&relative), ..Default::default() };
+            for key in inputs {
+                if options.cancel.is_cancelled() {
+                    summary.cancelled = true;
+                    break;
+                }
+                let input = base.sibling(key);
+                let stem = Path::new(key.filename().unwrap_or_default()).file_stem().unwrap_or_default();
+                let name = output_file_name(stem, output::selected_format(options, OutputFormat::default()), options);
+                let output = join(&out_dir, &name.to_string_lossy());
+                match count(&input, remove_uri(&input, Some(&output), options), &mut counts) {
+                    Ok(()) => {}
+                    Err(RemoveBgError::Cancelled) => summary.cancelled = true,
+                    Err(e) => return Err(e),
+                }
+                if summary.cancelled {
+                    break;
+                }
+            }
+            summary.processed += counts.processed;
+            summary.failed += counts.failed;
+            summary.directories.push(counts);
+            if summary.cancelled {
+                break;
+            }
+        }
+        Ok(summary)
+    }
+
+    /// `<dir>/<name>` for keys relative to a prefix, where `dir` may be empty.
+    fn join_key(dir: &str, name: &str) -> String {
+        match dir.is_empty() {
+            true => name.to_string(),
+            false => format!("{}/{}", dir, name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::NamingOptions;
+
+    #[test]
+    fn object_uris_are_told_from_paths() {
+        for uri in ["s3://bucket/a.jpg", "gs://bucket/", "AZ://container/dir/a.png"] {
+            assert!(is_object_uri(uri), "{}", uri);
+        }
+        for path in ["photo.jpg", "s3", "s3:/bucket/a.jpg", "https://example.com/a.jpg", "./s3://a.jpg", ""] {
+            assert!(!is_object_uri(path), "{}", path);
+        }
+    }
+
+    #[test]
+    fn output_formats_are_negotiated_like_files() {
+        let options = RemoveBgOptions::default();
+        assert_eq!(negotiate("s3://cutouts/a.webp", &options).unwrap(), ("s3://cutouts/a.webp".into(), OutputFormat::WebP));
+        assert!(negotiate("s3://cutouts/a", &options).is_err());
+        let tiff = RemoveBgOptions { format: Some(OutputFormat::Tiff), ..Default::default() };
+        assert!(negotiate("s3://cutouts/a.webp", &tiff).is_err());
+        let fix = RemoveBgOptions { naming: NamingOptions { fix_extension: true, ..Default::default() }, ..tiff };
+        assert_eq!(negotiate("s3://cutouts/a.webp", &fix).unwrap(), ("s3://cutouts/a.tiff".into(), OutputFormat::Tiff));
+        assert_eq!(negotiate("s3://cutouts/a", &fix).unwrap(), ("s3://cutouts/a.tiff".into(), OutputFormat::Tiff));
+    }
+
+    #[test]
+    fn outputs_join_uris_and_directories() {
+        assert_eq!(join("s3://cutouts/2024/", "a_nobg.png"), "s3://cutouts/2024/a_nobg.png");
+        assert_eq!(join("gs://cutouts", "a_nobg.png"), "gs://cutouts/a_nobg.png");
+        assert_eq!(join("out", "a_nobg.png"), Path::new("out").join("a_nobg.png").to_string_lossy());
+    }
+
+    #[cfg(not(feature = "object-store"))]
+    #[test]
+    fn uris_need_the_feature() {
+        let error = remove_background_uri("s3://bucket/a.jpg", None, &RemoveBgOptions::default()).unwrap_err();
+        assert!(matches!(error, RemoveBgError::StorageError(ref message) if message.contains("--features object-store")));
+        assert_eq!(error.exit_code(), 6);
+    }
+}
@@ -0,0 +1,365 @@
+//! A/B comparison of two option sets on the same images (`serde` feature).
+//!
+//! Used by `removebg compare`. Each input is processed under both option
+//! sets, and [`compare_image`] measures how far apart the results are: the
+//! mean alpha difference and the share of changed pixels of the finished
+//! masks, the overlap of the subjects' bounding boxes, and the size of the
+//! encoded outputs. Nothing is written besides the optional difference
+//! images.
+//!
+//! When the option sets differ only after segmentation, as when tuning
+//! refinement or compositing, the image is segmented once and both option
+//! sets finish that mask, so a comparison costs one model run per image.
+//!
+//! Option sets are written as comma-separated settings, parsed by
+//! [`parse_option_set`]:
+//!
+//! ```text
+//! preset=product,feather=3,threshold=0.4,model=u2net,geometry.square=true
+//! ```
+
+use crate::core::{self, Channel, Gray16Image, SessionKey};
+use crate::daemon;
+use crate::error::{self, RemoveBgError, Result, Stage};
+use crate::geometry;
+use crate::mask_cache;
+use crate::options::{MaskOp, RemoveBgOptions};
+use crate::output::{self, OutputFormat, Selection};
+use crate::preset::Preset;
+use crate::report::{Rect, StageDurations};
+use crate::testing;
+use image::imageops::FilterType;
+use image::{GrayImage, Luma};
+use serde_json::{Map, Value};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Names of the mask operations an option set may list, as in `--mask-ops`.
+const MASK_OPS: &[&str] = &["threshold", "levels", "gamma", "erode", "dilate", "feather", "fill-holes", "keep-largest"];
+
+/// How the results of two option sets differ on one image.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ImageComparison {
+    /// The compared image.
+    pub input: PathBuf,
+    /// Mean absolute difference of the finished masks, from 0.0 (the same)
+    /// to 1.0.
+    pub mean_alpha_difference: f32,
+    /// Percentage of pixels whose 8-bit alpha differs.
+    pub changed_percent: f32,
+    /// Intersection over union of the subjects' bounding boxes, from 0.0 (no
+    /// overlap) to 1.0; 1.0 when both masks are empty.
+    pub bounds_iou: f32,
+    /// Size of the encoded output of option set A in bytes.
+    pub size_a: u64,
+    /// Size of the encoded output of option set B in bytes.
+    pub size_b: u64,
+    /// `size_b - size_a`.
+    pub size_delta: i64,
+    /// Times the image was segmented: 1 when both option sets share the
+    /// mask, else 2.
+    pub inferences: u32,
+    /// The difference image, when one was written.
+    pub diff_path: Option<PathBuf>,
+}
+
+/// Aggregate of the [`ImageComparison`]s of a run.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub struct ComparisonSummary {
+    /// Images compared.
+    pub images: usize,
+    /// Mean over the images of [`ImageComparison::mean_alpha_difference`].
+    pub mean_alpha_difference: f32,
+    /// Mean over the images of [`ImageComparison::changed_percent`].
+    pub changed_percent: f32,
+    /// Mean over the images of [`ImageComparison::bounds_iou`].
+    pub bounds_iou: f32,
+    /// Sum of the images' [`ImageComparison::size_delta`].
+    pub size_delta: i64,
+    /// Sum of the images' [`ImageComparison::inferences`].
+    pub inferences: u32,
+}
+
+impl ComparisonSummary {
+    /// Summarize `images`.
+    pub fn of(images: &[ImageComparison]) -> Self {
+        let mean = |value: fn(&ImageComparison) -> f32| {
+            images.iter().map(value).sum::<f32>() / images.len().max(1) as f32
+        };
+        ComparisonSummary {
+            images: images.len(),
+            mean_alpha_difference: mean(|image| image.mean_alpha_difference),
+            changed_percent: mean(|image| image.changed_percent),
+            bounds_iou: mean(|image| image.bounds_iou),
+            size_delta: images.iter().map(|image| image.size_delta).sum(),
+            inferences: images.iter().map(|image| image.inferences).sum(),
+        }
+    }
+}
+
+/// `base` with the settings in `spec` applied.
+///
+/// `spec` lists comma-separated settings, applied in this order whatever
+/// their order in `spec`:
+///
+/// * `preset=NAME` - The fields a [`Preset`] sets
+/// * `KEY=VALUE` - A field of [`RemoveBgOptions`] in its serialized form, as
+///   in the daemon's `options`: `model=u2net`, `background=white`,
+///   `fill_holes=true`. Nested fields are named with dots, like
+///   `geometry.pad=0.05`, and dashes may stand for underscores. Values are
+///   read as JSON, or as a string if they aren't JSON.
+/// * Mask operations as in `--mask-ops`, like `feather=3` or
+///   `threshold=0.4`, appended to [`RemoveBgOptions::mask_ops`]
+///
+/// # Errors
+/// * `InvalidConfig` - If a setting is unknown or its value is invalid
+///
+/// # Examples
+/// ```
+/// use removebg::compare::parse_option_set;
+/// use removebg::{Background, MaskOp, RemoveBgOptions};
+///
+/// let options = parse_option_set("preset=product,feather=3,threshold=0.4", &RemoveBgOptions::default())?;
+/// assert_eq!(options.background, Background::Color([255, 255, 255]));
+/// assert_eq!(options.mask_ops, [MaskOp::Feather(3.0), MaskOp::Threshold(0.4)]);
+/// # Ok::<(), removebg::error::RemoveBgError>(())
+/// ```
+pub fn parse_option_set(spec: &str, base: &RemoveBgOptions) -> Result<RemoveBgOptions> {
+    let invalid = |message: String| RemoveBgError::InvalidConfig(format!("option set '{}': {}", spec, message));
+    let fields = serde_json::to_value(base).map_err(|e| RemoveBgError::InvalidConfig(e.to_string()))?;
+    let mut options = base.clone();
+    let (mut overrides, mut mask_ops) = (Map::new(), Vec::new());
+    for setting in spec.split(',').map(str::trim).filter(|setting| !setting.is_empty()) {
+        let (key, value) = match setting.split_once('=') {
+            Some((key, value)) => (key.trim().to_ascii_lowercase(), Some(value.trim())),
+            None => (setting.to_ascii_lowercase(), None),
+        };
+        if key == "preset" {
+            value.unwrap_or_default().parse::<Preset>().map_err(invalid)?.apply(&mut options);
+            continue;
+        }
+        if MASK_OPS.contains(&key.as_str()) {
+            mask_ops.push(setting.parse::<MaskOp>().map_err(invalid)?);
+            continue;
+        }
+        let value = value.ok_or_else(|| invalid(format!("'{}' needs a value, like {}=...", key, key)))?;
+        let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()));
+        let key = key.replace('-', "_");
+        let path: Vec<&str> = key.split('.').collect();
+        // Fields that are unset have no known subfields to check against
+        let mut known = Some(&fields);
+        let mut target = &mut overrides;
+        for (depth, name) in path.iter().enumerate() {
+            if let Some(parent) = known.filter(|parent| !parent.is_null()) {
+                known = Some(parent.get(name).ok_or_else(|| invalid(format!("unknown setting '{}'", key)))?);
+            } else {
+                known = None;
+            }
+            if depth + 1 == path.len() {
+                target.insert(name.to_string(), value.clone());
+            } else {
+                let entry = target.entry(name.to_string()).or_insert_with(|| Value::Object(Map::new()));
+                target = entry.as_object_mut().ok_or_else(|| invalid(format!("'{}' is set twice", key)))?;
+            }
+        }
+    }
+    let mut options = daemon::merge(&options, Value::Object(overrides)).map_err(|e| invalid(e.to_string()))?;
+    options.mask_ops.extend(mask_ops);
+    Ok(options)
+}
+
+/// Whether options `a` and `b` get the same unrefined mask for an image, so
+/// it can be computed once for both.
+pub fn shares_segmentation(a: &RemoveBgOptions, b: &RemoveBgOptions) -> bool {
+    SessionKey::from_options(a) == SessionKey::from_options(b)
+        && mask_cache::settings(a) == mask_cache::settings(b)
+        && a.max_output_size == b.max_output_size
+        && a.external_mask == b.external_mask
+        && a.line_art == b.line_art
+        && a.auto_model == b.auto_model
+}
+
+/// Process `input` under option sets `a` and `b` and measure how their
+/// results differ. With `diff_dir`, a difference image named
+/// `<stem>_diff.png` is written into it (see [`testing::diff_image`]): B's
+/// mask in dark gray, red where A keeps more of the subject and blue where B
+/// does.
+///
+/// The image is decoded once, with the limits of `a`. Outputs are encoded
+/// in memory to measure their size, without `max_file_size`.
+///
+/// # Errors
+/// * The errors of [`remove_background_with_options`](crate::remove_background_with_options)
+///   under either option set, except for writing the output
+/// * `IoError` - If the difference image cannot be written
+pub fn compare_image(input: &Path, a: &RemoveBgOptions, b: &RemoveBgOptions, diff_dir: Option<&Path>) -> Result<ImageComparison> {
+    let (a, b) = (&a.timed(), &b.timed());
+    error::catch_panic(|| {
+        for options in [a, b] {
+            core::check_transparency(output::selected_format(options, OutputFormat::default()), Selection::of(options), options)?;
+        }
+        let (decoded, metadata) = core::open_input(input, &a.limits)?;
+        let shared = shares_segmentation(a, b);
+        let (mut segmentation, mut inferences) = (None, 0);
+        let mut run = |options: &RemoveBgOptions| -> Result<(Gray16Image, u64)> {
+            let image = core::limit_output_size(decoded.clone(), options).map_err(|e| e.in_stage(Stage::Decode))?;
+            let mut durations = StageDurations::default();
+            let segmented = match segmentation.clone().filter(|_| shared) {
+                Some(segmented) => segmented,
+                None => {
+                    inferences += 1;
+                    let segmented = core::segment(&image, options, &mut durations, || core::shared_remover(options))
+                        .map_err(|e| e.in_stage(Stage::Segmentation))?;
+                    segmentation = Some(segmented.clone());
+                    segmented
+                }
+            };
+            let processed = core::finish_processing(&image, segmented, options, &mut durations, true)?;
+            let mut encoded = Vec::new();
+            let format = output::selected_format(options, OutputFormat::default());
+            output::encode_stream(&processed.image, &mut encoded, format, options, &metadata)
+                .map_err(|e| e.in_stage(Stage::Encode))?;
+            Ok((processed.mask.expect("the mask is kept"), encoded.len() as u64))
+        };
+        let (mask_a, size_a) = run(a)?;
+        let (mask_b, size_b) = run(b)?;
+        // Masks of different `max_output_size`s are compared at the size of A's
+        let mask_b = match mask_b.dimensions() == mask_a.dimensions() {
+            true => mask_b,
+            false => image::imageops::resize(&mask_b, mask_a.width(), mask_a.height(), FilterType::Triangle),
+        };
+
+        let diff_path = match diff_dir {
+            Some(dir) => {
+                std::fs::create_dir_all(dir)?;
+                let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+                let path = dir.join(format!("{}_diff.png", stem));
+                testing::diff_image(&mask_8bit(&mask_a), &mask_8bit(&mask_b)).save(&path)?;
+                Some(path)
+            }
+            None => None,
+        };
+        let (total, changed) = mask_a.iter().zip(mask_b.iter()).fold((0u64, 0u64), |(total, changed), (&a, &b)| {
+            (total + a.abs_diff(b) as u64, changed + (u8::from_mask(a) != u8::from_mask(b)) as u64)
+        });
+        let pixels = mask_a.len().max(1) as f64;
+        Ok(ImageComparison {
+            input: input.to_path_buf(),
+            mean_alpha_difference: (total as f64 / 65535.0 / pixels) as f32,
+            changed_percent: (changed as f64 * 100.0 / pixels) as f32,
+            bounds_iou: bounds_iou(mask_bounds(&mask_a), mask_bounds(&mask_b)),
+            size_a,
+            size_b,
+            size_delta: size_b as i64 - size_a as i64,
+            inferences,
+            diff_path,
+        })
+    })
+    .map_err(|e| e.for_input(input))
+}
+
+/// Write `images` as CSV, one row per image with the fields of
+/// [`ImageComparison`] as columns.
+///
+/// # Errors
+/// * `IoError` - If writing fails
+pub fn write_csv(writer: impl Write, images: &[ImageComparison]) -> Result<()> {
+    let mut csv = csv::Writer::from_writer(writer);
+    csv.write_record([
+        "input",
+        "mean_alpha_difference",
+        "changed_percent",
+        "bounds_iou",
+        "size_a",
+        "size_b",
+        "size_delta",
+        "inferences",
+        "diff_path",
+    ])
+    .map_err(io::Error::from)?;
+    for image in images {
+        csv.write_record([
+            image.input.display().to_string(),
+            image.mean_alpha_difference.to_string(),
+            image.changed_percent.to_string(),
+            image.bounds_iou.to_string(),
+            image.size_a.to_string(),
+            image.size_b.to_string(),
+            image.size_delta.to_string(),
+            image.inferences.to_string(),
+            image.diff_path.as_ref().map(|path| path.display().to_string()).unwrap_or_default(),
+        ])
+        .map_err(io::Error::from)?;
+    }
+    csv.flush()?;
+    Ok(())
+}
+
+fn mask_bounds(mask: &Gray16Image) -> Option<Rect> {
+    geometry::bounds(mask.width(), mask.height(), |x, y| mask.get_pixel(x, y)[0] as f32 / 65535.0)
+}
+
+/// Intersection over union of two bounding boxes; 1.0 when both are empty.
+fn bounds_iou(a: Option<Rect>, b: Option<Rect>) -> f32 {
+    let (a, b) = match (a, b) {
+        (None, None) => return 1.0,
+        (Some(a), Some(b)) => (a, b),
+        _ => return 0.0,
+    };
+    let area = |rect: &Rect| rect.width as u64 * rect.height as u64;
+    let width = (a.x + a.width).min(b.x + b.width).saturating_sub(a.x.max(b.x));
+    let height = (a.y + a.height).min(b.y + b.height).saturating_sub(a.y.max(b.y));
+    let intersection = width as u64 * height as u64;
+    intersection as f32 / (area(&a) + area(&b) - intersection) as f32
+}
+
+/// `mask` reduced to 8 bits.
+fn mask_8bit(mask: &Gray16Image) -> GrayImage {
+    GrayImage::from_fn(mask.width(), mask.height(), |x, y| Luma([u8::from_mask(mask.get_pixel(x, y)[0])]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Model;
+    use crate::options::{Background, MaskFilter};
+
+    #[test]
+    fn option_sets_apply_presets_fields_and_mask_ops() {
+        let base = RemoveBgOptions::default();
+        let options = parse_option_set("feather=3, geometry.square=true, preset=product, mask-filter=lanczos3", &base).unwrap();
+        assert_eq!(options.background, Background::Color([255, 255, 255]));
+        assert!(options.geometry.square && options.fill_holes);
+        assert_eq!(options.mask_filter, MaskFilter::Lanczos3);
+        assert_eq!(options.mask_ops, [MaskOp::Feather(3.0)]);
+        // Fields win over the preset
+        let options = parse_option_set("preset=product,background=transparent,model=u2netp", &base).unwrap();
+        assert_eq!((options.background, options.model), (Background::Transparent, Model::U2netp));
+        assert_eq!(parse_option_set("", &base).unwrap().mask_ops, []);
+
+        for spec in ["colour=red", "preset=poster", "threshold=2", "model=u3net", "fill_holes", "geometry.depth=1"] {
+            let error = parse_option_set(spec, &base).unwrap_err();
+            assert!(matches!(error, RemoveBgError::InvalidConfig(_)) && error.to_string().contains(spec), "{}: {}", spec, error);
+        }
+    }
+
+    #[test]
+    fn only_mask_settings_prevent_sharing() {
+        let base = RemoveBgOptions::default();
+        let refined = parse_option_set("preset=product,feather=3,threshold=0.4", &base).unwrap();
+        assert!(shares_segmentation(&base, &refined));
+        for spec in ["model=u2netp", "mask_filter=nearest", "two_pass=true", "max_output_size=100"] {
+            assert!(!shares_segmentation(&base, &parse_option_set(spec, &base).unwrap()), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn boxes_overlap() {
+        let rect = |x, y, width, height| Some(Rect { x, y, width, height });
+        assert_eq!(bounds_iou(rect(0, 0, 10, 10), rect(0, 0, 10, 10)), 1.0);
+        assert_eq!(bounds_iou(rect(0, 0, 10, 10), rect(5, 0, 10, 10)), 50.0 / 150.0);
+        assert_eq!(bounds_iou(rect(0, 0, 10, 10), rect(20, 20, 5, 5)), 0.0);
+        assert_eq!(bounds_iou(None, rect(0, 0, 1, 1)), 0.0);
+        assert_eq!(bounds_iou(None, None), 1.0);
+    }
+}
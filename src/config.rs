@@ -0,0 +1,360 @@
+//! Settings read from `REMOVEBG_*` environment variables and `removebg.toml`
+//! config files.
+//!
+//! [`Config::from_env`] reads the variables once, at startup, so containers
+//! can configure the CLI without wrapper scripts of flags.
+//! [`Config::from_toml`] reads the same settings from a config file, which
+//! the CLI takes from `--config` or finds with [`discover`], so a project can
+//! keep its defaults next to its images, and from the user's own file at
+//! [`user_config`]. The CLI merges the layers beneath its flags: a flag
+//! always wins, then the environment, then the project's config file, then
+//! the user's, then the built-in default. Library users get the same layers
+//! through [`RemoveBgOptions::from_env`] and [`RemoveBgOptions::from_toml`].
+//!
+//! Every variable the crate reads is listed in [`ENV_VARS`], which the CLI's
+//! `--help` prints as its `ENVIRONMENT:` section, so the two can't drift.
+
+use crate::error::{RemoveBgError, Result};
+use crate::model::{Model, MODEL_DIR_ENV, MODEL_URL_ENV, U2NET_HOME_ENV};
+use crate::options::{Background, Backend, Device, RemoveBgOptions};
+use crate::output::OutputFormat;
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use toml::Spanned;
+
+/// One environment variable the crate reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EnvVar {
+    /// Name of the variable.
+    pub name: &'static str,
+    /// What it sets.
+    pub description: &'static str,
+}
+
+/// Model to use, as in `--model`.
+pub const MODEL_ENV: &str = "REMOVEBG_MODEL";
+/// Inference engine, as in `--backend`.
+pub const BACKEND_ENV: &str = "REMOVEBG_BACKEND";
+/// Inference device, as in `--device`.
+pub const DEVICE_ENV: &str = "REMOVEBG_DEVICE";
+/// Output format, as in `--format`.
+pub const FORMAT_ENV: &str = "REMOVEBG_FORMAT";
+/// Lossy output quality, as in `--quality`.
+pub const QUALITY_ENV: &str = "REMOVEBG_QUALITY";
+/// Background color, as in `--bg-color`.
+pub const BG_COLOR_ENV: &str = "REMOVEBG_BG_COLOR";
+/// Never download models, as in `--offline`.
+pub const OFFLINE_ENV: &str = "REMOVEBG_OFFLINE";
+/// Base directory of the user config file on every platform, as on Linux.
+pub const CONFIG_HOME_ENV: &str = "XDG_CONFIG_HOME";
+
+/// Every environment variable read by the crate or the CLI, in the order
+/// `--help` lists them.
+pub const ENV_VARS: &[EnvVar] = &[
+    EnvVar { name: MODEL_ENV, description: "Segmentation model, like --model" },
+    EnvVar { name: MODEL_DIR_ENV, description: "Directory for cached model files, like --model-dir" },
+    EnvVar { name: U2NET_HOME_ENV, description: "Model directory used when REMOVEBG_MODEL_DIR is not set" },
+    EnvVar { name: MODEL_URL_ENV, description: "URL to download the model from, like --model-url" },
+    EnvVar { name: OFFLINE_ENV, description: "Never download models when set to 1, true or yes, like --offline" },
+    EnvVar { name: BACKEND_ENV, description: "Inference engine, like --backend" },
+    EnvVar { name: DEVICE_ENV, description: "Inference device, like --device" },
+    EnvVar { name: FORMAT_ENV, description: "Output format, like --format" },
+    EnvVar { name: QUALITY_ENV, description: "Lossy quality 0-100 for WebP and JPEG, like --quality" },
+    EnvVar { name: BG_COLOR_ENV, description: "Background color, like --bg-color" },
+    EnvVar { name: "REMOVEBG_MASK_CACHE", description: "Mask cache directory, like --mask-cache" },
+    EnvVar { name: "REMOVEBG_MASK_CACHE_SIZE", description: "Mask cache size limit, like --mask-cache-size" },
+    EnvVar { name: "REMOVEBG_DOWNLOAD_ATTEMPTS", description: "Model download attempts, like --download-attempts" },
+    EnvVar { name: "REMOVEBG_TIMEOUT", description: "Time budget per input, like --timeout" },
+    EnvVar { name: "REMOVEBG_MAX_MEMORY", description: "Memory budget per image, like --max-memory" },
+    EnvVar { name: "REMOVEBG_AUTO_DOWNLOAD", description: "Download a missing model without asking when set to 1, like --yes" },
+    EnvVar { name: "REMOVEBG_ORT_THREADS", description: "ONNX Runtime intra-op threads, like --ort-threads" },
+    EnvVar { name: "REMOVEBG_ORT_INTER_THREADS", description: "ONNX Runtime inter-op threads, like --ort-inter-threads" },
+    EnvVar { name: "REMOVEBG_ORT_OPT_LEVEL", description: "ONNX Runtime optimization level, like --ort-opt-level" },
+    EnvVar { name: "REMOVEBG_ORT_MEMORY_LIMIT", description: "ONNX Runtime memory arena cap, like --ort-memory-limit" },
+    EnvVar { name: "RAYON_NUM_THREADS", description: "Threads for mask application and compositing (parallel feature), like --jobs" },
+    EnvVar { name: "SOURCE_DATE_EPOCH", description: "Modification time of the outputs written, in seconds since 1970" },
+    EnvVar { name: CONFIG_HOME_ENV, description: "Directory holding the user config file removebg/removebg.toml" },
+    EnvVar { name: "RUST_LOG", description: "Log filter, overriding -v and -q (e.g. removebg=debug)" },
+];
+
+/// Names of the config file [`discover`] looks for in each directory, in
+/// order.
+pub const CONFIG_FILE_NAMES: &[&str] = &["removebg.toml", ".removebg.toml"];
+
+/// Keys a config file may set, named like the flags they stand in for.
+pub const CONFIG_KEYS: &[&str] =
+    &["model", "model_dir", "backend", "device", "format", "quality", "bg_color", "offline", "output_dir"];
+
+/// Settings taken from the environment or a config file; `None` for
+/// variables that are unset or empty and keys the file leaves out.
+///
+/// A config file is deserialized into this directly, with the keys in
+/// [`CONFIG_KEYS`] and values written as they would be on the command line:
+///
+/// ```toml
+/// model = "isnet-general-use"
+/// bg_color = "white"
+/// quality = 90
+/// output_dir = "cutouts"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// From `REMOVEBG_MODEL` or `model`.
+    #[serde(deserialize_with = "from_str")]
+    pub model: Option<Model>,
+    /// From `REMOVEBG_MODEL_DIR` or `model_dir`.
+    pub model_dir: Option<PathBuf>,
+    /// From `REMOVEBG_BACKEND` or `backend`.
+    #[serde(deserialize_with = "from_str")]
+    pub backend: Option<Backend>,
+    /// From `REMOVEBG_DEVICE` or `device`.
+    #[serde(deserialize_with = "from_str")]
+    pub device: Option<Device>,
+    /// From `REMOVEBG_FORMAT` or `format`.
+    #[serde(deserialize_with = "from_str")]
+    pub format: Option<OutputFormat>,
+    /// From `REMOVEBG_QUALITY` or `quality`.
+    #[serde(deserialize_with = "quality")]
+    pub quality: Option<f32>,
+    /// From `REMOVEBG_BG_COLOR` or `bg_color`.
+    #[serde(rename = "bg_color", deserialize_with = "from_str")]
+    pub background: Option<Background>,
+    /// From `REMOVEBG_OFFLINE` or `offline`.
+    pub offline: Option<bool>,
+    /// From `output_dir`, where the CLI writes the cutouts of directory
+    /// input; config files only, and not an option of the library.
+    pub output_dir: Option<PathBuf>,
+}
+
+impl Config {
+    /// Read the settings from the process environment.
+    ///
+    /// # Errors
+    /// * `InvalidConfig` - If a variable has a value that doesn't parse; the
+    ///   message names the variable
+    pub fn from_env() -> Result<Self> {
+        Self::from_vars(|name| std::env::var_os(name))
+    }
+
+    /// Read the settings through `var`, which returns the value of the named
+    /// variable, e.g. from a map in tests.
+    ///
+    /// # Errors
+    /// Same as [`from_env`](Config::from_env).
+    pub fn from_vars(var: impl Fn(&str) -> Option<OsString>) -> Result<Self> {
+        let value = |name: &str| -> Result<Option<String>> {
+            match var(name).filter(|value| !value.is_empty()) {
+                Some(value) => value
+                    .into_string()
+                    .map(|value| Some(value.trim().to_string()))
+                    .map_err(|_| invalid(name, "value is not valid UTF-8".into())),
+                None => Ok(None),
+            }
+        };
+        let quality = value(QUALITY_ENV)?
+            .map(|v| match v.parse::<f32>() {
+                Ok(quality) if (0.0..=100.0).contains(&quality) => Ok(quality),
+                _ => Err(invalid(QUALITY_ENV, invalid_quality(&v))),
+            })
+            .transpose()?;
+        let offline = value(OFFLINE_ENV)?
+            .map(|v| match v.to_ascii_lowercase().as_str() {
+                "1" | "true" | "yes" | "on" => Ok(true),
+                "0" | "false" | "no" | "off" => Ok(false),
+                _ => Err(invalid(OFFLINE_ENV, format!("invalid value '{}' (expected 1, true, yes, 0, false or no)", v))),
+            })
+            .transpose()?;
+
+        Ok(Config {
+            model: parsed(MODEL_ENV, value(MODEL_ENV)?)?,
+            model_dir: var(MODEL_DIR_ENV).filter(|dir| !dir.is_empty()).map(PathBuf::from),
+            backend: parsed(BACKEND_ENV, value(BACKEND_ENV)?)?,
+            device: parsed(DEVICE_ENV, value(DEVICE_ENV)?)?,
+            format: parsed(FORMAT_ENV, value(FORMAT_ENV)?)?,
+            quality,
+            background: parsed(BG_COLOR_ENV, value(BG_COLOR_ENV)?)?,
+            offline,
+            output_dir: None,
+        })
+    }
+
+    /// Read the settings of the config file at `path`.
+    ///
+    /// Relative directories in the file are taken relative to the directory
+    /// it is in, so a project's file works from any subdirectory. Keys that
+    /// aren't in [`CONFIG_KEYS`] are logged as warnings and otherwise
+    /// ignored, unless `strict`.
+    ///
+    /// # Errors
+    /// * `InvalidConfig` - If the file can't be read or isn't valid TOML, if
+    ///   a value has the wrong type or doesn't parse, or if `strict` and the
+    ///   file has an unknown key; the message names the file and the line
+    pub fn from_toml(path: &Path, strict: bool) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| RemoveBgError::InvalidConfig(format!("{}: {}", path.display(), e)))?;
+        let mut config = Self::from_toml_str(&text, path, strict)?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for slot in [&mut config.model_dir, &mut config.output_dir] {
+            if let Some(relative) = slot.as_ref().filter(|dir| dir.is_relative()) {
+                *slot = Some(dir.join(relative));
+            }
+        }
+        Ok(config)
+    }
+
+    /// Parse the text of the config file at `path`, which is only used in
+    /// messages.
+    fn from_toml_str(text: &str, path: &Path, strict: bool) -> Result<Self> {
+        let error = |message: String| RemoveBgError::InvalidConfig(format!("{}: {}", path.display(), message));
+        let keys: BTreeMap<Spanned<String>, IgnoredAny> =
+            toml::from_str(text).map_err(|e| error(e.to_string().trim_end().to_string()))?;
+        for key in keys.keys().filter(|key| !CONFIG_KEYS.contains(&key.get_ref().as_str())) {
+            let line = text[..key.span().start].matches('\n').count() + 1;
+            let message =
+                format!("unknown key '{}' on line {} (expected one of: {})", key.get_ref(), line, CONFIG_KEYS.join(", "));
+            if strict {
+                return Err(error(message));
+            }
+            log::warn!("{}: {}", path.display(), message);
+        }
+        toml::from_str(text).map_err(|e| error(e.to_string().trim_end().to_string()))
+    }
+
+    /// These settings, with the ones they leave unset taken from `lower`:
+    /// `env.over(file)` layers the environment over a config file.
+    pub fn over(self, lower: Config) -> Config {
+        Config {
+            model: self.model.or(lower.model),
+            model_dir: self.model_dir.or(lower.model_dir),
+            backend: self.backend.or(lower.backend),
+            device: self.device.or(lower.device),
+            format: self.format.or(lower.format),
+            quality: self.quality.or(lower.quality),
+            background: self.background.or(lower.background),
+            offline: self.offline.or(lower.offline),
+            output_dir: self.output_dir.or(lower.output_dir),
+        }
+    }
+
+    /// Set the options these settings cover, leaving the rest as they are.
+    pub fn apply(&self, options: &mut RemoveBgOptions) {
+        if let Some(model) = self.model {
+            options.model = model;
+        }
+        if let Some(dir) = &self.model_dir {
+            options.model_dir = Some(dir.clone());
+        }
+        if let Some(backend) = self.backend {
+            options.backend = backend;
+        }
+        if let Some(device) = self.device {
+            options.device = device;
+        }
+        if let Some(format) = self.format {
+            options.format = Some(format);
+        }
+        if let Some(quality) = self.quality {
+            options.quality = Some(quality);
+        }
+        if let Some(background) = self.background {
+            options.background = background;
+        }
+        if let Some(offline) = self.offline {
+            options.download.offline = offline;
+        }
+    }
+}
+
+impl RemoveBgOptions {
+    /// The default options with the settings of `REMOVEBG_*` environment
+    /// variables applied (see [`Config`]).
+    ///
+    /// # Errors
+    /// * `InvalidConfig` - If a variable has a value that doesn't parse
+    pub fn from_env() -> Result<Self> {
+        let mut options = RemoveBgOptions::default();
+        Config::from_env()?.apply(&mut options);
+        Ok(options)
+    }
+
+    /// The default options with the settings of the config file at `path`
+    /// applied (see [`Config::from_toml`]); unknown keys are logged as
+    /// warnings.
+    ///
+    /// # Errors
+    /// * `InvalidConfig` - If the file can't be read or a setting in it
+    ///   doesn't parse
+    pub fn from_toml(path: impl AsRef<Path>) -> Result<Self> {
+        let mut options = RemoveBgOptions::default();
+        Config::from_toml(path.as_ref(), false)?.apply(&mut options);
+        Ok(options)
+    }
+}
+
+/// The config file for a run started in `dir`: the first of
+/// [`CONFIG_FILE_NAMES`] found in `dir` or the nearest of its ancestors.
+pub fn discover(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .flat_map(|dir| CONFIG_FILE_NAMES.iter().map(move |name| dir.join(name)))
+        .find(|path| path.is_file())
+}
+
+/// The user's config file, `removebg/removebg.toml` in `XDG_CONFIG_HOME` or
+/// else the platform config directory (e.g. `~/.config` on Linux), if it
+/// exists. Its settings apply beneath those of the project's file.
+pub fn user_config() -> Option<PathBuf> {
+    std::env::var_os(CONFIG_HOME_ENV)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(dirs::config_dir)
+        .map(|dir| dir.join("removebg").join(CONFIG_FILE_NAMES[0]))
+        .filter(|path| path.is_file())
+}
+
+/// The `ENVIRONMENT:` section of `--help`, listing [`ENV_VARS`].
+pub fn help() -> String {
+    let width = ENV_VARS.iter().map(|var| var.name.len()).max().unwrap_or(0);
+    let mut help = String::from("ENVIRONMENT:\n");
+    for var in ENV_VARS {
+        let _ = writeln!(help, "  {:width$}  {}", var.name, var.description, width = width);
+    }
+    help.push_str(
+        "\nFlags take precedence over the environment, the environment over removebg.toml, \
+         and a project's removebg.toml over the user's. Empty variables count as unset.",
+    );
+    help
+}
+
+/// Parse the value of the variable `name`, if it is set.
+fn parsed<T: FromStr<Err = String>>(name: &str, value: Option<String>) -> Result<Option<T>> {
+    value.map(|value| value.parse().map_err(|e| invalid(name, e))).transpose()
+}
+
+fn invalid(name: &str, message: String) -> RemoveBgError {
+    RemoveBgError::InvalidConfig(format!("{}: {}", name, message))
+}
+
+fn invalid_quality(value: &dyn std::fmt::Display) -> String {
+    format!("invalid quality '{}' (expected a number from 0 to 100)", value)
+}
+
+/// Deserialize a config file string the way its flag parses it.
+fn from_str<'de, D: Deserializer<'de>, T: FromStr<Err = String>>(deserializer: D) -> std::result::Result<Option<T>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    value.parse().map(Some).map_err(serde::de::Error::custom)
+}
+
+/// Deserialize a config file quality, which may be written as an integer.
+fn quality<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<f32>, D::Error> {
+    let quality = f32::deserialize(deserializer)?;
+    if !(0.0..=100.0).contains(&quality) {
+        return Err(serde::de::Error::custom(invalid_quality(&quality)));
+    }
+    Ok(Some(quality))
+}
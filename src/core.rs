@@ -3,240 +3,4099 @@
 //! This module provides the main background removal functionality using the U2-Net
 //! deep learning model via ONNX Runtime for accurate background segmentation.
 
-use crate::error::{RemoveBgError, Result};
-use image::{DynamicImage, ImageBuffer, Rgba, RgbaImage};
-use ndarray::{Array, Array4};
-use ort::{Session, SessionOutputs, Value};
-use std::io::Read;
+use crate::analysis::{self, AlphaStats, SubjectAnalysis};
+use crate::animation::{self, AnimationKind};
+use crate::artifacts::{self, ArtifactTarget};
+use crate::auto_model;
+use crate::blur;
+use crate::chroma;
+use crate::disk_space;
+use crate::enhance;
+use crate::error::{self, RemoveBgError, Result, Stage};
+use crate::fallback;
+use crate::file_size;
+use crate::geometry;
+use crate::heif;
+use crate::icon;
+use crate::instances;
+use crate::line_art;
+use crate::mask_cache;
+use crate::mask_ops;
+use crate::npy;
+use crate::matte;
+use crate::memory;
+use crate::model::{self, Model};
+use crate::options::{
+    Background, Backend, Collision, DecodeLimits, Device, Fusion, InferenceSize, MaskCacheOptions, MaskFilter, MaskFormat, NamingOptions, OptimizationLevel,
+    OrtAllocator, Pages, RemoveBgOptions, Reprocess, SessionMemoryOptions,
+};
+use crate::output::{self, Metadata, OutputFormat, Selection};
+use crate::pages;
+use crate::paths;
+use crate::pipeline;
+use crate::raw;
+use crate::preview::{self, DebugMasks};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::remote;
+use crate::resolution;
+use crate::segmentation::Runner;
+use crate::remover::{BackgroundRemover, Segmenter};
+use crate::report::{Downgrade, InferenceInfo, MaskFastPath, MemoryEstimate, ModelInfo, ModelSelection, PageOutput, PageSummary, Rect, RemovalReport, StageDurations};
+use crate::rows;
+use crate::sniff;
+use crate::sticker;
+use crate::tiling;
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use image::error::{ImageFormatHint, UnsupportedErrorKind};
+use image::{
+    DynamicImage, ExtendedColorType, GenericImageView, GrayImage, ImageBuffer, ImageDecoder, ImageError, ImageFormat, ImageReader,
+    Luma, Pixel, Primitive, Rgba,
+};
+use ndarray::{Array4, ArrayView3, ArrayViewD, Axis, CowArray, Ix2};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Seek, Write};
+use std::ops::Deref;
+use std::panic::AssertUnwindSafe;
 use std::path::{Path, PathBuf};
-use std::sync::OnceLock;
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use web_time::Instant;
 
-/// U2-Net model URL for download
-const MODEL_URL: &str = "https://github.com/danielgatis/rembg/releases/download/v0.0.0/u2net.onnx";
+/// Session settings that affect how a model session is built.
+///
+/// Sessions are cached per distinct set of settings, so callers asking for
+/// different devices or thread counts never silently share a session.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct SessionKey {
+    model: Model,
+    model_dir: Option<PathBuf>,
+    backend: Backend,
+    device: Device,
+    intra_threads: Option<usize>,
+    inter_threads: Option<usize>,
+    optimization_level: OptimizationLevel,
+    session_memory: SessionMemoryOptions,
+    allocator: OrtAllocator,
+    /// Whether the model runs at sizes other than its own, which tract
+    /// needs to keep the unoptimized model for.
+    resizable: bool,
+}
+
+impl SessionKey {
+    pub(crate) fn from_options(options: &RemoveBgOptions) -> Self {
+        SessionKey {
+            model: options.model,
+            model_dir: options.model_dir.clone(),
+            backend: options.backend,
+            device: options.device,
+            intra_threads: options.intra_threads,
+            inter_threads: options.inter_threads,
+            optimization_level: options.optimization_level,
+            session_memory: options.session_memory,
+            allocator: options.ort_environment.allocator,
+            resizable: options.inference_size != InferenceSize::Native,
+        }
+    }
+}
 
-/// Singleton session holder for the ONNX model
-static MODEL_SESSION: OnceLock<Session> = OnceLock::new();
+/// How in-memory inputs are named in errors.
+const IN_MEMORY_INPUT: &str = "input data";
 
-/// Initialize the ONNX Runtime environment and load the U2-Net model.
+/// A shared remover, filled in by the first caller to need it.
+type SharedEntry = Arc<Mutex<Option<Arc<BackgroundRemover>>>>;
+
+/// Shared removers used by the free functions, keyed by their session settings.
 ///
-/// This function downloads the model if not present and initializes the ONNX session.
-/// The model is cached in memory for subsequent uses.
-fn get_or_init_model() -> Result<&'static Session> {
-    MODEL_SESSION.get_or_try_init(|| {
-        // Initialize ORT environment
-        ort::init()
-            .with_name("removebg")
-            .commit()
-            .map_err(|e| RemoveBgError::ModelInitError(e.to_string()))?;
+/// The cache is unbounded: each distinct set of session settings keeps its
+/// session until the process exits. Callers that cycle through many settings
+/// should own [`BackgroundRemover`]s instead.
+static SHARED_REMOVERS: OnceLock<Mutex<HashMap<SessionKey, SharedEntry>>> = OnceLock::new();
 
-        let model_path = get_model_path()?;
+/// The cache entry for `key`, created empty if there is none.
+///
+/// The map is only locked to find the entry, so loading one model doesn't
+/// hold up callers of the others.
+fn shared_entry(key: &SessionKey) -> SharedEntry {
+    let mut removers = SHARED_REMOVERS
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    Arc::clone(removers.entry(key.clone()).or_default())
+}
 
-        // Download model if it doesn't exist
-        if !model_path.exists() {
-            download_model(&model_path)?;
-        }
+/// Get the shared remover for the session settings in `options`, creating it on first use.
+///
+/// This downloads the model if not present and initializes the ONNX session.
+/// Removers are cached in memory for subsequent uses with the same settings;
+/// callers with the same settings wait for the first to finish loading, while
+/// other settings load in parallel. Also returns whether the model file had
+/// to be downloaded.
+pub(crate) fn shared_remover(options: &RemoveBgOptions) -> Result<(Arc<BackgroundRemover>, bool)> {
+    let key = SessionKey::from_options(options);
+    let entry = shared_entry(&key);
+    // A panic while loading a model, caught at the entry point, leaves the
+    // entry empty for the next caller to retry
+    let mut remover = entry.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(remover) = remover.as_ref() {
+        return Ok((Arc::clone(remover), false));
+    }
 
-        // Load the ONNX model
-        Session::builder()
-            .map_err(|e| RemoveBgError::ModelInitError(e.to_string()))?
-            .commit_from_file(&model_path)
-            .map_err(|e| RemoveBgError::ModelInitError(e.to_string()))
-    })
+    let downloaded = model::embedded_model(key.model).is_none()
+        && !model::model_path(key.model, key.model_dir.as_deref())?.exists();
+    let loaded = Arc::new(BackgroundRemover::new(options.clone())?);
+    *remover = Some(Arc::clone(&loaded));
+    Ok((loaded, downloaded))
 }
 
-/// Get the path where the model should be stored.
-fn get_model_path() -> Result<PathBuf> {
-    let home = dirs::home_dir()
-        .ok_or_else(|| RemoveBgError::ModelInitError("Could not determine home directory".into()))?;
+/// 16-bit grayscale image, used for masks so feathered edges don't band.
+pub type Gray16Image = ImageBuffer<Luma<u16>, Vec<u16>>;
+
+/// Floating-point grayscale image, used for masks kept in 0.0-1.0 until they
+/// are quantized.
+pub type Gray32FImage = ImageBuffer<Luma<f32>, Vec<f32>>;
 
-    let model_dir = home.join(".u2net");
-    std::fs::create_dir_all(&model_dir)?;
+/// A 16-bit mask in floating point.
+pub(crate) fn float_mask(mask: &Gray16Image) -> Gray32FImage {
+    let values = mask.iter().map(|&value| value.to_unit()).collect();
+    Gray32FImage::from_raw(mask.width(), mask.height(), values).expect("one value per pixel")
+}
 
-    Ok(model_dir.join("u2net.onnx"))
+/// Quantize a floating-point mask to 16 bits, rounding and clamping to 0-1.
+pub(crate) fn quantize_mask(mask: &Gray32FImage) -> Gray16Image {
+    let values = mask.iter().map(|&value| u16::from_unit(value)).collect();
+    Gray16Image::from_raw(mask.width(), mask.height(), values).expect("one value per pixel")
 }
 
-/// Download the U2-Net model from the official repository.
-fn download_model(path: &Path) -> Result<()> {
-    println!("Downloading U2-Net model (~176 MB)...");
+/// Run the remover's model to generate an alpha mask.
+///
+/// The mask is built and upscaled to the image size in 16-bit. Time spent in
+/// each stage is recorded in `durations`.
+pub(crate) fn generate_mask(
+    remover: &BackgroundRemover,
+    image: &DynamicImage,
+    options: &RemoveBgOptions,
+    durations: &mut StageDurations,
+) -> Result<Gray16Image> {
+    let mask = predict_mask(remover, image, options, durations)?;
+    let started = Instant::now();
+    let (mask, _) = upscale_model_mask(&mask, image, options)?;
+    durations.postprocess += started.elapsed();
+    Ok(mask)
+}
 
-    let response = ureq::get(MODEL_URL)
-        .call()
-        .map_err(|e| RemoveBgError::ModelInitError(format!("Failed to download model: {}", e)))?;
+/// Upscale a mask at the model's resolution to the size of `image`, or fill
+/// a mask of that size if [`mask_fast_path`] finds it empty or full, which
+/// gives the same mask without resampling. The mask is upscaled in bands
+/// when `options.max_memory_bytes` needs it to be (see [`memory`]).
+fn upscale_model_mask(
+    mask: &Gray16Image,
+    image: &DynamicImage,
+    options: &RemoveBgOptions,
+) -> Result<(Gray16Image, Option<MaskFastPath>)> {
+    let (width, height) = image.dimensions();
+    match mask_fast_path(mask) {
+        Some(fast_path) => {
+            let value = if fast_path == MaskFastPath::Full { u16::MAX } else { 0 };
+            Ok((Gray16Image::from_pixel(width, height, Luma([value])), Some(fast_path)))
+        }
+        None => {
+            let banded = memory::estimate((width, height), image.color(), options).banded;
+            let mask = pipeline::upscale_mask_until(mask, (width, height), options.mask_filter, banded, &options.cancel)?;
+            Ok((mask, None))
+        }
+    }
+}
 
-    let mut bytes = Vec::new();
-    response.into_reader()
-        .read_to_end(&mut bytes)
-        .map_err(|e| RemoveBgError::ModelInitError(format!("Failed to read model data: {}", e)))?;
+/// Whether a mask at the model's resolution is 0 everywhere or 1
+/// everywhere.
+///
+/// There is no tolerance beyond the 16-bit quantization: a mask with any
+/// other value would upscale to something other than a constant, and the
+/// fast paths must not change the output.
+pub(crate) fn mask_fast_path(mask: &Gray16Image) -> Option<MaskFastPath> {
+    let (min, max) = mask.iter().fold((u16::MAX, 0), |(min, max), &value| (min.min(value), max.max(value)));
+    match (min, max) {
+        (_, 0) => Some(MaskFastPath::Empty),
+        (u16::MAX, _) => Some(MaskFastPath::Full),
+        _ => None,
+    }
+}
 
-    std::fs::write(path, bytes)?;
-    println!("Model downloaded successfully!");
+/// Run the remover's model on `image` and return the mask at the model's
+/// output resolution.
+///
+/// Custom models get the mask curve from `options` applied to their masks;
+/// their whole run is recorded as inference time.
+fn predict_mask(
+    remover: &BackgroundRemover,
+    image: &DynamicImage,
+    options: &RemoveBgOptions,
+    durations: &mut StageDurations,
+) -> Result<Gray16Image> {
+    match remover.segmenter() {
+        Segmenter::Builtin(runner) => infer_mask(runner.as_ref(), image, options, durations),
+        Segmenter::Custom(model) => {
+            matte::check_mask_curve(options)?;
+            options.cancel.check()?;
+            enter_span!("inference", model = "custom", batch = 1);
+            let started = Instant::now();
+            let mask = model.predict_mask(image);
+            durations.inference = started.elapsed();
+            Ok(curved_mask(mask?, options))
+        }
+    }
+}
 
-    Ok(())
+/// Run the remover's model on several images, batching them if the model
+/// supports it, as in [`predict_mask`].
+fn predict_masks(
+    remover: &BackgroundRemover,
+    images: &[&DynamicImage],
+    options: &RemoveBgOptions,
+    durations: &mut StageDurations,
+) -> Vec<Result<Gray16Image>> {
+    match remover.segmenter() {
+        Segmenter::Builtin(runner) => infer_masks(runner.as_ref(), images, options, durations),
+        Segmenter::Custom(model) => {
+            if let Err(e) = matte::check_mask_curve(options).and_then(|_| options.cancel.check()) {
+                let message = e.to_string();
+                return images
+                    .iter()
+                    .map(|_| match e {
+                        RemoveBgError::Cancelled => Err(RemoveBgError::Cancelled),
+                        _ => Err(RemoveBgError::ProcessingError(message.clone())),
+                    })
+                    .collect();
+            }
+            let started = Instant::now();
+            let masks = model.predict_masks(images);
+            durations.inference += started.elapsed();
+            masks.into_iter().map(|mask| Ok(curved_mask(mask?, options))).collect()
+        }
+    }
+}
+
+/// Apply the mask curve from `options` to a mask predicted by a custom model.
+fn curved_mask(mut mask: Gray16Image, options: &RemoveBgOptions) -> Gray16Image {
+    if matte::has_mask_curve(options) {
+        for value in mask.iter_mut() {
+            *value = (matte::mask_curve(*value as f32 / 65535.0, options) * 65535.0).round() as u16;
+        }
+    }
+    mask
+}
+
+/// Run inference and return the mask at the model's output resolution.
+pub(crate) fn infer_mask(
+    runner: &dyn Runner,
+    image: &DynamicImage,
+    options: &RemoveBgOptions,
+    durations: &mut StageDurations,
+) -> Result<Gray16Image> {
+    Ok(quantize_mask(&infer_mask_f32(runner, image, options, durations)?))
+}
+
+/// [`infer_mask`] without quantizing the mask.
+fn infer_mask_f32(
+    runner: &dyn Runner,
+    image: &DynamicImage,
+    options: &RemoveBgOptions,
+    durations: &mut StageDurations,
+) -> Result<Gray32FImage> {
+    // Preprocess the image
+    let started = Instant::now();
+    let (input_size, _) = inference_size(runner, image, options)?;
+    let input = {
+        enter_span!("preprocess", width = image.width(), height = image.height(), input_size);
+        pipeline::preprocess_to(image, input_size, &options.model.spec().normalization)
+    };
+    let elapsed = started.elapsed();
+    durations.preprocess += elapsed;
+    log::debug!(
+        "preprocessed {}x{} image to {}x{} in {:?}",
+        image.width(),
+        image.height(),
+        input_size,
+        input_size,
+        elapsed
+    );
+
+    let mut masks = run_model_f32(runner, input, options, durations)?;
+    Ok(masks.remove(0))
 }
 
-/// Preprocess image for U2-Net model inference.
+/// The size to run `runner` at on `image` for `options.inference_size`, and
+/// the image's detail with `Auto`. An automatic size the model can't run at
+/// falls back to the model's own size.
 ///
-/// Resizes image to 320x320 and normalizes pixel values.
-fn preprocess_image(image: &DynamicImage) -> Array4<f32> {
-    // Resize to 320x320 (U2-Net input size)
-    let resized = image.resize_exact(320, 320, image::imageops::FilterType::Lanczos3);
-    let rgb = resized.to_rgb8();
+/// # Errors
+/// * `ProcessingError` - If a fixed size is out of range
+/// * `ModelError` - If the model can't run at a fixed size
+pub(crate) fn inference_size(runner: &dyn Runner, image: &DynamicImage, options: &RemoveBgOptions) -> Result<(u32, Option<f32>)> {
+    let native = runner.input_size();
+    let (size, detail) = resolution::resolve(options.inference_size, image, native, options.tiling.is_some())?;
+    if size == native || runner.accepts_size(size) {
+        return Ok((size, detail));
+    }
+    if options.inference_size == InferenceSize::Auto {
+        log::info!("{} only runs at {}x{}; using that instead of {}x{}", options.model, native, native, size, size);
+        return Ok((native, detail));
+    }
+    Err(RemoveBgError::ModelError(format!(
+        "{} only runs at {}x{}, not {}x{}; its model file declares a fixed input size",
+        options.model, native, native, size, size
+    )))
+}
 
-    // Convert to float array and normalize
-    let mut input = Array::zeros((1, 3, 320, 320));
+/// `options` with an automatic inference size fixed to the one chosen for
+/// all of `image`, so regions, crops and tiles run at it too, and the size
+/// for the report. Custom models run at their own size and report none.
+fn fix_inference_size<'a>(
+    remover: &BackgroundRemover,
+    image: &DynamicImage,
+    options: &'a RemoveBgOptions,
+    durations: &mut StageDurations,
+) -> Result<(Cow<'a, RemoveBgOptions>, Option<InferenceInfo>)> {
+    let Segmenter::Builtin(runner) = remover.segmenter() else {
+        return Ok((Cow::Borrowed(options), None));
+    };
+    let started = Instant::now();
+    let (size, detail) = inference_size(runner.as_ref(), image, options)?;
+    durations.preprocess += started.elapsed();
+    let info = InferenceInfo { size, relative_cost: resolution::relative_cost(size, runner.input_size()), detail };
+    let options = match options.inference_size {
+        InferenceSize::Auto => Cow::Owned(RemoveBgOptions { inference_size: InferenceSize::Fixed(size), ..options.clone() }),
+        _ => Cow::Borrowed(options),
+    };
+    Ok((options, Some(info)))
+}
+
+/// Run inference on several images in one batch and return their masks at
+/// the model's output resolution, in order.
+///
+/// Falls back to one inference per image if the model's input has a fixed
+/// batch size or the batched run fails, so each image gets its own result.
+/// Time for the whole batch is recorded in `durations`.
+pub(crate) fn infer_masks(
+    runner: &dyn Runner,
+    images: &[&DynamicImage],
+    options: &RemoveBgOptions,
+    durations: &mut StageDurations,
+) -> Vec<Result<Gray16Image>> {
+    let per_image = |durations: &mut StageDurations| {
+        let mut results = Vec::with_capacity(images.len());
+        for image in images {
+            let mut image_durations = StageDurations::default();
+            results.push(infer_mask(runner, image, options, &mut image_durations));
+            durations.preprocess += image_durations.preprocess;
+            durations.inference += image_durations.inference;
+        }
+        results
+    };
+    if images.len() < 2 {
+        return per_image(durations);
+    }
+    if !runner.accepts_batch(images.len()) {
+        log::debug!("{} has a fixed batch size; running images one at a time", options.model);
+        trace_event!("fallback", reason = "fixed batch size", batch = images.len());
+        return per_image(durations);
+    }
+    // Automatic sizes differ between images, and invalid ones fail for each
+    let input_size = match options.inference_size {
+        InferenceSize::Auto => return per_image(durations),
+        _ => match inference_size(runner, images[0], options) {
+            Ok((size, _)) => size,
+            Err(_) => return per_image(durations),
+        },
+    };
 
-    for (y, row) in rgb.rows().enumerate() {
-        for (x, pixel) in row.enumerate() {
-            // Normalize to [0, 1] and convert to CHW format
-            input[[0, 0, y, x]] = pixel[0] as f32 / 255.0;
-            input[[0, 1, y, x]] = pixel[1] as f32 / 255.0;
-            input[[0, 2, y, x]] = pixel[2] as f32 / 255.0;
+    let started = Instant::now();
+    let mut input = Array4::zeros((images.len(), 3, input_size as usize, input_size as usize));
+    {
+        enter_span!("preprocess", batch = images.len(), input_size);
+        for (mut slot, image) in input.outer_iter_mut().zip(images) {
+            slot.assign(&pipeline::preprocess_to(image, input_size, &options.model.spec().normalization).index_axis(Axis(0), 0));
         }
     }
+    durations.preprocess += started.elapsed();
 
-    input
+    let mut batch_durations = StageDurations::default();
+    match run_model(runner, input, options, &mut batch_durations) {
+        Ok(masks) => {
+            durations.inference += batch_durations.inference;
+            log::debug!("ran a batch of {} images in {:?}", images.len(), batch_durations.inference);
+            masks.into_iter().map(Ok).collect()
+        }
+        Err(RemoveBgError::Cancelled) => images.iter().map(|_| Err(RemoveBgError::Cancelled)).collect(),
+        Err(e) => {
+            log::debug!("batched inference failed ({}); running images one at a time", e);
+            trace_event!("fallback", reason = "batch failed", batch = images.len(), error = %e);
+            per_image(durations)
+        }
+    }
 }
 
-/// Run inference on the U2-Net model to generate an alpha mask.
-fn generate_mask(image: &DynamicImage) -> Result<DynamicImage> {
-    let session = get_or_init_model()?;
+/// Run the model on a preprocessed `(n, 3, size, size)` input and return one
+/// mask per batch entry, in order.
+pub(crate) fn run_model(
+    runner: &dyn Runner,
+    input: Array4<f32>,
+    options: &RemoveBgOptions,
+    durations: &mut StageDurations,
+) -> Result<Vec<Gray16Image>> {
+    Ok(run_model_f32(runner, input, options, durations)?.iter().map(quantize_mask).collect())
+}
 
-    // Preprocess the image
-    let input_tensor = preprocess_image(image);
+/// [`run_model`] without quantizing the masks.
+fn run_model_f32(
+    runner: &dyn Runner,
+    input: Array4<f32>,
+    options: &RemoveBgOptions,
+    durations: &mut StageDurations,
+) -> Result<Vec<Gray32FImage>> {
+    matte::check_mask_curve(options)?;
+    let batch = input.shape()[0];
 
     // Run inference
-    let outputs: SessionOutputs = session
-        .run(ort::inputs!["input" => Value::from_array(input_tensor)
-            .map_err(|e| RemoveBgError::ModelError(e.to_string()))?]?)
-        .map_err(|e| RemoveBgError::ModelError(e.to_string()))?;
+    options.cancel.check()?;
+    enter_span!("inference", model = %options.model, device = %options.device, batch);
+    runner.run(input, &mut |outputs, elapsed| {
+        durations.inference = elapsed;
+        log::debug!("inference with {} took {:?}", options.model, elapsed);
+        masks_from_outputs(outputs, batch, options)
+    })
+}
+
+/// How far outside 0-1 an output of a model with [`ModelSpec::logits`]
+/// may stray, from rounding, and still be taken as probabilities.
+///
+/// [`ModelSpec::logits`]: crate::ModelSpec::logits
+const LOGIT_MARGIN: f32 = 1e-3;
 
-    // Extract the output tensor
-    let output = outputs[0]
-        .try_extract_tensor::<f32>()
-        .map_err(|e| RemoveBgError::ModelError(e.to_string()))?;
+/// Fuse the side outputs of a model run into one mask per batch entry, as
+/// selected by `options.mask_fusion`, and apply the mask curve. The masks
+/// are clamped to 0-1 but not quantized; outputs of models that may end in
+/// logits get a sigmoid first if their values leave 0-1.
+pub(crate) fn masks_from_outputs(outputs: &[ArrayViewD<'_, f32>], batch: usize, options: &RemoveBgOptions) -> Result<Vec<Gray32FImage>> {
+    // Select the output tensors to combine
+    let weights = options.mask_fusion.weights(outputs.len()).map_err(RemoveBgError::ProcessingError)?;
+    if weights.len() == 1 && options.mask_fusion != Fusion::First {
+        log::debug!("{} has a single output; mask fusion is not applied", options.model);
+    }
+    let (stages, channels): (Vec<_>, Vec<_>) = outputs[..weights.len()]
+        .iter()
+        .map(|output| mask_planes(output.view(), batch))
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .unzip();
 
     // Get dimensions
-    let shape = output.shape();
-    let height = shape[2];
-    let width = shape[3];
+    let (_, height, width) = stages[0].dim();
+    if stages.iter().any(|stage| stage.dim() != stages[0].dim()) {
+        return Err(RemoveBgError::ModelError("model outputs differ in shape and can't be fused".into()));
+    }
+    if let Some(&channels) = channels.iter().max().filter(|&&channels| channels > 1) {
+        log::warn!("{} returned masks with {} channels; only the first is used", options.model, channels);
+    }
+    // Some exports end before the final sigmoid; their values leave 0-1
+    let logits: Vec<bool> = stages
+        .iter()
+        .map(|stage| options.model.spec().logits && stage.iter().any(|value| !(-LOGIT_MARGIN..=1.0 + LOGIT_MARGIN).contains(value)))
+        .collect();
+    if logits.contains(&true) {
+        log::debug!("{} returned logits; applying a sigmoid", options.model);
+    }
 
-    // Create mask image
-    let mut mask = ImageBuffer::new(width as u32, height as u32);
+    // Create one mask per batch entry
+    let mut masks = Vec::with_capacity(batch);
+    for b in 0..batch {
+        // Contiguous height x width planes of this entry, one per stage
+        let planes: Vec<CowArray<'_, f32, Ix2>> = stages
+            .iter()
+            .zip(&logits)
+            .map(|(stage, &logits)| {
+                let plane = stage.index_axis(Axis(0), b);
+                if logits {
+                    plane.mapv(|logit| 1.0 / (1.0 + (-logit).exp())).into()
+                } else if plane.is_standard_layout() {
+                    plane.into()
+                } else {
+                    plane.as_standard_layout().into_owned().into()
+                }
+            })
+            .collect();
+        let planes: Vec<&[f32]> = planes.iter().map(|plane| plane.as_slice().expect("standard layout")).collect();
+        let mut mask = Gray32FImage::new(width as u32, height as u32);
+        for (y, row) in mask.chunks_mut(width.max(1)).enumerate() {
+            options.cancel.check()?;
+            let start = y * width;
+            for (i, pixel) in (start..).zip(row) {
+                let value: f32 = planes.iter().zip(&weights).map(|(plane, w)| plane[i] * w).sum();
+                // NaN outputs count as background rather than escaping the clamp
+                let value = if value.is_nan() { 0.0 } else { value.clamp(0.0, 1.0) };
+                *pixel = matte::mask_curve(value, options);
+            }
+        }
 
-    for y in 0..height {
-        for x in 0..width {
-            let value = output[[0, 0, y, x]];
-            let pixel_value = (value.max(0.0).min(1.0) * 255.0) as u8;
-            mask.put_pixel(x as u32, y as u32, image::Luma([pixel_value]));
+        if log::log_enabled!(log::Level::Debug) {
+            let total = mask.len().max(1) as f64;
+            let mean = mask.iter().map(|&v| v as f64 * 255.0).sum::<f64>() / total;
+            let foreground = mask.iter().filter(|&&v| v >= 0.5).count() as f64 / total;
+            log::debug!(
+                "mask {}x{}: mean alpha {:.1}, {:.1}% foreground",
+                width,
+                height,
+                mean,
+                foreground * 100.0
+            );
         }
+        masks.push(mask);
     }
 
-    // Resize mask back to original image size
-    let mask_resized = image::DynamicImage::ImageLuma8(mask)
-        .resize_exact(image.width(), image.height(), image::imageops::FilterType::Lanczos3);
+    Ok(masks)
+}
+
+/// View a model output as one `height x width` plane per batch entry, and
+/// return it with the number of channels it had.
+///
+/// Exported models differ in the shape of their mask: `(n, 1, h, w)`,
+/// `(n, h, w)` or, for single images, `(h, w)`. The last two axes are the
+/// mask; an axis before them the size of the batch is the batch, and any
+/// remaining axis is a channel axis, of which the first channel is taken.
+fn mask_planes(output: ArrayViewD<'_, f32>, batch: usize) -> Result<(ArrayView3<'_, f32>, usize)> {
+    let shape = output.shape().to_vec();
+    let unexpected = || RemoveBgError::ModelError(format!("expected a 2D mask, got shape {:?}", shape));
+    let Some(leading) = shape.len().checked_sub(2) else {
+        return Err(unexpected());
+    };
+    if shape[leading..].contains(&0) || shape[leading..].iter().any(|&size| u32::try_from(size).is_err()) {
+        return Err(unexpected());
+    }
+    let batched = leading > 0 && (batch > 1 || shape[0] == 1);
+    if !batched && batch > 1 {
+        return Err(RemoveBgError::ModelError(format!("model returned 1 mask for a batch of {} images", batch)));
+    }
+    if batched && shape[0] != batch {
+        return Err(RemoveBgError::ModelError(format!(
+            "model returned {} masks for a batch of {} images",
+            shape[0], batch
+        )));
+    }
 
-    Ok(mask_resized)
+    // At most one channel axis that isn't a singleton
+    let channel_axes = usize::from(batched)..leading;
+    if shape[channel_axes.clone()].iter().filter(|&&size| size > 1).count() > 1 {
+        return Err(unexpected());
+    }
+    let channels = shape[channel_axes.clone()].iter().product();
+    let mut planes = output;
+    for axis in channel_axes.rev() {
+        planes = planes.index_axis_move(Axis(axis), 0);
+    }
+    if !batched {
+        planes = planes.insert_axis(Axis(0));
+    }
+    Ok((planes.into_dimensionality().expect("batch, height and width axes"), channels))
 }
 
-/// Apply alpha mask to image to create transparent background.
-fn apply_alpha_mask(image: &DynamicImage, mask: &DynamicImage) -> RgbaImage {
-    let rgb = image.to_rgba8();
-    let mask_gray = mask.to_luma8();
+/// Compute the refined full-resolution subject mask for `image`.
+///
+/// Runs tiled inference when `options.tiling` is set and applies the mask
+/// refinements enabled in `options`. With `options.invert_mask`, the returned
+/// mask covers the background instead.
+///
+/// The mask is floating point: the model's fused output is upscaled and
+/// refined without being quantized. Tiled, region-of-interest and two-pass
+/// masks and masks from custom models are put together in 16 bits and
+/// converted before refinement.
+pub(crate) fn subject_mask(
+    remover: &BackgroundRemover,
+    image: &DynamicImage,
+    options: &RemoveBgOptions,
+    durations: &mut StageDurations,
+) -> Result<Gray32FImage> {
+    let mut mask = match remover.segmenter() {
+        Segmenter::Builtin(runner) if options.tiling.is_none() && options.roi.is_none() && !options.two_pass => {
+            let mask = infer_mask_f32(runner.as_ref(), image, options, durations)?;
+            let started = Instant::now();
+            let mask = pipeline::upscale_mask_f32(&mask, image.dimensions(), options.mask_filter);
+            durations.postprocess += started.elapsed();
+            mask
+        }
+        _ => float_mask(&raw_subject_mask(remover, image, options, durations)?),
+    };
+    finish_mask(&mut mask, image, options)?;
+    Ok(mask)
+}
 
-    let mut output = RgbaImage::new(image.width(), image.height());
+/// Compute the full-resolution subject mask for `image` as the model sees it,
+/// before any refinement, restricted to `options.roi` if set.
+fn raw_subject_mask(
+    remover: &BackgroundRemover,
+    image: &DynamicImage,
+    options: &RemoveBgOptions,
+    durations: &mut StageDurations,
+) -> Result<Gray16Image> {
+    let Some(roi) = options.roi else {
+        return whole_subject_mask(remover, image, options, durations);
+    };
+    let region = roi_region(roi, options.roi_margin, image.width(), image.height())?;
+    let crop = image.crop_imm(region.x, region.y, region.width, region.height);
+    let crop_mask = whole_subject_mask(remover, &crop, options, durations)?;
+    let mut mask = Gray16Image::new(image.width(), image.height());
+    image::imageops::replace(&mut mask, &crop_mask, region.x as i64, region.y as i64);
+    Ok(mask)
+}
 
-    for (x, y, pixel) in rgb.enumerate_pixels() {
-        let alpha = mask_gray.get_pixel(x, y)[0];
-        output.put_pixel(x, y, Rgba([pixel[0], pixel[1], pixel[2], alpha]));
+/// Compute the full-resolution subject mask for all of `image`, tiled if
+/// `options.tiling` is set and refined by a second pass if
+/// `options.two_pass` is.
+fn whole_subject_mask(
+    remover: &BackgroundRemover,
+    image: &DynamicImage,
+    options: &RemoveBgOptions,
+    durations: &mut StageDurations,
+) -> Result<Gray16Image> {
+    let mut mask = single_pass_mask(remover, image, options, durations)?;
+    if options.two_pass {
+        second_pass(remover, image, &mut mask, options, durations)?;
     }
+    Ok(mask)
+}
 
-    output
+/// Compute the full-resolution subject mask for all of `image` with one
+/// model run, or one per tile if `options.tiling` is set.
+fn single_pass_mask(
+    remover: &BackgroundRemover,
+    image: &DynamicImage,
+    options: &RemoveBgOptions,
+    durations: &mut StageDurations,
+) -> Result<Gray16Image> {
+    match options.tiling {
+        Some(tiling) => tiling::generate_tiled_mask(remover, image, tiling, options, durations),
+        None => generate_mask(remover, image, options, durations),
+    }
 }
 
-/// Remove background from an image and save as transparent PNG.
+/// Context around the subject in the second pass of `options.two_pass`, as
+/// a fraction of the subject's larger side on every side.
+const SECOND_PASS_MARGIN: f32 = 0.15;
+
+/// Largest fraction of the image the second pass's crop may cover; a larger
+/// crop would hardly enlarge the subject in the model's input.
+const SECOND_PASS_MAX_AREA: f32 = 0.5;
+
+/// Run the model again on the subject found in `mask`, cropped from `image`
+/// with a margin, and put the crop's mask in place of that area of `mask`.
+fn second_pass(
+    remover: &BackgroundRemover,
+    image: &DynamicImage,
+    mask: &mut Gray16Image,
+    options: &RemoveBgOptions,
+    durations: &mut StageDurations,
+) -> Result<()> {
+    let (width, height) = image.dimensions();
+    let subject = geometry::bounds(width, height, |x, y| if mask.get_pixel(x, y)[0] >= 32768 { 1.0 } else { 0.0 });
+    let Some(subject) = subject else {
+        log::debug!("first pass found no subject; skipping the second pass");
+        return Ok(());
+    };
+    let region = roi_region(subject, SECOND_PASS_MARGIN, width, height)?;
+    let area = region.width as f32 * region.height as f32 / (width as f32 * height as f32);
+    if area > SECOND_PASS_MAX_AREA {
+        log::debug!("subject fills {:.0}% of the image; skipping the second pass", area * 100.0);
+        return Ok(());
+    }
+
+    let started = Instant::now();
+    let crop = image.crop_imm(region.x, region.y, region.width, region.height);
+    let crop_mask = single_pass_mask(remover, &crop, options, &mut StageDurations::default())?;
+    image::imageops::replace(mask, &crop_mask, region.x as i64, region.y as i64);
+    let elapsed = started.elapsed();
+    durations.second_pass += elapsed;
+    log::debug!("second pass on {}x{} at {},{} took {:?}", region.width, region.height, region.x, region.y, elapsed);
+    Ok(())
+}
+
+/// The area of a `width`x`height` image segmented for the region of interest
+/// `roi`: the rectangle expanded by `margin` times its larger side on every
+/// side, clamped to the image.
 ///
-/// This function uses the U2-Net model to perform accurate background segmentation
-/// and removal. The model automatically detects the main subject in the image and
-/// removes everything else, replacing it with transparency.
+/// A rectangle reaching past the image is clamped with a warning; one with
+/// zero area, or entirely outside the image, is an error.
+fn roi_region(roi: Rect, margin: f32, width: u32, height: u32) -> Result<Rect> {
+    if roi.width == 0 || roi.height == 0 {
+        return Err(RemoveBgError::ProcessingError(format!(
+            "Region of interest {}x{} at {},{} has zero area",
+            roi.width, roi.height, roi.x, roi.y
+        )));
+    }
+    if !(margin.is_finite() && margin >= 0.0) {
+        return Err(RemoveBgError::ProcessingError(format!(
+            "Region of interest margin must be 0 or more, got {}",
+            margin
+        )));
+    }
+    let right = roi.x.saturating_add(roi.width).min(width);
+    let bottom = roi.y.saturating_add(roi.height).min(height);
+    if roi.x >= right || roi.y >= bottom {
+        return Err(RemoveBgError::ProcessingError(format!(
+            "Region of interest {}x{} at {},{} lies outside the {}x{} image",
+            roi.width, roi.height, roi.x, roi.y, width, height
+        )));
+    }
+    let clamped = Rect { x: roi.x, y: roi.y, width: right - roi.x, height: bottom - roi.y };
+    if clamped != roi {
+        log::warn!(
+            "region of interest {}x{} at {},{} extends past the {}x{} image; clamped to {}x{}",
+            roi.width,
+            roi.height,
+            roi.x,
+            roi.y,
+            width,
+            height,
+            clamped.width,
+            clamped.height
+        );
+    }
+
+    let extra = (margin * clamped.width.max(clamped.height) as f32).round() as u32;
+    let left = clamped.x.saturating_sub(extra);
+    let top = clamped.y.saturating_sub(extra);
+    let right = (clamped.x + clamped.width).saturating_add(extra).min(width);
+    let bottom = (clamped.y + clamped.height).saturating_add(extra).min(height);
+    Ok(Rect { x: left, y: top, width: right - left, height: bottom - top })
+}
+
+/// Apply the refinements enabled in `options` and the inversion to a mask of
+/// `image`, then the image's own alpha channel, if it has one. The mask may
+/// be 16-bit or floating point; each step works at the mask's own depth.
+pub(crate) fn finish_mask<S: Channel>(
+    mask: &mut ImageBuffer<Luma<S>, Vec<S>>,
+    image: &DynamicImage,
+    options: &RemoveBgOptions,
+) -> Result<()> {
+    trim_border(mask, options.trim_border);
+    if let Some(guided) = &options.guided_filter {
+        options.cancel.check()?;
+        matte::guided_filter(mask, image, guided)?;
+    }
+    match options.mask_ops.as_slice() {
+        [] => mask_ops::apply(mask, &mask_ops::from_options(options))?,
+        ops => mask_ops::apply(mask, ops)?,
+    }
+    if options.invert_mask {
+        image::imageops::invert(mask);
+    }
+    keep_input_alpha(mask, image);
+    Ok(())
+}
+
+/// Copy the mask value `border` pixels in from each edge out over the
+/// outermost `border` rows and columns. Masks too small for that keep their
+/// middle row or column.
+fn trim_border<S: Channel>(mask: &mut ImageBuffer<Luma<S>, Vec<S>>, border: u32) {
+    let (width, height) = mask.dimensions();
+    if border == 0 || width == 0 || height == 0 {
+        return;
+    }
+    let inner = |len: u32| {
+        let first = border.min((len - 1) / 2);
+        first..=len - 1 - first
+    };
+    let (columns, rows) = (inner(width), inner(height));
+    for y in 0..height {
+        let source_y = y.clamp(*rows.start(), *rows.end());
+        for x in 0..width {
+            let source_x = x.clamp(*columns.start(), *columns.end());
+            if (source_x, source_y) != (x, y) {
+                let value = *mask.get_pixel(source_x, source_y);
+                mask.put_pixel(x, y, value);
+            }
+        }
+    }
+}
+
+/// Multiply `mask` by the alpha channel of `image`, so pixels that were
+/// transparent in the input stay transparent in the output. Images without
+/// alpha leave the mask unchanged.
+fn keep_input_alpha<S: Channel>(mask: &mut ImageBuffer<Luma<S>, Vec<S>>, image: &DynamicImage) {
+    fn multiply<S: Channel>(mask: &mut ImageBuffer<Luma<S>, Vec<S>>, alpha: impl Iterator<Item = f32>) {
+        for (value, alpha) in mask.iter_mut().zip(alpha) {
+            *value = S::from_unit(value.to_unit() * alpha);
+        }
+    }
+    match image {
+        DynamicImage::ImageLumaA8(image) => multiply(mask, image.pixels().map(|pixel| pixel[1].to_unit())),
+        DynamicImage::ImageRgba8(image) => multiply(mask, image.pixels().map(|pixel| pixel[3].to_unit())),
+        DynamicImage::ImageLumaA16(image) => multiply(mask, image.pixels().map(|pixel| pixel[1].to_unit())),
+        DynamicImage::ImageRgba16(image) => multiply(mask, image.pixels().map(|pixel| pixel[3].to_unit())),
+        DynamicImage::ImageRgba32F(image) => multiply(mask, image.pixels().map(|pixel| pixel[3].clamp(0.0, 1.0))),
+        _ => {}
+    }
+}
+
+/// Largest relative difference between the aspect ratios of an external mask
+/// and the image it is stretched onto.
+const MASK_ASPECT_TOLERANCE: f64 = 0.01;
+
+/// Convert an external mask to a 16-bit mask of the given size.
 ///
-/// # Process Flow
-/// 1. Validate input file exists and is readable
-/// 2. Load image data
-/// 3. Process with U2-Net model to generate alpha mask
-/// 4. Apply mask to create transparency
-/// 5. Save result as PNG
+/// The mask's luminance is the alpha. A mask of a different size is resized
+/// with `filter` and a warning, but only if its aspect ratio matches the
+/// image's.
+pub(crate) fn fit_mask(mask: &DynamicImage, width: u32, height: u32, filter: MaskFilter) -> Result<Gray16Image> {
+    let mask = mask.to_luma16();
+    if mask.dimensions() == (width, height) {
+        return Ok(mask);
+    }
+
+    let ratio = |w: u32, h: u32| w as f64 / h.max(1) as f64;
+    let (image_ratio, mask_ratio) = (ratio(width, height), ratio(mask.width(), mask.height()));
+    if (mask_ratio - image_ratio).abs() / image_ratio > MASK_ASPECT_TOLERANCE {
+        return Err(RemoveBgError::InvalidMask(format!(
+            "mask is {}x{} but the image is {}x{}; their aspect ratios differ",
+            mask.width(),
+            mask.height(),
+            width,
+            height
+        )));
+    }
+    log::warn!(
+        "resizing {}x{} mask to the {}x{} image",
+        mask.width(),
+        mask.height(),
+        width,
+        height
+    );
+    Ok(pipeline::upscale_mask(&mask, (width, height), filter))
+}
+
+/// Load the mask file named by `options.external_mask` for `image`: a
+/// `.npy` array, or an image in any other format.
+fn load_external_mask(path: &Path, image: &DynamicImage, options: &RemoveBgOptions) -> Result<Gray16Image> {
+    if !path.is_file() {
+        return Err(RemoveBgError::FileNotFound(path.to_path_buf()));
+    }
+    let reader = BufReader::new(File::open(paths::long_path(path))?);
+    let mask = match npy::is_npy(path) {
+        true => npy::read_mask(reader, &options.limits)?,
+        false => decode_image(reader, path, &options.limits)?.0,
+    };
+    fit_mask(&mask, image.width(), image.height(), options.mask_filter)
+}
+
+/// Cut out `image` with a precomputed mask instead of running the model.
+///
+/// The mask is a grayscale image whose brightness is the alpha (white keeps a
+/// pixel, black removes it), for example one painted or corrected by hand. It
+/// goes through the same steps as a generated mask: component filtering, hole
+/// filling, inversion, decontamination, and background replacement, as
+/// enabled in `options`. The model options are ignored.
 ///
 /// # Arguments
-/// * `input_path` - Path to the input image file. Supports common formats like JPEG, PNG, BMP, TIFF, etc.
-/// * `output_path` - Optional path to save the output image. If not provided, saves to the same
-///   directory as input with "_nobg" suffix. Extension is forced to .png regardless of specified extension.
+/// * `image` - Image to cut out.
+/// * `mask` - Mask image; color masks are converted to grayscale.
+/// * `options` - Post-processing options.
 ///
 /// # Returns
-/// The absolute path to the output file that was created.
+/// The cutout as 8-bit or 16-bit RGBA, matching the input's bit depth.
 ///
 /// # Errors
-/// * `FileNotFound` - If the input file doesn't exist
-/// * `NotAFile` - If the input path is not a file (e.g., it's a directory)
-/// * `ImageError` - If the file cannot be processed as a valid image
-/// * `ModelError` - If model inference fails
+/// * `InvalidMask` - If the mask's aspect ratio differs from the image's by more than 1%
+/// * `Cancelled` - If `options.cancel` is triggered
 ///
 /// # Examples
 /// ```no_run
-/// use removebg::remove_background;
-///
-/// // Basic usage with auto-generated output path
-/// let output = remove_background("photo.jpg", None)?;
-/// println!("Saved to: {}", output);
+/// use removebg::{apply_external_mask, RemoveBgOptions};
 ///
-/// // Custom output path
-/// let output = remove_background("photo.jpg", Some("result.png"))?;
-/// println!("Saved to: {}", output);
-/// # Ok::<(), removebg::error::RemoveBgError>(())
+/// let image = image::open("photo.jpg")?;
+/// let mask = image::open("photo_mask.png")?;
+/// let cutout = apply_external_mask(&image, &mask, &RemoveBgOptions::default())?;
+/// cutout.save("photo_nobg.png")?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
 /// ```
-pub fn remove_background(input_path: &str, output_path: Option<&str>) -> Result<String> {
-    let input_file = Path::new(input_path);
+pub fn apply_external_mask(
+    image: &DynamicImage,
+    mask: &DynamicImage,
+    options: &RemoveBgOptions,
+) -> Result<DynamicImage> {
+    let options = &options.timed();
+    error::catch_panic(|| {
+        let mut mask = fit_mask(mask, image.width(), image.height(), options.mask_filter)?;
+        finish_mask(&mut mask, image, options)?;
+        compose(image, &mask, options).map(|(image, _)| image)
+    })
+}
+
+/// Apply a finished mask to `image` and flatten it onto the kept (blurred or
+/// grayscale) background, or draw the sticker outline and shadow and flatten
+/// onto the background color, as enabled, then apply `options.geometry`.
+///
+/// Also returns the subject's bounding box on the output canvas.
+pub(crate) fn compose(
+    image: &DynamicImage,
+    mask: &Gray16Image,
+    options: &RemoveBgOptions,
+) -> Result<(DynamicImage, Option<Rect>)> {
+    options.cancel.check()?;
+    let output_image = apply_alpha_mask(image, mask, options)?;
+    compose_masked(image, mask, output_image, options)
+}
 
-    // Validate input file exists
-    if !input_file.exists() {
-        return Err(RemoveBgError::FileNotFound(input_path.to_string()));
+/// [`compose`] with `image` already masked into `output_image`.
+fn compose_masked(
+    image: &DynamicImage,
+    mask: &Gray16Image,
+    mut output_image: DynamicImage,
+    options: &RemoveBgOptions,
+) -> Result<(DynamicImage, Option<Rect>)> {
+    if blur::keeps_background(options) {
+        options.cancel.check()?;
+        let background = blur::background_layer(image, mask, options)?;
+        let layer = |x, y| background.get_pixel(x, y).0;
+        match &mut output_image {
+            DynamicImage::ImageRgba8(image) => flatten_onto(image, layer),
+            DynamicImage::ImageRgba16(image) => flatten_onto(image, layer),
+            // apply_alpha_mask only produces RGBA8 and RGBA16
+            _ => {}
+        }
+        // The output is opaque, so the subject is located from the mask
+        let subject = geometry::bounds(mask.width(), mask.height(), |x, y| mask.get_pixel(x, y)[0].to_unit());
+        return geometry::apply(output_image, subject, &options.geometry);
     }
 
-    // Ensure input is a file, not a directory
-    if !input_file.is_file() {
-        return Err(RemoveBgError::NotAFile(input_path.to_string()));
+    if let Some(sticker) = &options.sticker {
+        options.cancel.check()?;
+        output_image = sticker::render(&output_image, sticker)?;
+    }
+    let mut subject = geometry::alpha_bounds(&output_image);
+    if geometry::is_enabled(&options.geometry) {
+        options.cancel.check()?;
+        (output_image, subject) = geometry::apply(output_image, subject, &options.geometry)?;
+    }
+    if let Some(key) = options.chroma_key {
+        chroma::key_out(&mut output_image, key);
+    } else if let Some(color) = background_color(image, mask, options) {
+        // Replace the background with a solid color
+        let color = color.map(|c| c as f32 / 255.0);
+        match &mut output_image {
+            DynamicImage::ImageRgba8(image) => flatten_onto(image, |_, _| color),
+            DynamicImage::ImageRgba16(image) => flatten_onto(image, |_, _| color),
+            _ => {}
+        }
     }
+    Ok((output_image, subject))
+}
 
-    // Generate output path if not provided
-    let output_path = match output_path {
-        Some(path) => {
-            let p = Path::new(path);
-            // Ensure .png extension
-            if p.extension().and_then(|s| s.to_str()) != Some("png") {
-                p.with_extension("png")
-            } else {
-                p.to_path_buf()
+/// The solid color `options.background` flattens `image` onto, estimated
+/// from the background under `mask` for [`Background::Auto`]; `None` when
+/// the background stays transparent.
+pub(crate) fn background_color(image: &DynamicImage, mask: &Gray16Image, options: &RemoveBgOptions) -> Option<[u8; 3]> {
+    match options.background {
+        Background::Transparent => None,
+        Background::Color(color) => Some(color),
+        Background::Auto => Some(analysis::background_color(image, mask).unwrap_or_else(|| {
+            log::warn!("too little background to pick an automatic background color; using white");
+            [255, 255, 255]
+        })),
+    }
+}
+
+/// Color channel of an 8-bit or 16-bit image.
+pub(crate) trait Channel: Primitive + Send + Sync {
+    /// Scale to 0.0-1.0.
+    fn to_unit(self) -> f32;
+    /// Scale from 0.0-1.0, rounding and clamping.
+    fn from_unit(value: f32) -> Self;
+    /// Convert a 16-bit mask value to this depth.
+    fn from_mask(value: u16) -> Self;
+}
+
+impl Channel for u8 {
+    fn to_unit(self) -> f32 {
+        self as f32 / 255.0
+    }
+
+    fn from_unit(value: f32) -> Self {
+        (value * 255.0).round().clamp(0.0, 255.0) as u8
+    }
+
+    fn from_mask(value: u16) -> Self {
+        ((value as u32 + 128) / 257) as u8
+    }
+}
+
+impl Channel for u16 {
+    fn to_unit(self) -> f32 {
+        self as f32 / 65535.0
+    }
+
+    fn from_unit(value: f32) -> Self {
+        (value * 65535.0).round().clamp(0.0, 65535.0) as u16
+    }
+
+    fn from_mask(value: u16) -> Self {
+        value
+    }
+}
+
+impl Channel for f32 {
+    fn to_unit(self) -> f32 {
+        self
+    }
+
+    fn from_unit(value: f32) -> Self {
+        if value.is_nan() {
+            0.0
+        } else {
+            value.clamp(0.0, 1.0)
+        }
+    }
+
+    fn from_mask(value: u16) -> Self {
+        value.to_unit()
+    }
+}
+
+/// Flatten an RGBA image onto a background, making it opaque.
+///
+/// `background` gives the 0.0-1.0 RGB background color at each pixel.
+pub(crate) fn flatten_onto<S: Channel>(
+    image: &mut ImageBuffer<Rgba<S>, Vec<S>>,
+    background: impl Fn(u32, u32) -> [f32; 3] + Sync + Send,
+) where
+    Rgba<S>: Pixel<Subpixel = S>,
+{
+    let width = image.width() as usize;
+    rows::for_each_row(image, width * 4, |y, row| {
+        for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+            let alpha = pixel[3].to_unit();
+            let background = background(x as u32, y as u32);
+            for c in 0..3 {
+                pixel[c] = S::from_unit(pixel[c].to_unit() * alpha + background[c] * (1.0 - alpha));
             }
+            pixel[3] = S::DEFAULT_MAX_VALUE;
         }
-        None => {
-            let stem = input_file.file_stem()
-                .ok_or_else(|| RemoveBgError::ProcessingError("Invalid input filename".into()))?;
-            let parent = input_file.parent().unwrap_or(Path::new("."));
-            parent.join(format!("{}_nobg.png", stem.to_string_lossy()))
+    });
+}
+
+/// Whether `image` has more than 8 bits per channel.
+fn is_high_depth(image: &DynamicImage) -> bool {
+    let color = image.color();
+    color.bytes_per_pixel() > color.channel_count()
+}
+
+/// Apply alpha mask to image to create transparent background.
+///
+/// Inputs with more than 8 bits per channel, including floating-point ones,
+/// produce a 16-bit RGBA image, and 8-bit inputs an 8-bit one. With
+/// `options.decontaminate` (or `options.chroma_key`), background color
+/// bleeding into the semi-transparent edge is removed afterwards. With
+/// `options.premultiplied`, the colors are then multiplied by the alpha.
+///
+/// The input is copied once, into the output; [`apply_alpha_mask_owned`]
+/// avoids even that for RGBA inputs.
+pub(crate) fn apply_alpha_mask(
+    image: &DynamicImage,
+    mask: &Gray16Image,
+    options: &RemoveBgOptions,
+) -> Result<DynamicImage> {
+    Ok(match image {
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => {
+            DynamicImage::ImageRgba16(masked(float_to_rgba16(image), mask, options)?)
+        }
+        image if is_high_depth(image) => DynamicImage::ImageRgba16(masked(image.to_rgba16(), mask, options)?),
+        image => DynamicImage::ImageRgba8(masked(image.to_rgba8(), mask, options)?),
+    })
+}
+
+/// [`apply_alpha_mask`] on an image the caller no longer needs: RGBA8 and
+/// RGBA16 inputs are masked in place, without a copy.
+pub(crate) fn apply_alpha_mask_owned(
+    image: DynamicImage,
+    mask: &Gray16Image,
+    options: &RemoveBgOptions,
+) -> Result<DynamicImage> {
+    Ok(match image {
+        DynamicImage::ImageRgba8(image) => DynamicImage::ImageRgba8(masked(image, mask, options)?),
+        DynamicImage::ImageRgba16(image) => DynamicImage::ImageRgba16(masked(image, mask, options)?),
+        image => apply_alpha_mask(&image, mask, options)?,
+    })
+}
+
+/// [`apply_alpha_mask`] for a mask that is opaque everywhere: `image`
+/// converted the same way, with opaque alpha. Decontamination and
+/// premultiplication leave opaque pixels as they are, so they are skipped.
+fn opaque_copy(image: &DynamicImage) -> DynamicImage {
+    fn opaque<S: Channel>(mut image: ImageBuffer<Rgba<S>, Vec<S>>) -> ImageBuffer<Rgba<S>, Vec<S>>
+    where
+        Rgba<S>: Pixel<Subpixel = S>,
+    {
+        for pixel in image.pixels_mut() {
+            pixel[3] = S::from_mask(u16::MAX);
+        }
+        image
+    }
+    match image {
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => DynamicImage::ImageRgba16(opaque(float_to_rgba16(image))),
+        image if is_high_depth(image) => DynamicImage::ImageRgba16(opaque(image.to_rgba16())),
+        image => DynamicImage::ImageRgba8(opaque(image.to_rgba8())),
+    }
+}
+
+/// Scale a floating-point image to 16 bits: 0.0-1.0 maps linearly onto the
+/// 16-bit range, values outside it are clipped rather than tone mapped, and
+/// NaN becomes 0.
+fn float_to_rgba16(image: &DynamicImage) -> ImageBuffer<Rgba<u16>, Vec<u16>> {
+    let rgba = match image {
+        DynamicImage::ImageRgba32F(rgba) => Cow::Borrowed(rgba),
+        image => Cow::Owned(image.to_rgba32f()),
+    };
+    let (width, height) = rgba.dimensions();
+    let samples = rgba.as_raw().iter().map(|&value| u16::from_unit(f32::from_unit(value))).collect();
+    ImageBuffer::from_raw(width, height, samples).expect("one sample per channel")
+}
+
+fn masked<S: Channel>(
+    mut image: ImageBuffer<Rgba<S>, Vec<S>>,
+    mask: &Gray16Image,
+    options: &RemoveBgOptions,
+) -> Result<ImageBuffer<Rgba<S>, Vec<S>>>
+where
+    Rgba<S>: Pixel<Subpixel = S>,
+{
+    let cancel = &options.cancel;
+    let width = image.width() as usize;
+    let alpha = mask.as_raw();
+    rows::try_for_each_row(&mut image, width * 4, |y, row| {
+        cancel.check()?;
+        for (pixel, &alpha) in row.chunks_exact_mut(4).zip(&alpha[y * width..(y + 1) * width]) {
+            pixel[3] = S::from_mask(alpha);
+        }
+        Ok::<_, RemoveBgError>(())
+    })?;
+
+    // Chroma keying needs clean edges, so it decontaminates by default
+    let strength = options.decontaminate.or(options.chroma_key.map(|_| 1.0));
+    if let Some(strength) = strength {
+        cancel.check()?;
+        matte::decontaminate(&mut image, strength);
+    }
+
+    // Decontamination estimates colors from straight alpha, so this comes last
+    if options.premultiplied {
+        cancel.check()?;
+        premultiply(&mut image, mask);
+    }
+
+    Ok(image)
+}
+
+/// Multiply the color channels of `image` by the alpha in `mask`, rounding
+/// once from the 16-bit mask value instead of the quantized alpha channel.
+fn premultiply<S: Channel>(image: &mut ImageBuffer<Rgba<S>, Vec<S>>, mask: &Gray16Image)
+where
+    Rgba<S>: Pixel<Subpixel = S>,
+{
+    let width = image.width() as usize;
+    let mask = mask.as_raw();
+    rows::for_each_row(image, width * 4, |y, row| {
+        for (pixel, &alpha) in row.chunks_exact_mut(4).zip(&mask[y * width..(y + 1) * width]) {
+            let alpha = alpha as f32 / 65535.0;
+            for channel in &mut pixel[..3] {
+                *channel = S::from_unit(channel.to_unit() * alpha);
+            }
         }
+    });
+}
+
+/// Decode an image of any supported format, enforcing `limits`.
+///
+/// The format is detected from the content, never from a file extension;
+/// content that is not a readable image is an `UnsupportedFormat` error
+/// naming `input`. The limits are checked against the header before any pixel
+/// data is decoded. The EXIF orientation is applied to the pixels, and the
+/// ICC profile and EXIF data (with its orientation reset) are returned for the
+/// encoder.
+pub(crate) fn decode_image<R: BufRead + Seek>(
+    mut reader: R,
+    input: &Path,
+    limits: &DecodeLimits,
+) -> Result<(DynamicImage, Metadata)> {
+    let header = reader.fill_buf()?;
+    if heif::is_heif(header) {
+        return decode_heif(reader, limits);
+    }
+    let format = sniff_format(header, input)?;
+    let mut reader = ImageReader::with_format(reader, format);
+    reader.limits(limits.to_image_limits());
+    let mut decoder = reader.into_decoder().map_err(decode_error)?;
+    let (width, height) = decoder.dimensions();
+    let pixels = (width as u64 * height as u64).max(1);
+    limits.check(width, height, decoder.total_bytes().div_ceil(pixels))?;
+
+    // Malformed metadata shouldn't fail an otherwise readable image
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let mut metadata = Metadata {
+        icc_profile: decoder.icc_profile().ok().flatten(),
+        exif: decoder.exif_metadata().ok().flatten(),
     };
+    if let Some(exif) = metadata.exif.as_mut() {
+        let _ = Orientation::remove_from_exif_chunk(exif);
+    }
+    if matches!(decoder.original_color_type(), ExtendedColorType::Cmyk8 | ExtendedColorType::Cmyk16) {
+        log::debug!("converting CMYK input to RGB");
+    }
+    // CMYK (and YCCK) JPEGs and TIFFs are converted to RGB while decoding, so
+    // a CMYK profile no longer describes the pixels and would garble the
+    // output's colors in color-managed viewers
+    if let Some(space) = metadata.icc_profile.as_deref().and_then(icc_color_space) {
+        if !matches!(space, "RGB " | "GRAY") {
+            log::debug!("dropping the {} color profile of the input", space.trim_end());
+            metadata.icc_profile = None;
+        }
+    }
 
-    // Load the input image
-    let image = image::open(input_path)
-        .map_err(|e| RemoveBgError::ProcessingError(format!("Failed to load image: {}", e)))?;
+    let mut image = DynamicImage::from_decoder(decoder).map_err(decode_error)?;
+    image.apply_orientation(orientation);
+    Ok((image, metadata))
+}
 
-    // Generate alpha mask using U2-Net
-    let mask = generate_mask(&image)?;
+/// The image format of the file starting with `header`, if it can be read.
+fn sniff_format(header: &[u8], input: &Path) -> Result<ImageFormat> {
+    match image::guess_format(header) {
+        Ok(format) if format.reading_enabled() => Ok(format),
+        Ok(format) => Err(RemoveBgError::UnsupportedFormat {
+            path: input.to_path_buf(),
+            detected: Some(format.extensions_str().first().map_or_else(
+                || format!("{:?}", format),
+                |ext| ext.to_ascii_uppercase(),
+            )),
+        }),
+        Err(_) => Err(RemoveBgError::UnsupportedFormat {
+            path: input.to_path_buf(),
+            detected: sniff::describe(header).map(String::from),
+        }),
+    }
+}
 
-    // Apply mask to create transparent image
-    let output_image = apply_alpha_mask(&image, &mask);
+/// Decode a HEIF image with libheif (see [`heif`]).
+#[cfg(feature = "heif")]
+fn decode_heif<R: BufRead + Seek>(mut reader: R, limits: &DecodeLimits) -> Result<(DynamicImage, Metadata)> {
+    let mut data = Vec::new();
+    std::io::Read::read_to_end(&mut reader, &mut data)?;
+    heif::decode(&data, limits)
+}
 
-    // Save as PNG
-    output_image.save(&output_path)?;
+#[cfg(not(feature = "heif"))]
+fn decode_heif<R: BufRead + Seek>(_reader: R, _limits: &DecodeLimits) -> Result<(DynamicImage, Metadata)> {
+    Err(heif::unsupported())
+}
+
+/// Data color space signature of an ICC profile, such as `"RGB "` or `"CMYK"`.
+fn icc_color_space(profile: &[u8]) -> Option<&str> {
+    profile.get(16..20).and_then(|space| std::str::from_utf8(space).ok())
+}
 
-    Ok(output_path.to_string_lossy().to_string())
+/// Human-readable name of a color type for error messages.
+fn color_space_name(color: ExtendedColorType) -> String {
+    match color {
+        ExtendedColorType::Cmyk8 => "8-bit CMYK".into(),
+        ExtendedColorType::Cmyk16 => "16-bit CMYK".into(),
+        ExtendedColorType::Unknown(bits) => format!("unknown ({} bits per pixel)", bits),
+        other => format!("{:?}", other),
+    }
+}
+
+/// Report decoding errors caused by an unsupported color space with the
+/// name of that color space.
+fn decode_error(error: ImageError) -> RemoveBgError {
+    let ImageError::Unsupported(unsupported) = &error else {
+        return error.into();
+    };
+    let UnsupportedErrorKind::Color(color) = unsupported.kind() else {
+        return error.into();
+    };
+    let format = match unsupported.format_hint() {
+        ImageFormatHint::Exact(format) => format!("{:?} ", format),
+        _ => String::new(),
+    };
+    RemoveBgError::ProcessingError(format!(
+        "{}images in the {} color space are not supported",
+        format,
+        color_space_name(color)
+    ))
+}
+
+/// Decode the input `input` read through `reader`, sending camera RAW files
+/// (by the extension of `input_file`) to [`raw`] and anything else to
+/// [`load_image`].
+///
+/// A RAW extension on a file whose content is a regular image other than
+/// TIFF, which most RAW formats are built on, is taken as a misnamed image.
+fn load_input<R: BufRead + Seek>(
+    mut reader: R,
+    input: &Path,
+    input_file: &Path,
+    limits: &DecodeLimits,
+) -> Result<(DynamicImage, Metadata)> {
+    enter_span!("decode", input = %input.display());
+    // Decoders of malformed files may panic
+    let decoded = error::catch_panic(|| {
+        if raw::is_raw(input_file) {
+            match image::guess_format(reader.fill_buf()?) {
+                Ok(format) if format != ImageFormat::Tiff => {
+                    log::debug!("{} is a {:?} image despite its extension", input.display(), format);
+                }
+                _ => return decode_raw(reader, input_file, limits),
+            }
+        }
+        load_image(reader, input, limits)
+    });
+    let (image, metadata) = decoded.map_err(|e| e.in_stage(Stage::Decode))?;
+    trace_event!("decoded", width = image.width(), height = image.height());
+    Ok((image, metadata))
+}
+
+/// Number of pages of the input read through `open`, named `input_file`
+/// (see [`pages::count`]). Camera RAW files, many of them TIFFs with
+/// previews in further directories, have one.
+fn page_count<I: BufRead + Seek>(open: impl Fn() -> Result<I>, input_file: &Path) -> Result<u32> {
+    if raw::is_raw(input_file) {
+        return Ok(1);
+    }
+    error::catch_panic(|| pages::count(open()?)).map_err(|e| e.in_stage(Stage::Decode))
+}
+
+/// [`load_input`] for page `page` (from 1) of the input read through `reader`.
+fn load_page<R: BufRead + Seek>(
+    reader: R,
+    page: u32,
+    input: &Path,
+    input_file: &Path,
+    limits: &DecodeLimits,
+) -> Result<(DynamicImage, Metadata)> {
+    if page == 1 {
+        return load_input(reader, input, input_file, limits);
+    }
+    let reader = error::catch_panic(|| pages::open_page(reader, page)).map_err(|e| e.in_stage(Stage::Decode))?;
+    load_input(reader, input, input_file, limits)
+}
+
+/// The input read through `reader`, checked by [`memory::check_input`] when
+/// `page` is the first; later pages are checked once decoded.
+fn checked_page<R: BufRead + Seek>(reader: R, page: u32, options: &RemoveBgOptions) -> Result<R> {
+    match page {
+        1 => memory::check_input(reader, options),
+        _ => Ok(reader),
+    }
+}
+
+#[cfg(feature = "raw")]
+fn decode_raw<R: BufRead + Seek>(mut reader: R, path: &Path, limits: &DecodeLimits) -> Result<(DynamicImage, Metadata)> {
+    let mut data = Vec::new();
+    std::io::Read::read_to_end(&mut reader, &mut data)?;
+    raw::decode(&data, path, limits)
+}
+
+#[cfg(not(feature = "raw"))]
+fn decode_raw<R: BufRead + Seek>(_reader: R, path: &Path, _limits: &DecodeLimits) -> Result<(DynamicImage, Metadata)> {
+    Err(raw::unsupported(path))
+}
+
+/// Decode an input image like [`decode_image`], reporting undecodable files
+/// as processing errors.
+fn load_image<R: BufRead + Seek>(reader: R, input: &Path, limits: &DecodeLimits) -> Result<(DynamicImage, Metadata)> {
+    decode_image(reader, input, limits).map_err(|e| match e {
+        RemoveBgError::ImageError(e) => {
+            RemoveBgError::ProcessingError(format!("Failed to load image: {}", e))
+        }
+        other => other,
+    })
+}
+
+/// Downscale a decoded input so its longer side fits
+/// `options.max_output_size`, keeping the aspect ratio; smaller inputs are
+/// returned as they are.
+pub(crate) fn limit_output_size(image: DynamicImage, options: &RemoveBgOptions) -> Result<DynamicImage> {
+    let (width, height) = limited_size(image.dimensions(), options)?;
+    if (width, height) == image.dimensions() {
+        return Ok(image);
+    }
+    log::debug!("downscaling {}x{} input to {}x{}", image.width(), image.height(), width, height);
+    Ok(image.resize_exact(width, height, FilterType::Lanczos3))
+}
+
+/// The size [`limit_output_size`] scales a `width`x`height` input to.
+pub(crate) fn limited_size((width, height): (u32, u32), options: &RemoveBgOptions) -> Result<(u32, u32)> {
+    let Some(max_size) = options.max_output_size else {
+        return Ok((width, height));
+    };
+    if max_size == 0 {
+        return Err(RemoveBgError::ProcessingError("Max output size must be at least 1 pixel".into()));
+    }
+    if options.roi.is_some() {
+        return Err(RemoveBgError::ProcessingError(
+            "Max output size cannot be combined with a region of interest".into(),
+        ));
+    }
+    let longer = width.max(height);
+    if longer <= max_size {
+        return Ok((width, height));
+    }
+    let scale = max_size as f64 / longer as f64;
+    let fit = |side: u32| ((side as f64 * scale).round() as u32).clamp(1, max_size);
+    Ok((fit(width), fit(height)))
+}
+
+/// Where the output for an input goes, before it is resolved to a file by
+/// [`resolve_output_path`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputLocation<'a> {
+    /// Next to the input.
+    NextToInput,
+    /// In this directory, named after the input.
+    Directory(&'a Path),
+    /// This file.
+    File(&'a Path),
+}
+
+impl<'a> OutputLocation<'a> {
+    /// Where an output path given by the caller points: a directory if it
+    /// ends with a path separator or is an existing directory (see
+    /// [`output::is_directory_path`]), a file otherwise.
+    fn of(output_path: Option<&'a Path>) -> Self {
+        match output_path {
+            None => OutputLocation::NextToInput,
+            Some(path) if output::is_directory_path(path) => OutputLocation::Directory(path),
+            Some(path) => OutputLocation::File(path),
+        }
+    }
+}
+
+/// Determine the output path and format.
+///
+/// Outputs next to the input or in a directory are named
+/// `<prefix><input stem><suffix>.<extension>` (see [`output_file_name`]), in
+/// `options.format` or else `default_format`; such a name that is the input
+/// itself is refused. The format of an output file is negotiated by
+/// [`output::negotiate_format`]. Formats without alpha are only accepted
+/// when the output is opaque. A custom `options.encoder` sets the extension
+/// instead, and the returned format is PNG, which stands for the RGBA cutout
+/// it gets.
+///
+/// With [`Collision::Rename`], the path is numbered (see [`free_path`]) until
+/// it is neither the input nor an existing file and `taken` doesn't claim
+/// it; batches pass the outputs of earlier inputs as `taken`.
+fn resolve_output_path(
+    input_file: &Path,
+    location: OutputLocation<'_>,
+    default_format: OutputFormat,
+    options: &RemoveBgOptions,
+    taken: &dyn Fn(&Path) -> bool,
+) -> Result<(PathBuf, OutputFormat)> {
+    let (path, format) = match location {
+        OutputLocation::NextToInput => {
+            let format = output::selected_format(options, default_format);
+            check_transparency(format, Selection::of(options), options)?;
+            let parent = input_file.parent().unwrap_or(Path::new("."));
+            (auto_output_path(input_file, parent, format, options)?, format)
+        }
+        OutputLocation::Directory(dir) => {
+            let format = output::selected_format(options, default_format);
+            check_transparency(format, Selection::of(options), options)?;
+            (auto_output_path(input_file, dir, format, options)?, format)
+        }
+        OutputLocation::File(path) if options.encoder.is_some() => {
+            check_transparency(OutputFormat::Png, Selection::Option, options)?;
+            let extension = output::output_extension(OutputFormat::Png, options);
+            let matches = path.extension().and_then(|s| s.to_str()).is_some_and(|e| e.eq_ignore_ascii_case(extension));
+            let path = if matches { path.to_path_buf() } else { path.with_extension(extension) };
+            (path, OutputFormat::Png)
+        }
+        OutputLocation::File(path) => output::negotiate_format(path, default_format, options)?,
+    };
+
+    let path = match options.naming.on_collision {
+        Collision::Rename => free_path(path, &|path| taken(path) || is_same_file(path, input_file)),
+        _ if !matches!(location, OutputLocation::File(_)) && is_same_file(&path, input_file) => {
+            return Err(RemoveBgError::InvalidOutputFormat(format!(
+                "{} would be written over its input; set a prefix or suffix for output names",
+                path.display()
+            )));
+        }
+        _ => path,
+    };
+    Ok((path, format))
+}
+
+/// `<dir>/<prefix><input stem><suffix>.<extension>`, e.g. `photo_nobg.png`.
+fn auto_output_path(
+    input_file: &Path,
+    dir: &Path,
+    format: OutputFormat,
+    options: &RemoveBgOptions,
+) -> Result<PathBuf> {
+    check_naming(&options.naming)?;
+    let stem = input_file.file_stem()
+        .ok_or_else(|| RemoveBgError::ProcessingError("Invalid input filename".into()))?;
+    Ok(dir.join(output_file_name(stem, format, options)))
+}
+
+/// `<prefix><stem><suffix>.<extension>`, the name of an output named after
+/// an input with this file stem.
+pub(crate) fn output_file_name(stem: &OsStr, format: OutputFormat, options: &RemoveBgOptions) -> OsString {
+    let extension = format!(".{}", output::output_extension(format, options));
+    let prefix: &OsStr = options.naming.prefix.as_ref();
+    let suffix: &OsStr = output_suffix(options).as_ref();
+    paths::file_name([prefix, stem, suffix, extension.as_ref()])
+}
+
+/// Suffix appended to the input stem for auto-generated output names:
+/// `options.naming.suffix` if set, else `_pop` for a grayscale background
+/// ("color pop") and `_nobg` otherwise.
+pub(crate) fn output_suffix(options: &RemoveBgOptions) -> &str {
+    match &options.naming.suffix {
+        Some(suffix) => suffix,
+        None if options.grayscale_background => "_pop",
+        None => "_nobg",
+    }
+}
+
+/// Refuse a prefix or suffix that would put outputs in another directory.
+pub(crate) fn check_naming(naming: &NamingOptions) -> Result<()> {
+    for (name, part) in [("prefix", &naming.prefix), ("suffix", naming.suffix.as_ref().unwrap_or(&String::new()))] {
+        if part.contains(['/', '\\', '\0']) {
+            return Err(RemoveBgError::InvalidOutputFormat(format!(
+                "output name {} {:?} contains a path separator",
+                name, part
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// `path`, or if a file exists there or `taken` claims it, the first free
+/// one of `<stem>-1.<ext>`, `<stem>-2.<ext>`, ...
+pub(crate) fn free_path(path: PathBuf, taken: &dyn Fn(&Path) -> bool) -> PathBuf {
+    let is_free = |path: &Path| !path.exists() && !taken(path);
+    if is_free(&path) {
+        return path;
+    }
+    let stem = path.file_stem().unwrap_or_default().to_os_string();
+    let extension = path.extension().map(|extension| paths::file_name([".".as_ref(), extension])).unwrap_or_default();
+    (1u64..)
+        .map(|n| path.with_file_name(paths::file_name([stem.as_os_str(), format!("-{}", n).as_ref(), extension.as_os_str()])))
+        .find(|candidate| is_free(candidate))
+        .expect("some number is free")
+}
+
+/// Whether `a` and `b` name the same file, directly or through links.
+pub(crate) fn is_same_file(a: &Path, b: &Path) -> bool {
+    a == b || matches!((a.canonicalize(), b.canonicalize()), (Ok(a), Ok(b)) if a == b)
+}
+
+/// Whether existing outputs are replaced: `options.overwrite` or
+/// [`Collision::Overwrite`].
+pub(crate) fn replaces_existing(options: &RemoveBgOptions) -> bool {
+    options.overwrite || options.naming.on_collision == Collision::Overwrite
+}
+
+/// `<dir>/<input stem>_mask.png`, or `.npy` as `format` selects, where the
+/// mask sidecar of `save_mask` goes.
+fn mask_sidecar_path(input_file: &Path, dir: &Path, format: MaskFormat) -> Result<PathBuf> {
+    let stem = input_file.file_stem()
+        .ok_or_else(|| RemoveBgError::ProcessingError("Invalid input filename".into()))?;
+    let suffix = format!("_mask.{}", format.extension());
+    Ok(dir.join(paths::file_name([stem, suffix.as_ref()])))
+}
+
+/// Whether `path` is named like one of our own outputs: a cutout (`*_nobg.*`
+/// or the prefix and suffix `options` select, possibly renamed to `*-1.*`),
+/// with `options.save_mask` a mask sidecar (`*_mask.png`), one of
+/// `options.outputs`, or with `options.split_instances` a numbered cutout
+/// (`*_nobg_1.*`).
+pub(crate) fn is_output_name(path: &Path, options: &RemoveBgOptions) -> bool {
+    let stem = path.file_stem().unwrap_or_default().as_encoded_bytes();
+    let is_png = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png"));
+    let prefix = options.naming.prefix.as_bytes();
+    let suffix = output_suffix(options).as_bytes();
+    let is_cutout = |stem: &[u8]| {
+        let stem = strip_collision_number(stem);
+        // Without a prefix or suffix, outputs look like any other image
+        stem.ends_with(b"_nobg")
+            || (stem.starts_with(prefix) && stem.ends_with(suffix) && !(prefix.is_empty() && suffix.is_empty()))
+    };
+    is_cutout(stem)
+        || (options.save_mask && is_png && stem.ends_with(b"_mask"))
+        || artifacts::is_artifact_name(path, options)
+        || (options.split_instances.is_some() && instances::strip_suffix(stem).is_some_and(is_cutout))
+}
+
+/// `stem` without the `-<n>` [`free_path`] appends, if it has one.
+fn strip_collision_number(stem: &[u8]) -> &[u8] {
+    let digits = stem.iter().rev().take_while(|byte| byte.is_ascii_digit()).count();
+    match stem[..stem.len() - digits].strip_suffix(b"-") {
+        Some(rest) if digits > 0 => rest,
+        _ => stem,
+    }
+}
+
+/// Share of pixels that must be visibly transparent before an input counts
+/// as an earlier cutout. Antialiased edges of an opaque logo stay below it.
+const REPROCESS_TRANSPARENT_SHARE: f64 = 0.05;
+
+/// Why `image`, read from `input_file`, looks like the cutout of an earlier
+/// run, if it does: it is named like one of our outputs, or enough of it is
+/// already transparent.
+pub(crate) fn reprocess_reason(image: &DynamicImage, input_file: &Path, options: &RemoveBgOptions) -> Option<&'static str> {
+    if is_output_name(input_file, options) {
+        return Some("is named like an earlier output");
+    }
+    if !image.color().has_alpha() {
+        return None;
+    }
+    let transparent = image.pixels().filter(|(_, _, pixel)| pixel[3] < 250).count();
+    let total = u64::from(image.width()) * u64::from(image.height());
+    (transparent as f64 > total as f64 * REPROCESS_TRANSPARENT_SHARE).then_some("already has transparency")
+}
+
+/// Apply `options.reprocess` to an input file that looks like an earlier
+/// cutout: warn, refuse with [`RemoveBgError::AlreadyProcessed`], or let it
+/// pass. Inputs cut out with an external mask never reach the model, so they
+/// are not checked.
+fn check_reprocess(image: &DynamicImage, input_file: &Path, options: &RemoveBgOptions) -> Result<()> {
+    if options.reprocess == Reprocess::Allow || options.external_mask.is_some() {
+        return Ok(());
+    }
+    let Some(reason) = reprocess_reason(image, input_file, options) else {
+        return Ok(());
+    };
+    if options.reprocess == Reprocess::Refuse {
+        return Err(RemoveBgError::AlreadyProcessed { path: input_file.to_path_buf(), reason: reason.into() });
+    }
+    log::warn!(
+        "{}: input {}; results may degrade (use --use-mask to reapply a mask instead, or --allow-reprocess)",
+        input_file.display(),
+        reason
+    );
+    Ok(())
+}
+
+/// Path an input would be written to when no output path is given, placed in
+/// `dir` instead of next to the input.
+///
+/// Animated inputs keep their container unless `options.format` is set,
+/// exactly as in [`remove_background`]. With [`Collision::Rename`], paths
+/// `taken` claims are numbered around like existing files.
+pub(crate) fn default_output_path(
+    input_file: &Path,
+    dir: &Path,
+    options: &RemoveBgOptions,
+    taken: &dyn Fn(&Path) -> bool,
+) -> Result<PathBuf> {
+    let animation = animation::detect(BufReader::new(File::open(input_file)?), &options.limits);
+    let default_format = animation.map_or(OutputFormat::default(), AnimationKind::output_format);
+    let (path, _) = resolve_output_path(input_file, OutputLocation::Directory(dir), default_format, options, taken)?;
+    Ok(path)
+}
+
+/// Reject formats without alpha unless the output is opaque: flattened onto a
+/// color or keeping a blurred or grayscale background. Premultiplied alpha
+/// needs a transparent output. `selection` says how `format` was chosen.
+pub(crate) fn check_transparency(format: OutputFormat, selection: Selection<'_>, options: &RemoveBgOptions) -> Result<()> {
+    let opaque = options.background != Background::Transparent
+        || options.chroma_key.is_some()
+        || blur::keeps_background(options);
+    if options.premultiplied {
+        if opaque || !format.supports_alpha() {
+            return Err(RemoveBgError::InvalidOutputFormat(
+                "premultiplied alpha needs a transparent output; it cannot be combined with a background \
+                 color, chroma key, kept background or JPEG"
+                    .into(),
+            ));
+        }
+        if options.sticker.is_some() {
+            return Err(RemoveBgError::InvalidOutputFormat(
+                "premultiplied alpha cannot be combined with sticker mode".into(),
+            ));
+        }
+    }
+    if !format.supports_alpha() && !opaque {
+        return Err(RemoveBgError::InvalidOutputFormat(format!(
+            "{} {}, but the output has transparency; add --bg-color or choose PNG/WebP/TIFF",
+            format.name(),
+            selection
+        )));
+    }
+    Ok(())
+}
+
+/// Warn that an animated input is being reduced to its first frame.
+fn warn_first_frame_only(input: &Path, format: OutputFormat) {
+    log::warn!(
+        "{} is animated but {} output holds a single image; only the first frame is kept \
+         (use --format gif or --format apng to keep the animation)",
+        input.display(),
+        format.extension().to_ascii_uppercase()
+    );
+}
+
+/// Output of the segmentation stages for one image.
+pub(crate) struct Processed {
+    /// 8-bit or 16-bit RGBA, matching the input's bit depth.
+    pub image: DynamicImage,
+    pub mask_coverage: f32,
+    /// Confidence score of the unrefined mask.
+    pub confidence: f32,
+    /// Metrics of the finished mask, measured only with `options.alpha_stats`.
+    pub alpha_stats: Option<AlphaStats>,
+    /// `None` when an external mask or a custom model was used.
+    pub model: Option<ModelInfo>,
+    /// The mask before refinement, kept only with `options.save_mask`.
+    pub raw_mask: Option<Gray16Image>,
+    /// Trimap of the mask before refinement, made only with
+    /// `options.export_trimap`.
+    pub trimap: Option<GrayImage>,
+    /// Bounding box of the subject on `image`.
+    pub subject: Option<Rect>,
+    /// Masks for the debug images, kept only with `options.debug_output`.
+    pub debug_masks: Option<DebugMasks>,
+    /// Transparent cutout for `options.outputs`, made only when they need
+    /// it and `image` is not already that cutout.
+    pub cutout: Option<DynamicImage>,
+    /// The finished mask, kept only when `options.outputs` or
+    /// `options.split_instances` need it.
+    pub mask: Option<Gray16Image>,
+    /// Solid color the background was flattened onto, if any.
+    pub background: Option<[u8; 3]>,
+    /// Whether the mask came from `options.mask_cache`.
+    pub mask_cached: bool,
+    /// Shortcut taken for an empty or full model mask.
+    pub mask_fast_path: Option<MaskFastPath>,
+    /// Memory the image was estimated to take, checked against
+    /// `options.max_memory_bytes` before segmentation.
+    pub memory_estimate: Option<MemoryEstimate>,
+}
+
+/// Run segmentation on a decoded image and build the output image.
+///
+/// `load` provides the remover to run (and whether its model was downloaded);
+/// it is called only once the image is ready, so its time is recorded as the
+/// model load stage. With `options.external_mask`, the mask is read from that
+/// file instead and `load` is never called. An image over
+/// `options.max_memory_bytes` is refused before segmentation.
+pub(crate) fn process_image<R: Deref<Target = BackgroundRemover>>(
+    image: &DynamicImage,
+    options: &RemoveBgOptions,
+    durations: &mut StageDurations,
+    load: impl FnOnce() -> Result<(R, bool)>,
+) -> Result<Processed> {
+    let estimate = check_memory(image, options)?;
+    let segmentation = segment(image, options, durations, load).map_err(|e| e.in_stage(Stage::Segmentation))?;
+    let processed = finish_processing(image, segmentation, options, durations, false)?;
+    Ok(Processed { memory_estimate: Some(estimate), ..processed })
+}
+
+/// Check a decoded image against `options.max_memory_bytes` (see
+/// [`memory::check`]), which inputs whose header couldn't be checked before
+/// decoding are checked by.
+fn check_memory(image: &DynamicImage, options: &RemoveBgOptions) -> Result<MemoryEstimate> {
+    memory::check(image.dimensions(), image.color(), options).map_err(|e| e.in_stage(Stage::Decode))
+}
+
+/// Run segmentation on several decoded images at once, packing them into
+/// batched model runs, and build their output images, as in
+/// [`process_image`]. `durations` holds one entry per image. Images over
+/// `options.max_memory_bytes` fail on their own and are left out of the
+/// batch.
+///
+/// A panic while processing the batch fails each of its images.
+pub(crate) fn process_images(
+    images: &[&DynamicImage],
+    options: &RemoveBgOptions,
+    remover: &BackgroundRemover,
+    durations: &mut [StageDurations],
+) -> Vec<Result<Processed>> {
+    let mut results: Vec<Option<Result<Processed>>> = Vec::with_capacity(images.len());
+    let mut estimates = Vec::with_capacity(images.len());
+    for image in images {
+        match check_memory(image, options) {
+            Ok(estimate) => {
+                results.push(None);
+                estimates.push(estimate);
+            }
+            Err(e) => results.push(Some(Err(e))),
+        }
+    }
+    let fitting: Vec<usize> = (0..images.len()).filter(|&i| results[i].is_none()).collect();
+    let batch: Vec<&DynamicImage> = fitting.iter().map(|&i| images[i]).collect();
+    let mut batch_durations: Vec<StageDurations> = fitting.iter().map(|&i| durations[i]).collect();
+
+    let processed = std::panic::catch_unwind(AssertUnwindSafe(|| {
+        segment_batch(&batch, options, remover, &mut batch_durations)
+            .into_iter()
+            .zip(&batch)
+            .zip(batch_durations.iter_mut())
+            .map(|((segmentation, image), durations)| {
+                let segmentation = segmentation.map_err(|e| e.in_stage(Stage::Segmentation))?;
+                finish_processing(image, segmentation, options, durations, false)
+            })
+            .collect::<Vec<_>>()
+    }));
+    let processed = processed.unwrap_or_else(|panic| batch.iter().map(|_| Err(error::panic_error(&*panic))).collect());
+    for (((i, processed), estimate), batch_durations) in fitting.into_iter().zip(processed).zip(estimates).zip(batch_durations) {
+        durations[i] = batch_durations;
+        results[i] = Some(processed.map(|processed| Processed { memory_estimate: Some(estimate), ..processed }));
+    }
+    results.into_iter().flatten().collect()
+}
+
+/// Refine the mask of a segmented image and build the output image. The
+/// finished mask is kept in the result with `keep_mask`, as well as when
+/// the options need it.
+pub(crate) fn finish_processing(
+    image: &DynamicImage,
+    segmentation: Segmentation,
+    options: &RemoveBgOptions,
+    durations: &mut StageDurations,
+    keep_mask: bool,
+) -> Result<Processed> {
+    let Segmentation { mut mask, model_mask, model, cached, fast_path } = segmentation;
+    enter_span!("postprocess", width = image.width(), height = image.height());
+    if fast_path == Some(MaskFastPath::Empty) {
+        log::warn!("the model's mask is empty; no subject was found");
+    }
+
+    let started = Instant::now();
+    let postprocess = |e: RemoveBgError| e.in_stage(Stage::Postprocess);
+    let confidence = check_confidence(&mask, options).map_err(postprocess)?;
+    let raw_mask = options.save_mask.then(|| mask.clone());
+    let trimap = match options.export_trimap {
+        Some(_) => {
+            matte::check_trimap(&options.trimap).map_err(postprocess)?;
+            Some(matte::trimap(&mask, &options.trimap))
+        }
+        None => None,
+    };
+    finish_mask(&mut mask, image, options).map_err(postprocess)?;
+    let debug_masks = options.debug_output.is_some().then(|| DebugMasks { model: model_mask, applied: mask.clone() });
+    durations.postprocess += started.elapsed();
+
+    // Apply mask to create transparent image
+    let started = Instant::now();
+    let background = match options.chroma_key.is_none() && !blur::keeps_background(options) {
+        true => background_color(image, &mask, options),
+        false => None,
+    };
+    // Estimate an automatic color once, for the output, the report and --emit
+    let resolved;
+    let options = match (options.background, background) {
+        (Background::Auto, Some(color)) => {
+            resolved = RemoveBgOptions { background: Background::Color(color), ..options.clone() };
+            &resolved
+        }
+        _ => options,
+    };
+    let composite = |e: RemoveBgError| e.in_stage(Stage::Composite);
+    // A mask that is still opaque everywhere after refinement makes the
+    // cutout a copy of the input
+    let opaque = fast_path == Some(MaskFastPath::Full) && mask.iter().all(|&alpha| alpha == u16::MAX);
+    let (output_image, subject) = match opaque {
+        true => options.cancel.check().and_then(|()| compose_masked(image, &mask, opaque_copy(image), options)),
+        false => compose(image, &mask, options),
+    }
+    .map_err(composite)?;
+    let cutout = match artifacts::needs_cutout(options) && !artifacts::main_is_transparent(options) {
+        true => Some(artifacts::transparent_cutout(image, &mask, options).map_err(composite)?),
+        false => None,
+    };
+    let kept = mask.iter().filter(|&&alpha| u8::from_mask(alpha) > 0).count();
+    let mask_coverage = kept as f32 / mask.len().max(1) as f32;
+    let alpha_stats = options.alpha_stats.then(|| analysis::measure_alpha(&mask));
+    durations.composite = started.elapsed();
+
+    let mask = (keep_mask || artifacts::needs_mask(options) || options.split_instances.is_some()).then_some(mask);
+    Ok(Processed {
+        image: output_image,
+        mask_coverage,
+        confidence,
+        alpha_stats,
+        model,
+        raw_mask,
+        trimap,
+        subject,
+        debug_masks,
+        cutout,
+        mask,
+        background,
+        mask_cached: cached,
+        mask_fast_path: fast_path,
+        memory_estimate: None,
+    })
+}
+
+/// Score the confidence of an unrefined mask and reject it if the score is
+/// below `options.confidence.min_score`.
+fn check_confidence(mask: &Gray16Image, options: &RemoveBgOptions) -> Result<f32> {
+    let score = analysis::confidence(mask, &options.confidence)?;
+    log::debug!("mask confidence {:.3}", score);
+    match options.confidence.min_score {
+        Some(min_score) if score < min_score => Err(RemoveBgError::LowConfidence { score, min_score }),
+        _ => Ok(score),
+    }
+}
+
+/// Masks computed for one image by [`segment`].
+#[derive(Clone)]
+pub(crate) struct Segmentation {
+    /// Unrefined full-resolution mask.
+    mask: Gray16Image,
+    /// The mask at the model's output resolution, kept only for
+    /// `options.debug_output` without tiling.
+    model_mask: Option<Gray16Image>,
+    /// `None` when an external mask or a custom model was used.
+    model: Option<ModelInfo>,
+    /// Whether `mask` came from `options.mask_cache` instead of the model.
+    cached: bool,
+    /// Shortcut taken for an empty or full model mask.
+    fast_path: Option<MaskFastPath>,
+}
+
+/// Compute the unrefined full-resolution mask for a decoded image, by running
+/// the model or reading `options.external_mask`, as in [`process_image`].
+pub(crate) fn segment<R: Deref<Target = BackgroundRemover>>(
+    image: &DynamicImage,
+    options: &RemoveBgOptions,
+    durations: &mut StageDurations,
+    load: impl FnOnce() -> Result<(R, bool)>,
+) -> Result<Segmentation> {
+    options.cancel.check()?;
+    check_min_size(image)?;
+    match &options.external_mask {
+        Some(path) => {
+            let started = Instant::now();
+            let mask = load_external_mask(path, image, options)?;
+            durations.decode += started.elapsed();
+            Ok(Segmentation { mask, model_mask: None, model: None, cached: false, fast_path: None })
+        }
+        None if options.line_art && line_art::is_line_art(image) => {
+            let started = Instant::now();
+            let mask = line_art::luminance_mask(image);
+            durations.postprocess += started.elapsed();
+            log::debug!("input looks like line art; the mask is taken from luminance");
+            Ok(Segmentation { mask, model_mask: None, model: None, cached: false, fast_path: None })
+        }
+        None if options.auto_model.is_some() => segment_auto(image, options, durations),
+        None if options.quality_fallback.is_some() => segment_within_deadline(image, options, durations, load),
+        None => {
+            let cache = options.mask_cache.as_ref().map(|cache| (cache, mask_cache::key(image, options)));
+            if let Some(segmentation) = cached_mask(image, options, cache.as_ref(), durations) {
+                return Ok(segmentation);
+            }
+
+            let started = Instant::now();
+            let loaded = load()?;
+            if loaded.0.loaded_since(started) {
+                durations.warmup = loaded.0.warmup_duration();
+            }
+            durations.model_load = started.elapsed() - durations.warmup;
+            segment_loaded(image, options, durations, loaded, cache.as_ref())
+        }
+    }
+}
+
+/// The mask of `image` in the mask cache entry `cache`, if it is stored.
+fn cached_mask(
+    image: &DynamicImage,
+    options: &RemoveBgOptions,
+    cache: Option<&(&MaskCacheOptions, String)>,
+    durations: &mut StageDurations,
+) -> Option<Segmentation> {
+    let (cache, key) = cache?;
+    let started = Instant::now();
+    let mask = mask_cache::load(cache, key, image.dimensions());
+    durations.decode += started.elapsed();
+    let mask = mask?;
+    trace_event!("mask_cache_hit", model = %options.model);
+    Some(cached_segmentation(mask, options))
+}
+
+/// The model's part of [`segment`], with the remover loaded already; the
+/// mask is stored in the mask cache entry `cache`.
+fn segment_loaded<R: Deref<Target = BackgroundRemover>>(
+    image: &DynamicImage,
+    options: &RemoveBgOptions,
+    durations: &mut StageDurations,
+    (remover, downloaded): (R, bool),
+    cache: Option<&(&MaskCacheOptions, String)>,
+) -> Result<Segmentation> {
+    // Generate alpha mask using U2-Net, on the enhanced copy with auto_enhance
+    let enhanced = model_input(image, options, durations)?;
+    let model_image = enhanced.as_ref().unwrap_or(image);
+    let (options, inference) = fix_inference_size(&remover, model_image, options, durations)?;
+    let options = options.as_ref();
+    let (mask, model_mask, fast_path) = if options.tiling.is_none() && options.roi.is_none() && !options.two_pass {
+        let model_mask = predict_mask(&remover, model_image, options, durations)?;
+        let started = Instant::now();
+        let (mask, fast_path) = upscale_model_mask(&model_mask, image, options)?;
+        durations.postprocess += started.elapsed();
+        (mask, options.debug_output.is_some().then_some(model_mask), fast_path)
+    } else {
+        (raw_subject_mask(&remover, model_image, options, durations)?, None, None)
+    };
+    if let Some((cache, key)) = cache {
+        mask_cache::store(cache, key, &mask);
+    }
+    let model = remover_info(&remover, options, downloaded, inference)?;
+    Ok(Segmentation { mask, model_mask, model, cached: false, fast_path })
+}
+
+/// Load the remover for an input that runs it several times, for frames or
+/// pages, recording the load in `durations`. An external mask replaces the
+/// model for every run, so nothing is loaded then.
+pub(crate) fn load_once<R: Deref<Target = BackgroundRemover>>(
+    options: &RemoveBgOptions,
+    durations: &mut StageDurations,
+    load: impl FnOnce() -> Result<(R, bool)>,
+) -> Result<Option<(R, bool)>> {
+    let started = Instant::now();
+    let loaded = match options.external_mask {
+        Some(_) => None,
+        None => Some(load()?),
+    };
+    if let Some((remover, _)) = &loaded {
+        if remover.loaded_since(started) {
+            durations.warmup = remover.warmup_duration();
+        }
+    }
+    durations.model_load = started.elapsed() - durations.warmup;
+    Ok(loaded)
+}
+
+/// [`segment`] with the models chosen by `options.auto_model`, each run
+/// like a fixed model with the shared remover for it.
+fn segment_auto(image: &DynamicImage, options: &RemoveBgOptions, durations: &mut StageDurations) -> Result<Segmentation> {
+    let auto = options.auto_model.unwrap_or_default();
+    let mut model = auto_model::FIRST;
+    let (mut tried, mut reasons) = (Vec::new(), Vec::new());
+    loop {
+        let model_options = RemoveBgOptions { model, auto_model: None, ..options.clone() };
+        // Each run records its own model load, so they are added up
+        let mut run = StageDurations::default();
+        let mut segmentation = segment(image, &model_options, &mut run, || shared_remover(&model_options))?;
+        *durations += run;
+        tried.push(model);
+        match auto_model::decide(model, &segmentation.mask, tried.len() as u32, auto, &options.confidence)? {
+            auto_model::Decision::Rerun(next, reason) => {
+                log::info!("{}; running {}", reason, next);
+                trace_event!("fallback", reason = %reason, model = %next);
+                reasons.push(reason);
+                model = next;
+            }
+            auto_model::Decision::Keep(reason) => {
+                match &reason {
+                    Some(reason) => log::info!("{}; keeping the {} mask", reason, model),
+                    None => log::info!("Keeping the {} mask", model),
+                }
+                reasons.extend(reason);
+                if let Some(info) = &mut segmentation.model {
+                    info.selection = Some(ModelSelection { tried, reason: reasons.join("; ") });
+                }
+                return Ok(segmentation);
+            }
+        }
+    }
+}
+
+/// [`segment`] under `options.quality_fallback`, with the options planned
+/// to meet its deadline. A downgraded model comes from the shared remover
+/// cache, like the models of `auto_model`; a lowered size that the model
+/// can't run at is given up again. A single model run is recorded in the
+/// [`calibration`](fallback::calibration).
+fn segment_within_deadline<R: Deref<Target = BackgroundRemover>>(
+    image: &DynamicImage,
+    options: &RemoveBgOptions,
+    durations: &mut StageDurations,
+    load: impl FnOnce() -> Result<(R, bool)>,
+) -> Result<Segmentation> {
+    let plan = fallback::plan(image, options, fallback::spent(durations), fallback::calibration())?;
+    if SessionKey::from_options(&plan.options) == SessionKey::from_options(options) {
+        segment_planned(image, plan, options, durations, load)
+    } else {
+        let planned = plan.options.clone();
+        segment_planned(image, plan, options, durations, || shared_remover(&planned))
+    }
+}
+
+/// Load the remover for `plan`, check its size and segment `image` with it.
+fn segment_planned<R: Deref<Target = BackgroundRemover>>(
+    image: &DynamicImage,
+    mut plan: fallback::Plan,
+    configured: &RemoveBgOptions,
+    durations: &mut StageDurations,
+    load: impl FnOnce() -> Result<(R, bool)>,
+) -> Result<Segmentation> {
+    let loaded = load_once(&plan.options, durations, load)?.expect("segmented without an external mask");
+    if let (Segmenter::Builtin(runner), InferenceSize::Fixed(size)) = (loaded.0.segmenter(), plan.options.inference_size) {
+        if plan.downgrades.contains(&Downgrade::InferenceSize) && !runner.accepts_size(size) {
+            log::debug!("{} only runs at its own size; keeping the configured inference size", plan.options.model);
+            plan.options.inference_size = configured.inference_size;
+            plan.downgrades.retain(|&downgrade| downgrade != Downgrade::InferenceSize);
+        }
+    }
+
+    let cache = plan.options.mask_cache.as_ref().map(|cache| (cache, mask_cache::key(image, &plan.options)));
+    let mut segmentation = match cached_mask(image, &plan.options, cache.as_ref(), durations) {
+        Some(segmentation) => segmentation,
+        None => segment_loaded(image, &plan.options, durations, loaded, cache.as_ref())?,
+    };
+    if let Some(info) = &mut segmentation.model {
+        let single = plan.options.tiling.is_none() && plan.options.roi.is_none() && !plan.options.two_pass;
+        if let (Some(inference), false, true) = (info.inference, segmentation.cached, single) {
+            fallback::record(&plan.options, inference.relative_cost, durations.inference);
+        }
+        info.downgrades = plan.downgrades;
+    }
+    Ok(segmentation)
+}
+
+/// Smallest width and height segmentation accepts. Masks of single-pixel
+/// rows or columns have no edge to speak of, and the refinement filters
+/// assume a neighborhood on every axis.
+const MIN_IMAGE_SIZE: u32 = 2;
+
+/// Return `Err(ImageTooSmall)` for images below [`MIN_IMAGE_SIZE`] on
+/// either side.
+pub(crate) fn check_min_size(image: &DynamicImage) -> Result<()> {
+    let (width, height) = image.dimensions();
+    if width < MIN_IMAGE_SIZE || height < MIN_IMAGE_SIZE {
+        return Err(RemoveBgError::ImageTooSmall { width, height });
+    }
+    Ok(())
+}
+
+/// The copy of `image` the model runs on with `options.auto_enhance`, or
+/// `None` to run it on `image` itself. The time taken is recorded as
+/// preprocessing.
+fn model_input(image: &DynamicImage, options: &RemoveBgOptions, durations: &mut StageDurations) -> Result<Option<DynamicImage>> {
+    let Some(strength) = options.auto_enhance else {
+        return Ok(None);
+    };
+    enhance::check(strength)?;
+    let started = Instant::now();
+    let enhanced = enhance::enhance(image, strength);
+    durations.preprocess += started.elapsed();
+    Ok(enhanced)
+}
+
+/// A segmentation from a mask found in `options.mask_cache`.
+///
+/// The model wasn't loaded, so its report says it wasn't downloaded, and
+/// leaves it out if its file has since been removed.
+fn cached_segmentation(mask: Gray16Image, options: &RemoveBgOptions) -> Segmentation {
+    Segmentation { mask, model_mask: None, model: model_info(options, false).ok(), cached: true, fast_path: None }
+}
+
+/// Segment several decoded images like [`segment`], running the model once
+/// per batch instead of once per image.
+///
+/// Tiled inference, regions of interest, two-pass inference, external masks
+/// and line art don't go through a single model run, and quality fallbacks
+/// are planned per image, so with any of them each image is segmented on
+/// its own. The time of a batched run is split evenly between its images.
+/// Images whose masks are in `options.mask_cache` are left out of the batch.
+fn segment_batch(
+    images: &[&DynamicImage],
+    options: &RemoveBgOptions,
+    remover: &BackgroundRemover,
+    durations: &mut [StageDurations],
+) -> Vec<Result<Segmentation>> {
+    if options.tiling.is_some()
+        || options.roi.is_some()
+        || options.two_pass
+        || options.external_mask.is_some()
+        || options.line_art
+        || options.auto_model.is_some()
+        || options.quality_fallback.is_some()
+        || options.inference_size == InferenceSize::Auto
+        || options.auto_enhance.is_some_and(|strength| enhance::check(strength).is_err())
+    {
+        return images
+            .iter()
+            .zip(durations.iter_mut())
+            .map(|(image, durations)| segment(image, options, durations, || Ok((remover, false))))
+            .collect();
+    }
+
+    let keys: Vec<Option<String>> = images
+        .iter()
+        .map(|image| options.mask_cache.as_ref().map(|_| mask_cache::key(image, options)))
+        .collect();
+    let mut results: Vec<Option<Result<Segmentation>>> = images
+        .iter()
+        .zip(&keys)
+        .zip(durations.iter_mut())
+        .map(|((image, key), durations)| {
+            if let Err(error) = check_min_size(image) {
+                return Some(Err(error));
+            }
+            let (cache, key) = (options.mask_cache.as_ref()?, key.as_ref()?);
+            let started = Instant::now();
+            let mask = mask_cache::load(cache, key, image.dimensions());
+            durations.decode += started.elapsed();
+            Some(Ok(cached_segmentation(mask?, options)))
+        })
+        .collect();
+    let missing: Vec<usize> = (0..images.len()).filter(|&i| results[i].is_none()).collect();
+    if missing.is_empty() {
+        return results.into_iter().flatten().collect();
+    }
+
+    let mut batch = StageDurations::default();
+    let enhanced: Vec<Option<DynamicImage>> = missing
+        .iter()
+        .map(|&i| model_input(images[i], options, &mut durations[i]).expect("strength was checked"))
+        .collect();
+    let batch_images: Vec<&DynamicImage> =
+        missing.iter().zip(&enhanced).map(|(&i, enhanced)| enhanced.as_ref().unwrap_or(images[i])).collect();
+    let masks = predict_masks(remover, &batch_images, options, &mut batch);
+    let share = missing.len() as u32;
+    for (i, model_mask) in missing.into_iter().zip(masks) {
+        let durations = &mut durations[i];
+        durations.preprocess += batch.preprocess / share;
+        durations.inference += batch.inference / share;
+        results[i] = Some(model_mask.and_then(|model_mask| {
+            let started = Instant::now();
+            let (mask, fast_path) = upscale_model_mask(&model_mask, images[i], options)?;
+            durations.postprocess += started.elapsed();
+            if let (Some(cache), Some(key)) = (&options.mask_cache, &keys[i]) {
+                mask_cache::store(cache, key, &mask);
+            }
+            let model_mask = options.debug_output.is_some().then_some(model_mask);
+            let (_, inference) = fix_inference_size(remover, images[i], options, durations)?;
+            let model = remover_info(remover, options, false, inference)?;
+            Ok(Segmentation { mask, model_mask, model, cached: false, fast_path })
+        }));
+    }
+    results.into_iter().flatten().collect()
+}
+
+/// Analyze where the subject is in a decoded image without building a cutout.
+fn analyze_image<R: Deref<Target = BackgroundRemover>>(
+    image: &DynamicImage,
+    threshold: f32,
+    options: &RemoveBgOptions,
+    durations: &mut StageDurations,
+    load: impl FnOnce() -> Result<(R, bool)>,
+) -> Result<SubjectAnalysis> {
+    analysis::check_threshold(threshold)?;
+    let Segmentation { mut mask, model, .. } =
+        segment(image, options, durations, load).map_err(|e| e.in_stage(Stage::Segmentation))?;
+
+    let started = Instant::now();
+    let postprocess = |e: RemoveBgError| e.in_stage(Stage::Postprocess);
+    let confidence = analysis::confidence(&mask, &options.confidence).map_err(postprocess)?;
+    finish_mask(&mut mask, image, options).map_err(postprocess)?;
+    let mut analysis = analysis::analyze_mask(&mask, threshold);
+    analysis.confidence = confidence;
+    analysis.model = model;
+    durations.postprocess += started.elapsed();
+    Ok(analysis)
+}
+
+/// Describe the model a remover runs and the size it ran at, or `None`
+/// for a custom model.
+fn remover_info(
+    remover: &BackgroundRemover,
+    options: &RemoveBgOptions,
+    downloaded: bool,
+    inference: Option<InferenceInfo>,
+) -> Result<Option<ModelInfo>> {
+    match remover.segmenter() {
+        Segmenter::Builtin(_) => model_info(options, downloaded).map(|info| Some(ModelInfo { inference, ..info })),
+        Segmenter::Custom(_) => Ok(None),
+    }
+}
+
+/// Describe the cached model selected by `options`.
+fn model_info(options: &RemoveBgOptions, downloaded: bool) -> Result<ModelInfo> {
+    if let Some(bytes) = model::embedded_model(options.model) {
+        return Ok(ModelInfo {
+            model: options.model,
+            device: options.device,
+            path: PathBuf::new(),
+            size_bytes: bytes.len() as u64,
+            downloaded: false,
+            selection: None,
+            inference: None,
+            downgrades: Vec::new(),
+        });
+    }
+    let path = model::model_path(options.model, options.model_dir.as_deref())?;
+    let size_bytes = std::fs::metadata(&path)?.len();
+    Ok(ModelInfo {
+        model: options.model,
+        device: options.device,
+        path,
+        size_bytes,
+        downloaded,
+        selection: None,
+        inference: None,
+        downgrades: Vec::new(),
+    })
+}
+
+/// Download, verify, and load the model selected by `options` ahead of time.
+///
+/// Servers can call this at startup so the first request doesn't pay for the
+/// model download and session creation, and deployment scripts can use it to
+/// bake the model into an image. The model file's SHA-256 is verified before
+/// the session is created. With [`RemoveBgOptions::warmup`], the model also
+/// runs once on a blank image, so the engine's first-run setup doesn't land on
+/// the first request either. Calling it again with the same options is a cheap
+/// no-op that returns the cached session's details.
+///
+/// # Arguments
+/// * `options` - Options selecting the model, device, and session settings.
+///
+/// # Returns
+/// Information about the prepared model, including whether it was downloaded.
+///
+/// # Errors
+/// * `ModelInitError` - If the download, checksum verification, or session creation fails
+///
+/// # Examples
+/// ```no_run
+/// use removebg::{prepare_model, Model, RemoveBgOptions};
+///
+/// let options = RemoveBgOptions { model: Model::U2netp, ..Default::default() };
+/// let info = prepare_model(&options)?;
+/// println!("{} ready at {}", info.model, info.path.display());
+/// # Ok::<(), removebg::error::RemoveBgError>(())
+/// ```
+#[cfg(not(target_arch = "wasm32"))]
+pub fn prepare_model(options: &RemoveBgOptions) -> Result<ModelInfo> {
+    let key = SessionKey::from_options(options);
+    let cached = SHARED_REMOVERS
+        .get()
+        .and_then(|removers| removers.lock().unwrap_or_else(PoisonError::into_inner).get(&key).cloned())
+        .is_some_and(|entry| entry.lock().unwrap_or_else(PoisonError::into_inner).is_some());
+    if cached {
+        return model_info(options, false);
+    }
+
+    if model::embedded_model(options.model).is_some() {
+        shared_remover(options)?;
+        return model_info(options, false);
+    }
+
+    let model_dir = options.model_dir.as_deref();
+    let downloaded = !model::model_path(options.model, model_dir)?.exists();
+    model::ensure_model(options.model, model_dir, &options.download, &options.download_progress, &options.cancel)?;
+    model::verify_model_against(options.model, model_dir, options.download.sha256.as_deref())?;
+    shared_remover(options)?;
+
+    model_info(options, downloaded)
+}
+
+/// Remove background from an image and save as transparent PNG.
+///
+/// This function uses the U2-Net model to perform accurate background segmentation
+/// and removal. The model automatically detects the main subject in the image and
+/// removes everything else, replacing it with transparency.
+///
+/// # Process Flow
+/// 1. Validate input file exists and is readable
+/// 2. Load image data
+/// 3. Process with U2-Net model to generate alpha mask
+/// 4. Apply mask to create transparency
+/// 5. Save result as PNG
+///
+/// # Arguments
+/// * `input_path` - Path to the input image file. Supports common formats like JPEG, PNG, BMP, TIFF, etc.
+///   A `&str`, `String`, `Path`, or `PathBuf`; names that are not valid UTF-8 are kept as they are.
+/// * `output_path` - Optional path to save the output image. If not provided, saves to the same
+///   directory as input with "_nobg" suffix; an existing directory or a path ending with a
+///   separator gets that name inside it. The extension selects the format (PNG, WebP,
+///   TIFF, BMP); JPEG requires a background color, unknown extensions become `.png`, and
+///   a path without an extension is refused.
+///
+/// # Returns
+/// The path to the output file that was created.
+///
+/// # Errors
+/// * `FileNotFound` - If the input file doesn't exist
+/// * `NotAFile` - If the input path is not a file (e.g., it's a directory)
+/// * `ImageError` - If the file cannot be processed as a valid image
+/// * `ModelError` - If model inference fails
+/// * `InvalidOutputFormat` - If the output format cannot store transparency, or the output
+///   path has no extension
+/// * `OutputExists` - If the output file already exists (see [`RemoveBgOptions::overwrite`])
+/// * `OutputDirectoryMissing` - If the output directory doesn't exist (see
+///   [`RemoveBgOptions::create_dirs`])
+/// * `AlreadyProcessed` - If the input looks like an earlier cutout and
+///   [`RemoveBgOptions::reprocess`] is [`Reprocess::Refuse`]
+/// * `Cancelled` - If [`RemoveBgOptions::cancel`] was triggered; no output file is written
+///
+/// # Examples
+/// ```no_run
+/// use removebg::remove_background;
+/// use std::path::Path;
+///
+/// // Basic usage with auto-generated output path
+/// let output = remove_background("photo.jpg", None)?;
+/// println!("Saved to: {}", output.display());
+///
+/// // Custom output path
+/// let output = remove_background("photo.jpg", Some(Path::new("result.png")))?;
+/// println!("Saved to: {}", output.display());
+///
+/// // Into a directory: processed/photo_nobg.png
+/// let output = remove_background("photo.jpg", Some(Path::new("processed/")))?;
+/// println!("Saved to: {}", output.display());
+/// # Ok::<(), removebg::error::RemoveBgError>(())
+/// ```
+pub fn remove_background(input_path: impl AsRef<Path>, output_path: Option<&Path>) -> Result<PathBuf> {
+    remove_background_with_options(input_path, output_path, &RemoveBgOptions::default())
+}
+
+/// Remove background from an image using custom options.
+///
+/// Behaves like [`remove_background`], but allows configuring how the model is
+/// run, for example selecting a hardware execution provider.
+///
+/// # Arguments
+/// * `input_path` - Path to the input image file.
+/// * `output_path` - Optional path to save the output image (see [`remove_background`]).
+/// * `options` - Options controlling model execution.
+///
+/// # Returns
+/// The path to the output file that was created.
+///
+/// # Errors
+/// Same as [`remove_background`].
+pub fn remove_background_with_options(
+    input_path: impl AsRef<Path>,
+    output_path: Option<&Path>,
+    options: &RemoveBgOptions,
+) -> Result<PathBuf> {
+    let report = remove_background_detailed(input_path, output_path, options)?;
+    Ok(report.output_path)
+}
+
+/// Remove background from an image and return a detailed report.
+///
+/// Behaves like [`remove_background_with_options`], but returns the input
+/// dimensions, mask coverage, per-stage timings, and model details along with
+/// the output path.
+///
+/// # Arguments
+/// * `input_path` - Path to the input image file.
+/// * `output_path` - Optional path to save the output image (see [`remove_background`]).
+/// * `options` - Options controlling model execution.
+///
+/// # Returns
+/// A [`RemovalReport`] describing the run.
+///
+/// # Errors
+/// Same as [`remove_background`].
+///
+/// # Examples
+/// ```no_run
+/// use removebg::{remove_background_detailed, RemoveBgOptions};
+///
+/// let report = remove_background_detailed("photo.jpg", None, &RemoveBgOptions::default())?;
+/// if report.mask_coverage < 0.01 {
+///     eprintln!("No subject found in photo.jpg");
+/// }
+/// # Ok::<(), removebg::error::RemoveBgError>(())
+/// ```
+pub fn remove_background_detailed(
+    input_path: impl AsRef<Path>,
+    output_path: Option<&Path>,
+    options: &RemoveBgOptions,
+) -> Result<RemovalReport> {
+    remove_file(input_path.as_ref(), output_path, options, || shared_remover(options))
+}
+
+/// Find where the subject is in an image without writing a cutout.
+///
+/// Runs the model (or applies `options.external_mask`) and the mask
+/// refinements enabled in `options`, then measures the mask instead of
+/// applying it: the subject's bounding box, coverage, centroid, coverage per
+/// quadrant, and each separate region, largest first. Mask pixels count as
+/// subject when their alpha is at least `threshold`.
+///
+/// # Arguments
+/// * `input_path` - Path to the input image file.
+/// * `threshold` - Alpha (0.0-1.0, above zero) from which a pixel belongs to the subject.
+/// * `options` - Options controlling model execution and mask refinement.
+///
+/// # Returns
+/// A [`SubjectAnalysis`] of the mask.
+///
+/// # Errors
+/// * `FileNotFound` - If the input file doesn't exist
+/// * `NotAFile` - If the input path is a directory
+/// * `ProcessingError` - If the image can't be decoded or `threshold` is out of range
+/// * `ModelError` - If model inference fails
+///
+/// # Examples
+/// ```no_run
+/// use removebg::{analyze, RemoveBgOptions};
+///
+/// let analysis = analyze("photo.jpg", 0.5, &RemoveBgOptions::default())?;
+/// if let Some(bounds) = analysis.bounds {
+///     println!("Subject at {},{} size {}x{}", bounds.x, bounds.y, bounds.width, bounds.height);
+/// }
+/// # Ok::<(), removebg::error::RemoveBgError>(())
+/// ```
+pub fn analyze(input_path: impl AsRef<Path>, threshold: f32, options: &RemoveBgOptions) -> Result<SubjectAnalysis> {
+    analyze_file(input_path.as_ref(), threshold, options, || shared_remover(options))
+}
+
+/// [`analyze`] with the remover provided by `load`.
+pub(crate) fn analyze_file<R: Deref<Target = BackgroundRemover>>(
+    input_path: &Path,
+    threshold: f32,
+    options: &RemoveBgOptions,
+    load: impl FnOnce() -> Result<(R, bool)>,
+) -> Result<SubjectAnalysis> {
+    let options = &options.timed();
+    error::catch_panic(|| {
+        let started = Instant::now();
+        let mut durations = StageDurations::default();
+        let (image, _) = open_input(input_path, &options.limits).map_err(|e| e.for_input(input_path))?;
+        durations.decode = started.elapsed();
+
+        let mut analysis =
+            analyze_image(&image, threshold, options, &mut durations, load).map_err(|e| e.for_input(input_path))?;
+        durations.total = started.elapsed();
+        analysis.durations = durations;
+        Ok(analysis)
+    })
+}
+
+/// Measure the [`AlphaStats`] of an existing cutout from its alpha channel,
+/// without running the model, e.g. to check files written earlier.
+///
+/// 16-bit cutouts are measured at full depth, so they give the same numbers
+/// as [`RemovalReport::alpha_stats`](crate::RemovalReport::alpha_stats) did
+/// when they were written.
+///
+/// # Errors
+/// * `FileNotFound` - If the file doesn't exist
+/// * `NotAFile` - If the path is a directory
+/// * `UnsupportedFormat` - If the file is not a readable image
+/// * `InvalidMask` - If the image has no alpha channel
+///
+/// # Examples
+/// ```no_run
+/// let stats = removebg::cutout_stats("photo_nobg.png")?;
+/// if stats.specks > 0 || stats.ambiguous > 0.05 {
+///     println!("needs review");
+/// }
+/// # Ok::<(), removebg::error::RemoveBgError>(())
+/// ```
+pub fn cutout_stats(path: impl AsRef<Path>) -> Result<AlphaStats> {
+    let path = path.as_ref();
+    let (image, _) = open_input(path, &DecodeLimits::default()).map_err(|e| e.for_input(path))?;
+    if !image.color().has_alpha() {
+        return Err(RemoveBgError::InvalidMask(format!("{} has no alpha channel to measure", path.display())));
+    }
+    let cutout = image.into_rgba16();
+    let alpha = Gray16Image::from_fn(cutout.width(), cutout.height(), |x, y| Luma([cutout.get_pixel(x, y)[3]]));
+    Ok(analysis::measure_alpha(&alpha))
+}
+
+/// Decode the image file at `input_path` on its own, with its metadata, for
+/// the entry points that write no cutout file.
+pub(crate) fn open_input(input_file: &Path, limits: &DecodeLimits) -> Result<(DynamicImage, Metadata)> {
+    let file = checked_input(input_file)?;
+    load_input(BufReader::new(File::open(file)?), input_file, input_file, limits)
+}
+
+/// `input_file` in the form to open it with (see [`paths::long_path`]),
+/// after checking that it is an existing file.
+fn checked_input(input_file: &Path) -> Result<std::borrow::Cow<'_, Path>> {
+    let file = paths::long_path(input_file);
+    if !file.exists() {
+        return Err(RemoveBgError::FileNotFound(input_file.to_path_buf()));
+    }
+    // Ensure input is a file, not a directory
+    if !file.is_file() {
+        return Err(RemoveBgError::NotAFile(input_file.to_path_buf()));
+    }
+    Ok(file)
+}
+
+/// File-to-file pipeline shared by the free functions and [`BackgroundRemover`].
+pub(crate) fn remove_file<R: Deref<Target = BackgroundRemover>>(
+    input_file: &Path,
+    output_path: Option<&Path>,
+    options: &RemoveBgOptions,
+    load: impl FnOnce() -> Result<(R, bool)>,
+) -> Result<RemovalReport> {
+    let options = &options.timed();
+    error::catch_panic(|| {
+        enter_span!("process", input = %input_file.display());
+        let started = Instant::now();
+        let durations = StageDurations::default();
+        let file = checked_input(input_file)?;
+        let open = || Ok(BufReader::new(File::open(&file)?));
+        let mut report = remove_to_file(input_file, input_file, open, output_path, options, durations, load)
+            .map_err(|e| e.for_input(input_file))?;
+        report.durations.total = started.elapsed();
+        Ok(report)
+    })
+}
+
+/// Remove the background from one frame of an image sequence with
+/// `remover`, like [`remove_file`] for a still image, passing the unrefined
+/// mask through `adjust` together with the decoded frame before the mask is
+/// refined and applied.
+pub(crate) fn remove_frame(
+    input_file: &Path,
+    output_path: &Path,
+    options: &RemoveBgOptions,
+    remover: &BackgroundRemover,
+    adjust: impl FnOnce(&DynamicImage, &mut Gray16Image),
+) -> Result<RemovalReport> {
+    let options = &options.timed();
+    let started = Instant::now();
+    let mut durations = StageDurations::default();
+    let file = checked_input(input_file)?;
+    let open = || Ok(BufReader::new(File::open(&file)?));
+    let mut report = (|| {
+        let target = prepare_target(input_file, input_file, open, Some(output_path), options)?;
+        let stage = Instant::now();
+        let (image, metadata) = load_input(memory::check_input(open()?, options)?, input_file, input_file, &options.limits)?;
+        check_reprocess(&image, input_file, options)?;
+        let input_dimensions = image.dimensions();
+        let image = limit_output_size(image, options).map_err(|e| e.in_stage(Stage::Decode))?;
+        durations.decode = stage.elapsed();
+
+        let estimate = check_memory(&image, options)?;
+        let mut segmentation = segment(&image, options, &mut durations, || Ok((remover, false)))
+            .map_err(|e| e.in_stage(Stage::Segmentation))?;
+        adjust(&image, &mut segmentation.mask);
+        let processed = finish_processing(&image, segmentation, options, &mut durations, false)?;
+        let processed = Processed { memory_estimate: Some(estimate), ..processed };
+        let mut report = write_output(input_file, image, &metadata, processed, target, options, durations)?;
+        report.input_dimensions = input_dimensions;
+        Ok(report)
+    })()
+    .map_err(|e: RemoveBgError| e.for_input(input_file))?;
+    report.durations.total = started.elapsed();
+    Ok(report)
+}
+
+/// Remove the background from an image downloaded from `url`.
+///
+/// The image is downloaded into memory and processed like a local file. When
+/// `output_path` is `None`, the output is named after the last segment of the
+/// URL path (`https://host/shots/photo.jpg` becomes `photo_nobg.png` in the
+/// current directory), or `output_nobg.png` if the URL has no usable name.
+/// Downloads larger than `options.limits.max_download_size` are refused.
+///
+/// # Arguments
+/// * `url` - http or https URL of the input image.
+/// * `output_path` - Optional path to save the output image (see [`remove_background`]).
+/// * `options` - Options controlling model execution.
+///
+/// # Returns
+/// A [`RemovalReport`] describing the run.
+///
+/// # Errors
+/// * `FileNotFound` - If the server responds with 404 or 410
+/// * `DownloadError` - If the download fails, the response is not an image, or
+///   it exceeds the download size limit
+/// * Otherwise the same as [`remove_background`]
+///
+/// # Examples
+/// ```no_run
+/// use removebg::{remove_background_from_url, RemoveBgOptions};
+///
+/// let report = remove_background_from_url(
+///     "https://example.com/shots/photo.jpg",
+///     None,
+///     &RemoveBgOptions::default(),
+/// )?;
+/// println!("Saved to: {}", report.output_path.display());
+/// # Ok::<(), removebg::error::RemoveBgError>(())
+/// ```
+#[cfg(not(target_arch = "wasm32"))]
+pub fn remove_background_from_url(
+    url: &str,
+    output_path: Option<&Path>,
+    options: &RemoveBgOptions,
+) -> Result<RemovalReport> {
+    remove_url(url, output_path, options, || shared_remover(options))
+}
+
+/// URL-to-file pipeline shared by the free functions and [`BackgroundRemover`].
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn remove_url<R: Deref<Target = BackgroundRemover>>(
+    url: &str,
+    output_path: Option<&Path>,
+    options: &RemoveBgOptions,
+    load: impl FnOnce() -> Result<(R, bool)>,
+) -> Result<RemovalReport> {
+    let options = &options.timed();
+    error::catch_panic(|| {
+        let started = Instant::now();
+        let mut durations = StageDurations::default();
+
+        let data = remote::fetch_image_until(url, options.limits.max_download_size, &options.cancel)
+            .map_err(|e| e.in_stage(Stage::Download).for_input(Path::new(url)))?;
+        durations.decode = started.elapsed();
+
+        // Named like a local file so the output gets a matching name
+        let name = PathBuf::from(remote::file_name(url).unwrap_or_else(|| "output".into()));
+        let open = || Ok(Cursor::new(data.as_slice()));
+        let mut report = remove_to_file(Path::new(url), &name, open, output_path, options, durations, load)
+            .map_err(|e| e.for_input(Path::new(url)))?;
+        report.durations.total = started.elapsed();
+        Ok(report)
+    })
+}
+
+/// Where the result for one input is written, resolved before any image work.
+struct Target {
+    output_path: PathBuf,
+    format: OutputFormat,
+    /// Where `options.save_mask` writes the mask sidecar.
+    mask_path: Option<PathBuf>,
+    /// Where `options.icon.iconset_dir` puts the iconset for this input.
+    iconset_path: Option<PathBuf>,
+    /// Where `options.export_trimap` writes the trimap.
+    trimap_path: Option<PathBuf>,
+    /// Where and how `options.outputs` are written.
+    artifacts: Vec<ArtifactTarget>,
+    /// Format `options.split_instances` writes instances in.
+    instance_format: Option<OutputFormat>,
+    /// Set for animated inputs written as animations.
+    animation: Option<AnimationKind>,
+    /// Input file stem, naming the debug images.
+    stem: OsString,
+}
+
+/// Resolve the output path and format for an input read through `open`,
+/// refuse to overwrite earlier results, and create the output directory.
+fn prepare_target<I: BufRead + Seek>(
+    input: &Path,
+    input_file: &Path,
+    open: impl Fn() -> Result<I>,
+    output_path: Option<&Path>,
+    options: &RemoveBgOptions,
+) -> Result<Target> {
+    // Animated GIF/APNG inputs keep their container unless a format is chosen
+    let animation = animation::detect(open()?, &options.limits);
+    let default_format = animation.map_or(OutputFormat::default(), AnimationKind::output_format);
+
+    // Generate output path if not provided
+    let location = OutputLocation::of(output_path);
+    let (output_path, format) = resolve_output_path(input_file, location, default_format, options, &|_| false)?;
+
+    // The mask sidecar goes next to the output, named after the input
+    let keeps_animation = animation.is_some() && format.supports_animation();
+    let mask_path = if options.save_mask && keeps_animation {
+        log::warn!("{}: --save-mask does not apply to animations; no mask is written", input.display());
+        None
+    } else if options.save_mask {
+        let output_dir = output_path.parent().unwrap_or(Path::new(""));
+        Some(mask_sidecar_path(input_file, output_dir, options.mask_format)?)
+    } else {
+        None
+    };
+    if options.debug_output.is_some() && keeps_animation {
+        log::warn!("{}: --debug-output does not apply to animations; no debug images are written", input.display());
+    }
+    let stem = input_file.file_stem().unwrap_or_default().to_os_string();
+    let iconset_path = match &options.icon.iconset_dir {
+        Some(_) if keeps_animation => {
+            log::warn!("{}: --iconset-dir does not apply to animations; no iconset is written", input.display());
+            None
+        }
+        Some(dir) => Some(dir.join(paths::file_name([stem.as_os_str(), ".iconset".as_ref()]))),
+        None => None,
+    };
+
+    let trimap_path = match &options.export_trimap {
+        Some(_) if keeps_animation => {
+            log::warn!("{}: --export-trimap does not apply to animations; no trimap is written", input.display());
+            None
+        }
+        path => path.clone(),
+    };
+
+    let artifacts = match keeps_animation && !options.outputs.is_empty() {
+        true => {
+            log::warn!("{}: --emit does not apply to animations; only the main output is written", input.display());
+            Vec::new()
+        }
+        false => artifacts::resolve(input_file, &output_path, options)?,
+    };
+
+    let instance_format = match &options.split_instances {
+        Some(_) if keeps_animation => {
+            log::warn!("{}: --split-instances does not apply to animations; no instances are written", input.display());
+            None
+        }
+        Some(split) => {
+            instances::check(split)?;
+            Some(instances::format(format))
+        }
+        None => None,
+    };
+    let instance_paths = match (&options.split_instances, instance_format) {
+        (Some(split), Some(format)) => instances::candidate_paths(&output_path, format, split),
+        _ => Vec::new(),
+    };
+
+    // Refuse to clobber earlier results before doing any expensive work
+    let side_outputs = mask_path.iter().chain(&iconset_path).chain(&trimap_path);
+    let artifact_paths = artifacts.iter().map(|artifact| &artifact.path);
+    let mut claimed = HashMap::new();
+    for path in std::iter::once(&output_path).chain(side_outputs).chain(artifact_paths).chain(&instance_paths) {
+        // Case-insensitive, since macOS and Windows file systems usually are
+        if claimed.insert(path.to_string_lossy().to_lowercase(), ()).is_some() {
+            return Err(RemoveBgError::InvalidOutputFormat(format!(
+                "two outputs would both be written to {}",
+                path.display()
+            )));
+        }
+        if !replaces_existing(options) && path.exists() {
+            return Err(RemoveBgError::OutputExists(path.clone()));
+        }
+    }
+    output::prepare_output_dir(&output_path, options.create_dirs)?;
+    for path in trimap_path.iter().chain(artifacts.iter().map(|artifact| &artifact.path)) {
+        output::prepare_output_dir(path, options.create_dirs)?;
+    }
+
+    if animation.is_some() && !keeps_animation {
+        warn_first_frame_only(input, format);
+    }
+    Ok(Target {
+        output_path,
+        format,
+        mask_path,
+        iconset_path,
+        trimap_path,
+        artifacts,
+        instance_format,
+        animation: animation.filter(|_| keeps_animation),
+        stem,
+    })
+}
+
+/// Process an input read through `open` and save the result.
+///
+/// `input_file` names the input for the auto-generated output path; `open`
+/// must return a fresh reader at the start of the input on every call. The
+/// caller fills in the total duration.
+fn remove_to_file<I, R>(
+    input: &Path,
+    input_file: &Path,
+    open: impl Fn() -> Result<I>,
+    output_path: Option<&Path>,
+    options: &RemoveBgOptions,
+    mut durations: StageDurations,
+    load: impl FnOnce() -> Result<(R, bool)>,
+) -> Result<RemovalReport>
+where
+    I: BufRead + Seek,
+    R: Deref<Target = BackgroundRemover>,
+{
+    let count = page_count(&open, input_file)?;
+    let selected = pages::select(options.pages, count, input)?;
+    if count > 1 {
+        return remove_pages(input, input_file, open, output_path, options, durations, load, count, &selected);
+    }
+    let target = prepare_target(input, input_file, &open, output_path, options)?;
+
+    if let Some(kind) = target.animation {
+        let mut writer = output::AtomicFile::create(&target.output_path)?;
+        let animation = animation::remove_animation(
+            kind,
+            &open,
+            &mut writer,
+            target.format,
+            options,
+            &mut durations,
+            load,
+        )?;
+        writer.commit()?;
+        log::debug!("processed {} frames", animation.frames);
+
+        return Ok(RemovalReport {
+            output_path: target.output_path,
+            mask_path: None,
+            artifacts: Vec::new(),
+            instances: Vec::new(),
+            pages: None,
+            input_dimensions: animation.dimensions,
+            output_dimensions: animation.output_dimensions,
+            subject_bounds: None,
+            mask_coverage: animation.mask_coverage,
+            confidence: animation.confidence,
+            alpha_stats: None,
+            file_size: None,
+            background_color: animation.background,
+            premultiplied: options.premultiplied,
+            durations,
+            model: animation.model,
+            mask_cached: false,
+            mask_fast_path: None,
+            memory_estimate: None,
+        });
+    }
+
+    // Load the input image
+    let stage = Instant::now();
+    let (image, metadata) = load_input(memory::check_input(open()?, options)?, input, input_file, &options.limits)?;
+    check_reprocess(&image, input, options)?;
+    let input_dimensions = image.dimensions();
+    let image = limit_output_size(image, options).map_err(|e| e.in_stage(Stage::Decode))?;
+    durations.decode += stage.elapsed();
+
+    let processed = process_image(&image, options, &mut durations, load)?;
+    let mut report = write_output(input, image, &metadata, processed, target, options, durations)?;
+    report.input_dimensions = input_dimensions;
+    Ok(report)
+}
+
+/// [`remove_to_file`] for the `selected` pages of a TIFF with `count` pages.
+///
+/// Outputs named after the input are numbered like the page (`scan.tiff`
+/// gives `scan_p2_nobg.png`) unless only the first page is processed, and an
+/// output file is numbered when several pages are written to it. All
+/// outputs are resolved before the first page is decoded, and the model is
+/// loaded once for all pages.
+#[allow(clippy::too_many_arguments)]
+fn remove_pages<I, R>(
+    input: &Path,
+    input_file: &Path,
+    open: impl Fn() -> Result<I>,
+    output_path: Option<&Path>,
+    options: &RemoveBgOptions,
+    mut durations: StageDurations,
+    load: impl FnOnce() -> Result<(R, bool)>,
+    count: u32,
+    selected: &[u32],
+) -> Result<RemovalReport>
+where
+    I: BufRead + Seek,
+    R: Deref<Target = BackgroundRemover>,
+{
+    if options.pages == Pages::First {
+        log::warn!(
+            "{} has {} pages; only page 1 is processed (use --page N or --all-pages)",
+            input.display(),
+            count
+        );
+    }
+    let location = OutputLocation::of(output_path);
+    let mut targets = Vec::with_capacity(selected.len());
+    for &page in selected {
+        let (page_file, page_output) = match (options.pages, location) {
+            (Pages::First, _) => (input_file.to_path_buf(), output_path.map(Path::to_path_buf)),
+            (_, OutputLocation::File(path)) if selected.len() > 1 => {
+                (pages::numbered(input_file, page), Some(pages::numbered(path, page)))
+            }
+            _ => (pages::numbered(input_file, page), output_path.map(Path::to_path_buf)),
+        };
+        let target = prepare_target(input, &page_file, &open, page_output.as_deref(), options)?;
+        targets.push((page, target));
+    }
+
+    let loaded = load_once(options, &mut durations, load)?;
+    let mut report: Option<RemovalReport> = None;
+    let mut processed = Vec::with_capacity(targets.len());
+    for (page, target) in targets {
+        options.cancel.check()?;
+        let mut page_durations = StageDurations::default();
+        let stage = Instant::now();
+        let (image, metadata) = load_page(checked_page(open()?, page, options)?, page, input, input_file, &options.limits)?;
+        check_reprocess(&image, input, options)?;
+        let input_dimensions = image.dimensions();
+        let image = limit_output_size(image, options).map_err(|e| e.in_stage(Stage::Decode))?;
+        page_durations.decode = stage.elapsed();
+
+        let result = process_image(&image, options, &mut page_durations, || match &loaded {
+            Some((remover, downloaded)) => Ok((&**remover, *downloaded)),
+            None => Err(RemoveBgError::ModelInitError("Model not loaded".into())),
+        })?;
+        let mut page_report = write_output(input, image, &metadata, result, target, options, page_durations)?;
+        page_report.input_dimensions = input_dimensions;
+        processed.push(PageOutput { page, output_path: page_report.output_path.clone() });
+        match &mut report {
+            Some(report) => report.durations += page_report.durations,
+            None => report = Some(page_report),
+        }
+    }
+
+    // Selecting pages always yields at least one
+    let mut report = report.expect("a page was processed");
+    durations += report.durations;
+    report.durations = durations;
+    report.pages = Some(PageSummary { count, processed });
+    Ok(report)
+}
+
+/// Whether writing the outputs of a processed image still needs the decoded
+/// input, for instances, overlay outputs or debug images.
+fn keeps_input(options: &RemoveBgOptions) -> bool {
+    options.split_instances.is_some() || artifacts::needs_input(options) || options.debug_output.is_some()
+}
+
+/// Save a processed image of the decoded input `image` (and its mask sidecar
+/// and debug images, as enabled) to `target` and report on it.
+///
+/// The input is dropped before encoding unless a side output needs it, so it
+/// isn't held alongside the output image while the file is written.
+fn write_output(
+    input: &Path,
+    image: DynamicImage,
+    metadata: &Metadata,
+    processed: Processed,
+    target: Target,
+    options: &RemoveBgOptions,
+    mut durations: StageDurations,
+) -> Result<RemovalReport> {
+    let input_dimensions = image.dimensions();
+    let image = keeps_input(options).then_some(image);
+
+    // Save in the selected format
+    options.cancel.check()?;
+    enter_span!("encode", format = %target.format, output = %target.output_path.display());
+    let stage = Instant::now();
+    let saved = (|| {
+        disk_space::check_output(&processed.image, &target.output_path, target.format, options)?;
+        let file_size = match options.max_file_size {
+            Some(limit) => Some(file_size::save_within(
+                &processed.image,
+                &target.output_path,
+                target.format,
+                options,
+                metadata,
+                limit,
+            )?),
+            None => {
+                output::save_image(&processed.image, &target.output_path, target.format, options, metadata)?;
+                None
+            }
+        };
+        if let (Some(mask), Some(path)) = (&processed.raw_mask, &target.mask_path) {
+            match options.mask_format {
+                MaskFormat::Png => output::save_mask(mask, path)?,
+                MaskFormat::Npy => npy::write_mask(mask, path)?,
+            }
+        }
+        if let Some(dir) = &target.iconset_path {
+            icon::write_iconset(&processed.image, dir, &options.icon)?;
+        }
+        if let (Some(trimap), Some(path)) = (&processed.trimap, &target.trimap_path) {
+            output::save_trimap(trimap, path)?;
+        }
+        let instances = match (&options.split_instances, target.instance_format, &processed.mask, &image) {
+            (Some(split), Some(format), Some(mask), Some(image)) => {
+                instances::write(image, mask, &target.output_path, format, metadata, options, split)?
+            }
+            _ => Vec::new(),
+        };
+        Ok((file_size, instances))
+    })();
+    let (file_size, instances) = saved.map_err(|e: RemoveBgError| e.in_stage(Stage::Encode))?;
+    trace_event!("encoded", bytes = std::fs::metadata(&target.output_path).map_or(0, |metadata| metadata.len()));
+    let sources = artifacts::Sources {
+        input: image.as_ref(),
+        cutout: Some(processed.cutout.as_ref().unwrap_or(&processed.image)),
+        mask: processed.mask.as_ref(),
+        background: processed.background,
+    };
+    let artifacts = artifacts::write(target.artifacts, &sources, metadata, options);
+    for artifact in &artifacts {
+        if let Some(error) = &artifact.error {
+            log::warn!("{}: failed to write {} output {}: {}", input.display(), artifact.kind, artifact.path.display(), error);
+        }
+    }
+    durations.encode = stage.elapsed();
+
+    // Debug images are a side product; failing to write them doesn't fail the run
+    if let (Some(dir), Some(masks), Some(image)) = (&options.debug_output, &processed.debug_masks, &image) {
+        if let Err(e) = preview::write_debug_images(dir, &target.stem, image, masks, &processed.image) {
+            log::warn!("{}: failed to write debug images to {}: {}", input.display(), dir.display(), e);
+        }
+    }
+
+    Ok(RemovalReport {
+        output_path: target.output_path,
+        mask_path: target.mask_path,
+        artifacts,
+        instances,
+        pages: None,
+        input_dimensions,
+        output_dimensions: file_size.map_or(processed.image.dimensions(), |fit| fit.dimensions),
+        subject_bounds: processed.subject,
+        mask_coverage: processed.mask_coverage,
+        confidence: processed.confidence,
+        alpha_stats: processed.alpha_stats,
+        file_size,
+        background_color: processed.background,
+        premultiplied: options.premultiplied,
+        durations,
+        model: processed.model,
+        mask_cached: processed.mask_cached,
+        mask_fast_path: processed.mask_fast_path,
+        memory_estimate: processed.memory_estimate,
+    })
+}
+
+/// Remove the background from several image files with `remover`, like
+/// [`remove_file`] for each `(input, output)` pair, running the model on all
+/// still images in one batch. Animations are processed on their own.
+///
+/// Returns one result per pair, in order. The total duration of each report
+/// covers its own stages, including its share of the batched model run.
+pub(crate) fn remove_files(
+    jobs: &[(&Path, Option<&Path>)],
+    options: &RemoveBgOptions,
+    remover: &BackgroundRemover,
+) -> Vec<Result<RemovalReport>> {
+    if let [(input_file, output_path)] = jobs {
+        return vec![remove_file(input_file, *output_path, options, || Ok((remover, false)))];
+    }
+    let mut results: Vec<Option<Result<RemovalReport>>> = jobs.iter().map(|_| None).collect();
+    // Decoded still images waiting for the batched model run, which share
+    // one timeout; inputs processed on their own get their own
+    let mut pending = Vec::new();
+    let timed = &options.timed();
+
+    for (index, &(input_file, output_path)) in jobs.iter().enumerate() {
+        let prepared = error::catch_panic(|| {
+            let file = checked_input(input_file)?;
+            let open = || Ok(BufReader::new(File::open(&file)?));
+            // Multi-page inputs are processed on their own too
+            if page_count(open, input_file)? > 1 {
+                return Ok(None);
+            }
+            let target = prepare_target(input_file, input_file, open, output_path, timed)?;
+            if target.animation.is_some() {
+                return Ok(None);
+            }
+            let mut durations = StageDurations::default();
+            let stage = Instant::now();
+            let (image, metadata) = load_input(memory::check_input(open()?, timed)?, input_file, input_file, &timed.limits)?;
+            check_reprocess(&image, input_file, timed)?;
+            let input_dimensions = image.dimensions();
+            let image = limit_output_size(image, timed).map_err(|e| e.in_stage(Stage::Decode))?;
+            durations.decode = stage.elapsed();
+            Ok(Some((target, image, metadata, input_dimensions, durations)))
+        });
+        match prepared {
+            Ok(Some(decoded)) => pending.push((index, decoded)),
+            Ok(None) => results[index] = Some(remove_file(input_file, output_path, options, || Ok((remover, false)))),
+            Err(e) => results[index] = Some(Err(RemoveBgError::for_input(e, input_file))),
+        }
+    }
+
+    let images: Vec<&DynamicImage> = pending.iter().map(|(_, (_, image, _, _, _))| image).collect();
+    let mut durations: Vec<StageDurations> = pending.iter().map(|(_, (_, _, _, _, durations))| *durations).collect();
+    let processed = process_images(&images, timed, remover, &mut durations);
+    for (((index, (target, image, metadata, input_dimensions, _)), processed), durations) in
+        pending.into_iter().zip(processed).zip(durations)
+    {
+        let result = processed
+            .and_then(|processed| {
+                error::catch_panic(|| write_output(jobs[index].0, image, &metadata, processed, target, timed, durations))
+            })
+            .map_err(|e| e.for_input(jobs[index].0));
+        results[index] = Some(result.map(|mut report| {
+            report.input_dimensions = input_dimensions;
+            let d = &report.durations;
+            report.durations.total = d.decode + d.preprocess + d.inference + d.postprocess + d.composite + d.encode;
+            report
+        }));
+    }
+
+    results.into_iter().map(|result| result.expect("every job has a result")).collect()
+}
+
+/// Remove background from an in-memory encoded image.
+///
+/// Works like [`remove_background_with_options`] without touching the
+/// filesystem: `data` holds the encoded input image (any supported format) and
+/// the result is returned as encoded bytes in `options.format` (PNG if unset).
+///
+/// # Arguments
+/// * `data` - Encoded input image bytes.
+/// * `options` - Options controlling model execution and output encoding.
+///
+/// # Returns
+/// The encoded output image.
+///
+/// # Errors
+/// * `ImageError` - If the bytes cannot be decoded as an image
+/// * `ModelError` - If model inference fails
+/// * `InvalidOutputFormat` - If the output format cannot store transparency
+///
+/// # Examples
+/// ```no_run
+/// use removebg::{remove_background_from_bytes, RemoveBgOptions};
+///
+/// let input = std::fs::read("photo.jpg")?;
+/// let png = remove_background_from_bytes(&input, &RemoveBgOptions::default())?;
+/// std::fs::write("photo_nobg.png", png)?;
+/// # Ok::<(), removebg::error::RemoveBgError>(())
+/// ```
+pub fn remove_background_from_bytes(data: &[u8], options: &RemoveBgOptions) -> Result<Vec<u8>> {
+    remove_bytes(data, options, || shared_remover(options))
+}
+
+/// In-memory pipeline shared by the free functions and [`BackgroundRemover`].
+pub(crate) fn remove_bytes<R: Deref<Target = BackgroundRemover>>(
+    data: &[u8],
+    options: &RemoveBgOptions,
+    load: impl FnOnce() -> Result<(R, bool)>,
+) -> Result<Vec<u8>> {
+    remove_bytes_detailed(data, options, load).map(|(encoded, _)| encoded)
+}
+
+/// [`remove_bytes`], also returning the model the image ran with, as
+/// [`remove_bytes_to_writer`] does.
+pub(crate) fn remove_bytes_detailed<R: Deref<Target = BackgroundRemover>>(
+    data: &[u8],
+    options: &RemoveBgOptions,
+    load: impl FnOnce() -> Result<(R, bool)>,
+) -> Result<(Vec<u8>, Option<ModelInfo>)> {
+    let mut encoded = Vec::new();
+    let model = remove_bytes_to_writer(data, &mut encoded, options, load)?;
+    Ok((encoded, model))
+}
+
+/// Remove the background from an in-memory encoded image and encode the
+/// result into `writer`, as [`remove_bytes`] does into a vector. Returns
+/// the model a still image ran with; `None` for animations and when no
+/// model ran.
+pub(crate) fn remove_bytes_to_writer<W: Write, R: Deref<Target = BackgroundRemover>>(
+    data: &[u8],
+    writer: &mut W,
+    options: &RemoveBgOptions,
+    load: impl FnOnce() -> Result<(R, bool)>,
+) -> Result<Option<ModelInfo>> {
+    let options = &options.timed();
+    error::catch_panic(|| {
+        enter_span!("process", bytes = data.len());
+        let format = output::selected_format(options, OutputFormat::default());
+        check_transparency(format, Selection::of(options), options)?;
+
+        if let Some(kind) = animation::detect(Cursor::new(data), &options.limits) {
+            if format.supports_animation() {
+                animation::remove_animation(
+                    kind,
+                    || Ok(Cursor::new(data)),
+                    &mut *writer,
+                    format,
+                    options,
+                    &mut StageDurations::default(),
+                    load,
+                )?;
+                return Ok(None);
+            }
+            warn_first_frame_only(Path::new("input"), format);
+        }
+
+        let decode = |e: RemoveBgError| e.in_stage(Stage::Decode);
+        let (image, metadata) = {
+            enter_span!("decode", input = IN_MEMORY_INPUT);
+            let (image, metadata) =
+                decode_image(memory::check_input(Cursor::new(data), options)?, Path::new(IN_MEMORY_INPUT), &options.limits)
+                    .map_err(decode)?;
+            trace_event!("decoded", width = image.width(), height = image.height());
+            (image, metadata)
+        };
+        let image = limit_output_size(image, options).map_err(decode)?;
+        let processed = process_image(&image, options, &mut StageDurations::default(), load)?;
+        drop(image);
+
+        options.cancel.check()?;
+        enter_span!("encode", format = %format);
+        let encode = |e: RemoveBgError| e.in_stage(Stage::Encode);
+        if let Some(limit) = options.max_file_size {
+            let (encoded, _) =
+                file_size::encode_within(&processed.image, format, options, &metadata, limit).map_err(encode)?;
+            writer.write_all(&encoded).map_err(|e| RemoveBgError::from(e).in_stage(Stage::Encode))?;
+            return Ok(processed.model);
+        }
+        output::encode_stream(&processed.image, &mut *writer, format, options, &metadata).map_err(encode)?;
+        writer.flush().map_err(|e| RemoveBgError::from(e).in_stage(Stage::Encode))?;
+        Ok(processed.model)
+    })
+}
+
+/// Remove the background from an image file and encode the result into
+/// `writer`.
+///
+/// Works like [`remove_background_with_options`], but instead of saving a
+/// file, the result is encoded in `options.format` (PNG if unset) and written
+/// to `writer` as it is encoded. The decoded input is dropped before encoding
+/// starts, and PNG output is compressed row by row and written in chunks, so
+/// peak memory is about the input and output images while processing, and
+/// the output image alone while encoding. Side outputs such as a mask sidecar
+/// or `--emit` outputs are not written.
+///
+/// Wrap unbuffered writers such as a [`File`] in a [`BufWriter`](std::io::BufWriter).
+///
+/// # Arguments
+/// * `input_path` - Path to the input image file.
+/// * `writer` - Where the encoded output goes.
+/// * `options` - Options controlling model execution and output encoding.
+///
+/// # Errors
+/// * `IoError` - If writing to `writer` fails
+/// * Otherwise the same as [`remove_background_from_bytes`]
+///
+/// # Examples
+/// ```no_run
+/// use removebg::{remove_background_to_writer, RemoveBgOptions};
+///
+/// let mut stdout = std::io::stdout().lock();
+/// remove_background_to_writer("panorama.jpg", &mut stdout, &RemoveBgOptions::default())?;
+/// # Ok::<(), removebg::error::RemoveBgError>(())
+/// ```
+pub fn remove_background_to_writer(
+    input_path: impl AsRef<Path>,
+    writer: &mut impl Write,
+    options: &RemoveBgOptions,
+) -> Result<()> {
+    let input_path = input_path.as_ref();
+    remove_to_writer(input_path, writer, options, || shared_remover(options))
+}
+
+/// Streaming pipeline shared by the free functions and [`BackgroundRemover`].
+pub(crate) fn remove_to_writer<W: Write, R: Deref<Target = BackgroundRemover>>(
+    input_file: &Path,
+    writer: &mut W,
+    options: &RemoveBgOptions,
+    load: impl FnOnce() -> Result<(R, bool)>,
+) -> Result<()> {
+    let options = &options.timed();
+    error::catch_panic(|| {
+        enter_span!("process", input = %input_file.display());
+        let format = output::selected_format(options, OutputFormat::default());
+        check_transparency(format, Selection::of(options), options)?;
+        let file = checked_input(input_file)?;
+        let open = || Ok(BufReader::new(File::open(&file)?));
+
+        if let Some(kind) = animation::detect(open()?, &options.limits) {
+            if format.supports_animation() {
+                let mut durations = StageDurations::default();
+                animation::remove_animation(kind, open, &mut *writer, format, options, &mut durations, load)?;
+                return Ok(());
+            }
+            warn_first_frame_only(input_file, format);
+        }
+
+        let count = page_count(open, input_file)?;
+        let page = match pages::select(options.pages, count, input_file)?[..] {
+            [page] if count == 1 || options.pages != Pages::First => page,
+            _ => {
+                log::warn!("{} has {} pages; only page 1 is written", input_file.display(), count);
+                1
+            }
+        };
+        let (image, metadata) = load_page(checked_page(open()?, page, options)?, page, input_file, input_file, &options.limits)?;
+        check_reprocess(&image, input_file, options)?;
+        let image = limit_output_size(image, options).map_err(|e| e.in_stage(Stage::Decode))?;
+        let processed = process_image(&image, options, &mut StageDurations::default(), load)?;
+        drop(image);
+
+        options.cancel.check()?;
+        enter_span!("encode", format = %format);
+        let encode = |e: RemoveBgError| e.in_stage(Stage::Encode);
+        if let Some(limit) = options.max_file_size {
+            let (encoded, _) =
+                file_size::encode_within(&processed.image, format, options, &metadata, limit).map_err(encode)?;
+            return writer.write_all(&encoded).map_err(|e| RemoveBgError::from(e).in_stage(Stage::Encode));
+        }
+        output::encode_stream(&processed.image, &mut *writer, format, options, &metadata).map_err(encode)?;
+        writer.flush().map_err(|e| RemoveBgError::from(e).in_stage(Stage::Encode))
+    })
+    .map_err(|e: RemoveBgError| e.for_input(input_file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, Rgb32FImage, RgbImage, RgbaImage};
+    use ndarray::{ArrayD, IxDyn};
+
+    /// SplitMix64 values for random test images.
+    fn random(seed: u64) -> impl FnMut() -> u64 {
+        let mut state = seed;
+        move || {
+            state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            z ^ (z >> 31)
+        }
+    }
+
+    type Rgba16Image = ImageBuffer<Rgba<u16>, Vec<u16>>;
+
+    const SIZES: [(u32, u32); 4] = [(1, 1), (7, 3), (64, 48), (333, 17)];
+
+    fn random_mask(seed: u64, width: u32, height: u32) -> Gray16Image {
+        let mut next = random(seed);
+        Gray16Image::from_fn(width, height, |_, _| Luma([next() as u16]))
+    }
+
+    fn random_rgba16(seed: u64, width: u32, height: u32) -> Rgba16Image {
+        let mut next = random(seed);
+        Rgba16Image::from_fn(width, height, |_, _| Rgba(std::array::from_fn(|_| next() as u16)))
+    }
+
+    // The per-pixel loops the slice-based versions replaced
+
+    fn naive_preprocess(image: &DynamicImage, size: u32) -> Array4<f32> {
+        let rgb = image.resize_exact(size, size, pipeline::input_filter(image, size)).to_rgb8();
+        let mut input = Array4::zeros((1, 3, size as usize, size as usize));
+        for (y, row) in rgb.rows().enumerate() {
+            for (x, pixel) in row.enumerate() {
+                input[[0, 0, y, x]] = pixel[0] as f32 / 255.0;
+                input[[0, 1, y, x]] = pixel[1] as f32 / 255.0;
+                input[[0, 2, y, x]] = pixel[2] as f32 / 255.0;
+            }
+        }
+        input
+    }
+
+    fn naive_masks(outputs: &[ArrayViewD<'_, f32>], weights: &[f32], options: &RemoveBgOptions) -> Vec<Gray16Image> {
+        let shape = outputs[0].shape();
+        (0..shape[0])
+            .map(|b| {
+                Gray16Image::from_fn(shape[3] as u32, shape[2] as u32, |x, y| {
+                    let (x, y) = (x as usize, y as usize);
+                    let value: f32 = outputs.iter().zip(weights).map(|(stage, w)| stage[[b, 0, y, x]] * w).sum();
+                    let value = matte::mask_curve(value.clamp(0.0, 1.0), options);
+                    Luma([(value * 65535.0).round() as u16])
+                })
+            })
+            .collect()
+    }
+
+    fn naive_masked<S: Channel>(mut image: ImageBuffer<Rgba<S>, Vec<S>>, mask: &Gray16Image) -> ImageBuffer<Rgba<S>, Vec<S>>
+    where
+        Rgba<S>: Pixel<Subpixel = S>,
+    {
+        for (y, row) in image.rows_mut().enumerate() {
+            for (x, pixel) in row.enumerate() {
+                pixel[3] = S::from_mask(mask.get_pixel(x as u32, y as u32)[0]);
+            }
+        }
+        for (pixel, alpha) in image.pixels_mut().zip(mask.iter()) {
+            let alpha = *alpha as f32 / 65535.0;
+            for channel in &mut pixel.0[..3] {
+                *channel = S::from_unit(channel.to_unit() * alpha);
+            }
+        }
+        image
+    }
+
+    fn naive_flatten<S: Channel>(image: &mut ImageBuffer<Rgba<S>, Vec<S>>, background: impl Fn(u32, u32) -> [f32; 3])
+    where
+        Rgba<S>: Pixel<Subpixel = S>,
+    {
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            let alpha = pixel[3].to_unit();
+            let background = background(x, y);
+            for c in 0..3 {
+                pixel[c] = S::from_unit(pixel[c].to_unit() * alpha + background[c] * (1.0 - alpha));
+            }
+            pixel[3] = S::DEFAULT_MAX_VALUE;
+        }
+    }
+
+    #[test]
+    fn preprocess_matches_naive_loop() {
+        for (seed, (width, height)) in SIZES.into_iter().enumerate() {
+            let mut next = random(seed as u64);
+            let image = DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |_, _| Rgb(std::array::from_fn(|_| next() as u8))));
+            let spec = Model::U2netp.spec();
+            assert_eq!(pipeline::preprocess(&image, spec), naive_preprocess(&image, spec.input_size), "{}x{}", width, height);
+        }
+    }
+
+    #[test]
+    fn mask_construction_matches_naive_loop() {
+        let options = RemoveBgOptions { mask_fusion: Fusion::Weighted(vec![0.5, 0.3, 0.2]), mask_gamma: Some(1.3), ..Default::default() };
+        let weights = options.mask_fusion.weights(3).unwrap();
+        for (seed, (width, height)) in SIZES.into_iter().enumerate() {
+            let mut next = random(seed as u64);
+            let shape = IxDyn(&[2, 1, height as usize, width as usize]);
+            let mut stages: Vec<ArrayD<f32>> =
+                (0..3).map(|_| ArrayD::from_shape_simple_fn(shape.clone(), || (next() >> 40) as f32 / (1 << 23) as f32 - 0.5)).collect();
+            // Model outputs aren't always in standard layout
+            stages[1] = stages[1].t().as_standard_layout().t().to_owned();
+            let views: Vec<_> = stages.iter().map(|stage| stage.view()).collect();
+            let masks = masks_from_outputs(&views, 2, &options).unwrap();
+            assert!(masks.iter().flat_map(|mask| mask.iter()).all(|value| (0.0..=1.0).contains(value)));
+            let masks: Vec<Gray16Image> = masks.iter().map(quantize_mask).collect();
+            assert_eq!(masks, naive_masks(&views, &weights, &options), "{}x{}", width, height);
+        }
+    }
+
+    #[test]
+    fn fused_masks_differ_from_the_first_output() {
+        // Three side outputs: a sharp square, a softer one, and a coarse blob
+        let stage = |radius: f32| ArrayD::from_shape_fn(IxDyn(&[1, 1, 32, 32]), |index| {
+            let distance = (index[2] as f32 - 15.5).abs().max((index[3] as f32 - 15.5).abs());
+            (1.0 - distance / radius).clamp(0.0, 1.0)
+        });
+        let stages = [stage(8.0), stage(12.0), stage(20.0)];
+        let views: Vec<_> = stages.iter().map(|stage| stage.view()).collect();
+        let mask = |mask_fusion| masks_from_outputs(&views, 1, &RemoveBgOptions { mask_fusion, ..Default::default() }).unwrap().remove(0);
+
+        let first = mask(Fusion::First);
+        assert_eq!(first, mask(Fusion::Weighted(vec![1.0, 0.0, 0.0])));
+        for fusion in [Fusion::Mean(3), Fusion::Weighted(vec![0.5, 0.3, 0.2])] {
+            let fused = mask(fusion.clone());
+            assert_ne!(fused, first, "{}", fusion);
+            // The coarser stages widen the mask around the square
+            assert!(fused.get_pixel(15, 4)[0] > first.get_pixel(15, 4)[0], "{}", fusion);
+        }
+    }
+
+    #[test]
+    fn fusion_weights_must_match_the_outputs() {
+        let stages: Vec<ArrayD<f32>> = (0..3).map(|_| ArrayD::from_elem(IxDyn(&[1, 1, 4, 4]), 0.5)).collect();
+        let views: Vec<_> = stages.iter().map(|stage| stage.view()).collect();
+        for weights in [vec![0.5, 0.5], vec![0.4, 0.3, 0.2, 0.1]] {
+            let options = RemoveBgOptions { mask_fusion: Fusion::Weighted(weights.clone()), ..Default::default() };
+            let error = masks_from_outputs(&views, 1, &options).unwrap_err();
+            assert!(error.to_string().contains("but the model has 3 outputs"), "{:?}: {}", weights, error);
+            // A single-output model ignores the weights
+            assert!(masks_from_outputs(&views[..1], 1, &options).is_ok());
+        }
+    }
+
+    #[test]
+    fn masks_are_read_from_any_output_shape() {
+        let options = RemoveBgOptions::default();
+        let plane = ArrayD::from_shape_fn(IxDyn(&[6, 8]), |index| (index[0] * 8 + index[1]) as f32 / 47.0);
+        let expected = masks_from_outputs(&[plane.view().insert_axis(Axis(0)).insert_axis(Axis(0))], 1, &options).unwrap();
+        for shape in [&[6, 8][..], &[1, 6, 8], &[1, 1, 6, 8], &[1, 1, 1, 6, 8]] {
+            let output = plane.to_shape(IxDyn(shape)).unwrap();
+            assert_eq!(masks_from_outputs(&[output.view()], 1, &options).unwrap(), expected, "{:?}", shape);
+        }
+
+        // Further channels are dropped, with or without a batch axis
+        let noise = plane.mapv(|value| 1.0 - value);
+        let channels = ndarray::stack(Axis(0), &[plane.view(), noise.view(), noise.view()]).unwrap();
+        assert_eq!(masks_from_outputs(&[channels.view()], 1, &options).unwrap(), expected);
+        let batched = channels.view().insert_axis(Axis(0));
+        assert_eq!(masks_from_outputs(&[batched], 1, &options).unwrap(), expected);
+
+        // (n, h, w) batches
+        let batch = ndarray::stack(Axis(0), &[plane.view(), noise.view()]).unwrap();
+        let masks = masks_from_outputs(&[batch.view()], 2, &options).unwrap();
+        assert_eq!((masks.len(), &masks[..1]), (2, &expected[..]));
+    }
+
+    #[test]
+    fn unexpected_output_shapes_are_model_errors() {
+        let options = RemoveBgOptions::default();
+        for (shape, batch, message) in [
+            (&[1, 2, 3, 6, 8][..], 1, "expected a 2D mask, got shape [1, 2, 3, 6, 8]"),
+            (&[320], 1, "expected a 2D mask, got shape [320]"),
+            (&[1, 1, 0, 8], 1, "expected a 2D mask, got shape [1, 1, 0, 8]"),
+            (&[6, 8], 2, "model returned 1 mask for a batch of 2 images"),
+            (&[3, 1, 6, 8], 2, "model returned 3 masks for a batch of 2 images"),
+        ] {
+            let output = ArrayD::<f32>::zeros(IxDyn(shape));
+            match masks_from_outputs(&[output.view()], batch, &options) {
+                Err(RemoveBgError::ModelError(error)) => assert_eq!(error, message),
+                other => panic!("{:?}: {:?}", shape, other.map(|masks| masks.len())),
+            }
+        }
+    }
+
+    #[test]
+    fn logits_get_a_sigmoid_only_for_models_that_may_return_them() {
+        let logits = ArrayD::from_shape_vec(IxDyn(&[1, 1, 1, 3]), vec![-4.0, 0.0, 4.0]).unwrap();
+        let alpha = |model: Model, output: &ArrayD<f32>| {
+            let options = RemoveBgOptions { model, ..Default::default() };
+            masks_from_outputs(&[output.view()], 1, &options).unwrap()[0].iter().map(|&v| (v * 255.0).round() as u8).collect::<Vec<_>>()
+        };
+        assert_eq!(alpha(Model::BirefnetLite, &logits), [5, 128, 250]);
+        // Other models clamp
+        assert_eq!(alpha(Model::U2net, &logits), [0, 0, 255]);
+        // Probabilities, rounding included, are left alone
+        let probabilities = ArrayD::from_shape_vec(IxDyn(&[1, 1, 1, 3]), vec![0.0, 0.5, 1.0005]).unwrap();
+        assert_eq!(alpha(Model::BirefnetLite, &probabilities), [0, 128, 255]);
+    }
+
+    #[test]
+    fn mask_application_matches_naive_loop() {
+        let options = RemoveBgOptions { premultiplied: true, ..Default::default() };
+        for (seed, (width, height)) in SIZES.into_iter().enumerate() {
+            let seed = seed as u64;
+            let mask = random_mask(seed, width, height);
+            let image = random_rgba16(seed + 100, width, height);
+            assert_eq!(masked(image.clone(), &mask, &options).unwrap(), naive_masked(image.clone(), &mask));
+            let image = DynamicImage::ImageRgba16(image).into_rgba8();
+            assert_eq!(masked(image.clone(), &mask, &options).unwrap(), naive_masked(image, &mask));
+        }
+    }
+
+    #[test]
+    fn premultiplied_colors_never_exceed_alpha() {
+        let image = DynamicImage::ImageLuma8(GrayImage::from_pixel(40, 4, Luma([200])));
+        let mask = DynamicImage::ImageLuma8(GrayImage::from_fn(40, 4, |x, _| Luma([(x * 8).min(255) as u8])));
+        let options = RemoveBgOptions { premultiplied: true, ..Default::default() };
+        let cutout = apply_external_mask(&image, &mask, &options).unwrap().into_rgba8();
+        assert!(cutout.pixels().any(|pixel| (1..255).contains(&pixel[3])));
+        for pixel in cutout.pixels() {
+            let [red, green, blue, alpha] = pixel.0;
+            assert!(red.max(green).max(blue) <= alpha, "{:?}", pixel);
+            assert!((red as f32 - 200.0 * alpha as f32 / 255.0).abs() <= 1.0, "{:?}", pixel);
+        }
+        // Opaque pixels keep their color
+        assert_eq!(cutout.get_pixel(39, 0).0, [200, 200, 200, 255]);
+    }
+
+    #[test]
+    fn premultiplied_alpha_needs_a_transparent_output() {
+        let premultiplied = RemoveBgOptions { premultiplied: true, ..Default::default() };
+        assert!(check_transparency(OutputFormat::Png, Selection::Default, &premultiplied).is_ok());
+        let white = RemoveBgOptions { background: Background::Color([255, 255, 255]), ..premultiplied.clone() };
+        let sticker = RemoveBgOptions { sticker: Some(Default::default()), ..premultiplied.clone() };
+        for (format, options) in [(OutputFormat::Png, &white), (OutputFormat::Jpeg, &premultiplied), (OutputFormat::Png, &sticker)] {
+            let error = check_transparency(format, Selection::Default, options).unwrap_err();
+            assert!(matches!(error, RemoveBgError::InvalidOutputFormat(_)) && error.to_string().contains("premultiplied"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn alpha_mask_keeps_the_input_depth() {
+        let options = RemoveBgOptions { decontaminate: Some(0.8), ..Default::default() };
+        let (width, height) = (37, 11);
+        let mask = random_mask(5, width, height);
+        let rgba16 = random_rgba16(6, width, height);
+        let rgba8 = DynamicImage::ImageRgba16(rgba16.clone()).into_rgba8();
+
+        // 8-bit inputs: the same bytes as masking their RGBA8 conversion,
+        // borrowed or owned
+        let eight_bit = [
+            DynamicImage::ImageRgba8(rgba8.clone()),
+            DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(rgba8.clone()).into_rgb8()),
+            DynamicImage::ImageLuma8(DynamicImage::ImageRgba8(rgba8.clone()).into_luma8()),
+            DynamicImage::ImageLumaA8(DynamicImage::ImageRgba8(rgba8).into_luma_alpha8()),
+        ];
+        for image in eight_bit {
+            let expected = DynamicImage::ImageRgba8(masked(image.to_rgba8(), &mask, &options).unwrap());
+            assert_eq!(apply_alpha_mask(&image, &mask, &options).unwrap(), expected, "{:?}", image.color());
+            assert_eq!(apply_alpha_mask_owned(image, &mask, &options).unwrap(), expected);
+        }
+
+        // 16-bit inputs keep their low bits
+        let image = DynamicImage::ImageRgb16(DynamicImage::ImageRgba16(rgba16).into_rgb16());
+        let cutout = apply_alpha_mask(&image, &mask, &RemoveBgOptions::default()).unwrap();
+        let cutout = cutout.as_rgba16().expect("16-bit output");
+        for ((pixel, input), alpha) in cutout.pixels().zip(image.as_rgb16().unwrap().pixels()).zip(mask.iter()) {
+            assert_eq!(pixel.0, [input[0], input[1], input[2], *alpha]);
+        }
+        let owned = apply_alpha_mask_owned(DynamicImage::ImageRgba16(cutout.clone()), &mask, &RemoveBgOptions::default()).unwrap();
+        assert_eq!(owned.as_rgba16(), Some(cutout));
+
+        // Floats are scaled to 16 bits, clipping out-of-range values
+        let floats = [0.0, 0.5, 1.0, 1.7, -0.3, f32::NAN, 0.25, 1e-6];
+        let image = DynamicImage::ImageRgb32F(Rgb32FImage::from_fn(8, 1, |x, _| Rgb([floats[x as usize]; 3])));
+        let mask = Gray16Image::from_pixel(8, 1, Luma([40000]));
+        let cutout = apply_alpha_mask(&image, &mask, &RemoveBgOptions::default()).unwrap();
+        let values: Vec<[u16; 4]> = cutout.as_rgba16().expect("16-bit output").pixels().map(|pixel| pixel.0).collect();
+        let expected = [0, 32768, 65535, 65535, 0, 0, 16384, 0];
+        assert_eq!(values, expected.map(|value| [value, value, value, 40000]));
+    }
+
+    /// A PNG claiming `width`x`height` pixels whose image data is an empty
+    /// zlib stream.
+    fn png_header(width: u32, height: u32, depth: png::BitDepth) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut data, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(depth);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_chunk(png::chunk::IDAT, &[0x78, 0x01, 0x03, 0x00, 0x00, 0x00, 0x00, 0x01]).unwrap();
+        drop(writer);
+        data
+    }
+
+    #[test]
+    fn huge_headers_fail_before_any_pixels_are_decoded() {
+        let input = Path::new("huge.png");
+        let decode = |data: Vec<u8>, limits: &DecodeLimits| decode_image(Cursor::new(data), input, limits).map(|_| ());
+        let huge = png_header(30_000, 30_000, png::BitDepth::Eight);
+
+        match decode(huge.clone(), &DecodeLimits::default()) {
+            Err(RemoveBgError::ImageTooLarge { width: 30_000, height: 30_000, limit: 80_000_000 }) => {}
+            other => panic!("{:?}", other),
+        }
+        // The memory limit in pixels of the decoded size
+        let memory_only = DecodeLimits { max_pixels: None, ..Default::default() };
+        match decode(huge, &memory_only) {
+            Err(RemoveBgError::ImageTooLarge { limit, .. }) => assert_eq!(limit, (1 << 30) / 4),
+            other => panic!("{:?}", other),
+        }
+        match decode(png_header(12_000, 12_000, png::BitDepth::Sixteen), &memory_only) {
+            Err(RemoveBgError::ImageTooLarge { limit, .. }) => assert_eq!(limit, (1 << 30) / 8),
+            other => panic!("{:?}", other),
+        }
+
+        // Within the limits, the missing pixel data is the problem
+        match decode(png_header(100, 100, png::BitDepth::Eight), &DecodeLimits::default()) {
+            Err(RemoveBgError::ImageTooLarge { .. }) | Ok(()) => panic!("a header alone decoded"),
+            Err(_) => {}
+        }
+    }
+
+    #[test]
+    fn inputs_are_decoded_by_content_not_extension() {
+        let mut jpeg = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(24, 16, Rgb([200, 100, 50]))).write_to(&mut Cursor::new(&mut jpeg), image::ImageFormat::Jpeg).unwrap();
+        assert_eq!(sniff_format(&jpeg, Path::new("photo.png")).unwrap(), image::ImageFormat::Jpeg);
+        let (image, _) = decode_image(Cursor::new(jpeg), Path::new("photo.png"), &DecodeLimits::default()).unwrap();
+        assert_eq!((image.width(), image.height()), (24, 16));
+
+        for (data, expected) in [(&b""[..], Some("empty file")), (b"%PDF-1.4\n", Some("PDF")), (b"Just some notes\n", Some("text")), (b"\x00\x9f\x92\x96", None)] {
+            match decode_image(Cursor::new(data.to_vec()), Path::new("photo.png"), &DecodeLimits::default()) {
+                Err(RemoveBgError::UnsupportedFormat { path, detected }) => {
+                    assert_eq!(path, Path::new("photo.png"));
+                    assert_eq!(detected.as_deref(), expected);
+                }
+                other => panic!("{:?}: {:?}", expected, other.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn sixteen_bit_gradients_round_trip_through_png() {
+        // 1024 levels per channel, 64 apart
+        let gradient = ImageBuffer::<Rgb<u16>, Vec<u16>>::from_fn(1024, 8, |x, y| {
+            Rgb([x as u16 * 64, 65535 - x as u16 * 64, (x as u16 * 64).wrapping_add(y as u16 * 4096)])
+        });
+        let mut input = Vec::new();
+        DynamicImage::ImageRgb16(gradient.clone()).write_to(&mut Cursor::new(&mut input), image::ImageFormat::Png).unwrap();
+
+        let remover = crate::testing::TestModel::Brightness.remover(RemoveBgOptions::default()).unwrap();
+        let output = image::load_from_memory(&remover.process_bytes(&input).unwrap()).unwrap();
+        let cutout = output.as_rgba16().expect("16-bit output");
+        for c in 0..3 {
+            let levels: std::collections::HashSet<u16> = cutout.pixels().map(|pixel| pixel[c]).collect();
+            assert!(levels.len() > 256, "{} levels in channel {}", levels.len(), c);
+        }
+        for (pixel, input) in cutout.pixels().zip(gradient.pixels()) {
+            assert_eq!(&pixel.0[..3], &input.0);
+        }
+    }
+
+    #[test]
+    fn flattening_matches_naive_loop() {
+        for (seed, (width, height)) in SIZES.into_iter().enumerate() {
+            let seed = seed as u64;
+            let mut next = random(seed + 200);
+            let background = Rgb32FImage::from_fn(width, height, |_, _| Rgb(std::array::from_fn(|_| next() as u16 as f32 / 65535.0)));
+            let layer = |x, y| background.get_pixel(x, y).0;
+
+            let image = random_rgba16(seed, width, height);
+            let (mut actual, mut expected) = (image.clone(), image);
+            flatten_onto(&mut actual, layer);
+            naive_flatten(&mut expected, layer);
+            assert_eq!(actual, expected);
+
+            let image: RgbaImage = DynamicImage::ImageRgba16(random_rgba16(seed, width, height)).into_rgba8();
+            let (mut actual, mut expected) = (image.clone(), image);
+            flatten_onto(&mut actual, layer);
+            naive_flatten(&mut expected, layer);
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn output_paths_resolve_for_every_kind_of_location() {
+        let dir = std::env::temp_dir().join(format!("removebg-output-paths-{}", std::process::id()));
+        // An existing directory whose name looks like a file
+        let shots = dir.join("shots.png");
+        std::fs::create_dir_all(&shots).unwrap();
+        let input = Path::new("in/photo.jpg");
+        let white = RemoveBgOptions { background: Background::Color([255, 255, 255]), ..Default::default() };
+        let webp = RemoveBgOptions { format: Some(OutputFormat::WebP), ..Default::default() };
+        let pop = RemoveBgOptions { grayscale_background: true, ..Default::default() };
+        let fix = NamingOptions { fix_extension: true, ..Default::default() };
+        let fixed_webp = RemoveBgOptions { naming: fix.clone(), ..webp.clone() };
+        let fixed = RemoveBgOptions { naming: fix, ..Default::default() };
+        let defaults = RemoveBgOptions::default();
+
+        // Output, options, and the resolved path and format or `None` for an error
+        type Case<'a> = (Option<PathBuf>, &'a RemoveBgOptions, Option<(PathBuf, OutputFormat)>);
+        let cases: [Case; 15] = [
+            (None, &defaults, Some(("in/photo_nobg.png".into(), OutputFormat::Png))),
+            (None, &webp, Some(("in/photo_nobg.webp".into(), OutputFormat::WebP))),
+            (Some("out/".into()), &defaults, Some(("out/photo_nobg.png".into(), OutputFormat::Png))),
+            (Some("out/".into()), &pop, Some(("out/photo_pop.png".into(), OutputFormat::Png))),
+            (Some(shots.clone()), &defaults, Some((shots.join("photo_nobg.png"), OutputFormat::Png))),
+            (Some(shots.clone()), &webp, Some((shots.join("photo_nobg.webp"), OutputFormat::WebP))),
+            (Some("cut.JPG".into()), &white, Some(("cut.JPG".into(), OutputFormat::Jpeg))),
+            // Extensions are only replaced with --fix-extension
+            (Some("cut.png".into()), &fixed_webp, Some(("cut.webp".into(), OutputFormat::WebP))),
+            (Some("cut.final".into()), &fixed, Some(("cut.png".into(), OutputFormat::Png))),
+            (Some("cut".into()), &fixed_webp, Some(("cut.webp".into(), OutputFormat::WebP))),
+            (Some("cut.png".into()), &webp, None),
+            (Some("cut.final".into()), &defaults, None),
+            (Some("cut".into()), &webp, None),
+            // Extensionless files are refused, as are opaque formats for transparent output
+            (Some("cut".into()), &defaults, None),
+            (Some("cut.jpg".into()), &defaults, None),
+        ];
+        for (output, options, expected) in cases {
+            let location = OutputLocation::of(output.as_deref());
+            let resolved = resolve_output_path(input, location, OutputFormat::Png, options, &|_| false);
+            match expected {
+                Some(expected) => assert_eq!(resolved.unwrap(), expected, "{:?}", output),
+                None => assert!(
+                    matches!(resolved, Err(RemoveBgError::InvalidOutputFormat(_))),
+                    "{:?}: {:?}",
+                    output,
+                    resolved
+                ),
+            }
+        }
+        let message = resolve_output_path(input, OutputLocation::of(Some(Path::new("cut"))), OutputFormat::Png, &defaults, &|_| false)
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("cut has no extension"), "{}", message);
+
+        // Animations keep their container in a directory too
+        let location = OutputLocation::Directory(Path::new("out"));
+        let resolved = resolve_output_path(input, location, OutputFormat::Gif, &defaults, &|_| false).unwrap();
+        assert_eq!(resolved, ("out/photo_nobg.gif".into(), OutputFormat::Gif));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn naming(prefix: &str, suffix: Option<&str>, on_collision: Collision) -> RemoveBgOptions {
+        let naming = NamingOptions { prefix: prefix.into(), suffix: suffix.map(String::from), on_collision, ..Default::default() };
+        RemoveBgOptions { naming, ..Default::default() }
+    }
+
+    #[test]
+    fn output_names_take_the_prefix_and_suffix() {
+        let out = OutputLocation::Directory(Path::new("out"));
+        let pop = RemoveBgOptions { grayscale_background: true, ..naming("", None, Collision::Error) };
+        let cases = [
+            ("in/photo.jpg", naming("", None, Collision::Error), "out/photo_nobg.png"),
+            ("in/photo.jpg", pop, "out/photo_pop.png"),
+            ("in/photo.jpg", naming("cut-", None, Collision::Error), "out/cut-photo_nobg.png"),
+            ("in/photo.jpg", naming("", Some(".final"), Collision::Error), "out/photo.final.png"),
+            ("in/photo.jpg", naming("", Some(""), Collision::Error), "out/photo.png"),
+            ("in/photo.tar.jpg", naming("x_", Some("_y"), Collision::Error), "out/x_photo.tar_y.png"),
+            // Unicode stems, prefixes and suffixes are kept as they are
+            ("in/фото 写真.jpg", naming("é_", Some("_ø"), Collision::Error), "out/é_фото 写真_ø.png"),
+            ("in/café.jpeg", naming("", None, Collision::Rename), "out/café_nobg.png"),
+            // A stem already ending in the suffix gets it again rather than
+            // being taken for an output
+            ("in/photo_nobg.jpg", naming("", None, Collision::Error), "out/photo_nobg_nobg.png"),
+            ("in/cut-photo.jpg", naming("cut-", None, Collision::Error), "out/cut-cut-photo_nobg.png"),
+        ];
+        for (input, options, expected) in cases {
+            let resolved = resolve_output_path(Path::new(input), out, OutputFormat::Png, &options, &|_| false);
+            assert_eq!(resolved.unwrap(), (PathBuf::from(expected), OutputFormat::Png), "{}", input);
+        }
+
+        // Names that would replace the input are refused, or numbered
+        let input = Path::new("in/photo.png");
+        let bare = naming("", Some(""), Collision::Error);
+        let error = resolve_output_path(input, OutputLocation::NextToInput, OutputFormat::Png, &bare, &|_| false).unwrap_err();
+        assert!(error.to_string().contains("would be written over its input"), "{}", error);
+        let overwrite = naming("", Some(""), Collision::Overwrite);
+        assert!(resolve_output_path(input, OutputLocation::NextToInput, OutputFormat::Png, &overwrite, &|_| false).is_err());
+        let rename = naming("", Some(""), Collision::Rename);
+        let resolved = resolve_output_path(input, OutputLocation::NextToInput, OutputFormat::Png, &rename, &|_| false).unwrap();
+        assert_eq!(resolved.0, Path::new("in/photo-1.png"));
+        // A different extension is a different file
+        let resolved = resolve_output_path(Path::new("in/photo.jpg"), OutputLocation::NextToInput, OutputFormat::Png, &bare, &|_| false);
+        assert_eq!(resolved.unwrap().0, Path::new("in/photo.png"));
+
+        for (prefix, suffix) in [("out/", None), ("", Some("/x")), ("a\\", None), ("", Some("\0"))] {
+            let options = naming(prefix, suffix, Collision::Error);
+            let error = resolve_output_path(Path::new("in/photo.jpg"), out, OutputFormat::Png, &options, &|_| false).unwrap_err();
+            assert!(matches!(error, RemoveBgError::InvalidOutputFormat(_)), "{:?} {:?}: {:?}", prefix, suffix, error);
+        }
+    }
+
+    #[test]
+    fn taken_outputs_follow_the_collision_policy() {
+        let dir = std::env::temp_dir().join(format!("removebg-collisions-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = Path::new("in/photo.jpg");
+        let location = OutputLocation::Directory(&dir);
+        let resolve = |policy, taken: &dyn Fn(&Path) -> bool| {
+            resolve_output_path(input, location, OutputFormat::Png, &naming("", None, policy), taken).unwrap().0
+        };
+        let name = |name: &str| dir.join(name);
+        let claimed = |names: &'static [&'static str]| {
+            let dir = dir.clone();
+            move |path: &Path| names.iter().any(|name| dir.join(name) == path)
+        };
+
+        // Nothing taken: every policy keeps the name
+        for policy in [Collision::Error, Collision::Overwrite, Collision::Rename] {
+            assert_eq!(resolve(policy, &|_| false), name("photo_nobg.png"), "{}", policy);
+        }
+
+        // Taken by an existing file, by another input, or both: only rename
+        // moves away, to the first number neither claims
+        std::fs::write(name("photo_nobg.png"), b"").unwrap();
+        std::fs::write(name("photo_nobg-2.png"), b"").unwrap();
+        type Case = (&'static [&'static str], &'static str);
+        let cases: [Case; 4] = [
+            (&[], "photo_nobg-1.png"),
+            (&["photo_nobg-1.png"], "photo_nobg-3.png"),
+            (&["photo_nobg-1.png", "photo_nobg-3.png"], "photo_nobg-4.png"),
+            (&["photo_nobg-4.png"], "photo_nobg-1.png"),
+        ];
+        for (taken, expected) in cases {
+            let taken = claimed(taken);
+            assert_eq!(resolve(Collision::Rename, &taken), name(expected));
+            assert_eq!(resolve(Collision::Error, &taken), name("photo_nobg.png"));
+            assert_eq!(resolve(Collision::Overwrite, &taken), name("photo_nobg.png"));
+        }
+        std::fs::remove_file(name("photo_nobg.png")).unwrap();
+        assert_eq!(resolve(Collision::Rename, &claimed(&["photo_nobg.png"])), name("photo_nobg-1.png"));
+
+        // Output files given by the caller are numbered too, keeping their extension
+        let file = name("photo_nobg-2.png");
+        let options = naming("", None, Collision::Rename);
+        let resolved = resolve_output_path(input, OutputLocation::File(&file), OutputFormat::Png, &options, &|_| false);
+        assert_eq!(resolved.unwrap().0, name("photo_nobg-2-1.png"));
+
+        assert!(!replaces_existing(&naming("", None, Collision::Error)));
+        assert!(!replaces_existing(&naming("", None, Collision::Rename)));
+        assert!(replaces_existing(&naming("", None, Collision::Overwrite)));
+        assert!(replaces_existing(&RemoveBgOptions { overwrite: true, ..Default::default() }));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn renamed_and_prefixed_outputs_are_recognized() {
+        let defaults = RemoveBgOptions::default();
+        let custom = naming("cut-", Some(".done"), Collision::Rename);
+        let bare = naming("", Some(""), Collision::Rename);
+        let cases = [
+            ("photo_nobg.png", &defaults, true),
+            ("photo_nobg-1.png", &defaults, true),
+            ("photo_nobg-12.webp", &defaults, true),
+            ("photo-1.png", &defaults, false),
+            ("photo_nobg-.png", &defaults, false),
+            ("photo_nobg-1a.png", &defaults, false),
+            ("cut-photo.done.png", &custom, true),
+            ("cut-photo.done-3.png", &custom, true),
+            ("photo.done.png", &custom, false),
+            ("cut-photo.png", &custom, false),
+            // Earlier default outputs are still skipped
+            ("photo_nobg.png", &custom, true),
+            // Without a prefix or suffix, nothing looks like an output
+            ("photo.png", &bare, false),
+            ("photo-1.png", &bare, false),
+        ];
+        for (name, options, expected) in cases {
+            assert_eq!(is_output_name(Path::new(name), options), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn inputs_look_processed_by_name_or_by_transparency() {
+        let options = RemoveBgOptions::default();
+        // An opaque square whose antialiased edge is partly transparent
+        let logo = DynamicImage::ImageRgba8(RgbaImage::from_fn(128, 128, |x, y| {
+            let edge = x == 0 || y == 0 || x == 127 || y == 127;
+            Rgba([200, 30, 30, if edge { 128 } else { 255 }])
+        }));
+        let cutout = DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 64, |x, _| Rgba([200, 30, 30, if x < 16 { 0 } else { 255 }])));
+        let photo = DynamicImage::ImageRgb8(RgbImage::new(64, 64));
+        let cases = [
+            (&logo, "logo.png", None),
+            (&cutout, "logo.png", Some("already has transparency")),
+            (&photo, "photo_nobg.png", Some("is named like an earlier output")),
+            (&photo, "photo.png", None),
+        ];
+        for (image, name, expected) in cases {
+            assert_eq!(reprocess_reason(image, Path::new(name), &options), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn loading_one_shared_remover_does_not_block_others() {
+        let options = crate::testing::TestModel::Brightness.options(RemoveBgOptions::default());
+        // Another caller is still loading the session with other settings
+        let loading = shared_entry(&SessionKey::from_options(&RemoveBgOptions { intra_threads: Some(3), ..options.clone() }));
+        let _loading = loading.lock().unwrap();
+
+        let (first, _) = shared_remover(&options).unwrap();
+        let (second, downloaded) = shared_remover(&options).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert!(!downloaded);
+    }
+
+    #[test]
+    fn threads_share_one_remover_and_get_the_same_masks() {
+        let options = crate::testing::TestModel::CenterBlob.options(RemoveBgOptions { intra_threads: Some(1), ..Default::default() });
+        let images: Vec<DynamicImage> =
+            (0..3).map(|seed| DynamicImage::ImageRgb8(crate::testing::synthetic_image(seed, 96, 64))).collect();
+        let (remover, _) = shared_remover(&options).unwrap();
+        let expected: Vec<_> = images.iter().map(|image| remover.mask(image).unwrap()).collect();
+        let cutout = remover.process_image(&images[0]).unwrap();
+
+        std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..4)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let (remover, _) = shared_remover(&options).unwrap();
+                        let masks: Vec<_> = images.iter().map(|image| remover.mask(image).unwrap()).collect();
+                        (masks, remover.process_image(&images[0]).unwrap())
+                    })
+                })
+                .collect();
+            for thread in threads {
+                let (masks, image) = thread.join().unwrap();
+                assert!(masks == expected, "masks differ from the single-threaded ones");
+                assert!(image == cutout, "cutout differs from the single-threaded one");
+            }
+        });
+    }
+
+    #[test]
+    fn sessions_are_cached_per_session_setting() {
+        let options = RemoveBgOptions::default();
+        let key = SessionKey::from_options(&options);
+        let changed = [
+            RemoveBgOptions { intra_threads: Some(2), ..options.clone() },
+            RemoveBgOptions { inter_threads: Some(2), ..options.clone() },
+            RemoveBgOptions { optimization_level: OptimizationLevel::Basic, ..options.clone() },
+            RemoveBgOptions { device: Device::CoreMl, ..options.clone() },
+        ];
+        for changed in &changed {
+            assert_ne!(SessionKey::from_options(changed), key, "{:?}", changed);
+        }
+        // Settings applied after inference share the session
+        assert_eq!(SessionKey::from_options(&RemoveBgOptions { invert_mask: true, ..options }), key);
+    }
 }
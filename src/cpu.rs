@@ -0,0 +1,235 @@
+//! The number of CPUs this process may use, for sizing thread pools.
+//!
+//! Containers often get a CPU quota (`docker run --cpus 2`, a Kubernetes CPU
+//! limit) on a machine with many more cores. Threads beyond the quota don't
+//! run any faster; they are throttled, and one per core makes ONNX Runtime's
+//! spinning threads burn the quota. The quota is read from the process's own
+//! cgroup, found in `/proc/self/cgroup`, and its parents: `cpu.max` for cgroup
+//! v2, `cpu.cfs_quota_us` and `cpu.cfs_period_us` for v1; the smallest one
+//! applies. It caps the count of [`std::thread::available_parallelism`],
+//! which also reflects CPU affinity, and is reported by `removebg doctor`.
+//!
+//! The result is the default for ONNX Runtime's intra-op threads, rayon's
+//! pool (`parallel` feature) and the server's request handlers; explicit
+//! settings of any of them are used as given.
+
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// Where the cgroup filesystem is mounted. Inside a container it usually
+/// shows the container's own cgroup.
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// The cgroups of this process, one `<id>:<controllers>:<path>` line per
+/// hierarchy.
+const PROC_CGROUP: &str = "/proc/self/cgroup";
+
+/// CPUs this process may use, as found by [`detect`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Parallelism {
+    /// Threads worth running at once: `available` capped by the quota,
+    /// rounded up, and at least 1.
+    pub cpus: usize,
+    /// CPUs' worth of time the cgroup quota allows per period, if there is a
+    /// quota.
+    pub quota: Option<f64>,
+    /// What [`std::thread::available_parallelism`] reports, or 1 if it fails.
+    pub available: usize,
+}
+
+impl Parallelism {
+    fn new(available: usize, quota: Option<f64>) -> Self {
+        let cpus = match quota {
+            Some(quota) => (quota.ceil() as usize).clamp(1, available.max(1)),
+            None => available.max(1),
+        };
+        Parallelism { cpus, quota, available }
+    }
+
+    /// Whether the quota lowered the count.
+    pub fn limited(&self) -> bool {
+        self.cpus < self.available
+    }
+}
+
+/// Read the CPU quota and the parallelism of this process.
+pub fn detect() -> Parallelism {
+    let available = std::thread::available_parallelism().map_or(1, usize::from);
+    let quota = if cfg!(target_os = "linux") {
+        let cgroups = std::fs::read_to_string(PROC_CGROUP).unwrap_or_default();
+        cgroup_quota(Path::new(CGROUP_ROOT), &cgroups)
+    } else {
+        None
+    };
+    Parallelism::new(available, quota)
+}
+
+/// Threads worth running at once in this process: [`detect`]'s `cpus`,
+/// detected once and logged at debug level.
+pub fn available_parallelism() -> usize {
+    static DETECTED: OnceLock<Parallelism> = OnceLock::new();
+    DETECTED
+        .get_or_init(|| {
+            let detected = detect();
+            match detected.quota {
+                Some(quota) => log::debug!(
+                    "Using {} threads: cgroup CPU quota of {:.2} CPUs, {} CPUs available",
+                    detected.cpus,
+                    quota,
+                    detected.available
+                ),
+                None => log::debug!("Using {} threads: no cgroup CPU quota", detected.cpus),
+            }
+            detected
+        })
+        .cpus
+}
+
+/// Size rayon's global pool to [`available_parallelism`], unless
+/// `RAYON_NUM_THREADS` sets it. Does nothing once the pool exists.
+#[cfg(feature = "parallel")]
+pub fn init_thread_pool() {
+    init_thread_pool_with(None);
+}
+
+/// Like [`init_thread_pool`], with `threads` (`--jobs`) taking precedence
+/// over `RAYON_NUM_THREADS`.
+#[cfg(feature = "parallel")]
+pub fn init_thread_pool_with(threads: Option<usize>) {
+    let threads = match threads {
+        Some(threads) => threads,
+        None if std::env::var_os("RAYON_NUM_THREADS").is_some_and(|threads| !threads.is_empty()) => return,
+        None => available_parallelism(),
+    };
+    let _ = rayon::ThreadPoolBuilder::new().num_threads(threads).build_global();
+}
+
+/// The CPU quota, in CPUs, of the cgroup that `cgroups` (the contents of
+/// `/proc/self/cgroup`) names in the cgroup filesystem at `root`: the
+/// smallest of the v2 `cpu.max` and the v1 files in `cpu` or `cpu,cpuacct`
+/// of the cgroup and its parents. A cgroup that isn't under `root`, as in
+/// containers that only mount their own, is read at `root`.
+///
+/// # Returns
+/// `None` if there is no quota or none of the files can be read.
+pub fn cgroup_quota(root: &Path, cgroups: &str) -> Option<f64> {
+    let v2 = smallest_quota(root, own_cgroup(cgroups, None), |dir| parse_cpu_max(&read(&dir.join("cpu.max"))?));
+    let v1 = ["cpu", "cpu,cpuacct", "cpuacct,cpu"].iter().map(|controllers| root.join(controllers)).find(|mount| mount.is_dir());
+    let v1 = v1.and_then(|mount| {
+        smallest_quota(&mount, own_cgroup(cgroups, Some("cpu")), |dir| {
+            parse_cfs(&read(&dir.join("cpu.cfs_quota_us"))?, &read(&dir.join("cpu.cfs_period_us"))?)
+        })
+    });
+    [v2, v1].into_iter().flatten().min_by(f64::total_cmp)
+}
+
+/// The path of this process's cgroup in `cgroups`: in the v2 hierarchy, or
+/// else in the v1 hierarchy with `controller`. `/` if it isn't listed.
+fn own_cgroup<'a>(cgroups: &'a str, controller: Option<&str>) -> &'a str {
+    cgroups
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, ':');
+            let (_, controllers, path) = (fields.next()?, fields.next()?, fields.next()?);
+            let listed = match controller {
+                Some(controller) => controllers.split(',').any(|listed| listed == controller),
+                None => controllers.is_empty(),
+            };
+            listed.then_some(path)
+        })
+        .next()
+        .unwrap_or("/")
+}
+
+/// The smallest quota `quota` finds in the cgroup at `path` under `mount`,
+/// or at `mount` if there is no such directory, and in its parents up to
+/// `mount`.
+fn smallest_quota(mount: &Path, path: &str, quota: impl Fn(&Path) -> Option<f64>) -> Option<f64> {
+    let dir = mount.join(path.trim_start_matches('/'));
+    let dir = if dir.is_dir() { dir } else { mount.to_path_buf() };
+    dir.ancestors().take_while(|dir| dir.starts_with(mount)).filter_map(quota).min_by(f64::total_cmp)
+}
+
+fn read(path: &Path) -> Option<String> {
+    std::fs::read_to_string(path).ok()
+}
+
+/// The quota in a cgroup v2 `cpu.max`: `<quota> <period>` in microseconds,
+/// with `max` for no quota.
+fn parse_cpu_max(contents: &str) -> Option<f64> {
+    let mut fields = contents.split_whitespace();
+    let quota = fields.next()?;
+    let period = fields.next().unwrap_or("100000");
+    if quota == "max" {
+        return None;
+    }
+    ratio(quota, period)
+}
+
+/// The quota in cgroup v1's `cpu.cfs_quota_us` and `cpu.cfs_period_us`,
+/// where a quota of -1 means none.
+fn parse_cfs(quota: &str, period: &str) -> Option<f64> {
+    if quota.trim() == "-1" {
+        return None;
+    }
+    ratio(quota, period)
+}
+
+fn ratio(quota: &str, period: &str) -> Option<f64> {
+    let quota: u64 = quota.trim().parse().ok()?;
+    let period: u64 = period.trim().parse().ok()?;
+    (quota > 0 && period > 0).then(|| quota as f64 / period as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn fixture(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/cgroup").join(name)
+    }
+
+    #[test]
+    fn reads_quotas_of_both_cgroup_versions() {
+        assert_eq!(cgroup_quota(&fixture("v2"), "0::/\n"), Some(1.5));
+        assert_eq!(cgroup_quota(&fixture("v2-unlimited"), "0::/\n"), None);
+        assert_eq!(cgroup_quota(&fixture("v1"), "4:cpu,cpuacct:/\n2:memory:/\n"), Some(2.0));
+        assert_eq!(cgroup_quota(&fixture("v1-unlimited"), "4:cpu,cpuacct:/\n"), None);
+        assert_eq!(cgroup_quota(&fixture("missing"), ""), None);
+    }
+
+    #[test]
+    fn follows_the_process_cgroup_and_its_parents() {
+        // The pod's quota of 1.5 CPUs is below the container's own 2
+        let nested = fixture("v2-nested");
+        assert_eq!(cgroup_quota(&nested, "0::/kubepods/pod/container\n"), Some(1.5));
+        assert_eq!(cgroup_quota(&nested, "0::/kubepods/pod\n"), Some(1.5));
+        assert_eq!(cgroup_quota(&nested, "0::/system.slice\n"), None);
+        // A container that mounts only its own cgroup
+        assert_eq!(cgroup_quota(&fixture("v2"), "0::/docker/0123abcd\n"), Some(1.5));
+        assert_eq!(cgroup_quota(&fixture("v1"), "5:cpuacct,cpu:/docker/0123abcd\n"), Some(2.0));
+    }
+
+    #[test]
+    fn parses_quota_files() {
+        assert_eq!(parse_cpu_max("max 100000\n"), None);
+        assert_eq!(parse_cpu_max("50000 100000\n"), Some(0.5));
+        assert_eq!(parse_cpu_max("400000\n"), Some(4.0));
+        assert_eq!(parse_cpu_max(""), None);
+        assert_eq!(parse_cpu_max("0 100000"), None);
+        assert_eq!(parse_cfs("-1\n", "100000\n"), None);
+        assert_eq!(parse_cfs("300000\n", "100000\n"), Some(3.0));
+        assert_eq!(parse_cfs("garbage", "100000"), None);
+    }
+
+    #[test]
+    fn quota_caps_and_rounds_up() {
+        assert_eq!(Parallelism::new(16, Some(1.5)).cpus, 2);
+        assert_eq!(Parallelism::new(16, Some(0.1)).cpus, 1);
+        assert_eq!(Parallelism::new(4, Some(8.0)).cpus, 4);
+        assert_eq!(Parallelism::new(8, None).cpus, 8);
+        assert!(Parallelism::new(16, Some(2.0)).limited());
+        assert!(!Parallelism::new(2, Some(2.0)).limited());
+    }
+}
@@ -0,0 +1,217 @@
+//! Long-lived processing driven by JSON lines (`serde` feature).
+//!
+//! Used by `removebg daemon`. Programs that process many images start the
+//! daemon once and write one command per line to its input instead of
+//! starting a process, and loading the model, per image:
+//!
+//! ```text
+//! {"input": "a.jpg", "output": "a.png", "options": {"background": "white"}, "id": 1}
+//! {"cmd": "shutdown"}
+//! ```
+//!
+//! * `input` - Image to process; relative paths are resolved against the
+//!   daemon's working directory
+//! * `output` - Where to write the cutout; by default it is named like the
+//!   outputs of [`remove_background`](crate::remove_background)
+//! * `options` - Fields of [`RemoveBgOptions`], in their serialized form,
+//!   that replace the daemon's options for this command only; nested
+//!   objects replace only the fields they name
+//! * `id` - Any JSON value, echoed in the result to match it to the command
+//!
+//! Every command gets exactly one result line, written and flushed before
+//! the next command is read, so callers can wait for it:
+//! `{"id": 1, "success": true, "report": {...}}` with the
+//! [`RemovalReport`](crate::RemovalReport), or
+//! `{"id": 1, "success": false, "error": {...}}` with the [`ErrorInfo`].
+//! Malformed lines get an `invalid_config` error and the daemon reads on.
+//! `{"cmd": "shutdown"}` or the end of the input stops it.
+//!
+//! The daemon's model is loaded before the first command is read, and with
+//! [`quality_fallback`](crate::RemoveBgOptions::quality_fallback) u2netp too,
+//! so downgraded commands don't wait for it; their reports list the
+//! downgrades in `model.downgrades`. Commands whose options select another
+//! model or session settings load that model on first use and keep it for
+//! later commands. `removebg daemon` reports model downloads as
+//! [`DownloadEvent`](crate::progress::DownloadEvent) lines on stderr, so
+//! they never mix with the results.
+
+use crate::core::{self, SessionKey};
+use crate::error::{ErrorInfo, RemoveBgError, Result};
+use crate::fallback;
+use crate::options::RemoveBgOptions;
+use crate::remover::BackgroundRemover;
+use crate::report::RemovalReport;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Timeout per command of `removebg daemon` unless `--timeout` sets
+/// another; a command's `timeout_ms` option replaces it.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Counts of the commands a daemon has run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DaemonSummary {
+    /// Commands that wrote their output.
+    pub processed: usize,
+    /// Commands that failed, including malformed lines.
+    pub failed: usize,
+}
+
+/// Run commands read from `input`, one per line, writing one result line per
+/// command to `output`, until a `shutdown` command or the end of `input`.
+///
+/// `options` apply to every command unless the command's `options` replace
+/// them. Cancelling `options.cancel` stops the daemon before the next command.
+///
+/// # Errors
+/// * `ModelInitError` - If the model in `options`, or u2netp with `quality_fallback`, cannot be
+///   loaded; no command is read
+/// * `IoError` - If `input` cannot be read or a result cannot be written
+pub fn run(input: impl BufRead, mut output: impl Write, options: &RemoveBgOptions) -> Result<DaemonSummary> {
+    let mut removers = HashMap::new();
+    removers.insert(SessionKey::from_options(options), load(options)?);
+    if let Some(fallback) = fallback::fallback_options(options) {
+        // Downgraded commands take it from the shared removers
+        let started = Instant::now();
+        let (remover, _) = core::shared_remover(&fallback)?;
+        log::info!("Loaded {} for the deadline in {:.1}s", fallback.model, (started.elapsed() - remover.warmup_duration()).as_secs_f64());
+    }
+    let mut summary = DaemonSummary::default();
+
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if options.cancel.is_cancelled() {
+            break;
+        }
+        let command = match serde_json::from_str::<Value>(&line) {
+            Ok(Value::Object(command)) => command,
+            Ok(_) => {
+                summary.failed += 1;
+                respond(&mut output, None, Err(malformed("a command must be a JSON object")))?;
+                continue;
+            }
+            Err(e) => {
+                summary.failed += 1;
+                respond(&mut output, None, Err(malformed(&e.to_string())))?;
+                continue;
+            }
+        };
+        let id = command.get("id").cloned();
+        match command.get("cmd") {
+            Some(Value::String(cmd)) if cmd == "shutdown" => break,
+            None => {}
+            Some(cmd) => {
+                summary.failed += 1;
+                respond(&mut output, id, Err(malformed(&format!("unknown command {}", cmd))))?;
+                continue;
+            }
+        }
+
+        let result = process(&command, options, &mut removers);
+        match result {
+            Ok(_) => summary.processed += 1,
+            Err(_) => summary.failed += 1,
+        }
+        respond(&mut output, id, result)?;
+    }
+    Ok(summary)
+}
+
+/// Load the model for `options`, logging how long it took.
+fn load(options: &RemoveBgOptions) -> Result<BackgroundRemover> {
+    let started = Instant::now();
+    let remover = BackgroundRemover::new(options.clone())?;
+    let warmup = remover.warmup_duration();
+    log::info!("Loaded {} in {:.1}s", options.model, (started.elapsed() - warmup).as_secs_f64());
+    if options.warmup {
+        log::info!("Warmed up {} in {:.1}s", options.model, warmup.as_secs_f64());
+    }
+    Ok(remover)
+}
+
+/// Process one command with the remover for its session settings.
+fn process(
+    command: &Map<String, Value>,
+    defaults: &RemoveBgOptions,
+    removers: &mut HashMap<SessionKey, BackgroundRemover>,
+) -> Result<RemovalReport> {
+    let path = |key: &str| match command.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(path)) => Ok(Some(Path::new(path))),
+        Some(_) => Err(malformed(&format!("`{}` must be a path", key))),
+    };
+    let input = path("input")?.ok_or_else(|| malformed("missing `input`"))?;
+    let output = path("output")?;
+    let options = match command.get("options") {
+        None | Some(Value::Null) => defaults.clone(),
+        Some(overrides) => merge(defaults, overrides.clone())?,
+    };
+
+    let key = SessionKey::from_options(&options);
+    if !removers.contains_key(&key) {
+        let remover = load(&options)?;
+        removers.insert(key.clone(), remover);
+    }
+    core::remove_file(input, output, &options, || Ok((&removers[&key], false)))
+}
+
+/// `defaults` with the fields in `overrides` replaced.
+pub(crate) fn merge(defaults: &RemoveBgOptions, overrides: Value) -> Result<RemoveBgOptions> {
+    if !overrides.is_object() {
+        return Err(malformed("`options` must be an object"));
+    }
+    let mut merged = serde_json::to_value(defaults).map_err(|e| RemoveBgError::InvalidConfig(e.to_string()))?;
+    overlay(&mut merged, overrides);
+    let mut options: RemoveBgOptions =
+        serde_json::from_value(merged).map_err(|e| RemoveBgError::InvalidConfig(format!("options: {}", e)))?;
+    // None of these is serialized
+    options.cancel = defaults.cancel.clone();
+    options.download_progress = defaults.download_progress.clone();
+    options.encoder = defaults.encoder.clone();
+    #[cfg(feature = "ort")]
+    {
+        options.ort_environment.environment = defaults.ort_environment.environment.clone();
+    }
+    Ok(options)
+}
+
+/// Replace the values in `target` by those in `overrides`, field by field in
+/// objects that are in both.
+fn overlay(target: &mut Value, overrides: Value) {
+    match (target, overrides) {
+        (Value::Object(target), Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                overlay(target.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (target, overrides) => *target = overrides,
+    }
+}
+
+fn malformed(message: &str) -> RemoveBgError {
+    RemoveBgError::InvalidConfig(format!("malformed command: {}", message))
+}
+
+/// Write the result line of a command and flush it.
+fn respond(output: &mut impl Write, id: Option<Value>, result: Result<RemovalReport>) -> Result<()> {
+    let mut line = Map::new();
+    if let Some(id) = id {
+        line.insert("id".into(), id);
+    }
+    let (key, value) = match result {
+        Ok(report) => ("report", serde_json::to_value(report)),
+        Err(e) => ("error", serde_json::to_value(ErrorInfo::from(&e))),
+    };
+    line.insert("success".into(), Value::Bool(key == "report"));
+    line.insert(key.into(), value.map_err(std::io::Error::from)?);
+    serde_json::to_writer(&mut *output, &line).map_err(std::io::Error::from)?;
+    output.write_all(b"\n")?;
+    output.flush()?;
+    Ok(())
+}
@@ -0,0 +1,200 @@
+//! Free disk space checks before large writes.
+//!
+//! Running out of space mid-write fails late: after the whole model
+//! download, or after encoding, with `No space left on device` and a
+//! truncated file left behind. A model of known size is checked before its
+//! download starts, and fails with [`RemoveBgError::InsufficientDiskSpace`]
+//! right away. Outputs are checked against an estimate of their encoded size
+//! from their dimensions, which warns or fails as
+//! [`disk_space`](crate::RemoveBgOptions::disk_space) selects.
+//!
+//! Free space is what an unprivileged process may use: `statvfs`'s
+//! `f_bavail` on Unix and `GetDiskFreeSpaceExW` on Windows, which honor
+//! reserved blocks and quotas. Where it can't be read, nothing is checked.
+
+use crate::error::{RemoveBgError, Result};
+use crate::model::Model;
+use crate::options::{DiskSpaceCheck, RemoveBgOptions};
+use crate::output::OutputFormat;
+use image::DynamicImage;
+use std::io;
+use std::path::Path;
+
+/// Bytes available to this process on the filesystem holding `path`, or
+/// that would hold it: the nearest existing ancestor is queried when `path`
+/// doesn't exist yet.
+///
+/// # Errors
+/// The platform query's error, or `Unsupported` on platforms without one.
+pub(crate) fn available_space(path: &Path) -> io::Result<u64> {
+    let existing = path
+        .ancestors()
+        .map(|dir| if dir.as_os_str().is_empty() { Path::new(".") } else { dir })
+        .find(|dir| dir.exists())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no existing parent directory"))?;
+    query(existing)
+}
+
+#[cfg(unix)]
+fn query(path: &Path) -> io::Result<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+    let mut stats = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is NUL-terminated and `stats` is written before it's read
+    if unsafe { libc::statvfs(path.as_ptr(), stats.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let stats = unsafe { stats.assume_init() };
+    #[allow(clippy::unnecessary_cast, clippy::useless_conversion)]
+    Ok(u64::from(stats.f_bavail).saturating_mul(stats.f_frsize as u64))
+}
+
+#[cfg(windows)]
+fn query(path: &Path) -> io::Result<u64> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+    let path: Vec<u16> = path.as_os_str().encode_wide().chain([0]).collect();
+    let mut available = 0u64;
+    // SAFETY: `path` is NUL-terminated; the totals not asked for may be null
+    if unsafe { GetDiskFreeSpaceExW(path.as_ptr(), &mut available, std::ptr::null_mut(), std::ptr::null_mut()) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(available)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn query(_path: &Path) -> io::Result<u64> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "free space can't be queried on this platform"))
+}
+
+/// Check that `needed` bytes fit at `path`, asking `available` for the free
+/// space. A failed query passes, logged at debug level.
+///
+/// # Errors
+/// * `InsufficientDiskSpace` - If less than `needed` bytes are free
+pub(crate) fn ensure_space(path: &Path, needed: u64, available: impl FnOnce(&Path) -> io::Result<u64>) -> Result<()> {
+    match available(path) {
+        Ok(available) if available < needed => {
+            Err(RemoveBgError::InsufficientDiskSpace { needed, available, path: path.to_path_buf() })
+        }
+        Ok(_) => Ok(()),
+        Err(e) => {
+            log::debug!("free space at {} unknown: {}", path.display(), e);
+            Ok(())
+        }
+    }
+}
+
+/// Space a download of `bytes` needs: the file plus a tenth, since the
+/// filesystem rounds up to blocks and other writers may take some meanwhile.
+fn with_margin(bytes: u64) -> u64 {
+    bytes.saturating_add(bytes / 10)
+}
+
+/// Check there is room in `dir` to download `model`. Models without a known
+/// size pass.
+pub(crate) fn check_download(model: Model, dir: &Path) -> Result<()> {
+    match model.spec().size_bytes {
+        Some(bytes) => ensure_space(dir, with_margin(bytes), available_space),
+        None => Ok(()),
+    }
+}
+
+/// A rough upper estimate of `image`'s size encoded as `format`: the pixel
+/// data for lossless formats, which photos barely compress, and a quarter of
+/// it for lossy and palette formats.
+pub(crate) fn estimated_output_size(image: &DynamicImage, format: OutputFormat) -> u64 {
+    encoded_size(image.width() as u64 * image.height() as u64 * image.color().bytes_per_pixel() as u64, format)
+}
+
+/// [`estimated_output_size`] of an image of `raw` bytes of pixel data.
+pub(crate) fn encoded_size(raw: u64, format: OutputFormat) -> u64 {
+    match format {
+        OutputFormat::Png | OutputFormat::Apng | OutputFormat::Tiff | OutputFormat::Bmp => raw,
+        OutputFormat::WebP | OutputFormat::Jpeg | OutputFormat::Gif => raw / 4,
+        // At most a few 256x256 images
+        OutputFormat::Ico => 0,
+    }
+}
+
+/// Check there is room at `path` for `image` encoded as `format`, or within
+/// `options.max_file_size` if smaller, as `options.disk_space` selects: log
+/// a warning, fail, or skip the check.
+pub(crate) fn check_output(image: &DynamicImage, path: &Path, format: OutputFormat, options: &RemoveBgOptions) -> Result<()> {
+    let mut estimate = estimated_output_size(image, format);
+    if let Some(limit) = &options.max_file_size {
+        estimate = estimate.min(limit.max_bytes);
+    }
+    check_output_with(estimate, path, options.disk_space, available_space)
+}
+
+fn check_output_with(
+    estimate: u64,
+    path: &Path,
+    check: DiskSpaceCheck,
+    available: impl FnOnce(&Path) -> io::Result<u64>,
+) -> Result<()> {
+    if check == DiskSpaceCheck::Skip {
+        return Ok(());
+    }
+    match ensure_space(path, estimate, available) {
+        Err(e) if check == DiskSpaceCheck::Warn => {
+            log::warn!("{}: {}; writing it may fail", path.display(), e);
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+    use std::path::PathBuf;
+
+    fn free(bytes: u64) -> impl FnOnce(&Path) -> io::Result<u64> {
+        move |_| Ok(bytes)
+    }
+
+    fn unknown(_: &Path) -> io::Result<u64> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "no statvfs"))
+    }
+
+    #[test]
+    fn too_little_space_names_both_sizes_and_the_path() {
+        let dir = PathBuf::from("/home/me/.u2net");
+        let error = ensure_space(&dir, with_margin(175_997_641), free(40_000_000)).unwrap_err();
+        assert_eq!(error.to_string(), "Not enough disk space: need 193.60 MB, have 40.00 MB at /home/me/.u2net");
+        assert!(matches!(error, RemoveBgError::InsufficientDiskSpace { needed: 193_597_405, available: 40_000_000, .. }));
+        assert!(ensure_space(&dir, 100, free(100)).is_ok());
+        // A failed query doesn't stop the write
+        assert!(ensure_space(&dir, u64::MAX, unknown).is_ok());
+    }
+
+    #[test]
+    fn outputs_warn_fail_or_skip() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::new(1000, 500));
+        assert_eq!(estimated_output_size(&image, OutputFormat::Png), 2_000_000);
+        assert_eq!(estimated_output_size(&image, OutputFormat::Jpeg), 500_000);
+        assert_eq!(estimated_output_size(&image, OutputFormat::Ico), 0);
+
+        let path = Path::new("out/photo.png");
+        let check = |check, bytes| check_output_with(2_000_000, path, check, free(bytes));
+        assert!(check(DiskSpaceCheck::Warn, 1_000).is_ok());
+        assert!(matches!(check(DiskSpaceCheck::Refuse, 1_000), Err(RemoveBgError::InsufficientDiskSpace { .. })));
+        assert!(check(DiskSpaceCheck::Refuse, 2_000_000).is_ok());
+        assert!(check_output_with(2_000_000, path, DiskSpaceCheck::Skip, |_| unreachable!()).is_ok());
+    }
+
+    #[cfg(any(unix, windows))]
+    #[test]
+    fn queries_the_nearest_existing_directory() {
+        let dir = std::env::temp_dir();
+        let available = available_space(&dir).unwrap();
+        assert!(available > 0);
+        assert!(available_space(&dir.join("removebg-missing/deeper/photo.png")).is_ok());
+        assert!(available_space(Path::new("missing-relative/photo.png")).is_ok());
+    }
+}
@@ -0,0 +1,204 @@
+//! Environment checks for `removebg doctor`: whether ONNX Runtime loads,
+//! whether the model cache directory and the selected model are usable, and
+//! how many CPUs the process may use.
+//!
+//! Every check runs even when an earlier one fails, and none of them
+//! downloads anything or creates the model directory, so the report shows
+//! everything that is wrong at once without changing the machine.
+
+use crate::cpu;
+use crate::model;
+use crate::options::{Backend, OrtEnvironmentConfig, RemoveBgOptions};
+use std::fmt;
+use std::path::Path;
+
+/// Outcome of one [`Check`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    /// Works as needed.
+    Pass,
+    /// Works now but something may fail later, e.g. a model that still has
+    /// to be downloaded.
+    Warn,
+    /// Processing will fail until this is fixed.
+    Fail,
+}
+
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CheckStatus::Pass => "PASS",
+            CheckStatus::Warn => "WARN",
+            CheckStatus::Fail => "FAIL",
+        })
+    }
+}
+
+/// One line of the doctor report.
+#[derive(Debug, Clone)]
+pub struct Check {
+    /// What was checked, e.g. `ONNX Runtime`.
+    pub name: &'static str,
+    /// Whether it passed.
+    pub status: CheckStatus,
+    /// What was found, or what went wrong.
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self { name, status, detail: detail.into() }
+    }
+}
+
+/// Run every check for the model, model directory and backend in `options`.
+///
+/// # Returns
+/// The checks in report order: ONNX Runtime, model directory, model, CPUs.
+pub fn run(options: &RemoveBgOptions) -> Vec<Check> {
+    vec![check_onnx_runtime(options), check_model_dir(options), check_model(options), check_cpus()]
+}
+
+/// Try to load ONNX Runtime, creating its environment with the default
+/// [`OrtEnvironmentConfig`] if it doesn't exist yet.
+///
+/// # Returns
+/// Why it could not be loaded, or `None` if it loaded or is not compiled in.
+pub fn onnx_runtime_error() -> Option<String> {
+    runtime_error(&OrtEnvironmentConfig::default())
+}
+
+#[cfg(feature = "ort")]
+fn runtime_error(config: &OrtEnvironmentConfig) -> Option<String> {
+    crate::ort_backend::init_runtime(config).err().map(|e| e.to_string())
+}
+
+#[cfg(not(feature = "ort"))]
+fn runtime_error(_config: &OrtEnvironmentConfig) -> Option<String> {
+    None
+}
+
+/// How to install ONNX Runtime on this platform, for when it cannot be loaded.
+pub fn onnx_runtime_hint() -> &'static str {
+    if cfg!(windows) {
+        "Download onnxruntime-win-x64-<version>.zip from https://github.com/microsoft/onnxruntime/releases \
+         and put lib\\onnxruntime.dll next to removebg.exe, or set ORT_DYLIB_PATH to its full path. \
+         Or run with --backend tract, which needs no native library."
+    } else if cfg!(target_os = "macos") {
+        "Install it with `brew install onnxruntime` and set \
+         ORT_DYLIB_PATH=$(brew --prefix onnxruntime)/lib/libonnxruntime.dylib. \
+         Or run with --backend tract, which needs no native library."
+    } else {
+        "Download onnxruntime-linux-x64-<version>.tgz from https://github.com/microsoft/onnxruntime/releases, \
+         unpack it and set ORT_DYLIB_PATH to lib/libonnxruntime.so inside it (or add its lib directory to \
+         LD_LIBRARY_PATH). Or run with --backend tract, which needs no native library."
+    }
+}
+
+fn check_onnx_runtime(options: &RemoveBgOptions) -> Check {
+    const NAME: &str = "ONNX Runtime";
+    if !cfg!(feature = "ort") {
+        return Check::new(NAME, CheckStatus::Pass, "not compiled in; models run on the tract engine");
+    }
+    match runtime_error(&options.ort_environment) {
+        None => Check::new(NAME, CheckStatus::Pass, runtime_info()),
+        // Only a problem if it is going to be used
+        Some(error) if options.backend == Backend::Tract => Check::new(NAME, CheckStatus::Warn, format!("{} (not needed with --backend tract)", error)),
+        Some(error) => Check::new(NAME, CheckStatus::Fail, error),
+    }
+}
+
+#[cfg(feature = "ort")]
+fn runtime_info() -> String {
+    ort::info().to_string()
+}
+
+#[cfg(not(feature = "ort"))]
+fn runtime_info() -> String {
+    String::new()
+}
+
+fn check_model_dir(options: &RemoveBgOptions) -> Check {
+    const NAME: &str = "Model directory";
+    let dir = match model::resolve_model_dir(options.model_dir.as_deref()) {
+        Ok(dir) => dir,
+        Err(e) => return Check::new(NAME, CheckStatus::Fail, e.to_string()),
+    };
+    if dir.is_dir() {
+        return match probe_write(&dir) {
+            Ok(()) => Check::new(NAME, CheckStatus::Pass, format!("{} (writable)", dir.display())),
+            Err(e) => Check::new(NAME, CheckStatus::Fail, format!("{} is not writable: {}", dir.display(), e)),
+        };
+    }
+    if dir.exists() {
+        return Check::new(NAME, CheckStatus::Fail, format!("{} exists but is not a directory", dir.display()));
+    }
+    // It is created on the first download, so its nearest existing parent
+    // has to be writable
+    let Some(parent) = dir.ancestors().skip(1).find(|ancestor| ancestor.is_dir()) else {
+        return Check::new(NAME, CheckStatus::Fail, format!("{} has no existing parent directory", dir.display()));
+    };
+    match probe_write(parent) {
+        Ok(()) => Check::new(NAME, CheckStatus::Pass, format!("{} (not created yet; {} is writable)", dir.display(), parent.display())),
+        Err(e) => Check::new(
+            NAME,
+            CheckStatus::Fail,
+            format!("{} cannot be created: {} is not writable: {}", dir.display(), parent.display(), e),
+        ),
+    }
+}
+
+/// Informational: the thread pools' default size and the quota behind it.
+fn check_cpus() -> Check {
+    const NAME: &str = "CPUs";
+    let detected = cpu::detect();
+    let detail = match detected.quota {
+        Some(quota) => format!("{} (cgroup CPU quota {:.2}; {} available)", detected.cpus, quota, detected.available),
+        None => format!("{} (no cgroup CPU quota)", detected.cpus),
+    };
+    Check::new(NAME, CheckStatus::Pass, detail)
+}
+
+/// Create and delete a file in `dir` to see whether it is writable.
+fn probe_write(dir: &Path) -> std::io::Result<()> {
+    let probe = dir.join(format!(".removebg-doctor-{}", std::process::id()));
+    std::fs::File::create(&probe)?;
+    std::fs::remove_file(&probe)
+}
+
+fn check_model(options: &RemoveBgOptions) -> Check {
+    const NAME: &str = "Model";
+    let model = options.model;
+    if model::embedded_model(model).is_some() {
+        return Check::new(NAME, CheckStatus::Pass, format!("{} (compiled in)", model));
+    }
+    let path = match model::resolve_model_dir(options.model_dir.as_deref()) {
+        Ok(dir) => dir.join(model.spec().file_name),
+        Err(e) => return Check::new(NAME, CheckStatus::Fail, e.to_string()),
+    };
+    if !path.is_file() {
+        let status = if options.download.offline { CheckStatus::Fail } else { CheckStatus::Warn };
+        return Check::new(
+            NAME,
+            status,
+            format!("{} is not cached at {}; it is downloaded on first use", model::name_with_size(model), path.display()),
+        );
+    }
+    let expected = match model::expected_sha256(model, options.model_dir.as_deref()) {
+        Ok(expected) => expected,
+        Err(e) => return Check::new(NAME, CheckStatus::Fail, e.to_string()),
+    };
+    let actual = match model::file_sha256(&path) {
+        Ok(actual) => actual,
+        Err(e) => return Check::new(NAME, CheckStatus::Fail, format!("{} could not be read: {}", path.display(), e)),
+    };
+    match expected {
+        Some(expected) if !expected.eq_ignore_ascii_case(&actual) => Check::new(
+            NAME,
+            CheckStatus::Fail,
+            format!("{} at {} has SHA-256 {} but {} was expected; delete it to download it again", model, path.display(), actual, expected),
+        ),
+        Some(_) => Check::new(NAME, CheckStatus::Pass, format!("{} at {} (checksum ok)", model, path.display())),
+        None => Check::new(NAME, CheckStatus::Pass, format!("{} at {} (no checksum recorded)", model, path.display())),
+    }
+}
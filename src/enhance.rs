@@ -0,0 +1,115 @@
+//! Exposure normalization of the model's copy of the input
+//! ([`auto_enhance`](crate::RemoveBgOptions::auto_enhance)).
+//!
+//! U2-Net was trained mostly on well-exposed photos and finds little subject
+//! in dark ones. The luminance between its 0.5th and 99.5th percentiles is
+//! stretched to the full range, and every channel is mapped the same way so
+//! hues stay put. Only the image fed to the model is changed; the composite
+//! uses the original pixels.
+//!
+//! Images whose luminance already spans most of the range are left alone,
+//! which also covers noise: stretching it would only amplify the noise. So
+//! are nearly flat images, which have nothing to stretch.
+
+use crate::error::{RemoveBgError, Result};
+use image::DynamicImage;
+
+/// Share of the pixels clipped at each end of the luminance range, so a few
+/// highlights or dead pixels don't decide the stretch.
+const CLIP: f32 = 0.005;
+
+/// Luminance range (of 255) at and above which the image counts as well
+/// exposed and isn't enhanced.
+const WIDE_RANGE: u8 = 160;
+
+/// Luminance range (of 255) below which the image counts as flat and isn't
+/// enhanced.
+const FLAT_RANGE: u8 = 4;
+
+/// Largest gain applied at full strength, so very dark images don't turn
+/// into amplified sensor noise.
+const MAX_GAIN: f32 = 8.0;
+
+/// Check that the enhancement strength is usable.
+pub(crate) fn check(strength: f32) -> Result<()> {
+    if !(0.0..=1.0).contains(&strength) {
+        return Err(RemoveBgError::ProcessingError(format!(
+            "Auto-enhance strength must be from 0 to 1, got {}",
+            strength
+        )));
+    }
+    Ok(())
+}
+
+/// The model's copy of `image` with its exposure normalized at `strength`
+/// (0-1), or `None` if the image doesn't need it. Alpha is kept. The stretch
+/// is logged at debug level, so `--verbose` shows it.
+pub(crate) fn enhance(image: &DynamicImage, strength: f32) -> Option<DynamicImage> {
+    let rgb = image.to_rgb8();
+    let mut histogram = [0u64; 256];
+    for pixel in rgb.pixels() {
+        histogram[luminance(pixel.0) as usize] += 1;
+    }
+    let total = rgb.width() as u64 * rgb.height() as u64;
+    let clipped = (total as f32 * CLIP) as u64;
+    let low = percentile(&histogram, clipped);
+    let high = percentile(&histogram, total.saturating_sub(clipped + 1));
+    let range = high.saturating_sub(low);
+    if range >= WIDE_RANGE {
+        log::debug!("auto-enhance: luminance already spans {}-{}; leaving the image as is", low, high);
+        return None;
+    }
+    if range < FLAT_RANGE {
+        log::debug!("auto-enhance: luminance only spans {}-{}; nothing to stretch", low, high);
+        return None;
+    }
+
+    let full = (255.0 / range as f32).min(MAX_GAIN);
+    let offset = low as f32 * strength;
+    let gain = 1.0 + (full - 1.0) * strength;
+    log::debug!(
+        "auto-enhance: stretching luminance {}-{} by {:.2}x at strength {}",
+        low,
+        high,
+        gain,
+        strength
+    );
+    let mut lut = [0u8; 256];
+    for (value, slot) in lut.iter_mut().enumerate() {
+        *slot = ((value as f32 - offset) * gain).round().clamp(0.0, 255.0) as u8;
+    }
+
+    let enhanced = if image.color().has_alpha() {
+        let mut rgba = image.to_rgba8();
+        for pixel in rgba.pixels_mut() {
+            for channel in &mut pixel.0[..3] {
+                *channel = lut[*channel as usize];
+            }
+        }
+        DynamicImage::ImageRgba8(rgba)
+    } else {
+        let mut rgb = rgb;
+        for channel in rgb.iter_mut() {
+            *channel = lut[*channel as usize];
+        }
+        DynamicImage::ImageRgb8(rgb)
+    };
+    Some(enhanced)
+}
+
+/// Rec. 601 luma of an 8-bit RGB pixel.
+fn luminance([r, g, b]: [u8; 3]) -> u8 {
+    ((77 * r as u32 + 150 * g as u32 + 29 * b as u32) >> 8) as u8
+}
+
+/// The value of the `rank`th pixel (0-based) in luminance order.
+fn percentile(histogram: &[u64; 256], rank: u64) -> u8 {
+    let mut seen = 0;
+    for (value, &count) in histogram.iter().enumerate() {
+        seen += count;
+        if seen > rank {
+            return value as u8;
+        }
+    }
+    255
+}
@@ -2,19 +2,76 @@
 //!
 //! This module defines custom error types for background removal operations,
 //! providing detailed error information for different failure scenarios.
+//!
+//! Errors raised while processing an input are wrapped in
+//! [`RemoveBgError::Context`], naming the [`Stage`] that failed and the
+//! input it was working on; [`RemoveBgError::root`] gives the error inside.
 
+use crate::frame::PixelFormat;
+use crate::model::Model;
+use std::any::Any;
+use std::fmt;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use thiserror::Error;
 
+/// A stage of processing one input, named by [`RemoveBgError::Context`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "lowercase"))]
+pub enum Stage {
+    /// Downloading a remote input.
+    Download,
+    /// Decoding the input image.
+    Decode,
+    /// Computing the mask: loading and running the model, or reading an
+    /// external mask.
+    Segmentation,
+    /// Scoring and refining the mask.
+    Postprocess,
+    /// Applying the mask to the image.
+    Composite,
+    /// Encoding and writing the outputs.
+    Encode,
+}
+
+impl Stage {
+    /// Stable machine-readable name of the stage, e.g. `"decode"`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Download => "download",
+            Stage::Decode => "decode",
+            Stage::Segmentation => "segmentation",
+            Stage::Postprocess => "postprocess",
+            Stage::Composite => "composite",
+            Stage::Encode => "encode",
+        }
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Stage::Download => "Download",
+            Stage::Decode => "Decoding",
+            Stage::Segmentation => "Segmentation",
+            Stage::Postprocess => "Mask refinement",
+            Stage::Composite => "Compositing",
+            Stage::Encode => "Encoding",
+        })
+    }
+}
+
 /// Main error type for background removal operations.
 #[derive(Error, Debug)]
 pub enum RemoveBgError {
     /// Input file was not found at the specified path.
-    #[error("Input file not found: {0}")]
-    FileNotFound(String),
+    #[error("Input file not found: {}", .0.display())]
+    FileNotFound(PathBuf),
 
     /// Input path exists but is not a file (e.g., it's a directory).
-    #[error("Input path is not a file: {0}")]
-    NotAFile(String),
+    #[error("Input path is not a file: {}", .0.display())]
+    NotAFile(PathBuf),
 
     /// Failed to read the input image file.
     #[error("Failed to read input file: {0}")]
@@ -24,18 +81,781 @@ pub enum RemoveBgError {
     #[error("Failed to process image: {0}")]
     ImageError(#[from] image::ImageError),
 
+    /// The input image exceeds the configured pixel limit.
+    #[error("Image is too large: {width}x{height} exceeds the limit of {limit} pixels")]
+    ImageTooLarge {
+        /// Width of the input image.
+        width: u32,
+        /// Height of the input image.
+        height: u32,
+        /// Configured maximum pixel count.
+        limit: u64,
+    },
+
+    /// The input image is smaller than the 2x2 pixels segmentation needs.
+    #[error("Image is too small: {width}x{height} is below the minimum of 2x2 pixels")]
+    ImageTooSmall {
+        /// Width of the input image.
+        width: u32,
+        /// Height of the input image.
+        height: u32,
+    },
+
+    /// A raw pixel buffer's dimensions or stride describe no image: a
+    /// dimension is zero, or rows are shorter than `width` pixels.
+    #[error(
+        "Invalid raw image layout: {width}x{height} {format} pixels with a stride of {stride} bytes (dimensions must be nonzero and the stride at least {} bytes)",
+        *.width as usize * .format.bytes_per_pixel()
+    )]
+    InvalidRawLayout {
+        /// Width given for the buffer.
+        width: u32,
+        /// Height given for the buffer.
+        height: u32,
+        /// Bytes from the start of one row to the start of the next.
+        stride: usize,
+        /// Layout of the pixels.
+        format: PixelFormat,
+    },
+
+    /// A raw pixel buffer ends before the last row its layout describes.
+    #[error("Raw image buffer is too small: {len} bytes, but the layout needs {needed}")]
+    RawBufferTooSmall {
+        /// Bytes the layout needs.
+        needed: usize,
+        /// Bytes in the buffer.
+        len: usize,
+    },
+
+    /// A page was selected that a multi-page input doesn't have (see
+    /// [`pages`](crate::RemoveBgOptions::pages)).
+    #[error("Page {page} is out of range: {} has {count} page(s)", .path.display())]
+    PageOutOfRange {
+        /// The input file.
+        path: PathBuf,
+        /// The page selected, counting from 1.
+        page: u32,
+        /// Number of pages the input has.
+        count: u32,
+    },
+
+    /// The input's content is not an image in a supported format, whatever
+    /// its extension says.
+    #[error(
+        "Unsupported input format: {} is not an image{}",
+        .path.display(),
+        .detected.as_ref().map(|detected| format!(" (detected: {})", detected)).unwrap_or_default()
+    )]
+    UnsupportedFormat {
+        /// The input as it was given, or `input data` for in-memory input.
+        path: PathBuf,
+        /// What the content looks like instead, such as `"PDF"`, `"HTML"` or
+        /// `"empty file"`; `None` if it wasn't recognized.
+        detected: Option<String>,
+    },
+
     /// ONNX model execution failed.
     #[error("Model inference failed: {0}")]
     ModelError(String),
 
+    /// A remote input could not be downloaded.
+    #[error("Failed to download input: {0}")]
+    DownloadError(String),
+
+    /// An object store refused the credentials, or found none, for an
+    /// `s3://`, `gs://` or `az://` input or output.
+    #[error("Access denied: {0}")]
+    AccessDenied(String),
+
+    /// An object store input or output could not be read or written, such as
+    /// on a network failure.
+    #[error("Object store error: {0}")]
+    StorageError(String),
+
     /// Model download or initialization failed.
     #[error("Model initialization failed: {0}")]
     ModelInitError(String),
 
-    /// Generic processing error.
+    /// A model that isn't cached is needed, and downloading it was declined
+    /// (see [`confirm_download`](crate::model::confirm_download)) or
+    /// downloads are disabled (`DownloadOptions::offline`).
+    #[error(
+        "Model {model} is not cached at {}{}",
+        .path.display(),
+        if *.offline {
+            " and downloads are disabled (offline); copy the model file there".to_string()
+        } else {
+            format!(
+                "; download it with `removebg model fetch {model}`, or pass --yes \
+                 (or set REMOVEBG_AUTO_DOWNLOAD=1) to download it without asking"
+            )
+        }
+    )]
+    ModelNotCached {
+        /// The model.
+        model: Model,
+        /// Where the model file would be cached.
+        path: PathBuf,
+        /// Whether downloads are disabled, rather than declined.
+        offline: bool,
+    },
+
+    /// An external mask cannot be applied to the image.
+    #[error("Invalid mask: {0}")]
+    InvalidMask(String),
+
+    /// The requested output format cannot be produced.
+    #[error("Invalid output format: {0}")]
+    InvalidOutputFormat(String),
+
+    /// The output file already exists and overwriting was not requested.
+    #[error("Output file already exists: {} (use --force to overwrite)", .0.display())]
+    OutputExists(PathBuf),
+
+    /// The directory the output would be written to does not exist.
+    #[error("Output directory does not exist: {} (use --create-dirs to create it)", .0.display())]
+    OutputDirectoryMissing(PathBuf),
+
+    /// The mask's confidence score is below
+    /// [`ConfidenceOptions::min_score`](crate::ConfidenceOptions::min_score),
+    /// e.g. because the image has no clear subject.
+    #[error("No clear subject found: confidence {score:.2} is below the minimum of {min_score:.2}")]
+    LowConfidence {
+        /// Confidence score of the mask (0.0-1.0).
+        score: f32,
+        /// Configured minimum score.
+        min_score: f32,
+    },
+
+    /// The input looks like the cutout of an earlier run and
+    /// [`reprocess`](crate::RemoveBgOptions::reprocess) is
+    /// [`Refuse`](crate::Reprocess::Refuse).
+    #[error("Input looks already processed: {} {reason} (use --use-mask to reapply a mask, or --allow-reprocess)", .path.display())]
+    AlreadyProcessed {
+        /// The input file.
+        path: PathBuf,
+        /// Why it looks processed, such as `"already has transparency"`.
+        reason: String,
+    },
+
+    /// A batch manifest has problems; nothing was processed. Each entry
+    /// names the line it was found on.
+    #[error("Invalid manifest: {}", .0.join("; "))]
+    InvalidManifest(Vec<String>),
+
+    /// The output could not be encoded within
+    /// [`max_file_size`](crate::RemoveBgOptions::max_file_size), even at the
+    /// smallest settings tried.
+    #[error(
+        "Output does not fit in {}: the smallest encoding was {}{}",
+        crate::format::decimal_size(*.limit),
+        crate::format::decimal_size(*.smallest),
+        if *.downscale_allowed { "" } else { " (--allow-downscale may help)" }
+    )]
+    OutputTooLarge {
+        /// The size limit in bytes.
+        limit: u64,
+        /// Size in bytes of the smallest encoding tried.
+        smallest: u64,
+        /// Whether scaling the image down was among the settings tried.
+        downscale_allowed: bool,
+    },
+
+    /// There isn't enough free space to write a file: a model download, or
+    /// an output by an estimate of its size (see
+    /// [`disk_space`](crate::RemoveBgOptions::disk_space)).
+    #[error(
+        "Not enough disk space: need {}, have {} at {}",
+        crate::format::decimal_size(*.needed),
+        crate::format::decimal_size(*.available),
+        .path.display()
+    )]
+    InsufficientDiskSpace {
+        /// Bytes the write needs, including a margin for downloads.
+        needed: u64,
+        /// Bytes free for this process.
+        available: u64,
+        /// Where the file would be written.
+        path: PathBuf,
+    },
+
+    /// Processing an image would take more memory than
+    /// [`max_memory_bytes`](crate::RemoveBgOptions::max_memory_bytes)
+    /// allows, by an estimate made before its buffers are allocated (see
+    /// [`memory`](crate::memory)).
+    #[error(
+        "Image needs about {} of memory, over the budget of {}; raise --max-memory or limit the size with --max-output-size",
+        crate::format::binary_size(*.estimated),
+        crate::format::binary_size(*.budget)
+    )]
+    MemoryBudgetExceeded {
+        /// Estimated peak bytes, with the mask upscaled in bands.
+        estimated: u64,
+        /// Configured budget in bytes.
+        budget: u64,
+    },
+
+    /// A setting read from the environment has an invalid value; the
+    /// message names the variable.
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+
+    /// The system clipboard is unavailable or holds no image.
+    #[error("Clipboard error: {0}")]
+    ClipboardError(String),
+
+    /// A ZIP archive input cannot be read: it is corrupt, encrypted, or
+    /// removebg was built without the `archive` feature.
+    #[error("Archive error: {0}")]
+    ArchiveError(String),
+
+    /// The operation was cancelled through a [`CancellationToken`](crate::CancellationToken).
+    #[error("Operation cancelled")]
+    Cancelled,
+
+    /// Processing took longer than [`timeout`](crate::RemoveBgOptions::timeout)
+    /// and was stopped; no output was written.
+    #[error(
+        "Timed out after {:.1}s{}",
+        .elapsed.as_secs_f64(),
+        .stage.map(|stage| format!(" in {}", stage.name())).unwrap_or_default()
+    )]
+    TimedOut {
+        /// Time spent when the timeout was noticed.
+        elapsed: Duration,
+        /// The stage that was running.
+        stage: Option<Stage>,
+    },
+
+    /// Generic processing error. Panics inside the library, such as a
+    /// decoder failing on a malformed file, are reported as this too.
     #[error("Failed to process image: {0}")]
     ProcessingError(String),
+
+    /// An error raised in one stage of processing an input, such as
+    /// `Decoding failed for photos/3172.jpg: Failed to process image: ...`.
+    #[error(
+        "{stage} failed{}: {source}",
+        .input.as_ref().map(|input| format!(" for {}", input.display())).unwrap_or_default()
+    )]
+    Context {
+        /// The stage that failed.
+        stage: Stage,
+        /// The input being processed; `None` for in-memory input.
+        input: Option<PathBuf>,
+        /// The error the stage raised.
+        source: Box<RemoveBgError>,
+    },
+}
+
+impl RemoveBgError {
+    /// Stable machine-readable name of the error variant, e.g. `"file_not_found"`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RemoveBgError::FileNotFound(_) => "file_not_found",
+            RemoveBgError::NotAFile(_) => "not_a_file",
+            RemoveBgError::IoError(_) => "io_error",
+            RemoveBgError::ImageError(_) => "image_error",
+            RemoveBgError::ImageTooLarge { .. } => "image_too_large",
+            RemoveBgError::ImageTooSmall { .. } => "image_too_small",
+            RemoveBgError::InvalidRawLayout { .. } => "invalid_raw_layout",
+            RemoveBgError::RawBufferTooSmall { .. } => "raw_buffer_too_small",
+            RemoveBgError::UnsupportedFormat { .. } => "unsupported_format",
+            RemoveBgError::PageOutOfRange { .. } => "page_out_of_range",
+            RemoveBgError::ModelError(_) => "model_error",
+            RemoveBgError::DownloadError(_) => "download_error",
+            RemoveBgError::AccessDenied(_) => "access_denied",
+            RemoveBgError::StorageError(_) => "storage_error",
+            RemoveBgError::ModelInitError(_) => "model_init_error",
+            RemoveBgError::ModelNotCached { .. } => "model_not_cached",
+            RemoveBgError::InvalidMask(_) => "invalid_mask",
+            RemoveBgError::InvalidOutputFormat(_) => "invalid_output_format",
+            RemoveBgError::OutputExists(_) => "output_exists",
+            RemoveBgError::OutputDirectoryMissing(_) => "output_directory_missing",
+            RemoveBgError::LowConfidence { .. } => "low_confidence",
+            RemoveBgError::AlreadyProcessed { .. } => "already_processed",
+            RemoveBgError::InvalidManifest(_) => "invalid_manifest",
+            RemoveBgError::InvalidConfig(_) => "invalid_config",
+            RemoveBgError::OutputTooLarge { .. } => "output_too_large",
+            RemoveBgError::InsufficientDiskSpace { .. } => "insufficient_disk_space",
+            RemoveBgError::MemoryBudgetExceeded { .. } => "memory_budget_exceeded",
+            RemoveBgError::ClipboardError(_) => "clipboard_error",
+            RemoveBgError::ArchiveError(_) => "archive_error",
+            RemoveBgError::Cancelled => "cancelled",
+            RemoveBgError::TimedOut { .. } => "timed_out",
+            RemoveBgError::ProcessingError(_) => "processing_error",
+            RemoveBgError::Context { source, .. } => source.kind(),
+        }
+    }
+
+    /// The error inside any [`Context`](RemoveBgError::Context) wrapper,
+    /// for matching on what went wrong.
+    pub fn root(&self) -> &RemoveBgError {
+        match self {
+            RemoveBgError::Context { source, .. } => source.root(),
+            error => error,
+        }
+    }
+
+    /// The stage that failed, if the error was raised while processing an input.
+    pub fn stage(&self) -> Option<Stage> {
+        match self {
+            RemoveBgError::Context { stage, .. } => Some(*stage),
+            RemoveBgError::TimedOut { stage, .. } => *stage,
+            _ => None,
+        }
+    }
+
+    /// The input being processed when the error was raised, if known.
+    pub fn input(&self) -> Option<&Path> {
+        match self {
+            RemoveBgError::Context { input, .. } => input.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Exit code of the `removebg` CLI for this error, one of
+    /// [`EXIT_CODES`]:
+    ///
+    /// | Code | Errors |
+    /// |------|--------|
+    /// | 1 | `FileNotFound` |
+    /// | 2 | Invalid input or options: `NotAFile`, `ImageError`, `ImageTooLarge`, `ImageTooSmall`, `InvalidRawLayout`, `RawBufferTooSmall`, `UnsupportedFormat`, `PageOutOfRange`, `InvalidOutputFormat`, `OutputTooLarge`, `MemoryBudgetExceeded`, `InvalidMask`, `OutputDirectoryMissing`, `InvalidManifest`, `InvalidConfig`, `ClipboardError`, `ArchiveError` |
+    /// | 3 | Anything else: `IoError`, `InsufficientDiskSpace`, `ProcessingError` |
+    /// | 4 | `OutputExists` |
+    /// | 6 | `DownloadError`, `AccessDenied`, `StorageError` |
+    /// | 7 | `LowConfidence`, `AlreadyProcessed` |
+    /// | 8 | `TimedOut` |
+    /// | 9 | `ModelInitError` |
+    /// | 10 | `ModelNotCached` |
+    /// | 11 | `ModelError` |
+    /// | 130 | `Cancelled` |
+    ///
+    /// A [`Context`](RemoveBgError::Context) has the code of the error
+    /// inside it. The codes are part of the CLI's interface: a code is
+    /// never reused for other errors.
+    #[deny(clippy::wildcard_enum_match_arm)]
+    pub fn exit_code(&self) -> i32 {
+        match self.root() {
+            RemoveBgError::FileNotFound(_) => 1,
+            RemoveBgError::NotAFile(_)
+            | RemoveBgError::ImageError(_)
+            | RemoveBgError::ImageTooLarge { .. }
+            | RemoveBgError::ImageTooSmall { .. }
+            | RemoveBgError::InvalidRawLayout { .. }
+            | RemoveBgError::RawBufferTooSmall { .. }
+            | RemoveBgError::UnsupportedFormat { .. }
+            | RemoveBgError::PageOutOfRange { .. }
+            | RemoveBgError::InvalidOutputFormat(_)
+            | RemoveBgError::OutputTooLarge { .. }
+            | RemoveBgError::MemoryBudgetExceeded { .. }
+            | RemoveBgError::InvalidMask(_)
+            | RemoveBgError::OutputDirectoryMissing(_)
+            | RemoveBgError::InvalidManifest(_)
+            | RemoveBgError::InvalidConfig(_)
+            | RemoveBgError::ClipboardError(_)
+            | RemoveBgError::ArchiveError(_) => 2,
+            RemoveBgError::OutputExists(_) => 4,
+            RemoveBgError::DownloadError(_) | RemoveBgError::AccessDenied(_) | RemoveBgError::StorageError(_) => 6,
+            RemoveBgError::LowConfidence { .. } | RemoveBgError::AlreadyProcessed { .. } => 7,
+            RemoveBgError::TimedOut { .. } => 8,
+            RemoveBgError::ModelInitError(_) => 9,
+            RemoveBgError::ModelNotCached { .. } => 10,
+            RemoveBgError::ModelError(_) => 11,
+            RemoveBgError::Cancelled => 130,
+            RemoveBgError::IoError(_)
+            | RemoveBgError::InsufficientDiskSpace { .. }
+            | RemoveBgError::ProcessingError(_)
+            | RemoveBgError::Context { .. } => 3,
+        }
+    }
+
+    /// Wrap the error in a [`Context`](RemoveBgError::Context) naming
+    /// `stage`.
+    ///
+    /// Errors that already name their input or stage are returned as they
+    /// are: paths that were checked before processing started, model loading,
+    /// cancellation, and errors that already have a context. A timeout
+    /// records the stage itself.
+    pub(crate) fn in_stage(self, stage: Stage) -> Self {
+        match self {
+            RemoveBgError::TimedOut { elapsed, stage: None } => RemoveBgError::TimedOut { elapsed, stage: Some(stage) },
+            RemoveBgError::FileNotFound(_)
+            | RemoveBgError::NotAFile(_)
+            | RemoveBgError::UnsupportedFormat { .. }
+            | RemoveBgError::PageOutOfRange { .. }
+            | RemoveBgError::OutputExists(_)
+            | RemoveBgError::OutputDirectoryMissing(_)
+            | RemoveBgError::AlreadyProcessed { .. }
+            | RemoveBgError::ModelInitError(_)
+            | RemoveBgError::ModelNotCached { .. }
+            | RemoveBgError::Cancelled
+            | RemoveBgError::TimedOut { .. }
+            | RemoveBgError::Context { .. } => self,
+            error => RemoveBgError::Context { stage, input: None, source: Box::new(error) },
+        }
+    }
+
+    /// Record `input` as the input a [`Context`](RemoveBgError::Context)
+    /// was processing, unless it already names one. Other errors are returned
+    /// as they are.
+    pub(crate) fn for_input(self, input: &Path) -> Self {
+        match self {
+            RemoveBgError::Context { stage, input: None, source } => {
+                RemoveBgError::Context { stage, input: Some(input.to_path_buf()), source }
+            }
+            error => error,
+        }
+    }
+}
+
+/// What went wrong, in a form that can be stored or sent elsewhere, e.g. as
+/// the `error` object of the CLI's `--json` output.
+///
+/// # Examples
+/// ```
+/// use removebg::{ErrorInfo, RemoveBgError};
+///
+/// let info = ErrorInfo::from(&RemoveBgError::FileNotFound("photo.jpg".into()));
+/// assert_eq!(info.kind, "file_not_found");
+/// assert_eq!(info.message, "Input file not found: photo.jpg");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ErrorInfo {
+    /// [`RemoveBgError::kind`] of the error, e.g. `"file_not_found"`.
+    pub kind: String,
+    /// The error's message.
+    pub message: String,
+    /// [`RemoveBgError::stage`]: the stage that failed, if known.
+    pub stage: Option<Stage>,
+    /// [`RemoveBgError::input`]: the input being processed, if known.
+    pub input: Option<PathBuf>,
+}
+
+impl From<&RemoveBgError> for ErrorInfo {
+    fn from(error: &RemoveBgError) -> Self {
+        ErrorInfo {
+            kind: error.kind().to_string(),
+            message: error.to_string(),
+            stage: error.stage(),
+            input: error.input().map(Path::to_path_buf),
+        }
+    }
+}
+
+/// One exit code of the `removebg` CLI, as listed by
+/// `removebg --list-exit-codes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ExitCodeInfo {
+    /// The process exit code.
+    pub code: i32,
+    /// Stable name of the code, e.g. `"file_not_found"`.
+    pub name: &'static str,
+    /// What the code means.
+    pub description: &'static str,
+    /// [`RemoveBgError::kind`] of the errors with this code.
+    pub kinds: &'static [&'static str],
+}
+
+impl ExitCodeInfo {
+    /// Rank of the code in the order of [`most_severe_exit_code`], 1 being
+    /// the most severe, or `None` for success.
+    pub fn severity(&self) -> Option<usize> {
+        SEVERITY.iter().position(|code| *code == self.code).map(|index| index + 1)
+    }
+}
+
+/// Every exit code of the `removebg` CLI, in ascending order.
+pub const EXIT_CODES: &[ExitCodeInfo] = &[
+    ExitCodeInfo { code: 0, name: "success", description: "Every input was processed", kinds: &[] },
+    ExitCodeInfo { code: 1, name: "file_not_found", description: "An input file was not found", kinds: &["file_not_found"] },
+    ExitCodeInfo {
+        code: 2,
+        name: "invalid_input",
+        description: "An input, mask, option or setting is invalid",
+        kinds: &[
+            "not_a_file",
+            "image_error",
+            "image_too_large",
+            "image_too_small",
+            "invalid_raw_layout",
+            "raw_buffer_too_small",
+            "unsupported_format",
+            "page_out_of_range",
+            "invalid_output_format",
+            "output_too_large",
+            "memory_budget_exceeded",
+            "invalid_mask",
+            "output_directory_missing",
+            "invalid_manifest",
+            "invalid_config",
+            "clipboard_error",
+            "archive_error",
+        ],
+    },
+    ExitCodeInfo {
+        code: 3,
+        name: "unexpected_error",
+        description: "An unexpected error, such as a failed read or write or a full disk",
+        kinds: &["io_error", "insufficient_disk_space", "processing_error"],
+    },
+    ExitCodeInfo { code: 4, name: "output_exists", description: "An output file already exists", kinds: &["output_exists"] },
+    ExitCodeInfo {
+        code: 5,
+        name: "partial_failure",
+        description: "Some --emit outputs could not be written, or images of an object store prefix or ZIP archive failed",
+        kinds: &[],
+    },
+    ExitCodeInfo {
+        code: 6,
+        name: "download_failed",
+        description: "A URL, object store input or model could not be downloaded or stored",
+        kinds: &["download_error", "access_denied", "storage_error"],
+    },
+    ExitCodeInfo {
+        code: 7,
+        name: "low_confidence",
+        description: "No clear subject was found, or the input was already processed",
+        kinds: &["low_confidence", "already_processed"],
+    },
+    ExitCodeInfo { code: 8, name: "timed_out", description: "An input ran out of its --timeout", kinds: &["timed_out"] },
+    ExitCodeInfo {
+        code: 9,
+        name: "model_init_failed",
+        description: "The model or its runtime could not be loaded",
+        kinds: &["model_init_error"],
+    },
+    ExitCodeInfo {
+        code: 10,
+        name: "model_not_cached",
+        description: "The model is not cached and may not be downloaded",
+        kinds: &["model_not_cached"],
+    },
+    ExitCodeInfo { code: 11, name: "inference_failed", description: "Running the model failed", kinds: &["model_error"] },
+    ExitCodeInfo { code: 130, name: "cancelled", description: "Interrupted with Ctrl-C", kinds: &["cancelled"] },
+];
+
+/// Exit codes of failures, most severe first. A run over several inputs
+/// exits with the most severe code among its failures: problems with the
+/// model or the machine before problems with single inputs.
+const SEVERITY: [i32; 12] = [130, 9, 10, 3, 11, 6, 8, 5, 4, 2, 1, 7];
+
+/// The most severe of `codes` by the order `--list-exit-codes` shows, or
+/// `None` if there are none. Codes of successes and unknown codes come last.
+///
+/// # Examples
+/// ```
+/// use removebg::{most_severe_exit_code, RemoveBgError};
+///
+/// let errors = [RemoveBgError::FileNotFound("a.jpg".into()), RemoveBgError::ModelError("NaN output".into())];
+/// assert_eq!(most_severe_exit_code(errors.iter().map(RemoveBgError::exit_code)), Some(11));
+/// ```
+pub fn most_severe_exit_code(codes: impl IntoIterator<Item = i32>) -> Option<i32> {
+    codes.into_iter().min_by_key(|code| SEVERITY.iter().position(|severe| severe == code).unwrap_or(SEVERITY.len()))
+}
+
+/// `error` for a log line about `input`, led by the input unless the error
+/// already names it.
+pub(crate) fn describe(input: &Path, error: &RemoveBgError) -> String {
+    match error.input() {
+        Some(_) => error.to_string(),
+        None => format!("{}: {}", input.display(), error),
+    }
+}
+
+/// Run `f`, turning a panic into a `ProcessingError` with the panic's
+/// message, so none escapes the processing entry points.
+pub(crate) fn catch_panic<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+    std::panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| Err(panic_error(&*payload)))
+}
+
+/// The error a caught panic is reported as.
+pub(crate) fn panic_error(payload: &(dyn Any + Send)) -> RemoveBgError {
+    RemoveBgError::ProcessingError(format!("internal error: {}", panic_message(payload)))
+}
+
+/// The message a caught panic was raised with.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<String>()
+        .map(String::as_str)
+        .or_else(|| payload.downcast_ref::<&str>().copied())
+        .unwrap_or("unknown panic")
 }
 
 /// Result type alias for RemoveBG operations.
 pub type Result<T> = std::result::Result<T, RemoveBgError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as _;
+
+    fn io_error() -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::PermissionDenied, "denied")
+    }
+
+    /// One error of each variant with its documented exit code. The match
+    /// stops compiling when a variant is added, so it gets a case here.
+    fn every_error() -> Vec<(RemoveBgError, i32)> {
+        let path = || PathBuf::from("photo.jpg");
+        let image = image::ImageError::Unsupported(image::error::UnsupportedError::from_format_and_kind(
+            image::error::ImageFormatHint::Unknown,
+            image::error::UnsupportedErrorKind::GenericFeature("test".into()),
+        ));
+        let cases = vec![
+            (RemoveBgError::FileNotFound(path()), 1),
+            (RemoveBgError::NotAFile(path()), 2),
+            (RemoveBgError::IoError(io_error()), 3),
+            (RemoveBgError::ImageError(image), 2),
+            (RemoveBgError::ImageTooLarge { width: 2, height: 2, limit: 1 }, 2),
+            (RemoveBgError::ImageTooSmall { width: 1, height: 9 }, 2),
+            (RemoveBgError::InvalidRawLayout { width: 0, height: 9, stride: 0, format: PixelFormat::Bgra8 }, 2),
+            (RemoveBgError::RawBufferTooSmall { needed: 12, len: 8 }, 2),
+            (RemoveBgError::UnsupportedFormat { path: path(), detected: None }, 2),
+            (RemoveBgError::PageOutOfRange { path: path(), page: 3, count: 2 }, 2),
+            (RemoveBgError::ModelError("x".into()), 11),
+            (RemoveBgError::DownloadError("x".into()), 6),
+            (RemoveBgError::AccessDenied("x".into()), 6),
+            (RemoveBgError::StorageError("x".into()), 6),
+            (RemoveBgError::ModelInitError("x".into()), 9),
+            (RemoveBgError::ModelNotCached { model: Model::U2net, path: path(), offline: true }, 10),
+            (RemoveBgError::InvalidMask("x".into()), 2),
+            (RemoveBgError::InvalidOutputFormat("x".into()), 2),
+            (RemoveBgError::OutputExists(path()), 4),
+            (RemoveBgError::OutputDirectoryMissing(path()), 2),
+            (RemoveBgError::LowConfidence { score: 0.1, min_score: 0.5 }, 7),
+            (RemoveBgError::AlreadyProcessed { path: path(), reason: "x".into() }, 7),
+            (RemoveBgError::InvalidManifest(vec!["line 1: x".into()]), 2),
+            (RemoveBgError::InvalidConfig("REMOVEBG_DEVICE: x".into()), 2),
+            (RemoveBgError::OutputTooLarge { limit: 1, smallest: 2, downscale_allowed: true }, 2),
+            (RemoveBgError::InsufficientDiskSpace { needed: 2, available: 1, path: path() }, 3),
+            (RemoveBgError::MemoryBudgetExceeded { estimated: 2, budget: 1 }, 2),
+            (RemoveBgError::ClipboardError("x".into()), 2),
+            (RemoveBgError::ArchiveError("x".into()), 2),
+            (RemoveBgError::Cancelled, 130),
+            (RemoveBgError::TimedOut { elapsed: Duration::from_secs(2), stage: None }, 8),
+            (RemoveBgError::ProcessingError("x".into()), 3),
+        ];
+        for (error, _) in &cases {
+            match error {
+                RemoveBgError::FileNotFound(_)
+                | RemoveBgError::NotAFile(_)
+                | RemoveBgError::IoError(_)
+                | RemoveBgError::ImageError(_)
+                | RemoveBgError::ImageTooLarge { .. }
+                | RemoveBgError::ImageTooSmall { .. }
+                | RemoveBgError::InvalidRawLayout { .. }
+                | RemoveBgError::RawBufferTooSmall { .. }
+                | RemoveBgError::UnsupportedFormat { .. }
+                | RemoveBgError::PageOutOfRange { .. }
+                | RemoveBgError::ModelError(_)
+                | RemoveBgError::DownloadError(_)
+                | RemoveBgError::AccessDenied(_)
+                | RemoveBgError::StorageError(_)
+                | RemoveBgError::ModelInitError(_)
+                | RemoveBgError::ModelNotCached { .. }
+                | RemoveBgError::InvalidMask(_)
+                | RemoveBgError::InvalidOutputFormat(_)
+                | RemoveBgError::OutputExists(_)
+                | RemoveBgError::OutputDirectoryMissing(_)
+                | RemoveBgError::LowConfidence { .. }
+                | RemoveBgError::AlreadyProcessed { .. }
+                | RemoveBgError::InvalidManifest(_)
+                | RemoveBgError::InvalidConfig(_)
+                | RemoveBgError::OutputTooLarge { .. }
+                | RemoveBgError::InsufficientDiskSpace { .. }
+                | RemoveBgError::MemoryBudgetExceeded { .. }
+                | RemoveBgError::ClipboardError(_)
+                | RemoveBgError::ArchiveError(_)
+                | RemoveBgError::Cancelled
+                | RemoveBgError::TimedOut { .. }
+                | RemoveBgError::ProcessingError(_)
+                | RemoveBgError::Context { .. } => {}
+            }
+        }
+        cases
+    }
+
+    #[test]
+    fn exit_codes_follow_the_root_error() {
+        for (error, code) in every_error() {
+            assert_eq!(error.exit_code(), code, "{:?}", error);
+            let kind = error.kind();
+            let wrapped = error.in_stage(Stage::Encode).for_input(Path::new("photo.jpg"));
+            assert_eq!(wrapped.exit_code(), code, "{:?}", wrapped);
+            assert_eq!(wrapped.kind(), kind);
+        }
+    }
+
+    #[test]
+    fn exit_code_table_lists_every_error_once() {
+        let errors = every_error();
+        for (error, code) in &errors {
+            let listed: Vec<_> = EXIT_CODES.iter().filter(|info| info.kinds.contains(&error.kind())).collect();
+            assert_eq!(listed.len(), 1, "{} is listed {} times", error.kind(), listed.len());
+            assert_eq!(listed[0].code, *code, "{}", error.kind());
+        }
+        for kind in EXIT_CODES.iter().flat_map(|info| info.kinds) {
+            assert!(errors.iter().any(|(error, _)| error.kind() == *kind), "no error has kind {}", kind);
+        }
+
+        // Codes are ascending and unique, and every failure has a severity
+        assert!(EXIT_CODES.windows(2).all(|pair| pair[0].code < pair[1].code));
+        for info in EXIT_CODES {
+            assert_eq!(info.severity().is_none(), info.code == 0, "{}", info.code);
+        }
+        assert_eq!(SEVERITY.len(), EXIT_CODES.len() - 1);
+    }
+
+    #[test]
+    fn the_most_severe_failure_wins() {
+        assert_eq!(most_severe_exit_code([]), None);
+        assert_eq!(most_severe_exit_code([7, 1, 2]), Some(2));
+        assert_eq!(most_severe_exit_code([2, 4, 8]), Some(8));
+        assert_eq!(most_severe_exit_code([6, 11, 3]), Some(3));
+        assert_eq!(most_severe_exit_code([1, 130, 9]), Some(130));
+        assert_eq!(most_severe_exit_code([42, 7]), Some(7));
+    }
+
+    #[test]
+    fn context_names_the_stage_and_input() {
+        let error = RemoveBgError::from(io_error()).in_stage(Stage::Decode).for_input(Path::new("photos/3172.jpg"));
+        assert_eq!(error.to_string(), "Decoding failed for photos/3172.jpg: Failed to read input file: denied");
+        assert_eq!((error.stage(), error.input()), (Some(Stage::Decode), Some(Path::new("photos/3172.jpg"))));
+        assert!(matches!(error.root(), RemoveBgError::IoError(_)));
+
+        // The source chain reaches the I/O error
+        let source = error.source().expect("the wrapped error");
+        assert_eq!(source.to_string(), "Failed to read input file: denied");
+        assert_eq!(source.source().expect("the I/O error").to_string(), "denied");
+
+        // The innermost stage and the first input win
+        let error = error.in_stage(Stage::Encode).for_input(Path::new("other.jpg"));
+        assert_eq!((error.stage(), error.input()), (Some(Stage::Decode), Some(Path::new("photos/3172.jpg"))));
+
+        let in_memory = RemoveBgError::ModelError("bad output".into()).in_stage(Stage::Segmentation);
+        assert_eq!(in_memory.to_string(), "Segmentation failed: Model inference failed: bad output");
+        assert!(in_memory.input().is_none());
+    }
+
+    #[test]
+    fn errors_naming_their_input_are_not_wrapped() {
+        let error = RemoveBgError::FileNotFound(PathBuf::from("photo.jpg")).in_stage(Stage::Decode);
+        assert!(error.stage().is_none());
+        assert!(matches!(RemoveBgError::Cancelled.in_stage(Stage::Segmentation), RemoveBgError::Cancelled));
+        assert!(RemoveBgError::ProcessingError("x".into()).for_input(Path::new("photo.jpg")).input().is_none());
+    }
+
+    #[test]
+    fn panics_become_processing_errors() {
+        let error = catch_panic::<()>(|| panic!("index out of bounds: {}", 7)).unwrap_err();
+        assert_eq!(error.to_string(), "Failed to process image: internal error: index out of bounds: 7");
+        assert_eq!(error.exit_code(), 3);
+        let error = catch_panic::<()>(|| std::panic::panic_any(7u8)).unwrap_err();
+        assert_eq!(error.to_string(), "Failed to process image: internal error: unknown panic");
+        assert_eq!(catch_panic(|| Ok(1)).unwrap(), 1);
+    }
+}
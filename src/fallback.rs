@@ -0,0 +1,367 @@
+//! Giving up quality to meet a deadline
+//! ([`quality_fallback`](crate::RemoveBgOptions::quality_fallback)).
+//!
+//! Whether an image fits its deadline is decided before the model runs, from
+//! the time it has taken so far and an estimate of the model run: the
+//! [`Calibration`] holds the measured time of one run of each model at its
+//! native size, scaled by the size the model runs at and the number of runs.
+//! Models not measured yet are estimated from one that was, by their rough
+//! cost relative to each other.
+
+use crate::error::Result;
+use crate::model::Model;
+use crate::options::{Backend, Device, InferenceSize, RemoveBgOptions};
+use crate::report::{Downgrade, StageDurations};
+use crate::resolution;
+use crate::tiling;
+use image::DynamicImage;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::Duration;
+
+/// Weight of a new measurement in the running average; the rest stays with
+/// the measurements before it, so one slow run moves the estimate by a fifth
+/// of the difference.
+const EWMA_WEIGHT: f64 = 0.2;
+
+/// The steps of [`QualityFallback`](crate::QualityFallback), in the order
+/// they are taken.
+const LADDER: [Downgrade; 3] = [Downgrade::Model, Downgrade::Refinement, Downgrade::InferenceSize];
+
+/// The model [`Downgrade::Model`] switches to.
+pub(crate) const FALLBACK_MODEL: Model = Model::U2netp;
+
+/// Measured inference times of the models on this machine: the time of one
+/// run of a model at its native size on a backend and device.
+///
+/// [`calibration`] is the one used for
+/// [`quality_fallback`](crate::RemoveBgOptions::quality_fallback). Removers
+/// loaded with it record their warm-up run, and every image processed with
+/// it records its model run, both as an exponentially weighted moving
+/// average. Applications that know their timings can [`set`](Self::set)
+/// them instead of waiting for the first run.
+#[derive(Debug, Default)]
+pub struct Calibration {
+    times: Mutex<HashMap<(Model, Backend, Device), Duration>>,
+}
+
+impl Calibration {
+    /// A calibration without measurements.
+    pub fn new() -> Self {
+        Calibration::default()
+    }
+
+    /// The measured time of one run of `model` at its native size with
+    /// `backend` on `device`, if it was measured.
+    pub fn per_run(&self, model: Model, backend: Backend, device: Device) -> Option<Duration> {
+        self.times().get(&(model, backend, device)).copied()
+    }
+
+    /// Replace the time of one run of `model` at its native size with
+    /// `backend` on `device` by `time`.
+    pub fn set(&self, model: Model, backend: Backend, device: Device, time: Duration) {
+        self.times().insert((model, backend, device), time);
+    }
+
+    /// Add a measured run of `model` at its native size with `backend` on
+    /// `device` to the running average; the first one is taken as it is.
+    pub fn record(&self, model: Model, backend: Backend, device: Device, time: Duration) {
+        let mut times = self.times();
+        let average = times
+            .get(&(model, backend, device))
+            .map_or(time, |&average| average.mul_f64(1.0 - EWMA_WEIGHT) + time.mul_f64(EWMA_WEIGHT));
+        times.insert((model, backend, device), average);
+    }
+
+    /// The time of one run at its native size of the model `options`
+    /// select: measured, or estimated from the first model measured on the
+    /// same backend and device.
+    fn estimate(&self, options: &RemoveBgOptions) -> Option<Duration> {
+        let times = self.times();
+        if let Some(&time) = times.get(&(options.model, options.backend, options.device)) {
+            return Some(time);
+        }
+        Model::ALL.iter().find_map(|&measured| {
+            let time = times.get(&(measured, options.backend, options.device))?;
+            Some(time.mul_f64(relative_cost(options.model) / relative_cost(measured)))
+        })
+    }
+
+    fn times(&self) -> std::sync::MutexGuard<'_, HashMap<(Model, Backend, Device), Duration>> {
+        self.times.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// The calibration of this process, shared by all removers.
+pub fn calibration() -> &'static Calibration {
+    static CALIBRATION: OnceLock<Calibration> = OnceLock::new();
+    CALIBRATION.get_or_init(Calibration::new)
+}
+
+/// Rough time of one run of `model` at its native size relative to u2net,
+/// for estimating models that weren't measured from ones that were.
+fn relative_cost(model: Model) -> f64 {
+    match model {
+        Model::U2net | Model::U2netHumanSeg => 1.0,
+        Model::U2netp => 0.3,
+        Model::U2netQuant => 0.7,
+        Model::Silueta => 0.8,
+        Model::BirefnetLite => 12.0,
+    }
+}
+
+/// What to run an image with to meet its deadline.
+#[derive(Debug)]
+pub(crate) struct Plan {
+    /// The options to run with, without `quality_fallback`.
+    pub(crate) options: RemoveBgOptions,
+    /// The steps taken to get there, in order.
+    pub(crate) downgrades: Vec<Downgrade>,
+}
+
+/// Plan the model run on `image` under `options.quality_fallback`, which
+/// has taken `spent` so far, taking the steps of [`LADDER`] until the
+/// estimate from `calibration` fits the deadline. Without an estimate, the
+/// image runs as configured.
+///
+/// # Errors
+/// * `ProcessingError` - If a fixed inference size is out of range
+pub(crate) fn plan(image: &DynamicImage, options: &RemoveBgOptions, spent: Duration, calibration: &Calibration) -> Result<Plan> {
+    let mut planned = RemoveBgOptions { quality_fallback: None, ..options.clone() };
+    let Some(deadline) = options.quality_fallback.map(|fallback| fallback.deadline) else {
+        return Ok(Plan { options: planned, downgrades: Vec::new() });
+    };
+    let Some(mut estimate) = estimate(image, &planned, calibration)? else {
+        log::debug!("no inference time of {} measured yet; running it as configured", options.model);
+        return Ok(Plan { options: planned, downgrades: Vec::new() });
+    };
+    let remaining = deadline.saturating_sub(spent);
+
+    let mut downgrades = Vec::new();
+    for downgrade in LADDER {
+        if estimate <= remaining {
+            break;
+        }
+        if !step_down(&mut planned, downgrade, image, estimate.as_secs_f32().max(f32::MIN_POSITIVE), remaining)? {
+            continue;
+        }
+        trace_event!("downgrade", step = downgrade.name());
+        downgrades.push(downgrade);
+        estimate = estimate_for(image, &planned, calibration)?;
+    }
+
+    if !downgrades.is_empty() {
+        let names: Vec<&str> = downgrades.iter().map(|downgrade| downgrade.name()).collect();
+        log::info!("Gave up {} to meet the {}ms deadline", names.join(", "), deadline.as_millis());
+    }
+    if estimate > remaining {
+        log::warn!(
+            "the model run is estimated to take {}ms, with {}ms of the deadline left",
+            estimate.as_millis(),
+            remaining.as_millis()
+        );
+    }
+    Ok(Plan { options: planned, downgrades })
+}
+
+/// Take `downgrade` on `options`, whose model run is estimated to take
+/// `estimate` seconds, to fit `remaining`. Returns whether it changed
+/// anything.
+fn step_down(options: &mut RemoveBgOptions, downgrade: Downgrade, image: &DynamicImage, estimate: f32, remaining: Duration) -> Result<bool> {
+    match downgrade {
+        Downgrade::Model => {
+            let changed = options.model != FALLBACK_MODEL;
+            options.model = FALLBACK_MODEL;
+            Ok(changed)
+        }
+        Downgrade::Refinement => {
+            let changed = options.two_pass || options.tiling.is_some() || options.guided_filter.is_some();
+            (options.two_pass, options.tiling, options.guided_filter) = (false, None, None);
+            Ok(changed)
+        }
+        Downgrade::InferenceSize => {
+            if options.inference_size == InferenceSize::Native {
+                return Ok(false);
+            }
+            let native = options.model.spec().input_size;
+            let (size, _) = resolution::resolve(options.inference_size, image, native, options.tiling.is_some())?;
+            let cost = resolution::relative_cost(size, native) * remaining.as_secs_f32() / estimate;
+            match resolution::smaller_within(size, native, cost) {
+                Some(smaller) => {
+                    options.inference_size = InferenceSize::Fixed(smaller);
+                    Ok(true)
+                }
+                None => Ok(false),
+            }
+        }
+    }
+}
+
+/// Estimated time of the model runs on `image` with `options`, or `None`
+/// if no model was measured on their backend and device.
+fn estimate(image: &DynamicImage, options: &RemoveBgOptions, calibration: &Calibration) -> Result<Option<Duration>> {
+    let Some(per_run) = calibration.estimate(options) else {
+        return Ok(None);
+    };
+    let native = options.model.spec().input_size;
+    let (size, _) = resolution::resolve(options.inference_size, image, native, options.tiling.is_some())?;
+    let runs = match options.tiling {
+        Some(tiling) => tiling::max_runs(image.width(), image.height(), tiling, native),
+        None => 1,
+    } + options.two_pass as u32;
+    Ok(Some(per_run.mul_f32(resolution::relative_cost(size, native) * runs as f32)))
+}
+
+/// [`estimate`] once a model was measured.
+fn estimate_for(image: &DynamicImage, options: &RemoveBgOptions, calibration: &Calibration) -> Result<Duration> {
+    Ok(estimate(image, options, calibration)?.expect("a model on the backend and device was measured"))
+}
+
+/// `options` with the model [`Downgrade::Model`] switches to, for servers
+/// to load ahead of time; `None` without `quality_fallback` or when that is
+/// the model already.
+pub(crate) fn fallback_options(options: &RemoveBgOptions) -> Option<RemoveBgOptions> {
+    (options.quality_fallback.is_some() && options.auto_model.is_none() && options.model != FALLBACK_MODEL)
+        .then(|| RemoveBgOptions { model: FALLBACK_MODEL, ..options.clone() })
+}
+
+/// Time an image has taken in the stages before its model run.
+pub(crate) fn spent(durations: &StageDurations) -> Duration {
+    durations.model_load + durations.warmup + durations.decode + durations.preprocess
+}
+
+/// Record a single model run of `options` at `relative_cost` times the cost
+/// of the native size that took `inference`.
+pub(crate) fn record(options: &RemoveBgOptions, relative_cost: f32, inference: Duration) {
+    if inference > Duration::ZERO && relative_cost > 0.0 {
+        calibration().record(options.model, options.backend, options.device, inference.div_f32(relative_cost));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::{GuidedFilterOptions, QualityFallback, TileOptions};
+    use image::RgbImage;
+
+    fn image() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::new(800, 600))
+    }
+
+    fn options(deadline: u64) -> RemoveBgOptions {
+        RemoveBgOptions {
+            model: Model::U2net,
+            two_pass: true,
+            guided_filter: Some(GuidedFilterOptions::default()),
+            inference_size: InferenceSize::Fixed(640),
+            quality_fallback: Some(QualityFallback { deadline: Duration::from_millis(deadline) }),
+            ..Default::default()
+        }
+    }
+
+    /// The default backend of this build, which the test options run on.
+    fn backend() -> Backend {
+        RemoveBgOptions::default().backend
+    }
+
+    /// u2net at 400ms a run and u2netp at 100ms, both at 320
+    fn calibration() -> Calibration {
+        let calibration = Calibration::new();
+        calibration.set(Model::U2net, backend(), Device::Cpu, Duration::from_millis(400));
+        calibration.set(Model::U2netp, backend(), Device::Cpu, Duration::from_millis(100));
+        calibration
+    }
+
+    fn downgrades(deadline: u64, spent: u64) -> (Vec<Downgrade>, RemoveBgOptions) {
+        let plan = plan(&image(), &options(deadline), Duration::from_millis(spent), &calibration()).unwrap();
+        assert!(plan.options.quality_fallback.is_none());
+        (plan.downgrades, plan.options)
+    }
+
+    #[track_caller]
+    fn assert_close(time: Option<Duration>, millis: u64) {
+        let time = time.expect("a time");
+        assert!(time.abs_diff(Duration::from_millis(millis)) < Duration::from_micros(1), "{:?}", time);
+    }
+
+    #[test]
+    fn the_ladder_is_taken_in_order_until_the_estimate_fits() {
+        // u2net at 640 with two passes: 2 x 4 x 400ms
+        let (taken, planned) = downgrades(4000, 0);
+        assert!(taken.is_empty());
+        assert_eq!(planned.model, Model::U2net);
+        assert!(planned.two_pass);
+
+        // u2netp at 640 with two passes: 2 x 4 x 100ms
+        let (taken, planned) = downgrades(1000, 0);
+        assert_eq!(taken, [Downgrade::Model]);
+        assert_eq!((planned.model, planned.inference_size), (Model::U2netp, InferenceSize::Fixed(640)));
+        assert!(planned.two_pass && planned.guided_filter.is_some());
+
+        // One pass: 4 x 100ms
+        let (taken, planned) = downgrades(500, 0);
+        assert_eq!(taken, [Downgrade::Model, Downgrade::Refinement]);
+        assert!(!planned.two_pass && planned.guided_filter.is_none());
+        assert_eq!(planned.inference_size, InferenceSize::Fixed(640));
+
+        // 200ms left is twice the cost of 320: 448
+        let (taken, planned) = downgrades(300, 100);
+        assert_eq!(taken, [Downgrade::Model, Downgrade::Refinement, Downgrade::InferenceSize]);
+        assert_eq!(planned.inference_size, InferenceSize::Fixed(448));
+
+        // Nothing fits: everything is given up, down to the smallest size
+        let (taken, planned) = downgrades(100, 500);
+        assert_eq!(taken.len(), 3);
+        assert_eq!(planned.inference_size, InferenceSize::Fixed(128));
+    }
+
+    #[test]
+    fn steps_that_change_nothing_are_left_out() {
+        let calibration = calibration();
+        let options = RemoveBgOptions {
+            model: Model::U2netp,
+            inference_size: InferenceSize::Native,
+            tiling: Some(TileOptions::default()),
+            quality_fallback: Some(QualityFallback { deadline: Duration::from_millis(50) }),
+            ..Default::default()
+        };
+        let plan = plan(&image(), &options, Duration::ZERO, &calibration).unwrap();
+        // Already u2netp, and the native size isn't lowered
+        assert_eq!(plan.downgrades, [Downgrade::Refinement]);
+        assert!(plan.options.tiling.is_none());
+        assert_eq!(plan.options.inference_size, InferenceSize::Native);
+    }
+
+    #[test]
+    fn without_measurements_images_run_as_configured() {
+        let plan = plan(&image(), &options(1), Duration::ZERO, &Calibration::new()).unwrap();
+        assert!(plan.downgrades.is_empty());
+        assert_eq!(plan.options.model, Model::U2net);
+
+        // Other devices' measurements don't count
+        let calibration = Calibration::new();
+        calibration.set(Model::U2net, backend(), Device::CoreMl, Duration::from_secs(1));
+        assert!(super::plan(&image(), &options(1), Duration::ZERO, &calibration).unwrap().downgrades.is_empty());
+    }
+
+    #[test]
+    fn unmeasured_models_are_estimated_from_measured_ones() {
+        let calibration = Calibration::new();
+        calibration.set(Model::U2net, backend(), Device::Cpu, Duration::from_millis(400));
+        let u2netp = RemoveBgOptions { model: Model::U2netp, ..Default::default() };
+        assert_close(calibration.estimate(&u2netp), 120);
+        assert_eq!(calibration.per_run(Model::U2netp, backend(), Device::Cpu), None);
+    }
+
+    #[test]
+    fn measurements_are_averaged() {
+        let calibration = Calibration::new();
+        let per_run = || calibration.per_run(Model::U2netp, backend(), Device::Cpu);
+        calibration.record(Model::U2netp, backend(), Device::Cpu, Duration::from_millis(100));
+        assert_close(per_run(), 100);
+        calibration.record(Model::U2netp, backend(), Device::Cpu, Duration::from_millis(200));
+        assert_close(per_run(), 120);
+        calibration.set(Model::U2netp, backend(), Device::Cpu, Duration::from_millis(50));
+        assert_eq!(per_run(), Some(Duration::from_millis(50)));
+    }
+}
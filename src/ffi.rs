@@ -0,0 +1,423 @@
+//! C API for embedding removebg in non-Rust applications (`capi` feature).
+//!
+//! The functions here are `extern "C"` and declared in `include/removebg.h`,
+//! which the build script regenerates with cbindgen whenever the feature is
+//! enabled. Build a static library with
+//! `cargo rustc --lib --release --crate-type staticlib --features capi`.
+//!
+//! # Conventions
+//!
+//! * Functions return [`REMOVEBG_OK`] or one of the `REMOVEBG_ERROR_*`
+//!   codes, which mirror the [`RemoveBgError`] variants. The message of the
+//!   last failed call on a handle is available from [`removebg_last_error`].
+//! * Strings are NUL-terminated UTF-8.
+//! * Buffers returned through `out_ptr` belong to the caller, who releases
+//!   them with [`removebg_free_buffer`]; handles are released with
+//!   [`removebg_free`]. Nothing else needs freeing.
+//! * Panics never cross the boundary; they are reported as
+//!   [`REMOVEBG_ERROR_PANIC`].
+//! * A handle may be used from any thread, but not from two at once.
+
+use crate::error::{self, RemoveBgError};
+use crate::frame::PixelFormat;
+use crate::options::RemoveBgOptions;
+use crate::remover::BackgroundRemover;
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+/// The call succeeded.
+pub const REMOVEBG_OK: c_int = 0;
+/// The input file does not exist.
+pub const REMOVEBG_ERROR_FILE_NOT_FOUND: c_int = 1;
+/// The input path is not a file.
+pub const REMOVEBG_ERROR_NOT_A_FILE: c_int = 2;
+/// Reading or writing a file failed.
+pub const REMOVEBG_ERROR_IO: c_int = 3;
+/// The image could not be decoded or encoded, or lacks the selected page.
+pub const REMOVEBG_ERROR_IMAGE: c_int = 4;
+/// The image exceeds the pixel limit.
+pub const REMOVEBG_ERROR_IMAGE_TOO_LARGE: c_int = 5;
+/// Model inference failed.
+pub const REMOVEBG_ERROR_MODEL: c_int = 6;
+/// A remote input could not be downloaded, or an object store input or
+/// output could not be read or written.
+pub const REMOVEBG_ERROR_DOWNLOAD: c_int = 7;
+/// The model could not be downloaded or loaded.
+pub const REMOVEBG_ERROR_MODEL_INIT: c_int = 8;
+/// An external mask cannot be applied.
+pub const REMOVEBG_ERROR_INVALID_MASK: c_int = 9;
+/// The output format cannot be produced.
+pub const REMOVEBG_ERROR_INVALID_OUTPUT_FORMAT: c_int = 10;
+/// The output file exists and overwriting is off.
+pub const REMOVEBG_ERROR_OUTPUT_EXISTS: c_int = 11;
+/// The output directory does not exist.
+pub const REMOVEBG_ERROR_OUTPUT_DIRECTORY_MISSING: c_int = 12;
+/// The mask's confidence is below the configured minimum, or the input
+/// looks like an earlier cutout and reprocessing is refused.
+pub const REMOVEBG_ERROR_LOW_CONFIDENCE: c_int = 13;
+/// The operation was cancelled.
+pub const REMOVEBG_ERROR_CANCELLED: c_int = 14;
+/// Any other processing failure.
+pub const REMOVEBG_ERROR_PROCESSING: c_int = 15;
+/// The input is not an image in a supported format.
+pub const REMOVEBG_ERROR_UNSUPPORTED_FORMAT: c_int = 16;
+/// The input image is smaller than 2x2 pixels.
+pub const REMOVEBG_ERROR_IMAGE_TOO_SMALL: c_int = 17;
+/// Processing took longer than the configured timeout.
+pub const REMOVEBG_ERROR_TIMED_OUT: c_int = 18;
+/// A raw pixel buffer's dimensions, stride or length don't fit together.
+pub const REMOVEBG_ERROR_INVALID_BUFFER: c_int = 19;
+/// There isn't enough free disk space for the model download or the output.
+pub const REMOVEBG_ERROR_INSUFFICIENT_DISK_SPACE: c_int = 20;
+/// Processing the image would exceed the memory budget.
+pub const REMOVEBG_ERROR_MEMORY_BUDGET_EXCEEDED: c_int = 21;
+/// Raw pixels in the order red, green, blue.
+pub const REMOVEBG_PIXEL_RGB8: c_int = 0;
+/// Raw pixels in the order red, green, blue, alpha.
+pub const REMOVEBG_PIXEL_RGBA8: c_int = 1;
+/// Raw pixels in the order blue, green, red.
+pub const REMOVEBG_PIXEL_BGR8: c_int = 2;
+/// Raw pixels in the order blue, green, red, alpha.
+pub const REMOVEBG_PIXEL_BGRA8: c_int = 3;
+
+/// A required pointer was NULL or a string was not valid UTF-8.
+pub const REMOVEBG_ERROR_INVALID_ARGUMENT: c_int = -1;
+/// removebg panicked; the handle should be freed.
+pub const REMOVEBG_ERROR_PANIC: c_int = -2;
+
+/// Settings for [`removebg_init`]. NULL strings and zero fields keep the
+/// defaults.
+#[repr(C)]
+pub struct RemoveBgConfig {
+    /// Model name: "u2net" (default), "u2netp", "u2net-quant", "u2net-human-seg", "silueta" or "birefnet-lite".
+    pub model: *const c_char,
+    /// Directory the model is cached in.
+    pub model_dir: *const c_char,
+    /// Inference engine: "ort" or "tract".
+    pub backend: *const c_char,
+    /// Output format, e.g. "png" (default) or "webp". Also applies to
+    /// removebg_process_file unless the output path has an extension.
+    pub format: *const c_char,
+    /// Non-zero to overwrite existing output files.
+    pub overwrite: c_int,
+}
+
+/// A loaded model, created by [`removebg_init`].
+pub struct RemoveBgHandle {
+    remover: BackgroundRemover,
+    last_error: Option<CString>,
+}
+
+thread_local! {
+    /// Why the last [`removebg_init`] on this thread failed.
+    static INIT_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Load the model and create a handle.
+///
+/// `config` may be NULL for the defaults. The model is downloaded on first
+/// use. Returns NULL on failure; `removebg_last_error(NULL)` then describes
+/// the error.
+///
+/// # Safety
+/// `config` must be NULL or point to a valid [`RemoveBgConfig`] whose strings
+/// are NULL or NUL-terminated.
+#[no_mangle]
+pub unsafe extern "C" fn removebg_init(config: *const RemoveBgConfig) -> *mut RemoveBgHandle {
+    let result = guard(|| {
+        let options = unsafe { config_options(config) }?;
+        let remover = BackgroundRemover::new(options).map_err(error_status)?;
+        Ok(RemoveBgHandle { remover, last_error: None })
+    });
+    match result {
+        Ok(handle) => {
+            INIT_ERROR.with(|error| error.borrow_mut().take());
+            Box::into_raw(Box::new(handle))
+        }
+        Err((_, message)) => {
+            INIT_ERROR.with(|error| *error.borrow_mut() = Some(c_string(message)));
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Remove the background from the image file `in_path` and write the cutout
+/// to `out_path`.
+///
+/// `out_path` may be NULL to write `<input>_nobg.<format>` next to the input.
+///
+/// # Safety
+/// `handle` must come from [`removebg_init`] and not be freed; the paths must
+/// be NULL or NUL-terminated.
+#[no_mangle]
+pub unsafe extern "C" fn removebg_process_file(
+    handle: *mut RemoveBgHandle,
+    in_path: *const c_char,
+    out_path: *const c_char,
+) -> c_int {
+    let Some(handle) = (unsafe { handle.as_mut() }) else {
+        return REMOVEBG_ERROR_INVALID_ARGUMENT;
+    };
+    let remover = &handle.remover;
+    let result = guard(|| {
+        let input = unsafe { str_arg(in_path, "in_path") }?
+            .ok_or_else(|| (REMOVEBG_ERROR_INVALID_ARGUMENT, "in_path is NULL".to_string()))?;
+        let output = unsafe { str_arg(out_path, "out_path") }?;
+        remover.process_file(input, output.map(Path::new)).map(drop).map_err(error_status)
+    });
+    finish(handle, result)
+}
+
+/// Remove the background from the encoded image in `data[0..len]` and return
+/// the encoded cutout in `*out_ptr` / `*out_len`.
+///
+/// On success the caller owns `*out_ptr` and must release it with
+/// [`removebg_free_buffer`]. On failure `*out_ptr` is set to NULL and
+/// `*out_len` to 0.
+///
+/// # Safety
+/// `handle` must come from [`removebg_init`] and not be freed; `data` must
+/// point to `len` readable bytes; `out_ptr` and `out_len` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn removebg_process_bytes(
+    handle: *mut RemoveBgHandle,
+    data: *const u8,
+    len: usize,
+    out_ptr: *mut *mut u8,
+    out_len: *mut usize,
+) -> c_int {
+    let Some(handle) = (unsafe { handle.as_mut() }) else {
+        return REMOVEBG_ERROR_INVALID_ARGUMENT;
+    };
+    if out_ptr.is_null() || out_len.is_null() {
+        return finish(handle, Err((REMOVEBG_ERROR_INVALID_ARGUMENT, "out_ptr or out_len is NULL".into())));
+    }
+    unsafe {
+        *out_ptr = ptr::null_mut();
+        *out_len = 0;
+    }
+
+    let remover = &handle.remover;
+    let result = guard(|| {
+        if data.is_null() {
+            return Err((REMOVEBG_ERROR_INVALID_ARGUMENT, "data is NULL".into()));
+        }
+        let data = unsafe { std::slice::from_raw_parts(data, len) };
+        remover.process_bytes(data).map_err(error_status)
+    });
+    let result = result.map(|encoded| {
+        let encoded = Box::into_raw(encoded.into_boxed_slice());
+        unsafe {
+            *out_len = encoded.len();
+            *out_ptr = encoded.cast();
+        }
+    });
+    finish(handle, result)
+}
+
+/// Remove the background from a raw frame of `width` x `height` pixels in
+/// `format` (one of the `REMOVEBG_PIXEL_*` values), whose rows start
+/// `stride` bytes apart in `data[0..len]`, and return the cutout as 8-bit
+/// RGBA pixels, `width * 4` bytes a row, in `*out_ptr` / `*out_len`.
+///
+/// Ownership of the result and the handling of failures are as for
+/// [`removebg_process_bytes`]. A layout that doesn't fit `len` fails with
+/// [`REMOVEBG_ERROR_INVALID_BUFFER`].
+///
+/// # Safety
+/// `handle` must come from [`removebg_init`] and not be freed; `data` must
+/// point to `len` readable bytes; `out_ptr` and `out_len` must be writable.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn removebg_process_raw(
+    handle: *mut RemoveBgHandle,
+    data: *const u8,
+    len: usize,
+    width: u32,
+    height: u32,
+    stride: usize,
+    format: c_int,
+    out_ptr: *mut *mut u8,
+    out_len: *mut usize,
+) -> c_int {
+    let Some(handle) = (unsafe { handle.as_mut() }) else {
+        return REMOVEBG_ERROR_INVALID_ARGUMENT;
+    };
+    if out_ptr.is_null() || out_len.is_null() {
+        return finish(handle, Err((REMOVEBG_ERROR_INVALID_ARGUMENT, "out_ptr or out_len is NULL".into())));
+    }
+    unsafe {
+        *out_ptr = ptr::null_mut();
+        *out_len = 0;
+    }
+
+    let remover = &handle.remover;
+    let result = guard(|| {
+        if data.is_null() {
+            return Err((REMOVEBG_ERROR_INVALID_ARGUMENT, "data is NULL".into()));
+        }
+        let format = match format {
+            REMOVEBG_PIXEL_RGB8 => PixelFormat::Rgb8,
+            REMOVEBG_PIXEL_RGBA8 => PixelFormat::Rgba8,
+            REMOVEBG_PIXEL_BGR8 => PixelFormat::Bgr8,
+            REMOVEBG_PIXEL_BGRA8 => PixelFormat::Bgra8,
+            other => return Err((REMOVEBG_ERROR_INVALID_ARGUMENT, format!("unknown pixel format {}", other))),
+        };
+        let data = unsafe { std::slice::from_raw_parts(data, len) };
+        remover.process_raw(data, width, height, stride, format).map(|cutout| cutout.into_raw()).map_err(error_status)
+    });
+    let result = result.map(|pixels| {
+        let pixels = Box::into_raw(pixels.into_boxed_slice());
+        unsafe {
+            *out_len = pixels.len();
+            *out_ptr = pixels.cast();
+        }
+    });
+    finish(handle, result)
+}
+
+/// Message of the last failed call on `handle`, or of the last failed
+/// [`removebg_init`] on this thread if `handle` is NULL.
+///
+/// Returns NULL if that call succeeded. The string is owned by removebg and
+/// stays valid until the next call with the same handle (or the next
+/// [`removebg_init`] for NULL).
+///
+/// # Safety
+/// `handle` must be NULL or come from [`removebg_init`] and not be freed.
+#[no_mangle]
+pub unsafe extern "C" fn removebg_last_error(handle: *const RemoveBgHandle) -> *const c_char {
+    match unsafe { handle.as_ref() } {
+        Some(handle) => handle.last_error.as_ref().map_or(ptr::null(), |message| message.as_ptr()),
+        None => INIT_ERROR.with(|error| error.borrow().as_ref().map_or(ptr::null(), |message| message.as_ptr())),
+    }
+}
+
+/// Release a handle and its model. NULL is ignored.
+///
+/// # Safety
+/// `handle` must be NULL or come from [`removebg_init`], and must not be used
+/// afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn removebg_free(handle: *mut RemoveBgHandle) {
+    if !handle.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(unsafe { Box::from_raw(handle) })));
+    }
+}
+
+/// Release a buffer returned by [`removebg_process_bytes`] or
+/// [`removebg_process_raw`]. NULL is ignored.
+///
+/// # Safety
+/// `ptr` and `len` must be exactly as returned by removebg, and the buffer
+/// must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn removebg_free_buffer(ptr: *mut u8, len: usize) {
+    if !ptr.is_null() {
+        drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(ptr, len)) });
+    }
+}
+
+/// Failed call: status code and message.
+type Failure = (c_int, String);
+
+/// Run `f`, turning a panic into [`REMOVEBG_ERROR_PANIC`].
+fn guard<T>(f: impl FnOnce() -> Result<T, Failure>) -> Result<T, Failure> {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => result,
+        Err(payload) => Err((REMOVEBG_ERROR_PANIC, format!("removebg panicked: {}", error::panic_message(&*payload)))),
+    }
+}
+
+/// Record the outcome of a call on `handle` and return its status.
+fn finish(handle: &mut RemoveBgHandle, result: Result<(), Failure>) -> c_int {
+    match result {
+        Ok(()) => {
+            handle.last_error = None;
+            REMOVEBG_OK
+        }
+        Err((status, message)) => {
+            handle.last_error = Some(c_string(message));
+            status
+        }
+    }
+}
+
+fn error_status(error: RemoveBgError) -> Failure {
+    let status = match error.root() {
+        RemoveBgError::FileNotFound(_) => REMOVEBG_ERROR_FILE_NOT_FOUND,
+        RemoveBgError::NotAFile(_) => REMOVEBG_ERROR_NOT_A_FILE,
+        RemoveBgError::IoError(_) => REMOVEBG_ERROR_IO,
+        RemoveBgError::ImageError(_) | RemoveBgError::PageOutOfRange { .. } => REMOVEBG_ERROR_IMAGE,
+        RemoveBgError::ImageTooLarge { .. } => REMOVEBG_ERROR_IMAGE_TOO_LARGE,
+        RemoveBgError::ImageTooSmall { .. } => REMOVEBG_ERROR_IMAGE_TOO_SMALL,
+        RemoveBgError::InvalidRawLayout { .. } | RemoveBgError::RawBufferTooSmall { .. } => REMOVEBG_ERROR_INVALID_BUFFER,
+        RemoveBgError::ModelError(_) => REMOVEBG_ERROR_MODEL,
+        RemoveBgError::DownloadError(_) | RemoveBgError::AccessDenied(_) | RemoveBgError::StorageError(_) => {
+            REMOVEBG_ERROR_DOWNLOAD
+        }
+        RemoveBgError::ModelInitError(_) | RemoveBgError::ModelNotCached { .. } => REMOVEBG_ERROR_MODEL_INIT,
+        RemoveBgError::InvalidMask(_) => REMOVEBG_ERROR_INVALID_MASK,
+        RemoveBgError::InvalidOutputFormat(_) | RemoveBgError::OutputTooLarge { .. } => {
+            REMOVEBG_ERROR_INVALID_OUTPUT_FORMAT
+        }
+        RemoveBgError::OutputExists(_) => REMOVEBG_ERROR_OUTPUT_EXISTS,
+        RemoveBgError::OutputDirectoryMissing(_) => REMOVEBG_ERROR_OUTPUT_DIRECTORY_MISSING,
+        RemoveBgError::LowConfidence { .. } | RemoveBgError::AlreadyProcessed { .. } => REMOVEBG_ERROR_LOW_CONFIDENCE,
+        RemoveBgError::Cancelled => REMOVEBG_ERROR_CANCELLED,
+        RemoveBgError::TimedOut { .. } => REMOVEBG_ERROR_TIMED_OUT,
+        RemoveBgError::InsufficientDiskSpace { .. } => REMOVEBG_ERROR_INSUFFICIENT_DISK_SPACE,
+        RemoveBgError::MemoryBudgetExceeded { .. } => REMOVEBG_ERROR_MEMORY_BUDGET_EXCEEDED,
+        RemoveBgError::ProcessingError(_)
+        | RemoveBgError::InvalidManifest(_)
+        | RemoveBgError::InvalidConfig(_)
+        | RemoveBgError::ClipboardError(_)
+        | RemoveBgError::ArchiveError(_)
+        | RemoveBgError::Context { .. } => REMOVEBG_ERROR_PROCESSING,
+        RemoveBgError::UnsupportedFormat { .. } => REMOVEBG_ERROR_UNSUPPORTED_FORMAT,
+    };
+    (status, error.to_string())
+}
+
+/// Options from a C config, with unset fields left at their defaults.
+unsafe fn config_options(config: *const RemoveBgConfig) -> Result<RemoveBgOptions, Failure> {
+    let mut options = RemoveBgOptions::default();
+    let Some(config) = (unsafe { config.as_ref() }) else {
+        return Ok(options);
+    };
+    let invalid = |message| (REMOVEBG_ERROR_INVALID_ARGUMENT, message);
+    if let Some(model) = unsafe { str_arg(config.model, "model") }? {
+        options.model = model.parse().map_err(invalid)?;
+    }
+    if let Some(dir) = unsafe { str_arg(config.model_dir, "model_dir") }? {
+        options.model_dir = Some(PathBuf::from(dir));
+    }
+    if let Some(backend) = unsafe { str_arg(config.backend, "backend") }? {
+        options.backend = backend.parse().map_err(invalid)?;
+    }
+    if let Some(format) = unsafe { str_arg(config.format, "format") }? {
+        options.format = Some(format.parse().map_err(invalid)?);
+    }
+    options.overwrite = config.overwrite != 0;
+    Ok(options)
+}
+
+/// Borrow a C string argument, `None` if NULL.
+unsafe fn str_arg<'a>(value: *const c_char, name: &str) -> Result<Option<&'a str>, Failure> {
+    if value.is_null() {
+        return Ok(None);
+    }
+    let value = unsafe { CStr::from_ptr(value) };
+    value
+        .to_str()
+        .map(Some)
+        .map_err(|_| (REMOVEBG_ERROR_INVALID_ARGUMENT, format!("{} is not valid UTF-8", name)))
+}
+
+/// Convert a message for C, dropping any interior NUL bytes.
+fn c_string(message: String) -> CString {
+    CString::new(message.replace('\0', "")).expect("NUL bytes removed")
+}
@@ -0,0 +1,233 @@
+//! Fitting the output file under a size cap (`--max-file-size`).
+//!
+//! Marketplaces and CMSs reject uploads over a fixed size, and a detailed
+//! cutout easily exceeds one as a lossless PNG. The output is encoded in
+//! memory with progressively smaller settings until it fits: the highest
+//! JPEG or WebP quality under the cap (found by binary search), or for PNG
+//! the best compression and then an 8-bit palette. With
+//! [`FileSizeLimit::allow_downscale`], the image is then scaled down in
+//! proportion to how far over the cap it still is. The first setting that
+//! fits is written; if none does, the error names the smallest size reached.
+
+use crate::error::{RemoveBgError, Result};
+use crate::format::decimal_size;
+use crate::options::{FileSizeLimit, RemoveBgOptions};
+use crate::output::{self, AtomicFile, Metadata, OutputFormat, PngCompression, PngOptions};
+use crate::report::FileSizeFit;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView};
+use std::io::{Cursor, Write};
+use std::path::Path;
+
+/// Lowest JPEG or WebP quality tried; below it, artifacts take over.
+pub const MIN_QUALITY: u8 = 10;
+
+/// Shortest side an image is scaled down to before giving up.
+const MIN_DOWNSCALED_SIDE: u32 = 16;
+
+/// Most downscaling steps tried.
+const MAX_DOWNSCALE_STEPS: u32 = 8;
+
+/// Encoder settings for one attempt.
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    quality: Option<f32>,
+    png: PngOptions,
+}
+
+/// Searches for settings that fit, remembering the smallest result.
+struct Search<'a> {
+    format: OutputFormat,
+    options: &'a RemoveBgOptions,
+    metadata: &'a Metadata,
+    limit: FileSizeLimit,
+    attempts: u32,
+    /// Smallest encoding so far.
+    smallest: u64,
+    /// Smallest encoding at the current scale.
+    smallest_at_scale: u64,
+}
+
+/// An encoding that fits.
+struct Fitted {
+    data: Vec<u8>,
+    settings: Settings,
+}
+
+impl Search<'_> {
+    /// Encode `image` with `settings`; `Some` if the result fits.
+    fn try_encode(&mut self, image: &DynamicImage, settings: Settings) -> Result<Option<Fitted>> {
+        self.options.cancel.check()?;
+        let options = RemoveBgOptions { quality: settings.quality, png: settings.png, ..self.options.clone() };
+        let mut encoded = Cursor::new(Vec::new());
+        output::encode_image(image, &mut encoded, self.format, &options, self.metadata)?;
+        let data = encoded.into_inner();
+        self.attempts += 1;
+        self.smallest = self.smallest.min(data.len() as u64);
+        self.smallest_at_scale = self.smallest_at_scale.min(data.len() as u64);
+        log::debug!("{:?} encodes to {} bytes", settings, data.len());
+        Ok((data.len() as u64 <= self.limit.max_bytes).then_some(Fitted { data, settings }))
+    }
+
+    /// The highest quality from [`MIN_QUALITY`] to `highest` that fits.
+    fn search_quality(&mut self, image: &DynamicImage, highest: u8, png: PngOptions) -> Result<Option<Fitted>> {
+        let settings = |quality: u8| Settings { quality: Some(quality as f32), png };
+        let Some(mut best) = self.try_encode(image, settings(MIN_QUALITY))? else {
+            return Ok(None);
+        };
+        let (mut low, mut high) = (MIN_QUALITY, highest.max(MIN_QUALITY));
+        // `low` always fits; find the highest quality above it that does
+        while low < high {
+            let middle = low + (high - low).div_ceil(2);
+            match self.try_encode(image, settings(middle))? {
+                Some(fitted) => {
+                    best = fitted;
+                    low = middle;
+                }
+                None => high = middle - 1,
+            }
+        }
+        Ok(Some(best))
+    }
+
+    /// Try the smaller settings the format offers at this size, after
+    /// `initial` has been found too large.
+    fn shrink(&mut self, image: &DynamicImage, initial: Settings) -> Result<Option<Fitted>> {
+        match self.format {
+            OutputFormat::Jpeg => {
+                let default = match self.options.chroma_key {
+                    Some(_) => output::CHROMA_KEY_JPEG_QUALITY,
+                    None => output::DEFAULT_JPEG_QUALITY,
+                };
+                let highest = initial.quality.map_or(default, |q| q.round() as u8);
+                self.search_quality(image, highest.saturating_sub(1), initial.png)
+            }
+            OutputFormat::WebP => {
+                // Lossless WebP moves to lossy, starting from the top
+                let highest = initial.quality.map_or(100, |q| (q.round() as u8).saturating_sub(1));
+                self.search_quality(image, highest, initial.png)
+            }
+            OutputFormat::Png => {
+                let mut png = initial.png;
+                if png.compression != PngCompression::Best {
+                    png.compression = PngCompression::Best;
+                    if let Some(fitted) = self.try_encode(image, Settings { png, ..initial })? {
+                        return Ok(Some(fitted));
+                    }
+                }
+                if !png.quantize {
+                    png.quantize = true;
+                    return self.try_encode(image, Settings { png, ..initial });
+                }
+                Ok(None)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// The most compact settings [`shrink`](Self::shrink) reaches, used for
+    /// downscaled attempts.
+    fn compact(&self, initial: Settings) -> Settings {
+        match self.format {
+            OutputFormat::Png => Settings {
+                png: PngOptions { compression: PngCompression::Best, quantize: true, ..initial.png },
+                ..initial
+            },
+            _ => initial,
+        }
+    }
+}
+
+/// Encode `image` within `limit`, trying smaller settings as described in
+/// the [module documentation](self).
+///
+/// Returns the encoded file and the settings that made it fit.
+///
+/// # Errors
+/// * `OutputTooLarge` - If no setting tried fits
+/// * `InvalidConfig` - With a custom `options.encoder`, whose settings can't be searched
+/// * Otherwise the errors of encoding the image
+pub(crate) fn encode_within(
+    image: &DynamicImage,
+    format: OutputFormat,
+    options: &RemoveBgOptions,
+    metadata: &Metadata,
+    limit: FileSizeLimit,
+) -> Result<(Vec<u8>, FileSizeFit)> {
+    if options.encoder.is_some() {
+        return Err(RemoveBgError::InvalidConfig("max_file_size only applies to the built-in output formats".into()));
+    }
+    let mut search =
+        Search { format, options, metadata, limit, attempts: 0, smallest: u64::MAX, smallest_at_scale: u64::MAX };
+    let initial = Settings { quality: options.quality, png: options.png };
+    let fitted = match search.try_encode(image, initial)? {
+        Some(fitted) => Some(fitted),
+        None => search.shrink(image, initial)?,
+    };
+    let report = |fitted: Fitted, dimensions: (u32, u32), attempts: u32| {
+        let size = fitted.data.len() as u64;
+        let lossy = matches!(format, OutputFormat::Jpeg | OutputFormat::WebP);
+        let fit = FileSizeFit {
+            size,
+            quality: fitted.settings.quality.filter(|_| lossy),
+            png_compression: (format == OutputFormat::Png).then_some(fitted.settings.png.compression),
+            quantized: format == OutputFormat::Png && fitted.settings.png.quantize,
+            downscaled: dimensions != image.dimensions(),
+            dimensions,
+            attempts,
+        };
+        (fitted.data, fit)
+    };
+    if let Some(fitted) = fitted {
+        return Ok(report(fitted, image.dimensions(), search.attempts));
+    }
+
+    if limit.allow_downscale {
+        let compact = search.compact(initial);
+        let mut scale = 1.0f64;
+        for _ in 0..MAX_DOWNSCALE_STEPS {
+            // File size grows roughly with the pixel count
+            let over = limit.max_bytes as f64 / search.smallest_at_scale as f64;
+            let step = (over.sqrt() * 0.95).clamp(0.25, 0.9);
+            scale *= step;
+            let width = (image.width() as f64 * scale).round() as u32;
+            let height = (image.height() as f64 * scale).round() as u32;
+            if width.min(height) < MIN_DOWNSCALED_SIDE {
+                break;
+            }
+            log::debug!("scaling the output down to {}x{} to fit {}", width, height, decimal_size(limit.max_bytes));
+            let resized = image.resize_exact(width, height, FilterType::Lanczos3);
+            search.smallest_at_scale = u64::MAX;
+            let fitted = match search.try_encode(&resized, compact)? {
+                Some(fitted) => Some(fitted),
+                None => search.shrink(&resized, compact)?,
+            };
+            if let Some(fitted) = fitted {
+                return Ok(report(fitted, (width, height), search.attempts));
+            }
+        }
+    }
+
+    Err(RemoveBgError::OutputTooLarge {
+        limit: limit.max_bytes,
+        smallest: search.smallest,
+        downscale_allowed: limit.allow_downscale,
+    })
+}
+
+/// Encode `image` within `limit` (see [`encode_within`]) and write it to
+/// `path` atomically.
+pub(crate) fn save_within(
+    image: &DynamicImage,
+    path: &Path,
+    format: OutputFormat,
+    options: &RemoveBgOptions,
+    metadata: &Metadata,
+    limit: FileSizeLimit,
+) -> Result<FileSizeFit> {
+    let (data, fit) = encode_within(image, format, options, metadata, limit)?;
+    let mut file = AtomicFile::create(path)?;
+    file.write_all(&data)?;
+    file.commit()?;
+    Ok(fit)
+}
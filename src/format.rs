@@ -0,0 +1,51 @@
+//! Numbers in human-readable messages.
+//!
+//! Sizes are formatted here instead of at each message, so every message
+//! agrees on units and precision and a later translation has one place to
+//! change. The decimal separator is always `.`, whatever the system locale.
+//! Machine-readable output (`--json`, the daemon, the
+//! [`DownloadEvent`](crate::progress::DownloadEvent)s) carries exact byte
+//! counts instead of these strings.
+
+/// Format a byte count in decimal units (1 KB = 1000 bytes), as file and
+/// download sizes are usually quoted, e.g. `176.00 MB`.
+///
+/// # Examples
+/// ```
+/// use removebg::format::decimal_size;
+///
+/// assert_eq!(decimal_size(512), "512 bytes");
+/// assert_eq!(decimal_size(45_300), "45.3 KB");
+/// assert_eq!(decimal_size(175_997_641), "176.00 MB");
+/// ```
+pub fn decimal_size(bytes: u64) -> String {
+    match bytes {
+        0..1_000 => format!("{} bytes", bytes),
+        1_000..1_000_000 => format!("{:.1} KB", bytes as f64 / 1e3),
+        1_000_000..1_000_000_000 => format!("{:.2} MB", bytes as f64 / 1e6),
+        _ => format!("{:.2} GB", bytes as f64 / 1e9),
+    }
+}
+
+/// Format a byte count in binary units (1 KiB = 1024 bytes), as memory is
+/// usually measured, e.g. `167.84 MiB`.
+///
+/// # Examples
+/// ```
+/// use removebg::format::binary_size;
+///
+/// assert_eq!(binary_size(512), "512 bytes");
+/// assert_eq!(binary_size(1536), "1.5 KiB");
+/// assert_eq!(binary_size(3 << 30), "3.00 GiB");
+/// ```
+pub fn binary_size(bytes: u64) -> String {
+    const KIB: u64 = 1 << 10;
+    const MIB: u64 = 1 << 20;
+    const GIB: u64 = 1 << 30;
+    match bytes {
+        0..KIB => format!("{} bytes", bytes),
+        KIB..MIB => format!("{:.1} KiB", bytes as f64 / KIB as f64),
+        MIB..GIB => format!("{:.2} MiB", bytes as f64 / MIB as f64),
+        _ => format!("{:.2} GiB", bytes as f64 / GIB as f64),
+    }
+}
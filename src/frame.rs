@@ -0,0 +1,107 @@
+//! Raw pixel buffers, as camera SDKs and capture pipelines hand them out.
+//!
+//! A frame is a run of rows `stride` bytes apart, each starting with `width`
+//! pixels in one of the [`PixelFormat`]s and possibly followed by padding.
+//! [`image_from_raw`] copies such a buffer into an image once, reordering
+//! the channels and dropping the padding in the same pass, so frames need not
+//! be encoded to go through
+//! [`BackgroundRemover::process_raw`](crate::BackgroundRemover::process_raw).
+
+use crate::error::{RemoveBgError, Result};
+use image::{DynamicImage, RgbImage, RgbaImage};
+use std::fmt;
+
+/// Layout of the pixels of a raw buffer, with 8 bits per channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PixelFormat {
+    /// Red, green, blue.
+    Rgb8,
+    /// Red, green, blue, alpha.
+    Rgba8,
+    /// Blue, green, red.
+    Bgr8,
+    /// Blue, green, red, alpha, as most camera and screen capture APIs
+    /// deliver frames.
+    Bgra8,
+}
+
+impl PixelFormat {
+    /// Bytes one pixel takes.
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Rgb8 | PixelFormat::Bgr8 => 3,
+            PixelFormat::Rgba8 | PixelFormat::Bgra8 => 4,
+        }
+    }
+
+    /// Whether the pixels have an alpha channel.
+    pub fn has_alpha(self) -> bool {
+        self.bytes_per_pixel() == 4
+    }
+}
+
+impl fmt::Display for PixelFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PixelFormat::Rgb8 => "RGB8",
+            PixelFormat::Rgba8 => "RGBA8",
+            PixelFormat::Bgr8 => "BGR8",
+            PixelFormat::Bgra8 => "BGRA8",
+        })
+    }
+}
+
+/// Copy a `width`x`height` frame of `format` pixels, whose rows start
+/// `stride` bytes apart in `data`, into an image.
+///
+/// RGB and BGR frames become [`DynamicImage::ImageRgb8`], RGBA and BGRA
+/// frames [`DynamicImage::ImageRgba8`], keeping their alpha. The last row
+/// needs no padding, so `data` must hold at least
+/// `stride * (height - 1) + width * format.bytes_per_pixel()` bytes.
+///
+/// # Errors
+/// * `InvalidRawLayout` - If `width` or `height` is zero, or `stride` is
+///   shorter than a row of pixels
+/// * `RawBufferTooSmall` - If `data` ends before the last row does
+///
+/// # Examples
+/// ```
+/// use removebg::frame::{image_from_raw, PixelFormat};
+///
+/// // Two BGRA pixels a row, padded to 12 bytes
+/// let data = [
+///     255, 0, 0, 255, 0, 255, 0, 255, 9, 9, 9, 9, //
+///     0, 0, 255, 255, 0, 0, 0, 0,
+/// ];
+/// let image = image_from_raw(&data, 2, 2, 12, PixelFormat::Bgra8)?.into_rgba8();
+/// assert_eq!(image.get_pixel(0, 0).0, [0, 0, 255, 255]);
+/// assert_eq!(image.get_pixel(0, 1).0, [255, 0, 0, 255]);
+/// # Ok::<(), removebg::RemoveBgError>(())
+/// ```
+pub fn image_from_raw(data: &[u8], width: u32, height: u32, stride: usize, format: PixelFormat) -> Result<DynamicImage> {
+    let invalid = || RemoveBgError::InvalidRawLayout { width, height, stride, format };
+    let row = (width as usize).checked_mul(format.bytes_per_pixel()).ok_or_else(invalid)?;
+    if width == 0 || height == 0 || stride < row {
+        return Err(invalid());
+    }
+    let needed = stride.checked_mul(height as usize - 1).and_then(|rows| rows.checked_add(row)).ok_or_else(invalid)?;
+    if data.len() < needed {
+        return Err(RemoveBgError::RawBufferTooSmall { needed, len: data.len() });
+    }
+
+    let mut pixels = Vec::with_capacity(row * height as usize);
+    for line in data.chunks(stride).take(height as usize) {
+        let line = &line[..row];
+        match format {
+            PixelFormat::Rgb8 | PixelFormat::Rgba8 => pixels.extend_from_slice(line),
+            PixelFormat::Bgr8 => line.chunks_exact(3).for_each(|bgr| pixels.extend_from_slice(&[bgr[2], bgr[1], bgr[0]])),
+            PixelFormat::Bgra8 => {
+                line.chunks_exact(4).for_each(|bgra| pixels.extend_from_slice(&[bgra[2], bgra[1], bgra[0], bgra[3]]))
+            }
+        }
+    }
+    Ok(match format.has_alpha() {
+        true => DynamicImage::ImageRgba8(RgbaImage::from_raw(width, height, pixels).expect("one pixel per position")),
+        false => DynamicImage::ImageRgb8(RgbImage::from_raw(width, height, pixels).expect("one pixel per position")),
+    })
+}
@@ -0,0 +1,171 @@
+//! Output canvas geometry: cropping to the subject, squaring, and resizing.
+//!
+//! These steps run on the finished cutout, before it is flattened onto a
+//! background color, so padding added here is transparent and picks up the
+//! color like the rest of the background.
+
+use crate::error::{RemoveBgError, Result};
+use crate::options::GeometryOptions;
+use crate::report::Rect;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageBuffer, Rgba, Rgba32FImage, RgbaImage};
+
+/// Smallest alpha that survives 8-bit rounding; fainter pixels are not part
+/// of the subject's bounding box.
+const VISIBLE_ALPHA: f32 = 0.5 / 255.0;
+
+/// Whether `geometry` changes the canvas at all.
+pub(crate) fn is_enabled(geometry: &GeometryOptions) -> bool {
+    geometry.pad.is_some() || geometry.square || geometry.resize.is_some()
+}
+
+/// Bounding box of the pixels whose alpha, as returned by `alpha` in
+/// 0.0-1.0, is visible; `None` if there are none.
+pub(crate) fn bounds(width: u32, height: u32, alpha: impl Fn(u32, u32) -> f32) -> Option<Rect> {
+    let (mut left, mut top, mut right, mut bottom) = (u32::MAX, u32::MAX, 0, 0);
+    for y in 0..height {
+        for x in 0..width {
+            if alpha(x, y) >= VISIBLE_ALPHA {
+                left = left.min(x);
+                top = top.min(y);
+                right = right.max(x + 1);
+                bottom = bottom.max(y + 1);
+            }
+        }
+    }
+    (right > left).then(|| Rect { x: left, y: top, width: right - left, height: bottom - top })
+}
+
+/// Bounding box of the visible pixels of an RGBA image.
+pub(crate) fn alpha_bounds(image: &DynamicImage) -> Option<Rect> {
+    match image {
+        DynamicImage::ImageRgba8(image) => bounds(image.width(), image.height(), |x, y| {
+            image.get_pixel(x, y)[3] as f32 / 255.0
+        }),
+        DynamicImage::ImageRgba16(image) => bounds(image.width(), image.height(), |x, y| {
+            image.get_pixel(x, y)[3] as f32 / 65535.0
+        }),
+        other => {
+            let image = other.to_rgba32f();
+            bounds(image.width(), image.height(), |x, y| image.get_pixel(x, y)[3])
+        }
+    }
+}
+
+/// Apply `geometry` to an 8-bit or 16-bit RGBA image.
+///
+/// `subject` is the subject's bounding box on `image`; it is moved and scaled
+/// along with the canvas and returned with the new image.
+pub(crate) fn apply(
+    mut image: DynamicImage,
+    mut subject: Option<Rect>,
+    geometry: &GeometryOptions,
+) -> Result<(DynamicImage, Option<Rect>)> {
+    if let Some(pad) = geometry.pad {
+        if !(pad.is_finite() && pad >= 0.0) {
+            return Err(RemoveBgError::ProcessingError(format!(
+                "Padding must be a non-negative percentage, got {}%",
+                pad * 100.0
+            )));
+        }
+        match subject {
+            Some(rect) => {
+                let margin = (pad as f64 * rect.width.max(rect.height) as f64).round() as u32;
+                let size = |length: u32| {
+                    length.checked_add(margin.saturating_mul(2)).ok_or_else(|| {
+                        RemoveBgError::ProcessingError("Padding makes the canvas too large".into())
+                    })
+                };
+                let (width, height) = (size(rect.width)?, size(rect.height)?);
+                let x = margin as i64 - rect.x as i64;
+                let y = margin as i64 - rect.y as i64;
+                image = place(&image, width, height, x, y);
+                subject = Some(Rect { x: margin, y: margin, ..rect });
+            }
+            None => log::warn!("no subject found; --pad leaves the canvas unchanged"),
+        }
+    }
+
+    if geometry.square {
+        let side = image.width().max(image.height());
+        // Odd differences put the extra pixel on the right or bottom
+        let x = (side - image.width()) / 2;
+        let y = (side - image.height()) / 2;
+        image = place(&image, side, side, x as i64, y as i64);
+        subject = subject.map(|rect| Rect { x: rect.x + x, y: rect.y + y, ..rect });
+    }
+
+    if let Some((max_width, max_height)) = geometry.resize.filter(|_| image.width() > 0 && image.height() > 0) {
+        if max_width == 0 || max_height == 0 {
+            return Err(RemoveBgError::ProcessingError(format!(
+                "Resize target must be at least 1x1, got {}x{}",
+                max_width, max_height
+            )));
+        }
+        let (width, height) = image.dimensions();
+        let mut scale = (max_width as f64 / width as f64).min(max_height as f64 / height as f64);
+        if scale > 1.0 && !geometry.allow_upscale {
+            scale = 1.0;
+        }
+        let scaled = |length: u32| (length as f64 * scale).round() as u32;
+        let (new_width, new_height) = (scaled(width).max(1), scaled(height).max(1));
+        if (new_width, new_height) != (width, height) {
+            image = resize(&image, new_width, new_height);
+            subject = subject.map(|rect| {
+                let x = scaled(rect.x).min(new_width - 1);
+                let y = scaled(rect.y).min(new_height - 1);
+                let right = scaled(rect.x + rect.width).clamp(x + 1, new_width);
+                let bottom = scaled(rect.y + rect.height).clamp(y + 1, new_height);
+                Rect { x, y, width: right - x, height: bottom - y }
+            });
+        }
+    }
+
+    Ok((image, subject))
+}
+
+/// A transparent `width`×`height` canvas with `image` drawn at (`x`, `y`),
+/// clipped to the canvas.
+fn place(image: &DynamicImage, width: u32, height: u32, x: i64, y: i64) -> DynamicImage {
+    match image {
+        DynamicImage::ImageRgba16(image) => {
+            let mut canvas: ImageBuffer<Rgba<u16>, Vec<u16>> = ImageBuffer::new(width, height);
+            image::imageops::replace(&mut canvas, image, x, y);
+            DynamicImage::ImageRgba16(canvas)
+        }
+        other => {
+            let mut canvas: RgbaImage = ImageBuffer::new(width, height);
+            image::imageops::replace(&mut canvas, &other.to_rgba8(), x, y);
+            DynamicImage::ImageRgba8(canvas)
+        }
+    }
+}
+
+/// Resize an RGBA image with Lanczos3, keeping its bit depth.
+///
+/// Colors are premultiplied by alpha while filtering, so the colors of fully
+/// transparent pixels don't bleed into the subject's edge.
+pub(crate) fn resize(image: &DynamicImage, width: u32, height: u32) -> DynamicImage {
+    let mut premultiplied = image.to_rgba32f();
+    for pixel in premultiplied.pixels_mut() {
+        let alpha = pixel[3];
+        for c in 0..3 {
+            pixel[c] *= alpha;
+        }
+    }
+    let mut resized: Rgba32FImage =
+        image::imageops::resize(&premultiplied, width, height, FilterType::Lanczos3);
+    for pixel in resized.pixels_mut() {
+        // Lanczos rings slightly outside 0-1
+        let alpha = pixel[3].clamp(0.0, 1.0);
+        for c in 0..3 {
+            pixel[c] = if alpha > 0.0 { (pixel[c] / alpha).clamp(0.0, 1.0) } else { 0.0 };
+        }
+        pixel[3] = alpha;
+    }
+    let resized = DynamicImage::ImageRgba32F(resized);
+    match image {
+        DynamicImage::ImageRgba16(_) => DynamicImage::ImageRgba16(resized.into_rgba16()),
+        _ => DynamicImage::ImageRgba8(resized.into_rgba8()),
+    }
+}
@@ -0,0 +1,112 @@
+//! HEIC/HEIF input, decoded with libheif behind the `heif` feature.
+//!
+//! HEIF files are recognized by their `ftyp` box in every build, so without
+//! the feature they fail with a message saying how to read them instead of
+//! the image crate's "format not supported".
+
+use crate::error::RemoveBgError;
+
+/// Major brands of HEIF images. AVIF shares the container but is decoded by
+/// the image crate, so files listing an AVIF brand are left to it.
+const HEIF_BRANDS: &[&[u8]] = &[b"heic", b"heix", b"heim", b"heis", b"hevc", b"hevx", b"hevm", b"hevs", b"mif1", b"msf1"];
+const AVIF_BRANDS: &[&[u8]] = &[b"avif", b"avis"];
+
+/// Whether `header`, the start of a file, is a HEIF image.
+pub(crate) fn is_heif(header: &[u8]) -> bool {
+    if header.get(4..8) != Some(b"ftyp") {
+        return false;
+    }
+    let Some(major) = header.get(8..12) else {
+        return false;
+    };
+    // The compatible brands follow the major brand and minor version
+    let size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let compatible = header.get(16..size.min(header.len())).unwrap_or_default();
+    HEIF_BRANDS.contains(&major) && !compatible.chunks_exact(4).any(|brand| AVIF_BRANDS.contains(&brand))
+}
+
+/// The error for HEIF inputs in builds without the `heif` feature.
+#[cfg(not(feature = "heif"))]
+pub(crate) fn unsupported() -> RemoveBgError {
+    RemoveBgError::ProcessingError(
+        "HEIC/HEIF images need removebg built with the `heif` feature \
+         (cargo install removebg --features heif); or convert the image to JPEG or PNG first"
+            .into(),
+    )
+}
+
+#[cfg(feature = "heif")]
+pub(crate) use decode::decode;
+
+#[cfg(feature = "heif")]
+mod decode {
+    use super::*;
+    use crate::error::Result;
+    use crate::options::DecodeLimits;
+    use crate::output::Metadata;
+    use image::metadata::Orientation;
+    use image::{DynamicImage, RgbImage, RgbaImage};
+    use libheif_rs::{ColorSpace, HeifContext, HeifError, ImageHandle, LibHeif, RgbChroma};
+
+    /// Decode the primary image of a HEIF file, enforcing `limits`.
+    ///
+    /// libheif applies the rotation, mirroring and cropping stored in the
+    /// container, so the pixels come out upright. The ICC profile and EXIF
+    /// data (with its orientation reset) are returned for the encoder.
+    pub(crate) fn decode(data: &[u8], limits: &DecodeLimits) -> Result<(DynamicImage, Metadata)> {
+        let context = HeifContext::read_from_bytes(data).map_err(heif_error)?;
+        let handle = context.primary_image_handle().map_err(heif_error)?;
+        let alpha = handle.has_alpha_channel();
+        let (width, height) = (handle.width(), handle.height());
+        limits.check(width, height, if alpha { 4 } else { 3 })?;
+
+        let chroma = if alpha { RgbChroma::Rgba } else { RgbChroma::Rgb };
+        let decoded = LibHeif::new()
+            .decode(&handle, ColorSpace::Rgb(chroma), None)
+            .map_err(heif_error)?;
+        let plane = decoded.planes().interleaved.ok_or_else(|| {
+            RemoveBgError::ProcessingError("libheif returned no interleaved RGB plane".into())
+        })?;
+
+        // Rows may be padded; copy them into a tightly packed buffer
+        let row = plane.width as usize * if alpha { 4 } else { 3 };
+        let mut pixels = Vec::with_capacity(row * plane.height as usize);
+        for line in plane.data.chunks(plane.stride).take(plane.height as usize) {
+            pixels.extend_from_slice(&line[..row]);
+        }
+        let image = if alpha {
+            RgbaImage::from_raw(plane.width, plane.height, pixels).map(DynamicImage::ImageRgba8)
+        } else {
+            RgbImage::from_raw(plane.width, plane.height, pixels).map(DynamicImage::ImageRgb8)
+        };
+        let image = image.ok_or_else(|| {
+            RemoveBgError::ProcessingError("libheif returned a truncated image".into())
+        })?;
+
+        let metadata = Metadata {
+            icc_profile: handle.color_profile_raw().map(|profile| profile.data),
+            exif: exif(&handle),
+        };
+        Ok((image, metadata))
+    }
+
+    /// The EXIF block of an image without the HEIF offset prefix, with its
+    /// orientation reset since libheif already rotated the pixels.
+    fn exif(handle: &ImageHandle) -> Option<Vec<u8>> {
+        let mut ids = [0];
+        if handle.metadata_block_ids(&mut ids, b"Exif") == 0 {
+            return None;
+        }
+        let block = handle.metadata(ids[0]).ok()?;
+        // The block starts with the offset of the TIFF header
+        let offset = u32::from_be_bytes(block.get(..4)?.try_into().ok()?) as usize;
+        let mut exif = block.get(4 + offset..)?.to_vec();
+        let _ = Orientation::remove_from_exif_chunk(&mut exif);
+        Some(exif)
+    }
+
+    fn heif_error(error: HeifError) -> RemoveBgError {
+        let message = if error.message.is_empty() { format!("{:?}", error.code) } else { error.message };
+        RemoveBgError::ProcessingError(format!("Failed to decode HEIF image: {}", message))
+    }
+}
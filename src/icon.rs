@@ -0,0 +1,201 @@
+//! Icon output: multi-resolution ICO files and macOS iconsets.
+//!
+//! The cutout is cropped to the subject and padded to a square, and every
+//! icon size is resized from that square on its own, so small sizes don't
+//! inherit the blur of earlier downscaling steps.
+
+use crate::error::{RemoveBgError, Result};
+use crate::geometry;
+use crate::options::{GeometryOptions, IconOptions};
+use crate::output;
+use crate::paths;
+use image::codecs::ico::{IcoEncoder, IcoFrame};
+use image::codecs::png::PngEncoder;
+use image::{DynamicImage, ExtendedColorType};
+use std::io::Write;
+use std::path::Path;
+
+/// Largest entry an ICO file can hold.
+const MAX_ICO_SIZE: u32 = 256;
+
+/// File names and edge lengths of the images in a macOS iconset.
+const ICONSET: &[(&str, u32)] = &[
+    ("icon_16x16.png", 16),
+    ("icon_16x16@2x.png", 32),
+    ("icon_32x32.png", 32),
+    ("icon_32x32@2x.png", 64),
+    ("icon_128x128.png", 128),
+    ("icon_128x128@2x.png", 256),
+    ("icon_256x256.png", 256),
+    ("icon_256x256@2x.png", 512),
+    ("icon_512x512.png", 512),
+    ("icon_512x512@2x.png", 1024),
+];
+
+/// The cutout cropped to its subject and padded to a transparent square.
+fn square(image: &DynamicImage) -> Result<DynamicImage> {
+    let subject = geometry::alpha_bounds(image);
+    let canvas = GeometryOptions { pad: subject.map(|_| 0.0), square: true, ..GeometryOptions::default() };
+    let (square, _) = geometry::apply(image.clone(), subject, &canvas)?;
+    Ok(square)
+}
+
+/// The sizes to render from a `source`-pixel square: sizes larger than it
+/// are dropped unless upscaling is allowed, with one warning either way.
+fn fitting_sizes(sizes: &[u32], source: u32, options: &IconOptions) -> Vec<u32> {
+    let larger: Vec<String> = sizes.iter().filter(|&&size| size > source).map(u32::to_string).collect();
+    if !larger.is_empty() {
+        let action = if options.allow_upscale { "upscaling" } else { "skipping" };
+        log::warn!(
+            "{} icon sizes {} larger than the {2}x{2} subject",
+            action,
+            larger.join(", "),
+            source
+        );
+    }
+    sizes.iter().copied().filter(|&size| size <= source || options.allow_upscale).collect()
+}
+
+/// `square` resized to `size`×`size`.
+fn resized(square: &DynamicImage, size: u32) -> DynamicImage {
+    if size == square.width() {
+        square.clone()
+    } else {
+        geometry::resize(square, size, size)
+    }
+}
+
+/// Encode `image` as an ICO file with one PNG-compressed entry per size in
+/// `options.sizes`.
+///
+/// # Errors
+/// * `InvalidOutputFormat` - If no sizes are given or a size is outside
+///   1-256 pixels
+/// * `ProcessingError` - If every size was skipped because it is larger
+///   than the subject
+pub(crate) fn write_ico<W: Write>(image: &DynamicImage, writer: W, options: &IconOptions) -> Result<()> {
+    if options.sizes.is_empty() {
+        return Err(RemoveBgError::InvalidOutputFormat("ICO output needs at least one icon size".into()));
+    }
+    if let Some(size) = options.sizes.iter().find(|&&size| size == 0 || size > MAX_ICO_SIZE) {
+        return Err(RemoveBgError::InvalidOutputFormat(format!(
+            "ICO icon sizes must be between 1 and {} pixels, got {}",
+            MAX_ICO_SIZE, size
+        )));
+    }
+
+    let square = square(image)?;
+    let mut sizes = options.sizes.clone();
+    sizes.sort_unstable();
+    sizes.dedup();
+    let sizes = fitting_sizes(&sizes, square.width(), options);
+    if sizes.is_empty() {
+        return Err(RemoveBgError::ProcessingError(format!(
+            "every icon size is larger than the {0}x{0} subject; allow upscaling to write them",
+            square.width()
+        )));
+    }
+    let mut frames = Vec::with_capacity(sizes.len());
+    for size in sizes {
+        let icon = resized(&square, size).to_rgba8();
+        frames.push(IcoFrame::as_png(icon.as_raw(), size, size, ExtendedColorType::Rgba8)?);
+    }
+    IcoEncoder::new(writer).encode_images(&frames)?;
+    Ok(())
+}
+
+/// Write the macOS iconset for `image` into the directory `dir`, which is
+/// created if needed. Sizes are skipped or upscaled like ICO entries.
+pub(crate) fn write_iconset(image: &DynamicImage, dir: &Path, options: &IconOptions) -> Result<()> {
+    std::fs::create_dir_all(paths::long_path(dir))?;
+    let square = square(image)?;
+    let mut sizes: Vec<u32> = ICONSET.iter().map(|&(_, size)| size).collect();
+    sizes.dedup();
+    let fitting = fitting_sizes(&sizes, square.width(), options);
+    for &(name, size) in ICONSET.iter().filter(|(_, size)| fitting.contains(size)) {
+        let icon = resized(&square, size);
+        let mut file = output::AtomicFile::create(&dir.join(name))?;
+        icon.to_rgba8().write_with_encoder(PngEncoder::new(&mut file))?;
+        file.commit()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImageView, Rgba, RgbaImage};
+
+    /// An opaque `width`×`height` cutout.
+    fn cutout(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(width, height, Rgba([200, 40, 40, 255])))
+    }
+
+    /// Edge lengths of the entries in an ICO file, from its directory and
+    /// checked against each entry's PNG header.
+    fn entries(ico: &[u8]) -> Vec<(u32, u32)> {
+        assert_eq!(&ico[..4], b"\0\0\x01\0");
+        let count = u16::from_le_bytes([ico[4], ico[5]]) as usize;
+        (0..count)
+            .map(|index| {
+                let entry = &ico[6 + 16 * index..][..16];
+                let offset = u32::from_le_bytes(entry[12..16].try_into().unwrap()) as usize;
+                let png = &ico[offset..];
+                assert!(png.starts_with(b"\x89PNG"));
+                let size = (u32::from_be_bytes(png[16..20].try_into().unwrap()), u32::from_be_bytes(png[20..24].try_into().unwrap()));
+                let listed = |byte: u8| if byte == 0 { 256 } else { byte as u32 };
+                assert_eq!((listed(entry[0]), listed(entry[1])), size);
+                size
+            })
+            .collect()
+    }
+
+    fn ico(image: &DynamicImage, options: &IconOptions) -> Result<Vec<(u32, u32)>> {
+        let mut data = Vec::new();
+        write_ico(image, &mut data, options)?;
+        Ok(entries(&data))
+    }
+
+    #[test]
+    fn sizes_larger_than_the_subject_are_skipped() {
+        // 64x48 squares to 64x64, so 128 and 256 aren't upscaled
+        assert_eq!(ico(&cutout(64, 48), &IconOptions::default()).unwrap(), [(16, 16), (32, 32), (48, 48), (64, 64)]);
+        let upscaled = IconOptions { allow_upscale: true, ..Default::default() };
+        assert_eq!(ico(&cutout(64, 48), &upscaled).unwrap().last(), Some(&(256, 256)));
+        // Sizes are sorted and deduplicated
+        let sizes = IconOptions { sizes: vec![24, 16, 24], ..Default::default() };
+        assert_eq!(ico(&cutout(64, 48), &sizes).unwrap(), [(16, 16), (24, 24)]);
+
+        let error = ico(&cutout(8, 8), &IconOptions { sizes: vec![16, 32], ..Default::default() }).unwrap_err();
+        assert!(error.to_string().contains("larger than the 8x8 subject"), "{}", error);
+    }
+
+    #[test]
+    fn ico_sizes_must_fit_the_format() {
+        for sizes in [vec![], vec![16, 512], vec![0]] {
+            let options = IconOptions { sizes: sizes.clone(), ..Default::default() };
+            assert!(matches!(ico(&cutout(64, 64), &options), Err(RemoveBgError::InvalidOutputFormat(_))), "{:?}", sizes);
+        }
+    }
+
+    #[test]
+    fn icons_are_cropped_to_the_subject() {
+        // A 20x10 subject in the corner of a large transparent canvas
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(300, 200, |x, y| Rgba([0, 0, 255, if x < 20 && y < 10 { 255 } else { 0 }])));
+        let square = square(&image).unwrap();
+        assert_eq!(square.dimensions(), (20, 20));
+        assert_eq!(ico(&image, &IconOptions::default()).unwrap(), [(16, 16)]);
+    }
+
+    #[test]
+    fn iconsets_hold_the_fitting_sizes() {
+        let dir = std::env::temp_dir().join(format!("removebg-icon-iconset-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        write_iconset(&cutout(40, 40), &dir, &IconOptions::default()).unwrap();
+        let mut names: Vec<String> = std::fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        names.sort();
+        assert_eq!(names, ["icon_16x16.png", "icon_16x16@2x.png", "icon_32x32.png"]);
+        assert_eq!(image::open(dir.join("icon_16x16@2x.png")).unwrap().dimensions(), (32, 32));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
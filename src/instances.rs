@@ -0,0 +1,179 @@
+//! Per-subject outputs for
+//! [`split_instances`](crate::RemoveBgOptions::split_instances).
+//!
+//! The finished mask is cut at half alpha into 8-connected regions. Regions
+//! below the minimum area are dropped as noise, and the rest, largest first
+//! up to the cap, are each written cropped to their bounding box plus a
+//! margin. An instance keeps the alpha of its own region and of the soft
+//! edge pixels nearer to it than to any other region, so neighbors don't
+//! bleed into each other's crops.
+//!
+//! Subjects that touch in the mask form one region; telling them apart would
+//! need an instance segmentation model, which this does not attempt.
+
+use crate::core::{self, Gray16Image};
+use crate::error::{RemoveBgError, Result};
+use crate::matte::{distance_transform, label_components};
+use crate::options::{InstanceOptions, RemoveBgOptions};
+use crate::output::{self, Metadata, OutputFormat};
+use crate::paths;
+use crate::report::{Instance, Rect};
+use image::{DynamicImage, Luma};
+use std::path::{Path, PathBuf};
+
+/// Mask value at or above which a pixel belongs to a region.
+const THRESHOLD: u16 = 32768;
+
+/// Check that instance settings are usable.
+pub(crate) fn check(options: &InstanceOptions) -> Result<()> {
+    if !(options.margin.is_finite() && options.margin >= 0.0) {
+        return Err(RemoveBgError::ProcessingError(format!(
+            "Instance margin must be 0 or more, got {}",
+            options.margin
+        )));
+    }
+    Ok(())
+}
+
+/// Format instances are written in: the main output's format when it is
+/// PNG, WebP or TIFF, and PNG otherwise, since every instance is a
+/// transparent cutout.
+pub(crate) fn format(main: OutputFormat) -> OutputFormat {
+    match main {
+        OutputFormat::WebP | OutputFormat::Tiff => main,
+        _ => OutputFormat::Png,
+    }
+}
+
+/// Path of the `index`th instance (from 1) of the main output at
+/// `output_path`: `<output stem>_<index>.<ext>` next to it.
+pub(crate) fn path(output_path: &Path, index: usize, format: OutputFormat) -> PathBuf {
+    let stem = output_path.file_stem().unwrap_or_default();
+    let suffix = format!("_{}.{}", index, format.extension());
+    output_path.with_file_name(paths::file_name([stem, suffix.as_ref()]))
+}
+
+/// Every path an instance of the main output at `output_path` may be
+/// written to, so they can be checked before any work is done.
+pub(crate) fn candidate_paths(output_path: &Path, format: OutputFormat, options: &InstanceOptions) -> Vec<PathBuf> {
+    (1..=options.max_instances.max(1)).map(|index| path(output_path, index, format)).collect()
+}
+
+/// Whether `stem` ends in an instance suffix, `_` and a number; returns the
+/// stem without it.
+pub(crate) fn strip_suffix(stem: &[u8]) -> Option<&[u8]> {
+    let digits = stem.iter().rev().take_while(|byte| byte.is_ascii_digit()).count();
+    let rest = &stem[..stem.len() - digits];
+    (digits > 0).then_some(rest.strip_suffix(b"_")?)
+}
+
+/// One region of the mask picked as an instance.
+struct Region {
+    label: u32,
+    area: u64,
+    bounds: Rect,
+}
+
+/// The labeled regions written as instances, largest first.
+fn regions(labels: &[u32], count: usize, width: u32, options: &InstanceOptions) -> Vec<Region> {
+    let mut extents = vec![(0u64, u32::MAX, u32::MAX, 0u32, 0u32); count];
+    for (index, &label) in labels.iter().enumerate() {
+        if label == 0 {
+            continue;
+        }
+        let (x, y) = ((index % width as usize) as u32, (index / width as usize) as u32);
+        let (area, left, top, right, bottom) = &mut extents[label as usize - 1];
+        *area += 1;
+        *left = (*left).min(x);
+        *top = (*top).min(y);
+        *right = (*right).max(x + 1);
+        *bottom = (*bottom).max(y + 1);
+    }
+    let mut regions: Vec<Region> = extents
+        .into_iter()
+        .zip(1..)
+        .filter(|((area, ..), _)| *area > 0 && *area >= options.min_area)
+        .map(|((area, left, top, right, bottom), label)| Region {
+            label,
+            area,
+            bounds: Rect { x: left, y: top, width: right - left, height: bottom - top },
+        })
+        .collect();
+    // Stable, so equal areas keep the order they were found in
+    regions.sort_by_key(|region| std::cmp::Reverse(region.area));
+    regions.truncate(options.max_instances.max(1));
+    regions
+}
+
+/// `bounds` grown by `margin` times its larger side on every side, clamped
+/// to a `width`x`height` image.
+fn expand(bounds: Rect, margin: f32, width: u32, height: u32) -> Rect {
+    let pad = (bounds.width.max(bounds.height) as f32 * margin).ceil() as u32;
+    let left = bounds.x.saturating_sub(pad);
+    let top = bounds.y.saturating_sub(pad);
+    let right = bounds.x.saturating_add(bounds.width).saturating_add(pad).min(width);
+    let bottom = bounds.y.saturating_add(bounds.height).saturating_add(pad).min(height);
+    Rect { x: left, y: top, width: right - left, height: bottom - top }
+}
+
+/// The mask of `region` on `crop`: the region's own pixels, and soft pixels
+/// below the threshold that are at least as near to it as to any other
+/// region in the crop.
+fn instance_mask(mask: &Gray16Image, labels: &[u32], label: u32, crop: Rect) -> Gray16Image {
+    let (width, height) = (crop.width as usize, crop.height as usize);
+    let label_at = |x: usize, y: usize| labels[(crop.y as usize + y) * mask.width() as usize + crop.x as usize + x];
+    let mut own = Vec::with_capacity(width * height);
+    let mut other = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let found = label_at(x, y);
+            own.push(found == label);
+            other.push(found != 0 && found != label);
+        }
+    }
+    let to_own = distance_transform(&own, width, height);
+    let to_other = distance_transform(&other, width, height);
+    Gray16Image::from_fn(crop.width, crop.height, |x, y| {
+        let index = y as usize * width + x as usize;
+        let alpha = mask.get_pixel(crop.x + x, crop.y + y)[0];
+        let keep = own[index] || (!other[index] && to_own[index] <= to_other[index]);
+        Luma([if keep { alpha } else { 0 }])
+    })
+}
+
+/// Write the instances of `image` with the finished `mask` next to the main
+/// output at `output_path`, in `format`, and list them largest first.
+///
+/// # Errors
+/// * Any error from encoding or writing an instance
+pub(crate) fn write(
+    image: &DynamicImage,
+    mask: &Gray16Image,
+    output_path: &Path,
+    format: OutputFormat,
+    metadata: &Metadata,
+    options: &RemoveBgOptions,
+    instances: &InstanceOptions,
+) -> Result<Vec<Instance>> {
+    check(instances)?;
+    // Instances are written in `format`, not by a custom encoder
+    let options = &RemoveBgOptions { encoder: None, ..options.clone() };
+    let (width, height) = mask.dimensions();
+    let region: Vec<bool> = mask.iter().map(|&alpha| alpha >= THRESHOLD).collect();
+    let (labels, count) = label_components(&region, width as usize, height as usize, true);
+
+    regions(&labels, count, width, instances)
+        .into_iter()
+        .enumerate()
+        .map(|(index, region)| {
+            options.cancel.check()?;
+            let crop = expand(region.bounds, instances.margin, width, height);
+            let alpha = instance_mask(mask, &labels, region.label, crop);
+            let pixels = image.crop_imm(crop.x, crop.y, crop.width, crop.height);
+            let cutout = core::apply_alpha_mask_owned(pixels, &alpha, options)?;
+            let path = path(output_path, index + 1, format);
+            output::save_image(&cutout, &path, format, options, metadata)?;
+            Ok(Instance { bounds: region.bounds, area: region.area, path })
+        })
+        .collect()
+}
@@ -9,30 +9,170 @@
 //! - AI-powered background removal using U2-Net model
 //! - Automatic model download on first use
 //! - Support for multiple image formats (JPEG, PNG, BMP, TIFF, etc.)
-//! - Transparent PNG output
-//! - Simple API and CLI interface
+//! - Transparent PNG, WebP, TIFF, or BMP output, or JPEG on a solid background
+//! - Multi-resolution ICO favicons and macOS iconsets
+//! - Numbered image sequences (video frames) with temporally smoothed masks
+//! - Simple API and CLI interface, including in-memory and stdin/stdout processing
+//! - http(s) URLs as input
+//! - Optional `s3://`, `gs://` and `az://` inputs and outputs (`object-store` feature)
+//! - Optional hardware acceleration via CoreML (macOS) and DirectML (Windows)
+//! - Optional pure-Rust inference with tract (`backend-tract` feature), for
+//!   builds without the native ONNX Runtime library
+//! - Optional HEIC/HEIF input via libheif (`heif` feature)
+//! - Optional camera RAW input via rawler (`raw` feature)
+//! - Optional C API for embedding in other languages (`capi` feature)
+//! - Optional in-browser API for `wasm32-unknown-unknown` (`wasm` feature)
+//! - Optional async API for tokio applications (`async` feature)
+//! - Optional HTTP server (`server` feature)
+//! - Serde support for options, reports and errors (`serde` feature, on by default)
+//! - Multi-threaded mask application and compositing (`parallel` feature, on by default)
+//! - Optional `tracing` spans for each pipeline stage (`tracing` feature)
 //!
 //! # Examples
 //!
 //! ```no_run
 //! use removebg::remove_background;
+//! use std::path::Path;
 //!
 //! // Basic usage - auto-generates output filename
 //! let output = remove_background("photo.jpg", None)?;
-//! println!("Saved to: {}", output);
+//! println!("Saved to: {}", output.display());
 //!
 //! // Custom output path
-//! let output = remove_background("photo.jpg", Some("result.png"))?;
-//! println!("Saved to: {}", output);
+//! let output = remove_background("photo.jpg", Some(Path::new("result.png")))?;
+//! println!("Saved to: {}", output.display());
 //! # Ok::<(), removebg::error::RemoveBgError>(())
 //! ```
+//!
+//! # Untrusted input
+//!
+//! The processing entry points (`remove_background*`, the
+//! [`BackgroundRemover`] methods and [`batch`]) don't panic on any input
+//! bytes: decoding is bounded by [`DecodeLimits`], and a panic inside the
+//! pipeline, such as a decoder failing on a malformed BMP or TIFF, is caught
+//! and returned as [`RemoveBgError::ProcessingError`] with the panic's
+//! message. The `fuzz` directory holds a `cargo fuzz`
+//! target for the in-memory path.
+
+// Declared first so the span macros are visible in every module
+#[macro_use]
+mod telemetry;
 
+pub mod analysis;
+mod animation;
+pub mod archive;
+mod artifacts;
+mod auto_model;
+#[cfg(feature = "async")]
+pub mod async_api;
+pub mod batch;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod bench;
+mod blur;
+mod chroma;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod clipboard;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod cloud;
+#[cfg(feature = "serde")]
+pub mod compare;
+pub mod config;
 pub mod core;
+pub mod cpu;
+#[cfg(feature = "serde")]
+pub mod daemon;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod doctor;
+mod disk_space;
+mod enhance;
 pub mod error;
+#[cfg(feature = "capi")]
+pub mod ffi;
+pub mod fallback;
+mod file_size;
+pub mod format;
+pub mod frame;
+mod geometry;
+mod heif;
+mod icon;
+mod instances;
+mod line_art;
+pub mod manifest;
+pub mod mask_cache;
+mod mask_ops;
+mod matte;
+pub mod memory;
+pub mod model;
+pub mod npy;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod notification;
+pub mod options;
+#[cfg(feature = "ort")]
+mod ort_backend;
+pub mod output;
+mod pages;
+mod paths;
+pub mod pipeline;
+pub mod preset;
+pub mod preview;
+pub mod progress;
+mod quantize;
+mod raw;
+mod resolution;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod remote;
+pub mod remover;
+pub mod report;
+pub mod reproducible;
+mod rows;
+pub mod segmentation;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod sequence;
+#[cfg(feature = "serde")]
+pub mod serialization;
+mod sniff;
+mod sticker;
+pub mod testing;
+mod tiling;
+#[cfg(feature = "backend-tract")]
+mod tract_backend;
+mod upscale;
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+pub mod wasm;
+pub mod watch;
 
 // Re-export main API
-pub use core::remove_background;
-pub use error::{RemoveBgError, Result};
+pub use analysis::{alpha_stats, mask_confidence, AlphaStats, Component, CoverageWarning, SubjectAnalysis};
+pub use core::{
+    analyze, apply_external_mask, cutout_stats, remove_background, remove_background_detailed, remove_background_from_bytes,
+    remove_background_to_writer, remove_background_with_options,
+};
+#[cfg(not(target_arch = "wasm32"))]
+pub use core::{prepare_model, remove_background_from_url};
+#[cfg(not(target_arch = "wasm32"))]
+pub use cloud::remove_background_uri;
+pub use blur::{blur_layers, BlurLayers};
+#[cfg(feature = "async")]
+pub use async_api::{remove_background_async, remove_background_from_bytes_async};
+pub use error::{most_severe_exit_code, ErrorInfo, ExitCodeInfo, RemoveBgError, Result, Stage, EXIT_CODES};
+pub use frame::PixelFormat;
+pub use model::{Model, ModelSpec};
+pub use options::{
+    AutoModel, Background, Backend, CancellationToken, Collision, ConfidenceOptions, DecodeLimits, Device, DiskSpaceCheck, DownloadOptions, FileSizeLimit, Fusion, MaskFilter, MaskFormat, MaskOp, OptimizationLevel,
+    GeometryOptions, GuidedFilterOptions, IconOptions, InferenceSize, InstanceOptions, MaskCacheOptions, NamingOptions, OutputKind, OutputSpec, OrtAllocator, OrtEnvironmentConfig, Pages, QualityFallback, RemoveBgOptions, Reprocess, RetryPolicy, SessionMemoryOptions, StickerOptions,
+    TileOptions, TrimapOptions,
+};
+pub use output::{JpegOutput, OutputEncoder, OutputFormat, PngCompression, PngFilter, PngOptions, PngOutput, TiffOutput, WebPOutput};
+pub use preset::Preset;
+pub use preview::{before_after_gif, composite_on_checkerboard, make_thumbnail};
+pub use remover::BackgroundRemover;
+pub use report::{Artifact, Downgrade, FileSizeFit, InferenceInfo, Instance, MaskFastPath, MemoryEstimate, ModelInfo, ModelSelection, PageOutput, PageSummary, Rect, RemovalReport, StageDurations};
+pub use segmentation::SegmentationModel;
+#[cfg(feature = "ort")]
+pub use segmentation::OrtU2Net;
+#[cfg(feature = "backend-tract")]
+pub use segmentation::TractU2Net;
 
 /// Library version
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
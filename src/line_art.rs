@@ -0,0 +1,52 @@
+//! Masks for line art: scans, faxes and drawings of ink on paper.
+//!
+//! The segmentation models are trained on photos and produce broken masks
+//! for 1-bit scans, where there is no subject in the photographic sense. With
+//! `line_art`, inputs with only a few distinct colors skip the model and take
+//! their mask from luminance instead: the ink is kept and the paper removed.
+
+use crate::core::Gray16Image;
+use image::{DynamicImage, GenericImageView, Luma};
+use std::collections::{HashMap, HashSet};
+
+/// Inputs with at most this many distinct colors count as line art.
+const MAX_COLORS: usize = 16;
+
+/// Whether `image` looks like line art: no more than [`MAX_COLORS`]
+/// distinct colors.
+pub(crate) fn is_line_art(image: &DynamicImage) -> bool {
+    let mut colors = HashSet::new();
+    for (_, _, pixel) in image.pixels() {
+        if colors.insert(pixel.0) && colors.len() > MAX_COLORS {
+            return false;
+        }
+    }
+    true
+}
+
+/// A mask keeping the ink of a line-art image.
+///
+/// The most common luminance is taken as the paper and the level farthest
+/// from it as the ink, so white-on-black drawings work as well as black on
+/// white; levels in between get partial alpha. An image of a single color
+/// has no ink, and its mask is empty.
+pub(crate) fn luminance_mask(image: &DynamicImage) -> Gray16Image {
+    let luma = image.to_luma16();
+    let mut histogram: HashMap<u16, usize> = HashMap::new();
+    for pixel in luma.pixels() {
+        *histogram.entry(pixel[0]).or_default() += 1;
+    }
+    // Ties go to the brighter level, for a stable choice, here and for the ink
+    let paper = histogram.iter().max_by_key(|&(&level, &count)| (count, level)).map_or(u16::MAX, |(&level, _)| level);
+    let ink = histogram.keys().copied().max_by_key(|&level| (level.abs_diff(paper), level)).unwrap_or(paper);
+    let range = ink.abs_diff(paper) as f32;
+    log::debug!("line art: paper at luminance {}, ink at {}", paper >> 8, ink >> 8);
+
+    Gray16Image::from_fn(luma.width(), luma.height(), |x, y| {
+        if range == 0.0 {
+            return Luma([0]);
+        }
+        let alpha = luma.get_pixel(x, y)[0].abs_diff(paper) as f32 / range;
+        Luma([(alpha.min(1.0) * 65535.0).round() as u16])
+    })
+}
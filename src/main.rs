@@ -3,83 +3,3668 @@
 //! This binary provides a user-friendly CLI for removing backgrounds from images,
 //! with support for custom output paths and verbose logging.
 
-use clap::Parser;
-use removebg::{remove_background, RemoveBgError};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueHint};
+use indicatif::{ProgressBar, ProgressStyle};
+use removebg::archive::{self, EntryStatus};
+use removebg::batch::{process_directory, BatchConfig, BatchSummary, SkipExisting};
+use removebg::bench::{run_benchmark, STAGES};
+use removebg::clipboard;
+use removebg::compare::{compare_image, parse_option_set, shares_segmentation, write_csv, ComparisonSummary};
+use removebg::cloud;
+use removebg::config::{self, Config};
+use removebg::doctor::{self, CheckStatus};
+use removebg::manifest::{run_manifest, write_results, ItemStatus, ManifestConfig, ManifestFormat};
+use removebg::model::{
+    confirm_download, download_url, embedded_model, expected_sha256, model_path, name_with_size, needs_download, quantize_model,
+    remove_model, validate_model_url, ModelSource, Normalization,
+};
+use removebg::notification::{self, notify_outcome, Outcome};
+use removebg::output::{self, is_directory_path, negotiate_format, prepare_output_dir, write_atomic};
+use removebg::format::decimal_size;
+use removebg::progress::{self, DownloadProgress, Progress, ProgressEvent};
+use removebg::remote;
+use removebg::reproducible;
+use removebg::{
+    analyze, most_severe_exit_code, cutout_stats, AlphaStats, AutoModel, prepare_model, remove_background_detailed, remove_background_from_bytes, remove_background_from_url, Background, Backend, CancellationToken, Collision, ConfidenceOptions, CoverageWarning, DecodeLimits, Device, DiskSpaceCheck, ErrorInfo, DownloadOptions, FileSizeLimit, Fusion, GeometryOptions, MaskFilter, MaskFormat, MaskOp, Model, OptimizationLevel, OutputFormat, PngCompression,
+    GuidedFilterOptions, IconOptions, InferenceSize, InstanceOptions, MaskCacheOptions, NamingOptions, OutputKind, OrtEnvironmentConfig, OutputSpec, Pages, PngFilter, PngOptions, Preset, QualityFallback, Rect, RemovalReport, RemoveBgError, RemoveBgOptions, Reprocess, RetryPolicy, SessionMemoryOptions, StageDurations, StickerOptions, TileOptions,
+    TrimapOptions, EXIT_CODES,
+};
+use std::fs::File;
+use std::io::{self, BufWriter, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
 
 /// AI-powered background removal tool using U2-Net deep learning model
 #[derive(Parser, Debug)]
 #[command(name = "removebg")]
 #[command(author, version, about, long_about = None)]
-#[command(after_help = "EXAMPLES:
+#[command(after_help = format!("EXAMPLES:
     removebg input.jpg
     removebg input.jpg -o output.png
     removebg photo.png --output result.png
-    removebg image.jpg -v")]
+    removebg shoots/ --recursive --output-dir cutouts/
+    removebg image.jpg -v
+    removebg image.jpg --json
+    removebg photo.jpg --analyze
+    curl -s https://example.com/photo.jpg | removebg - --stdout > cutout.png
+    removebg photo.jpg --device coreml
+    removebg photo.jpg -o cutout.webp --quality 90
+    removebg photo.jpg -o product.jpg --bg-color white
+    removebg photo.jpg --model u2net-quant
+    removebg photo.jpg --preset product
+    removebg compare shots/*.jpg --a preset=product --b preset=product,feather=3
+    removebg model list
+    removebg model fetch u2netp
+    removebg model quantize
+
+{}
+
+{}", output::help(), config::help()))]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    /// Path to the input image file
-    #[arg(value_name = "INPUT")]
-    input: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Path to the input image file, directory or .zip archive, an http(s) URL, an s3://, gs:// or az:// URI (a prefix ending in / for a batch), or `-` to read from stdin
+    #[arg(value_name = "INPUT", required_unless_present_any = ["from_clipboard", "show_config", "list_exit_codes"], value_hint = ValueHint::AnyPath)]
+    input: Option<PathBuf>,
+
+    /// Path or s3://, gs:// or az:// URI to save the output image, or a directory to save <input>_nobg.png in (default: <input>_nobg.png)
+    #[arg(short, long, value_name = "OUTPUT", value_hint = ValueHint::AnyPath, conflicts_with = "stdout")]
+    output: Option<PathBuf>,
+
+    /// For directory input: write cutouts under DIR, mirroring the input tree (default: next to each input)
+    #[arg(long, value_name = "DIR", value_hint = ValueHint::DirPath, conflicts_with_all = ["output", "stdout"])]
+    output_dir: Option<PathBuf>,
+
+    /// For .zip archive input: write the cutouts into this archive, keeping their directories (default: <input>_nobg.zip)
+    #[arg(long, value_name = "ZIP", value_hint = ValueHint::FilePath, conflicts_with_all = ["output", "stdout", "output_dir"])]
+    output_zip: Option<PathBuf>,
+
+    /// For directory input: also process images in subdirectories
+    #[arg(short, long)]
+    recursive: bool,
+
+    /// For directory input: skip files and directories whose names start with a dot
+    #[arg(long)]
+    skip_hidden: bool,
+
+    /// For directory input: skip images whose output file already exists
+    #[arg(long, conflicts_with_all = ["force", "newer_only"])]
+    skip_existing: bool,
+
+    /// For directory input: skip images whose output is newer than the input, replacing stale outputs
+    #[arg(long, conflicts_with = "force")]
+    newer_only: bool,
+
+    /// For directory input: run the model on N images at once for higher throughput
+    #[arg(long, value_name = "N", default_value = "1", value_parser = parse_batch_size)]
+    batch_size: usize,
+
+    /// Write the output image to stdout instead of a file
+    #[arg(long)]
+    stdout: bool,
+
+    /// Read the input image from the system clipboard instead of INPUT (`clipboard` feature)
+    #[arg(long, conflicts_with_all = ["input", "analyze", "bench", "dump_intermediates"])]
+    from_clipboard: bool,
+
+    /// Put the result on the system clipboard as PNG instead of writing a file (`clipboard` feature)
+    #[arg(long, conflicts_with_all = ["output", "stdout", "output_dir", "format", "analyze", "bench", "dump_intermediates"])]
+    to_clipboard: bool,
+
+    /// Print the subject's bounding box, coverage and regions as JSON instead of writing a cutout
+    #[arg(long, conflicts_with_all = ["output", "stdout", "output_dir", "save_mask"])]
+    analyze: bool,
+
+    /// Process the input N more times after a warm-up run and print min/median/max per stage
+    #[arg(long, value_name = "N", value_parser = parse_bench_iterations, conflicts_with_all = ["stdout", "analyze", "output_dir"])]
+    bench: Option<usize>,
+
+    /// Mask alpha from 0 to 1 at which a pixel counts as subject for --analyze
+    #[arg(long, value_name = "ALPHA", default_value = "0.5", value_parser = parse_threshold, requires = "analyze")]
+    analyze_threshold: f32,
+
+    /// Write the preprocessed tensor, raw model output and final mask into DIR instead of a cutout
+    #[arg(long, value_name = "DIR", value_hint = ValueHint::DirPath, hide = true, conflicts_with_all = ["analyze", "output", "stdout", "output_dir"])]
+    dump_intermediates: Option<PathBuf>,
+
+    /// Overwrite existing output files, and allow binary output to a terminal
+    #[arg(short, long)]
+    force: bool,
+
+    /// Create the output file's directory if it doesn't exist
+    #[arg(long)]
+    create_dirs: bool,
+
+    /// Text put before the input's name in generated output names
+    #[arg(long, value_name = "TEXT", default_value = "", allow_hyphen_values = true)]
+    prefix: String,
+
+    /// Text put after the input's name in generated output names [default: _nobg, or _pop with --grayscale-background]
+    #[arg(long, value_name = "TEXT", allow_hyphen_values = true)]
+    suffix: Option<String>,
+
+    /// Keep the input's name in generated output names, without a suffix
+    #[arg(long, conflicts_with = "suffix")]
+    no_suffix: bool,
+
+    /// When an output file exists or two inputs would write the same one: error, overwrite (like --force), or rename (append -1, -2, ...)
+    #[arg(long, value_name = "POLICY", default_value = "error", conflicts_with = "force")]
+    on_collision: Collision,
+
+    /// Start from a named set of options: product, portrait, sticker, web (see `removebg presets`); other flags override it
+    #[arg(long, value_name = "NAME")]
+    preset: Option<Preset>,
+
+    /// Output format: png, webp, tiff, bmp, jpeg, gif, apng, ico (default: from the output extension, else png; animated inputs keep gif/apng; see OUTPUT FORMAT below)
+    #[arg(long, value_name = "FORMAT")]
+    format: Option<OutputFormat>,
+
+    /// Replace an -o extension that doesn't match the output format (out.png with --format webp writes out.webp) instead of refusing it
+    #[arg(long)]
+    fix_extension: bool,
+
+    /// Lossy quality 0-100 for WebP and JPEG (default: lossless WebP, JPEG 90)
+    #[arg(long, value_name = "QUALITY")]
+    quality: Option<f32>,
+
+    /// Flatten onto a background color (name or #rrggbb, or auto to match the photo's own background); required for JPEG output
+    #[arg(long, value_name = "COLOR")]
+    bg_color: Option<Background>,
+
+    /// Replace the background with a key color (default: green) with clean edges for video keying
+    #[arg(long, value_name = "COLOR", value_parser = parse_color, num_args = 0..=1, default_missing_value = "green", conflicts_with_all = ["bg_color", "blur_background", "grayscale_background"])]
+    chroma_key: Option<[u8; 3]>,
+
+    /// Downscale inputs whose longer side exceeds PX right after loading, so masking and compositing run at that size
+    #[arg(long, value_name = "PX", value_parser = parse_max_output_size, conflicts_with = "roi")]
+    max_output_size: Option<u32>,
+
+    /// Run inference on overlapping full-resolution tiles to keep fine detail in large images
+    #[arg(long)]
+    tiled: bool,
+
+    /// Tile edge length in pixels for --tiled
+    #[arg(long, value_name = "PX", default_value = "1024", requires = "tiled")]
+    tile_size: u32,
+
+    /// Overlap between neighboring tiles in pixels for --tiled
+    #[arg(long, value_name = "PX", default_value = "128", requires = "tiled")]
+    tile_overlap: u32,
+
+    /// Only keep the subject inside this rectangle of the input, given as X,Y,W,H in pixels
+    #[arg(long, value_name = "X,Y,W,H", value_parser = parse_roi)]
+    roi: Option<Rect>,
+
+    /// Context around --roi given to the model, as a percentage of its longer side
+    #[arg(long, value_name = "PERCENT", default_value = "0", value_parser = parse_margin, requires = "roi")]
+    roi_margin: f32,
+
+    /// Run the model again on a crop around the subject, for small subjects in large scenes (about twice the inference time)
+    #[arg(long)]
+    two_pass: bool,
+
+    /// Size the model runs at: a multiple of 32 from 128 to 2048 (e.g. 640 or 1024 for more detail, at about the square of the time), native for the model's own (320 for U2-Net), or auto to choose 320, 640 or 1024 per image
+    #[arg(long, value_name = "N|auto", default_value = "native")]
+    inference_size: InferenceSize,
+
+    /// Brighten and stretch the contrast of the copy the model sees, for dark photos, with optional strength 0-1; the output keeps the original look
+    #[arg(long, value_name = "STRENGTH", num_args = 0..=1, default_missing_value = "1.0", value_parser = parse_strength)]
+    auto_enhance: Option<f32>,
+
+    /// Refine the mask edges with the full-resolution image: guided
+    #[arg(long, value_name = "METHOD", value_parser = ["guided"])]
+    refine: Option<String>,
+
+    /// Window radius in pixels for --refine guided
+    #[arg(long, value_name = "PX", default_value = "16", value_parser = parse_refine_radius)]
+    refine_radius: u32,
+
+    /// Regularization for --refine guided; smaller follows fainter edges
+    #[arg(long, value_name = "EPSILON", default_value = "0.001", value_parser = parse_refine_epsilon)]
+    refine_epsilon: f32,
+
+    /// Keep only the largest subject region, removing stray blobs from the mask
+    #[arg(long)]
+    only_largest: bool,
+
+    /// Keep the N largest subject regions (implies --only-largest)
+    #[arg(long, value_name = "N")]
+    keep_components: Option<usize>,
+
+    /// Treat subject regions up to PX pixels apart as one when filtering regions
+    #[arg(long, value_name = "PX", default_value = "0")]
+    component_gap: u32,
+
+    /// Fill see-through patches enclosed by the subject
+    #[arg(long)]
+    fill_holes: bool,
+
+    /// Only fill holes up to this many pixels in area (default: no limit)
+    #[arg(long, value_name = "PIXELS")]
+    max_hole_size: Option<usize>,
+
+    /// Use this grayscale image, or 2-D float32 or uint8 .npy array, as the mask instead of running the model
+    #[arg(long, value_name = "MASK", value_hint = ValueHint::FilePath)]
+    use_mask: Option<PathBuf>,
+
+    /// Take the mask of line art (scans, drawings with at most 16 colors) from luminance instead of the model
+    #[arg(long, conflicts_with = "use_mask")]
+    line_art: bool,
+
+    /// Also save the unrefined mask as <input>_mask.png, or with --save-mask=npy as a float32 <input>_mask.npy
+    /// array, for reuse with --use-mask
+    #[arg(long, value_name = "FORMAT", num_args = 0..=1, require_equals = true, default_missing_value = "png", conflicts_with = "stdout")]
+    save_mask: Option<MaskFormat>,
+
+    /// Also write a trimap of the unrefined mask (0 background, 128 unknown, 255 foreground) to this PNG
+    #[arg(long, value_name = "PATH", value_hint = ValueHint::FilePath, conflicts_with = "stdout")]
+    export_trimap: Option<PathBuf>,
+
+    /// Mask values (0-1) at or below LOW and at or above HIGH are definite background and foreground in the trimap
+    #[arg(long, value_name = "LOW,HIGH", default_value = "0.05,0.95", value_parser = parse_trimap_thresholds, requires = "export_trimap")]
+    trimap_thresholds: (f32, f32),
+
+    /// Shrink the definite regions of the trimap by this many pixels, widening the unknown band
+    #[arg(long, value_name = "PX", default_value = "10", requires = "export_trimap")]
+    trimap_erode: u32,
+
+    /// Also write each separate subject as its own transparent image, named <output>_1, _2, ... from largest to smallest
+    #[arg(long, conflicts_with = "stdout")]
+    split_instances: bool,
+
+    /// Subjects with fewer pixels than this are dropped as noise by --split-instances
+    #[arg(long, value_name = "PX", default_value = "1000", requires = "split_instances")]
+    min_instance_area: u64,
+
+    /// Most subjects written by --split-instances, largest first
+    #[arg(long, value_name = "N", default_value = "32", requires = "split_instances")]
+    max_instances: usize,
+
+    /// Margin around each subject written by --split-instances, as a fraction of its larger side
+    #[arg(long, value_name = "FRACTION", default_value = "0.05", requires = "split_instances")]
+    instance_margin: f32,
+
+    /// Remove the subject and keep the background, leaving a subject-shaped hole
+    #[arg(long)]
+    invert: bool,
+
+    /// Remove background color fringes along the cutout edge, with optional strength 0-1
+    #[arg(long, value_name = "STRENGTH", num_args = 0..=1, default_missing_value = "1.0")]
+    decontaminate: Option<f32>,
+
+    /// Write premultiplied alpha (color times alpha) for game engines and compositors
+    #[arg(long)]
+    premultiply: bool,
+
+    /// Keep the background but blur it with this Gaussian sigma in pixels (portrait mode)
+    #[arg(long, value_name = "SIGMA", value_parser = parse_sigma, conflicts_with = "bg_color")]
+    blur_background: Option<f32>,
+
+    /// Keep the background but make it grayscale, leaving the subject in color (output: <input>_pop.png)
+    #[arg(long, conflicts_with = "bg_color")]
+    grayscale_background: bool,
+
+    /// Lighten (above 1) or darken (below 1) a blurred or grayscale background
+    #[arg(long, value_name = "FACTOR", value_parser = parse_brightness)]
+    background_brightness: Option<f32>,
+
+    /// Make a sticker: draw an outline and a drop shadow around the cutout
+    #[arg(long, conflicts_with_all = ["blur_background", "grayscale_background"])]
+    sticker: bool,
+
+    /// Outline width in pixels for --sticker (0 = no outline)
+    #[arg(long, value_name = "PX", default_value = "12")]
+    outline_width: u32,
+
+    /// Outline color for --sticker, as a name or #rrggbb
+    #[arg(long, value_name = "COLOR", default_value = "white", value_parser = parse_color)]
+    outline_color: [u8; 3],
+
+    /// Shadow offset in pixels for --sticker, as X,Y (right and down)
+    #[arg(long, value_name = "X,Y", default_value = "4,6", value_parser = parse_offset, allow_hyphen_values = true)]
+    shadow_offset: (i32, i32),
+
+    /// Shadow blur (Gaussian sigma) in pixels for --sticker
+    #[arg(long, value_name = "SIGMA", default_value = "6", value_parser = parse_shadow_blur)]
+    shadow_blur: f32,
+
+    /// Shadow opacity from 0 to 1 for --sticker (0 = no shadow)
+    #[arg(long, value_name = "OPACITY", default_value = "0.35", value_parser = parse_opacity)]
+    shadow_opacity: f32,
+
+    /// Shrink the output to fit within WxH, keeping its aspect ratio
+    #[arg(long, value_name = "WxH", value_parser = parse_size)]
+    resize: Option<(u32, u32)>,
+
+    /// Let --resize and icon sizes enlarge images smaller than the target
+    #[arg(long)]
+    allow_upscale: bool,
+
+    /// Pad the output to a square canvas, centering the image
+    #[arg(long)]
+    square: bool,
+
+    /// Crop to the subject plus a margin of this percentage of its longer side
+    #[arg(long, value_name = "PERCENT", value_parser = parse_percent)]
+    pad: Option<f32>,
+
+    /// Entry sizes in pixels for --format ico, comma-separated (1-256)
+    #[arg(long, value_name = "SIZES", value_delimiter = ',', default_value = "16,32,48,64,128,256", value_parser = parse_icon_size)]
+    icon_sizes: Vec<u32>,
+
+    /// Also write a macOS iconset (<input>.iconset with 16-1024 px PNGs) into DIR
+    #[arg(long, value_name = "DIR", value_hint = ValueHint::DirPath)]
+    iconset_dir: Option<PathBuf>,
+
+    /// Also write the model's mask, an overlay, and a comparison strip for each input into DIR
+    #[arg(long, value_name = "DIR", value_hint = ValueHint::DirPath)]
+    debug_output: Option<PathBuf>,
+
+    /// Also write KIND (cutout, mask, flattened, overlay, thumbnail, preview) from the same model run; repeatable.
+    /// TEMPLATE may use {stem}, {dir} (the output's directory) and {kind}
+    #[arg(long, value_name = "KIND[@QUALITY][:TEMPLATE]")]
+    emit: Vec<OutputSpec>,
+
+    /// Also write the cutout shrunk to fit within SIZE pixels, with its transparency, as <input>_thumb.png next to the output
+    #[arg(long, value_name = "SIZE", value_parser = parse_max_output_size, conflicts_with = "stdout")]
+    thumbnail: Option<u32>,
+
+    /// Also write a looping GIF alternating between the input and the cutout on a checkerboard to PATH; for a
+    /// directory, <stem>_<PATH's stem>.gif next to each output
+    #[arg(long, value_name = "PATH", value_hint = ValueHint::FilePath, conflicts_with = "stdout")]
+    preview_gif: Option<PathBuf>,
+
+    /// Longest side in pixels of --preview-gif frames
+    #[arg(long, value_name = "SIZE", default_value = "480", value_parser = parse_max_output_size)]
+    preview_size: u32,
+
+    /// How long each --preview-gif frame shows, e.g. 800ms or 2s
+    #[arg(long, value_name = "DURATION", default_value = "1s", value_parser = parse_duration)]
+    preview_delay: Duration,
+
+    /// Fail with exit code 7 instead of writing an output when the mask's confidence score (0-1) is below this
+    #[arg(long, value_name = "SCORE", value_parser = parse_confidence)]
+    min_confidence: Option<f32>,
+
+    /// Exit with code 7 when the subject covers under 1% or over 99% of the image, or when an input looks
+    /// already processed; fail instead of warning when an output looks too big for the free disk space
+    #[arg(long)]
+    strict: bool,
+
+    /// Process inputs that look like earlier cutouts (named like an output, or already transparent) without a warning
+    #[arg(long)]
+    allow_reprocess: bool,
+
+    /// Process only page N (from 1) of a multi-page TIFF, saved as <stem>_pN_nobg.png
+    #[arg(long, value_name = "N", value_parser = parse_page, conflicts_with = "all_pages")]
+    page: Option<u32>,
+
+    /// Process every page of a multi-page TIFF, saved as <stem>_p1_nobg.png, <stem>_p2_nobg.png, ...
+    #[arg(long, conflicts_with = "stdout")]
+    all_pages: bool,
+
+    /// Refuse input images with more pixels than this; 0 disables the limit
+    #[arg(long, value_name = "PIXELS", default_value = "80000000")]
+    max_pixels: u64,
+
+    /// Refuse URL inputs larger than this many bytes; 0 disables the limit
+    #[arg(long, value_name = "BYTES", default_value = "104857600")]
+    max_download_size: u64,
+
+    /// Keep processing one image under SIZE of memory (e.g. 1GB, 512MiB) by an estimate from its header, upscaling the mask in bands if that fits and refusing the image otherwise
+    #[arg(long, value_name = "SIZE", env = "REMOVEBG_MAX_MEMORY", value_parser = parse_byte_size, global = true)]
+    max_memory: Option<u64>,
+
+    /// Give up on an input after this long, e.g. 10s, 500ms or 2m, leaving no output; 0 disables the limit (default: none, 60s for daemon and serve)
+    #[arg(long, value_name = "DURATION", env = "REMOVEBG_TIMEOUT", value_parser = parse_duration, global = true)]
+    timeout: Option<Duration>,
+
+    /// Don't copy the input's ICC color profile and EXIF data to the output
+    #[arg(long)]
+    strip_metadata: bool,
+
+    /// PNG compression level: fast, default, best
+    #[arg(long, value_name = "LEVEL", default_value = "fast")]
+    png_compression: PngCompression,
+
+    /// PNG row filter: none, sub, up, avg, paeth, adaptive
+    #[arg(long, value_name = "FILTER", default_value = "adaptive")]
+    png_filter: PngFilter,
+
+    /// Reduce PNG output to an 8-bit palette with alpha for smaller files
+    #[arg(long)]
+    png_quantize: bool,
+
+    /// Keep the output file under SIZE (e.g. 2MB, 500KB, 1MiB) by lowering quality, compressing harder or palettizing
+    #[arg(long, value_name = "SIZE", value_parser = parse_byte_size)]
+    max_file_size: Option<u64>,
+
+    /// Scale the output down when no quality setting fits --max-file-size
+    #[arg(long, requires = "max_file_size")]
+    allow_downscale: bool,
+
+    /// Print verbose output, including debug logging
+    #[arg(short, long, conflicts_with = "quiet")]
+    verbose: bool,
+
+    /// Print only the output path on stdout (one per line for directories), and only warnings and errors on stderr
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Print the result as a JSON object on stdout; other messages go to stderr
+    #[arg(long, conflicts_with = "stdout")]
+    json: bool,
+
+    /// Show a desktop notification when done, for runs from a file manager (`notifications` feature)
+    #[arg(long)]
+    notify: bool,
+
+    /// Segmentation model: u2net, u2netp, u2net-quant, u2net-human-seg, silueta, birefnet-lite, or auto to pick one per image
+    #[arg(short, long, value_name = "MODEL", default_value_t = ModelChoice::Fixed(Model::default()))]
+    model: ModelChoice,
+
+    /// With --model auto, run at most this many models on one image, counting the first
+    #[arg(long, value_name = "N", value_parser = parse_max_models, default_value = "2")]
+    max_models: u32,
+
+    /// How to combine U2-Net's side outputs: first, mean:N, or weighted:W1,W2,...
+    #[arg(long, value_name = "FUSION", default_value = "first")]
+    mask_fusion: Fusion,
+
+    /// Stretch the model's mask range LOW,HIGH (0-1) to fully transparent..opaque
+    #[arg(long, value_name = "LOW,HIGH", value_parser = parse_levels)]
+    mask_levels: Option<(f32, f32)>,
+
+    /// Raise the model's mask to this power: below 1 is more opaque, above 1 more transparent
+    #[arg(long, value_name = "GAMMA", value_parser = parse_gamma)]
+    mask_gamma: Option<f32>,
+
+    /// Post-process the mask with these operators in order instead of the flags above, e.g. dilate=3,feather=2.5,threshold=0.5 (threshold=T, levels=LOW:HIGH, gamma=G, erode=PX, dilate=PX, feather=SIGMA, fill-holes[=PIXELS], keep-largest[=N[:GAP]])
+    #[arg(long, value_name = "OPS", value_delimiter = ',', conflicts_with_all = ["mask_levels", "mask_gamma", "only_largest", "keep_components", "fill_holes"])]
+    mask_ops: Vec<MaskOp>,
+
+    /// Filter for scaling the model's mask to the image: nearest, triangle, catmullrom, lanczos3
+    #[arg(long, value_name = "FILTER", default_value = "triangle")]
+    mask_filter: MaskFilter,
+
+    /// Set the alpha of the outermost PX pixels on each side to the mask value just inside them, for a faint frame around the output
+    #[arg(long, value_name = "PX", default_value = "0")]
+    trim_border: u32,
+
+    /// Directory for cached model files (default: REMOVEBG_MODEL_DIR, U2NET_HOME, or the user cache dir)
+    #[arg(long, value_name = "DIR", value_hint = ValueHint::DirPath, global = true)]
+    model_dir: Option<PathBuf>,
+
+    /// Reuse model masks of inputs seen before from DIR, skipping inference when only post-processing changed
+    #[arg(long, value_name = "DIR", env = "REMOVEBG_MASK_CACHE", value_hint = ValueHint::DirPath, global = true)]
+    mask_cache: Option<PathBuf>,
+
+    /// Delete the least recently used cached masks once the cache exceeds SIZE (e.g. 500MB, 2GiB)
+    #[arg(long, value_name = "SIZE", env = "REMOVEBG_MASK_CACHE_SIZE", default_value = "1GB", value_parser = parse_byte_size, global = true)]
+    mask_cache_size: u64,
+
+    /// Attempts for a model download before giving up; transient failures are retried with backoff
+    #[arg(long, value_name = "N", env = "REMOVEBG_DOWNLOAD_ATTEMPTS", default_value = "3", global = true)]
+    download_attempts: u32,
+
+    /// Download the model from this URL instead of GitHub, e.g. an internal mirror (default: REMOVEBG_MODEL_URL)
+    #[arg(long, value_name = "URL", value_parser = parse_model_url, global = true)]
+    model_url: Option<String>,
+
+    /// Expected SHA-256 of the downloaded model, for mirrors serving a different file
+    #[arg(long, value_name = "HEX", value_parser = parse_sha256, global = true)]
+    model_sha256: Option<String>,
+
+    /// Never download models; fail if the model isn't cached
+    #[arg(long, global = true)]
+    offline: bool,
+
+    /// Download a missing model without asking first (also REMOVEBG_AUTO_DOWNLOAD=1)
+    #[arg(long, env = "REMOVEBG_AUTO_DOWNLOAD", value_parser = clap::builder::BoolishValueParser::new())]
+    yes: bool,
+
+    /// Read defaults from this TOML file instead of the nearest removebg.toml or .removebg.toml
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath, global = true)]
+    config: Option<PathBuf>,
+
+    /// Fail on unknown keys in the config file instead of warning
+    #[arg(long, global = true)]
+    strict_config: bool,
+
+    /// Print the effective settings and where each one came from, then exit
+    #[arg(long, global = true)]
+    show_config: bool,
+
+    /// Print the exit codes, the errors behind each and their severity in batch runs, then exit
+    #[arg(long)]
+    list_exit_codes: bool,
+
+    /// Inference engine: ort (ONNX Runtime), or tract (pure Rust, slower; needs the backend-tract feature)
+    #[arg(long, value_name = "BACKEND", default_value_t = Backend::default(), global = true)]
+    backend: Backend,
+
+    /// Inference device: cpu, coreml, directml (falls back to cpu if unavailable)
+    #[arg(long, value_name = "DEVICE", default_value = "cpu")]
+    device: Device,
+
+    /// Threads for mask application and compositing (default: one per CPU, or RAYON_NUM_THREADS); outputs don't
+    /// depend on it
+    #[arg(long, value_name = "N", value_parser = parse_jobs, global = true)]
+    jobs: Option<usize>,
+
+    /// Write the same bytes for the same input, options and model on every run and machine: inference on one CPU
+    /// thread and one image per model run
+    #[arg(long, conflicts_with_all = ["ort_threads", "ort_inter_threads", "batch_size"])]
+    reproducible: bool,
+
+    /// ONNX Runtime intra-op threads (default: one per CPU, within the container's CPU quota)
+    #[arg(long, value_name = "N", env = "REMOVEBG_ORT_THREADS")]
+    ort_threads: Option<usize>,
+
+    /// ONNX Runtime inter-op threads
+    #[arg(long, value_name = "N", env = "REMOVEBG_ORT_INTER_THREADS")]
+    ort_inter_threads: Option<usize>,
+
+    /// ONNX Runtime graph optimization level (0-3)
+    #[arg(long, value_name = "LEVEL", env = "REMOVEBG_ORT_OPT_LEVEL", default_value = "3")]
+    ort_opt_level: OptimizationLevel,
+
+    /// Read the model file into memory instead of memory-mapping it
+    #[arg(long)]
+    no_mmap: bool,
+
+    /// Disable the ONNX Runtime CPU memory arena, returning memory to the system after each run
+    #[arg(long, conflicts_with = "ort_memory_limit")]
+    ort_no_arena: bool,
+
+    /// Cap the ONNX Runtime CPU memory arena at this many bytes
+    #[arg(long, value_name = "BYTES", env = "REMOVEBG_ORT_MEMORY_LIMIT")]
+    ort_memory_limit: Option<usize>,
+}
+
+/// Subcommands besides the default background removal.
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Manage segmentation models
+    #[command(subcommand)]
+    Model(ModelCommand),
+
+    /// Manage the mask cache selected by --mask-cache or REMOVEBG_MASK_CACHE
+    #[command(subcommand)]
+    Cache(CacheCommand),
+
+    /// List the presets for --preset with what each one sets
+    Presets,
+
+    /// Measure the alpha of an existing cutout without running the model
+    ///
+    /// Prints the same metrics as alpha_stats in --json output: a 16-bucket
+    /// alpha histogram, the share of ambiguous alpha (0.2-0.8), the edge
+    /// length and softness in pixels, and the regions of the subject and the
+    /// specks under a thousandth of the image. Exits with code 2 if the file
+    /// has no alpha channel.
+    Stats {
+        /// Cutout with an alpha channel, such as photo_nobg.png
+        #[arg(value_name = "CUTOUT", value_hint = ValueHint::FilePath)]
+        cutout: PathBuf,
+
+        /// Print the metrics as a JSON object instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Check that ONNX Runtime loads and the model cache is usable
+    ///
+    /// Prints one PASS, WARN or FAIL line per check and exits with code 3 if
+    /// any check fails. Nothing is downloaded.
+    Doctor {
+        /// Model to check the cached file of
+        #[arg(long, value_name = "MODEL", default_value_t = Model::default())]
+        model: Model,
+    },
+
+    /// Process images as they appear in a directory
+    ///
+    /// The model is loaded once at startup. Each new or modified image is
+    /// processed after it stops growing; press Ctrl-C to stop.
+    Watch {
+        /// Directory to watch
+        #[arg(value_name = "DIR", value_hint = ValueHint::DirPath)]
+        dir: PathBuf,
+
+        /// Directory to write cutouts to
+        #[arg(long, value_name = "DIR", value_hint = ValueHint::DirPath)]
+        output_dir: PathBuf,
+
+        /// Seconds a file must stay unchanged before it is processed
+        #[arg(long, value_name = "SECS", default_value = "1")]
+        settle: f64,
+
+        /// Output format (png, webp, tiff, bmp, jpeg, gif, apng, ico); png if not specified
+        #[arg(long, value_name = "FORMAT")]
+        format: Option<OutputFormat>,
+
+        /// Segmentation model: u2net, u2netp, u2net-quant, u2net-human-seg, silueta, birefnet-lite
+        #[arg(short, long, value_name = "MODEL", default_value_t = Model::default())]
+        model: Model,
+
+        /// Inference device: cpu, coreml, directml
+        #[arg(long, value_name = "DEVICE", default_value = "cpu")]
+        device: Device,
+    },
+
+    /// Process a numbered image sequence, such as exported video frames
+    ///
+    /// Frames are processed in numeric order with one loaded model, and each
+    /// mask is blended with the masks of the previous frames so the cutouts
+    /// don't flicker. A large change between frames is treated as a scene cut
+    /// and starts the blending over.
+    Sequence {
+        /// Frame files, or quoted patterns like 'frames/frame_*.png'
+        #[arg(value_name = "FRAMES", required = true, value_hint = ValueHint::FilePath)]
+        inputs: Vec<PathBuf>,
+
+        /// Directory to write cutouts to
+        #[arg(long, value_name = "DIR", value_hint = ValueHint::DirPath)]
+        output_dir: PathBuf,
+
+        /// Weight of the current frame's mask (0-1); older masks fade by
+        /// 1 - ALPHA per frame, and 1 turns smoothing off
+        #[arg(long, value_name = "ALPHA", default_value = "0.5", value_parser = parse_temporal_alpha)]
+        temporal_alpha: f32,
+
+        /// Number of frames whose masks are blended, including the current one
+        #[arg(long, value_name = "FRAMES", default_value = "4", value_parser = parse_temporal_window)]
+        temporal_window: usize,
+
+        /// Mean difference between consecutive frames (0-1) treated as a scene cut
+        #[arg(long, value_name = "THRESHOLD", default_value = "0.25", value_parser = parse_threshold)]
+        scene_cut_threshold: f32,
+
+        /// Output format (png, webp, tiff, bmp, jpeg, gif, apng, ico); png if not specified
+        #[arg(long, value_name = "FORMAT")]
+        format: Option<OutputFormat>,
+
+        /// Segmentation model: u2net, u2netp, u2net-quant, u2net-human-seg, silueta, birefnet-lite
+        #[arg(short, long, value_name = "MODEL", default_value_t = Model::default())]
+        model: Model,
+
+        /// Inference device: cpu, coreml, directml
+        #[arg(long, value_name = "DEVICE", default_value = "cpu")]
+        device: Device,
+
+        /// Overwrite existing output files
+        #[arg(short, long)]
+        force: bool,
+    },
+
+    /// Process the images listed in a CSV or JSONL manifest
+    ///
+    /// Each row names an input and optionally its output and per-image
+    /// overrides: format, quality, bg_color, crop, pad, square, resize.
+    /// Empty fields take the values of the flags below. The manifest is
+    /// checked before anything is processed, and a results manifest with
+    /// each image's status, output path and time is written next to it.
+    Batch {
+        /// CSV file with a header row, or JSONL file with one object per line
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        manifest: PathBuf,
+
+        /// Where to write the results manifest (default: <manifest>.results.csv or .jsonl next to it)
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        results: Option<PathBuf>,
+
+        /// Directory for outputs; relative output paths in the manifest are resolved against it
+        #[arg(long, value_name = "DIR", value_hint = ValueHint::DirPath)]
+        output_dir: Option<PathBuf>,
+
+        /// Output format (png, webp, tiff, bmp, jpeg, gif, apng, ico); png if not specified
+        #[arg(long, value_name = "FORMAT")]
+        format: Option<OutputFormat>,
+
+        /// Lossy quality 0-100 for WebP and JPEG
+        #[arg(long, value_name = "QUALITY")]
+        quality: Option<f32>,
+
+        /// Flatten onto a background color (name or #rrggbb)
+        #[arg(long, value_name = "COLOR")]
+        bg_color: Option<Background>,
+
+        /// Crop to the subject plus a margin of this percentage of its longer side
+        #[arg(long, value_name = "PERCENT", value_parser = parse_percent)]
+        pad: Option<f32>,
+
+        /// Pad the outputs to a square canvas
+        #[arg(long)]
+        square: bool,
+
+        /// Shrink the outputs to fit within WxH
+        #[arg(long, value_name = "WxH", value_parser = parse_size)]
+        resize: Option<(u32, u32)>,
+
+        /// Run the model on N images with the same options at once
+        #[arg(long, value_name = "N", default_value = "1", value_parser = parse_batch_size)]
+        batch_size: usize,
+
+        /// Segmentation model: u2net, u2netp, u2net-quant, u2net-human-seg, silueta, birefnet-lite
+        #[arg(short, long, value_name = "MODEL", default_value_t = Model::default())]
+        model: Model,
+
+        /// Inference device: cpu, coreml, directml
+        #[arg(long, value_name = "DEVICE", default_value = "cpu")]
+        device: Device,
+
+        /// Overwrite existing output files
+        #[arg(short, long)]
+        force: bool,
+
+        /// Create missing directories of output paths
+        #[arg(long)]
+        create_dirs: bool,
+    },
+
+    /// Compare two option sets on the same images
+    ///
+    /// Each input is processed under both option sets, and the differences
+    /// are reported per image and overall: the mean alpha difference, the
+    /// share of changed pixels, the overlap of the subjects' bounding boxes
+    /// and the size of the encoded outputs. Nothing else is written. When
+    /// the sets differ only after segmentation, each image runs the model
+    /// once for both.
+    ///
+    /// An option set lists comma-separated settings: preset=NAME, mask
+    /// operations as in --mask-ops (feather=3, threshold=0.4, ...), and
+    /// fields of the library options by their serialized names, with dots
+    /// for nested ones (model=u2net, background=white, geometry.pad=0.05).
+    Compare {
+        /// Images to compare the option sets on
+        #[arg(value_name = "INPUTS", required = true, value_hint = ValueHint::FilePath)]
+        inputs: Vec<PathBuf>,
+
+        /// Option set A, like "preset=product"
+        #[arg(long, value_name = "SETTINGS")]
+        a: String,
+
+        /// Option set B, like "feather=3,threshold=0.4"
+        #[arg(long, value_name = "SETTINGS")]
+        b: String,
+
+        /// Write a difference image per input into DIR: red where A keeps more of the subject, blue where B does
+        #[arg(long, value_name = "DIR", value_hint = ValueHint::DirPath)]
+        diff_dir: Option<PathBuf>,
+
+        /// Print the results as a JSON object instead of a table
+        #[arg(long)]
+        json: bool,
+
+        /// Also write the per-image results to FILE as CSV
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        csv: Option<PathBuf>,
+
+        /// Segmentation model of both option sets unless they set another
+        #[arg(short, long, value_name = "MODEL", default_value_t = Model::default())]
+        model: Model,
+
+        /// Inference device: cpu, coreml, directml
+        #[arg(long, value_name = "DEVICE", default_value = "cpu")]
+        device: Device,
+    },
+
+    /// Process images named in JSON commands read from stdin
+    ///
+    /// The model is loaded once at startup. Each line of stdin is a command
+    /// like {"input": "a.jpg", "output": "a.png", "options": {...}}, and gets
+    /// one JSON result line on stdout, flushed before the next command is
+    /// read. {"cmd": "shutdown"} or the end of stdin exits.
+    Daemon {
+        /// Segmentation model: u2net, u2netp, u2net-quant, u2net-human-seg, silueta, birefnet-lite
+        #[arg(short, long, value_name = "MODEL", default_value_t = Model::default())]
+        model: Model,
+
+        /// Inference device: cpu, coreml, directml
+        #[arg(long, value_name = "DEVICE", default_value = "cpu")]
+        device: Device,
+
+        /// Overwrite existing output files
+        #[arg(short, long)]
+        force: bool,
+
+        /// Skip the warm-up run on a blank image after loading the model
+        #[arg(long)]
+        no_warmup: bool,
+
+        /// Give up quality to finish each image's model run within DURATION, e.g. 800ms: switch to u2netp, skip refinements, lower the inference size
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        deadline: Option<Duration>,
+    },
+
+    /// Serve background removal over HTTP
+    ///
+    /// POST an image to /remove to get the cutout back; GET /health and
+    /// /version report status.
+    #[cfg(feature = "server")]
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
+
+        /// Port to listen on
+        #[arg(short, long, default_value = "8080")]
+        port: u16,
+
+        /// Largest accepted request body in bytes
+        #[arg(long, value_name = "BYTES", default_value = "20971520")]
+        max_body_size: usize,
+
+        /// Number of request handler threads (default: 4, or fewer with fewer CPUs)
+        #[arg(long, value_name = "N")]
+        workers: Option<usize>,
+
+        /// Segmentation model: u2net, u2netp, u2net-quant, u2net-human-seg, silueta, birefnet-lite
+        #[arg(short, long, value_name = "MODEL", default_value_t = Model::default())]
+        model: Model,
+
+        /// Inference device: cpu, coreml, directml
+        #[arg(long, value_name = "DEVICE", default_value = "cpu")]
+        device: Device,
+
+        /// Skip the warm-up run on a blank image after loading the model
+        #[arg(long)]
+        no_warmup: bool,
+
+        /// Give up quality to finish each image's model run within DURATION, e.g. 800ms: switch to u2netp, skip refinements, lower the inference size
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        deadline: Option<Duration>,
+    },
+    /// Write shell completions or man pages, for packagers
+    ///
+    /// Both are generated from the argument definitions of this binary, so
+    /// they always match its flags.
+    #[command(hide = true)]
+    Generate {
+        /// Shell to write completions for
+        #[arg(long, value_name = "SHELL", required_unless_present = "man", conflicts_with = "man")]
+        shell: Option<clap_complete::Shell>,
+
+        /// Write the man page instead; with --out-dir, also one page per subcommand
+        #[arg(long)]
+        man: bool,
+
+        /// Write files into DIR instead of printing to stdout
+        #[arg(long, value_name = "DIR", value_hint = ValueHint::DirPath)]
+        out_dir: Option<PathBuf>,
+    },
+}
+
+/// Model management subcommands.
+#[derive(Subcommand, Debug)]
+enum ModelCommand {
+    /// List available models and whether they are cached
+    List,
+
+    /// Show details about a model
+    Info {
+        /// Model to describe
+        #[arg(value_name = "MODEL")]
+        model: Model,
+    },
+
+    /// Delete a cached model file
+    Remove {
+        /// Model to delete
+        #[arg(value_name = "MODEL")]
+        model: Model,
+
+        /// Don't ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+
+    /// Download, verify, and load a model so later runs start immediately
+    Fetch {
+        /// Model to prepare
+        #[arg(value_name = "MODEL", default_value_t = Model::default())]
+        model: Model,
+
+        /// Inference device to initialize the session for
+        #[arg(long, value_name = "DEVICE", default_value = "cpu")]
+        device: Device,
+    },
+
+    /// Create an INT8-quantized model from its cached fp32 base model
+    ///
+    /// Requires Python with the `onnxruntime` package installed.
+    Quantize {
+        /// Quantized model to create
+        #[arg(value_name = "MODEL", default_value = "u2net-quant")]
+        model: Model,
+    },
+}
+
+/// Mask cache subcommands.
+#[derive(Subcommand, Debug)]
+enum CacheCommand {
+    /// Show the number and total size of the cached masks
+    Stats,
+
+    /// Delete all cached masks
+    Clear,
+}
+
+fn main() {
+    let command = Args::command();
+    let subcommands: Vec<String> = command.get_subcommands().map(|command| command.get_name().to_string()).collect();
+    let matches = command.try_get_matches().unwrap_or_else(|e| {
+        // Flags of subcommands differ; the first word that isn't a flag names one
+        let subcommand = std::env::args_os()
+            .skip(1)
+            .find(|arg| !arg.as_encoded_bytes().starts_with(b"-"))
+            .is_some_and(|arg| subcommands.iter().any(|name| arg == name.as_str()));
+        suggest_flag(e, subcommand).exit()
+    });
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if args.list_exit_codes {
+        list_exit_codes(args.json);
+        process::exit(0);
+    }
+    init_logging(args.verbose, args.quiet);
+    #[cfg(feature = "parallel")]
+    removebg::cpu::init_thread_pool_with(args.jobs);
+    let config_file = args.config.clone().or_else(|| std::env::current_dir().ok().and_then(|dir| config::discover(&dir)));
+    let user_file = config::user_config().filter(|user| Some(user) != config_file.as_ref());
+    let read = |path: Option<&Path>| {
+        path.map(|path| {
+            log::debug!("Reading settings from {}", path.display());
+            Config::from_toml(path, args.strict_config)
+        })
+        .transpose()
+        .map(Option::unwrap_or_default)
+    };
+    let layers = read(config_file.as_deref())
+        .and_then(|file| Ok((Config::from_env()?, file, read(user_file.as_deref())?)));
+    match layers {
+        Ok((env, file, user)) => {
+            apply_config(&mut args, &matches, &env.clone().over(file.clone()).over(user.clone()));
+            apply_preset(&mut args, &matches);
+            if args.show_config {
+                let files = ConfigFiles { project: &file, project_path: config_file.as_deref(), user: &user, user_path: user_file.as_deref() };
+                print!("{}", show_config(&args, &matches, &env, &files));
+                process::exit(0);
+            }
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(e.exit_code());
+        }
+    }
+
+    // Run the requested command and handle errors
+    let result = match args.command.take() {
+        Some(command) => run_command(
+            command,
+            args.model_dir.as_deref(),
+            args.mask_cache.as_deref(),
+            args.backend,
+            download_options(&args),
+            args.timeout,
+            args.max_memory,
+            show_progress_bars(&args),
+            args.quiet,
+        ),
+        None => {
+            let checks = preflight(&mut args, dirs::home_dir().as_deref());
+            for note in &checks.notes {
+                log::info!("{}", note);
+            }
+            let result = run(args);
+            if result.is_err() {
+                for hint in &checks.hints {
+                    eprintln!("Hint: {}", hint);
+                }
+            }
+            result
+        }
+    };
+
+    match result {
+        Ok(()) => process::exit(0),
+        Err(code) => process::exit(code),
+    }
+}
+
+/// Print [`EXIT_CODES`] for `--list-exit-codes`, as a table or, with `json`,
+/// as a JSON array.
+fn list_exit_codes(json: bool) {
+    if json {
+        let codes: Vec<_> = EXIT_CODES
+            .iter()
+            .map(|info| {
+                serde_json::json!({
+                    "code": info.code,
+                    "name": info.name,
+                    "description": info.description,
+                    "kinds": info.kinds,
+                    "severity": info.severity(),
+                })
+            })
+            .collect();
+        print_json(&serde_json::Value::Array(codes));
+        return;
+    }
+    println!("{:<5} {:<18} {:>8}  DESCRIPTION", "CODE", "NAME", "SEVERITY");
+    for info in EXIT_CODES {
+        let severity = info.severity().map_or_else(|| "-".to_string(), |rank| rank.to_string());
+        println!("{:<5} {:<18} {:>8}  {}", info.code, info.name, severity, info.description);
+        if !info.kinds.is_empty() {
+            println!("{:<34}{}", "", info.kinds.join(", "));
+        }
+    }
+    println!("\nA run over several inputs exits with the code of its most severe failure (severity 1).");
+}
+
+/// Fill in the options `REMOVEBG_*` variables and the config file set that
+/// weren't given as flags, on the top level and on the subcommand: a flag
+/// always wins over `config`, which has the environment layered over the
+/// file, and `config` over the default.
+fn apply_config(args: &mut Args, matches: &ArgMatches, config: &Config) {
+    fn fill<T>(slot: &mut T, value: Option<T>, matches: &ArgMatches, id: &str) {
+        if let Some(value) = value.filter(|_| matches.value_source(id) != Some(ValueSource::CommandLine)) {
+            *slot = value;
+        }
+    }
+
+    fill(&mut args.model, config.model.map(ModelChoice::Fixed), matches, "model");
+    fill(&mut args.model_dir, config.model_dir.clone().map(Some), matches, "model_dir");
+    fill(&mut args.offline, config.offline, matches, "offline");
+    fill(&mut args.backend, config.backend, matches, "backend");
+    fill(&mut args.device, config.device, matches, "device");
+    fill(&mut args.format, config.format.map(Some), matches, "format");
+    fill(&mut args.quality, config.quality.map(Some), matches, "quality");
+    fill(&mut args.bg_color, config.background.map(Some), matches, "bg_color");
+    // Only for directory input, and not alongside the flags it conflicts with
+    let directory_input = args.input.as_deref().is_some_and(Path::is_dir);
+    let conflicts = ["output", "stdout", "to_clipboard", "analyze", "bench", "dump_intermediates"];
+    if directory_input && !conflicts.iter().any(|id| matches.value_source(id) == Some(ValueSource::CommandLine)) {
+        fill(&mut args.output_dir, config.output_dir.clone().map(Some), matches, "output_dir");
+    }
+
+    let Some((_, sub)) = matches.subcommand() else {
+        return;
+    };
+    match &mut args.command {
+        Some(Command::Watch { model, device, format, .. }) | Some(Command::Sequence { model, device, format, .. }) => {
+            fill(model, config.model, sub, "model");
+            fill(device, config.device, sub, "device");
+            fill(format, config.format.map(Some), sub, "format");
+        }
+        Some(Command::Batch { model, device, format, quality, bg_color, output_dir, .. }) => {
+            fill(output_dir, config.output_dir.clone().map(Some), sub, "output_dir");
+            fill(model, config.model, sub, "model");
+            fill(device, config.device, sub, "device");
+            fill(format, config.format.map(Some), sub, "format");
+            fill(quality, config.quality.map(Some), sub, "quality");
+            fill(bg_color, config.background.map(Some), sub, "bg_color");
+        }
+        #[cfg(feature = "server")]
+        Some(Command::Serve { model, device, .. }) => {
+            fill(model, config.model, sub, "model");
+            fill(device, config.device, sub, "device");
+        }
+        Some(Command::Daemon { model, device, .. }) | Some(Command::Compare { model, device, .. }) => {
+            fill(model, config.model, sub, "model");
+            fill(device, config.device, sub, "device");
+        }
+        Some(Command::Doctor { model }) => fill(model, config.model, sub, "model"),
+        _ => {}
+    }
+}
+
+/// Fill in the options `--preset` sets that weren't given as flags, then
+/// check the flags that only apply to a mode: the mode may come from the
+/// preset instead of its own flag, so clap can't check them.
+///
+/// The preset is a flag too, so it wins over the environment and the config
+/// file, which `apply_config` has already filled in.
+fn apply_preset(args: &mut Args, matches: &ArgMatches) {
+    let given = |ids: &[&str]| ids.iter().any(|id| matches.value_source(id) == Some(ValueSource::CommandLine));
+    if let Some(preset) = args.preset {
+        let preset = RemoveBgOptions::preset(preset);
+        let defaults = RemoveBgOptions::default();
+        if preset.model != defaults.model && !given(&["model"]) {
+            args.model = ModelChoice::Fixed(preset.model);
+        }
+        // An -o extension naming a format asks for it as plainly as --format
+        let named = args.output.as_deref().and_then(Path::extension).and_then(|e| e.to_str()).and_then(OutputFormat::from_extension);
+        if preset.format.is_some() && !given(&["format"]) && named.is_none() {
+            args.format = preset.format;
+        }
+        if preset.background != defaults.background && !given(&["bg_color", "chroma_key", "blur_background", "grayscale_background"]) {
+            args.bg_color = Some(preset.background);
+        }
+        if preset.max_output_size.is_some() && !given(&["max_output_size", "roi"]) {
+            args.max_output_size = preset.max_output_size;
+        }
+        if preset.geometry.pad.is_some() && !given(&["pad"]) {
+            args.pad = preset.geometry.pad;
+        }
+        args.fill_holes |= preset.fill_holes;
+        if preset.guided_filter.is_some() && !given(&["refine"]) {
+            args.refine = Some("guided".to_string());
+        }
+        if preset.decontaminate.is_some() && !given(&["decontaminate"]) {
+            args.decontaminate = preset.decontaminate;
+        }
+        if preset.mask_levels.is_some() && !given(&["mask_levels"]) {
+            args.mask_levels = preset.mask_levels;
+        }
+        if preset.sticker.is_some() && !given(&["blur_background", "grayscale_background"]) {
+            args.sticker = true;
+        }
+    }
+
+    let modes: [(&[&str], &str, bool); 3] = [
+        (&["outline_width", "outline_color", "shadow_offset", "shadow_blur", "shadow_opacity"], "sticker", args.sticker),
+        (&["refine_radius", "refine_epsilon"], "refine", args.refine.is_some()),
+        (&["max_hole_size"], "fill_holes", args.fill_holes),
+    ];
+    for (ids, mode, enabled) in modes {
+        if let Some(id) = ids.iter().find(|id| given(&[id])).filter(|_| !enabled) {
+            let message = format!("--{} requires --{} or a --preset that sets it", id.replace('_', "-"), mode.replace('_', "-"));
+            Args::command().error(clap::error::ErrorKind::MissingRequiredArgument, message).exit();
+        }
+    }
+}
+
+/// The config files a run read, and the settings of each.
+struct ConfigFiles<'a> {
+    /// The project's file, from `--config` or found by [`config::discover`].
+    project: &'a Config,
+    project_path: Option<&'a Path>,
+    /// The user's file, from [`config::user_config`].
+    user: &'a Config,
+    user_path: Option<&'a Path>,
+}
+
+/// The `--show-config` listing: the top-level settings the config files and
+/// the environment cover as TOML, each with where its value came from, so the
+/// output can be pasted into a `removebg.toml`.
+fn show_config(args: &Args, matches: &ArgMatches, env: &Config, files: &ConfigFiles) -> String {
+    let (preset, defaults) = (args.preset.map(RemoveBgOptions::preset), RemoveBgOptions::default());
+    let from_preset = |id: &str| {
+        preset.as_ref().is_some_and(|preset| match id {
+            "model" => preset.model != defaults.model && args.model == ModelChoice::Fixed(preset.model),
+            "format" => preset.format.is_some() && args.format == preset.format,
+            "bg_color" => preset.background != defaults.background && args.bg_color == Some(preset.background),
+            _ => false,
+        })
+    };
+    let file_name = |path: Option<&Path>| path.map_or_else(String::new, |path| path.display().to_string());
+    // `set` tells whether a layer sets the value
+    let source = |id: &str, var: Option<&str>, set: fn(&Config) -> bool| match () {
+        _ if matches.value_source(id) == Some(ValueSource::CommandLine) => format!("--{}", id.replace('_', "-")),
+        _ if from_preset(id) => format!("--preset {}", args.preset.map_or("", Preset::name)),
+        _ if set(env) => var.unwrap_or("environment").to_string(),
+        _ if set(files.project) => file_name(files.project_path),
+        _ if set(files.user) => file_name(files.user_path),
+        _ => "default".to_string(),
+    };
+    let string = |value: &dyn std::fmt::Display| Some(toml::Value::String(value.to_string()));
+    let path_value = |value: &Option<PathBuf>| value.as_deref().and_then(|dir| string(&dir.display()));
+    let settings = [
+        ("model", string(&args.model), source("model", Some(config::MODEL_ENV), |c| c.model.is_some())),
+        ("model_dir", path_value(&args.model_dir), source("model_dir", Some(removebg::model::MODEL_DIR_ENV), |c| c.model_dir.is_some())),
+        ("backend", string(&args.backend), source("backend", Some(config::BACKEND_ENV), |c| c.backend.is_some())),
+        ("device", string(&args.device), source("device", Some(config::DEVICE_ENV), |c| c.device.is_some())),
+        ("format", args.format.and_then(|format| string(&format)), source("format", Some(config::FORMAT_ENV), |c| c.format.is_some())),
+        (
+            "quality",
+            args.quality.map(|quality| toml::Value::Float(quality.into())),
+            source("quality", Some(config::QUALITY_ENV), |c| c.quality.is_some()),
+        ),
+        (
+            "bg_color",
+            args.bg_color.and_then(|background| string(&background)),
+            source("bg_color", Some(config::BG_COLOR_ENV), |c| c.background.is_some()),
+        ),
+        ("offline", Some(toml::Value::Boolean(args.offline)), source("offline", Some(config::OFFLINE_ENV), |c| c.offline.is_some())),
+        // output_dir has no variable, so `env` never sets it
+        ("output_dir", path_value(&args.output_dir), source("output_dir", None, |c| c.output_dir.is_some())),
+    ];
+
+    let mut listing = String::from(
+        "# Effective settings: flags, then --preset, then the environment, then the config file, then the user config file, then defaults\n",
+    );
+    match files.project_path {
+        Some(path) => listing.push_str(&format!("# Config file: {}\n", path.display())),
+        None => listing.push_str("# Config file: none (no removebg.toml or .removebg.toml here or above)\n"),
+    }
+    if let Some(path) = files.user_path {
+        listing.push_str(&format!("# User config file: {}\n", path.display()));
+    }
+    for (key, value, source) in settings {
+        match value {
+            Some(value) => listing.push_str(&format!("{} = {}  # {}\n", key, value, source)),
+            None => listing.push_str(&format!("# {} is not set  # {}\n", key, source)),
+        }
+    }
+    listing
+}
+
+/// Install the logger for library messages, which always go to stderr.
+///
+/// `-v` enables debug records and `-q` hides informational ones. `RUST_LOG`
+/// overrides both.
+fn init_logging(verbose: bool, quiet: bool) {
+    let level = if verbose {
+        log::LevelFilter::Debug
+    } else if quiet {
+        log::LevelFilter::Warn
+    } else {
+        log::LevelFilter::Info
+    };
+
+    let logger = env_logger::Builder::new()
+        .filter_level(level)
+        .parse_default_env()
+        .format(|f, record| match record.level() {
+            log::Level::Info => writeln!(f, "{}", record.args()),
+            log::Level::Warn => writeln!(f, "Warning: {}", record.args()),
+            log::Level::Error => writeln!(f, "Error: {}", record.args()),
+            level => writeln!(f, "[{}] {}", level, record.args()),
+        })
+        .build();
+    log::set_max_level(logger.filter());
+    log::set_boxed_logger(Box::new(BarLogger { inner: logger, verbose })).expect("the logger is installed once");
+}
+
+/// The progress bar being drawn, if any. Log records are printed above it.
+static PROGRESS_BAR: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+/// Logger that keeps library messages from tearing the progress bar.
+///
+/// While a bar is drawn, the line logged for each finished image is left out
+/// unless `-v` was given, since the bar shows the same thing.
+struct BarLogger {
+    inner: env_logger::Logger,
+    verbose: bool,
+}
+
+impl log::Log for BarLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.inner.matches(record) {
+            return;
+        }
+        let bar = PROGRESS_BAR.lock().unwrap_or_else(PoisonError::into_inner).clone();
+        match bar {
+            Some(_) if record.target() == progress::LOG_TARGET && record.level() == log::Level::Info && !self.verbose => {}
+            Some(bar) => bar.suspend(|| self.inner.log(record)),
+            None => self.inner.log(record),
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Whether runs over many images draw a progress bar: only on a terminal,
+/// and never with `--json` or `--quiet`.
+fn show_progress_bars(args: &Args) -> bool {
+    !args.json && !args.quiet && io::stdout().is_terminal() && io::stderr().is_terminal()
+}
+
+/// Terminal progress of a batch, watch, sequence or manifest run: a bar
+/// while it runs, then a list of the inputs that failed.
+struct RunProgress {
+    bar: Option<ProgressBar>,
+    print_outputs: bool,
+    failures: Arc<Mutex<Vec<(String, i32)>>>,
+}
+
+impl RunProgress {
+    /// Start showing progress, with a bar if `bar` is set. Failures are
+    /// collected either way. With `print_outputs` (`--quiet`), the path of
+    /// each output written, and of its instances, is printed to stdout, one
+    /// per line.
+    fn start(bar: bool, print_outputs: bool) -> Self {
+        let bar = bar.then(|| {
+            let style = ProgressStyle::with_template("{bar:30.cyan/blue} {pos}/{len} ETA {eta} {prefix:.red} {wide_msg}")
+                .expect("the progress template is valid")
+                .progress_chars("=> ");
+            let bar = ProgressBar::new(0).with_style(style);
+            bar.enable_steady_tick(Duration::from_millis(200));
+            *PROGRESS_BAR.lock().unwrap_or_else(PoisonError::into_inner) = Some(bar.clone());
+            bar
+        });
+        RunProgress { bar, print_outputs, failures: Arc::default() }
+    }
+
+    /// The handler to put in the run's config.
+    fn handler(&self) -> Progress {
+        let bar = self.bar.clone();
+        let print_outputs = self.print_outputs;
+        let failures = Arc::clone(&self.failures);
+        Progress::new(move |event| {
+            if let (true, ProgressEvent::Processed { report, .. }) = (print_outputs, event) {
+                println!("{}", report.output_path.display());
+                for instance in &report.instances {
+                    println!("{}", instance.path.display());
+                }
+            }
+            if let ProgressEvent::Failed { input, error } = event {
+                let mut failures = failures.lock().unwrap_or_else(PoisonError::into_inner);
+                let message = match error.input() {
+                    Some(_) => error.to_string(),
+                    None => format!("{}: {}", input.display(), error),
+                };
+                failures.push((message, error.exit_code()));
+                if let Some(bar) = &bar {
+                    bar.set_prefix(format!("{} failed", failures.len()));
+                }
+            }
+            let Some(bar) = &bar else {
+                return;
+            };
+            match event {
+                ProgressEvent::Queued { count } => bar.inc_length(*count as u64),
+                ProgressEvent::Started { input } => {
+                    bar.set_message(input.file_name().unwrap_or(input.as_os_str()).to_string_lossy().into_owned())
+                }
+                ProgressEvent::Processed { .. } | ProgressEvent::Skipped { .. } | ProgressEvent::Failed { .. } => {
+                    bar.inc(1)
+                }
+            }
+        })
+    }
+
+    /// Remove the bar and list the failures, in red where the terminal
+    /// supports it. The list is left out of JSON output.
+    ///
+    /// Returns the exit code of the most severe failure, if any.
+    fn finish(self, json: bool) -> Option<i32> {
+        if let Some(bar) = self.bar {
+            *PROGRESS_BAR.lock().unwrap_or_else(PoisonError::into_inner) = None;
+            bar.finish_and_clear();
+        }
+        let failures = self.failures.lock().unwrap_or_else(PoisonError::into_inner);
+        if !json && !failures.is_empty() {
+            eprintln!("{}", console::style(format!("Failed ({}):", failures.len())).red().bold().for_stderr());
+            for (failure, _) in failures.iter() {
+                eprintln!("{}", console::style(format!("  {}", failure)).red().for_stderr());
+            }
+        }
+        most_severe_exit_code(failures.iter().map(|(_, code)| *code))
+    }
+}
+
+/// Run `removebg compare` on `inputs` with the option sets `a` and `b`,
+/// each given as its spec and its options.
+fn run_compare(
+    inputs: &[PathBuf],
+    (a, options_a): (&str, &RemoveBgOptions),
+    (b, options_b): (&str, &RemoveBgOptions),
+    diff_dir: Option<&Path>,
+    json: bool,
+    csv: Option<&Path>,
+    quiet: bool,
+) -> Result<(), i32> {
+    if !quiet && shares_segmentation(options_a, options_b) {
+        log::info!("The option sets differ only after segmentation; each image is segmented once");
+    }
+    let (mut images, mut failures) = (Vec::new(), Vec::new());
+    for input in inputs {
+        match compare_image(input, options_a, options_b, diff_dir) {
+            Ok(image) => images.push(image),
+            Err(e) => {
+                if !json {
+                    eprintln!("Error: {}", e);
+                }
+                failures.push(e);
+            }
+        }
+    }
+    let summary = ComparisonSummary::of(&images);
+
+    if let Some(path) = csv {
+        let written = File::create(path).map_err(RemoveBgError::from).and_then(|file| write_csv(BufWriter::new(file), &images));
+        if let Err(e) = written {
+            eprintln!("Error: could not write {}: {}", path.display(), e);
+            return Err(e.exit_code());
+        }
+    }
+    if json {
+        print_json(&serde_json::json!({
+            "a": a,
+            "b": b,
+            "images": to_json(&images),
+            "summary": to_json(&summary),
+            "failed": failures.iter().map(error_json).collect::<Vec<_>>(),
+        }));
+    } else {
+        let name = |input: &Path| input.file_name().unwrap_or(input.as_os_str()).to_string_lossy().into_owned();
+        let width = images.iter().map(|image| name(&image.input).chars().count()).max().unwrap_or(0).max(5);
+        println!("A: {}", a);
+        println!("B: {}", b);
+        println!(
+            "{:<width$} {:>10} {:>8} {:>8} {:>10} {:>10} {:>10} {:>5}",
+            "IMAGE", "ALPHA DIFF", "CHANGED", "BOX IOU", "SIZE A", "SIZE B", "SIZE DIFF", "RUNS"
+        );
+        for image in &images {
+            println!(
+                "{:<width$} {:>10.4} {:>7.2}% {:>8.3} {:>10} {:>10} {:>+10} {:>5}",
+                name(&image.input),
+                image.mean_alpha_difference,
+                image.changed_percent,
+                image.bounds_iou,
+                image.size_a,
+                image.size_b,
+                image.size_delta,
+                image.inferences,
+            );
+        }
+        println!(
+            "{:<width$} {:>10.4} {:>7.2}% {:>8.3} {:>10} {:>10} {:>+10} {:>5}",
+            "ALL",
+            summary.mean_alpha_difference,
+            summary.changed_percent,
+            summary.bounds_iou,
+            "",
+            "",
+            summary.size_delta,
+            summary.inferences,
+        );
+    }
+    match most_severe_exit_code(failures.iter().map(RemoveBgError::exit_code)) {
+        None => Ok(()),
+        Some(code) => Err(code),
+    }
+}
+
+/// Execute a subcommand.
+#[allow(clippy::too_many_arguments)]
+fn run_command(
+    command: Command,
+    model_dir: Option<&Path>,
+    mask_cache: Option<&Path>,
+    backend: Backend,
+    download: DownloadOptions,
+    timeout: Option<Duration>,
+    max_memory: Option<u64>,
+    progress_bars: bool,
+    quiet: bool,
+) -> Result<(), i32> {
+    let report_error = |e: RemoveBgError| {
+        eprintln!("Error: {}", e);
+        e.exit_code()
+    };
+
+    match command {
+        Command::Watch { dir, output_dir, settle, format, model, device } => {
+            let progress = RunProgress::start(progress_bars, quiet);
+            let config = removebg::watch::WatchConfig {
+                settle_time: Duration::try_from_secs_f64(settle.max(0.0)).unwrap_or(Duration::MAX),
+                options: RemoveBgOptions {
+                    model,
+                    model_dir: model_dir.map(Path::to_path_buf),
+                    download: download.clone(),
+                    backend,
+                    device,
+                    format,
+                    ..Default::default()
+                },
+                progress: progress.handler(),
+                ..removebg::watch::WatchConfig::new(dir, output_dir)
+            };
+
+            let cancel = config.options.cancel.clone();
+            if let Err(e) = ctrlc::set_handler(move || cancel.cancel()) {
+                log::debug!("could not install Ctrl-C handler: {}", e);
+            }
+
+            let result = removebg::watch::watch(config);
+            progress.finish(false);
+            let summary = result.map_err(report_error)?;
+            if !quiet {
+                eprintln!(
+                    "Processed {}, skipped {}, failed {}",
+                    summary.processed, summary.skipped, summary.failed
+                );
+            }
+            Ok(())
+        }
+        Command::Sequence {
+            inputs,
+            output_dir,
+            temporal_alpha,
+            temporal_window,
+            scene_cut_threshold,
+            format,
+            model,
+            device,
+            force,
+        } => {
+            let progress = RunProgress::start(progress_bars, quiet);
+            let config = removebg::sequence::SequenceConfig {
+                temporal_alpha,
+                temporal_window,
+                scene_cut_threshold,
+                options: RemoveBgOptions {
+                    model,
+                    model_dir: model_dir.map(Path::to_path_buf),
+                    download: download.clone(),
+                    backend,
+                    device,
+                    format,
+                    overwrite: force,
+                    ..Default::default()
+                },
+                progress: progress.handler(),
+                ..removebg::sequence::SequenceConfig::new(inputs, output_dir)
+            };
+
+            let cancel = config.options.cancel.clone();
+            if let Err(e) = ctrlc::set_handler(move || cancel.cancel()) {
+                log::debug!("could not install Ctrl-C handler: {}", e);
+            }
+
+            let result = removebg::sequence::process_sequence(config);
+            progress.finish(false);
+            let summary = result.map_err(report_error)?;
+            if !quiet {
+                eprintln!(
+                    "Processed {} frames in {:.1}s ({:.1} frames/s), scene cuts: {}",
+                    summary.frames,
+                    summary.elapsed.as_secs_f64(),
+                    summary.frames_per_second(),
+                    summary.scene_cuts
+                );
+            }
+            Ok(())
+        }
+        Command::Batch {
+            manifest,
+            results,
+            output_dir,
+            format,
+            quality,
+            bg_color,
+            pad,
+            square,
+            resize,
+            batch_size,
+            model,
+            device,
+            force,
+            create_dirs,
+        } => {
+            let progress = RunProgress::start(progress_bars, quiet);
+            let config = ManifestConfig {
+                output_dir,
+                options: RemoveBgOptions {
+                    model,
+                    model_dir: model_dir.map(Path::to_path_buf),
+                    download: download.clone(),
+                    backend,
+                    device,
+                    format,
+                    quality,
+                    background: bg_color.unwrap_or_default(),
+                    geometry: GeometryOptions { pad, square, resize, ..Default::default() },
+                    batch_size,
+                    overwrite: force,
+                    create_dirs,
+                    ..Default::default()
+                },
+                progress: progress.handler(),
+            };
+            let results_path = results.unwrap_or_else(|| {
+                let format = ManifestFormat::from_path(&manifest);
+                let stem = manifest.file_stem().unwrap_or_default().to_string_lossy();
+                manifest.with_file_name(format!("{}.results.{}", stem, format.extension()))
+            });
+
+            let cancel = config.options.cancel.clone();
+            if let Err(e) = ctrlc::set_handler(move || cancel.cancel()) {
+                log::debug!("could not install Ctrl-C handler: {}", e);
+            }
+
+            let file = File::open(&manifest).map_err(|e| {
+                eprintln!("Error: {}: {}", manifest.display(), e);
+                if e.kind() == io::ErrorKind::NotFound { 1 } else { 2 }
+            })?;
+            let result = run_manifest(file, &config);
+            let failure = progress.finish(false);
+            let items = match result {
+                Ok(items) => items,
+                Err(RemoveBgError::InvalidManifest(problems)) => {
+                    eprintln!("Error: {} has problems; nothing was processed:", manifest.display());
+                    for problem in problems {
+                        eprintln!("  {}", problem);
+                    }
+                    return Err(2);
+                }
+                Err(e) => return Err(report_error(e)),
+            };
+            let written = File::create(&results_path)
+                .map_err(RemoveBgError::from)
+                .and_then(|file| write_results(BufWriter::new(file), ManifestFormat::from_path(&results_path), &items));
+            if let Err(e) = written {
+                eprintln!("Error: could not write {}: {}", results_path.display(), e);
+                return Err(e.exit_code());
+            }
+
+            let count = |status| items.iter().filter(|item| item.status == status).count();
+            let (processed, failed, cancelled) =
+                (count(ItemStatus::Done), count(ItemStatus::Failed), count(ItemStatus::Cancelled));
+            if !quiet {
+                eprintln!(
+                    "Processed {}, failed {}{}; results written to {}",
+                    processed,
+                    failed,
+                    if cancelled > 0 { format!(", cancelled {}", cancelled) } else { String::new() },
+                    results_path.display()
+                );
+            }
+            match (cancelled, failed) {
+                (0, 0) => Ok(()),
+                (0, _) => Err(failure.unwrap_or(5)),
+                _ => Err(130),
+            }
+        }
+        Command::Compare { inputs, a, b, diff_dir, json, csv, model, device } => {
+            let base = RemoveBgOptions {
+                model,
+                model_dir: model_dir.map(Path::to_path_buf),
+                download: download.clone(),
+                backend,
+                device,
+                ..Default::default()
+            };
+            let (options_a, options_b) = match (parse_option_set(&a, &base), parse_option_set(&b, &base)) {
+                (Ok(options_a), Ok(options_b)) => (options_a, options_b),
+                (Err(e), _) | (_, Err(e)) => {
+                    eprintln!("Error: {}", e);
+                    return Err(2);
+                }
+            };
+            run_compare(&inputs, (&a, &options_a), (&b, &options_b), diff_dir.as_deref(), json, csv.as_deref(), quiet)
+        }
+        Command::Daemon { model, device, force, no_warmup, deadline } => {
+            let options = RemoveBgOptions {
+                model,
+                model_dir: model_dir.map(Path::to_path_buf),
+                download: download.clone(),
+                download_progress: json_download_progress(),
+                backend,
+                device,
+                warmup: !no_warmup,
+                quality_fallback: deadline.map(|deadline| QualityFallback { deadline }),
+                overwrite: force,
+                timeout: timeout.or(Some(removebg::daemon::DEFAULT_TIMEOUT)).filter(|timeout| !timeout.is_zero()),
+                max_memory_bytes: max_memory,
+                ..Default::default()
+            };
+            let summary = removebg::daemon::run(io::stdin().lock(), io::stdout().lock(), &options).map_err(report_error)?;
+            if !quiet {
+                eprintln!("Processed {}, failed {}", summary.processed, summary.failed);
+            }
+            Ok(())
+        }
+        #[cfg(feature = "server")]
+        Command::Serve { host, port, max_body_size, workers, model, device, no_warmup, deadline } => {
+            let config = removebg::server::ServerConfig {
+                host,
+                port,
+                max_body_size,
+                workers: workers.unwrap_or_else(removebg::server::default_workers),
+                options: RemoveBgOptions {
+                    model,
+                    model_dir: model_dir.map(Path::to_path_buf),
+                    download: download.clone(),
+                    backend,
+                    device,
+                    warmup: !no_warmup,
+                    quality_fallback: deadline.map(|deadline| QualityFallback { deadline }),
+                    timeout: timeout.or(Some(removebg::server::DEFAULT_TIMEOUT)).filter(|timeout| !timeout.is_zero()),
+                    max_memory_bytes: max_memory,
+                    ..Default::default()
+                },
+            };
+            removebg::server::serve(config).map_err(report_error)
+        }
+        Command::Generate { shell, man, out_dir } => generate(shell, man, out_dir.as_deref()).map_err(|e| {
+            eprintln!("Error: {}", e);
+            3
+        }),
+        Command::Presets => {
+            for &preset in Preset::ALL {
+                println!("{:<10} {}", preset, preset.description());
+            }
+            Ok(())
+        }
+        Command::Stats { cutout, json } => match cutout_stats(&cutout) {
+            Ok(stats) if json => {
+                print_json(&serde_json::json!({ "input": cutout.to_string_lossy(), "alpha_stats": stats, "success": true }));
+                Ok(())
+            }
+            Ok(stats) => {
+                print_alpha_stats(&stats);
+                Ok(())
+            }
+            Err(e) => {
+                if json {
+                    print_json(&serde_json::json!({
+                        "input": cutout.to_string_lossy(),
+                        "success": false,
+                        "error": error_json(&e),
+                    }));
+                }
+                eprintln!("Error: {}", e);
+                Err(e.exit_code())
+            }
+        },
+        Command::Doctor { model } => {
+            let options = RemoveBgOptions { model, model_dir: model_dir.map(Path::to_path_buf), backend, download, ..Default::default() };
+            println!("removebg {} ({} {})", env!("CARGO_PKG_VERSION"), std::env::consts::OS, std::env::consts::ARCH);
+            let checks = doctor::run(&options);
+            for check in &checks {
+                println!("{} {:<16} {}", check.status, check.name, check.detail);
+            }
+            if checks.iter().any(|check| check.name == "ONNX Runtime" && check.status != CheckStatus::Pass) {
+                println!("\n{}", doctor::onnx_runtime_hint());
+            }
+            if checks.iter().any(|check| check.status == CheckStatus::Fail) {
+                return Err(3);
+            }
+            Ok(())
+        }
+        Command::Model(ModelCommand::List) => {
+            println!("{:<16} {:<8} {:>10}  PATH", "MODEL", "CACHED", "SIZE");
+            for &model in Model::ALL {
+                if let Some(bytes) = embedded_model(model) {
+                    let size = decimal_size(bytes.len() as u64);
+                    println!("{:<16} {:<8} {:>10}  (compiled in)", model, "embedded", size);
+                    continue;
+                }
+                let path = model_path(model, model_dir).map_err(report_error)?;
+                let (cached, size) = match std::fs::metadata(&path) {
+                    Ok(meta) => ("yes", decimal_size(meta.len())),
+                    Err(_) => ("no", model.spec().size_bytes.map_or_else(|| "-".to_string(), decimal_size)),
+                };
+                println!("{:<16} {:<8} {:>10}  {}", model, cached, size, path.display());
+            }
+            Ok(())
+        }
+        Command::Model(ModelCommand::Info { model }) => {
+            let spec = model.spec();
+            let path = model_path(model, model_dir).map_err(report_error)?;
+            let sha256 = match &download.sha256 {
+                Some(sha256) => Some(sha256.clone()),
+                None => expected_sha256(model, model_dir).map_err(report_error)?,
+            };
+            println!("Name:        {}", spec.name);
+            println!("Description: {}", spec.description);
+            match spec.source {
+                ModelSource::Download(_) => {
+                    let url = download_url(model, &download).map_err(report_error)?;
+                    println!("URL:         {}", url.unwrap_or_default());
+                }
+                ModelSource::Quantized(base) => {
+                    println!("Source:      quantized from {} (removebg model quantize)", base)
+                }
+            }
+            println!("SHA-256:     {}", sha256.as_deref().unwrap_or("not pinned"));
+            println!("Input size:  {}x{}", spec.input_size, spec.input_size);
+            if spec.normalization != Normalization::NONE {
+                println!("Normalize:   mean {:?}, std {:?}", spec.normalization.mean, spec.normalization.std);
+            }
+            if let Some(bytes) = spec.size_bytes {
+                println!("Size:        {} ({} bytes)", decimal_size(bytes), bytes);
+            }
+            if embedded_model(model).is_some() {
+                println!("Path:        compiled into the binary");
+            } else {
+                println!(
+                    "Path:        {}{}",
+                    path.display(),
+                    if path.exists() { "" } else { " (not cached)" }
+                );
+            }
+            Ok(())
+        }
+        Command::Model(ModelCommand::Remove { model, yes }) => {
+            let path = model_path(model, model_dir).map_err(report_error)?;
+            if !path.exists() {
+                println!("Model {} is not cached", model);
+                return Ok(());
+            }
+            if !yes && !confirm(&format!("Delete {}?", path.display())) {
+                println!("Aborted");
+                return Ok(());
+            }
+            remove_model(model, model_dir).map_err(report_error)?;
+            println!("Removed {}", path.display());
+            Ok(())
+        }
+        Command::Model(ModelCommand::Fetch { model, device }) => {
+            let options = RemoveBgOptions {
+                model,
+                model_dir: model_dir.map(Path::to_path_buf),
+                download: download.clone(),
+                backend,
+                device,
+                ..Default::default()
+            };
+            match prepare_model(&options) {
+                Ok(info) => {
+                    let location = match embedded_model(info.model) {
+                        Some(_) => "compiled into the binary".to_string(),
+                        None => info.path.display().to_string(),
+                    };
+                    println!(
+                        "Model {} ready{}: {} ({})",
+                        info.model,
+                        if info.downloaded { " (downloaded)" } else { "" },
+                        location,
+                        decimal_size(info.size_bytes)
+                    );
+                    Ok(())
+                }
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    Err(e.exit_code())
+                }
+            }
+        }
+        Command::Cache(command) => {
+            let Some(dir) = mask_cache else {
+                eprintln!("Error: no mask cache selected; pass --mask-cache DIR or set REMOVEBG_MASK_CACHE");
+                return Err(2);
+            };
+            match command {
+                CacheCommand::Stats => {
+                    let stats = removebg::mask_cache::stats(dir).map_err(report_error)?;
+                    println!("Directory: {}", dir.display());
+                    println!("Masks:     {}", stats.entries);
+                    println!("Size:      {}", decimal_size(stats.bytes));
+                }
+                CacheCommand::Clear => {
+                    let removed = removebg::mask_cache::clear(dir).map_err(report_error)?;
+                    println!(
+                        "Removed {} cached masks ({}) from {}",
+                        removed.entries,
+                        decimal_size(removed.bytes),
+                        dir.display()
+                    );
+                }
+            }
+            Ok(())
+        }
+        Command::Model(ModelCommand::Quantize { model }) => {
+            eprintln!("Quantizing {}...", model);
+            match quantize_model(model, model_dir) {
+                Ok(path) => {
+                    println!("Saved to: {}", path.display());
+                    Ok(())
+                }
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    Err(e.exit_code())
+                }
+            }
+        }
+    }
+}
+
+/// Write completions for `shell`, or man pages with `man`, to stdout or into
+/// `out_dir`. On stdout only the top-level man page is written.
+fn generate(shell: Option<clap_complete::Shell>, man: bool, out_dir: Option<&Path>) -> io::Result<()> {
+    let mut command = Args::command();
+    if let Some(dir) = out_dir {
+        std::fs::create_dir_all(dir)?;
+    }
+    match (shell, out_dir) {
+        (Some(shell), Some(dir)) => {
+            let path = clap_complete::generate_to(shell, &mut command, "removebg", dir)?;
+            println!("Wrote {}", path.display());
+        }
+        (Some(shell), None) => clap_complete::generate(shell, &mut command, "removebg", &mut io::stdout()),
+        (None, Some(dir)) if man => {
+            clap_mangen::generate_to(command, dir)?;
+            println!("Wrote man pages to {}", dir.display());
+        }
+        (None, None) if man => clap_mangen::Man::new(command).render(&mut io::stdout())?,
+        // Clap requires --shell or --man
+        (None, _) => {}
+    }
+    Ok(())
+}
+
+/// Download settings from the global model flags.
+fn download_options(args: &Args) -> DownloadOptions {
+    DownloadOptions {
+        url: args.model_url.clone(),
+        sha256: args.model_sha256.clone(),
+        retry: RetryPolicy { attempts: args.download_attempts, ..Default::default() },
+        offline: args.offline,
+    }
+}
+
+/// Write each model download event to stderr as a line of JSON, for
+/// `--json` and daemon runs, whose stdout holds the results.
+fn json_download_progress() -> DownloadProgress {
+    DownloadProgress::new(|event| {
+        if let Ok(line) = serde_json::to_string(event) {
+            eprintln!("{}", line);
+        }
+    })
+}
+
+/// Parse `--model-url`, rejecting anything but absolute http(s) URLs.
+fn parse_model_url(s: &str) -> Result<String, String> {
+    validate_model_url(s).map_err(|e| e.to_string())?;
+    Ok(s.to_string())
+}
+
+/// Parse `--model-sha256` as 64 hex digits, normalized to lowercase.
+fn parse_sha256(s: &str) -> Result<String, String> {
+    if s.len() == 64 && s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        Ok(s.to_ascii_lowercase())
+    } else {
+        Err("expected a SHA-256 as 64 hexadecimal digits".into())
+    }
+}
+
+/// Parse `--background-brightness`, a non-negative factor.
+fn parse_brightness(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(factor) if factor.is_finite() && factor >= 0.0 => Ok(factor),
+        _ => Err(format!("invalid brightness '{}' (expected a non-negative factor like 1.2)", s)),
+    }
+}
+
+/// Parse `--outline-color` and `--chroma-key` like `--bg-color`, without `transparent`.
+fn parse_color(s: &str) -> Result<[u8; 3], String> {
+    match s.parse::<Background>()? {
+        Background::Color(color) => Ok(color),
+        Background::Transparent => Err("expected a color, not 'transparent'".into()),
+        Background::Auto => Err("expected a color, not 'auto'".into()),
+    }
+}
+
+/// Format a color as `#rrggbb`, as `--bg-color` accepts it.
+fn hex_color([r, g, b]: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+/// Parse `--shadow-offset` as `X,Y`.
+fn parse_offset(s: &str) -> Result<(i32, i32), String> {
+    let invalid = || format!("invalid offset '{}' (expected X,Y like 4,6)", s);
+    let (x, y) = s.split_once(',').ok_or_else(invalid)?;
+    Ok((x.trim().parse().map_err(|_| invalid())?, y.trim().parse().map_err(|_| invalid())?))
+}
+
+/// Parse one `--icon-sizes` entry, 1-256 pixels.
+fn parse_icon_size(s: &str) -> Result<u32, String> {
+    match s.trim().parse::<u32>() {
+        Ok(size) if (1..=256).contains(&size) => Ok(size),
+        _ => Err(format!("invalid icon size '{}' (expected 1 to 256 pixels)", s)),
+    }
+}
+
+/// Parse `--shadow-blur`, a non-negative number of pixels.
+fn parse_shadow_blur(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(sigma) if sigma.is_finite() && sigma >= 0.0 => Ok(sigma),
+        _ => Err(format!("invalid shadow blur '{}' (expected a non-negative number of pixels)", s)),
+    }
+}
+
+/// Parse `--shadow-opacity`, between 0 and 1.
+fn parse_opacity(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(opacity) if (0.0..=1.0).contains(&opacity) => Ok(opacity),
+        _ => Err(format!("invalid opacity '{}' (expected a number from 0 to 1)", s)),
+    }
+}
+
+/// Parse `--auto-enhance` as a strength from 0 to 1.
+fn parse_strength(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(strength) if (0.0..=1.0).contains(&strength) => Ok(strength),
+        _ => Err(format!("invalid strength '{}' (expected a number from 0 to 1)", s)),
+    }
+}
+
+/// Parse `--max-output-size` as a non-zero pixel count.
+fn parse_max_output_size(s: &str) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(size) if size > 0 => Ok(size),
+        _ => Err(format!("invalid size '{}' (expected a number of pixels like 1500)", s)),
+    }
+}
+
+/// Parse `--max-models`, at least 1.
+fn parse_max_models(s: &str) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(format!("invalid model count '{}' (expected a whole number, at least 1)", s)),
+    }
+}
+
+/// Parse `--page`, counting from 1.
+fn parse_page(s: &str) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(page) if page > 0 => Ok(page),
+        _ => Err(format!("invalid page '{}' (expected a page number from 1)", s)),
+    }
+}
+
+/// Parse `--max-file-size` as a number of bytes with an optional unit: B, KB,
+/// MB, GB (powers of 1000) or KiB, MiB, GiB (powers of 1024).
+fn parse_byte_size(s: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid file size '{}' (expected a size like 2MB, 500KB or 1MiB)", s);
+    let trimmed = s.trim();
+    let split = trimmed.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(split);
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1_000,
+        "m" | "mb" => 1_000_000,
+        "g" | "gb" => 1_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        _ => return Err(invalid()),
+    };
+    match number.parse::<f64>() {
+        Ok(number) if number.is_finite() && number * multiplier as f64 >= 1.0 => {
+            Ok((number * multiplier as f64).round() as u64)
+        }
+        _ => Err(invalid()),
+    }
+}
+
+/// Parse `--timeout`, `--deadline` and `--preview-delay` as a number with a
+/// unit: ms, s, m or h (seconds without one), e.g. 10s, 1.5m or 500ms.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid duration '{}' (expected a duration like 10s, 500ms or 2m)", s);
+    let trimmed = s.trim();
+    let split = trimmed.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(split);
+    let seconds: f64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "ms" => 0.001,
+        "" | "s" => 1.0,
+        "m" | "min" => 60.0,
+        "h" => 3600.0,
+        _ => return Err(invalid()),
+    };
+    number
+        .parse::<f64>()
+        .ok()
+        .and_then(|number| Duration::try_from_secs_f64(number * seconds).ok())
+        .ok_or_else(invalid)
+}
+
+/// The `--preview-gif` path as an output template: the path itself for one
+/// input, and `{dir}/{stem}_<its stem>.gif` for a directory, so each input
+/// gets its own preview next to its output. Paths with placeholders are
+/// used as given.
+fn preview_template(path: &Path, batch: bool) -> String {
+    let path = path.to_string_lossy();
+    if !batch || path.contains('{') {
+        return path.into_owned();
+    }
+    let stem = Path::new(path.as_ref()).file_stem().map_or("preview".into(), |stem| stem.to_string_lossy());
+    format!("{{dir}}/{{stem}}_{}.gif", stem)
+}
+
+/// Parse `--resize` as `WxH`.
+fn parse_size(s: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("invalid size '{}' (expected WxH like 1600x1600)", s);
+    let (w, h) = s.split_once(['x', 'X']).ok_or_else(invalid)?;
+    match (w.trim().parse::<u32>(), h.trim().parse::<u32>()) {
+        (Ok(w), Ok(h)) if w > 0 && h > 0 => Ok((w, h)),
+        _ => Err(invalid()),
+    }
+}
+
+/// Parse `--pad` as a percentage, with or without `%`, into a fraction.
+fn parse_percent(s: &str) -> Result<f32, String> {
+    match s.strip_suffix('%').unwrap_or(s).trim().parse::<f32>() {
+        Ok(percent) if percent.is_finite() && percent >= 0.0 => Ok(percent / 100.0),
+        _ => Err(format!("invalid padding '{}' (expected a non-negative percentage like 10%)", s)),
+    }
+}
+
+/// Parse `--roi-margin` like `--pad`.
+fn parse_margin(s: &str) -> Result<f32, String> {
+    parse_percent(s).map_err(|_| format!("invalid margin '{}' (expected a non-negative percentage like 10%)", s))
+}
+
+/// Parse `--roi` as `X,Y,W,H` with a non-zero width and height.
+fn parse_roi(s: &str) -> Result<Rect, String> {
+    let invalid = || format!("invalid region '{}' (expected X,Y,W,H in pixels like 0,0,800,1200)", s);
+    let values: Vec<u32> = s.split(',').map(|v| v.trim().parse().map_err(|_| invalid())).collect::<Result<_, _>>()?;
+    match values[..] {
+        [_, _, 0, _] | [_, _, _, 0] => Err(format!("invalid region '{}' (the width and height must be at least 1)", s)),
+        [x, y, width, height] => Ok(Rect { x, y, width, height }),
+        _ => Err(invalid()),
+    }
+}
+
+/// Parse `--min-confidence`, between 0 and 1.
+fn parse_confidence(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(score) if (0.0..=1.0).contains(&score) => Ok(score),
+        _ => Err(format!("invalid confidence '{}' (expected a score from 0 to 1)", s)),
+    }
+}
+
+/// Parse `--analyze-threshold`, above 0 and at most 1.
+fn parse_threshold(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(threshold) if threshold > 0.0 && threshold <= 1.0 => Ok(threshold),
+        _ => Err(format!("invalid threshold '{}' (expected a number above 0 and at most 1)", s)),
+    }
+}
+
+/// Parse `--temporal-alpha`, above 0 and at most 1.
+fn parse_temporal_alpha(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(alpha) if alpha > 0.0 && alpha <= 1.0 => Ok(alpha),
+        _ => Err(format!("invalid temporal alpha '{}' (expected a number above 0 and at most 1)", s)),
+    }
+}
+
+/// Parse `--temporal-window`, at least 1 frame.
+fn parse_temporal_window(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(frames) if frames > 0 => Ok(frames),
+        _ => Err(format!("invalid temporal window '{}' (expected a whole number of frames, at least 1)", s)),
+    }
+}
+
+/// Parse `--refine-radius`, at least 1 pixel.
+fn parse_refine_radius(s: &str) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(radius) if radius > 0 => Ok(radius),
+        _ => Err(format!("invalid radius '{}' (expected a whole number of pixels, at least 1)", s)),
+    }
+}
+
+/// Parse `--refine-epsilon`, a positive number.
+fn parse_refine_epsilon(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(epsilon) if epsilon.is_finite() && epsilon > 0.0 => Ok(epsilon),
+        _ => Err(format!("invalid epsilon '{}' (expected a positive number like 0.001)", s)),
+    }
+}
+
+/// Parse `--mask-levels` as `LOW,HIGH` with 0 <= LOW <= HIGH <= 1.
+fn parse_levels(s: &str) -> Result<(f32, f32), String> {
+    let invalid = || format!("invalid levels '{}' (expected LOW,HIGH from 0 to 1 like 0.1,0.7)", s);
+    let (low, high) = s.split_once(',').ok_or_else(invalid)?;
+    match (low.trim().parse::<f32>(), high.trim().parse::<f32>()) {
+        (Ok(low), Ok(high)) if 0.0 <= low && low <= high && high <= 1.0 => Ok((low, high)),
+        _ => Err(invalid()),
+    }
+}
+
+/// Parse `--trimap-thresholds` as `LOW,HIGH` with 0 <= LOW < HIGH <= 1.
+fn parse_trimap_thresholds(s: &str) -> Result<(f32, f32), String> {
+    let invalid = || format!("invalid thresholds '{}' (expected LOW,HIGH from 0 to 1 with LOW below HIGH, like 0.05,0.95)", s);
+    let (low, high) = s.split_once(',').ok_or_else(invalid)?;
+    match (low.trim().parse::<f32>(), high.trim().parse::<f32>()) {
+        (Ok(low), Ok(high)) if 0.0 <= low && low < high && high <= 1.0 => Ok((low, high)),
+        _ => Err(invalid()),
+    }
+}
 
-    /// Path to save the output image (default: <input>_nobg.png)
-    #[arg(short, long, value_name = "OUTPUT")]
-    output: Option<String>,
+/// Parse `--mask-gamma`, a positive number.
+fn parse_gamma(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(gamma) if gamma.is_finite() && gamma > 0.0 => Ok(gamma),
+        _ => Err(format!("invalid gamma '{}' (expected a positive number like 0.7)", s)),
+    }
+}
 
-    /// Print verbose output
-    #[arg(short, long)]
-    verbose: bool,
+/// Parse `--batch-size`, at least 1.
+fn parse_bench_iterations(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(n) if n >= 1 => Ok(n),
+        _ => Err(format!("invalid iteration count '{}' (expected a whole number of at least 1)", s)),
+    }
 }
 
-fn main() {
-    let args = Args::parse();
+fn parse_batch_size(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(size) if size > 0 => Ok(size),
+        _ => Err(format!("invalid batch size '{}' (expected a whole number, at least 1)", s)),
+    }
+}
 
-    // Run the background removal and handle errors
-    match run(args) {
-        Ok(()) => process::exit(0),
-        Err(code) => process::exit(code),
+/// Parse `--jobs`, which must be at least 1.
+fn parse_jobs(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(jobs) if jobs > 0 => Ok(jobs),
+        _ => Err(format!("invalid number of jobs '{}' (expected a whole number, at least 1)", s)),
+    }
+}
+
+/// Parse `--blur-background`, which must be a positive number of pixels.
+fn parse_sigma(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(sigma) if sigma.is_finite() && sigma > 0.0 => Ok(sigma),
+        _ => Err(format!("invalid blur sigma '{}' (expected a positive number of pixels)", s)),
     }
 }
 
+/// Ask a yes/no question on stderr and read the answer from stdin.
+fn confirm(question: &str) -> bool {
+    eprint!("{} [y/N] ", question);
+    let _ = io::stderr().flush();
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).is_ok()
+        && matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
 /// Main execution logic with error handling.
 ///
-/// Returns exit codes:
-/// - 0: Success
-/// - 1: File not found
-/// - 2: Invalid input (not a valid image or is a directory)
-/// - 3: Unexpected error
+/// Returns the exit code of [`RemoveBgError::exit_code`] for a failed input,
+/// the most severe of them for directories, or 2 for invalid arguments; see
+/// `--list-exit-codes`.
 fn run(args: Args) -> Result<(), i32> {
+    let started = Instant::now();
+
+    // Clap guarantees INPUT is present when no subcommand was given
+    let input_path = args.input.clone().unwrap_or_default();
+    // For messages, JSON, and recognizing `-` and URLs; files are opened by `input_path`
+    let input = match args.from_clipboard {
+        true => "clipboard".to_string(),
+        false => input_path.to_string_lossy().into_owned(),
+    };
+
+    if args.stdout && io::stdout().is_terminal() && !args.force {
+        eprintln!("Error: refusing to write binary image data to a terminal; redirect stdout or use --force");
+        return Err(2);
+    }
+
+    if (input == "-" || args.from_clipboard) && !args.stdout && !args.to_clipboard && args.output.is_none() {
+        let message = match args.from_clipboard {
+            true => "reading from the clipboard requires --output, --stdout or --to-clipboard",
+            false => "reading from stdin requires --output or --stdout",
+        };
+        if args.json {
+            print_json(&serde_json::json!({
+                "input": input,
+                "success": false,
+                "error": { "kind": "usage", "message": message },
+            }));
+        }
+        eprintln!("Error: {}", message);
+        return Err(2);
+    }
+
     if args.verbose {
-        println!("Processing: {}", args.input);
+        eprintln!("Processing: {}", input);
     }
 
-    match remove_background(&args.input, args.output.as_deref()) {
-        Ok(output_path) => {
-            println!("Background removed successfully!");
-            println!("Saved to: {}", output_path);
-            Ok(())
+    let options = RemoveBgOptions {
+        model: match args.model {
+            ModelChoice::Fixed(model) => model,
+            ModelChoice::Auto => Model::U2netp,
+        },
+        auto_model: (args.model == ModelChoice::Auto).then_some(AutoModel { max_models: args.max_models }),
+        quality_fallback: None,
+        model_dir: args.model_dir.clone(),
+        download: download_options(&args),
+        download_progress: if args.json { json_download_progress() } else { DownloadProgress::default() },
+        backend: args.backend,
+        device: args.device,
+        intra_threads: args.ort_threads,
+        inter_threads: args.ort_inter_threads,
+        optimization_level: args.ort_opt_level,
+        session_memory: SessionMemoryOptions {
+            mmap_model: !args.no_mmap,
+            arena: !args.ort_no_arena,
+            arena_limit: args.ort_memory_limit,
+        },
+        ort_environment: OrtEnvironmentConfig::default(),
+        warmup: false,
+        mask_cache: args
+            .mask_cache
+            .clone()
+            .map(|dir| MaskCacheOptions { dir, max_size: Some(args.mask_cache_size) }),
+        format: args.format,
+        quality: args.quality,
+        encoder: None,
+        background: args.bg_color.unwrap_or_default(),
+        max_output_size: args.max_output_size,
+        tiling: args.tiled.then_some(TileOptions { size: args.tile_size, overlap: args.tile_overlap }),
+        roi: args.roi,
+        roi_margin: args.roi_margin,
+        two_pass: args.two_pass,
+        inference_size: args.inference_size,
+        auto_enhance: args.auto_enhance,
+        guided_filter: args.refine.is_some().then_some(GuidedFilterOptions {
+            radius: args.refine_radius,
+            epsilon: args.refine_epsilon,
+        }),
+        batch_size: args.batch_size,
+        keep_largest_component: args.only_largest || args.keep_components.is_some(),
+        keep_components: args.keep_components.unwrap_or(1),
+        component_gap: args.component_gap,
+        fill_holes: args.fill_holes,
+        max_hole_size: args.max_hole_size,
+        external_mask: args.use_mask.clone(),
+        line_art: args.line_art,
+        save_mask: args.save_mask.is_some(),
+        mask_format: args.save_mask.unwrap_or_default(),
+        export_trimap: args.export_trimap.clone(),
+        trimap: TrimapOptions {
+            low: args.trimap_thresholds.0,
+            high: args.trimap_thresholds.1,
+            erode_radius: args.trimap_erode,
+        },
+        split_instances: args.split_instances.then_some(InstanceOptions {
+            min_area: args.min_instance_area,
+            max_instances: args.max_instances,
+            margin: args.instance_margin,
+        }),
+        invert_mask: args.invert,
+        decontaminate: args.decontaminate,
+        premultiplied: args.premultiply,
+        blur_background: args.blur_background,
+        grayscale_background: args.grayscale_background,
+        background_brightness: args.background_brightness,
+        sticker: args.sticker.then_some(StickerOptions {
+            outline_width: args.outline_width,
+            outline_color: args.outline_color,
+            shadow_offset: args.shadow_offset,
+            shadow_blur: args.shadow_blur,
+            shadow_opacity: args.shadow_opacity,
+        }),
+        geometry: GeometryOptions {
+            pad: args.pad,
+            square: args.square,
+            resize: args.resize,
+            allow_upscale: args.allow_upscale,
+        },
+        icon: IconOptions {
+            sizes: args.icon_sizes.clone(),
+            iconset_dir: args.iconset_dir.clone(),
+            allow_upscale: args.allow_upscale,
+        },
+        chroma_key: args.chroma_key,
+        confidence: ConfidenceOptions { min_score: args.min_confidence, ..Default::default() },
+        alpha_stats: args.json,
+        debug_output: args.debug_output.clone(),
+        outputs: args
+            .emit
+            .iter()
+            .cloned()
+            .chain(args.thumbnail.map(|_| OutputSpec {
+                path: Some("{dir}/{stem}_thumb.png".into()),
+                ..OutputSpec::new(OutputKind::Thumbnail)
+            }))
+            .chain(args.preview_gif.as_deref().map(|path| OutputSpec {
+                path: Some(preview_template(path, input_path.is_dir() || (cloud::is_object_uri(&input) && input.ends_with('/')))),
+                ..OutputSpec::new(OutputKind::Preview)
+            }))
+            .collect(),
+        thumbnail_size: args.thumbnail,
+        preview_size: Some(args.preview_size),
+        preview_delay: Some(args.preview_delay),
+        mask_fusion: args.mask_fusion.clone(),
+        mask_levels: args.mask_levels,
+        mask_gamma: args.mask_gamma,
+        mask_ops: args.mask_ops.clone(),
+        mask_filter: args.mask_filter,
+        trim_border: args.trim_border,
+        png: PngOptions {
+            compression: args.png_compression,
+            filter: args.png_filter,
+            quantize: args.png_quantize,
+        },
+        max_file_size: args
+            .max_file_size
+            .map(|max_bytes| FileSizeLimit { max_bytes, allow_downscale: args.allow_downscale }),
+        limits: DecodeLimits {
+            max_pixels: (args.max_pixels > 0).then_some(args.max_pixels),
+            max_download_size: (args.max_download_size > 0).then_some(args.max_download_size),
+            ..Default::default()
+        },
+        max_memory_bytes: args.max_memory,
+        timeout: args.timeout.filter(|timeout| !timeout.is_zero()),
+        strip_metadata: args.strip_metadata,
+        naming: NamingOptions {
+            prefix: args.prefix.clone(),
+            suffix: if args.no_suffix { Some(String::new()) } else { args.suffix.clone() },
+            on_collision: args.on_collision,
+            fix_extension: args.fix_extension,
+        },
+        overwrite: args.force,
+        pages: match (args.page, args.all_pages) {
+            (Some(page), _) => Pages::Page(page),
+            (None, true) => Pages::All,
+            (None, false) => Pages::First,
+        },
+        reprocess: match (args.allow_reprocess, args.strict) {
+            (true, _) => Reprocess::Allow,
+            (false, true) => Reprocess::Refuse,
+            (false, false) => Reprocess::Warn,
+        },
+        disk_space: if args.strict { DiskSpaceCheck::Refuse } else { DiskSpaceCheck::Warn },
+        create_dirs: args.create_dirs,
+        cancel: CancellationToken::new(),
+    };
+    if args.reproducible && args.device != Device::Cpu {
+        eprintln!("Error: --reproducible needs --device cpu; {} may give different results on every run", args.device);
+        return Err(2);
+    }
+    let options = if args.reproducible { reproducible::options(options) } else { options };
+
+    // Ctrl-C stops processing at the next checkpoint instead of killing the process
+    let cancel = options.cancel.clone();
+    if let Err(e) = ctrlc::set_handler(move || cancel.cancel()) {
+        log::debug!("could not install Ctrl-C handler: {}", e);
+    }
+
+    if args.chroma_key.is_some() && args.png_quantize {
+        log::warn!("--png-quantize may merge subject colors into the --chroma-key color");
+    }
+    if args.background_brightness.is_some() && args.blur_background.is_none() && !args.grayscale_background {
+        log::warn!("--background-brightness only applies with --blur-background or --grayscale-background");
+    }
+    if args.use_mask.is_some() && (args.mask_levels.is_some() || args.mask_gamma.is_some()) {
+        log::warn!("--mask-levels and --mask-gamma only adjust the model's mask, not --use-mask");
+    }
+
+    if let Err(e) = confirm_model_download(&args, &input, &options) {
+        return Err(report_error(&e, args.verbose));
+    }
+
+    if args.analyze {
+        return run_analyze(&args, &input_path, &options);
+    }
+    if let Some(iterations) = args.bench {
+        return run_bench(&args, &input_path, iterations, &options);
+    }
+    if let Some(dir) = &args.dump_intermediates {
+        return run_dump_intermediates(&args, &input_path, dir, &options);
+    }
+
+    if cloud::is_object_uri(&input) && input.ends_with('/') {
+        if args.stdout || args.output.is_some() {
+            eprintln!("Error: {} is a prefix; use --output-dir instead of --output/--stdout", input);
+            return Err(2);
         }
-        Err(e) => {
-            match &e {
-                RemoveBgError::FileNotFound(_) => {
-                    eprintln!("Error: {}", e);
-                    Err(1)
+        let output_dir = args.output_dir.as_deref().map(Path::to_string_lossy);
+        let result = cloud::process_prefix(&input, output_dir.as_deref(), args.recursive, &options);
+        return finish_batch(&args, &input, result, None);
+    }
+    if archive::is_zip(&input_path) && !is_stream_or_url(&input_path) {
+        if args.stdout || args.output.is_some() {
+            eprintln!("Error: {} is a ZIP archive; use --output-zip instead of --output/--stdout", input);
+            return Err(2);
+        }
+        return run_archive(&args, &input_path, &options);
+    }
+    if args.output_zip.is_some() {
+        log::warn!("--output-zip only applies to .zip archive input");
+    }
+    if input_path.is_dir() {
+        if args.stdout || args.output.is_some() {
+            eprintln!("Error: {} is a directory; use --output-dir instead of --output/--stdout", input);
+            return Err(2);
+        }
+        if args.export_trimap.is_some() {
+            eprintln!("Error: --export-trimap writes one file and needs a single image input, not a directory");
+            return Err(2);
+        }
+        return run_batch(&args, &input_path, options);
+    }
+    if args.recursive || args.output_dir.is_some() || args.skip_existing || args.newer_only || args.batch_size > 1 {
+        log::warn!(
+            "--recursive, --output-dir, --skip-existing, --newer-only, and --batch-size only apply to directory input"
+        );
+    }
+
+    let mut dimensions = None;
+    let mut output_dimensions = None;
+    let mut subject = None;
+    let mut confidence = None;
+    let mut alpha_stats = None;
+    let mut warning = None;
+    let mut background_color = None;
+    let mut file_size = None;
+    let mut mask_cached = false;
+    let mut mask_fast_path = None;
+    let mut memory_estimate = None;
+    let mut mask_path = None;
+    let mut artifacts = Vec::new();
+    let mut instances = Vec::new();
+    let mut pages = None;
+    let mut model = None;
+    let output_uri = args.output.as_deref().map(Path::to_string_lossy).filter(|output| cloud::is_object_uri(output));
+    let result = if input == "-" || args.stdout || args.from_clipboard || args.to_clipboard {
+        let stream = if args.from_clipboard || args.to_clipboard { "the clipboard" } else { "stdin" };
+        if args.save_mask.is_some() {
+            log::warn!("--save-mask needs a file input; no mask is written for {}", stream);
+        }
+        if args.export_trimap.is_some() {
+            log::warn!("--export-trimap needs a file input; no trimap is written for {}", stream);
+        }
+        if args.debug_output.is_some() {
+            log::warn!("--debug-output needs a file input; no debug images are written for {}", stream);
+        }
+        if !args.emit.is_empty() || args.thumbnail.is_some() || args.preview_gif.is_some() {
+            log::warn!(
+                "--emit, --thumbnail and --preview-gif need a file input and output; no additional outputs are written for {}",
+                stream
+            );
+        }
+        if args.split_instances {
+            log::warn!("--split-instances needs a file input and output; no instances are written for {}", stream);
+        }
+        let destination = match (args.to_clipboard, args.output.as_deref()) {
+            (true, _) => Destination::Clipboard,
+            (false, Some(output)) => Destination::File(output),
+            (false, None) => Destination::Stdout,
+        };
+        run_streams(&input_path, args.from_clipboard, destination, &options)
+    } else if cloud::is_object_uri(&input) || output_uri.is_some() {
+        // Only the output is known, without a report
+        cloud::remove_background_uri(&input, output_uri.as_deref(), &options).map(|output| Some(PathBuf::from(output)))
+    } else {
+        let report = if remote::is_url(&input) {
+            remove_background_from_url(&input, args.output.as_deref(), &options)
+        } else {
+            remove_background_detailed(&input_path, args.output.as_deref(), &options)
+        };
+        report.map(|report| {
+            if args.verbose && !args.json {
+                print_report(&report);
+            }
+            dimensions = Some(report.input_dimensions);
+            output_dimensions = Some(report.output_dimensions);
+            subject = report.subject_bounds;
+            confidence = Some(report.confidence);
+            alpha_stats = report.alpha_stats;
+            warning = CoverageWarning::from_coverage(report.mask_coverage);
+            background_color = report.background_color;
+            file_size = report.file_size;
+            mask_cached = report.mask_cached;
+            mask_fast_path = report.mask_fast_path;
+            memory_estimate = report.memory_estimate;
+            mask_path = report.mask_path;
+            artifacts = report.artifacts;
+            instances = report.instances;
+            pages = report.pages;
+            model = report.model;
+            Some(report.output_path)
+        })
+    };
+
+    if args.json {
+        let elapsed_ms = started.elapsed().as_secs_f64() * 1000.0;
+        let (width, height) = dimensions.unzip();
+        let (output_width, output_height) = output_dimensions.unzip();
+        let value = match &result {
+            Ok(output_path) => serde_json::json!({
+                "input": input,
+                "output": output_path.as_deref().map(Path::to_string_lossy),
+                "mask": mask_path.as_deref().map(Path::to_string_lossy),
+                "artifacts": artifacts.iter().map(|artifact| {
+                    let mut value = to_json(artifact);
+                    value["success"] = artifact.error.is_none().into();
+                    value
+                }).collect::<Vec<_>>(),
+                "instances": instances,
+                "pages": pages,
+                "model": model.as_ref().map(|info| info.model),
+                "model_selection": model.as_ref().and_then(|info| info.selection.as_ref()),
+                "width": width,
+                "height": height,
+                "output_width": output_width,
+                "output_height": output_height,
+                "subject": subject,
+                "confidence": confidence.map(|score| (score as f64 * 1000.0).round() / 1000.0),
+                "alpha_stats": alpha_stats,
+                "mask_cached": mask_cached,
+                "mask_fast_path": mask_fast_path,
+                "memory_estimate": memory_estimate,
+                "premultiplied": args.premultiply,
+                "background_color": background_color.map(hex_color),
+                "file_size": file_size,
+                "warning": warning.map(CoverageWarning::kind),
+                "time_ms": elapsed_ms,
+                "success": true,
+            }),
+            Err(e) => serde_json::json!({
+                "input": input,
+                "time_ms": elapsed_ms,
+                "success": false,
+                "error": error_json(e),
+            }),
+        };
+        print_json(&value);
+    }
+
+    if let (Ok(_), Some(warning)) = (&result, warning) {
+        log::warn!("{}: {}", input, warning);
+    }
+    if args.notify {
+        let output = match &result {
+            Ok(Some(output_path)) => output_path.file_name().unwrap_or_default().to_string_lossy(),
+            Ok(None) if args.to_clipboard => "the clipboard".into(),
+            Ok(None) => "stdout".into(),
+            Err(_) => Default::default(),
+        };
+        let outcome = match &result {
+            Ok(_) => Outcome::Saved { output: &output, warning },
+            Err(error) => Outcome::Failed { input: &input, error },
+        };
+        if let Err(e) = notification::desktop().and_then(|mut notifier| notify_outcome(notifier.as_mut(), &outcome)) {
+            log::warn!("could not show a notification: {}", e);
+        }
+    }
+    let strict_failure = if args.strict && warning.is_some() { Err(7) } else { Ok(()) };
+
+    // stdout only carries results: the JSON, the image, or with --quiet the
+    // output path; everything for people goes to stderr
+    match result {
+        Ok(Some(output_path)) => {
+            if args.quiet {
+                if !args.json {
+                    let page_paths = pages.iter().flat_map(|pages| pages.processed.iter().skip(1));
+                    println!("{}", output_path.display());
+                    for page in page_paths {
+                        println!("{}", page.output_path.display());
+                    }
+                    for instance in &instances {
+                        println!("{}", instance.path.display());
+                    }
                 }
-                RemoveBgError::NotAFile(_) => {
-                    eprintln!("Error: {}", e);
-                    Err(2)
+            } else if args.json {
+                eprintln!("Background removed successfully!");
+            } else {
+                eprintln!("Background removed successfully!");
+                match &pages {
+                    Some(pages) if pages.processed.len() > 1 => {
+                        for page in &pages.processed {
+                            eprintln!("Saved page {} to: {}", page.page, page.output_path.display());
+                        }
+                    }
+                    _ => eprintln!("Saved to: {}", output_path.display()),
                 }
-                RemoveBgError::ImageError(_) => {
-                    eprintln!("Error: {}", e);
-                    Err(2)
+                if let Some(mask_path) = &mask_path {
+                    eprintln!("Mask saved to: {}", mask_path.display());
                 }
-                _ => {
-                    eprintln!("Unexpected error: {}", e);
-                    if args.verbose {
-                        eprintln!("Error details: {:?}", e);
-                    }
-                    Err(3)
+                for artifact in artifacts.iter().filter(|artifact| artifact.error.is_none()) {
+                    eprintln!("Saved {} to: {}", artifact.kind, artifact.path.display());
+                }
+                if args.split_instances {
+                    eprintln!("Instances: {}", instances.len());
+                }
+                for (index, instance) in instances.iter().enumerate() {
+                    let rect = instance.bounds;
+                    eprintln!(
+                        "  {}. {}x{} at ({}, {}), {} px: {}",
+                        index + 1,
+                        rect.width,
+                        rect.height,
+                        rect.x,
+                        rect.y,
+                        instance.area,
+                        instance.path.display()
+                    );
+                }
+                if let (Some(Background::Auto), Some(color)) = (args.bg_color, background_color) {
+                    eprintln!("Background color: {}", hex_color(color));
+                }
+                if let Some(fit) = &file_size {
+                    eprintln!("File size: {} bytes", fit.size);
+                }
+            }
+            let failed: Vec<_> = artifacts.iter().filter(|artifact| artifact.error.is_some()).collect();
+            for artifact in &failed {
+                let error = artifact.error.as_deref().unwrap_or_default();
+                eprintln!("Error: failed to write {} output {}: {}", artifact.kind, artifact.path.display(), error);
+            }
+            if !failed.is_empty() {
+                return Err(5);
+            }
+            strict_failure
+        }
+        Ok(None) => {
+            if !args.quiet {
+                eprintln!("Background removed successfully!");
+                if args.to_clipboard && !args.json {
+                    eprintln!("Copied result to clipboard");
+                }
+            }
+            Ok(())
+        }
+        Err(e) => Err(report_error(&e, args.verbose)),
+    }
+}
+
+/// Ask before the first run downloads the model, when stdin is a terminal
+/// and neither `--yes` nor `REMOVEBG_AUTO_DOWNLOAD` is set. Without a
+/// terminal the model is downloaded as before, with a notice.
+fn confirm_model_download(args: &Args, input: &str, options: &RemoveBgOptions) -> Result<(), RemoveBgError> {
+    let model = options.model;
+    if args.yes || options.external_mask.is_some() || !needs_download(model, args.model_dir.as_deref(), &options.download)? {
+        return Ok(());
+    }
+    if io::stdin().is_terminal() && input != "-" {
+        return confirm_download(model, args.model_dir.as_deref(), &mut io::stdin().lock(), &mut io::stderr());
+    }
+    log::warn!(
+        "Model {} is not cached and will be downloaded; pass --yes or set REMOVEBG_AUTO_DOWNLOAD=1 to skip this notice",
+        name_with_size(model)
+    );
+    Ok(())
+}
+
+/// Print an error for a single input on stderr and return its exit code.
+fn report_error(e: &RemoveBgError, verbose: bool) -> i32 {
+    let code = e.exit_code();
+    match code {
+        130 => eprintln!("Cancelled"),
+        // Model, inference and unexpected errors; the rest explain themselves
+        3 | 9 | 11 => {
+            eprintln!("Unexpected error: {}", e);
+            if verbose {
+                eprintln!("Error details: {:?}", e);
+            }
+            // The error from loading ONNX Runtime, kept and returned again
+            if matches!(e, RemoveBgError::ModelInitError(_)) && doctor::onnx_runtime_error() == Some(e.to_string()) {
+                eprintln!("Hint: {}", doctor::onnx_runtime_hint());
+            }
+        }
+        _ => eprintln!("Error: {}", e),
+    }
+    code
+}
+
+/// Whether `input` is `-` (stdin), a URL or an object store URI rather
+/// than a local path.
+fn is_stream_or_url(input: &Path) -> bool {
+    input.as_os_str() == "-" || input.to_str().is_some_and(|input| remote::is_url(input) || cloud::is_object_uri(input))
+}
+
+/// Flags from other tools, or guesses at ours, and the flag they stand for,
+/// for the mistakes clap's similarity check gets wrong or misses.
+const FLAG_ALIASES: &[(&str, &str)] = &[
+    ("--out", "--output"),
+    ("--bg", "--bg-color"),
+    ("--background", "--bg-color"),
+    ("--overwrite", "--force"),
+    ("--dir", "--output-dir"),
+    ("--threads", "--jobs"),
+    ("--gpu", "--device"),
+    ("--png", "--format"),
+    ("--jpg", "--format"),
+    ("--jpeg", "--format"),
+    ("--webp", "--format"),
+    ("--width", "--max-output-size"),
+    ("--size", "--max-output-size"),
+    ("--mask", "--save-mask"),
+];
+
+/// The flag in [`FLAG_ALIASES`] that the unknown flag `arg`, given as
+/// `--name` or `--name=value`, stands for.
+fn flag_alias(arg: &str) -> Option<&'static str> {
+    let name = arg.split_once('=').map_or(arg, |(name, _)| name);
+    FLAG_ALIASES.iter().find(|(alias, _)| *alias == name).map(|&(_, flag)| flag)
+}
+
+/// Suggest the flag from [`FLAG_ALIASES`] in an unknown-argument error from
+/// clap, in place of clap's own guess. Other errors, and unknown flags of
+/// subcommands, whose flags differ, are returned as they are.
+fn suggest_flag(mut error: clap::Error, subcommand: bool) -> clap::Error {
+    use clap::error::{ContextKind, ContextValue, ErrorKind};
+
+    if error.kind() != ErrorKind::UnknownArgument || subcommand {
+        return error;
+    }
+    let flag = match error.get(ContextKind::InvalidArg) {
+        Some(ContextValue::String(arg)) => flag_alias(arg),
+        _ => None,
+    };
+    if let Some(flag) = flag {
+        error.insert(ContextKind::SuggestedArg, ContextValue::String(flag.to_string()));
+    }
+    error
+}
+
+/// What [`preflight`] found in the arguments of a run.
+#[derive(Debug, Default)]
+struct Preflight {
+    /// Changes made to the arguments, printed before the run.
+    notes: Vec<String>,
+    /// What was probably meant, printed if the run fails.
+    hints: Vec<String>,
+}
+
+/// Check the arguments of a run for common mistakes before any work is
+/// done.
+///
+/// Paths starting with a `~` the shell didn't expand (quoted, or from a
+/// config file) are expanded to `home` when no such file exists. Swapped
+/// input and output, a directory given where one image is needed, and an
+/// output without an extension are only detected: the run fails on them as
+/// it would anyway, with the same exit code, and the hints say what to run
+/// instead.
+fn preflight(args: &mut Args, home: Option<&Path>) -> Preflight {
+    let mut checks = Preflight::default();
+    if let Some(home) = home {
+        let paths = [&mut args.input, &mut args.output, &mut args.output_dir, &mut args.output_zip];
+        for path in paths.into_iter().flatten() {
+            if let Some(expanded) = expand_tilde(path, home) {
+                checks.notes.push(format!("Expanded '{}' to '{}'", path.display(), expanded.display()));
+                *path = expanded;
+            }
+        }
+    }
+
+    let Some(input) = args.input.as_deref().filter(|input| !args.from_clipboard && !is_stream_or_url(input)) else {
+        return checks;
+    };
+    let output = args.output.as_deref().filter(|output| !is_stream_or_url(output));
+    if !input.exists() {
+        if let Some(output) = output.filter(|output| output.is_file()) {
+            checks.hints.push(format!(
+                "input '{}' doesn't exist, but '{}' does; did you mean `removebg {} -o {}`?",
+                input.display(),
+                output.display(),
+                shell_word(output),
+                shell_word(input)
+            ));
+        }
+    } else if input.is_dir() {
+        let single = if let Some(iterations) = args.bench {
+            Some(format!("--bench {}", iterations))
+        } else if args.analyze {
+            Some("--analyze".to_string())
+        } else {
+            args.dump_intermediates.as_deref().map(|dir| format!("--dump-intermediates {}", shell_word(dir)))
+        };
+        match (single, output) {
+            (Some(flag), _) => {
+                let example = first_image(input).unwrap_or_else(|| input.join("photo.jpg"));
+                checks.hints.push(format!(
+                    "'{}' is a directory, and {} runs on one image; did you mean `removebg {} {}`?",
+                    input.display(),
+                    flag.split(' ').next().unwrap_or_default(),
+                    shell_word(&example),
+                    flag
+                ));
+            }
+            (None, Some(output)) => checks.hints.push(format!(
+                "'{}' is a directory, which is processed as a batch; did you mean `removebg {} --output-dir {}`?",
+                input.display(),
+                shell_word(input),
+                shell_word(&output.with_extension(""))
+            )),
+            (None, None) => {}
+        }
+    }
+    if let Some(output) = output.filter(|output| {
+        output.extension().is_none() && !args.fix_extension && !is_directory_path(output) && !input.is_dir() && !archive::is_zip(input)
+    }) {
+        let extension = args.format.unwrap_or_default().extension();
+        checks.hints.push(format!(
+            "output '{}' has no extension; did you mean `removebg {} -o {}`?",
+            output.display(),
+            shell_word(input),
+            shell_word(&output.with_extension(extension))
+        ));
+    }
+    checks
+}
+
+/// `path` with a leading `~` replaced by `home`, or `None` if it doesn't
+/// start with one or a file of that name exists.
+fn expand_tilde(path: &Path, home: &Path) -> Option<PathBuf> {
+    let rest = path.to_str()?.strip_prefix('~')?;
+    if !(rest.is_empty() || rest.starts_with(std::path::is_separator)) || path.exists() {
+        return None;
+    }
+    match rest.trim_start_matches(std::path::is_separator) {
+        "" => Some(home.to_path_buf()),
+        rest => Some(home.join(rest)),
+    }
+}
+
+/// The first image in `dir` by name, for examples.
+fn first_image(dir: &Path) -> Option<PathBuf> {
+    let mut images: Vec<PathBuf> = std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.is_file() && image::ImageFormat::from_path(path).is_ok())
+        .collect();
+    images.sort();
+    images.into_iter().next()
+}
+
+/// `path` as one shell word, single-quoted if it needs to be.
+fn shell_word(path: &Path) -> String {
+    let word = path.to_string_lossy();
+    match word.chars().all(|c| c.is_ascii_alphanumeric() || "-_./~:+@%,=".contains(c)) && !word.is_empty() {
+        true => word.into_owned(),
+        false => format!("'{}'", word.replace('\'', r"'\''")),
+    }
+}
+
+/// Write the intermediates of `input` into `dir` for `--dump-intermediates`
+/// and list the files written.
+fn run_dump_intermediates(args: &Args, input: &Path, dir: &Path, options: &RemoveBgOptions) -> Result<(), i32> {
+    if is_stream_or_url(input) || input.is_dir() {
+        eprintln!("Error: --dump-intermediates needs an image file as input");
+        return Err(2);
+    }
+    match removebg::testing::dump_intermediates(input, dir, options) {
+        Ok(paths) => {
+            if !args.quiet {
+                for path in paths {
+                    println!("{}", path.display());
                 }
             }
+            Ok(())
+        }
+        Err(e) => Err(report_error(&e, args.verbose)),
+    }
+}
+
+/// Time `iterations` runs on `input` for `--bench` and print per-stage
+/// statistics, as a table or with `--json` as one JSON object.
+fn run_bench(args: &Args, input_path: &Path, iterations: usize, options: &RemoveBgOptions) -> Result<(), i32> {
+    if is_stream_or_url(input_path) || input_path.is_dir() {
+        eprintln!("Error: --bench needs an image file as input");
+        return Err(2);
+    }
+    let input = input_path.to_string_lossy();
+    let ms = |duration: Duration| (duration.as_secs_f64() * 1_000_000.0).round() / 1000.0;
+
+    let report = match run_benchmark(input_path, args.output.as_deref(), iterations, options) {
+        Ok(report) => report,
+        Err(e) => {
+            if args.json {
+                print_json(&serde_json::json!({
+                    "input": input,
+                    "success": false,
+                    "error": error_json(&e),
+                }));
+            }
+            return Err(report_error(&e, args.verbose));
+        }
+    };
+
+    let (width, height) = report.input_dimensions;
+    if args.json {
+        let stats = |stage: &dyn Fn(&StageDurations) -> Duration| {
+            let stats = report.stats(stage);
+            serde_json::json!({ "min_ms": ms(stats.min), "median_ms": ms(stats.median), "max_ms": ms(stats.max) })
+        };
+        let stages: serde_json::Map<_, _> = STAGES.iter().map(|&(name, stage)| (name.to_string(), stats(&stage))).collect();
+        let runs: Vec<_> = report
+            .runs
+            .iter()
+            .map(|run| {
+                let run: serde_json::Map<_, _> =
+                    STAGES.iter().map(|&(name, stage)| (format!("{}_ms", name), ms(stage(run)).into())).collect();
+                serde_json::Value::Object(run)
+            })
+            .collect();
+        print_json(&serde_json::json!({
+            "input": input,
+            "width": width,
+            "height": height,
+            "warmup_runs": 1,
+            "iterations": report.runs.len(),
+            "download_excluded": true,
+            "downloaded": report.downloaded,
+            "model_load_ms": { "cold": ms(report.cold_model_load), "warm": ms(report.warm_model_load) },
+            "stages": stages,
+            "runs": runs,
+            "images_per_second": (report.images_per_second() * 1000.0).round() / 1000.0,
+            "megapixels_per_second": (report.megapixels_per_second() * 1000.0).round() / 1000.0,
+            "success": true,
+        }));
+        return Ok(());
+    }
+
+    println!("Benchmark: {} ({}x{}), {} runs after 1 warm-up run", input, width, height, report.runs.len());
+    println!(
+        "Model load: cold {:.1} ms, warm {:.1} ms ({})",
+        ms(report.cold_model_load),
+        ms(report.warm_model_load),
+        if report.downloaded { "downloaded first; the download is not timed" } else { "download excluded" }
+    );
+    println!("{:<12} {:>12} {:>12} {:>12}", "STAGE", "MIN", "MEDIAN", "MAX");
+    for (name, stage) in STAGES {
+        let stats = report.stats(stage);
+        println!(
+            "{:<12} {:>9.1} ms {:>9.1} ms {:>9.1} ms",
+            name,
+            ms(stats.min),
+            ms(stats.median),
+            ms(stats.max)
+        );
+    }
+    println!(
+        "Throughput: {:.2} images/s, {:.1} MP/s",
+        report.images_per_second(),
+        report.megapixels_per_second()
+    );
+    Ok(())
+}
+
+/// Analyze where the subject is in `input` for `--analyze` and print the
+/// result as JSON on stdout.
+fn run_analyze(args: &Args, input_path: &Path, options: &RemoveBgOptions) -> Result<(), i32> {
+    if is_stream_or_url(input_path) || input_path.is_dir() {
+        eprintln!("Error: --analyze needs an image file as input");
+        return Err(2);
+    }
+    let input = input_path.to_string_lossy();
+
+    let started = Instant::now();
+    match analyze(input_path, args.analyze_threshold, options) {
+        Ok(analysis) => {
+            let rect = |rect: Rect| to_json(&rect);
+            let round = |value: f32, scale: f64| (value as f64 * scale).round() / scale;
+            let point = |(x, y): (f32, f32)| serde_json::json!({ "x": round(x, 100.0), "y": round(y, 100.0) });
+            let percent = |fraction: f32| round(fraction * 100.0, 10.0);
+            let (width, height) = analysis.dimensions;
+            let pixels = (width as u64 * height as u64).max(1) as f32;
+            let [top_left, top_right, bottom_left, bottom_right] = analysis.quadrant_coverage.map(percent);
+            let components: Vec<_> = analysis
+                .components
+                .iter()
+                .map(|component| {
+                    serde_json::json!({
+                        "bounds": rect(component.bounds),
+                        "area": component.area,
+                        "area_percent": percent(component.area as f32 / pixels),
+                        "centroid": point(component.centroid),
+                    })
+                })
+                .collect();
+            print_json(&serde_json::json!({
+                "input": input,
+                "width": width,
+                "height": height,
+                "threshold": round(analysis.threshold, 1000.0),
+                "bounds": analysis.bounds.map(rect),
+                "coverage_percent": percent(analysis.coverage),
+                "centroid": analysis.centroid.map(point),
+                "quadrants": {
+                    "top_left": top_left,
+                    "top_right": top_right,
+                    "bottom_left": bottom_left,
+                    "bottom_right": bottom_right,
+                },
+                "components": components,
+                "confidence": round(analysis.confidence, 1000.0),
+                "alpha_stats": analysis.alpha_stats,
+                "time_ms": started.elapsed().as_secs_f64() * 1000.0,
+                "success": true,
+            }));
+            Ok(())
+        }
+        Err(e) => {
+            if args.json {
+                print_json(&serde_json::json!({
+                    "input": input,
+                    "time_ms": started.elapsed().as_secs_f64() * 1000.0,
+                    "success": false,
+                    "error": error_json(&e),
+                }));
+            }
+            Err(report_error(&e, args.verbose))
+        }
+    }
+}
+
+/// Process every image in the directory `input`.
+///
+/// A summary with per-directory counts is printed at the end, also after
+/// Ctrl-C. Exits with the code of the most severe failure if any image
+/// failed.
+fn run_batch(args: &Args, input: &Path, options: RemoveBgOptions) -> Result<(), i32> {
+    let progress = RunProgress::start(show_progress_bars(args), args.quiet && !args.json);
+    let config = BatchConfig {
+        output_dir: args.output_dir.clone(),
+        recursive: args.recursive,
+        skip_hidden: args.skip_hidden,
+        skip_existing: if args.skip_existing {
+            SkipExisting::Always
+        } else if args.newer_only {
+            SkipExisting::IfNewer
+        } else {
+            SkipExisting::Never
+        },
+        options,
+        progress: progress.handler(),
+        ..BatchConfig::new(input)
+    };
+
+    let result = process_directory(config);
+    let failure = progress.finish(args.json);
+    finish_batch(args, &input.to_string_lossy(), result, failure)
+}
+
+/// Print the outcome of a directory or prefix batch, as JSON with `--json`,
+/// and return the exit code: that of `failure`, the most severe failed
+/// image, or 5 if failures aren't known one by one.
+fn finish_batch(
+    args: &Args,
+    input: &str,
+    result: Result<BatchSummary, RemoveBgError>,
+    failure: Option<i32>,
+) -> Result<(), i32> {
+    let summary = match result {
+        Ok(summary) => summary,
+        Err(e) => {
+            if args.json {
+                print_json(&serde_json::json!({
+                    "input": input,
+                    "success": false,
+                    "error": error_json(&e),
+                }));
+            }
+            eprintln!("Error: {}", e);
+            return Err(e.exit_code());
+        }
+    };
+
+    if args.json {
+        let directories: Vec<_> = summary
+            .directories
+            .iter()
+            .map(|dir| {
+                serde_json::json!({
+                    "path": dir.path.to_string_lossy(),
+                    "processed": dir.processed,
+                    "skipped": dir.skipped,
+                    "failed": dir.failed,
+                })
+            })
+            .collect();
+        print_json(&serde_json::json!({
+            "input": input,
+            "processed": summary.processed,
+            "skipped": summary.skipped,
+            "failed": summary.failed,
+            "directories": directories,
+            "cancelled": summary.cancelled,
+            "success": summary.failed == 0 && !summary.cancelled,
+        }));
+    } else if !args.quiet && summary.directories.len() > 1 {
+        for dir in &summary.directories {
+            let path = match dir.path.as_os_str().is_empty() {
+                true => ".".into(),
+                false => dir.path.display().to_string(),
+            };
+            eprintln!(
+                "  {}: processed {}, skipped {}, failed {}",
+                path, dir.processed, dir.skipped, dir.failed
+            );
+        }
+    }
+    if !args.quiet {
+        eprintln!(
+            "Processed {}, skipped {}, failed {}",
+            summary.processed, summary.skipped, summary.failed
+        );
+    }
+
+    if summary.cancelled {
+        eprintln!("Cancelled");
+        Err(130)
+    } else if summary.failed > 0 {
+        Err(failure.unwrap_or(5))
+    } else {
+        Ok(())
+    }
+}
+
+/// Process the images of a ZIP archive into another and print the outcome
+/// of each entry with `--json`, or a summary line.
+fn run_archive(args: &Args, input: &Path, options: &RemoveBgOptions) -> Result<(), i32> {
+    let summary = match archive::process_zip(input, args.output_zip.as_deref(), options) {
+        Ok(summary) => summary,
+        Err(e) => {
+            if args.json {
+                print_json(&serde_json::json!({
+                    "input": input.to_string_lossy(),
+                    "success": false,
+                    "error": error_json(&e),
+                }));
+            }
+            eprintln!("Error: {}", e);
+            return Err(e.exit_code());
+        }
+    };
+
+    let (processed, skipped, failed) =
+        (summary.count(EntryStatus::Done), summary.count(EntryStatus::Skipped), summary.count(EntryStatus::Failed));
+    if args.json {
+        let entries: Vec<_> = summary
+            .entries
+            .iter()
+            .map(|entry| {
+                serde_json::json!({
+                    "name": entry.name,
+                    "output": entry.output,
+                    "status": entry.status.as_str(),
+                    "error": entry.error.as_ref().map(to_json),
+                    "elapsed_ms": entry.elapsed.as_secs_f64() * 1000.0,
+                })
+            })
+            .collect();
+        print_json(&serde_json::json!({
+            "input": input.to_string_lossy(),
+            "output": summary.output.to_string_lossy(),
+            "processed": processed,
+            "skipped": skipped,
+            "failed": failed,
+            "entries": entries,
+            "cancelled": summary.cancelled,
+            "success": failed == 0 && !summary.cancelled,
+        }));
+    }
+    if !args.quiet {
+        eprintln!(
+            "Processed {}, skipped {}, failed {}; cutouts written to {}",
+            processed,
+            skipped,
+            failed,
+            summary.output.display()
+        );
+    }
+
+    if summary.cancelled {
+        eprintln!("Cancelled");
+        Err(130)
+    } else if failed > 0 {
+        Err(5)
+    } else {
+        Ok(())
+    }
+}
+
+/// Print a JSON value as a single line on stdout.
+fn print_json(value: &serde_json::Value) {
+    println!("{}", value);
+}
+
+/// The `"error"` object of JSON output: the error's kind and message, and
+/// the stage that failed and its input when known.
+fn error_json(e: &RemoveBgError) -> serde_json::Value {
+    to_json(&ErrorInfo::from(e))
+}
+
+/// A value of the library's types in JSON output, with the field names of
+/// its serde serialization.
+fn to_json(value: &impl serde::Serialize) -> serde_json::Value {
+    serde_json::to_value(value).expect("library types serialize to JSON")
+}
+
+/// Print the metrics of `removebg stats` as a table.
+fn print_alpha_stats(stats: &AlphaStats) {
+    let pixels = stats.histogram.iter().sum::<u64>().max(1) as f64;
+    println!("{:<10} {:>10} {:>7}", "ALPHA", "PIXELS", "SHARE");
+    for (bucket, &count) in stats.histogram.iter().enumerate() {
+        let range = format!("{}-{}", bucket * 16, bucket * 16 + 15);
+        println!("{:<10} {:>10} {:>6.1}%", range, count, count as f64 / pixels * 100.0);
+    }
+    println!("Ambiguous (0.2-0.8): {:.1}%", stats.ambiguous * 100.0);
+    println!("Edge length: {} px", stats.edge_length);
+    println!("Edge softness: {:.2} px", stats.edge_softness);
+    println!(
+        "Components: {} ({} specks under {} px)",
+        stats.components, stats.specks, stats.min_component_area
+    );
+}
+
+/// Print the details of a removal run to stderr for `--verbose`.
+fn print_report(report: &RemovalReport) {
+    let (width, height) = report.input_dimensions;
+    let d = &report.durations;
+    eprintln!("Input size: {}x{}", width, height);
+    let (output_width, output_height) = report.output_dimensions;
+    eprintln!("Output size: {}x{}", output_width, output_height);
+    match report.subject_bounds {
+        Some(rect) => eprintln!("Subject: {}x{} at ({}, {})", rect.width, rect.height, rect.x, rect.y),
+        None => eprintln!("Subject: none found"),
+    }
+    match &report.model {
+        Some(model) => eprintln!(
+            "Model: {} on {}{}",
+            model.model,
+            model.device,
+            if model.downloaded { " (downloaded)" } else { "" }
+        ),
+        None => eprintln!("Mask: external"),
+    }
+    if let Some(selection) = report.model.as_ref().and_then(|model| model.selection.as_ref()) {
+        let tried: Vec<String> = selection.tried.iter().map(Model::to_string).collect();
+        eprintln!("Model choice: {} ({})", tried.join(" -> "), selection.reason);
+    }
+    if let Some(inference) = report.model.as_ref().and_then(|model| model.inference) {
+        eprintln!(
+            "Inference size: {}x{} (estimated {:.1}x the cost of the native size; inference took {:?})",
+            inference.size, inference.size, inference.relative_cost, d.inference
+        );
+    }
+    if report.mask_cached {
+        eprintln!("Mask: from the cache");
+    }
+    if let Some(fast_path) = report.mask_fast_path {
+        eprintln!("Mask: {} everywhere (fast path)", fast_path.name());
+    }
+    if let Some(memory) = &report.memory_estimate {
+        eprintln!(
+            "Memory estimate: {}{}",
+            removebg::format::binary_size(memory.total_bytes),
+            if memory.banded { " (mask upscaled in bands)" } else { "" }
+        );
+    }
+    eprintln!("Mask coverage: {:.1}%", report.mask_coverage * 100.0);
+    eprintln!("Confidence: {:.2}", report.confidence);
+    if let Some(color) = report.background_color {
+        eprintln!("Background: {}", hex_color(color));
+    }
+    if let Some(fit) = &report.file_size {
+        let mut settings = Vec::new();
+        if let Some(quality) = fit.quality {
+            settings.push(format!("quality {}", quality));
+        }
+        if let Some(compression) = fit.png_compression {
+            settings.push(format!("{} compression", compression));
+        }
+        if fit.quantized {
+            settings.push("palette".to_string());
+        }
+        if fit.downscaled {
+            settings.push("downscaled".to_string());
+        }
+        eprintln!("File size: {} bytes ({}; {} attempts)", fit.size, settings.join(", "), fit.attempts);
+    }
+    if report.premultiplied {
+        eprintln!("Alpha: premultiplied");
+    }
+    eprintln!(
+        "Timings: model {:?}, decode {:?}, preprocess {:?}, inference {:?}, postprocess {:?}, composite {:?}, encode {:?}, total {:?}",
+        d.model_load, d.decode, d.preprocess, d.inference, d.postprocess, d.composite, d.encode, d.total
+    );
+    if d.warmup > Duration::ZERO {
+        eprintln!("Warm-up: {:?}", d.warmup);
+    }
+    if d.second_pass > Duration::ZERO {
+        eprintln!("Second pass: {:?}", d.second_pass);
+    }
+}
+
+/// `--model`: a registered model, or `auto` to choose one per image (see
+/// [`AutoModel`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ModelChoice {
+    Fixed(Model),
+    Auto,
+}
+
+impl std::fmt::Display for ModelChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModelChoice::Fixed(model) => model.fmt(f),
+            ModelChoice::Auto => f.pad("auto"),
+        }
+    }
+}
+
+impl std::str::FromStr for ModelChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.eq_ignore_ascii_case("auto") {
+            true => Ok(ModelChoice::Auto),
+            false => s.parse().map(ModelChoice::Fixed).map_err(|e: String| match e.strip_suffix(')') {
+                Some(e) => format!("{}, or auto)", e),
+                None => e,
+            }),
+        }
+    }
+}
+
+/// Where [`run_streams`] writes its result.
+enum Destination<'a> {
+    Stdout,
+    File(&'a Path),
+    Clipboard,
+}
+
+/// Process an image read from stdin or the clipboard and/or written to
+/// stdout or the clipboard.
+///
+/// `input` of `-` reads stdin, and `from_clipboard` reads the clipboard
+/// instead of `input`. Returns the output path when the result was written to
+/// a file.
+fn run_streams(
+    input: &Path,
+    from_clipboard: bool,
+    destination: Destination,
+    options: &RemoveBgOptions,
+) -> Result<Option<PathBuf>, RemoveBgError> {
+    // Opened first, so a missing clipboard fails before any work
+    let to_clipboard = matches!(destination, Destination::Clipboard);
+    let mut clipboard = if from_clipboard || to_clipboard { Some(clipboard::system()?) } else { None };
+    if let (true, true, Some(clipboard)) = (from_clipboard, to_clipboard, clipboard.as_deref_mut()) {
+        clipboard::remove_background_on_clipboard(clipboard, options)?;
+        return Ok(None);
+    }
+
+    let data = if let Some(clipboard) = clipboard.as_deref_mut().filter(|_| from_clipboard) {
+        clipboard::read_image(clipboard)?
+    } else if input.as_os_str() == "-" {
+        let mut data = Vec::new();
+        io::stdin().lock().read_to_end(&mut data)?;
+        data
+    } else if let Some(url) = input.to_str().filter(|input| remote::is_url(input)) {
+        remote::fetch_image(url, options.limits.max_download_size)?
+    } else {
+        if !input.exists() {
+            return Err(RemoveBgError::FileNotFound(input.to_path_buf()));
+        }
+        if !input.is_file() {
+            return Err(RemoveBgError::NotAFile(input.to_path_buf()));
+        }
+        std::fs::read(input)?
+    };
+
+    let output = match destination {
+        Destination::File(output) => output,
+        Destination::Stdout => {
+            let encoded = remove_background_from_bytes(&data, options)?;
+            // Rust writes raw bytes to redirected stdout on every platform,
+            // including Windows, so no text-mode translation can corrupt the image
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(&encoded)?;
+            stdout.flush()?;
+            return Ok(None);
         }
+        Destination::Clipboard => {
+            let options = RemoveBgOptions { format: Some(OutputFormat::Png), ..options.clone() };
+            let encoded = remove_background_from_bytes(&data, &options)?;
+            clipboard::write_image(clipboard.as_deref_mut().expect("the clipboard was opened above"), &encoded)?;
+            return Ok(None);
+        }
+    };
+
+    // Stdin has no file name to name an output in a directory after
+    if is_directory_path(output) {
+        return Err(RemoveBgError::InvalidOutputFormat(format!(
+            "{} is a directory; give a file name for output that isn't read from a file",
+            output.display()
+        )));
+    }
+    // Stdin has no file name, so the format comes from --format or the output extension
+    let (output, format) = negotiate_format(output, OutputFormat::default(), options)?;
+    if !options.overwrite && output.exists() {
+        return Err(RemoveBgError::OutputExists(output));
+    }
+    prepare_output_dir(&output, options.create_dirs)?;
+    let options = RemoveBgOptions { format: Some(format), ..options.clone() };
+
+    let encoded = remove_background_from_bytes(&data, &options)?;
+    write_atomic(&output, &encoded)?;
+    Ok(Some(output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("removebg-preflight-{}-{}", name, process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("photos")).unwrap();
+        std::fs::write(dir.join("photo.jpg"), b"").unwrap();
+        std::fs::write(dir.join("photos/b.png"), b"").unwrap();
+        std::fs::write(dir.join("photos/a.jpg"), b"").unwrap();
+        dir
+    }
+
+    fn checks(args: &[&str], home: Option<&Path>) -> (Args, Preflight) {
+        let mut args = Args::try_parse_from(std::iter::once("removebg").chain(args.iter().copied())).unwrap();
+        let checks = preflight(&mut args, home);
+        (args, checks)
+    }
+
+    fn path(dir: &Path, name: &str) -> String {
+        dir.join(name).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn aliases_name_real_flags() {
+        let command = Args::command();
+        let flags: Vec<String> = command.get_arguments().filter_map(|arg| arg.get_long()).map(|long| format!("--{}", long)).collect();
+        for (alias, flag) in FLAG_ALIASES {
+            assert!(flags.contains(&flag.to_string()), "{} -> {}", alias, flag);
+            assert!(!flags.contains(&alias.to_string()), "{} is a flag itself", alias);
+        }
+        assert_eq!(flag_alias("--out"), Some("--output"));
+        assert_eq!(flag_alias("--bg=red"), Some("--bg-color"));
+        assert_eq!(flag_alias("--nonsense"), None);
+    }
+
+    #[test]
+    fn unknown_flags_get_the_aliased_suggestion() {
+        let error = Args::command().try_get_matches_from(["removebg", "photo.jpg", "--overwrite"]).unwrap_err();
+        let message = suggest_flag(error, false).to_string();
+        assert!(message.contains("'--force'"), "{}", message);
+
+        let error = Args::command().try_get_matches_from(["removebg", "photo.jpg", "--overwrite"]).unwrap_err();
+        assert!(!suggest_flag(error, true).to_string().contains("'--force'"));
+        let error = Args::command().try_get_matches_from(["removebg", "--help"]).unwrap_err();
+        assert_eq!(suggest_flag(error, false).kind(), clap::error::ErrorKind::DisplayHelp);
+    }
+
+    #[test]
+    fn swapped_input_and_output_are_suggested_the_right_way_round() {
+        let dir = temp_dir("swapped");
+        let (result, photo) = (path(&dir, "result.png"), path(&dir, "photo.jpg"));
+        let (_, found) = checks(&[&result, "-o", &photo], None);
+        assert_eq!(
+            found.hints,
+            [format!("input '{}' doesn't exist, but '{}' does; did you mean `removebg {} -o {}`?", result, photo, photo, result)]
+        );
+        // Both missing, or the input there: nothing to suggest
+        assert!(checks(&[&result, "-o", &path(&dir, "other.png")], None).1.hints.is_empty());
+        assert!(checks(&[&photo, "-o", &result], None).1.hints.is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn directories_get_a_batch_or_single_image_command() {
+        let dir = temp_dir("directory");
+        let photos = path(&dir, "photos");
+        let (_, found) = checks(&[&photos, "-o", &path(&dir, "cutouts.png")], None);
+        assert_eq!(found.hints.len(), 1);
+        assert!(found.hints[0].ends_with(&format!("did you mean `removebg {} --output-dir {}`?", photos, path(&dir, "cutouts"))), "{:?}", found.hints);
+
+        let (_, found) = checks(&[&photos, "--bench", "3"], None);
+        assert!(found.hints[0].ends_with(&format!("did you mean `removebg {} --bench 3`?", path(&dir, "photos/a.jpg"))), "{:?}", found.hints);
+        let (_, found) = checks(&[&photos, "--analyze"], None);
+        assert!(found.hints[0].contains("--analyze runs on one image"), "{:?}", found.hints);
+
+        // A directory on its own is a batch run
+        assert!(checks(&[&photos], None).1.hints.is_empty());
+        assert!(checks(&[&photos, "--output-dir", &path(&dir, "out")], None).1.hints.is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn outputs_without_an_extension_get_one() {
+        let dir = temp_dir("extension");
+        let photo = path(&dir, "photo.jpg");
+        let (_, found) = checks(&[&photo, "-o", &path(&dir, "result")], None);
+        assert_eq!(
+            found.hints,
+            [format!("output '{}' has no extension; did you mean `removebg {} -o {}`?", path(&dir, "result"), photo, path(&dir, "result.png"))]
+        );
+        let (_, found) = checks(&[&photo, "-o", &path(&dir, "result"), "--format", "webp"], None);
+        assert!(found.hints[0].ends_with(&format!("-o {}`?", path(&dir, "result.webp"))), "{:?}", found.hints);
+
+        // Directories and --fix-extension are fine
+        assert!(checks(&[&photo, "-o", &path(&dir, "photos")], None).1.hints.is_empty());
+        assert!(checks(&[&photo, "-o", &format!("{}/", path(&dir, "new"))], None).1.hints.is_empty());
+        assert!(checks(&[&photo, "-o", &path(&dir, "result"), "--fix-extension"], None).1.hints.is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn tildes_are_expanded_with_a_note() {
+        let dir = temp_dir("tilde");
+        let note = |from: &str, to: &Path| format!("Expanded '{}' to '{}'", from, to.display());
+        let (args, found) = checks(&["~/photo.jpg", "-o", "~/out/photo.png"], Some(&dir));
+        assert_eq!(args.input, Some(dir.join("photo.jpg")));
+        assert_eq!(args.output, Some(dir.join("out/photo.png")));
+        assert_eq!(found.notes, [note("~/photo.jpg", &dir.join("photo.jpg")), note("~/out/photo.png", &dir.join("out/photo.png"))]);
+        let (args, found) = checks(&["photos", "--output-dir", "~/out"], Some(&dir));
+        assert_eq!(args.output_dir, Some(dir.join("out")));
+        assert_eq!(found.notes, [note("~/out", &dir.join("out"))]);
+        let (args, found) = checks(&["photos", "--output-zip", "~"], Some(&dir));
+        assert_eq!(args.output_zip, Some(dir.clone()));
+        assert_eq!(found.notes, [note("~", &dir)]);
+
+        // Other users' homes, and names that only start with a tilde, are left alone
+        let (args, found) = checks(&["~other/photo.jpg", "-o", "~photo.png"], Some(&dir));
+        assert_eq!((args.input, args.output), (Some("~other/photo.jpg".into()), Some("~photo.png".into())));
+        assert!(found.notes.is_empty());
+        assert!(checks(&["~/photo.jpg"], None).1.notes.is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn paths_are_quoted_for_the_shell_when_needed() {
+        assert_eq!(shell_word(Path::new("photos/a.jpg")), "photos/a.jpg");
+        assert_eq!(shell_word(Path::new("my photos/a.jpg")), "'my photos/a.jpg'");
+        assert_eq!(shell_word(Path::new("it's.png")), r"'it'\''s.png'");
     }
 }
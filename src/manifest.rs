@@ -0,0 +1,733 @@
+//! Batch jobs driven by a manifest file.
+//!
+//! Used by `removebg batch --manifest jobs.csv`. A manifest lists one image
+//! per CSV row or JSONL line: an `input`, an optional `output` and optional
+//! overrides of the default options for that image (see [`COLUMNS`]). The
+//! whole manifest is checked before anything is processed, so a typo on the
+//! last line doesn't leave a half-finished run behind, and the outcome of
+//! every item is reported as [`ItemResult`]s that [`write_results`] turns
+//! into a results manifest.
+
+use crate::core::{self, default_output_path};
+use crate::error::{self, RemoveBgError, Result};
+use crate::mask_ops;
+use crate::options::{Background, MaskOp, RemoveBgOptions};
+use crate::output::OutputFormat;
+use crate::progress::{Progress, ProgressEvent, LOG_TARGET};
+use crate::remover::BackgroundRemover;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Columns of a CSV manifest, or keys of a JSONL one. Only `input` is
+/// required, and empty or missing values take the defaults.
+///
+/// * `input` - Image to process
+/// * `output` - Where to write the cutout; by default it is named like the
+///   outputs of [`remove_background`](crate::remove_background)
+/// * `format` - Output format: png, webp, tiff, bmp, jpeg, gif, apng or ico
+/// * `quality` - Lossy quality 0-100 for WebP and JPEG
+/// * `bg_color` - Background color (name or `#rrggbb`), or `transparent`
+/// * `crop` - `yes` or `no`: crop to the subject, with the margin of `pad`
+/// * `pad` - Crop margin as a percentage of the subject's longer side, like
+///   `10%`; implies `crop`
+/// * `square` - `yes` or `no`: pad the output to a square canvas
+/// * `resize` - Shrink the output to fit within `WxH`
+/// * `mask_ops` - Mask post-processing operators, comma-separated like
+///   `dilate=3,feather=2.5` (see [`MaskOp`])
+pub const COLUMNS: [&str; 10] = ["input", "output", "format", "quality", "bg_color", "crop", "pad", "square", "resize", "mask_ops"];
+
+/// Syntax of a manifest file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ManifestFormat {
+    /// Comma-separated values with a header row naming the [`COLUMNS`].
+    #[default]
+    Csv,
+    /// One JSON object per line, keyed by the [`COLUMNS`].
+    Jsonl,
+}
+
+impl ManifestFormat {
+    /// JSONL for `.jsonl` and `.ndjson` files, CSV otherwise.
+    pub fn from_path(path: &Path) -> Self {
+        let extension = path.extension().unwrap_or_default();
+        if extension.eq_ignore_ascii_case("jsonl") || extension.eq_ignore_ascii_case("ndjson") {
+            ManifestFormat::Jsonl
+        } else {
+            ManifestFormat::Csv
+        }
+    }
+
+    /// File extension for this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ManifestFormat::Csv => "csv",
+            ManifestFormat::Jsonl => "jsonl",
+        }
+    }
+
+    /// The format of manifest text: JSONL if it starts with an object.
+    fn detect(text: &str) -> Self {
+        if text.trim_start().starts_with('{') {
+            ManifestFormat::Jsonl
+        } else {
+            ManifestFormat::Csv
+        }
+    }
+}
+
+/// Settings for [`run_manifest`].
+#[derive(Debug, Clone, Default)]
+pub struct ManifestConfig {
+    /// Directory for outputs: items without an `output` are written here
+    /// instead of next to their input, and relative `output` paths are
+    /// resolved against it. Created if it doesn't exist.
+    pub output_dir: Option<PathBuf>,
+    /// Options for every item, before its overrides. Images with equal
+    /// overrides are run through the model `options.batch_size` at a time;
+    /// cancelling `options.cancel` stops the run after the current batch.
+    pub options: RemoveBgOptions,
+    /// Receives each item's progress.
+    pub progress: Progress,
+}
+
+/// Per-item overrides of the default options, from the manifest's columns.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ItemOverrides {
+    /// `format`
+    pub format: Option<OutputFormat>,
+    /// `quality`
+    pub quality: Option<f32>,
+    /// `bg_color`
+    pub background: Option<Background>,
+    /// `crop`
+    pub crop: Option<bool>,
+    /// `pad`, as a fraction (0.1 = 10%)
+    pub pad: Option<f32>,
+    /// `square`
+    pub square: Option<bool>,
+    /// `resize`
+    pub resize: Option<(u32, u32)>,
+    /// `mask_ops`
+    pub mask_ops: Option<Vec<MaskOp>>,
+}
+
+impl ItemOverrides {
+    /// `defaults` with these overrides applied.
+    pub fn apply(&self, defaults: &RemoveBgOptions) -> RemoveBgOptions {
+        let mut options = defaults.clone();
+        options.format = self.format.or(defaults.format);
+        options.quality = self.quality.or(defaults.quality);
+        options.background = self.background.unwrap_or(defaults.background);
+        options.geometry.pad = match (self.crop, self.pad) {
+            (Some(false), _) => None,
+            (_, Some(pad)) => Some(pad),
+            (Some(true), None) => Some(defaults.geometry.pad.unwrap_or(0.0)),
+            (None, None) => defaults.geometry.pad,
+        };
+        options.geometry.square = self.square.unwrap_or(defaults.geometry.square);
+        options.geometry.resize = self.resize.or(defaults.geometry.resize);
+        if let Some(ops) = &self.mask_ops {
+            options.mask_ops = ops.clone();
+        }
+        options
+    }
+}
+
+/// One image of a manifest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestItem {
+    /// Line of the manifest the item is on, counting from 1.
+    pub line: usize,
+    /// Image to process.
+    pub input: PathBuf,
+    /// Where to write the cutout, as given in the manifest.
+    pub output: Option<PathBuf>,
+    /// Options that differ from the defaults for this image.
+    pub overrides: ItemOverrides,
+}
+
+/// How an item of a manifest ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ItemStatus {
+    /// The cutout was written.
+    Done,
+    /// Processing failed; the run went on with the next item.
+    Failed,
+    /// The run was cancelled before the item was processed.
+    Cancelled,
+}
+
+impl ItemStatus {
+    /// Name used in results manifests: `ok`, `failed` or `cancelled`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ItemStatus::Done => "ok",
+            ItemStatus::Failed => "failed",
+            ItemStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// Outcome of one item of a manifest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ItemResult {
+    /// Line of the manifest the item is on, counting from 1.
+    pub line: usize,
+    /// Image the item processed.
+    pub input: PathBuf,
+    /// Where the cutout was, or would have been, written.
+    pub output: PathBuf,
+    /// How the item ended.
+    pub status: ItemStatus,
+    /// Why the item failed.
+    pub error: Option<String>,
+    /// Processing time, including the item's share of a batched model run;
+    /// zero unless the item is done.
+    pub elapsed: Duration,
+}
+
+/// An item with its output path resolved.
+struct Job {
+    line: usize,
+    input: PathBuf,
+    output: PathBuf,
+    overrides: ItemOverrides,
+}
+
+/// Process every image listed in a manifest read from `reader`, CSV or
+/// JSONL (detected from the content).
+///
+/// Before anything is processed, the whole manifest is checked: columns and
+/// values must be valid, every input must exist, and no two items may be
+/// written to the same output (compared case-insensitively, as macOS and
+/// Windows file systems usually are). All problems found are reported
+/// together, with their line numbers.
+///
+/// Failures of single items are logged and recorded in their results; the
+/// run continues with the next item.
+///
+/// # Returns
+/// One result per item, in manifest order.
+///
+/// # Errors
+/// * `InvalidManifest` - If the manifest has problems; nothing was processed
+/// * `IoError` - If the manifest cannot be read or the output directory cannot be created
+/// * `ModelInitError` - If the model cannot be loaded
+pub fn run_manifest(mut reader: impl Read, config: &ManifestConfig) -> Result<Vec<ItemResult>> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+    let mut problems = Problems::new();
+    let items = parse(&text, &mut problems);
+    let jobs = plan(items, config, &mut problems);
+    check(problems)?;
+    if let Some(dir) = &config.output_dir {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let defaults = &config.options;
+    let remover = BackgroundRemover::new(defaults.clone())?;
+    let batch_size = defaults.batch_size.max(1);
+    let mut results = Vec::with_capacity(jobs.len());
+    let mut cancelled = false;
+    config.progress.report(ProgressEvent::Queued { count: jobs.len() });
+    // Consecutive items with the same options share batched model runs
+    for group in jobs.chunk_by(|a, b| a.overrides == b.overrides) {
+        let options = group[0].overrides.apply(defaults);
+        for chunk in group.chunks(batch_size) {
+            cancelled |= options.cancel.is_cancelled();
+            if cancelled {
+                results.extend(chunk.iter().map(|job| job.result(ItemStatus::Cancelled, None)));
+                continue;
+            }
+            let paths: Vec<(&Path, Option<&Path>)> =
+                chunk.iter().map(|job| (job.input.as_path(), Some(job.output.as_path()))).collect();
+            for job in chunk {
+                config.progress.report(ProgressEvent::Started { input: &job.input });
+            }
+            for (job, result) in chunk.iter().zip(core::remove_files(&paths, &options, &remover)) {
+                results.push(match result {
+                    Ok(report) => {
+                        log::info!(target: LOG_TARGET, "{} -> {}", job.input.display(), report.output_path.display());
+                        config.progress.report(ProgressEvent::Processed { input: &job.input, report: &report });
+                        ItemResult { elapsed: report.durations.total, ..job.result(ItemStatus::Done, None) }
+                    }
+                    Err(RemoveBgError::Cancelled) => {
+                        cancelled = true;
+                        job.result(ItemStatus::Cancelled, None)
+                    }
+                    Err(e @ (RemoveBgError::ModelInitError(_) | RemoveBgError::ModelNotCached { .. })) => return Err(e),
+                    Err(e) => {
+                        log::warn!("line {}: {}", job.line, error::describe(&job.input, &e));
+                        config.progress.report(ProgressEvent::Failed { input: &job.input, error: &e });
+                        job.result(ItemStatus::Failed, Some(e.to_string()))
+                    }
+                });
+            }
+        }
+    }
+    Ok(results)
+}
+
+impl Job {
+    fn result(&self, status: ItemStatus, error: Option<String>) -> ItemResult {
+        ItemResult {
+            line: self.line,
+            input: self.input.clone(),
+            output: self.output.clone(),
+            status,
+            error,
+            elapsed: Duration::ZERO,
+        }
+    }
+}
+
+/// Write `results` as a results manifest: columns `line`, `input`,
+/// `output`, `status`, `time_ms` and `error` (empty unless failed).
+///
+/// # Errors
+/// * `IoError` - If writing fails
+pub fn write_results(mut writer: impl Write, format: ManifestFormat, results: &[ItemResult]) -> Result<()> {
+    let time_ms = |result: &ItemResult| (result.elapsed.as_secs_f64() * 10_000.0).round() / 10.0;
+    match format {
+        ManifestFormat::Csv => {
+            let mut csv = csv::Writer::from_writer(writer);
+            csv.write_record(["line", "input", "output", "status", "time_ms", "error"]).map_err(io::Error::from)?;
+            for result in results {
+                csv.write_record([
+                    result.line.to_string(),
+                    result.input.display().to_string(),
+                    result.output.display().to_string(),
+                    result.status.as_str().to_string(),
+                    time_ms(result).to_string(),
+                    result.error.clone().unwrap_or_default(),
+                ])
+                .map_err(io::Error::from)?;
+            }
+            csv.flush()?;
+        }
+        ManifestFormat::Jsonl => {
+            for result in results {
+                let record = serde_json::json!({
+                    "line": result.line,
+                    "input": result.input.display().to_string(),
+                    "output": result.output.display().to_string(),
+                    "status": result.status.as_str(),
+                    "time_ms": time_ms(result),
+                    "error": result.error,
+                });
+                writeln!(writer, "{}", record)?;
+            }
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// Parse manifest text into its items, checking columns and values.
+///
+/// # Errors
+/// * `InvalidManifest` - With every problem found
+pub fn parse_manifest(text: &str) -> Result<Vec<ManifestItem>> {
+    let mut problems = Problems::new();
+    let items = parse(text, &mut problems);
+    check(problems)?;
+    Ok(items)
+}
+
+/// Problems found in a manifest, as `(line, message)`; line 0 stands for
+/// the whole manifest.
+type Problems = Vec<(usize, String)>;
+
+/// `InvalidManifest` listing `problems` in line order, if there are any.
+fn check(mut problems: Problems) -> Result<()> {
+    if problems.is_empty() {
+        return Ok(());
+    }
+    problems.sort_by_key(|&(line, _)| line);
+    let problems = problems
+        .into_iter()
+        .map(|(line, problem)| match line {
+            0 => problem,
+            line => format!("line {}: {}", line, problem),
+        })
+        .collect();
+    Err(RemoveBgError::InvalidManifest(problems))
+}
+
+/// The items of a manifest in either format, with the problems of its
+/// syntax, columns and values added to `problems`.
+fn parse(text: &str, problems: &mut Problems) -> Vec<ManifestItem> {
+    let items = match ManifestFormat::detect(text) {
+        ManifestFormat::Csv => csv_items(text, problems),
+        ManifestFormat::Jsonl => jsonl_items(text, problems),
+    };
+    if items.is_empty() && problems.is_empty() {
+        problems.push((0, "the manifest lists no images".into()));
+    }
+    items
+}
+
+/// Items of a CSV manifest. Unknown columns are reported once, for the
+/// header, and otherwise ignored.
+fn csv_items(text: &str, problems: &mut Problems) -> Vec<ManifestItem> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).flexible(true).from_reader(text.as_bytes());
+    let headers = match reader.headers() {
+        Ok(headers) => headers.clone(),
+        Err(e) => {
+            problems.push((1, e.to_string()));
+            return Vec::new();
+        }
+    };
+    for header in &headers {
+        if !COLUMNS.contains(&header) {
+            problems.push((1, format!("unknown column '{}' (expected {})", header, COLUMNS.join(", "))));
+        }
+    }
+    if !headers.iter().any(|header| header == "input") {
+        problems.push((1, "no input column".into()));
+        return Vec::new();
+    }
+
+    let mut items = Vec::new();
+    for record in reader.records() {
+        match record {
+            Ok(record) => {
+                let line = record.position().map_or(0, |position| position.line() as usize);
+                if record.len() > headers.len() {
+                    problems.push((line, format!("{} values for {} columns", record.len(), headers.len())));
+                    continue;
+                }
+                let fields = headers
+                    .iter()
+                    .zip(&record)
+                    .filter(|(header, _)| COLUMNS.contains(header))
+                    .map(|(header, value)| (header.to_string(), value.to_string()))
+                    .collect();
+                items.extend(parse_item(line, fields, problems));
+            }
+            Err(e) => {
+                let line = e.position().map_or(0, |position| position.line() as usize);
+                problems.push((line, e.to_string()));
+            }
+        }
+    }
+    items
+}
+
+/// Items of a JSONL manifest, with numbers and booleans taken as text.
+fn jsonl_items(text: &str, problems: &mut Problems) -> Vec<ManifestItem> {
+    let mut items = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let number = index + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let object = match serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(line) {
+            Ok(object) => object,
+            Err(e) => {
+                problems.push((number, e.to_string()));
+                continue;
+            }
+        };
+        let mut fields = Vec::new();
+        for (key, value) in object {
+            let value = match value {
+                serde_json::Value::Null => continue,
+                serde_json::Value::String(value) => value,
+                serde_json::Value::Bool(value) => value.to_string(),
+                serde_json::Value::Number(value) => value.to_string(),
+                _ => {
+                    problems.push((number, format!("'{}' must be a string, number or boolean", key)));
+                    continue;
+                }
+            };
+            fields.push((key, value));
+        }
+        items.extend(parse_item(number, fields, problems));
+    }
+    items
+}
+
+/// The item on `line`, with the problems of its values added to
+/// `problems`; `None` if it has no input.
+fn parse_item(line: usize, fields: Vec<(String, String)>, problems: &mut Problems) -> Option<ManifestItem> {
+    let mut input = None;
+    let mut output = None;
+    let mut overrides = ItemOverrides::default();
+    for (key, value) in fields {
+        if value.trim().is_empty() {
+            continue;
+        }
+        let parsed = match key.as_str() {
+            "input" => {
+                input = Some(PathBuf::from(value));
+                Ok(())
+            }
+            "output" => {
+                output = Some(PathBuf::from(value));
+                Ok(())
+            }
+            "format" => value.parse().map(|format| overrides.format = Some(format)),
+            "quality" => parse_quality(&value).map(|quality| overrides.quality = Some(quality)),
+            "bg_color" => value.parse().map(|background| overrides.background = Some(background)),
+            "crop" => parse_bool(&key, &value).map(|crop| overrides.crop = Some(crop)),
+            "pad" => parse_percent(&value).map(|pad| overrides.pad = Some(pad)),
+            "square" => parse_bool(&key, &value).map(|square| overrides.square = Some(square)),
+            "resize" => parse_size(&value).map(|size| overrides.resize = Some(size)),
+            "mask_ops" => mask_ops::parse_list(&value).map(|ops| overrides.mask_ops = Some(ops)),
+            _ => Err(format!("unknown key '{}' (expected {})", key, COLUMNS.join(", "))),
+        };
+        if let Err(problem) = parsed {
+            problems.push((line, problem));
+        }
+    }
+    if overrides.crop == Some(false) && overrides.pad.is_some() {
+        problems.push((line, "pad contradicts crop=no".into()));
+    }
+    let Some(input) = input else {
+        problems.push((line, "no input".into()));
+        return None;
+    };
+    Some(ManifestItem { line, input, output, overrides })
+}
+
+/// Resolve the output of every item and check the inputs and outputs.
+fn plan(items: Vec<ManifestItem>, config: &ManifestConfig, problems: &mut Problems) -> Vec<Job> {
+    let mut jobs = Vec::with_capacity(items.len());
+    // Lower-cased output path -> line of the first item writing it
+    let mut claimed: HashMap<String, usize> = HashMap::new();
+    for item in items {
+        if !item.input.is_file() {
+            let problem = if item.input.exists() { "is not a file" } else { "not found" };
+            problems.push((item.line, format!("input {} {}", item.input.display(), problem)));
+            continue;
+        }
+        let output = match (&item.output, &config.output_dir) {
+            (Some(output), Some(dir)) => dir.join(output),
+            (Some(output), None) => output.clone(),
+            (None, dir) => {
+                let dir = dir.as_deref().or(item.input.parent()).unwrap_or(Path::new(""));
+                let taken = |path: &Path| claimed.contains_key(&path.to_string_lossy().to_lowercase());
+                match default_output_path(&item.input, dir, &item.overrides.apply(&config.options), &taken) {
+                    Ok(output) => output,
+                    Err(e) => {
+                        problems.push((item.line, e.to_string()));
+                        continue;
+                    }
+                }
+            }
+        };
+        let key = output.to_string_lossy().to_lowercase();
+        if let Some(first) = claimed.get(&key) {
+            problems.push((item.line, format!("output {} is also written by line {}", output.display(), first)));
+            continue;
+        }
+        claimed.insert(key, item.line);
+        jobs.push(Job { line: item.line, input: item.input, output, overrides: item.overrides });
+    }
+    jobs
+}
+
+fn parse_bool(key: &str, value: &str) -> std::result::Result<bool, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "yes" | "true" | "1" => Ok(true),
+        "no" | "false" | "0" => Ok(false),
+        _ => Err(format!("invalid {} '{}' (expected yes or no)", key, value)),
+    }
+}
+
+fn parse_quality(value: &str) -> std::result::Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(quality) if (0.0..=100.0).contains(&quality) => Ok(quality),
+        _ => Err(format!("invalid quality '{}' (expected 0-100)", value)),
+    }
+}
+
+/// Parse a percentage, with or without `%`, into a fraction.
+fn parse_percent(value: &str) -> std::result::Result<f32, String> {
+    match value.strip_suffix('%').unwrap_or(value).trim().parse::<f32>() {
+        Ok(percent) if percent.is_finite() && percent >= 0.0 => Ok(percent / 100.0),
+        _ => Err(format!("invalid pad '{}' (expected a non-negative percentage like 10%)", value)),
+    }
+}
+
+fn parse_size(value: &str) -> std::result::Result<(u32, u32), String> {
+    let invalid = || format!("invalid resize '{}' (expected WxH like 1600x1600)", value);
+    let (width, height) = value.split_once(['x', 'X']).ok_or_else(invalid)?;
+    match (width.trim().parse::<u32>(), height.trim().parse::<u32>()) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CSV: &str = include_str!("../tests/fixtures/manifests/jobs.csv");
+    const JSONL: &str = include_str!("../tests/fixtures/manifests/jobs.jsonl");
+    const INVALID: &str = include_str!("../tests/fixtures/manifests/invalid.csv");
+
+    fn problems_of(result: Result<impl std::fmt::Debug>) -> Vec<String> {
+        match result {
+            Err(RemoveBgError::InvalidManifest(problems)) => problems,
+            other => panic!("expected an invalid manifest, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn csv_and_jsonl_manifests_parse_alike() {
+        let csv = parse_manifest(CSV).unwrap();
+        let jsonl = parse_manifest(JSONL).unwrap();
+        assert_eq!(csv.len(), 3);
+        for (csv, jsonl) in csv.iter().zip(&jsonl) {
+            assert_eq!((&csv.input, &csv.output, &csv.overrides), (&jsonl.input, &jsonl.output, &jsonl.overrides));
+        }
+        // Line numbers count the CSV header
+        assert_eq!(csv.iter().map(|item| item.line).collect::<Vec<_>>(), [2, 3, 4]);
+        assert_eq!(jsonl.iter().map(|item| item.line).collect::<Vec<_>>(), [1, 2, 3]);
+
+        assert_eq!(csv[0].output, None);
+        assert_eq!(csv[0].overrides, ItemOverrides::default());
+        assert_eq!(
+            csv[1].overrides,
+            ItemOverrides {
+                format: Some(OutputFormat::Jpeg),
+                quality: Some(80.0),
+                background: Some(Background::Color([255, 255, 255])),
+                crop: Some(true),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            csv[2].overrides,
+            ItemOverrides { pad: Some(0.1), square: Some(true), resize: Some((64, 64)), ..Default::default() }
+        );
+    }
+
+    #[test]
+    fn unspecified_fields_inherit_the_defaults() {
+        let defaults = RemoveBgOptions { quality: Some(50.0), format: Some(OutputFormat::WebP), ..Default::default() };
+        let items = parse_manifest(CSV).unwrap();
+        let options = items[1].overrides.apply(&defaults);
+        assert_eq!((options.format, options.quality), (Some(OutputFormat::Jpeg), Some(80.0)));
+        assert_eq!(options.geometry.pad, Some(0.0));
+
+        let options = items[0].overrides.apply(&defaults);
+        assert_eq!((options.format, options.quality), (Some(OutputFormat::WebP), Some(50.0)));
+        assert_eq!(options.geometry.pad, None);
+    }
+
+    #[test]
+    fn problems_are_reported_with_line_numbers() {
+        let problems = problems_of(parse_manifest(INVALID));
+        assert_eq!(
+            problems,
+            [
+                "line 1: unknown column 'colour' (expected input, output, format, quality, bg_color, crop, pad, square, resize, mask_ops)",
+                "line 3: invalid quality '120' (expected 0-100)",
+                "line 4: no input",
+            ]
+        );
+
+        let problems = problems_of(parse_manifest("{\"input\": \"a.png\", \"sqaure\": true}\nnot json\n"));
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems[0].starts_with("line 1: unknown key 'sqaure'"), "{:?}", problems);
+        assert!(problems[1].starts_with("line 2: "), "{:?}", problems);
+
+        assert_eq!(problems_of(parse_manifest("input,output\n")), ["the manifest lists no images"]);
+    }
+
+    #[test]
+    fn mask_ops_are_read_as_the_flag_writes_them() {
+        let csv = parse_manifest("input,mask_ops\na.png,\"dilate=3,feather=2.5\"\nb.png,\n").unwrap();
+        let jsonl = parse_manifest("{\"input\": \"a.png\", \"mask_ops\": \"dilate=3,feather=2.5\"}\n").unwrap();
+        let ops = vec![MaskOp::Dilate(3), MaskOp::Feather(2.5)];
+        assert_eq!(csv[0].overrides.mask_ops.as_ref(), Some(&ops));
+        assert_eq!(jsonl[0].overrides, csv[0].overrides);
+        let defaults = RemoveBgOptions { mask_ops: vec![MaskOp::Threshold(0.5)], ..Default::default() };
+        assert_eq!(csv[0].overrides.apply(&defaults).mask_ops, ops);
+        assert_eq!(csv[1].overrides.apply(&defaults).mask_ops, defaults.mask_ops);
+
+        let problems = problems_of(parse_manifest("input,mask_ops\na.png,blur=2\n"));
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("line 2: unknown mask operation 'blur'"), "{:?}", problems);
+    }
+
+    #[test]
+    fn missing_inputs_and_duplicate_outputs_stop_the_run() {
+        let manifest = "input,output\n\
+                        tests/fixtures/golden/portrait.png,out/a.png\n\
+                        tests/fixtures/golden/missing.png,out/b.png\n\
+                        tests/fixtures/golden/gray16.png,OUT/A.png\n";
+        // Nothing is loaded or written, so no model is needed
+        let config = ManifestConfig::default();
+        let problems = problems_of(run_manifest(manifest.as_bytes(), &config));
+        assert_eq!(
+            problems,
+            [
+                "line 3: input tests/fixtures/golden/missing.png not found",
+                "line 4: output OUT/A.png is also written by line 2",
+            ]
+        );
+    }
+
+    #[test]
+    fn default_outputs_collide_too() {
+        // Listing an input twice writes its default output twice
+        let manifest = "{\"input\": \"tests/fixtures/golden/portrait.png\"}\n\
+                        {\"input\": \"tests/fixtures/golden/portrait.png\", \"output\": null}\n";
+        let problems = problems_of(run_manifest(manifest.as_bytes(), &ManifestConfig::default()));
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("line 2: output ") && problems[0].ends_with("portrait_nobg.png is also written by line 1"));
+    }
+
+    #[test]
+    fn results_are_written_in_the_manifest_format() {
+        let results = [
+            ItemResult {
+                line: 2,
+                input: "a.jpg".into(),
+                output: "a_nobg.png".into(),
+                status: ItemStatus::Done,
+                error: None,
+                elapsed: Duration::from_micros(12_340),
+            },
+            ItemResult {
+                line: 3,
+                input: "b, c.jpg".into(),
+                output: "b_nobg.png".into(),
+                status: ItemStatus::Failed,
+                error: Some("Output file already exists".into()),
+                elapsed: Duration::ZERO,
+            },
+        ];
+        let mut csv = Vec::new();
+        write_results(&mut csv, ManifestFormat::Csv, &results).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "line,input,output,status,time_ms,error\n\
+             2,a.jpg,a_nobg.png,ok,12.3,\n\
+             3,\"b, c.jpg\",b_nobg.png,failed,0,Output file already exists\n"
+        );
+
+        let mut jsonl = Vec::new();
+        write_results(&mut jsonl, ManifestFormat::Jsonl, &results).unwrap();
+        let lines: Vec<serde_json::Value> =
+            String::from_utf8(jsonl).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["status"], "ok");
+        assert_eq!(lines[0]["time_ms"], 12.3);
+        assert_eq!(lines[1]["error"], "Output file already exists");
+        assert!(lines[0]["error"].is_null());
+    }
+
+    #[test]
+    fn format_follows_the_extension() {
+        assert_eq!(ManifestFormat::from_path(Path::new("jobs.JSONL")), ManifestFormat::Jsonl);
+        assert_eq!(ManifestFormat::from_path(Path::new("jobs.ndjson")), ManifestFormat::Jsonl);
+        assert_eq!(ManifestFormat::from_path(Path::new("jobs.csv")), ManifestFormat::Csv);
+    }
+}
@@ -0,0 +1,289 @@
+//! On-disk cache of model masks (`--mask-cache`).
+//!
+//! Iterating on post-processing flags reruns the model on the same inputs,
+//! which is the slow part of a run. With a cache, the unrefined
+//! full-resolution mask of each input is stored as a 16-bit PNG named for
+//! the SHA-256 of:
+//!
+//! - [`CACHE_VERSION`]
+//! - the model, engine and the settings that shape the model's mask: mask
+//!   fusion, levels, gamma and resize filter, tiling, the region of interest
+//!   and two-pass inference
+//! - the decoded pixels, dimensions and color type of the input
+//!
+//! A later run with the same key reads the mask back and skips loading and
+//! running the model; refinement, compositing and output run as usual.
+//! Keying by the decoded pixels rather than the file bytes lets in-memory
+//! and URL inputs hit as well, and a changed EXIF orientation or
+//! `max_output_size` can never return a mask of the wrong shape.
+//!
+//! Entries are evicted least recently used first, by modification time,
+//! which a hit refreshes, once the cache grows past its
+//! [`max_size`](MaskCacheOptions::max_size). Failing to read or write the
+//! cache never fails a run; it is logged and the model runs instead.
+
+use crate::core::Gray16Image;
+use crate::error::Result;
+use crate::matte;
+use crate::options::{InferenceSize, MaskCacheOptions, RemoveBgOptions};
+use crate::output;
+use image::{DynamicImage, GenericImageView};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Version of the mask generation, part of every key. Bump it when
+/// preprocessing, mask fusion or upscaling changes the masks a model
+/// produces, so entries from older versions are never used.
+pub const CACHE_VERSION: u32 = 2;
+
+/// Default [`MaskCacheOptions::max_size`]: 1 GB.
+pub const DEFAULT_MAX_SIZE: u64 = 1_000_000_000;
+
+/// Extension of cached mask files.
+const EXTENSION: &str = "png";
+
+/// Number and total size of the masks in a cache directory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of cached masks.
+    pub entries: usize,
+    /// Total size of the cached masks in bytes.
+    pub bytes: u64,
+}
+
+/// Count the masks cached in `dir`; a missing directory is an empty cache.
+///
+/// # Errors
+/// * `IoError` - If the directory cannot be read
+pub fn stats(dir: &Path) -> Result<CacheStats> {
+    let entries = entries(dir)?;
+    Ok(CacheStats { entries: entries.len(), bytes: entries.iter().map(|entry| entry.bytes).sum() })
+}
+
+/// Delete every mask cached in `dir` and report what was deleted. Other
+/// files in the directory are left alone.
+///
+/// # Errors
+/// * `IoError` - If the directory cannot be read or a mask cannot be deleted
+pub fn clear(dir: &Path) -> Result<CacheStats> {
+    let mut removed = CacheStats::default();
+    for entry in entries(dir)? {
+        std::fs::remove_file(&entry.path)?;
+        removed.entries += 1;
+        removed.bytes += entry.bytes;
+    }
+    Ok(removed)
+}
+
+/// The cache key of `image` under `options`, as lowercase hex.
+pub(crate) fn key(image: &DynamicImage, options: &RemoveBgOptions) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(settings(options).as_bytes());
+    let (width, height) = image.dimensions();
+    hasher.update(format!("|{}x{}|{:?}|", width, height, image.color()).as_bytes());
+    hasher.update(image.as_bytes());
+    hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The settings in `options` that shape the model's mask, the part of the
+/// key that doesn't depend on the image. Options with the same settings get
+/// the same mask for an image.
+pub(crate) fn settings(options: &RemoveBgOptions) -> String {
+    // Levels and gamma that an explicit `mask_ops` list overrides don't shape the mask
+    let (levels, gamma) = if matte::has_mask_curve(options) {
+        (options.mask_levels, options.mask_gamma)
+    } else {
+        (None, None)
+    };
+    let mut settings = format!(
+        "{}|{}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{}|{}",
+        CACHE_VERSION,
+        options.model,
+        options.backend,
+        options.mask_fusion,
+        levels,
+        gamma,
+        options.tiling,
+        options.roi,
+        options.roi_margin,
+        options.two_pass,
+        options.mask_filter,
+    );
+    // Appended only when set, so entries written without it stay valid
+    if let Some(strength) = options.auto_enhance {
+        settings.push_str(&format!("|enhance {}", strength));
+    }
+    if options.inference_size != InferenceSize::Native {
+        settings.push_str(&format!("|size {}", options.inference_size));
+    }
+    settings
+}
+
+/// The cached mask for `key`, if there is one of the image's `dimensions`.
+///
+/// A hit marks the entry as recently used.
+pub(crate) fn load(cache: &MaskCacheOptions, key: &str, dimensions: (u32, u32)) -> Option<Gray16Image> {
+    let path = entry_path(&cache.dir, key);
+    let mask = match image::open(&path) {
+        Ok(mask) => mask.into_luma16(),
+        Err(e) => {
+            if path.exists() {
+                log::warn!("ignoring unreadable cached mask {}: {}", path.display(), e);
+            }
+            return None;
+        }
+    };
+    if mask.dimensions() != dimensions {
+        log::debug!("cached mask {} has the wrong size; running the model", path.display());
+        return None;
+    }
+    if let Err(e) = File::options().write(true).open(&path).and_then(|file| file.set_modified(SystemTime::now())) {
+        log::debug!("could not mark {} as used: {}", path.display(), e);
+    }
+    log::debug!("using the cached mask {}", path.display());
+    Some(mask)
+}
+
+/// Cache `mask` under `key`, then evict old entries if the cache has grown
+/// past its maximum size.
+pub(crate) fn store(cache: &MaskCacheOptions, key: &str, mask: &Gray16Image) {
+    let path = entry_path(&cache.dir, key);
+    let stored = std::fs::create_dir_all(&cache.dir).map_err(Into::into).and_then(|_| output::save_mask(mask, &path));
+    if let Err(e) = stored {
+        log::warn!("could not cache the mask in {}: {}", cache.dir.display(), e);
+        return;
+    }
+    log::debug!("cached the mask as {}", path.display());
+    if let Some(max_size) = cache.max_size {
+        if let Err(e) = evict(&cache.dir, max_size) {
+            log::warn!("could not evict old masks from {}: {}", cache.dir.display(), e);
+        }
+    }
+}
+
+/// Delete the least recently used masks in `dir` until the rest fit in
+/// `max_size` bytes.
+fn evict(dir: &Path, max_size: u64) -> Result<()> {
+    let mut entries = entries(dir)?;
+    let mut total: u64 = entries.iter().map(|entry| entry.bytes).sum();
+    entries.sort_by_key(|entry| entry.modified);
+    for entry in entries {
+        if total <= max_size {
+            break;
+        }
+        std::fs::remove_file(&entry.path)?;
+        total -= entry.bytes;
+        log::debug!("evicted {} from the mask cache", entry.path.display());
+    }
+    Ok(())
+}
+
+/// A cached mask file.
+struct Entry {
+    path: PathBuf,
+    bytes: u64,
+    modified: SystemTime,
+}
+
+/// The cached masks in `dir`: files named for a key, skipping the temporary
+/// files of writes in progress.
+fn entries(dir: &Path) -> Result<Vec<Entry>> {
+    let read = match std::fs::read_dir(dir) {
+        Ok(read) => read,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut entries = Vec::new();
+    for item in read {
+        let item = item?;
+        let path = item.path();
+        let is_key = path.file_stem().and_then(|stem| stem.to_str()).is_some_and(|stem| {
+            stem.len() == 64 && stem.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+        });
+        if !is_key || path.extension().is_none_or(|extension| extension != EXTENSION) {
+            continue;
+        }
+        let metadata = item.metadata()?;
+        entries.push(Entry { path, bytes: metadata.len(), modified: metadata.modified()? });
+    }
+    Ok(entries)
+}
+
+fn entry_path(dir: &Path, key: &str) -> PathBuf {
+    dir.join(key).with_extension(EXTENSION)
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use image::{Luma, Rgb, RgbImage};
+    use std::time::Duration;
+
+    fn temp_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("removebg-mask-cache-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn photo(seed: u8) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(32, 24, |x, y| Rgb([x as u8 ^ seed, y as u8, seed])))
+    }
+
+    fn mask(value: u16) -> Gray16Image {
+        Gray16Image::from_pixel(32, 24, Luma([value]))
+    }
+
+    #[test]
+    fn keys_follow_pixels_and_mask_settings() {
+        let options = RemoveBgOptions::default();
+        let key = key(&photo(1), &options);
+        assert_eq!(key.len(), 64);
+        assert_eq!(key, super::key(&photo(1), &options));
+        assert_ne!(key, super::key(&photo(2), &options));
+        let refined = RemoveBgOptions { invert_mask: true, background: crate::Background::Color([0, 0, 0]), ..Default::default() };
+        assert_eq!(key, super::key(&photo(1), &refined));
+        let model = RemoveBgOptions { model: crate::Model::U2netp, ..Default::default() };
+        assert_ne!(key, super::key(&photo(1), &model));
+        let gamma = RemoveBgOptions { mask_gamma: Some(2.0), ..Default::default() };
+        assert_ne!(key, super::key(&photo(1), &gamma));
+    }
+
+    #[test]
+    fn stored_masks_load_back_at_their_size() {
+        let cache = MaskCacheOptions::new(temp_dir("load"));
+        assert!(load(&cache, "0".repeat(64).as_str(), (32, 24)).is_none());
+        let key = key(&photo(1), &RemoveBgOptions::default());
+        store(&cache, &key, &mask(40000));
+        assert_eq!(load(&cache, &key, (32, 24)), Some(mask(40000)));
+        assert!(load(&cache, &key, (24, 32)).is_none());
+        assert_eq!(stats(&cache.dir).unwrap().entries, 1);
+        assert_eq!(clear(&cache.dir).unwrap().entries, 1);
+        assert_eq!(stats(&cache.dir).unwrap(), CacheStats::default());
+        std::fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[test]
+    fn least_recently_used_masks_are_evicted() {
+        let dir = temp_dir("evict");
+        let unlimited = MaskCacheOptions { dir: dir.clone(), max_size: None };
+        let keys: Vec<String> = (0..3).map(|seed| key(&photo(seed), &RemoveBgOptions::default())).collect();
+        for (age, key) in keys.iter().enumerate() {
+            store(&unlimited, key, &mask(age as u16 * 1000));
+            // Oldest first, whatever the file system's timestamp resolution
+            let modified = SystemTime::now() - Duration::from_secs(100 - age as u64 * 10);
+            File::options().write(true).open(entry_path(&dir, key)).unwrap().set_modified(modified).unwrap();
+        }
+        // Using the oldest makes it the most recent
+        assert!(load(&unlimited, &keys[0], (32, 24)).is_some());
+
+        let entry = stats(&dir).unwrap().bytes / 3;
+        let limited = MaskCacheOptions { dir: dir.clone(), max_size: Some(entry * 7 / 2) };
+        store(&limited, &key(&photo(3), &RemoveBgOptions::default()), &mask(3000));
+        assert!(!entry_path(&dir, &keys[1]).exists());
+        assert!(entry_path(&dir, &keys[0]).exists() && entry_path(&dir, &keys[2]).exists());
+        assert_eq!(stats(&dir).unwrap().entries, 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
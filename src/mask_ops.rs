@@ -0,0 +1,420 @@
+//! Mask post-processing as an ordered list of operators.
+//!
+//! [`RemoveBgOptions::mask_ops`] lists the [`MaskOp`]s applied one after
+//! another to the full-resolution mask. Without it, the refinement options
+//! compile down to the operators they have always stood for (see
+//! [`from_options`]), so both paths share one implementation.
+//!
+//! Operators on values run on a floating-point copy of the mask; region
+//! operators (`fill-holes`, `keep-largest`) run on an 8-bit copy, as region
+//! analysis only needs to tell subject from background, and only the pixels
+//! they change are written back.
+
+use crate::core::Channel;
+use crate::error::{RemoveBgError, Result};
+use crate::matte;
+use crate::options::{MaskOp, RemoveBgOptions};
+use image::{GrayImage, ImageBuffer, Luma};
+use std::collections::VecDeque;
+
+/// The operators the refinement options of `options` stand for: the largest
+/// regions kept, then holes filled. `mask_levels` and `mask_gamma` aren't
+/// included, as they apply to the model's mask before it is scaled to the
+/// image.
+pub(crate) fn from_options(options: &RemoveBgOptions) -> Vec<MaskOp> {
+    let mut ops = Vec::new();
+    if options.keep_largest_component {
+        ops.push(MaskOp::KeepLargest { count: options.keep_components.max(1), gap: options.component_gap });
+    }
+    if options.fill_holes {
+        ops.push(MaskOp::FillHoles(options.max_hole_size));
+    }
+    ops
+}
+
+/// Parse a comma-separated list of operators, like `dilate=3,feather=2.5`.
+pub(crate) fn parse_list(s: &str) -> std::result::Result<Vec<MaskOp>, String> {
+    s.split(',').map(str::parse).collect()
+}
+
+/// Check the parameters of every operator in `ops`.
+pub(crate) fn check(ops: &[MaskOp]) -> Result<()> {
+    for op in ops {
+        op.validate()
+            .map_err(|e| RemoveBgError::ProcessingError(format!("Invalid mask operation '{}': {}", op, e)))?;
+    }
+    Ok(())
+}
+
+/// Apply `ops` to `mask` in order.
+pub(crate) fn apply<S: Channel>(mask: &mut ImageBuffer<Luma<S>, Vec<S>>, ops: &[MaskOp]) -> Result<()> {
+    check(ops)?;
+    let (width, height) = (mask.width() as usize, mask.height() as usize);
+    let mut rest = ops;
+    while let Some(first) = rest.first() {
+        let regional = is_regional(first);
+        let (run, tail) = rest.split_at(rest.iter().take_while(|op| is_regional(op) == regional).count());
+        if regional {
+            apply_regional(mask, run);
+        } else {
+            let mut values: Vec<f32> = mask.iter().map(|value| value.to_unit()).collect();
+            for op in run {
+                apply_to_values(&mut values, width, height, op);
+            }
+            for (value, new) in mask.iter_mut().zip(values) {
+                *value = S::from_unit(new);
+            }
+        }
+        rest = tail;
+    }
+    Ok(())
+}
+
+/// Whether `op` works on regions of the binarized mask.
+fn is_regional(op: &MaskOp) -> bool {
+    matches!(op, MaskOp::FillHoles(_) | MaskOp::KeepLargest { .. })
+}
+
+/// Apply region operators to an 8-bit copy of `mask` and write back the
+/// pixels they changed, so untouched pixels keep their full precision.
+fn apply_regional<S: Channel>(mask: &mut ImageBuffer<Luma<S>, Vec<S>>, ops: &[MaskOp]) {
+    let original = GrayImage::from_fn(mask.width(), mask.height(), |x, y| {
+        Luma([u8::from_unit(mask.get_pixel(x, y)[0].to_unit())])
+    });
+    let mut refined = original.clone();
+    for op in ops {
+        match *op {
+            MaskOp::KeepLargest { count, gap } => matte::keep_largest_components(&mut refined, count.max(1), gap),
+            MaskOp::FillHoles(max_size) => matte::fill_holes(&mut refined, max_size),
+            _ => unreachable!("{} is not a region operator", op),
+        }
+    }
+    for ((alpha, &before), &after) in mask.iter_mut().zip(original.iter()).zip(refined.iter()) {
+        if before != after {
+            *alpha = S::from_unit(after.to_unit());
+        }
+    }
+}
+
+/// Apply an operator on values to a row-major mask of 0.0-1.0 values.
+fn apply_to_values(values: &mut [f32], width: usize, height: usize, op: &MaskOp) {
+    match *op {
+        MaskOp::Threshold(level) => threshold(values, level),
+        MaskOp::Levels(low, high) => levels(values, low, high),
+        MaskOp::Gamma(g) => gamma(values, g),
+        MaskOp::Erode(radius) => erode(values, width, height, radius as usize),
+        MaskOp::Dilate(radius) => dilate(values, width, height, radius as usize),
+        MaskOp::Feather(sigma) => feather(values, width, height, sigma),
+        MaskOp::FillHoles(_) | MaskOp::KeepLargest { .. } => unreachable!("{} is a region operator", op),
+    }
+}
+
+/// Set values at or above `level` to 1 and the others to 0.
+fn threshold(values: &mut [f32], level: f32) {
+    for value in values {
+        *value = (*value >= level) as u8 as f32;
+    }
+}
+
+/// Linearly remap `low..high` to 0-1, clamping; with `low == high`, a
+/// threshold at that value.
+fn levels(values: &mut [f32], low: f32, high: f32) {
+    if high <= low {
+        return threshold(values, low);
+    }
+    for value in values {
+        *value = ((*value - low) / (high - low)).clamp(0.0, 1.0);
+    }
+}
+
+/// Raise each value to the power `gamma`.
+fn gamma(values: &mut [f32], gamma: f32) {
+    if gamma != 1.0 {
+        for value in values {
+            *value = value.powf(gamma);
+        }
+    }
+}
+
+/// Replace each value with the smallest within a square of `radius`.
+fn erode(values: &mut [f32], width: usize, height: usize, radius: usize) {
+    square_extremum(values, width, height, radius, false);
+}
+
+/// Replace each value with the largest within a square of `radius`.
+fn dilate(values: &mut [f32], width: usize, height: usize, radius: usize) {
+    square_extremum(values, width, height, radius, true);
+}
+
+/// Blur the values with a Gaussian of `sigma` pixels; 0 leaves them as they are.
+fn feather(values: &mut [f32], width: usize, height: usize, sigma: f32) {
+    if sigma <= 0.0 || values.is_empty() {
+        return;
+    }
+    let image = ImageBuffer::<Luma<f32>, Vec<f32>>::from_raw(width as u32, height as u32, values.to_vec())
+        .expect("mask values match its size");
+    let blurred = image::imageops::fast_blur(&image, sigma);
+    for (value, new) in values.iter_mut().zip(blurred.into_raw()) {
+        *value = new.clamp(0.0, 1.0);
+    }
+}
+
+/// Minimum or maximum filter over a square of `radius`, as a row pass and a
+/// column pass; the square is clipped at the borders.
+fn square_extremum(values: &mut [f32], width: usize, height: usize, radius: usize, max: bool) {
+    if radius == 0 || values.is_empty() {
+        return;
+    }
+    for row in values.chunks_mut(width) {
+        let filtered = sliding_extremum(row, radius, max);
+        row.copy_from_slice(&filtered);
+    }
+    let mut column = Vec::with_capacity(height);
+    for x in 0..width {
+        column.clear();
+        column.extend((0..height).map(|y| values[y * width + x]));
+        for (y, value) in sliding_extremum(&column, radius, max).into_iter().enumerate() {
+            values[y * width + x] = value;
+        }
+    }
+}
+
+/// Minimum or maximum of the window of `radius` around each value of
+/// `line`, in linear time: the queue holds the indices of the window's
+/// candidates, best first.
+fn sliding_extremum(line: &[f32], radius: usize, max: bool) -> Vec<f32> {
+    let beats = |a: f32, b: f32| if max { a >= b } else { a <= b };
+    let mut candidates: VecDeque<usize> = VecDeque::new();
+    let mut next = 0;
+    let mut output = Vec::with_capacity(line.len());
+    for i in 0..line.len() {
+        while next < line.len() && next <= i + radius {
+            while candidates.back().is_some_and(|&last| beats(line[next], line[last])) {
+                candidates.pop_back();
+            }
+            candidates.push_back(next);
+            next += 1;
+        }
+        while candidates.front().is_some_and(|&first| first + radius < i) {
+            candidates.pop_front();
+        }
+        output.push(line[candidates[0]]);
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Gray16Image;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    /// A 7x7 mask with a centered 3x3 opaque square.
+    fn square() -> Vec<f32> {
+        centered_square(7, 3)
+    }
+
+    /// A `size`x`size` mask with a centered opaque square of `side`.
+    fn centered_square(size: usize, side: usize) -> Vec<f32> {
+        let inside = (size - side) / 2..(size + side) / 2;
+        (0..size * size).map(|i| (inside.contains(&(i % size)) && inside.contains(&(i / size))) as u8 as f32).collect()
+    }
+
+    fn count_opaque(values: &[f32]) -> usize {
+        values.iter().filter(|&&v| v == 1.0).count()
+    }
+
+    #[test]
+    fn threshold_cuts_at_the_level() {
+        let mut values = vec![0.0, 0.49, 0.5, 0.9];
+        threshold(&mut values, 0.5);
+        assert_eq!(values, [0.0, 0.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn levels_stretch_and_clamp() {
+        let mut values = vec![0.0, 0.25, 0.5, 0.75, 1.0];
+        levels(&mut values, 0.25, 0.75);
+        assert_eq!(values, [0.0, 0.0, 0.5, 1.0, 1.0]);
+        // Equal bounds are a hard cut
+        let mut values = vec![0.2, 0.6];
+        levels(&mut values, 0.5, 0.5);
+        assert_eq!(values, [0.0, 1.0]);
+    }
+
+    #[test]
+    fn gamma_raises_to_the_power() {
+        let mut values = vec![0.0, 0.25, 1.0];
+        gamma(&mut values, 0.5);
+        assert!(close(values[0], 0.0) && close(values[1], 0.5) && close(values[2], 1.0), "{:?}", values);
+    }
+
+    #[test]
+    fn erode_and_dilate_use_a_square() {
+        let mut eroded = square();
+        erode(&mut eroded, 7, 7, 1);
+        assert_eq!(count_opaque(&eroded), 1);
+        assert_eq!(eroded[3 * 7 + 3], 1.0);
+
+        let mut dilated = square();
+        dilate(&mut dilated, 7, 7, 1);
+        assert_eq!(count_opaque(&dilated), 25);
+        // Clipped at the border instead of wrapping
+        dilate(&mut dilated, 7, 7, 5);
+        assert_eq!(count_opaque(&dilated), 49);
+
+        // Soft values are kept, not binarized
+        let mut soft = vec![0.2, 0.7, 0.4];
+        dilate(&mut soft, 3, 1, 1);
+        assert_eq!(soft, [0.7, 0.7, 0.7]);
+        let mut soft = vec![0.2, 0.7, 0.4];
+        erode(&mut soft, 3, 1, 1);
+        assert_eq!(soft, [0.2, 0.2, 0.4]);
+    }
+
+    #[test]
+    fn sliding_extremum_matches_a_naive_window() {
+        let line: Vec<f32> = (0..40).map(|i| ((i * 37) % 11) as f32).collect();
+        for radius in [1, 2, 5, 50] {
+            for max in [false, true] {
+                let expected: Vec<f32> = (0..line.len())
+                    .map(|i| {
+                        let window = &line[i.saturating_sub(radius)..(i + radius + 1).min(line.len())];
+                        let pick = if max { f32::max } else { f32::min };
+                        window.iter().copied().reduce(pick).unwrap()
+                    })
+                    .collect();
+                assert_eq!(sliding_extremum(&line, radius, max), expected, "radius {} max {}", radius, max);
+            }
+        }
+    }
+
+    #[test]
+    fn feather_softens_edges_only() {
+        let mut values = centered_square(21, 11);
+        feather(&mut values, 21, 21, 1.0);
+        let at = |x: usize, y: usize| values[y * 21 + x];
+        assert!(at(10, 10) > 0.99, "{:?}", values);
+        // Just outside the square's edge (x = 5) and far from it
+        assert!(at(4, 10) > 0.0 && at(4, 10) < 0.5, "{:?}", values);
+        assert!(at(0, 0) < 0.01, "{:?}", values);
+        assert!(values.iter().all(|v| (0.0..=1.0).contains(v)));
+
+        let mut unchanged = square();
+        feather(&mut unchanged, 7, 7, 0.0);
+        assert_eq!(unchanged, square());
+    }
+
+    #[test]
+    fn region_operators_match_the_refinement_options() {
+        // Two blobs and a hole in the larger one
+        let mut mask = Gray16Image::from_fn(20, 10, |x, y| {
+            let big = (1..10).contains(&x) && (1..9).contains(&y) && !(x == 5 && y == 4);
+            let small = (14..17).contains(&x) && (3..6).contains(&y);
+            Luma([if big || small { 60000 } else { 100 }])
+        });
+        let options = RemoveBgOptions { keep_largest_component: true, fill_holes: true, ..Default::default() };
+        let ops = from_options(&options);
+        assert_eq!(ops, [MaskOp::KeepLargest { count: 1, gap: 0 }, MaskOp::FillHoles(None)]);
+        apply(&mut mask, &ops).unwrap();
+        assert_eq!(mask.get_pixel(15, 4)[0], 0);
+        assert!(mask.get_pixel(5, 4)[0] > 32768);
+        // Pixels the operators leave alone keep their 16-bit values
+        assert_eq!(mask.get_pixel(2, 2)[0], 60000);
+        assert_eq!(mask.get_pixel(0, 0)[0], 100);
+
+        assert!(from_options(&RemoveBgOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn order_matters() {
+        let run = |ops: &str| {
+            let mut mask = ImageBuffer::<Luma<f32>, Vec<f32>>::from_raw(7, 7, square()).unwrap();
+            apply(&mut mask, &parse_list(ops).unwrap()).unwrap();
+            mask.into_raw()
+        };
+        // Feathering after the threshold leaves a soft edge, before it a hard one
+        let soft = run("dilate=1,feather=1");
+        let hard = run("feather=1,threshold=0.5");
+        assert!(soft.iter().any(|&v| v > 0.0 && v < 1.0));
+        assert!(hard.iter().all(|&v| v == 0.0 || v == 1.0));
+        // Opening removes a speck that closing keeps
+        let speckled = |ops: &str| {
+            let mut values = square();
+            values[0] = 1.0;
+            let mut mask = ImageBuffer::<Luma<f32>, Vec<f32>>::from_raw(7, 7, values).unwrap();
+            apply(&mut mask, &parse_list(ops).unwrap()).unwrap();
+            mask.into_raw()[0]
+        };
+        assert_eq!((speckled("dilate=1,erode=1"), speckled("erode=1,dilate=1")), (1.0, 0.0));
+    }
+
+    #[test]
+    fn invalid_parameters_are_rejected_when_applied() {
+        let mut mask = ImageBuffer::<Luma<f32>, Vec<f32>>::new(2, 2);
+        let error = apply(&mut mask, &[MaskOp::Gamma(0.0)]).unwrap_err();
+        assert_eq!(error.to_string(), "Failed to process image: Invalid mask operation 'gamma=0': gamma must be above 0, got 0");
+        assert!(apply(&mut mask, &[MaskOp::KeepLargest { count: 0, gap: 0 }]).is_err());
+    }
+
+    #[test]
+    fn parses_the_command_line_syntax() {
+        assert_eq!(
+            parse_list("dilate=3,feather=2.5,threshold=0.5").unwrap(),
+            [MaskOp::Dilate(3), MaskOp::Feather(2.5), MaskOp::Threshold(0.5)]
+        );
+        assert_eq!(
+            parse_list(" Levels = 0.1:0.9 ,gamma=0.7,erode=0").unwrap(),
+            [MaskOp::Levels(0.1, 0.9), MaskOp::Gamma(0.7), MaskOp::Erode(0)]
+        );
+        assert_eq!(
+            parse_list("fill-holes,fill-holes=500,keep-largest,keep-largest=2,keep-largest=2:10").unwrap(),
+            [
+                MaskOp::FillHoles(None),
+                MaskOp::FillHoles(Some(500)),
+                MaskOp::KeepLargest { count: 1, gap: 0 },
+                MaskOp::KeepLargest { count: 2, gap: 0 },
+                MaskOp::KeepLargest { count: 2, gap: 10 },
+            ]
+        );
+        // Display writes what parses back
+        let ops = parse_list("threshold=0.5,levels=0.2:0.8,feather=1.5,fill-holes,fill-holes=9,keep-largest,keep-largest=3:4").unwrap();
+        let written: Vec<String> = ops.iter().map(|op| op.to_string()).collect();
+        assert_eq!(parse_list(&written.join(",")).unwrap(), ops);
+    }
+
+    #[test]
+    fn rejects_unknown_operators() {
+        let error = parse_list("dilate=3,blur=2").unwrap_err();
+        assert!(error.starts_with("unknown mask operation 'blur'"), "{}", error);
+        assert!(parse_list("dilate=3,,feather=2").unwrap_err().starts_with("empty mask operation"));
+    }
+
+    #[test]
+    fn rejects_bad_parameters() {
+        for s in [
+            "threshold",
+            "threshold=half",
+            "threshold=1.5",
+            "levels=0.5",
+            "levels=0.9:0.1",
+            "gamma=0",
+            "gamma=-1",
+            "gamma=inf",
+            "erode=-1",
+            "erode=1.5",
+            "dilate",
+            "feather=-2",
+            "feather=NaN",
+            "fill-holes=big",
+            "keep-largest=0",
+            "keep-largest=2:x",
+        ] {
+            let error = s.parse::<MaskOp>().unwrap_err();
+            assert!(error.starts_with(&format!("invalid mask operation '{}'", s)), "{}: {}", s, error);
+        }
+    }
+}
@@ -0,0 +1,813 @@
+//! Mask refinement between inference and compositing.
+//!
+//! These steps work on the full-resolution mask and clean up typical model
+//! artifacts before the mask is applied to the image.
+
+use crate::core::{Channel, Gray16Image};
+use crate::error::{RemoveBgError, Result};
+use crate::options::{GuidedFilterOptions, RemoveBgOptions, TrimapOptions};
+use image::{DynamicImage, GrayImage, ImageBuffer, Luma, Pixel, Rgba};
+
+/// Alpha above which a mask pixel counts as foreground for region analysis.
+const FOREGROUND_THRESHOLD: u8 = 16;
+
+/// Alpha below which a pixel enclosed by the subject counts as a hole.
+const HOLE_THRESHOLD: u8 = 128;
+
+/// Alpha at or above which a pixel's color is taken as pure subject color.
+const OPAQUE_THRESHOLD: u8 = 240;
+
+/// Check `options.mask_levels` and `options.mask_gamma`.
+pub(crate) fn check_mask_curve(options: &RemoveBgOptions) -> Result<()> {
+    if let Some((low, high)) = options.mask_levels {
+        if !(0.0 <= low && low <= high && high <= 1.0) {
+            return Err(RemoveBgError::ProcessingError(format!(
+                "Mask levels must satisfy 0 <= low <= high <= 1, got {} and {}",
+                low, high
+            )));
+        }
+    }
+    if let Some(gamma) = options.mask_gamma {
+        if !(gamma.is_finite() && gamma > 0.0) {
+            return Err(RemoveBgError::ProcessingError(format!("Mask gamma must be above 0, got {}", gamma)));
+        }
+    }
+    Ok(())
+}
+
+/// Whether `options` adjust the model's mask with `mask_levels` or
+/// `mask_gamma`; an explicit `mask_ops` list replaces them.
+pub(crate) fn has_mask_curve(options: &RemoveBgOptions) -> bool {
+    (options.mask_levels.is_some() || options.mask_gamma.is_some()) && options.mask_ops.is_empty()
+}
+
+/// Apply `options.mask_levels`, then `options.mask_gamma`, to a mask value
+/// in 0.0-1.0. Levels of (0, 1) and a gamma of 1 leave it unchanged, as does
+/// a non-empty `options.mask_ops`.
+pub(crate) fn mask_curve(alpha: f32, options: &RemoveBgOptions) -> f32 {
+    if !options.mask_ops.is_empty() {
+        return alpha;
+    }
+    let alpha = match options.mask_levels {
+        Some((low, high)) if high > low => ((alpha - low) / (high - low)).clamp(0.0, 1.0),
+        Some((cut, _)) => (alpha >= cut) as u8 as f32,
+        None => alpha,
+    };
+    match options.mask_gamma {
+        Some(gamma) if gamma != 1.0 => alpha.powf(gamma),
+        _ => alpha,
+    }
+}
+
+/// Trimap level of definite background.
+const TRIMAP_BACKGROUND: u8 = 0;
+
+/// Trimap level of the unknown band.
+const TRIMAP_UNKNOWN: u8 = 128;
+
+/// Trimap level of definite foreground.
+const TRIMAP_FOREGROUND: u8 = 255;
+
+/// Check the thresholds of `options`.
+pub(crate) fn check_trimap(options: &TrimapOptions) -> Result<()> {
+    if !(0.0 <= options.low && options.low < options.high && options.high <= 1.0) {
+        return Err(RemoveBgError::ProcessingError(format!(
+            "Trimap thresholds must satisfy 0 <= low < high <= 1, got {} and {}",
+            options.low, options.high
+        )));
+    }
+    Ok(())
+}
+
+/// Split a soft mask into a trimap.
+///
+/// Pixels at or above `options.high` are definite foreground and pixels at
+/// or below `options.low` definite background; both regions are then eroded
+/// by `options.erode_radius` (Euclidean) along their borders with the other
+/// regions, and everything else is unknown. The image edge doesn't erode, so
+/// a mask that is entirely foreground or background gives a trimap without
+/// an unknown band.
+pub(crate) fn trimap(mask: &Gray16Image, options: &TrimapOptions) -> GrayImage {
+    let (width, height) = (mask.width() as usize, mask.height() as usize);
+    let low = (options.low * 65535.0).round() as u16;
+    let high = (options.high * 65535.0).round() as u16;
+    let radius = options.erode_radius as f32;
+    // A pixel survives erosion if every pixel outside its region is farther away than the radius
+    let eroded = |inside: &dyn Fn(u16) -> bool| -> Vec<bool> {
+        let outside: Vec<bool> = mask.iter().map(|&value| !inside(value)).collect();
+        let distances = distance_transform(&outside, width, height);
+        mask.iter().zip(distances).map(|(&value, distance)| inside(value) && distance > radius).collect()
+    };
+    let foreground = eroded(&|value| value >= high);
+    let background = eroded(&|value| value <= low);
+
+    let levels = foreground.iter().zip(&background).map(|(&fg, &bg)| match (fg, bg) {
+        (true, _) => TRIMAP_FOREGROUND,
+        (_, true) => TRIMAP_BACKGROUND,
+        _ => TRIMAP_UNKNOWN,
+    });
+    GrayImage::from_raw(mask.width(), mask.height(), levels.collect()).expect("one level per mask pixel")
+}
+
+/// Snap the edges of `mask` to edges in `guide`, an image of the same size,
+/// with a guided filter.
+///
+/// Within each window the mask is fitted as a linear function of the guide's
+/// luminance, so where the mask blurs across a sharp image edge, the output
+/// follows the image edge. The fits are computed on a grid subsampled by a
+/// quarter of the radius and interpolated back to full resolution (He and
+/// Sun, "Fast Guided Filter"); all steps are box filters or linear passes, so
+/// the cost doesn't depend on the radius.
+pub(crate) fn guided_filter<S: Channel>(
+    mask: &mut ImageBuffer<Luma<S>, Vec<S>>,
+    guide: &DynamicImage,
+    options: &GuidedFilterOptions,
+) -> Result<()> {
+    if options.radius == 0 {
+        return Err(RemoveBgError::ProcessingError("Guided filter radius must be at least 1".into()));
+    }
+    if !(options.epsilon.is_finite() && options.epsilon > 0.0) {
+        return Err(RemoveBgError::ProcessingError(format!(
+            "Guided filter epsilon must be above 0, got {}",
+            options.epsilon
+        )));
+    }
+    let (width, height) = (mask.width() as usize, mask.height() as usize);
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+    let step = (options.radius as usize / 4).max(1);
+    let radius = options.radius as usize / step;
+    let guide = guide.to_luma32f();
+    let (low_width, low_height) = (width.div_ceil(step), height.div_ceil(step));
+    let luma = downsample(guide.as_raw(), width, height, step, |l| l);
+    let alpha = downsample(mask.as_raw(), width, height, step, |p| p.to_unit());
+
+    // Fit alpha ≈ a * luma + b in each window
+    let coefficients = box_filter(
+        low_width,
+        low_height,
+        radius,
+        |i| [luma[i], alpha[i], luma[i] * luma[i], luma[i] * alpha[i]],
+        |[mean_l, mean_p, mean_ll, mean_lp]| {
+            let variance = mean_ll - mean_l * mean_l;
+            let covariance = mean_lp - mean_l * mean_p;
+            let a = covariance / (variance + options.epsilon);
+            [a, mean_p - a * mean_l]
+        },
+    );
+    // Average the fits of all windows covering each pixel
+    let coefficients = box_filter(low_width, low_height, radius, |i| coefficients[i], |fit| fit);
+
+    // Apply the averaged fits to the full-resolution luminance, two grid
+    // rows at a time
+    let columns: Vec<(usize, usize, f32)> = (0..width).map(|x| grid_position(x, step, low_width)).collect();
+    let widen = |row: usize| -> Vec<[f32; 2]> {
+        let row = &coefficients[row * low_width..][..low_width];
+        columns.iter().map(|&(left, right, fx)| lerp(row[left], row[right], fx)).collect()
+    };
+    let mut grid_rows = (usize::MAX, Vec::new(), Vec::new());
+    for (y, (row, luma)) in mask.chunks_exact_mut(width).zip(guide.chunks_exact(width)).enumerate() {
+        let (top, bottom, fy) = grid_position(y, step, low_height);
+        if grid_rows.0 != top {
+            grid_rows = (top, widen(top), widen(bottom));
+        }
+        for ((alpha, &l), (&upper, &lower)) in row.iter_mut().zip(luma).zip(grid_rows.1.iter().zip(&grid_rows.2)) {
+            let [a, b] = lerp(upper, lower, fy);
+            *alpha = S::from_unit(a * l + b);
+        }
+    }
+    Ok(())
+}
+
+/// Average `values` over `step`×`step` blocks, converting them with `value`.
+/// Blocks at the right and bottom edges may be smaller.
+fn downsample<T: Copy>(values: &[T], width: usize, height: usize, step: usize, value: impl Fn(T) -> f32) -> Vec<f32> {
+    let low_width = width.div_ceil(step);
+    let mut sums = vec![0.0f32; low_width * height.div_ceil(step)];
+    for (y, row) in values.chunks_exact(width).enumerate() {
+        let sums = &mut sums[y / step * low_width..][..low_width];
+        for (sum, block) in sums.iter_mut().zip(row.chunks(step)) {
+            *sum += block.iter().map(|&v| value(v)).sum::<f32>();
+        }
+    }
+    let extent = |i: usize, len: usize| step.min(len - i * step) as f32;
+    for (i, sum) in sums.iter_mut().enumerate() {
+        *sum /= extent(i % low_width, width) * extent(i / low_width, height);
+    }
+    sums
+}
+
+/// Linear interpolation between `a` and `b`.
+fn lerp(a: [f32; 2], b: [f32; 2], t: f32) -> [f32; 2] {
+    [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t]
+}
+
+/// The two grid points of a subsampled grid around full-resolution position
+/// `i` and the interpolation weight of the second.
+fn grid_position(i: usize, step: usize, len: usize) -> (usize, usize, f32) {
+    let position = ((i as f32 + 0.5) / step as f32 - 0.5).clamp(0.0, (len - 1) as f32);
+    let lower = position as usize;
+    (lower, (lower + 1).min(len - 1), position - lower as f32)
+}
+
+/// Mean of `value` over a square window of the given radius around each
+/// pixel, clipped to the image, passed through `finish`.
+///
+/// Works row by row with running column sums, so apart from the output it
+/// only needs memory for one row of sums.
+fn box_filter<const N: usize, T>(
+    width: usize,
+    height: usize,
+    radius: usize,
+    value: impl Fn(usize) -> [f32; N],
+    finish: impl Fn([f32; N]) -> T,
+) -> Vec<T> {
+    let mut output = Vec::with_capacity(width * height);
+    let mut columns = vec![[0.0f64; N]; width];
+    let add_row = |columns: &mut [[f64; N]], y: usize, sign: f64| {
+        for (x, column) in columns.iter_mut().enumerate() {
+            let value = value(y * width + x);
+            for c in 0..N {
+                column[c] += sign * value[c] as f64;
+            }
+        }
+    };
+    // Reciprocal window lengths, clipped at the image border
+    let span = |i: usize, len: usize| 1.0 / ((i + radius).min(len - 1) - i.saturating_sub(radius) + 1) as f64;
+    let column_spans: Vec<f64> = (0..width).map(|x| span(x, width)).collect();
+
+    for y in 0..radius.min(height) {
+        add_row(&mut columns, y, 1.0);
+    }
+    for y in 0..height {
+        if y + radius < height {
+            add_row(&mut columns, y + radius, 1.0);
+        }
+        if y > radius {
+            add_row(&mut columns, y - radius - 1, -1.0);
+        }
+        let rows = span(y, height);
+
+        let mut sum = [0.0f64; N];
+        for column in &columns[..radius.min(width)] {
+            for c in 0..N {
+                sum[c] += column[c];
+            }
+        }
+        for x in 0..width {
+            if x + radius < width {
+                for c in 0..N {
+                    sum[c] += columns[x + radius][c];
+                }
+            }
+            if x > radius {
+                for c in 0..N {
+                    sum[c] -= columns[x - radius - 1][c];
+                }
+            }
+            let scale = rows * column_spans[x];
+            output.push(finish(std::array::from_fn(|c| (sum[c] * scale) as f32)));
+        }
+    }
+    output
+}
+
+/// Binarize a mask at [`FOREGROUND_THRESHOLD`].
+fn foreground(mask: &GrayImage) -> Vec<bool> {
+    mask.as_raw().iter().map(|&alpha| alpha > FOREGROUND_THRESHOLD).collect()
+}
+
+/// Keep the `keep` largest foreground regions of `mask` and clear the others.
+///
+/// Regions are 8-connected. Regions separated by at most `gap` pixels are
+/// treated as one, so a subject split by a thin occlusion isn't cut apart.
+/// Alpha values inside kept regions are left untouched.
+pub(crate) fn keep_largest_components(mask: &mut GrayImage, keep: usize, gap: u32) {
+    let (width, height) = (mask.width() as usize, mask.height() as usize);
+    let foreground = foreground(mask);
+
+    // Growing every region by half the gap joins regions closer than the gap
+    let grown = match gap {
+        0 => foreground.clone(),
+        gap => dilate(&foreground, width, height, gap.div_ceil(2) as usize),
+    };
+    let (labels, count) = label_components(&grown, width, height, true);
+    if count <= keep {
+        return;
+    }
+
+    // Rank regions by their original foreground area, not the grown one
+    let mut sizes = vec![0usize; count];
+    for (&label, &is_foreground) in labels.iter().zip(&foreground) {
+        if is_foreground {
+            sizes[label as usize - 1] += 1;
+        }
+    }
+    let mut order: Vec<usize> = (0..count).collect();
+    order.sort_by_key(|&index| std::cmp::Reverse(sizes[index]));
+    let mut kept = vec![false; count + 1];
+    for &index in order.iter().take(keep) {
+        kept[index + 1] = true;
+    }
+
+    for ((alpha, &label), &is_foreground) in mask.iter_mut().zip(&labels).zip(&foreground) {
+        if is_foreground && !kept[label as usize] {
+            *alpha = 0;
+        }
+    }
+}
+
+/// Fill see-through patches enclosed by the subject.
+///
+/// Regions below [`HOLE_THRESHOLD`] that can't be reached from the image
+/// border are holes. Their alpha is raised to the mean alpha of the pixels
+/// surrounding them. Holes larger than `max_size` pixels are kept, since they
+/// are usually real gaps like the inside of a mug handle.
+pub(crate) fn fill_holes(mask: &mut GrayImage, max_size: Option<usize>) {
+    let (width, height) = (mask.width() as usize, mask.height() as usize);
+    let background: Vec<bool> = mask.as_raw().iter().map(|&alpha| alpha < HOLE_THRESHOLD).collect();
+    // 4-connected background pairs with 8-connected foreground, so a diagonal
+    // line of subject pixels is enough to enclose a hole
+    let (labels, count) = label_components(&background, width, height, false);
+
+    let mut touches_border = vec![false; count + 1];
+    let mut sizes = vec![0usize; count + 1];
+    let mut edge_sum = vec![0u64; count + 1];
+    let mut edge_count = vec![0u64; count + 1];
+    for (index, &label) in labels.iter().enumerate() {
+        if label == 0 {
+            continue;
+        }
+        let label = label as usize;
+        let (x, y) = (index % width, index / width);
+        sizes[label] += 1;
+        if x == 0 || y == 0 || x == width - 1 || y == height - 1 {
+            touches_border[label] = true;
+            continue;
+        }
+        for neighbor in [index - 1, index + 1, index - width, index + width] {
+            if labels[neighbor] == 0 {
+                edge_sum[label] += mask.as_raw()[neighbor] as u64;
+                edge_count[label] += 1;
+            }
+        }
+    }
+
+    let fill: Vec<Option<u8>> = (0..=count)
+        .map(|label| {
+            let is_hole = label > 0
+                && !touches_border[label]
+                && edge_count[label] > 0
+                && max_size.is_none_or(|max| sizes[label] <= max);
+            is_hole.then(|| (edge_sum[label] / edge_count[label]) as u8)
+        })
+        .collect();
+
+    for (alpha, &label) in mask.iter_mut().zip(&labels) {
+        if let Some(fill) = fill[label as usize] {
+            *alpha = (*alpha).max(fill);
+        }
+    }
+}
+
+/// Remove background color bleeding into the semi-transparent edge.
+///
+/// An edge pixel's color is a mix of subject and background, weighted by its
+/// alpha. The local background color is estimated from nearby transparent
+/// pixels (which still hold the original colors) and unmixed from the pixel.
+/// Where no background is nearby, the color of nearby opaque subject pixels is
+/// used instead. `strength` (0-1) blends between the original and corrected
+/// colors.
+pub(crate) fn decontaminate<S: Channel>(image: &mut ImageBuffer<Rgba<S>, Vec<S>>, strength: f32)
+where
+    Rgba<S>: Pixel<Subpixel = S>,
+{
+    let strength = strength.clamp(0.0, 1.0);
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let radius = (width.max(height) / 100).clamp(4, 32);
+    let background = local_mean_color(image, radius, |alpha| alpha <= FOREGROUND_THRESHOLD);
+    let subject = local_mean_color(image, radius, |alpha| alpha >= OPAQUE_THRESHOLD);
+
+    for (index, pixel) in image.pixels_mut().enumerate() {
+        let alpha = pixel[3].to_unit();
+        if alpha == 0.0 || u8::from_unit(alpha) >= OPAQUE_THRESHOLD {
+            continue;
+        }
+        let estimate = match (background[index], subject[index]) {
+            // observed = alpha * subject + (1 - alpha) * background
+            (Some(background), _) => {
+                std::array::from_fn(|c| (pixel[c].to_unit() - (1.0 - alpha) * background[c]) / alpha)
+            }
+            (None, Some(subject)) => subject,
+            (None, None) => continue,
+        };
+        for c in 0..3 {
+            let original = pixel[c].to_unit();
+            pixel[c] = S::from_unit(original + (estimate[c] - original) * strength);
+        }
+    }
+}
+
+/// Mean color (0-1) of the pixels within `radius` whose 8-bit alpha passes `select`.
+///
+/// Returns `None` for pixels with no selected pixel nearby.
+fn local_mean_color<S: Channel>(
+    image: &ImageBuffer<Rgba<S>, Vec<S>>,
+    radius: usize,
+    select: impl Fn(u8) -> bool,
+) -> Vec<Option<[f32; 3]>>
+where
+    Rgba<S>: Pixel<Subpixel = S>,
+{
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let weights: Vec<f64> = image.pixels().map(|p| select(u8::from_unit(p[3].to_unit())) as u8 as f64).collect();
+    let sums: Vec<Vec<f64>> = (0..3)
+        .map(|c| {
+            let values: Vec<f64> =
+                image.pixels().zip(&weights).map(|(p, w)| p[c].to_unit() as f64 * w).collect();
+            box_sum(&values, width, height, radius)
+        })
+        .collect();
+    let counts = box_sum(&weights, width, height, radius);
+
+    (0..counts.len())
+        .map(|i| {
+            (counts[i] >= 0.5).then(|| std::array::from_fn(|c| (sums[c][i] / counts[i]) as f32))
+        })
+        .collect()
+}
+
+/// Sum of `values` over a square window of the given radius around each pixel.
+fn box_sum(values: &[f64], width: usize, height: usize, radius: usize) -> Vec<f64> {
+    let mut horizontal = vec![0.0; values.len()];
+    for y in 0..height {
+        let row = &values[y * width..(y + 1) * width];
+        window_sum(row.iter().copied(), radius, |x, sum| horizontal[y * width + x] = sum);
+    }
+
+    let mut output = vec![0.0; values.len()];
+    for x in 0..width {
+        let column = (0..height).map(|y| horizontal[y * width + x]);
+        window_sum(column, radius, |y, sum| output[y * width + x] = sum);
+    }
+    output
+}
+
+/// Sliding window sums over one line, reported through `set`.
+fn window_sum(line: impl Iterator<Item = f64>, radius: usize, mut set: impl FnMut(usize, f64)) {
+    let mut prefix = vec![0.0];
+    for value in line {
+        prefix.push(prefix.last().unwrap() + value);
+    }
+    let len = prefix.len() - 1;
+    for i in 0..len {
+        let start = i.saturating_sub(radius);
+        let end = (i + radius + 1).min(len);
+        set(i, prefix[end] - prefix[start]);
+    }
+}
+
+/// Label connected regions of `pixels`, 8-connected if `diagonal` is set and
+/// 4-connected otherwise.
+///
+/// Returns one label per pixel (0 for unset pixels, 1..=count for regions) and
+/// the number of regions.
+pub(crate) fn label_components(pixels: &[bool], width: usize, height: usize, diagonal: bool) -> (Vec<u32>, usize) {
+    let mut labels = vec![0u32; pixels.len()];
+    let mut count = 0;
+    let mut stack = Vec::new();
+
+    for start in 0..pixels.len() {
+        if !pixels[start] || labels[start] != 0 {
+            continue;
+        }
+        count += 1;
+        labels[start] = count as u32;
+        stack.push(start);
+
+        while let Some(index) = stack.pop() {
+            let (x, y) = (index % width, index / width);
+            for ny in y.saturating_sub(1)..=(y + 1).min(height - 1) {
+                for nx in x.saturating_sub(1)..=(x + 1).min(width - 1) {
+                    if !diagonal && nx != x && ny != y {
+                        continue;
+                    }
+                    let neighbor = ny * width + nx;
+                    if pixels[neighbor] && labels[neighbor] == 0 {
+                        labels[neighbor] = count as u32;
+                        stack.push(neighbor);
+                    }
+                }
+            }
+        }
+    }
+
+    (labels, count)
+}
+
+/// Dilate a binary image with a square of the given radius.
+fn dilate(pixels: &[bool], width: usize, height: usize, radius: usize) -> Vec<bool> {
+    let values: Vec<f64> = pixels.iter().map(|&set| set as u8 as f64).collect();
+    box_sum(&values, width, height, radius).into_iter().map(|sum| sum >= 0.5).collect()
+}
+
+/// Euclidean distance from each pixel to the nearest set pixel of `pixels`.
+///
+/// Exact two-pass transform (Felzenszwalb and Huttenlocher): squared
+/// distances along each column, then the lower envelope of parabolas along
+/// each row. Without any set pixel, every distance is infinite.
+pub(crate) fn distance_transform(pixels: &[bool], width: usize, height: usize) -> Vec<f32> {
+    let mut squared: Vec<f32> = pixels.iter().map(|&set| if set { 0.0 } else { f32::INFINITY }).collect();
+    let mut line = Vec::new();
+    for x in 0..width {
+        line.clear();
+        line.extend((0..height).map(|y| squared[y * width + x]));
+        for (y, value) in squared_distances(&line).into_iter().enumerate() {
+            squared[y * width + x] = value;
+        }
+    }
+    for row in squared.chunks_mut(width.max(1)) {
+        let distances = squared_distances(row);
+        row.copy_from_slice(&distances);
+    }
+    squared.into_iter().map(f32::sqrt).collect()
+}
+
+/// One-dimensional squared distance transform of sampled values `f`.
+fn squared_distances(f: &[f32]) -> Vec<f32> {
+    let mut output = vec![f32::INFINITY; f.len()];
+    // Parabola vertices of the lower envelope and where each one starts
+    let mut vertices: Vec<usize> = Vec::with_capacity(f.len());
+    let mut starts: Vec<f64> = Vec::with_capacity(f.len());
+    let parabola = |p: usize| f[p] as f64 + (p * p) as f64;
+    for q in (0..f.len()).filter(|&q| f[q].is_finite()) {
+        let intersection = |p: usize| (parabola(q) - parabola(p)) / (2.0 * (q - p) as f64);
+        while let Some(&p) = vertices.last() {
+            if intersection(p) > starts[starts.len() - 1] {
+                break;
+            }
+            vertices.pop();
+            starts.pop();
+        }
+        starts.push(vertices.last().map_or(f64::NEG_INFINITY, |&p| intersection(p)));
+        vertices.push(q);
+    }
+    if vertices.is_empty() {
+        return output;
+    }
+
+    let mut k = 0;
+    for (q, value) in output.iter_mut().enumerate() {
+        while k + 1 < vertices.len() && starts[k + 1] < q as f64 {
+            k += 1;
+        }
+        let p = vertices[k];
+        let offset = q as f64 - p as f64;
+        *value = (offset * offset + f[p] as f64) as f32;
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `width`×`height` mask with `alpha(x, y)` as 8-bit alpha.
+    fn alphas(width: u32, height: u32, alpha: impl Fn(u32, u32) -> u8) -> GrayImage {
+        GrayImage::from_fn(width, height, |x, y| Luma([alpha(x, y)]))
+    }
+
+    /// A 100x100 mask: a 40x40 blob with a soft 4 pixel rim, a 10x10 square
+    /// and 2x2 specks at `specks`.
+    fn blob_and_specks(specks: &[(u32, u32)]) -> GrayImage {
+        alphas(100, 100, |x, y| {
+            let rim = x.abs_diff(30).max(y.abs_diff(30));
+            if rim < 20 {
+                return if rim >= 16 { 60 + (19 - rim as u8) * 40 } else { 255 };
+            }
+            if (70..80).contains(&x) && (70..80).contains(&y) {
+                return 200;
+            }
+            let speck = specks.iter().any(|&(sx, sy)| (sx..sx + 2).contains(&x) && (sy..sy + 2).contains(&y));
+            if speck { 180 } else { 0 }
+        })
+    }
+
+    /// The trimap of a 40x20 mask with `alpha(x)` in every row, as the
+    /// levels of one row.
+    fn trimap_row(alpha: impl Fn(u32) -> u16, options: TrimapOptions) -> Vec<u8> {
+        let mask = Gray16Image::from_fn(40, 20, |x, _| Luma([alpha(x)]));
+        let trimap = trimap(&mask, &options);
+        assert!(trimap.rows().all(|row| row.eq(trimap.rows().next().unwrap())));
+        trimap.rows().next().unwrap().map(|level| level[0]).collect()
+    }
+
+    #[test]
+    fn trimap_bands_are_as_wide_as_the_erosion() {
+        let half = |x: u32| if x < 20 { 65535 } else { 0 };
+        // Eroding both sides by 3 px leaves a 6 px unknown band around x = 20
+        let row = trimap_row(half, TrimapOptions { erode_radius: 3, ..Default::default() });
+        assert_eq!(row, [[255; 17].as_slice(), &[128; 6], &[0; 17]].concat());
+        let row = trimap_row(half, TrimapOptions { erode_radius: 5, ..Default::default() });
+        assert_eq!(row.iter().filter(|&&level| level == 128).count(), 10);
+
+        // Values between the thresholds are unknown even without erosion
+        let steps = |x: u32| if x < 15 { 65535 } else if x < 25 { 32768 } else { 0 };
+        let row = trimap_row(steps, TrimapOptions { low: 0.1, high: 0.9, erode_radius: 0 });
+        assert_eq!(row, [[255; 15].as_slice(), &[128; 10], &[0; 15]].concat());
+
+        // The image edge doesn't erode
+        assert_eq!(trimap_row(|_| 65535, TrimapOptions::default()), [255; 40]);
+        assert_eq!(trimap_row(|_| 0, TrimapOptions::default()), [0; 40]);
+    }
+
+    #[test]
+    fn trimap_thresholds_must_be_ordered() {
+        assert!(check_trimap(&TrimapOptions::default()).is_ok());
+        for (low, high) in [(0.9, 0.1), (0.5, 0.5), (-0.1, 0.5), (0.5, 1.5)] {
+            let error = check_trimap(&TrimapOptions { low, high, ..Default::default() }).unwrap_err();
+            assert!(error.to_string().contains("0 <= low < high <= 1"), "{}", error);
+        }
+    }
+
+    /// The largest step between neighbouring pixels of row `y`, and where it
+    /// ends.
+    fn steepest_step(mask: &GrayImage, y: u32) -> (u8, u32) {
+        (1..mask.width()).map(|x| (mask.get_pixel(x, y)[0].saturating_sub(mask.get_pixel(x - 1, y)[0]), x)).max().unwrap()
+    }
+
+    #[test]
+    fn guided_filter_snaps_the_mask_to_the_image_edge() {
+        // A step edge at x = 50 and a mask ramping over 24 pixels around x = 46
+        let guide = DynamicImage::ImageLuma8(GrayImage::from_fn(100, 40, |x, _| Luma([if x < 50 { 20 } else { 230 }])));
+        let mut mask = alphas(100, 40, |x, _| ((x as f32 - 34.0) / 24.0 * 255.0).clamp(0.0, 255.0) as u8);
+        let (ramp, _) = steepest_step(&mask, 20);
+        assert!(ramp < 20);
+
+        // The mask now steps where the image does, several times as steeply
+        guided_filter(&mut mask, &guide, &GuidedFilterOptions { radius: 8, epsilon: 1e-3 }).unwrap();
+        for y in [0, 20, 39] {
+            let (step, x) = steepest_step(&mask, y);
+            assert_eq!(x, 50, "row {}", y);
+            assert!(step > 5 * ramp, "row {}: {} vs {}", y, step, ramp);
+        }
+    }
+
+    #[test]
+    fn only_the_largest_blob_survives() {
+        let specks = [(5, 90), (60, 5), (90, 40), (55, 60)];
+        let original = blob_and_specks(&specks);
+        let mut mask = original.clone();
+        keep_largest_components(&mut mask, 1, 0);
+
+        let kept = |x: u32, y: u32| x.abs_diff(30).max(y.abs_diff(30)) < 20;
+        for (x, y, alpha) in mask.enumerate_pixels() {
+            let expected = if kept(x, y) { original.get_pixel(x, y)[0] } else { 0 };
+            assert_eq!(alpha[0], expected, "({}, {})", x, y);
+        }
+        // The soft rim is kept as it was
+        assert_eq!((mask.get_pixel(11, 30)[0], mask.get_pixel(13, 30)[0]), (60, 140));
+
+        let mut two = original.clone();
+        keep_largest_components(&mut two, 2, 0);
+        assert_eq!(two.get_pixel(75, 75)[0], 200);
+        assert!(specks.iter().all(|&(x, y)| two.get_pixel(x, y)[0] == 0));
+
+        // Nothing to drop
+        let mut all = original.clone();
+        keep_largest_components(&mut all, 6, 0);
+        assert_eq!(all, original);
+    }
+
+    #[test]
+    fn regions_within_the_gap_are_kept_together() {
+        // A subject split by a 3 pixel wide occlusion, and a distant speck
+        let original = alphas(60, 20, |x, y| match (x, y) {
+            (5..=24, 5..=14) | (28..=35, 5..=14) => 255,
+            (55..=56, 2..=3) => 255,
+            _ => 0,
+        });
+        let mut split = original.clone();
+        keep_largest_components(&mut split, 1, 0);
+        assert_eq!((split.get_pixel(10, 10)[0], split.get_pixel(30, 10)[0]), (255, 0));
+
+        let mut joined = original.clone();
+        keep_largest_components(&mut joined, 1, 4);
+        assert_eq!((joined.get_pixel(10, 10)[0], joined.get_pixel(30, 10)[0]), (255, 255));
+        assert_eq!(joined.get_pixel(55, 2)[0], 0);
+        // The gap itself stays transparent
+        assert_eq!(joined.get_pixel(26, 10)[0], 0);
+    }
+
+    /// A 60x60 donut: an opaque ring of outer radius 25 around a hole of
+    /// radius `hole` with `inside` alpha.
+    fn donut(hole: f32, inside: u8) -> GrayImage {
+        alphas(60, 60, |x, y| {
+            let distance = (x as f32 - 30.0).hypot(y as f32 - 30.0);
+            match distance {
+                d if d < hole => inside,
+                d if d < 25.0 => 255,
+                _ => 0,
+            }
+        })
+    }
+
+    #[test]
+    fn enclosed_holes_are_filled() {
+        for inside in [0, 40, 127] {
+            let mut mask = donut(6.0, inside);
+            fill_holes(&mut mask, None);
+            assert_eq!(mask.get_pixel(30, 30)[0], 255, "{}", inside);
+            assert!(mask.enumerate_pixels().all(|(x, y, alpha)| (alpha[0] == 255) == ((x as f32 - 30.0).hypot(y as f32 - 30.0) < 25.0)));
+        }
+
+        // Half transparent is not a hole
+        let mut solid = donut(6.0, 128);
+        fill_holes(&mut solid, None);
+        assert_eq!(solid, donut(6.0, 128));
+    }
+
+    #[test]
+    fn large_holes_and_open_gaps_are_kept() {
+        // About 113 and 452 pixels
+        let mut small = donut(6.0, 0);
+        fill_holes(&mut small, Some(120));
+        assert_eq!(small.get_pixel(30, 30)[0], 255);
+        let mut large = donut(12.0, 0);
+        fill_holes(&mut large, Some(120));
+        assert_eq!(large, donut(12.0, 0));
+
+        // A channel to the border makes the hole part of the background
+        let mut open = donut(6.0, 0);
+        for x in 30..60 {
+            open.put_pixel(x, 30, Luma([0]));
+        }
+        let expected = open.clone();
+        fill_holes(&mut open, None);
+        assert_eq!(open, expected);
+    }
+
+    const SUBJECT: [f32; 3] = [0.8, 0.2, 0.2];
+    const GREEN_SCREEN: [f32; 3] = [0.0, 1.0, 0.0];
+
+    /// Alpha of an 80x20 cutout: transparent, a 6 pixel ramp from x = 30,
+    /// then opaque.
+    fn ramp(x: u32) -> f32 {
+        ((x as f32 - 29.0) / 7.0).clamp(0.0, 1.0)
+    }
+
+    /// A red subject shot on a green screen, cut out with [`ramp`]: edge
+    /// pixels still hold the mix of both colors.
+    fn green_screen_cutout() -> image::RgbaImage {
+        image::RgbaImage::from_fn(80, 20, |x, _| {
+            let alpha = ramp(x);
+            let color = |c: usize| alpha * SUBJECT[c] + (1.0 - alpha) * GREEN_SCREEN[c];
+            Rgba([u8::from_unit(color(0)), u8::from_unit(color(1)), u8::from_unit(color(2)), u8::from_unit(alpha)])
+        })
+    }
+
+    /// Squared difference between `cutout` over white and the subject alone
+    /// over white, summed over the edge: the green fringe.
+    fn fringe_energy(cutout: &image::RgbaImage) -> f32 {
+        cutout
+            .enumerate_pixels()
+            .map(|(x, _, pixel)| {
+                let alpha = pixel[3].to_unit();
+                (0..3)
+                    .map(|c| {
+                        let composite = alpha * pixel[c].to_unit() + 1.0 - alpha;
+                        let clean = ramp(x) * SUBJECT[c] + 1.0 - ramp(x);
+                        (composite - clean).powi(2)
+                    })
+                    .sum::<f32>()
+            })
+            .sum()
+    }
+
+    #[test]
+    fn decontamination_removes_the_green_fringe() {
+        let cutout = green_screen_cutout();
+        let before = fringe_energy(&cutout);
+        assert!(before > 1.0, "{}", before);
+
+        let mut clean = cutout.clone();
+        decontaminate(&mut clean, 1.0);
+        let after = fringe_energy(&clean);
+        assert!(after < before * 0.05, "{} -> {}", before, after);
+        let mut half = cutout.clone();
+        decontaminate(&mut half, 0.5);
+        assert!((after..before).contains(&fringe_energy(&half)));
+
+        // Alpha, transparent and opaque pixels are left alone
+        for (original, cleaned) in cutout.pixels().zip(clean.pixels()) {
+            assert_eq!(original[3], cleaned[3]);
+            if original[3] == 0 || original[3] == 255 {
+                assert_eq!(original, cleaned);
+            }
+        }
+        let mut untouched = cutout.clone();
+        decontaminate(&mut untouched, 0.0);
+        assert_eq!(untouched, cutout);
+    }
+}
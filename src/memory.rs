@@ -0,0 +1,242 @@
+//! Memory estimates for processing one image, and the
+//! [`max_memory_bytes`](crate::RemoveBgOptions::max_memory_bytes) budget.
+//!
+//! A worker handed one huge image shouldn't be killed for running out of
+//! memory, taking its other jobs with it. The largest buffers processing
+//! allocates all follow from the image's dimensions and color type, which a
+//! decoder reads from the header: the decoded input, the full-resolution
+//! mask, the RGBA output and the encoder's copy of it. They are added up
+//! before the input is decoded, and again for the decoded image, which is
+//! how camera RAW and HEIF inputs and later pages are checked. An image over
+//! budget is estimated again with its mask upscaled in bands of rows, as
+//! panoramas always are, and refused with
+//! [`RemoveBgError::MemoryBudgetExceeded`] only if that is still too much.
+//!
+//! The estimate leaves out the model's tensors and session, refinement
+//! scratch buffers and anything else not sized by the image, so a budget
+//! should leave room for them; the report's
+//! [`memory_estimate`](crate::RemovalReport::memory_estimate) helps tune it.
+
+use crate::core::limited_size;
+use crate::disk_space;
+use crate::error::{RemoveBgError, Result, Stage};
+use crate::options::RemoveBgOptions;
+use crate::output::OutputFormat;
+use crate::report::MemoryEstimate;
+use crate::upscale;
+use image::{ColorType, ImageDecoder, ImageReader};
+use std::io::{BufRead, Seek, SeekFrom};
+
+/// Estimate the memory processing a `width`x`height` input decoded as
+/// `color` takes with `options`.
+///
+/// The mask is upscaled in one piece unless the image is large enough to be
+/// banded anyway, or a budget in `options.max_memory_bytes` needs it to be.
+/// The encoder's share assumes `options.format`, or PNG without it.
+///
+/// # Examples
+/// ```
+/// use removebg::{memory, RemoveBgOptions};
+///
+/// let estimate = memory::estimate((1000, 1000), image::ColorType::Rgba8, &RemoveBgOptions::default());
+/// assert_eq!(estimate.output_bytes, 4_000_000);
+/// assert!(!estimate.banded);
+/// ```
+pub fn estimate(dimensions: (u32, u32), color: ColorType, options: &RemoveBgOptions) -> MemoryEstimate {
+    let whole = estimate_with(dimensions, color, options, false);
+    match options.max_memory_bytes {
+        Some(budget) if whole.total_bytes > budget && !whole.banded => estimate_with(dimensions, color, options, true),
+        _ => whole,
+    }
+}
+
+/// [`estimate`], failing with `MemoryBudgetExceeded` if the estimate is
+/// over `options.max_memory_bytes`.
+pub(crate) fn check(dimensions: (u32, u32), color: ColorType, options: &RemoveBgOptions) -> Result<MemoryEstimate> {
+    let estimate = estimate(dimensions, color, options);
+    match options.max_memory_bytes {
+        Some(budget) if estimate.total_bytes > budget => {
+            Err(RemoveBgError::MemoryBudgetExceeded { estimated: estimate.total_bytes, budget })
+        }
+        _ => Ok(estimate),
+    }
+}
+
+/// Check the image `reader` starts with against `options.max_memory_bytes`
+/// by its header, before it is decoded, and return the reader as it was.
+/// The first image of a multi-page file is the one checked.
+///
+/// Without a budget nothing is read. Headers that can't be read pass: the
+/// decoder reports them, and formats it doesn't read itself are checked
+/// once decoded.
+pub(crate) fn check_input<R: BufRead + Seek>(mut reader: R, options: &RemoveBgOptions) -> Result<R> {
+    if options.max_memory_bytes.is_none() {
+        return Ok(reader);
+    }
+    let start = reader.stream_position()?;
+    let header = ImageReader::new(&mut reader).with_guessed_format().ok().and_then(|mut reader| {
+        reader.limits(options.limits.to_image_limits());
+        reader.into_decoder().ok().map(|decoder| (decoder.dimensions(), decoder.color_type()))
+    });
+    if let Some((dimensions, color)) = header {
+        check(dimensions, color, options).map_err(|e| e.in_stage(Stage::Decode))?;
+    }
+    reader.seek(SeekFrom::Start(start))?;
+    Ok(reader)
+}
+
+fn estimate_with((width, height): (u32, u32), color: ColorType, options: &RemoveBgOptions, force_bands: bool) -> MemoryEstimate {
+    let (working_width, working_height) = limited_size((width, height), options).unwrap_or((width, height));
+    let pixels = working_width as u64 * working_height as u64;
+    let input_pixel = color.bytes_per_pixel() as u64;
+    let high_depth = color.bytes_per_pixel() > color.channel_count();
+    let banded = upscale::band_height((working_width, working_height), force_bands).is_some();
+
+    let mut input_bytes = width as u64 * height as u64 * input_pixel;
+    if (working_width, working_height) != (width, height) {
+        input_bytes += pixels * input_pixel;
+    }
+    // 16-bit mask, and the floats of a single-pass upscale
+    let mask_pixel = if banded { 2 } else { 2 + 4 };
+    let mut mask_bytes = pixels * mask_pixel;
+    if let Some(tiling) = options.tiling {
+        // Weighted sums and weights for one band of tile rows
+        let tile = tiling.size.max(options.model.spec().input_size).min(working_height) as u64;
+        mask_bytes += 2 * 4 * working_width as u64 * tile;
+    }
+    let crop_bytes = match options.two_pass {
+        true => pixels / 2 * (input_pixel + mask_pixel),
+        false => 0,
+    };
+    let output_pixel = if high_depth { 8 } else { 4 };
+    let output_bytes = pixels * output_pixel;
+
+    let format = options.format.unwrap_or_default();
+    let keeps_depth = match format {
+        OutputFormat::Png => !options.png.quantize,
+        OutputFormat::Tiff => true,
+        _ => false,
+    };
+    let converted = match format {
+        OutputFormat::Jpeg => pixels * 3,
+        OutputFormat::Gif | OutputFormat::Apng => pixels * 4,
+        _ if high_depth && !keeps_depth => pixels * 4,
+        _ => 0,
+    };
+    let encoder_bytes = converted + disk_space::encoded_size(output_bytes, format);
+
+    MemoryEstimate {
+        input_bytes,
+        mask_bytes,
+        crop_bytes,
+        output_bytes,
+        encoder_bytes,
+        total_bytes: input_bytes + mask_bytes + crop_bytes + output_bytes + encoder_bytes,
+        banded,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::TileOptions;
+    use std::io::Cursor;
+
+    const MB: u64 = 1_000_000;
+
+    #[test]
+    fn adds_up_the_buffers_of_a_photo() {
+        let options = RemoveBgOptions::default();
+        assert_eq!(
+            estimate((1000, 1000), ColorType::Rgb8, &options),
+            MemoryEstimate {
+                input_bytes: 3 * MB,
+                mask_bytes: 6 * MB,
+                crop_bytes: 0,
+                output_bytes: 4 * MB,
+                encoder_bytes: 4 * MB,
+                total_bytes: 17 * MB,
+                banded: false,
+            }
+        );
+        // JPEG converts to RGB and compresses to about a quarter
+        let jpeg = RemoveBgOptions { format: Some(OutputFormat::Jpeg), ..Default::default() };
+        assert_eq!(estimate((1000, 1000), ColorType::Rgb8, &jpeg).encoder_bytes, 4 * MB);
+    }
+
+    #[test]
+    fn sixteen_bits_double_the_image_buffers() {
+        let options = RemoveBgOptions::default();
+        for size in [(640, 480), (1000, 1000), (1, 3000)] {
+            let eight = estimate(size, ColorType::Rgba8, &options);
+            let sixteen = estimate(size, ColorType::Rgba16, &options);
+            assert_eq!(sixteen.input_bytes, 2 * eight.input_bytes, "{:?}", size);
+            assert_eq!(sixteen.output_bytes, 2 * eight.output_bytes, "{:?}", size);
+            assert_eq!(sixteen.mask_bytes, eight.mask_bytes, "{:?}", size);
+        }
+        // WebP is 8-bit, so a 16-bit output is reduced first
+        let webp = RemoveBgOptions { format: Some(OutputFormat::WebP), ..Default::default() };
+        assert_eq!(estimate((1000, 1000), ColorType::Rgba8, &webp).encoder_bytes, MB);
+        assert_eq!(estimate((1000, 1000), ColorType::Rgba16, &webp).encoder_bytes, 6 * MB);
+    }
+
+    #[test]
+    fn options_add_their_buffers() {
+        let plain = estimate((2000, 1000), ColorType::Rgb8, &RemoveBgOptions::default());
+        let two_pass = estimate((2000, 1000), ColorType::Rgb8, &RemoveBgOptions { two_pass: true, ..Default::default() });
+        assert_eq!(two_pass.crop_bytes, MB * (3 + 6));
+        assert_eq!(two_pass.total_bytes, plain.total_bytes + two_pass.crop_bytes);
+
+        let tiling = RemoveBgOptions { tiling: Some(TileOptions::default()), ..Default::default() };
+        let tiled = estimate((2000, 1000), ColorType::Rgb8, &tiling);
+        assert_eq!(tiled.mask_bytes, plain.mask_bytes + 8 * 2000 * 1000);
+
+        // The input is decoded at full size, everything after at the limit
+        let limited = RemoveBgOptions { max_output_size: Some(1000), ..Default::default() };
+        let limited = estimate((2000, 1000), ColorType::Rgb8, &limited);
+        assert_eq!(limited.input_bytes, 6 * MB + 1_500_000);
+        assert_eq!(limited.output_bytes, 2 * MB);
+    }
+
+    #[test]
+    fn large_images_are_banded_anyway() {
+        let panorama = estimate((4000, 3000), ColorType::Rgb8, &RemoveBgOptions::default());
+        assert!(panorama.banded);
+        assert_eq!(panorama.mask_bytes, 24 * MB);
+    }
+
+    #[test]
+    fn a_tight_budget_bands_the_mask_before_refusing() {
+        let options = |budget| RemoveBgOptions { max_memory_bytes: Some(budget), ..Default::default() };
+        let (size, color) = ((1000, 1000), ColorType::Rgb8);
+        assert!(!check(size, color, &options(17 * MB)).unwrap().banded);
+
+        let banded = check(size, color, &options(16 * MB)).unwrap();
+        assert!(banded.banded);
+        assert_eq!((banded.mask_bytes, banded.total_bytes), (2 * MB, 13 * MB));
+
+        let error = check(size, color, &options(12 * MB)).unwrap_err();
+        assert!(matches!(error, RemoveBgError::MemoryBudgetExceeded { estimated, budget } if estimated == 13 * MB && budget == 12 * MB));
+        assert!(error.to_string().contains("needs about 12.40 MiB of memory, over the budget of 11.44 MiB"), "{}", error);
+        assert!(check(size, color, &RemoveBgOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn headers_are_checked_before_decoding() {
+        let mut png = Cursor::new(Vec::new());
+        image::RgbImage::new(1000, 1000).write_to(&mut png, image::ImageFormat::Png).unwrap();
+        png.set_position(0);
+        let options = |budget| RemoveBgOptions { max_memory_bytes: Some(budget), ..Default::default() };
+
+        let mut reader = check_input(png, &options(13 * MB)).unwrap();
+        assert_eq!(reader.stream_position().unwrap(), 0);
+        let error = check_input(&mut reader, &options(MB)).unwrap_err();
+        assert!(matches!(error.root(), RemoveBgError::MemoryBudgetExceeded { .. }));
+        assert_eq!(error.stage(), Some(Stage::Decode));
+
+        // What the decoder can't read is left to it
+        let mut garbage = Cursor::new(b"not an image".to_vec());
+        assert!(check_input(&mut garbage, &options(1)).is_ok());
+        assert_eq!(garbage.position(), 0);
+    }
+}
@@ -0,0 +1,1243 @@
+//! Model registry and model file management.
+//!
+//! This module describes the segmentation models removebg knows about, where
+//! their files are cached on disk, and how they are obtained (downloaded from
+//! the rembg releases or derived locally from another model).
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::disk_space;
+use crate::error::{RemoveBgError, Result};
+use crate::format::decimal_size;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::options::{CancellationToken, DownloadOptions, RetryPolicy};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::progress::DownloadProgress;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::remote;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::File;
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::{BufRead, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+#[cfg(not(target_arch = "wasm32"))]
+use std::process::Command;
+use std::str::FromStr;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Duration;
+
+/// A segmentation model available to removebg.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Model {
+    /// Full-size U2-Net, the general-purpose default.
+    #[cfg_attr(not(feature = "embed-u2netp"), default)]
+    U2net,
+    /// Lightweight U2-Net (u2netp), much smaller and faster at lower quality.
+    /// The default when it is compiled in with the `embed-u2netp` feature.
+    #[cfg_attr(feature = "embed-u2netp", default)]
+    U2netp,
+    /// INT8 dynamically-quantized U2-Net, derived locally from `u2net`.
+    U2netQuant,
+    /// U2-Net trained on people, for portraits and full-body shots.
+    U2netHumanSeg,
+    /// Silueta, a U2-Net distilled to a quarter of its size, between u2netp
+    /// and u2net in size and quality.
+    Silueta,
+    /// BiRefNet (Swin-T backbone) at 1024x1024, the highest quality and the
+    /// slowest.
+    BirefnetLite,
+}
+
+/// How a model file is obtained when it is not cached yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModelSource {
+    /// Downloaded from the given URL.
+    Download(&'static str),
+    /// Produced locally by quantizing another model (see [`quantize_model`]).
+    Quantized(Model),
+}
+
+/// Static description of a registered model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModelSpec {
+    /// Name used on the command line and in messages.
+    pub name: &'static str,
+    /// File name inside the model cache directory.
+    pub file_name: &'static str,
+    /// Where the model file comes from.
+    pub source: ModelSource,
+    /// Square input resolution expected by the model.
+    pub input_size: u32,
+    /// How pixels are normalized for the model's input.
+    pub normalization: Normalization,
+    /// Whether exported graphs of the model may end in logits instead of
+    /// probabilities. Outputs with values outside 0-1 then go through a
+    /// sigmoid before they are used as a mask.
+    pub logits: bool,
+    /// Exact size of the model file in bytes, for messages; `None` for
+    /// models created locally, whose size depends on the tool creating them,
+    /// and for files whose size isn't pinned.
+    pub size_bytes: Option<u64>,
+    /// Pinned SHA-256 of the model file (lowercase hex), if known.
+    ///
+    /// Without a pinned hash, the hash of the first download is recorded next
+    /// to the model file and later verifications compare against it.
+    pub sha256: Option<&'static str>,
+    /// One-line description.
+    pub description: &'static str,
+}
+
+/// Per-channel normalization of input pixels: each red, green and blue value
+/// from 0 to 1 becomes `(value - mean) / std`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Normalization {
+    pub mean: [f32; 3],
+    pub std: [f32; 3],
+}
+
+impl Normalization {
+    /// Pixels as they are, from 0 to 1, as the U2-Net models here take them.
+    pub const NONE: Normalization = Normalization { mean: [0.0; 3], std: [1.0; 3] };
+
+    /// The ImageNet statistics, as models with ImageNet-trained backbones
+    /// take their input.
+    pub const IMAGENET: Normalization = Normalization { mean: [0.485, 0.456, 0.406], std: [0.229, 0.224, 0.225] };
+}
+
+// Models use finite constants, for which float equality is an equivalence
+impl Eq for Normalization {}
+
+const U2NET: ModelSpec = ModelSpec {
+    name: "u2net",
+    file_name: "u2net.onnx",
+    source: ModelSource::Download(
+        "https://github.com/danielgatis/rembg/releases/download/v0.0.0/u2net.onnx",
+    ),
+    input_size: 320,
+    normalization: Normalization::NONE,
+    logits: false,
+    size_bytes: Some(175_997_641),
+    sha256: None,
+    description: "General-purpose U2-Net (default)",
+};
+
+const U2NETP: ModelSpec = ModelSpec {
+    name: "u2netp",
+    file_name: "u2netp.onnx",
+    source: ModelSource::Download(
+        "https://github.com/danielgatis/rembg/releases/download/v0.0.0/u2netp.onnx",
+    ),
+    input_size: 320,
+    normalization: Normalization::NONE,
+    logits: false,
+    size_bytes: Some(4_574_861),
+    sha256: None,
+    description: "Lightweight U2-Net, faster with lower quality",
+};
+
+const U2NET_QUANT: ModelSpec = ModelSpec {
+    name: "u2net-quant",
+    file_name: "u2net_quant.onnx",
+    source: ModelSource::Quantized(Model::U2net),
+    input_size: 320,
+    normalization: Normalization::NONE,
+    logits: false,
+    size_bytes: None,
+    sha256: None,
+    description: "INT8-quantized U2-Net, ~2x faster on CPU (created with `removebg model quantize`)",
+};
+
+const U2NET_HUMAN_SEG: ModelSpec = ModelSpec {
+    name: "u2net-human-seg",
+    file_name: "u2net_human_seg.onnx",
+    source: ModelSource::Download(
+        "https://github.com/danielgatis/rembg/releases/download/v0.0.0/u2net_human_seg.onnx",
+    ),
+    input_size: 320,
+    normalization: Normalization::NONE,
+    logits: false,
+    size_bytes: Some(175_997_641),
+    sha256: None,
+    description: "U2-Net trained on people, for portraits and full-body shots",
+};
+
+const SILUETA: ModelSpec = ModelSpec {
+    name: "silueta",
+    file_name: "silueta.onnx",
+    source: ModelSource::Download(
+        "https://github.com/danielgatis/rembg/releases/download/v0.0.0/silueta.onnx",
+    ),
+    input_size: 320,
+    normalization: Normalization::NONE,
+    logits: false,
+    size_bytes: None,
+    sha256: None,
+    description: "U2-Net distilled to ~43 MB, between u2netp and u2net",
+};
+
+const BIREFNET_LITE: ModelSpec = ModelSpec {
+    name: "birefnet-lite",
+    file_name: "birefnet_lite.onnx",
+    source: ModelSource::Download(
+        "https://github.com/danielgatis/rembg/releases/download/v0.0.0/BiRefNet-general-bb_swin_v1_tiny-epoch_232.onnx",
+    ),
+    input_size: 1024,
+    normalization: Normalization::IMAGENET,
+    logits: true,
+    size_bytes: None,
+    sha256: None,
+    description: "BiRefNet at 1024x1024, highest quality, much slower",
+};
+
+impl Model {
+    /// All registered models.
+    pub const ALL: &'static [Model] =
+        &[Model::U2net, Model::U2netp, Model::U2netQuant, Model::U2netHumanSeg, Model::Silueta, Model::BirefnetLite];
+
+    /// Get the registry entry for this model.
+    pub fn spec(self) -> &'static ModelSpec {
+        match self {
+            Model::U2net => &U2NET,
+            Model::U2netp => &U2NETP,
+            Model::U2netQuant => &U2NET_QUANT,
+            Model::U2netHumanSeg => &U2NET_HUMAN_SEG,
+            Model::Silueta => &SILUETA,
+            Model::BirefnetLite => &BIREFNET_LITE,
+        }
+    }
+}
+
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.spec().name)
+    }
+}
+
+impl FromStr for Model {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let name = s.to_ascii_lowercase().replace('_', "-");
+        Model::ALL
+            .iter()
+            .copied()
+            .find(|model| model.spec().name == name)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Model::ALL.iter().map(|m| m.spec().name).collect();
+                format!("unknown model '{}' (expected one of: {})", s, names.join(", "))
+            })
+    }
+}
+
+/// Environment variable overriding the model cache directory.
+pub const MODEL_DIR_ENV: &str = "REMOVEBG_MODEL_DIR";
+
+/// rembg's model directory variable, honored so existing downloads are reused.
+pub const U2NET_HOME_ENV: &str = "U2NET_HOME";
+
+/// Environment variable overriding the download URL of the selected model.
+pub const MODEL_URL_ENV: &str = "REMOVEBG_MODEL_URL";
+
+/// Get the directory where model files are cached, creating it if needed.
+///
+/// The first of these is used:
+/// 1. `explicit` (the `model_dir` option / `--model-dir` flag)
+/// 2. The `REMOVEBG_MODEL_DIR` environment variable
+/// 3. The `U2NET_HOME` environment variable (shared with rembg)
+/// 4. The legacy `~/.u2net` directory, if it already contains a model
+/// 5. The platform cache directory, e.g. `~/.cache/removebg` on Linux
+///
+/// # Errors
+/// * `ModelInitError` - If no directory can be determined or it cannot be created
+pub fn model_dir(explicit: Option<&Path>) -> Result<PathBuf> {
+    let dir = resolve_model_dir(explicit)?;
+    std::fs::create_dir_all(&dir).map_err(|e| {
+        RemoveBgError::ModelInitError(format!(
+            "Could not create model directory {}: {}",
+            dir.display(),
+            e
+        ))
+    })?;
+    Ok(dir)
+}
+
+/// Pick the model directory without creating it.
+pub(crate) fn resolve_model_dir(explicit: Option<&Path>) -> Result<PathBuf> {
+    if let Some(dir) = explicit {
+        return Ok(dir.to_path_buf());
+    }
+
+    for var in [MODEL_DIR_ENV, U2NET_HOME_ENV] {
+        if let Some(dir) = std::env::var_os(var).filter(|v| !v.is_empty()) {
+            return Ok(PathBuf::from(dir));
+        }
+    }
+
+    // Keep using models downloaded by earlier versions
+    if let Some(legacy) = dirs::home_dir().map(|home| home.join(".u2net")) {
+        if Model::ALL.iter().any(|m| legacy.join(m.spec().file_name).is_file()) {
+            return Ok(legacy);
+        }
+    }
+
+    dirs::cache_dir()
+        .map(|cache| cache.join("removebg"))
+        .ok_or_else(|| {
+            RemoveBgError::ModelInitError(format!(
+                "Could not determine a model directory; set {} or use --model-dir",
+                MODEL_DIR_ENV
+            ))
+        })
+}
+
+/// u2netp compiled into the binary; the build script locates the file.
+#[cfg(feature = "embed-u2netp")]
+const EMBEDDED_U2NETP: &[u8] = include_bytes!(env!("REMOVEBG_EMBEDDED_U2NETP"));
+
+/// Get the model file compiled into the binary, if `model` is embedded.
+///
+/// With the `embed-u2netp` feature, u2netp is loaded from memory and never
+/// downloaded or looked up in the model directory; other models are still
+/// downloaded as usual.
+pub fn embedded_model(model: Model) -> Option<&'static [u8]> {
+    match model {
+        #[cfg(feature = "embed-u2netp")]
+        Model::U2netp => Some(EMBEDDED_U2NETP),
+        _ => None,
+    }
+}
+
+/// Get the path where a model file should be stored (see [`model_dir`]).
+pub fn model_path(model: Model, dir: Option<&Path>) -> Result<PathBuf> {
+    Ok(model_dir(dir)?.join(model.spec().file_name))
+}
+
+/// Get the URL a model is downloaded from.
+///
+/// The first of these is used:
+/// 1. `download.url` (the `--model-url` flag)
+/// 2. The `REMOVEBG_MODEL_URL` environment variable
+/// 3. The URL from the model registry
+///
+/// Locally derived models (such as `u2net-quant`) are never downloaded and
+/// have no URL.
+///
+/// # Errors
+/// * `ModelInitError` - If the overriding URL is not a valid http(s) URL
+#[cfg(not(target_arch = "wasm32"))]
+pub fn download_url(model: Model, download: &DownloadOptions) -> Result<Option<String>> {
+    let ModelSource::Download(registry) = model.spec().source else {
+        return Ok(None);
+    };
+    let env = std::env::var(MODEL_URL_ENV).ok().filter(|v| !v.is_empty());
+    match download.url.clone().or(env) {
+        Some(url) => {
+            validate_model_url(&url)?;
+            Ok(Some(url))
+        }
+        None => Ok(Some(registry.to_string())),
+    }
+}
+
+/// Check that a model download URL is an absolute http or https URL.
+///
+/// # Errors
+/// * `ModelInitError` - If the URL cannot be parsed or uses another scheme
+#[cfg(not(target_arch = "wasm32"))]
+pub fn validate_model_url(url: &str) -> Result<()> {
+    remote::validate_url(url).map_err(|reason| {
+        RemoveBgError::ModelInitError(format!("Invalid model URL '{}': {}", url, reason))
+    })
+}
+
+/// Make sure the model file is available locally, downloading it if needed.
+///
+/// The file is downloaded from [`download_url`] and checked against
+/// `download.sha256` or the pinned hash. Locally derived models (such as
+/// `u2net-quant`) are never created implicitly; an error explains how to
+/// create them instead. Failed downloads are retried according to
+/// `download.retry`, and stop with `Cancelled` when `cancel` is triggered.
+/// With `download.offline`, a missing model is an error instead. Progress
+/// goes to `progress` as data arrives.
+///
+/// Processes sharing a model directory download each model once: the first
+/// takes the model's [`DownloadLock`] and the others wait for it, then use
+/// the file it downloaded.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn ensure_model(
+    model: Model,
+    dir: Option<&Path>,
+    download: &DownloadOptions,
+    progress: &DownloadProgress,
+    cancel: &CancellationToken,
+) -> Result<PathBuf> {
+    let path = model_path(model, dir)?;
+    if path.exists() {
+        return Ok(path);
+    }
+
+    match (model.spec().source, download_url(model, download)?) {
+        (ModelSource::Download(_), Some(_)) if download.offline => return Err(offline_error(model, &path)),
+        (ModelSource::Download(_), Some(url)) => {
+            let lock = lock_download(&path, LOCK_STALL_TIMEOUT, cancel)?;
+            let expected = expected_download_sha256(model, download);
+            if !downloaded_meanwhile(&path, expected)? {
+                let target = DownloadTarget { model, url: &url, expected, path: &path, part_path: &lock.part_path };
+                download_model(&target, &download.retry, progress, cancel)?;
+            }
+        }
+        (ModelSource::Download(_), None) => unreachable!("downloaded models always have a URL"),
+        (ModelSource::Quantized(base), _) => {
+            return Err(RemoveBgError::ModelInitError(format!(
+                "Model {} is not available; create it from {} with `removebg model quantize`",
+                model, base
+            )))
+        }
+    }
+
+    Ok(path)
+}
+
+/// The error for a model that isn't cached when downloads are disabled.
+#[cfg(not(target_arch = "wasm32"))]
+fn offline_error(model: Model, path: &Path) -> RemoveBgError {
+    RemoveBgError::ModelNotCached { model, path: path.to_path_buf(), offline: true }
+}
+
+/// `model` and its file size for messages, like `u2net (176.00 MB)`, or
+/// just the name if the size isn't known.
+pub fn name_with_size(model: Model) -> String {
+    match model.spec().size_bytes {
+        Some(bytes) => format!("{} ({})", model, decimal_size(bytes)),
+        None => model.to_string(),
+    }
+}
+
+/// Whether using `model` would download it: it is neither compiled in nor
+/// cached in `dir`, and `download` allows downloading it.
+///
+/// # Errors
+/// * `ModelInitError` - If the model directory can't be determined
+#[cfg(not(target_arch = "wasm32"))]
+pub fn needs_download(model: Model, dir: Option<&Path>, download: &DownloadOptions) -> Result<bool> {
+    if embedded_model(model).is_some() || download.offline || !matches!(model.spec().source, ModelSource::Download(_)) {
+        return Ok(false);
+    }
+    Ok(!model_path(model, dir)?.exists())
+}
+
+/// Ask on `prompt` whether to download `model`, which isn't cached in
+/// `dir`, and read the answer as a line from `answer`.
+///
+/// The question names the model and its size, like
+/// `Model u2net (176.00 MB) is not cached. Download now? [Y/n]`. An empty
+/// answer, `y` or `yes` accepts; anything else, including the end of the
+/// input, declines.
+///
+/// # Errors
+/// * `ModelNotCached` - If the download was declined
+/// * `IoError` - If the prompt can't be written or the answer read
+#[cfg(not(target_arch = "wasm32"))]
+pub fn confirm_download(model: Model, dir: Option<&Path>, answer: &mut dyn BufRead, prompt: &mut dyn Write) -> Result<()> {
+    write!(prompt, "Model {} is not cached. Download now? [Y/n] ", name_with_size(model))?;
+    prompt.flush()?;
+    let mut line = String::new();
+    let accepted = answer.read_line(&mut line)? > 0
+        && matches!(line.trim().to_ascii_lowercase().as_str(), "" | "y" | "yes");
+    match accepted {
+        true => Ok(()),
+        false => Err(RemoveBgError::ModelNotCached { model, path: model_path(model, dir)?, offline: false }),
+    }
+}
+
+/// How often a process waiting for another's download checks the lock.
+#[cfg(not(target_arch = "wasm32"))]
+const LOCK_POLL: Duration = Duration::from_millis(250);
+
+/// How long the download holding a [`DownloadLock`] may go without writing
+/// anything before waiting processes stop waiting for it.
+#[cfg(not(target_arch = "wasm32"))]
+const LOCK_STALL_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Path of the file locked while a model is downloaded.
+#[cfg(not(target_arch = "wasm32"))]
+fn lock_path(path: &Path) -> PathBuf {
+    path.with_extension("onnx.lock")
+}
+
+/// Path of the shared partial download of a model.
+#[cfg(not(target_arch = "wasm32"))]
+fn part_path(path: &Path) -> PathBuf {
+    path.with_extension("onnx.part")
+}
+
+/// The right to download one model file, shared by every process using the
+/// same model directory.
+///
+/// This is an advisory lock on a `.lock` file next to the model. The
+/// operating system releases it when the holder exits, so a crashed process
+/// never leaves the model locked; the `.lock` file itself stays in place.
+#[cfg(not(target_arch = "wasm32"))]
+struct DownloadLock {
+    /// The locked file; `None` when the lock could not be taken.
+    _file: Option<File>,
+    /// Where to write the partial download: the shared `.part` file while
+    /// holding the lock, or one of this process's own otherwise.
+    part_path: PathBuf,
+}
+
+/// Waiting for another process to finish downloading a model.
+#[cfg(not(target_arch = "wasm32"))]
+struct LockWait {
+    path: PathBuf,
+    file: Option<File>,
+    stall_timeout: Duration,
+    /// Size of the other process's `.part` file when it last changed.
+    progress: Option<u64>,
+    last_progress: std::time::Instant,
+    waiting: bool,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl LockWait {
+    fn new(path: &Path, stall_timeout: Duration) -> Result<Self> {
+        let file = std::fs::OpenOptions::new().create(true).truncate(false).write(true).open(lock_path(path))?;
+        Ok(LockWait {
+            path: path.to_path_buf(),
+            file: Some(file),
+            stall_timeout,
+            progress: None,
+            last_progress: std::time::Instant::now(),
+            waiting: false,
+        })
+    }
+
+    /// Try to take the lock. Returns `None` while another process holds it
+    /// and its download is making progress.
+    ///
+    /// Once that download has written nothing for `stall_timeout`, or if the
+    /// file system doesn't support locking, this returns a lock that isn't
+    /// held, with a partial download path of this process's own.
+    fn poll(&mut self) -> Result<Option<DownloadLock>> {
+        let Some(file) = self.file.take() else {
+            return Err(RemoveBgError::ModelInitError("Model download lock was already taken".into()));
+        };
+        let unlocked = || DownloadLock {
+            _file: None,
+            part_path: self.path.with_extension(format!("onnx.{}.part", std::process::id())),
+        };
+        match file.try_lock() {
+            Ok(()) => return Ok(Some(DownloadLock { _file: Some(file), part_path: part_path(&self.path) })),
+            Err(std::fs::TryLockError::WouldBlock) => {}
+            Err(std::fs::TryLockError::Error(e)) => {
+                log::debug!("could not lock {}: {}", lock_path(&self.path).display(), e);
+                return Ok(Some(unlocked()));
+            }
+        }
+
+        if !self.waiting {
+            log::info!("Waiting for another process to finish downloading {}", self.path.display());
+            self.waiting = true;
+        }
+        let progress = std::fs::metadata(part_path(&self.path)).ok().map(|m| m.len());
+        if progress != self.progress {
+            self.progress = progress;
+            self.last_progress = std::time::Instant::now();
+        } else if self.last_progress.elapsed() >= self.stall_timeout {
+            log::warn!(
+                "The other download of {} has made no progress for {}s; downloading it here instead",
+                self.path.display(),
+                self.stall_timeout.as_secs()
+            );
+            return Ok(Some(unlocked()));
+        }
+        self.file = Some(file);
+        Ok(None)
+    }
+}
+
+/// Take the download lock for the model at `path`, waiting while another
+/// process downloads it (see [`LockWait::poll`]).
+#[cfg(not(target_arch = "wasm32"))]
+fn lock_download(path: &Path, stall_timeout: Duration, cancel: &CancellationToken) -> Result<DownloadLock> {
+    let mut wait = LockWait::new(path, stall_timeout)?;
+    loop {
+        if let Some(lock) = wait.poll()? {
+            return Ok(lock);
+        }
+        backoff(LOCK_POLL, cancel)?;
+    }
+}
+
+/// Whether the model at `path` was downloaded by another process while this
+/// one waited for the lock. A file that doesn't match `expected` is
+/// downloaded again.
+#[cfg(not(target_arch = "wasm32"))]
+fn downloaded_meanwhile(path: &Path, expected: Option<&str>) -> Result<bool> {
+    if !path.exists() {
+        return Ok(false);
+    }
+    let digest = file_sha256(path)?;
+    match expected {
+        Some(expected) if !expected.eq_ignore_ascii_case(&digest) => {
+            log::warn!("{} does not have the expected checksum; downloading it again", path.display());
+            Ok(false)
+        }
+        _ => Ok(true),
+    }
+}
+
+/// A model file to download, and where to put it.
+#[cfg(not(target_arch = "wasm32"))]
+struct DownloadTarget<'a> {
+    model: Model,
+    url: &'a str,
+    /// Hash the file must have, if known.
+    expected: Option<&'a str>,
+    path: &'a Path,
+    part_path: &'a Path,
+}
+
+/// Why a download attempt failed.
+#[cfg(not(target_arch = "wasm32"))]
+enum DownloadFailure {
+    /// Worth retrying: connection errors, timeouts, 5xx responses, and
+    /// connections dropped mid-transfer.
+    Transient(String),
+    /// Retrying won't help.
+    Fatal(RemoveBgError),
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl From<RemoveBgError> for DownloadFailure {
+    fn from(e: RemoveBgError) -> Self {
+        DownloadFailure::Fatal(e)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl From<std::io::Error> for DownloadFailure {
+    fn from(e: std::io::Error) -> Self {
+        DownloadFailure::Fatal(e.into())
+    }
+}
+
+/// Decide how to continue after a failed download attempt.
+///
+/// Transient failures are recorded in `failures`. Returns the delay before
+/// the next attempt, or the error to report once retrying won't help or no
+/// attempts are left; that error summarizes every attempt.
+#[cfg(not(target_arch = "wasm32"))]
+fn next_attempt(
+    failures: &mut Vec<String>,
+    failure: DownloadFailure,
+    retry: &RetryPolicy,
+) -> Result<Duration> {
+    let reason = match failure {
+        DownloadFailure::Fatal(e) => return Err(e),
+        DownloadFailure::Transient(reason) => reason,
+    };
+    let attempt = failures.len() as u32 + 1;
+    let attempts = retry.attempts.max(1);
+
+    if attempt < attempts {
+        let delay = retry.delay(attempt);
+        log::warn!(
+            "Download attempt {}/{} failed: {}; retrying in {:.1}s",
+            attempt,
+            attempts,
+            reason,
+            delay.as_secs_f64()
+        );
+        failures.push(reason);
+        return Ok(delay);
+    }
+
+    failures.push(reason);
+    let message = match failures.as_slice() {
+        [reason] => format!("Failed to download model: {}", reason),
+        failures => format!(
+            "Failed to download model after {} attempts: {}",
+            failures.len(),
+            failures
+                .iter()
+                .enumerate()
+                .map(|(i, reason)| format!("attempt {}: {}", i + 1, reason))
+                .collect::<Vec<_>>()
+                .join("; ")
+        ),
+    };
+    Err(RemoveBgError::ModelInitError(message))
+}
+
+/// Interval at which backoff sleeps check for cancellation.
+#[cfg(not(target_arch = "wasm32"))]
+const CANCEL_POLL: Duration = Duration::from_millis(100);
+
+/// Sleep for `delay`, returning early with `Cancelled` if `cancel` triggers.
+#[cfg(not(target_arch = "wasm32"))]
+fn backoff(delay: Duration, cancel: &CancellationToken) -> Result<()> {
+    let until = std::time::Instant::now() + delay;
+    loop {
+        cancel.check()?;
+        let left = until.saturating_duration_since(std::time::Instant::now());
+        if left.is_zero() {
+            return Ok(());
+        }
+        std::thread::sleep(left.min(CANCEL_POLL));
+    }
+}
+
+/// Download a model file from the given URL.
+///
+/// The file is streamed to `part_path` and renamed into place once complete,
+/// and must match `expected` if given. Transient failures are retried
+/// according to `retry`, resuming the partial file with a range request when
+/// the server supports it. The partial file is removed if the download
+/// finally fails or is cancelled.
+#[cfg(not(target_arch = "wasm32"))]
+fn download_model(
+    target: &DownloadTarget<'_>,
+    retry: &RetryPolicy,
+    progress: &DownloadProgress,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let DownloadTarget { model, url, path, part_path, .. } = *target;
+    enter_span!("download", model = %model, url);
+    disk_space::check_download(model, part_path.parent().unwrap_or(Path::new(".")))?;
+    log::info!("Downloading model {}...", name_with_size(model));
+    log::debug!("model URL: {}", url);
+
+    let _ = std::fs::remove_file(part_path);
+    let mut failures = Vec::new();
+    let digest = loop {
+        let failure = match download_attempt(target, progress, cancel) {
+            Ok(digest) => break digest,
+            Err(failure) => failure,
+        };
+        let retried = next_attempt(&mut failures, failure, retry).and_then(|delay| backoff(delay, cancel));
+        if let Err(e) = retried {
+            let _ = std::fs::remove_file(part_path);
+            return Err(e);
+        }
+    };
+
+    std::fs::rename(part_path, path)?;
+    std::fs::write(checksum_path(path), &digest)?;
+    log::info!("Model downloaded successfully!");
+    trace_event!("downloaded", bytes = std::fs::metadata(path).map_or(0, |metadata| metadata.len()));
+
+    Ok(())
+}
+
+/// Run one download attempt into `part_path`, continuing a partial file left
+/// by an earlier attempt if the server honors the range request.
+///
+/// Returns the SHA-256 of the complete file.
+#[cfg(not(target_arch = "wasm32"))]
+fn download_attempt(
+    target: &DownloadTarget<'_>,
+    progress: &DownloadProgress,
+    cancel: &CancellationToken,
+) -> std::result::Result<String, DownloadFailure> {
+    let part_path = target.part_path;
+    let partial = std::fs::metadata(part_path).map_or(0, |m| m.len());
+    let mut request = remote::get(target.url);
+    if partial > 0 {
+        request = request.header("Range", format!("bytes={}-", partial));
+    }
+    let response = request.call().map_err(|e| match e {
+        // The partial file can't be continued; start over on the next attempt
+        ureq::Error::StatusCode(416) => {
+            let _ = std::fs::remove_file(part_path);
+            DownloadFailure::Transient("server rejected resuming the download".into())
+        }
+        ureq::Error::StatusCode(code) if is_transient_status(code) => {
+            DownloadFailure::Transient(format!("HTTP status {}", code))
+        }
+        ureq::Error::Io(_)
+        | ureq::Error::Timeout(_)
+        | ureq::Error::ConnectionFailed
+        | ureq::Error::HostNotFound
+        | ureq::Error::Protocol(_) => DownloadFailure::Transient(e.to_string()),
+        e => DownloadFailure::Fatal(RemoveBgError::ModelInitError(format!(
+            "Failed to download model: {}",
+            e
+        ))),
+    })?;
+
+    let mut hasher = Sha256::new();
+    let resumed = partial > 0 && response.status() == 206;
+    let file = if resumed {
+        log::info!("Resuming download at {}", decimal_size(partial));
+        std::io::copy(&mut File::open(part_path)?, &mut hasher)?;
+        std::fs::OpenOptions::new().append(true).open(part_path)?
+    } else {
+        File::create(part_path)?
+    };
+
+    let kept = if resumed { partial } else { 0 };
+    let total = response.body().content_length().map(|length| kept + length);
+    let mut meter = progress.meter(target.model, kept, total);
+    let mut reader = response.into_body().into_reader();
+    let mut file = BufWriter::new(file);
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        cancel.check()?;
+        let read = match reader.read(&mut buffer) {
+            Ok(read) => read,
+            Err(e) => {
+                // Keep what arrived so the next attempt can resume from it
+                file.flush()?;
+                return Err(DownloadFailure::Transient(format!("Failed to read model data: {}", e)));
+            }
+        };
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        file.write_all(&buffer[..read])?;
+        meter.advance(read);
+    }
+    file.flush()?;
+    meter.finish();
+    let spec = target.model.spec();
+    Ok(check_download_digest(spec, target.expected, &format!("{:x}", hasher.finalize()))?)
+}
+
+/// Whether an HTTP error status is worth retrying.
+#[cfg(not(target_arch = "wasm32"))]
+fn is_transient_status(code: u16) -> bool {
+    code >= 500 || code == 408 || code == 429
+}
+
+/// Path of the file recording the SHA-256 of a cached model.
+fn checksum_path(path: &Path) -> PathBuf {
+    path.with_extension("onnx.sha256")
+}
+
+/// Hash a download of `model` must have: the override from `download`, or
+/// else the pinned hash.
+#[cfg(not(target_arch = "wasm32"))]
+fn expected_download_sha256(model: Model, download: &DownloadOptions) -> Option<&str> {
+    download.sha256.as_deref().or(model.spec().sha256)
+}
+
+/// Compare a freshly downloaded file's hash with the expected hash, if any.
+///
+/// Returns the hash so it can be recorded next to the model file.
+#[cfg(not(target_arch = "wasm32"))]
+fn check_download_digest(spec: &ModelSpec, expected: Option<&str>, digest: &str) -> Result<String> {
+    match expected {
+        Some(expected) if !expected.eq_ignore_ascii_case(digest) => Err(RemoveBgError::ModelInitError(format!(
+            "Checksum mismatch for downloaded {} model: expected {}, got {}",
+            spec.name, expected, digest
+        ))),
+        _ => Ok(digest.to_string()),
+    }
+}
+
+/// Get the SHA-256 the cached model is expected to have.
+///
+/// This is the pinned hash from the registry, or else the hash recorded when
+/// the file was downloaded or first verified.
+pub fn expected_sha256(model: Model, dir: Option<&Path>) -> Result<Option<String>> {
+    if let Some(pinned) = model.spec().sha256 {
+        return Ok(Some(pinned.to_string()));
+    }
+    let recorded = std::fs::read_to_string(checksum_path(&model_path(model, dir)?)).ok();
+    Ok(recorded.map(|s| s.trim().to_string()))
+}
+
+/// Delete a cached model file and its recorded checksum.
+///
+/// # Returns
+/// Whether the model file existed.
+pub fn remove_model(model: Model, dir: Option<&Path>) -> Result<bool> {
+    let path = model_path(model, dir)?;
+    let _ = std::fs::remove_file(checksum_path(&path));
+    match std::fs::remove_file(&path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Compute the SHA-256 of a file as lowercase hex.
+pub(crate) fn file_sha256(path: &Path) -> Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Verify the cached model file against its expected SHA-256.
+///
+/// The expected hash is the pinned hash from the registry, or else the hash
+/// recorded when the file was downloaded. If neither exists (for example, a
+/// locally quantized model), the current hash is recorded for later checks.
+///
+/// # Returns
+/// The SHA-256 of the model file.
+///
+/// # Errors
+/// * `ModelInitError` - If the model is not cached or its hash does not match
+pub fn verify_model(model: Model, dir: Option<&Path>) -> Result<String> {
+    verify_model_against(model, dir, None)
+}
+
+/// Verify the cached model file, preferring `expected` (a `sha256` override
+/// from [`DownloadOptions`]) over the pinned or recorded hash.
+pub(crate) fn verify_model_against(model: Model, dir: Option<&Path>, expected: Option<&str>) -> Result<String> {
+    let path = model_path(model, dir)?;
+    if !path.exists() {
+        return Err(RemoveBgError::ModelInitError(format!("Model {} is not cached", model)));
+    }
+
+    let digest = file_sha256(&path)?;
+    let expected = match expected {
+        Some(expected) => Some(expected.to_ascii_lowercase()),
+        None => expected_sha256(model, dir)?,
+    };
+    match expected {
+        Some(expected) if expected != digest => Err(RemoveBgError::ModelInitError(format!(
+            "Checksum mismatch for {} ({}): expected {}, got {}; delete the file to download it again",
+            model,
+            path.display(),
+            expected,
+            digest
+        ))),
+        Some(_) => Ok(digest),
+        None => {
+            std::fs::write(checksum_path(&path), &digest)?;
+            Ok(digest)
+        }
+    }
+}
+
+/// Make sure the model file is available locally, downloading it asynchronously if needed.
+///
+/// Concurrent callers asking for the same model share a single download: the
+/// first one fetches the file and the others wait for it to finish. Other
+/// processes are kept out by the same [`DownloadLock`] as [`ensure_model`].
+/// The file is streamed to a `.part` file and renamed into place once
+/// complete.
+#[cfg(feature = "async")]
+pub(crate) async fn ensure_model_async(
+    model: Model,
+    dir: Option<&Path>,
+    download: &DownloadOptions,
+    progress: &DownloadProgress,
+    cancel: &CancellationToken,
+) -> Result<PathBuf> {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex, OnceLock};
+    use tokio::sync::OnceCell;
+
+    static DOWNLOADS: OnceLock<Mutex<HashMap<PathBuf, Arc<OnceCell<PathBuf>>>>> = OnceLock::new();
+
+    let path = model_path(model, dir)?;
+    let cell = {
+        let mut downloads = DOWNLOADS
+            .get_or_init(|| Mutex::new(HashMap::new()))
+            .lock()
+            .map_err(|_| RemoveBgError::ModelInitError("Model download lock poisoned".into()))?;
+        Arc::clone(downloads.entry(path.clone()).or_default())
+    };
+
+    // A failed download leaves the cell empty, so a later call retries
+    cell.get_or_try_init(|| async {
+        if path.exists() {
+            return Ok(path);
+        }
+
+        match (model.spec().source, download_url(model, download)?) {
+            (ModelSource::Download(_), Some(_)) if download.offline => return Err(offline_error(model, &path)),
+            (ModelSource::Download(_), Some(url)) => {
+                let lock = lock_download_async(&path, LOCK_STALL_TIMEOUT, cancel).await?;
+                let expected = expected_download_sha256(model, download);
+                if !downloaded_meanwhile(&path, expected)? {
+                    let target = DownloadTarget { model, url: &url, expected, path: &path, part_path: &lock.part_path };
+                    let fetch = download_model_async(&target, &download.retry, progress, cancel);
+                    // An entered span must not be held across an await
+                    #[cfg(feature = "tracing")]
+                    let fetch = tracing::Instrument::instrument(
+                        fetch,
+                        tracing::info_span!(target: "removebg", "download", model = %model, url = %url),
+                    );
+                    fetch.await?
+                }
+            }
+            (ModelSource::Download(_), None) => unreachable!("downloaded models always have a URL"),
+            (ModelSource::Quantized(_), _) => return ensure_model(model, dir, download, progress, cancel),
+        }
+        Ok(path.clone())
+    })
+    .await
+    .cloned()
+}
+
+/// Async counterpart of [`lock_download`].
+#[cfg(feature = "async")]
+async fn lock_download_async(path: &Path, stall_timeout: Duration, cancel: &CancellationToken) -> Result<DownloadLock> {
+    let mut wait = LockWait::new(path, stall_timeout)?;
+    loop {
+        if let Some(lock) = wait.poll()? {
+            return Ok(lock);
+        }
+        backoff_async(LOCK_POLL, cancel).await?;
+    }
+}
+
+/// Download a model file from the given URL without blocking the runtime.
+///
+/// Retries and resumes like [`download_model`].
+#[cfg(feature = "async")]
+async fn download_model_async(
+    target: &DownloadTarget<'_>,
+    retry: &RetryPolicy,
+    progress: &DownloadProgress,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let DownloadTarget { model, url, path, part_path, .. } = *target;
+    disk_space::check_download(model, part_path.parent().unwrap_or(Path::new(".")))?;
+    log::info!("Downloading model {}...", name_with_size(model));
+    log::debug!("model URL: {}", url);
+
+    let _ = tokio::fs::remove_file(part_path).await;
+    let mut failures = Vec::new();
+    let digest = loop {
+        let failure = match download_attempt_async(target, progress, cancel).await {
+            Ok(digest) => break digest,
+            Err(failure) => failure,
+        };
+        let retried = match next_attempt(&mut failures, failure, retry) {
+            Ok(delay) => backoff_async(delay, cancel).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = retried {
+            let _ = tokio::fs::remove_file(part_path).await;
+            return Err(e);
+        }
+    };
+
+    tokio::fs::rename(part_path, path).await?;
+    tokio::fs::write(checksum_path(path), &digest).await?;
+    log::info!("Model downloaded successfully!");
+    trace_event!("downloaded", bytes = tokio::fs::metadata(path).await.map_or(0, |metadata| metadata.len()));
+
+    Ok(())
+}
+
+/// Async counterpart of [`backoff`].
+#[cfg(feature = "async")]
+async fn backoff_async(delay: Duration, cancel: &CancellationToken) -> Result<()> {
+    let until = tokio::time::Instant::now() + delay;
+    loop {
+        cancel.check()?;
+        let left = until.saturating_duration_since(tokio::time::Instant::now());
+        if left.is_zero() {
+            return Ok(());
+        }
+        tokio::time::sleep(left.min(CANCEL_POLL)).await;
+    }
+}
+
+/// Async counterpart of [`download_attempt`].
+#[cfg(feature = "async")]
+async fn download_attempt_async(
+    target: &DownloadTarget<'_>,
+    progress: &DownloadProgress,
+    cancel: &CancellationToken,
+) -> std::result::Result<String, DownloadFailure> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let part_path = target.part_path;
+    let classify = |e: reqwest::Error| match e.status() {
+        Some(status) if status.as_u16() == 416 => {
+            let _ = std::fs::remove_file(part_path);
+            DownloadFailure::Transient("server rejected resuming the download".into())
+        }
+        Some(status) if is_transient_status(status.as_u16()) => {
+            DownloadFailure::Transient(format!("HTTP status {}", status.as_u16()))
+        }
+        Some(_) => DownloadFailure::Fatal(RemoveBgError::ModelInitError(format!(
+            "Failed to download model: {}",
+            e
+        ))),
+        None => DownloadFailure::Transient(e.to_string()),
+    };
+
+    let partial = tokio::fs::metadata(part_path).await.map_or(0, |m| m.len());
+    let client = reqwest::Client::builder()
+        .connect_timeout(remote::CONNECT_TIMEOUT)
+        .build()
+        .map_err(|e| RemoveBgError::ModelInitError(format!("Failed to download model: {}", e)))?;
+    let mut request = client.get(target.url);
+    if partial > 0 {
+        request = request.header("Range", format!("bytes={}-", partial));
+    }
+    let mut response = request
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(classify)?;
+
+    let mut hasher = Sha256::new();
+    let resumed = partial > 0 && response.status().as_u16() == 206;
+    let mut file = if resumed {
+        log::info!("Resuming download at {}", decimal_size(partial));
+        let mut existing = tokio::fs::File::open(part_path).await?;
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            let read = existing.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+        tokio::fs::OpenOptions::new().append(true).open(part_path).await?
+    } else {
+        tokio::fs::File::create(part_path).await?
+    };
+
+    let kept = if resumed { partial } else { 0 };
+    let total = response.content_length().map(|length| kept + length);
+    let mut meter = progress.meter(target.model, kept, total);
+    loop {
+        let chunk = match response.chunk().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(e) => {
+                // Keep what arrived so the next attempt can resume from it
+                file.flush().await?;
+                return Err(DownloadFailure::Transient(format!("Failed to read model data: {}", e)));
+            }
+        };
+        cancel.check()?;
+        hasher.update(&chunk);
+        file.write_all(&chunk).await?;
+        meter.advance(chunk.len());
+    }
+    file.flush().await?;
+    meter.finish();
+    let spec = target.model.spec();
+    Ok(check_download_digest(spec, target.expected, &format!("{:x}", hasher.finalize()))?)
+}
+
+/// Create a quantized model by running ONNX Runtime's dynamic quantization.
+///
+/// Dynamic INT8 quantization is provided by the `onnxruntime` Python package
+/// (`onnxruntime.quantization.quantize_dynamic`), so a Python interpreter with
+/// `onnxruntime` installed must be available as `python3` or `python`. The base
+/// model is downloaded first if necessary, and the result is stored in the
+/// model cache next to it.
+///
+/// # Errors
+/// * `ModelInitError` - If `model` is not a quantized model, or quantization fails
+#[cfg(not(target_arch = "wasm32"))]
+pub fn quantize_model(model: Model, dir: Option<&Path>) -> Result<PathBuf> {
+    let base = match model.spec().source {
+        ModelSource::Quantized(base) => base,
+        ModelSource::Download(_) => {
+            return Err(RemoveBgError::ModelInitError(format!(
+                "Model {} is not a quantized model",
+                model
+            )))
+        }
+    };
+
+    let base_path = ensure_model(base, dir, &DownloadOptions::default(), &DownloadProgress::default(), &CancellationToken::default())?;
+    let output_path = model_path(model, dir)?;
+
+    let script = "import sys\n\
+                  from onnxruntime.quantization import QuantType, quantize_dynamic\n\
+                  quantize_dynamic(sys.argv[1], sys.argv[2], weight_type=QuantType.QUInt8)\n";
+
+    let mut last_error = String::from("no Python interpreter found");
+    for python in ["python3", "python"] {
+        let status = Command::new(python)
+            .arg("-c")
+            .arg(script)
+            .arg(&base_path)
+            .arg(&output_path)
+            .status();
+
+        match status {
+            Ok(status) if status.success() => return Ok(output_path),
+            Ok(status) => {
+                let _ = std::fs::remove_file(&output_path);
+                return Err(RemoveBgError::ModelInitError(format!(
+                    "Quantization failed ({}); make sure the `onnxruntime` Python package is installed",
+                    status
+                )));
+            }
+            Err(e) => last_error = format!("{}: {}", python, e),
+        }
+    }
+
+    Err(RemoveBgError::ModelInitError(format!(
+        "Quantization requires Python with the `onnxruntime` package ({})",
+        last_error
+    )))
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    fn model_file(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("removebg-lock-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join("u2netp.onnx")
+    }
+
+    #[test]
+    fn the_lock_is_shared_in_turn() {
+        let path = model_file("turn");
+        let first = LockWait::new(&path, LOCK_STALL_TIMEOUT).unwrap().poll().unwrap().expect("lock is free");
+        assert!(first._file.is_some());
+        assert_eq!(first.part_path, path.with_extension("onnx.part"));
+
+        let mut second = LockWait::new(&path, LOCK_STALL_TIMEOUT).unwrap();
+        assert!(second.poll().unwrap().is_none());
+        drop(first);
+        let second = second.poll().unwrap().expect("lock was released");
+        assert!(second._file.is_some());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn a_stalled_download_stops_the_wait() {
+        let path = model_file("stall");
+        let _held = LockWait::new(&path, LOCK_STALL_TIMEOUT).unwrap().poll().unwrap().unwrap();
+        let mut wait = LockWait::new(&path, Duration::from_secs(1)).unwrap();
+        assert!(wait.poll().unwrap().is_none());
+
+        // Progress on the shared partial file keeps the wait going
+        std::thread::sleep(Duration::from_millis(600));
+        std::fs::write(path.with_extension("onnx.part"), b"some").unwrap();
+        assert!(wait.poll().unwrap().is_none());
+        std::thread::sleep(Duration::from_millis(600));
+        assert!(wait.poll().unwrap().is_none());
+
+        std::thread::sleep(Duration::from_millis(500));
+        let lock = wait.poll().unwrap().expect("the wait gave up");
+        assert!(lock._file.is_none());
+        assert_ne!(lock.part_path, path.with_extension("onnx.part"));
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn a_model_downloaded_meanwhile_is_verified() {
+        let path = model_file("verify");
+        assert!(!downloaded_meanwhile(&path, None).unwrap());
+        std::fs::write(&path, b"model").unwrap();
+        let digest = file_sha256(&path).unwrap();
+        assert!(downloaded_meanwhile(&path, None).unwrap());
+        assert!(downloaded_meanwhile(&path, Some(&digest.to_ascii_uppercase())).unwrap());
+        assert!(!downloaded_meanwhile(&path, Some(&"0".repeat(64))).unwrap());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,148 @@
+//! Desktop notifications, for `--notify`.
+//!
+//! When removebg runs from a file-manager context menu, its terminal closes
+//! before anyone can read it; a notification says whether the cutout was
+//! saved, where, and whether it is likely to be empty. The desktop is reached
+//! through notify-rust behind the `notifications` feature; without it,
+//! [`desktop`] fails with a message saying how to build it in. Everything
+//! else goes through the [`Notifier`] trait.
+//!
+//! A notification that cannot be shown is not a failed removal, so errors
+//! here are plain messages for the caller to log rather than
+//! [`RemoveBgError`]s.
+
+use crate::analysis::CoverageWarning;
+use crate::error::RemoveBgError;
+use std::path::Path;
+
+/// Something that shows notifications to the user.
+pub trait Notifier {
+    /// Show a notification with a one-line `summary` and a longer `body`.
+    ///
+    /// # Errors
+    /// A description of the problem if the notification cannot be shown.
+    fn notify(&mut self, summary: &str, body: &str) -> Result<(), String>;
+}
+
+/// Notifications on the desktop: D-Bus on Linux and the BSDs, the
+/// Notification Center on macOS, and toasts on Windows.
+#[cfg(feature = "notifications")]
+pub struct DesktopNotifier;
+
+#[cfg(feature = "notifications")]
+impl Notifier for DesktopNotifier {
+    fn notify(&mut self, summary: &str, body: &str) -> Result<(), String> {
+        notify_rust::Notification::new()
+            .appname("removebg")
+            .summary(summary)
+            .body(body)
+            .show()
+            .map(drop)
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// The desktop's notifier.
+///
+/// # Errors
+/// A description of the problem if removebg was built without the
+/// `notifications` feature.
+pub fn desktop() -> Result<Box<dyn Notifier>, String> {
+    #[cfg(feature = "notifications")]
+    return Ok(Box::new(DesktopNotifier));
+    #[cfg(not(feature = "notifications"))]
+    Err("desktop notifications need removebg built with the `notifications` feature \
+         (cargo install removebg --features notifications)"
+        .into())
+}
+
+/// How a run ended, for [`notify_outcome`].
+#[derive(Debug, Clone, Copy)]
+pub enum Outcome<'a> {
+    /// The cutout was saved.
+    Saved {
+        /// Where it went: the output's file name, or "the clipboard".
+        output: &'a str,
+        /// Set if the mask's coverage suggests the cutout is empty or
+        /// unchanged.
+        warning: Option<CoverageWarning>,
+    },
+    /// Processing `input` failed with `error`.
+    Failed {
+        /// The input as given on the command line.
+        input: &'a str,
+        /// The error, as shown on stderr.
+        error: &'a RemoveBgError,
+    },
+}
+
+/// Tell the user how a run ended: where the cutout went on success, with any
+/// coverage warning, or the input's file name and the error on failure.
+///
+/// # Errors
+/// A description of the problem if the notification cannot be shown.
+pub fn notify_outcome(notifier: &mut dyn Notifier, outcome: &Outcome) -> Result<(), String> {
+    match *outcome {
+        Outcome::Saved { output, warning: None } => notifier.notify("Background removed", &format!("Saved to {}", output)),
+        Outcome::Saved { output, warning: Some(warning) } => notifier.notify(
+            "Background removed, with a warning",
+            &format!("Saved to {}\n{}", output, warning),
+        ),
+        Outcome::Failed { input, error } => {
+            let name = Path::new(input).file_name().map_or(input.into(), |name| name.to_string_lossy());
+            notifier.notify("Background removal failed", &format!("{}: {}", name, error))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A notifier that records what it was asked to show.
+    #[derive(Default)]
+    struct MockNotifier {
+        shown: Vec<(String, String)>,
+    }
+
+    impl Notifier for MockNotifier {
+        fn notify(&mut self, summary: &str, body: &str) -> Result<(), String> {
+            self.shown.push((summary.to_string(), body.to_string()));
+            Ok(())
+        }
+    }
+
+    fn shown(outcome: Outcome) -> (String, String) {
+        let mut notifier = MockNotifier::default();
+        notify_outcome(&mut notifier, &outcome).unwrap();
+        assert_eq!(notifier.shown.len(), 1);
+        notifier.shown.remove(0)
+    }
+
+    #[test]
+    fn success_names_the_output_file() {
+        let (summary, body) = shown(Outcome::Saved { output: "cat_nobg.png", warning: None });
+        assert_eq!(summary, "Background removed");
+        assert_eq!(body, "Saved to cat_nobg.png");
+    }
+
+    #[test]
+    fn coverage_warnings_are_included() {
+        let warning = Some(CoverageWarning::NothingDetected);
+        let (summary, body) = shown(Outcome::Saved { output: "cat_nobg.png", warning });
+        assert_eq!(summary, "Background removed, with a warning");
+        assert_eq!(body, "Saved to cat_nobg.png\nnothing was detected — output may be empty");
+
+        let warning = Some(CoverageWarning::NoBackground);
+        let (_, body) = shown(Outcome::Saved { output: "the clipboard", warning });
+        assert!(body.starts_with("Saved to the clipboard\nno background"), "{}", body);
+    }
+
+    #[test]
+    fn failure_names_the_input_and_error() {
+        let error = RemoveBgError::FileNotFound("/home/me/cat.png".into());
+        let (summary, body) = shown(Outcome::Failed { input: "/home/me/cat.png", error: &error });
+        assert_eq!(summary, "Background removal failed");
+        assert_eq!(body, format!("cat.png: {}", error));
+    }
+}
@@ -0,0 +1,345 @@
+//! NumPy `.npy` masks, for exchange with Python pipelines without a
+//! conversion step.
+//!
+//! Masks are written as C-order, little-endian float32 arrays of shape
+//! `(height, width)` with values in 0-1, which `numpy.load` returns as they
+//! are. They are read back from float32 arrays in 0-1 or uint8 arrays in
+//! 0-255. Only what `numpy.save` writes for such arrays is understood:
+//! format versions 1.0 to 3.0, C order, and those two dtypes. Anything else
+//! is an `InvalidMask` error naming what the file holds.
+
+use crate::core::{self, Gray16Image, Gray32FImage};
+use crate::error::{RemoveBgError, Result};
+use crate::options::DecodeLimits;
+use crate::output::AtomicFile;
+use image::{DynamicImage, GrayImage};
+use ndarray::ArrayViewD;
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// The first bytes of every `.npy` file, before the format version.
+const MAGIC: &[u8] = b"\x93NUMPY";
+
+/// Whether `path` names a `.npy` file, by extension.
+pub fn is_npy(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("npy"))
+}
+
+/// Write `array` to `writer` as a little-endian float32 `.npy` array in
+/// format version 1.0, with the header padded to 64-byte alignment as
+/// `numpy.save` does.
+pub fn write_f32(mut writer: impl Write, array: ArrayViewD<'_, f32>) -> std::io::Result<()> {
+    let shape = match array.shape() {
+        [length] => format!("({},)", length),
+        dims => format!("({})", dims.iter().map(usize::to_string).collect::<Vec<_>>().join(", ")),
+    };
+    let mut header = format!("{{'descr': '<f4', 'fortran_order': False, 'shape': {}, }}", shape);
+    // The magic, version and length take 10 bytes; the header ends in a
+    // newline and pads the data to 64-byte alignment
+    let unpadded = MAGIC.len() + 4 + header.len() + 1;
+    header.push_str(&" ".repeat(unpadded.next_multiple_of(64) - unpadded));
+    header.push('\n');
+
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[1, 0])?;
+    writer.write_all(&(header.len() as u16).to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+    for value in array.iter() {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    writer.flush()
+}
+
+/// Write `mask` to `path` atomically as a float32 `(height, width)` array,
+/// each 16-bit value scaled to 0-1 so nothing is lost to rounding.
+pub fn write_mask(mask: &Gray16Image, path: &Path) -> Result<()> {
+    let mask = core::float_mask(mask);
+    let shape = [mask.height() as usize, mask.width() as usize];
+    let array = ArrayViewD::from_shape(&shape[..], mask.as_raw()).expect("one value per pixel");
+    let mut file = AtomicFile::create(path)?;
+    write_f32(&mut file, array)?;
+    file.commit()
+}
+
+/// Element types of the arrays [`read_mask`] accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dtype {
+    Float32,
+    Uint8,
+}
+
+impl Dtype {
+    fn size(self) -> usize {
+        match self {
+            Dtype::Float32 => 4,
+            Dtype::Uint8 => 1,
+        }
+    }
+}
+
+/// The parts of a `.npy` header a mask needs.
+#[derive(Debug, PartialEq)]
+struct Header {
+    dtype: Dtype,
+    height: u32,
+    width: u32,
+}
+
+fn invalid(message: impl Into<String>) -> RemoveBgError {
+    RemoveBgError::InvalidMask(message.into())
+}
+
+/// Read a mask from a 2-D `.npy` array: float32 values in 0-1, clamped,
+/// with NaN as background, or uint8 values in 0-255.
+///
+/// # Returns
+/// A 16-bit grayscale image for float32 arrays and an 8-bit one for uint8.
+///
+/// # Errors
+/// * `InvalidMask` - If the file isn't a `.npy` array, or holds an array of
+///   another dtype, dimensionality or memory order
+/// * `ImageTooLarge` - If the array's shape exceeds `limits`
+/// * `IoError` - If reading fails
+pub fn read_mask(mut reader: impl Read, limits: &DecodeLimits) -> Result<DynamicImage> {
+    let header = read_header(&mut reader)?;
+    let needed = (header.width as usize)
+        .checked_mul(header.height as usize)
+        .and_then(|pixels| pixels.checked_mul(header.dtype.size()))
+        .ok_or_else(|| invalid(format!("npy mask shape ({}, {}) is too large", header.height, header.width)))?;
+    limits.check(header.width, header.height, header.dtype.size() as u64)?;
+    // Read as data arrives rather than trusting the header's size up front
+    let mut data = Vec::new();
+    reader.take(needed as u64).read_to_end(&mut data)?;
+    if data.len() < needed {
+        return Err(invalid(format!("npy array data ends after {} of {} bytes", data.len(), needed)));
+    }
+
+    let (width, height) = (header.width, header.height);
+    let image = match header.dtype {
+        Dtype::Uint8 => DynamicImage::ImageLuma8(GrayImage::from_raw(width, height, data).expect("one byte per pixel")),
+        Dtype::Float32 => {
+            let values = data.chunks_exact(4).map(|bytes| {
+                let value = f32::from_le_bytes(bytes.try_into().expect("4-byte chunks"));
+                if value.is_nan() { 0.0 } else { value.clamp(0.0, 1.0) }
+            });
+            let mask = Gray32FImage::from_raw(width, height, values.collect()).expect("one value per pixel");
+            DynamicImage::ImageLuma16(core::quantize_mask(&mask))
+        }
+    };
+    Ok(image)
+}
+
+/// Read and check the magic, version and header of a `.npy` file.
+fn read_header(reader: &mut impl Read) -> Result<Header> {
+    let mut preamble = [0u8; 8];
+    reader.read_exact(&mut preamble).map_err(|_| invalid("not an npy file: too short"))?;
+    if &preamble[..6] != MAGIC {
+        return Err(invalid("not an npy file: missing the \\x93NUMPY magic"));
+    }
+    let length = match preamble[6] {
+        1 => {
+            let mut length = [0u8; 2];
+            reader.read_exact(&mut length)?;
+            u16::from_le_bytes(length) as usize
+        }
+        2 | 3 => {
+            let mut length = [0u8; 4];
+            reader.read_exact(&mut length)?;
+            u32::from_le_bytes(length) as usize
+        }
+        major => return Err(invalid(format!("unsupported npy format version {}.{}", major, preamble[7]))),
+    };
+    // numpy refuses headers over 10 KB unless asked; a mask's is under 200 bytes
+    if length > 10_000 {
+        return Err(invalid(format!("npy header of {} bytes is too long", length)));
+    }
+    let mut header = vec![0u8; length];
+    reader.read_exact(&mut header).map_err(|_| invalid("npy header ends early"))?;
+    let header = String::from_utf8(header).map_err(|_| invalid("npy header is not text"))?;
+    parse_header(&header)
+}
+
+/// Parse the Python dict literal of a `.npy` header, such as
+/// `{'descr': '<f4', 'fortran_order': False, 'shape': (480, 640), }`.
+fn parse_header(header: &str) -> Result<Header> {
+    let descr = header_value(header, "descr")?;
+    let dtype = match descr.trim_matches(|c| c == '\'' || c == '"') {
+        "<f4" => Dtype::Float32,
+        "|u1" | "<u1" | "u1" => Dtype::Uint8,
+        other => {
+            return Err(invalid(format!(
+                "unsupported npy dtype '{}'; expected float32 ('<f4') or uint8 ('|u1')",
+                other
+            )))
+        }
+    };
+    match header_value(header, "fortran_order")? {
+        "False" => {}
+        "True" => {
+            return Err(invalid(
+                "Fortran-order npy arrays are not supported; save a C-order array (numpy.ascontiguousarray)",
+            ))
+        }
+        other => return Err(invalid(format!("invalid npy fortran_order '{}'", other))),
+    }
+
+    let shape = header_value(header, "shape")?;
+    let dims = shape
+        .trim_start_matches('(')
+        .trim_end_matches(')')
+        .split(',')
+        .map(str::trim)
+        .filter(|dim| !dim.is_empty())
+        .map(|dim| dim.parse::<u64>())
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|_| invalid(format!("invalid npy shape {}", shape)))?;
+    let [height, width] = dims[..] else {
+        return Err(invalid(format!("expected a 2-D (height, width) npy array, got shape {}", shape)));
+    };
+    let dimension = |dim: u64| u32::try_from(dim).ok().filter(|&dim| dim > 0);
+    match (dimension(height), dimension(width)) {
+        (Some(height), Some(width)) => Ok(Header { dtype, height, width }),
+        _ => Err(invalid(format!("npy mask shape {} has no pixels or is too large", shape))),
+    }
+}
+
+/// The text of the value of `key` in a header dict: a quoted string, a
+/// tuple or a bare word.
+fn header_value<'a>(header: &'a str, key: &str) -> Result<&'a str> {
+    let missing = || invalid(format!("npy header has no '{}'", key));
+    let start = [format!("'{}':", key), format!("\"{}\":", key)]
+        .iter()
+        .find_map(|pattern| header.find(pattern.as_str()).map(|at| at + pattern.len()))
+        .ok_or_else(missing)?;
+    let rest = header[start..].trim_start();
+    let end = match rest.chars().next() {
+        Some('(') => rest.find(')').map(|end| end + 1),
+        Some(quote @ ('\'' | '"')) => rest[1..].find(quote).map(|end| end + 2),
+        Some(_) => rest.find([',', '}']),
+        None => None,
+    };
+    end.map(|end| rest[..end].trim()).ok_or_else(missing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+    use ndarray::ArrayD;
+
+    /// A format 1.0 file with `header` and `data`.
+    fn npy(header: &str, data: &[u8]) -> Vec<u8> {
+        let mut file = b"\x93NUMPY\x01\x00".to_vec();
+        file.extend((header.len() as u16).to_le_bytes());
+        file.extend(header.as_bytes());
+        file.extend(data);
+        file
+    }
+
+    #[test]
+    fn header_is_aligned_and_data_follows() {
+        let array = ArrayD::from_shape_vec(vec![1, 2, 3], vec![0.0f32, 0.5, 1.0, 1.5, 2.0, 2.5]).unwrap();
+        let mut data = Vec::new();
+        write_f32(&mut data, array.view()).unwrap();
+
+        assert_eq!(&data[..8], b"\x93NUMPY\x01\x00");
+        let header_len = u16::from_le_bytes([data[8], data[9]]) as usize;
+        assert_eq!((10 + header_len) % 64, 0);
+        let header = std::str::from_utf8(&data[10..10 + header_len]).unwrap();
+        assert!(header.starts_with("{'descr': '<f4', 'fortran_order': False, 'shape': (1, 2, 3), }"));
+        assert!(header.ends_with('\n'));
+        let values: Vec<f32> =
+            data[10 + header_len..].chunks(4).map(|b| f32::from_le_bytes(b.try_into().unwrap())).collect();
+        assert_eq!(values, [0.0, 0.5, 1.0, 1.5, 2.0, 2.5]);
+    }
+
+    #[test]
+    fn masks_round_trip_exactly() {
+        let dir = std::env::temp_dir().join(format!("removebg-npy-mask-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("photo_mask.npy");
+        let mask = Gray16Image::from_fn(5, 3, |x, y| Luma([(x * 13_107 + y * 1_001) as u16]));
+        write_mask(&mask, &path).unwrap();
+        let read = read_mask(std::fs::File::open(&path).unwrap(), &DecodeLimits::default()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(read.into_luma16(), mask);
+    }
+
+    #[test]
+    fn reads_uint8_and_clamps_float_values() {
+        let header = "{'descr': '|u1', 'fortran_order': False, 'shape': (1, 3), }\n";
+        let mask = read_mask(&npy(header, &[0, 128, 255])[..], &DecodeLimits::default()).unwrap();
+        assert_eq!(mask.as_luma8().unwrap().as_raw(), &[0, 128, 255]);
+
+        let header = "{'descr': '<f4', 'fortran_order': False, 'shape': (2, 2), }\n";
+        let data: Vec<u8> = [-0.5f32, 0.5, 1.5, f32::NAN].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let mask = read_mask(&npy(header, &data)[..], &DecodeLimits::default()).unwrap();
+        assert_eq!(mask.as_luma16().unwrap().as_raw(), &[0, 32_768, 65_535, 0]);
+    }
+
+    #[test]
+    fn unsupported_arrays_are_named_precisely() {
+        for (header, data, message) in [
+            (
+                "{'descr': '<f8', 'fortran_order': False, 'shape': (2, 2), }",
+                &[0u8; 32][..],
+                "unsupported npy dtype '<f8'; expected float32 ('<f4') or uint8 ('|u1')",
+            ),
+            (
+                "{'descr': '>f4', 'fortran_order': False, 'shape': (2, 2), }",
+                &[0; 16],
+                "unsupported npy dtype '>f4'; expected float32 ('<f4') or uint8 ('|u1')",
+            ),
+            (
+                "{'descr': '<f4', 'fortran_order': True, 'shape': (2, 2), }",
+                &[0; 16],
+                "Fortran-order npy arrays are not supported; save a C-order array (numpy.ascontiguousarray)",
+            ),
+            (
+                "{'descr': '|u1', 'fortran_order': False, 'shape': (2, 2, 3), }",
+                &[0; 12],
+                "expected a 2-D (height, width) npy array, got shape (2, 2, 3)",
+            ),
+            ("{'descr': '|u1', 'fortran_order': False, 'shape': (4,), }", &[0; 4], "expected a 2-D (height, width) npy array, got shape (4,)"),
+            ("{'descr': '|u1', 'fortran_order': False, 'shape': (0, 4), }", &[], "npy mask shape (0, 4) has no pixels or is too large"),
+            ("{'descr': '|u1', 'fortran_order': False, 'shape': (2, 2), }", &[0; 3], "npy array data ends after 3 of 4 bytes"),
+            ("{'descr': '|u1', 'shape': (2, 2), }", &[0; 4], "npy header has no 'fortran_order'"),
+        ] {
+            match read_mask(&npy(header, data)[..], &DecodeLimits::default()) {
+                Err(RemoveBgError::InvalidMask(error)) => assert_eq!(error, message),
+                other => panic!("{}: {:?}", header, other.map(|mask| (mask.width(), mask.height()))),
+            }
+        }
+        assert!(matches!(read_mask(&b"\x89PNG\r\n\x1a\n"[..], &DecodeLimits::default()), Err(RemoveBgError::InvalidMask(_))));
+    }
+
+    #[test]
+    fn huge_shapes_are_refused_before_reading() {
+        // 2^31 × 2^31 float32 values wrap a 64-bit byte count to 0
+        let header = "{'descr': '<f4', 'fortran_order': False, 'shape': (2147483648, 2147483648), }\n";
+        let no_limits = DecodeLimits { max_pixels: None, max_memory: None, max_download_size: None };
+        match read_mask(&npy(header, &[])[..], &no_limits) {
+            Err(RemoveBgError::InvalidMask(error)) => {
+                assert_eq!(error, "npy mask shape (2147483648, 2147483648) is too large")
+            }
+            other => panic!("{:?}", other.map(|mask| (mask.width(), mask.height()))),
+        }
+
+        let header = "{'descr': '|u1', 'fortran_order': False, 'shape': (100, 100), }\n";
+        let limits = DecodeLimits { max_pixels: Some(9_999), ..DecodeLimits::default() };
+        assert!(matches!(
+            read_mask(&npy(header, &[0; 10_000])[..], &limits),
+            Err(RemoveBgError::ImageTooLarge { width: 100, height: 100, limit: 9_999 })
+        ));
+    }
+
+    #[test]
+    fn later_format_versions_have_longer_lengths() {
+        let header = "{'descr': '|u1', 'fortran_order': False, 'shape': (1, 2), }\n";
+        let mut file = b"\x93NUMPY\x02\x00".to_vec();
+        file.extend((header.len() as u32).to_le_bytes());
+        file.extend(header.as_bytes());
+        file.extend([7, 9]);
+        assert_eq!(read_mask(&file[..], &DecodeLimits::default()).unwrap().as_luma8().unwrap().as_raw(), &[7, 9]);
+    }
+}
@@ -0,0 +1,1868 @@
+//! Configuration options for background removal.
+//!
+//! This module defines the options that control how the U2-Net model is run,
+//! such as which hardware execution provider ONNX Runtime should use and how
+//! its thread pools are sized.
+
+use crate::error::RemoveBgError;
+use crate::model::Model;
+use crate::output::{OutputEncoder, OutputFormat, PngOptions};
+use crate::progress::DownloadProgress;
+use crate::report::Rect;
+use std::borrow::Cow;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use web_time::Instant;
+
+/// Hardware device used to run model inference.
+///
+/// Non-CPU devices require the matching cargo feature (`coreml` or `directml`).
+/// If the requested provider is unavailable at runtime, inference falls back to
+/// the CPU with a warning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Device {
+    /// Default ONNX Runtime CPU execution provider.
+    #[default]
+    Cpu,
+    /// Apple CoreML execution provider (macOS / Apple Silicon).
+    CoreMl,
+    /// DirectML execution provider (Windows, any DirectX 12 GPU).
+    DirectMl,
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Device::Cpu => "cpu",
+            Device::CoreMl => "coreml",
+            Device::DirectMl => "directml",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Device {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "cpu" => Ok(Device::Cpu),
+            "coreml" => Ok(Device::CoreMl),
+            "directml" => Ok(Device::DirectMl),
+            other => Err(format!(
+                "unknown device '{}' (expected one of: cpu, coreml, directml)",
+                other
+            )),
+        }
+    }
+}
+
+/// Inference engine that runs the built-in models.
+///
+/// The default is ONNX Runtime when removebg is built with the `ort` feature
+/// (on by default), and tract otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Backend {
+    /// ONNX Runtime (`ort` feature), the fastest engine, with hardware
+    /// acceleration through [`Device`].
+    Ort,
+    /// tract (`backend-tract` feature), a pure-Rust engine that needs no
+    /// native library. Several times slower than ONNX Runtime, always on
+    /// the CPU.
+    Tract,
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        if cfg!(feature = "ort") {
+            Backend::Ort
+        } else {
+            Backend::Tract
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Backend::Ort => "ort",
+            Backend::Tract => "tract",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ort" | "onnxruntime" => Ok(Backend::Ort),
+            "tract" => Ok(Backend::Tract),
+            other => Err(format!("unknown backend '{}' (expected one of: ort, tract)", other)),
+        }
+    }
+}
+
+/// ONNX Runtime graph optimization level.
+///
+/// Levels are numbered 0-3, matching the `--ort-opt-level` CLI flag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum OptimizationLevel {
+    /// Level 0: disable all graph optimizations.
+    Disable,
+    /// Level 1: semantics-preserving rewrites such as constant folding.
+    Basic,
+    /// Level 2: extended optimizations including node fusions.
+    Extended,
+    /// Level 3: all optimizations, including memory layout changes (ORT default).
+    #[default]
+    All,
+}
+
+impl fmt::Display for OptimizationLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = match self {
+            OptimizationLevel::Disable => 0,
+            OptimizationLevel::Basic => 1,
+            OptimizationLevel::Extended => 2,
+            OptimizationLevel::All => 3,
+        };
+        write!(f, "{}", level)
+    }
+}
+
+impl FromStr for OptimizationLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "0" | "disable" => Ok(OptimizationLevel::Disable),
+            "1" | "basic" => Ok(OptimizationLevel::Basic),
+            "2" | "extended" => Ok(OptimizationLevel::Extended),
+            "3" | "all" => Ok(OptimizationLevel::All),
+            other => Err(format!(
+                "invalid optimization level '{}' (expected 0-3)",
+                other
+            )),
+        }
+    }
+}
+
+/// Choose the model for each image instead of using a fixed one
+/// (`--model auto`).
+///
+/// Every image is first segmented with the small u2netp. A mask shaped like
+/// a person (a tall region, centered and reaching the bottom edge) is redone
+/// with u2net-human-seg; otherwise, a mask whose
+/// [confidence](crate::analysis::mask_confidence) is below 0.5 is redone
+/// with the full u2net, and any other u2netp mask is kept. A person mask
+/// with a low confidence goes on to u2net as well. The last model run
+/// produces the output, and the report's
+/// [`ModelInfo::selection`](crate::ModelInfo::selection) lists the models
+/// run and why the last was kept.
+///
+/// The models come from the shared remover cache, as with the free
+/// functions, whatever remover processes the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct AutoModel {
+    /// Most models run on one image, counting u2netp; at least 1. 1 always
+    /// keeps the u2netp mask.
+    pub max_models: u32,
+}
+
+impl Default for AutoModel {
+    fn default() -> Self {
+        AutoModel { max_models: 2 }
+    }
+}
+
+/// Trade quality for time when an image can't be processed in full before a
+/// deadline, for interactive services that would rather answer with a rougher
+/// cutout than an error.
+///
+/// Before the model runs, the time the image has taken so far plus the
+/// estimated inference time is compared with `deadline`. While it doesn't
+/// fit, quality is given up one step at a time (see
+/// [`Downgrade`](crate::Downgrade)): the model is switched to u2netp, then
+/// two-pass inference, tiling and the guided filter are skipped, then the
+/// model runs at a smaller size, as large as still fits. The size is only
+/// lowered from a fixed or automatic [`inference_size`](RemoveBgOptions::inference_size),
+/// since the native size runs on a session that may not accept others. The
+/// steps taken are listed in
+/// [`ModelInfo::downgrades`](crate::ModelInfo::downgrades).
+///
+/// Inference times come from the process-wide
+/// [`Calibration`](crate::fallback::Calibration), measured by the warm-up
+/// run and updated after every run; until the model or another one has been
+/// measured, images are processed as configured. Like
+/// [`auto_model`](RemoveBgOptions::auto_model), u2netp comes from the shared
+/// remover cache, so servers should load it ahead of time. Not used with
+/// `auto_model` or by removers with a custom model. The deadline is a
+/// target, not a limit: see [`timeout`](RemoveBgOptions::timeout) for that.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct QualityFallback {
+    /// Time from reading an input to the end of its model run that quality
+    /// is given up to meet.
+    #[cfg_attr(feature = "serde", serde(rename = "deadline_ms", with = "crate::serialization::millis"))]
+    pub deadline: Duration,
+}
+
+/// How the side outputs of a multi-output model such as U2-Net are combined
+/// into one mask.
+///
+/// U2-Net produces seven outputs, from the fused map (d0) to the deepest,
+/// coarsest stage (d6). Models with a single output always use it, whatever
+/// the fusion setting.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Fusion {
+    /// Use the first output only (U2-Net's fused map).
+    #[default]
+    First,
+    /// Average the first `n` outputs; all outputs if the model has fewer.
+    Mean(usize),
+    /// Weighted sum of the first outputs, one weight per output starting
+    /// with the first. Weights are normalized to sum to 1; a model with
+    /// several outputs needs exactly one weight per output.
+    Weighted(Vec<f32>),
+}
+
+impl Fusion {
+    /// Check the settings that don't depend on the model.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        match self {
+            Fusion::First => Ok(()),
+            Fusion::Mean(0) => Err("mean fusion needs at least one output".into()),
+            Fusion::Mean(_) => Ok(()),
+            Fusion::Weighted(weights) => {
+                if weights.is_empty() {
+                    return Err("weighted fusion needs at least one weight".into());
+                }
+                if weights.iter().any(|w| !(w.is_finite() && *w >= 0.0)) {
+                    return Err(format!("fusion weights must be non-negative numbers, got {:?}", weights));
+                }
+                if weights.iter().sum::<f32>() <= 0.0 {
+                    return Err("fusion weights must not all be zero".into());
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Normalized weights of the outputs to combine, for a model with
+    /// `outputs` outputs.
+    pub(crate) fn weights(&self, outputs: usize) -> Result<Vec<f32>, String> {
+        self.validate()?;
+        if outputs <= 1 {
+            return Ok(vec![1.0]);
+        }
+        let weights = match self {
+            Fusion::First => vec![1.0],
+            Fusion::Mean(n) => vec![1.0; (*n).min(outputs)],
+            Fusion::Weighted(weights) if weights.len() != outputs => {
+                return Err(format!(
+                    "{} fusion weights given but the model has {} outputs",
+                    weights.len(),
+                    outputs
+                ));
+            }
+            Fusion::Weighted(weights) => weights.clone(),
+        };
+        let sum: f32 = weights.iter().sum();
+        Ok(weights.into_iter().map(|w| w / sum).collect())
+    }
+}
+
+impl fmt::Display for Fusion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fusion::First => f.write_str("first"),
+            Fusion::Mean(n) => write!(f, "mean:{}", n),
+            Fusion::Weighted(weights) => {
+                let weights: Vec<String> = weights.iter().map(|w| w.to_string()).collect();
+                write!(f, "weighted:{}", weights.join(","))
+            }
+        }
+    }
+}
+
+impl FromStr for Fusion {
+    type Err = String;
+
+    /// Parse `first`, `mean:N`, or `weighted:W1,W2,...`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid mask fusion '{}' (expected first, mean:N, or weighted:W1,W2,...)", s);
+        let lower = s.to_ascii_lowercase();
+        let fusion = match lower.split_once(':') {
+            None if lower == "first" => Fusion::First,
+            Some(("mean", n)) => Fusion::Mean(n.trim().parse().map_err(|_| invalid())?),
+            Some(("weighted", weights)) => Fusion::Weighted(
+                weights
+                    .split(',')
+                    .map(|w| w.trim().parse::<f32>())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| invalid())?,
+            ),
+            _ => return Err(invalid()),
+        };
+        fusion.validate()?;
+        Ok(fusion)
+    }
+}
+
+/// One step of mask post-processing, applied to the full-resolution mask in
+/// the order listed in [`RemoveBgOptions::mask_ops`].
+///
+/// Written as `NAME` or `NAME=VALUE`, the same on the command line and in
+/// stored jobs, e.g. `dilate=3`, `levels=0.1:0.9` or `keep-largest=2:10`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MaskOp {
+    /// Hard cut: values at or above this level (0-1) become opaque, the
+    /// others transparent (`threshold=T`).
+    Threshold(f32),
+    /// Linearly remap the range `(low, high)` (0-1) to 0-1, clamping values
+    /// outside it, like [`mask_levels`](RemoveBgOptions::mask_levels)
+    /// (`levels=LOW:HIGH`).
+    Levels(f32, f32),
+    /// Raise values to this power, like
+    /// [`mask_gamma`](RemoveBgOptions::mask_gamma) (`gamma=G`).
+    Gamma(f32),
+    /// Shrink the subject: each value becomes the smallest within a square
+    /// of this radius in pixels (`erode=PX`).
+    Erode(u32),
+    /// Grow the subject: each value becomes the largest within a square of
+    /// this radius in pixels (`dilate=PX`).
+    Dilate(u32),
+    /// Soften the edges with a Gaussian blur of this sigma in pixels
+    /// (`feather=SIGMA`).
+    Feather(f32),
+    /// Fill holes enclosed by the subject up to this area in pixels (`None`
+    /// = no limit), like [`fill_holes`](RemoveBgOptions::fill_holes)
+    /// (`fill-holes` or `fill-holes=PIXELS`).
+    FillHoles(Option<usize>),
+    /// Keep the `count` largest regions, counting regions at most `gap`
+    /// pixels apart as one, like
+    /// [`keep_largest_component`](RemoveBgOptions::keep_largest_component)
+    /// (`keep-largest`, `keep-largest=N` or `keep-largest=N:GAP`).
+    KeepLargest {
+        /// Regions kept, at least 1.
+        count: usize,
+        /// Largest distance in pixels between parts of one region.
+        gap: u32,
+    },
+}
+
+impl MaskOp {
+    /// Check the operator's parameters.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        match *self {
+            MaskOp::Threshold(level) if !(0.0..=1.0).contains(&level) => {
+                Err(format!("threshold must be from 0 to 1, got {}", level))
+            }
+            MaskOp::Levels(low, high) if !(0.0 <= low && low <= high && high <= 1.0) => {
+                Err(format!("levels must satisfy 0 <= low <= high <= 1, got {} and {}", low, high))
+            }
+            MaskOp::Gamma(gamma) if !(gamma.is_finite() && gamma > 0.0) => {
+                Err(format!("gamma must be above 0, got {}", gamma))
+            }
+            MaskOp::Feather(sigma) if !(sigma.is_finite() && sigma >= 0.0) => {
+                Err(format!("feather sigma must be a non-negative number, got {}", sigma))
+            }
+            MaskOp::KeepLargest { count: 0, .. } => Err("keep-largest must keep at least 1 region".into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for MaskOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MaskOp::Threshold(level) => write!(f, "threshold={}", level),
+            MaskOp::Levels(low, high) => write!(f, "levels={}:{}", low, high),
+            MaskOp::Gamma(gamma) => write!(f, "gamma={}", gamma),
+            MaskOp::Erode(radius) => write!(f, "erode={}", radius),
+            MaskOp::Dilate(radius) => write!(f, "dilate={}", radius),
+            MaskOp::Feather(sigma) => write!(f, "feather={}", sigma),
+            MaskOp::FillHoles(None) => f.write_str("fill-holes"),
+            MaskOp::FillHoles(Some(max)) => write!(f, "fill-holes={}", max),
+            MaskOp::KeepLargest { count, gap: 0 } => write!(f, "keep-largest={}", count),
+            MaskOp::KeepLargest { count, gap } => write!(f, "keep-largest={}:{}", count, gap),
+        }
+    }
+}
+
+impl FromStr for MaskOp {
+    type Err = String;
+
+    /// Parse `threshold=T`, `levels=LOW:HIGH`, `gamma=G`, `erode=PX`,
+    /// `dilate=PX`, `feather=SIGMA`, `fill-holes[=PIXELS]` or
+    /// `keep-largest[=N[:GAP]]`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = match s.split_once('=') {
+            Some((name, value)) => (name.trim().to_ascii_lowercase(), Some(value.trim())),
+            None => (s.trim().to_ascii_lowercase(), None),
+        };
+        let invalid = |expected: &str| format!("invalid mask operation '{}' (expected {})", s, expected);
+        let number = |expected: &str| -> Result<f32, String> {
+            value.and_then(|v| v.parse::<f32>().ok()).filter(|v| v.is_finite()).ok_or_else(|| invalid(expected))
+        };
+        let pixels = |expected: &str| -> Result<u32, String> {
+            value.and_then(|v| v.parse::<u32>().ok()).ok_or_else(|| invalid(expected))
+        };
+        let op = match name.as_str() {
+            "threshold" => MaskOp::Threshold(number("threshold=T with T from 0 to 1, like threshold=0.5")?),
+            "levels" => {
+                let expected = "levels=LOW:HIGH from 0 to 1, like levels=0.1:0.9";
+                let (low, high) = value.and_then(|v| v.split_once(':')).ok_or_else(|| invalid(expected))?;
+                match (low.trim().parse::<f32>(), high.trim().parse::<f32>()) {
+                    (Ok(low), Ok(high)) => MaskOp::Levels(low, high),
+                    _ => return Err(invalid(expected)),
+                }
+            }
+            "gamma" => MaskOp::Gamma(number("gamma=G with G above 0, like gamma=0.7")?),
+            "erode" => MaskOp::Erode(pixels("erode=PX with a whole number of pixels, like erode=2")?),
+            "dilate" => MaskOp::Dilate(pixels("dilate=PX with a whole number of pixels, like dilate=3")?),
+            "feather" => MaskOp::Feather(number("feather=SIGMA in pixels, like feather=2.5")?),
+            "fill-holes" => match value {
+                None => MaskOp::FillHoles(None),
+                Some(max) => MaskOp::FillHoles(Some(
+                    max.parse().map_err(|_| invalid("fill-holes or fill-holes=PIXELS, like fill-holes=500"))?,
+                )),
+            },
+            "keep-largest" => {
+                let expected = "keep-largest, keep-largest=N or keep-largest=N:GAP, like keep-largest=2:10";
+                let (count, gap) = match value.map(|v| v.split_once(':').unwrap_or((v, "0"))) {
+                    None => (Ok(1), Ok(0)),
+                    Some((count, gap)) => (count.trim().parse::<usize>(), gap.trim().parse::<u32>()),
+                };
+                match (count, gap) {
+                    (Ok(count), Ok(gap)) => MaskOp::KeepLargest { count, gap },
+                    _ => return Err(invalid(expected)),
+                }
+            }
+            "" => return Err(format!("empty mask operation in '{}'", s)),
+            _ => {
+                return Err(format!(
+                    "unknown mask operation '{}' (expected threshold, levels, gamma, erode, dilate, feather, fill-holes or keep-largest)",
+                    name
+                ))
+            }
+        };
+        op.validate().map_err(|e| format!("invalid mask operation '{}': {}", s, e))?;
+        Ok(op)
+    }
+}
+
+/// Filter the model's mask is resized to the image with.
+///
+/// The mask is resized in floating point and clamped afterwards, whatever
+/// the filter. Filters with negative lobes (`CatmullRom`, `Lanczos3`) are
+/// slightly sharper but ring next to hard edges, leaving faint bands in
+/// flat areas of the mask; `Triangle` never overshoots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum MaskFilter {
+    /// Nearest neighbor: blocky edges at the model's resolution.
+    Nearest,
+    /// Bilinear interpolation (default).
+    #[default]
+    Triangle,
+    /// Catmull-Rom cubic.
+    CatmullRom,
+    /// Lanczos with a window of 3, the filter used before this option existed.
+    Lanczos3,
+}
+
+impl fmt::Display for MaskFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            MaskFilter::Nearest => "nearest",
+            MaskFilter::Triangle => "triangle",
+            MaskFilter::CatmullRom => "catmullrom",
+            MaskFilter::Lanczos3 => "lanczos3",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for MaskFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "nearest" => Ok(MaskFilter::Nearest),
+            "triangle" | "bilinear" => Ok(MaskFilter::Triangle),
+            "catmullrom" | "catmull-rom" => Ok(MaskFilter::CatmullRom),
+            "lanczos3" | "lanczos" => Ok(MaskFilter::Lanczos3),
+            other => Err(format!(
+                "invalid mask filter '{}' (expected nearest, triangle, catmullrom, or lanczos3)",
+                other
+            )),
+        }
+    }
+}
+
+/// File format of the mask sidecar of
+/// [`save_mask`](RemoveBgOptions::save_mask).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum MaskFormat {
+    /// 16-bit grayscale PNG (default).
+    #[default]
+    Png,
+    /// NumPy float32 `(height, width)` array in 0-1 (see [`npy`](crate::npy)).
+    Npy,
+}
+
+impl MaskFormat {
+    /// File extension of the format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            MaskFormat::Png => "png",
+            MaskFormat::Npy => "npy",
+        }
+    }
+}
+
+impl fmt::Display for MaskFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+impl FromStr for MaskFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "png" => Ok(MaskFormat::Png),
+            "npy" => Ok(MaskFormat::Npy),
+            other => Err(format!("invalid mask format '{}' (expected png or npy)", other)),
+        }
+    }
+}
+
+/// What replaces the removed background.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Background {
+    /// Keep the background transparent (default).
+    #[default]
+    Transparent,
+    /// Flatten the cutout onto a solid RGB color, producing an opaque image.
+    Color([u8; 3]),
+    /// Flatten onto the dominant color of the input's own background, so a
+    /// product shot on off-white stays off-white. Falls back to white when
+    /// almost none of the image is background; the color used is reported
+    /// as [`RemovalReport::background_color`](crate::RemovalReport::background_color).
+    Auto,
+}
+
+impl fmt::Display for Background {
+    /// `transparent`, `auto`, or `#rrggbb`, which parse back to the same value.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Background::Transparent => f.write_str("transparent"),
+            Background::Auto => f.write_str("auto"),
+            Background::Color([r, g, b]) => write!(f, "#{:02x}{:02x}{:02x}", r, g, b),
+        }
+    }
+}
+
+impl FromStr for Background {
+    type Err = String;
+
+    /// Parse a color name (`white`, `black`, ...), `#rrggbb`/`rrggbb` hex,
+    /// `transparent`, or `auto`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let color = match s.to_ascii_lowercase().as_str() {
+            "transparent" | "none" => return Ok(Background::Transparent),
+            "auto" => return Ok(Background::Auto),
+            "white" => [255, 255, 255],
+            "black" => [0, 0, 0],
+            "gray" | "grey" => [128, 128, 128],
+            "red" => [255, 0, 0],
+            "green" => [0, 255, 0],
+            "blue" => [0, 0, 255],
+            other => {
+                let hex = other.strip_prefix('#').unwrap_or(other);
+                if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(format!(
+                        "invalid color '{}' (expected a name like 'white' or hex like '#ffffff')",
+                        s
+                    ));
+                }
+                let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap_or(0);
+                [channel(0), channel(2), channel(4)]
+            }
+        };
+        Ok(Background::Color(color))
+    }
+}
+
+/// Cooperative cancellation flag shared between a caller and running operations.
+///
+/// Clones share the same flag. Operations check it between pipeline stages and
+/// inside long loops, and stop with [`RemoveBgError::Cancelled`] once it is set.
+/// The same checks enforce [`RemoveBgOptions::timeout`].
+///
+/// # Examples
+/// ```no_run
+/// use removebg::{remove_background_with_options, CancellationToken, RemoveBgOptions};
+///
+/// let cancel = CancellationToken::new();
+/// let options = RemoveBgOptions { cancel: cancel.clone(), ..Default::default() };
+///
+/// // From another thread, e.g. a "Cancel" button handler
+/// let handle = std::thread::spawn(move || cancel.cancel());
+///
+/// let result = remove_background_with_options("photo.jpg", None, &options);
+/// # handle.join().unwrap();
+/// # drop(result);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    /// When the operation holding this copy started, and how long it may
+    /// take (see [`expiring`](Self::expiring)).
+    deadline: Option<(Instant, Duration)>,
+}
+
+impl CancellationToken {
+    /// Create a token that is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request cancellation of all operations using this token.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether cancellation has been requested.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// A token sharing this one's flag that also expires `timeout` from
+    /// now. A deadline this token already has is kept, so operations that
+    /// call others don't extend their budget.
+    pub(crate) fn expiring(&self, timeout: Option<Duration>) -> Self {
+        let deadline = self.deadline.or_else(|| timeout.map(|timeout| (Instant::now(), timeout)));
+        CancellationToken { cancelled: self.cancelled.clone(), deadline }
+    }
+
+    /// Return `Err(Cancelled)` if cancellation has been requested, or
+    /// `Err(TimedOut)` once the deadline has passed.
+    pub(crate) fn check(&self) -> Result<(), RemoveBgError> {
+        if self.is_cancelled() {
+            return Err(RemoveBgError::Cancelled);
+        }
+        match self.deadline {
+            Some((started, timeout)) if started.elapsed() > timeout => {
+                Err(RemoveBgError::TimedOut { elapsed: started.elapsed(), stage: None })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// How an ONNX Runtime session holds the model and allocates memory.
+///
+/// The defaults favor small peak memory for the large models: the model file
+/// is memory-mapped, so the kernel can page the weights instead of holding a
+/// second copy in the heap. The tract engine ignores these settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct SessionMemoryOptions {
+    /// Memory-map the model file and create the session from the mapping.
+    ///
+    /// When mapping fails (some network and FUSE file systems don't support
+    /// it), the model is read as with `false`, after a debug log message.
+    pub mmap_model: bool,
+    /// Let ONNX Runtime keep freed memory in its CPU arena for reuse. Off,
+    /// every tensor goes back to the system allocator: slower, but the
+    /// process shrinks between runs.
+    pub arena: bool,
+    /// Largest size in bytes the CPU arena may grow to; `None` = no limit.
+    ///
+    /// The limited arena is shared by every session in the process and is
+    /// created with the first limit asked for.
+    pub arena_limit: Option<usize>,
+}
+
+impl Default for SessionMemoryOptions {
+    fn default() -> Self {
+        SessionMemoryOptions { mmap_model: true, arena: true, arena_limit: None }
+    }
+}
+
+/// Which allocator ONNX Runtime sessions use for CPU tensors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "lowercase"))]
+pub enum OrtAllocator {
+    /// Each session has its own CPU arena, or none with
+    /// [`SessionMemoryOptions::arena`] off.
+    #[default]
+    Session,
+    /// One arena registered with the environment is shared by every session,
+    /// so memory freed by one model is reused by the next. Also used whenever
+    /// [`SessionMemoryOptions::arena_limit`] is set.
+    Shared,
+}
+
+/// Settings of the process-wide ONNX Runtime environment, for embedding the
+/// library in hosts that restrict threads or system calls, such as workers
+/// in a seccomp sandbox.
+///
+/// ONNX Runtime has one environment per process, created with the first
+/// session: the settings of the first remover using the `ort` engine apply,
+/// and later removers asking for others share it after a warning. The tract
+/// engine ignores these settings.
+///
+/// # Examples
+/// Run inference on the calling thread only, without telemetry:
+/// ```
+/// use removebg::{OrtEnvironmentConfig, RemoveBgOptions};
+///
+/// let options = RemoveBgOptions {
+///     ort_environment: OrtEnvironmentConfig { telemetry: false, global_threads: Some(0), ..Default::default() },
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct OrtEnvironmentConfig {
+    /// Let ONNX Runtime send telemetry events. Only Microsoft's Windows
+    /// builds send any; the builds `ort` downloads never do.
+    pub telemetry: bool,
+    /// Run every session on one global thread pool with this many threads
+    /// besides the calling thread; `Some(0)` creates no threads and runs
+    /// inference on the caller's thread, slower but allowed where creating
+    /// threads or reading the CPU count from `/proc` is not. `None` gives
+    /// each session its own pools, sized by `intra_threads` and
+    /// `inter_threads`, which a global pool replaces.
+    pub global_threads: Option<usize>,
+    /// Allocator of CPU tensors.
+    pub allocator: OrtAllocator,
+    /// An environment the application created itself, with `ort::init()`
+    /// and `Environment::current()`, to use instead of creating one; the
+    /// settings above are then the application's. Creating a remover fails
+    /// if it isn't the process's current environment.
+    #[cfg(feature = "ort")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub environment: Option<Arc<ort::environment::Environment>>,
+}
+
+impl Default for OrtEnvironmentConfig {
+    fn default() -> Self {
+        OrtEnvironmentConfig {
+            telemetry: true,
+            global_threads: None,
+            allocator: OrtAllocator::Session,
+            #[cfg(feature = "ort")]
+            environment: None,
+        }
+    }
+}
+
+/// Limits applied when decoding input images.
+///
+/// They protect against inputs that would otherwise allocate many gigabytes
+/// while decoding and processing, whether crafted or just absurdly large.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct DecodeLimits {
+    /// Largest accepted image in pixels (width × height); `None` = no limit.
+    pub max_pixels: Option<u64>,
+    /// Largest decoded image size in bytes; `None` = no limit.
+    pub max_memory: Option<u64>,
+    /// Largest accepted download in bytes for URL inputs; `None` = no limit.
+    pub max_download_size: Option<u64>,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        DecodeLimits {
+            max_pixels: Some(80_000_000),
+            max_memory: Some(1024 * 1024 * 1024),
+            max_download_size: Some(100 * 1024 * 1024),
+        }
+    }
+}
+
+impl DecodeLimits {
+    /// Return `Err(ImageTooLarge)` if an image of this size exceeds the limits.
+    ///
+    /// `bytes_per_pixel` is the decoded pixel size; `max_memory` is reported
+    /// as the pixel count it allows at that size.
+    pub(crate) fn check(&self, width: u32, height: u32, bytes_per_pixel: u64) -> Result<(), RemoveBgError> {
+        let by_memory = self.max_memory.map(|bytes| bytes / bytes_per_pixel.max(1));
+        let limit = match (self.max_pixels, by_memory) {
+            (Some(pixels), Some(memory)) => pixels.min(memory),
+            (pixels, memory) => pixels.or(memory).unwrap_or(u64::MAX),
+        };
+        if width as u64 * height as u64 > limit {
+            return Err(RemoveBgError::ImageTooLarge { width, height, limit });
+        }
+        Ok(())
+    }
+
+    /// Limits in the form the image crate's decoders take.
+    pub(crate) fn to_image_limits(self) -> image::Limits {
+        let mut limits = image::Limits::no_limits();
+        limits.max_alloc = self.max_memory;
+        limits
+    }
+}
+
+/// Settings for tiled high-resolution inference.
+///
+/// The image is split into overlapping `size`×`size` tiles, each run through
+/// the model separately, and the tile masks are blended across the overlaps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct TileOptions {
+    /// Edge length of a tile in pixels.
+    pub size: u32,
+    /// Overlap between neighboring tiles in pixels; clamped to half the tile size.
+    pub overlap: u32,
+}
+
+impl Default for TileOptions {
+    fn default() -> Self {
+        TileOptions { size: 1024, overlap: 128 }
+    }
+}
+
+/// Settings for sticker mode: an outline and a drop shadow around the cutout.
+///
+/// Layers are drawn back to front: shadow, outline, subject. The canvas grows
+/// where the outline or shadow would otherwise be cut off at the image edge.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct StickerOptions {
+    /// Width of the outline ring around the subject in pixels; 0 disables it.
+    pub outline_width: u32,
+    /// RGB color of the outline.
+    pub outline_color: [u8; 3],
+    /// Shadow offset in pixels, right and down.
+    pub shadow_offset: (i32, i32),
+    /// Gaussian blur of the shadow in pixels (sigma).
+    pub shadow_blur: f32,
+    /// Opacity of the shadow (0-1); 0 disables it.
+    pub shadow_opacity: f32,
+}
+
+impl Default for StickerOptions {
+    fn default() -> Self {
+        StickerOptions {
+            outline_width: 12,
+            outline_color: [255, 255, 255],
+            shadow_offset: (4, 6),
+            shadow_blur: 6.0,
+            shadow_opacity: 0.35,
+        }
+    }
+}
+
+/// Settings for guided filter edge refinement.
+///
+/// The upscaled mask is filtered with the luminance of the full-resolution
+/// image as the guide (He et al., "Guided Image Filtering"), so soft mask
+/// edges from upscaling snap to the edges actually in the image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct GuidedFilterOptions {
+    /// Window radius in pixels; at least 1. Larger radii correct wider
+    /// blurred edges.
+    pub radius: u32,
+    /// Regularization, as a variance of the 0-1 luminance; above 0. Smaller
+    /// values follow fainter image edges, larger values smooth more.
+    pub epsilon: f32,
+}
+
+impl Default for GuidedFilterOptions {
+    fn default() -> Self {
+        GuidedFilterOptions { radius: 16, epsilon: 1e-3 }
+    }
+}
+
+/// How the confidence score of a mask is computed, and the score below
+/// which a result is rejected.
+///
+/// The score (0.0-1.0) multiplies three factors: the mean alpha of the mask
+/// pixels in the high band, the share of pixels outside the low band that
+/// are not in the high band (ambiguous), subtracted from one, and how far the
+/// subject's coverage is inside the accepted coverage range. A clear subject
+/// scores near 1; an empty, full, or noisy mask scores near 0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct ConfidenceOptions {
+    /// Fail with [`LowConfidence`](crate::RemoveBgError::LowConfidence)
+    /// instead of writing an output when the score is below this; `None`
+    /// only reports the score.
+    pub min_score: Option<f32>,
+    /// Alpha (0.0-1.0) at or above which a mask pixel is confidently subject.
+    pub high: f32,
+    /// Alpha (0.0-1.0) at or below which a mask pixel is confidently
+    /// background; values between `low` and `high` are ambiguous.
+    pub low: f32,
+    /// Smallest fraction of the image the subject may cover without lowering
+    /// the score.
+    pub min_coverage: f32,
+    /// Largest fraction of the image the subject may cover without lowering
+    /// the score.
+    pub max_coverage: f32,
+}
+
+impl Default for ConfidenceOptions {
+    fn default() -> Self {
+        ConfidenceOptions { min_score: None, high: 0.8, low: 0.2, min_coverage: 0.005, max_coverage: 0.95 }
+    }
+}
+
+/// Output canvas geometry, applied to the finished cutout.
+///
+/// The steps run in field order: `pad` crops to the subject, `square` pads to
+/// a square, and `resize` scales the result. Padding is transparent, or the
+/// background color when one is set.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct GeometryOptions {
+    /// Crop to the subject's bounding box plus a margin on every side, as a
+    /// fraction of the box's larger side (0.1 = 10%).
+    pub pad: Option<f32>,
+    /// Pad the canvas to a square, keeping the image centered.
+    pub square: bool,
+    /// Fit the output within this width and height, preserving the aspect
+    /// ratio.
+    pub resize: Option<(u32, u32)>,
+    /// Let `resize` enlarge images beyond their original resolution.
+    pub allow_upscale: bool,
+}
+
+/// Kind of image written by an [`OutputSpec`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OutputKind {
+    /// The transparent cutout, without any background color, chroma key or
+    /// kept background.
+    Cutout,
+    /// The cutout's alpha channel as a grayscale image.
+    Mask,
+    /// The cutout flattened onto the `background` color, or white.
+    Flattened,
+    /// The mask tinted red over the input, for checking the cutout.
+    Overlay,
+    /// The transparent cutout shrunk to fit within
+    /// [`thumbnail_size`](RemoveBgOptions::thumbnail_size) pixels (see
+    /// [`make_thumbnail`](crate::preview::make_thumbnail)).
+    Thumbnail,
+    /// A looping GIF alternating between the input and the cutout on a
+    /// checkerboard, within [`preview_size`](RemoveBgOptions::preview_size)
+    /// pixels (see [`before_after_gif`](crate::preview::before_after_gif)).
+    Preview,
+}
+
+impl OutputKind {
+    /// Name used on the command line and for `{kind}` in path templates.
+    pub fn name(self) -> &'static str {
+        match self {
+            OutputKind::Cutout => "cutout",
+            OutputKind::Mask => "mask",
+            OutputKind::Flattened => "flattened",
+            OutputKind::Overlay => "overlay",
+            OutputKind::Thumbnail => "thumbnail",
+            OutputKind::Preview => "preview",
+        }
+    }
+
+    /// Format used when neither the spec nor its path selects one: JPEG for
+    /// flattened images, GIF for previews, PNG otherwise.
+    pub fn default_format(self) -> OutputFormat {
+        match self {
+            OutputKind::Flattened => OutputFormat::Jpeg,
+            OutputKind::Preview => OutputFormat::Gif,
+            _ => OutputFormat::Png,
+        }
+    }
+}
+
+impl fmt::Display for OutputKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for OutputKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "cutout" => Ok(OutputKind::Cutout),
+            "mask" => Ok(OutputKind::Mask),
+            "flattened" => Ok(OutputKind::Flattened),
+            "overlay" => Ok(OutputKind::Overlay),
+            "thumbnail" => Ok(OutputKind::Thumbnail),
+            "preview" => Ok(OutputKind::Preview),
+            other => Err(format!(
+                "invalid output kind '{}' (expected cutout, mask, flattened, overlay, thumbnail, or preview)",
+                other
+            )),
+        }
+    }
+}
+
+/// An additional image written from the same model run as the main output.
+///
+/// Path templates may contain `{stem}` (the input's file stem), `{dir}` (the
+/// directory of the main output, so directory batches write next to each
+/// cutout) and `{kind}` (the kind's name). A template without a recognized
+/// extension gets the format's extension appended.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OutputSpec {
+    /// What to write.
+    pub kind: OutputKind,
+    /// Path template; `None` uses `{dir}/{stem}_{kind}`.
+    pub path: Option<String>,
+    /// Output container; `None` infers it from the path's extension, or
+    /// uses the kind's [default](OutputKind::default_format).
+    pub format: Option<OutputFormat>,
+    /// Lossy encoding quality (0-100) for WebP and JPEG; `None` uses the
+    /// main output's `quality`.
+    pub quality: Option<f32>,
+}
+
+impl OutputSpec {
+    /// An output of `kind` with the default path, format and quality.
+    pub fn new(kind: OutputKind) -> Self {
+        OutputSpec { kind, path: None, format: None, quality: None }
+    }
+}
+
+impl FromStr for OutputSpec {
+    type Err = String;
+
+    /// Parse `KIND[@QUALITY][:TEMPLATE]`, e.g. `mask` or
+    /// `flattened@85:{dir}/{stem}_white.jpg`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, path) = match s.split_once(':') {
+            Some((kind, path)) => (kind, Some(path)),
+            None => (s, None),
+        };
+        let (kind, quality) = match kind.split_once('@') {
+            Some((kind, quality)) => {
+                let quality = quality
+                    .trim()
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|q| (0.0..=100.0).contains(q))
+                    .ok_or_else(|| format!("invalid quality '{}' in '{}' (expected 0-100)", quality, s))?;
+                (kind, Some(quality))
+            }
+            None => (kind, None),
+        };
+        let path = match path {
+            Some("") => return Err(format!("empty path template in '{}'", s)),
+            Some(path) => {
+                crate::artifacts::check_template(path)?;
+                Some(path.to_string())
+            }
+            None => None,
+        };
+        Ok(OutputSpec { kind: kind.trim().parse()?, path, format: None, quality })
+    }
+}
+
+/// A cap on the size of the output file, such as a marketplace's upload limit.
+///
+/// The output is first encoded as configured. If that is too large, JPEG and
+/// WebP output is searched for the highest quality that fits, and PNG output
+/// is tried with the best compression and then reduced to a palette. Failing
+/// that, the image is scaled down if `allow_downscale` is set. The settings
+/// used are reported as
+/// [`RemovalReport::file_size`](crate::RemovalReport::file_size).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FileSizeLimit {
+    /// Largest acceptable file size in bytes.
+    pub max_bytes: u64,
+    /// Scale the image down when no encoder setting fits at full size.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub allow_downscale: bool,
+}
+
+/// Where the mask cache lives and how large it may grow.
+///
+/// Masks are stored by the content of the input and the settings that shape
+/// the model's mask (see [`mask_cache`](crate::mask_cache)), so later runs on
+/// the same image with only other post-processing changed skip the model.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MaskCacheOptions {
+    /// Directory holding the cached masks; created on first use.
+    pub dir: PathBuf,
+    /// Total size of the cached masks in bytes above which the least
+    /// recently used ones are deleted; `None` lets the cache grow freely.
+    #[cfg_attr(feature = "serde", serde(default = "default_mask_cache_size"))]
+    pub max_size: Option<u64>,
+}
+
+/// `max_size` of a stored [`MaskCacheOptions`] that doesn't set it, as with
+/// [`MaskCacheOptions::new`].
+#[cfg(feature = "serde")]
+fn default_mask_cache_size() -> Option<u64> {
+    Some(crate::mask_cache::DEFAULT_MAX_SIZE)
+}
+
+impl MaskCacheOptions {
+    /// A cache in `dir` limited to [`DEFAULT_MAX_SIZE`](crate::mask_cache::DEFAULT_MAX_SIZE).
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        MaskCacheOptions { dir: dir.into(), max_size: Some(crate::mask_cache::DEFAULT_MAX_SIZE) }
+    }
+}
+
+/// Settings for icon output: ICO files and macOS iconsets.
+///
+/// Icons are made from the cutout cropped to the subject and padded to a
+/// square, resized with Lanczos3 for every size.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct IconOptions {
+    /// Edge lengths of the entries in `.ico` output, each 1-256 pixels.
+    pub sizes: Vec<u32>,
+    /// Also write a macOS iconset (16 to 512 px, with @2x variants) named
+    /// `<input stem>.iconset` in this directory.
+    pub iconset_dir: Option<PathBuf>,
+    /// Enlarge the cutout for sizes larger than it, with a warning, instead
+    /// of skipping those sizes.
+    pub allow_upscale: bool,
+}
+
+impl Default for IconOptions {
+    fn default() -> Self {
+        IconOptions { sizes: vec![16, 32, 48, 64, 128, 256], iconset_dir: None, allow_upscale: false }
+    }
+}
+
+/// How a soft mask is split into a trimap: definite foreground, definite
+/// background, and an unknown band between them for a matting algorithm to
+/// resolve.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct TrimapOptions {
+    /// Mask values (0.0-1.0) at or below this are definite background.
+    pub low: f32,
+    /// Mask values at or above this are definite foreground; must be above
+    /// `low`.
+    pub high: f32,
+    /// Both definite regions shrink by this many pixels where they border
+    /// other regions, widening the unknown band.
+    pub erode_radius: u32,
+}
+
+impl Default for TrimapOptions {
+    fn default() -> Self {
+        TrimapOptions { low: 0.05, high: 0.95, erode_radius: 10 }
+    }
+}
+
+/// How the mask is split into instances for
+/// [`split_instances`](RemoveBgOptions::split_instances).
+///
+/// Instances are the 8-connected regions of the finished mask at half
+/// alpha. Each is written cropped to its bounding box plus `margin`, with
+/// only its own soft alpha: the semi-transparent edge around a region goes
+/// to the nearest region. The model can merge subjects that touch or overlap
+/// into one region; those come out as a single instance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct InstanceOptions {
+    /// Regions with fewer pixels than this are dropped as noise.
+    pub min_area: u64,
+    /// Most instances written, largest first; 0 is treated as 1.
+    pub max_instances: usize,
+    /// Context kept around each region, as a fraction of its bounding box's
+    /// larger side on every side (0.1 = 10%).
+    pub margin: f32,
+}
+
+impl Default for InstanceOptions {
+    fn default() -> Self {
+        InstanceOptions { min_area: 1000, max_instances: 32, margin: 0.05 }
+    }
+}
+
+/// What happens when an output path is already taken: by an existing file,
+/// or in a batch by the output of another input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Collision {
+    /// Fail with `OutputExists` (default); colliding inputs of a batch fail.
+    #[default]
+    Error,
+    /// Replace existing files, like [`overwrite`](RemoveBgOptions::overwrite).
+    /// Inputs of a batch that would write the same file still fail.
+    Overwrite,
+    /// Append `-1`, `-2`, ... to the main output's file name until it is
+    /// free. Other outputs (mask sidecars, `outputs`) are not renamed.
+    Rename,
+}
+
+impl fmt::Display for Collision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Collision::Error => "error",
+            Collision::Overwrite => "overwrite",
+            Collision::Rename => "rename",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Collision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(Collision::Error),
+            "overwrite" => Ok(Collision::Overwrite),
+            "rename" => Ok(Collision::Rename),
+            _ => Err(format!("Unknown collision policy: {}; expected error, overwrite or rename", s)),
+        }
+    }
+}
+
+/// Which pages of a multi-page TIFF input are processed. Other inputs have
+/// a single page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Pages {
+    /// The first page, logging a notice when there are more (default).
+    #[default]
+    First,
+    /// Only this page, counting from 1; the output is named as usual.
+    Page(u32),
+    /// Every page, each into its own output with `_p<page>` added to the
+    /// input's stem (`scan_p1_nobg.png`, `scan_p2_nobg.png`, ...), or to the
+    /// stem of an output file given by the caller.
+    All,
+}
+
+impl fmt::Display for Pages {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pages::First => f.write_str("first"),
+            Pages::Page(page) => write!(f, "{}", page),
+            Pages::All => f.write_str("all"),
+        }
+    }
+}
+
+impl FromStr for Pages {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "first" => Ok(Pages::First),
+            "all" => Ok(Pages::All),
+            page => match page.parse() {
+                Ok(page) if page > 0 => Ok(Pages::Page(page)),
+                _ => Err(format!("Invalid page: {}; expected first, all or a page number from 1", s)),
+            },
+        }
+    }
+}
+
+/// Edge length of the square image the model runs on
+/// ([`RemoveBgOptions::inference_size`]).
+///
+/// Larger sizes keep more detail, such as hair and thin parts, but
+/// inference time grows with the square of the size: 640 costs about four
+/// times as much as 320.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum InferenceSize {
+    /// The size the model file declares, or the one registered for the
+    /// model if it is dynamic: 320 for the U2-Net family (default).
+    #[default]
+    Native,
+    /// This size, a multiple of 32 from 128 to 2048. Models whose file
+    /// declares a fixed size only run at that size.
+    Fixed(u32),
+    /// 320, 640 or 1024, chosen for each image from its size and how much
+    /// fine detail it has; never below the native size. A warning suggests
+    /// [`tiling`](RemoveBgOptions::tiling) when the image would want more
+    /// than 1024.
+    Auto,
+}
+
+impl fmt::Display for InferenceSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InferenceSize::Native => f.write_str("native"),
+            InferenceSize::Fixed(size) => write!(f, "{}", size),
+            InferenceSize::Auto => f.write_str("auto"),
+        }
+    }
+}
+
+impl FromStr for InferenceSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "native" => Ok(InferenceSize::Native),
+            "auto" => Ok(InferenceSize::Auto),
+            size => match size.parse() {
+                Ok(size) => crate::resolution::check(size).map(|_| InferenceSize::Fixed(size)).map_err(|e| e.to_string()),
+                _ => Err(format!("Invalid inference size: {}; expected native, auto or a size in pixels", s)),
+            },
+        }
+    }
+}
+
+/// What happens to an input file that looks like the cutout of an earlier
+/// run: named like one of our outputs, or already partly transparent.
+///
+/// Running the model on a cutout again degrades its edges. Transparent
+/// parts of any input are flattened onto neutral gray before inference,
+/// which limits the damage, but reapplying a saved mask with
+/// [`external_mask`](RemoveBgOptions::external_mask) is better still.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "lowercase"))]
+pub enum Reprocess {
+    /// Process it, logging a warning (default).
+    #[default]
+    Warn,
+    /// Process it silently.
+    Allow,
+    /// Fail with `AlreadyProcessed`.
+    Refuse,
+}
+
+/// What happens when an output looks too big for the free space where it
+/// would be written, by an estimate from its dimensions and format (see
+/// [`disk_space`](RemoveBgOptions::disk_space)).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "lowercase"))]
+pub enum DiskSpaceCheck {
+    /// Write it anyway, logging a warning (default).
+    #[default]
+    Warn,
+    /// Fail with `InsufficientDiskSpace` before encoding.
+    Refuse,
+    /// Don't check, e.g. on filesystems that misreport their free space.
+    Skip,
+}
+
+/// How outputs named after their input are named, and what happens when the
+/// name is taken.
+///
+/// Applies to outputs written next to the input or into a directory:
+/// `<prefix><input stem><suffix>.<extension>`. An output path given as a
+/// file keeps its name, except that [`Collision::Rename`] numbers it too
+/// and `fix_extension` corrects its extension.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct NamingOptions {
+    /// Text before the input stem; empty by default.
+    pub prefix: String,
+    /// Text after the input stem; `None` uses `_nobg`, or `_pop` with
+    /// [`grayscale_background`](RemoveBgOptions::grayscale_background).
+    pub suffix: Option<String>,
+    /// What to do when the output path is taken.
+    pub on_collision: Collision,
+    /// Replace the extension of an output file that doesn't match its
+    /// format, instead of refusing it (see
+    /// [`negotiate_format`](crate::output::negotiate_format)).
+    pub fix_extension: bool,
+}
+
+/// How often a failed model download is retried.
+///
+/// Connection errors, timeouts, and 5xx responses are retried after an
+/// exponentially growing delay with random jitter; other failures, such as a
+/// 404 or a checksum mismatch, fail immediately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct RetryPolicy {
+    /// Total download attempts, including the first; 0 is treated as 1.
+    pub attempts: u32,
+    /// Delay before the first retry; doubled for each further retry.
+    #[cfg_attr(feature = "serde", serde(rename = "initial_delay_ms", with = "crate::serialization::millis"))]
+    pub initial_delay: Duration,
+    /// Upper bound for the delay between attempts.
+    #[cfg_attr(feature = "serde", serde(rename = "max_delay_ms", with = "crate::serialization::millis"))]
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 3,
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retrying after failed attempt number `attempt` (1-based).
+    ///
+    /// Jitter picks a random point in the upper half of the backoff window,
+    /// so clients that failed together don't retry in lockstep.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        use std::hash::{BuildHasher, Hasher};
+
+        let backoff = self
+            .initial_delay
+            .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+            .min(self.max_delay);
+        let random = std::collections::hash_map::RandomState::new().build_hasher().finish();
+        let jitter = 0.5 + (random % 1000) as f64 / 2000.0;
+        backoff.mul_f64(jitter)
+    }
+}
+
+/// Where a model is downloaded from and how failed downloads are retried.
+///
+/// The URL and checksum overrides let locked-down environments fetch models
+/// from an internal mirror instead of GitHub.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct DownloadOptions {
+    /// URL the selected model is downloaded from instead of its registry URL.
+    /// `None` uses `REMOVEBG_MODEL_URL` if set, else the registry URL.
+    pub url: Option<String>,
+    /// Expected SHA-256 of the model file (hex), replacing the pinned hash,
+    /// for mirrors that intentionally serve a different file.
+    pub sha256: Option<String>,
+    /// Retry behavior for failed downloads.
+    pub retry: RetryPolicy,
+    /// Never download: a model that isn't cached fails with
+    /// `ModelNotCached` instead, e.g. in air-gapped deployments that ship
+    /// the model.
+    pub offline: bool,
+}
+
+/// Options controlling background removal.
+///
+/// With the `serde` feature, options serialize with their field names, which
+/// are part of the public API (see the `serialization` module).
+/// Missing fields take their defaults and unknown fields are ignored, so
+/// stored options keep loading after fields are added.
+///
+/// # Examples
+/// ```no_run
+/// use removebg::{remove_background_with_options, Device, RemoveBgOptions};
+///
+/// let options = RemoveBgOptions {
+///     device: Device::CoreMl,
+///     ..Default::default()
+/// };
+/// let output = remove_background_with_options("photo.jpg", None, &options)?;
+/// # Ok::<(), removebg::error::RemoveBgError>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct RemoveBgOptions {
+    /// Segmentation model used to generate the mask.
+    pub model: Model,
+
+    /// Choose the model for each image instead, starting from u2netp; `model`
+    /// is not used then. See [`AutoModel`].
+    pub auto_model: Option<AutoModel>,
+
+    /// Give up quality to finish the model run before a deadline; `None`
+    /// always processes at the configured quality. See [`QualityFallback`].
+    pub quality_fallback: Option<QualityFallback>,
+
+    /// Directory holding model files; `None` uses the default lookup (see
+    /// [`model_dir`](crate::model::model_dir)).
+    pub model_dir: Option<PathBuf>,
+
+    /// Download source and retry behavior when the model has to be downloaded.
+    pub download: DownloadOptions,
+
+    /// Receives the progress of model downloads; none by default. Not
+    /// serialized.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub download_progress: DownloadProgress,
+
+    /// Inference engine for the built-in models.
+    pub backend: Backend,
+
+    /// Device used for model inference.
+    pub device: Device,
+
+    /// Threads used to parallelize work within a single operator.
+    ///
+    /// `None` uses one thread per CPU the process may use, as found by
+    /// [`cpu::available_parallelism`](crate::cpu::available_parallelism),
+    /// which respects container CPU quotas. When several images are
+    /// processed in parallel, keep `intra_threads` times the number of parallel
+    /// workers at or below the core count to avoid oversubscription.
+    pub intra_threads: Option<usize>,
+
+    /// Threads used to run independent graph nodes in parallel (`None` = ORT default).
+    pub inter_threads: Option<usize>,
+
+    /// Graph optimization level applied when the session is created.
+    pub optimization_level: OptimizationLevel,
+
+    /// Model loading and memory arena settings of ONNX Runtime sessions.
+    pub session_memory: SessionMemoryOptions,
+
+    /// Telemetry, thread pool and allocator settings of the process-wide
+    /// ONNX Runtime environment, or an environment created by the application.
+    pub ort_environment: OrtEnvironmentConfig,
+
+    /// Run the model once on a blank image when it is loaded, so the
+    /// engine's first-run setup (ONNX Runtime allocates its memory arenas and
+    /// finalizes kernels on the first run) doesn't slow down the first real
+    /// image. The time it takes is reported as
+    /// [`StageDurations::warmup`](crate::StageDurations::warmup).
+    pub warmup: bool,
+
+    /// Reuse masks of inputs seen before from this on-disk cache instead of
+    /// loading and running the model; `None` always runs it. Not used by
+    /// removers with a custom model.
+    pub mask_cache: Option<MaskCacheOptions>,
+
+    /// Output container; `None` infers it from the output path's extension.
+    pub format: Option<OutputFormat>,
+
+    /// Lossy encoding quality (0-100) for WebP and JPEG output; `None` encodes
+    /// WebP losslessly and JPEG at quality 90 (95 with `chroma_key`).
+    pub quality: Option<f32>,
+
+    /// Encoder for the output instead of the built-in formats: `format` and
+    /// the output path's extension no longer choose one, and outputs named
+    /// after their input get the encoder's extension. The additional
+    /// `outputs` and `split_instances` keep their own formats. Not
+    /// serialized; deserialized options have none.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub encoder: Option<Arc<dyn OutputEncoder>>,
+
+    /// Background behind the subject; a color flattens the output (required for JPEG).
+    pub background: Background,
+
+    /// Downscale decoded inputs whose longer side exceeds this many pixels,
+    /// keeping the aspect ratio, before the mask is upscaled and applied, so
+    /// every full-resolution step runs at the smaller size. `None` keeps the
+    /// input's resolution. [`RemovalReport::input_dimensions`](crate::RemovalReport::input_dimensions)
+    /// still records the original size. Applies to files, URLs and encoded
+    /// bytes, not to images passed in decoded; cannot be combined with `roi`.
+    pub max_output_size: Option<u32>,
+
+    /// Run inference on overlapping full-resolution tiles instead of the whole
+    /// downscaled image, keeping fine detail in very large images. `None` runs
+    /// a single pass.
+    pub tiling: Option<TileOptions>,
+
+    /// Only segment inside this rectangle of the input, e.g. to keep one of
+    /// several people. The model runs on the rectangle expanded by
+    /// `roi_margin`, and the mask is empty outside that area; the output keeps
+    /// the input's size. Coordinates past the image edge are clamped with a
+    /// warning, and a rectangle with zero area is rejected. External masks
+    /// are not restricted.
+    pub roi: Option<Rect>,
+
+    /// Context around `roi` included in the model's input, as a fraction of
+    /// the rectangle's larger side on every side (0.1 = 10%).
+    pub roi_margin: f32,
+
+    /// Run the model a second time on the subject found by the first run,
+    /// cropped with a margin, so a small subject in a large scene fills the
+    /// model's input. The crop's mask replaces the first mask inside the
+    /// crop. Skipped when the first run finds nothing or the crop would
+    /// cover most of the image; otherwise it roughly doubles inference time,
+    /// reported as [`StageDurations::second_pass`](crate::StageDurations::second_pass).
+    pub two_pass: bool,
+
+    /// Size of the square image the model runs on; see [`InferenceSize`].
+    /// Regions of interest, crops of the second pass and tiles run at this
+    /// size too, with [`Auto`](InferenceSize::Auto) chosen once for the
+    /// whole image. The chosen size is reported in
+    /// [`ModelInfo::inference`](crate::ModelInfo::inference).
+    pub inference_size: InferenceSize,
+
+    /// Normalize the exposure of the model's copy of the image with this
+    /// strength (0-1), for dark or low-contrast photos: the luminance range
+    /// is stretched to the full range. The output keeps the original pixels.
+    /// Images whose luminance already spans most of the range, and flat
+    /// ones, aren't changed. `None` feeds the image to the model as is.
+    pub auto_enhance: Option<f32>,
+
+    /// Sharpen the mask along edges in the image with a guided filter, before
+    /// the other mask refinements. `None` leaves the upscaled mask as is.
+    pub guided_filter: Option<GuidedFilterOptions>,
+
+    /// Keep only the largest connected region(s) of the mask, clearing stray
+    /// blobs the model picked up in the background.
+    pub keep_largest_component: bool,
+
+    /// Number of regions kept when `keep_largest_component` is set; 0 is treated as 1.
+    pub keep_components: usize,
+
+    /// Regions at most this many pixels apart count as one region when
+    /// filtering components, so limbs split by a thin occlusion are kept.
+    pub component_gap: u32,
+
+    /// Fill semi-transparent patches fully enclosed by the subject, such as
+    /// dark glasses or shirt folds the model was unsure about.
+    pub fill_holes: bool,
+
+    /// Largest hole area in pixels that `fill_holes` fills (`None` = no limit);
+    /// larger enclosed areas are kept as real gaps.
+    pub max_hole_size: Option<usize>,
+
+    /// Images run through the model together in one batch by
+    /// [`process_images`](crate::BackgroundRemover::process_images) and
+    /// directory batches; 0 and 1 run each image on its own. Larger batches
+    /// raise throughput for many small images at the cost of memory. Models
+    /// with a fixed batch size fall back to one image at a time. Tiled
+    /// inference, regions of interest and external masks always run one
+    /// image at a time.
+    pub batch_size: usize,
+
+    /// Grayscale image used as the mask instead of running the model, e.g. a
+    /// hand-corrected mask. It is resized to the input if needed and then
+    /// refined and applied like a generated mask.
+    pub external_mask: Option<PathBuf>,
+
+    /// Skip the model for line art (scans, faxes, drawings with at most 16
+    /// distinct colors) and take the mask from luminance instead, keeping
+    /// the ink and removing the paper. Other inputs run the model as usual.
+    /// Like external masks, these masks are not restricted to `roi`.
+    pub line_art: bool,
+
+    /// Also write the unrefined full-resolution mask next to the output as
+    /// `<input stem>_mask.png` (16-bit grayscale), or `_mask.npy` as
+    /// `mask_format` selects, for reuse as `external_mask` in a later run.
+    /// Only applies to single-image outputs written to a file.
+    pub save_mask: bool,
+
+    /// Format of the `save_mask` sidecar.
+    pub mask_format: MaskFormat,
+
+    /// Also write a trimap of the unrefined mask to this path, for external
+    /// matting tools: an 8-bit grayscale PNG with definite background at 0,
+    /// the unknown band at 128 and definite foreground at 255, split as set
+    /// in `trimap`. Only applies to single-image outputs written to a file.
+    pub export_trimap: Option<PathBuf>,
+
+    /// Thresholds and erosion of the trimap written with `export_trimap`.
+    pub trimap: TrimapOptions,
+
+    /// Also write each separate subject as its own transparent image, for
+    /// group shots of several objects (see [`InstanceOptions`]). They are
+    /// listed in [`RemovalReport::instances`](crate::RemovalReport::instances).
+    /// Only applies to still images written to a file.
+    pub split_instances: Option<InstanceOptions>,
+
+    /// Keep the background and cut out the subject instead, leaving a
+    /// subject-shaped hole (alpha = 1 − mask). Applied after the other mask
+    /// refinements, which still act on the subject.
+    pub invert_mask: bool,
+
+    /// Remove background color bleeding into semi-transparent edge pixels,
+    /// with the given strength (0-1). `None` leaves edge colors unchanged.
+    pub decontaminate: Option<f32>,
+
+    /// Write premultiplied alpha: the color channels are multiplied by the
+    /// alpha, as game engines and some compositors expect. The product is
+    /// taken from the 16-bit mask after `decontaminate`, so edges keep more
+    /// precision than premultiplying an 8-bit file afterwards. Image formats
+    /// have no flag for this, so [`RemovalReport`](crate::RemovalReport)
+    /// records it. Cannot be combined with outputs flattened onto a
+    /// background (`background`, `chroma_key`, kept backgrounds, JPEG) or
+    /// with `sticker`.
+    pub premultiplied: bool,
+
+    /// Keep the background but blur it with a Gaussian of this sigma (in
+    /// pixels) instead of removing it, like a portrait-mode photo. The result
+    /// is opaque; `background` is ignored when this is set.
+    pub blur_background: Option<f32>,
+
+    /// Keep the background but convert it to grayscale ("color pop"), with
+    /// the soft mask edge blending into the colored subject. Combines with
+    /// `blur_background`. The result is opaque, `background` is ignored, and
+    /// auto-generated output names end in `_pop` instead of `_nobg`.
+    pub grayscale_background: bool,
+
+    /// Brightness factor for a kept (blurred or grayscale) background: above
+    /// 1.0 lightens it, below 1.0 darkens it. `None` leaves it unchanged.
+    pub background_brightness: Option<f32>,
+
+    /// Turn the cutout into a sticker with an outline and a drop shadow.
+    /// Ignored when the background is kept (blurred or grayscale); not
+    /// supported for animations.
+    pub sticker: Option<StickerOptions>,
+
+    /// Output canvas geometry (cropping to the subject, squaring, resizing).
+    /// Not supported for animations.
+    pub geometry: GeometryOptions,
+
+    /// ICO entry sizes and the optional macOS iconset. Not supported for
+    /// animations.
+    pub icon: IconOptions,
+
+    /// Replace the background with this key color for chroma keying in a
+    /// video editor. Works like a solid `background` color (which it takes
+    /// precedence over), but edge colors are decontaminated (at full strength
+    /// unless `decontaminate` is set) and subject pixels with alpha of at
+    /// least 0.95 are shifted minimally away from the key color, so a keyer
+    /// removes only the background. Ignored when the background is kept
+    /// (blurred or grayscale).
+    pub chroma_key: Option<[u8; 3]>,
+
+    /// How the outputs of a multi-output model are combined into the mask.
+    pub mask_fusion: Fusion,
+
+    /// Linearly remap the model's mask range `(low, high)` (0.0-1.0) to the
+    /// full 0-1 range, clamping values outside it. With `low == high` the
+    /// mask becomes a hard cut at that value. Applied to the model's mask
+    /// before `mask_gamma` and before it is scaled to the image; `None`
+    /// leaves the mask unchanged. External masks are not adjusted.
+    pub mask_levels: Option<(f32, f32)>,
+
+    /// Raise the model's mask values to this power, after `mask_levels`:
+    /// below 1 makes semi-transparent areas more opaque, above 1 more
+    /// transparent. `None` leaves the mask unchanged. External masks are not
+    /// adjusted.
+    pub mask_gamma: Option<f32>,
+
+    /// Post-processing of the full-resolution mask as an ordered list of
+    /// operators, applied after the guided filter and before `invert_mask`.
+    /// Empty uses the refinement options in their fixed order: levels and
+    /// gamma on the model's mask, then the largest regions kept, then holes
+    /// filled. When set, `mask_levels`, `mask_gamma`,
+    /// `keep_largest_component` and `fill_holes` are ignored; unlike those,
+    /// the list also applies to external masks.
+    pub mask_ops: Vec<MaskOp>,
+
+    /// Filter the model's mask, and an external mask of another size, is
+    /// resized to the image with.
+    pub mask_filter: MaskFilter,
+
+    /// Set the alpha of the outermost `trim_border` pixels on each side to
+    /// the mask value just inside them, before the other mask refinements,
+    /// for models that leave a faint frame around the image. 0 leaves the
+    /// border as it is.
+    pub trim_border: u32,
+
+    /// Confidence scoring of the model's mask and the minimum accepted score.
+    pub confidence: ConfidenceOptions,
+
+    /// Measure the finished mask for
+    /// [`RemovalReport::alpha_stats`](crate::RemovalReport::alpha_stats).
+    /// Off by default, as labeling the subject's regions takes another pass
+    /// over the mask.
+    pub alpha_stats: bool,
+
+    /// Also write debug images for each input file into this directory: the
+    /// mask at the model's resolution (not available with tiling, a region
+    /// of interest or an external mask), the full-resolution mask, a red overlay of the mask on
+    /// the original, and a comparison strip. They are named
+    /// `<input stem>.raw.png`, `.mask.png`, `.overlay.png` and `.compare.png`.
+    /// The primary output is unaffected. Only applies to single-image outputs
+    /// written to a file.
+    pub debug_output: Option<PathBuf>,
+
+    /// Additional images written from the same model run as the main
+    /// output, such as the mask or a flattened JPEG, each with its own path,
+    /// format and quality (see [`OutputSpec`]). They are listed in
+    /// [`RemovalReport::artifacts`](crate::RemovalReport::artifacts); one that
+    /// fails to write doesn't stop the others. Only applies to still images
+    /// written to a file.
+    pub outputs: Vec<OutputSpec>,
+
+    /// Longest side in pixels of [`Thumbnail`](OutputKind::Thumbnail)
+    /// outputs; `None` uses 256. Smaller cutouts are written at their size.
+    pub thumbnail_size: Option<u32>,
+
+    /// Longest side in pixels of [`Preview`](OutputKind::Preview) outputs;
+    /// `None` uses [`DEFAULT_PREVIEW_SIZE`](crate::preview::DEFAULT_PREVIEW_SIZE).
+    pub preview_size: Option<u32>,
+
+    /// How long each frame of [`Preview`](OutputKind::Preview) outputs
+    /// shows; `None` uses
+    /// [`DEFAULT_PREVIEW_DELAY`](crate::preview::DEFAULT_PREVIEW_DELAY).
+    #[cfg_attr(feature = "serde", serde(rename = "preview_delay_ms", with = "crate::serialization::optional_millis"))]
+    pub preview_delay: Option<Duration>,
+
+    /// PNG encoder settings.
+    pub png: PngOptions,
+
+    /// Cap on the size of the main output file; `None` writes it with the
+    /// configured settings whatever its size. Doesn't apply to animations or
+    /// the additional `outputs`.
+    pub max_file_size: Option<FileSizeLimit>,
+
+    /// Size limits enforced when decoding the input image.
+    pub limits: DecodeLimits,
+
+    /// Soft cap on the memory processing one image takes, in bytes, checked
+    /// against an estimate of its largest buffers (see [`memory`](crate::memory))
+    /// from the image header before it is decoded. An image over budget is
+    /// processed with its mask upscaled in bands if that fits, and otherwise
+    /// refused with `MemoryBudgetExceeded`. `None` has no limit.
+    pub max_memory_bytes: Option<u64>,
+
+    /// Drop the input's ICC profile and EXIF data instead of copying them to
+    /// the output. The EXIF orientation is applied to the pixels either way.
+    pub strip_metadata: bool,
+
+    /// Names of outputs named after their input, and what happens when an
+    /// output path is taken.
+    pub naming: NamingOptions,
+
+    /// Replace an existing output file instead of failing with `OutputExists`.
+    pub overwrite: bool,
+
+    /// Pages of multi-page TIFF input files and URLs to process. Images
+    /// given as bytes are processed from their first page.
+    pub pages: Pages,
+
+    /// What happens to input files that look like earlier cutouts. Images
+    /// given as bytes or already decoded are not checked.
+    pub reprocess: Reprocess,
+
+    /// What happens when an output's estimated size exceeds the free space
+    /// where it would be written. Model downloads of known size are always
+    /// checked, and fail with `InsufficientDiskSpace` before they start.
+    pub disk_space: DiskSpaceCheck,
+
+    /// Create a missing output directory instead of failing with
+    /// `OutputDirectoryMissing`.
+    pub create_dirs: bool,
+
+    /// Token checked during processing; cancelling it aborts with `Cancelled`.
+    /// Not serialized; deserialized options get a fresh token.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub cancel: CancellationToken,
+
+    /// Wall-clock budget for processing one input, from reading it to
+    /// writing its outputs, including a model download on first use.
+    /// Checked where `cancel` is, so an operation over budget stops at the
+    /// next stage boundary or row band with `TimedOut`, leaving no output.
+    /// Inputs batched into one model run (`batch_size`) share the budget
+    /// from the start of the batch. `None` has no limit.
+    #[cfg_attr(feature = "serde", serde(rename = "timeout_ms", with = "crate::serialization::optional_millis"))]
+    pub timeout: Option<Duration>,
+}
+
+impl RemoveBgOptions {
+    /// These options with `timeout` running from now: `cancel` in the copy
+    /// also expires. Borrowed unchanged without a timeout.
+    pub(crate) fn timed(&self) -> Cow<'_, RemoveBgOptions> {
+        match self.timeout {
+            Some(timeout) => Cow::Owned(RemoveBgOptions { cancel: self.cancel.expiring(Some(timeout)), ..self.clone() }),
+            None => Cow::Borrowed(self),
+        }
+    }
+}
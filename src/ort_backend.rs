@@ -0,0 +1,530 @@
+//! ONNX Runtime inference engine (`ort` feature).
+
+use crate::core::{self, Gray16Image, Gray32FImage};
+use crate::error::{self, RemoveBgError, Result};
+use crate::model;
+use crate::options::{Device, OptimizationLevel, OrtAllocator, OrtEnvironmentConfig, RemoveBgOptions, SessionMemoryOptions};
+use crate::report::StageDurations;
+use crate::segmentation::{self, ReadOutputs, Runner, SegmentationModel};
+use image::DynamicImage;
+use ndarray::Array4;
+use memmap2::Mmap;
+use ort::environment::{Environment, GlobalThreadPoolOptions};
+use ort::ep::ExecutionProviderDispatch;
+use ort::memory::{AllocationDevice, AllocatorType, MemoryInfo, MemoryType};
+use ort::session::builder::{GraphOptimizationLevel, SessionBuilder};
+use ort::session::{Session, SessionOutputs};
+use ort::sys::{OrtApi, OrtStatusPtr};
+use ort::value::{Tensor, TensorElementType};
+use ort::AsPointer;
+use std::ffi::CStr;
+use std::fs::File;
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Instant;
+
+/// The built-in [`SegmentationModel`]: a U2-Net family model run through
+/// ONNX Runtime.
+///
+/// [`BackgroundRemover::new`](crate::BackgroundRemover::new) uses this
+/// model unless [`backend`](RemoveBgOptions::backend) selects another engine;
+/// creating it directly is only needed to wrap it in another model.
+pub struct OrtU2Net {
+    options: RemoveBgOptions,
+    session: Mutex<Session>,
+    /// Name of the model's image input, e.g. `input` or `input.1`.
+    input_name: String,
+    input_size: u32,
+    /// Whether the input's height and width are dynamic, so other sizes run.
+    resizable: bool,
+    /// Names of the model's outputs, in the order they are declared.
+    output_names: Vec<String>,
+}
+
+impl OrtU2Net {
+    /// Load the model selected by `options`, downloading it if needed.
+    ///
+    /// The model, device and session options are taken from `options`, as
+    /// are the mask fusion and curve applied to its output.
+    ///
+    /// The input's name and size are read from the model file, so models
+    /// exported with another input name or size work; a dynamic size falls
+    /// back to the size registered for the model.
+    ///
+    /// # Errors
+    /// * `ModelInitError` - If the model cannot be downloaded, the session cannot be created, or
+    ///   the model's input is not a float32 `(batch, 3, size, size)` image
+    pub fn new(options: &RemoveBgOptions) -> Result<Self> {
+        let session = create_session(options)?;
+        let input = session
+            .inputs()
+            .first()
+            .ok_or_else(|| RemoveBgError::ModelInitError("model has no inputs".into()))?;
+        let dtype = input.dtype();
+        if dtype.tensor_type() != Some(TensorElementType::Float32) {
+            return Err(RemoveBgError::ModelInitError(format!(
+                "model input '{}' is {}; expected a float32 tensor",
+                input.name(),
+                dtype
+            )));
+        }
+        // Dynamic dimensions are reported as -1
+        let dims: Vec<Option<i64>> =
+            dtype.tensor_shape().map_or(Vec::new(), |shape| shape.iter().map(|&dim| (dim > 0).then_some(dim)).collect());
+        let input_size = segmentation::input_size(input.name(), &dims, options.model)?;
+        let resizable = dims.len() == 4 && dims[2..].iter().all(Option::is_none);
+        let input_name = input.name().to_string();
+        let output_names = session.outputs().iter().map(|output| output.name().to_string()).collect();
+        log::debug!("model input '{}' takes {}x{} images", input_name, input_size, input_size);
+        Ok(OrtU2Net { options: options.clone(), session: Mutex::new(session), input_name, input_size, resizable, output_names })
+    }
+}
+
+impl SegmentationModel for OrtU2Net {
+    fn predict_mask(&self, image: &DynamicImage) -> Result<Gray16Image> {
+        core::infer_mask(self, image, &self.options, &mut StageDurations::default())
+    }
+
+    fn predict_masks(&self, images: &[&DynamicImage]) -> Vec<Result<Gray16Image>> {
+        core::infer_masks(self, images, &self.options, &mut StageDurations::default())
+    }
+}
+
+impl Runner for OrtU2Net {
+    fn accepts_batch(&self, size: usize) -> bool {
+        let Ok(session) = self.session.lock() else {
+            return false;
+        };
+        let batch = session
+            .inputs()
+            .first()
+            .and_then(|input| input.dtype().tensor_shape())
+            .and_then(|shape| shape.first().copied());
+        // Dynamic dimensions are reported as -1
+        batch == Some(-1) || batch == Some(size as i64)
+    }
+
+    fn input_size(&self) -> u32 {
+        self.input_size
+    }
+
+    fn accepts_size(&self, _size: u32) -> bool {
+        self.resizable
+    }
+
+    fn run(
+        &self,
+        input: Array4<f32>,
+        read: &mut ReadOutputs<'_>,
+    ) -> Result<Vec<Gray32FImage>> {
+        let input_tensor = Tensor::from_array(input).map_err(|e| RemoveBgError::ModelError(e.to_string()))?;
+        let mut session = self
+            .session
+            .lock()
+            .map_err(|_| RemoveBgError::ModelError("Model session lock poisoned".into()))?;
+        let started = Instant::now();
+        let outputs: SessionOutputs = session
+            .run(ort::inputs![self.input_name.as_str() => input_tensor])
+            .map_err(|e| RemoveBgError::ModelError(e.to_string()))?;
+        let elapsed = started.elapsed();
+
+        let arrays = self
+            .output_names
+            .iter()
+            .map(|name| {
+                let output = outputs
+                    .get(name)
+                    .ok_or_else(|| RemoveBgError::ModelError(format!("model returned no output '{}'", name)))?;
+                output.try_extract_array::<f32>().map_err(|e| RemoveBgError::ModelError(e.to_string()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        read(&arrays, elapsed)
+    }
+}
+
+impl std::fmt::Debug for OrtU2Net {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OrtU2Net").field("model", &self.options.model).finish_non_exhaustive()
+    }
+}
+
+/// Initialize the ONNX Runtime environment and create a session for `options`.
+///
+/// Downloads the model first if it is not cached, unless it is compiled into
+/// the binary.
+fn create_session(options: &RemoveBgOptions) -> Result<Session> {
+    init_runtime(&options.ort_environment)?;
+
+    if let Some(bytes) = model::embedded_model(options.model) {
+        log::debug!("creating session for embedded {} on {}", options.model, options.device);
+        return session_builder(options)?
+            .commit_from_memory(bytes)
+            .map_err(|e| RemoveBgError::ModelInitError(e.to_string()));
+    }
+
+    // Download model if it doesn't exist
+    let model_path = model::ensure_model(
+        options.model,
+        options.model_dir.as_deref(),
+        &options.download,
+        &options.download_progress,
+        &options.cancel,
+    )?;
+
+    log::debug!("creating session for {} on {}", options.model, options.device);
+    let mut builder = session_builder(options)?;
+    let session = match model_data(&model_path, &options.session_memory) {
+        // ONNX Runtime copies what it keeps, so the mapping can go once the session exists
+        ModelData::Mapped(map) => builder.commit_from_memory(&map),
+        ModelData::File(path) => builder.commit_from_file(path),
+    };
+    session.map_err(|e| RemoveBgError::ModelInitError(e.to_string()))
+}
+
+/// Load ONNX Runtime and create its environment with `config`, once per
+/// process.
+///
+/// With the `load-dynamic` feature the library is loaded here, from
+/// `ORT_DYLIB_PATH` or the system library path. `ort` panics when the library
+/// is missing or too old, so loading is done up front and any panic left is
+/// caught: both become a `ModelInitError` saying where the library was looked
+/// for. The outcome is kept, so later sessions fail the same way without
+/// trying again, and a later, different `config` only logs a warning.
+///
+/// An environment the application created, whether passed in `config` or
+/// committed with `ort::init()` before this runs, is used as it is.
+pub(crate) fn init_runtime(config: &OrtEnvironmentConfig) -> Result<()> {
+    static RUNTIME: OnceLock<std::result::Result<EnvironmentSettings, String>> = OnceLock::new();
+    let created = RUNTIME.get_or_init(|| {
+        // The config is only read
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| create_environment(config)))
+            .unwrap_or_else(|panic| Err(runtime_missing(error::panic_message(&*panic))))
+    });
+    match (created, &config.environment) {
+        (Err(e), _) => Err(RemoveBgError::ModelInitError(e.clone())),
+        (Ok(_), Some(host)) => check_host_environment(host).map_err(RemoveBgError::ModelInitError),
+        (Ok(settings), None)
+            if !settings.hosted && (settings.telemetry, settings.global_threads) != (config.telemetry, config.global_threads) =>
+        {
+            log::warn!(
+                "the ONNX Runtime environment already exists with telemetry {} and {}; ignoring the new settings",
+                if settings.telemetry { "on" } else { "off" },
+                match settings.global_threads {
+                    Some(threads) => format!("a global pool of {} extra threads", threads),
+                    None => "a thread pool per session".into(),
+                }
+            );
+            Ok(())
+        }
+        (Ok(_), None) => Ok(()),
+    }
+}
+
+/// The settings an environment was created with.
+#[derive(Debug, Clone, Copy)]
+struct EnvironmentSettings {
+    telemetry: bool,
+    global_threads: Option<usize>,
+    /// Created by the application, with settings of its own.
+    hosted: bool,
+}
+
+fn create_environment(config: &OrtEnvironmentConfig) -> std::result::Result<EnvironmentSettings, String> {
+    let requested =
+        EnvironmentSettings { telemetry: config.telemetry, global_threads: config.global_threads, hosted: false };
+    if let Some(host) = &config.environment {
+        check_host_environment(host)?;
+        log::debug!("using the ONNX Runtime environment passed by the application");
+        return Ok(EnvironmentSettings { hosted: true, ..requested });
+    }
+
+    #[cfg(feature = "load-dynamic")]
+    let builder = ort::init_from(dylib_path()).map_err(|e| runtime_missing(&e.to_string()))?;
+    #[cfg(not(feature = "load-dynamic"))]
+    let builder = ort::init();
+    let mut builder = builder.with_name("removebg").with_telemetry(config.telemetry);
+    if let Some(threads) = config.global_threads {
+        builder = builder.with_global_thread_pool(global_thread_pool(threads).map_err(|e| e.to_string())?);
+    }
+    // Fails when the application has committed an environment of its own
+    let committed = builder.commit();
+    // Resolves the C API, which is where a missing library shows up
+    ort::api();
+    Environment::current().map_err(|e| format!("Failed to create the ONNX Runtime environment: {}", e))?;
+    if committed {
+        Ok(requested)
+    } else {
+        log::debug!("using the ONNX Runtime environment created by the application; its settings apply");
+        Ok(EnvironmentSettings { hosted: true, ..requested })
+    }
+}
+
+/// Check that an environment passed by the application is the one sessions
+/// are created in: `ort` keeps one per process.
+fn check_host_environment(host: &Arc<Environment>) -> std::result::Result<(), String> {
+    let current = Environment::current().map_err(|e| format!("Failed to get the ONNX Runtime environment: {}", e))?;
+    if Arc::ptr_eq(host, &current) {
+        Ok(())
+    } else {
+        Err("the environment in OrtEnvironmentConfig is not ONNX Runtime's current environment; \
+             pass the one Environment::current() returns after committing it"
+            .into())
+    }
+}
+
+/// A global thread pool with `threads` threads besides the caller's.
+///
+/// ONNX Runtime counts the calling thread in a pool's size and creates no
+/// threads for a size of 1. Explicit sizes also keep it from reading the
+/// core count.
+fn global_thread_pool(threads: usize) -> ort::Result<GlobalThreadPoolOptions> {
+    let pool = GlobalThreadPoolOptions::default().with_intra_threads(threads + 1)?.with_inter_threads(threads + 1)?;
+    if threads == 0 {
+        // Nothing to wake up, so waiting threads need not spin
+        pool.with_spin_control(false)
+    } else {
+        Ok(pool)
+    }
+}
+
+/// File name of the ONNX Runtime library on this platform.
+pub(crate) const DYLIB_NAME: &str = if cfg!(windows) {
+    "onnxruntime.dll"
+} else if cfg!(target_os = "macos") {
+    "libonnxruntime.dylib"
+} else {
+    "libonnxruntime.so"
+};
+
+/// The library `load-dynamic` builds load: `ORT_DYLIB_PATH` if set, else
+/// [`DYLIB_NAME`], looked up next to the executable and then on the system
+/// library path.
+#[cfg(feature = "load-dynamic")]
+fn dylib_path() -> std::path::PathBuf {
+    std::env::var_os("ORT_DYLIB_PATH").filter(|path| !path.is_empty()).unwrap_or_else(|| DYLIB_NAME.into()).into()
+}
+
+/// The error message for an ONNX Runtime library that could not be loaded.
+fn runtime_missing(error: &str) -> String {
+    let search = if cfg!(windows) {
+        "PATH"
+    } else if cfg!(target_os = "macos") {
+        "DYLD_LIBRARY_PATH"
+    } else {
+        "LD_LIBRARY_PATH"
+    };
+    let location = match std::env::var_os("ORT_DYLIB_PATH").filter(|path| !path.is_empty()) {
+        Some(path) => format!("ORT_DYLIB_PATH points to {}", Path::new(&path).display()),
+        None => format!("{} was looked for next to the executable and in {} and the system library directories", DYLIB_NAME, search),
+    };
+    format!(
+        "ONNX Runtime could not be loaded ({}): {}; set ORT_DYLIB_PATH to the library file, or use --backend tract",
+        error.trim_end(),
+        location
+    )
+}
+
+/// Where a session reads the model from.
+enum ModelData<'a> {
+    /// The model file mapped into memory.
+    Mapped(Mmap),
+    /// The model file, read by ONNX Runtime.
+    File(&'a Path),
+}
+
+/// Memory-map the model at `path` when `memory.mmap_model` is set, or fall
+/// back to reading the file if it can't be mapped.
+fn model_data<'a>(path: &'a Path, memory: &SessionMemoryOptions) -> ModelData<'a> {
+    if !memory.mmap_model {
+        return ModelData::File(path);
+    }
+    // Safety: the mapping is only read while the session is created; model
+    // files are replaced by renaming a new file over them, never truncated
+    match File::open(path).and_then(|file| unsafe { Mmap::map(&file) }) {
+        Ok(map) => ModelData::Mapped(map),
+        Err(e) => {
+            log::debug!("cannot memory-map {} ({}), reading it instead", path.display(), e);
+            ModelData::File(path)
+        }
+    }
+}
+
+/// Get the ONNX Runtime execution provider for a device, if one is compiled in.
+fn execution_provider(device: Device) -> Option<ExecutionProviderDispatch> {
+    match device {
+        Device::Cpu => None,
+        #[cfg(feature = "coreml")]
+        Device::CoreMl => Some(ort::ep::CoreML::default().build().error_on_failure()),
+        #[cfg(feature = "directml")]
+        Device::DirectMl => Some(ort::ep::DirectML::default().build().error_on_failure()),
+        #[allow(unreachable_patterns)]
+        _ => None,
+    }
+}
+
+/// Convert our optimization level to the ONNX Runtime equivalent.
+fn graph_optimization_level(level: OptimizationLevel) -> GraphOptimizationLevel {
+    match level {
+        OptimizationLevel::Disable => GraphOptimizationLevel::Disable,
+        OptimizationLevel::Basic => GraphOptimizationLevel::Level1,
+        OptimizationLevel::Extended => GraphOptimizationLevel::Level2,
+        OptimizationLevel::All => GraphOptimizationLevel::All,
+    }
+}
+
+/// Configure a session builder for `options`; the caller loads the model into it.
+///
+/// Failure to register a hardware execution provider is not fatal: the session
+/// falls back to the CPU provider and a warning is printed.
+fn session_builder(options: &RemoveBgOptions) -> Result<SessionBuilder> {
+    let device = options.device;
+    let mut builder = Session::builder()
+        .map_err(|e| RemoveBgError::ModelInitError(e.to_string()))?
+        .with_optimization_level(graph_optimization_level(options.optimization_level))
+        .map_err(|e| RemoveBgError::ModelInitError(e.to_string()))?;
+
+    if options.ort_environment.global_threads.is_some() && (options.intra_threads.is_some() || options.inter_threads.is_some()) {
+        log::warn!("sessions run on the global ONNX Runtime thread pool; ignoring the intra- and inter-op thread counts");
+    }
+
+    // ONNX Runtime's own default is one thread per core, whatever the
+    // process's CPU quota
+    let intra_threads =
+        options.intra_threads.or_else(|| options.ort_environment.global_threads.is_none().then(crate::cpu::available_parallelism));
+    if let Some(threads) = intra_threads {
+        builder = builder
+            .with_intra_threads(threads)
+            .map_err(|e| RemoveBgError::ModelInitError(e.to_string()))?;
+    }
+
+    if let Some(threads) = options.inter_threads {
+        builder = builder
+            .with_inter_threads(threads)
+            .map_err(|e| RemoveBgError::ModelInitError(e.to_string()))?;
+    }
+
+    let memory = &options.session_memory;
+    if !memory.arena {
+        builder = builder
+            .with_execution_providers([ort::ep::CPU::default().with_arena_allocator(false).build()])
+            .map_err(|e| RemoveBgError::ModelInitError(e.to_string()))?;
+    } else if memory.arena_limit.is_some() || options.ort_environment.allocator == OrtAllocator::Shared {
+        register_shared_arena(memory.arena_limit)?;
+        builder = builder
+            .with_env_allocators()
+            .map_err(|e| RemoveBgError::ModelInitError(e.to_string()))?;
+    }
+
+    match execution_provider(device) {
+        Some(provider) => {
+            builder = builder
+                .with_execution_providers([provider])
+                .unwrap_or_else(|e| {
+                    log::warn!(
+                        "{} execution provider unavailable, falling back to CPU: {}",
+                        device, e
+                    );
+                    trace_event!("fallback", reason = "execution provider unavailable", device = %device, error = %e);
+                    e.recover()
+                });
+        }
+        None if device != Device::Cpu => {
+            log::warn!(
+                "removebg was built without the `{}` feature, falling back to CPU",
+                device
+            );
+            trace_event!("fallback", reason = "execution provider not built", device = %device);
+        }
+        None => {}
+    }
+
+    Ok(builder)
+}
+
+/// Register a CPU arena allocator that grows to at most `limit` bytes (`None`
+/// = no limit) with the ONNX Runtime environment, for sessions created with
+/// `with_env_allocators`.
+///
+/// The environment holds one such allocator, so the first limit applies to
+/// the whole process; later, different limits only log a warning.
+fn register_shared_arena(limit: Option<usize>) -> Result<()> {
+    static REGISTERED: OnceLock<std::result::Result<Option<usize>, String>> = OnceLock::new();
+
+    match REGISTERED.get_or_init(|| create_shared_arena(limit).map(|()| limit)) {
+        Ok(registered) if *registered != limit => {
+            let describe = |limit: Option<usize>| limit.map_or("unlimited".to_string(), |bytes| format!("limited to {} bytes", bytes));
+            log::warn!(
+                "the shared ONNX Runtime arena is already {}; ignoring the request for one {}",
+                describe(*registered),
+                describe(limit)
+            );
+            Ok(())
+        }
+        Ok(_) => Ok(()),
+        Err(e) => Err(RemoveBgError::ModelInitError(format!("Failed to create the shared ONNX Runtime arena: {}", e))),
+    }
+}
+
+fn create_shared_arena(limit: Option<usize>) -> std::result::Result<(), String> {
+    let api = ort::api();
+    let environment = Environment::current().map_err(|e| e.to_string())?;
+    let memory_info = MemoryInfo::new(AllocationDevice::CPU, 0, AllocatorType::Arena, MemoryType::Default)
+        .map_err(|e| e.to_string())?;
+    let keys = [c"max_mem".as_ptr()];
+    // ONNX Runtime reads 0 as no limit
+    let values = [limit.unwrap_or(0)];
+    let mut config = std::ptr::null_mut();
+    // Safety: the pointers come from live ort objects and the local arrays;
+    // ONNX Runtime copies the arena config when registering the allocator
+    unsafe {
+        check_status(api, (api.CreateArenaCfgV2)(keys.as_ptr(), values.as_ptr(), keys.len(), &mut config))?;
+        let status = (api.CreateAndRegisterAllocator)(environment.ptr().cast_mut(), memory_info.ptr(), config);
+        (api.ReleaseArenaCfg)(config);
+        check_status(api, status)
+    }
+}
+
+/// Turn an `OrtStatus` returned by the C API into its error message.
+///
+/// # Safety
+/// `status` must be null or a status returned by `api`, which is released.
+unsafe fn check_status(api: &OrtApi, status: OrtStatusPtr) -> std::result::Result<(), String> {
+    if status.0.is_null() {
+        return Ok(());
+    }
+    let message = unsafe { CStr::from_ptr((api.GetErrorMessage)(status.0)) }.to_string_lossy().into_owned();
+    unsafe { (api.ReleaseStatus)(status.0) };
+    Err(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stub_model() -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/models/u2netp.onnx")
+    }
+
+    #[test]
+    fn maps_the_model_file() {
+        let path = stub_model();
+        match model_data(&path, &SessionMemoryOptions::default()) {
+            ModelData::Mapped(map) => assert_eq!(&map[..], std::fs::read(&path).unwrap()),
+            ModelData::File(_) => panic!("the model file was not mapped"),
+        }
+    }
+
+    #[test]
+    fn reads_the_file_when_mapping_is_off() {
+        let path = stub_model();
+        let memory = SessionMemoryOptions { mmap_model: false, ..Default::default() };
+        assert!(matches!(model_data(&path, &memory), ModelData::File(file) if file == path));
+    }
+
+    #[test]
+    fn falls_back_to_reading_when_mapping_fails() {
+        // Directories open but can't be mapped, like files on file systems without mmap
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/models");
+        assert!(matches!(model_data(&dir, &SessionMemoryOptions::default()), ModelData::File(file) if file == dir));
+    }
+}
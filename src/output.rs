@@ -0,0 +1,1173 @@
+//! Output formats and image encoding.
+//!
+//! This module decides which container an output image is written in and
+//! encodes the RGBA result accordingly. Applications that need another
+//! container implement [`OutputEncoder`] and set it as
+//! [`RemoveBgOptions::encoder`].
+
+use crate::core::Gray16Image;
+use crate::error::{RemoveBgError, Result};
+use crate::icon;
+use crate::options::RemoveBgOptions;
+use crate::paths;
+use crate::quantize;
+use crate::reproducible;
+use image::codecs::bmp::BmpEncoder;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::tiff::TiffEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::{Delay, DynamicImage, Frame, GrayImage, ImageEncoder, RgbImage, RgbaImage};
+use image::metadata::LoopCount;
+use std::borrow::Cow;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Cursor, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::str::FromStr;
+
+/// Image container used for the output file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum OutputFormat {
+    /// Lossless PNG with alpha (default).
+    #[default]
+    Png,
+    /// WebP with alpha; lossless unless a quality is given.
+    WebP,
+    /// TIFF with alpha.
+    Tiff,
+    /// 32-bit BMP with alpha.
+    Bmp,
+    /// JPEG; no alpha, so output must be flattened onto a background color.
+    Jpeg,
+    /// GIF with 1-bit transparency; animated inputs stay animated.
+    Gif,
+    /// Animated PNG with alpha.
+    Apng,
+    /// Multi-resolution icon of the subject, cropped and squared (see
+    /// `RemoveBgOptions::icon`).
+    Ico,
+}
+
+/// Capabilities of an output format.
+struct FormatInfo {
+    format: OutputFormat,
+    /// Name in messages and help.
+    name: &'static str,
+    /// Canonical file extension.
+    extension: &'static str,
+    /// Additional recognized extensions.
+    aliases: &'static [&'static str],
+    /// Whether the container can store an alpha channel.
+    alpha: bool,
+    /// MIME type of encoded files.
+    mime: &'static str,
+}
+
+/// Capability table for all supported output formats.
+const FORMATS: &[FormatInfo] = &[
+    FormatInfo { format: OutputFormat::Png, name: "PNG", extension: "png", aliases: &[], alpha: true, mime: "image/png" },
+    FormatInfo { format: OutputFormat::WebP, name: "WebP", extension: "webp", aliases: &[], alpha: true, mime: "image/webp" },
+    FormatInfo { format: OutputFormat::Tiff, name: "TIFF", extension: "tiff", aliases: &["tif"], alpha: true, mime: "image/tiff" },
+    FormatInfo { format: OutputFormat::Bmp, name: "BMP", extension: "bmp", aliases: &[], alpha: true, mime: "image/bmp" },
+    FormatInfo { format: OutputFormat::Jpeg, name: "JPEG", extension: "jpg", aliases: &["jpeg"], alpha: false, mime: "image/jpeg" },
+    FormatInfo { format: OutputFormat::Gif, name: "GIF", extension: "gif", aliases: &[], alpha: true, mime: "image/gif" },
+    // Listed after PNG so that `.png` paths are detected as still PNG
+    FormatInfo { format: OutputFormat::Apng, name: "APNG", extension: "apng", aliases: &["png"], alpha: true, mime: "image/apng" },
+    FormatInfo { format: OutputFormat::Ico, name: "ICO", extension: "ico", aliases: &[], alpha: true, mime: "image/x-icon" },
+];
+
+impl OutputFormat {
+    fn info(self) -> &'static FormatInfo {
+        FORMATS
+            .iter()
+            .find(|info| info.format == self)
+            .expect("every output format has a table entry")
+    }
+
+    /// Name of this format in messages, e.g. `JPEG`.
+    pub fn name(self) -> &'static str {
+        self.info().name
+    }
+
+    /// File extension used for this format, without the leading dot.
+    pub fn extension(self) -> &'static str {
+        self.info().extension
+    }
+
+    /// Whether this format can store an alpha channel.
+    pub fn supports_alpha(self) -> bool {
+        self.info().alpha
+    }
+
+    /// MIME type of files in this format, e.g. `image/png`.
+    pub fn mime_type(self) -> &'static str {
+        self.info().mime
+    }
+
+    /// Whether `extension` (case-insensitive) is a valid extension for this format.
+    pub(crate) fn matches_extension(self, extension: &str) -> bool {
+        let info = self.info();
+        let extension = extension.to_ascii_lowercase();
+        info.extension == extension || info.aliases.contains(&extension.as_str())
+    }
+
+    /// Whether this format can store several frames.
+    pub fn supports_animation(self) -> bool {
+        matches!(self, OutputFormat::Gif | OutputFormat::Apng)
+    }
+
+    /// Detect the output format from a file extension (case-insensitive).
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        let extension = extension.to_ascii_lowercase();
+        FORMATS
+            .iter()
+            .find(|info| info.format.matches_extension(&extension))
+            .map(|info| info.format)
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        OutputFormat::from_extension(s)
+            .ok_or_else(|| {
+                format!(
+                    "unknown output format '{}' (expected one of: png, webp, tiff, bmp, jpeg, gif, apng, ico)",
+                    s
+                )
+            })
+    }
+}
+
+/// PNG compression level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum PngCompression {
+    /// Fast encoding with decent compression (default).
+    #[default]
+    Fast,
+    /// Balanced speed and file size.
+    Default,
+    /// Smallest files, slowest encoding.
+    Best,
+}
+
+impl fmt::Display for PngCompression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PngCompression::Fast => "fast",
+            PngCompression::Default => "default",
+            PngCompression::Best => "best",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for PngCompression {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "fast" => Ok(PngCompression::Fast),
+            "default" => Ok(PngCompression::Default),
+            "best" => Ok(PngCompression::Best),
+            other => Err(format!(
+                "unknown PNG compression '{}' (expected one of: fast, default, best)",
+                other
+            )),
+        }
+    }
+}
+
+/// PNG row filter strategy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum PngFilter {
+    /// No filtering.
+    None,
+    /// Difference to the previous pixel in the row.
+    Sub,
+    /// Difference to the pixel above.
+    Up,
+    /// Difference to the average of left and above.
+    Avg,
+    /// Paeth predictor.
+    Paeth,
+    /// Choose the best filter per row (default).
+    #[default]
+    Adaptive,
+}
+
+impl fmt::Display for PngFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PngFilter::None => "none",
+            PngFilter::Sub => "sub",
+            PngFilter::Up => "up",
+            PngFilter::Avg => "avg",
+            PngFilter::Paeth => "paeth",
+            PngFilter::Adaptive => "adaptive",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for PngFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(PngFilter::None),
+            "sub" => Ok(PngFilter::Sub),
+            "up" => Ok(PngFilter::Up),
+            "avg" => Ok(PngFilter::Avg),
+            "paeth" => Ok(PngFilter::Paeth),
+            "adaptive" => Ok(PngFilter::Adaptive),
+            other => Err(format!(
+                "unknown PNG filter '{}' (expected one of: none, sub, up, avg, paeth, adaptive)",
+                other
+            )),
+        }
+    }
+}
+
+/// PNG encoder settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct PngOptions {
+    /// Compression level.
+    pub compression: PngCompression,
+    /// Row filter strategy.
+    pub filter: PngFilter,
+    /// Reduce the image to an 8-bit palette with alpha (median cut) for smaller
+    /// files at some loss of color fidelity.
+    pub quantize: bool,
+}
+
+/// Color profile and EXIF data carried over from the input image.
+///
+/// The EXIF orientation is reset, since the orientation is applied to the
+/// pixels when the input is decoded.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Metadata {
+    pub icc_profile: Option<Vec<u8>>,
+    pub exif: Option<Vec<u8>>,
+}
+
+impl Metadata {
+    /// Attach the metadata to an image crate encoder, skipping what the format can't store.
+    fn apply(&self, encoder: &mut impl ImageEncoder, format: OutputFormat) {
+        if let Some(icc_profile) = &self.icc_profile {
+            if encoder.set_icc_profile(icc_profile.clone()).is_err() {
+                log::debug!("{} output cannot store an ICC profile; dropping it", format);
+            }
+        }
+        if let Some(exif) = &self.exif {
+            if encoder.set_exif_metadata(exif.clone()).is_err() {
+                log::debug!("{} output cannot store EXIF metadata; dropping it", format);
+            }
+        }
+    }
+
+    /// `png` crate header info for an image carrying this metadata.
+    fn png_info(&self, width: u32, height: u32) -> png::Info<'static> {
+        let mut info = png::Info::with_size(width, height);
+        info.icc_profile = self.icc_profile.clone().map(Into::into);
+        info.exif_metadata = self.exif.clone().map(Into::into);
+        info
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn is_empty(&self) -> bool {
+        self.icc_profile.is_none() && self.exif.is_none()
+    }
+}
+
+/// View of `image` as 8-bit RGBA, converting only when needed.
+fn rgba8(image: &DynamicImage) -> Cow<'_, RgbaImage> {
+    match image.as_rgba8() {
+        Some(image) => Cow::Borrowed(image),
+        None => Cow::Owned(image.to_rgba8()),
+    }
+}
+
+/// Default JPEG quality when none is specified.
+pub(crate) const DEFAULT_JPEG_QUALITY: u8 = 90;
+
+/// Default JPEG quality for chroma-key output, where compression artifacts
+/// along the edge spoil the key.
+pub(crate) const CHROMA_KEY_JPEG_QUALITY: u8 = 95;
+
+/// Whether an output path names a directory to write into rather than a
+/// file: it ends with a path separator (`processed/`) or is an existing
+/// directory.
+pub fn is_directory_path(path: &Path) -> bool {
+    let trailing_separator = path.as_os_str().as_encoded_bytes().last().is_some_and(|&b| std::path::is_separator(b as char));
+    trailing_separator || paths::long_path(path).is_dir()
+}
+
+/// Make sure an output file can be written at `path` before any work is done.
+///
+/// A missing parent directory is created when `create_dirs` is set and
+/// reported otherwise. Write permission is checked by briefly creating a
+/// probe file next to the output, so a read-only location fails up front
+/// instead of after inference.
+///
+/// # Arguments
+/// * `path` - Output file path.
+/// * `create_dirs` - Create missing parent directories.
+///
+/// # Errors
+/// * `OutputDirectoryMissing` - If the parent directory doesn't exist and `create_dirs` is false
+/// * `IoError` - If the directory cannot be created or written to
+pub fn prepare_output_dir(path: &Path, create_dirs: bool) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let long_dir = paths::long_path(dir);
+    if !long_dir.is_dir() {
+        if !create_dirs {
+            return Err(RemoveBgError::OutputDirectoryMissing(dir.to_path_buf()));
+        }
+        std::fs::create_dir_all(&long_dir)?;
+    }
+
+    let name = path.file_name().unwrap_or_default();
+    let suffix = format!(".probe-{}", std::process::id());
+    let probe = long_dir.join(paths::file_name([".".as_ref(), name, suffix.as_ref()]));
+    let created = std::fs::OpenOptions::new().write(true).create_new(true).open(&probe);
+    match created {
+        Ok(_) => {
+            let _ = std::fs::remove_file(&probe);
+            Ok(())
+        }
+        Err(e) => Err(std::io::Error::new(
+            e.kind(),
+            format!("cannot write to {}: {}", dir.display(), e),
+        )
+        .into()),
+    }
+}
+
+/// An output file written under a temporary name and moved into place once complete.
+///
+/// Data goes to `.<name>.tmp-<id>` in the destination directory, so the final
+/// rename stays on one file system and is atomic: the destination holds either
+/// its previous contents or the complete new file, never a truncated one. The
+/// temporary file is removed if the `AtomicFile` is dropped without
+/// [`commit`](Self::commit), e.g. when encoding fails or the run is cancelled.
+pub(crate) struct AtomicFile {
+    path: PathBuf,
+    temp_path: PathBuf,
+    file: BufWriter<File>,
+    committed: bool,
+}
+
+impl AtomicFile {
+    /// Create the temporary file for `path`.
+    pub(crate) fn create(path: &Path) -> Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let path = paths::long_path(path);
+        let name = path.file_name().unwrap_or_default();
+        let id = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let suffix = format!(".tmp-{:x}-{:x}", std::process::id(), id);
+        let temp_path = path.with_file_name(paths::file_name([".".as_ref(), name, suffix.as_ref()]));
+        let temp_path = paths::long_path(&temp_path).into_owned();
+        let file = std::fs::OpenOptions::new().write(true).create_new(true).open(&temp_path)?;
+        Ok(AtomicFile {
+            path: path.into_owned(),
+            temp_path,
+            file: BufWriter::new(file),
+            committed: false,
+        })
+    }
+
+    /// Flush and sync the data, then rename it over the destination. With
+    /// `SOURCE_DATE_EPOCH` set, the file is dated to it.
+    pub(crate) fn commit(mut self) -> Result<()> {
+        self.file.flush()?;
+        if let Some(time) = reproducible::source_date_epoch() {
+            if let Err(e) = self.file.get_ref().set_modified(time) {
+                log::debug!("could not set the modification time of {}: {}", self.path.display(), e);
+            }
+        }
+        self.file.get_ref().sync_all()?;
+        std::fs::rename(&self.temp_path, &self.path)?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Seek for AtomicFile {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = std::fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+/// Write `data` to `path` atomically (see [`AtomicFile`]).
+///
+/// Either the complete data ends up at `path` or, on failure, the destination
+/// is left as it was and no temporary file remains.
+///
+/// # Errors
+/// * `IoError` - If the file cannot be written or renamed into place
+pub fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let mut file = AtomicFile::create(path)?;
+    file.write_all(data)?;
+    file.commit()
+}
+
+/// Encode an RGBA image and write it to `path` atomically.
+///
+/// See [`encode_image`] for how each format is encoded.
+pub(crate) fn save_image(
+    image: &DynamicImage,
+    path: &Path,
+    format: OutputFormat,
+    options: &RemoveBgOptions,
+    metadata: &Metadata,
+) -> Result<()> {
+    let mut file = AtomicFile::create(path)?;
+    encode_image(image, &mut file, format, options, metadata)?;
+    file.commit()
+}
+
+/// Write a mask to `path` atomically as a 16-bit grayscale PNG.
+///
+/// The full 16-bit range is kept so the mask reads back unchanged when used
+/// as an external mask.
+pub(crate) fn save_mask(mask: &Gray16Image, path: &Path) -> Result<()> {
+    let mut file = AtomicFile::create(path)?;
+    mask.write_with_encoder(PngEncoder::new(&mut file))?;
+    file.commit()
+}
+
+/// Write a trimap to `path` atomically as an 8-bit grayscale PNG.
+pub(crate) fn save_trimap(trimap: &GrayImage, path: &Path) -> Result<()> {
+    let mut file = AtomicFile::create(path)?;
+    trimap.write_with_encoder(PngEncoder::new(&mut file))?;
+    file.commit()
+}
+
+/// Encode an 8-bit or 16-bit RGBA image into `writer`.
+///
+/// Like [`encode_stream`], except that TIFF, which seeks back to write its
+/// directory, is encoded straight into `writer` instead of through a buffer.
+pub(crate) fn encode_image<W: Write + Seek>(
+    image: &DynamicImage,
+    writer: W,
+    format: OutputFormat,
+    options: &RemoveBgOptions,
+    metadata: &Metadata,
+) -> Result<()> {
+    match format {
+        OutputFormat::Tiff if options.encoder.is_none() => {
+            check_quality(options)?;
+            let stripped = Metadata::default();
+            write_tiff(image, writer, if options.strip_metadata { &stripped } else { metadata })
+        }
+        _ => encode_stream(image, writer, format, options, metadata),
+    }
+}
+
+/// Encode an 8-bit or 16-bit RGBA image into `writer`, which only has to
+/// accept writes.
+///
+/// PNG and TIFF keep 16 bits per channel; the other formats are written at
+/// 8 bits. PNG is encoded row by row and written out as it is compressed, so
+/// the encoded file is never held in memory; TIFF is encoded into a buffer
+/// first. For WebP, `quality` selects lossy encoding (0-100); `None` writes
+/// lossless WebP.
+/// JPEG uses `quality` or a default of 90 (95 with `options.chroma_key`), and
+/// drops the alpha channel, so the image should already be flattened. PNG
+/// output uses `options.png`, and ICO output `options.icon`.
+///
+/// `metadata` is embedded where the format supports it, unless
+/// `options.strip_metadata` is set.
+///
+/// With `options.encoder`, the image is reduced to 8-bit RGBA and encoded by
+/// it instead, whatever `format` is; the encoder gets no metadata.
+pub(crate) fn encode_stream<W: Write>(
+    image: &DynamicImage,
+    mut writer: W,
+    format: OutputFormat,
+    options: &RemoveBgOptions,
+    metadata: &Metadata,
+) -> Result<()> {
+    if let Some(encoder) = &options.encoder {
+        return encoder.encode(&rgba8(image), &mut writer);
+    }
+
+    let stripped = Metadata::default();
+    let metadata = if options.strip_metadata { &stripped } else { metadata };
+
+    check_quality(options)?;
+    let quality = options.quality;
+
+    let keeps_depth = match format {
+        OutputFormat::Png => !options.png.quantize,
+        OutputFormat::Tiff => true,
+        _ => false,
+    };
+    if image.as_rgba8().is_none() && !keeps_depth {
+        log::debug!("{} output is 8-bit; reducing the 16-bit image", format);
+    }
+
+    match format {
+        OutputFormat::Png => write_png(image, writer, &options.png, metadata)?,
+        OutputFormat::Tiff => {
+            let mut buffer = Cursor::new(Vec::new());
+            write_tiff(image, &mut buffer, metadata)?;
+            writer.write_all(buffer.get_ref())?;
+        }
+        OutputFormat::Bmp => rgba8(image).write_with_encoder(BmpEncoder::new(&mut writer))?,
+        OutputFormat::Jpeg => {
+            let default = match options.chroma_key {
+                Some(_) => CHROMA_KEY_JPEG_QUALITY,
+                None => DEFAULT_JPEG_QUALITY,
+            };
+            let quality = quality.map(|q| q.round() as u8).unwrap_or(default);
+            write_jpeg(&image.to_rgb8(), writer, quality, metadata)?;
+        }
+        OutputFormat::WebP => write_webp(&rgba8(image), writer, quality, metadata)?,
+        OutputFormat::Gif | OutputFormat::Apng => {
+            let (width, height) = (image.width(), image.height());
+            let mut encoder = AnimationEncoder::new(
+                writer,
+                format,
+                (width, height),
+                1,
+                LoopCount::Infinite,
+                options,
+                metadata,
+            )?;
+            encoder.write_frame(image.to_rgba8(), Delay::from_numer_denom_ms(0, 1))?;
+            encoder.finish()?;
+        }
+        OutputFormat::Ico => icon::write_ico(image, writer, &options.icon)?,
+    }
+
+    Ok(())
+}
+
+/// Reject an `options.quality` outside 0-100.
+fn check_quality(options: &RemoveBgOptions) -> Result<()> {
+    match options.quality {
+        Some(quality) if !(0.0..=100.0).contains(&quality) => Err(RemoveBgError::InvalidOutputFormat(format!(
+            "Quality must be between 0 and 100, got {}",
+            quality
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Encoder for the cutout that replaces the built-in output formats, set as
+/// [`RemoveBgOptions::encoder`].
+///
+/// For containers this crate doesn't write, such as GPU texture formats.
+/// Outputs are still named (with [`extension`](OutputEncoder::extension)),
+/// written atomically and reported like built-in ones; only the encoding is
+/// replaced. The encoder gets the finished 8-bit RGBA cutout, without the
+/// input's metadata. [`PngOutput`], [`WebPOutput`], [`TiffOutput`] and
+/// [`JpegOutput`] encode like the built-in formats.
+///
+/// # Examples
+/// ```no_run
+/// use removebg::output::OutputEncoder;
+/// use removebg::{remove_background_with_options, RemoveBgOptions, Result};
+/// use std::io::Write;
+/// use std::sync::Arc;
+///
+/// /// Raw RGBA pixels after a width and height header.
+/// #[derive(Debug)]
+/// struct RawRgba;
+///
+/// impl OutputEncoder for RawRgba {
+///     fn encode(&self, image: &image::RgbaImage, writer: &mut dyn Write) -> Result<()> {
+///         writer.write_all(&image.width().to_le_bytes())?;
+///         writer.write_all(&image.height().to_le_bytes())?;
+///         writer.write_all(image.as_raw())?;
+///         Ok(())
+///     }
+///
+///     fn extension(&self) -> &str {
+///         "rgba"
+///     }
+/// }
+///
+/// let options = RemoveBgOptions { encoder: Some(Arc::new(RawRgba)), ..Default::default() };
+/// // Writes photo_nobg.rgba
+/// let output = remove_background_with_options("photo.jpg", None, &options)?;
+/// # Ok::<(), removebg::error::RemoveBgError>(())
+/// ```
+pub trait OutputEncoder: fmt::Debug + Send + Sync {
+    /// Encode `image` into `writer`.
+    ///
+    /// # Errors
+    /// Any error; it fails the output, which is then not written.
+    fn encode(&self, image: &RgbaImage, writer: &mut dyn Write) -> Result<()>;
+
+    /// File extension of the encoded files, without the leading dot.
+    fn extension(&self) -> &str;
+}
+
+/// PNG with alpha, as [`OutputFormat::Png`] writes it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PngOutput(pub PngOptions);
+
+impl OutputEncoder for PngOutput {
+    fn encode(&self, image: &RgbaImage, writer: &mut dyn Write) -> Result<()> {
+        if self.0.quantize {
+            return write_indexed_png(image, writer, &self.0, &Metadata::default());
+        }
+        let size = (image.width(), image.height());
+        write_png_pixels(image.as_raw(), size, (png::ColorType::Rgba, png::BitDepth::Eight), writer, &self.0, &Metadata::default())
+    }
+
+    fn extension(&self) -> &str {
+        OutputFormat::Png.extension()
+    }
+}
+
+/// WebP with alpha: lossy at `quality` (0-100), lossless if `None`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WebPOutput {
+    /// Lossy encoding quality; `None` encodes losslessly.
+    pub quality: Option<f32>,
+}
+
+impl OutputEncoder for WebPOutput {
+    fn encode(&self, image: &RgbaImage, writer: &mut dyn Write) -> Result<()> {
+        write_webp(image, writer, self.quality, &Metadata::default())
+    }
+
+    fn extension(&self) -> &str {
+        OutputFormat::WebP.extension()
+    }
+}
+
+/// TIFF with alpha.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TiffOutput;
+
+impl OutputEncoder for TiffOutput {
+    fn encode(&self, image: &RgbaImage, writer: &mut dyn Write) -> Result<()> {
+        // TIFF seeks back to write its directory
+        let mut buffer = Cursor::new(Vec::new());
+        image.write_with_encoder(TiffEncoder::new(&mut buffer))?;
+        writer.write_all(buffer.get_ref())?;
+        Ok(())
+    }
+
+    fn extension(&self) -> &str {
+        OutputFormat::Tiff.extension()
+    }
+}
+
+/// JPEG, flattened onto `background` since JPEG has no alpha channel.
+#[derive(Debug, Clone, Copy)]
+pub struct JpegOutput {
+    /// Encoding quality (1-100).
+    pub quality: u8,
+    /// Color transparent pixels are blended onto.
+    pub background: [u8; 3],
+}
+
+impl Default for JpegOutput {
+    /// Quality 90 on white.
+    fn default() -> Self {
+        JpegOutput { quality: DEFAULT_JPEG_QUALITY, background: [255, 255, 255] }
+    }
+}
+
+impl OutputEncoder for JpegOutput {
+    fn encode(&self, image: &RgbaImage, writer: &mut dyn Write) -> Result<()> {
+        let flattened = RgbImage::from_fn(image.width(), image.height(), |x, y| {
+            let pixel = image.get_pixel(x, y);
+            let alpha = pixel[3] as u32;
+            image::Rgb(std::array::from_fn(|c| {
+                ((pixel[c] as u32 * alpha + self.background[c] as u32 * (255 - alpha) + 127) / 255) as u8
+            }))
+        });
+        write_jpeg(&flattened, writer, self.quality, &Metadata::default())
+    }
+
+    fn extension(&self) -> &str {
+        OutputFormat::Jpeg.extension()
+    }
+}
+
+/// The format an output without a path of its own is encoded in:
+/// `options.format`, else `default`. With a custom encoder it is PNG, which
+/// like the encoder takes the RGBA cutout as it is.
+pub(crate) fn selected_format(options: &RemoveBgOptions, default: OutputFormat) -> OutputFormat {
+    match options.encoder {
+        Some(_) => OutputFormat::Png,
+        None => options.format.unwrap_or(default),
+    }
+}
+
+/// File extension of outputs in `format`, or of the custom encoder's.
+pub(crate) fn output_extension(format: OutputFormat, options: &RemoveBgOptions) -> &str {
+    match &options.encoder {
+        Some(encoder) => encoder.extension(),
+        None => format.extension(),
+    }
+}
+
+/// How the container of an output was chosen, for error messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Selection<'a> {
+    /// `RemoveBgOptions::format` (`--format`).
+    Option,
+    /// The extension of the output path.
+    Extension(&'a str),
+    /// Neither; the default for the input.
+    Default,
+}
+
+impl Selection<'_> {
+    /// The selection of an output without a path of its own.
+    pub(crate) fn of(options: &RemoveBgOptions) -> Selection<'static> {
+        match options.format {
+            Some(_) => Selection::Option,
+            None => Selection::Default,
+        }
+    }
+}
+
+impl fmt::Display for Selection<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Selection::Option => f.write_str("selected by --format"),
+            Selection::Extension(extension) => write!(f, "selected by the .{} extension", extension),
+            Selection::Default => f.write_str("used by default"),
+        }
+    }
+}
+
+/// The container and final path of an output written to `path`.
+///
+/// This is the one place the format of a named output is decided, by the
+/// rules `removebg --help` lists under `OUTPUT FORMAT:`:
+///
+/// 1. `options.format` (`--format`) wins.
+/// 2. Otherwise the extension of `path` decides, if it names a supported
+///    container. `default` wins over other formats sharing its extension,
+///    so an animated input written to `.png` stays APNG.
+/// 3. Otherwise `default`: PNG, or GIF or APNG for animated inputs.
+///
+/// The path is never renamed silently: an extension that doesn't match the
+/// chosen container, or none at all, is refused unless
+/// [`NamingOptions::fix_extension`](crate::NamingOptions::fix_extension) is
+/// set, which replaces it with the container's. The container must then be
+/// able to hold the output: JPEG has no alpha channel, so it needs a
+/// background color or another opaque background.
+///
+/// # Examples
+/// ```
+/// use removebg::output::negotiate_format;
+/// use removebg::{Background, NamingOptions, OutputFormat, RemoveBgOptions};
+/// use std::path::{Path, PathBuf};
+///
+/// let white = RemoveBgOptions { background: Background::Color([255, 255, 255]), ..Default::default() };
+/// let (path, format) = negotiate_format(Path::new("out.jpg"), OutputFormat::Png, &white)?;
+/// assert_eq!((path, format), (PathBuf::from("out.jpg"), OutputFormat::Jpeg));
+///
+/// // A transparent cutout can't be a JPEG
+/// assert!(negotiate_format(Path::new("out.jpg"), OutputFormat::Png, &RemoveBgOptions::default()).is_err());
+///
+/// // Nor is `out.png` renamed to match --format, unless asked to
+/// let webp = RemoveBgOptions { format: Some(OutputFormat::WebP), ..Default::default() };
+/// assert!(negotiate_format(Path::new("out.png"), OutputFormat::Png, &webp).is_err());
+/// let fix = RemoveBgOptions { naming: NamingOptions { fix_extension: true, ..Default::default() }, ..webp };
+/// assert_eq!(negotiate_format(Path::new("out.png"), OutputFormat::Png, &fix)?.0, Path::new("out.webp"));
+/// # Ok::<(), removebg::RemoveBgError>(())
+/// ```
+///
+/// # Errors
+/// * `InvalidOutputFormat` - If the extension doesn't match the container
+///   and `fix_extension` isn't set, or the container can't hold the output
+pub fn negotiate_format(path: &Path, default: OutputFormat, options: &RemoveBgOptions) -> Result<(PathBuf, OutputFormat)> {
+    let extension = path.extension().and_then(|s| s.to_str());
+    let (format, selection) = match (options.format, extension) {
+        (Some(format), _) => (format, Selection::Option),
+        (None, Some(extension)) if default.matches_extension(extension) => (default, Selection::Extension(extension)),
+        (None, Some(extension)) => match OutputFormat::from_extension(extension) {
+            Some(detected) => (detected, Selection::Extension(extension)),
+            None => (default, Selection::Default),
+        },
+        (None, None) => (default, Selection::Default),
+    };
+
+    let path = match extension {
+        Some(extension) if format.matches_extension(extension) => path.to_path_buf(),
+        _ if options.naming.fix_extension => path.with_extension(format.extension()),
+        _ => return Err(extension_mismatch(path, format, selection)),
+    };
+    crate::core::check_transparency(format, selection, options)?;
+    Ok((path, format))
+}
+
+/// The error for an output path whose extension doesn't match `format`.
+fn extension_mismatch(path: &Path, format: OutputFormat, selection: Selection<'_>) -> RemoveBgError {
+    let renamed = path.with_extension(format.extension());
+    let message = match path.extension() {
+        None => format!(
+            "{} has no extension; name it {} for {} output, end it with {} to write into that directory, \
+             or pass --fix-extension",
+            path.display(),
+            renamed.display(),
+            format.name(),
+            std::path::MAIN_SEPARATOR
+        ),
+        Some(extension) if selection == Selection::Default => format!(
+            "{} doesn't end in the extension of an output format (.{} is none of {}); name it {} for {} output, \
+             or pass --fix-extension",
+            path.display(),
+            extension.to_string_lossy(),
+            extension_list(),
+            renamed.display(),
+            format.name()
+        ),
+        Some(extension) => format!(
+            "the .{} extension of {} doesn't match the {} output {}; name it {}, or pass --fix-extension",
+            extension.to_string_lossy(),
+            path.display(),
+            format.name(),
+            selection,
+            renamed.display()
+        ),
+    };
+    RemoveBgError::InvalidOutputFormat(message)
+}
+
+/// Every recognized extension, e.g. `.png, .webp, .tiff, .tif, ...`.
+fn extension_list() -> String {
+    let extensions = FORMATS.iter().flat_map(|info| std::iter::once(info.extension).chain(info.aliases.iter().copied()));
+    let mut seen = Vec::new();
+    for extension in extensions {
+        if !seen.contains(&extension) {
+            seen.push(extension);
+        }
+    }
+    seen.iter().map(|extension| format!(".{}", extension)).collect::<Vec<_>>().join(", ")
+}
+
+/// The `OUTPUT FORMAT:` section of `--help`: the rules of
+/// [`negotiate_format`] and the formats of [`FORMATS`].
+pub fn help() -> String {
+    use std::fmt::Write as _;
+    let mut help = String::from(
+        "OUTPUT FORMAT:\n  \
+         1. --format wins.\n  \
+         2. Otherwise the extension of -o decides, if it is one of the formats below.\n  \
+         3. Otherwise PNG, or GIF/APNG for animated inputs.\n\n",
+    );
+    let width = FORMATS.iter().map(|info| info.name.len()).max().unwrap_or(0);
+    for info in FORMATS {
+        let extensions: Vec<_> = std::iter::once(info.extension).chain(info.aliases.iter().copied()).map(|e| format!(".{}", e)).collect();
+        let alpha = if info.alpha { "transparency" } else { "no transparency: needs --bg-color" };
+        let _ = writeln!(help, "  {:width$}  {:12}  {}", info.name, extensions.join(" "), alpha, width = width);
+    }
+    help.push_str(
+        "\nAn -o extension that doesn't match the chosen format, or none at all, is an error; \
+         --fix-extension replaces it instead.",
+    );
+    help
+}
+
+/// Encode an RGB image as JPEG.
+fn write_jpeg<W: Write>(image: &RgbImage, writer: W, quality: u8, metadata: &Metadata) -> Result<()> {
+    let mut encoder = JpegEncoder::new_with_quality(writer, quality);
+    metadata.apply(&mut encoder, OutputFormat::Jpeg);
+    image.write_with_encoder(encoder)?;
+    Ok(())
+}
+
+/// Encode an RGBA image as WebP: lossy at `quality`, else lossless.
+fn write_webp<W: Write>(image: &RgbaImage, writer: W, quality: Option<f32>, metadata: &Metadata) -> Result<()> {
+    match quality {
+        #[cfg(target_arch = "wasm32")]
+        Some(_) => Err(RemoveBgError::ProcessingError(
+            "lossy WebP output needs libwebp, which is not available on wasm32".into(),
+        )),
+        #[cfg(not(target_arch = "wasm32"))]
+        Some(quality) => {
+            // The image crate only encodes lossless WebP, so use libwebp for lossy output
+            let mut writer = writer;
+            if !metadata.is_empty() {
+                log::debug!("lossy WebP output cannot store metadata; dropping it");
+            }
+            let encoded = webp::Encoder::from_rgba(image.as_raw(), image.width(), image.height()).encode(quality);
+            writer.write_all(&encoded)?;
+            Ok(())
+        }
+        None => {
+            let mut encoder = WebPEncoder::new_lossless(writer);
+            metadata.apply(&mut encoder, OutputFormat::WebP);
+            image.write_with_encoder(encoder)?;
+            Ok(())
+        }
+    }
+}
+
+/// Encode an image as TIFF, keeping 16-bit channels.
+fn write_tiff<W: Write + Seek>(image: &DynamicImage, writer: W, metadata: &Metadata) -> Result<()> {
+    let mut encoder = TiffEncoder::new(writer);
+    metadata.apply(&mut encoder, OutputFormat::Tiff);
+    image.write_with_encoder(encoder)?;
+    Ok(())
+}
+
+/// Size of the IDAT chunks streamed PNG data is written in.
+const PNG_CHUNK_SIZE: usize = 256 * 1024;
+
+/// Encode an image as PNG with the given encoder settings.
+///
+/// 16-bit images are written as 16-bit PNGs, unless quantized. The ICC
+/// profile is written as an iCCP chunk and EXIF data as an eXIf chunk.
+///
+/// Rows are filtered and compressed as they are passed to the `png` crate's
+/// stream writer, which writes each IDAT chunk once it fills, so neither the
+/// compressed data nor a big-endian copy of a 16-bit image is held in memory.
+fn write_png<W: Write>(image: &DynamicImage, writer: W, options: &PngOptions, metadata: &Metadata) -> Result<()> {
+    if options.quantize {
+        return write_indexed_png(&rgba8(image), writer, options, metadata);
+    }
+
+    let (color, depth) = match image {
+        DynamicImage::ImageLuma8(_) => (png::ColorType::Grayscale, png::BitDepth::Eight),
+        DynamicImage::ImageLumaA8(_) => (png::ColorType::GrayscaleAlpha, png::BitDepth::Eight),
+        DynamicImage::ImageRgb8(_) => (png::ColorType::Rgb, png::BitDepth::Eight),
+        DynamicImage::ImageRgba8(_) => (png::ColorType::Rgba, png::BitDepth::Eight),
+        DynamicImage::ImageLuma16(_) => (png::ColorType::Grayscale, png::BitDepth::Sixteen),
+        DynamicImage::ImageLumaA16(_) => (png::ColorType::GrayscaleAlpha, png::BitDepth::Sixteen),
+        DynamicImage::ImageRgb16(_) => (png::ColorType::Rgb, png::BitDepth::Sixteen),
+        DynamicImage::ImageRgba16(_) => (png::ColorType::Rgba, png::BitDepth::Sixteen),
+        // Floating point images are written at 16 bits
+        _ => return write_png(&DynamicImage::ImageRgba16(image.to_rgba16()), writer, options, metadata),
+    };
+    write_png_pixels(image.as_bytes(), (image.width(), image.height()), (color, depth), writer, options, metadata)
+}
+
+/// Encode `data`, the pixels of a `width` x `height` image in native byte
+/// order, as PNG in `color` at `depth`.
+fn write_png_pixels<W: Write>(
+    data: &[u8],
+    (width, height): (u32, u32),
+    (color, depth): (png::ColorType, png::BitDepth),
+    writer: W,
+    options: &PngOptions,
+    metadata: &Metadata,
+) -> Result<()> {
+    let mut encoder = png::Encoder::with_info(writer, metadata.png_info(width, height)).map_err(png_error)?;
+    encoder.set_color(color);
+    encoder.set_depth(depth);
+    set_png_options(&mut encoder, options);
+    let mut writer = encoder.write_header().map_err(png_error)?;
+    let mut stream = writer.stream_writer_with_size(PNG_CHUNK_SIZE).map_err(png_error)?;
+
+    let row_length = data.len() / height.max(1) as usize;
+    let mut row = Vec::new();
+    for pixels in data.chunks_exact(row_length.max(1)) {
+        match depth {
+            // PNG samples are big-endian
+            png::BitDepth::Sixteen => {
+                row.clear();
+                row.extend(pixels.chunks_exact(2).flat_map(|sample| u16::from_ne_bytes([sample[0], sample[1]]).to_be_bytes()));
+                stream.write_all(&row)?;
+            }
+            _ => stream.write_all(pixels)?,
+        }
+    }
+    stream.finish().map_err(png_error)?;
+    writer.finish().map_err(png_error)?;
+    Ok(())
+}
+
+/// Encode an RGBA image as an 8-bit palette PNG with a tRNS alpha chunk.
+fn write_indexed_png<W: Write>(
+    image: &RgbaImage,
+    writer: W,
+    options: &PngOptions,
+    metadata: &Metadata,
+) -> Result<()> {
+    let indexed = quantize::median_cut(image, 256);
+    let palette: Vec<u8> = indexed.palette.iter().flat_map(|c| [c[0], c[1], c[2]]).collect();
+    let alpha: Vec<u8> = indexed.palette.iter().map(|c| c[3]).collect();
+
+    let info = metadata.png_info(image.width(), image.height());
+    let mut encoder = png::Encoder::with_info(writer, info).map_err(png_error)?;
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(palette);
+    encoder.set_trns(alpha);
+    set_png_options(&mut encoder, options);
+
+    let mut writer = encoder.write_header().map_err(png_error)?;
+    writer.write_image_data(&indexed.indices).map_err(png_error)?;
+    writer.finish().map_err(png_error)?;
+
+    Ok(())
+}
+
+/// Apply compression and filter settings to a `png` crate encoder.
+fn set_png_options<W: Write>(encoder: &mut png::Encoder<W>, options: &PngOptions) {
+    encoder.set_compression(match options.compression {
+        PngCompression::Fast => png::Compression::Fast,
+        PngCompression::Default => png::Compression::Balanced,
+        PngCompression::Best => png::Compression::High,
+    });
+    encoder.set_filter(match options.filter {
+        PngFilter::None => png::Filter::NoFilter,
+        PngFilter::Sub => png::Filter::Sub,
+        PngFilter::Up => png::Filter::Up,
+        PngFilter::Avg => png::Filter::Avg,
+        PngFilter::Paeth => png::Filter::Paeth,
+        PngFilter::Adaptive => png::Filter::Adaptive,
+    });
+}
+
+fn png_error(e: png::EncodingError) -> RemoveBgError {
+    RemoveBgError::ProcessingError(format!("Failed to encode PNG: {}", e))
+}
+
+/// Frame-by-frame encoder for GIF and APNG output.
+///
+/// Frames are written as soon as they are passed in, so only one frame needs
+/// to be held in memory. Every frame covers the full canvas.
+pub(crate) enum AnimationEncoder<W: Write> {
+    Gif(GifEncoder<W>),
+    Apng(png::Writer<W>),
+}
+
+impl<W: Write> AnimationEncoder<W> {
+    /// Start an animation of `frame_count` frames (APNG needs the count up front).
+    pub(crate) fn new(
+        writer: W,
+        format: OutputFormat,
+        (width, height): (u32, u32),
+        frame_count: u32,
+        loop_count: LoopCount,
+        options: &RemoveBgOptions,
+        metadata: &Metadata,
+    ) -> Result<Self> {
+        let plays = match loop_count {
+            LoopCount::Infinite => 0,
+            LoopCount::Finite(n) => n.get(),
+        };
+
+        match format {
+            OutputFormat::Gif => {
+                let mut encoder = GifEncoder::new(writer);
+                encoder.set_repeat(match plays {
+                    0 => Repeat::Infinite,
+                    n => Repeat::Finite(n.min(u16::MAX as u32) as u16),
+                })?;
+                Ok(AnimationEncoder::Gif(encoder))
+            }
+            OutputFormat::Apng => {
+                let info = if options.strip_metadata {
+                    png::Info::with_size(width, height)
+                } else {
+                    metadata.png_info(width, height)
+                };
+                let mut encoder = png::Encoder::with_info(writer, info).map_err(png_error)?;
+                encoder.set_color(png::ColorType::Rgba);
+                encoder.set_depth(png::BitDepth::Eight);
+                set_png_options(&mut encoder, &options.png);
+                encoder.set_animated(frame_count.max(1), plays).map_err(png_error)?;
+                Ok(AnimationEncoder::Apng(encoder.write_header().map_err(png_error)?))
+            }
+            other => Err(RemoveBgError::InvalidOutputFormat(format!(
+                "{} cannot store animations; use GIF or APNG",
+                other.extension().to_ascii_uppercase()
+            ))),
+        }
+    }
+
+    /// Append one full-canvas frame shown for `delay`.
+    pub(crate) fn write_frame(&mut self, image: RgbaImage, delay: Delay) -> Result<()> {
+        match self {
+            AnimationEncoder::Gif(encoder) => {
+                encoder.encode_frame(Frame::from_parts(image, 0, 0, delay))?;
+            }
+            AnimationEncoder::Apng(writer) => {
+                let (numerator, denominator) = delay.numer_denom_ms();
+                let millis = (numerator as f64 / denominator.max(1) as f64).round();
+                writer
+                    .set_frame_delay(millis.min(u16::MAX as f64) as u16, 1000)
+                    .map_err(png_error)?;
+                writer.write_image_data(image.as_raw()).map_err(png_error)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Finish the animation and flush trailing data.
+    pub(crate) fn finish(self) -> Result<()> {
+        match self {
+            // The GIF trailer is written when the encoder is dropped
+            AnimationEncoder::Gif(encoder) => drop(encoder),
+            AnimationEncoder::Apng(writer) => writer.finish().map_err(png_error)?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn quantized_pngs_keep_alpha_in_trns() {
+        // Every alpha level over two colors, more than fit in the palette
+        let image = RgbaImage::from_fn(256, 4, |x, y| Rgba(if y < 2 { [230, 40, 20, x as u8] } else { [20, 60, 200, x as u8] }));
+        let mut png = Vec::new();
+        PngOutput(PngOptions { quantize: true, ..Default::default() }).encode(&image, &mut png).unwrap();
+
+        let decoder = png::Decoder::new(std::io::Cursor::new(&png));
+        let reader = decoder.read_info().unwrap();
+        assert_eq!(reader.info().color_type, png::ColorType::Indexed);
+        let trns = reader.info().trns.as_deref().expect("tRNS chunk");
+        assert!(trns.len() <= 256);
+
+        let decoded = image::load_from_memory(&png).unwrap().into_rgba8();
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let alpha = decoded.get_pixel(x, y)[3];
+            assert!(alpha.abs_diff(pixel[3]) <= 2, "({}, {}): {} for {}", x, y, alpha, pixel[3]);
+        }
+        assert_eq!(decoded.get_pixel(0, 0)[3], 0);
+        assert_eq!(decoded.get_pixel(255, 3)[3], 255);
+    }
+}
@@ -0,0 +1,219 @@
+//! Pages of multi-page TIFF inputs.
+//!
+//! Scanned documents often arrive as one TIFF holding a page per image file
+//! directory (IFD), of which the image crate only decodes the first.
+//! [`count`] follows the directory chain with the tiff decoder without
+//! decoding any pixels, and [`open_page`] presents a later page to the
+//! regular decoder as if it were the first, so its color handling, metadata
+//! and limits apply to every page alike.
+
+use crate::error::{RemoveBgError, Result};
+use crate::options::Pages;
+use crate::paths;
+use std::io::{self, BufRead, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use tiff::decoder::Decoder;
+
+/// Whether `header` starts like a TIFF file, classic or BigTIFF.
+fn is_tiff(header: &[u8]) -> bool {
+    [b"II*\0", b"MM\0*", b"II+\0", b"MM\0+"].iter().any(|magic| header.starts_with(*magic))
+}
+
+/// Number of pages of the image read from `reader`: the directories of a
+/// TIFF, and 1 for other formats.
+///
+/// A TIFF whose first directory can't be read counts as one page, leaving
+/// the error to the decoder; counting stops at a later directory that can't
+/// be read.
+pub(crate) fn count<R: BufRead + Seek>(mut reader: R) -> Result<u32> {
+    if !is_tiff(reader.fill_buf()?) {
+        return Ok(1);
+    }
+    let Ok(mut decoder) = Decoder::new(reader) else {
+        return Ok(1);
+    };
+    let mut count = 1;
+    while decoder.more_images() {
+        if let Err(e) = decoder.next_image() {
+            log::warn!("ignoring TIFF pages from page {} on: {}", count + 1, e);
+            break;
+        }
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Pages `pages` selects of an input with `count` pages, counting from 1.
+///
+/// # Errors
+/// * `PageOutOfRange` - If a single page is selected that the input doesn't have
+pub(crate) fn select(pages: Pages, count: u32, input: &Path) -> Result<Vec<u32>> {
+    match pages {
+        Pages::First => Ok(vec![1]),
+        Pages::Page(page) if (1..=count).contains(&page) => Ok(vec![page]),
+        Pages::Page(page) => Err(RemoveBgError::PageOutOfRange { path: input.to_path_buf(), page, count }),
+        Pages::All => Ok((1..=count).collect()),
+    }
+}
+
+/// `path` with `_p<page>` appended to its stem: `scan.tiff` becomes
+/// `scan_p2.tiff`.
+pub(crate) fn numbered(path: &Path, page: u32) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default();
+    let mut name = paths::file_name([stem, format!("_p{}", page).as_ref()]);
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
+
+/// A TIFF read with the first-directory offset in its header replaced by
+/// that of another page. Everything else is read from the file unchanged.
+pub(crate) struct PageReader<R> {
+    inner: R,
+    /// The file's header, up to and including the replaced offset.
+    header: Vec<u8>,
+    position: u64,
+}
+
+/// Open page `page` (from 1) of the TIFF read from `reader`.
+///
+/// # Errors
+/// * `ProcessingError` - If the file has no such page or its directory can't be read
+pub(crate) fn open_page<R: BufRead + Seek>(mut reader: R, page: u32) -> Result<PageReader<R>> {
+    let failed = |e: tiff::TiffError| RemoveBgError::ProcessingError(format!("Failed to load image page {}: {}", page, e));
+    let mut decoder = Decoder::new(&mut reader).map_err(failed)?;
+    decoder.seek_to_image(page.saturating_sub(1) as usize).map_err(failed)?;
+    let offset = decoder.ifd_pointer().map_or(0, |pointer| pointer.0);
+    drop(decoder);
+
+    // Classic TIFF keeps a 32-bit offset at byte 4, BigTIFF a 64-bit one at byte 8
+    let mut header = vec![0; 4];
+    reader.seek(SeekFrom::Start(0))?;
+    reader.read_exact(&mut header)?;
+    let little_endian = header.starts_with(b"II");
+    let offset = match header[2..4] {
+        [0, 43] | [43, 0] => {
+            header.resize(8, 0);
+            reader.read_exact(&mut header[4..])?;
+            if little_endian { offset.to_le_bytes() } else { offset.to_be_bytes() }.to_vec()
+        }
+        _ => {
+            let offset = u32::try_from(offset).map_err(|_| RemoveBgError::ProcessingError("TIFF directory offset out of range".into()))?;
+            if little_endian { offset.to_le_bytes() } else { offset.to_be_bytes() }.to_vec()
+        }
+    };
+    header.extend(offset);
+    reader.seek(SeekFrom::Start(header.len() as u64))?;
+    Ok(PageReader { inner: reader, header, position: 0 })
+}
+
+impl<R: BufRead + Seek> Read for PageReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let read = available.len().min(buf.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.consume(read);
+        Ok(read)
+    }
+}
+
+impl<R: BufRead + Seek> BufRead for PageReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // The inner reader waits at the end of the header while it is read
+        if self.position < self.header.len() as u64 {
+            return Ok(&self.header[self.position as usize..]);
+        }
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        if self.position >= self.header.len() as u64 {
+            self.inner.consume(amount);
+        }
+        self.position += amount as u64;
+    }
+}
+
+impl<R: Seek> Seek for PageReader<R> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let target = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.inner.seek(SeekFrom::End(0))?.checked_add_signed(delta),
+        }
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before the start of the file"))?;
+        self.inner.seek(SeekFrom::Start(target.max(self.header.len() as u64)))?;
+        self.position = target;
+        Ok(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::tiff::TiffEncoder;
+    use image::{ImageReader, Rgb, RgbImage};
+    use std::io::Cursor;
+
+    /// A TIFF with one solid page per color, built with the tiff encoder.
+    fn pages(colors: &[[u8; 3]], big: bool) -> Vec<u8> {
+        use tiff::encoder::{colortype, TiffEncoder as Encoder, TiffKindBig, TiffKindStandard};
+        let mut data = Cursor::new(Vec::new());
+        let pixels = |color: &[u8; 3]| color.repeat(4 * 3);
+        if big {
+            let mut encoder = Encoder::<_, TiffKindBig>::new_generic(&mut data).unwrap();
+            for color in colors {
+                encoder.write_image::<colortype::RGB8>(4, 3, &pixels(color)).unwrap();
+            }
+        } else {
+            let mut encoder = Encoder::<_, TiffKindStandard>::new_generic(&mut data).unwrap();
+            for color in colors {
+                encoder.write_image::<colortype::RGB8>(4, 3, &pixels(color)).unwrap();
+            }
+        }
+        data.into_inner()
+    }
+
+    fn decode(reader: impl BufRead + Seek) -> RgbImage {
+        ImageReader::with_format(reader, image::ImageFormat::Tiff).decode().unwrap().to_rgb8()
+    }
+
+    #[test]
+    fn pages_are_counted_and_read_as_the_first() {
+        let colors = [[255, 0, 0], [0, 255, 0], [0, 0, 255]];
+        for big in [false, true] {
+            let data = pages(&colors, big);
+            assert_eq!(count(Cursor::new(&data)).unwrap(), 3);
+            for (page, color) in (1..).zip(colors) {
+                let image = decode(open_page(Cursor::new(&data), page).unwrap());
+                assert_eq!(image.dimensions(), (4, 3));
+                assert!(image.pixels().all(|pixel| *pixel == Rgb(color)), "page {} of big {}", page, big);
+            }
+            assert!(open_page(Cursor::new(&data), 4).is_err());
+        }
+    }
+
+    #[test]
+    fn other_images_have_one_page() {
+        let mut single = Cursor::new(Vec::new());
+        RgbImage::new(2, 2).write_with_encoder(TiffEncoder::new(&mut single)).unwrap();
+        assert_eq!(count(Cursor::new(single.into_inner())).unwrap(), 1);
+        let mut png = Cursor::new(Vec::new());
+        RgbImage::new(2, 2).write_to(&mut png, image::ImageFormat::Png).unwrap();
+        assert_eq!(count(Cursor::new(png.into_inner())).unwrap(), 1);
+    }
+
+    #[test]
+    fn pages_are_selected_and_numbered() {
+        let input = Path::new("scan.tiff");
+        assert_eq!(select(Pages::First, 3, input).unwrap(), [1]);
+        assert_eq!(select(Pages::Page(2), 3, input).unwrap(), [2]);
+        assert_eq!(select(Pages::All, 3, input).unwrap(), [1, 2, 3]);
+        let error = select(Pages::Page(4), 3, input).unwrap_err();
+        assert!(matches!(error, RemoveBgError::PageOutOfRange { page: 4, count: 3, .. }), "{:?}", error);
+        assert_eq!(numbered(Path::new("in/scan.tiff"), 2), Path::new("in/scan_p2.tiff"));
+        assert_eq!(numbered(Path::new("out"), 1), Path::new("out_p1"));
+    }
+}
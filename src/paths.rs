@@ -0,0 +1,59 @@
+//! Platform details of file paths.
+//!
+//! Paths stay `Path`s from the API to the file system, so file names that
+//! aren't valid UTF-8 survive, and names derived from them (such as
+//! `photo_nobg.png`) are built from `OsStr` pieces instead of lossy strings.
+
+use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+
+/// `path` in a form the file system accepts at any length.
+///
+/// On Windows, absolute paths longer than the legacy `MAX_PATH` limit get the
+/// `\\?\` extended-length prefix (`\\?\UNC\` for network shares); shorter
+/// paths and other platforms are returned unchanged.
+#[cfg(windows)]
+pub(crate) fn long_path(path: &Path) -> Cow<'_, Path> {
+    use std::os::windows::ffi::{OsStrExt, OsStringExt};
+
+    // CreateDirectory allows 12 characters less than MAX_PATH (260), for an 8.3 file name
+    const LEGACY_LIMIT: usize = 248;
+    const BACKSLASH: u16 = b'\\' as u16;
+
+    let wide: Vec<u16> = path.as_os_str().encode_wide().collect();
+    let verbatim = wide.starts_with(&[BACKSLASH, BACKSLASH, b'?' as u16, BACKSLASH]);
+    if wide.len() < LEGACY_LIMIT || verbatim {
+        return Cow::Borrowed(path);
+    }
+    // Extended-length paths skip the normalization of `/` and `..`, so
+    // resolve them first
+    let Ok(absolute) = std::path::absolute(path) else {
+        return Cow::Borrowed(path);
+    };
+    let wide: Vec<u16> = absolute.as_os_str().encode_wide().collect();
+    let prefixed: Vec<u16> = if wide.starts_with(&[BACKSLASH, BACKSLASH]) {
+        // \\server\share\... becomes \\?\UNC\server\share\...
+        r"\\?\UNC".encode_utf16().chain(wide[1..].iter().copied()).collect()
+    } else {
+        r"\\?\".encode_utf16().chain(wide).collect()
+    };
+    Cow::Owned(OsString::from_wide(&prefixed).into())
+}
+
+/// `path` in a form the file system accepts at any length; only Windows has
+/// a path length limit to work around.
+#[cfg(not(windows))]
+pub(crate) fn long_path(path: &Path) -> Cow<'_, Path> {
+    Cow::Borrowed(path)
+}
+
+/// Concatenate path pieces into a file name without converting them to
+/// strings, e.g. `["photo", "_nobg", ".png"]`.
+pub(crate) fn file_name<'a>(parts: impl IntoIterator<Item = &'a OsStr>) -> OsString {
+    let mut name = OsString::new();
+    for part in parts {
+        name.push(part);
+    }
+    name
+}
@@ -0,0 +1,432 @@
+//! The stages of background removal as separate building blocks.
+//!
+//! [`remove_background`](crate::remove_background) and friends run these
+//! stages in order:
+//!
+//! 1. [`preprocess`] resizes and normalizes the image into the model's input
+//!    tensor.
+//! 2. [`infer`] runs the model and returns the mask at the model's output
+//!    resolution.
+//! 3. [`upscale_mask`] resizes that mask to the image size.
+//! 4. [`refine`] applies the mask refinements enabled in the options.
+//! 5. [`composite`] applies the mask to the image and builds the output.
+//!
+//! Calling them directly allows replacing or extending a single stage, such
+//! as a different resize or a custom mask post-processing step, while
+//! reusing the rest. Only [`infer`] needs a loaded model; the other stages
+//! work on plain images and arrays.
+//!
+//! Masks are 16-bit, so feathered edges don't band. An 8-bit mask converts
+//! with `DynamicImage::ImageLuma8(mask).into_luma16()`.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use removebg::pipeline;
+//! use removebg::{BackgroundRemover, RemoveBgOptions};
+//!
+//! let remover = BackgroundRemover::new(RemoveBgOptions::default())?;
+//! let options = remover.options();
+//! let image = image::open("photo.jpg")?;
+//!
+//! let input = pipeline::preprocess(&image, options.model.spec());
+//! let mask = pipeline::infer(&remover, input)?;
+//! let mut mask = pipeline::upscale_mask(&mask, (image.width(), image.height()), options.mask_filter);
+//! // Custom post-processing: drop faint pixels entirely
+//! for value in mask.iter_mut() {
+//!     if *value < 4096 {
+//!         *value = 0;
+//!     }
+//! }
+//! pipeline::refine(&mut mask, &image, options)?;
+//! let cutout = pipeline::composite(&image, &mask, options)?;
+//! cutout.save("photo_nobg.png")?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::core::{self, finish_mask};
+use crate::error::{RemoveBgError, Result};
+use crate::format;
+use crate::model::{ModelSpec, Normalization};
+use crate::options::{CancellationToken, MaskFilter, RemoveBgOptions};
+use crate::remover::{BackgroundRemover, Segmenter};
+use crate::report::StageDurations;
+use crate::upscale;
+use image::imageops::FilterType;
+use image::DynamicImage;
+use ndarray::Array4;
+
+pub use crate::core::{Gray16Image, Gray32FImage};
+
+/// Resize and normalize an image into the input tensor of the model
+/// described by `spec`.
+///
+/// The image is resized to the model's square input size (with a Triangle
+/// filter if that enlarges it, Lanczos3 otherwise) and converted to a
+/// `(1, 3, size, size)` array of RGB values in 0.0-1.0, channels first,
+/// then normalized with the spec's [`Normalization`] (a no-op for the U2-Net
+/// models, ImageNet statistics for birefnet-lite). Grayscale values are repeated into all three channels. Transparent parts
+/// are flattened onto neutral gray, so a cutout run through again doesn't
+/// show the model a black background; the alpha is also applied to the mask
+/// (see [`refine`]).
+///
+/// # Arguments
+/// * `image` - Image to segment, of any color type
+/// * `spec` - Model the tensor is prepared for, from [`Model::spec`](crate::Model::spec)
+///
+/// # Examples
+/// ```
+/// use image::{DynamicImage, RgbImage};
+/// use removebg::{pipeline, Model};
+///
+/// let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(64, 48, image::Rgb([255, 0, 0])));
+/// let input = pipeline::preprocess(&image, Model::U2netp.spec());
+/// assert_eq!(input.shape(), &[1, 3, 320, 320]);
+/// assert_eq!(input[[0, 0, 10, 10]], 1.0);
+/// assert_eq!(input[[0, 1, 10, 10]], 0.0);
+/// ```
+pub fn preprocess(image: &DynamicImage, spec: &ModelSpec) -> Array4<f32> {
+    preprocess_to(image, spec.input_size, &spec.normalization)
+}
+
+/// Gray transparent input pixels are flattened onto before inference.
+const FLATTEN_GRAY: f32 = 0.5;
+
+/// [`preprocess`] for a model taking `size`x`size` images, such as a model
+/// file that declares a size other than its [`ModelSpec`].
+pub(crate) fn preprocess_to(image: &DynamicImage, size: u32, normalization: &Normalization) -> Array4<f32> {
+    // Resize to the model input size (320x320 for U2-Net)
+    let resized = image.resize_exact(size, size, input_filter(image, size));
+
+    // Normalize to [0, 1] and split the interleaved pixels into CHW planes
+    let plane = size as usize * size as usize;
+    let mut data = vec![0.0; 3 * plane];
+    let (red, rest) = data.split_at_mut(plane);
+    let (green, blue) = rest.split_at_mut(plane);
+    if resized.color().has_alpha() {
+        let rgba = resized.to_rgba8();
+        for (((pixel, r), g), b) in rgba.as_raw().chunks_exact(4).zip(red).zip(green).zip(blue) {
+            let alpha = pixel[3] as f32 / 255.0;
+            let over_gray = |c: u8| c as f32 / 255.0 * alpha + FLATTEN_GRAY * (1.0 - alpha);
+            (*r, *g, *b) = (over_gray(pixel[0]), over_gray(pixel[1]), over_gray(pixel[2]));
+        }
+    } else {
+        let rgb = resized.to_rgb8();
+        for (((pixel, r), g), b) in rgb.as_raw().chunks_exact(3).zip(red).zip(green).zip(blue) {
+            *r = pixel[0] as f32 / 255.0;
+            *g = pixel[1] as f32 / 255.0;
+            *b = pixel[2] as f32 / 255.0;
+        }
+    }
+    if *normalization != Normalization::NONE {
+        for (channel, values) in data.chunks_exact_mut(plane).enumerate() {
+            let (mean, std) = (normalization.mean[channel], normalization.std[channel]);
+            values.iter_mut().for_each(|value| *value = (*value - mean) / std);
+        }
+    }
+
+    Array4::from_shape_vec((1, 3, size as usize, size as usize), data).expect("three planes of size x size")
+}
+
+/// Filter [`preprocess_to`] resizes `image` with: Lanczos3 when it shrinks
+/// on both axes, Triangle when either side is enlarged. Lanczos rings around
+/// edges when it enlarges, and on a 48x48 avatar stretched to 320x320 the
+/// ringing is wide enough for the model to pick up as edges of its own.
+pub(crate) fn input_filter(image: &DynamicImage, size: u32) -> FilterType {
+    if image.width() < size || image.height() < size {
+        FilterType::Triangle
+    } else {
+        FilterType::Lanczos3
+    }
+}
+
+/// Run the remover's model on a preprocessed input and return the mask at
+/// the model's output resolution.
+///
+/// The remover's options select how the model's outputs are fused
+/// ([`mask_fusion`](RemoveBgOptions::mask_fusion)) and the curve applied to
+/// the mask ([`mask_levels`](RemoveBgOptions::mask_levels),
+/// [`mask_gamma`](RemoveBgOptions::mask_gamma)). Use
+/// [`BackgroundRemover::process_images`] to run several images at once.
+///
+/// # Arguments
+/// * `remover` - Loaded model to run
+/// * `input` - A `(1, 3, size, size)` tensor, as returned by [`preprocess`]; other sizes work
+///   when the remover was loaded with an [`inference_size`](RemoveBgOptions::inference_size)
+///   the model runs at
+///
+/// # Errors
+/// * `ProcessingError` - If `input` doesn't hold exactly one image at a size the model runs at, the mask
+///   curve options are invalid, or the remover runs a custom [`SegmentationModel`](crate::SegmentationModel)
+/// * `ModelError` - If model inference fails
+/// * `Cancelled` - If the options' cancellation token was triggered
+///
+/// # Examples
+/// ```no_run
+/// use removebg::{pipeline, BackgroundRemover, RemoveBgOptions};
+///
+/// let remover = BackgroundRemover::new(RemoveBgOptions::default())?;
+/// let image = image::open("photo.jpg")?;
+/// let mask = pipeline::infer(&remover, pipeline::preprocess(&image, remover.options().model.spec()))?;
+/// println!("model mask is {}x{}", mask.width(), mask.height());
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn infer(remover: &BackgroundRemover, input: Array4<f32>) -> Result<Gray16Image> {
+    let Segmenter::Builtin(runner) = remover.segmenter() else {
+        return Err(RemoveBgError::ProcessingError(
+            "infer runs the built-in models only; call predict_mask on a custom model instead".into(),
+        ));
+    };
+    let size = runner.input_size() as usize;
+    let resized = matches!(input.shape(), &[1, 3, height, width] if height == width && runner.accepts_size(height as u32));
+    if input.shape() != [1, 3, size, size] && !resized {
+        return Err(RemoveBgError::ProcessingError(format!(
+            "expected an input tensor of shape (1, 3, {}, {}), got {:?}",
+            size,
+            size,
+            input.shape()
+        )));
+    }
+    let options = remover.options();
+    options.cancel.check()?;
+    let mut masks = core::run_model(runner.as_ref(), input, options, &mut StageDurations::default())?;
+    Ok(masks.remove(0))
+}
+
+/// Resize a mask to `(width, height)`, usually the size of the image it was
+/// computed for, with `filter`.
+///
+/// The mask is resized in floating point and clamped to 0-1 before it is
+/// converted back, so a filter's overshoot next to hard edges can't wrap
+/// around or pile up at the limits. Samples past the mask's edges repeat
+/// its outermost rows and columns, so the filter doesn't extrapolate a
+/// frame around the image. Large outputs, such as panoramas, are resized in
+/// parallel bands of rows straight into the result, without a full-size
+/// floating-point copy; they match a single-pass resize to within one
+/// 16-bit level.
+///
+/// # Examples
+/// ```
+/// use removebg::pipeline::{self, Gray16Image};
+/// use removebg::MaskFilter;
+///
+/// let mask = Gray16Image::from_pixel(320, 320, image::Luma([65535]));
+/// let mask = pipeline::upscale_mask(&mask, (1200, 800), MaskFilter::default());
+/// assert_eq!(mask.dimensions(), (1200, 800));
+/// assert_eq!(mask.get_pixel(600, 400)[0], 65535);
+/// ```
+pub fn upscale_mask(mask: &Gray16Image, size: (u32, u32), filter: MaskFilter) -> Gray16Image {
+    upscale_mask_until(mask, size, filter, false, &CancellationToken::default()).expect("a token without a deadline isn't cancelled")
+}
+
+/// [`upscale_mask`], checking `cancel` between the bands of large outputs.
+/// Outputs of any size are upscaled in bands with `banded`.
+pub(crate) fn upscale_mask_until(
+    mask: &Gray16Image,
+    size: (u32, u32),
+    filter: MaskFilter,
+    banded: bool,
+    cancel: &CancellationToken,
+) -> Result<Gray16Image> {
+    let mask = core::float_mask(mask);
+    match upscale::band_height(size, banded) {
+        Some(band) => {
+            log::debug!(
+                "upscaling the mask to {}x{} in bands of {} rows instead of through {} of floats",
+                size.0,
+                size.1,
+                band,
+                format::binary_size(size.0 as u64 * size.1 as u64 * 4)
+            );
+            upscale::upscale_in_bands(&mask, size, filter, band, cancel)
+        }
+        None => Ok(core::quantize_mask(&upscale_mask_f32(&mask, size, filter))),
+    }
+}
+
+/// [`upscale_mask`] for a floating-point mask, such as one from
+/// [`BackgroundRemover::mask_f32`]. The result is clamped to 0-1 and not
+/// quantized.
+///
+/// # Examples
+/// ```
+/// use removebg::pipeline::{self, Gray32FImage};
+/// use removebg::MaskFilter;
+///
+/// let mask = Gray32FImage::from_fn(2, 1, |x, _| image::Luma([x as f32]));
+/// let mask = pipeline::upscale_mask_f32(&mask, (8, 4), MaskFilter::CatmullRom);
+/// assert!(mask.iter().all(|&value| (0.0..=1.0).contains(&value)));
+/// ```
+pub fn upscale_mask_f32(mask: &Gray32FImage, size: (u32, u32), filter: MaskFilter) -> Gray32FImage {
+    // Bands only split the work between threads here; the floats are kept
+    let band = upscale::band_height(size, true).expect("forced bands");
+    upscale::upscale_in_bands(mask, size, filter, band, &CancellationToken::default())
+        .expect("a token without a deadline isn't cancelled")
+}
+
+/// Apply the mask refinements enabled in `options` to a full-resolution mask
+/// of `image`, in place.
+///
+/// This covers the guided filter, keeping the largest components, filling
+/// holes and inversion, in the same order as
+/// [`remove_background_with_options`](crate::remove_background_with_options).
+/// If `image` has an alpha channel, the mask is then multiplied by it, so
+/// areas that were already transparent stay transparent.
+///
+/// # Errors
+/// * `ProcessingError` - If the mask doesn't match the image size or the refinement options are invalid
+/// * `Cancelled` - If the options' cancellation token was triggered
+///
+/// # Examples
+/// ```
+/// use image::{DynamicImage, RgbImage};
+/// use removebg::pipeline::{self, Gray16Image};
+/// use removebg::RemoveBgOptions;
+///
+/// let image = DynamicImage::ImageRgb8(RgbImage::new(40, 40));
+/// let mut mask = Gray16Image::from_pixel(40, 40, image::Luma([65535]));
+/// let options = RemoveBgOptions { invert_mask: true, ..Default::default() };
+/// pipeline::refine(&mut mask, &image, &options)?;
+/// assert_eq!(mask.get_pixel(20, 20)[0], 0);
+/// # Ok::<(), removebg::error::RemoveBgError>(())
+/// ```
+pub fn refine(mask: &mut Gray16Image, image: &DynamicImage, options: &RemoveBgOptions) -> Result<()> {
+    check_mask_size(mask, image)?;
+    finish_mask(mask, image, options)
+}
+
+/// Apply a finished full-resolution mask to `image` and build the output
+/// image.
+///
+/// The mask becomes the alpha channel. Background replacement, blurring,
+/// decontamination, sticker outlines and the geometry options are applied as
+/// enabled in `options`. Inputs with more than 8 bits per channel produce a
+/// 16-bit RGBA image, and 8-bit inputs an 8-bit one.
+///
+/// # Errors
+/// * `ProcessingError` - If the mask doesn't match the image size or the compositing options are invalid
+/// * `Cancelled` - If the options' cancellation token was triggered
+///
+/// # Examples
+/// ```
+/// use image::{DynamicImage, RgbImage};
+/// use removebg::pipeline::{self, Gray16Image};
+/// use removebg::RemoveBgOptions;
+///
+/// let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(40, 40, image::Rgb([10, 20, 30])));
+/// let mask = Gray16Image::from_fn(40, 40, |x, _| image::Luma([if x < 20 { 0 } else { 65535 }]));
+/// let cutout = pipeline::composite(&image, &mask, &RemoveBgOptions::default())?.into_rgba8();
+/// assert_eq!(cutout.get_pixel(5, 5).0, [10, 20, 30, 0]);
+/// assert_eq!(cutout.get_pixel(30, 5).0, [10, 20, 30, 255]);
+/// # Ok::<(), removebg::error::RemoveBgError>(())
+/// ```
+pub fn composite(image: &DynamicImage, mask: &Gray16Image, options: &RemoveBgOptions) -> Result<DynamicImage> {
+    check_mask_size(mask, image)?;
+    core::compose(image, mask, options).map(|(image, _)| image)
+}
+
+fn check_mask_size(mask: &Gray16Image, image: &DynamicImage) -> Result<()> {
+    if mask.dimensions() != (image.width(), image.height()) {
+        return Err(RemoveBgError::ProcessingError(format!(
+            "mask is {}x{} but the image is {}x{}",
+            mask.width(),
+            mask.height(),
+            image.width(),
+            image.height()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Model;
+    use image::{GrayAlphaImage, ImageBuffer, Luma, LumaA, Rgb, RgbImage};
+
+    /// A 32x32 mask, opaque inside the square from 8 to 24 and transparent
+    /// around it.
+    fn hard_square() -> Gray16Image {
+        Gray16Image::from_fn(32, 32, |x, y| Luma([if (8..24).contains(&x) && (8..24).contains(&y) { 65535 } else { 0 }]))
+    }
+
+    /// Largest difference, in 8-bit steps, between the alpha of an 8x upscale
+    /// of [`hard_square`] and its flat value, over the pixels more than one
+    /// mask pixel away from the square's edge.
+    fn interior_deviation(filter: MaskFilter) -> f32 {
+        let mask = upscale_mask(&hard_square(), (256, 256), filter);
+        let flat = |v: u32| (72..184).contains(&v);
+        let outside = |v: u32| !(56..200).contains(&v);
+        mask.enumerate_pixels()
+            .filter_map(|(x, y, pixel)| {
+                let expected = if flat(x) && flat(y) {
+                    255.0
+                } else if outside(x) || outside(y) {
+                    0.0
+                } else {
+                    return None;
+                };
+                Some((pixel[0] as f32 / 257.0 - expected).abs())
+            })
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn default_filter_does_not_ring_next_to_hard_edges() {
+        assert!(interior_deviation(MaskFilter::default()) <= 1.0, "{}", interior_deviation(MaskFilter::default()));
+        assert!(interior_deviation(MaskFilter::Nearest) <= 1.0);
+        // The old filter rings, though the clamping keeps it from wrapping
+        assert!(interior_deviation(MaskFilter::Lanczos3) > 1.0);
+        let lanczos = upscale_mask(&hard_square(), (256, 256), MaskFilter::Lanczos3);
+        assert_eq!(lanczos.get_pixel(128, 128)[0], 65535);
+        assert_eq!(lanczos.get_pixel(0, 0)[0], 0);
+    }
+
+    #[test]
+    fn preprocess_repeats_gray_and_flattens_transparency_onto_gray() {
+        let image = DynamicImage::ImageLumaA8(GrayAlphaImage::from_fn(64, 64, |x, _| LumaA([200, if x < 32 { 255 } else { 0 }])));
+        let input = preprocess(&image, Model::U2netp.spec());
+        assert_eq!(input.shape(), &[1, 3, 320, 320]);
+        for c in 0..3 {
+            assert_eq!(input[[0, c, 100, 20]], 200.0 / 255.0);
+            assert_eq!(input[[0, c, 100, 300]], FLATTEN_GRAY);
+        }
+    }
+
+    #[test]
+    fn upscaled_masks_keep_their_flat_areas() {
+        let mask = upscale_mask(&hard_square(), (100, 60), MaskFilter::default());
+        assert_eq!(mask.dimensions(), (100, 60));
+        assert_eq!(mask.get_pixel(50, 30)[0], 65535);
+        assert_eq!(mask.get_pixel(2, 2)[0], 0);
+        // Upscaling to the same size changes nothing
+        assert_eq!(upscale_mask(&hard_square(), (32, 32), MaskFilter::default()), hard_square());
+    }
+
+    #[test]
+    fn refine_applies_the_image_alpha_and_checks_the_size() {
+        let image = DynamicImage::ImageLumaA8(GrayAlphaImage::from_fn(32, 32, |x, _| LumaA([90, if x < 16 { 255 } else { 0 }])));
+        let mut mask = hard_square();
+        refine(&mut mask, &image, &RemoveBgOptions::default()).unwrap();
+        assert_eq!(mask.get_pixel(12, 12)[0], 65535);
+        assert_eq!(mask.get_pixel(20, 12)[0], 0);
+
+        let error = refine(&mut hard_square(), &DynamicImage::ImageLumaA8(GrayAlphaImage::new(30, 32)), &RemoveBgOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("mask is 32x32 but the image is 30x32"), "{}", error);
+    }
+
+    #[test]
+    fn composite_keeps_the_bit_depth_and_checks_the_size() {
+        let options = RemoveBgOptions::default();
+        let cutout = composite(&DynamicImage::ImageRgb8(RgbImage::from_pixel(32, 32, Rgb([10, 20, 30]))), &hard_square(), &options).unwrap();
+        let cutout = cutout.as_rgba8().expect("8-bit input gives an 8-bit cutout");
+        assert_eq!(cutout.get_pixel(16, 16).0, [10, 20, 30, 255]);
+        assert_eq!(cutout.get_pixel(2, 2)[3], 0);
+
+        let deep = DynamicImage::ImageRgb16(ImageBuffer::from_pixel(32, 32, Rgb([1000, 2000, 3000])));
+        let cutout = composite(&deep, &hard_square(), &options).unwrap();
+        assert_eq!(cutout.as_rgba16().expect("16-bit input gives a 16-bit cutout").get_pixel(16, 16).0, [1000, 2000, 3000, 65535]);
+
+        assert!(composite(&deep, &Gray16Image::new(16, 16), &options).is_err());
+    }
+}
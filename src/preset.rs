@@ -0,0 +1,112 @@
+//! Named bundles of settings for common jobs (`--preset`).
+//!
+//! A preset is only a starting point: [`RemoveBgOptions::preset`] returns
+//! the default options with the preset's fields filled in, and any field can
+//! be changed afterwards. On the command line, flags given next to
+//! `--preset` win over the preset's values.
+
+use crate::model::Model;
+use crate::options::{Background, GuidedFilterOptions, RemoveBgOptions, StickerOptions};
+use crate::output::OutputFormat;
+use std::fmt;
+use std::str::FromStr;
+
+/// A named set of options for a common kind of cutout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Preset {
+    /// Catalog shots: a white background, cropped to the product with a 5%
+    /// margin, with holes in the subject filled.
+    Product,
+    /// People: the full U2-Net model, guided filter edge refinement for
+    /// hair, and background color fringes removed. There is no human-specific
+    /// model in the registry, so this uses the general one.
+    Portrait,
+    /// Stickers: a firmer mask edge, then a white outline and a drop shadow.
+    Sticker,
+    /// Web images: lossless WebP no larger than 2000 pixels on the longer
+    /// side.
+    Web,
+}
+
+impl Preset {
+    /// All presets, in the order they are listed.
+    pub const ALL: &'static [Preset] = &[Preset::Product, Preset::Portrait, Preset::Sticker, Preset::Web];
+
+    /// Name used by `--preset` and `removebg presets`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::Product => "product",
+            Preset::Portrait => "portrait",
+            Preset::Sticker => "sticker",
+            Preset::Web => "web",
+        }
+    }
+
+    /// One-line description for `removebg presets`.
+    pub fn description(self) -> &'static str {
+        match self {
+            Preset::Product => "White background, cropped to the subject with a 5% margin, holes filled",
+            Preset::Portrait => "Full u2net model, guided edge refinement for hair, color fringes removed",
+            Preset::Sticker => "Firmer mask edge with a white outline and a drop shadow",
+            Preset::Web => "WebP, scaled down to at most 2000 px on the longer side",
+        }
+    }
+
+    /// Set this preset's fields on `options`, leaving the others unchanged.
+    pub fn apply(self, options: &mut RemoveBgOptions) {
+        match self {
+            Preset::Product => {
+                options.background = Background::Color([255, 255, 255]);
+                options.geometry.pad = Some(0.05);
+                options.fill_holes = true;
+            }
+            Preset::Portrait => {
+                options.model = Model::U2net;
+                options.guided_filter = Some(GuidedFilterOptions::default());
+                options.decontaminate = Some(1.0);
+            }
+            Preset::Sticker => {
+                options.mask_levels = Some((0.4, 0.6));
+                options.sticker = Some(StickerOptions::default());
+            }
+            Preset::Web => {
+                options.format = Some(OutputFormat::WebP);
+                options.max_output_size = Some(2000);
+            }
+        }
+    }
+}
+
+impl RemoveBgOptions {
+    /// The default options with `preset`'s fields filled in.
+    ///
+    /// ```
+    /// use removebg::{OutputFormat, Preset, RemoveBgOptions};
+    ///
+    /// let options = RemoveBgOptions { quality: Some(80.0), ..RemoveBgOptions::preset(Preset::Web) };
+    /// assert_eq!(options.format, Some(OutputFormat::WebP));
+    /// ```
+    pub fn preset(preset: Preset) -> Self {
+        let mut options = RemoveBgOptions::default();
+        preset.apply(&mut options);
+        options
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+impl FromStr for Preset {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let name = s.to_ascii_lowercase();
+        Preset::ALL.iter().copied().find(|preset| preset.name() == name).ok_or_else(|| {
+            let names: Vec<&str> = Preset::ALL.iter().map(|p| p.name()).collect();
+            format!("unknown preset '{}' (expected one of: {})", s, names.join(", "))
+        })
+    }
+}
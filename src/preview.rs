@@ -0,0 +1,244 @@
+//! Preview and debug images.
+//!
+//! [`composite_on_checkerboard`] shows transparency the way image editors do,
+//! [`make_thumbnail`] shrinks a cutout for a file list or gallery, and
+//! [`before_after_gif`] makes a small looping before/after animation to
+//! share, so frontends around the crate don't each write their own. The debug images written for
+//! [`debug_output`](crate::RemoveBgOptions::debug_output) show what the model
+//! predicted next to the original, to help tune mask settings.
+
+use crate::core::Gray16Image;
+use crate::error::{RemoveBgError, Result};
+use crate::geometry;
+use crate::paths;
+use crate::quantize;
+use image::{DynamicImage, GrayImage, ImageFormat, Rgb, RgbImage, Rgba, RgbaImage};
+use std::ffi::OsStr;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+/// Light squares of the checkerboard in the debug images and `--emit`
+/// previews; a good default for [`composite_on_checkerboard`].
+pub const CHECKER_LIGHT: Rgba<u8> = Rgba([204, 204, 204, 255]);
+
+/// Dark squares of the checkerboard; see [`CHECKER_LIGHT`].
+pub const CHECKER_DARK: Rgba<u8> = Rgba([153, 153, 153, 255]);
+
+/// Size of the checkerboard squares in the debug images, in pixels.
+const CHECKER_CELL: u32 = 16;
+
+/// Longest side of [`before_after_gif`] previews unless
+/// [`preview_size`](crate::RemoveBgOptions::preview_size) sets another.
+pub const DEFAULT_PREVIEW_SIZE: u32 = 480;
+
+/// How long each frame of a [`before_after_gif`] preview shows unless
+/// [`preview_delay`](crate::RemoveBgOptions::preview_delay) sets another.
+pub const DEFAULT_PREVIEW_DELAY: Duration = Duration::from_secs(1);
+
+/// Red used to tint the mask in the overlay image.
+const OVERLAY_COLOR: [f32; 3] = [1.0, 0.0, 0.0];
+
+/// Opacity of the overlay tint where the mask is fully opaque.
+const OVERLAY_OPACITY: f32 = 0.5;
+
+/// Gap between the panels of the comparison strip, in pixels.
+const STRIP_GAP: u32 = 8;
+
+/// Composite an RGBA image over a checkerboard, making transparency visible
+/// in an opaque preview.
+///
+/// The squares are anchored at the image's top-left pixel: the square of a
+/// pixel depends only on its coordinates, so the pattern doesn't shift with
+/// how the image was cropped or split into rows, and a crop at a multiple of
+/// `2 * cell` matches the same area of the whole image's preview.
+///
+/// # Arguments
+/// * `image` - Image with transparency, e.g. a cutout.
+/// * `cell` - Size of the checkerboard squares in pixels (at least 1).
+/// * `light` - Color of the square at the top-left corner, e.g.
+///   [`CHECKER_LIGHT`]. The preview is opaque, so its alpha is ignored.
+/// * `dark` - Color of the other squares, e.g. [`CHECKER_DARK`].
+///
+/// # Returns
+/// The opaque composite at the image's size.
+///
+/// # Examples
+/// ```no_run
+/// use removebg::preview::{composite_on_checkerboard, CHECKER_DARK, CHECKER_LIGHT};
+/// use removebg::{BackgroundRemover, RemoveBgOptions};
+///
+/// let remover = BackgroundRemover::new(RemoveBgOptions::default())?;
+/// let cutout = remover.process_image(&image::open("photo.jpg")?)?;
+/// composite_on_checkerboard(&cutout, 16, CHECKER_LIGHT, CHECKER_DARK).save("preview.png")?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn composite_on_checkerboard(image: &RgbaImage, cell: u32, light: Rgba<u8>, dark: Rgba<u8>) -> RgbImage {
+    let cell = cell.max(1);
+    RgbImage::from_fn(image.width(), image.height(), |x, y| {
+        let pixel = image.get_pixel(x, y);
+        let alpha = pixel[3] as f32 / 255.0;
+        let checker = if (x / cell + y / cell).is_multiple_of(2) { light } else { dark };
+        Rgb(std::array::from_fn(|c| {
+            let value = pixel[c] as f32 * alpha + checker[c] as f32 * (1.0 - alpha);
+            value.round() as u8
+        }))
+    })
+}
+
+/// Shrink an RGBA image to fit within `max_side`×`max_side`, keeping its
+/// aspect ratio and alpha.
+///
+/// Colors are premultiplied by alpha while filtering (Lanczos3), so fully
+/// transparent pixels don't darken the subject's edge. Images that already
+/// fit are returned unchanged; neither side becomes smaller than 1 pixel.
+///
+/// # Examples
+/// ```no_run
+/// use removebg::preview::make_thumbnail;
+///
+/// let cutout = image::open("photo_nobg.png")?.to_rgba8();
+/// make_thumbnail(&cutout, 256).save("photo_thumb.png")?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn make_thumbnail(image: &RgbaImage, max_side: u32) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    let max_side = max_side.max(1);
+    if width <= max_side && height <= max_side {
+        return image.clone();
+    }
+    let scale = max_side as f64 / width.max(height) as f64;
+    let scaled = |length: u32| ((length as f64 * scale).round() as u32).clamp(1, max_side);
+    geometry::resize(&DynamicImage::ImageRgba8(image.clone()), scaled(width), scaled(height)).into_rgba8()
+}
+
+/// Write a looping two-frame GIF to `writer`: `original`, then `cutout` on a
+/// checkerboard, both shrunk to fit within `max_side`×`max_side` pixels and
+/// each shown for `delay` (GIF counts in hundredths of a second).
+///
+/// Both frames are quantized to one shared palette of 256 colors, so the
+/// colors they have in common don't shift as the animation loops. The GIF
+/// has the size of the shrunk cutout; an original of another shape, such as
+/// one padded or squared by the geometry options, is centered in it.
+///
+/// # Errors
+/// * `IoError` - If `writer` fails
+/// * `ProcessingError` - If the GIF can't be encoded
+///
+/// # Examples
+/// ```no_run
+/// use removebg::preview::before_after_gif;
+/// use std::time::Duration;
+///
+/// let original = image::open("photo.jpg")?.to_rgba8();
+/// let cutout = image::open("photo_nobg.png")?.to_rgba8();
+/// let file = std::fs::File::create("photo_preview.gif")?;
+/// before_after_gif(&original, &cutout, 480, Duration::from_millis(800), file)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn before_after_gif(original: &RgbaImage, cutout: &RgbaImage, max_side: u32, delay: Duration, writer: impl Write) -> Result<()> {
+    let after = make_thumbnail(cutout, max_side.min(u16::MAX as u32));
+    let (width, height) = after.dimensions();
+    let scale = (width as f64 / original.width().max(1) as f64).min(height as f64 / original.height().max(1) as f64);
+    let scaled = |length: u32, within: u32| ((length as f64 * scale).round() as u32).clamp(1, within);
+    let fitted = geometry::resize(
+        &DynamicImage::ImageRgba8(original.clone()),
+        scaled(original.width(), width),
+        scaled(original.height(), height),
+    )
+    .into_rgba8();
+    let mut before = RgbaImage::new(width, height);
+    let (x, y) = ((width - fitted.width()) / 2, (height - fitted.height()) / 2);
+    image::imageops::replace(&mut before, &fitted, x as i64, y as i64);
+
+    // Quantized together, stacked, for the shared palette
+    let mut frames = RgbaImage::new(width, height * 2);
+    for (offset, frame) in [before, after].iter().enumerate() {
+        let flattened = composite_on_checkerboard(frame, CHECKER_CELL, CHECKER_LIGHT, CHECKER_DARK);
+        let flattened = DynamicImage::ImageRgb8(flattened).into_rgba8();
+        image::imageops::replace(&mut frames, &flattened, 0, (offset as u32 * height) as i64);
+    }
+    let indexed = quantize::median_cut(&frames, 256);
+    let palette: Vec<u8> = indexed.palette.iter().flat_map(|color| [color[0], color[1], color[2]]).collect();
+
+    let gif_error = |e: gif::EncodingError| match e {
+        gif::EncodingError::Io(e) => RemoveBgError::IoError(e),
+        e => RemoveBgError::ProcessingError(format!("Failed to encode GIF: {}", e)),
+    };
+    let mut encoder = gif::Encoder::new(writer, width as u16, height as u16, &palette).map_err(gif_error)?;
+    encoder.set_repeat(gif::Repeat::Infinite).map_err(gif_error)?;
+    let delay = (delay.as_millis() / 10).min(u16::MAX as u128) as u16;
+    for indices in indexed.indices.chunks_exact((width * height) as usize) {
+        let frame = gif::Frame { delay, ..gif::Frame::from_indexed_pixels(width as u16, height as u16, indices, None) };
+        encoder.write_frame(&frame).map_err(gif_error)?;
+    }
+    Ok(())
+}
+
+/// Masks kept for the debug images of one image.
+pub(crate) struct DebugMasks {
+    /// The mask at the model's output resolution; `None` for external masks
+    /// and tiled inference.
+    pub model: Option<Gray16Image>,
+    /// The full-resolution mask as applied, after refinement.
+    pub applied: Gray16Image,
+}
+
+/// Write the debug images for one processed image into `dir`:
+///
+/// * `<stem>.raw.png` - the mask at the model's resolution, if available
+/// * `<stem>.mask.png` - the full-resolution mask as applied
+/// * `<stem>.overlay.png` - the mask tinted red over the original
+/// * `<stem>.compare.png` - original, mask, and cutout on a checkerboard side by side
+pub(crate) fn write_debug_images(
+    dir: &Path,
+    stem: &OsStr,
+    original: &DynamicImage,
+    masks: &DebugMasks,
+    cutout: &DynamicImage,
+) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    let path = |kind: &str| dir.join(paths::file_name([stem, format!(".{}.png", kind).as_ref()]));
+    let to_8bit = |mask: &Gray16Image| DynamicImage::ImageLuma16(mask.clone()).into_luma8();
+
+    if let Some(model_mask) = &masks.model {
+        to_8bit(model_mask).save_with_format(path("raw"), ImageFormat::Png)?;
+    }
+    let mask = to_8bit(&masks.applied);
+    mask.save_with_format(path("mask"), ImageFormat::Png)?;
+
+    let original = original.to_rgb8();
+    overlay(&original, &mask).save_with_format(path("overlay"), ImageFormat::Png)?;
+
+    let checkered = composite_on_checkerboard(&cutout.to_rgba8(), CHECKER_CELL, CHECKER_LIGHT, CHECKER_DARK);
+    let mask_rgb = DynamicImage::ImageLuma8(mask).into_rgb8();
+    strip(&[&original, &mask_rgb, &checkered]).save_with_format(path("compare"), ImageFormat::Png)?;
+    Ok(())
+}
+
+/// Tint `original` red where `mask` is set.
+pub(crate) fn overlay(original: &RgbImage, mask: &GrayImage) -> RgbImage {
+    RgbImage::from_fn(original.width(), original.height(), |x, y| {
+        let pixel = original.get_pixel(x, y);
+        let weight = mask.get_pixel(x, y)[0] as f32 / 255.0 * OVERLAY_OPACITY;
+        Rgb(std::array::from_fn(|c| {
+            let value = pixel[c] as f32 / 255.0 * (1.0 - weight) + OVERLAY_COLOR[c] * weight;
+            (value * 255.0).round() as u8
+        }))
+    })
+}
+
+/// Place `panels` left to right on a white strip, top-aligned.
+fn strip(panels: &[&RgbImage]) -> RgbImage {
+    let gaps = STRIP_GAP * panels.len().saturating_sub(1) as u32;
+    let width = panels.iter().map(|panel| panel.width()).sum::<u32>() + gaps;
+    let height = panels.iter().map(|panel| panel.height()).max().unwrap_or(0);
+    let mut strip = RgbImage::from_pixel(width, height, Rgb([255, 255, 255]));
+    let mut x = 0;
+    for panel in panels {
+        image::imageops::replace(&mut strip, *panel, x as i64, 0);
+        x += panel.width() + STRIP_GAP;
+    }
+    strip
+}
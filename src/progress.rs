@@ -0,0 +1,209 @@
+//! Progress of runs over many images.
+//!
+//! Batch, manifest, sequence and watch runs report each input to the
+//! [`Progress`] handler in their config as it is queued, started and
+//! finished, so front ends can draw progress bars and collect failures
+//! without parsing log messages. The handler is called on the thread doing
+//! the work, between images.
+//!
+//! Model downloads report to the [`DownloadProgress`] handler in
+//! [`RemoveBgOptions`](crate::RemoveBgOptions) as data arrives, at most
+//! every [`DOWNLOAD_INTERVAL`]. With the `serde` feature a
+//! [`DownloadEvent`] serializes to one line of the documented schema,
+//! `{"event":"download","model":"u2net","received":12345,"total":175997641}`,
+//! which `--json` and daemon runs write to stderr.
+//!
+//! The one-line-per-image messages of those runs (`a.jpg -> a_nobg.png`) are
+//! logged with the target [`LOG_TARGET`], so a front end showing its own
+//! progress can filter them out.
+
+use crate::error::RemoveBgError;
+use crate::model::Model;
+use crate::report::RemovalReport;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use web_time::Instant;
+
+/// Log target of the informational message logged for each image of a run.
+pub const LOG_TARGET: &str = "removebg::progress";
+
+/// A progress handler.
+type Handler = dyn Fn(&ProgressEvent<'_>) + Send + Sync;
+
+/// Something that happened to one input of a run.
+#[derive(Debug)]
+pub enum ProgressEvent<'a> {
+    /// `count` more inputs were found. Batch runs list one directory at a
+    /// time, and watch runs queue each file once it has settled, so the total
+    /// grows as the run goes on.
+    Queued {
+        /// Number of inputs found.
+        count: usize,
+    },
+    /// Work on `input` started. Inputs run through the model together are
+    /// all started before the first finishes.
+    Started {
+        /// The input.
+        input: &'a Path,
+    },
+    /// `input` was processed and its outputs written.
+    Processed {
+        /// The input.
+        input: &'a Path,
+        /// The report of the run.
+        report: &'a RemovalReport,
+    },
+    /// `input` was skipped because its output already exists.
+    Skipped {
+        /// The input.
+        input: &'a Path,
+    },
+    /// `input` failed. The run goes on, except for sequences.
+    Failed {
+        /// The input.
+        input: &'a Path,
+        /// Why it failed.
+        error: &'a RemoveBgError,
+    },
+}
+
+/// Receives the [`ProgressEvent`]s of a run; the default ignores them.
+///
+/// # Examples
+/// ```no_run
+/// use removebg::batch::{process_directory, BatchConfig};
+/// use removebg::progress::{Progress, ProgressEvent};
+///
+/// let config = BatchConfig {
+///     progress: Progress::new(|event| {
+///         if let ProgressEvent::Failed { input, error } = event {
+///             eprintln!("{}: {}", input.display(), error);
+///         }
+///     }),
+///     ..BatchConfig::new("shoots")
+/// };
+/// process_directory(config)?;
+/// # Ok::<(), removebg::RemoveBgError>(())
+/// ```
+#[derive(Clone, Default)]
+pub struct Progress(Option<Arc<Handler>>);
+
+impl Progress {
+    /// Call `handler` with every event.
+    pub fn new(handler: impl Fn(&ProgressEvent<'_>) + Send + Sync + 'static) -> Self {
+        Progress(Some(Arc::new(handler)))
+    }
+
+    /// Pass `event` to the handler, if there is one.
+    pub(crate) fn report(&self, event: ProgressEvent<'_>) {
+        if let Some(handler) = &self.0 {
+            handler(&event);
+        }
+    }
+}
+
+impl fmt::Debug for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(if self.0.is_some() { "Progress(handler)" } else { "Progress(none)" })
+    }
+}
+
+/// Shortest time between two [`DownloadEvent`]s of one download, apart from
+/// the last, which is always reported.
+pub const DOWNLOAD_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A download handler.
+type DownloadHandler = dyn Fn(&DownloadEvent) + Send + Sync;
+
+/// How far the download of a model has got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(tag = "event", rename = "download"))]
+pub struct DownloadEvent {
+    /// The model being downloaded.
+    pub model: Model,
+    /// Bytes of the model file received so far, including any part kept
+    /// from an earlier attempt.
+    pub received: u64,
+    /// Size of the whole file, if the server announced it.
+    pub total: Option<u64>,
+}
+
+/// Receives the [`DownloadEvent`]s of model downloads; the default ignores
+/// them.
+///
+/// # Examples
+/// ```no_run
+/// use removebg::progress::DownloadProgress;
+/// use removebg::{prepare_model, RemoveBgOptions};
+///
+/// let options = RemoveBgOptions {
+///     download_progress: DownloadProgress::new(|event| {
+///         if let Some(total) = event.total {
+///             eprint!("\r{}: {}%", event.model, event.received * 100 / total.max(1));
+///         }
+///     }),
+///     ..Default::default()
+/// };
+/// prepare_model(&options)?;
+/// # Ok::<(), removebg::RemoveBgError>(())
+/// ```
+#[derive(Clone, Default)]
+pub struct DownloadProgress(Option<Arc<DownloadHandler>>);
+
+impl DownloadProgress {
+    /// Call `handler` with the events of every download.
+    pub fn new(handler: impl Fn(&DownloadEvent) + Send + Sync + 'static) -> Self {
+        DownloadProgress(Some(Arc::new(handler)))
+    }
+
+    /// Start reporting a download of `model` whose first `received` bytes
+    /// are already there.
+    pub(crate) fn meter(&self, model: Model, received: u64, total: Option<u64>) -> DownloadMeter<'_> {
+        DownloadMeter { progress: self, event: DownloadEvent { model, received, total }, reported: None }
+    }
+}
+
+impl fmt::Debug for DownloadProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(if self.0.is_some() { "DownloadProgress(handler)" } else { "DownloadProgress(none)" })
+    }
+}
+
+/// Counts the bytes of one download attempt and reports them, throttled to
+/// [`DOWNLOAD_INTERVAL`].
+pub(crate) struct DownloadMeter<'a> {
+    progress: &'a DownloadProgress,
+    event: DownloadEvent,
+    /// When the last event was reported, and how much it said had arrived.
+    reported: Option<(Instant, u64)>,
+}
+
+impl DownloadMeter<'_> {
+    /// Count `bytes` more, reporting them unless an event went out less than
+    /// [`DOWNLOAD_INTERVAL`] ago.
+    pub(crate) fn advance(&mut self, bytes: usize) {
+        self.event.received += bytes as u64;
+        if self.reported.is_none_or(|(at, _)| at.elapsed() >= DOWNLOAD_INTERVAL) {
+            self.report();
+        }
+    }
+
+    /// Report the end of the download, unless the last event already said
+    /// as much.
+    pub(crate) fn finish(&mut self) {
+        if self.reported.is_none_or(|(_, received)| received != self.event.received) {
+            self.report();
+        }
+    }
+
+    fn report(&mut self) {
+        if let Some(handler) = &self.progress.0 {
+            handler(&self.event);
+        }
+        self.reported = Some((Instant::now(), self.event.received));
+    }
+}
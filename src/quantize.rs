@@ -0,0 +1,167 @@
+//! Color quantization for palette-based outputs.
+//!
+//! This module implements median-cut quantization of RGBA images to an 8-bit
+//! palette, used to produce small indexed PNGs for sticker-style assets.
+
+use image::RgbaImage;
+use std::collections::HashMap;
+
+/// An RGBA image reduced to a palette plus one palette index per pixel.
+pub(crate) struct IndexedImage {
+    /// Palette entries as RGBA colors (at most 256).
+    pub palette: Vec<[u8; 4]>,
+    /// Palette index of each pixel, in row-major order.
+    pub indices: Vec<u8>,
+}
+
+/// A box of distinct colors (with pixel counts) in RGBA space.
+struct ColorBox {
+    colors: Vec<([u8; 4], u32)>,
+}
+
+impl ColorBox {
+    /// Channel with the widest value range and that range.
+    fn widest_channel(&self) -> (usize, u8) {
+        (0..4)
+            .map(|channel| {
+                let (min, max) = self
+                    .colors
+                    .iter()
+                    .fold((u8::MAX, u8::MIN), |(min, max), (c, _)| {
+                        (min.min(c[channel]), max.max(c[channel]))
+                    });
+                (channel, max - min)
+            })
+            .max_by_key(|&(_, range)| range)
+            .unwrap_or((0, 0))
+    }
+
+    /// Pixel-count weighted average color of the box.
+    fn average(&self) -> [u8; 4] {
+        let mut sums = [0u64; 4];
+        let mut total = 0u64;
+        for (color, count) in &self.colors {
+            for channel in 0..4 {
+                sums[channel] += color[channel] as u64 * *count as u64;
+            }
+            total += *count as u64;
+        }
+        let total = total.max(1);
+        sums.map(|sum| ((sum + total / 2) / total) as u8)
+    }
+
+    /// Split the box at the pixel-weighted median of its widest channel.
+    fn split(mut self) -> (ColorBox, ColorBox) {
+        let (channel, _) = self.widest_channel();
+        // The whole color breaks ties, so the split doesn't depend on the
+        // order the histogram was collected in
+        self.colors.sort_unstable_by_key(|(color, _)| (color[channel], *color));
+
+        let total: u64 = self.colors.iter().map(|(_, count)| *count as u64).sum();
+        let mut running = 0u64;
+        let mut split_at = 1;
+        for (i, (_, count)) in self.colors.iter().enumerate() {
+            running += *count as u64;
+            if running * 2 >= total {
+                split_at = i + 1;
+                break;
+            }
+        }
+        // Both halves must be non-empty
+        let split_at = split_at.clamp(1, self.colors.len() - 1);
+
+        let upper = self.colors.split_off(split_at);
+        (self, ColorBox { colors: upper })
+    }
+}
+
+/// Quantize an RGBA image to at most `max_colors` colors using median cut.
+///
+/// Fully transparent pixels are collapsed to a single transparent color so they
+/// don't consume palette entries.
+pub(crate) fn median_cut(image: &RgbaImage, max_colors: usize) -> IndexedImage {
+    let max_colors = max_colors.clamp(1, 256);
+    let normalize = |p: &image::Rgba<u8>| if p[3] == 0 { [0, 0, 0, 0] } else { p.0 };
+
+    // Histogram of distinct colors
+    let mut histogram: HashMap<[u8; 4], u32> = HashMap::new();
+    for pixel in image.pixels() {
+        *histogram.entry(normalize(pixel)).or_insert(0) += 1;
+    }
+
+    let mut boxes = vec![ColorBox { colors: histogram.into_iter().collect() }];
+
+    // Repeatedly split the box with the widest channel range
+    while boxes.len() < max_colors {
+        let Some((index, _)) = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.colors.len() > 1)
+            .max_by_key(|(_, b)| b.widest_channel().1)
+        else {
+            break;
+        };
+        let (lower, upper) = boxes.swap_remove(index).split();
+        boxes.push(lower);
+        boxes.push(upper);
+    }
+
+    let palette: Vec<[u8; 4]> = boxes.iter().map(ColorBox::average).collect();
+
+    // Map every pixel to its nearest palette entry, caching per distinct color
+    let mut lookup: HashMap<[u8; 4], u8> = HashMap::new();
+    let indices = image
+        .pixels()
+        .map(|pixel| {
+            let color = normalize(pixel);
+            *lookup.entry(color).or_insert_with(|| nearest(&palette, color))
+        })
+        .collect();
+
+    IndexedImage { palette, indices }
+}
+
+/// Index of the palette entry closest to `color` (squared RGBA distance).
+fn nearest(palette: &[[u8; 4]], color: [u8; 4]) -> u8 {
+    palette
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, entry)| {
+            (0..4)
+                .map(|c| {
+                    let d = entry[c] as i32 - color[c] as i32;
+                    (d * d) as u32
+                })
+                .sum::<u32>()
+        })
+        .map(|(i, _)| i as u8)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn palettes_have_at_most_the_requested_colors() {
+        // 4096 distinct colors, a quarter of them fully transparent
+        let image = RgbaImage::from_fn(64, 64, |x, y| Rgba([x as u8 * 4, y as u8 * 4, (x ^ y) as u8 * 4, if x < 16 { 0 } else { 255 - y as u8 }]));
+        for max_colors in [256, 16, 1] {
+            let indexed = median_cut(&image, max_colors);
+            assert!(indexed.palette.len() <= max_colors, "{} colors for {}", indexed.palette.len(), max_colors);
+            assert_eq!(indexed.indices.len(), 64 * 64);
+            assert!(indexed.indices.iter().all(|&index| (index as usize) < indexed.palette.len()));
+        }
+        assert_eq!(median_cut(&image, 1000).palette.len(), 256);
+
+        // Images with few enough colors keep them exactly
+        let few = RgbaImage::from_fn(8, 8, |x, _| Rgba([255, 0, 0, [0, 128, 255][x as usize % 3]]));
+        let indexed = median_cut(&few, 256);
+        assert_eq!(indexed.palette.len(), 3);
+        for (pixel, &index) in few.pixels().zip(&indexed.indices) {
+            let expected = if pixel[3] == 0 { [0, 0, 0, 0] } else { pixel.0 };
+            assert_eq!(indexed.palette[index as usize], expected);
+        }
+    }
+}
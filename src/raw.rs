@@ -0,0 +1,129 @@
+//! Camera RAW input, decoded with rawler behind the `raw` feature.
+//!
+//! RAW files are recognized by extension, since most are TIFF containers the
+//! image crate would otherwise decode as a tiny thumbnail or reject.
+
+use crate::error::RemoveBgError;
+use std::path::Path;
+
+/// Extensions of camera RAW formats.
+pub(crate) const RAW_EXTENSIONS: &[&str] = &[
+    "3fr", "ari", "arw", "cr2", "cr3", "crw", "dcr", "dcs", "dng", "erf", "iiq", "kdc", "mef", "mos", "mrw",
+    "nef", "nrw", "orf", "pef", "raf", "rw2", "rwl", "srw", "x3f",
+];
+
+/// Whether `path` has a camera RAW extension (case-insensitive).
+pub(crate) fn is_raw(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| RAW_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+/// The RAW format of `path` for messages, e.g. `"CR3"`.
+fn format_name(path: &Path) -> String {
+    path.extension().unwrap_or_default().to_string_lossy().to_ascii_uppercase()
+}
+
+/// The error for RAW inputs in builds without the `raw` feature.
+#[cfg(not(feature = "raw"))]
+pub(crate) fn unsupported(path: &Path) -> RemoveBgError {
+    RemoveBgError::ProcessingError(format!(
+        "{} camera RAW files need removebg built with the `raw` feature \
+         (cargo install removebg --features raw); or convert the image to JPEG or PNG first",
+        format_name(path)
+    ))
+}
+
+#[cfg(feature = "raw")]
+pub(crate) use decode::decode;
+
+#[cfg(feature = "raw")]
+mod decode {
+    use super::*;
+    use crate::error::Result;
+    use crate::options::DecodeLimits;
+    use crate::output::Metadata;
+    use image::metadata::Orientation;
+    use image::DynamicImage;
+    use rawler::decoders::RawDecodeParams;
+    use rawler::imgop::develop::RawDevelop;
+    use rawler::rawsource::RawSource;
+    use rawler::RawlerError;
+    use std::panic::{self, AssertUnwindSafe};
+
+    /// Decode the RAW file `data`, read from `path`, enforcing `limits`.
+    ///
+    /// A full-size embedded preview is used when the file has one, which is
+    /// much faster than developing the sensor data and matches what the
+    /// camera showed. Otherwise the sensor data is demosaiced into a 16-bit
+    /// RGB image. The orientation recorded by the camera is applied.
+    pub(crate) fn decode(data: &[u8], path: &Path, limits: &DecodeLimits) -> Result<(DynamicImage, Metadata)> {
+        let format = format_name(path);
+        // rawler panics on some malformed or unusual files
+        let image = panic::catch_unwind(AssertUnwindSafe(|| develop(data, &format, limits)))
+            .unwrap_or_else(|_| Err(RemoveBgError::ProcessingError(format!("Failed to decode {} file", format))))?;
+        Ok((image, Metadata::default()))
+    }
+
+    fn develop(data: &[u8], format: &str, limits: &DecodeLimits) -> Result<DynamicImage> {
+        let source = RawSource::new_from_slice(data);
+        let params = RawDecodeParams::default();
+        let decoder = rawler::get_decoder(&source).map_err(|e| raw_error(e, format, None))?;
+        let metadata = decoder.raw_metadata(&source, &params).map_err(|e| raw_error(e, format, None))?;
+        let camera = format!("{} {}", metadata.make, metadata.model);
+        let error = |e| raw_error(e, format, Some(&camera));
+
+        // Without pixel data, for the dimensions and orientation
+        let header = decoder.raw_image(&source, &params, true).map_err(error)?;
+        let (width, height) = header
+            .crop_area
+            .map_or((header.width, header.height), |area| (area.d.w, area.d.h));
+        let orientation = metadata.exif.orientation.unwrap_or(header.orientation.to_u16());
+
+        let preview = decoder.preview_image(&source, &params).ok().flatten().filter(|preview| {
+            // Smaller previews would lose detail the mask needs
+            let long_side = preview.width().max(preview.height()) as usize;
+            long_side * 10 >= width.max(height) * 9
+        });
+        let mut image = match preview {
+            Some(preview) => {
+                log::debug!("using the embedded {}x{} preview of the {} file", preview.width(), preview.height(), format);
+                limits.check(preview.width(), preview.height(), preview.color().bytes_per_pixel() as u64)?;
+                preview
+            }
+            None => {
+                log::debug!("developing the {}x{} sensor data of the {} file", width, height, format);
+                limits.check(width as u32, height as u32, 6)?;
+                let raw = decoder.raw_image(&source, &params, false).map_err(error)?;
+                let developed = RawDevelop::default()
+                    .develop_intermediate(&raw)
+                    .map_err(error)?
+                    .to_dynamic_image()
+                    .ok_or_else(|| RemoveBgError::ProcessingError(format!("Failed to develop {} file from the {}", format, camera)))?;
+                // Four-color sensors come out as RGBA without alpha
+                DynamicImage::ImageRgb16(developed.into_rgb16())
+            }
+        };
+
+        if let Some(orientation) = u8::try_from(orientation).ok().and_then(Orientation::from_exif) {
+            image.apply_orientation(orientation);
+        }
+        Ok(image)
+    }
+
+    /// Name the format and, when known, the camera in rawler's errors.
+    fn raw_error(error: RawlerError, format: &str, camera: Option<&str>) -> RemoveBgError {
+        RemoveBgError::ProcessingError(match error {
+            RawlerError::Unsupported { what, make, model, .. } if make.is_empty() && model.is_empty() => {
+                format!("Unsupported {} file: {}", format, what)
+            }
+            RawlerError::Unsupported { what, make, model, .. } => {
+                format!("{} files from the {} {} are not supported: {}", format, make, model, what)
+            }
+            RawlerError::DecoderFailed(message) => match camera {
+                Some(camera) => format!("Failed to decode {} file from the {}: {}", format, camera, message),
+                None => format!("Failed to decode {} file: {}", format, message),
+            },
+        })
+    }
+}
@@ -0,0 +1,130 @@
+//! Remote (http/https) inputs.
+//!
+//! Images given as URLs are downloaded into memory and processed like the
+//! bytes of a local file; no temporary input file is written. Requests use the
+//! same HTTP client settings as model downloads, including the proxy from the
+//! `HTTPS_PROXY`/`HTTP_PROXY` environment variables.
+
+use crate::error::{RemoveBgError, Result};
+use crate::options::CancellationToken;
+use std::io::Read;
+use std::time::Duration;
+
+/// How long to wait for a connection and for the response headers.
+pub(crate) const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Start a GET request with the connect and response timeouts applied.
+pub(crate) fn get(url: &str) -> ureq::RequestBuilder<ureq::typestate::WithoutBody> {
+    ureq::get(url)
+        .config()
+        .timeout_connect(Some(CONNECT_TIMEOUT))
+        .timeout_recv_response(Some(CONNECT_TIMEOUT))
+        .build()
+}
+
+/// Whether an input argument is an http or https URL rather than a path.
+pub fn is_url(input: &str) -> bool {
+    let lower = input.get(..8).unwrap_or(input).to_ascii_lowercase();
+    lower.starts_with("http://") || lower.starts_with("https://")
+}
+
+/// Check that `url` is an absolute http or https URL with a host.
+///
+/// Returns a description of the problem otherwise.
+pub(crate) fn validate_url(url: &str) -> std::result::Result<(), String> {
+    let uri: ureq::http::Uri = url.parse().map_err(|e: ureq::http::uri::InvalidUri| e.to_string())?;
+    match uri.scheme_str() {
+        Some("http" | "https") => {}
+        Some(scheme) => return Err(format!("unsupported scheme '{}', expected http or https", scheme)),
+        None => return Err("expected an absolute http or https URL".into()),
+    }
+    if uri.host().is_none_or(str::is_empty) {
+        return Err("missing host".into());
+    }
+    Ok(())
+}
+
+/// Download an image into memory.
+///
+/// Responses declaring a non-image content type are rejected; a missing or
+/// generic binary content type (as object stores often send) is accepted and
+/// left to the decoder.
+///
+/// # Arguments
+/// * `url` - http or https URL of the image.
+/// * `max_size` - Largest accepted response body in bytes (`None` = no limit).
+///
+/// # Returns
+/// The encoded image bytes.
+///
+/// # Errors
+/// * `FileNotFound` - If the server responds with 404 or 410
+/// * `DownloadError` - If the URL is invalid, the request fails, the response
+///   is not a 2xx image, or the body exceeds `max_size`
+///
+/// # Examples
+/// ```no_run
+/// use removebg::{remote, remove_background_from_bytes, RemoveBgOptions};
+///
+/// let input = remote::fetch_image("https://example.com/photo.jpg", Some(50_000_000))?;
+/// let png = remove_background_from_bytes(&input, &RemoveBgOptions::default())?;
+/// # Ok::<(), removebg::error::RemoveBgError>(())
+/// ```
+pub fn fetch_image(url: &str, max_size: Option<u64>) -> Result<Vec<u8>> {
+    fetch_image_until(url, max_size, &CancellationToken::default())
+}
+
+/// Like [`fetch_image`], checking `cancel` between chunks of the body.
+pub(crate) fn fetch_image_until(url: &str, max_size: Option<u64>, cancel: &CancellationToken) -> Result<Vec<u8>> {
+    let error = |message: String| RemoveBgError::DownloadError(format!("{}: {}", url, message));
+    validate_url(url).map_err(|e| error(format!("invalid URL ({})", e)))?;
+
+    let mut response = get(url).call().map_err(|e| match e {
+        ureq::Error::StatusCode(404 | 410) => RemoveBgError::FileNotFound(url.into()),
+        ureq::Error::StatusCode(code) => error(format!("HTTP status {}", code)),
+        e => error(e.to_string()),
+    })?;
+
+    let body = response.body_mut();
+    if let Some(mime) = body.mime_type().map(str::to_ascii_lowercase) {
+        if !is_image_content_type(&mime) {
+            return Err(error(format!("not an image (content type {})", mime)));
+        }
+    }
+    let limit = max_size.unwrap_or(u64::MAX);
+    if let Some(length) = body.content_length().filter(|&length| length > limit) {
+        return Err(error(format!("response of {} bytes exceeds the limit of {} bytes", length, limit)));
+    }
+
+    let mut reader = body.as_reader();
+    let mut data = Vec::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        cancel.check()?;
+        let read = reader.read(&mut buffer).map_err(|e| error(e.to_string()))?;
+        if read == 0 {
+            return Ok(data);
+        }
+        if (data.len() + read) as u64 > limit {
+            return Err(error(format!("response exceeds the limit of {} bytes", limit)));
+        }
+        data.extend_from_slice(&buffer[..read]);
+    }
+}
+
+/// Whether a response content type may hold an image.
+fn is_image_content_type(mime: &str) -> bool {
+    mime.starts_with("image/") || matches!(mime, "application/octet-stream" | "binary/octet-stream")
+}
+
+/// File name of the last URL path segment, if it looks like one.
+///
+/// Used to name the output of a URL input like a local file's; the query
+/// string and fragment are ignored.
+pub(crate) fn file_name(url: &str) -> Option<String> {
+    let uri: ureq::http::Uri = url.parse().ok()?;
+    let name = uri.path().rsplit('/').next()?;
+    let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+    let usable = !stem.is_empty() && !stem.starts_with('.') && !name.contains(['\\', ':', '%']);
+    usable.then(|| name.to_string())
+}
@@ -0,0 +1,355 @@
+//! Reusable background remover with an explicitly owned model session.
+//!
+//! The free functions such as [`remove_background`](crate::remove_background)
+//! share lazily created removers that live for the whole process. A
+//! [`BackgroundRemover`] gives callers control over that lifecycle instead:
+//! several models can be loaded side by side, and dropping the remover frees
+//! its session memory.
+
+use crate::analysis::SubjectAnalysis;
+use crate::core::{self, analyze_file, remove_bytes, remove_file, remove_files, remove_to_writer, subject_mask, Channel};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::core::remove_url;
+use crate::error::{self, RemoveBgError, Result};
+use crate::fallback;
+use crate::frame::{image_from_raw, PixelFormat};
+use crate::options::{Backend, RemoveBgOptions};
+use crate::report::{RemovalReport, StageDurations};
+use crate::segmentation::{Runner, SegmentationModel};
+use image::{DynamicImage, GrayImage, RgbaImage};
+use ndarray::{Array2, Array4};
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+use web_time::Instant;
+
+/// A loaded segmentation model together with the options it runs with.
+///
+/// `BackgroundRemover` is `Send + Sync`, so one instance can be shared across a
+/// thread pool (for example behind an `Arc`). Inference calls are serialized on
+/// the session; pre- and post-processing run concurrently.
+///
+/// The model is U2-Net, run by the engine selected with
+/// [`backend`](RemoveBgOptions::backend); [`with_model`](Self::with_model)
+/// runs the same pipeline with any [`SegmentationModel`].
+///
+/// # Examples
+/// ```no_run
+/// use removebg::{BackgroundRemover, Model, RemoveBgOptions};
+///
+/// let remover = BackgroundRemover::new(RemoveBgOptions {
+///     model: Model::U2netp,
+///     ..Default::default()
+/// })?;
+///
+/// for input in ["a.jpg", "b.jpg"] {
+///     let report = remover.process_file(input, None)?;
+///     println!("Saved to: {}", report.output_path.display());
+/// }
+///
+/// // Dropping the remover frees the model session
+/// drop(remover);
+/// # Ok::<(), removebg::error::RemoveBgError>(())
+/// ```
+pub struct BackgroundRemover {
+    options: RemoveBgOptions,
+    segmenter: Segmenter,
+    /// When loading started, to tell calls that loaded the model apart.
+    created: Instant,
+    warmup: Duration,
+}
+
+/// The model a [`BackgroundRemover`] runs.
+pub(crate) enum Segmenter {
+    /// A built-in model, run with the options of each call so per-stage
+    /// timings and batching are available.
+    Builtin(Box<dyn Runner>),
+    Custom(Box<dyn SegmentationModel>),
+}
+
+impl BackgroundRemover {
+    /// Load the model selected by `options`, downloading it if needed.
+    ///
+    /// With [`warmup`](RemoveBgOptions::warmup), the model also runs once on
+    /// a blank image before this returns; with
+    /// [`quality_fallback`](RemoveBgOptions::quality_fallback) too, that run
+    /// is recorded in the [`calibration`](crate::fallback::calibration).
+    ///
+    /// # Errors
+    /// * `ModelInitError` - If the model cannot be downloaded, the session cannot be created, or the
+    ///   selected backend was not compiled in
+    /// * `ModelNotCached` - If the model isn't cached and `options.download.offline` is set
+    /// * `ModelError` - If the warm-up run fails
+    pub fn new(options: RemoveBgOptions) -> Result<Self> {
+        enter_span!("model_init", model = %options.model, backend = %options.backend, device = %options.device);
+        let created = Instant::now();
+        let runner = builtin_runner(&options)?;
+        let warmup = if options.warmup { warm_up(runner.as_ref())? } else { Duration::ZERO };
+        if options.warmup && options.quality_fallback.is_some() {
+            fallback::calibration().record(options.model, options.backend, options.device, warmup);
+        }
+        Ok(BackgroundRemover { options, segmenter: Segmenter::Builtin(runner), created, warmup })
+    }
+
+    /// Use a custom segmentation model instead of loading one.
+    ///
+    /// The model replaces the one selected by `options.model`; all other
+    /// options (mask refinement, compositing, output) apply as usual, and
+    /// [`mask_levels`](RemoveBgOptions::mask_levels) and
+    /// [`mask_gamma`](RemoveBgOptions::mask_gamma) are applied to the masks it
+    /// predicts. Reports of such a remover carry no
+    /// [`ModelInfo`](crate::ModelInfo). The
+    /// [`mask_cache`](RemoveBgOptions::mask_cache) is not used, since its
+    /// entries are keyed by the built-in model's name, nor the
+    /// [`quality_fallback`](RemoveBgOptions::quality_fallback), which
+    /// switches between built-in models.
+    ///
+    /// See [`segmentation`](crate::segmentation) for an example.
+    pub fn with_model(model: impl SegmentationModel + 'static, options: RemoveBgOptions) -> Self {
+        let options = RemoveBgOptions { mask_cache: None, quality_fallback: None, ..options };
+        BackgroundRemover { options, segmenter: Segmenter::Custom(Box::new(model)), created: Instant::now(), warmup: Duration::ZERO }
+    }
+
+    /// Options this remover was created with.
+    pub fn options(&self) -> &RemoveBgOptions {
+        &self.options
+    }
+
+    /// How long the warm-up run of [`new`](Self::new) took; zero without
+    /// [`warmup`](RemoveBgOptions::warmup).
+    pub fn warmup_duration(&self) -> Duration {
+        self.warmup
+    }
+
+    /// Whether the model was loaded after `started`, by the call that began
+    /// then.
+    pub(crate) fn loaded_since(&self, started: Instant) -> bool {
+        self.created >= started
+    }
+
+    pub(crate) fn segmenter(&self) -> &Segmenter {
+        &self.segmenter
+    }
+
+    /// Remove the background from an image file and save the result.
+    ///
+    /// Output path and format rules are the same as for
+    /// [`remove_background`](crate::remove_background).
+    ///
+    /// # Errors
+    /// Same as [`remove_background`](crate::remove_background).
+    pub fn process_file(&self, input_path: impl AsRef<Path>, output_path: Option<&Path>) -> Result<RemovalReport> {
+        remove_file(input_path.as_ref(), output_path, &self.options, || Ok((self, false)))
+    }
+
+    /// Remove the background from an image downloaded from `url`.
+    ///
+    /// # Errors
+    /// Same as [`remove_background_from_url`](crate::remove_background_from_url).
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn process_url(&self, url: &str, output_path: Option<&Path>) -> Result<RemovalReport> {
+        remove_url(url, output_path, &self.options, || Ok((self, false)))
+    }
+
+    /// Remove the background from an in-memory encoded image.
+    ///
+    /// The result is encoded in the configured output format (PNG if unset).
+    ///
+    /// # Errors
+    /// Same as [`remove_background_from_bytes`](crate::remove_background_from_bytes).
+    pub fn process_bytes(&self, data: &[u8]) -> Result<Vec<u8>> {
+        remove_bytes(data, &self.options, || Ok((self, false)))
+    }
+
+    /// Remove the background from an image file and encode the result into
+    /// `writer` as it is produced.
+    ///
+    /// # Errors
+    /// Same as [`remove_background_to_writer`](crate::remove_background_to_writer).
+    pub fn process_to_writer(&self, input_path: impl AsRef<Path>, writer: &mut impl Write) -> Result<()> {
+        remove_to_writer(input_path.as_ref(), writer, &self.options, || Ok((self, false)))
+    }
+
+    /// Find where the subject is in an image file without writing a cutout.
+    ///
+    /// # Errors
+    /// Same as [`analyze`](crate::analyze).
+    pub fn analyze_file(&self, input_path: impl AsRef<Path>, threshold: f32) -> Result<SubjectAnalysis> {
+        analyze_file(input_path.as_ref(), threshold, &self.options, || Ok((self, false)))
+    }
+
+    /// Remove the background from a decoded image.
+    ///
+    /// The result is 8-bit RGBA. [`process_file`](Self::process_file) and
+    /// [`process_bytes`](Self::process_bytes) keep 16-bit inputs at 16 bits
+    /// in PNG and TIFF output.
+    ///
+    /// # Errors
+    /// * `ModelError` - If model inference fails
+    /// * `Cancelled` - If the options' cancellation token was triggered
+    pub fn process_image(&self, image: &DynamicImage) -> Result<RgbaImage> {
+        let options = &self.options.timed();
+        error::catch_panic(|| {
+            let processed =
+                core::process_image(image, options, &mut StageDurations::default(), || Ok((self, false)))?;
+            Ok(processed.image.into_rgba8())
+        })
+    }
+
+    /// Remove the background from a raw frame of `width`x`height` pixels in
+    /// `format`, whose rows start `stride` bytes apart in `data`, as handed
+    /// out by camera SDKs.
+    ///
+    /// The frame is copied once, with its channels reordered and its row
+    /// padding dropped in the same pass (see
+    /// [`image_from_raw`](crate::frame::image_from_raw)), and then processed
+    /// like [`process_image`](Self::process_image).
+    ///
+    /// # Errors
+    /// * `InvalidRawLayout` - If `width` or `height` is zero, or `stride` is
+    ///   shorter than a row of pixels
+    /// * `RawBufferTooSmall` - If `data` ends before the last row does
+    /// * Otherwise the same as [`process_image`](Self::process_image)
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use removebg::{BackgroundRemover, PixelFormat, RemoveBgOptions};
+    ///
+    /// let remover = BackgroundRemover::new(RemoveBgOptions::default())?;
+    /// // A 1280x720 BGRA frame with rows padded to 5376 bytes
+    /// let frame = vec![0u8; 5376 * 720];
+    /// let cutout = remover.process_raw(&frame, 1280, 720, 5376, PixelFormat::Bgra8)?;
+    /// assert_eq!(cutout.dimensions(), (1280, 720));
+    /// # Ok::<(), removebg::RemoveBgError>(())
+    /// ```
+    pub fn process_raw(&self, data: &[u8], width: u32, height: u32, stride: usize, format: PixelFormat) -> Result<RgbaImage> {
+        let image = image_from_raw(data, width, height, stride, format)?;
+        self.process_image(&image)
+    }
+
+    /// Remove the background from several decoded images, running the model
+    /// on up to [`batch_size`](RemoveBgOptions::batch_size) of them at once.
+    ///
+    /// Returns one result per image, in order. Each result is 8-bit RGBA, as
+    /// with [`process_image`](Self::process_image); a failing image doesn't
+    /// fail the others.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use removebg::{BackgroundRemover, RemoveBgOptions};
+    ///
+    /// let remover = BackgroundRemover::new(RemoveBgOptions { batch_size: 8, ..Default::default() })?;
+    /// let images = vec![image::open("a.jpg")?, image::open("b.jpg")?];
+    /// for (i, cutout) in remover.process_images(&images).into_iter().enumerate() {
+    ///     cutout?.save(format!("cutout_{}.png", i))?;
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn process_images(&self, images: &[DynamicImage]) -> Vec<Result<RgbaImage>> {
+        let mut results = Vec::with_capacity(images.len());
+        for chunk in images.chunks(self.options.batch_size.max(1)) {
+            let chunk: Vec<&DynamicImage> = chunk.iter().collect();
+            let mut durations = vec![StageDurations::default(); chunk.len()];
+            let processed = core::process_images(&chunk, &self.options, self, &mut durations);
+            results.extend(processed.into_iter().map(|processed| Ok(processed?.image.into_rgba8())));
+        }
+        results
+    }
+
+    /// Remove the background from several image files, as with
+    /// [`process_file`](Self::process_file) for each `(input, output)` pair,
+    /// running the model on all still images in one batch.
+    pub(crate) fn process_files(&self, jobs: &[(&Path, Option<&Path>)]) -> Vec<Result<RemovalReport>> {
+        remove_files(jobs, &self.options, self)
+    }
+
+    /// Compute the subject mask for a decoded image at the image's own size.
+    ///
+    /// White pixels belong to the subject and black pixels to the background.
+    /// Mask refinements enabled in the options are applied. This is
+    /// [`mask_f32`](Self::mask_f32) rounded to 8 bits.
+    ///
+    /// # Errors
+    /// * `ImageTooSmall` - If the image is smaller than 2x2 pixels
+    /// * `ModelError` - If model inference fails
+    /// * `Cancelled` - If the options' cancellation token was triggered
+    pub fn mask(&self, image: &DynamicImage) -> Result<GrayImage> {
+        let mask = self.mask_f32(image)?;
+        let values = mask.iter().map(|&value| u8::from_unit(value)).collect();
+        Ok(GrayImage::from_raw(image.width(), image.height(), values).expect("one value per pixel"))
+    }
+
+    /// Compute the subject mask for a decoded image at the image's own size,
+    /// as a `(height, width)` array of values in 0.0-1.0.
+    ///
+    /// The model's output is upscaled and refined in floating point, so soft
+    /// edges keep their precision for matting or compositing of your own.
+    /// Tiled, region-of-interest and two-pass masks and those of custom
+    /// models are 16-bit before refinement. [`mask`](Self::mask) rounds this
+    /// mask to 8 bits.
+    ///
+    /// # Errors
+    /// * `ImageTooSmall` - If the image is smaller than 2x2 pixels
+    /// * `ModelError` - If model inference fails
+    /// * `Cancelled` - If the options' cancellation token was triggered
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use removebg::{BackgroundRemover, RemoveBgOptions};
+    ///
+    /// let remover = BackgroundRemover::new(RemoveBgOptions::default())?;
+    /// let image = image::open("photo.jpg")?;
+    /// let mask = remover.mask_f32(&image)?;
+    /// assert_eq!(mask.dim(), (image.height() as usize, image.width() as usize));
+    /// let edge = mask.iter().filter(|&&alpha| alpha > 0.0 && alpha < 1.0).count();
+    /// println!("{} edge pixels", edge);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn mask_f32(&self, image: &DynamicImage) -> Result<Array2<f32>> {
+        self.options.cancel.check()?;
+        core::check_min_size(image)?;
+        let mask = error::catch_panic(|| subject_mask(self, image, &self.options, &mut StageDurations::default()))?;
+        let shape = (mask.height() as usize, mask.width() as usize);
+        Ok(Array2::from_shape_vec(shape, mask.into_raw()).expect("one value per pixel"))
+    }
+}
+
+/// Load the built-in model selected by `options` on its backend.
+/// Run `runner` once on a blank image and return how long it took.
+fn warm_up(runner: &dyn Runner) -> Result<Duration> {
+    let started = Instant::now();
+    let size = runner.input_size() as usize;
+    runner.run(Array4::zeros((1, 3, size, size)), &mut |_, _| Ok(Vec::new()))?;
+    Ok(started.elapsed())
+}
+
+fn builtin_runner(options: &RemoveBgOptions) -> Result<Box<dyn Runner>> {
+    match options.backend {
+        #[cfg(feature = "ort")]
+        Backend::Ort => Ok(Box::new(crate::segmentation::OrtU2Net::new(options)?)),
+        #[cfg(feature = "backend-tract")]
+        Backend::Tract => Ok(Box::new(crate::segmentation::TractU2Net::new(options)?)),
+        #[allow(unreachable_patterns)]
+        backend => Err(RemoveBgError::ModelInitError(format!(
+            "removebg was built without the `{}` feature",
+            match backend {
+                Backend::Ort => "ort",
+                Backend::Tract => "backend-tract",
+            }
+        ))),
+    }
+}
+
+impl std::fmt::Debug for BackgroundRemover {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BackgroundRemover")
+            .field("options", &self.options)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Sharing one remover across threads is part of the public contract.
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<BackgroundRemover>();
+};
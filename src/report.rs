@@ -0,0 +1,368 @@
+//! Detailed results of a background removal run.
+//!
+//! This module defines the report returned by
+//! [`remove_background_detailed`](crate::remove_background_detailed), which
+//! describes the processed image, the time spent in each stage, and the model
+//! that was used.
+
+use crate::analysis::AlphaStats;
+use crate::model::Model;
+use crate::options::{Device, OutputKind};
+use crate::output::PngCompression;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Time spent in each stage of the pipeline.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct StageDurations {
+    /// Downloading the model (if needed) and creating the inference session.
+    #[cfg_attr(feature = "serde", serde(rename = "model_load_ms", with = "crate::serialization::millis"))]
+    pub model_load: Duration,
+    /// Running the model once on a blank image after loading it, with
+    /// [`warmup`](crate::RemoveBgOptions::warmup); zero unless the model was
+    /// loaded during the call. Not included in `model_load`.
+    #[cfg_attr(feature = "serde", serde(rename = "warmup_ms", with = "crate::serialization::millis"))]
+    pub warmup: Duration,
+    /// Reading and decoding the input image.
+    #[cfg_attr(feature = "serde", serde(rename = "decode_ms", with = "crate::serialization::millis"))]
+    pub decode: Duration,
+    /// Resizing and normalizing the image into the model input tensor.
+    #[cfg_attr(feature = "serde", serde(rename = "preprocess_ms", with = "crate::serialization::millis"))]
+    pub preprocess: Duration,
+    /// Running the model.
+    #[cfg_attr(feature = "serde", serde(rename = "inference_ms", with = "crate::serialization::millis"))]
+    pub inference: Duration,
+    /// Preprocessing and running the model again on the subject's crop, with
+    /// [`two_pass`](crate::RemoveBgOptions::two_pass); zero when the second
+    /// pass was skipped.
+    #[cfg_attr(feature = "serde", serde(rename = "second_pass_ms", with = "crate::serialization::millis"))]
+    pub second_pass: Duration,
+    /// Upscaling the model's mask to the image size and refining it.
+    #[cfg_attr(feature = "serde", serde(rename = "postprocess_ms", with = "crate::serialization::millis"))]
+    pub postprocess: Duration,
+    /// Applying the mask to the image, with any background or effects.
+    #[cfg_attr(feature = "serde", serde(rename = "composite_ms", with = "crate::serialization::millis"))]
+    pub composite: Duration,
+    /// Encoding and writing the output image.
+    #[cfg_attr(feature = "serde", serde(rename = "encode_ms", with = "crate::serialization::millis"))]
+    pub encode: Duration,
+    /// Wall time of the whole call.
+    #[cfg_attr(feature = "serde", serde(rename = "total_ms", with = "crate::serialization::millis"))]
+    pub total: Duration,
+}
+
+impl std::ops::AddAssign for StageDurations {
+    /// Add up per-stage times, e.g. across the frames of an animation.
+    fn add_assign(&mut self, other: Self) {
+        self.model_load += other.model_load;
+        self.warmup += other.warmup;
+        self.decode += other.decode;
+        self.preprocess += other.preprocess;
+        self.inference += other.inference;
+        self.second_pass += other.second_pass;
+        self.postprocess += other.postprocess;
+        self.composite += other.composite;
+        self.encode += other.encode;
+        self.total += other.total;
+    }
+}
+
+/// The model used for a run.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ModelInfo {
+    /// Segmentation model.
+    pub model: Model,
+    /// Device requested for inference.
+    pub device: Device,
+    /// Path of the cached model file; empty for a model compiled into the
+    /// binary (see [`embedded_model`](crate::model::embedded_model)).
+    pub path: PathBuf,
+    /// Size of the model file in bytes.
+    pub size_bytes: u64,
+    /// Whether the model file had to be downloaded during this call.
+    pub downloaded: bool,
+    /// How the model was chosen with [`AutoModel`](crate::AutoModel); `None`
+    /// for a fixed model.
+    pub selection: Option<ModelSelection>,
+    /// Size the model ran at; `None` when it didn't run, such as for a mask
+    /// from the [`mask_cache`](crate::RemoveBgOptions::mask_cache) or
+    /// [`prepare_model`](crate::prepare_model).
+    pub inference: Option<InferenceInfo>,
+    /// What [`quality_fallback`](crate::RemoveBgOptions::quality_fallback)
+    /// gave up to meet its deadline, in the order it was given up; `model`
+    /// and `inference` describe the run that was made. Empty without it or
+    /// when the full-quality run fit.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub downgrades: Vec<Downgrade>,
+}
+
+/// The size a model ran at ([`inference_size`](crate::RemoveBgOptions::inference_size)).
+/// The inference time is in [`StageDurations::inference`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InferenceInfo {
+    /// Edge length of the square image the model ran on.
+    pub size: u32,
+    /// Estimated cost of a run at `size` relative to one at the model's
+    /// native size, from the number of pixels: 4.0 for 640 with a 320
+    /// model.
+    pub relative_cost: f32,
+    /// Fine detail of the image, the variance of the Laplacian of its
+    /// luminance on a thumbnail, with [`InferenceSize::Auto`](crate::InferenceSize::Auto);
+    /// `None` for a fixed size.
+    pub detail: Option<f32>,
+}
+
+/// How [`AutoModel`](crate::AutoModel) chose the model of a run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ModelSelection {
+    /// Models run on the image, in order; the last produced the output.
+    pub tried: Vec<Model>,
+    /// Why each model after the first was run and why the last was kept,
+    /// such as "u2netp mask looks like a person" for u2net-human-seg.
+    pub reason: String,
+}
+
+/// One step [`QualityFallback`](crate::QualityFallback) takes down from the
+/// configured quality, in the order they are taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum Downgrade {
+    /// The model was switched to u2netp.
+    Model,
+    /// Two-pass inference, tiling and the guided filter were skipped.
+    Refinement,
+    /// The model ran at a smaller size than configured.
+    InferenceSize,
+}
+
+impl Downgrade {
+    /// Name used in serialized reports and the server's
+    /// `X-Quality-Downgrades` header.
+    pub fn name(self) -> &'static str {
+        match self {
+            Downgrade::Model => "model",
+            Downgrade::Refinement => "refinement",
+            Downgrade::InferenceSize => "inference_size",
+        }
+    }
+}
+
+/// A rectangle in pixel coordinates.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rect {
+    /// Left edge.
+    pub x: u32,
+    /// Top edge.
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// An additional image written for an [`OutputSpec`](crate::OutputSpec).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Artifact {
+    /// What was written.
+    pub kind: OutputKind,
+    /// Where it was written, or would have been.
+    pub path: PathBuf,
+    /// Why it could not be written; `None` if it was.
+    pub error: Option<String>,
+}
+
+/// One subject written on its own with
+/// [`split_instances`](crate::RemoveBgOptions::split_instances).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Instance {
+    /// Bounding box of the region at half alpha, on the input image.
+    /// Serialized as `x`, `y`, `width` and `height` next to the other fields.
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub bounds: Rect,
+    /// Number of pixels in the region.
+    pub area: u64,
+    /// Where the instance was written.
+    pub path: PathBuf,
+}
+
+/// Pages of a multi-page TIFF input and the ones that were processed (see
+/// [`pages`](crate::RemoveBgOptions::pages)).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PageSummary {
+    /// Number of pages in the input.
+    pub count: u32,
+    /// Pages processed, in order.
+    pub processed: Vec<PageOutput>,
+}
+
+/// One processed page of a multi-page input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PageOutput {
+    /// The page, counting from 1.
+    pub page: u32,
+    /// Where its cutout was written.
+    pub output_path: PathBuf,
+}
+
+/// Encoder settings that brought an output under its
+/// [`max_file_size`](crate::RemoveBgOptions::max_file_size).
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FileSizeFit {
+    /// Size of the written file in bytes.
+    pub size: u64,
+    /// Lossy quality (0-100) of JPEG and WebP output; `None` for lossless
+    /// output.
+    pub quality: Option<f32>,
+    /// Compression level of PNG output; `None` for other formats.
+    pub png_compression: Option<PngCompression>,
+    /// Whether PNG output was reduced to an 8-bit palette.
+    pub quantized: bool,
+    /// Whether the image had to be scaled down to fit.
+    pub downscaled: bool,
+    /// Width and height the image was written at, as in the report's
+    /// `output_dimensions`.
+    pub dimensions: (u32, u32),
+    /// Number of encodings tried.
+    pub attempts: u32,
+}
+
+/// Shortcut taken for a model mask that is the same everywhere (see
+/// [`RemovalReport::mask_fast_path`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "lowercase"))]
+pub enum MaskFastPath {
+    /// The model found nothing: the mask was filled with zero instead of
+    /// being upscaled.
+    Empty,
+    /// The model kept everything: the mask was filled instead of being
+    /// upscaled, and the cutout is a copy of the input with opaque alpha
+    /// unless refinement, inversion or the input's own alpha made part of
+    /// the mask transparent.
+    Full,
+}
+
+impl MaskFastPath {
+    /// Name used in `--json` output.
+    pub fn name(self) -> &'static str {
+        match self {
+            MaskFastPath::Empty => "empty",
+            MaskFastPath::Full => "full",
+        }
+    }
+}
+
+/// The largest buffers processing one image takes, estimated from its
+/// dimensions before they are allocated (see [`memory`](crate::memory)).
+/// All sizes are in bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MemoryEstimate {
+    /// The decoded input at its own bit depth, and its downscaled copy with
+    /// [`max_output_size`](crate::RemoveBgOptions::max_output_size).
+    pub input_bytes: u64,
+    /// The 16-bit full-resolution mask, the floats it is upscaled through
+    /// in one piece, and the blending buffers of
+    /// [`tiling`](crate::RemoveBgOptions::tiling).
+    pub mask_bytes: u64,
+    /// The subject's crop and its mask in the second pass of
+    /// [`two_pass`](crate::RemoveBgOptions::two_pass), taken as half the
+    /// image; zero without it.
+    pub crop_bytes: u64,
+    /// The RGBA output, 16-bit for high bit depth inputs.
+    pub output_bytes: u64,
+    /// The copy the encoder converts the output to, if any, and the
+    /// encoded file.
+    pub encoder_bytes: u64,
+    /// Sum of the above.
+    pub total_bytes: u64,
+    /// Whether the mask is upscaled in bands of rows instead of through a
+    /// full-resolution float copy, for a large image or to fit
+    /// [`max_memory_bytes`](crate::RemoveBgOptions::max_memory_bytes).
+    pub banded: bool,
+}
+
+/// Detailed result of removing the background from one image.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RemovalReport {
+    /// Path of the output file that was written.
+    pub output_path: PathBuf,
+    /// Path of the mask sidecar written with
+    /// [`save_mask`](crate::RemoveBgOptions::save_mask), if any.
+    pub mask_path: Option<PathBuf>,
+    /// Additional images written for
+    /// [`outputs`](crate::RemoveBgOptions::outputs), in the same order,
+    /// including any that failed.
+    pub artifacts: Vec<Artifact>,
+    /// Subjects written on their own with
+    /// [`split_instances`](crate::RemoveBgOptions::split_instances), largest
+    /// first; empty without it.
+    pub instances: Vec<Instance>,
+    /// Page count of a multi-page TIFF input and the pages processed;
+    /// `None` for single-page inputs. When several pages were written, the
+    /// other fields describe the first, and the durations add up all pages.
+    pub pages: Option<PageSummary>,
+    /// Width and height of the input image.
+    pub input_dimensions: (u32, u32),
+    /// Width and height of the output canvas, which differs from the input
+    /// with [`geometry`](crate::RemoveBgOptions::geometry) or a sticker.
+    pub output_dimensions: (u32, u32),
+    /// Bounding box of the visible subject on the output canvas; `None` if
+    /// the mask is empty or the input is an animation.
+    pub subject_bounds: Option<Rect>,
+    /// Fraction of pixels (0.0-1.0) the mask kept, i.e. with alpha above zero.
+    ///
+    /// A value near zero usually means the model found no subject (near one
+    /// with [`invert_mask`](crate::RemoveBgOptions::invert_mask)).
+    pub mask_coverage: f32,
+    /// Confidence score (0.0-1.0) of the model's mask before refinement; low
+    /// scores mean no clear subject was found (see
+    /// [`ConfidenceOptions`](crate::ConfidenceOptions)). For animations, the
+    /// lowest score of any frame.
+    pub confidence: f32,
+    /// Quality metrics of the finished mask's alpha, measured with
+    /// [`alpha_stats`](crate::RemoveBgOptions::alpha_stats); `None` without
+    /// it and for animations.
+    pub alpha_stats: Option<AlphaStats>,
+    /// Encoder settings used to meet
+    /// [`max_file_size`](crate::RemoveBgOptions::max_file_size); `None`
+    /// without a limit.
+    pub file_size: Option<FileSizeFit>,
+    /// Solid color the background was flattened onto: the
+    /// [`background`](crate::RemoveBgOptions::background) color, or the
+    /// color picked for [`Background::Auto`](crate::Background::Auto), which
+    /// can be passed back as a fixed color to match other images to this
+    /// one. `None` when the background is transparent, chroma keyed or kept.
+    pub background_color: Option<[u8; 3]>,
+    /// Whether the output's color channels are premultiplied by its alpha
+    /// (see [`premultiplied`](crate::RemoveBgOptions::premultiplied)).
+    pub premultiplied: bool,
+    /// Time spent in each stage.
+    pub durations: StageDurations,
+    /// Model used to generate the mask; `None` when an external mask was
+    /// applied instead (see [`external_mask`](crate::RemoveBgOptions::external_mask)),
+    /// the mask of line art was taken from luminance (see
+    /// [`line_art`](crate::RemoveBgOptions::line_art)) or the remover runs a
+    /// custom [`SegmentationModel`](crate::SegmentationModel).
+    pub model: Option<ModelInfo>,
+    /// Whether the mask was read from the
+    /// [`mask_cache`](crate::RemoveBgOptions::mask_cache) instead of running
+    /// the model; the model was then not loaded either.
+    pub mask_cached: bool,
+    /// Shortcut taken because the model's mask was empty or full
+    /// everywhere; `None` when the mask was upscaled and applied as usual.
+    /// The output is the same either way.
+    pub mask_fast_path: Option<MaskFastPath>,
+    /// Estimated memory of the largest buffers processing the image took,
+    /// as checked against [`max_memory_bytes`](crate::RemoveBgOptions::max_memory_bytes);
+    /// for several pages, that of the first. `None` for animations.
+    pub memory_estimate: Option<MemoryEstimate>,
+}
@@ -0,0 +1,76 @@
+//! Byte-reproducible outputs (`--reproducible`) and `SOURCE_DATE_EPOCH`.
+//!
+//! The encoders write no timestamps or software names, and the mask math
+//! works row by row without sums across rows, so rayon's thread count never
+//! changes a result. What still varies between runs and machines is
+//! inference: ONNX Runtime splits its sums over as many threads as there are
+//! CPUs, and GPU execution providers promise no bit-identical results at
+//! all. [`options`] pins those down.
+//!
+//! [`SOURCE_DATE_EPOCH`](https://reproducible-builds.org/specs/source-date-epoch/)
+//! becomes the modification time of every output file written, with or
+//! without `--reproducible`.
+
+use crate::options::{Device, RemoveBgOptions};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The environment variable holding the timestamp outputs get.
+pub const SOURCE_DATE_EPOCH_ENV: &str = "SOURCE_DATE_EPOCH";
+
+/// `options` with everything that can vary the output bytes between runs
+/// and machines pinned down: inference on the CPU with one intra-op and one
+/// inter-op thread, one image per model run, and no
+/// [`quality_fallback`](RemoveBgOptions::quality_fallback), whose downgrades
+/// depend on how long runs take.
+///
+/// Results are then the same for the same input bytes, options, model and
+/// backend on every machine of the same architecture, as long as the
+/// inference engine picks the same kernels; ONNX Runtime and tract choose
+/// them by instruction set, so an x86-64 machine with AVX-512 may still
+/// differ from one without.
+///
+/// # Examples
+/// ```
+/// use removebg::{reproducible, Device, RemoveBgOptions};
+///
+/// let options = reproducible::options(RemoveBgOptions { device: Device::CoreMl, ..Default::default() });
+/// assert_eq!((options.device, options.intra_threads, options.batch_size), (Device::Cpu, Some(1), 1));
+/// ```
+pub fn options(options: RemoveBgOptions) -> RemoveBgOptions {
+    if options.device != Device::Cpu {
+        log::warn!("Reproducible output runs inference on the CPU instead of {}", options.device);
+    }
+    RemoveBgOptions { device: Device::Cpu, intra_threads: Some(1), inter_threads: Some(1), batch_size: 1, quality_fallback: None, ..options }
+}
+
+/// The time in `SOURCE_DATE_EPOCH`, if it is set to whole seconds since the
+/// Unix epoch. Other values are ignored with a warning, once.
+pub fn source_date_epoch() -> Option<SystemTime> {
+    static EPOCH: OnceLock<Option<SystemTime>> = OnceLock::new();
+    *EPOCH.get_or_init(|| parse(&std::env::var(SOURCE_DATE_EPOCH_ENV).ok()?))
+}
+
+fn parse(value: &str) -> Option<SystemTime> {
+    match value.trim().parse::<u64>() {
+        Ok(seconds) => UNIX_EPOCH.checked_add(Duration::from_secs(seconds)),
+        Err(_) => {
+            log::warn!("Ignoring {}={:?}: expected seconds since 1970-01-01", SOURCE_DATE_EPOCH_ENV, value);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_date_epoch_is_whole_seconds() {
+        assert_eq!(parse("1700000000"), UNIX_EPOCH.checked_add(Duration::from_secs(1_700_000_000)));
+        assert_eq!(parse(" 0\n"), Some(UNIX_EPOCH));
+        assert_eq!(parse("-1"), None);
+        assert_eq!(parse("2024-01-01"), None);
+        assert_eq!(parse("1.5"), None);
+    }
+}
@@ -0,0 +1,210 @@
+//! Size of the image the model runs on
+//! ([`inference_size`](crate::RemoveBgOptions::inference_size)).
+//!
+//! U2-Net was trained at 320x320, but it is fully convolutional and runs at
+//! larger sizes too, where it keeps finer detail: hair, lace, thin branches.
+//! Doubling the size costs four times the compute, so
+//! [`InferenceSize::Auto`] only goes up for images that are large enough to
+//! have that detail. How much detail an image has is estimated by the
+//! variance of the Laplacian of its luminance on a thumbnail, which is high
+//! for sharp, busy images and low for smooth or blurry ones.
+
+use crate::error::{RemoveBgError, Result};
+use crate::options::InferenceSize;
+use image::DynamicImage;
+
+/// Smallest and largest fixed sizes accepted. Below 128 the model's
+/// downsampling stages leave too little to segment; past 2048 a single run
+/// takes gigabytes, and tiling does better.
+const MIN_SIZE: u32 = 128;
+const MAX_SIZE: u32 = 2048;
+
+/// U2-Net halves its feature maps five times, so sizes are multiples of 32.
+const STEP: u32 = 32;
+
+/// Sizes [`InferenceSize::Auto`] chooses from, smallest first.
+const AUTO_SIZES: [u32; 3] = [320, 640, 1024];
+
+/// Longer side of the thumbnail the detail is measured on.
+const THUMBNAIL: u32 = 256;
+
+/// Laplacian variance at and above which an image counts as detailed.
+/// Smooth photos and flat graphics stay well below it; fur, foliage and
+/// sharp, busy scenes are above.
+const DETAILED: f32 = 400.0;
+
+/// Image pixels per model pixel along the longer side that `Auto` accepts,
+/// for plain and for detailed images.
+const PLAIN_SCALE: f32 = 4.0;
+const DETAILED_SCALE: f32 = 2.0;
+
+/// Check that a fixed inference size is usable.
+pub(crate) fn check(size: u32) -> Result<()> {
+    if !(MIN_SIZE..=MAX_SIZE).contains(&size) || !size.is_multiple_of(STEP) {
+        return Err(RemoveBgError::ProcessingError(format!(
+            "Inference size must be a multiple of {} from {} to {}, got {}",
+            STEP, MIN_SIZE, MAX_SIZE, size
+        )));
+    }
+    Ok(())
+}
+
+/// The size to run the model on `image` at for `inference_size`, and the
+/// image's detail with `Auto`. `native` is the size the model was loaded
+/// for; with `tiled`, images that would want more than the largest
+/// automatic size aren't told to use tiling.
+pub(crate) fn resolve(inference_size: InferenceSize, image: &DynamicImage, native: u32, tiled: bool) -> Result<(u32, Option<f32>)> {
+    match inference_size {
+        InferenceSize::Native => Ok((native, None)),
+        InferenceSize::Fixed(size) => check(size).map(|_| (size, None)),
+        InferenceSize::Auto => {
+            let (size, detail) = choose(image, tiled);
+            Ok((size.max(native), Some(detail)))
+        }
+    }
+}
+
+/// The automatic size for `image` and its detail.
+fn choose(image: &DynamicImage, tiled: bool) -> (u32, f32) {
+    let detail = detail(image);
+    let (width, height) = (image.width(), image.height());
+    let scale = if detail >= DETAILED { DETAILED_SCALE } else { PLAIN_SCALE };
+    let wanted = width.max(height) as f32 / scale;
+    let size = match AUTO_SIZES.into_iter().find(|&size| size as f32 >= wanted) {
+        Some(size) => size,
+        None => {
+            let largest = AUTO_SIZES[AUTO_SIZES.len() - 1];
+            if !tiled {
+                log::warn!(
+                    "{}x{} image would need an inference size of about {}, more than {}; tiled inference would keep more of its detail",
+                    width,
+                    height,
+                    (wanted as u32).div_ceil(STEP) * STEP,
+                    largest
+                );
+            }
+            largest
+        }
+    };
+    log::debug!("inference size {} for a {}x{} image with detail {:.0}", size, width, height, detail);
+    (size, detail)
+}
+
+/// Variance of the 4-neighbor Laplacian of the luminance (0-255) of a
+/// thumbnail of `image`.
+pub(crate) fn detail(image: &DynamicImage) -> f32 {
+    let (width, height) = (image.width(), image.height());
+    let thumbnail = if width.max(height) > THUMBNAIL {
+        image.thumbnail(THUMBNAIL, THUMBNAIL).to_luma8()
+    } else {
+        image.to_luma8()
+    };
+    let (width, height) = thumbnail.dimensions();
+    if width < 3 || height < 3 {
+        return 0.0;
+    }
+    let at = |x: u32, y: u32| thumbnail.get_pixel(x, y)[0] as f64;
+    let (mut sum, mut squares, mut count) = (0.0, 0.0, 0.0);
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let laplacian = at(x - 1, y) + at(x + 1, y) + at(x, y - 1) + at(x, y + 1) - 4.0 * at(x, y);
+            sum += laplacian;
+            squares += laplacian * laplacian;
+            count += 1.0;
+        }
+    }
+    let mean = sum / count;
+    (squares / count - mean * mean) as f32
+}
+
+/// Estimated cost of a run at `size` relative to one at `native`.
+pub(crate) fn relative_cost(size: u32, native: u32) -> f32 {
+    (size as f32 / native as f32).powi(2)
+}
+
+/// The largest size below `size` whose cost relative to `native` is at
+/// most `cost`, or the smallest size if none is; `None` if `size` is the
+/// smallest already.
+pub(crate) fn smaller_within(size: u32, native: u32, cost: f32) -> Option<u32> {
+    if size <= MIN_SIZE {
+        return None;
+    }
+    let fitting = (native as f32 * cost.max(0.0).sqrt()) as u32 / STEP * STEP;
+    Some(fitting.min(size - STEP).max(MIN_SIZE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma};
+
+    #[test]
+    fn sizes_are_multiples_of_32_in_range() {
+        for size in [128, 320, 640, 1024, 2048] {
+            assert!(check(size).is_ok(), "{}", size);
+        }
+        for size in [0, 96, 330, 2080] {
+            assert!(check(size).is_err(), "{}", size);
+        }
+    }
+
+    #[test]
+    fn parses_like_the_command_line() {
+        assert_eq!("auto".parse(), Ok(InferenceSize::Auto));
+        assert_eq!("Native".parse(), Ok(InferenceSize::Native));
+        assert_eq!("640".parse(), Ok(InferenceSize::Fixed(640)));
+        assert!("650".parse::<InferenceSize>().unwrap_err().contains("multiple of 32"));
+        assert!("large".parse::<InferenceSize>().is_err());
+        for size in [InferenceSize::Auto, InferenceSize::Native, InferenceSize::Fixed(1024)] {
+            assert_eq!(size.to_string().parse(), Ok(size));
+        }
+    }
+
+    fn flat(size: u32) -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_pixel(size, size * 3 / 4, Luma([128])))
+    }
+
+    /// 32 black and 32 white stripes, so the thumbnail keeps them
+    fn stripes(size: u32) -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_fn(size, size * 3 / 4, |x, _| Luma([if (x * 64 / size).is_multiple_of(2) { 0 } else { 255 }])))
+    }
+
+    #[test]
+    fn detail_separates_flat_from_busy_images() {
+        assert_eq!(detail(&flat(600)), 0.0);
+        assert!(detail(&stripes(600)) > DETAILED);
+    }
+
+    #[test]
+    fn auto_grows_with_size_and_detail() {
+        let auto = |image: &DynamicImage| resolve(InferenceSize::Auto, image, 320, false).unwrap().0;
+        assert_eq!(auto(&flat(800)), 320);
+        assert_eq!(auto(&flat(2000)), 640);
+        assert_eq!(auto(&flat(4000)), 1024);
+        assert_eq!(auto(&stripes(800)), 640);
+        assert_eq!(auto(&stripes(1600)), 1024);
+        // More than 1024 is never chosen; tiling is suggested instead
+        assert_eq!(auto(&stripes(4000)), 1024);
+        // Nor less than the model's own size
+        assert_eq!(resolve(InferenceSize::Auto, &flat(800), 1024, false).unwrap().0, 1024);
+    }
+
+    #[test]
+    fn fixed_sizes_are_checked() {
+        assert_eq!(resolve(InferenceSize::Fixed(640), &flat(100), 320, false).unwrap(), (640, None));
+        assert!(resolve(InferenceSize::Fixed(100), &flat(100), 320, false).is_err());
+        assert_eq!(resolve(InferenceSize::Native, &flat(100), 1024, false).unwrap(), (1024, None));
+        assert_eq!(relative_cost(640, 320), 4.0);
+    }
+
+    #[test]
+    fn smaller_sizes_fit_the_cost() {
+        // A quarter of the cost of 640 at native 320 is 320
+        assert_eq!(smaller_within(640, 320, 1.0), Some(320));
+        assert_eq!(smaller_within(1024, 320, 2.0), Some(448));
+        // Always smaller, and never below the smallest size
+        assert_eq!(smaller_within(640, 320, 9.0), Some(608));
+        assert_eq!(smaller_within(320, 320, 0.0), Some(128));
+        assert_eq!(smaller_within(128, 320, 0.0), None);
+    }
+}
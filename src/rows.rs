@@ -0,0 +1,34 @@
+//! Row loops over full-resolution image buffers.
+//!
+//! Applying the mask and compositing touch every pixel of the input image
+//! once, which for a 24 MP photo takes longer than inference. The rows are
+//! independent, so with the default `parallel` feature they are spread over
+//! rayon's thread pool; without it (as in the wasm build) they run in order.
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+/// Call `f` with the index and samples of each `row_len`-sample row of
+/// `data`.
+pub(crate) fn for_each_row<T: Send>(data: &mut [T], row_len: usize, f: impl Fn(usize, &mut [T]) + Sync + Send) {
+    let _ = try_for_each_row(data, row_len, |y, row| {
+        f(y, row);
+        Ok::<(), ()>(())
+    });
+}
+
+/// Like [`for_each_row`], stopping at the first error (such as a
+/// cancellation); with `parallel`, rows already started still finish.
+pub(crate) fn try_for_each_row<T: Send, E: Send>(
+    data: &mut [T],
+    row_len: usize,
+    f: impl Fn(usize, &mut [T]) -> Result<(), E> + Sync + Send,
+) -> Result<(), E> {
+    if row_len == 0 {
+        return Ok(());
+    }
+    #[cfg(feature = "parallel")]
+    return data.par_chunks_mut(row_len).enumerate().try_for_each(|(y, row)| f(y, row));
+    #[cfg(not(feature = "parallel"))]
+    return data.chunks_mut(row_len).enumerate().try_for_each(|(y, row)| f(y, row));
+}
@@ -0,0 +1,145 @@
+//! Pluggable segmentation models.
+//!
+//! Everything after the model (mask refinement, compositing, output
+//! encoding) works on the mask alone, so any network that produces a subject
+//! mask can drive the full pipeline. Implement [`SegmentationModel`] and pass
+//! it to [`BackgroundRemover::with_model`](crate::BackgroundRemover::with_model);
+//! the built-in models run the U2-Net family through ONNX Runtime
+//! ([`OrtU2Net`]) or tract (`TractU2Net`, with the `backend-tract` feature).
+//!
+//! # Examples
+//!
+//! A custom model that keeps an ellipse in the middle of the image, driven
+//! through the full file-to-file flow:
+//!
+//! ```no_run
+//! use image::{DynamicImage, Luma};
+//! use removebg::pipeline::Gray16Image;
+//! use removebg::{BackgroundRemover, RemoveBgOptions, Result, SegmentationModel};
+//! use std::path::Path;
+//!
+//! struct CenterModel;
+//!
+//! impl SegmentationModel for CenterModel {
+//!     fn predict_mask(&self, _image: &DynamicImage) -> Result<Gray16Image> {
+//!         // Masks at any resolution are resized to the image
+//!         Ok(Gray16Image::from_fn(64, 64, |x, y| {
+//!             let (dx, dy) = (x as f32 - 31.5, y as f32 - 31.5);
+//!             Luma([if dx * dx + dy * dy < 24.0 * 24.0 { 65535 } else { 0 }])
+//!         }))
+//!     }
+//! }
+//!
+//! let remover = BackgroundRemover::with_model(CenterModel, RemoveBgOptions::default());
+//! let report = remover.process_file("photo.jpg", Some(Path::new("photo_nobg.png")))?;
+//! println!("kept {:.0}% of the image", report.mask_coverage * 100.0);
+//! # Ok::<(), removebg::error::RemoveBgError>(())
+//! ```
+
+use crate::core::{Gray16Image, Gray32FImage};
+use crate::error::{RemoveBgError, Result};
+use crate::model::Model;
+use image::DynamicImage;
+use ndarray::{Array4, ArrayViewD};
+use std::time::Duration;
+
+#[cfg(feature = "ort")]
+pub use crate::ort_backend::OrtU2Net;
+#[cfg(feature = "backend-tract")]
+pub use crate::tract_backend::TractU2Net;
+
+/// A network that predicts which pixels of an image belong to the subject.
+///
+/// Implementations must be `Send + Sync`, since a
+/// [`BackgroundRemover`](crate::BackgroundRemover) may be shared across
+/// threads.
+pub trait SegmentationModel: Send + Sync {
+    /// Predict the subject mask of `image`.
+    ///
+    /// White (65535) marks the subject and black the background. The mask may
+    /// have any size, such as the model's output resolution; it is resized to
+    /// the image before refinement.
+    ///
+    /// # Errors
+    /// Implementations return `ModelError` if inference fails.
+    fn predict_mask(&self, image: &DynamicImage) -> Result<Gray16Image>;
+
+    /// Predict the masks of several images, one result per image, in order.
+    ///
+    /// The default calls [`predict_mask`](Self::predict_mask) for each image;
+    /// models that can run a batch in one pass override it.
+    fn predict_masks(&self, images: &[&DynamicImage]) -> Vec<Result<Gray16Image>> {
+        images.iter().map(|image| self.predict_mask(image)).collect()
+    }
+}
+
+/// Callback that turns the outputs of a model run and its inference time
+/// into masks, still in floating point.
+pub(crate) type ReadOutputs<'a> = dyn FnMut(&[ArrayViewD<'_, f32>], Duration) -> Result<Vec<Gray32FImage>> + 'a;
+
+/// Edge length of the square image input of `model`, from the dimensions
+/// the model file declares for its input `name` (`None` for dynamic ones).
+///
+/// The input must be `(batch, 3, height, width)`. Static height and width
+/// give the size; where both are dynamic, the size registered for `model`
+/// is used.
+// Unused without an inference engine, as in the fuzz targets
+#[cfg_attr(not(any(feature = "ort", feature = "backend-tract")), allow(dead_code))]
+pub(crate) fn input_size(name: &str, dims: &[Option<i64>], model: Model) -> Result<u32> {
+    let [_, channels, height, width] = dims else {
+        return Err(RemoveBgError::ModelInitError(format!(
+            "model input '{}' has {} dimensions; expected (batch, 3, height, width)",
+            name,
+            dims.len()
+        )));
+    };
+    if channels.is_some_and(|channels| channels != 3) {
+        return Err(RemoveBgError::ModelInitError(format!(
+            "model input '{}' has {} channels; expected 3 (RGB)",
+            name,
+            channels.unwrap_or_default()
+        )));
+    }
+    let size = match (*height, *width) {
+        (Some(height), Some(width)) if height != width => {
+            return Err(RemoveBgError::ModelInitError(format!(
+                "model input '{}' is {}x{}; only square inputs are supported",
+                name, width, height
+            )));
+        }
+        (Some(size), _) | (None, Some(size)) => size,
+        (None, None) => {
+            let size = model.spec().input_size;
+            log::debug!("model input '{}' has a dynamic size; using {}x{}", name, size, size);
+            return Ok(size);
+        }
+    };
+    u32::try_from(size).ok().filter(|&size| size > 0).ok_or_else(|| {
+        RemoveBgError::ModelInitError(format!("model input '{}' has an invalid size {}", name, size))
+    })
+}
+
+/// Inference engine behind the built-in models.
+///
+/// The engine only runs the model; preprocessing, fusing the outputs and the
+/// mask curve are shared by all engines.
+pub(crate) trait Runner: Send + Sync {
+    /// Whether the model's input accepts a batch of `size` images.
+    fn accepts_batch(&self, size: usize) -> bool;
+
+    /// Edge length of the square images the model takes, read from the model
+    /// file when it declares one.
+    fn input_size(&self) -> u32;
+
+    /// Whether the model also runs on `size`x`size` images, for
+    /// [`InferenceSize`](crate::InferenceSize) other than the native one.
+    fn accepts_size(&self, size: u32) -> bool;
+
+    /// Run the model on a preprocessed `(n, 3, size, size)` input and pass
+    /// its outputs, in order, and the inference time to `read`.
+    fn run(
+        &self,
+        input: Array4<f32>,
+        read: &mut ReadOutputs<'_>,
+    ) -> Result<Vec<Gray32FImage>>;
+}
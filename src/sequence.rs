@@ -0,0 +1,361 @@
+//! Image sequences: remove backgrounds from numbered video frames.
+//!
+//! Used by `removebg sequence`. Frames are processed in numeric order with
+//! one loaded model, and each frame's mask is blended with the masks of the
+//! frames before it, so the cutouts don't flicker when the frames are played
+//! back. A large change between consecutive frames is taken as a scene cut
+//! and starts the blending over.
+
+use crate::core::{check_naming, remove_frame, Gray16Image};
+use crate::error::{RemoveBgError, Result};
+use crate::options::{Background, RemoveBgOptions};
+use crate::output::{self, OutputFormat};
+use crate::progress::{Progress, ProgressEvent, LOG_TARGET};
+use crate::remover::BackgroundRemover;
+use crate::watch::{is_image_file, output_path};
+use image::imageops::FilterType;
+use image::{DynamicImage, GrayImage};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Edge length of the thumbnails compared to detect scene cuts.
+const CUT_THUMBNAIL_SIZE: u32 = 64;
+
+/// Settings for [`process_sequence`].
+#[derive(Debug, Clone)]
+pub struct SequenceConfig {
+    /// Frame files, or patterns with `*` and `?` wildcards in the file name
+    /// (`frames/frame_*.png`). They are processed in the order of the last
+    /// number in their names.
+    pub inputs: Vec<PathBuf>,
+    /// Directory the cutouts are written to; created if missing.
+    pub output_dir: PathBuf,
+    /// Weight of the current frame's mask, 0.0-1.0. Each older mask weighs
+    /// `1 - temporal_alpha` times the next newer one; 1.0 disables smoothing.
+    pub temporal_alpha: f32,
+    /// Number of masks, including the current one, that are blended.
+    pub temporal_window: usize,
+    /// Mean difference between consecutive frames, 0.0-1.0, above which a
+    /// scene cut is detected and the older masks are dropped.
+    pub scene_cut_threshold: f32,
+    /// Options used for every frame. Cancelling `options.cancel` stops
+    /// processing after the current frame.
+    pub options: RemoveBgOptions,
+    /// Receives each frame's progress. The first failure ends the sequence.
+    pub progress: Progress,
+}
+
+impl SequenceConfig {
+    /// Process `inputs` into `output_dir` with default smoothing.
+    pub fn new(inputs: Vec<PathBuf>, output_dir: impl Into<PathBuf>) -> Self {
+        SequenceConfig {
+            inputs,
+            output_dir: output_dir.into(),
+            temporal_alpha: 0.5,
+            temporal_window: 4,
+            scene_cut_threshold: 0.25,
+            options: RemoveBgOptions::default(),
+            progress: Progress::default(),
+        }
+    }
+}
+
+/// What happened while processing a sequence.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SequenceSummary {
+    /// Frames processed.
+    pub frames: usize,
+    /// Scene cuts detected, where smoothing started over.
+    pub scene_cuts: usize,
+    /// Time spent on the frames, excluding model loading.
+    pub elapsed: Duration,
+}
+
+impl SequenceSummary {
+    /// Frames processed per second.
+    pub fn frames_per_second(&self) -> f64 {
+        self.frames as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+/// Remove the background from every frame of a sequence.
+///
+/// The frames' masks are smoothed over time before they are refined and
+/// applied (see [`SequenceConfig`]). Processing stops at the first frame that
+/// fails, since the rest of the sequence would not match the frames written
+/// so far.
+///
+/// # Errors
+/// * `FileNotFound` - If no frames match the inputs, or a frame is missing
+/// * `ProcessingError` - If the smoothing settings are out of range
+/// * `ModelInitError` - If the model cannot be loaded
+/// * `Cancelled` - If `config.options.cancel` is triggered
+/// * Otherwise the errors of processing a single image
+pub fn process_sequence(config: SequenceConfig) -> Result<SequenceSummary> {
+    if !(config.temporal_alpha > 0.0 && config.temporal_alpha <= 1.0) {
+        return Err(RemoveBgError::ProcessingError(format!(
+            "Temporal alpha must be above 0 and at most 1, got {}",
+            config.temporal_alpha
+        )));
+    }
+    if config.temporal_window == 0 {
+        return Err(RemoveBgError::ProcessingError("Temporal window must be at least 1 frame".into()));
+    }
+    check_naming(&config.options.naming)?;
+    let frames = list_frames(&config.inputs)?;
+    std::fs::create_dir_all(&config.output_dir)?;
+
+    let options = &config.options;
+    let format = output::selected_format(options, OutputFormat::default());
+    let remover = BackgroundRemover::new(options.clone())?;
+
+    let mut smoother = Smoother::new(&config);
+    // An automatic background color is picked on the first frame and kept,
+    // so the backdrop doesn't change from frame to frame
+    let mut fixed = None;
+    let mut summary = SequenceSummary::default();
+    let started = Instant::now();
+    config.progress.report(ProgressEvent::Queued { count: frames.len() });
+    for (index, frame) in frames.iter().enumerate() {
+        options.cancel.check()?;
+        let output = output_path(frame, &config.output_dir, format, options);
+        config.progress.report(ProgressEvent::Started { input: frame });
+        let report = match remove_frame(
+            frame,
+            &output,
+            fixed.as_ref().unwrap_or(options),
+            &remover,
+            |image, mask| smoother.smooth(image, mask),
+        ) {
+            Ok(report) => report,
+            Err(RemoveBgError::Cancelled) => return Err(RemoveBgError::Cancelled),
+            Err(e) => {
+                config.progress.report(ProgressEvent::Failed { input: frame, error: &e });
+                return Err(e);
+            }
+        };
+        if let (Background::Auto, None, Some(color)) = (options.background, &fixed, report.background_color) {
+            fixed = Some(RemoveBgOptions { background: Background::Color(color), ..options.clone() });
+        }
+
+        summary.frames += 1;
+        summary.elapsed = started.elapsed();
+        config.progress.report(ProgressEvent::Processed { input: frame, report: &report });
+        log::info!(
+            target: LOG_TARGET,
+            "[{}/{}] {} -> {} ({:.1} frames/s)",
+            index + 1,
+            frames.len(),
+            frame.display(),
+            report.output_path.display(),
+            summary.frames_per_second()
+        );
+    }
+    summary.scene_cuts = smoother.scene_cuts;
+    Ok(summary)
+}
+
+/// Expand the inputs into frame paths sorted by frame number.
+fn list_frames(inputs: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut frames = Vec::new();
+    for input in inputs {
+        let name = input.file_name().unwrap_or_default().to_string_lossy();
+        if !name.contains(['*', '?']) {
+            frames.push(input.clone());
+            continue;
+        }
+        let dir = match input.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let before = frames.len();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let matches = path.file_name().is_some_and(|file| wildcard_match(&name, &file.to_string_lossy()));
+            if matches && path.is_file() && is_image_file(&path) {
+                frames.push(path);
+            }
+        }
+        if frames.len() == before {
+            return Err(RemoveBgError::FileNotFound(input.clone()));
+        }
+    }
+    if frames.is_empty() {
+        return Err(RemoveBgError::ProcessingError("No frames given".into()));
+    }
+    frames.sort_by_cached_key(|path| (frame_number(path), path.clone()));
+    frames.dedup();
+    Ok(frames)
+}
+
+/// The last run of digits in the file stem, e.g. 12 for `frame_00012.png`.
+fn frame_number(path: &Path) -> Option<u64> {
+    let stem = path.file_stem()?.to_string_lossy();
+    let end = stem.rfind(|c: char| c.is_ascii_digit())? + 1;
+    let start = stem[..end].rfind(|c: char| !c.is_ascii_digit()).map_or(0, |i| i + 1);
+    stem[start..end].parse().ok()
+}
+
+/// Whether `name` matches `pattern`, where `*` matches any run of characters
+/// and `?` any single character.
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` and the name position it was tried at
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Blends each frame's mask with the masks of the frames before it.
+struct Smoother {
+    alpha: f32,
+    window: usize,
+    cut_threshold: f32,
+    /// Unsmoothed masks of the previous frames, newest last.
+    history: VecDeque<Gray16Image>,
+    /// Thumbnail of the previous frame, for scene cut detection.
+    previous: Option<GrayImage>,
+    scene_cuts: usize,
+}
+
+impl Smoother {
+    fn new(config: &SequenceConfig) -> Self {
+        Smoother {
+            alpha: config.temporal_alpha,
+            window: config.temporal_window,
+            cut_threshold: config.scene_cut_threshold,
+            history: VecDeque::new(),
+            previous: None,
+            scene_cuts: 0,
+        }
+    }
+
+    /// Replace `mask`, the unsmoothed mask of `image`, with the weighted
+    /// average of it and the masks of the previous frames in the window.
+    fn smooth(&mut self, image: &DynamicImage, mask: &mut Gray16Image) {
+        let thumbnail = image.resize_exact(CUT_THUMBNAIL_SIZE, CUT_THUMBNAIL_SIZE, FilterType::Triangle).to_luma8();
+        if let Some(previous) = self.previous.replace(thumbnail) {
+            let current = self.previous.as_ref().expect("just set");
+            let resized = self.history.back().is_some_and(|last| last.dimensions() != mask.dimensions());
+            if resized || frame_difference(&previous, current) > self.cut_threshold {
+                log::debug!("scene cut detected; mask smoothing starts over");
+                self.history.clear();
+                self.scene_cuts += 1;
+            }
+        }
+
+        self.history.push_back(mask.clone());
+        while self.history.len() > self.window {
+            self.history.pop_front();
+        }
+        if self.history.len() < 2 {
+            return;
+        }
+
+        // Weights from the newest mask back: alpha, alpha(1-alpha), ...
+        let weights: Vec<f32> = (0..self.history.len() as i32).map(|age| self.alpha * (1.0 - self.alpha).powi(age)).collect();
+        let total: f32 = weights.iter().sum();
+        for (i, value) in mask.iter_mut().enumerate() {
+            let sum: f32 = self.history.iter().rev().zip(&weights).map(|(m, w)| m.as_raw()[i] as f32 * w).sum();
+            *value = (sum / total).round() as u16;
+        }
+    }
+}
+
+/// Mean absolute difference of two thumbnails, 0.0-1.0.
+fn frame_difference(a: &GrayImage, b: &GrayImage) -> f32 {
+    let sum: u64 = a.iter().zip(b.iter()).map(|(&x, &y)| x.abs_diff(y) as u64).sum();
+    sum as f32 / (a.len().max(1) as f32 * 255.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    #[test]
+    fn wildcards_match_whole_names() {
+        assert!(wildcard_match("frame_*.png", "frame_12.png"));
+        assert!(wildcard_match("frame_*.png", "frame_.png"));
+        assert!(wildcard_match("shot_???.png", "shot_007.png"));
+        assert!(wildcard_match("*_*.png", "a_b_c.png"));
+        assert!(!wildcard_match("frame_*.png", "frame_12.png.bak"));
+        assert!(!wildcard_match("shot_???.png", "shot_0007.png"));
+        assert!(!wildcard_match("frame_*.png", "other_1.png"));
+    }
+
+    #[test]
+    fn frames_are_ordered_by_their_last_number() {
+        assert_eq!(frame_number(Path::new("take2/frame_00012.png")), Some(12));
+        assert_eq!(frame_number(Path::new("clip3_frame10.png")), Some(10));
+        assert_eq!(frame_number(Path::new("poster.png")), None);
+
+        let dir = std::env::temp_dir().join(format!("removebg-sequence-order-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        // Without padding, name order (1, 10, 2) differs from frame order
+        for name in ["frame_1.png", "frame_10.png", "frame_2.png", "notes.txt"] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+        let frames = list_frames(&[dir.join("frame_*.png"), dir.join("frame_2.png")]).unwrap();
+        let names: Vec<_> = frames.iter().map(|frame| frame.file_name().unwrap().to_string_lossy().into_owned()).collect();
+        assert_eq!(names, ["frame_1.png", "frame_2.png", "frame_10.png"]);
+
+        assert!(matches!(list_frames(&[dir.join("shot_*.png")]), Err(RemoveBgError::FileNotFound(_))));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn smoother(alpha: f32, window: usize) -> Smoother {
+        let config = SequenceConfig { temporal_alpha: alpha, temporal_window: window, ..SequenceConfig::new(Vec::new(), "out") };
+        Smoother::new(&config)
+    }
+
+    #[test]
+    fn masks_are_blended_with_the_previous_frames() {
+        let frame = DynamicImage::ImageLuma8(GrayImage::from_pixel(8, 8, Luma([100])));
+        let mut smoother = smoother(0.5, 3);
+        let mut smoothed = Vec::new();
+        for value in [0, 60000, 0, 60000] {
+            let mut mask = Gray16Image::from_pixel(8, 8, Luma([value]));
+            smoother.smooth(&frame, &mut mask);
+            smoothed.push(mask.get_pixel(0, 0)[0]);
+        }
+        // Weights 0.5, 0.25, 0.125 from the newest mask back, normalized
+        assert_eq!(smoothed, [0, 40000, 17143, 42857]);
+        assert_eq!(smoother.scene_cuts, 0);
+    }
+
+    #[test]
+    fn scene_cuts_start_the_blending_over() {
+        let mut smoother = smoother(0.5, 4);
+        for (shade, value) in [(30, 60000), (30, 60000), (220, 0), (220, 0)] {
+            let frame = DynamicImage::ImageLuma8(GrayImage::from_fn(16, 16, |x, _| Luma([if x < 8 { shade } else { 120 }])));
+            let mut mask = Gray16Image::from_pixel(16, 16, Luma([value]));
+            smoother.smooth(&frame, &mut mask);
+            // Nothing of the masks before the cut carries over
+            assert_eq!(mask.get_pixel(3, 3)[0], value);
+        }
+        assert_eq!(smoother.scene_cuts, 1);
+    }
+}
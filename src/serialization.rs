@@ -0,0 +1,84 @@
+//! Serde support for options, reports and errors (`serde` feature).
+//!
+//! Enums that have a command-line spelling are written as that string and
+//! read back with their `FromStr` implementation, so `"u2netp"`, `"#ffffff"`
+//! or `"mean:3"` mean the same in a stored job as on the command line. Every
+//! other type derives its implementation where it is defined. Durations are
+//! written as floating-point milliseconds in fields ending in `_ms`.
+
+use crate::model::Model;
+use crate::options::{Backend, Background, Collision, Device, Fusion, InferenceSize, MaskFilter, MaskFormat, MaskOp, OptimizationLevel, OutputKind, Pages};
+use crate::output::{OutputFormat, PngCompression, PngFilter};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// Serialize and deserialize each type through its `Display` and `FromStr`.
+macro_rules! as_string {
+    ($($ty:ty),* $(,)?) => {$(
+        impl Serialize for $ty {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $ty {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+            }
+        }
+    )*};
+}
+
+as_string!(
+    Model,
+    Device,
+    Backend,
+    OptimizationLevel,
+    Fusion,
+    MaskFilter,
+    MaskFormat,
+    MaskOp,
+    Background,
+    OutputKind,
+    Collision,
+    Pages,
+    InferenceSize,
+    OutputFormat,
+    PngCompression,
+    PngFilter,
+);
+
+/// A [`Duration`](std::time::Duration) as floating-point milliseconds, for
+/// `#[serde(with = "millis")]`.
+pub(crate) mod millis {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub(crate) fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(duration.as_secs_f64() * 1000.0)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let millis = f64::deserialize(deserializer)?;
+        Duration::try_from_secs_f64(millis / 1000.0).map_err(de::Error::custom)
+    }
+}
+
+/// An optional [`Duration`](std::time::Duration) as floating-point
+/// milliseconds or `null`, for `#[serde(with = "optional_millis")]`.
+pub(crate) mod optional_millis {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub(crate) fn serialize<S: Serializer>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => super::millis::serialize(duration, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+        #[derive(Deserialize)]
+        struct Millis(#[serde(with = "super::millis")] Duration);
+        Ok(Option::<Millis>::deserialize(deserializer)?.map(|Millis(duration)| duration))
+    }
+}
@@ -0,0 +1,328 @@
+//! HTTP server exposing background removal as a service.
+//!
+//! Available with the `server` cargo feature and used by `removebg serve`.
+//!
+//! # Endpoints
+//! - `POST /remove` - request body is an encoded image; responds with the cutout
+//!   (PNG unless the `format` query parameter selects another output format).
+//!   With [`quality_fallback`](crate::RemoveBgOptions::quality_fallback),
+//!   the `X-Quality-Downgrades` header lists the
+//!   [`Downgrade`](crate::Downgrade)s made to meet the deadline, separated
+//!   by commas, or is `none`
+//! - `GET /health` - `{"status":"ok"}` once the model is loaded
+//! - `GET /version` - `{"version":"..."}`
+
+use crate::core::{self, prepare_model, shared_remover};
+use crate::error::{RemoveBgError, Result};
+use crate::fallback;
+use crate::options::RemoveBgOptions;
+use crate::output::OutputFormat;
+use crate::report::ModelInfo;
+use std::io::Read;
+use std::sync::Arc;
+use std::time::Duration;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+/// Timeout per request in [`ServerConfig::default`] and `removebg serve`.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Settings for [`serve`].
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Address to listen on.
+    pub host: String,
+    /// Port to listen on; 0 picks a free port.
+    pub port: u16,
+    /// Largest accepted request body in bytes.
+    pub max_body_size: usize,
+    /// Number of threads handling requests; by default
+    /// [`default_workers`].
+    pub workers: usize,
+    /// Options used for every request; `format` is the default output format.
+    /// By default they have a `timeout` of [`DEFAULT_TIMEOUT`], and requests
+    /// running longer get status 503.
+    pub options: RemoveBgOptions,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            max_body_size: 20 * 1024 * 1024,
+            workers: default_workers(),
+            options: RemoveBgOptions { timeout: Some(DEFAULT_TIMEOUT), ..Default::default() },
+        }
+    }
+}
+
+/// Request handler threads when none are configured: 4, or one per CPU
+/// the process may use if that is fewer (see [`crate::cpu`]).
+pub fn default_workers() -> usize {
+    crate::cpu::available_parallelism().min(4)
+}
+
+/// Run the HTTP server until the process exits.
+///
+/// The model is loaded before the server starts listening, so the first
+/// request is not slowed down by the download or session creation, nor, with
+/// `options.warmup`, by the engine's first run. All worker threads share the
+/// same model session. With `options.quality_fallback`, u2netp is loaded
+/// too, and the warm-up runs calibrate the deadline's estimates.
+///
+/// # Errors
+/// * `ModelInitError` - If a model cannot be prepared
+/// * `ProcessingError` - If the server cannot bind to the address
+pub fn serve(config: ServerConfig) -> Result<()> {
+    let server = bind(&config)?;
+    run(server, config);
+    Ok(())
+}
+
+/// Prepare the models of `config` and listen on its address.
+fn bind(config: &ServerConfig) -> Result<Server> {
+    let fallback = fallback::fallback_options(&config.options);
+    for options in std::iter::once(&config.options).chain(&fallback) {
+        prepare_model(options)?;
+        if options.warmup {
+            let (remover, _) = shared_remover(options)?;
+            log::info!("Warmed up {} in {:.1}s", options.model, remover.warmup_duration().as_secs_f64());
+        }
+    }
+
+    let address = format!("{}:{}", config.host, config.port);
+    let server = Server::http(&address).map_err(|e| {
+        RemoveBgError::ProcessingError(format!("Failed to listen on {}: {}", address, e))
+    })?;
+    log::info!("Listening on http://{}", server.server_addr());
+    Ok(server)
+}
+
+/// Answer requests on `config.workers` threads until the server stops.
+fn run(server: Server, config: ServerConfig) {
+    let server = Arc::new(server);
+    let config = Arc::new(config);
+    let workers: Vec<_> = (0..config.workers.max(1))
+        .map(|_| {
+            let server = Arc::clone(&server);
+            let config = Arc::clone(&config);
+            std::thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    handle(request, &config);
+                }
+            })
+        })
+        .collect();
+
+    for worker in workers {
+        let _ = worker.join();
+    }
+}
+
+/// Route a request and send the response.
+fn handle(mut request: Request, config: &ServerConfig) {
+    let (path, query) = match request.url().split_once('?') {
+        Some((path, query)) => (path.to_string(), query.to_string()),
+        None => (request.url().to_string(), String::new()),
+    };
+
+    let result = match (request.method(), path.as_str()) {
+        (Method::Get, "/health") => Ok(json(200, serde_json::json!({ "status": "ok" }))),
+        (Method::Get, "/version") => Ok(json(200, serde_json::json!({ "version": crate::VERSION }))),
+        (Method::Post, "/remove") => remove(&mut request, &query, config),
+        (_, "/health" | "/version" | "/remove") => Ok(error(405, "method not allowed")),
+        _ => Ok(error(404, "not found")),
+    };
+
+    let response = result.unwrap_or_else(|e| error(status_for(&e), &e.to_string()));
+    log::debug!("{} {} -> {}", request.method(), path, response.status_code().0);
+    if let Err(e) = request.respond(response) {
+        log::warn!("Failed to send response: {}", e);
+    }
+}
+
+/// Handle `POST /remove`.
+fn remove(
+    request: &mut Request,
+    query: &str,
+    config: &ServerConfig,
+) -> Result<Response<std::io::Cursor<Vec<u8>>>> {
+    if request.body_length().is_some_and(|len| len > config.max_body_size) {
+        return Ok(too_large(config.max_body_size));
+    }
+
+    // Read one byte past the limit to detect oversized chunked bodies
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(config.max_body_size as u64 + 1)
+        .read_to_end(&mut body)?;
+    if body.len() > config.max_body_size {
+        return Ok(too_large(config.max_body_size));
+    }
+
+    let mut options = config.options.clone();
+    for (key, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+        if key == "format" {
+            let format = value.parse::<OutputFormat>().map_err(RemoveBgError::InvalidOutputFormat)?;
+            options.format = Some(format);
+        }
+    }
+
+    let (encoded, model) = core::remove_bytes_detailed(&body, &options, || shared_remover(&options))?;
+    let mime = options.format.unwrap_or_default().mime_type();
+    let mut response = Response::from_data(encoded).with_header(header("Content-Type", mime));
+    if options.quality_fallback.is_some() {
+        response.add_header(header("X-Quality-Downgrades", &downgrades_header(model.as_ref())));
+    }
+    Ok(response)
+}
+
+/// Value of the `X-Quality-Downgrades` header for a run of `model`.
+fn downgrades_header(model: Option<&ModelInfo>) -> String {
+    let names: Vec<&str> = model.iter().flat_map(|info| &info.downgrades).map(|downgrade| downgrade.name()).collect();
+    match names.is_empty() {
+        true => "none".to_string(),
+        false => names.join(","),
+    }
+}
+
+/// HTTP status code for a processing error.
+fn status_for(error: &RemoveBgError) -> u16 {
+    match error.root() {
+        RemoveBgError::ImageError(_)
+        | RemoveBgError::ImageTooSmall { .. }
+        | RemoveBgError::InvalidRawLayout { .. }
+        | RemoveBgError::RawBufferTooSmall { .. }
+        | RemoveBgError::InvalidOutputFormat(_) => 400,
+        RemoveBgError::ImageTooLarge { .. } | RemoveBgError::MemoryBudgetExceeded { .. } => 413,
+        RemoveBgError::UnsupportedFormat { .. } => 415,
+        RemoveBgError::LowConfidence { .. } => 422,
+        RemoveBgError::TimedOut { .. } => 503,
+        RemoveBgError::InsufficientDiskSpace { .. } => 507,
+        _ => 500,
+    }
+}
+
+fn too_large(limit: usize) -> Response<std::io::Cursor<Vec<u8>>> {
+    error(413, &format!("request body exceeds {} bytes", limit))
+}
+
+fn error(status: u16, message: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    json(status, serde_json::json!({ "error": message }))
+}
+
+fn json(status: u16, body: serde_json::Value) -> Response<std::io::Cursor<Vec<u8>>> {
+    Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(header("Content-Type", "application/json"))
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("static header is valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Model;
+    use crate::options::QualityFallback;
+    use crate::testing;
+    use image::DynamicImage;
+    use std::io::Write;
+    use std::net::{SocketAddr, TcpStream};
+
+    /// Send one request to `address` and return the status, the headers and
+    /// the body of the response.
+    fn send(address: SocketAddr, method: &str, path: &str, body: &[u8]) -> (u16, String, Vec<u8>) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n", method, path).unwrap();
+        write!(stream, "Content-Length: {}\r\n\r\n", body.len()).unwrap();
+        stream.write_all(body).unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+
+        let end = response.windows(4).position(|window| window == b"\r\n\r\n").expect("response has a head");
+        let head = String::from_utf8(response[..end].to_vec()).unwrap();
+        let status = head.split(' ').nth(1).and_then(|code| code.parse().ok()).expect("status line");
+        (status, head, response[end + 4..].to_vec())
+    }
+
+    fn json_body(body: &[u8]) -> serde_json::Value {
+        serde_json::from_slice(body).expect("body is JSON")
+    }
+
+    #[test]
+    fn requests_round_trip_on_an_ephemeral_port() {
+        let config = ServerConfig {
+            port: 0,
+            max_body_size: 64 * 1024,
+            workers: 2,
+            options: testing::TestModel::Brightness.options(RemoveBgOptions::default()),
+            ..Default::default()
+        };
+        let server = bind(&config).unwrap();
+        let address = server.server_addr().to_ip().expect("listening on TCP");
+        std::thread::spawn(move || run(server, config));
+
+        let (status, _, body) = send(address, "GET", "/health", b"");
+        assert_eq!((status, json_body(&body)), (200, serde_json::json!({ "status": "ok" })));
+        let (status, _, body) = send(address, "GET", "/version", b"");
+        assert_eq!((status, json_body(&body)), (200, serde_json::json!({ "version": crate::VERSION })));
+
+        let mut png = std::io::Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(testing::synthetic_image(5, 64, 48)).write_to(&mut png, image::ImageFormat::Png).unwrap();
+        let (status, head, body) = send(address, "POST", "/remove", png.get_ref());
+        assert_eq!(status, 200, "{}", String::from_utf8_lossy(&body));
+        assert!(head.to_ascii_lowercase().contains("content-type: image/png"), "{}", head);
+        let cutout = image::load_from_memory(&body).unwrap();
+        assert_eq!((cutout.width(), cutout.height()), (64, 48));
+        assert!(cutout.color().has_alpha());
+
+        let (status, _, body) = send(address, "GET", "/nowhere", b"");
+        assert_eq!((status, json_body(&body)), (404, serde_json::json!({ "error": "not found" })));
+        let (status, _, body) = send(address, "GET", "/remove", b"");
+        assert_eq!((status, json_body(&body)), (405, serde_json::json!({ "error": "method not allowed" })));
+        let (status, _, body) = send(address, "POST", "/remove", &vec![0; 64 * 1024 + 1]);
+        assert_eq!(status, 413);
+        assert_eq!(json_body(&body), serde_json::json!({ "error": "request body exceeds 65536 bytes" }));
+    }
+
+    #[test]
+    fn error_bodies_stay_valid_json() {
+        let message = "decoder failed:\n\tline \"2\"\\\u{1}";
+        let mut body = String::new();
+        error(500, message).into_reader().read_to_string(&mut body).unwrap();
+        assert_eq!(json_body(body.as_bytes()), serde_json::json!({ "error": message }));
+    }
+
+    #[test]
+    fn downgrades_are_named_in_the_header() {
+        // The stub model under both names, with u2net measured as far too slow
+        let dir = std::env::temp_dir().join(format!("removebg-server-downgrades-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let stub = testing::TestModel::Brightness.dir().join("u2netp.onnx");
+        for model in [Model::U2net, Model::U2netp] {
+            std::fs::copy(&stub, dir.join(model.spec().file_name)).unwrap();
+        }
+        let deadline = |millis| Some(QualityFallback { deadline: Duration::from_millis(millis) });
+        let options =
+            RemoveBgOptions { model: Model::U2net, model_dir: Some(dir.clone()), two_pass: true, quality_fallback: deadline(500), ..Default::default() };
+        for model in [Model::U2net, Model::U2netp] {
+            fallback::calibration().set(model, options.backend, options.device, Duration::from_secs(10));
+        }
+        let mut png = std::io::Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(testing::synthetic_image(2, 64, 48)).write_to(&mut png, image::ImageFormat::Png).unwrap();
+
+        let (_, model) = core::remove_bytes_detailed(png.get_ref(), &options, || shared_remover(&options)).unwrap();
+        assert_eq!(model.as_ref().map(|info| info.model), Some(Model::U2netp));
+        assert_eq!(downgrades_header(model.as_ref()), "model,refinement");
+
+        // Within a deadline of a minute nothing is given up
+        let options = RemoveBgOptions { quality_fallback: deadline(60_000), ..options };
+        let (_, model) = core::remove_bytes_detailed(png.get_ref(), &options, || shared_remover(&options)).unwrap();
+        assert_eq!(downgrades_header(model.as_ref()), "none");
+        assert_eq!(downgrades_header(None), "none");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,97 @@
+//! Recognizing inputs by their content rather than their extension.
+//!
+//! Inputs are decoded in the format their first bytes show, so a JPEG saved
+//! as `.png` or a download without an extension still works. When the bytes
+//! are not an image at all, [`describe`] names what they look like instead,
+//! for an error that says more than "failed to load image".
+
+/// What a file starting with `header` looks like when it is not an image
+/// the image crate recognizes, e.g. `"PDF"` or `"empty file"`; `None` for
+/// unrecognized binary data.
+pub(crate) fn describe(header: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"%PDF", "PDF"),
+        (b"%!PS", "PostScript"),
+        (b"PK\x03\x04", "ZIP archive"),
+        (b"\x1f\x8b", "gzip archive"),
+        (b"7z\xbc\xaf\x27\x1c", "7z archive"),
+        (b"Rar!", "RAR archive"),
+        (b"\x7fELF", "executable"),
+        (b"MZ", "executable"),
+        (b"ID3", "MP3 audio"),
+        (b"OggS", "Ogg media"),
+        (b"fLaC", "FLAC audio"),
+        (b"\x1a\x45\xdf\xa3", "Matroska/WebM video"),
+    ];
+
+    if header.is_empty() {
+        return Some("empty file");
+    }
+    if let Some(&(_, name)) = SIGNATURES.iter().find(|(magic, _)| header.starts_with(magic)) {
+        return Some(name);
+    }
+    if header.starts_with(b"RIFF") {
+        return match header.get(8..12) {
+            Some(b"WAVE") => Some("WAV audio"),
+            Some(b"AVI ") => Some("AVI video"),
+            _ => None,
+        };
+    }
+    // HEIF and AVIF are recognized before this, so other ISO media files are videos
+    if header.get(4..8) == Some(b"ftyp") {
+        return Some("MP4/QuickTime video");
+    }
+    describe_text(header)
+}
+
+/// The kind of text document `header` starts, if it is text.
+fn describe_text(header: &[u8]) -> Option<&'static str> {
+    // The header may end inside a multi-byte character
+    let text = match std::str::from_utf8(header) {
+        Ok(text) => text,
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&header[..e.valid_up_to()]).ok()?,
+        Err(_) => return None,
+    };
+    if text.chars().any(|c| c.is_control() && !c.is_whitespace()) {
+        return None;
+    }
+    let start = text.trim_start_matches('\u{feff}').trim_start().to_ascii_lowercase();
+    if start.starts_with("<!doctype html") || start.starts_with("<html") {
+        Some("HTML")
+    } else if start.contains("<svg") {
+        Some("SVG")
+    } else if start.starts_with("<?xml") {
+        Some("XML")
+    } else if start.starts_with('{') || start.starts_with('[') {
+        Some("JSON")
+    } else {
+        Some("text")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn documents_are_named() {
+        assert_eq!(describe(b""), Some("empty file"));
+        assert_eq!(describe(b"%PDF-1.7\n%\xe2\xe3\xcf\xd3\n"), Some("PDF"));
+        assert_eq!(describe(b"\xef\xbb\xbf  <!DOCTYPE html>\n<html lang=\"en\">"), Some("HTML"));
+        assert_eq!(describe(b"<HTML><BODY>Not Found</BODY></HTML>"), Some("HTML"));
+        assert_eq!(describe(b"<?xml version=\"1.0\"?>\n<svg xmlns=\"http://www.w3.org/2000/svg\">"), Some("SVG"));
+        assert_eq!(describe(b"{\"error\": \"not found\"}"), Some("JSON"));
+        assert_eq!(describe(b"RIFF\0\0\0\0WAVEfmt "), Some("WAV audio"));
+        assert_eq!(describe(b"\0\0\0\x20ftypisom"), Some("MP4/QuickTime video"));
+    }
+
+    #[test]
+    fn text_is_recognized_even_cut_off_mid_character() {
+        assert_eq!(describe(b"Photo credits: Jane Doe\r\n"), Some("text"));
+        // The header ends inside a two-byte character
+        assert_eq!(describe(b"caf\xc3"), Some("text"));
+        // Invalid UTF-8 and control characters are binary data
+        assert_eq!(describe(b"\xff\xfe\xfd\x00\x01"), None);
+        assert_eq!(describe(b"abc\x00def"), None);
+    }
+}
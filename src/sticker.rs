@@ -0,0 +1,141 @@
+//! Sticker mode: an outline and a drop shadow around the cutout.
+//!
+//! The subject's silhouette is grown by the outline width to form the
+//! outline, and the outlined shape is offset and blurred to form the shadow.
+//! Both are drawn behind the subject, so every layer keeps its soft alpha.
+
+use crate::error::{RemoveBgError, Result};
+use crate::matte::distance_transform;
+use crate::options::StickerOptions;
+use image::{DynamicImage, ImageBuffer, Luma, Rgba32FImage};
+
+/// Alpha at or above which a subject pixel belongs to the silhouette the
+/// outline is grown from.
+const SILHOUETTE_THRESHOLD: f32 = 0.5;
+
+/// Smallest alpha that survives 8-bit rounding; fainter pixels don't count
+/// when deciding how far the canvas has to grow.
+const VISIBLE_ALPHA: f32 = 0.5 / 255.0;
+
+/// Draw the outline and shadow described by `sticker` behind a cutout.
+///
+/// The cutout's alpha is taken as the subject mask. The result keeps the
+/// cutout's bit depth. Its canvas is the original one, grown on each side just
+/// enough to hold the parts of the outline and shadow that reach past it.
+pub(crate) fn render(cutout: &DynamicImage, sticker: &StickerOptions) -> Result<DynamicImage> {
+    validate(sticker)?;
+    let subject = cutout.to_rgba32f();
+    let (width, height) = subject.dimensions();
+    let (dx, dy) = sticker.shadow_offset;
+
+    // Margin that holds everything that can be drawn outside the subject
+    let blur_reach = (3.0 * sticker.shadow_blur).ceil() as u64;
+    let margin = sticker.outline_width as u64 + blur_reach + dx.unsigned_abs().max(dy.unsigned_abs()) as u64 + 1;
+    let canvas_size = |size: u32| {
+        u32::try_from(size as u64 + 2 * margin)
+            .map_err(|_| RemoveBgError::ProcessingError("Sticker outline and shadow are too large".into()))
+    };
+    let (canvas_width, canvas_height) = (canvas_size(width)?, canvas_size(height)?);
+    let margin = margin as u32;
+    let (cw, ch) = (canvas_width as usize, canvas_height as usize);
+
+    let mut alpha = vec![0.0f32; cw * ch];
+    for (x, y, pixel) in subject.enumerate_pixels() {
+        alpha[(y + margin) as usize * cw + (x + margin) as usize] = pixel[3];
+    }
+
+    // Outline: the silhouette grown by the outline width, antialiased at its
+    // rim. Distances run between pixel centers, half a pixel past the edge.
+    let outline = match sticker.outline_width {
+        0 => vec![0.0; cw * ch],
+        outline_width => {
+            let silhouette: Vec<bool> = alpha.iter().map(|&a| a >= SILHOUETTE_THRESHOLD).collect();
+            distance_transform(&silhouette, cw, ch)
+                .into_iter()
+                .map(|distance| (outline_width as f32 + 1.0 - distance).clamp(0.0, 1.0))
+                .collect()
+        }
+    };
+
+    // Shadow: the outlined subject, offset and blurred
+    let shadow = if sticker.shadow_opacity > 0.0 {
+        let shape = ImageBuffer::<Luma<f32>, Vec<f32>>::from_fn(canvas_width, canvas_height, |x, y| {
+            let (sx, sy) = (x as i64 - dx as i64, y as i64 - dy as i64);
+            if sx < 0 || sy < 0 || sx >= cw as i64 || sy >= ch as i64 {
+                return Luma([0.0]);
+            }
+            let i = sy as usize * cw + sx as usize;
+            Luma([alpha[i].max(outline[i])])
+        });
+        let shape = match sticker.shadow_blur {
+            sigma if sigma > 0.0 => image::imageops::fast_blur(&shape, sigma),
+            _ => shape,
+        };
+        shape.into_raw().into_iter().map(|a| a * sticker.shadow_opacity).collect()
+    } else {
+        vec![0.0; cw * ch]
+    };
+
+    // Back to front: shadow, outline, subject
+    let outline_color = sticker.outline_color.map(|c| c as f32 / 255.0);
+    let canvas = Rgba32FImage::from_fn(canvas_width, canvas_height, |x, y| {
+        let i = y as usize * cw + x as usize;
+        let [r, g, b] = outline_color;
+        let mut pixel = over([r, g, b, outline[i]], [0.0, 0.0, 0.0, shadow[i]]);
+        if let (Some(sx), Some(sy)) = (x.checked_sub(margin), y.checked_sub(margin)) {
+            if sx < width && sy < height {
+                pixel = over(subject.get_pixel(sx, sy).0, pixel);
+            }
+        }
+        image::Rgba(pixel)
+    });
+
+    // Keep the original canvas plus whatever was drawn outside it
+    let (mut left, mut top) = (margin, margin);
+    let (mut right, mut bottom) = (margin + width, margin + height);
+    for (x, y, pixel) in canvas.enumerate_pixels() {
+        if pixel[3] >= VISIBLE_ALPHA {
+            left = left.min(x);
+            top = top.min(y);
+            right = right.max(x + 1);
+            bottom = bottom.max(y + 1);
+        }
+    }
+    let cropped = image::imageops::crop_imm(&canvas, left, top, right - left, bottom - top).to_image();
+
+    let cropped = DynamicImage::ImageRgba32F(cropped);
+    Ok(match cutout {
+        DynamicImage::ImageRgba16(_) => DynamicImage::ImageRgba16(cropped.into_rgba16()),
+        _ => DynamicImage::ImageRgba8(cropped.into_rgba8()),
+    })
+}
+
+/// Reject settings that can't be rendered.
+fn validate(sticker: &StickerOptions) -> Result<()> {
+    if !(0.0..=1.0).contains(&sticker.shadow_opacity) {
+        return Err(RemoveBgError::ProcessingError(format!(
+            "Shadow opacity must be between 0 and 1, got {}",
+            sticker.shadow_opacity
+        )));
+    }
+    if !(sticker.shadow_blur.is_finite() && sticker.shadow_blur >= 0.0) {
+        return Err(RemoveBgError::ProcessingError(format!(
+            "Shadow blur must be a non-negative number, got {}",
+            sticker.shadow_blur
+        )));
+    }
+    Ok(())
+}
+
+/// Composite straight-alpha RGBA `top` over `bottom`.
+fn over(top: [f32; 4], bottom: [f32; 4]) -> [f32; 4] {
+    let alpha = top[3] + bottom[3] * (1.0 - top[3]);
+    if alpha <= 0.0 {
+        return [0.0; 4];
+    }
+    let mut pixel = [0.0, 0.0, 0.0, alpha];
+    for c in 0..3 {
+        pixel[c] = (top[c] * top[3] + bottom[c] * bottom[3] * (1.0 - top[3])) / alpha;
+    }
+    pixel
+}
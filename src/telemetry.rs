@@ -0,0 +1,52 @@
+//! Tracing spans and events of the pipeline (`tracing` feature).
+//!
+//! With the feature on, each stage of a run is a `tracing` span with the
+//! target `removebg`, nested under the caller's current span:
+//!
+//! * `process` - one input, with `input` (its path), or `bytes` for
+//!   in-memory input
+//! * `model_init` - loading a model, with `model`, `backend` and `device`
+//! * `download` - downloading a model file, with `model` and `url`,
+//!   followed by a `downloaded` event with `bytes`
+//! * `decode` - reading and decoding the input, with `input`, followed by a
+//!   `decoded` event with `width` and `height`
+//! * `preprocess` - resizing and normalizing for the model, with `width` and
+//!   `height` (or `batch`) and `input_size`
+//! * `inference` - the model run, with `model`, `device` and `batch`
+//! * `postprocess` - refining the mask and compositing the output, with
+//!   `width` and `height`
+//! * `encode` - writing the outputs, with `format` (and `output` for files),
+//!   followed by an `encoded` event with `bytes` for files
+//!
+//! Events with the same target report mask cache hits (`mask_cache_hit`) and
+//! fallbacks (`fallback`, with a `reason`): batched inference falling back
+//! to one image at a time, an unavailable execution provider, and reruns of
+//! `--model auto`. The crate's `log` messages stay log
+//! records; a subscriber that also converts those, like `tracing-subscriber`
+//! with its default `tracing-log` feature, shows both from one switch:
+//!
+//! ```text
+//! tracing_subscriber::fmt().with_env_filter("removebg=debug").init();
+//! ```
+//!
+//! Without the feature the macros expand to nothing, so no span is created
+//! and no field is evaluated; with it, spans cost a callsite check when no
+//! subscriber is installed.
+
+/// Enter a span named `$name` with the given fields until the end of the
+/// enclosing block. Give each span its own block: spans entered in one block
+/// nest, the later inside the earlier.
+macro_rules! enter_span {
+    ($name:literal $(, $($fields:tt)+)?) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!(target: "removebg", $name $(, $($fields)+)?).entered();
+    };
+}
+
+/// Emit an event named `$name` with the given fields in the current span.
+macro_rules! trace_event {
+    ($name:literal $(, $($fields:tt)+)?) => {
+        #[cfg(feature = "tracing")]
+        tracing::event!(name: $name, target: "removebg", tracing::Level::INFO, { $($($fields)+)? });
+    };
+}
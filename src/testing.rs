@@ -0,0 +1,360 @@
+//! Regression testing support: deterministic runs, intermediate dumps and
+//! golden mask comparison.
+//!
+//! Changes to preprocessing or mask post-processing can shift the output
+//! without anything failing. The golden tests in `tests/golden.rs` run the
+//! fixture images in `tests/fixtures/golden` through a stub model and compare
+//! their masks with checked-in golden masks using [`assert_mask_close`]; a
+//! mismatch writes a diff image to `target/golden-diffs`. The hidden
+//! `removebg --dump-intermediates DIR` flag writes the same intermediates
+//! for any input, with [`dump_intermediates`].
+//!
+//! `TestModel`, with the `testing` feature, points options and command
+//! lines at the stub models, so the test suite runs offline; tests of the
+//! real models are `#[ignore]`d.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use removebg::testing::{self, assert_mask_close};
+//! use removebg::{BackgroundRemover, RemoveBgOptions};
+//!
+//! let remover = BackgroundRemover::new(testing::deterministic(RemoveBgOptions::default()))?;
+//! let image = image::open("photo.jpg")?;
+//! let mask = testing::intermediates(&remover, &image)?.mask_8bit();
+//! let expected = image::open("photo_mask.png")?.into_luma8();
+//! assert_mask_close(&mask, &expected, 0.002);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::core::{self, Gray16Image};
+use crate::error::{RemoveBgError, Result};
+use crate::matte;
+use crate::npy;
+use crate::options::RemoveBgOptions;
+use crate::paths;
+use crate::pipeline;
+use crate::remover::{BackgroundRemover, Segmenter};
+use crate::reproducible;
+use image::{DynamicImage, GenericImageView, GrayImage, ImageFormat, Rgb, RgbImage};
+use ndarray::{Array4, ArrayD, ArrayViewD};
+use std::fs::File;
+use std::io::BufWriter;
+use std::panic::Location;
+use std::path::{Path, PathBuf};
+
+/// Directory under the target directory that diff images are written to.
+const DIFF_DIR: &str = "golden-diffs";
+
+/// `options` with everything that can vary between runs pinned down, as
+/// with `--reproducible` (see [`reproducible::options`]).
+///
+/// Inference runs on one CPU thread, so floating-point sums are always
+/// taken in the same order, and images are processed one at a time. The
+/// resize filters of the pipeline are fixed, so nothing else needs to be set.
+pub fn deterministic(options: RemoveBgOptions) -> RemoveBgOptions {
+    reproducible::options(options)
+}
+
+/// A stub model checked in under `tests/fixtures/models`, loaded as u2netp
+/// through the same session, input detection and normalization as the real
+/// models, so nothing is downloaded. `make_stub.py` there writes both.
+///
+/// The stubs are found under the crate's source directory as it was built,
+/// so this is only available to the crate's own unit tests and, with the
+/// `testing` feature, to its integration tests and benches.
+#[cfg(any(test, feature = "testing"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TestModel {
+    /// Averages the normalized input channels into the mask, so the mask is
+    /// the image's brightness. The golden masks are made with it.
+    Brightness,
+    /// A soft blob in the middle of the image whose edge moves with the
+    /// image's content, in 0-1 like the real model's output.
+    CenterBlob,
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl TestModel {
+    /// Directory holding the stub as `u2netp.onnx`, for
+    /// [`RemoveBgOptions::model_dir`] or `--model-dir`.
+    pub fn dir(self) -> PathBuf {
+        let models = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/models");
+        match self {
+            TestModel::Brightness => models,
+            TestModel::CenterBlob => models.join("blob"),
+        }
+    }
+
+    /// `options` with the model replaced by the stub.
+    pub fn options(self, options: RemoveBgOptions) -> RemoveBgOptions {
+        RemoveBgOptions { model: crate::Model::U2netp, model_dir: Some(self.dir()), ..options }
+    }
+
+    /// A remover running the stub with [`deterministic`] `options`.
+    ///
+    /// # Errors
+    /// * `ModelInitError` - If the stub cannot be loaded
+    pub fn remover(self, options: RemoveBgOptions) -> Result<BackgroundRemover> {
+        BackgroundRemover::new(deterministic(self.options(options)))
+    }
+
+    /// The `removebg` arguments selecting the stub.
+    pub fn args(self) -> [std::ffi::OsString; 4] {
+        ["--model".into(), "u2netp".into(), "--model-dir".into(), self.dir().into()]
+    }
+}
+
+/// The stages of one model run on an image.
+#[derive(Debug, Clone)]
+pub struct Intermediates {
+    /// The preprocessed `(1, 3, size, size)` input tensor.
+    pub input: Array4<f32>,
+    /// The model's first output as returned, before the outputs are fused and
+    /// the mask curve is applied.
+    pub model_output: ArrayD<f32>,
+    /// The refined full-resolution mask, as it is applied to the image.
+    pub mask: Gray16Image,
+}
+
+impl Intermediates {
+    /// The final mask reduced to 8 bits, as stored in golden masks.
+    pub fn mask_8bit(&self) -> GrayImage {
+        DynamicImage::ImageLuma16(self.mask.clone()).into_luma8()
+    }
+}
+
+/// Run `remover`'s model on `image` once and keep each stage.
+///
+/// Tiling and the region of interest in the remover's options are not
+/// applied; the other mask refinements are.
+///
+/// # Errors
+/// * `ProcessingError` - If the remover runs a custom
+///   [`SegmentationModel`](crate::SegmentationModel), which has no tensors
+///   to dump, or the refinement options are invalid
+/// * `ModelError` - If model inference fails
+pub fn intermediates(remover: &BackgroundRemover, image: &DynamicImage) -> Result<Intermediates> {
+    let Segmenter::Builtin(runner) = remover.segmenter() else {
+        return Err(RemoveBgError::ProcessingError(
+            "intermediates are only available for the built-in models".into(),
+        ));
+    };
+    let options = remover.options();
+    matte::check_mask_curve(options)?;
+    let (size, _) = core::inference_size(runner.as_ref(), image, options)?;
+    let input = pipeline::preprocess_to(image, size, &options.model.spec().normalization);
+    let mut model_output = None;
+    let mut masks = runner.run(input.clone(), &mut |outputs, _| {
+        model_output = outputs.first().map(|output| output.to_owned());
+        core::masks_from_outputs(outputs, 1, options)
+    })?;
+    let model_output =
+        model_output.ok_or_else(|| RemoveBgError::ModelError("model returned no outputs".into()))?;
+
+    let mask = core::quantize_mask(&masks.remove(0));
+    let mut mask = pipeline::upscale_mask(&mask, image.dimensions(), options.mask_filter);
+    core::finish_mask(&mut mask, image, options)?;
+    Ok(Intermediates { input, model_output, mask })
+}
+
+/// Write the intermediates of `input_path` into `dir`, which is created if
+/// needed, and return the paths written:
+///
+/// * `<stem>.input.npy` - the preprocessed tensor
+/// * `<stem>.output.npy` - the model's first output
+/// * `<stem>.mask.png` - the final mask, 16-bit
+///
+/// The model runs with [`deterministic`] options.
+///
+/// # Errors
+/// * `FileNotFound` - If the input doesn't exist
+/// * `ModelInitError` - If the model cannot be loaded
+/// * Otherwise as [`intermediates`]
+pub fn dump_intermediates(
+    input_path: impl AsRef<Path>,
+    dir: &Path,
+    options: &RemoveBgOptions,
+) -> Result<Vec<PathBuf>> {
+    let input_path = input_path.as_ref();
+    let (image, _) = core::open_input(input_path, &options.limits)?;
+    let remover = BackgroundRemover::new(deterministic(options.clone()))?;
+    let stages = intermediates(&remover, &image)?;
+
+    std::fs::create_dir_all(dir)?;
+    let stem = input_path.file_stem().unwrap_or_default();
+    let path = |kind: &str| dir.join(paths::file_name([stem, format!(".{}", kind).as_ref()]));
+    let paths = vec![path("input.npy"), path("output.npy"), path("mask.png")];
+    write_npy(&paths[0], stages.input.view().into_dyn())?;
+    write_npy(&paths[1], stages.model_output.view())?;
+    stages.mask.save_with_format(&paths[2], ImageFormat::Png)?;
+    Ok(paths)
+}
+
+/// Write `array` as a little-endian float32 NumPy `.npy` file, readable with
+/// `numpy.load`.
+pub fn write_npy(path: &Path, array: ArrayViewD<'_, f32>) -> Result<()> {
+    npy::write_f32(BufWriter::new(File::create(path)?), array)?;
+    Ok(())
+}
+
+/// How far two masks of the same size are apart, in 0.0-1.0 of full scale.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaskDiff {
+    /// Mean absolute difference over all pixels.
+    pub mean_abs_diff: f32,
+    /// Largest absolute difference of any pixel.
+    pub max_abs_diff: f32,
+}
+
+/// Compare two masks pixel by pixel.
+///
+/// # Panics
+/// If the masks differ in size.
+pub fn mask_diff(actual: &GrayImage, expected: &GrayImage) -> MaskDiff {
+    assert_eq!(actual.dimensions(), expected.dimensions(), "masks differ in size");
+    let (sum, max) = actual.iter().zip(expected.iter()).fold((0u64, 0u8), |(sum, max), (&a, &b)| {
+        let diff = a.abs_diff(b);
+        (sum + diff as u64, max.max(diff))
+    });
+    MaskDiff {
+        mean_abs_diff: sum as f32 / (actual.len().max(1) as f32 * 255.0),
+        max_abs_diff: max as f32 / 255.0,
+    }
+}
+
+/// A picture of where two masks differ: the expected mask in dark gray, with
+/// pixels where `actual` is higher in red and lower in blue, brighter the
+/// larger the difference.
+pub fn diff_image(actual: &GrayImage, expected: &GrayImage) -> RgbImage {
+    RgbImage::from_fn(actual.width(), actual.height(), |x, y| {
+        let (a, b) = (actual.get_pixel(x, y)[0], expected.get_pixel(x, y)[0]);
+        let base = b / 4;
+        let amplified = |diff: u8| base.saturating_add(diff.saturating_mul(4));
+        if a > b {
+            Rgb([amplified(a - b), base, base])
+        } else if a < b {
+            Rgb([base, base, amplified(b - a)])
+        } else {
+            Rgb([base, base, base])
+        }
+    })
+}
+
+/// Assert that `actual` is within `max_mean_abs_diff` (0.0-1.0 of full
+/// scale, averaged over all pixels) of the golden mask `expected`.
+///
+/// On failure, the [`diff_image`] and the actual mask are written to
+/// `target/golden-diffs` (under `CARGO_TARGET_DIR` if set), named after the
+/// calling test file and line, before panicking.
+///
+/// # Panics
+/// If the masks differ in size or by more than `max_mean_abs_diff`.
+#[track_caller]
+pub fn assert_mask_close(actual: &GrayImage, expected: &GrayImage, max_mean_abs_diff: f32) {
+    let caller = Location::caller();
+    assert_eq!(
+        actual.dimensions(),
+        expected.dimensions(),
+        "mask is {:?} but the golden mask is {:?}",
+        actual.dimensions(),
+        expected.dimensions()
+    );
+    let diff = mask_diff(actual, expected);
+    if diff.mean_abs_diff <= max_mean_abs_diff {
+        return;
+    }
+
+    let target = std::env::var_os("CARGO_TARGET_DIR").map_or_else(|| PathBuf::from("target"), PathBuf::from);
+    let dir = target.join(DIFF_DIR);
+    let name = format!(
+        "{}_{}",
+        Path::new(caller.file()).file_stem().unwrap_or_default().to_string_lossy(),
+        caller.line()
+    );
+    let written = std::fs::create_dir_all(&dir)
+        .map_err(image::ImageError::IoError)
+        .and_then(|_| diff_image(actual, expected).save(dir.join(format!("{}.diff.png", name))))
+        .and_then(|_| actual.save(dir.join(format!("{}.actual.png", name))));
+    let note = match written {
+        Ok(()) => format!("diff written to {}", dir.join(format!("{}.diff.png", name)).display()),
+        Err(e) => format!("diff image could not be written: {}", e),
+    };
+    panic!(
+        "mask differs from the golden mask: mean difference {:.5} > {:.5} (max {:.3}); {}",
+        diff.mean_abs_diff, max_mean_abs_diff, diff.max_abs_diff, note
+    );
+}
+
+/// A deterministic test image for `seed`: a tilted ellipse in a random color
+/// on a random two-color gradient. The same seed always gives the same
+/// pixels, on every platform.
+pub fn synthetic_image(seed: u64, width: u32, height: u32) -> RgbImage {
+    let mut state = seed;
+    let mut next = move || {
+        // SplitMix64
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        (z ^ (z >> 31)) as f32 / u64::MAX as f32
+    };
+    let mut color = || Rgb([(next() * 255.0) as u8, (next() * 255.0) as u8, (next() * 255.0) as u8]);
+    let (top, bottom, subject) = (color(), color(), color());
+    let (w, h) = (width as f32, height as f32);
+    let (cx, cy) = (w * (0.3 + 0.4 * next()), h * (0.3 + 0.4 * next()));
+    let (rx, ry) = (w * (0.15 + 0.15 * next()), h * (0.15 + 0.15 * next()));
+    let (sin, cos) = (next() * std::f32::consts::PI).sin_cos();
+
+    RgbImage::from_fn(width, height, |x, y| {
+        let (dx, dy) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+        let (u, v) = (dx * cos + dy * sin, dy * cos - dx * sin);
+        if (u / rx).powi(2) + (v / ry).powi(2) <= 1.0 {
+            return subject;
+        }
+        let t = y as f32 / (h - 1.0).max(1.0);
+        Rgb(std::array::from_fn(|c| (top[c] as f32 * (1.0 - t) + bottom[c] as f32 * t).round() as u8))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    #[test]
+    fn mask_diff_measures_full_scale_fractions() {
+        let expected = GrayImage::from_pixel(4, 4, Luma([100]));
+        let mut actual = expected.clone();
+        assert_eq!(mask_diff(&actual, &expected), MaskDiff { mean_abs_diff: 0.0, max_abs_diff: 0.0 });
+        actual.put_pixel(0, 0, Luma([255]));
+        actual.put_pixel(1, 0, Luma([0]));
+        let diff = mask_diff(&actual, &expected);
+        assert!((diff.mean_abs_diff - 255.0 / (16.0 * 255.0)).abs() < 1e-6);
+        assert!((diff.max_abs_diff - 155.0 / 255.0).abs() < 1e-6);
+
+        let picture = diff_image(&actual, &expected);
+        assert_eq!(picture.get_pixel(0, 0)[0], 255);
+        assert_eq!(picture.get_pixel(1, 0)[2], 255);
+        assert_eq!(picture.get_pixel(2, 0).0, [25, 25, 25]);
+    }
+
+    #[test]
+    fn assert_mask_close_accepts_small_differences() {
+        let expected = GrayImage::from_fn(8, 8, |x, _| Luma([x as u8 * 30]));
+        let actual = GrayImage::from_fn(8, 8, |x, y| Luma([x as u8 * 30 + (x == y) as u8]));
+        assert_mask_close(&actual, &expected, 0.001);
+    }
+
+    #[test]
+    #[should_panic(expected = "mask differs from the golden mask")]
+    fn assert_mask_close_rejects_large_differences() {
+        assert_mask_close(&GrayImage::new(8, 8), &GrayImage::from_pixel(8, 8, Luma([255])), 0.5);
+    }
+
+    #[test]
+    fn synthetic_images_depend_only_on_the_seed() {
+        assert_eq!(synthetic_image(7, 40, 30), synthetic_image(7, 40, 30));
+        assert_ne!(synthetic_image(7, 40, 30), synthetic_image(8, 40, 30));
+    }
+}
@@ -0,0 +1,227 @@
+//! Tiled high-resolution inference for very large images.
+//!
+//! Squashing a 50 MP scan to the model's 320×320 input loses thin structures
+//! entirely. In tiled mode, overlapping tiles of the full-resolution image are
+//! each run through the model, and the tile masks are blended with weights
+//! that fade out across the overlaps so no seams show. A global low-resolution
+//! pass decides which tiles contain any subject at all; tiles that are
+//! entirely background reuse the global mask instead of running inference.
+//!
+//! Blending works on one band of tile rows at a time, so besides the output
+//! mask only one tile row's worth of accumulators is kept in memory.
+
+use crate::core::{generate_mask, Gray16Image};
+use crate::error::Result;
+use crate::options::{RemoveBgOptions, TileOptions};
+use crate::remover::BackgroundRemover;
+use crate::report::StageDurations;
+use image::{DynamicImage, GenericImageView, Luma};
+
+/// Tiles whose global mask never exceeds this alpha (8 of 255) are treated as background.
+const BACKGROUND_GATE: u16 = 8 * 257;
+
+/// Compute the subject mask of `image` tile by tile.
+///
+/// Time spent in each stage is accumulated into `durations`.
+pub(crate) fn generate_tiled_mask(
+    remover: &BackgroundRemover,
+    image: &DynamicImage,
+    tiling: TileOptions,
+    options: &RemoveBgOptions,
+    durations: &mut StageDurations,
+) -> Result<Gray16Image> {
+    // Global pass, used for gating and for the background tiles
+    let global = generate_mask(remover, image, options, durations)?;
+
+    let (width, height) = image.dimensions();
+    let size = tiling.size.max(options.model.spec().input_size);
+    if width <= size && height <= size {
+        return Ok(global);
+    }
+    let overlap = tiling.overlap.min(size / 2);
+    let xs = tile_starts(width, size, size - overlap);
+    let ys = tile_starts(height, size, size - overlap);
+    log::debug!("tiled inference: {}x{} tiles of {}px, {}px overlap", xs.len(), ys.len(), size, overlap);
+
+    let mut mask = Gray16Image::new(width, height);
+    let row_len = width as usize;
+    let band_rows = size.min(height) as usize;
+    // Weighted mask sums and weight totals for rows band_top..band_top + band_rows
+    let mut sums = vec![0f32; row_len * band_rows];
+    let mut weights = vec![0f32; row_len * band_rows];
+    let mut band_top = 0;
+    let (mut inferred, mut skipped) = (0, 0);
+
+    for (row, &y0) in ys.iter().enumerate() {
+        // Rows above this tile row receive no further contributions
+        flush_rows(&mut mask, &mut sums, &mut weights, band_top, y0);
+        band_top = y0;
+
+        let tile_height = size.min(height - y0);
+        let y_ramp = Ramp::new(&ys, row, size, tile_height);
+        for (column, &x0) in xs.iter().enumerate() {
+            options.cancel.check()?;
+            let tile_width = size.min(width - x0);
+            let x_ramp = Ramp::new(&xs, column, size, tile_width);
+
+            let global_tile = global.view(x0, y0, tile_width, tile_height);
+            let is_background = global_tile.pixels().all(|(_, _, p)| p[0] <= BACKGROUND_GATE);
+            let tile_mask = if is_background {
+                skipped += 1;
+                global_tile.to_image()
+            } else {
+                inferred += 1;
+                let tile = image.crop_imm(x0, y0, tile_width, tile_height);
+                let mut tile_durations = StageDurations::default();
+                let tile_mask = generate_mask(remover, &tile, options, &mut tile_durations)?;
+                durations.preprocess += tile_durations.preprocess;
+                durations.inference += tile_durations.inference;
+                durations.postprocess += tile_durations.postprocess;
+                tile_mask
+            };
+
+            for (x, y, pixel) in tile_mask.enumerate_pixels() {
+                let weight = x_ramp.weight(x) * y_ramp.weight(y);
+                let index = (y0 + y - band_top) as usize * row_len + (x0 + x) as usize;
+                sums[index] += pixel[0] as f32 * weight;
+                weights[index] += weight;
+            }
+        }
+    }
+    flush_rows(&mut mask, &mut sums, &mut weights, band_top, height);
+
+    log::debug!("tiled inference: {} tiles inferred, {} background tiles skipped", inferred, skipped);
+    Ok(mask)
+}
+
+/// Most model runs [`generate_tiled_mask`] makes on a `width`x`height`
+/// image: the global pass and one per tile, none of them skipped.
+pub(crate) fn max_runs(width: u32, height: u32, tiling: TileOptions, native: u32) -> u32 {
+    let size = tiling.size.max(native);
+    if width <= size && height <= size {
+        return 1;
+    }
+    let step = size - tiling.overlap.min(size / 2);
+    1 + (tile_starts(width, size, step).len() * tile_starts(height, size, step).len()) as u32
+}
+
+/// Start offsets of tiles covering `length` pixels, the last one flush with the end.
+fn tile_starts(length: u32, size: u32, step: u32) -> Vec<u32> {
+    if length <= size {
+        return vec![0];
+    }
+    let mut starts: Vec<u32> = (0..).map(|i| i * step).take_while(|&start| start + size < length).collect();
+    starts.push(length - size);
+    starts.dedup();
+    starts
+}
+
+/// Blend weight along one axis of a tile: rises across the overlap with the
+/// previous tile and falls across the overlap with the next one.
+struct Ramp {
+    length: u32,
+    rise: u32,
+    fall: u32,
+}
+
+impl Ramp {
+    fn new(starts: &[u32], index: usize, size: u32, length: u32) -> Self {
+        let start = starts[index];
+        let rise = match index {
+            0 => 0,
+            _ => starts[index - 1] + size - start,
+        };
+        let fall = starts.get(index + 1).map_or(0, |&next| start + length - next);
+        Ramp { length, rise, fall }
+    }
+
+    fn weight(&self, position: u32) -> f32 {
+        let ramp = |distance: u32, span: u32| match span {
+            0 => 1.0,
+            span => ((distance as f32 + 0.5) / span as f32).min(1.0),
+        };
+        ramp(position, self.rise) * ramp(self.length - 1 - position, self.fall)
+    }
+}
+
+/// Write finished rows `top..end` of the band into `mask` and shift the band
+/// so that it starts at `end`.
+fn flush_rows(mask: &mut Gray16Image, sums: &mut [f32], weights: &mut [f32], top: u32, end: u32) {
+    let row_len = mask.width() as usize;
+    let rows = (end - top) as usize;
+    for y in 0..rows {
+        for x in 0..row_len {
+            let index = y * row_len + x;
+            let value = if weights[index] > 0.0 { sums[index] / weights[index] } else { 0.0 };
+            mask.put_pixel(x as u32, top + y as u32, Luma([value.round().clamp(0.0, 65535.0) as u16]));
+        }
+    }
+
+    let shift = (rows * row_len).min(sums.len());
+    sums.copy_within(shift.., 0);
+    weights.copy_within(shift.., 0);
+    let keep = sums.len() - shift;
+    sums[keep..].fill(0.0);
+    weights[keep..].fill(0.0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestModel;
+
+    #[test]
+    fn tiles_cover_the_image_and_weights_sum_to_one() {
+        assert_eq!(tile_starts(300, 320, 256), [0]);
+        assert_eq!(tile_starts(1000, 320, 256), [0, 256, 512, 680]);
+        for (length, size, step) in [(1000, 320, 256), (641, 320, 300), (5000, 1024, 896)] {
+            let starts = tile_starts(length, size, step);
+            let ramps: Vec<Ramp> = (0..starts.len()).map(|i| Ramp::new(&starts, i, size, size)).collect();
+            for position in 0..length {
+                let total: f32 = starts
+                    .iter()
+                    .zip(&ramps)
+                    .filter(|(&start, _)| (start..start + size).contains(&position))
+                    .map(|(&start, ramp)| ramp.weight(position - start))
+                    .sum();
+                assert!((total - 1.0).abs() < 1e-5, "{} at {} of {}", total, position, length);
+            }
+        }
+    }
+
+    #[test]
+    fn tile_boundaries_show_no_seams() {
+        // The blob stub puts a blob in the middle of every tile, so
+        // neighboring tiles disagree wherever they overlap
+        let image = DynamicImage::ImageRgb8(image::RgbImage::from_fn(1000, 700, |x, y| {
+            let shade = 140.0 + 60.0 * (x as f32 / 90.0).sin() + 40.0 * (y as f32 / 70.0).cos();
+            image::Rgb([shade as u8, (shade * 0.9) as u8, (shade * 0.8) as u8])
+        }));
+        let tiling = TileOptions { size: 320, overlap: 64 };
+        let options = TestModel::CenterBlob.options(RemoveBgOptions { tiling: Some(tiling), ..Default::default() });
+        let remover = TestModel::CenterBlob.remover(options.clone()).unwrap();
+        let mask = generate_tiled_mask(&remover, &image, tiling, &options, &mut StageDurations::default()).unwrap();
+        assert_eq!(mask.dimensions(), (1000, 700));
+
+        // Without blending, a tile edge steps by most of the alpha range;
+        // blended, no step across one is steeper than the blobs' own edges
+        let step = |a: u16, b: u16| a.abs_diff(b);
+        let mut edges_x: Vec<u32> = tile_starts(1000, 320, 256).iter().flat_map(|&x| [x, x + 320]).collect();
+        let mut edges_y: Vec<u32> = tile_starts(700, 320, 256).iter().flat_map(|&y| [y, y + 320]).collect();
+        edges_x.retain(|&x| (1..1000).contains(&x));
+        edges_y.retain(|&y| (1..700).contains(&y));
+        let (mut inside, mut across) = (0, 0);
+        for (x, y, pixel) in mask.enumerate_pixels() {
+            if x > 0 {
+                let difference = step(pixel[0], mask.get_pixel(x - 1, y)[0]);
+                if edges_x.contains(&x) { across = across.max(difference) } else { inside = inside.max(difference) }
+            }
+            if y > 0 {
+                let difference = step(pixel[0], mask.get_pixel(x, y - 1)[0]);
+                if edges_y.contains(&y) { across = across.max(difference) } else { inside = inside.max(difference) }
+            }
+        }
+        assert!(inside > 0);
+        assert!(across <= inside + 64, "{} across tile edges, {} inside", across, inside);
+    }
+}
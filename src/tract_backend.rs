@@ -0,0 +1,229 @@
+//! Pure-Rust tract inference engine (`backend-tract` feature).
+//!
+//! tract runs the same ONNX model files as ONNX Runtime without a native
+//! library, which makes static musl builds and cross-compilation easy. It is
+//! several times slower than ONNX Runtime and always runs on the CPU.
+
+use crate::core::{self, Gray16Image, Gray32FImage};
+use crate::error::{RemoveBgError, Result};
+use crate::model;
+use crate::options::{Device, InferenceSize, RemoveBgOptions};
+use crate::report::StageDurations;
+use crate::segmentation::{self, ReadOutputs, Runner, SegmentationModel};
+use image::DynamicImage;
+use ndarray::{Array4, ArrayViewD, IxDyn};
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+use web_time::Instant;
+use tract_onnx::tract_hir::infer::Factoid;
+use tract_onnx::prelude::{
+    tvec, Datum, Framework, InferenceFact, InferenceModel, InferenceModelExt, IntoTValue, Tensor, TractResult, TypedModel,
+    TypedRunnableModel,
+};
+
+/// The built-in U2-Net family model run through tract, a pure-Rust
+/// inference engine.
+///
+/// [`BackgroundRemover::new`](crate::BackgroundRemover::new) uses this
+/// model when [`backend`](RemoveBgOptions::backend) is
+/// [`Backend::Tract`](crate::Backend::Tract). Images always run one at a time.
+pub struct TractU2Net {
+    options: RemoveBgOptions,
+    plan: Arc<TypedRunnableModel<TypedModel>>,
+    input_size: u32,
+    /// The model before optimization, kept to optimize it for other
+    /// [`InferenceSize`]s when `options` asks for them.
+    model: Option<InferenceModel>,
+    /// Plans optimized for sizes other than `input_size`, by size.
+    resized: Mutex<HashMap<u32, Arc<TypedRunnableModel<TypedModel>>>>,
+}
+
+impl TractU2Net {
+    /// Load and optimize the model selected by `options`, downloading it if
+    /// needed.
+    ///
+    /// The model file and cache directory are the same as for ONNX Runtime.
+    /// Session options specific to ONNX Runtime (device, thread counts,
+    /// optimization level) don't apply. On wasm32 there is no model cache, so
+    /// only embedded models load here; use [`from_bytes`](Self::from_bytes)
+    /// for the others.
+    ///
+    /// # Errors
+    /// * `ModelInitError` - If the model cannot be downloaded or tract cannot load it
+    pub fn new(options: &RemoveBgOptions) -> Result<Self> {
+        match model::embedded_model(options.model) {
+            Some(bytes) => {
+                log::debug!("loading embedded {} with tract", options.model);
+                Self::from_bytes(bytes, options)
+            }
+            None => Self::from_cache(options),
+        }
+    }
+
+    /// Load and optimize a model from the contents of its ONNX file.
+    ///
+    /// `options.model` must be the model the bytes hold, since its outputs
+    /// drive post-processing and its input size is used when the file
+    /// declares a dynamic one.
+    ///
+    /// # Errors
+    /// * `ModelInitError` - If tract cannot load the model
+    pub fn from_bytes(bytes: &[u8], options: &RemoveBgOptions) -> Result<Self> {
+        let model = tract_onnx::onnx().model_for_read(&mut Cursor::new(bytes)).map_err(init_error)?;
+        Self::optimize(model, options)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn from_cache(options: &RemoveBgOptions) -> Result<Self> {
+        let path = model::ensure_model(options.model, options.model_dir.as_deref(), &options.download, &options.download_progress, &options.cancel)?;
+        log::debug!("loading {} with tract", options.model);
+        let model = tract_onnx::onnx().model_for_path(path).map_err(init_error)?;
+        Self::optimize(model, options)
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn from_cache(options: &RemoveBgOptions) -> Result<Self> {
+        Err(RemoveBgError::ModelInitError(format!(
+            "model {} is not embedded; load it with TractU2Net::from_bytes on wasm32",
+            options.model
+        )))
+    }
+
+    fn optimize(model: InferenceModel, options: &RemoveBgOptions) -> Result<Self> {
+        if options.device != Device::Cpu {
+            log::warn!("the tract backend runs on the CPU only; ignoring device {}", options.device);
+        }
+
+        let input_size = Self::input_size(&model, options)?;
+        let started = Instant::now();
+        let kept = (options.inference_size != InferenceSize::Native).then(|| model.clone());
+        let plan = plan(model, input_size, false).map_err(init_error)?;
+        log::debug!("optimized {} for tract in {:?}", options.model, started.elapsed());
+
+        Ok(TractU2Net {
+            options: options.clone(),
+            plan: Arc::new(plan),
+            input_size,
+            model: kept,
+            resized: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The plan for `size`x`size` inputs, optimizing the model for that size
+    /// on first use.
+    fn plan_for(&self, size: u32) -> Result<Arc<TypedRunnableModel<TypedModel>>> {
+        if size == self.input_size {
+            return Ok(Arc::clone(&self.plan));
+        }
+        let model = self.model.as_ref().ok_or_else(|| {
+            RemoveBgError::ModelError(format!("{} was loaded for {}x{} inputs only", self.options.model, self.input_size, self.input_size))
+        })?;
+        let mut resized = self.resized.lock().map_err(|_| RemoveBgError::ModelError("tract plan lock poisoned".into()))?;
+        if let Some(plan) = resized.get(&size) {
+            return Ok(Arc::clone(plan));
+        }
+        let started = Instant::now();
+        let plan = Arc::new(plan(model.clone(), size, true).map_err(model_error)?);
+        log::debug!("optimized {} for {}x{} inputs in {:?}", self.options.model, size, size, started.elapsed());
+        resized.insert(size, Arc::clone(&plan));
+        Ok(plan)
+    }
+
+    /// Read the size of the model's first input, checking that it is a
+    /// float32 `(batch, 3, size, size)` image.
+    fn input_size(model: &InferenceModel, options: &RemoveBgOptions) -> Result<u32> {
+        let outlet = *model
+            .input_outlets()
+            .map_err(init_error)?
+            .first()
+            .ok_or_else(|| RemoveBgError::ModelInitError("model has no inputs".into()))?;
+        let name = &model.node(outlet.node).name;
+        let fact = model.input_fact(0).map_err(init_error)?;
+        if let Some(datum_type) = fact.datum_type.concretize().filter(|&datum_type| datum_type != f32::datum_type()) {
+            return Err(RemoveBgError::ModelInitError(format!(
+                "model input '{}' is {:?}; expected a float32 tensor",
+                name, datum_type
+            )));
+        }
+        let dims: Vec<Option<i64>> =
+            fact.shape.dims().map(|dim| dim.concretize().and_then(|dim| dim.to_i64().ok())).collect();
+        let size = segmentation::input_size(name, &dims, options.model)?;
+        log::debug!("model input '{}' takes {}x{} images", name, size, size);
+        Ok(size)
+    }
+}
+
+impl SegmentationModel for TractU2Net {
+    fn predict_mask(&self, image: &DynamicImage) -> Result<Gray16Image> {
+        core::infer_mask(self, image, &self.options, &mut StageDurations::default())
+    }
+}
+
+impl Runner for TractU2Net {
+    fn accepts_batch(&self, size: usize) -> bool {
+        // The plan is optimized for a batch of one
+        size == 1
+    }
+
+    fn input_size(&self) -> u32 {
+        self.input_size
+    }
+
+    fn accepts_size(&self, _size: u32) -> bool {
+        self.model.is_some()
+    }
+
+    fn run(&self, input: Array4<f32>, read: &mut ReadOutputs<'_>) -> Result<Vec<Gray32FImage>> {
+        let input = input.as_standard_layout();
+        let data = input
+            .as_slice()
+            .ok_or_else(|| RemoveBgError::ModelError("input tensor is not contiguous".into()))?;
+        let tensor = Tensor::from_shape(input.shape(), data).map_err(model_error)?;
+        let plan = self.plan_for(input.shape()[2] as u32)?;
+
+        let started = Instant::now();
+        let outputs = plan.run(tvec!(tensor.into_tvalue())).map_err(model_error)?;
+        let elapsed = started.elapsed();
+
+        let arrays = outputs
+            .iter()
+            .map(|output| {
+                let data = output.as_slice::<f32>().map_err(model_error)?;
+                ArrayViewD::from_shape(IxDyn(output.shape()), data)
+                    .map_err(|e| RemoveBgError::ModelError(e.to_string()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        read(&arrays, elapsed)
+    }
+}
+
+impl std::fmt::Debug for TractU2Net {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TractU2Net").field("model", &self.options.model).finish_non_exhaustive()
+    }
+}
+
+/// Optimize `model` for `size`x`size` inputs. With `resized`, the output
+/// shapes the model file declares for its own size are dropped and inferred
+/// from the input instead.
+fn plan(mut model: InferenceModel, size: u32, resized: bool) -> TractResult<TypedRunnableModel<TypedModel>> {
+    if resized {
+        for output in 0..model.output_outlets()?.len() {
+            model.set_output_fact(output, InferenceFact::default())?;
+        }
+    }
+    let size = size as usize;
+    model
+        .with_input_fact(0, InferenceFact::dt_shape(f32::datum_type(), [1, 3, size, size]))?
+        .into_optimized()?
+        .into_runnable()
+}
+
+fn init_error(e: tract_onnx::prelude::TractError) -> RemoveBgError {
+    RemoveBgError::ModelInitError(format!("{:#}", e))
+}
+
+fn model_error(e: tract_onnx::prelude::TractError) -> RemoveBgError {
+    RemoveBgError::ModelError(format!("{:#}", e))
+}
@@ -0,0 +1,250 @@
+//! Mask upscaling, in horizontal bands for panoramas and other huge outputs.
+//!
+//! `image::imageops::resize` builds the whole resized mask in 32-bit floats
+//! before it is quantized, so upscaling the model's 320×320 mask to a
+//! 20000×5000 panorama holds 400 MB of floats next to the 200 MB 16-bit
+//! result. Large outputs are resized here instead, band by band of output
+//! rows, straight into the 16-bit mask: each band takes only the few mask
+//! rows it needs, and bands run in parallel (see [`rows`]). The filters
+//! follow the image crate's resampling (the same kernels, supports and
+//! vertical-then-horizontal order), so results match it to within one
+//! 16-bit level away from the borders.
+//!
+//! At the borders the image crate drops the taps that fall outside the
+//! source and scales up the rest, which extrapolates the outermost mask rows
+//! and columns and left a faint frame around cutouts. Taps outside the mask
+//! repeat its edge sample here instead, as if the mask were padded by
+//! replicating its border, so the filter never invents values. Smaller
+//! masks are resized here too, into floats, so every output has the same
+//! borders.
+
+use crate::core::{Channel, Gray32FImage};
+use crate::error::{RemoveBgError, Result};
+use crate::options::{CancellationToken, MaskFilter};
+use crate::rows;
+use image::{ImageBuffer, Luma};
+
+/// Outputs with at most this many pixels are resized in one piece, the
+/// simple path for all but panoramas and the like.
+const SINGLE_PASS_PIXELS: u64 = 1 << 22;
+
+/// Output pixels per band, about 2 MB of 16-bit mask.
+const BAND_PIXELS: u32 = 1 << 20;
+
+/// Height of the bands to resize a mask to `(width, height)` in, or `None`
+/// for an output small enough to resize in one piece. Any output is banded
+/// with `force`, as for a memory budget (see [`memory`](crate::memory)).
+pub(crate) fn band_height((width, height): (u32, u32), force: bool) -> Option<u32> {
+    if !force && width as u64 * height as u64 <= SINGLE_PASS_PIXELS {
+        return None;
+    }
+    Some((BAND_PIXELS / width.max(1)).clamp(1, height.max(1)))
+}
+
+/// The source samples one output sample is made from: `weights` for the
+/// samples from `start` on.
+struct Taps {
+    start: usize,
+    weights: Vec<f32>,
+}
+
+/// Kernel and support (in source samples, before any downscaling) of
+/// `filter`, as in `image::imageops::resize`.
+fn kernel(filter: MaskFilter) -> (fn(f32) -> f32, f32) {
+    match filter {
+        MaskFilter::Nearest => (|_| 1.0, 0.0),
+        MaskFilter::Triangle => (|x| (1.0 - x.abs()).max(0.0), 1.0),
+        MaskFilter::CatmullRom => (catmull_rom, 2.0),
+        MaskFilter::Lanczos3 => (lanczos3, 3.0),
+    }
+}
+
+fn catmull_rom(x: f32) -> f32 {
+    // Mitchell-Netravali with B = 0 and C = 0.5
+    let a = x.abs();
+    let k = if a < 1.0 {
+        9.0 * a.powi(3) - 15.0 * a.powi(2) + 6.0
+    } else if a < 2.0 {
+        -3.0 * a.powi(3) + 15.0 * a.powi(2) - 24.0 * a + 12.0
+    } else {
+        0.0
+    };
+    k / 6.0
+}
+
+fn lanczos3(x: f32) -> f32 {
+    let sinc = |t: f32| if t == 0.0 { 1.0 } else { (t * std::f32::consts::PI).sin() / (t * std::f32::consts::PI) };
+    if x.abs() < 3.0 {
+        sinc(x) * sinc(x / 3.0)
+    } else {
+        0.0
+    }
+}
+
+/// The taps of each of `dst` output samples resampled from `src` samples.
+/// Taps before the first sample or after the last are folded onto it.
+fn taps(src: u32, dst: u32, filter: MaskFilter) -> Vec<Taps> {
+    let (kernel, support) = kernel(filter);
+    let ratio = src as f32 / dst as f32;
+    let scale = ratio.max(1.0);
+    let support = support * scale;
+    let last = src as i64 - 1;
+    (0..dst)
+        .map(|out| {
+            let center = (out as f32 + 0.5) * ratio;
+            let left = (center - support).floor() as i64;
+            let right = ((center + support).ceil() as i64).max(left + 1);
+            let (first, end) = (left.clamp(0, last), (right - 1).clamp(0, last));
+            // The kernel is centered on the middle of a source sample
+            let center = center - 0.5;
+            let mut weights = vec![0.0f32; (end - first + 1) as usize];
+            for i in left..right {
+                weights[(i.clamp(first, end) - first) as usize] += kernel((i as f32 - center) / scale);
+            }
+            let sum: f32 = weights.iter().sum();
+            weights.iter_mut().for_each(|weight| *weight /= sum);
+            Taps { start: first as usize, weights }
+        })
+        .collect()
+}
+
+/// Resize `mask` to `(width, height)` with `filter`, `band` output rows at a
+/// time, clamped to 0-1 and converted to `S` (quantized to 16 bits for a
+/// `u16` mask). `cancel` is checked before each band.
+pub(crate) fn upscale_in_bands<S: Channel>(
+    mask: &Gray32FImage,
+    (width, height): (u32, u32),
+    filter: MaskFilter,
+    band: u32,
+    cancel: &CancellationToken,
+) -> Result<ImageBuffer<Luma<S>, Vec<S>>> {
+    let mut out = ImageBuffer::new(width, height);
+    let (src_width, src_height) = mask.dimensions();
+    if src_width == 0 || src_height == 0 {
+        return Ok(out);
+    }
+    let (rows_taps, columns_taps) = (taps(src_height, height, filter), taps(src_width, width, filter));
+    let src = mask.as_raw();
+    let src_width = src_width as usize;
+    let band_len = width as usize * band as usize;
+    rows::try_for_each_row(&mut out, band_len, |index, band| {
+        cancel.check()?;
+        let mut row = vec![0.0f32; src_width];
+        for (offset, out_row) in band.chunks_mut(width as usize).enumerate() {
+            // Down the columns first, then across, like the image crate
+            let taps = &rows_taps[index * band_len / width as usize + offset];
+            row.fill(0.0);
+            for (i, &weight) in taps.weights.iter().enumerate() {
+                let src_row = &src[(taps.start + i) * src_width..][..src_width];
+                for (sum, &value) in row.iter_mut().zip(src_row) {
+                    *sum += value * weight;
+                }
+            }
+            for (value, taps) in out_row.iter_mut().zip(&columns_taps) {
+                let samples = &row[taps.start..][..taps.weights.len()];
+                let sum: f32 = samples.iter().zip(&taps.weights).map(|(&sample, &weight)| sample * weight).sum();
+                *value = S::from_unit(sum);
+            }
+        }
+        Ok::<_, RemoveBgError>(())
+    })?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{float_mask, quantize_mask, Gray16Image};
+    use image::Luma;
+
+    /// A mask of uniformly random values, or of random hard 0/1 values
+    /// with `hard`, from a 64-bit LCG.
+    fn random_mask(seed: u64, width: u32, height: u32, hard: bool) -> Gray16Image {
+        let mut state = seed;
+        Gray16Image::from_fn(width, height, |_, _| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            let value = (state >> 48) as u16;
+            Luma([if hard { (value >> 15) * u16::MAX } else { value }])
+        })
+    }
+
+    #[test]
+    fn bands_match_a_single_pass_resize() {
+        let filters = [MaskFilter::Nearest, MaskFilter::Triangle, MaskFilter::CatmullRom, MaskFilter::Lanczos3];
+        for (seed, &filter) in filters.iter().enumerate() {
+            for hard in [false, true] {
+                let mask = float_mask(&random_mask(seed as u64, 40, 30, hard));
+                for size in [(733, 301), (97, 64), (20, 15)] {
+                    let single = quantize_mask(&crate::pipeline::upscale_mask_f32(&mask, size, filter));
+                    // Band heights that don't divide the height leave a short last band
+                    for band in [1, 7, 64, size.1] {
+                        let banded = upscale_in_bands(&mask, size, filter, band, &CancellationToken::default()).unwrap();
+                        let diff = single.iter().zip(banded.iter()).map(|(&a, &b)| a.abs_diff(b)).max();
+                        assert!(diff <= Some(1), "{:?} {:?} band {}: {:?}", filter, size, band, diff);
+                    }
+                }
+            }
+        }
+    }
+
+    /// `mask` padded by `pad` samples on each side, repeating its border.
+    fn replicate_border(mask: &Gray32FImage, pad: u32) -> Gray32FImage {
+        let (width, height) = mask.dimensions();
+        Gray32FImage::from_fn(width + 2 * pad, height + 2 * pad, |x, y| {
+            let at = |v: u32, len: u32| v.saturating_sub(pad).min(len - 1);
+            *mask.get_pixel(at(x, width), at(y, height))
+        })
+    }
+
+    #[test]
+    fn borders_match_a_resize_of_the_padded_mask() {
+        use image::imageops::FilterType;
+        let filters = [
+            (MaskFilter::Nearest, FilterType::Nearest),
+            (MaskFilter::Triangle, FilterType::Triangle),
+            (MaskFilter::CatmullRom, FilterType::CatmullRom),
+            (MaskFilter::Lanczos3, FilterType::Lanczos3),
+        ];
+        for (seed, &(filter, filter_type)) in filters.iter().enumerate() {
+            let mask = float_mask(&random_mask(seed as u64, 40, 30, false));
+            let ours = upscale_in_bands::<u16>(&mask, (160, 120), filter, 120, &CancellationToken::default()).unwrap();
+            // Four times the size, so the padding scales to whole output pixels
+            let padded = image::imageops::resize(&replicate_border(&mask, 4), 192, 152, filter_type);
+            let padded = quantize_mask(&image::imageops::crop_imm(&padded, 16, 16, 160, 120).to_image());
+            let diff = ours.iter().zip(padded.iter()).map(|(&a, &b)| a.abs_diff(b)).max();
+            assert!(diff <= Some(1), "{:?}: {:?}", filter, diff);
+        }
+    }
+
+    #[test]
+    fn subjects_clear_of_the_edges_leave_no_frame() {
+        // Clear background for two mask samples around an opaque subject
+        let mask = Gray32FImage::from_fn(16, 16, |x, y| Luma([(x.min(15 - x).min(y.min(15 - y)) >= 2) as u8 as f32]));
+        let border = |mask: &Gray16Image| {
+            let (width, height) = mask.dimensions();
+            mask.enumerate_pixels().filter(|&(x, y, _)| x == 0 || y == 0 || x == width - 1 || y == height - 1).map(|(_, _, p)| p[0]).max()
+        };
+        // The image crate rescales the taps left inside the mask, which more
+        // than doubles the Lanczos lobe reaching the edge
+        let theirs = quantize_mask(&image::imageops::resize(&mask, 200, 150, image::imageops::FilterType::Lanczos3));
+        let ours = upscale_in_bands::<u16>(&mask, (200, 150), MaskFilter::Lanczos3, 150, &CancellationToken::default()).unwrap();
+        assert!(border(&theirs) > Some(3000), "{:?}", border(&theirs));
+        assert!(border(&ours) < Some(2500), "{:?}", border(&ours));
+        for filter in [MaskFilter::Nearest, MaskFilter::Triangle] {
+            let mask = upscale_in_bands::<u16>(&mask, (200, 150), filter, 150, &CancellationToken::default()).unwrap();
+            assert_eq!(border(&mask), Some(0), "{:?}", filter);
+        }
+    }
+
+    #[test]
+    fn only_large_outputs_are_banded() {
+        assert_eq!(band_height((1920, 1080), false), None);
+        assert_eq!(band_height((2048, 2048), false), None);
+        assert_eq!(band_height((20000, 5000), false), Some(52));
+        assert_eq!(band_height((4_000_000, 2), false), Some(1));
+        assert_eq!(band_height((3, 2_000_000), false), Some(349_525));
+        assert_eq!(band_height((5000, 1000), false), Some(209));
+        assert_eq!(band_height((1920, 1080), true), Some(546));
+        assert_eq!(band_height((800, 600), true), Some(600));
+    }
+}
@@ -0,0 +1,58 @@
+//! JavaScript API for in-browser background removal (`wasm` feature).
+//!
+//! Built for `wasm32-unknown-unknown`, the crate runs U2-Net through the
+//! pure-Rust tract backend, so no native library or server is involved.
+//! There is no model cache or download in the browser: the page fetches the
+//! ONNX file itself and passes its bytes in. Images cross the boundary as
+//! RGBA pixels, as read from and written to a canvas with `getImageData` and
+//! `putImageData`. See `examples/wasm/` for a complete page.
+
+use crate::options::{Backend, RemoveBgOptions};
+use crate::remover::BackgroundRemover;
+use crate::segmentation::TractU2Net;
+use image::{DynamicImage, RgbaImage};
+use wasm_bindgen::prelude::*;
+
+/// A loaded model, for removing the background from several images without
+/// optimizing the model again each time.
+#[wasm_bindgen]
+pub struct Remover {
+    remover: BackgroundRemover,
+}
+
+#[wasm_bindgen]
+impl Remover {
+    /// Load a U2-Net family model from the contents of its ONNX file.
+    #[wasm_bindgen(constructor)]
+    pub fn new(model: &[u8]) -> Result<Remover, JsError> {
+        let options = RemoveBgOptions { backend: Backend::Tract, ..Default::default() };
+        let model = TractU2Net::from_bytes(model, &options)?;
+        Ok(Remover { remover: BackgroundRemover::with_model(model, options) })
+    }
+
+    /// Remove the background from `width` x `height` RGBA pixels and return
+    /// the cutout as RGBA pixels of the same size.
+    pub fn remove(&self, data: &[u8], width: u32, height: u32) -> Result<Vec<u8>, JsError> {
+        let image = RgbaImage::from_raw(width, height, data.to_vec()).ok_or_else(|| {
+            JsError::new(&format!(
+                "expected {} bytes of RGBA pixels for a {}x{} image, got {}",
+                width as usize * height as usize * 4,
+                width,
+                height,
+                data.len()
+            ))
+        })?;
+        let cutout = self.remover.process_image(&DynamicImage::ImageRgba8(image))?;
+        Ok(cutout.into_raw())
+    }
+}
+
+/// Remove the background from `width` x `height` RGBA pixels with the ONNX
+/// model in `model`, returning the cutout as RGBA pixels of the same size.
+///
+/// The model is loaded and optimized on every call; create a [`Remover`] to
+/// process several images.
+#[wasm_bindgen]
+pub fn remove_background_rgba(data: &[u8], width: u32, height: u32, model: &[u8]) -> Result<Vec<u8>, JsError> {
+    Remover::new(model)?.remove(data, width, height)
+}
@@ -0,0 +1,224 @@
+//! Hot-folder processing: remove backgrounds from images as they appear.
+//!
+//! Used by `removebg watch`. The model is loaded once when watching starts and
+//! every new or modified image in the watched directory is processed into the
+//! output directory.
+
+use crate::batch::is_up_to_date;
+use crate::core::{check_naming, is_output_name, output_file_name};
+use crate::error::{self, RemoveBgError, Result};
+use crate::options::{Collision, RemoveBgOptions};
+use crate::output::{self, OutputFormat};
+use crate::progress::{Progress, ProgressEvent, LOG_TARGET};
+use crate::raw;
+use crate::remover::BackgroundRemover;
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// Extensions of files picked up by the watcher and batch mode.
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "webp", "bmp", "tif", "tiff", "gif"];
+
+/// Whether `path` has the extension of an image this build can read.
+pub(crate) fn is_image_file(path: &Path) -> bool {
+    let Some(ext) = path.extension().and_then(|ext| ext.to_str()) else {
+        return false;
+    };
+    let ext = ext.to_ascii_lowercase();
+    IMAGE_EXTENSIONS.contains(&ext.as_str())
+        || (cfg!(feature = "heif") && matches!(ext.as_str(), "heic" | "heif"))
+        || (cfg!(feature = "raw") && raw::RAW_EXTENSIONS.contains(&ext.as_str()))
+}
+
+/// How often pending files and the cancellation token are checked.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Settings for [`watch`].
+#[derive(Debug, Clone)]
+pub struct WatchConfig {
+    /// Directory to watch for new images.
+    pub input_dir: PathBuf,
+    /// Directory the cutouts are written to; created if missing.
+    pub output_dir: PathBuf,
+    /// How long a file's size must stay unchanged before it is processed.
+    pub settle_time: Duration,
+    /// Options used for every image. Cancelling `options.cancel` stops watching.
+    pub options: RemoveBgOptions,
+    /// Receives each image's progress, queued once its size has settled.
+    pub progress: Progress,
+}
+
+impl WatchConfig {
+    /// Watch `input_dir` and write results to `output_dir` with default settings.
+    pub fn new(input_dir: impl Into<PathBuf>, output_dir: impl Into<PathBuf>) -> Self {
+        WatchConfig {
+            input_dir: input_dir.into(),
+            output_dir: output_dir.into(),
+            settle_time: Duration::from_secs(1),
+            options: RemoveBgOptions::default(),
+            progress: Progress::default(),
+        }
+    }
+}
+
+/// Counts of what happened while watching.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WatchSummary {
+    /// Images processed successfully.
+    pub processed: usize,
+    /// Images skipped because an up-to-date output already existed.
+    pub skipped: usize,
+    /// Images that failed to process.
+    pub failed: usize,
+}
+
+/// A file seen by the watcher that has not been processed yet.
+struct Pending {
+    size: u64,
+    changed: Instant,
+}
+
+/// Watch a directory until `config.options.cancel` is triggered.
+///
+/// Files are processed once their size has stopped changing for
+/// `settle_time`, so images that are still being copied in are not read
+/// half-written. An image is skipped when its output exists and is newer than
+/// the input. Failures are logged and counted; watching continues.
+///
+/// # Errors
+/// * `FileNotFound` - If the input directory does not exist
+/// * `ModelInitError` - If the model cannot be loaded
+/// * `IoError` - If the output directory cannot be created
+/// * `ProcessingError` - If the directory cannot be watched
+pub fn watch(config: WatchConfig) -> Result<WatchSummary> {
+    if !config.input_dir.is_dir() {
+        return Err(RemoveBgError::FileNotFound(config.input_dir.clone()));
+    }
+    check_naming(&config.options.naming)?;
+    std::fs::create_dir_all(&config.output_dir)?;
+    let output_dir = config.output_dir.canonicalize()?;
+    let input_dir = config.input_dir.canonicalize()?;
+
+    let mut options = config.options.clone();
+    // Outputs older than their input are replaced, never renamed; newer ones
+    // are skipped below
+    options.overwrite = true;
+    options.naming.on_collision = Collision::Overwrite;
+    let cancel = options.cancel.clone();
+    let format = output::selected_format(&options, OutputFormat::default());
+    let remover = BackgroundRemover::new(options)?;
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).map_err(watch_error)?;
+    watcher
+        .watch(&config.input_dir, RecursiveMode::NonRecursive)
+        .map_err(watch_error)?;
+    log::info!(
+        "Watching {} (output: {})",
+        config.input_dir.display(),
+        output_dir.display()
+    );
+
+    let mut summary = WatchSummary::default();
+    let mut pending: HashMap<PathBuf, Pending> = HashMap::new();
+    while !cancel.is_cancelled() {
+        match rx.recv_timeout(POLL_INTERVAL) {
+            Ok(Ok(event)) => {
+                if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                    for path in event.paths {
+                        if is_candidate(&path, &input_dir, &output_dir, &config.options) {
+                            let size = file_size(&path);
+                            pending.insert(path, Pending { size, changed: Instant::now() });
+                        }
+                    }
+                }
+            }
+            Ok(Err(e)) => log::warn!("Watch error: {}", e),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+
+        let ready: Vec<PathBuf> = pending
+            .iter_mut()
+            .filter_map(|(path, entry)| {
+                let size = file_size(path);
+                if size != entry.size {
+                    entry.size = size;
+                    entry.changed = Instant::now();
+                    None
+                } else if entry.changed.elapsed() >= config.settle_time {
+                    Some(path.clone())
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        for input in ready {
+            pending.remove(&input);
+            if cancel.is_cancelled() {
+                break;
+            }
+            if !input.is_file() {
+                continue;
+            }
+            let output = output_path(&input, &output_dir, format, &config.options);
+            config.progress.report(ProgressEvent::Queued { count: 1 });
+            if is_up_to_date(&input, &output) {
+                log::debug!("{}: output is up to date, skipping", input.display());
+                config.progress.report(ProgressEvent::Skipped { input: &input });
+                summary.skipped += 1;
+                continue;
+            }
+            config.progress.report(ProgressEvent::Started { input: &input });
+            match remover.process_file(&input, Some(&output)) {
+                Ok(report) => {
+                    log::info!(target: LOG_TARGET, "{} -> {}", input.display(), report.output_path.display());
+                    config.progress.report(ProgressEvent::Processed { input: &input, report: &report });
+                    summary.processed += 1;
+                }
+                Err(RemoveBgError::Cancelled) => break,
+                Err(e) => {
+                    log::warn!("{}", error::describe(&input, &e));
+                    config.progress.report(ProgressEvent::Failed { input: &input, error: &e });
+                    summary.failed += 1;
+                }
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+fn watch_error(e: notify::Error) -> RemoveBgError {
+    RemoveBgError::ProcessingError(format!("Failed to watch directory: {}", e))
+}
+
+/// Whether `path` is an image to process rather than one of our own outputs.
+///
+/// When the output directory is the watched directory itself, only files
+/// named like our outputs (`*_nobg.*`, and `*_mask.png` with
+/// `options.save_mask`) are ignored.
+fn is_candidate(path: &Path, input_dir: &Path, output_dir: &Path, options: &RemoveBgOptions) -> bool {
+    let is_image = is_image_file(path);
+    let is_output = path
+        .parent()
+        .and_then(|parent| parent.canonicalize().ok())
+        .is_some_and(|parent| {
+            parent.starts_with(output_dir)
+                && (parent != input_dir || is_output_name(path, options))
+        });
+    is_image && !is_output
+}
+
+fn file_size(path: &Path) -> u64 {
+    std::fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+/// Output path for `input`: same stem in the output directory with the
+/// prefix and suffix of `options` added.
+pub(crate) fn output_path(input: &Path, output_dir: &Path, format: OutputFormat, options: &RemoveBgOptions) -> PathBuf {
+    output_dir.join(output_file_name(input.file_stem().unwrap_or_default(), format, options))
+}
@@ -0,0 +1,168 @@
+//! ZIP archive inputs: `process_zip` and `removebg photos.zip`. Runs the
+//! stub model in `tests/fixtures/models` on the archives in
+//! `tests/fixtures/archive` (see `make_zip.py` there).
+
+#![cfg(feature = "archive")]
+
+use removebg::archive::{process_zip, ArchiveSummary, EntryStatus};
+use removebg::testing::TestModel;
+use removebg::{remove_background_from_bytes, NamingOptions, RemoveBgError, RemoveBgOptions};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn fixtures() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+fn archive(name: &str) -> PathBuf {
+    fixtures().join("archive").join(name)
+}
+
+fn options() -> RemoveBgOptions {
+    TestModel::Brightness.options(RemoveBgOptions::default())
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("removebg-archive-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Names and contents of the entries of the archive at `path`.
+fn entries(path: &Path) -> Vec<(String, Vec<u8>)> {
+    let mut archive = zip::ZipArchive::new(File::open(path).unwrap()).unwrap();
+    (0..archive.len())
+        .map(|index| {
+            let mut entry = archive.by_index(index).unwrap();
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).unwrap();
+            (entry.name().to_string(), data)
+        })
+        .collect()
+}
+
+fn statuses(summary: &ArchiveSummary) -> Vec<(&str, EntryStatus)> {
+    summary.entries.iter().map(|entry| (entry.name.as_str(), entry.status)).collect()
+}
+
+#[test]
+fn images_round_trip_through_an_archive() {
+    let dir = temp_dir("round-trip");
+    let output = dir.join("cutouts.zip");
+    let summary = process_zip(&archive("photos.zip"), Some(&output), &options()).unwrap();
+
+    assert_eq!(summary.output, output);
+    assert!(!summary.cancelled);
+    assert_eq!(
+        statuses(&summary),
+        [("portrait.png", EntryStatus::Done), ("shoot/square.bmp", EntryStatus::Done), ("notes.txt", EntryStatus::Skipped)]
+    );
+    assert_eq!(summary.entries[1].output.as_deref(), Some("shoot/square_nobg.png"));
+    assert!(summary.entries[2].output.is_none() && summary.entries[2].error.is_none());
+
+    // The cutouts are what the in-memory pipeline makes of the entries
+    let inputs = entries(&archive("photos.zip"));
+    let cutouts = entries(&output);
+    let names: Vec<_> = cutouts.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, ["portrait_nobg.png", "shoot/square_nobg.png"]);
+    let expected = remove_background_from_bytes(&inputs[0].1, &options()).unwrap();
+    assert_eq!(cutouts[0].1, expected);
+
+    let square = image::load_from_memory(&cutouts[1].1).unwrap().to_rgba8();
+    assert_eq!(square.dimensions(), (24, 24));
+    assert_eq!(square.get_pixel(12, 12)[3], 255);
+    assert_eq!(square.get_pixel(0, 0)[3], 0);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn no_suffix_keeps_the_entry_names() {
+    let dir = temp_dir("no-suffix");
+    let output = dir.join("cutouts.zip");
+    let naming = NamingOptions { suffix: Some(String::new()), ..Default::default() };
+    process_zip(&archive("photos.zip"), Some(&output), &RemoveBgOptions { naming, ..options() }).unwrap();
+    let names: Vec<_> = entries(&output).into_iter().map(|(name, _)| name).collect();
+    assert_eq!(names, ["portrait.png", "shoot/square.png"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn damaged_entries_fail_without_stopping_the_run() {
+    let dir = temp_dir("damaged");
+    let output = dir.join("cutouts.zip");
+    let summary = process_zip(&archive("damaged.zip"), Some(&output), &options()).unwrap();
+
+    assert_eq!(statuses(&summary), [("portrait.png", EntryStatus::Done), ("broken.png", EntryStatus::Failed)]);
+    let error = summary.entries[1].error.as_ref().unwrap();
+    assert_eq!(error.kind, "archive_error");
+    let names: Vec<_> = entries(&output).into_iter().map(|(name, _)| name).collect();
+    assert_eq!(names, ["portrait_nobg.png"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn password_protected_archives_are_refused() {
+    let dir = temp_dir("locked");
+    let output = dir.join("cutouts.zip");
+    let error = process_zip(&archive("locked.zip"), Some(&output), &options()).unwrap_err();
+    assert!(matches!(error, RemoveBgError::ArchiveError(ref message) if message.contains("password-protected")), "{}", error);
+    assert_eq!(error.exit_code(), 2);
+    assert!(!output.exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn existing_outputs_are_kept_unless_forced() {
+    let dir = temp_dir("existing");
+    let output = dir.join("cutouts.zip");
+    std::fs::write(&output, b"earlier").unwrap();
+    let error = process_zip(&archive("photos.zip"), Some(&output), &options()).unwrap_err();
+    assert!(matches!(error, RemoveBgError::OutputExists(_)), "{}", error);
+    assert_eq!(std::fs::read(&output).unwrap(), b"earlier");
+
+    process_zip(&archive("photos.zip"), Some(&output), &RemoveBgOptions { overwrite: true, ..options() }).unwrap();
+    assert_eq!(entries(&output).len(), 2);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn the_cli_reports_every_entry() {
+    let dir = temp_dir("cli");
+    let run = |input: &str, output: &str| {
+        Command::new(env!("CARGO_BIN_EXE_removebg"))
+            .arg(archive(input))
+            .arg("--output-zip")
+            .arg(dir.join(output))
+            .args(["--json"])
+            .args(TestModel::Brightness.args())
+            .output()
+            .expect("removebg runs")
+    };
+
+    let run_photos = run("photos.zip", "photos.zip");
+    assert!(run_photos.status.success(), "{}", String::from_utf8_lossy(&run_photos.stderr));
+    let report: serde_json::Value = serde_json::from_slice(&run_photos.stdout).unwrap();
+    assert_eq!((report["processed"].as_u64(), report["skipped"].as_u64()), (Some(2), Some(1)));
+    let entries = report["entries"].as_array().unwrap();
+    assert_eq!(entries[0]["output"], "portrait_nobg.png");
+    assert_eq!(entries[2]["status"], "skipped");
+
+    let run_damaged = run("damaged.zip", "damaged.zip");
+    assert_eq!(run_damaged.status.code(), Some(5));
+    let report: serde_json::Value = serde_json::from_slice(&run_damaged.stdout).unwrap();
+    assert_eq!(report["entries"][1]["error"]["kind"], "archive_error");
+    assert_eq!(report["success"], false);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(archive("photos.zip"))
+        .arg("-o")
+        .arg(dir.join("out.png"))
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--output-zip"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
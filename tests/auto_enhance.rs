@@ -0,0 +1,90 @@
+//! Exposure normalization of the model's input (`auto_enhance`,
+//! `--auto-enhance`), with the stub model in `tests/fixtures/models`, whose
+//! mask is the brightness of its input: a dark photo gives an empty mask
+//! unless it is enhanced.
+
+use image::{DynamicImage, Rgb, RgbImage, RgbaImage};
+use removebg::{testing::{self, TestModel}, BackgroundRemover, RemoveBgOptions};
+use std::process::Command;
+
+fn remover(auto_enhance: Option<f32>) -> BackgroundRemover {
+    let options = TestModel::Brightness.options(RemoveBgOptions { auto_enhance, ..Default::default() });
+    BackgroundRemover::new(testing::deterministic(options)).expect("stub model loads")
+}
+
+/// A light disc on a dark gray background, with every value scaled by
+/// `exposure`.
+fn photo(exposure: f32) -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_fn(120, 90, |x, y| {
+        let inside = (x as f32 - 60.0).hypot(y as f32 - 45.0) < 30.0;
+        let value = if inside { 240.0 } else { 30.0 } * exposure;
+        Rgb([value as u8, (value * 0.9) as u8, (value * 0.8) as u8])
+    }))
+}
+
+/// Fraction of the cutout's pixels that are at least half opaque.
+fn coverage(cutout: &RgbaImage) -> f32 {
+    cutout.pixels().filter(|pixel| pixel[3] >= 128).count() as f32 / cutout.pixels().len() as f32
+}
+
+#[test]
+fn dark_photos_get_a_mask_and_keep_their_pixels() {
+    let dark = photo(0.2);
+    let plain = remover(None).process_image(&dark).unwrap();
+    let enhanced = remover(Some(1.0)).process_image(&dark).unwrap();
+    // The disc is about a quarter of the image
+    assert!(coverage(&plain) < 0.01, "{}", coverage(&plain));
+    assert!((0.2..0.3).contains(&coverage(&enhanced)), "{}", coverage(&enhanced));
+    let half = remover(Some(0.5)).process_image(&dark).unwrap();
+    assert!(coverage(&half) > coverage(&plain) && coverage(&half) <= coverage(&enhanced));
+
+    // Only the model's copy was brightened
+    let dark = dark.to_rgb8();
+    for (pixel, original) in enhanced.pixels().zip(dark.pixels()).filter(|(pixel, _)| pixel[3] > 0) {
+        assert_eq!(pixel.0[..3], original.0);
+    }
+}
+
+#[test]
+fn wide_ranges_and_bad_strengths() {
+    // Well-exposed photos and noise already span the range and are left as is
+    let bright = photo(1.0);
+    assert!(remover(Some(1.0)).process_image(&bright).unwrap() == remover(None).process_image(&bright).unwrap());
+    let mut state = 0x9E37_79B9_7F4A_7C15_u64;
+    let noise = DynamicImage::ImageRgb8(RgbImage::from_fn(64, 64, |_, _| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        let [r, g, b, ..] = state.to_le_bytes();
+        Rgb([r, g, b])
+    }));
+    assert!(remover(Some(1.0)).process_image(&noise).unwrap() == remover(None).process_image(&noise).unwrap());
+
+    let error = remover(Some(1.5)).process_image(&bright).unwrap_err();
+    assert!(error.to_string().contains("Auto-enhance strength"), "{}", error);
+}
+
+#[test]
+fn verbose_runs_report_the_stretch() {
+    let dir = std::env::temp_dir().join(format!("removebg-auto-enhance-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("dark.png");
+    photo(0.2).save(&input).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(&input)
+        .args(TestModel::Brightness.args())
+        .args(["--auto-enhance", "-v"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("auto-enhance: stretching luminance 5-43"), "{}", stderr);
+    let cutout = image::open(dir.join("dark_nobg.png")).unwrap().to_rgba8();
+    assert!(coverage(&cutout) > 0.2);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_removebg")).arg(&input).args(["--auto-enhance", "2"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
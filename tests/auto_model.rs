@@ -0,0 +1,101 @@
+//! Automatic model choice (`--model auto`): u2netp runs first, a mask shaped
+//! like a person is redone with u2net-human-seg, an unclear one with u2net,
+//! and `--max-models` caps the models run. Every model is the stub from
+//! `tests/fixtures/models` under its registered file name, so the choices
+//! follow from the fixtures' brightness, which the stub takes as the mask.
+
+use image::{Rgb, RgbImage};
+use removebg::{remove_background_detailed, testing::{self, TestModel}, AutoModel, Model, RemoveBgOptions};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn fixtures() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+/// A directory with the stub model under the names of the models `auto` may run.
+fn workspace(name: &str) -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("removebg-auto-{}-{}", name, std::process::id()));
+    let models = dir.join("models");
+    std::fs::create_dir_all(&models).unwrap();
+    for model in [Model::U2netp, Model::U2netHumanSeg, Model::U2net] {
+        std::fs::copy(TestModel::Brightness.dir().join("u2netp.onnx"), models.join(model.spec().file_name)).unwrap();
+    }
+    (dir, models)
+}
+
+/// A white box floating on a plain background of `gray`.
+fn product(gray: u8) -> RgbImage {
+    RgbImage::from_fn(160, 120, |x, y| match (50..110).contains(&x) && (30..80).contains(&y) {
+        true => Rgb([255, 255, 255]),
+        false => Rgb([gray; 3]),
+    })
+}
+
+fn options(models: &Path, auto: AutoModel) -> RemoveBgOptions {
+    testing::deterministic(RemoveBgOptions {
+        model_dir: Some(models.to_path_buf()),
+        auto_model: Some(auto),
+        overwrite: true,
+        ..Default::default()
+    })
+}
+
+fn chosen(input: &Path, options: &RemoveBgOptions) -> (Model, Vec<Model>, String) {
+    let report = remove_background_detailed(input, None, options).unwrap();
+    let model = report.model.expect("a model ran");
+    let selection = model.selection.expect("the choice is reported");
+    assert_eq!(selection.tried.last(), Some(&model.model));
+    (model.model, selection.tried, selection.reason)
+}
+
+#[test]
+fn portraits_get_the_human_model_and_products_keep_u2netp() {
+    let (dir, models) = workspace("choice");
+    let portrait = dir.join("portrait.png");
+    std::fs::copy(fixtures().join("golden/portrait.png"), &portrait).unwrap();
+    let clear = dir.join("clear.png");
+    product(0).save(&clear).unwrap();
+    let unclear = dir.join("unclear.png");
+    product(110).save(&unclear).unwrap();
+    let options = options(&models, AutoModel::default());
+
+    let (model, tried, _) = chosen(&portrait, &options);
+    assert_eq!((model, tried), (Model::U2netHumanSeg, vec![Model::U2netp, Model::U2netHumanSeg]));
+
+    let (model, tried, reason) = chosen(&clear, &options);
+    assert_eq!((model, tried), (Model::U2netp, vec![Model::U2netp]));
+    assert!(reason.contains("high enough"), "{}", reason);
+
+    let (model, tried, reason) = chosen(&unclear, &options);
+    assert_eq!((model, tried), (Model::U2net, vec![Model::U2netp, Model::U2net]));
+    assert!(reason.contains("below 0.5"), "{}", reason);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn max_models_caps_the_runs() {
+    let (dir, models) = workspace("cap");
+    let portrait = dir.join("portrait.png");
+    std::fs::copy(fixtures().join("golden/portrait.png"), &portrait).unwrap();
+
+    let (model, _, reason) = chosen(&portrait, &options(&models, AutoModel { max_models: 1 }));
+    assert_eq!(model, Model::U2netp);
+    assert!(reason.contains("looks like a person") && reason.contains("at most 1"), "{}", reason);
+    // The stub's person mask is unclear too, so a third model may follow
+    let (_, tried, _) = chosen(&portrait, &options(&models, AutoModel { max_models: 3 }));
+    assert_eq!(tried, [Model::U2netp, Model::U2netHumanSeg, Model::U2net]);
+
+    let run = Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(&portrait)
+        .args(["--model", "auto", "--max-models", "1", "--json", "--force", "--model-dir"])
+        .arg(&models)
+        .output()
+        .expect("removebg runs");
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+    assert!(String::from_utf8_lossy(&run.stderr).contains("looks like a person"));
+    let json: serde_json::Value = serde_json::from_slice(&run.stdout).expect("one JSON object");
+    assert_eq!(json["model"], "u2netp");
+    assert_eq!(json["model_selection"]["tried"], serde_json::json!(["u2netp"]));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,22 @@
+//! The tract and ONNX Runtime backends (`--backend tract|ort`): the same
+//! model file gives the same masks on either, up to floating-point
+//! differences. Needs both engines; runs use the blob stub model in
+//! `tests/fixtures/models/blob`.
+#![cfg(all(feature = "ort", feature = "backend-tract"))]
+
+use image::DynamicImage;
+use removebg::testing::{self, TestModel};
+use removebg::{Backend, RemoveBgOptions};
+
+#[test]
+fn tract_masks_match_ort_masks() {
+    let ort = TestModel::CenterBlob.remover(RemoveBgOptions { backend: Backend::Ort, ..Default::default() }).unwrap();
+    let tract = TestModel::CenterBlob.remover(RemoveBgOptions { backend: Backend::Tract, ..Default::default() }).unwrap();
+
+    for (seed, width, height) in [(1, 64, 48), (2, 320, 320), (3, 500, 180)] {
+        let image = DynamicImage::ImageRgb8(testing::synthetic_image(seed, width, height));
+        let diff = testing::mask_diff(&tract.mask(&image).unwrap(), &ort.mask(&image).unwrap());
+        assert!(diff.mean_abs_diff < 0.002, "{}x{}: {:?}", width, height, diff);
+        assert!(diff.max_abs_diff < 0.02, "{}x{}: {:?}", width, height, diff);
+    }
+}
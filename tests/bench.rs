@@ -0,0 +1,69 @@
+//! `--bench` tests, run with the stub model in `tests/fixtures/models`.
+
+use removebg::bench::{run_benchmark, STAGES};
+use removebg::{testing::{self, TestModel}, RemoveBgOptions};
+use std::path::PathBuf;
+use std::process::Command;
+
+/// A fresh directory under the system temp dir with a test image, named for
+/// `test`.
+fn input(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("removebg-bench-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("photo.png");
+    testing::synthetic_image(6, 80, 60).save(&input).unwrap();
+    input
+}
+
+#[test]
+fn three_iterations_are_timed() {
+    let input = input("library");
+    let options = TestModel::Brightness.options(RemoveBgOptions::default());
+    let report = run_benchmark(&input, None, 3, &options).unwrap();
+
+    assert_eq!(report.runs.len(), 3);
+    assert_eq!(report.input_dimensions, (80, 60));
+    assert!(!report.downloaded);
+    for run in &report.runs {
+        assert!(run.inference > std::time::Duration::ZERO);
+        let stages = run.decode + run.preprocess + run.inference + run.postprocess + run.composite + run.encode;
+        assert!(stages <= run.total, "stages {:?} exceed the total {:?}", stages, run.total);
+    }
+    for (name, stage) in STAGES {
+        let stats = report.stats(stage);
+        assert!(stats.min <= stats.median && stats.median <= stats.max, "{}: {:?}", name, stats);
+    }
+    assert!(report.images_per_second() > 0.0);
+    std::fs::remove_dir_all(input.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn zero_iterations_are_rejected() {
+    let input = input("zero");
+    let options = TestModel::Brightness.options(RemoveBgOptions::default());
+    assert!(run_benchmark(&input, None, 0, &options).is_err());
+    std::fs::remove_dir_all(input.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn cli_reports_three_runs_as_json() {
+    let input = input("cli");
+    let output = Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(&input)
+        .args(["--bench", "3", "--json"])
+        .args(TestModel::Brightness.args())
+        .output()
+        .expect("removebg runs");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let report: serde_json::Value = serde_json::from_str(stdout.trim()).expect("one JSON object");
+    assert_eq!(report["iterations"], 3);
+    assert_eq!(report["runs"].as_array().map(Vec::len), Some(3));
+    assert_eq!(report["download_excluded"], true);
+    for (name, _) in STAGES {
+        assert!(report["stages"][name]["median_ms"].is_number(), "{} is missing", name);
+    }
+    std::fs::remove_dir_all(input.parent().unwrap()).unwrap();
+}
@@ -0,0 +1,96 @@
+//! Alpha at the image borders: the mask upscale repeats the mask's edge
+//! instead of extrapolating a frame, and `trim_border` / `--trim-border`
+//! copies the interior out over the outermost pixels. Runs the stub model
+//! in `tests/fixtures/models`, whose mask follows the image's brightness.
+
+use image::{DynamicImage, Rgb, RgbImage, RgbaImage};
+use removebg::testing::TestModel;
+use removebg::{BackgroundRemover, MaskFilter, RemoveBgOptions};
+use std::process::Command;
+
+fn remover(options: RemoveBgOptions) -> BackgroundRemover {
+    TestModel::Brightness.remover(options).expect("stub model loads")
+}
+
+/// A white ellipse on black, clear of the frame by a tenth of the image on
+/// each side.
+fn centered_subject(width: u32, height: u32) -> DynamicImage {
+    let (cx, cy) = (width as f32 / 2.0, height as f32 / 2.0);
+    DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+        let (dx, dy) = ((x as f32 + 0.5 - cx) / (cx * 0.8), (y as f32 + 0.5 - cy) / (cy * 0.8));
+        if dx * dx + dy * dy <= 1.0 {
+            Rgb([255; 3])
+        } else {
+            Rgb([0; 3])
+        }
+    }))
+}
+
+fn border_alpha(cutout: &RgbaImage, depth: u32) -> impl Iterator<Item = u8> + '_ {
+    let (width, height) = cutout.dimensions();
+    cutout
+        .enumerate_pixels()
+        .filter(move |&(x, y, _)| x < depth || y < depth || x >= width - depth || y >= height - depth)
+        .map(|(_, _, pixel)| pixel[3])
+}
+
+#[test]
+fn subjects_clear_of_the_frame_leave_transparent_borders() {
+    let filters = [MaskFilter::Nearest, MaskFilter::Triangle, MaskFilter::CatmullRom, MaskFilter::Lanczos3];
+    for filter in filters {
+        let remover = remover(RemoveBgOptions { mask_filter: filter, ..Default::default() });
+        // Upscaled from the 320x320 mask, and one the size of the mask
+        for (width, height) in [(1280, 960), (640, 427), (320, 320)] {
+            let cutout = remover.process_image(&centered_subject(width, height)).unwrap();
+            let max = border_alpha(&cutout, 2).max();
+            assert!(max <= Some(1), "{:?} {}x{}: border alpha up to {:?}", filter, width, height, max);
+            assert_eq!(cutout.get_pixel(width / 2, height / 2)[3], 255, "{:?} {}x{}", filter, width, height);
+        }
+    }
+}
+
+#[test]
+fn trim_border_copies_the_interior_outwards() {
+    // A vertical gradient reaching the frame, so the border isn't clear
+    let image = DynamicImage::ImageRgb8(RgbImage::from_fn(200, 150, |_, y| Rgb([(y * 255 / 149) as u8; 3])));
+    let plain = remover(RemoveBgOptions::default()).process_image(&image).unwrap();
+    let trimmed = remover(RemoveBgOptions { trim_border: 3, ..Default::default() }).process_image(&image).unwrap();
+    for (x, y, pixel) in trimmed.enumerate_pixels() {
+        let inner = plain.get_pixel(x.clamp(3, 196), y.clamp(3, 146));
+        assert_eq!(pixel[3], inner[3], "({}, {})", x, y);
+    }
+    assert_ne!(plain.get_pixel(100, 0)[3], trimmed.get_pixel(100, 0)[3]);
+
+    // Wider than half the image, the middle row and column are copied
+    let thin = DynamicImage::ImageRgb8(RgbImage::from_fn(5, 4, |x, _| Rgb([(x * 60) as u8; 3])));
+    let trimmed = remover(RemoveBgOptions { trim_border: 10, ..Default::default() }).process_image(&thin).unwrap();
+    assert!(trimmed.pixels().all(|pixel| pixel[3] == trimmed.get_pixel(2, 1)[3]));
+}
+
+#[test]
+fn the_cli_trims_the_border() {
+    let dir = std::env::temp_dir().join(format!("removebg-borders-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("gradient.png");
+    RgbImage::from_fn(120, 90, |_, y| Rgb([(y * 255 / 89) as u8; 3])).save(&input).unwrap();
+
+    let run = |output: &str, args: &[&str]| {
+        let status = Command::new(env!("CARGO_BIN_EXE_removebg"))
+            .arg(&input)
+            .arg("-o")
+            .arg(dir.join(output))
+            .args(TestModel::Brightness.args())
+            .args(args)
+            .status()
+            .expect("removebg runs");
+        assert!(status.success(), "{:?}", args);
+        image::open(dir.join(output)).unwrap().to_rgba8()
+    };
+    let plain = run("plain.png", &[]);
+    let trimmed = run("trimmed.png", &["--trim-border", "2"]);
+    assert_eq!(trimmed.get_pixel(60, 0)[3], plain.get_pixel(60, 2)[3]);
+    assert_eq!(trimmed.get_pixel(60, 89)[3], plain.get_pixel(60, 87)[3]);
+    assert_eq!(trimmed.get_pixel(60, 45), plain.get_pixel(60, 45));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
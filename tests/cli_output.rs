@@ -0,0 +1,116 @@
+//! What the CLI prints where: stdout only carries results, so scripts can
+//! read it, and everything else goes to stderr.
+
+use image::GenericImageView;
+use removebg::testing::{self, TestModel};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// A fresh directory under the system temp dir with three test images,
+/// named for `test`.
+fn inputs(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("removebg-cli-output-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    for (seed, name) in [(1, "a.png"), (2, "b.png"), (3, "c.png")] {
+        testing::synthetic_image(seed, 80, 60).save(dir.join(name)).unwrap();
+    }
+    dir
+}
+
+fn removebg(args: &[&str], input: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(input)
+        .args(args)
+        .args(TestModel::Brightness.args())
+        .output()
+        .expect("removebg runs")
+}
+
+#[test]
+fn quiet_prints_only_the_output_path() {
+    let dir = inputs("single");
+    let output = dir.join("cutout.png");
+    let run = removebg(&["-q", "-o", output.to_str().unwrap()], &dir.join("a.png"));
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+    assert_eq!(String::from_utf8(run.stdout).unwrap(), format!("{}\n", output.display()));
+    // Warnings still show; the synthetic image has no clear background
+    let stderr = String::from_utf8_lossy(&run.stderr);
+    assert!(stderr.lines().all(|line| line.starts_with("Warning: ")), "{}", stderr);
+
+    // Without --quiet the messages are for people, on stderr
+    let run = removebg(&["--force", "-o", output.to_str().unwrap()], &dir.join("a.png"));
+    assert!(run.status.success());
+    assert!(run.stdout.is_empty(), "{}", String::from_utf8_lossy(&run.stdout));
+    assert!(String::from_utf8_lossy(&run.stderr).contains(&format!("Saved to: {}", output.display())));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn quiet_directories_print_one_path_per_written_output_in_order() {
+    let dir = inputs("batch");
+    std::fs::write(dir.join("b.png"), b"not a png").unwrap();
+    let out = dir.join("out");
+    let run = removebg(&["-q", "--batch-size", "2", "--output-dir", out.to_str().unwrap()], &dir);
+    assert_eq!(run.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8(run.stdout).unwrap(),
+        format!("{}\n{}\n", out.join("a_nobg.png").display(), out.join("c_nobg.png").display())
+    );
+    let stderr = String::from_utf8_lossy(&run.stderr);
+    assert!(stderr.contains("b.png") && !stderr.contains("Processed"), "{}", stderr);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn errors_never_reach_stdout() {
+    let dir = inputs("errors");
+    for args in [&["-q"][..], &[][..], &["-v"][..]] {
+        let run = removebg(args, &dir.join("missing.png"));
+        assert_eq!(run.status.code(), Some(1));
+        assert!(run.stdout.is_empty(), "{:?}: {}", args, String::from_utf8_lossy(&run.stdout));
+        assert!(String::from_utf8_lossy(&run.stderr).contains("Error: "), "{:?}", args);
+    }
+
+    // An existing output with --quiet: the error, and nothing on stdout
+    let run = removebg(&["-q"], &dir.join("a.png"));
+    assert!(run.status.success());
+    let run = removebg(&["-q"], &dir.join("a.png"));
+    assert_eq!(run.status.code(), Some(4));
+    assert!(run.stdout.is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn existing_outputs_are_kept_unless_forced() {
+    let dir = inputs("force");
+    let output = dir.join("a_nobg.png");
+    std::fs::write(&output, b"an earlier cutout").unwrap();
+
+    let run = removebg(&[], &dir.join("a.png"));
+    assert_eq!(run.status.code(), Some(4));
+    let stderr = String::from_utf8_lossy(&run.stderr);
+    assert!(stderr.contains("already exists") && stderr.contains("--force"), "{}", stderr);
+    assert_eq!(std::fs::read(&output).unwrap(), b"an earlier cutout");
+
+    let run = removebg(&["--force"], &dir.join("a.png"));
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+    assert_eq!(image::open(&output).unwrap().dimensions(), (80, 60));
+
+    // A directory run keeps the existing output, fails that input with the
+    // same exit code, and does the rest
+    let (batch, out) = (dir.join("batch"), dir.join("out"));
+    std::fs::create_dir_all(&batch).unwrap();
+    std::fs::create_dir_all(&out).unwrap();
+    for name in ["b.png", "c.png"] {
+        std::fs::copy(dir.join(name), batch.join(name)).unwrap();
+    }
+    std::fs::write(out.join("b_nobg.png"), b"an earlier cutout").unwrap();
+    let run = removebg(&["--output-dir", out.to_str().unwrap()], &batch);
+    let stderr = String::from_utf8_lossy(&run.stderr);
+    assert_eq!(run.status.code(), Some(4), "{}", stderr);
+    assert!(stderr.contains("Processed 1, skipped 0, failed 1"), "{}", stderr);
+    assert_eq!(std::fs::read(out.join("b_nobg.png")).unwrap(), b"an earlier cutout");
+    assert!(out.join("c_nobg.png").is_file());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
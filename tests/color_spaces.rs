@@ -0,0 +1,117 @@
+//! Inputs beyond RGB files: CMYK JPEGs are converted, color spaces the
+//! decoders can't read are named, and HEIC and camera RAW files decode in
+//! builds with the `heif` and `raw` features and name the feature otherwise.
+//! The fixtures are in `tests/fixtures/inputs`; runs use the stub model in
+//! `tests/fixtures/models`.
+
+use image::RgbaImage;
+use removebg::testing::TestModel;
+use removebg::{BackgroundRemover, RemoveBgOptions};
+use std::path::PathBuf;
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/inputs").join(name)
+}
+
+fn remover() -> BackgroundRemover {
+    TestModel::Brightness.remover(RemoveBgOptions::default()).expect("stub model loads")
+}
+
+/// Assert that the pixel at `(x, y)` of `cutout` is red.
+#[track_caller]
+fn assert_red(cutout: &RgbaImage, x: u32, y: u32) {
+    let [red, green, blue, _] = cutout.get_pixel(x, y).0;
+    assert!(red > 200 && green < 60 && blue < 60, "({}, {}): {:?}", x, y, [red, green, blue]);
+}
+
+/// Assert that the pixel at `(x, y)` of `cutout` is a mid gray.
+#[track_caller]
+fn assert_gray(cutout: &RgbaImage, x: u32, y: u32) {
+    let [red, green, blue, _] = cutout.get_pixel(x, y).0;
+    let (max, min) = (red.max(green).max(blue), red.min(green).min(blue));
+    assert!(max - min < 20 && (100..160).contains(&red), "({}, {}): {:?}", x, y, [red, green, blue]);
+}
+
+#[test]
+fn cmyk_jpegs_are_converted_to_rgb() {
+    // 16x8 with a CMYK ICC profile: red (0, 100, 100, 0) on the left, 50%
+    // black on the right
+    let data = std::fs::read(fixture("cmyk.jpg")).unwrap();
+    let png = remover().process_bytes(&data).unwrap();
+    // The CMYK profile doesn't describe the converted RGB pixels
+    assert!(!png.windows(4).any(|chunk| chunk == b"iCCP"));
+
+    let cutout = image::load_from_memory(&png).unwrap().into_rgba8();
+    assert_eq!(cutout.dimensions(), (16, 8));
+    assert_red(&cutout, 2, 2);
+    assert_gray(&cutout, 12, 2);
+}
+
+#[test]
+fn unsupported_color_spaces_are_named() {
+    // Uncompressed YCbCr TIFF, which the TIFF decoder doesn't read
+    let data = std::fs::read(fixture("ycbcr.tif")).unwrap();
+    let error = remover().process_bytes(&data).unwrap_err();
+    assert!(error.to_string().contains("color space"), "{}", error);
+}
+
+/// 8x16 lossless HEIC, red on top and gray below, stored with a 90°
+/// clockwise rotation, so it decodes as 16x8 with red on the right.
+fn heic() -> removebg::Result<Vec<u8>> {
+    remover().process_bytes(&std::fs::read(fixture("rotated.heic")).unwrap())
+}
+
+#[cfg(feature = "heif")]
+#[test]
+fn heic_inputs_are_rotated_upright() {
+    let cutout = image::load_from_memory(&heic().unwrap()).unwrap().into_rgba8();
+    assert_eq!(cutout.dimensions(), (16, 8));
+    assert_red(&cutout, 13, 4);
+    assert_gray(&cutout, 2, 4);
+}
+
+#[cfg(not(feature = "heif"))]
+#[test]
+fn heic_without_the_feature_names_it() {
+    let error = heic().unwrap_err().to_string();
+    assert!(error.contains("heif") && error.contains("convert"), "{}", error);
+}
+
+/// A fresh directory holding `name` with `data`, named for `test`.
+fn raw_input(test: &str, name: &str, data: &[u8]) -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("removebg-color-spaces-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join(name);
+    std::fs::write(&input, data).unwrap();
+    (dir, input)
+}
+
+#[cfg(feature = "raw")]
+#[test]
+fn raw_files_use_their_embedded_preview() {
+    // Stub DNG: gray 8x16 sensor data with a full-size preview, red on top
+    // and gray below, and orientation 6 (rotate 90° clockwise)
+    let (dir, input) = raw_input("dng", "IMG_0123.dng", &std::fs::read(fixture("IMG_0123.dng")).unwrap());
+    let report = remover().process_file(&input, None).unwrap();
+    assert_eq!(report.output_path, dir.join("IMG_0123_nobg.png"));
+    let cutout = image::open(&report.output_path).unwrap().into_rgba8();
+    assert_eq!(cutout.dimensions(), (16, 8));
+    assert_red(&cutout, 13, 4);
+    assert_gray(&cutout, 2, 4);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let (dir, input) = raw_input("cr3", "IMG_0001.cr3", b"not a raw file");
+    let error = remover().process_file(&input, None).unwrap_err();
+    assert!(error.to_string().contains("CR3"), "{}", error);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(not(feature = "raw"))]
+#[test]
+fn raw_without_the_feature_names_it() {
+    let (dir, input) = raw_input("dng", "IMG_0123.dng", &std::fs::read(fixture("IMG_0123.dng")).unwrap());
+    let error = remover().process_file(&input, None).unwrap_err().to_string();
+    assert!(error.contains("DNG") && error.contains("raw") && error.contains("convert"), "{}", error);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,226 @@
+//! Round trips of every input color type, run with the stub model in
+//! `tests/fixtures/models`.
+//!
+//! The stub's mask is the brightness of the input, so a gradient from black
+//! on the left to white on the right must come out transparent on the left
+//! and opaque on the right, whatever its color type.
+
+use image::codecs::gif::GifEncoder;
+use image::{DynamicImage, Frame, ImageFormat, Luma, LumaA, Rgb, Rgba, RgbaImage};
+use removebg::testing::{self, TestModel};
+use removebg::{BackgroundRemover, RemoveBgOptions};
+use std::io::Cursor;
+
+const WIDTH: u32 = 64;
+const HEIGHT: u32 = 32;
+
+fn remover(options: RemoveBgOptions) -> BackgroundRemover {
+    TestModel::Brightness.remover(options).expect("stub model loads")
+}
+
+/// Gray level of the gradient at column `x`, 0-255.
+fn level(x: u32) -> u8 {
+    (x * 255 / (WIDTH - 1)) as u8
+}
+
+/// Whether the gradient is transparent at `(x, y)` in the inputs with alpha:
+/// the top left quarter.
+fn transparent(x: u32, y: u32) -> bool {
+    x < WIDTH / 4 && y < HEIGHT / 2
+}
+
+fn encode(image: &DynamicImage, format: ImageFormat) -> Vec<u8> {
+    let mut bytes = Cursor::new(Vec::new());
+    image.write_to(&mut bytes, format).unwrap();
+    bytes.into_inner()
+}
+
+fn encode_png(image: &DynamicImage) -> Vec<u8> {
+    encode(image, ImageFormat::Png)
+}
+
+/// Encode `data` as a PNG with the `png` crate, for the color types the
+/// image crate can decode but not encode.
+fn raw_png(color: png::ColorType, depth: png::BitDepth, palette: Option<(Vec<u8>, Vec<u8>)>, data: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, WIDTH, HEIGHT);
+    encoder.set_color(color);
+    encoder.set_depth(depth);
+    if let Some((palette, trns)) = palette {
+        encoder.set_palette(palette);
+        if !trns.is_empty() {
+            encoder.set_trns(trns);
+        }
+    }
+    encoder.write_header().unwrap().write_image_data(data).unwrap();
+    bytes
+}
+
+/// Remove the background of `input` and return the output's alpha channel.
+fn alpha(input: &[u8], options: RemoveBgOptions) -> Vec<u16> {
+    let output = remover(options).process_bytes(input).expect("removal succeeds");
+    let output = image::load_from_memory(&output).unwrap().to_rgba16();
+    assert_eq!(output.dimensions(), (WIDTH, HEIGHT));
+    output.pixels().map(|pixel| pixel[3]).collect()
+}
+
+/// Check the alpha of a gradient round trip: low on the dark side, high on
+/// the light side and zero where the input was transparent.
+fn check_gradient(name: &str, input: &[u8], has_alpha: bool) {
+    let alpha = alpha(input, RemoveBgOptions::default());
+    let at = |x: u32, y: u32| alpha[(y * WIDTH + x) as usize];
+    let row = HEIGHT - 1;
+    assert!(at(1, row) < 8192, "{}: dark side alpha {}", name, at(1, row));
+    assert!(at(WIDTH - 2, row) > 57343, "{}: light side alpha {}", name, at(WIDTH - 2, row));
+    assert!(at(WIDTH / 2 - 8, row) < at(WIDTH / 2 + 8, row), "{}: alpha doesn't follow brightness", name);
+    if has_alpha {
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                if transparent(x, y) {
+                    assert_eq!(at(x, y), 0, "{}: transparent input at ({}, {}) became visible", name, x, y);
+                }
+            }
+        }
+        // The opaque part of the same columns keeps its mask
+        assert!(at(WIDTH / 4 - 1, row) > 0, "{}: opaque pixels lost their alpha", name);
+    }
+}
+
+#[test]
+fn truecolor_and_grayscale_inputs_round_trip() {
+    let opacity = |x, y| if transparent(x, y) { 0 } else { 255 };
+    let inputs = [
+        ("L8", DynamicImage::ImageLuma8(image::ImageBuffer::from_fn(WIDTH, HEIGHT, |x, _| Luma([level(x)]))), false),
+        (
+            "La8",
+            DynamicImage::ImageLumaA8(image::ImageBuffer::from_fn(WIDTH, HEIGHT, |x, y| LumaA([level(x), opacity(x, y)]))),
+            true,
+        ),
+        (
+            "L16",
+            DynamicImage::ImageLuma16(image::ImageBuffer::from_fn(WIDTH, HEIGHT, |x, _| Luma([level(x) as u16 * 257]))),
+            false,
+        ),
+        (
+            "La16",
+            DynamicImage::ImageLumaA16(image::ImageBuffer::from_fn(WIDTH, HEIGHT, |x, y| {
+                LumaA([level(x) as u16 * 257, opacity(x, y) as u16 * 257])
+            })),
+            true,
+        ),
+        ("Rgb8", DynamicImage::ImageRgb8(image::ImageBuffer::from_fn(WIDTH, HEIGHT, |x, _| Rgb([level(x); 3]))), false),
+        (
+            "Rgba8",
+            DynamicImage::ImageRgba8(image::ImageBuffer::from_fn(WIDTH, HEIGHT, |x, y| {
+                let l = level(x);
+                Rgba([l, l, l, opacity(x, y)])
+            })),
+            true,
+        ),
+        (
+            "Rgb16",
+            DynamicImage::ImageRgb16(image::ImageBuffer::from_fn(WIDTH, HEIGHT, |x, _| Rgb([level(x) as u16 * 257; 3]))),
+            false,
+        ),
+        (
+            "Rgba16",
+            DynamicImage::ImageRgba16(image::ImageBuffer::from_fn(WIDTH, HEIGHT, |x, y| {
+                let l = level(x) as u16 * 257;
+                Rgba([l, l, l, opacity(x, y) as u16 * 257])
+            })),
+            true,
+        ),
+    ];
+    for (name, image, has_alpha) in inputs {
+        check_gradient(name, &encode_png(&image), has_alpha);
+    }
+
+    // PNG has no float samples; TIFF carries them
+    let rgba32f = DynamicImage::ImageRgba32F(image::ImageBuffer::from_fn(WIDTH, HEIGHT, |x, y| {
+        let l = level(x) as f32 / 255.0;
+        Rgba([l, l, l, opacity(x, y) as f32 / 255.0])
+    }));
+    check_gradient("Rgba32F", &encode(&rgba32f, ImageFormat::Tiff), true);
+}
+
+#[test]
+fn palette_inputs_round_trip() {
+    // 64 gray levels, one per column, with the first two entries fully
+    // transparent copies for the transparent quarter
+    let mut palette = vec![0, 0, 0, 0, 0, 0];
+    let mut trns = vec![0, 0];
+    for x in 2..WIDTH {
+        palette.extend([level(x); 3]);
+        trns.push(255);
+    }
+    let data: Vec<u8> = (0..HEIGHT)
+        .flat_map(|y| (0..WIDTH).map(move |x| if transparent(x, y) { 0 } else { x.max(2) as u8 }))
+        .collect();
+    let indexed = raw_png(png::ColorType::Indexed, png::BitDepth::Eight, Some((palette.clone(), trns)), &data);
+    check_gradient("palette PNG with tRNS", &indexed, true);
+
+    let opaque: Vec<u8> = (0..HEIGHT).flat_map(|_| (0..WIDTH).map(|x| x.max(2) as u8)).collect();
+    let indexed = raw_png(png::ColorType::Indexed, png::BitDepth::Eight, Some((palette, Vec::new())), &opaque);
+    check_gradient("palette PNG", &indexed, false);
+
+    // GIF is always paletted; the frame is quantized to at most 256 colors
+    let mut gif = Vec::new();
+    let frame = RgbaImage::from_fn(WIDTH, HEIGHT, |x, y| {
+        let l = level(x);
+        Rgba([l, l, l, if transparent(x, y) { 0 } else { 255 }])
+    });
+    GifEncoder::new(&mut gif).encode_frame(Frame::new(frame)).unwrap();
+    check_gradient("GIF", &gif, true);
+}
+
+/// A 1-bit PNG of black ink on white paper: a frame around the image and a
+/// bar across the middle.
+fn one_bit_drawing() -> (Vec<u8>, impl Fn(u32, u32) -> bool) {
+    let ink = |x: u32, y: u32| x < 2 || y < 2 || x >= WIDTH - 2 || y >= HEIGHT - 2 || (12..14).contains(&y);
+    let stride = WIDTH.div_ceil(8) as usize;
+    let mut data = vec![0u8; stride * HEIGHT as usize];
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            if !ink(x, y) {
+                data[y as usize * stride + x as usize / 8] |= 0x80 >> (x % 8);
+            }
+        }
+    }
+    (raw_png(png::ColorType::Grayscale, png::BitDepth::One, None, &data), ink)
+}
+
+#[test]
+fn one_bit_inputs_round_trip() {
+    let (png, _) = one_bit_drawing();
+    let alpha = alpha(&png, RemoveBgOptions::default());
+    // The stub keeps the white paper and drops the black ink, blurred a
+    // little by the resize to the model's input size
+    assert_eq!(alpha[(20 * WIDTH + 20) as usize], 65535);
+    assert!(alpha[(12 * WIDTH + 20) as usize] < 16384);
+}
+
+#[test]
+fn line_art_mask_comes_from_luminance() {
+    let (png, ink) = one_bit_drawing();
+    let dir = std::env::temp_dir().join(format!("removebg-line-art-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("drawing.png");
+    std::fs::write(&input, &png).unwrap();
+
+    let options = RemoveBgOptions { line_art: true, ..Default::default() };
+    let report = remover(options).process_file(&input, Some(&dir.join("drawing_nobg.png"))).unwrap();
+    assert!(report.model.is_none(), "the model ran on line art");
+    let output = image::open(&report.output_path).unwrap().to_rgba8();
+    for (x, y, pixel) in output.enumerate_pixels() {
+        let expected = if ink(x, y) { 255 } else { 0 };
+        assert_eq!(pixel[3], expected, "alpha at ({}, {})", x, y);
+    }
+
+    // Photos still go through the model
+    let photo = dir.join("photo.png");
+    DynamicImage::ImageRgb8(testing::synthetic_image(5, WIDTH, HEIGHT)).save(&photo).unwrap();
+    let options = RemoveBgOptions { line_art: true, ..Default::default() };
+    let report = remover(options).process_file(&photo, Some(&dir.join("photo_nobg.png"))).unwrap();
+    assert!(report.model.is_some(), "the model didn't run on a photo");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,108 @@
+//! A/B comparison of option sets (`removebg compare`): the metrics of each
+//! image, one model run per image when the sets differ only after
+//! segmentation, and the table, JSON, CSV and difference image outputs.
+//! Runs use the stub model in `tests/fixtures/models`, whose mask is the
+//! image's brightness, so masks are soft and thresholds change them.
+
+use removebg::compare::{compare_image, parse_option_set, ComparisonSummary};
+use removebg::testing::{self, TestModel};
+use removebg::{Model, RemoveBgOptions};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A directory with two small images and the stub model under two model
+/// names.
+fn workspace(name: &str) -> (PathBuf, Vec<PathBuf>) {
+    let dir = std::env::temp_dir().join(format!("removebg-compare-{}-{}", name, std::process::id()));
+    let models = dir.join("models");
+    std::fs::create_dir_all(&models).unwrap();
+    for model in [Model::U2netp, Model::U2net] {
+        std::fs::copy(TestModel::Brightness.dir().join("u2netp.onnx"), models.join(model.spec().file_name)).unwrap();
+    }
+    let inputs: Vec<PathBuf> = (1..=2).map(|seed| dir.join(format!("image{}.png", seed))).collect();
+    for (seed, input) in (1..).zip(&inputs) {
+        testing::synthetic_image(seed, 64, 48).save(input).unwrap();
+    }
+    (dir, inputs)
+}
+
+fn base(dir: &Path) -> RemoveBgOptions {
+    testing::deterministic(RemoveBgOptions { model: Model::U2netp, model_dir: Some(dir.join("models")), ..Default::default() })
+}
+
+#[test]
+fn post_processing_changes_share_one_inference() {
+    let (dir, inputs) = workspace("shared");
+    let base = base(&dir);
+
+    let same = compare_image(&inputs[0], &base, &base, None).unwrap();
+    assert_eq!((same.mean_alpha_difference, same.changed_percent, same.bounds_iou), (0.0, 0.0, 1.0));
+    assert_eq!((same.size_delta, same.inferences), (0, 1));
+
+    let refined = parse_option_set("threshold=0.5", &base).unwrap();
+    let diff = dir.join("diffs");
+    let compared = compare_image(&inputs[0], &base, &refined, Some(&diff)).unwrap();
+    assert_eq!(compared.inferences, 1);
+    assert!(compared.mean_alpha_difference > 0.0 && compared.changed_percent > 0.0, "{:?}", compared);
+    assert!(compared.bounds_iou > 0.0 && compared.bounds_iou <= 1.0, "{:?}", compared);
+    assert!(compared.size_a > 0 && compared.size_b > 0);
+    assert_eq!(compared.size_delta, compared.size_b as i64 - compared.size_a as i64);
+    assert_eq!(compared.diff_path, Some(diff.join("image1_diff.png")));
+    assert_eq!(image::image_dimensions(diff.join("image1_diff.png")).unwrap(), (64, 48));
+
+    // Another model needs its own run, even when its mask is the same
+    let other = parse_option_set("model=u2net", &base).unwrap();
+    let compared = compare_image(&inputs[0], &base, &other, None).unwrap();
+    assert_eq!((compared.inferences, compared.mean_alpha_difference), (2, 0.0));
+
+    let summary = ComparisonSummary::of(&[same, compared]);
+    assert_eq!((summary.images, summary.inferences, summary.bounds_iou), (2, 3, 1.0));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cli_reports_json_and_csv() {
+    let (dir, inputs) = workspace("cli");
+    let csv = dir.join("results.csv");
+    let run = Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg("compare")
+        .args(&inputs)
+        .args(["--a", "preset=product", "--b", "preset=product,feather=3,threshold=0.4", "--json", "--csv"])
+        .arg(&csv)
+        .args(["--model", "u2netp", "--model-dir"])
+        .arg(dir.join("models"))
+        .output()
+        .expect("removebg runs");
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+    let json: serde_json::Value = serde_json::from_slice(&run.stdout).expect("one JSON object");
+    assert_eq!(json["b"], "preset=product,feather=3,threshold=0.4");
+    assert_eq!(json["summary"]["images"], 2);
+    assert_eq!(json["summary"]["inferences"], 2);
+    assert!(json["summary"]["changed_percent"].as_f64().unwrap() > 0.0);
+    assert!(json["images"][1]["input"].as_str().unwrap().ends_with("image2.png"));
+    let rows = std::fs::read_to_string(&csv).unwrap();
+    assert_eq!(rows.lines().count(), 3);
+    assert!(rows.starts_with("input,mean_alpha_difference,changed_percent,bounds_iou"));
+
+    let table = Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg("compare")
+        .arg(&inputs[0])
+        .arg(dir.join("missing.png"))
+        .args(["--a", "", "--b", "dilate=2", "--model", "u2netp", "--model-dir"])
+        .arg(dir.join("models"))
+        .output()
+        .expect("removebg runs");
+    // The missing input's code
+    assert_eq!(table.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&table.stdout);
+    assert!(stdout.contains("ALPHA DIFF") && stdout.contains("image1.png") && stdout.contains("ALL"), "{}", stdout);
+    assert!(String::from_utf8_lossy(&table.stderr).contains("missing.png"));
+
+    let invalid = Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .args(["compare", "x.png", "--a", "colour=red", "--b", ""])
+        .output()
+        .expect("removebg runs");
+    assert_eq!(invalid.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&invalid.stderr).contains("unknown setting 'colour'"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,58 @@
+//! `removebg generate` tests: completions and man pages must follow the
+//! flags the binary actually has.
+
+use std::collections::BTreeSet;
+use std::process::Command;
+
+fn removebg(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_removebg")).args(args).output().expect("removebg runs");
+    assert!(output.status.success(), "removebg {:?} failed: {}", args, String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).expect("output is UTF-8")
+}
+
+/// Long flags listed by `removebg --help`.
+fn top_level_flags() -> BTreeSet<String> {
+    let help = removebg(&["--help"]);
+    let flags: BTreeSet<String> = help
+        .split(|c: char| !(c.is_ascii_alphanumeric() || c == '-'))
+        .filter(|word| word.len() > 2 && word.starts_with("--"))
+        .map(str::to_string)
+        .collect();
+    assert!(flags.contains("--output") && flags.contains("--model-dir"), "unexpected help: {}", help);
+    flags
+}
+
+#[test]
+fn bash_completions_mention_every_top_level_flag() {
+    let script = removebg(&["generate", "--shell", "bash"]);
+    let missing: Vec<_> = top_level_flags().into_iter().filter(|flag| !script.contains(flag.as_str())).collect();
+    assert!(missing.is_empty(), "flags missing from the bash completions: {:?}", missing);
+    // Nested subcommands complete too
+    assert!(script.contains("removebg__subcmd__model__subcmd__fetch"));
+}
+
+#[test]
+fn zsh_completions_complete_directories_for_directory_flags() {
+    let script = removebg(&["generate", "--shell", "zsh"]);
+    let line = script
+        .lines()
+        .find(|line| line.contains("--output-dir=[For directory input"))
+        .expect("--output-dir is completed");
+    assert!(line.contains("_files -/"), "{}", line);
+}
+
+#[test]
+fn man_pages_cover_subcommands() {
+    let page = removebg(&["generate", "--man"]);
+    assert!(page.contains(".TH removebg 1"));
+    assert!(page.contains("\\-\\-output\\-dir"));
+
+    let dir = std::env::temp_dir().join(format!("removebg-man-{}", std::process::id()));
+    removebg(&["generate", "--man", "--out-dir", dir.to_str().unwrap()]);
+    for page in ["removebg.1", "removebg-model.1", "removebg-model-fetch.1", "removebg-watch.1"] {
+        assert!(dir.join(page).is_file(), "{} was not written", page);
+    }
+    // The generator itself is hidden
+    assert!(!dir.join("removebg-generate.1").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,185 @@
+//! `REMOVEBG_*` environment variables: parsing, precedence of flags over the
+//! environment over the defaults, the order the model directory is chosen
+//! in, and the `ENVIRONMENT:` section of `--help`.
+//! CLI runs use the stub model in `tests/fixtures/models`, with variables
+//! set only for the child process.
+
+use removebg::config::{Config, ENV_VARS};
+use removebg::{testing::{self, TestModel}, Background, Model, OutputFormat, RemoveBgError};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn from_vars(vars: &[(&str, &str)]) -> removebg::Result<Config> {
+    let vars: HashMap<&str, OsString> = vars.iter().map(|&(name, value)| (name, value.into())).collect();
+    Config::from_vars(|name| vars.get(name).cloned())
+}
+
+/// A fresh directory with one test image, named for `test`.
+fn input(test: &str) -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("removebg-config-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("photo.png");
+    testing::synthetic_image(1, 64, 48).save(&input).unwrap();
+    (dir, input)
+}
+
+/// Run the CLI on `input` with `--json`, the given variables and flags, and
+/// none of the crate's other variables.
+fn run(input: &Path, vars: &[(&str, &str)], flags: &[&str]) -> (Output, serde_json::Value) {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_removebg"));
+    for var in ENV_VARS {
+        command.env_remove(var.name);
+    }
+    let output = command.envs(vars.iter().copied()).arg(input).args(["--json", "--force"]).args(flags).output().unwrap();
+    let json = serde_json::from_slice(&output.stdout).unwrap_or(serde_json::Value::Null);
+    (output, json)
+}
+
+fn output_name(json: &serde_json::Value) -> String {
+    let output = json["output"].as_str().unwrap_or_else(|| panic!("no output in {}", json));
+    Path::new(output).file_name().unwrap().to_string_lossy().into_owned()
+}
+
+#[test]
+fn variables_parse_like_flags_and_empty_ones_are_unset() {
+    let config = from_vars(&[
+        ("REMOVEBG_MODEL", "u2netp"),
+        ("REMOVEBG_FORMAT", "webp"),
+        ("REMOVEBG_QUALITY", " 80 "),
+        ("REMOVEBG_BG_COLOR", "#ff8000"),
+        ("REMOVEBG_OFFLINE", "yes"),
+        ("REMOVEBG_DEVICE", ""),
+    ])
+    .unwrap();
+    assert_eq!(config.model, Some(Model::U2netp));
+    assert_eq!(config.format, Some(OutputFormat::WebP));
+    assert_eq!(config.quality, Some(80.0));
+    assert_eq!(config.background, Some(Background::Color([255, 128, 0])));
+    assert_eq!(config.offline, Some(true));
+    assert_eq!(config.device, None);
+
+    let mut options = removebg::RemoveBgOptions::default();
+    config.apply(&mut options);
+    assert_eq!((options.model, options.format, options.quality), (Model::U2netp, Some(OutputFormat::WebP), Some(80.0)));
+    assert!(options.download.offline);
+    assert_eq!(options.device, removebg::Device::default());
+}
+
+#[test]
+fn invalid_values_name_the_variable() {
+    for (name, value) in [
+        ("REMOVEBG_DEVICE", "gpu"),
+        ("REMOVEBG_MODEL", "u3net"),
+        ("REMOVEBG_QUALITY", "150"),
+        ("REMOVEBG_OFFLINE", "maybe"),
+        ("REMOVEBG_BG_COLOR", "#12"),
+    ] {
+        let error = from_vars(&[(name, value)]).unwrap_err();
+        assert!(matches!(error, RemoveBgError::InvalidConfig(_)), "{:?}", error);
+        assert!(error.to_string().contains(name), "{}", error);
+        assert_eq!(error.exit_code(), 2);
+    }
+
+    // The CLI refuses to start rather than falling back to the default
+    let (dir, input) = input("invalid");
+    let (output, _) = run(&input, &[("REMOVEBG_DEVICE", "gpu")], &[]);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("REMOVEBG_DEVICE") && stderr.contains("gpu"), "{}", stderr);
+    assert!(!dir.join("photo_nobg.png").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn flags_win_over_the_environment_which_wins_over_defaults() {
+    let (dir, input) = input("precedence");
+    let models = TestModel::Brightness.dir();
+    let models = models.to_str().unwrap();
+    let stub = [("REMOVEBG_MODEL", "u2netp"), ("REMOVEBG_MODEL_DIR", models)];
+    let with = |extra: &[(&'static str, &'static str)]| -> Vec<(&str, &str)> { stub.iter().chain(extra).copied().collect() };
+
+    // Model and model directory: the environment selects the stub model...
+    let (output, json) = run(&input, &stub, &[]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(json["success"], true);
+    // ...a flag overrides each of them, and the default model isn't cached
+    let empty = dir.join("empty");
+    std::fs::create_dir_all(&empty).unwrap();
+    let (output, _) = run(&input, &stub, &["--model", "u2net", "--offline"]);
+    assert_eq!(output.status.code(), Some(10));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not cached"));
+    let (output, _) = run(&input, &stub, &["--model-dir", empty.to_str().unwrap(), "--offline"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not cached"), "{:?}", output);
+    let (output, _) = run(&input, &[("REMOVEBG_MODEL_DIR", models)], &["--offline"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Model u2net is not cached"), "{:?}", output);
+
+    // Offline: the variable disables downloads, and so does the flag when the variable says otherwise
+    let (output, _) = run(&input, &[("REMOVEBG_MODEL_DIR", empty.to_str().unwrap()), ("REMOVEBG_OFFLINE", "1")], &[]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("downloads are disabled"), "{:?}", output);
+    let (output, _) = run(&input, &[("REMOVEBG_MODEL_DIR", empty.to_str().unwrap()), ("REMOVEBG_OFFLINE", "0")], &["--offline"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("downloads are disabled"), "{:?}", output);
+
+    // Format
+    assert_eq!(output_name(&run(&input, &stub, &[]).1), "photo_nobg.png");
+    assert_eq!(output_name(&run(&input, &with(&[("REMOVEBG_FORMAT", "webp")]), &[]).1), "photo_nobg.webp");
+    assert_eq!(output_name(&run(&input, &with(&[("REMOVEBG_FORMAT", "webp")]), &["--format", "tiff"]).1), "photo_nobg.tiff");
+
+    // Background color
+    assert_eq!(run(&input, &stub, &[]).1["background_color"], serde_json::Value::Null);
+    let white = with(&[("REMOVEBG_BG_COLOR", "white")]);
+    assert_eq!(run(&input, &white, &[]).1["background_color"], "#ffffff");
+    assert_eq!(run(&input, &white, &["--bg-color", "#102030"]).1["background_color"], "#102030");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn help_lists_every_variable() {
+    let output = Command::new(env!("CARGO_BIN_EXE_removebg")).arg("--help").output().unwrap();
+    let help = String::from_utf8_lossy(&output.stdout);
+    let section = help.split("ENVIRONMENT:\n").nth(1).unwrap_or_else(|| panic!("no ENVIRONMENT section in {}", help));
+    for var in ENV_VARS {
+        assert!(section.contains(var.name), "{} is missing from {}", var.name, section);
+    }
+    // Variables clap reads for a flag are in the table too
+    for (start, _) in help.match_indices("[env: ") {
+        let name: String = help[start + 6..].chars().take_while(|c| c.is_ascii_uppercase() || *c == '_').collect();
+        assert!(ENV_VARS.iter().any(|var| var.name == name), "{} is not in ENV_VARS", name);
+    }
+}
+
+/// The model directory picked from the flag, the variables, the legacy
+/// `~/.u2net` and the cache directory, in that order. The platform
+/// directories come from `HOME` and `XDG_CACHE_HOME` on Linux only.
+#[cfg(target_os = "linux")]
+#[test]
+fn model_directories_are_chosen_in_order() {
+    let (dir, _) = input("model-dir");
+    let model_path = |vars: &[(&str, PathBuf)], flags: &[&str]| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_removebg"));
+        for var in ENV_VARS {
+            command.env_remove(var.name);
+        }
+        command.env("HOME", dir.join("home")).env("XDG_CACHE_HOME", dir.join("cache"));
+        let output = command.envs(vars.iter().cloned()).args(["model", "info", "u2netp"]).args(flags).output().unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let line = stdout.lines().find_map(|line| line.strip_prefix("Path:")).unwrap_or_else(|| panic!("no path in {:?}", output));
+        PathBuf::from(line.trim().trim_end_matches(" (not cached)")).parent().unwrap().to_path_buf()
+    };
+
+    assert_eq!(model_path(&[], &[]), dir.join("cache/removebg"));
+    std::fs::create_dir_all(dir.join("home/.u2net")).unwrap();
+    assert_eq!(model_path(&[], &[]), dir.join("cache/removebg"), "an empty ~/.u2net isn't used");
+    std::fs::write(dir.join("home/.u2net/u2net.onnx"), b"").unwrap();
+    assert_eq!(model_path(&[], &[]), dir.join("home/.u2net"));
+
+    let u2net_home = [("U2NET_HOME", dir.join("u2net-home"))];
+    assert_eq!(model_path(&u2net_home, &[]), dir.join("u2net-home"));
+    let both = [("U2NET_HOME", dir.join("u2net-home")), ("REMOVEBG_MODEL_DIR", dir.join("models"))];
+    assert_eq!(model_path(&both, &[]), dir.join("models"));
+    let flag = dir.join("flag");
+    assert_eq!(model_path(&both, &["--model-dir", flag.to_str().unwrap()]), flag);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
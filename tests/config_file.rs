@@ -0,0 +1,220 @@
+//! `removebg.toml` config files: discovery, precedence of flags over the
+//! environment over the project's file over the user's over the defaults,
+//! and messages that point at the offending line. CLI runs use the stub
+//! model in `tests/fixtures/models` and start in a fresh directory, so no
+//! file above it is found, with no user config file unless a test makes one.
+
+use removebg::config::{self, Config, ENV_VARS};
+use removebg::{testing::{self, TestModel}, Background, Model, OutputFormat, RemoveBgError, RemoveBgOptions};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// A fresh directory named for `test`.
+fn temp_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("removebg-config-file-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn write(path: &Path, text: &str) -> PathBuf {
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, text).unwrap();
+    path.to_path_buf()
+}
+
+/// Run the CLI in `dir` with the given variables and arguments, and none of
+/// the crate's other variables; the user config file is looked for in
+/// `dir/user` unless `XDG_CONFIG_HOME` is given.
+fn run(dir: &Path, vars: &[(&str, &str)], args: &[&str]) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_removebg"));
+    for var in ENV_VARS {
+        command.env_remove(var.name);
+    }
+    command.env(config::CONFIG_HOME_ENV, dir.join("user"));
+    command.current_dir(dir).envs(vars.iter().copied()).args(args).output().unwrap()
+}
+
+fn output_name(output: &Output) -> String {
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap_or_default();
+    let path = json["output"].as_str().unwrap_or_else(|| panic!("no output in {:?}", output));
+    Path::new(path).file_name().unwrap().to_string_lossy().into_owned()
+}
+
+#[test]
+fn the_nearest_file_is_found_and_removebg_toml_comes_first() {
+    let dir = temp_dir("discover");
+    let nested = dir.join("project/shoots/day1");
+    std::fs::create_dir_all(&nested).unwrap();
+    assert_eq!(config::discover(&nested), None);
+
+    let top = write(&dir.join("project/.removebg.toml"), "");
+    assert_eq!(config::discover(&nested), Some(top));
+    let visible = write(&dir.join("project/removebg.toml"), "");
+    assert_eq!(config::discover(&nested), Some(visible));
+    let nearer = write(&dir.join("project/shoots/.removebg.toml"), "");
+    assert_eq!(config::discover(&nested), Some(nearer));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn files_deserialize_into_the_config_and_options() {
+    let dir = temp_dir("parse");
+    let path = write(
+        &dir.join("removebg.toml"),
+        "model = \"u2netp\"\nmodel_dir = \"models\"\nformat = \"webp\"\nquality = 85\nbg_color = \"white\"\noffline = true\n",
+    );
+    let config = Config::from_toml(&path, true).unwrap();
+    assert_eq!(config.model, Some(Model::U2netp));
+    // Relative to the file, not the working directory
+    assert_eq!(config.model_dir, Some(dir.join("models")));
+    assert_eq!(config.format, Some(OutputFormat::WebP));
+    assert_eq!(config.quality, Some(85.0));
+    assert_eq!(config.background, Some(Background::Color([255, 255, 255])));
+    assert_eq!(config.offline, Some(true));
+    assert_eq!(config.device, None);
+
+    let options = RemoveBgOptions::from_toml(&path).unwrap();
+    assert_eq!((options.model, options.format, options.quality), (Model::U2netp, Some(OutputFormat::WebP), Some(85.0)));
+    assert!(options.download.offline);
+
+    // The environment's settings win, and the file fills in the rest
+    let env = Config { format: Some(OutputFormat::Tiff), ..Default::default() };
+    let merged = env.over(config);
+    assert_eq!((merged.format, merged.model), (Some(OutputFormat::Tiff), Some(Model::U2netp)));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn errors_point_at_the_offending_line() {
+    let dir = temp_dir("errors");
+    for (text, line, expected) in [
+        ("model = \"u2netp\"\n\nquality = \"high\"\n", 3, "expected f32"),
+        ("model = \"u2netp\"\ndevice = \"gpu\"\n", 2, "unknown device 'gpu'"),
+        ("offline = \"yes\"\n", 1, "expected a boolean"),
+        ("quality = 150\n", 1, "invalid quality"),
+        ("model = \"u2netp\"\nbg_color = \n", 2, ""),
+    ] {
+        let path = write(&dir.join("removebg.toml"), text);
+        let error = Config::from_toml(&path, false).unwrap_err();
+        assert!(matches!(error, RemoveBgError::InvalidConfig(_)), "{:?}", error);
+        let message = error.to_string();
+        assert!(message.contains(&path.display().to_string()), "{}", message);
+        assert!(message.contains(&format!("at line {}", line)), "{}", message);
+        assert!(message.contains(expected), "{}", message);
+    }
+
+    // Unknown keys are ignored, or rejected with their line when strict
+    let path = write(&dir.join("removebg.toml"), "model = \"u2netp\"\nfeather = 3\n");
+    assert_eq!(Config::from_toml(&path, false).unwrap().model, Some(Model::U2netp));
+    let message = Config::from_toml(&path, true).unwrap_err().to_string();
+    assert!(message.contains("unknown key 'feather' on line 2"), "{}", message);
+
+    // The CLI warns or, with --strict-config, refuses to start
+    let output = run(&dir, &[], &["--show-config"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Warning:"), "{:?}", output);
+    let output = run(&dir, &[], &["--show-config", "--strict-config"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown key 'feather'"), "{:?}", output);
+    let output = run(&dir, &[], &["--show-config", "--config", "missing.toml"]);
+    assert_eq!(output.status.code(), Some(2));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn flags_win_over_the_environment_which_wins_over_the_file() {
+    let dir = temp_dir("precedence");
+    let models = TestModel::Brightness.dir();
+    write(&dir.join("removebg.toml"), &format!("model = \"u2netp\"\nmodel_dir = {:?}\nformat = \"webp\"\n", models));
+    let input = dir.join("photo.png");
+    testing::synthetic_image(1, 64, 48).save(&input).unwrap();
+    let input = input.to_str().unwrap();
+    let flags = [input, "--json", "--force"];
+
+    // The file selects the stub model and the format...
+    let output = run(&dir, &[], &flags);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(output_name(&output), "photo_nobg.webp");
+    // ...the environment overrides the file, and a flag both
+    assert_eq!(output_name(&run(&dir, &[("REMOVEBG_FORMAT", "tiff")], &flags)), "photo_nobg.tiff");
+    let png = [input, "--json", "--force", "--format", "png"];
+    assert_eq!(output_name(&run(&dir, &[("REMOVEBG_FORMAT", "tiff")], &png)), "photo_nobg.png");
+
+    // --config replaces discovery
+    let other = write(&dir.join("other.toml"), &format!("model = \"u2netp\"\nmodel_dir = {:?}\nformat = \"tiff\"\n", models));
+    let with_config = [input, "--json", "--force", "--config", other.to_str().unwrap()];
+    assert_eq!(output_name(&run(&dir, &[], &with_config)), "photo_nobg.tiff");
+
+    // output_dir applies to directory input
+    let shoot = dir.join("shoot");
+    std::fs::create_dir_all(&shoot).unwrap();
+    testing::synthetic_image(2, 32, 32).save(shoot.join("a.png")).unwrap();
+    write(&dir.join("removebg.toml"), &format!("model = \"u2netp\"\nmodel_dir = {:?}\noutput_dir = \"cutouts\"\n", models));
+    let output = run(&dir, &[], &["shoot", "-q"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(dir.join("cutouts/a_nobg.png").is_file(), "{:?}", output);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn the_project_file_wins_over_the_user_file() {
+    let dir = temp_dir("user");
+    let user = write(
+        &dir.join("user/removebg/removebg.toml"),
+        &format!("model = \"u2netp\"\nmodel_dir = {:?}\nformat = \"bmp\"\n", TestModel::Brightness.dir()),
+    );
+    let input = dir.join("photo.png");
+    testing::synthetic_image(1, 64, 48).save(&input).unwrap();
+    let input = input.to_str().unwrap();
+    let flags = [input, "--json", "--force"];
+
+    // The user's file alone selects the stub model and the format...
+    let output = run(&dir, &[], &flags);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(output_name(&output), "photo_nobg.bmp");
+    // ...the project's file overrides the format and keeps the user's model,
+    // the environment overrides both, and a flag all three
+    let file = write(&dir.join("removebg.toml"), "format = \"webp\"\n");
+    assert_eq!(output_name(&run(&dir, &[], &flags)), "photo_nobg.webp");
+    assert_eq!(output_name(&run(&dir, &[("REMOVEBG_FORMAT", "tiff")], &flags)), "photo_nobg.tiff");
+    let png = [input, "--json", "--force", "--format", "png"];
+    assert_eq!(output_name(&run(&dir, &[("REMOVEBG_FORMAT", "tiff")], &png)), "photo_nobg.png");
+
+    let output = run(&dir, &[], &["--show-config"]);
+    let listing = String::from_utf8_lossy(&output.stdout);
+    for line in [
+        format!("# User config file: {}", user.display()),
+        format!("model = \"u2netp\"  # {}", user.display()),
+        format!("format = \"webp\"  # {}", file.display()),
+    ] {
+        assert!(listing.lines().any(|l| l == line), "{} is missing from {}", line, listing);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn show_config_lists_each_setting_with_its_source() {
+    let dir = temp_dir("show");
+    let path = write(&dir.join(".removebg.toml"), "model = \"u2netp\"\nquality = 90\n");
+    let output = run(&dir, &[("REMOVEBG_BG_COLOR", "#102030")], &["--show-config", "--device", "cpu"]);
+    assert!(output.status.success(), "{:?}", output);
+    let listing = String::from_utf8_lossy(&output.stdout);
+    let file = path.display().to_string();
+    assert!(listing.contains(&format!("# Config file: {}", file)), "{}", listing);
+    for line in [
+        format!("model = \"u2netp\"  # {}", file),
+        format!("quality = 90.0  # {}", file),
+        "bg_color = \"#102030\"  # REMOVEBG_BG_COLOR".to_string(),
+        "device = \"cpu\"  # --device".to_string(),
+        "offline = false  # default".to_string(),
+        "# output_dir is not set  # default".to_string(),
+    ] {
+        assert!(listing.lines().any(|l| l == line), "{} is missing from {}", line, listing);
+    }
+
+    // The listing is a valid config file itself
+    let listed: Config = toml::from_str(&listing).unwrap();
+    assert_eq!(listed.model, Some(Model::U2netp));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
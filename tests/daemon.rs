@@ -0,0 +1,136 @@
+//! `removebg daemon`: JSON commands on stdin, one flushed result line per
+//! command on stdout, with the model loaded once. Runs use the stub model in
+//! `tests/fixtures/models`.
+#![cfg(feature = "serde")]
+
+use image::DynamicImage;
+use removebg::{daemon, fallback, testing::{self, TestModel}, Model, QualityFallback, RemoveBgOptions};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+fn temp_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("removebg-daemon-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn commands_share_one_model_load() {
+    let dir = temp_dir("commands");
+    for (seed, name) in ["a.png", "b.png"].into_iter().enumerate() {
+        DynamicImage::ImageRgb8(testing::synthetic_image(seed as u64, 48, 32)).save(dir.join(name)).unwrap();
+    }
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .args(["daemon"])
+        .args(TestModel::Brightness.args())
+        .current_dir(&dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = daemon.stdin.take().unwrap();
+    let mut results = BufReader::new(daemon.stdout.take().unwrap()).lines();
+
+    // Each result is flushed before the next command is read
+    writeln!(stdin, r#"{{"input": "a.png", "output": "a-out.png", "id": 1}}"#).unwrap();
+    let first: Value = serde_json::from_str(&results.next().unwrap().unwrap()).unwrap();
+    assert_eq!((&first["id"], &first["success"]), (&Value::from(1), &Value::from(true)), "{}", first);
+    assert!(first["report"]["output_path"].as_str().unwrap().ends_with("a-out.png"), "{}", first);
+    assert!(dir.join("a-out.png").is_file());
+
+    // A malformed line fails on its own, and the options of a command
+    // apply to it only
+    writeln!(stdin, "{{not json").unwrap();
+    writeln!(stdin, r#"{{"input": "b.png", "options": {{"background": "white", "format": "jpeg"}}, "id": "b"}}"#).unwrap();
+    writeln!(stdin, r#"{{"cmd": "shutdown"}}"#).unwrap();
+    writeln!(stdin, r#"{{"input": "a.png", "id": "after shutdown"}}"#).unwrap();
+    drop(stdin);
+
+    let rest: Vec<Value> = results.map(|line| serde_json::from_str(&line.unwrap()).unwrap()).collect();
+    assert_eq!(rest.len(), 2, "{:?}", rest);
+    assert_eq!(rest[0]["success"], false);
+    assert_eq!(rest[0]["error"]["kind"], "invalid_config");
+    assert!(rest[0].get("id").is_none());
+    assert_eq!((&rest[1]["id"], &rest[1]["success"]), (&Value::from("b"), &Value::from(true)), "{}", rest[1]);
+    assert_eq!(image::open(dir.join("b_nobg.jpg")).unwrap().color(), image::ColorType::Rgb8);
+    assert!(!dir.join("a_nobg.png").exists());
+
+    let output = daemon.wait_with_output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(stderr.matches("Loaded u2netp").count(), 1, "{}", stderr);
+    assert!(stderr.contains("Processed 2, failed 1"), "{}", stderr);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn options_and_errors() {
+    let dir = temp_dir("options");
+    let input = dir.join("a.png");
+    DynamicImage::ImageRgb8(testing::synthetic_image(3, 40, 30)).save(&input).unwrap();
+    let options = TestModel::Brightness.options(RemoveBgOptions::default());
+    let renamed = json!({"input": input, "options": {"naming": {"suffix": "-cut"}}});
+    let commands = [
+        renamed.to_string(),
+        // The output exists now
+        renamed.to_string(),
+        json!({"input": input, "options": {"quality": "high"}}).to_string(),
+        json!({"output": "x.png"}).to_string(),
+        json!({"cmd": "restart", "id": 7}).to_string(),
+        "[1, 2]".to_string(),
+        String::new(),
+        json!({"input": input, "options": {"naming": {"suffix": "-cut"}, "overwrite": true}}).to_string(),
+    ];
+    let mut output = Vec::new();
+    let summary = daemon::run(commands.join("\n").as_bytes(), &mut output, &options).unwrap();
+    assert_eq!((summary.processed, summary.failed), (2, 5));
+
+    let results: Vec<Value> = output.lines().map(|line| serde_json::from_str(&line.unwrap()).unwrap()).collect();
+    let kinds: Vec<&str> = results.iter().map(|result| result["error"]["kind"].as_str().unwrap_or("ok")).collect();
+    assert_eq!(kinds, ["ok", "output_exists", "invalid_config", "invalid_config", "invalid_config", "invalid_config", "ok"]);
+    assert!(dir.join("a-cut.png").is_file());
+    assert_eq!(results[4]["id"], 7);
+    assert!(results[2]["error"]["message"].as_str().unwrap().contains("expected f32"), "{}", results[2]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn deadlines_report_the_downgrades() {
+    // The stub model under both names, with u2net and u2netp measured as far too slow
+    let dir = temp_dir("deadline");
+    for model in [Model::U2net, Model::U2netp] {
+        std::fs::copy(TestModel::Brightness.dir().join("u2netp.onnx"), dir.join(model.spec().file_name)).unwrap();
+    }
+    let input = dir.join("a.png");
+    DynamicImage::ImageRgb8(testing::synthetic_image(4, 48, 32)).save(&input).unwrap();
+    let options = RemoveBgOptions {
+        model: Model::U2net,
+        model_dir: Some(dir.clone()),
+        quality_fallback: Some(QualityFallback { deadline: Duration::from_millis(500) }),
+        ..Default::default()
+    };
+    for model in [Model::U2net, Model::U2netp] {
+        fallback::calibration().set(model, options.backend, options.device, Duration::from_secs(10));
+    }
+
+    let commands = [
+        json!({"input": input, "output": dir.join("two-pass.png"), "options": {"two_pass": true}}),
+        json!({"input": input, "output": dir.join("relaxed.png"), "options": {"quality_fallback": {"deadline_ms": 60000.0}}}),
+    ];
+    let commands: Vec<String> = commands.iter().map(Value::to_string).collect();
+    let mut output = Vec::new();
+    let summary = daemon::run(commands.join("\n").as_bytes(), &mut output, &options).unwrap();
+    assert_eq!((summary.processed, summary.failed), (2, 0));
+
+    let results: Vec<Value> = output.lines().map(|line| serde_json::from_str(&line.unwrap()).unwrap()).collect();
+    let model = &results[0]["report"]["model"];
+    assert_eq!((&model["model"], &model["downgrades"]), (&json!("u2netp"), &json!(["model", "refinement"])), "{}", results[0]);
+    let model = &results[1]["report"]["model"];
+    assert_eq!((&model["model"], &model["downgrades"]), (&json!("u2net"), &json!([])), "{}", results[1]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,111 @@
+//! `removebg doctor`: a usable model cache passes, a model that fails its
+//! checksum or a model directory that can't be created fails with exit code 3,
+//! and with `load-dynamic` a missing ONNX Runtime library is reported with
+//! installation hints instead of a panic.
+
+use removebg::doctor::{self, CheckStatus};
+use removebg::testing::TestModel;
+use removebg::{Backend, RemoveBgOptions};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn temp_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("removebg-doctor-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Run `removebg doctor` with the tract engine, where a missing ONNX Runtime
+/// is only a warning.
+fn doctor(model_dir: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .args(["doctor", "--backend", "tract", "--model", "u2netp", "--model-dir"])
+        .arg(model_dir)
+        .env("ORT_DYLIB_PATH", "")
+        .output()
+        .unwrap()
+}
+
+fn status(checks: &[doctor::Check], name: &str) -> CheckStatus {
+    checks.iter().find(|check| check.name == name).unwrap_or_else(|| panic!("no {} check", name)).status
+}
+
+#[test]
+fn cached_model_passes_and_missing_model_warns() {
+    let options = TestModel::Brightness.options(RemoveBgOptions { backend: Backend::Tract, ..Default::default() });
+    let checks = doctor::run(&options);
+    assert_eq!(checks.iter().map(|check| check.name).collect::<Vec<_>>(), ["ONNX Runtime", "Model directory", "Model", "CPUs"]);
+    assert_eq!(status(&checks, "Model directory"), CheckStatus::Pass);
+    assert_eq!(status(&checks, "Model"), CheckStatus::Pass);
+    assert_ne!(status(&checks, "ONNX Runtime"), CheckStatus::Fail);
+
+    // A directory that doesn't exist yet is fine if it can be created, and
+    // the model is downloaded into it later, unless downloads are off
+    let dir = temp_dir("missing");
+    let options = RemoveBgOptions { model_dir: Some(dir.join("models/nested")), ..options };
+    let checks = doctor::run(&options);
+    assert_eq!(status(&checks, "Model directory"), CheckStatus::Pass);
+    assert_eq!(status(&checks, "Model"), CheckStatus::Warn);
+    assert!(!dir.join("models").exists(), "doctor created the model directory");
+    let mut offline = options.clone();
+    offline.download.offline = true;
+    assert_eq!(status(&doctor::run(&offline), "Model"), CheckStatus::Fail);
+
+    let output = doctor(&TestModel::Brightness.dir());
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with(&format!("removebg {}", env!("CARGO_PKG_VERSION"))), "{}", stdout);
+    assert!(stdout.contains("PASS Model directory"), "{}", stdout);
+    assert!(stdout.contains("PASS Model ") && stdout.contains("u2netp.onnx"), "{}", stdout);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn corrupt_model_and_unusable_directory_fail() {
+    let dir = temp_dir("corrupt");
+    std::fs::copy(TestModel::Brightness.dir().join("u2netp.onnx"), dir.join("u2netp.onnx")).unwrap();
+    std::fs::write(dir.join("u2netp.onnx.sha256"), "0".repeat(64)).unwrap();
+    let output = doctor(&dir);
+    assert_eq!(output.status.code(), Some(3), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("FAIL Model ") && stdout.contains("delete it to download it again"), "{}", stdout);
+    assert!(stdout.contains("PASS Model directory"), "{}", stdout);
+
+    // A file where the model directory should be
+    let file = dir.join("not-a-dir");
+    std::fs::write(&file, b"").unwrap();
+    let output = doctor(&file);
+    assert_eq!(output.status.code(), Some(3), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("is not a directory"), "{:?}", output);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "load-dynamic")]
+#[test]
+fn missing_onnx_runtime_is_reported_with_hints() {
+    let dir = temp_dir("no-ort");
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_removebg")).args(args).env("ORT_DYLIB_PATH", &dir).output().unwrap()
+    };
+    let model_dir = TestModel::Brightness.dir().to_string_lossy().into_owned();
+
+    let output = run(&["doctor", "--model", "u2netp", "--model-dir", &model_dir]);
+    assert_eq!(output.status.code(), Some(3), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("FAIL ONNX Runtime"), "{}", stdout);
+    assert!(stdout.contains(&format!("ORT_DYLIB_PATH points to {}", dir.display())), "{}", stdout);
+    assert!(stdout.contains(doctor::onnx_runtime_hint()), "{}", stdout);
+    assert!(stdout.contains("PASS Model "), "{}", stdout);
+
+    // Processing fails with a model error and the hint, not a panic
+    let input = dir.join("input.png");
+    removebg::testing::synthetic_image(1, 32, 32).save(&input).unwrap();
+    let output = run(&[input.to_str().unwrap(), "--model", "u2netp", "--model-dir", &model_dir, "--backend", "ort"]);
+    assert_eq!(output.status.code(), Some(9), "{:?}", output);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("ONNX Runtime could not be loaded"), "{}", stderr);
+    assert!(stderr.contains(&format!("Hint: {}", doctor::onnx_runtime_hint())), "{}", stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
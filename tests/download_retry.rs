@@ -0,0 +1,161 @@
+//! Retrying model downloads: 5xx responses and dropped connections are
+//! retried, resuming with a range request, a 404 is not, and the final
+//! error names every attempt. A local server plays each failure.
+
+use removebg::testing::TestModel;
+use removebg::{prepare_model, DownloadOptions, Model, RemoveBgError, RemoveBgOptions, RetryPolicy};
+use sha2::{Digest, Sha256};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How the server answers one request.
+#[derive(Debug, Clone, Copy)]
+enum Reply {
+    /// An empty response with this status.
+    Status(u16),
+    /// Announce the whole model but close the connection after this many
+    /// bytes.
+    Truncated(usize),
+    /// The model, or the requested range of it with status 206.
+    Model,
+}
+
+fn fixture_model() -> Vec<u8> {
+    std::fs::read(TestModel::Brightness.dir().join("u2netp.onnx")).unwrap()
+}
+
+/// Serve the stub model on a local port, answering the n-th request with
+/// `replies[n]` (the last reply repeats), and record each request's `Range`
+/// header.
+fn serve(replies: Vec<Reply>) -> (String, Arc<Mutex<Vec<Option<String>>>>) {
+    let body = fixture_model();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/u2netp.onnx", listener.local_addr().unwrap());
+    let ranges = Arc::new(Mutex::new(Vec::new()));
+    let recorded = ranges.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            let mut range = None;
+            while reader.read_line(&mut line).is_ok_and(|read| read > 2) {
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("range") {
+                        range = Some(value.trim().to_string());
+                    }
+                }
+                line.clear();
+            }
+            let request = {
+                let mut ranges = recorded.lock().unwrap();
+                ranges.push(range.clone());
+                ranges.len() - 1
+            };
+
+            let response = match replies[request.min(replies.len() - 1)] {
+                Reply::Status(code) => format!("HTTP/1.1 {} Failed\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", code).into_bytes(),
+                Reply::Truncated(sent) => {
+                    let mut response =
+                        format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len()).into_bytes();
+                    response.extend(&body[..sent]);
+                    response
+                }
+                Reply::Model => {
+                    let start = range.and_then(|range| range.strip_prefix("bytes=")?.strip_suffix('-')?.parse().ok());
+                    let mut response = match start {
+                        Some(start) => format!(
+                            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\n",
+                            start,
+                            body.len() - 1,
+                            body.len(),
+                            body.len() - start
+                        ),
+                        None => format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n", body.len()),
+                    }
+                    .into_bytes();
+                    response.extend(b"Connection: close\r\n\r\n");
+                    response.extend(&body[start.unwrap_or(0)..]);
+                    response
+                }
+            };
+            let _ = stream.write_all(&response);
+        }
+    });
+    (url, ranges)
+}
+
+/// Options downloading the stub model from `url` into a fresh directory,
+/// with three attempts and short delays.
+fn options(test: &str, url: String) -> (RemoveBgOptions, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("removebg-download-retry-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let download = DownloadOptions {
+        url: Some(url),
+        sha256: Some(format!("{:x}", Sha256::digest(fixture_model()))),
+        retry: RetryPolicy { attempts: 3, initial_delay: Duration::from_millis(10), max_delay: Duration::from_millis(50) },
+        ..Default::default()
+    };
+    (RemoveBgOptions { model: Model::U2netp, model_dir: Some(dir.clone()), download, ..Default::default() }, dir)
+}
+
+#[test]
+fn server_errors_are_retried_until_the_download_succeeds() {
+    let (url, requests) = serve(vec![Reply::Status(503), Reply::Status(500), Reply::Model]);
+    let (options, dir) = options("5xx", url);
+
+    let info = prepare_model(&options).unwrap();
+    assert!(info.downloaded);
+    assert_eq!(requests.lock().unwrap().len(), 3);
+    assert_eq!(std::fs::read(dir.join("u2netp.onnx")).unwrap(), fixture_model());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn not_found_fails_without_retrying() {
+    let (url, requests) = serve(vec![Reply::Status(404)]);
+    let (options, dir) = options("404", url);
+
+    match prepare_model(&options) {
+        Err(RemoveBgError::ModelInitError(message)) => {
+            assert!(message.contains("404"), "{}", message);
+            assert!(!message.contains("attempts"), "{}", message);
+        }
+        other => panic!("{:?}", other),
+    }
+    assert_eq!(requests.lock().unwrap().len(), 1);
+    assert!(!dir.join("u2netp.onnx").exists());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn the_error_lists_every_attempt() {
+    let (url, requests) = serve(vec![Reply::Status(503)]);
+    let (options, dir) = options("summary", url);
+
+    match prepare_model(&options) {
+        Err(RemoveBgError::ModelInitError(message)) => assert_eq!(
+            message,
+            "Failed to download model after 3 attempts: attempt 1: HTTP status 503; \
+             attempt 2: HTTP status 503; attempt 3: HTTP status 503"
+        ),
+        other => panic!("{:?}", other),
+    }
+    assert_eq!(requests.lock().unwrap().len(), 3);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn dropped_downloads_resume_with_a_range_request() {
+    let half = fixture_model().len() / 2;
+    let (url, requests) = serve(vec![Reply::Truncated(half), Reply::Model]);
+    let (options, dir) = options("resume", url);
+
+    prepare_model(&options).unwrap();
+    assert_eq!(*requests.lock().unwrap(), [None, Some(format!("bytes={}-", half))]);
+    assert_eq!(std::fs::read(dir.join("u2netp.onnx")).unwrap(), fixture_model());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
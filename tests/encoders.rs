@@ -0,0 +1,149 @@
+//! Custom output encoders (`RemoveBgOptions::encoder`): outputs are named
+//! with the encoder's extension and written atomically, the format options
+//! no longer apply, and the built-in encoders match the built-in formats.
+
+use image::{Rgb, RgbImage, RgbaImage};
+use removebg::{
+    remove_background_detailed, remove_background_to_writer, remove_background_with_options, testing::{self, TestModel}, FileSizeLimit, JpegOutput,
+    OutputEncoder, OutputFormat, PngOutput, RemoveBgError, RemoveBgOptions, Result, TiffOutput, WebPOutput,
+};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Writes `MOCK`, the width and height and the raw RGBA pixels, counting
+/// its calls; fails instead when `fail` is set.
+#[derive(Debug, Default)]
+struct MockEncoder {
+    calls: AtomicUsize,
+    fail: bool,
+}
+
+impl OutputEncoder for MockEncoder {
+    fn encode(&self, image: &RgbaImage, writer: &mut dyn Write) -> Result<()> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        writer.write_all(b"MOCK")?;
+        writer.write_all(&image.width().to_le_bytes())?;
+        writer.write_all(&image.height().to_le_bytes())?;
+        if self.fail {
+            return Err(RemoveBgError::ProcessingError("mock encoder failed".into()));
+        }
+        writer.write_all(image.as_raw())?;
+        Ok(())
+    }
+
+    fn extension(&self) -> &str {
+        "mock"
+    }
+}
+
+/// A fresh directory with a test image, named for `test`.
+fn input(test: &str) -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("removebg-encoders-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("photo.png");
+    RgbImage::from_fn(64, 48, |x, y| Rgb([x as u8 * 4, y as u8 * 5, 128])).save(&path).unwrap();
+    (dir, path)
+}
+
+fn options(encoder: Option<Arc<dyn OutputEncoder>>) -> RemoveBgOptions {
+    testing::deterministic(TestModel::Brightness.options(RemoveBgOptions { encoder, create_dirs: true, ..Default::default() }))
+}
+
+/// The pixels in a file written by [`MockEncoder`].
+fn decode_mock(path: &Path) -> RgbaImage {
+    let data = std::fs::read(path).unwrap();
+    assert_eq!(&data[..4], b"MOCK");
+    let width = u32::from_le_bytes(data[4..8].try_into().unwrap());
+    let height = u32::from_le_bytes(data[8..12].try_into().unwrap());
+    RgbaImage::from_raw(width, height, data[12..].to_vec()).unwrap()
+}
+
+#[test]
+fn mock_encoder_writes_the_output() {
+    let (dir, photo) = input("mock");
+    let mock = Arc::new(MockEncoder::default());
+    let expected = image::open(remove_background_with_options(&photo, Some(&dir.join("png/")), &options(None)).unwrap())
+        .unwrap()
+        .to_rgba8();
+
+    // Named after the input with the encoder's extension; `format` is not used
+    let options = RemoveBgOptions { format: Some(OutputFormat::Jpeg), ..options(Some(mock.clone())) };
+    let output = remove_background_with_options(&photo, None, &options).unwrap();
+    assert_eq!(output, dir.join("photo_nobg.mock"));
+    assert_eq!(decode_mock(&output), expected);
+    assert_eq!(mock.calls.load(Ordering::SeqCst), 1);
+
+    // An output file's extension is replaced, and the report names the file
+    let report = remove_background_detailed(&photo, Some(&dir.join("cutout.png")), &options).unwrap();
+    assert_eq!(report.output_path, dir.join("cutout.mock"));
+    assert!(!dir.join("cutout.png").exists());
+    let report = remove_background_detailed(&photo, Some(&dir.join("cutouts/")), &options).unwrap();
+    assert_eq!(report.output_path, dir.join("cutouts/photo_nobg.mock"));
+    assert_eq!(decode_mock(&report.output_path), expected);
+
+    let mut written = Vec::new();
+    remove_background_to_writer(&photo, &mut written, &options).unwrap();
+    assert_eq!(written, std::fs::read(&output).unwrap());
+    assert_eq!(mock.calls.load(Ordering::SeqCst), 4);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn failed_encoding_writes_nothing() {
+    let (dir, photo) = input("failing");
+    let options = options(Some(Arc::new(MockEncoder { fail: true, ..Default::default() })));
+    let error = remove_background_with_options(&photo, None, &options).unwrap_err();
+    assert!(error.to_string().contains("mock encoder failed"), "{}", error);
+    assert!(!dir.join("photo_nobg.mock").exists());
+    let leftovers: Vec<_> = std::fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+    assert_eq!(leftovers, ["photo.png"]);
+
+    // Its settings can't be searched for a size that fits
+    let options = RemoveBgOptions { max_file_size: Some(FileSizeLimit { max_bytes: 1000, allow_downscale: false }), ..options };
+    let error = remove_background_with_options(&photo, None, &options).unwrap_err();
+    assert!(matches!(error.root(), RemoveBgError::InvalidConfig(_)), "{:?}", error);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn failed_encoding_keeps_the_previous_output() {
+    let (dir, photo) = input("failing-overwrite");
+    let output = remove_background_with_options(&photo, None, &options(Some(Arc::new(MockEncoder::default())))).unwrap();
+    let previous = std::fs::read(&output).unwrap();
+
+    let failing = RemoveBgOptions { overwrite: true, ..options(Some(Arc::new(MockEncoder { fail: true, ..Default::default() }))) };
+    remove_background_with_options(&photo, Some(&output), &failing).unwrap_err();
+    assert_eq!(std::fs::read(&output).unwrap(), previous);
+    let mut leftovers: Vec<_> = std::fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+    leftovers.sort();
+    assert_eq!(leftovers, ["photo.png", "photo_nobg.mock"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn built_in_encoders_match_the_formats() {
+    let (dir, photo) = input("built-in");
+    let expected = image::open(remove_background_with_options(&photo, Some(&dir.join("png/")), &options(None)).unwrap())
+        .unwrap()
+        .to_rgba8();
+    let encoders: [(Arc<dyn OutputEncoder>, &str); 3] =
+        [(Arc::new(PngOutput::default()), "png"), (Arc::new(TiffOutput), "tiff"), (Arc::new(WebPOutput::default()), "webp")];
+    for (encoder, extension) in encoders {
+        let output = remove_background_with_options(&photo, None, &options(Some(encoder))).unwrap();
+        assert_eq!(output, dir.join(format!("photo_nobg.{}", extension)));
+        assert_eq!(image::open(&output).unwrap().to_rgba8(), expected, "{}", extension);
+    }
+
+    // JPEG is flattened onto its background
+    let jpeg = JpegOutput { quality: 100, background: [0, 0, 255] };
+    let output = remove_background_with_options(&photo, None, &options(Some(Arc::new(jpeg)))).unwrap();
+    assert_eq!(output, dir.join("photo_nobg.jpg"));
+    let decoded = image::open(&output).unwrap().to_rgb8();
+    let (transparent, _) = expected.enumerate_pixels().min_by_key(|(_, _, pixel)| pixel[3]).map(|(x, y, p)| ((x, y), p)).unwrap();
+    let pixel = decoded.get_pixel(transparent.0, transparent.1);
+    assert!(pixel[2] > pixel[0] && pixel[2] > pixel[1], "{:?}", pixel);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
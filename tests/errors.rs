@@ -0,0 +1,94 @@
+//! Error context tests: the stage that failed and the input it was
+//! processing, in the library's errors and the CLI's JSON output.
+
+use image::{DynamicImage, ImageBuffer, Luma};
+use removebg::core::Gray16Image;
+use removebg::testing::{self, TestModel};
+use removebg::{BackgroundRemover, ConfidenceOptions, RemoveBgError, RemoveBgOptions, Result, SegmentationModel, Stage};
+use std::error::Error;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// A fresh directory under the system temp dir, named for `test`.
+fn temp_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("removebg-errors-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A fresh directory with a PNG cut off halfway, named for `test`.
+fn truncated_png(test: &str) -> (PathBuf, PathBuf) {
+    let dir = temp_dir(test);
+    let mut data = Vec::new();
+    let image = DynamicImage::ImageRgb8(testing::synthetic_image(1, 64, 48));
+    image.write_to(&mut std::io::Cursor::new(&mut data), image::ImageFormat::Png).unwrap();
+    data.truncate(data.len() / 2);
+    let path = dir.join("3172.png");
+    std::fs::write(&path, data).unwrap();
+    (dir, path)
+}
+
+/// Finds nothing in any image.
+struct EmptyModel;
+
+impl SegmentationModel for EmptyModel {
+    fn predict_mask(&self, image: &DynamicImage) -> Result<Gray16Image> {
+        Ok(ImageBuffer::from_pixel(image.width(), image.height(), Luma([0])))
+    }
+}
+
+#[test]
+fn decoding_errors_name_the_stage_and_input() {
+    let (dir, input) = truncated_png("decode");
+    let error = BackgroundRemover::with_model(EmptyModel, RemoveBgOptions::default())
+        .process_file(&input, Some(&dir.join("out.png")))
+        .unwrap_err();
+    assert_eq!((error.stage(), error.input()), (Some(Stage::Decode), Some(input.as_path())));
+    assert!(matches!(error.root(), RemoveBgError::ProcessingError(_)), "{:?}", error);
+    assert!(error.to_string().starts_with(&format!("Decoding failed for {}: ", input.display())), "{}", error);
+    assert_eq!(error.source().unwrap().to_string(), error.root().to_string());
+    assert_eq!(error.exit_code(), 3);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn rejected_masks_fail_in_postprocessing() {
+    let dir = temp_dir("confidence");
+    let input = dir.join("photo.png");
+    DynamicImage::ImageRgb8(testing::synthetic_image(2, 64, 48)).save(&input).unwrap();
+    let options = RemoveBgOptions {
+        confidence: ConfidenceOptions { min_score: Some(0.5), ..Default::default() },
+        ..Default::default()
+    };
+    let remover = BackgroundRemover::with_model(EmptyModel, options);
+
+    let error = remover.process_file(&input, Some(&dir.join("out.png"))).unwrap_err();
+    assert_eq!((error.stage(), error.input()), (Some(Stage::Postprocess), Some(input.as_path())));
+    assert_eq!(error.kind(), "low_confidence");
+    assert_eq!(error.exit_code(), 7);
+
+    // In-memory input has no path to name
+    let error = remover.process_bytes(&std::fs::read(&input).unwrap()).unwrap_err();
+    assert_eq!((error.stage(), error.input()), (Some(Stage::Postprocess), None));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cli_json_reports_the_stage_and_input() {
+    let (dir, input) = truncated_png("cli");
+    let run = Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(&input)
+        .arg("--json")
+        .args(TestModel::Brightness.args())
+        .output()
+        .expect("removebg runs");
+    assert_eq!(run.status.code(), Some(3));
+    let json: serde_json::Value = serde_json::from_slice(&run.stdout).unwrap();
+    let error = &json["error"];
+    assert_eq!(error["kind"], "processing_error");
+    assert_eq!(error["stage"], "decode");
+    assert_eq!(error["input"], input.to_string_lossy().as_ref());
+    assert!(String::from_utf8_lossy(&run.stderr).contains("Decoding failed for"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,162 @@
+//! The examples in `examples/`, run with the stub model on generated images,
+//! so a change to the public API they use fails here as well as in their
+//! build. Each example is compiled in as a module; only its `main` goes
+//! unused.
+
+use image::DynamicImage;
+use removebg::pipeline::Gray16Image;
+use removebg::{testing::{self, TestModel}, BackgroundRemover, RemoveBgOptions};
+use std::path::PathBuf;
+
+#[cfg(feature = "parallel")]
+#[allow(dead_code)]
+#[path = "../examples/batch_rayon.rs"]
+mod batch_rayon;
+
+#[allow(dead_code)]
+#[path = "../examples/custom_postprocess.rs"]
+mod custom_postprocess;
+
+#[allow(dead_code)]
+#[path = "../examples/threshold_preview.rs"]
+mod threshold_preview;
+
+#[cfg(feature = "server")]
+#[allow(dead_code)]
+#[path = "../examples/http_service.rs"]
+mod http_service;
+
+#[cfg(feature = "axum")]
+#[allow(dead_code)]
+#[path = "../examples/axum_service.rs"]
+mod axum_service;
+
+fn remover() -> BackgroundRemover {
+    TestModel::Brightness.remover(RemoveBgOptions { overwrite: true, ..Default::default() }).unwrap()
+}
+
+/// A fresh directory named for `test`.
+fn workspace(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("removebg-examples-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn photo(seed: u64) -> DynamicImage {
+    DynamicImage::ImageRgb8(testing::synthetic_image(seed, 96, 64))
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn batch_rayon_shares_one_remover() {
+    let dir = workspace("batch");
+    let inputs: Vec<PathBuf> = (0..6)
+        .map(|seed| {
+            let path = dir.join(format!("photo{}.jpg", seed));
+            photo(seed).save(&path).unwrap();
+            path
+        })
+        .collect();
+    let output_dir = dir.join("out");
+    std::fs::create_dir_all(&output_dir).unwrap();
+
+    let remover = remover();
+    let reports = batch_rayon::remove_all(&remover, &inputs, &output_dir, 3);
+    for (seed, report) in reports.into_iter().enumerate() {
+        let report = report.unwrap();
+        assert_eq!(report.output_path, output_dir.join(format!("photo{}.png", seed)));
+        assert_eq!(image::open(&report.output_path).unwrap().to_rgba8().dimensions(), (96, 64));
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn custom_postprocess_fades_the_bottom_rows() {
+    let mut mask = Gray16Image::from_pixel(4, 8, image::Luma([60000]));
+    custom_postprocess::fade_bottom(&mut mask, 0.5);
+    let column: Vec<u16> = (0..8).map(|y| mask.get_pixel(0, y)[0]).collect();
+    assert_eq!(column[..4], [60000; 4]);
+    assert!(column[3..].windows(2).all(|pair| pair[1] < pair[0]), "{:?}", column);
+    assert!(column[7] > 0);
+
+    let remover = remover();
+    let photo = photo(3);
+    let plain = remover.process_image(&photo).unwrap();
+    let faded = custom_postprocess::faded_cutout(&remover, &photo, 0.25).unwrap().into_rgba8();
+    assert_eq!(faded.dimensions(), plain.dimensions());
+    // The top is untouched and the bottom no more opaque than before
+    assert_eq!(faded.rows().take(40).flatten().collect::<Vec<_>>(), plain.rows().take(40).flatten().collect::<Vec<_>>());
+    assert!(faded.pixels().zip(plain.pixels()).all(|(faded, plain)| faded[3] <= plain[3]));
+}
+
+#[test]
+fn threshold_preview_reuses_the_mask() {
+    let dir = workspace("threshold");
+    let remover = remover();
+    let cached = threshold_preview::CachedMask::new(&remover, photo(7)).unwrap();
+    let preview = dir.join("preview.png");
+    let mut log = Vec::new();
+    let written = threshold_preview::run(&cached, remover.options(), &b"0.5\nhalf\n0.9\n\n0.1\n"[..], &preview, &mut log).unwrap();
+    assert_eq!(written, 2);
+    let log = String::from_utf8(log).unwrap();
+    assert!(log.contains("not a threshold from 0 to 1: half"), "{}", log);
+
+    // Thresholded masks leave no partial alpha
+    let alpha: Vec<u8> = image::open(&preview).unwrap().to_rgba8().pixels().map(|pixel| pixel[3]).collect();
+    assert!(alpha.iter().all(|&alpha| alpha == 0 || alpha == 255), "{:?}", alpha);
+    let strict = cached.preview(0.9, remover.options()).unwrap().to_rgba8();
+    let loose = cached.preview(0.1, remover.options()).unwrap().to_rgba8();
+    let opaque = |image: &image::RgbaImage| image.pixels().filter(|pixel| pixel[3] == 255).count();
+    assert!(opaque(&strict) <= opaque(&loose));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn http_service_answers_with_png() {
+    let remover = remover();
+    let mut jpeg = Vec::new();
+    photo(5).write_to(&mut std::io::Cursor::new(&mut jpeg), image::ImageFormat::Jpeg).unwrap();
+
+    let reply = http_service::handle(&remover, "POST", &jpeg);
+    assert_eq!((reply.status, reply.content_type), (200, "image/png"));
+    assert_eq!(image::load_from_memory(&reply.body).unwrap().to_rgba8().dimensions(), (96, 64));
+    assert_eq!(http_service::handle(&remover, "GET", &[]).status, 405);
+    assert_eq!(http_service::handle(&remover, "POST", b"not an image").status, 400);
+}
+
+#[cfg(feature = "axum")]
+#[tokio::test]
+async fn axum_service_answers_with_png() {
+    use std::future::IntoFuture;
+    use std::io::{Read, Write};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(axum::serve(listener, axum_service::app(std::sync::Arc::new(remover()))).into_future());
+
+    // A plain HTTP/1.1 client, run off the async workers
+    let post = |body: Vec<u8>| {
+        tokio::task::spawn_blocking(move || {
+            let mut stream = std::net::TcpStream::connect(address).unwrap();
+            let head = format!("POST /cutout HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", address, body.len());
+            stream.write_all(head.as_bytes()).unwrap();
+            stream.write_all(&body).unwrap();
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).unwrap();
+            let split = response.windows(4).position(|window| window == b"\r\n\r\n").unwrap();
+            (String::from_utf8(response[..split].to_vec()).unwrap(), response[split + 4..].to_vec())
+        })
+    };
+
+    let mut jpeg = Vec::new();
+    photo(5).write_to(&mut std::io::Cursor::new(&mut jpeg), image::ImageFormat::Jpeg).unwrap();
+    let (head, body) = post(jpeg).await.unwrap();
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    assert!(head.to_lowercase().contains("content-type: image/png"), "{}", head);
+    assert_eq!(image::load_from_memory(&body).unwrap().to_rgba8().dimensions(), (96, 64));
+
+    let (head, _) = post(b"not an image".to_vec()).await.unwrap();
+    assert!(head.starts_with("HTTP/1.1 400"), "{}", head);
+}
@@ -0,0 +1,59 @@
+//! Hardware execution providers (`--device coreml|directml`): a provider
+//! that can't be registered, or wasn't compiled in, falls back to the CPU
+//! with a warning instead of failing. Runs use the stub model in
+//! `tests/fixtures/models`.
+#![cfg(feature = "ort")]
+
+use image::DynamicImage;
+use removebg::testing::{self, TestModel};
+use removebg::{Backend, Device, RemoveBgOptions};
+use std::process::Command;
+
+/// A provider this platform doesn't have.
+const UNAVAILABLE: Device = if cfg!(target_os = "macos") { Device::DirectMl } else { Device::CoreMl };
+
+fn options(device: Device) -> RemoveBgOptions {
+    RemoveBgOptions { backend: Backend::Ort, device, ..Default::default() }
+}
+
+#[test]
+fn unavailable_providers_fall_back_to_the_cpu() {
+    let remover = TestModel::Brightness.remover(options(UNAVAILABLE)).expect("the CPU provider is used instead");
+    let image = DynamicImage::ImageRgb8(testing::synthetic_image(1, 48, 32));
+    assert_eq!(remover.process_image(&image).unwrap().dimensions(), (48, 32));
+}
+
+#[test]
+fn the_cli_warns_about_the_fallback() {
+    let dir = std::env::temp_dir().join(format!("removebg-providers-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("photo.png");
+    testing::synthetic_image(2, 48, 32).save(&input).unwrap();
+
+    let run = Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(&input)
+        .args(["--backend", "ort", "--device", &UNAVAILABLE.to_string()])
+        .args(TestModel::Brightness.args())
+        .output()
+        .expect("removebg runs");
+    let stderr = String::from_utf8_lossy(&run.stderr);
+    assert!(run.status.success(), "{}", stderr);
+    assert!(stderr.contains("falling back to CPU"), "{}", stderr);
+    assert!(dir.join("photo_nobg.png").is_file());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "coreml")]
+#[test]
+#[ignore = "needs Apple hardware"]
+fn coreml_session_builds() {
+    TestModel::Brightness.remover(options(Device::CoreMl)).expect("CoreML session");
+}
+
+#[cfg(feature = "directml")]
+#[test]
+#[ignore = "needs a DirectX 12 GPU"]
+fn directml_session_builds() {
+    TestModel::Brightness.remover(options(Device::DirectMl)).expect("DirectML session");
+}
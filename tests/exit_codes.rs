@@ -0,0 +1,87 @@
+//! The exit code contract: `--list-exit-codes` prints `EXIT_CODES`, inputs
+//! that aren't images exit with 2 and name what they are, and a directory
+//! run exits with the code of its most severe failure. Runs use the stub
+//! model in `tests/fixtures/models`.
+
+use removebg::{testing::{self, TestModel}, EXIT_CODES};
+use std::path::Path;
+use std::process::{Command, Output};
+
+fn removebg(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_removebg")).args(args).output().expect("removebg runs")
+}
+
+#[test]
+fn list_exit_codes_prints_the_table() {
+    let output = removebg(&["--list-exit-codes"]);
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    for info in EXIT_CODES {
+        let row = format!("{:<5} {:<18}", info.code, info.name);
+        assert!(stdout.contains(&row), "no row for {} in\n{}", info.code, stdout);
+    }
+    assert!(stdout.contains("model_not_cached"), "{}", stdout);
+
+    let output = removebg(&["--list-exit-codes", "--json"]);
+    assert!(output.status.success(), "{:?}", output);
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let codes = json.as_array().unwrap();
+    assert_eq!(codes.len(), EXIT_CODES.len());
+    for (code, info) in codes.iter().zip(EXIT_CODES) {
+        assert_eq!(code["code"], info.code);
+        assert_eq!(code["name"], info.name);
+        assert_eq!(code["kinds"].as_array().unwrap().len(), info.kinds.len());
+        assert_eq!(code["severity"].as_u64().map(|rank| rank as usize), info.severity());
+    }
+}
+
+#[test]
+fn non_images_exit_with_2_and_name_their_format() {
+    let dir = std::env::temp_dir().join(format!("removebg-exit-codes-sniff-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let inputs: [(&str, &[u8], &str); 3] = [
+        ("invoice.png", b"%PDF-1.7\n1 0 obj\n", "(detected: PDF)"),
+        ("download.jpg", b"<!DOCTYPE html><html><body>Forbidden</body></html>", "(detected: HTML)"),
+        ("blank.png", b"", "(detected: empty file)"),
+    ];
+    for (name, content, detected) in inputs {
+        let input = dir.join(name);
+        std::fs::write(&input, content).unwrap();
+        let output = Command::new(env!("CARGO_BIN_EXE_removebg")).arg(&input).args(TestModel::Brightness.args()).output().expect("removebg runs");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_eq!(output.status.code(), Some(2), "{}: {}", name, stderr);
+        assert!(stderr.contains("is not an image") && stderr.contains(detected), "{}: {}", name, stderr);
+    }
+
+    // A JPEG named .png is still a JPEG
+    let input = dir.join("renamed.png");
+    image::DynamicImage::ImageRgb8(testing::synthetic_image(3, 80, 60)).save_with_format(&input, image::ImageFormat::Jpeg).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_removebg")).arg(&input).args(TestModel::Brightness.args()).output().expect("removebg runs");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(image::open(dir.join("renamed_nobg.png")).unwrap().width(), 80);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn directories_exit_with_the_most_severe_failure() {
+    let dir = std::env::temp_dir().join(format!("removebg-exit-codes-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    for (seed, name) in [(1, "a.png"), (2, "b.png")] {
+        testing::synthetic_image(seed, 80, 60).save(dir.join(name)).unwrap();
+    }
+    std::fs::write(dir.join("c.png"), b"not a png").unwrap();
+    let run = |dir: &Path| {
+        Command::new(env!("CARGO_BIN_EXE_removebg")).arg(dir).args(TestModel::Brightness.args()).output().expect("removebg runs")
+    };
+
+    // An unsupported input alone exits with its own code
+    let output = run(&dir);
+    assert_eq!(output.status.code(), Some(2), "{}", String::from_utf8_lossy(&output.stderr));
+
+    // Run again, the existing outputs of the others (4) outrank it
+    let output = run(&dir);
+    assert_eq!(output.status.code(), Some(4), "{}", String::from_utf8_lossy(&output.stderr));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,99 @@
+//! Fast paths for model masks that are empty or full everywhere: the output
+//! is the same as going through every stage of `removebg::pipeline`, and the
+//! report says which fast path was taken. Runs use the stub model in
+//! `tests/fixtures/models`, whose mask is the input's mean color, so solid
+//! black and white inputs give empty and full masks.
+
+use image::{DynamicImage, Rgb, RgbImage};
+use removebg::{pipeline, testing::{self, TestModel}, Background, BackgroundRemover, MaskFastPath, RemoveBgOptions};
+use std::path::PathBuf;
+use std::process::Command;
+
+fn remover(options: RemoveBgOptions) -> BackgroundRemover {
+    let options = TestModel::Brightness.options(RemoveBgOptions { overwrite: true, ..options });
+    BackgroundRemover::new(testing::deterministic(options)).expect("stub model loads")
+}
+
+fn temp_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("removebg-fast_path-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// The cutout built stage by stage, without the fast paths.
+fn staged(remover: &BackgroundRemover, image: &DynamicImage) -> DynamicImage {
+    let options = remover.options();
+    let mask = pipeline::infer(remover, pipeline::preprocess(image, options.model.spec())).unwrap();
+    let mut mask = pipeline::upscale_mask(&mask, (image.width(), image.height()), options.mask_filter);
+    pipeline::refine(&mut mask, image, options).unwrap();
+    pipeline::composite(image, &mask, options).unwrap()
+}
+
+#[test]
+fn fast_paths_match_the_staged_pipeline() {
+    let dir = temp_dir("staged");
+    let variants = [
+        RemoveBgOptions::default(),
+        RemoveBgOptions { premultiplied: true, decontaminate: Some(1.0), ..Default::default() },
+        RemoveBgOptions { background: Background::Color([10, 200, 30]), ..Default::default() },
+        RemoveBgOptions { invert_mask: true, ..Default::default() },
+    ];
+    for (value, expected) in [(0, MaskFastPath::Empty), (255, MaskFastPath::Full)] {
+        let solid = RgbImage::from_pixel(90, 60, Rgb([value; 3]));
+        let images = [
+            DynamicImage::ImageRgb8(solid.clone()),
+            DynamicImage::ImageRgb16(DynamicImage::ImageRgb8(solid.clone()).into_rgb16()),
+            DynamicImage::ImageRgb32F(DynamicImage::ImageRgb8(solid).into_rgb32f()),
+        ];
+        for options in &variants {
+            let remover = remover(options.clone());
+            for (i, image) in images.iter().enumerate() {
+                let label = format!("{:?} input {} with {:?}", expected, i, (options.premultiplied, options.background, options.invert_mask));
+                let input = dir.join(format!("input-{}.tiff", i));
+                let output = dir.join(format!("output-{}.png", i));
+                image.save(&input).unwrap();
+                let image = image::open(&input).unwrap();
+
+                let report = remover.process_file(&input, Some(&output)).unwrap();
+                assert_eq!(report.mask_fast_path, Some(expected), "{}", label);
+                assert!(image::open(&output).unwrap() == staged(&remover, &image), "{}", label);
+            }
+        }
+    }
+
+    // A mask with any other value goes the usual way
+    let image = DynamicImage::ImageRgb8(testing::synthetic_image(3, 90, 60));
+    let input = dir.join("synthetic.png");
+    image.save(&input).unwrap();
+    let report = remover(RemoveBgOptions::default()).process_file(&input, Some(&dir.join("out.png"))).unwrap();
+    assert_eq!(report.mask_fast_path, None);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn json_reports_the_fast_path() {
+    let dir = temp_dir("json");
+    let input = dir.join("black.png");
+    RgbImage::from_pixel(40, 30, Rgb([0, 0, 0])).save(&input).unwrap();
+    let run = |extra: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_removebg"))
+            .arg(&input)
+            .args(["--json", "--force"])
+            .args(TestModel::Brightness.args())
+            .args(extra)
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&[]);
+    assert!(output.status.success(), "{:?}", output);
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["mask_fast_path"], "empty");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no subject was found"), "{:?}", output);
+
+    // An empty mask fails under --strict like any other near-empty mask
+    let output = run(&["--strict"]);
+    assert_eq!(output.status.code(), Some(7), "{:?}", output);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,21 @@
+#!/bin/sh
+# Build removebg as a static library with the C API and run the C smoke test
+# against it. Uses the tract backend so no ONNX Runtime library is needed;
+# u2netp is downloaded on the first run unless REMOVEBG_MODEL_DIR has it.
+#
+# Usage: tests/ffi/run.sh
+set -eu
+
+root=$(cd "$(dirname "$0")/../.." && pwd)
+target=${CARGO_TARGET_DIR:-$root/target}
+scratch=$(mktemp -d)
+trap 'rm -rf "$scratch"' EXIT
+
+cd "$root"
+cargo rustc --lib --crate-type staticlib --no-default-features --features capi,backend-tract \
+    -- --print native-static-libs 2>"$scratch/build.log" || { cat "$scratch/build.log"; exit 1; }
+libs=$(sed -n 's/.*native-static-libs: //p' "$scratch/build.log" | tail -n 1)
+
+${CC:-cc} -Wall -Wextra -o "$scratch/smoke" tests/ffi/smoke.c -Iinclude \
+    "$target/debug/libremovebg.a" $libs
+"$scratch/smoke" "$scratch"
@@ -0,0 +1,134 @@
+/*
+ * Smoke test for the C API: drives every function in removebg.h once,
+ * including the error paths. Run through tests/ffi/run.sh.
+ *
+ * Usage: smoke <scratch directory>
+ */
+
+#include <stdio.h>
+#include <string.h>
+
+#include "removebg.h"
+
+static int failures = 0;
+
+#define CHECK(cond)                                                    \
+    do {                                                               \
+        if (!(cond)) {                                                 \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__,     \
+                    __LINE__, #cond);                                  \
+            failures++;                                                \
+        }                                                              \
+    } while (0)
+
+/* An 8x8 binary PPM: dark left half, light right half. */
+static size_t make_image(unsigned char *buf) {
+    size_t len = (size_t)sprintf((char *)buf, "P6\n8 8\n255\n");
+    for (int y = 0; y < 8; y++) {
+        for (int x = 0; x < 8; x++) {
+            unsigned char v = x < 4 ? 30 : 220;
+            buf[len++] = v;
+            buf[len++] = v;
+            buf[len++] = v;
+        }
+    }
+    return len;
+}
+
+int main(int argc, char **argv) {
+    if (argc != 2) {
+        fprintf(stderr, "usage: %s <scratch directory>\n", argv[0]);
+        return 2;
+    }
+
+    /* A bad config fails with a message and no handle */
+    RemoveBgConfig bad = {0};
+    bad.model = "no-such-model";
+    CHECK(removebg_init(&bad) == NULL);
+    CHECK(removebg_last_error(NULL) != NULL);
+
+    RemoveBgConfig config = {0};
+    config.model = "u2netp";
+    config.backend = "tract";
+    config.overwrite = 1;
+    RemoveBgHandle *handle = removebg_init(&config);
+    if (handle == NULL) {
+        fprintf(stderr, "removebg_init failed: %s\n", removebg_last_error(NULL));
+        return 1;
+    }
+    CHECK(removebg_last_error(NULL) == NULL);
+
+    /* In-memory processing returns a caller-owned PNG */
+    unsigned char image[256];
+    size_t image_len = make_image(image);
+    uint8_t *out = NULL;
+    size_t out_len = 0;
+    int status = removebg_process_bytes(handle, image, image_len, &out, &out_len);
+    CHECK(status == REMOVEBG_OK);
+    CHECK(removebg_last_error(handle) == NULL);
+    CHECK(out != NULL && out_len > 8 && memcmp(out, "\x89PNG", 4) == 0);
+    removebg_free_buffer(out, out_len);
+
+    /* Input that isn't an image maps to the format error and clears the output */
+    out = (uint8_t *)image;
+    status = removebg_process_bytes(handle, (const uint8_t *)"nope", 4, &out, &out_len);
+    CHECK(status == REMOVEBG_ERROR_UNSUPPORTED_FORMAT);
+    CHECK(out == NULL && out_len == 0);
+    CHECK(removebg_last_error(handle) != NULL);
+
+    /* A padded BGRA frame comes back as tightly packed RGBA */
+    unsigned char frame[8 * 40];
+    for (int y = 0; y < 8; y++) {
+        for (int x = 0; x < 10; x++) {
+            unsigned char *pixel = frame + y * 40 + x * 4;
+            pixel[0] = 200; /* blue */
+            pixel[1] = 100;
+            pixel[2] = 10; /* red */
+            pixel[3] = x < 8 ? 255 : 7; /* padding past the eighth pixel */
+        }
+    }
+    status = removebg_process_raw(handle, frame, sizeof frame, 8, 8, 40, REMOVEBG_PIXEL_BGRA8, &out, &out_len);
+    CHECK(status == REMOVEBG_OK);
+    CHECK(out != NULL && out_len == 8 * 8 * 4 && out[0] == 10 && out[2] == 200);
+    removebg_free_buffer(out, out_len);
+
+    /* A stride shorter than a row is rejected */
+    status = removebg_process_raw(handle, frame, sizeof frame, 8, 8, 16, REMOVEBG_PIXEL_BGRA8, &out, &out_len);
+    CHECK(status == REMOVEBG_ERROR_INVALID_BUFFER);
+    CHECK(out == NULL && out_len == 0);
+
+    /* File to file */
+    char in_path[1024], out_path[1024];
+    snprintf(in_path, sizeof in_path, "%s/smoke.ppm", argv[1]);
+    snprintf(out_path, sizeof out_path, "%s/smoke_out.png", argv[1]);
+    FILE *file = fopen(in_path, "wb");
+    CHECK(file != NULL);
+    if (file != NULL) {
+        fwrite(image, 1, image_len, file);
+        fclose(file);
+    }
+    CHECK(removebg_process_file(handle, in_path, out_path) == REMOVEBG_OK);
+    file = fopen(out_path, "rb");
+    CHECK(file != NULL);
+    if (file != NULL) {
+        fclose(file);
+    }
+
+    /* Error codes mirror the Rust error variants */
+    snprintf(in_path, sizeof in_path, "%s/missing.png", argv[1]);
+    CHECK(removebg_process_file(handle, in_path, NULL) == REMOVEBG_ERROR_FILE_NOT_FOUND);
+    CHECK(removebg_process_file(handle, NULL, NULL) == REMOVEBG_ERROR_INVALID_ARGUMENT);
+    CHECK(removebg_process_bytes(handle, NULL, 0, &out, &out_len) == REMOVEBG_ERROR_INVALID_ARGUMENT);
+    CHECK(removebg_process_bytes(NULL, image, image_len, &out, &out_len) == REMOVEBG_ERROR_INVALID_ARGUMENT);
+
+    removebg_free(handle);
+    removebg_free(NULL);
+    removebg_free_buffer(NULL, 0);
+
+    if (failures > 0) {
+        fprintf(stderr, "%d check(s) failed\n", failures);
+        return 1;
+    }
+    printf("ffi smoke test passed\n");
+    return 0;
+}
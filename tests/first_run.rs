@@ -0,0 +1,146 @@
+//! The first run's model download: the question asked before it, `--yes`
+//! and `REMOVEBG_AUTO_DOWNLOAD` skipping it, the notice of runs without a
+//! terminal, which download as before, and the download events of `--json`
+//! runs.
+
+use removebg::model::{confirm_download, needs_download};
+use removebg::{testing::{self, TestModel}, DownloadOptions, Model, RemoveBgError};
+use sha2::{Digest, Sha256};
+use std::io::{BufRead, BufReader, Cursor, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn fixture_model() -> Vec<u8> {
+    std::fs::read(TestModel::Brightness.dir().join("u2netp.onnx")).unwrap()
+}
+
+/// Serve `body` to every request on a local port.
+fn serve(body: Vec<u8>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/u2netp.onnx", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).is_ok_and(|read| read > 2) {
+                line.clear();
+            }
+            let header = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+            let _ = stream.write_all(header.as_bytes());
+            let _ = stream.write_all(&body);
+        }
+    });
+    url
+}
+
+/// A fresh directory with a test image and an empty model directory.
+fn workspace(test: &str) -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("removebg-first-run-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("models")).unwrap();
+    let photo = dir.join("photo.png");
+    testing::synthetic_image(3, 64, 48).save(&photo).unwrap();
+    (dir, photo)
+}
+
+/// Run the CLI on `photo` with a model that must be downloaded from `url`.
+fn run(dir: &Path, photo: &Path, url: &str, args: &[&str], env: &[(&str, &str)]) -> std::process::Output {
+    let sha256 = format!("{:x}", Sha256::digest(fixture_model()));
+    Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(photo)
+        .args(["--model", "u2netp", "--model-url", url, "--model-sha256", &sha256, "--model-dir"])
+        .arg(dir.join("models"))
+        .args(args)
+        .env("NO_PROXY", "127.0.0.1")
+        .env_remove("HTTP_PROXY")
+        .env_remove("http_proxy")
+        .env_remove("REMOVEBG_MODEL_URL")
+        .env_remove("REMOVEBG_AUTO_DOWNLOAD")
+        .envs(env.iter().copied())
+        .output()
+        .expect("removebg runs")
+}
+
+#[test]
+fn yes_downloads_without_a_notice() {
+    let url = serve(fixture_model());
+    for (test, args, env) in [("yes", &["--yes"][..], &[][..]), ("env", &[], &[("REMOVEBG_AUTO_DOWNLOAD", "1")])] {
+        let (dir, photo) = workspace(test);
+        let output = run(&dir, &photo, &url, args, env);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{}", stderr);
+        assert!(!stderr.contains("is not cached"), "{}", stderr);
+        assert!(dir.join("models/u2netp.onnx").exists());
+        assert!(dir.join("photo_nobg.png").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[test]
+fn runs_without_a_terminal_download_with_a_notice() {
+    let url = serve(fixture_model());
+    let (dir, photo) = workspace("notice");
+    let output = run(&dir, &photo, &url, &[], &[]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    assert!(stderr.contains("Model u2netp (4.57 MB) is not cached and will be downloaded"), "{}", stderr);
+    assert!(dir.join("photo_nobg.png").exists());
+
+    // Once cached there is nothing to say
+    let output = run(&dir, &photo, &url, &["--force"], &[]);
+    assert!(output.status.success());
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("is not cached"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn json_runs_report_download_events_on_stderr() {
+    let url = serve(fixture_model());
+    let (dir, photo) = workspace("events");
+    let download_events = |output: &std::process::Output| -> Vec<serde_json::Value> {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let lines = stderr.lines().filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok());
+        lines.filter(|event| event["event"] == "download").collect()
+    };
+    let output = run(&dir, &photo, &url, &["--yes", "--json"], &[]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let size = fixture_model().len() as u64;
+    let events = download_events(&output);
+    let last = events.last().expect("download events");
+    assert_eq!(*last, serde_json::json!({ "event": "download", "model": "u2netp", "received": size, "total": size }));
+    assert!(events.iter().all(|event| event["total"] == size && event["received"].as_u64() <= Some(size)));
+    // Stdout has the result alone
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(result["success"], true);
+
+    // Once cached there is nothing to report
+    let output = run(&dir, &photo, &url, &["--yes", "--json", "--force"], &[]);
+    assert!(output.status.success());
+    assert!(download_events(&output).is_empty(), "{}", String::from_utf8_lossy(&output.stderr));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn question_names_the_model_and_size() {
+    let dir = std::env::temp_dir().join(format!("removebg-first-run-question-{}", std::process::id()));
+    let cached = TestModel::Brightness.dir();
+    let download = DownloadOptions::default();
+    assert!(needs_download(Model::U2net, Some(&dir), &download).unwrap());
+    assert!(!needs_download(Model::U2netp, Some(&cached), &download).unwrap());
+    assert!(!needs_download(Model::U2net, Some(&dir), &DownloadOptions { offline: true, ..download }).unwrap());
+
+    for answer in ["\n", "y\n", "YES\n"] {
+        let mut prompt = Vec::new();
+        confirm_download(Model::U2net, Some(&dir), &mut Cursor::new(answer), &mut prompt).unwrap();
+        assert_eq!(String::from_utf8(prompt).unwrap(), "Model u2net (176.00 MB) is not cached. Download now? [Y/n] ");
+    }
+    for answer in ["n\n", "no\n", "later\n", ""] {
+        let error = confirm_download(Model::U2net, Some(&dir), &mut Cursor::new(answer), &mut Vec::new()).unwrap_err();
+        let RemoveBgError::ModelNotCached { model, path, offline: false } = &error else { panic!("{:?}", error) };
+        assert_eq!((*model, path.as_path()), (Model::U2net, dir.join("u2net.onnx").as_path()));
+        assert!(error.to_string().contains("removebg model fetch u2net"), "{}", error);
+        assert_eq!(error.exit_code(), 10);
+    }
+}
@@ -0,0 +1,105 @@
+"""Write the ZIP archive fixtures used by tests/archive.rs.
+
+photos.zip holds two images, one in a subdirectory, and a text file:
+
+    portrait.png        48x32, a white ellipse on black
+    shoot/square.bmp    24x24, a white square on black
+    notes.txt           not an image
+
+damaged.zip holds portrait.png and a copy whose stored bytes were changed
+after its checksum was written, so reading it fails. locked.zip is
+photos.zip with the encryption flag set on its entries; their data isn't
+really encrypted, which is enough for removebg to refuse it.
+
+Entries are dated 2024-05-01 12:00 so the files are the same on every run.
+The images are encoded here, so the script needs nothing beyond the
+standard library.
+
+Usage: python3 tests/fixtures/archive/make_zip.py
+"""
+import struct
+import zipfile
+import zlib
+from pathlib import Path
+
+DATE = (2024, 5, 1, 12, 0, 0)
+
+
+def png(width, height, pixel):
+    def chunk(kind, data):
+        return struct.pack(">I", len(data)) + kind + data + struct.pack(">I", zlib.crc32(kind + data))
+
+    rows = b"".join(b"\x00" + bytes(pixel(x, y) for x in range(width)) for y in range(height))
+    header = struct.pack(">IIBBBBB", width, height, 8, 0, 0, 0, 0)
+    return b"\x89PNG\r\n\x1a\n" + chunk(b"IHDR", header) + chunk(b"IDAT", zlib.compress(rows, 9)) + chunk(b"IEND", b"")
+
+
+def bmp(width, height, pixel):
+    # 24-bit, bottom-up rows padded to 4 bytes
+    stride = (width * 3 + 3) // 4 * 4
+    rows = b"".join(
+        b"".join(bytes([pixel(x, y)] * 3) for x in range(width)).ljust(stride, b"\x00")
+        for y in reversed(range(height))
+    )
+    info = struct.pack("<IiiHHIIiiII", 40, width, height, 1, 24, 0, len(rows), 2835, 2835, 0, 0)
+    return b"BM" + struct.pack("<IHHI", 14 + len(info) + len(rows), 0, 0, 14 + len(info)) + info + rows
+
+
+def ellipse(width, height):
+    cx, cy = width / 2, height / 2
+
+    def pixel(x, y):
+        dx, dy = (x + 0.5 - cx) / (cx * 0.8), (y + 0.5 - cy) / (cy * 0.8)
+        return 255 if dx * dx + dy * dy <= 1 else 0
+
+    return pixel
+
+
+def square(width, height):
+    return lambda x, y: 255 if width // 4 <= x < width * 3 // 4 and height // 4 <= y < height * 3 // 4 else 0
+
+
+def write(path, entries):
+    with zipfile.ZipFile(path, "w") as archive:
+        for name, data in entries:
+            info = zipfile.ZipInfo(name, DATE)
+            info.compress_type = zipfile.ZIP_DEFLATED if name.endswith((".bmp", ".txt")) else zipfile.ZIP_STORED
+            archive.writestr(info, data)
+
+
+def set_encrypted(path):
+    # zipfile clears flag_bits when writing, so set bit 0 in the local and
+    # central headers afterwards
+    data = bytearray(path.read_bytes())
+    for signature, offset in ((b"PK\x03\x04", 6), (b"PK\x01\x02", 8)):
+        start = data.find(signature)
+        while start >= 0:
+            data[start + offset] |= 0x1
+            start = data.find(signature, start + 4)
+    path.write_bytes(bytes(data))
+
+
+def main():
+    out = Path(__file__).parent
+    portrait = png(48, 32, ellipse(48, 32))
+    photos = [
+        ("portrait.png", portrait),
+        ("shoot/", b""),
+        ("shoot/square.bmp", bmp(24, 24, square(24, 24))),
+        ("notes.txt", b"Shoot of 2024-05-01: keep the originals.\n"),
+    ]
+    write(out / "photos.zip", photos)
+    write(out / "locked.zip", photos)
+    set_encrypted(out / "locked.zip")
+
+    damaged = out / "damaged.zip"
+    write(damaged, [("portrait.png", portrait), ("broken.png", portrait)])
+    # Flip a byte of the second (stored) copy's pixel data, past its local header
+    data = bytearray(damaged.read_bytes())
+    start = data.find(b"broken.png") + len("broken.png")
+    data[start + 60] ^= 0xFF
+    damaged.write_bytes(bytes(data))
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,127 @@
+"""Write the stub u2netp.onnx used by the golden tests.
+
+The stub stands in for the real model so the tests need no download: it
+averages the three input channels into a (1, 1, 320, 320) mask and returns
+it as its two outputs, d0 and d1. The protobuf is encoded by hand so the
+script needs nothing beyond the standard library.
+
+The same graph is also written with other input names, sizes and types into
+subdirectories (see VARIANTS), for the input detection tests in
+tests/model_io.rs.
+
+blob/u2netp.onnx is a second stub whose mask looks like a real cutout: a
+soft blob in the middle of the image, upscaled from an 8x8 prior, plus a
+3x3 convolution of the input so the content moves its edge. Its sigmoid
+output is in 0-1 like the real model's. Tests pick either stub with
+removebg::testing::TestModel.
+
+Usage: python3 tests/fixtures/models/make_stub.py
+"""
+import struct
+from pathlib import Path
+
+SIZE = 320
+
+
+def varint(value):
+    out = b""
+    while True:
+        byte, value = value & 0x7F, value >> 7
+        if not value:
+            return out + bytes([byte])
+        out += bytes([byte | 0x80])
+
+
+def field(number, wire_type):
+    return varint(number << 3 | wire_type)
+
+
+def message(number, data):
+    return field(number, 2) + varint(len(data)) + data
+
+
+def string(number, text):
+    return message(number, text.encode())
+
+
+def integer(number, value):
+    return field(number, 0) + varint(value)
+
+
+def value_info(name, shape, elem_type=1):
+    dims = b"".join(message(1, integer(1, dim)) for dim in shape)
+    tensor_type = integer(1, elem_type) + message(2, dims)  # 1 = float32, 7 = int64
+    return string(1, name) + message(2, message(1, tensor_type))
+
+
+def floats(values):
+    return b"".join(struct.pack("<f", value) for value in values)
+
+
+def tensor(name, shape, values):
+    # TensorProto: dims (1), data_type (2; 1 = float32), name (8), raw_data (9)
+    dims = b"".join(integer(1, dim) for dim in shape)
+    return dims + integer(2, 1) + string(8, name) + message(9, floats(values))
+
+
+def node(name, op, inputs, outputs, attributes=b""):
+    io = b"".join(string(1, i) for i in inputs) + b"".join(string(2, o) for o in outputs)
+    return io + string(3, name) + string(4, op) + attributes
+
+
+def stub(input_name="input", size=SIZE, outputs=("d0", "d1"), elem_type=1):
+    """The averaging stub model with the given input and output names."""
+    # AttributeProto: name (1), type (20; 7 = INTS, 2 = INT), ints (8), i (3)
+    axes = message(5, string(1, "axes") + integer(20, 7) + integer(8, 1))
+    keepdims = message(5, string(1, "keepdims") + integer(20, 2) + integer(3, 1))
+    first, second = outputs
+    graph = (
+        message(1, node("mean", "ReduceMean", [input_name], [first], axes + keepdims))
+        + message(1, node("copy", "Identity", [first], [second]))
+        + string(2, "stub")
+        + message(11, value_info(input_name, [1, 3, size, size], elem_type))
+        + message(12, value_info(first, [1, 1, size, size], elem_type))
+        + message(12, value_info(second, [1, 1, size, size], elem_type))
+    )
+    # ModelProto: ir_version (1), graph (7), opset_import (8) with opset 13
+    return integer(1, 7) + message(7, graph) + message(8, string(1, "") + integer(2, 13))
+
+
+def blob(size=SIZE, prior=8):
+    """The center blob stub: sigmoid(upscaled prior + 3x3 convolution of the input)."""
+    # Logits of the prior: positive within 0.55 of the center, on a -1..1 grid
+    grid = [(i + 0.5) / prior * 2 - 1 for i in range(prior)]
+    logits = [12 * (0.3 - x * x - y * y) for y in grid for x in grid]
+    pads = message(5, string(1, "pads") + integer(20, 7) + b"".join(integer(8, 1) for _ in range(4)))
+    linear = message(5, string(1, "mode") + integer(20, 3) + message(4, b"linear"))
+    graph = (
+        message(1, node("upscale", "Resize", ["prior", "", "scales"], ["upscaled"], linear))
+        + message(1, node("detail", "Conv", ["input", "weight"], ["detail"], pads))
+        + message(1, node("sum", "Add", ["upscaled", "detail"], ["logits"]))
+        + message(1, node("sigmoid", "Sigmoid", ["logits"], ["d0"]))
+        + message(1, node("copy", "Identity", ["d0"], ["d1"]))
+        + string(2, "blob")
+        + message(5, tensor("prior", [1, 1, prior, prior], logits))
+        + message(5, tensor("scales", [4], [1, 1, size / prior, size / prior]))
+        + message(5, tensor("weight", [1, 3, 3, 3], [1 / 27] * 27))
+        + message(11, value_info("input", [1, 3, size, size]))
+        + message(12, value_info("d0", [1, 1, size, size]))
+        + message(12, value_info("d1", [1, 1, size, size]))
+    )
+    return integer(1, 7) + message(7, graph) + message(8, string(1, "") + integer(2, 13))
+
+
+# Subdirectory: stub arguments
+VARIANTS = {
+    "input-1": dict(input_name="input.1", size=64, outputs=("mask", "side")),
+    "img": dict(input_name="img", size=96, outputs=("out", "aux")),
+    "int64": dict(input_name="input", size=64, elem_type=7),
+}
+
+here = Path(__file__).parent
+(here / "u2netp.onnx").write_bytes(stub())
+for directory, arguments in VARIANTS.items():
+    (here / directory).mkdir(exist_ok=True)
+    (here / directory / "u2netp.onnx").write_bytes(stub(**arguments))
+(here / "blob").mkdir(exist_ok=True)
+(here / "blob" / "u2netp.onnx").write_bytes(blob())
@@ -0,0 +1,39 @@
+"""Write the .npy mask fixtures used by tests/npy_masks.rs.
+
+The files are what numpy.save writes (numpy 1.24 and later, format 1.0)
+for these arrays:
+
+    np.save("mask_f32.npy", np.array([[0, 0.25, 0.5], [0.75, 1, 0.1]], dtype="<f4"))
+    np.save("mask_u8.npy", np.array([[0, 64, 128], [192, 255, 26]], dtype="u1"))
+
+The header is encoded here the way numpy.lib.format does it, including the
+spare spaces it leaves for the first axis to grow, so the script needs
+nothing beyond the standard library.
+
+Usage: python3 tests/fixtures/npy/make_npy.py
+"""
+import struct
+from pathlib import Path
+
+ARRAY_ALIGN = 64
+GROWTH_AXIS_MAX_DIGITS = 21
+
+
+def npy(descr, shape, data):
+    header = "{'descr': %r, 'fortran_order': False, 'shape': %r, }" % (descr, shape)
+    header += " " * (GROWTH_AXIS_MAX_DIGITS - len(repr(shape[0])))
+    header = header.encode("latin1")
+    hlen = len(header) + 1
+    padlen = ARRAY_ALIGN - ((6 + 2 + 2 + hlen) % ARRAY_ALIGN)
+    return b"\x93NUMPY\x01\x00" + struct.pack("<H", hlen + padlen) + header + b" " * padlen + b"\n" + data
+
+
+def main():
+    out = Path(__file__).parent
+    values = [0, 0.25, 0.5, 0.75, 1, 0.1]
+    (out / "mask_f32.npy").write_bytes(npy("<f4", (2, 3), struct.pack("<6f", *values)))
+    (out / "mask_u8.npy").write_bytes(npy("|u1", (2, 3), bytes([0, 64, 128, 192, 255, 26])))
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,60 @@
+//! Floating-point masks (`BackgroundRemover::mask_f32`): the 8-bit mask is
+//! the float mask rounded, values stay in 0-1 whatever refinements run, and
+//! the float mask is finer than 8 bits. Runs use the stub model in
+//! `tests/fixtures/models`.
+
+use image::{DynamicImage, Rgba, RgbaImage};
+use removebg::{testing::{self, TestModel}, BackgroundRemover, GuidedFilterOptions, RemoveBgOptions, TileOptions};
+
+fn remover(options: RemoveBgOptions) -> BackgroundRemover {
+    TestModel::Brightness.remover(options).expect("stub model loads")
+}
+
+#[test]
+fn eight_bit_mask_is_the_rounded_float_mask() {
+    let image = DynamicImage::ImageRgb8(testing::synthetic_image(7, 150, 100));
+    let variants = [
+        RemoveBgOptions::default(),
+        RemoveBgOptions { mask_gamma: Some(2.2), mask_levels: Some((0.1, 0.9)), ..Default::default() },
+        RemoveBgOptions { mask_levels: Some((0.5, 0.5)), ..Default::default() },
+        RemoveBgOptions { guided_filter: Some(GuidedFilterOptions::default()), ..Default::default() },
+        RemoveBgOptions { fill_holes: true, keep_largest_component: true, invert_mask: true, ..Default::default() },
+        RemoveBgOptions { tiling: Some(TileOptions { size: 64, overlap: 16 }), ..Default::default() },
+    ];
+    for options in variants {
+        let label = format!("{:?}", (options.mask_gamma, options.mask_levels, options.invert_mask, options.tiling));
+        let remover = remover(options);
+        let float = remover.mask_f32(&image).unwrap();
+        let mask = remover.mask(&image).unwrap();
+        assert_eq!(float.dim(), (100, 150), "{}", label);
+        assert!(float.iter().all(|value| (0.0..=1.0).contains(value)), "{}", label);
+        for (&value, &byte) in float.iter().zip(mask.iter()) {
+            assert_eq!(byte, (value * 255.0).round() as u8, "{}", label);
+        }
+    }
+}
+
+#[test]
+fn float_mask_keeps_soft_edges_and_input_alpha() {
+    // Half-transparent input: the mask is the model's mask times 0.5
+    let image = RgbaImage::from_fn(120, 80, |x, y| {
+        let value = (x * 2 + y) as u8;
+        Rgba([value, value, value, if x < 60 { 255 } else { 128 }])
+    });
+    let image = DynamicImage::ImageRgba8(image);
+    let remover = remover(RemoveBgOptions::default());
+    let float = remover.mask_f32(&image).unwrap();
+    assert!(float.iter().all(|value| (0.0..=1.0).contains(value)));
+
+    // Values between the 8-bit steps survive
+    let between = float.iter().filter(|&&value| ((value * 255.0).fract() - 0.5).abs() < 0.4).count();
+    assert!(between > float.len() / 4, "{} of {} values are finer than 8 bits", between, float.len());
+
+    // The cutout's alpha, built from the 16-bit mask, agrees to the rounding
+    let cutout = remover.process_image(&image).unwrap();
+    for ((x, y, pixel), &value) in cutout.enumerate_pixels().zip(float.iter()) {
+        let expected = value * 255.0;
+        assert!((pixel[3] as f32 - expected).abs() <= 1.0, "at {},{}: {} vs {}", x, y, pixel[3], expected);
+    }
+    assert!(float[[40, 100]] <= 128.0 / 255.0 + 1e-6);
+}
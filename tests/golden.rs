@@ -0,0 +1,147 @@
+//! Golden mask regression tests.
+//!
+//! The fixture images in `tests/fixtures/golden` run through the stub model
+//! in `tests/fixtures/models` (see `make_stub.py` there), so no model is
+//! downloaded, and their masks are compared with the checked-in
+//! `<name>.mask.png` goldens; sticker cutouts are compared with
+//! `<name>.png`, channel by channel. A failing comparison writes a diff image
+//! to `target/golden-diffs`.
+//!
+//! After an intended change to the masks, rewrite the goldens with
+//! `REMOVEBG_BLESS=1 cargo test --test golden` and review the new files.
+
+use image::{DynamicImage, GrayImage, RgbaImage};
+use removebg::testing::{self, TestModel, assert_mask_close};
+use removebg::{BackgroundRemover, RemoveBgOptions, StickerOptions};
+use std::path::PathBuf;
+
+/// Largest accepted mean difference from a golden mask, as a fraction of
+/// full scale.
+const TOLERANCE: f32 = 0.0005;
+
+fn fixtures() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+fn remover(options: RemoveBgOptions) -> BackgroundRemover {
+    TestModel::Brightness.remover(options).expect("stub model loads")
+}
+
+fn fixture(name: &str) -> DynamicImage {
+    image::open(fixtures().join("golden").join(name)).expect("fixture image decodes")
+}
+
+/// Compare `mask` with the golden mask `name`, or write it with
+/// `REMOVEBG_BLESS=1`.
+#[track_caller]
+fn check_golden(name: &str, mask: &GrayImage) {
+    let path = fixtures().join("golden").join(format!("{}.mask.png", name));
+    if std::env::var_os("REMOVEBG_BLESS").is_some() {
+        mask.save(&path).expect("golden mask is written");
+        return;
+    }
+    let expected = image::open(&path)
+        .unwrap_or_else(|e| panic!("no golden mask {} ({}); run with REMOVEBG_BLESS=1", path.display(), e))
+        .into_luma8();
+    assert_mask_close(mask, &expected, TOLERANCE);
+}
+
+/// Compare each channel of `cutout` with the golden image `name`, or write
+/// it with `REMOVEBG_BLESS=1`.
+#[track_caller]
+fn check_golden_rgba(name: &str, cutout: &RgbaImage) {
+    let path = fixtures().join("golden").join(format!("{}.png", name));
+    if std::env::var_os("REMOVEBG_BLESS").is_some() {
+        cutout.save(&path).expect("golden image is written");
+        return;
+    }
+    let expected = image::open(&path)
+        .unwrap_or_else(|e| panic!("no golden image {} ({}); run with REMOVEBG_BLESS=1", path.display(), e))
+        .into_rgba8();
+    let channel = |image: &RgbaImage, c: usize| GrayImage::from_fn(image.width(), image.height(), |x, y| image::Luma([image.get_pixel(x, y)[c]]));
+    assert_eq!(cutout.dimensions(), expected.dimensions(), "canvas size");
+    for c in 0..4 {
+        assert_mask_close(&channel(cutout, c), &channel(&expected, c), TOLERANCE);
+    }
+}
+
+fn golden_mask(image: &DynamicImage, options: RemoveBgOptions) -> GrayImage {
+    testing::intermediates(&remover(options), image).expect("model runs").mask_8bit()
+}
+
+#[test]
+fn portrait() {
+    let mask = golden_mask(&fixture("portrait.png"), RemoveBgOptions::default());
+    check_golden("portrait", &mask);
+}
+
+#[test]
+fn portrait_refined() {
+    let options = RemoveBgOptions {
+        keep_largest_component: true,
+        fill_holes: true,
+        mask_levels: Some((0.2, 0.8)),
+        ..Default::default()
+    };
+    let mask = golden_mask(&fixture("portrait.png"), options);
+    check_golden("portrait.refined", &mask);
+}
+
+#[test]
+fn sixteen_bit_input() {
+    let mask = golden_mask(&fixture("gray16.png"), RemoveBgOptions::default());
+    check_golden("gray16", &mask);
+}
+
+#[test]
+fn seeded_images() {
+    for seed in [1, 2] {
+        let image = DynamicImage::ImageRgb8(testing::synthetic_image(seed, 96, 72));
+        let mask = golden_mask(&image, RemoveBgOptions::default());
+        check_golden(&format!("seed{}", seed), &mask);
+    }
+}
+
+#[test]
+fn sticker_outline_and_shadow() {
+    let sticker = StickerOptions { outline_width: 4, outline_color: [255, 220, 0], shadow_offset: (3, 5), shadow_blur: 2.5, shadow_opacity: 0.5 };
+    let options = RemoveBgOptions { sticker: Some(sticker), ..Default::default() };
+    let cutout = remover(options).process_image(&fixture("portrait.png")).expect("model runs");
+    check_golden_rgba("portrait.sticker", &cutout);
+}
+
+#[test]
+fn intermediates_have_model_shapes() {
+    let stages = testing::intermediates(&remover(RemoveBgOptions::default()), &fixture("portrait.png")).unwrap();
+    assert_eq!(stages.input.shape(), &[1, 3, 320, 320]);
+    assert_eq!(stages.model_output.shape(), &[1, 1, 320, 320]);
+    assert_eq!(stages.mask.dimensions(), (120, 90));
+}
+
+#[test]
+fn runs_are_repeatable() {
+    let image = fixture("portrait.png");
+    let remover = remover(RemoveBgOptions::default());
+    let first = testing::intermediates(&remover, &image).unwrap();
+    let second = testing::intermediates(&remover, &image).unwrap();
+    assert_eq!(first.input, second.input);
+    assert_eq!(first.model_output, second.model_output);
+    assert_eq!(first.mask, second.mask);
+}
+
+#[test]
+fn dump_writes_numpy_and_mask_files() {
+    let dir = std::env::temp_dir().join(format!("removebg-dump-{}", std::process::id()));
+    let input = fixtures().join("golden/portrait.png");
+    let options = TestModel::Brightness.options(RemoveBgOptions::default());
+    let paths = testing::dump_intermediates(&input, &dir, &options).unwrap();
+
+    let names: Vec<_> = paths.iter().map(|path| path.file_name().unwrap().to_string_lossy().into_owned()).collect();
+    assert_eq!(names, ["portrait.input.npy", "portrait.output.npy", "portrait.mask.png"]);
+    let npy = std::fs::read(&paths[0]).unwrap();
+    assert!(npy.starts_with(b"\x93NUMPY"));
+    assert_eq!(npy.len(), 128 + 3 * 320 * 320 * 4);
+    let mask = image::open(&paths[2]).unwrap();
+    assert_eq!(mask.color(), image::ColorType::L16);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
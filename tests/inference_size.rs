@@ -0,0 +1,76 @@
+//! Inference size (`RemoveBgOptions::inference_size`, `--inference-size`).
+//! Runs the stub model in `tests/fixtures/models`, whose mask is the
+//! brightness of the image at the size it runs at, so thin lines only
+//! survive at larger sizes.
+
+use image::{DynamicImage, GrayImage, Luma};
+use removebg::testing::TestModel;
+use removebg::{BackgroundRemover, InferenceSize, RemoveBgOptions};
+use std::process::Command;
+
+fn remover(inference_size: InferenceSize) -> BackgroundRemover {
+    BackgroundRemover::new(TestModel::Brightness.options(RemoveBgOptions { inference_size, ..Default::default() }))
+        .unwrap()
+}
+
+/// A 1280x960 black image with a white disc and a grid of 3-pixel white
+/// lines, and its true mask. The lines fall across the pixels of the image
+/// the model runs on, so they are dimmer there the smaller it is.
+fn fine_detail() -> (DynamicImage, GrayImage) {
+    let truth = GrayImage::from_fn(1280, 960, |x, y| {
+        let disc = (x as f32 - 400.0).hypot(y as f32 - 480.0) < 200.0;
+        let line = (2..5).contains(&(x % 80)) || (2..5).contains(&(y % 80));
+        Luma([if disc || line { 255 } else { 0 }])
+    });
+    (DynamicImage::ImageLuma8(truth.clone()).into_rgb8().into(), truth)
+}
+
+/// Intersection over union of the pixels at or above half alpha.
+fn iou(mask: &GrayImage, truth: &GrayImage) -> f32 {
+    let (mut both, mut either) = (0u32, 0u32);
+    for (a, b) in mask.pixels().zip(truth.pixels()) {
+        let (a, b) = (a[0] >= 128, b[0] >= 128);
+        both += (a && b) as u32;
+        either += (a || b) as u32;
+    }
+    both as f32 / either.max(1) as f32
+}
+
+#[test]
+fn larger_sizes_keep_thin_lines() {
+    let (image, truth) = fine_detail();
+    let native = iou(&remover(InferenceSize::Native).mask(&image).unwrap(), &truth);
+    let larger = iou(&remover(InferenceSize::Fixed(640)).mask(&image).unwrap(), &truth);
+    assert!(larger > native + 0.1, "640 IoU {} vs 320 {}", larger, native);
+}
+
+#[test]
+fn the_report_records_the_size_and_its_cost() {
+    let dir = std::env::temp_dir().join(format!("removebg-inference-size-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let (image, _) = fine_detail();
+    let input = dir.join("grid.png");
+    image.save(&input).unwrap();
+
+    let report = remover(InferenceSize::Fixed(640)).process_file(&input, Some(&dir.join("fixed.png"))).unwrap();
+    let inference = report.model.unwrap().inference.unwrap();
+    assert_eq!((inference.size, inference.relative_cost, inference.detail), (640, 4.0, None));
+
+    // The grid is busy enough for 640 at this size, where a plain image gets 320
+    let report = remover(InferenceSize::Auto).process_file(&input, Some(&dir.join("auto.png"))).unwrap();
+    let inference = report.model.unwrap().inference.unwrap();
+    assert_eq!(inference.size, 640);
+    assert!(inference.detail.is_some_and(|detail| detail > 0.0), "{:?}", inference.detail);
+
+    let report = remover(InferenceSize::Native).process_file(&input, Some(&dir.join("native.png"))).unwrap();
+    assert_eq!(report.model.unwrap().inference.map(|inference| inference.size), Some(320));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn the_cli_checks_the_size() {
+    let output = Command::new(env!("CARGO_BIN_EXE_removebg")).args(["photo.jpg", "--inference-size", "650"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("multiple of 32"));
+}
@@ -0,0 +1,105 @@
+//! Per-subject outputs (`RemoveBgOptions::split_instances`,
+//! `--split-instances`) of a scene with well-separated subjects, with a
+//! model that finds red pixels.
+
+use image::{DynamicImage, GenericImageView, Luma, Rgb, RgbImage};
+use removebg::core::Gray16Image;
+use removebg::{BackgroundRemover, InstanceOptions, Rect, RemoveBgError, RemoveBgOptions, Result, SegmentationModel};
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+/// Marks red pixels as subject, with a one-pixel soft edge.
+struct RedModel;
+
+impl SegmentationModel for RedModel {
+    fn predict_mask(&self, image: &DynamicImage) -> Result<Gray16Image> {
+        let image = image.to_rgb8();
+        let red = |x: u32, y: u32| image.get_pixel(x, y).0[0] > 150;
+        Ok(Gray16Image::from_fn(image.width(), image.height(), |x, y| {
+            let near = [(-1, 0), (1, 0), (0, -1), (0, 1)].iter().any(|&(dx, dy)| {
+                let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                nx >= 0 && ny >= 0 && nx < image.width() as i64 && ny < image.height() as i64 && red(nx as u32, ny as u32)
+            });
+            Luma([if red(x, y) { 65535 } else if near { 16384 } else { 0 }])
+        }))
+    }
+}
+
+/// A gray scene with a large red disc on the left, a smaller one on the
+/// right, and a 3x3 red speck in a corner.
+fn scene() -> DynamicImage {
+    let disc = |x: u32, y: u32, cx: f32, cy: f32, r: f32| (x as f32 + 0.5 - cx).hypot(y as f32 + 0.5 - cy) <= r;
+    DynamicImage::ImageRgb8(RgbImage::from_fn(240, 120, |x, y| {
+        let speck = (230..233).contains(&x) && (5..8).contains(&y);
+        if disc(x, y, 60.0, 60.0, 40.0) || disc(x, y, 180.0, 60.0, 25.0) || speck {
+            Rgb([220, 40, 40])
+        } else {
+            Rgb([90, 90, 90])
+        }
+    }))
+}
+
+/// A fresh directory with the scene saved as `group.png`, named for `test`.
+fn input(test: &str) -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("removebg-instances-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("group.png");
+    scene().save(&input).unwrap();
+    (dir, input)
+}
+
+fn remover(split: InstanceOptions) -> BackgroundRemover {
+    BackgroundRemover::with_model(RedModel, RemoveBgOptions { split_instances: Some(split), ..Default::default() })
+}
+
+#[test]
+fn separated_subjects_are_written_largest_first_without_overlap() {
+    let (dir, input) = input("split");
+    let split = InstanceOptions { min_area: 50, margin: 0.0, ..Default::default() };
+    let report = remover(split).process_file(&input, None).unwrap();
+
+    let names: Vec<_> = report.instances.iter().map(|instance| instance.path.file_name().unwrap().to_owned()).collect();
+    assert_eq!(names, ["group_nobg_1.png", "group_nobg_2.png"]);
+    assert!(report.instances[0].area > report.instances[1].area);
+    assert_eq!(report.instances[0].bounds, Rect { x: 20, y: 20, width: 80, height: 80 });
+
+    // Opaque pixels, in input coordinates, of each instance
+    let opaque: Vec<HashSet<(u32, u32)>> = report
+        .instances
+        .iter()
+        .map(|instance| {
+            let image = image::open(&instance.path).unwrap();
+            assert_eq!(image.dimensions(), (instance.bounds.width, instance.bounds.height));
+            let image = image.to_rgba8();
+            let (left, top) = (instance.bounds.x, instance.bounds.y);
+            image.enumerate_pixels().filter(|(.., pixel)| pixel[3] == 255).map(|(x, y, _)| (x + left, y + top)).collect()
+        })
+        .collect();
+    assert!(opaque.iter().all(|pixels| !pixels.is_empty()));
+    assert!(opaque[0].is_disjoint(&opaque[1]));
+    assert!(opaque[0].iter().all(|&(x, _)| x < 120) && opaque[1].iter().all(|&(x, _)| x >= 120));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn margins_cap_and_soft_edges() {
+    let (dir, input) = input("cap");
+    let split = InstanceOptions { min_area: 1, max_instances: 1, margin: 0.1 };
+    let report = remover(split).process_file(&input, None).unwrap();
+    assert_eq!(report.instances.len(), 1);
+    assert!(!dir.join("group_nobg_2.png").exists());
+
+    // 10% of the 80 pixel box on every side, with the soft edge kept
+    let image = image::open(&report.instances[0].path).unwrap().to_rgba8();
+    assert_eq!(image.dimensions(), (96, 96));
+    assert!(image.pixels().any(|pixel| pixel[3] > 0 && pixel[3] < 255));
+
+    // An earlier instance stops a run that would overwrite it before any work
+    std::fs::remove_file(&report.output_path).unwrap();
+    match remover(split).process_file(&input, None) {
+        Err(RemoveBgError::OutputExists(path)) => assert_eq!(path, report.instances[0].path),
+        other => panic!("expected OutputExists, got {:?}", other),
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,138 @@
+//! Manifest-driven batch jobs, run with the stub model in
+//! `tests/fixtures/models`.
+
+use removebg::manifest::{run_manifest, ItemStatus, ManifestConfig};
+use removebg::{testing::{self, TestModel}, RemoveBgOptions};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A fresh directory under the system temp dir with three test images,
+/// named for `test`.
+fn inputs(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("removebg-manifest-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    for (seed, name) in [(1, "a.png"), (2, "b.png"), (3, "c.png")] {
+        testing::synthetic_image(seed, 80, 60).save(dir.join(name)).unwrap();
+    }
+    dir
+}
+
+fn options() -> RemoveBgOptions {
+    TestModel::Brightness.options(RemoveBgOptions::default())
+}
+
+#[test]
+fn items_take_their_overrides_and_inherit_the_rest() {
+    let dir = inputs("library");
+    let out = dir.join("out");
+    let manifest = format!(
+        "{{\"input\": \"{a}\"}}\n\
+         {{\"input\": \"{b}\", \"output\": \"b.jpg\", \"bg_color\": \"white\", \"quality\": 70}}\n\
+         {{\"input\": \"{c}\", \"resize\": \"40x40\"}}\n",
+        a = dir.join("a.png").display(),
+        b = dir.join("b.png").display(),
+        c = dir.join("c.png").display(),
+    );
+    let config = ManifestConfig {
+        output_dir: Some(out.clone()),
+        options: RemoveBgOptions { batch_size: 2, ..options() },
+        ..Default::default()
+    };
+    let results = run_manifest(manifest.as_bytes(), &config).unwrap();
+
+    assert_eq!(results.iter().map(|result| result.line).collect::<Vec<_>>(), [1, 2, 3]);
+    assert!(results.iter().all(|result| result.status == ItemStatus::Done), "{:?}", results);
+    assert_eq!(results[0].output, out.join("a_nobg.png"));
+    assert_eq!(results[1].output, out.join("b.jpg"));
+    assert!(image::open(out.join("a_nobg.png")).unwrap().color().has_alpha());
+    assert_eq!(image::ImageFormat::from_path(out.join("b.jpg")).unwrap(), image::ImageFormat::Jpeg);
+    assert_eq!(image::image_dimensions(out.join("c_nobg.png")).unwrap(), (40, 30));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn failed_items_do_not_stop_the_run() {
+    let dir = inputs("failures");
+    std::fs::write(dir.join("b_nobg.png"), b"taken").unwrap();
+    let manifest = ["a.png", "b.png", "c.png"]
+        .iter()
+        .fold(String::from("input\n"), |csv, name| format!("{}{}\n", csv, dir.join(name).display()));
+    let config = ManifestConfig { options: options(), ..Default::default() };
+    let results = run_manifest(manifest.as_bytes(), &config).unwrap();
+
+    let statuses: Vec<ItemStatus> = results.iter().map(|result| result.status).collect();
+    assert_eq!(statuses, [ItemStatus::Done, ItemStatus::Failed, ItemStatus::Done]);
+    assert!(results[1].error.as_deref().is_some_and(|error| error.contains("already exists")), "{:?}", results[1]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+fn removebg(manifest: &Path) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .args(["batch"])
+        .args(TestModel::Brightness.args())
+        .arg("--manifest")
+        .arg(manifest)
+        .output()
+        .expect("removebg runs")
+}
+
+#[test]
+fn cli_writes_a_results_manifest() {
+    let dir = inputs("cli");
+    let manifest = dir.join("jobs.csv");
+    std::fs::write(
+        &manifest,
+        format!(
+            "input,output,square\n{},,yes\n{},{},\n",
+            dir.join("a.png").display(),
+            dir.join("b.png").display(),
+            dir.join("b_out.webp").display()
+        ),
+    )
+    .unwrap();
+    let output = removebg(&manifest);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    assert_eq!(image::image_dimensions(dir.join("a_nobg.png")).unwrap(), (80, 80));
+    assert!(dir.join("b_out.webp").is_file());
+    let results = std::fs::read_to_string(dir.join("jobs.results.csv")).unwrap();
+    let lines: Vec<&str> = results.lines().collect();
+    assert_eq!(lines[0], "line,input,output,status,time_ms,error");
+    assert_eq!(lines.len(), 3);
+    assert!(lines[1].starts_with("2,") && lines[1].contains(",ok,"), "{}", lines[1]);
+    assert!(lines[2].starts_with("3,") && lines[2].contains("b_out.webp,ok,"), "{}", lines[2]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cli_lists_every_problem_and_processes_nothing() {
+    let dir = inputs("invalid");
+    let manifest = dir.join("jobs.jsonl");
+    let a = dir.join("a.png");
+    std::fs::write(
+        &manifest,
+        format!(
+            "{{\"input\": \"{a}\", \"output\": \"{out}\"}}\n\
+             {{\"input\": \"{missing}\"}}\n\
+             {{\"input\": \"{b}\", \"output\": \"{out}\", \"crop\": \"maybe\"}}\n",
+            a = a.display(),
+            b = dir.join("b.png").display(),
+            missing = dir.join("missing.png").display(),
+            out = dir.join("out.png").display(),
+        ),
+    )
+    .unwrap();
+    let output = removebg(&manifest);
+    assert_eq!(output.status.code(), Some(2));
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let problems: Vec<&str> = stderr.lines().filter(|line| line.starts_with("  line ")).collect();
+    assert_eq!(problems.len(), 3, "{}", stderr);
+    assert!(problems[0].starts_with("  line 2: input ") && problems[0].ends_with("missing.png not found"), "{}", stderr);
+    assert_eq!(problems[1], "  line 3: invalid crop 'maybe' (expected yes or no)");
+    assert!(problems[2].starts_with("  line 3: output ") && problems[2].ends_with("is also written by line 1"), "{}", stderr);
+    assert!(!dir.join("out.png").exists());
+    assert!(!dir.join("jobs.results.jsonl").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
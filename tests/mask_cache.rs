@@ -0,0 +1,152 @@
+//! Mask cache (`RemoveBgOptions::mask_cache`, `--mask-cache`) tests, run
+//! with the stub model in `tests/fixtures/models`.
+
+use image::{DynamicImage, ImageBuffer, Luma};
+use removebg::core::Gray16Image;
+use removebg::mask_cache::{self, CacheStats};
+use removebg::testing::{self, TestModel};
+use removebg::{
+    Background, BackgroundRemover, MaskCacheOptions, Model, RemoveBgOptions, Result, SegmentationModel,
+};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+/// A fresh directory under the system temp dir with two test images and a
+/// cache directory, named for `test`.
+fn workspace(test: &str) -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("removebg-mask-cache-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    for (seed, name) in [(11, "first.png"), (12, "second.png")] {
+        DynamicImage::ImageRgb8(testing::synthetic_image(seed, 160, 120)).save(dir.join(name)).unwrap();
+    }
+    let cache = dir.join("cache");
+    (dir, cache)
+}
+
+fn options(cache: &Path) -> RemoveBgOptions {
+    testing::deterministic(TestModel::Brightness.options(RemoveBgOptions {
+        mask_cache: Some(MaskCacheOptions::new(cache)),
+        overwrite: true,
+        ..Default::default()
+    }))
+}
+
+fn remover(options: RemoveBgOptions) -> BackgroundRemover {
+    BackgroundRemover::new(options).expect("stub model loads")
+}
+
+#[test]
+fn a_second_run_skips_inference_and_matches_the_first() {
+    let (dir, cache) = workspace("hit");
+    let remover = remover(options(&cache));
+    let first = remover.process_file(dir.join("first.png"), Some(&dir.join("cold.png"))).unwrap();
+    assert!(!first.mask_cached);
+    assert!(first.durations.inference > Duration::ZERO);
+    assert_eq!(mask_cache::stats(&cache).unwrap().entries, 1);
+
+    let second = remover.process_file(dir.join("first.png"), Some(&dir.join("warm.png"))).unwrap();
+    assert!(second.mask_cached);
+    assert_eq!(second.durations.inference, Duration::ZERO);
+    assert_eq!(second.durations.preprocess, Duration::ZERO);
+    assert_eq!(second.model.map(|model| model.model), Some(Model::U2netp));
+    assert_eq!(std::fs::read(dir.join("cold.png")).unwrap(), std::fs::read(dir.join("warm.png")).unwrap());
+    assert_eq!(mask_cache::stats(&cache).unwrap().entries, 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn post_processing_changes_hit_and_mask_settings_miss() {
+    let (dir, cache) = workspace("settings");
+    let input = dir.join("first.png");
+    let output = dir.join("out.png");
+    remover(options(&cache)).process_file(&input, Some(&output)).unwrap();
+
+    let flattened = RemoveBgOptions { background: Background::Color([255, 255, 255]), ..options(&cache) };
+    assert!(remover(flattened).process_file(&input, Some(&output)).unwrap().mask_cached);
+    let inverted = RemoveBgOptions { invert_mask: true, ..options(&cache) };
+    assert!(remover(inverted).process_file(&input, Some(&output)).unwrap().mask_cached);
+
+    let gamma = RemoveBgOptions { mask_gamma: Some(2.0), ..options(&cache) };
+    assert!(!remover(gamma).process_file(&input, Some(&output)).unwrap().mask_cached);
+    let other = remover(options(&cache)).process_file(dir.join("second.png"), Some(&output)).unwrap();
+    assert!(!other.mask_cached);
+    assert_eq!(mask_cache::stats(&cache).unwrap().entries, 3);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn batches_only_run_the_model_on_misses() {
+    let (dir, cache) = workspace("batch");
+    let first = image::open(dir.join("first.png")).unwrap();
+    let second = image::open(dir.join("second.png")).unwrap();
+    let options = RemoveBgOptions { batch_size: 2, ..options(&cache) };
+    let remover = remover(options);
+    let alone = remover.process_image(&first).unwrap();
+
+    let batch = remover.process_images(&[first, second]);
+    assert_eq!(batch[0].as_ref().unwrap(), &alone);
+    assert_eq!(mask_cache::stats(&cache).unwrap().entries, 2);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Keeps the whole image, unlike the stub model's masks in the cache.
+struct OpaqueModel;
+
+impl SegmentationModel for OpaqueModel {
+    fn predict_mask(&self, image: &DynamicImage) -> Result<Gray16Image> {
+        Ok(ImageBuffer::from_pixel(image.width(), image.height(), Luma([65535])))
+    }
+}
+
+#[test]
+fn custom_models_bypass_the_cache() {
+    let (dir, cache) = workspace("custom");
+    let input = dir.join("first.png");
+    remover(options(&cache)).process_file(&input, Some(&dir.join("builtin.png"))).unwrap();
+
+    let custom = BackgroundRemover::with_model(OpaqueModel, options(&cache));
+    assert!(custom.options().mask_cache.is_none());
+    let report = custom.process_file(&input, Some(&dir.join("custom.png"))).unwrap();
+    assert!(!report.mask_cached);
+    assert_eq!(report.mask_coverage, 1.0);
+    assert_eq!(mask_cache::stats(&cache).unwrap().entries, 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cli_reports_hits_and_manages_the_cache() {
+    let (dir, cache) = workspace("cli");
+    let removebg = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_removebg"))
+            .args(args)
+            .arg("--model-dir")
+            .arg(TestModel::Brightness.dir())
+            .env("REMOVEBG_MASK_CACHE", &cache)
+            .current_dir(&dir)
+            .output()
+            .expect("removebg runs")
+    };
+    for expected in [false, true] {
+        let run = removebg(&["first.png", "--model", "u2netp", "--json", "--force"]);
+        assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+        let json: serde_json::Value = serde_json::from_slice(&run.stdout).unwrap();
+        assert_eq!(json["mask_cached"], expected);
+    }
+
+    let stats = removebg(&["cache", "stats"]);
+    assert!(stats.status.success(), "{}", String::from_utf8_lossy(&stats.stderr));
+    assert!(String::from_utf8_lossy(&stats.stdout).contains("Masks:     1"));
+    let clear = removebg(&["cache", "clear"]);
+    assert!(String::from_utf8_lossy(&clear.stdout).starts_with("Removed 1 cached masks"));
+    assert_eq!(mask_cache::stats(&cache).unwrap(), CacheStats::default());
+
+    let unset = Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .args(["cache", "stats"])
+        .env_remove("REMOVEBG_MASK_CACHE")
+        .output()
+        .unwrap();
+    assert_eq!(unset.status.code(), Some(2));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,78 @@
+//! Mask post-processing as an ordered operator list (`mask_ops`, `--mask-ops`):
+//! the list replaces the individual refinement options, and the CLI rejects
+//! unknown operators, bad parameters and mixing the list with those flags.
+//! Runs use the stub model in `tests/fixtures/models`.
+
+use image::DynamicImage;
+use removebg::{testing::{self, TestModel}, BackgroundRemover, MaskOp, RemoveBgOptions};
+use std::path::Path;
+use std::process::{Command, Output};
+
+fn remover(options: RemoveBgOptions) -> BackgroundRemover {
+    TestModel::Brightness.remover(options).expect("stub model loads")
+}
+
+fn removebg(args: &[&str], input: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(input)
+        .args(args)
+        .args(TestModel::Brightness.args())
+        .output()
+        .expect("removebg runs")
+}
+
+#[test]
+fn operators_run_in_order_and_replace_the_refinement_options() {
+    let image = DynamicImage::ImageRgb8(testing::synthetic_image(3, 120, 80));
+    let plain = remover(RemoveBgOptions::default()).mask(&image).unwrap();
+
+    // A threshold last leaves a hard mask
+    let ops = vec![MaskOp::Dilate(2), MaskOp::Feather(1.5), MaskOp::Threshold(0.5)];
+    let hard = remover(RemoveBgOptions { mask_ops: ops, ..Default::default() }).mask(&image).unwrap();
+    assert!(hard.iter().all(|&value| value == 0 || value == 255));
+    assert_ne!(hard, plain);
+
+    // Levels, gamma and region options are ignored once a list is given
+    let options = RemoveBgOptions {
+        mask_levels: Some((0.5, 0.5)),
+        mask_gamma: Some(3.0),
+        fill_holes: true,
+        mask_ops: vec![MaskOp::Gamma(1.0)],
+        ..Default::default()
+    };
+    assert_eq!(remover(options).mask(&image).unwrap(), plain);
+
+    let error = remover(RemoveBgOptions { mask_ops: vec![MaskOp::Feather(-1.0)], ..Default::default() })
+        .mask(&image)
+        .unwrap_err();
+    assert!(error.to_string().contains("Invalid mask operation 'feather=-1'"), "{}", error);
+}
+
+#[test]
+fn cli_parses_the_operator_list() {
+    let dir = std::env::temp_dir().join(format!("removebg-mask-ops-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("a.png");
+    testing::synthetic_image(1, 80, 60).save(&input).unwrap();
+    let output = dir.join("a_nobg.png");
+
+    let run = removebg(&["--mask-ops", "dilate=3,feather=2.5,threshold=0.5", "-o", output.to_str().unwrap()], &input);
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+    let alpha = image::open(&output).unwrap().to_rgba8();
+    assert!(alpha.pixels().all(|pixel| pixel[3] == 0 || pixel[3] == 255));
+
+    for (ops, message) in [
+        ("dilate=3,blur=2", "unknown mask operation 'blur'"),
+        ("threshold=2", "threshold must be from 0 to 1"),
+        ("erode=-1", "invalid mask operation 'erode=-1'"),
+    ] {
+        let run = removebg(&["--mask-ops", ops, "--force"], &input);
+        assert_eq!(run.status.code(), Some(2), "{}", ops);
+        assert!(String::from_utf8_lossy(&run.stderr).contains(message), "{}: {}", ops, String::from_utf8_lossy(&run.stderr));
+    }
+
+    let run = removebg(&["--mask-ops", "dilate=1", "--fill-holes", "--force"], &input);
+    assert_eq!(run.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&run.stderr).contains("cannot be used with"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,195 @@
+//! Fitting outputs under `RemoveBgOptions::max_file_size` (`--max-file-size`),
+//! on a noisy photo that compresses badly in every format.
+
+use image::{DynamicImage, GenericImageView, ImageBuffer, Luma, Rgb, RgbImage};
+use removebg::core::Gray16Image;
+use removebg::testing::TestModel;
+use removebg::{
+    Background, BackgroundRemover, FileSizeLimit, OutputFormat, PngCompression, RemoveBgError, RemoveBgOptions,
+    Result, SegmentationModel, Stage,
+};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Keeps a soft-edged disc in the middle of the image.
+struct DiscModel;
+
+impl SegmentationModel for DiscModel {
+    fn predict_mask(&self, image: &DynamicImage) -> Result<Gray16Image> {
+        let (width, height) = image.dimensions();
+        let radius = width.min(height) as f32 * 0.4;
+        Ok(ImageBuffer::from_fn(width, height, |x, y| {
+            let distance = (x as f32 - width as f32 / 2.0).hypot(y as f32 - height as f32 / 2.0);
+            Luma([(((radius + 4.0 - distance) / 8.0).clamp(0.0, 1.0) * 65535.0) as u16])
+        }))
+    }
+}
+
+/// A fresh directory under the system temp dir with a 256x256 noise photo,
+/// named for `test`.
+fn input(test: &str) -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("removebg-max-file-size-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let photo = RgbImage::from_fn(256, 256, |x, y| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        let [r, g, b, ..] = state.to_le_bytes();
+        // Noise over a gradient, like sensor grain on a busy scene
+        Rgb([r / 2 + x as u8 / 2, g / 2 + y as u8 / 2, b])
+    });
+    let path = dir.join("busy.png");
+    photo.save(&path).unwrap();
+    (dir, path)
+}
+
+fn limit(max_bytes: u64) -> Option<FileSizeLimit> {
+    Some(FileSizeLimit { max_bytes, allow_downscale: false })
+}
+
+fn file_size(path: &Path) -> u64 {
+    std::fs::metadata(path).unwrap().len()
+}
+
+fn process(input: &Path, output: &Path, options: RemoveBgOptions) -> Result<removebg::RemovalReport> {
+    BackgroundRemover::with_model(DiscModel, options).process_file(input, Some(output))
+}
+
+#[test]
+fn jpeg_quality_is_lowered_to_fit() {
+    let (dir, photo) = input("jpeg");
+    let output = dir.join("busy.jpg");
+    let options = RemoveBgOptions { background: Background::Color([255, 255, 255]), ..Default::default() };
+    let unlimited = process(&photo, &dir.join("unlimited.jpg"), options.clone()).unwrap();
+    assert!(unlimited.file_size.is_none());
+    let cap = file_size(&unlimited.output_path) / 2;
+
+    let report = process(&photo, &output, RemoveBgOptions { max_file_size: limit(cap), ..options }).unwrap();
+    let fit = report.file_size.unwrap();
+    assert!(file_size(&output) <= cap, "{} > {}", file_size(&output), cap);
+    assert_eq!(fit.size, file_size(&output));
+    let quality = fit.quality.unwrap();
+    assert!((10.0..90.0).contains(&quality), "{}", quality);
+    assert!(!fit.downscaled);
+    assert_eq!(image::image_dimensions(&output).unwrap(), (256, 256));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn lossless_webp_turns_lossy_to_fit() {
+    let (dir, photo) = input("webp");
+    let output = dir.join("busy.webp");
+    let unlimited = process(&photo, &dir.join("unlimited.webp"), RemoveBgOptions::default()).unwrap();
+    let cap = file_size(&unlimited.output_path) / 3;
+
+    let report = process(&photo, &output, RemoveBgOptions { max_file_size: limit(cap), ..Default::default() }).unwrap();
+    let fit = report.file_size.unwrap();
+    assert!(file_size(&output) <= cap, "{} > {}", file_size(&output), cap);
+    assert!(fit.quality.is_some());
+    assert!(fit.attempts > 2, "a binary search takes several attempts, not {}", fit.attempts);
+    // Lossy WebP keeps the alpha channel
+    let alpha: Vec<u8> = image::open(&output).unwrap().to_rgba8().pixels().map(|pixel| pixel[3]).collect();
+    assert!(alpha.contains(&0) && alpha.contains(&255));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn png_is_compressed_then_palettized_to_fit() {
+    let (dir, photo) = input("png");
+    let unlimited = process(&photo, &dir.join("unlimited.png"), RemoveBgOptions::default()).unwrap();
+    let lossless = file_size(&unlimited.output_path);
+
+    // Just under the fast encoding: best compression is enough
+    let output = dir.join("best.png");
+    let options = RemoveBgOptions { max_file_size: limit(lossless - 1), ..Default::default() };
+    let fit = process(&photo, &output, options).unwrap().file_size.unwrap();
+    assert_eq!(fit.png_compression, Some(PngCompression::Best));
+    assert!(!fit.quantized);
+    assert!(file_size(&output) < lossless);
+
+    // Well under it, only a palette fits
+    let output = dir.join("palette.png");
+    let cap = lossless * 2 / 3;
+    let options = RemoveBgOptions { max_file_size: limit(cap), ..Default::default() };
+    let fit = process(&photo, &output, options).unwrap().file_size.unwrap();
+    assert!(fit.quantized);
+    assert!(file_size(&output) <= cap, "{} > {}", file_size(&output), cap);
+    assert_eq!(image::image_dimensions(&output).unwrap(), (256, 256));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn downscaling_is_the_last_resort() {
+    let (dir, photo) = input("downscale");
+    let output = dir.join("small.png");
+    let cap = 12_000;
+
+    let options = RemoveBgOptions { max_file_size: limit(cap), ..Default::default() };
+    let error = process(&photo, &output, options).unwrap_err();
+    assert_eq!((error.stage(), error.input()), (Some(Stage::Encode), Some(photo.as_path())));
+    match error.root() {
+        RemoveBgError::OutputTooLarge { limit, smallest, downscale_allowed: false } => {
+            assert_eq!(*limit, cap);
+            assert!(*smallest > cap);
+        }
+        other => panic!("unexpected error: {:?}", other),
+    }
+    assert!(error.to_string().contains("--allow-downscale"), "{}", error);
+    assert!(!output.exists());
+
+    let options = RemoveBgOptions {
+        max_file_size: Some(FileSizeLimit { max_bytes: cap, allow_downscale: true }),
+        ..Default::default()
+    };
+    let report = process(&photo, &output, options).unwrap();
+    let fit = report.file_size.unwrap();
+    assert!(fit.downscaled && fit.quantized);
+    assert!(file_size(&output) <= cap, "{} > {}", file_size(&output), cap);
+    let (width, height) = image::image_dimensions(&output).unwrap();
+    assert!(width < 256 && width == height, "{}x{}", width, height);
+    assert_eq!(report.output_dimensions, (width, height));
+    assert_eq!(fit.dimensions, (width, height));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn in_memory_output_is_capped() {
+    let (dir, photo) = input("bytes");
+    let data = std::fs::read(&photo).unwrap();
+    let options = RemoveBgOptions { format: Some(OutputFormat::WebP), max_file_size: limit(20_000), ..Default::default() };
+    let encoded = BackgroundRemover::with_model(DiscModel, options).process_bytes(&data).unwrap();
+    assert!(encoded.len() <= 20_000, "{}", encoded.len());
+    assert_eq!(image::load_from_memory(&encoded).unwrap().dimensions(), (256, 256));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cli_reports_the_chosen_settings() {
+    let (dir, photo) = input("cli");
+    let output = dir.join("busy.jpg");
+    let run = Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(&photo)
+        .arg("-o")
+        .arg(&output)
+        .args(["--bg-color", "white", "--max-file-size", "15KB", "--json"])
+        .args(TestModel::Brightness.args())
+        .output()
+        .expect("removebg runs");
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+    let json: serde_json::Value = serde_json::from_slice(&run.stdout).unwrap();
+    let fit = &json["file_size"];
+    assert_eq!(fit["size"], file_size(&output));
+    assert!(fit["size"].as_u64().unwrap() <= 15_000, "{}", fit);
+    assert!(fit["quality"].as_f64().is_some(), "{}", fit);
+
+    let run = Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(&photo)
+        .args(["--max-file-size", "two megabytes"])
+        .output()
+        .expect("removebg runs");
+    assert_eq!(run.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&run.stderr).contains("expected a size like 2MB"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
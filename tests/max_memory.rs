@@ -0,0 +1,94 @@
+//! `max_memory_bytes` / `--max-memory` tests, run with the stub model in
+//! `tests/fixtures/models`. The 120x90 RGB portrait is estimated at 183,600
+//! bytes, or 140,400 with its mask upscaled in bands.
+
+use image::DynamicImage;
+use removebg::testing::{self, TestModel};
+use removebg::{BackgroundRemover, RemoveBgError, RemoveBgOptions, Stage};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn fixtures() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+fn remover(max_memory_bytes: Option<u64>) -> BackgroundRemover {
+    TestModel::Brightness.remover(RemoveBgOptions { max_memory_bytes, ..Default::default() }).expect("stub model loads")
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("removebg-max-memory-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn a_tight_budget_bands_the_mask_and_keeps_the_output() {
+    let dir = temp_dir("bands");
+    let input = fixtures().join("golden/portrait.png");
+
+    let roomy = remover(Some(1 << 20)).process_file(&input, Some(&dir.join("roomy.png"))).unwrap();
+    let estimate = roomy.memory_estimate.unwrap();
+    assert_eq!((estimate.total_bytes, estimate.banded), (183_600, false));
+
+    let tight = remover(Some(150_000)).process_file(&input, Some(&dir.join("tight.png"))).unwrap();
+    let estimate = tight.memory_estimate.unwrap();
+    assert_eq!((estimate.total_bytes, estimate.banded), (140_400, true));
+
+    let (roomy, tight) = (image::open(&roomy.output_path).unwrap().to_rgba8(), image::open(&tight.output_path).unwrap().to_rgba8());
+    let diff = roomy.pixels().zip(tight.pixels()).map(|(a, b)| a[3].abs_diff(b[3])).max();
+    assert!(diff <= Some(1), "alpha differs by {:?}", diff);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn images_over_budget_are_refused_before_decoding() {
+    let dir = temp_dir("refused");
+    let output = dir.join("out.png");
+    let error = remover(Some(100_000)).process_file(fixtures().join("golden/portrait.png"), Some(&output)).unwrap_err();
+    assert!(
+        matches!(error.root(), RemoveBgError::MemoryBudgetExceeded { estimated: 140_400, budget: 100_000 }),
+        "{:?}",
+        error
+    );
+    assert_eq!((error.stage(), error.exit_code()), (Some(Stage::Decode), 2));
+    assert!(!output.exists());
+
+    // Decoded images are checked too, each on its own in a batch
+    let small = DynamicImage::ImageRgb8(testing::synthetic_image(1, 40, 30));
+    let large = DynamicImage::ImageRgb8(testing::synthetic_image(2, 400, 300));
+    let results = remover(Some(100_000)).process_images(&[small, large]);
+    assert!(results[0].is_ok());
+    assert!(matches!(results[1].as_ref().unwrap_err().root(), RemoveBgError::MemoryBudgetExceeded { .. }));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+fn removebg(output: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(fixtures().join("golden/portrait.png"))
+        .arg("-o")
+        .arg(output)
+        .args(TestModel::Brightness.args())
+        .args(args)
+        .env_remove("REMOVEBG_MAX_MEMORY")
+        .output()
+        .expect("removebg runs")
+}
+
+#[test]
+fn the_cli_reports_and_enforces_the_budget() {
+    let dir = temp_dir("cli");
+    let output = removebg(&dir.join("out.png"), &["--max-memory", "1MiB", "--json"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["memory_estimate"]["total_bytes"], 183_600);
+    assert_eq!(json["memory_estimate"]["banded"], false);
+
+    let output = removebg(&dir.join("refused.png"), &["--max-memory", "100KB"]);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("over the budget of 97.7 KiB; raise --max-memory"), "{}", stderr);
+    assert!(!dir.join("refused.png").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,64 @@
+//! `max_output_size` tests, run with the stub model in `tests/fixtures/models`.
+
+use image::DynamicImage;
+use removebg::testing::{self, TestModel};
+use removebg::{BackgroundRemover, RemoveBgOptions};
+use std::path::PathBuf;
+use std::time::Instant;
+
+fn fixtures() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+fn remover(options: RemoveBgOptions) -> BackgroundRemover {
+    TestModel::Brightness.remover(options).expect("stub model loads")
+}
+
+#[test]
+fn large_inputs_are_downscaled_before_compositing() {
+    let dir = std::env::temp_dir().join(format!("removebg-max-output-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("wide.png");
+    DynamicImage::ImageRgb8(testing::synthetic_image(3, 6000, 900)).save(&input).unwrap();
+
+    let started = Instant::now();
+    let full = remover(RemoveBgOptions::default()).process_file(&input, Some(&dir.join("full.png"))).unwrap();
+    let full_time = started.elapsed();
+    let started = Instant::now();
+    let options = RemoveBgOptions { max_output_size: Some(1500), ..Default::default() };
+    let small = remover(options).process_file(&input, Some(&dir.join("small.png"))).unwrap();
+    let small_time = started.elapsed();
+    // Timings vary too much between machines to assert on; see `cargo bench`
+    eprintln!("full resolution: {:?}, --max-output-size 1500: {:?}", full_time, small_time);
+
+    assert_eq!(full.output_dimensions, (6000, 900));
+    assert_eq!(small.input_dimensions, (6000, 900));
+    assert_eq!(small.output_dimensions, (1500, 225));
+    assert_eq!(image::image_dimensions(&small.output_path).unwrap(), (1500, 225));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn inputs_that_fit_keep_their_size() {
+    let image = DynamicImage::ImageRgb8(testing::synthetic_image(4, 300, 200));
+    let mut png = std::io::Cursor::new(Vec::new());
+    image.write_to(&mut png, image::ImageFormat::Png).unwrap();
+    let options = RemoveBgOptions { max_output_size: Some(300), ..Default::default() };
+    let output = remover(options).process_bytes(png.get_ref()).unwrap();
+    assert_eq!(image::load_from_memory(&output).unwrap().to_rgba8().dimensions(), (300, 200));
+}
+
+#[test]
+fn zero_and_roi_are_rejected() {
+    let image = fixtures().join("golden/portrait.png");
+    let options = RemoveBgOptions { max_output_size: Some(0), ..Default::default() };
+    let dir = std::env::temp_dir();
+    assert!(remover(options).process_file(&image, Some(&dir.join("removebg-max-zero.png"))).is_err());
+    let options = RemoveBgOptions {
+        max_output_size: Some(50),
+        roi: Some(removebg::Rect { x: 0, y: 0, width: 10, height: 10 }),
+        ..Default::default()
+    };
+    let error = remover(options).process_file(&image, Some(&dir.join("removebg-max-roi.png"))).unwrap_err();
+    assert!(error.to_string().contains("region of interest"), "{}", error);
+}
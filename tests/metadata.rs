@@ -0,0 +1,80 @@
+//! Color profiles carried from the input into the output: an iCCP chunk of a
+//! PNG input comes out unchanged in the cutout's, unless
+//! `--strip-metadata`. Runs use the stub model in `tests/fixtures/models`.
+
+use removebg::testing::{self, TestModel};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// A small RGB display profile named "Display P3 (removebg test)": the ICC
+/// header and one `desc` tag, enough for readers to take it as a profile.
+fn display_p3_profile() -> Vec<u8> {
+    let description = b"Display P3 (removebg test)\0";
+    let tag_data: Vec<u8> = [&b"text"[..], &[0; 4], description].concat();
+    let size = 128 + 4 + 12 + tag_data.len();
+    let mut profile = vec![0u8; 128];
+    profile[0..4].copy_from_slice(&(size as u32).to_be_bytes());
+    profile[8..12].copy_from_slice(&[4, 0x40, 0, 0]);
+    profile[12..16].copy_from_slice(b"mntr");
+    profile[16..20].copy_from_slice(b"RGB ");
+    profile[20..24].copy_from_slice(b"XYZ ");
+    profile[36..40].copy_from_slice(b"acsp");
+    profile.extend(1u32.to_be_bytes());
+    profile.extend(b"desc");
+    profile.extend(144u32.to_be_bytes());
+    profile.extend((tag_data.len() as u32).to_be_bytes());
+    profile.extend(tag_data);
+    profile
+}
+
+/// Write a test image as an RGB PNG carrying `profile`.
+fn write_png_with_profile(path: &Path, profile: &[u8]) {
+    let image = testing::synthetic_image(6, 64, 48);
+    let mut info = png::Info::with_size(image.width(), image.height());
+    info.icc_profile = Some(profile.to_vec().into());
+    let mut encoder = png::Encoder::with_info(BufWriter::new(File::create(path).unwrap()), info).unwrap();
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header().unwrap().write_image_data(image.as_raw()).unwrap();
+}
+
+/// The profile in the iCCP chunk of the PNG at `path`, if any.
+fn png_profile(path: &Path) -> Option<Vec<u8>> {
+    let reader = png::Decoder::new(std::io::BufReader::new(File::open(path).unwrap())).read_info().unwrap();
+    reader.info().icc_profile.as_ref().map(|profile| profile.to_vec())
+}
+
+fn removebg(input: &Path, output: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(input)
+        .arg("-o")
+        .arg(output)
+        .args(TestModel::Brightness.args())
+        .args(args)
+        .output()
+        .expect("removebg runs")
+}
+
+#[test]
+fn png_profiles_round_trip_unless_stripped() {
+    let dir: PathBuf = std::env::temp_dir().join(format!("removebg-metadata-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let profile = display_p3_profile();
+    let input = dir.join("p3.png");
+    write_png_with_profile(&input, &profile);
+    assert_eq!(png_profile(&input).as_deref(), Some(&profile[..]));
+
+    let kept = dir.join("kept.png");
+    let output = removebg(&input, &kept, &[]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(png_profile(&kept).as_deref(), Some(&profile[..]));
+
+    let stripped = dir.join("stripped.png");
+    let output = removebg(&input, &stripped, &["--strip-metadata"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(png_profile(&stripped), None);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,147 @@
+//! Several processes sharing a fresh model directory download the model
+//! once, and `removebg model list` and `model remove` report and delete the
+//! files in a model directory.
+
+use removebg::testing::TestModel;
+use sha2::{Digest, Sha256};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::net::TcpListener;
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Serve `body` to every request on a local port, slowly enough that the
+/// processes overlap, and count the requests.
+fn serve(body: Vec<u8>) -> (String, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/u2netp.onnx", listener.local_addr().unwrap());
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = requests.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            counter.fetch_add(1, Ordering::SeqCst);
+            let body = body.clone();
+            std::thread::spawn(move || {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).is_ok_and(|read| read > 2) {
+                    line.clear();
+                }
+                let header = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+                let _ = stream.write_all(header.as_bytes());
+                for chunk in body.chunks(body.len().div_ceil(10)) {
+                    std::thread::sleep(Duration::from_millis(100));
+                    let _ = stream.write_all(chunk);
+                }
+            });
+        }
+    });
+    (url, requests)
+}
+
+#[test]
+fn concurrent_processes_download_the_model_once() {
+    let fixture = TestModel::Brightness.dir().join("u2netp.onnx");
+    let body = std::fs::read(fixture).unwrap();
+    let sha256 = format!("{:x}", Sha256::digest(&body));
+    let (url, requests) = serve(body.clone());
+
+    let dir = std::env::temp_dir().join(format!("removebg-model-cache-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let processes: Vec<_> = (0..4)
+        .map(|_| {
+            Command::new(env!("CARGO_BIN_EXE_removebg"))
+                .args(["model", "fetch", "u2netp", "--model-url", &url, "--model-sha256", &sha256])
+                .arg("--model-dir")
+                .arg(&dir)
+                .env("NO_PROXY", "127.0.0.1")
+                .env_remove("HTTP_PROXY")
+                .env_remove("http_proxy")
+                .env_remove("REMOVEBG_MODEL_URL")
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .unwrap()
+        })
+        .collect();
+
+    let mut waited = false;
+    for process in processes {
+        let output = process.wait_with_output().unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{}", stderr);
+        waited |= stderr.contains("Waiting for another process to finish downloading");
+    }
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+    assert!(waited, "no process waited for the download");
+    assert_eq!(std::fs::read(dir.join("u2netp.onnx")).unwrap(), body);
+    let leftovers: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".part"))
+        .collect();
+    assert!(leftovers.is_empty(), "{:?}", leftovers);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Run `removebg model` with `args` on the models in `dir`, answering any
+/// question with `answer`.
+fn model_command(dir: &Path, args: &[&str], answer: &str) -> Output {
+    let mut process = Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg("model")
+        .args(args)
+        .arg("--model-dir")
+        .arg(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    process.stdin.take().unwrap().write_all(answer.as_bytes()).unwrap();
+    let output = process.wait_with_output().unwrap();
+    assert!(output.status.success(), "{:?}: {}", args, String::from_utf8_lossy(&output.stderr));
+    output
+}
+
+/// The line of `model list` output for `model`.
+fn list_line(dir: &Path, model: &str) -> String {
+    let stdout = String::from_utf8(model_command(dir, &["list"], "").stdout).unwrap();
+    let line = stdout.lines().find(|line| line.split_whitespace().next() == Some(model));
+    line.unwrap_or_else(|| panic!("no {} in {}", model, stdout)).to_string()
+}
+
+#[test]
+fn cached_models_are_listed_and_removed() {
+    let dir = std::env::temp_dir().join(format!("removebg-model-cache-commands-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let (model, checksum) = (dir.join("u2net.onnx"), dir.join("u2net.onnx.sha256"));
+
+    // Uncached models show their download size
+    let line = list_line(&dir, "u2net");
+    assert!(line.contains(" no ") && line.contains("176.00 MB"), "{}", line);
+    assert!(line.ends_with(&model.display().to_string()), "{}", line);
+    let stdout = model_command(&dir, &["remove", "u2net"], "").stdout;
+    assert_eq!(String::from_utf8_lossy(&stdout).trim(), "Model u2net is not cached");
+
+    std::fs::write(&model, vec![0; 1500]).unwrap();
+    std::fs::write(&checksum, "0".repeat(64)).unwrap();
+    let line = list_line(&dir, "u2net");
+    assert!(line.contains(" yes ") && line.contains("1.5 KB"), "{}", line);
+    assert!(list_line(&dir, "silueta").contains(" no "));
+
+    // Removing asks first, and keeps the files unless the answer is yes
+    let output = model_command(&dir, &["remove", "u2net"], "n\n");
+    assert!(String::from_utf8_lossy(&output.stderr).contains(&format!("Delete {}? [y/N]", model.display())));
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "Aborted");
+    assert!(model.exists() && checksum.exists());
+
+    let stdout = model_command(&dir, &["remove", "u2net", "--yes"], "").stdout;
+    assert_eq!(String::from_utf8_lossy(&stdout).trim(), format!("Removed {}", model.display()));
+    assert!(!model.exists() && !checksum.exists());
+    assert!(list_line(&dir, "u2net").contains(" no "));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,58 @@
+//! Model input detection tests: the stub model of `tests/fixtures/models`
+//! exported with other input names, sizes and element types (see
+//! `make_stub.py` there).
+
+use image::DynamicImage;
+use removebg::testing;
+use removebg::{BackgroundRemover, Model, RemoveBgError, RemoveBgOptions};
+use std::path::PathBuf;
+
+fn models(variant: &str) -> PathBuf {
+    testing::TestModel::Brightness.dir().join(variant)
+}
+
+fn remover(variant: &str) -> removebg::Result<BackgroundRemover> {
+    let options = RemoveBgOptions { model: Model::U2netp, model_dir: Some(models(variant)), ..Default::default() };
+    BackgroundRemover::new(testing::deterministic(options))
+}
+
+#[test]
+fn input_name_and_size_are_read_from_the_model() {
+    let image = DynamicImage::ImageRgb8(testing::synthetic_image(5, 120, 80));
+    for (variant, size) in [("input-1", 64), ("img", 96), ("", 320)] {
+        let remover = remover(variant).unwrap();
+        let stages = testing::intermediates(&remover, &image).unwrap();
+        assert_eq!(stages.input.shape(), &[1, 3, size, size], "{}", variant);
+        assert_eq!(stages.model_output.shape(), &[1, 1, size, size], "{}", variant);
+        assert_eq!(stages.mask.dimensions(), (120, 80), "{}", variant);
+    }
+}
+
+#[test]
+fn models_with_other_input_names_run_end_to_end() {
+    let mut png = std::io::Cursor::new(Vec::new());
+    DynamicImage::ImageRgb8(testing::synthetic_image(6, 50, 40)).write_to(&mut png, image::ImageFormat::Png).unwrap();
+    for variant in ["input-1", "img"] {
+        let output = remover(variant).unwrap().process_bytes(png.get_ref()).unwrap();
+        assert_eq!(image::load_from_memory(&output).unwrap().to_rgba8().dimensions(), (50, 40), "{}", variant);
+    }
+}
+
+#[test]
+fn preprocessed_input_must_match_the_model_size() {
+    let remover = remover("input-1").unwrap();
+    let image = DynamicImage::ImageRgb8(testing::synthetic_image(7, 30, 30));
+    let input = removebg::pipeline::preprocess(&image, Model::U2netp.spec());
+    let error = removebg::pipeline::infer(&remover, input).unwrap_err();
+    assert!(error.to_string().contains("(1, 3, 64, 64)"), "{}", error);
+}
+
+#[test]
+fn non_float_inputs_are_rejected() {
+    match remover("int64") {
+        Err(RemoveBgError::ModelInitError(message)) => {
+            assert!(message.contains("'input'") && message.contains("float32"), "{}", message)
+        }
+        other => panic!("expected ModelInitError, got {:?}", other.map(|_| ())),
+    }
+}
@@ -0,0 +1,88 @@
+//! The silueta and birefnet-lite models: registry entries, per-model input
+//! normalization and the sigmoid for outputs that are logits, run with the
+//! stub model under their file names. The end-to-end runs with the real
+//! models download them and are ignored by default:
+//! `cargo test --test model_tiers -- --ignored`.
+
+use image::{DynamicImage, Rgb, RgbImage};
+use removebg::model::Normalization;
+use removebg::{testing::{self, TestModel}, BackgroundRemover, Model, RemoveBgOptions};
+use std::path::{Path, PathBuf};
+
+fn fixtures() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+/// A model directory holding the stub model under `model`'s file name.
+fn stub_as(model: Model) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("removebg-model-tiers-{}-{}", model, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::copy(TestModel::Brightness.dir().join("u2netp.onnx"), dir.join(model.spec().file_name)).unwrap();
+    dir
+}
+
+#[test]
+fn registry_has_both_tiers() {
+    assert_eq!("silueta".parse::<Model>(), Ok(Model::Silueta));
+    assert_eq!("BiRefNet_Lite".parse::<Model>(), Ok(Model::BirefnetLite));
+    assert!(Model::ALL.contains(&Model::Silueta) && Model::ALL.contains(&Model::BirefnetLite));
+
+    let silueta = Model::Silueta.spec();
+    assert_eq!((silueta.input_size, silueta.normalization, silueta.logits), (320, Normalization::NONE, false));
+    let birefnet = Model::BirefnetLite.spec();
+    assert_eq!((birefnet.input_size, birefnet.normalization, birefnet.logits), (1024, Normalization::IMAGENET, true));
+    assert!("unknown".parse::<Model>().unwrap_err().to_string().contains("silueta, birefnet-lite"));
+}
+
+#[test]
+fn inputs_are_normalized_per_model_and_logits_squashed() {
+    // The stub averages its three input channels into the mask
+    let red = DynamicImage::ImageRgb8(RgbImage::from_pixel(64, 64, Rgb([255, 0, 0])));
+    let mask = |model: Model| {
+        let dir = stub_as(model);
+        let options = RemoveBgOptions { model, model_dir: Some(dir.clone()), ..Default::default() };
+        let mask = BackgroundRemover::new(testing::deterministic(options)).unwrap().mask(&red).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        mask.get_pixel(32, 32)[0]
+    };
+
+    // silueta: (1 + 0 + 0) / 3
+    assert_eq!(mask(Model::Silueta), 85);
+
+    // birefnet-lite: the ImageNet-normalized channels average to -0.53,
+    // outside 0-1, so the output is taken as a logit: sigmoid(-0.53) = 0.37
+    let n = Normalization::IMAGENET;
+    let logit = (0..3).map(|c| ([1.0, 0.0, 0.0][c] - n.mean[c]) / n.std[c]).sum::<f32>() / 3.0;
+    let expected = (255.0 / (1.0 + (-logit).exp())).round() as u8;
+    assert_eq!(expected, 94);
+    assert!(mask(Model::BirefnetLite).abs_diff(expected) <= 1);
+}
+
+/// Run `model`, downloading it if needed, on the portrait fixture and check
+/// the mask is a plausible subject: neither empty nor everything, and
+/// denser in the middle than along the edges.
+fn check_plausible_portrait(model: Model) {
+    let portrait = image::open(fixtures().join("golden/portrait.png")).unwrap();
+    let remover = BackgroundRemover::new(RemoveBgOptions { model, ..Default::default() }).unwrap();
+    let mask = remover.mask(&portrait).unwrap();
+    let coverage = mask.iter().filter(|&&alpha| alpha >= 128).count() as f32 / mask.len() as f32;
+    assert!((0.05..0.95).contains(&coverage), "{} covers {:.1}%", model, coverage * 100.0);
+
+    let (width, height) = mask.dimensions();
+    let mean = |pixels: Vec<u8>| pixels.iter().map(|&alpha| alpha as f32).sum::<f32>() / pixels.len() as f32;
+    let middle = mean(mask.enumerate_pixels().filter(|(x, y, _)| (width / 3..2 * width / 3).contains(x) && (height / 3..2 * height / 3).contains(y)).map(|(_, _, p)| p[0]).collect());
+    let border = mean(mask.enumerate_pixels().filter(|(x, y, _)| *x == 0 || *y == 0 || *x == width - 1).map(|(_, _, p)| p[0]).collect());
+    assert!(middle > border, "{}: middle {:.0}, border {:.0}", model, middle, border);
+}
+
+#[test]
+#[ignore = "downloads silueta (about 43 MB)"]
+fn silueta_finds_the_portrait() {
+    check_plausible_portrait(Model::Silueta);
+}
+
+#[test]
+#[ignore = "downloads birefnet-lite and runs it at 1024x1024"]
+fn birefnet_lite_finds_the_portrait() {
+    check_plausible_portrait(Model::BirefnetLite);
+}
@@ -0,0 +1,166 @@
+//! Output names: `--prefix` and `--suffix`, `--on-collision` for outputs
+//! that exist or that two inputs of a batch would share, and `-o` paths
+//! whose extension, in any case, picks the format. Runs use the stub model
+//! in `tests/fixtures/models`.
+
+use image::DynamicImage;
+use removebg::batch::{process_directory, BatchConfig};
+use removebg::{testing::{self, TestModel}, Collision, NamingOptions, RemoveBgOptions};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// A directory holding `a.jpg`, `a.png` and `b.png`: the first two share the
+/// stem, so their default outputs collide.
+fn inputs(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("removebg-naming-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    for (seed, name) in ["a.jpg", "a.png", "b.png"].into_iter().enumerate() {
+        DynamicImage::ImageRgb8(testing::synthetic_image(seed as u64, 48, 32)).save(dir.join(name)).unwrap();
+    }
+    dir
+}
+
+fn names(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> =
+        std::fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect();
+    names.sort();
+    names
+}
+
+fn batch(dir: &Path, naming: NamingOptions) -> removebg::batch::BatchSummary {
+    let options = TestModel::Brightness.options(RemoveBgOptions { naming, ..Default::default() });
+    process_directory(BatchConfig { options, ..BatchConfig::new(dir) }).unwrap()
+}
+
+fn removebg(args: &[&str], input: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(input)
+        .args(args)
+        .args(TestModel::Brightness.args())
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn batch_collisions_fail_or_are_renamed() {
+    // Both inputs sharing a name fail before anything is written
+    let dir = inputs("batch-error");
+    let summary = batch(&dir, NamingOptions::default());
+    assert_eq!((summary.processed, summary.failed), (1, 2));
+    assert_eq!(names(&dir), ["a.jpg", "a.png", "b.png", "b_nobg.png"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // Overwriting doesn't let one input replace another's output
+    let dir = inputs("batch-overwrite");
+    let summary = batch(&dir, NamingOptions { on_collision: Collision::Overwrite, ..Default::default() });
+    assert_eq!((summary.processed, summary.failed), (1, 2));
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // Renaming numbers the later input in name order, and again on a rerun
+    let dir = inputs("batch-rename");
+    let rename = NamingOptions { prefix: "cut_".into(), on_collision: Collision::Rename, ..Default::default() };
+    let summary = batch(&dir, rename.clone());
+    assert_eq!((summary.processed, summary.failed), (3, 0));
+    assert_eq!(names(&dir), ["a.jpg", "a.png", "b.png", "cut_a_nobg-1.png", "cut_a_nobg.png", "cut_b_nobg.png"]);
+
+    // The renamed outputs aren't taken for inputs
+    let summary = batch(&dir, rename);
+    assert_eq!((summary.processed, summary.failed), (3, 0));
+    assert_eq!(
+        names(&dir),
+        [
+            "a.jpg",
+            "a.png",
+            "b.png",
+            "cut_a_nobg-1.png",
+            "cut_a_nobg-2.png",
+            "cut_a_nobg-3.png",
+            "cut_a_nobg.png",
+            "cut_b_nobg-1.png",
+            "cut_b_nobg.png"
+        ]
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cli_names_and_collision_policies() {
+    let dir = inputs("cli");
+    let input = dir.join("b.png");
+
+    let output = removebg(&["--suffix", "-cutout", "--prefix", "ü_"], &input);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(dir.join("ü_b-cutout.png").is_file());
+
+    // An existing output fails with exit code 4 unless renamed or replaced
+    let output = removebg(&["--suffix", "-cutout", "--prefix", "ü_"], &input);
+    assert_eq!(output.status.code(), Some(4), "{:?}", output);
+    let output = removebg(&["--suffix", "-cutout", "--prefix", "ü_", "--on-collision", "rename"], &input);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(dir.join("ü_b-cutout-1.png").is_file());
+    let output = removebg(&["--suffix", "-cutout", "--prefix", "ü_", "--on-collision", "overwrite"], &input);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(!dir.join("ü_b-cutout-2.png").exists());
+
+    // An empty suffix would write over a PNG input
+    let output = removebg(&["--suffix", ""], &input);
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("would be written over its input"), "{:?}", output);
+    let output = removebg(&["--suffix", "", "--on-collision", "rename"], &input);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(dir.join("b-1.png").is_file());
+
+    let output = removebg(&["--on-collision", "sometimes"], &input);
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+    let output = removebg(&["--prefix", "sub/"], &input);
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn outputs_keep_the_extension_asked_for() {
+    use image::ImageFormat::{Bmp, Jpeg, Png, Tiff, WebP};
+    let dir = inputs("extensions");
+    let input = dir.join("b.png");
+
+    // The default name stays a PNG
+    let output = removebg(&[], &input);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(dir.join("b_nobg.png").is_file());
+
+    for (name, format, alpha) in [
+        ("out.png", Png, true),
+        ("OUT.PNG", Png, true),
+        ("out.tif", Tiff, true),
+        ("out.tiff", Tiff, true),
+        ("out.TIFF", Tiff, true),
+        ("out.bmp", Bmp, true),
+        ("out.Bmp", Bmp, true),
+        ("out.webp", WebP, true),
+        ("out.WebP", WebP, true),
+    ] {
+        let path = dir.join(name);
+        let output = removebg(&["-o", path.to_str().unwrap()], &input);
+        assert!(output.status.success(), "{}: {:?}", name, output);
+        let reader = image::ImageReader::open(&path).unwrap().with_guessed_format().unwrap();
+        assert_eq!(reader.format(), Some(format), "{}", name);
+        assert_eq!(reader.decode().unwrap().color().has_alpha(), alpha, "{}", name);
+    }
+
+    // JPEG can't hold the cutout's transparency unless it is flattened
+    for name in ["out.jpg", "out.JPEG"] {
+        let path = dir.join(name);
+        let output = removebg(&["-o", path.to_str().unwrap()], &input);
+        assert_eq!(output.status.code(), Some(2), "{}: {:?}", name, output);
+        assert!(String::from_utf8_lossy(&output.stderr).contains("JPEG"), "{}: {:?}", name, output);
+        assert!(!path.exists(), "{}", name);
+
+        let output = removebg(&["-o", path.to_str().unwrap(), "--bg-color", "white"], &input);
+        assert!(output.status.success(), "{}: {:?}", name, output);
+        let reader = image::ImageReader::open(&path).unwrap().with_guessed_format().unwrap();
+        assert_eq!(reader.format(), Some(Jpeg), "{}", name);
+        assert!(!reader.decode().unwrap().color().has_alpha(), "{}", name);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
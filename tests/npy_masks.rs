@@ -0,0 +1,95 @@
+//! NumPy `.npy` masks: the fixtures in `tests/fixtures/npy` (the bytes
+//! `numpy.save` writes, see `make_npy.py` there) read back as written, our
+//! writer produces the same bytes, and the CLI saves masks with
+//! `--save-mask=npy` and applies them with `--use-mask`. Runs use the stub
+//! model in `tests/fixtures/models`.
+
+use ndarray::ArrayD;
+use removebg::{npy, DecodeLimits};
+use removebg::testing::{self, TestModel};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+}
+
+/// A fresh directory named for `test`.
+fn workspace(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("removebg-npy-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn removebg(input: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(input)
+        .args(args)
+        .args(TestModel::Brightness.args())
+        .output()
+        .expect("removebg runs")
+}
+
+const VALUES: [f32; 6] = [0.0, 0.25, 0.5, 0.75, 1.0, 0.1];
+
+#[test]
+fn numpy_files_read_as_written() {
+    let mask = npy::read_mask(File::open(fixture("npy/mask_f32.npy")).unwrap(), &DecodeLimits::default()).unwrap().into_luma16();
+    assert_eq!(mask.dimensions(), (3, 2));
+    let expected: Vec<u16> = VALUES.iter().map(|value| (value * 65535.0).round() as u16).collect();
+    assert_eq!(mask.as_raw(), &expected);
+
+    let mask = npy::read_mask(File::open(fixture("npy/mask_u8.npy")).unwrap(), &DecodeLimits::default()).unwrap();
+    assert_eq!(mask.as_luma8().unwrap().as_raw(), &[0, 64, 128, 192, 255, 26]);
+}
+
+#[test]
+fn writer_matches_numpy_byte_for_byte() {
+    let array = ArrayD::from_shape_vec(vec![2, 3], VALUES.to_vec()).unwrap();
+    let mut written = Vec::new();
+    npy::write_f32(&mut written, array.view()).unwrap();
+    assert_eq!(written, std::fs::read(fixture("npy/mask_f32.npy")).unwrap());
+}
+
+#[test]
+fn cli_saves_and_reuses_npy_masks() {
+    let dir = workspace("cli");
+    let input = dir.join("photo.png");
+    testing::synthetic_image(3, 120, 80).save(&input).unwrap();
+
+    let output = removebg(&input, &["--save-mask=npy", "-o", dir.join("first.png").to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let mask_path = dir.join("photo_mask.npy");
+    let mask = npy::read_mask(File::open(&mask_path).unwrap(), &DecodeLimits::default()).unwrap().into_luma16();
+    assert_eq!(mask.dimensions(), (120, 80));
+    assert!(!dir.join("photo_mask.png").exists());
+
+    // The saved mask gives the same cutout without the model
+    let output = removebg(&input, &["--use-mask", mask_path.to_str().unwrap(), "-o", dir.join("second.png").to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let alpha = |name: &str| image::open(dir.join(name)).unwrap().to_rgba8().pixels().map(|pixel| pixel[3]).collect::<Vec<_>>();
+    assert_eq!(alpha("first.png"), alpha("second.png"));
+
+    // --save-mask alone still writes a PNG
+    let output = removebg(&input, &["--save-mask", "-o", dir.join("third.png").to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(dir.join("photo_mask.png").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cli_rejects_unsupported_arrays() {
+    let dir = workspace("reject");
+    let input = dir.join("photo.png");
+    testing::synthetic_image(4, 40, 30).save(&input).unwrap();
+    let rgb = dir.join("rgb.npy");
+    npy::write_f32(File::create(&rgb).unwrap(), ArrayD::zeros(vec![30, 40, 3]).view()).unwrap();
+
+    let output = removebg(&input, &["--use-mask", rgb.to_str().unwrap(), "-o", dir.join("out.png").to_str().unwrap()]);
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("expected a 2-D (height, width) npy array, got shape (30, 40, 3)"), "{}", stderr);
+}
@@ -0,0 +1,100 @@
+//! `s3://` inputs, outputs and prefix batches against an S3-compatible
+//! server such as MinIO or LocalStack. The tests need a running server and a
+//! bucket, so they are ignored by default:
+//!
+//! ```text
+//! docker run -d -p 9000:9000 minio/minio server /data
+//! AWS_ENDPOINT=http://localhost:9000 AWS_ALLOW_HTTP=true AWS_REGION=us-east-1 \
+//!     AWS_ACCESS_KEY_ID=minioadmin AWS_SECRET_ACCESS_KEY=minioadmin \
+//!     REMOVEBG_TEST_BUCKET=s3://removebg-test \
+//!     cargo test --features object-store --test object_store -- --ignored
+//! ```
+//!
+//! Runs use the stub model in `tests/fixtures/models`.
+
+#![cfg(feature = "object-store")]
+
+use image::DynamicImage;
+use removebg::cloud::{process_prefix, remove_background_uri};
+use removebg::{testing::{self, TestModel}, RemoveBgError, RemoveBgOptions};
+use std::path::PathBuf;
+use std::process::Command;
+
+fn options() -> RemoveBgOptions {
+    TestModel::Brightness.options(RemoveBgOptions::default())
+}
+
+/// `s3://<bucket>/<test>-<pid>`, a prefix of its own for each test run.
+fn prefix(test: &str) -> String {
+    let bucket = std::env::var("REMOVEBG_TEST_BUCKET").expect("REMOVEBG_TEST_BUCKET names the bucket to use");
+    format!("{}/{}-{}", bucket.trim_end_matches('/'), test, std::process::id())
+}
+
+/// Upload a synthetic image to `uri` by processing a local file into it.
+fn upload_input(uri: &str, seed: u64) {
+    let path = std::env::temp_dir().join(format!("removebg-object-store-{}-{}.png", seed, std::process::id()));
+    DynamicImage::ImageRgb8(testing::synthetic_image(seed, 48, 32)).save(&path).unwrap();
+    let plain = RemoveBgOptions { overwrite: true, ..options() };
+    // A cutout is as good an input as any
+    remove_background_uri(path.to_str().unwrap(), Some(uri), &plain).unwrap();
+}
+
+#[test]
+#[ignore = "needs an S3-compatible server"]
+fn round_trips_through_the_bucket() {
+    let prefix = prefix("round-trip");
+    upload_input(&format!("{}/in/photo.png", prefix), 1);
+
+    let output = remove_background_uri(&format!("{}/in/photo.png", prefix), None, &options()).unwrap();
+    assert_eq!(output, format!("{}/in/photo_nobg.png", prefix));
+
+    // Downloaded to a local file, and decodable
+    let local = std::env::temp_dir().join(format!("removebg-object-store-{}.webp", std::process::id()));
+    let written = remove_background_uri(&output, Some(local.to_str().unwrap()), &options()).unwrap();
+    assert_eq!(written, local.to_string_lossy());
+    assert_eq!(image::open(&local).unwrap().color(), image::ColorType::Rgba8);
+
+    // The existing output is kept unless overwriting is requested
+    let error = remove_background_uri(&format!("{}/in/photo.png", prefix), None, &options()).unwrap_err();
+    assert!(matches!(error, RemoveBgError::OutputExists(_)), "{:?}", error);
+}
+
+#[test]
+#[ignore = "needs an S3-compatible server"]
+fn missing_keys_and_bad_credentials_are_told_apart() {
+    let prefix = prefix("errors");
+    let error = remove_background_uri(&format!("{}/missing.jpg", prefix), None, &options()).unwrap_err();
+    assert_eq!((error.kind(), error.exit_code()), ("file_not_found", 1), "{:?}", error);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(format!("{}/missing.jpg", prefix))
+        .env("AWS_SECRET_ACCESS_KEY", "wrong")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(6), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Access denied"));
+}
+
+#[test]
+#[ignore = "needs an S3-compatible server"]
+fn prefixes_are_processed_like_directories() {
+    let prefix = prefix("batch");
+    for (seed, key) in ["a.png", "b.png", "nested/c.png"].into_iter().enumerate() {
+        upload_input(&format!("{}/in/{}", prefix, key), seed as u64);
+    }
+    let summary = process_prefix(&format!("{}/in/", prefix), Some(&format!("{}/out", prefix)), true, &options()).unwrap();
+    assert_eq!((summary.processed, summary.failed), (3, 0));
+    let paths: Vec<_> = summary.directories.iter().map(|dir| dir.path.clone()).collect();
+    assert_eq!(paths, [PathBuf::new(), PathBuf::from("nested")]);
+
+    // Outputs mirror the input keys, and are valid images
+    let local = std::env::temp_dir().join(format!("removebg-object-store-c-{}.png", std::process::id()));
+    remove_background_uri(&format!("{}/out/nested/c_nobg.png", prefix), Some(local.to_str().unwrap()), &options())
+        .unwrap();
+
+    // Without an output prefix, the earlier cutouts next to the inputs are not picked up again
+    let summary = process_prefix(&format!("{}/in/", prefix), None, false, &options()).unwrap();
+    assert_eq!(summary.processed, 2);
+    let summary = process_prefix(&format!("{}/in/", prefix), None, false, &options()).unwrap();
+    assert_eq!((summary.processed, summary.failed), (0, 2));
+}
@@ -0,0 +1,40 @@
+//! The ONNX Runtime environment configured for strict sandboxes: telemetry
+//! off and a global thread pool without extra threads, so inference runs on
+//! the calling thread. Runs use the stub model in `tests/fixtures/models`.
+//!
+//! The environment is created once per process, so every check runs in the
+//! one test of this file, in order.
+
+#![cfg(feature = "ort")]
+
+use image::DynamicImage;
+use removebg::{testing::{self, TestModel}, Backend, BackgroundRemover, OrtAllocator, OrtEnvironmentConfig, RemoveBgOptions};
+
+fn options(ort_environment: OrtEnvironmentConfig) -> RemoveBgOptions {
+    TestModel::Brightness.options(RemoveBgOptions { backend: Backend::Ort, ort_environment, ..Default::default() })
+}
+
+#[test]
+fn single_threaded_environment_runs_inference() {
+    let single_threaded = OrtEnvironmentConfig {
+        telemetry: false,
+        global_threads: Some(0),
+        allocator: OrtAllocator::Shared,
+        ..Default::default()
+    };
+    let image = DynamicImage::ImageRgb8(testing::synthetic_image(5, 64, 48));
+    let remover = BackgroundRemover::new(options(single_threaded.clone())).expect("stub model loads");
+    let mask = remover.mask(&image).unwrap();
+    assert_eq!(mask.dimensions(), (64, 48));
+
+    // The environment now exists: passing it back is how a host shares its own
+    let environment = ort::environment::Environment::current().unwrap();
+    let hosted = OrtEnvironmentConfig { environment: Some(environment), ..Default::default() };
+    let remover = BackgroundRemover::new(options(hosted)).expect("the current environment is accepted");
+    assert_eq!(remover.mask(&image).unwrap(), mask);
+
+    // Other settings only warn and share the environment
+    let threaded = OrtEnvironmentConfig { global_threads: Some(2), ..single_threaded };
+    let remover = BackgroundRemover::new(options(threaded)).expect("a second config shares the environment");
+    assert_eq!(remover.mask(&image).unwrap(), mask);
+}
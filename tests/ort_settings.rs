@@ -0,0 +1,59 @@
+//! ONNX Runtime session settings (`--ort-threads`, `--ort-inter-threads`,
+//! `--ort-opt-level` and their `REMOVEBG_ORT_*` variables): sessions built
+//! with any of them give the same masks, and invalid values are refused.
+//! Runs use the stub model in `tests/fixtures/models`.
+#![cfg(feature = "ort")]
+
+use image::DynamicImage;
+use removebg::testing::{self, TestModel};
+use removebg::{Backend, OptimizationLevel, RemoveBgOptions};
+use std::process::Command;
+
+#[test]
+fn settings_do_not_change_the_mask() {
+    let image = DynamicImage::ImageRgb8(testing::synthetic_image(3, 64, 48));
+    let mask = |options: RemoveBgOptions| {
+        let remover = TestModel::Brightness.remover(RemoveBgOptions { backend: Backend::Ort, ..options }).expect("stub model loads");
+        testing::intermediates(&remover, &image).unwrap().mask_8bit()
+    };
+    let expected = mask(RemoveBgOptions::default());
+    let tuned = RemoveBgOptions {
+        intra_threads: Some(2),
+        inter_threads: Some(2),
+        optimization_level: OptimizationLevel::Disable,
+        ..Default::default()
+    };
+    testing::assert_mask_close(&mask(tuned), &expected, 0.002);
+}
+
+#[test]
+fn the_cli_takes_flags_and_environment() {
+    let dir = std::env::temp_dir().join(format!("removebg-ort-settings-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("photo.png");
+    testing::synthetic_image(4, 48, 32).save(&input).unwrap();
+    let removebg = |args: &[&str], env: &[(&str, &str)]| {
+        Command::new(env!("CARGO_BIN_EXE_removebg"))
+            .arg(&input)
+            .args(["--force", "--backend", "ort"])
+            .args(args)
+            .args(TestModel::Brightness.args())
+            .envs(env.iter().copied())
+            .output()
+            .expect("removebg runs")
+    };
+
+    for (args, env) in [(&["--ort-opt-level", "4"][..], &[][..]), (&[], &[("REMOVEBG_ORT_OPT_LEVEL", "fastest")])] {
+        let run = removebg(args, env);
+        assert_eq!(run.status.code(), Some(2));
+        assert!(String::from_utf8_lossy(&run.stderr).contains("invalid optimization level"), "{:?}", run);
+    }
+
+    let run = removebg(&["--ort-threads", "2", "--ort-inter-threads", "1", "--ort-opt-level", "basic"], &[]);
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+    assert!(dir.join("photo_nobg.png").is_file());
+    let run = removebg(&[], &[("REMOVEBG_ORT_THREADS", "1"), ("REMOVEBG_ORT_OPT_LEVEL", "0")]);
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
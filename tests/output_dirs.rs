@@ -0,0 +1,84 @@
+//! Output locations checked before any work: a missing directory is refused
+//! (or created with `create_dirs`) and an unwritable one fails, both before
+//! the model is loaded. Runs use the stub model in `tests/fixtures/models`.
+
+use removebg::testing::{self, TestModel};
+use removebg::{remove_background_with_options, RemoveBgError, RemoveBgOptions};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// A fresh directory holding one test image, named for `test`.
+fn input(test: &str) -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("removebg-output-dirs-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("photo.png");
+    testing::synthetic_image(4, 64, 48).save(&input).unwrap();
+    (dir, input)
+}
+
+/// Options whose model can't be loaded: an empty model directory with
+/// downloads turned off, so reaching the model fails with `ModelNotCached`.
+fn without_model(dir: &Path) -> RemoveBgOptions {
+    let mut options = RemoveBgOptions { model_dir: Some(dir.join("no-models")), ..Default::default() };
+    options.download.offline = true;
+    options
+}
+
+#[test]
+fn missing_directories_fail_before_the_model_loads() {
+    let (dir, input) = input("missing");
+    let output = dir.join("results/cutouts/photo.png");
+
+    let started = Instant::now();
+    match remove_background_with_options(&input, Some(&output), &without_model(&dir)) {
+        Err(RemoveBgError::OutputDirectoryMissing(missing)) => assert_eq!(missing, dir.join("results/cutouts")),
+        other => panic!("{:?}", other),
+    }
+    assert!(started.elapsed() < Duration::from_secs(1), "{:?}", started.elapsed());
+    assert!(!dir.join("results").exists());
+
+    // The same options reach the model once the directory is there
+    std::fs::create_dir_all(output.parent().unwrap()).unwrap();
+    match remove_background_with_options(&input, Some(&output), &without_model(&dir)) {
+        Err(RemoveBgError::ModelNotCached { .. }) => {}
+        other => panic!("{:?}", other),
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn create_dirs_makes_the_directory() {
+    let (dir, input) = input("create");
+    let output = dir.join("results/cutouts/photo.png");
+    let options = TestModel::Brightness.options(RemoveBgOptions { create_dirs: true, ..Default::default() });
+
+    assert_eq!(remove_background_with_options(&input, Some(&output), &options).unwrap(), output);
+    assert!(image::open(&output).unwrap().color().has_alpha());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn unwritable_directories_fail_before_the_model_loads() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let (dir, input) = input("read-only");
+    let read_only = dir.join("read-only");
+    std::fs::create_dir(&read_only).unwrap();
+    std::fs::set_permissions(&read_only, std::fs::Permissions::from_mode(0o555)).unwrap();
+    if std::fs::File::create(read_only.join("probe")).is_ok() {
+        // Running as root: permissions aren't enforced
+        std::fs::remove_dir_all(&dir).unwrap();
+        return;
+    }
+
+    let started = Instant::now();
+    match remove_background_with_options(&input, Some(&read_only.join("photo.png")), &without_model(&dir)) {
+        Err(RemoveBgError::IoError(e)) => assert_eq!(e.kind(), std::io::ErrorKind::PermissionDenied),
+        other => panic!("{:?}", other),
+    }
+    assert!(started.elapsed() < Duration::from_secs(1), "{:?}", started.elapsed());
+    std::fs::set_permissions(&read_only, std::fs::Permissions::from_mode(0o755)).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
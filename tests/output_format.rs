@@ -0,0 +1,174 @@
+//! Output format negotiation (`output::negotiate_format`): `--format`, then
+//! the `-o` extension, then the default, with mismatched extensions refused
+//! unless `--fix-extension` is given. CLI runs use the stub model in
+//! `tests/fixtures/models`.
+
+use removebg::output::negotiate_format;
+use removebg::testing::TestModel;
+use removebg::{Background, NamingOptions, OutputFormat, RemoveBgError, RemoveBgOptions};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+#[test]
+fn every_combination_negotiates_by_the_rules() {
+    use OutputFormat::{Jpeg, Png, WebP};
+    // --format, -o extension, the format chosen, and whether the extension is kept
+    let rows: [(Option<OutputFormat>, &str, OutputFormat, bool); 24] = [
+        (None, "png", Png, true),
+        (None, "jpg", Jpeg, true),
+        (None, "JPEG", Jpeg, true),
+        (None, "webp", WebP, true),
+        (None, "final", Png, false),
+        (None, "", Png, false),
+        (Some(Png), "png", Png, true),
+        (Some(Png), "jpg", Png, false),
+        (Some(Png), "JPEG", Png, false),
+        (Some(Png), "webp", Png, false),
+        (Some(Png), "final", Png, false),
+        (Some(Png), "", Png, false),
+        (Some(Jpeg), "png", Jpeg, false),
+        (Some(Jpeg), "jpg", Jpeg, true),
+        (Some(Jpeg), "JPEG", Jpeg, true),
+        (Some(Jpeg), "webp", Jpeg, false),
+        (Some(Jpeg), "final", Jpeg, false),
+        (Some(Jpeg), "", Jpeg, false),
+        (Some(WebP), "png", WebP, false),
+        (Some(WebP), "jpg", WebP, false),
+        (Some(WebP), "JPEG", WebP, false),
+        (Some(WebP), "webp", WebP, true),
+        (Some(WebP), "final", WebP, false),
+        (Some(WebP), "", WebP, false),
+    ];
+    for (format, extension, expected, kept) in rows {
+        let path = PathBuf::from("out").with_extension(extension);
+        for background in [Background::Transparent, Background::Color([255, 255, 255])] {
+            for fix_extension in [false, true] {
+                let options = RemoveBgOptions {
+                    format,
+                    background,
+                    naming: NamingOptions { fix_extension, ..Default::default() },
+                    ..Default::default()
+                };
+                let case = format!("{:?} {} {:?} fix={}", format, path.display(), background, fix_extension);
+                let result = negotiate_format(&path, OutputFormat::Png, &options);
+                let message = match result {
+                    Ok((negotiated, format)) => {
+                        assert!(kept || fix_extension, "{}: renamed to {}", case, negotiated.display());
+                        assert!(expected != Jpeg || background != Background::Transparent, "{}: transparent JPEG", case);
+                        let renamed = path.with_extension(expected.extension());
+                        assert_eq!((negotiated, format), (if kept { path.clone() } else { renamed }, expected), "{}", case);
+                        continue;
+                    }
+                    Err(RemoveBgError::InvalidOutputFormat(message)) => message,
+                    Err(e) => panic!("{}: {:?}", case, e),
+                };
+                if !kept && !fix_extension {
+                    assert!(message.contains("--fix-extension"), "{}: {}", case, message);
+                } else {
+                    assert_eq!((expected, background), (Jpeg, Background::Transparent), "{}: {}", case, message);
+                    assert!(message.contains("JPEG") && message.contains("add --bg-color"), "{}: {}", case, message);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn errors_say_how_the_format_was_chosen() {
+    let message = |path: &str, format: Option<OutputFormat>| {
+        let options = RemoveBgOptions { format, ..Default::default() };
+        negotiate_format(Path::new(path), OutputFormat::Png, &options).unwrap_err().to_string()
+    };
+    assert!(
+        message("out.jpg", None).contains(
+            "JPEG selected by the .jpg extension, but the output has transparency; add --bg-color or choose PNG/WebP/TIFF"
+        ),
+        "{}",
+        message("out.jpg", None)
+    );
+    assert!(message("out.jpg", Some(OutputFormat::Jpeg)).contains("JPEG selected by --format"));
+    let mismatch = message("out.png", Some(OutputFormat::WebP));
+    assert!(mismatch.contains("the .png extension of out.png doesn't match the WebP output selected by --format"), "{}", mismatch);
+    assert!(mismatch.contains("name it out.webp"), "{}", mismatch);
+    let unknown = message("out.final", None);
+    assert!(unknown.contains(".final is none of .png, .webp"), "{}", unknown);
+    assert!(message("out", None).contains("out has no extension"));
+}
+
+#[test]
+fn animated_inputs_keep_their_container() {
+    let options = RemoveBgOptions::default();
+    for (path, default, expected) in [
+        ("out.png", OutputFormat::Apng, OutputFormat::Apng),
+        ("out.apng", OutputFormat::Gif, OutputFormat::Apng),
+        ("out.gif", OutputFormat::Apng, OutputFormat::Gif),
+        ("out.webp", OutputFormat::Gif, OutputFormat::WebP),
+    ] {
+        assert_eq!(negotiate_format(Path::new(path), default, &options).unwrap(), (PathBuf::from(path), expected));
+    }
+}
+
+fn removebg(output: &Path, args: &[&str]) -> Output {
+    let fixtures = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(fixtures.join("golden/portrait.png"))
+        .arg("-o")
+        .arg(output)
+        .args(TestModel::Brightness.args())
+        .args(args)
+        .output()
+        .expect("removebg runs")
+}
+
+#[test]
+fn the_cli_follows_the_output_path() {
+    let dir = std::env::temp_dir().join(format!("removebg-output-format-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // The .jpg asked for, not a .png beside it
+    let output = removebg(&dir.join("out.jpg"), &["--bg-color", "white"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(image::open(dir.join("out.jpg")).unwrap().color(), image::ColorType::Rgb8);
+    assert!(!dir.join("out.png").exists());
+
+    // A mismatch is refused, and corrected on request
+    let output = removebg(&dir.join("cut.png"), &["--format", "webp"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--fix-extension"), "{:?}", output);
+    assert!(!dir.join("cut.png").exists() && !dir.join("cut.webp").exists());
+    let output = removebg(&dir.join("cut.png"), &["--format", "webp", "--fix-extension"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(std::fs::read(dir.join("cut.webp")).unwrap().starts_with(b"RIFF"));
+
+    // The rules are in --help
+    let help = Command::new(env!("CARGO_BIN_EXE_removebg")).arg("--help").output().unwrap();
+    let help = String::from_utf8_lossy(&help.stdout);
+    assert!(help.contains("OUTPUT FORMAT:\n  1. --format wins."), "{}", help);
+    assert!(help.contains(".jpg .jpeg"), "{}", help);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn webp_outputs_decode_with_alpha() {
+    let dir = std::env::temp_dir().join(format!("removebg-output-webp-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let alpha = |path: &Path| -> Vec<u8> { image::open(path).unwrap().to_rgba8().pixels().map(|pixel| pixel[3]).collect() };
+    let output = removebg(&dir.join("out.png"), &[]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let expected = alpha(&dir.join("out.png"));
+
+    // Lossy WebP compresses the color but keeps the alpha plane lossless
+    for (name, args) in [("lossless.webp", &[][..]), ("lossy.webp", &["--quality", "80"][..])] {
+        let path = dir.join(name);
+        let output = removebg(&path, args);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let reader = image::ImageReader::open(&path).unwrap().with_guessed_format().unwrap();
+        assert_eq!(reader.format(), Some(image::ImageFormat::WebP), "{}", name);
+        let cutout = reader.decode().unwrap();
+        assert!(cutout.color().has_alpha(), "{}: {:?}", name, cutout.color());
+        assert!(alpha(&path) == expected, "{}: alpha differs from the PNG cutout", name);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
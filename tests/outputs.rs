@@ -0,0 +1,138 @@
+//! Additional outputs (`RemoveBgOptions::outputs`, `--emit`) written from a
+//! single model run.
+
+use image::{DynamicImage, GenericImageView, ImageBuffer, Luma, Rgb, RgbImage};
+use removebg::batch::{process_directory, BatchConfig};
+use removebg::core::Gray16Image;
+use removebg::testing::TestModel;
+use removebg::{Background, BackgroundRemover, GeometryOptions, OutputKind, OutputSpec, RemoveBgOptions, Result, SegmentationModel};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Keeps the left half of the image with a soft edge, and counts its runs.
+struct HalfModel(Arc<AtomicUsize>);
+
+impl SegmentationModel for HalfModel {
+    fn predict_mask(&self, image: &DynamicImage) -> Result<Gray16Image> {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        let width = image.width();
+        Ok(ImageBuffer::from_fn(width, image.height(), |x, _| {
+            Luma([((65535 * width.saturating_sub(2 * x)).min(65535 * 8) / 8) as u16])
+        }))
+    }
+}
+
+/// A fresh directory under the system temp dir with a test image, named
+/// for `test`.
+fn input(test: &str) -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("removebg-outputs-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("photo.png");
+    RgbImage::from_fn(64, 48, |x, y| Rgb([x as u8 * 4, y as u8 * 5, 128])).save(&path).unwrap();
+    (dir, path)
+}
+
+fn remover(outputs: &[&str], options: RemoveBgOptions) -> (BackgroundRemover, Arc<AtomicUsize>) {
+    let runs = Arc::new(AtomicUsize::new(0));
+    let outputs: Vec<OutputSpec> = outputs.iter().map(|spec| spec.parse().unwrap()).collect();
+    let options = RemoveBgOptions { outputs, ..options };
+    (BackgroundRemover::with_model(HalfModel(runs.clone()), options), runs)
+}
+
+fn alpha(path: &Path) -> Vec<u8> {
+    image::open(path).unwrap().to_rgba8().pixels().map(|pixel| pixel[3]).collect()
+}
+
+#[test]
+fn one_model_run_writes_every_output() {
+    let (dir, photo) = input("single");
+    let (remover, runs) =
+        remover(&["mask", "flattened@85:{dir}/{stem}_white.jpg", "overlay"], RemoveBgOptions::default());
+    let report = remover.process_file(&photo, None).unwrap();
+
+    assert_eq!(runs.load(Ordering::SeqCst), 1);
+    let written: Vec<(OutputKind, PathBuf)> =
+        report.artifacts.iter().map(|artifact| (artifact.kind, artifact.path.clone())).collect();
+    assert_eq!(
+        written,
+        [
+            (OutputKind::Mask, dir.join("photo_mask.png")),
+            (OutputKind::Flattened, dir.join("photo_white.jpg")),
+            (OutputKind::Overlay, dir.join("photo_overlay.png")),
+        ]
+    );
+    assert!(report.artifacts.iter().all(|artifact| artifact.error.is_none()), "{:?}", report.artifacts);
+
+    let mask = image::open(dir.join("photo_mask.png")).unwrap();
+    assert!(matches!(mask, DynamicImage::ImageLuma8(_)), "{:?}", mask.color());
+    assert_eq!(mask.into_luma8().into_raw(), alpha(&report.output_path));
+    let flattened = image::open(dir.join("photo_white.jpg")).unwrap();
+    assert_eq!(flattened.dimensions(), (64, 48));
+    let background = flattened.get_pixel(60, 24);
+    assert!(background[0] > 245 && background[1] > 245 && background[2] > 245, "{:?}", background);
+    assert_eq!(image::image_dimensions(dir.join("photo_overlay.png")).unwrap(), (64, 48));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn outputs_stay_transparent_under_a_flattened_main_output() {
+    let (dir, photo) = input("geometry");
+    let options = RemoveBgOptions {
+        background: Background::Color([255, 0, 0]),
+        geometry: GeometryOptions { pad: Some(0.1), square: true, ..Default::default() },
+        ..Default::default()
+    };
+    let (remover, _) = remover(&["cutout:{dir}/cut/{stem}.png", "mask"], RemoveBgOptions { create_dirs: true, ..options });
+    let report = remover.process_file(&photo, Some(&dir.join("red.jpg"))).unwrap();
+
+    assert!(alpha(&report.output_path).iter().all(|&alpha| alpha == 255));
+    let cutout = dir.join("cut/photo.png");
+    assert_eq!(image::image_dimensions(&cutout).unwrap(), report.output_dimensions);
+    let cutout_alpha = alpha(&cutout);
+    assert!(cutout_alpha.contains(&0) && cutout_alpha.contains(&255));
+    assert_eq!(image::open(dir.join("photo_mask.png")).unwrap().into_luma8().into_raw(), cutout_alpha);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_failed_output_keeps_the_others() {
+    let (dir, photo) = input("partial");
+    // A directory where the mask should go can't be replaced by a file
+    std::fs::create_dir(dir.join("photo_mask.png")).unwrap();
+    let (remover, _) = remover(&["cutout", "mask", "overlay"], RemoveBgOptions { overwrite: true, ..Default::default() });
+    let report = remover.process_file(&photo, None).unwrap();
+
+    let errors: Vec<bool> = report.artifacts.iter().map(|artifact| artifact.error.is_some()).collect();
+    assert_eq!(errors, [false, true, false]);
+    assert!(report.output_path.is_file());
+    assert!(dir.join("photo_cutout.png").is_file());
+    assert!(dir.join("photo_overlay.png").is_file());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn directory_batches_write_outputs_for_every_image() {
+    let (dir, photo) = input("batch");
+    std::fs::copy(&photo, dir.join("second.png")).unwrap();
+    let out = dir.join("out");
+    std::fs::create_dir(&out).unwrap();
+    let options = TestModel::Brightness.options(RemoveBgOptions {
+        outputs: vec![OutputSpec::new(OutputKind::Mask), "flattened:{dir}/{stem}.jpg".parse().unwrap()],
+        ..Default::default()
+    });
+    let config = BatchConfig { output_dir: Some(out.clone()), options: options.clone(), ..BatchConfig::new(&dir) };
+    let summary = process_directory(config).unwrap();
+    assert_eq!((summary.processed, summary.failed), (2, 0));
+    for name in ["photo_nobg.png", "photo_mask.png", "photo.jpg", "second_nobg.png", "second_mask.png", "second.jpg"] {
+        assert!(out.join(name).is_file(), "{} missing", name);
+    }
+
+    // A template without {stem} would write both images to the same file
+    let options = RemoveBgOptions { outputs: vec!["mask:{dir}/mask.png".parse().unwrap()], overwrite: true, ..options };
+    let summary = process_directory(BatchConfig { output_dir: Some(out.clone()), options, ..BatchConfig::new(&dir) }).unwrap();
+    assert_eq!((summary.processed, summary.failed), (0, 2));
+    assert!(!out.join("mask.png").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
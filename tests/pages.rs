@@ -0,0 +1,102 @@
+//! Multi-page TIFF inputs: the first page by default with a notice about the
+//! others, one page with `--page N`, and every page into numbered outputs
+//! with `--all-pages`. `tests/fixtures/pages/scan.tiff` has a 32x24 first
+//! page and a 40x30 second one, so each output shows which page it came
+//! from. Runs use the stub model in `tests/fixtures/models`.
+
+use removebg::{remove_background_detailed, testing::{self, TestModel}, Pages, RemoveBgError, RemoveBgOptions};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn fixtures() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+/// A directory holding a copy of the two-page fixture, so outputs named
+/// after the input land in it.
+fn scan(name: &str) -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("removebg-pages-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("scan.tiff");
+    std::fs::copy(fixtures().join("pages/scan.tiff"), &input).unwrap();
+    (dir, input)
+}
+
+fn removebg(input: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(input)
+        .args(args)
+        .args(TestModel::Brightness.args())
+        .output()
+        .expect("removebg runs")
+}
+
+fn dimensions(path: &Path) -> (u32, u32) {
+    image::image_dimensions(path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+}
+
+#[test]
+fn first_page_is_processed_with_a_notice() {
+    let (dir, input) = scan("first");
+    let run = removebg(&input, &[]);
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+    let stderr = String::from_utf8_lossy(&run.stderr);
+    assert!(stderr.contains("has 2 pages; only page 1 is processed"), "{}", stderr);
+    assert_eq!(dimensions(&dir.join("scan_nobg.png")), (32, 24));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn page_selects_one_page() {
+    let (dir, input) = scan("page");
+    let run = removebg(&input, &["--page", "2"]);
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+    assert!(!String::from_utf8_lossy(&run.stderr).contains("only page 1"));
+    assert_eq!(dimensions(&dir.join("scan_p2_nobg.png")), (40, 30));
+    assert!(!dir.join("scan_nobg.png").exists());
+
+    let run = removebg(&input, &["--page", "3"]);
+    assert_eq!(run.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&run.stderr);
+    assert!(stderr.contains("Page 3 is out of range") && stderr.contains("has 2 page(s)"), "{}", stderr);
+    assert!(!dir.join("scan_p3_nobg.png").exists());
+
+    let run = removebg(&input, &["--page", "0"]);
+    assert_eq!(run.status.code(), Some(2));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn all_pages_are_written_to_numbered_outputs() {
+    let (dir, input) = scan("all");
+    let run = removebg(&input, &["--all-pages", "--json"]);
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+    assert_eq!(dimensions(&dir.join("scan_p1_nobg.png")), (32, 24));
+    assert_eq!(dimensions(&dir.join("scan_p2_nobg.png")), (40, 30));
+
+    let json: serde_json::Value = serde_json::from_slice(&run.stdout).expect("one JSON object");
+    assert_eq!(json["pages"]["count"], 2);
+    let processed = json["pages"]["processed"].as_array().expect("processed pages");
+    assert_eq!(processed.iter().map(|page| page["page"].as_u64().unwrap()).collect::<Vec<_>>(), [1, 2]);
+    assert!(processed[1]["output_path"].as_str().unwrap().ends_with("scan_p2_nobg.png"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn reports_list_pages_and_number_output_files() {
+    let (dir, input) = scan("report");
+    let options = testing::deterministic(TestModel::Brightness.options(RemoveBgOptions { pages: Pages::All, ..Default::default() }));
+    let report = remove_background_detailed(&input, Some(&dir.join("out.png")), &options).unwrap();
+    let pages = report.pages.expect("a page summary");
+    assert_eq!(pages.count, 2);
+    let outputs: Vec<_> = pages.processed.iter().map(|page| page.output_path.clone()).collect();
+    assert_eq!(outputs, [dir.join("out_p1.png"), dir.join("out_p2.png")]);
+    assert_eq!(report.output_path, outputs[0]);
+    assert_eq!(report.input_dimensions, (32, 24));
+    assert_eq!(dimensions(&outputs[1]), (40, 30));
+
+    let single = RemoveBgOptions { pages: Pages::Page(2), overwrite: true, ..options };
+    let error = remove_background_detailed(dir.join("out_p1.png"), None, &single).unwrap_err();
+    assert!(matches!(error.root(), RemoveBgError::PageOutOfRange { page: 2, count: 1, .. }), "{:?}", error);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,44 @@
+//! Thread pool defaults stay within the CPUs the process may use: what
+//! `std::thread::available_parallelism` reports, capped by a cgroup CPU
+//! quota in containers. The quota parsing itself is unit-tested against the
+//! cgroup files in `tests/fixtures/cgroup`.
+
+use removebg::cpu;
+use removebg::testing::TestModel;
+
+#[test]
+fn defaults_never_exceed_the_detected_parallelism() {
+    let detected = cpu::detect();
+    let cpus = cpu::available_parallelism();
+    assert_eq!(cpus, detected.cpus);
+    assert!(cpus >= 1);
+    assert!(cpus <= std::thread::available_parallelism().map_or(1, usize::from));
+    if let Some(quota) = detected.quota {
+        assert!(cpus as f64 >= quota.min(detected.available as f64), "{:?}", detected);
+        assert!(cpus as f64 <= quota.ceil().max(1.0), "{:?}", detected);
+    }
+
+    #[cfg(feature = "parallel")]
+    if std::env::var_os("RAYON_NUM_THREADS").is_none() {
+        cpu::init_thread_pool();
+        assert!(rayon::current_num_threads() <= cpus);
+    }
+    #[cfg(feature = "server")]
+    {
+        assert!(removebg::server::ServerConfig::default().workers <= cpus);
+        assert_eq!(removebg::server::default_workers(), cpus.min(4));
+    }
+}
+
+#[test]
+fn doctor_reports_the_cpus() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .args(["doctor", "--backend", "tract"])
+        .args(TestModel::Brightness.args())
+        .env("ORT_DYLIB_PATH", "")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let line = stdout.lines().find(|line| line.starts_with("PASS CPUs")).unwrap_or_else(|| panic!("{}", stdout));
+    assert!(line.contains(&cpu::available_parallelism().to_string()), "{}", line);
+}
@@ -0,0 +1,120 @@
+//! Path handling tests: file names that aren't valid UTF-8, paths beyond
+//! the legacy Windows length limit and output directories, run with the stub
+//! model in `tests/fixtures/models`.
+
+use removebg::testing::{self, TestModel};
+use removebg::{BackgroundRemover, RemoveBgOptions};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn fixtures() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+fn remover() -> BackgroundRemover {
+    let options = TestModel::Brightness.options(RemoveBgOptions::default());
+    BackgroundRemover::new(testing::deterministic(options)).expect("stub model loads")
+}
+
+/// A fresh directory under the system temp dir, named for `test`.
+fn temp_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("removebg-paths-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn copy_portrait(to: &Path) {
+    std::fs::copy(fixtures().join("golden/portrait.png"), to).expect("fixture is copied");
+}
+
+#[cfg(unix)]
+#[test]
+fn non_utf8_names_are_kept() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = temp_dir("non-utf8");
+    let input = dir.join(OsStr::from_bytes(b"ph\xffoto.png"));
+    copy_portrait(&input);
+
+    let report = remover().process_file(&input, None).unwrap();
+    assert_eq!(report.output_path, dir.join(OsStr::from_bytes(b"ph\xffoto_nobg.png")));
+    assert!(report.output_path.is_file());
+
+    // Explicit outputs and mask sidecars keep their bytes too
+    let options = RemoveBgOptions { save_mask: true, ..remover().options().clone() };
+    let output = dir.join(OsStr::from_bytes(b"cut\xfe.png"));
+    let report = removebg::remove_background_detailed(&input, Some(&output), &options).unwrap();
+    assert_eq!(report.output_path, output);
+    assert_eq!(report.mask_path, Some(dir.join(OsStr::from_bytes(b"ph\xffoto_mask.png"))));
+    assert!(output.is_file());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn missing_non_utf8_input_reports_its_path() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let input = Path::new(OsStr::from_bytes(b"/nonexistent/\xff.png"));
+    match remover().process_file(input, None) {
+        Err(removebg::RemoveBgError::FileNotFound(path)) => assert_eq!(path, input),
+        other => panic!("expected FileNotFound, got {:?}", other.map(|report| report.output_path)),
+    }
+}
+
+#[cfg(windows)]
+#[test]
+fn long_paths_work() {
+    let mut dir = temp_dir("long");
+    let root = dir.clone();
+    while dir.as_os_str().len() < 300 {
+        dir.push("a-directory-name-of-forty-characters-xxx");
+    }
+    std::fs::create_dir_all(format!(r"\\?\{}", dir.display())).unwrap();
+    let input = dir.join("photo.png");
+    copy_portrait(Path::new(&format!(r"\\?\{}", input.display())));
+
+    let report = remover().process_file(&input, None).unwrap();
+    assert_eq!(report.output_path, dir.join("photo_nobg.png"));
+    assert!(Path::new(&format!(r"\\?\{}", report.output_path.display())).is_file());
+    std::fs::remove_dir_all(format!(r"\\?\{}", root.display())).unwrap();
+}
+
+#[test]
+fn output_directories_get_the_usual_name() {
+    let dir = temp_dir("output-dir");
+    let input = dir.join("photo.png");
+    copy_portrait(&input);
+    let removebg = |output: &str, extra: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_removebg"))
+            .args(["photo.png", "-o", output])
+            .args(TestModel::Brightness.args())
+            .args(extra)
+            .current_dir(&dir)
+            .output()
+            .expect("removebg runs")
+    };
+
+    // A trailing separator names a directory, created on request
+    let run = removebg("processed/", &[]);
+    assert_eq!(run.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&run.stderr).contains("Output directory does not exist"));
+    let run = removebg("processed/", &["--create-dirs"]);
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+    assert!(dir.join("processed/photo_nobg.png").is_file());
+    assert!(!dir.join("processed.png").exists());
+
+    // An existing directory doesn't need the separator
+    let run = removebg("processed", &["--format", "webp"]);
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+    assert!(dir.join("processed/photo_nobg.webp").is_file());
+
+    // Anything else without an extension is ambiguous
+    let run = removebg("cutout", &[]);
+    assert_eq!(run.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&run.stderr).contains("cutout has no extension"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,129 @@
+//! Named option sets (`RemoveBgOptions::preset`, `--preset`): each one is
+//! usable as is, and flags given next to `--preset` win over its values. CLI
+//! runs use the stub model in `tests/fixtures/models`.
+
+use image::{DynamicImage, GenericImageView, ImageBuffer, Luma};
+use removebg::config::ENV_VARS;
+use removebg::core::Gray16Image;
+use removebg::{testing::{self, TestModel}, Background, BackgroundRemover, OutputFormat, Preset, RemoveBgOptions, Result, SegmentationModel};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Keeps a disc in the middle of the image, with a hole in its center.
+struct RingModel;
+
+impl SegmentationModel for RingModel {
+    fn predict_mask(&self, image: &DynamicImage) -> Result<Gray16Image> {
+        let (width, height) = image.dimensions();
+        let radius = width.min(height) as f32 * 0.3;
+        Ok(ImageBuffer::from_fn(width, height, |x, y| {
+            let distance = (x as f32 - width as f32 / 2.0).hypot(y as f32 - height as f32 / 2.0);
+            Luma([if distance > 3.0 && distance < radius { 65535 } else { 0 }])
+        }))
+    }
+}
+
+/// A fresh directory with one test image, named for `test`.
+fn input(test: &str) -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("removebg-presets-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("photo.png");
+    testing::synthetic_image(1, 96, 64).save(&input).unwrap();
+    (dir, input)
+}
+
+/// Run the CLI on `input` with the stub model, `--json` and the given flags.
+fn run(input: &Path, flags: &[&str]) -> (Output, serde_json::Value) {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_removebg"));
+    for var in ENV_VARS {
+        command.env_remove(var.name);
+    }
+    let output = command
+        .arg(input)
+        .args(["--json", "--force"])
+        .args(TestModel::Brightness.args())
+        .args(flags)
+        .output()
+        .unwrap();
+    let json = serde_json::from_slice(&output.stdout).unwrap_or(serde_json::Value::Null);
+    (output, json)
+}
+
+#[test]
+fn every_preset_builds_usable_options() {
+    let image = DynamicImage::ImageRgb8(testing::synthetic_image(3, 80, 60));
+    let mut png = std::io::Cursor::new(Vec::new());
+    image.write_to(&mut png, image::ImageFormat::Png).unwrap();
+    for &preset in Preset::ALL {
+        assert_eq!(preset.name().parse::<Preset>(), Ok(preset));
+        assert!(!preset.description().is_empty());
+        let options = RemoveBgOptions::preset(preset);
+        assert_ne!(format!("{:?}", options), format!("{:?}", RemoveBgOptions::default()), "{} sets nothing", preset);
+
+        let remover = BackgroundRemover::with_model(RingModel, options.clone());
+        let cutout = remover.process_image(&image).unwrap_or_else(|e| panic!("{}: {}", preset, e));
+        let encoded = remover.process_bytes(png.get_ref()).unwrap();
+        match preset {
+            // Filled hole, white background, cropped to the disc plus 5%
+            Preset::Product => {
+                assert!(cutout.width() < 60 && cutout.pixels().all(|pixel| pixel[3] == 255));
+            }
+            Preset::Sticker => assert!(cutout.width() > 80, "the outline and shadow grow the canvas"),
+            Preset::Web => assert_eq!(image::guess_format(&encoded).unwrap(), image::ImageFormat::WebP),
+            Preset::Portrait => assert!(options.guided_filter.is_some() && options.decontaminate.is_some()),
+        }
+    }
+    assert!("passport".parse::<Preset>().unwrap_err().contains("product, portrait, sticker, web"));
+
+    // Fields can be changed after the preset fills them in
+    let options = RemoveBgOptions { format: Some(OutputFormat::Png), ..RemoveBgOptions::preset(Preset::Web) };
+    assert_eq!((options.format, options.max_output_size), (Some(OutputFormat::Png), Some(2000)));
+}
+
+#[test]
+fn flags_override_the_preset() {
+    let (dir, input) = input("override");
+    let name = |json: &serde_json::Value| Path::new(json["output"].as_str().unwrap()).file_name().unwrap().to_owned();
+
+    let (output, json) = run(&input, &["--preset", "web"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(name(&json), "photo_nobg.webp");
+    assert_eq!(name(&run(&input, &["--preset", "web", "--format", "png"]).1), "photo_nobg.png");
+    // So does the extension of an output file
+    let cutout = dir.join("cutout.png");
+    assert_eq!(name(&run(&input, &["--preset", "web", "-o", cutout.to_str().unwrap()]).1), "cutout.png");
+    let listing = run(&input, &["--preset", "web", "--show-config"]).0.stdout;
+    assert!(String::from_utf8_lossy(&listing).lines().any(|line| line == "format = \"webp\"  # --preset web"));
+
+    let (_, json) = run(&input, &["--preset", "product"]);
+    assert_eq!(json["background_color"], "#ffffff");
+    assert_eq!(run(&input, &["--preset", "product", "--bg-color", "black"]).1["background_color"], "#000000");
+    // A flag that conflicts with the preset's background replaces it
+    let (output, json) = run(&input, &["--preset", "product", "--blur-background", "4"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(json["background_color"], serde_json::Value::Null);
+
+    // Settings of a mode the preset turns on don't need the mode's own flag
+    let (output, _) = run(&input, &["--preset", "sticker", "--outline-width", "4"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let (output, _) = run(&input, &["--outline-width", "4"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--outline-width requires --sticker"), "{:?}", output);
+
+    assert_eq!(run(&input, &["--preset", "passport"]).0.status.code(), Some(2));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn presets_subcommand_lists_each_preset() {
+    let output = Command::new(env!("CARGO_BIN_EXE_removebg")).arg("presets").output().unwrap();
+    assert!(output.status.success());
+    let listing = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(listing.lines().count(), Preset::ALL.len());
+    for &preset in Preset::ALL {
+        let line = listing.lines().find(|line| line.starts_with(preset.name())).unwrap_or_else(|| panic!("{} is missing", preset));
+        assert!(line.ends_with(preset.description()), "{}", line);
+    }
+    assert_eq!(RemoveBgOptions::preset(Preset::Product).background, Background::Color([255, 255, 255]));
+}
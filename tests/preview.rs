@@ -0,0 +1,143 @@
+//! Preview helpers for frontends (`preview::composite_on_checkerboard`,
+//! `preview::make_thumbnail`), the thumbnails written by `--thumbnail`, and
+//! the debug images written by `--debug-output`.
+//! CLI runs use the stub model in `tests/fixtures/models`.
+
+use image::{GenericImageView, Rgb, Rgba, RgbaImage};
+use removebg::preview::{composite_on_checkerboard, make_thumbnail, CHECKER_DARK, CHECKER_LIGHT};
+use removebg::{testing::{self, TestModel}, BackgroundRemover, OutputKind, OutputSpec, RemoveBgOptions};
+use std::path::PathBuf;
+use std::process::Command;
+
+/// A fresh directory with one test image, named for `test`.
+fn input(test: &str) -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("removebg-preview-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("photo.png");
+    testing::synthetic_image(4, 200, 120).save(&input).unwrap();
+    (dir, input)
+}
+
+/// An opaque red disc in the middle of a transparent `width`×`height` image.
+fn disc(width: u32, height: u32) -> RgbaImage {
+    let radius = width.min(height) as f32 * 0.4;
+    RgbaImage::from_fn(width, height, |x, y| {
+        let distance = (x as f32 + 0.5 - width as f32 / 2.0).hypot(y as f32 + 0.5 - height as f32 / 2.0);
+        let alpha = ((radius - distance) * 255.0).clamp(0.0, 255.0) as u8;
+        Rgba([200, 30, 30, alpha])
+    })
+}
+
+#[test]
+fn checkerboard_pixels_are_exact_and_anchored_to_the_image() {
+    let (light, dark) = (Rgba([250, 240, 230, 255]), Rgba([10, 20, 30, 255]));
+    let mut image = RgbaImage::new(4, 3);
+    image.put_pixel(1, 0, Rgba([100, 100, 100, 255]));
+    image.put_pixel(2, 2, Rgba([0, 0, 0, 51]));
+    let preview = composite_on_checkerboard(&image, 2, light, dark);
+    let expected = [
+        [[250, 240, 230], [100, 100, 100], [10, 20, 30], [10, 20, 30]],
+        [[250, 240, 230], [250, 240, 230], [10, 20, 30], [10, 20, 30]],
+        [[10, 20, 30], [10, 20, 30], [200, 192, 184], [250, 240, 230]],
+    ];
+    for (y, row) in expected.iter().enumerate() {
+        for (x, &pixel) in row.iter().enumerate() {
+            assert_eq!(preview.get_pixel(x as u32, y as u32), &Rgb(pixel), "at {},{}", x, y);
+        }
+    }
+    // The checker alpha is ignored, and a cell of 0 is treated as 1
+    let translucent = composite_on_checkerboard(&image, 0, Rgba([250, 240, 230, 0]), dark);
+    assert_eq!(translucent.get_pixel(0, 0), &Rgb([250, 240, 230]));
+    assert_eq!(translucent.get_pixel(1, 1), &Rgb([250, 240, 230]));
+
+    // A crop at a multiple of two cells has the same squares as the whole
+    let cutout = disc(96, 64);
+    let whole = composite_on_checkerboard(&cutout, 8, CHECKER_LIGHT, CHECKER_DARK);
+    let crop = image::imageops::crop_imm(&cutout, 32, 16, 40, 40).to_image();
+    let cropped = composite_on_checkerboard(&crop, 8, CHECKER_LIGHT, CHECKER_DARK);
+    assert_eq!(cropped, image::imageops::crop_imm(&whole, 32, 16, 40, 40).to_image());
+}
+
+#[test]
+fn thumbnails_keep_the_aspect_ratio_and_alpha() {
+    let cutout = disc(300, 120);
+    let thumbnail = make_thumbnail(&cutout, 100);
+    assert_eq!(thumbnail.dimensions(), (100, 40));
+    assert_eq!(make_thumbnail(&disc(90, 270), 100).dimensions(), (33, 100));
+    assert_eq!(make_thumbnail(&disc(1000, 1), 10).dimensions(), (10, 1));
+    // Images that already fit aren't enlarged
+    assert_eq!(make_thumbnail(&cutout, 300), cutout);
+
+    assert_eq!(thumbnail.get_pixel(0, 0)[3], 0);
+    assert_eq!(thumbnail.get_pixel(50, 20)[3], 255);
+    // Transparent black doesn't bleed into the edge's color
+    let edge: Vec<_> = thumbnail.pixels().filter(|pixel| (16..240).contains(&pixel[3])).collect();
+    assert!(!edge.is_empty());
+    assert!(edge.iter().all(|pixel| pixel[0] > 180 && pixel[1] < 50), "{:?}", edge);
+}
+
+#[test]
+fn thumbnail_outputs_are_written_next_to_the_main_output() {
+    let (dir, input) = input("outputs");
+    let output = Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(&input)
+        .args(TestModel::Brightness.args())
+        .args(["--thumbnail", "50", "-q"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let main = image::open(dir.join("photo_nobg.png")).unwrap();
+    assert_eq!(main.dimensions(), (200, 120));
+    let thumbnail = image::open(dir.join("photo_thumb.png")).unwrap();
+    assert_eq!(thumbnail.dimensions(), (50, 30));
+    assert!(thumbnail.color().has_alpha());
+    assert_eq!(thumbnail.to_rgba8(), make_thumbnail(&main.to_rgba8(), 50));
+
+    // In the library, as an output of its own
+    let options = TestModel::Brightness.options(RemoveBgOptions {
+        outputs: vec![OutputSpec::new(OutputKind::Thumbnail)],
+        thumbnail_size: Some(64),
+        overwrite: true,
+        ..Default::default()
+    });
+    let report = BackgroundRemover::new(options.clone()).unwrap().process_file(&input, None).unwrap();
+    assert_eq!(report.artifacts[0].path, dir.join("photo_thumbnail.png"));
+    assert_eq!(image::open(&report.artifacts[0].path).unwrap().dimensions(), (64, 38));
+
+    // A thumbnail needs transparency
+    let jpeg = RemoveBgOptions { outputs: vec!["thumbnail:{dir}/{stem}.jpg".parse().unwrap()], ..options };
+    let error = BackgroundRemover::new(jpeg).unwrap().process_file(&input, None).unwrap_err();
+    assert!(error.to_string().contains("thumbnail output needs a format with transparency"), "{}", error);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn debug_images_leave_the_cutout_alone() {
+    let (dir, input) = input("debug");
+    let debug = dir.join("debug");
+    let run = |output: &str, args: &[&std::ffi::OsStr]| {
+        let result = Command::new(env!("CARGO_BIN_EXE_removebg"))
+            .arg(&input)
+            .args(TestModel::CenterBlob.args())
+            .args(["-q", "-o"])
+            .arg(dir.join(output))
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+        std::fs::read(dir.join(output)).unwrap()
+    };
+    let plain = run("plain.png", &[]);
+    let debugged = run("debugged.png", &["--debug-output".as_ref(), debug.as_os_str()]);
+    assert_eq!(debugged, plain);
+
+    for (kind, size) in [("mask", (200, 120)), ("overlay", (200, 120))] {
+        let image = image::open(debug.join(format!("photo.{}.png", kind))).unwrap();
+        assert_eq!(image.dimensions(), size, "{}", kind);
+    }
+    // Original, mask and cutout side by side
+    let compare = image::open(debug.join("photo.compare.png")).unwrap();
+    assert!(compare.width() > 3 * 200 && compare.height() == 120, "{:?}", compare.dimensions());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,124 @@
+//! Before/after preview GIFs (`preview::before_after_gif`, `--preview-gif`):
+//! two looping frames on one shared palette, sized by `--preview-size`, and
+//! named after each input in directory batches without changing the main
+//! outputs. CLI runs use the stub model in `tests/fixtures/models`.
+
+use image::{DynamicImage, Rgba, RgbaImage};
+use removebg::preview::before_after_gif;
+use removebg::testing::{self, TestModel};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+/// A fresh directory with test images named `names`, for `test`.
+fn inputs(test: &str, names: &[&str]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("removebg-preview-gif-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    for (seed, name) in names.iter().enumerate() {
+        testing::synthetic_image(seed as u64 + 5, 200, 120).save(dir.join(name)).unwrap();
+    }
+    dir
+}
+
+/// A decoded GIF: its size, whether it loops forever, and each frame's
+/// delay in hundredths of a second and whether it has its own palette.
+struct Gif {
+    size: (u16, u16),
+    loops: bool,
+    frames: Vec<(u16, bool)>,
+}
+
+fn decode(path: &Path) -> Gif {
+    let mut decoder = gif::DecodeOptions::new().read_info(std::fs::File::open(path).unwrap()).unwrap();
+    let mut frames = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().unwrap() {
+        frames.push((frame.delay, frame.palette.is_some()));
+    }
+    Gif { size: (decoder.width(), decoder.height()), loops: decoder.repeat() == gif::Repeat::Infinite, frames }
+}
+
+fn removebg(args: &[&str], input: &Path) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(input)
+        .args(TestModel::Brightness.args())
+        .args(args)
+        .output()
+        .expect("removebg runs")
+}
+
+#[test]
+fn two_looping_frames_on_one_palette() {
+    let dir = inputs("library", &[]);
+    let original = DynamicImage::ImageRgb8(testing::synthetic_image(1, 300, 180)).into_rgba8();
+    let cutout = RgbaImage::from_fn(300, 180, |x, _| Rgba([200, 30, 30, if x < 150 { 255 } else { 0 }]));
+    let path = dir.join("preview.gif");
+    before_after_gif(&original, &cutout, 100, Duration::from_millis(500), std::fs::File::create(&path).unwrap()).unwrap();
+    let gif = decode(&path);
+    assert_eq!(gif.size, (100, 60));
+    assert!(gif.loops);
+    assert_eq!(gif.frames, [(50, false), (50, false)]);
+
+    // An original of another shape is fitted into the cutout's frame
+    let mut written = Vec::new();
+    let narrow = DynamicImage::ImageRgb8(testing::synthetic_image(2, 40, 120)).into_rgba8();
+    before_after_gif(&narrow, &cutout, 600, Duration::ZERO, &mut written).unwrap();
+    std::fs::write(&path, written).unwrap();
+    let gif = decode(&path);
+    assert_eq!(gif.size, (300, 180));
+    assert_eq!(gif.frames.len(), 2);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cli_writes_the_preview_beside_the_cutout() {
+    let dir = inputs("cli", &["photo.png"]);
+    let photo = dir.join("photo.png");
+    let output = removebg(&["-q", "-o", dir.join("plain.png").to_str().unwrap()], &photo);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let preview = dir.join("before-after.gif");
+    let args = ["-q", "--preview-gif", preview.to_str().unwrap(), "--preview-size", "100", "--preview-delay", "250ms"];
+    let output = removebg(&args, &photo);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let gif = decode(&preview);
+    assert_eq!(gif.size, (100, 60));
+    assert!(gif.loops);
+    assert_eq!(gif.frames, [(25, false), (25, false)]);
+    // The main output is the same as without a preview
+    assert_eq!(std::fs::read(dir.join("photo_nobg.png")).unwrap(), std::fs::read(dir.join("plain.png")).unwrap());
+
+    // Defaults: 480 px at most, so this input keeps its size, and one second a frame
+    let output = removebg(&["-q", "--force", "--preview-gif", preview.to_str().unwrap()], &photo);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let gif = decode(&preview);
+    assert_eq!((gif.size, gif.frames), ((200, 120), vec![(100, false), (100, false)]));
+
+    // A preview is a GIF
+    let output = removebg(&["-q", "--force", "--preview-gif", dir.join("preview.png").to_str().unwrap()], &photo);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("preview output is an animated GIF, not PNG"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn batches_name_each_preview_after_its_input() {
+    let dir = inputs("batch", &["a.png", "b.png"]);
+    let out = dir.join("out");
+    let output = Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(&dir)
+        .args(TestModel::Brightness.args())
+        .arg("--output-dir")
+        .arg(&out)
+        .args(["-q", "--preview-gif", "preview.gif", "--preview-size", "50"])
+        .output()
+        .expect("removebg runs");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    for stem in ["a", "b"] {
+        assert!(out.join(format!("{}_nobg.png", stem)).exists());
+        let gif = decode(&out.join(format!("{}_preview.gif", stem)));
+        assert_eq!((gif.size, gif.frames.len(), gif.loops), ((50, 30), 2, true));
+    }
+    assert!(!dir.join("preview.gif").exists() && !out.join("preview.gif").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,83 @@
+//! Progress events of runs over many images, and the CLI's failure summary,
+//! run with the stub model in `tests/fixtures/models`.
+
+use removebg::batch::{process_directory, BatchConfig, SkipExisting};
+use removebg::progress::{Progress, ProgressEvent};
+use removebg::{testing::{self, TestModel}, RemoveBgOptions};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+
+/// A fresh directory under the system temp dir with two test images, one
+/// that isn't an image, and an existing output for the second, named for
+/// `test`.
+fn inputs(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("removebg-progress-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    for (seed, name) in [(1, "a.png"), (2, "b.png")] {
+        testing::synthetic_image(seed, 80, 60).save(dir.join(name)).unwrap();
+    }
+    std::fs::write(dir.join("c.png"), b"not a png").unwrap();
+    std::fs::copy(dir.join("b.png"), dir.join("b_nobg.png")).unwrap();
+    dir
+}
+
+fn name(path: &Path) -> String {
+    path.file_name().unwrap().to_string_lossy().into_owned()
+}
+
+#[test]
+fn batches_report_every_input() {
+    let dir = inputs("batch");
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&events);
+    let config = BatchConfig {
+        skip_existing: SkipExisting::Always,
+        options: TestModel::Brightness.options(RemoveBgOptions::default()),
+        progress: Progress::new(move |event| {
+            seen.lock().unwrap().push(match event {
+                ProgressEvent::Queued { count } => format!("queued {}", count),
+                ProgressEvent::Started { input } => format!("started {}", name(input)),
+                ProgressEvent::Processed { input, report } => format!("processed {} {}", name(input), name(&report.output_path)),
+                ProgressEvent::Skipped { input } => format!("skipped {}", name(input)),
+                ProgressEvent::Failed { input, error } => format!("failed {} {}", name(input), error.kind()),
+            });
+        }),
+        ..BatchConfig::new(&dir)
+    };
+    let summary = process_directory(config).unwrap();
+    assert_eq!((summary.processed, summary.skipped, summary.failed), (1, 1, 1));
+    assert_eq!(
+        *events.lock().unwrap(),
+        [
+            "queued 3",
+            "started a.png",
+            "processed a.png a_nobg.png",
+            "skipped b.png",
+            "started c.png",
+            "failed c.png unsupported_format",
+        ]
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cli_lists_failures_at_the_end_without_a_bar_when_piped() {
+    let dir = inputs("cli");
+    let run = Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(&dir)
+        .args(["--skip-existing"])
+        .args(TestModel::Brightness.args())
+        .output()
+        .expect("removebg runs");
+    // The unsupported input's code
+    assert_eq!(run.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&run.stderr);
+    let summary = stderr.split("Failed (1):\n").nth(1).unwrap_or_else(|| panic!("no failure summary in {}", stderr));
+    assert!(summary.starts_with("  ") && summary.lines().next().unwrap().contains("c.png"), "{}", stderr);
+    // No bar, and no escape codes, in a pipe
+    assert!(!stderr.contains('\r') && !stderr.contains('\u{1b}'), "{:?}", stderr);
+    assert!(stderr.contains(&format!("{} -> ", dir.join("a.png").display())), "{}", stderr);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,77 @@
+//! Raw frame input (`frame::image_from_raw`, `BackgroundRemover::process_raw`):
+//! padded BGRA, BGR, RGB and RGBA buffers come out with their channels in
+//! RGB order and the row padding dropped, and layouts that don't fit the
+//! buffer are errors instead of panics.
+
+use image::{DynamicImage, Rgba, RgbaImage};
+use removebg::frame::image_from_raw;
+use removebg::{testing::{self, TestModel}, PixelFormat, RemoveBgError, RemoveBgOptions};
+
+/// `image` as rows of `format` pixels padded to `stride` bytes with 0xEE.
+fn frame(image: &RgbaImage, format: PixelFormat, stride: usize) -> Vec<u8> {
+    let mut data = Vec::new();
+    for row in image.rows() {
+        let start = data.len();
+        for &Rgba([r, g, b, a]) in row {
+            match format {
+                PixelFormat::Rgb8 => data.extend_from_slice(&[r, g, b]),
+                PixelFormat::Rgba8 => data.extend_from_slice(&[r, g, b, a]),
+                PixelFormat::Bgr8 => data.extend_from_slice(&[b, g, r]),
+                PixelFormat::Bgra8 => data.extend_from_slice(&[b, g, r, a]),
+            }
+        }
+        data.resize(start + stride, 0xEE);
+    }
+    data
+}
+
+fn photo() -> RgbaImage {
+    DynamicImage::ImageRgb8(testing::synthetic_image(4, 96, 64)).into_rgba8()
+}
+
+#[test]
+fn padded_frames_are_unswizzled() {
+    let photo = photo();
+    for (format, stride) in [(PixelFormat::Bgra8, 96 * 4 + 64), (PixelFormat::Bgr8, 96 * 3 + 5), (PixelFormat::Rgba8, 96 * 4), (PixelFormat::Rgb8, 96 * 3 + 1)] {
+        let data = frame(&photo, format, stride);
+        let image = image_from_raw(&data, 96, 64, stride, format).unwrap();
+        assert_eq!(image.color().has_alpha(), format.has_alpha(), "{}", format);
+        assert_eq!(image.into_rgba8(), photo, "{}", format);
+
+        // The last row needs no padding
+        let trimmed = &data[..data.len() - (stride - 96 * format.bytes_per_pixel())];
+        assert_eq!(image_from_raw(trimmed, 96, 64, stride, format).unwrap().into_rgba8(), photo, "{}", format);
+    }
+
+    // Alpha comes through the swizzle too
+    let translucent = RgbaImage::from_fn(3, 2, |x, y| Rgba([10 * x as u8, 20, 200 - y as u8, 128 + x as u8]));
+    let data = frame(&translucent, PixelFormat::Bgra8, 16);
+    assert_eq!(image_from_raw(&data, 3, 2, 16, PixelFormat::Bgra8).unwrap().into_rgba8(), translucent);
+}
+
+#[test]
+fn layouts_that_do_not_fit_are_errors() {
+    let data = vec![0u8; 64];
+    for (width, height, stride) in [(0, 4, 16), (4, 0, 16), (4, 4, 15), (u32::MAX, 2, 16)] {
+        let error = image_from_raw(&data, width, height, stride, PixelFormat::Bgra8).unwrap_err();
+        assert!(matches!(error, RemoveBgError::InvalidRawLayout { .. }), "{:?}", error);
+        assert_eq!((error.kind(), error.exit_code()), ("invalid_raw_layout", 2));
+    }
+    let error = image_from_raw(&data, 4, 4, 20, PixelFormat::Bgra8).unwrap_err();
+    let RemoveBgError::RawBufferTooSmall { needed, len } = error else { panic!("{:?}", error) };
+    assert_eq!((needed, len), (76, 64));
+    assert!(image_from_raw(&data, 4, 4, usize::MAX, PixelFormat::Rgb8).is_err());
+    assert_eq!(error.to_string(), "Raw image buffer is too small: 64 bytes, but the layout needs 76");
+}
+
+#[test]
+fn remover_processes_raw_frames_like_images() {
+    let remover = TestModel::Brightness.remover(RemoveBgOptions::default()).unwrap();
+    let photo = photo();
+    let expected = remover.process_image(&DynamicImage::ImageRgba8(photo.clone())).unwrap();
+    let data = frame(&photo, PixelFormat::Bgra8, 96 * 4 + 32);
+    assert_eq!(remover.process_raw(&data, 96, 64, 96 * 4 + 32, PixelFormat::Bgra8).unwrap(), expected);
+
+    let error = remover.process_raw(&data[..100], 96, 64, 96 * 4 + 32, PixelFormat::Bgra8).unwrap_err();
+    assert!(matches!(error, RemoveBgError::RawBufferTooSmall { .. }), "{:?}", error);
+}
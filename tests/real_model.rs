@@ -0,0 +1,76 @@
+//! The same runs as `tests/stub_model.rs` with the real u2net model, which
+//! is downloaded into the model cache on the first run (176 MB), so they are
+//! ignored by default:
+//!
+//! `cargo test --test real_model -- --ignored`
+//!
+//! The quantized model is made from u2net with `onnxruntime`'s Python
+//! package, which that test needs too.
+
+use image::{DynamicImage, Rgb, RgbImage, RgbaImage};
+use removebg::model::quantize_model;
+use removebg::{remove_background_from_bytes, testing, BackgroundRemover, Model, RemoveBgOptions};
+use std::io::Cursor;
+use std::process::Command;
+
+/// A red disc in the middle of a gray background.
+fn disc(width: u32, height: u32) -> RgbImage {
+    let (cx, cy, r) = (width as f32 / 2.0, height as f32 / 2.0, width.min(height) as f32 / 3.0);
+    RgbImage::from_fn(width, height, |x, y| {
+        let (dx, dy) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+        if dx * dx + dy * dy <= r * r {
+            Rgb([200, 30, 30])
+        } else {
+            Rgb([128, 128, 128])
+        }
+    })
+}
+
+#[track_caller]
+fn assert_disc_kept(cutout: &RgbaImage) {
+    let (width, height) = cutout.dimensions();
+    let (center, corner) = (cutout.get_pixel(width / 2, height / 2)[3], cutout.get_pixel(0, 0)[3]);
+    assert!(center > 128 && corner < 128, "center alpha {}, corner alpha {}", center, corner);
+}
+
+#[test]
+#[ignore = "downloads the 176 MB u2net model"]
+fn the_bytes_api_keeps_the_subject() {
+    let mut png = Cursor::new(Vec::new());
+    DynamicImage::ImageRgb8(disc(320, 240)).write_to(&mut png, image::ImageFormat::Png).unwrap();
+    let options = testing::deterministic(RemoveBgOptions::default());
+    let cutout = remove_background_from_bytes(png.get_ref(), &options).expect("u2net runs");
+    assert_disc_kept(&image::load_from_memory(&cutout).unwrap().into_rgba8());
+}
+
+#[test]
+#[ignore = "downloads the 176 MB u2net model"]
+fn the_cli_keeps_the_subject() {
+    let dir = std::env::temp_dir().join(format!("removebg-real-model-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let (input, output) = (dir.join("disc.png"), dir.join("cutout.png"));
+    disc(320, 240).save(&input).unwrap();
+
+    let run = Command::new(env!("CARGO_BIN_EXE_removebg")).arg(&input).arg("-o").arg(&output).output().expect("removebg runs");
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+    assert_disc_kept(&image::open(&output).unwrap().into_rgba8());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+#[ignore = "downloads the 176 MB u2net model and needs Python with onnxruntime"]
+fn the_quantized_model_stays_close_to_u2net() {
+    if !removebg::model::model_path(Model::U2netQuant, None).unwrap().exists() {
+        quantize_model(Model::U2netQuant, None).expect("u2net quantizes");
+    }
+    let image = DynamicImage::ImageRgb8(disc(320, 240));
+    let mask = |model| {
+        let remover = BackgroundRemover::new(testing::deterministic(RemoveBgOptions { model, ..Default::default() }))
+            .expect("model loads");
+        testing::intermediates(&remover, &image).unwrap().mask_8bit()
+    };
+    let (full, quantized) = (mask(Model::U2net), mask(Model::U2netQuant));
+    let diff = testing::mask_diff(&quantized, &full);
+    assert!(diff.mean_abs_diff < 0.02, "mean alpha difference {:.4}", diff.mean_abs_diff);
+}
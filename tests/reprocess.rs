@@ -0,0 +1,81 @@
+//! Inputs that look like earlier cutouts: transparent parts are flattened
+//! onto gray rather than black before inference, and the CLI warns about
+//! them, stays quiet with `--allow-reprocess`, or refuses them with
+//! `--strict`. Runs use the stub model in `tests/fixtures/models`, which
+//! averages the input channels.
+
+use image::{DynamicImage, Rgb, RgbImage, Rgba, RgbaImage};
+use removebg::{testing::{self, TestModel}, BackgroundRemover, RemoveBgOptions};
+use std::path::Path;
+use std::process::{Command, Output};
+
+fn removebg(args: &[&str], input: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(input)
+        .args(args)
+        .args(TestModel::Brightness.args())
+        .output()
+        .expect("removebg runs")
+}
+
+/// A cutout: the synthetic image with its left half transparent.
+fn cutout(width: u32, height: u32) -> RgbaImage {
+    let image = testing::synthetic_image(5, width, height);
+    RgbaImage::from_fn(width, height, |x, y| {
+        let [r, g, b] = image.get_pixel(x, y).0;
+        Rgba([r, g, b, if x < width / 2 { 0 } else { 255 }])
+    })
+}
+
+/// `image` composited over `gray`, without alpha.
+fn flattened(image: &RgbaImage, gray: u8) -> RgbImage {
+    RgbImage::from_fn(image.width(), image.height(), |x, y| {
+        let [r, g, b, a] = image.get_pixel(x, y).0;
+        let over = |c: u8| ((c as u32 * a as u32 + gray as u32 * (255 - a as u32)) / 255) as u8;
+        Rgb([over(r), over(g), over(b)])
+    })
+}
+
+#[test]
+fn transparent_inputs_are_flattened_onto_gray_not_black() {
+    let options = TestModel::Brightness.options(RemoveBgOptions::default());
+    let remover = BackgroundRemover::new(testing::deterministic(options)).expect("stub model loads");
+    let cutout = cutout(320, 320);
+    let raw_mask = |image: DynamicImage| testing::intermediates(&remover, &image).unwrap().model_output;
+
+    let mask = raw_mask(DynamicImage::ImageRgba8(cutout.clone()));
+    let over_gray = raw_mask(DynamicImage::ImageRgb8(flattened(&cutout, 128)));
+    let over_black = raw_mask(DynamicImage::ImageRgb8(flattened(&cutout, 0)));
+    let mean_diff = |other: &ndarray::ArrayD<f32>| (&mask - other).mapv(f32::abs).mean().unwrap();
+    assert!(mean_diff(&over_gray) < 0.01, "{}", mean_diff(&over_gray));
+    assert!(mean_diff(&over_black) > 0.2, "{}", mean_diff(&over_black));
+}
+
+#[test]
+fn cli_warns_about_refuses_or_allows_earlier_cutouts() {
+    let dir = std::env::temp_dir().join(format!("removebg-reprocess-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let transparent = dir.join("a.png");
+    cutout(80, 60).save(&transparent).unwrap();
+    let named = dir.join("b_nobg.png");
+    testing::synthetic_image(1, 80, 60).save(&named).unwrap();
+    let output = dir.join("out.png");
+    let out = ["-o", output.to_str().unwrap(), "--force"];
+
+    for (input, reason) in [(&transparent, "already has transparency"), (&named, "is named like an earlier output")] {
+        let run = removebg(&out, input);
+        assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+        assert!(String::from_utf8_lossy(&run.stderr).contains(reason), "{}", String::from_utf8_lossy(&run.stderr));
+
+        let run = removebg(&[&out[..], &["--allow-reprocess"]].concat(), input);
+        assert!(run.status.success());
+        assert!(!String::from_utf8_lossy(&run.stderr).contains(reason));
+    }
+
+    std::fs::remove_file(&output).unwrap();
+    let run = removebg(&[&out[..], &["--strict"]].concat(), &transparent);
+    assert_eq!(run.status.code(), Some(7));
+    assert!(String::from_utf8_lossy(&run.stderr).contains("looks already processed"));
+    assert!(!output.exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
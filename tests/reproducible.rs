@@ -0,0 +1,92 @@
+//! `--reproducible`: the same fixture processed in separate processes, with
+//! different `--jobs`, gives outputs with the same SHA-256, and
+//! `SOURCE_DATE_EPOCH` dates them. Runs use the stub model in
+//! `tests/fixtures/models`.
+
+use sha2::{Digest, Sha256};
+use removebg::testing::TestModel;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::time::{Duration, UNIX_EPOCH};
+
+fn fixtures() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+/// A fresh directory named for `test`.
+fn workspace(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("removebg-reproducible-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn removebg(output: &Path, args: &[&str], env: &[(&str, &str)]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(fixtures().join("golden/portrait.png"))
+        .arg("-o")
+        .arg(output)
+        .args(TestModel::Brightness.args())
+        .args(args)
+        .envs(env.iter().copied())
+        .env_remove("RAYON_NUM_THREADS")
+        .output()
+        .expect("removebg runs")
+}
+
+fn sha256(path: &Path) -> String {
+    Sha256::digest(std::fs::read(path).unwrap()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[test]
+fn outputs_are_identical_across_runs_and_jobs() {
+    let dir = workspace("jobs");
+    let cases = [("png", &[][..]), ("png", &["--png-quantize"]), ("webp", &[]), ("jpg", &["--bg-color", "white"])];
+    for (case, (extension, extra)) in cases.into_iter().enumerate() {
+        let hashes: Vec<_> = [&["--jobs", "1"][..], &[], &["--jobs", "4"]]
+            .iter()
+            .enumerate()
+            .map(|(run, jobs)| {
+                let output = dir.join(format!("case{}-run{}.{}", case, run, extension));
+                let args: Vec<&str> = ["--reproducible"].iter().chain(*jobs).chain(extra).copied().collect();
+                let result = removebg(&output, &args, &[]);
+                assert!(result.status.success(), "{:?}: {}", args, String::from_utf8_lossy(&result.stderr));
+                sha256(&output)
+            })
+            .collect();
+        assert!(hashes.iter().all(|hash| *hash == hashes[0]), "{} {:?}: {:?}", extension, extra, hashes);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn source_date_epoch_dates_the_outputs() {
+    let dir = workspace("epoch");
+    let output = dir.join("cutout.png");
+    let result = removebg(&output, &["--save-mask"], &[("SOURCE_DATE_EPOCH", "1700000000")]);
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    let expected = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    assert_eq!(std::fs::metadata(&output).unwrap().modified().unwrap(), expected);
+    // The mask is named after the input, next to the output
+    assert_eq!(std::fs::metadata(dir.join("portrait_mask.png")).unwrap().modified().unwrap(), expected);
+
+    // Anything but whole seconds is ignored with a warning
+    let result = removebg(&output, &["--force"], &[("SOURCE_DATE_EPOCH", "yesterday")]);
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    assert!(String::from_utf8_lossy(&result.stderr).contains("Ignoring SOURCE_DATE_EPOCH"));
+    assert_ne!(std::fs::metadata(&output).unwrap().modified().unwrap(), expected);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn settings_that_vary_between_runs_are_refused() {
+    let dir = workspace("refused");
+    let output = dir.join("cutout.png");
+    for args in [&["--reproducible", "--ort-threads", "4"][..], &["--reproducible", "--batch-size", "2"], &["--reproducible", "--device", "coreml"]] {
+        let result = removebg(&output, args, &[]);
+        assert_eq!(result.status.code(), Some(2), "{:?}", args);
+        assert!(String::from_utf8_lossy(&result.stderr).contains("--reproducible"), "{:?}", result);
+        assert!(!output.exists());
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,59 @@
+//! Region of interest (`RemoveBgOptions::roi`): only the subject inside the
+//! rectangle, widened by `roi_margin`, is kept; rectangles past the image
+//! edge are clamped and empty ones are rejected. Runs use the stub model in
+//! `tests/fixtures/models`, whose mask is the brightness of the image.
+
+use image::{DynamicImage, GrayImage, Luma, RgbaImage};
+use removebg::testing::TestModel;
+use removebg::{Rect, RemoveBgOptions};
+
+/// Light squares on the left and right of a dark 64x32 image.
+fn two_subjects() -> DynamicImage {
+    DynamicImage::ImageLuma8(GrayImage::from_fn(64, 32, |x, y| {
+        let subject = (8..24).contains(&y) && ((4..20).contains(&x) || (44..60).contains(&x));
+        Luma([if subject { 230 } else { 20 }])
+    }))
+}
+
+fn cutout(roi: Option<Rect>, roi_margin: f32) -> removebg::Result<RgbaImage> {
+    let options = RemoveBgOptions { roi, roi_margin, ..Default::default() };
+    TestModel::Brightness.remover(options).expect("stub model loads").process_image(&two_subjects())
+}
+
+fn rect(x: u32, y: u32, width: u32, height: u32) -> Option<Rect> {
+    Some(Rect { x, y, width, height })
+}
+
+#[test]
+fn only_the_subject_inside_is_kept() {
+    let whole = cutout(None, 0.0).unwrap();
+    assert!(whole.get_pixel(12, 16)[3] > 0 && whole.get_pixel(52, 16)[3] > 0);
+
+    let left = cutout(rect(0, 0, 32, 32), 0.0).unwrap();
+    assert_eq!(left.dimensions(), (64, 32));
+    assert!(left.get_pixel(12, 16)[3] > 0);
+    assert!(left.enumerate_pixels().filter(|&(x, _, _)| x >= 32).all(|(_, _, pixel)| pixel[3] == 0));
+
+    // A 50% margin widens the segmented area to x < 48, reaching into the
+    // right square
+    let margin = cutout(rect(0, 0, 32, 32), 0.5).unwrap();
+    assert!(margin.get_pixel(46, 16)[3] > 0);
+    assert_eq!(margin.get_pixel(52, 16)[3], 0);
+}
+
+#[test]
+fn rectangles_past_the_edge_are_clamped() {
+    let clamped = cutout(rect(32, 0, 100, 100), 0.0).unwrap();
+    assert!(clamped.get_pixel(52, 16)[3] > 0);
+    assert_eq!(clamped.get_pixel(12, 16)[3], 0);
+}
+
+#[test]
+fn empty_rectangles_are_rejected() {
+    let error = cutout(rect(0, 0, 0, 32), 0.0).unwrap_err();
+    assert!(error.to_string().contains("zero area"), "{}", error);
+    let error = cutout(rect(64, 0, 10, 10), 0.0).unwrap_err();
+    assert!(error.to_string().contains("outside"), "{}", error);
+    let error = cutout(rect(0, 0, 10, 10), -1.0).unwrap_err();
+    assert!(error.to_string().contains("margin"), "{}", error);
+}
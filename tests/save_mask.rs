@@ -0,0 +1,60 @@
+//! `--save-mask` and `--use-mask` round trip: a mask saved next to a cutout
+//! and reapplied later, with the same options, gives the cutout of a single
+//! run byte for byte. Runs use the center blob stub model in
+//! `tests/fixtures/models`, whose masks have soft edges for the options to
+//! work on.
+
+use removebg::testing::{self, TestModel};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A fresh directory named for `test`.
+fn workspace(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("removebg-save-mask-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Run removebg on `input` with `args`, writing to `output`.
+fn removebg(input: &Path, output: &Path, args: &[&str]) {
+    let result = Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(input)
+        .arg("-o")
+        .arg(output)
+        .args(args)
+        .args(TestModel::CenterBlob.args())
+        .output()
+        .expect("removebg runs");
+    assert!(result.status.success(), "{:?}: {}", args, String::from_utf8_lossy(&result.stderr));
+}
+
+#[test]
+fn reapplied_masks_match_a_single_run() {
+    let dir = workspace("round-trip");
+    let input = dir.join("photo.png");
+    testing::synthetic_image(5, 160, 120).save(&input).unwrap();
+
+    for (name, options) in [
+        ("transparent", &[][..]),
+        ("feathered", &["--mask-ops", "feather=2.5,threshold=0.4"][..]),
+        ("white", &["--bg-color", "white", "--mask-gamma", "0.8"][..]),
+    ] {
+        let single = dir.join(format!("{}-single.png", name));
+        removebg(&input, &single, options);
+
+        let saved = dir.join(format!("{}-saved.png", name));
+        removebg(&input, &saved, &[&["--save-mask"], options].concat());
+        let mask = dir.join("photo_mask.png");
+        assert!(mask.exists(), "{}", name);
+
+        let reapplied = dir.join(format!("{}-reapplied.png", name));
+        removebg(&input, &reapplied, &[&["--use-mask", mask.to_str().unwrap()], options].concat());
+
+        let single = std::fs::read(&single).unwrap();
+        assert_eq!(std::fs::read(&saved).unwrap(), single, "{}: --save-mask changed the cutout", name);
+        assert_eq!(std::fs::read(&reapplied).unwrap(), single, "{}: the reapplied mask gave a different cutout", name);
+        std::fs::remove_file(&mask).unwrap();
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
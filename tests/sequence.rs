@@ -0,0 +1,89 @@
+//! Image sequences (`sequence::process_sequence`): smoothed masks flicker
+//! less than per-frame masks and still follow the subject, and a scene cut
+//! starts the smoothing over. Runs use the stub model in
+//! `tests/fixtures/models`, whose mask is the brightness of the frame.
+
+use image::{GrayImage, Luma};
+use removebg::sequence::{process_sequence, SequenceConfig, SequenceSummary};
+use removebg::testing::TestModel;
+use removebg::RemoveBgOptions;
+use std::path::{Path, PathBuf};
+
+/// A fresh directory named for `test`.
+fn workspace(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("removebg-sequence-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("frames")).unwrap();
+    dir
+}
+
+fn run(dir: &Path, pattern: &str, output: &str, smoothing: (f32, usize)) -> SequenceSummary {
+    let config = SequenceConfig {
+        temporal_alpha: smoothing.0,
+        temporal_window: smoothing.1,
+        options: TestModel::Brightness.options(RemoveBgOptions::default()),
+        ..SequenceConfig::new(vec![dir.join("frames").join(pattern)], dir.join(output))
+    };
+    process_sequence(config).expect("sequence is processed")
+}
+
+/// The alpha channels of the cutouts of frames 1 to `count` in `output`.
+fn alphas(dir: &Path, output: &str, count: usize) -> Vec<Vec<f32>> {
+    (1..=count)
+        .map(|index| {
+            let cutout = image::open(dir.join(output).join(format!("frame_{}_nobg.png", index))).unwrap().into_rgba8();
+            cutout.pixels().map(|pixel| pixel[3] as f32).collect()
+        })
+        .collect()
+}
+
+/// Mean squared alpha change between consecutive frames.
+fn flicker(masks: &[Vec<f32>]) -> f32 {
+    let changes: Vec<f32> = masks
+        .windows(2)
+        .map(|pair| pair[0].iter().zip(&pair[1]).map(|(a, b)| (a - b).powi(2)).sum::<f32>() / pair[0].len() as f32)
+        .collect();
+    changes.iter().sum::<f32>() / changes.len() as f32
+}
+
+/// Alpha-weighted mean column of a 72 pixel wide mask.
+fn center_x(mask: &[f32]) -> f32 {
+    let total: f32 = mask.iter().sum();
+    mask.iter().enumerate().map(|(i, alpha)| alpha * (i % 72) as f32).sum::<f32>() / total
+}
+
+#[test]
+fn smoothed_masks_flicker_less_and_follow_the_subject() {
+    let dir = workspace("smoothing");
+    // A light square moving 4 px right per frame; the frame numbers aren't
+    // padded, so name order differs from frame order
+    for index in 0..10 {
+        let left = 4 + 4 * index;
+        let frame = GrayImage::from_fn(72, 48, |x, y| Luma([if (left..left + 16).contains(&x) && (16..32).contains(&y) { 230 } else { 20 }]));
+        frame.save(dir.join("frames").join(format!("frame_{}.png", index + 1))).unwrap();
+    }
+
+    let summary = run(&dir, "frame_*.png", "raw", (1.0, 1));
+    assert_eq!((summary.frames, summary.scene_cuts), (10, 0));
+    let summary = run(&dir, "frame_*.png", "smoothed", (0.5, 4));
+    assert_eq!((summary.frames, summary.scene_cuts), (10, 0));
+
+    let (raw, smoothed) = (alphas(&dir, "raw", 10), alphas(&dir, "smoothed", 10));
+    assert!(flicker(&smoothed) < flicker(&raw), "{} >= {}", flicker(&smoothed), flicker(&raw));
+    let centers: Vec<f32> = smoothed.iter().map(|mask| center_x(mask)).collect();
+    assert!(centers.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", centers);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn scene_cuts_are_counted() {
+    let dir = workspace("cut");
+    for index in 0..4 {
+        let shade = if index < 2 { 30 } else { 220 };
+        let frame = GrayImage::from_fn(16, 16, |x, _| Luma([if x < 8 { shade } else { 120 }]));
+        frame.save(dir.join("frames").join(format!("shot_{:03}.png", index))).unwrap();
+    }
+    let summary = run(&dir, "shot_*.png", "out", (0.5, 4));
+    assert_eq!((summary.frames, summary.scene_cuts), (4, 1));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,290 @@
+//! Serde support (`serde` feature): options, reports and errors round-trip
+//! through JSON, documents stored with version 1 of the schema in
+//! `tests/fixtures/serde/v1` still deserialize to the same values, and the
+//! CLI's `--json` output uses the same representations.
+#![cfg(feature = "serde")]
+
+use removebg::{
+    AlphaStats, Artifact, AutoModel, Background, Backend, Collision, Device, Downgrade, ErrorInfo, FileSizeFit, Fusion, InferenceInfo, InferenceSize, Instance, MaskFastPath, MaskFilter, MaskOp, MemoryEstimate, Model, ModelInfo, ModelSelection,
+    OptimizationLevel, OrtAllocator, OrtEnvironmentConfig, OutputFormat, OutputKind, OutputSpec, PageOutput, PageSummary, Pages, PngCompression, PngFilter, QualityFallback, Rect, RemovalReport, RemoveBgError, RemoveBgOptions,
+    Reprocess, Stage, StageDurations, TileOptions,
+};
+use removebg::progress::DownloadEvent;
+use removebg::testing::TestModel;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+fn fixture(name: &str) -> String {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/serde/v1").join(name);
+    std::fs::read_to_string(path).unwrap()
+}
+
+#[track_caller]
+fn round_trip<T: Serialize + DeserializeOwned + PartialEq + Debug>(value: &T) {
+    let json = serde_json::to_string(value).unwrap();
+    let back: T = serde_json::from_str(&json).unwrap_or_else(|e| panic!("{} does not read back: {}", json, e));
+    assert_eq!(&back, value, "{}", json);
+}
+
+fn report() -> RemovalReport {
+    let millis = Duration::from_millis;
+    RemovalReport {
+        output_path: "out/a_nobg.png".into(),
+        mask_path: Some("out/a_nobg.mask.png".into()),
+        artifacts: vec![Artifact { kind: OutputKind::Mask, path: "out/a_mask.png".into(), error: Some("disk full".into()) }],
+        instances: vec![Instance { bounds: Rect { x: 1, y: 2, width: 30, height: 40 }, area: 900, path: "out/a_1.png".into() }],
+        pages: Some(PageSummary { count: 3, processed: vec![PageOutput { page: 2, output_path: "out/a_p2_nobg.png".into() }] }),
+        input_dimensions: (640, 480),
+        output_dimensions: (320, 240),
+        subject_bounds: Some(Rect { x: 10, y: 20, width: 100, height: 200 }),
+        mask_coverage: 0.375,
+        confidence: 0.5,
+        alpha_stats: Some(AlphaStats {
+            histogram: std::array::from_fn(|bucket| bucket as u64 * 10),
+            ambiguous: 0.125,
+            edge_length: 640,
+            edge_softness: 1.5,
+            components: 2,
+            specks: 3,
+            min_component_area: 308,
+        }),
+        file_size: Some(FileSizeFit {
+            size: 1234,
+            quality: Some(80.0),
+            png_compression: Some(PngCompression::Best),
+            quantized: true,
+            downscaled: false,
+            dimensions: (320, 240),
+            attempts: 3,
+        }),
+        background_color: Some([1, 2, 3]),
+        premultiplied: true,
+        durations: StageDurations { inference: millis(250), total: Duration::from_micros(1500), ..Default::default() },
+        model: Some(ModelInfo {
+            model: Model::U2netHumanSeg,
+            device: Device::Cpu,
+            path: "models/u2net_human_seg.onnx".into(),
+            size_bytes: 42,
+            downloaded: true,
+            selection: Some(ModelSelection { tried: vec![Model::U2netp, Model::U2netHumanSeg], reason: "u2netp mask looks like a person".into() }),
+            inference: Some(InferenceInfo { size: 640, relative_cost: 4.0, detail: Some(512.5) }),
+            downgrades: vec![Downgrade::Refinement, Downgrade::InferenceSize],
+        }),
+        mask_cached: true,
+        mask_fast_path: Some(MaskFastPath::Full),
+        memory_estimate: Some(MemoryEstimate {
+            input_bytes: 921_600,
+            mask_bytes: 1_843_200,
+            crop_bytes: 0,
+            output_bytes: 1_228_800,
+            encoder_bytes: 1_228_800,
+            total_bytes: 5_222_400,
+            banded: false,
+        }),
+    }
+}
+
+#[test]
+fn every_type_round_trips() {
+    for model in Model::ALL {
+        round_trip(model);
+    }
+    for device in [Device::Cpu, Device::CoreMl, Device::DirectMl] {
+        round_trip(&device);
+    }
+    for backend in [Backend::Ort, Backend::Tract] {
+        round_trip(&backend);
+    }
+    for level in [OptimizationLevel::Disable, OptimizationLevel::Basic, OptimizationLevel::Extended, OptimizationLevel::All] {
+        round_trip(&level);
+    }
+    for fusion in [Fusion::First, Fusion::Mean(3), Fusion::Weighted(vec![0.6, 0.3, 0.1])] {
+        round_trip(&fusion);
+    }
+    for filter in [MaskFilter::Nearest, MaskFilter::Triangle, MaskFilter::CatmullRom, MaskFilter::Lanczos3] {
+        round_trip(&filter);
+    }
+    for op in [MaskOp::Levels(0.1, 0.9), MaskOp::Feather(2.5), MaskOp::FillHoles(None), MaskOp::KeepLargest { count: 2, gap: 10 }] {
+        round_trip(&op);
+    }
+    for background in [Background::Transparent, Background::Auto, Background::Color([255, 128, 0])] {
+        round_trip(&background);
+    }
+    for kind in [OutputKind::Cutout, OutputKind::Mask, OutputKind::Flattened, OutputKind::Overlay, OutputKind::Thumbnail, OutputKind::Preview] {
+        round_trip(&kind);
+    }
+    for collision in [Collision::Error, Collision::Overwrite, Collision::Rename] {
+        round_trip(&collision);
+    }
+    for pages in [Pages::First, Pages::Page(2), Pages::All] {
+        round_trip(&pages);
+    }
+    for size in [InferenceSize::Native, InferenceSize::Fixed(640), InferenceSize::Auto] {
+        round_trip(&size);
+    }
+    for reprocess in [Reprocess::Warn, Reprocess::Allow, Reprocess::Refuse] {
+        round_trip(&reprocess);
+    }
+    for format in ["png", "webp", "tiff", "bmp", "jpeg", "gif", "apng", "ico"] {
+        round_trip(&format.parse::<OutputFormat>().unwrap());
+    }
+    for compression in [PngCompression::Fast, PngCompression::Default, PngCompression::Best] {
+        round_trip(&compression);
+    }
+    for filter in [PngFilter::None, PngFilter::Sub, PngFilter::Up, PngFilter::Avg, PngFilter::Paeth, PngFilter::Adaptive] {
+        round_trip(&filter);
+    }
+    for stage in [Stage::Download, Stage::Decode, Stage::Segmentation, Stage::Postprocess, Stage::Composite, Stage::Encode] {
+        round_trip(&stage);
+        assert_eq!(serde_json::to_value(stage).unwrap(), stage.name());
+    }
+    for fast_path in [MaskFastPath::Empty, MaskFastPath::Full] {
+        round_trip(&fast_path);
+        assert_eq!(serde_json::to_value(fast_path).unwrap(), fast_path.name());
+    }
+    for downgrade in [Downgrade::Model, Downgrade::Refinement, Downgrade::InferenceSize] {
+        round_trip(&downgrade);
+        assert_eq!(serde_json::to_value(downgrade).unwrap(), downgrade.name());
+    }
+    round_trip(&report());
+
+    let error = RemoveBgError::FileNotFound("a.png".into());
+    round_trip(&ErrorInfo::from(&error));
+    let info = ErrorInfo::from(&RemoveBgError::Cancelled);
+    assert_eq!((info.stage, info.input.as_deref()), (None, None));
+    round_trip(&info);
+
+    // Options have no PartialEq (the cancellation token isn't comparable),
+    // so they are compared as JSON
+    let options = RemoveBgOptions {
+        model: Model::U2netQuant,
+        auto_model: Some(AutoModel { max_models: 3 }),
+        quality_fallback: Some(QualityFallback { deadline: Duration::from_millis(800) }),
+        background: Background::Color([9, 8, 7]),
+        tiling: Some(TileOptions { size: 256, overlap: 32 }),
+        mask_fusion: Fusion::Mean(2),
+        mask_ops: vec![MaskOp::Dilate(3), MaskOp::Threshold(0.5)],
+        outputs: vec![OutputSpec::new(OutputKind::Overlay), "flattened@85:{dir}/{stem}_white.jpg".parse().unwrap()],
+        ort_environment: OrtEnvironmentConfig { global_threads: Some(0), allocator: OrtAllocator::Shared, ..Default::default() },
+        ..Default::default()
+    };
+    let json = serde_json::to_value(&options).unwrap();
+    assert!(json.get("cancel").is_none());
+    assert!(json.get("download_progress").is_none());
+    assert_eq!(json["ort_environment"]["allocator"], "shared");
+    assert_eq!(json["mask_ops"], serde_json::json!(["dilate=3", "threshold=0.5"]));
+    assert_eq!(json["quality_fallback"], serde_json::json!({"deadline_ms": 800.0}));
+    let back: RemoveBgOptions = serde_json::from_value(json.clone()).unwrap();
+    assert_eq!(serde_json::to_value(&back).unwrap(), json);
+}
+
+#[test]
+fn download_events_follow_the_documented_schema() {
+    let event = DownloadEvent { model: Model::U2net, received: 12345, total: Some(175_997_641) };
+    assert_eq!(
+        serde_json::to_string(&event).unwrap(),
+        r#"{"event":"download","model":"u2net","received":12345,"total":175997641}"#
+    );
+    round_trip(&event);
+    round_trip(&DownloadEvent { total: None, ..event });
+    let unknown: DownloadEvent = serde_json::from_str(r#"{"event":"download","model":"u2netp","received":0,"total":null}"#).unwrap();
+    assert_eq!(unknown, DownloadEvent { model: Model::U2netp, received: 0, total: None });
+}
+
+#[test]
+fn version_1_documents_still_deserialize() {
+    let options: RemoveBgOptions = serde_json::from_str(&fixture("options.json")).unwrap();
+    let defaults = RemoveBgOptions::default();
+    assert_eq!((options.model, options.auto_model), (Model::U2netp, None));
+    assert_eq!(options.model_dir.as_deref(), Some(Path::new("/var/lib/removebg/models")));
+    assert_eq!(options.download.url.as_deref(), Some("https://mirror.example.com/u2netp.onnx"));
+    assert_eq!(options.download.retry.attempts, 5);
+    assert_eq!(options.download.retry.initial_delay, Duration::from_millis(250));
+    assert_eq!(options.download.retry.max_delay, Duration::from_secs(8));
+    assert_eq!((options.backend, options.intra_threads), (Backend::Tract, Some(4)));
+    assert_eq!(options.optimization_level, OptimizationLevel::Extended);
+    let cache = options.mask_cache.as_ref().unwrap();
+    assert_eq!(cache.max_size, Some(removebg::mask_cache::DEFAULT_MAX_SIZE));
+    assert_eq!((options.format, options.quality), (Some(OutputFormat::WebP), Some(85.0)));
+    assert_eq!(options.background, Background::Color([255, 128, 0]));
+    assert_eq!(options.tiling, Some(TileOptions { size: 512, overlap: TileOptions::default().overlap }));
+    assert_eq!(options.guided_filter.map(|guided| guided.radius), Some(8));
+    assert!(options.fill_holes);
+    let sticker = options.sticker.unwrap();
+    assert_eq!((sticker.outline_width, sticker.shadow_offset), (6, (2, 3)));
+    assert_eq!(sticker.shadow_blur, removebg::StickerOptions::default().shadow_blur);
+    assert_eq!((options.geometry.pad, options.geometry.resize), (Some(0.1), Some((1024, 1024))));
+    assert_eq!(options.chroma_key, Some([0, 255, 0]));
+    assert_eq!(options.mask_fusion, Fusion::Weighted(vec![0.5, 0.25, 0.25]));
+    assert_eq!((options.mask_levels, options.mask_filter), (Some((0.1, 0.9)), MaskFilter::Lanczos3));
+    assert_eq!(options.confidence.min_score, Some(0.4));
+    assert_eq!(options.confidence.high, defaults.confidence.high);
+    assert_eq!(options.outputs.len(), 2);
+    assert_eq!(options.outputs[0], OutputSpec::new(OutputKind::Mask));
+    assert_eq!(options.outputs[1].format, Some(OutputFormat::Jpeg));
+    assert_eq!((options.png.compression, options.png.filter, options.png.quantize), (PngCompression::Best, PngFilter::Paeth, true));
+    let limit = options.max_file_size.unwrap();
+    assert_eq!((limit.max_bytes, limit.allow_downscale), (500_000, false));
+    assert_eq!((options.naming.prefix.as_str(), options.naming.suffix.as_deref()), ("cut_", Some("-nobg")));
+    assert_eq!(options.naming.on_collision, Collision::Rename);
+    assert!(options.overwrite);
+    // Fields the document leaves out keep their defaults
+    assert_eq!(options.limits, defaults.limits);
+    assert_eq!(options.trimap, defaults.trimap);
+    assert_eq!(options.icon, defaults.icon);
+    assert_eq!(options.session_memory, defaults.session_memory);
+    assert!(!options.cancel.is_cancelled());
+
+    let report: RemovalReport = serde_json::from_str(&fixture("report.json")).unwrap();
+    assert_eq!(report.output_path, Path::new("out/photo_nobg.png"));
+    assert_eq!(report.artifacts[1].error.as_deref(), Some("disk full"));
+    assert_eq!(report.instances[0].bounds, Rect { x: 10, y: 20, width: 300, height: 200 });
+    assert_eq!((report.input_dimensions, report.output_dimensions), ((1920, 1080), (1024, 1024)));
+    assert_eq!(report.file_size.unwrap().quality, Some(72.5));
+    assert_eq!(report.durations.preprocess, Duration::from_micros(4250));
+    assert_eq!(report.durations.total, Duration::from_millis(530));
+    assert_eq!(report.model.as_ref().unwrap().model, Model::U2netp);
+    assert_eq!(report.mask_fast_path, None);
+    assert_eq!(report.memory_estimate, None);
+    assert_eq!(report.model.as_ref().unwrap().selection, None);
+    assert!(report.model.as_ref().unwrap().downgrades.is_empty());
+    assert_eq!(report.pages, None);
+
+    let error: ErrorInfo = serde_json::from_str(&fixture("error.json")).unwrap();
+    assert_eq!((error.kind.as_str(), error.stage), ("unsupported_format", Some(Stage::Decode)));
+    assert_eq!(error.input.as_deref(), Some(Path::new("scan.xyz")));
+}
+
+#[test]
+fn cli_json_uses_the_same_representations() {
+    let dir = std::env::temp_dir().join(format!("removebg-serde-cli-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("missing.png");
+    let output = Command::new(env!("CARGO_BIN_EXE_removebg")).arg(&input).arg("--json").output().unwrap();
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let error: ErrorInfo = serde_json::from_value(json["error"].clone()).unwrap();
+    assert_eq!(error, ErrorInfo::from(&RemoveBgError::FileNotFound(input)));
+
+    let input = dir.join("photo.png");
+    removebg::testing::synthetic_image(1, 64, 48).save(&input).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(&input)
+        .args(["--json", "--emit", "mask"])
+        .args(TestModel::Brightness.args())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let subject: Rect = serde_json::from_value(json["subject"].clone()).unwrap();
+    assert!(subject.width > 0 && subject.height > 0);
+    let artifact: Artifact = serde_json::from_value(json["artifacts"][0].clone()).unwrap();
+    assert_eq!((artifact.kind, artifact.error), (OutputKind::Mask, None));
+    assert_eq!(json["artifacts"][0]["success"], true);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
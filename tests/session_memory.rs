@@ -0,0 +1,66 @@
+//! Peak memory of loading a model with and without memory-mapping it.
+//!
+//! Each measurement runs in a fresh process (this test binary, re-run with
+//! a filter), since the peak resident set size of a process only grows. The
+//! test needs the full u2net model, which is downloaded into the model cache
+//! on the first run, so it is ignored by default:
+//!
+//! `cargo test --test session_memory -- --ignored`
+
+#![cfg(all(feature = "ort", target_os = "linux"))]
+
+use removebg::{Backend, BackgroundRemover, Model, RemoveBgOptions, SessionMemoryOptions};
+use std::process::Command;
+
+/// Set in the child process to the `mmap_model` setting to measure.
+const CHILD_MMAP: &str = "REMOVEBG_TEST_CHILD_MMAP";
+
+/// Peak resident set size in kB of a process that loads u2net with
+/// `mmap_model` set to `mmap`.
+fn peak_rss_kb(mmap: bool) -> u64 {
+    let output = Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", "load_model_in_child", "--ignored", "--nocapture", "--test-threads=1"])
+        .env(CHILD_MMAP, if mmap { "1" } else { "0" })
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "child failed: {}{}", stdout, String::from_utf8_lossy(&output.stderr));
+    stdout
+        .lines()
+        .find_map(|line| line.strip_prefix("peak_rss_kb="))
+        .and_then(|kb| kb.trim().parse().ok())
+        .unwrap_or_else(|| panic!("child reported no peak: {}", stdout))
+}
+
+#[test]
+#[ignore = "run by mmap_lowers_peak_rss in a child process"]
+fn load_model_in_child() {
+    let Some(mmap) = std::env::var_os(CHILD_MMAP) else {
+        return;
+    };
+    let options = RemoveBgOptions {
+        model: Model::U2net,
+        backend: Backend::Ort,
+        session_memory: SessionMemoryOptions { mmap_model: mmap == "1", ..Default::default() },
+        ..Default::default()
+    };
+    BackgroundRemover::new(options).expect("u2net loads");
+
+    let status = std::fs::read_to_string("/proc/self/status").unwrap();
+    let peak = status.lines().find_map(|line| line.strip_prefix("VmHWM:")).expect("VmHWM in /proc/self/status");
+    println!("peak_rss_kb={}", peak.trim().trim_end_matches("kB").trim());
+}
+
+#[test]
+#[ignore = "downloads the 176 MB u2net model"]
+fn mmap_lowers_peak_rss() {
+    // Downloads the model first, so neither measurement includes the download
+    let options = RemoveBgOptions { model: Model::U2net, backend: Backend::Ort, ..Default::default() };
+    removebg::prepare_model(&options).expect("u2net is available");
+
+    let mapped = peak_rss_kb(true);
+    let read = peak_rss_kb(false);
+    println!("peak RSS: {} kB mapped, {} kB read", mapped, read);
+    // The model file alone is 176 MB; require a clear margin over noise
+    assert!(mapped + 32 * 1024 < read, "mapping saved only {} kB", read.saturating_sub(mapped));
+}
@@ -0,0 +1,80 @@
+//! `--skip-existing` and `--newer-only` batches: a second run over the same
+//! tree runs no inference, and doesn't even load the model. Runs use the stub
+//! model in `tests/fixtures/models`.
+
+use removebg::batch::{process_directory, BatchConfig, BatchSummary, SkipExisting};
+use removebg::progress::{Progress, ProgressEvent};
+use removebg::{testing::{self, TestModel}, RemoveBgOptions};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// A fresh directory with three test images, named for `test`.
+fn inputs(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("removebg-skip-existing-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("in")).unwrap();
+    for (seed, name) in [(1, "a.png"), (2, "b.png"), (3, "c.png")] {
+        testing::synthetic_image(seed, 64, 48).save(dir.join("in").join(name)).unwrap();
+    }
+    dir
+}
+
+/// Run a batch from `dir/in` to `dir/out` with `options`, and count the
+/// images that were started, i.e. run through the model.
+fn batch(dir: &Path, skip_existing: SkipExisting, options: RemoveBgOptions) -> (BatchSummary, usize) {
+    let started = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&started);
+    let config = BatchConfig {
+        output_dir: Some(dir.join("out")),
+        skip_existing,
+        options,
+        progress: Progress::new(move |event| {
+            if let ProgressEvent::Started { .. } = event {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+        }),
+        ..BatchConfig::new(dir.join("in"))
+    };
+    let summary = process_directory(config).unwrap();
+    (summary, started.load(Ordering::SeqCst))
+}
+
+/// Options whose model can't be loaded: an empty model directory with
+/// downloads turned off, so any inference fails.
+fn without_model(dir: &Path) -> RemoveBgOptions {
+    let mut options = RemoveBgOptions { model_dir: Some(dir.join("no-models")), ..Default::default() };
+    options.download.offline = true;
+    options
+}
+
+#[test]
+fn a_second_run_does_no_inference() {
+    let dir = inputs("rerun");
+    let stub = TestModel::Brightness.options(RemoveBgOptions::default());
+    let (summary, started) = batch(&dir, SkipExisting::Always, stub);
+    assert_eq!((summary.processed, summary.skipped, summary.failed, started), (3, 0, 0, 3));
+
+    for skip in [SkipExisting::Always, SkipExisting::IfNewer] {
+        let (summary, started) = batch(&dir, skip, without_model(&dir));
+        assert_eq!((summary.processed, summary.skipped, summary.failed, started), (0, 3, 0, 0), "{:?}", skip);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn newer_only_redoes_stale_outputs() {
+    let dir = inputs("stale");
+    let stub = TestModel::Brightness.options(RemoveBgOptions::default());
+    batch(&dir, SkipExisting::Always, stub.clone());
+
+    // b.png changed after its cutout was written
+    let later = SystemTime::now() + Duration::from_secs(60);
+    std::fs::File::options().write(true).open(dir.join("in/b.png")).unwrap().set_modified(later).unwrap();
+    let (summary, started) = batch(&dir, SkipExisting::IfNewer, stub);
+    assert_eq!((summary.processed, summary.skipped, summary.failed, started), (1, 2, 0, 1));
+    let (summary, started) = batch(&dir, SkipExisting::Always, without_model(&dir));
+    assert_eq!((summary.processed, summary.skipped, started), (0, 3, 0));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,71 @@
+//! Inputs far below the model's input size: tiny images, single rows and
+//! extreme aspect ratios are processed at their own size, and images under
+//! 2x2 pixels are refused. Runs use the stub model in `tests/fixtures/models`.
+
+use image::{DynamicImage, Rgb, RgbImage};
+use removebg::error::RemoveBgError;
+use removebg::{testing::{self, TestModel}, BackgroundRemover, RemoveBgOptions};
+use std::process::Command;
+
+fn remover() -> BackgroundRemover {
+    let options = TestModel::Brightness.options(RemoveBgOptions::default());
+    BackgroundRemover::new(testing::deterministic(options)).expect("stub model loads")
+}
+
+/// A white square on black, covering the middle half of each side.
+fn square(width: u32, height: u32) -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+        let inside = (width / 4..width - width / 4).contains(&x) && (height / 4..height - height / 4).contains(&y);
+        Rgb(if inside { [255, 255, 255] } else { [0, 0, 0] })
+    }))
+}
+
+#[test]
+fn small_and_narrow_images_keep_their_size() {
+    let remover = remover();
+    for (width, height) in [(2, 2), (47, 963), (963, 47), (48, 48)] {
+        let cutout = remover.process_image(&square(width, height)).unwrap_or_else(|e| panic!("{}x{}: {}", width, height, e));
+        assert_eq!(cutout.dimensions(), (width, height));
+    }
+
+    // The stub's mask is the input's brightness: the square is kept and the
+    // border dropped, without the ringing of Lanczos around the edge
+    let cutout = remover.process_image(&square(48, 48)).unwrap();
+    assert_eq!(cutout.get_pixel(24, 24)[3], 255);
+    assert_eq!(cutout.get_pixel(1, 1)[3], 0);
+    let border = (0..48).flat_map(|i| [(i, 0), (0, i), (i, 47), (47, i)]);
+    assert!(border.into_iter().all(|(x, y)| cutout.get_pixel(x, y)[3] == 0));
+    let cutout = remover.process_image(&square(47, 963)).unwrap();
+    assert_eq!(cutout.get_pixel(23, 480)[3], 255);
+    assert_eq!(cutout.get_pixel(23, 5)[3], 0);
+}
+
+#[test]
+fn images_below_two_pixels_are_refused() {
+    let remover = remover();
+    for (width, height) in [(1, 1), (1, 40), (40, 1)] {
+        let error = remover.process_image(&square(width, height)).unwrap_err();
+        assert!(matches!(error.root(), RemoveBgError::ImageTooSmall { .. }), "{:?}", error);
+        assert_eq!(error.exit_code(), 2);
+        assert!(error.to_string().contains(&format!("{}x{} is below the minimum of 2x2", width, height)), "{}", error);
+    }
+    // Batches fail only the image that is too small
+    let options = RemoveBgOptions { batch_size: 4, ..remover.options().clone() };
+    let results = BackgroundRemover::new(options).unwrap().process_images(&[square(1, 1), square(32, 32)]);
+    assert!(matches!(results[0].as_ref().map_err(|e| e.root()), Err(RemoveBgError::ImageTooSmall { width: 1, height: 1 })));
+    assert_eq!(results[1].as_ref().unwrap().dimensions(), (32, 32));
+
+    let dir = std::env::temp_dir().join(format!("removebg-small-images-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("pixel.png");
+    square(1, 1).save(&input).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(&input)
+        .args(TestModel::Brightness.args())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Image is too small: 1x1"), "{:?}", output);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
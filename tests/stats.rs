@@ -0,0 +1,112 @@
+//! Alpha quality metrics (`AlphaStats`): measured from existing cutouts by
+//! `cutout_stats` and `removebg stats`, and reported for new ones with
+//! `RemoveBgOptions::alpha_stats`, `analyze` and `--json`.
+
+use image::{ImageBuffer, Luma, Rgb, RgbImage, Rgba, RgbaImage};
+use removebg::{alpha_stats, analyze, cutout_stats, remove_background_detailed, testing::{self, TestModel}, RemoveBgError, RemoveBgOptions};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A fresh directory named for `test`.
+fn workspace(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("removebg-stats-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A 100x100 cutout of a hard-edged 20x10 rectangle.
+fn rectangle(x: u32, y: u32) -> u8 {
+    if (40..60).contains(&x) && (45..55).contains(&y) {
+        255
+    } else {
+        0
+    }
+}
+
+#[test]
+fn existing_cutouts_are_measured_from_their_alpha() {
+    let dir = workspace("library");
+    let cutout = dir.join("cutout.png");
+    RgbaImage::from_fn(100, 100, |x, y| Rgba([90, 120, 200, rectangle(x, y)])).save(&cutout).unwrap();
+    let stats = cutout_stats(&cutout).unwrap();
+    assert_eq!(stats, alpha_stats(&ImageBuffer::from_fn(100, 100, |x, y| Luma([rectangle(x, y)]))));
+    assert_eq!((stats.histogram[0], stats.histogram[15]), (9800, 200));
+    assert_eq!((stats.edge_length, stats.edge_softness, stats.components, stats.specks), (60, 0.0, 1, 0));
+
+    // 16-bit cutouts at full depth
+    let deep = dir.join("deep.png");
+    ImageBuffer::from_fn(100, 100, |x, y| Rgba([0u16, 0, 0, rectangle(x, y) as u16 * 257])).save(&deep).unwrap();
+    assert_eq!(cutout_stats(&deep).unwrap(), stats);
+
+    let opaque = dir.join("photo.jpg");
+    RgbImage::from_pixel(20, 20, Rgb([200, 200, 200])).save(&opaque).unwrap();
+    let error = cutout_stats(&opaque).unwrap_err();
+    assert!(matches!(error, RemoveBgError::InvalidMask(_)), "{:?}", error);
+    assert!(error.to_string().contains("has no alpha channel"), "{}", error);
+    assert_eq!(error.exit_code(), 2);
+    assert!(matches!(cutout_stats(dir.join("missing.png")).unwrap_err(), RemoveBgError::FileNotFound(_)));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn stats_subcommand_prints_the_metrics() {
+    let dir = workspace("cli");
+    let cutout = dir.join("cutout.png");
+    RgbaImage::from_fn(100, 100, |x, y| Rgba([90, 120, 200, rectangle(x, y)])).save(&cutout).unwrap();
+
+    let run = |args: &[&str], path: &Path| Command::new(env!("CARGO_BIN_EXE_removebg")).arg("stats").arg(path).args(args).output().unwrap();
+    let output = run(&["--json"], &cutout);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["success"], true);
+    let stats = &json["alpha_stats"];
+    assert_eq!(stats["histogram"][0], 9800);
+    assert_eq!(stats["histogram"][15], 200);
+    assert_eq!(stats["ambiguous"], 0.0);
+    assert_eq!(stats["edge_length"], 60);
+    assert_eq!(stats["edge_softness"], 0.0);
+    assert_eq!((&stats["components"], &stats["specks"], &stats["min_component_area"]), (&1.into(), &0.into(), &10.into()));
+
+    let output = run(&[], &cutout);
+    let table = String::from_utf8_lossy(&output.stdout);
+    assert!(table.contains("Edge length: 60 px"), "{}", table);
+    assert!(table.contains("Components: 1 (0 specks under 10 px)"), "{}", table);
+
+    let opaque = dir.join("photo.jpg");
+    RgbImage::from_pixel(20, 20, Rgb([200, 200, 200])).save(&opaque).unwrap();
+    let output = run(&["--json"], &opaque);
+    assert_eq!(output.status.code(), Some(2));
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["error"]["kind"], "invalid_mask");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn new_cutouts_report_the_metrics_on_request() {
+    let dir = workspace("report");
+    let photo = dir.join("photo.png");
+    testing::synthetic_image(9, 120, 80).save(&photo).unwrap();
+    let options = testing::deterministic(TestModel::Brightness.options(RemoveBgOptions { overwrite: true, ..Default::default() }));
+
+    let report = remove_background_detailed(&photo, None, &options).unwrap();
+    assert_eq!(report.alpha_stats, None);
+    let measured = RemoveBgOptions { alpha_stats: true, ..options.clone() };
+    let report = remove_background_detailed(&photo, None, &measured).unwrap();
+    let stats = report.alpha_stats.expect("measured");
+    assert_eq!(stats.histogram.iter().sum::<u64>(), 120 * 80);
+    assert_eq!(analyze(&photo, 0.5, &options).unwrap().alpha_stats, stats);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(&photo)
+        .args(["--json", "--force"])
+        .args(TestModel::Brightness.args())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let histogram: Vec<u64> = serde_json::from_value(json["alpha_stats"]["histogram"].clone()).unwrap();
+    assert_eq!(histogram.iter().sum::<u64>(), 120 * 80);
+    assert!(json["alpha_stats"]["edge_softness"].is_number());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
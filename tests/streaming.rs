@@ -0,0 +1,159 @@
+//! Streamed encoding (`remove_background_to_writer`): the same output as
+//! the file and bytes APIs, and a lower peak of heap memory than encoding the
+//! whole PNG in memory next to the decoded input. Allocations are counted
+//! by a global allocator, so the tests in this file run one at a time.
+
+use image::codecs::png::PngEncoder;
+use image::{DynamicImage, GenericImageView, ImageBuffer, Luma, Rgb, RgbImage};
+use removebg::core::Gray16Image;
+use removebg::{BackgroundRemover, OutputFormat, RemoveBgOptions, Result, SegmentationModel};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Tracks the bytes allocated now and the most allocated at once.
+struct Counting;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let now = CURRENT.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        PEAK.fetch_max(now, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if new_size > layout.size() {
+            let now = CURRENT.fetch_add(new_size - layout.size(), Ordering::Relaxed) + new_size - layout.size();
+            PEAK.fetch_max(now, Ordering::Relaxed);
+        } else {
+            CURRENT.fetch_sub(layout.size() - new_size, Ordering::Relaxed);
+        }
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+static SERIAL: Mutex<()> = Mutex::new(());
+
+/// Most bytes allocated at once while `f` runs, beyond those allocated
+/// before it.
+fn peak_during(f: impl FnOnce()) -> usize {
+    let base = CURRENT.load(Ordering::Relaxed);
+    PEAK.store(base, Ordering::Relaxed);
+    f();
+    PEAK.load(Ordering::Relaxed) - base
+}
+
+/// Keeps a soft-edged disc in the middle of the image.
+struct DiscModel;
+
+impl SegmentationModel for DiscModel {
+    fn predict_mask(&self, image: &DynamicImage) -> Result<Gray16Image> {
+        let (width, height) = image.dimensions();
+        let radius = width.min(height) as f32 * 0.45;
+        Ok(ImageBuffer::from_fn(width, height, |x, y| {
+            let distance = (x as f32 - width as f32 / 2.0).hypot(y as f32 - height as f32 / 2.0);
+            Luma([(((radius + 4.0 - distance) / 8.0).clamp(0.0, 1.0) * 65535.0) as u16])
+        }))
+    }
+}
+
+fn remover(format: OutputFormat) -> BackgroundRemover {
+    BackgroundRemover::with_model(DiscModel, RemoveBgOptions { format: Some(format), ..Default::default() })
+}
+
+/// Noise, which PNG can't compress, so the encoded image is about as large
+/// as the decoded one.
+fn noise(width: u32, height: u32) -> RgbImage {
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    RgbImage::from_fn(width, height, |_, _| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        let [r, g, b, ..] = state.to_le_bytes();
+        Rgb([r, g, b])
+    })
+}
+
+/// A fresh directory named for `test`.
+fn temp_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("removebg-streaming-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn streamed(remover: &BackgroundRemover, input: &Path) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    remover.process_to_writer(input, &mut encoded).unwrap();
+    encoded
+}
+
+#[test]
+fn streamed_output_matches_the_file_and_bytes_apis() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = temp_dir("matches");
+    let input = dir.join("photo.png");
+    noise(97, 61).save(&input).unwrap();
+    // 16-bit input stays 16-bit, with the samples written big-endian
+    let deep = dir.join("deep.png");
+    let gradient = |x: u32, y: u32| Rgb([x as u16 * 1601, y as u16 * 2111, 40_000]);
+    ImageBuffer::<Rgb<u16>, Vec<u16>>::from_fn(40, 30, gradient).save(&deep).unwrap();
+
+    for input in [&input, &deep] {
+        for format in [OutputFormat::Png, OutputFormat::Tiff, OutputFormat::WebP] {
+            let remover = remover(format);
+            let encoded = streamed(&remover, input);
+            assert_eq!(encoded, remover.process_bytes(&std::fs::read(input).unwrap()).unwrap(), "{:?}", format);
+
+            let report = remover.process_file(input, None).unwrap();
+            let saved = image::open(&report.output_path).unwrap();
+            let decoded = image::load_from_memory(&encoded).unwrap();
+            assert_eq!(decoded.color(), saved.color());
+            assert_eq!(decoded.as_bytes(), saved.as_bytes());
+            std::fs::remove_file(&report.output_path).unwrap();
+        }
+    }
+    let remover = remover(OutputFormat::Png);
+    assert_eq!(image::load_from_memory(&streamed(&remover, &deep)).unwrap().color(), image::ColorType::Rgba16);
+
+    // Errors name the input, like the file API's
+    let missing = dir.join("missing.png");
+    let error = remover.process_to_writer(&missing, &mut Vec::new()).unwrap_err();
+    assert!(error.to_string().contains("missing.png"), "{}", error);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn streaming_lowers_peak_memory() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = temp_dir("peak");
+    let input = dir.join("panorama.png");
+    noise(1600, 1000).save(&input).unwrap();
+    let remover = remover(OutputFormat::Png);
+
+    // The decoded input, the cutout and the whole encoded PNG at once
+    let buffered = peak_during(|| {
+        let image = image::open(&input).unwrap();
+        let cutout = remover.process_image(&image).unwrap();
+        let mut encoded = Vec::new();
+        cutout.write_with_encoder(PngEncoder::new(&mut encoded)).unwrap();
+        assert!(encoded.len() > 4_000_000);
+    });
+    let streamed = peak_during(|| remover.process_to_writer(&input, &mut std::io::sink()).unwrap());
+    eprintln!("peak heap: {} bytes buffered, {} bytes streamed", buffered, streamed);
+    // Compositing holds the input, mask and cutout at once, which now sets the peak
+    assert!((streamed as f64) < buffered as f64 * 0.85, "{} streamed vs {} buffered", streamed, buffered);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
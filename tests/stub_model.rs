@@ -0,0 +1,120 @@
+//! End-to-end runs on the center blob stub in `tests/fixtures/models/blob`
+//! (see `make_stub.py` there): the CLI, batches, the in-memory API and the
+//! errors of a broken model, with nothing downloaded. The stub's mask is a
+//! soft blob in the middle of the image, so cutouts keep the center and drop
+//! the corners, and brighter images keep a larger blob.
+
+use image::{DynamicImage, Rgb, RgbImage, RgbaImage};
+use removebg::batch::{process_directory, BatchConfig};
+use removebg::testing::{self, TestModel};
+use removebg::{remove_background_from_bytes, BackgroundRemover, RemoveBgError, RemoveBgOptions};
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn temp_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("removebg-stub-model-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn png(image: RgbImage) -> Vec<u8> {
+    let mut png = Cursor::new(Vec::new());
+    DynamicImage::ImageRgb8(image).write_to(&mut png, image::ImageFormat::Png).unwrap();
+    png.into_inner()
+}
+
+/// Assert that `cutout` keeps its center and drops its corners.
+#[track_caller]
+fn assert_blob(cutout: &RgbaImage) {
+    let (width, height) = cutout.dimensions();
+    assert!(cutout.get_pixel(width / 2, height / 2)[3] > 128, "center alpha {}", cutout.get_pixel(width / 2, height / 2)[3]);
+    for (x, y) in [(0, 0), (width - 1, 0), (0, height - 1), (width - 1, height - 1)] {
+        assert!(cutout.get_pixel(x, y)[3] < 32, "corner ({}, {}) alpha {}", x, y, cutout.get_pixel(x, y)[3]);
+    }
+}
+
+fn kept(cutout: &RgbaImage) -> usize {
+    cutout.pixels().filter(|pixel| pixel[3] > 128).count()
+}
+
+#[test]
+fn the_bytes_api_keeps_the_blob_and_follows_the_content() {
+    let options = testing::deterministic(TestModel::CenterBlob.options(RemoveBgOptions::default()));
+    let cutout = |image: RgbImage| {
+        let png = remove_background_from_bytes(&png(image), &options).expect("stub model runs");
+        image::load_from_memory(&png).unwrap().into_rgba8()
+    };
+
+    let photo = cutout(testing::synthetic_image(3, 120, 90));
+    assert_eq!(photo.dimensions(), (120, 90));
+    assert_blob(&photo);
+
+    // The input reaches the model normalized: a white image keeps more than a black one
+    let (white, black) = (cutout(RgbImage::from_pixel(120, 90, Rgb([255; 3]))), cutout(RgbImage::new(120, 90)));
+    assert_blob(&white);
+    assert_blob(&black);
+    assert!(kept(&white) > kept(&black), "white keeps {}, black {}", kept(&white), kept(&black));
+}
+
+#[test]
+fn the_cli_writes_the_cutout() {
+    let dir = temp_dir("cli");
+    let (input, output) = (dir.join("photo.jpg"), dir.join("cutout.png"));
+    testing::synthetic_image(5, 160, 120).save(&input).unwrap();
+
+    let run = Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(&input)
+        .arg("-o")
+        .arg(&output)
+        .args(TestModel::CenterBlob.args())
+        .output()
+        .expect("removebg runs");
+    assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+    let cutout = image::open(&output).unwrap().into_rgba8();
+    assert_eq!(cutout.dimensions(), (160, 120));
+    assert_blob(&cutout);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn batches_cut_out_every_image() {
+    let dir = temp_dir("batch");
+    for seed in 0..3 {
+        testing::synthetic_image(seed, 96, 64).save(dir.join(format!("{}.png", seed))).unwrap();
+    }
+
+    let options = TestModel::CenterBlob.options(RemoveBgOptions::default());
+    let summary = process_directory(BatchConfig { options, ..BatchConfig::new(&dir) }).unwrap();
+    assert_eq!((summary.processed, summary.failed), (3, 0));
+    for seed in 0..3 {
+        assert_blob(&image::open(dir.join(format!("{}_nobg.png", seed))).unwrap().into_rgba8());
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_broken_model_is_a_model_init_error() {
+    let dir = temp_dir("broken");
+    let models = dir.join("models");
+    std::fs::create_dir_all(&models).unwrap();
+    std::fs::write(models.join("u2netp.onnx"), b"not a model").unwrap();
+    let input = dir.join("photo.png");
+    testing::synthetic_image(1, 48, 32).save(&input).unwrap();
+
+    let options = RemoveBgOptions { model_dir: Some(models.clone()), ..TestModel::CenterBlob.options(RemoveBgOptions::default()) };
+    assert!(matches!(BackgroundRemover::new(options), Err(RemoveBgError::ModelInitError(_))));
+
+    let run = Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(&input)
+        .args(["--json", "--model", "u2netp", "--model-dir"])
+        .arg(&models)
+        .output()
+        .expect("removebg runs");
+    assert_eq!(run.status.code(), Some(9));
+    let json: serde_json::Value = serde_json::from_slice(&run.stdout).unwrap();
+    assert_eq!(json["error"]["kind"], "model_init_error");
+    assert!(!dir.join("photo_nobg.png").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
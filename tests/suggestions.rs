@@ -0,0 +1,45 @@
+//! Suggestions for common command line mistakes: the hint is printed, and
+//! the exit code is the one the mistake gets without it. Runs use the stub
+//! model in `tests/fixtures/models`.
+
+use removebg::testing::{self, TestModel};
+use std::process::{Command, Output};
+
+fn removebg(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .args(args)
+        .args(TestModel::Brightness.args())
+        .output()
+        .expect("removebg runs")
+}
+
+#[test]
+fn swapped_input_and_output_get_a_hint_and_the_usual_exit_code() {
+    let dir = std::env::temp_dir().join(format!("removebg-suggestions-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let (photo, result) = (dir.join("photo.png"), dir.join("result.png"));
+    testing::synthetic_image(1, 80, 60).save(&photo).unwrap();
+    let (photo, result) = (photo.to_str().unwrap(), result.to_str().unwrap());
+
+    let swapped = removebg(&[result, "-o", photo]);
+    let stderr = String::from_utf8_lossy(&swapped.stderr);
+    let hint = format!("Hint: input '{}' doesn't exist, but '{}' does; did you mean `removebg {} -o {}`?", result, photo, photo, result);
+    assert!(stderr.contains(&hint), "{}", stderr);
+
+    // The same missing input without the existing output: same code, no hint
+    let missing = removebg(&[result, "-o", dir.join("other.png").to_str().unwrap()]);
+    assert!(!String::from_utf8_lossy(&missing.stderr).contains("Hint:"));
+    assert_eq!(swapped.status.code(), missing.status.code());
+    assert!(!swapped.status.success());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn unknown_flags_suggest_the_flag_they_stand_for() {
+    let output = removebg(&["photo.png", "--bg", "white"]);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("unexpected argument '--bg'"), "{}", stderr);
+    assert!(stderr.contains("'--bg-color'"), "{}", stderr);
+}
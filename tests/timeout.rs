@@ -0,0 +1,66 @@
+//! Time budgets (`RemoveBgOptions::timeout`, `--timeout`): an input that
+//! runs out of time fails with `TimedOut` naming the stage it stopped in and
+//! leaves no output, and a generous budget changes nothing.
+
+use removebg::{remove_background_detailed, testing::{self, TestModel}, BackgroundRemover, RemoveBgError, RemoveBgOptions};
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::Duration;
+
+/// A fresh directory with a large test image, named for `test`.
+fn input(test: &str) -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("removebg-timeout-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("large.png");
+    testing::synthetic_image(7, 2400, 1600).save(&path).unwrap();
+    (dir, path)
+}
+
+fn options(timeout: Duration) -> RemoveBgOptions {
+    testing::deterministic(TestModel::Brightness.options(RemoveBgOptions { timeout: Some(timeout), ..Default::default() }))
+}
+
+#[test]
+fn timed_out_inputs_leave_no_output() {
+    let (dir, photo) = input("library");
+    let error = remove_background_detailed(&photo, None, &options(Duration::from_nanos(1))).unwrap_err();
+    let RemoveBgError::TimedOut { elapsed, stage } = error.root() else { panic!("{:?}", error) };
+    assert!(*elapsed >= Duration::from_nanos(1));
+    assert!(stage.is_some(), "{:?}", error);
+    assert_eq!(error.exit_code(), 8);
+    assert!(error.to_string().starts_with("Timed out after"), "{}", error);
+    let leftovers: Vec<_> = std::fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+    assert_eq!(leftovers, ["large.png"]);
+
+    // The budget starts again for each input
+    let remover = BackgroundRemover::new(options(Duration::from_secs(600))).unwrap();
+    let image = image::open(&photo).unwrap();
+    assert!(remover.process_image(&image).is_ok());
+    let report = remove_background_detailed(&photo, None, &options(Duration::from_secs(600))).unwrap();
+    assert_eq!(report.output_path, dir.join("large_nobg.png"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cli_exits_with_the_timeout_code() {
+    let (dir, photo) = input("cli");
+    let run = Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(&photo)
+        .args(["--timeout", "1ms"])
+        .args(TestModel::Brightness.args())
+        .output()
+        .expect("removebg runs");
+    assert_eq!(run.status.code(), Some(8), "{}", String::from_utf8_lossy(&run.stderr));
+    assert!(String::from_utf8_lossy(&run.stderr).contains("Timed out after"));
+    assert!(!dir.join("large_nobg.png").exists());
+
+    let invalid = Command::new(env!("CARGO_BIN_EXE_removebg"))
+        .arg(&photo)
+        .args(["--timeout", "soon"])
+        .output()
+        .expect("removebg runs");
+    assert_eq!(invalid.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&invalid.stderr).contains("invalid duration 'soon'"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
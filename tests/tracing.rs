@@ -0,0 +1,141 @@
+//! Tracing spans and events (`tracing` feature): one run of the pipeline,
+//! captured by a subscriber that records each span's parent and fields.
+#![cfg(feature = "tracing")]
+
+use removebg::{remove_background_detailed, testing::{self, TestModel}, MaskCacheOptions, RemoveBgOptions};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+/// A span or event: its name, the name of the span it happened in, and its
+/// fields as strings.
+#[derive(Debug, Clone)]
+struct Captured {
+    name: &'static str,
+    parent: Option<&'static str>,
+    fields: BTreeMap<&'static str, String>,
+}
+
+impl Visit for Captured {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.fields.insert(field.name(), format!("{:?}", value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.fields.insert(field.name(), value.to_string());
+    }
+}
+
+#[derive(Default)]
+struct Capture {
+    spans: Vec<Captured>,
+    events: Vec<Captured>,
+    stack: Vec<usize>,
+}
+
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<Capture>>);
+
+impl Recorder {
+    fn new_record(capture: &Capture, metadata: &Metadata<'_>) -> Captured {
+        let parent = capture.stack.last().map(|&index| capture.spans[index].name);
+        Captured { name: metadata.name(), parent, fields: BTreeMap::new() }
+    }
+
+    /// The spans recorded so far, and the events.
+    fn take(&self) -> (Vec<Captured>, Vec<Captured>) {
+        let mut capture = self.0.lock().unwrap();
+        (std::mem::take(&mut capture.spans), std::mem::take(&mut capture.events))
+    }
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.target() == "removebg"
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let mut capture = self.0.lock().unwrap();
+        let mut record = Recorder::new_record(&capture, span.metadata());
+        span.record(&mut record);
+        capture.spans.push(record);
+        Id::from_u64(capture.spans.len() as u64)
+    }
+
+    fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut capture = self.0.lock().unwrap();
+        let mut record = Recorder::new_record(&capture, event.metadata());
+        event.record(&mut record);
+        capture.events.push(record);
+    }
+
+    fn enter(&self, span: &Id) {
+        self.0.lock().unwrap().stack.push(span.into_u64() as usize - 1);
+    }
+
+    fn exit(&self, _span: &Id) {
+        self.0.lock().unwrap().stack.pop();
+    }
+}
+
+fn find<'a>(records: &'a [Captured], name: &str) -> &'a Captured {
+    records.iter().find(|record| record.name == name).unwrap_or_else(|| panic!("no {} in {:#?}", name, records))
+}
+
+#[test]
+fn one_run_is_a_tree_of_stage_spans() {
+    let dir = std::env::temp_dir().join(format!("removebg-tracing-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let photo = dir.join("photo.png");
+    testing::synthetic_image(5, 120, 80).save(&photo).unwrap();
+    let options = testing::deterministic(TestModel::Brightness.options(RemoveBgOptions {
+        mask_cache: Some(MaskCacheOptions::new(dir.join("cache"))),
+        overwrite: true,
+        ..Default::default()
+    }));
+
+    let recorder = Recorder::default();
+    let report = tracing::subscriber::with_default(recorder.clone(), || remove_background_detailed(&photo, None, &options)).unwrap();
+    let (spans, events) = recorder.take();
+
+    let names: Vec<_> = spans.iter().map(|span| span.name).collect();
+    assert_eq!(names, ["process", "decode", "model_init", "preprocess", "inference", "postprocess", "encode"]);
+    let process = find(&spans, "process");
+    assert_eq!((process.parent, &process.fields["input"]), (None, &photo.display().to_string()));
+    for span in &spans[1..] {
+        assert_eq!(span.parent, Some("process"), "{:?}", span);
+    }
+
+    let model_init = find(&spans, "model_init");
+    assert_eq!((model_init.fields["model"].as_str(), model_init.fields["backend"].as_str()), ("u2netp", "tract"));
+    assert!(model_init.fields.contains_key("device"));
+    let preprocess = find(&spans, "preprocess");
+    assert_eq!((preprocess.fields["width"].as_str(), preprocess.fields["height"].as_str()), ("120", "80"));
+    assert_eq!(preprocess.fields["input_size"], "320");
+    let inference = find(&spans, "inference");
+    assert_eq!((inference.fields["model"].as_str(), inference.fields["batch"].as_str()), ("u2netp", "1"));
+    assert!(find(&spans, "encode").fields.contains_key("format"));
+
+    let decoded = find(&events, "decoded");
+    assert_eq!((decoded.parent, decoded.fields["width"].as_str(), decoded.fields["height"].as_str()), (Some("decode"), "120", "80"));
+    let encoded = find(&events, "encoded");
+    assert_eq!(encoded.parent, Some("encode"));
+    assert_eq!(encoded.fields["bytes"], std::fs::metadata(&report.output_path).unwrap().len().to_string());
+    assert!(events.iter().all(|event| event.name != "mask_cache_hit"));
+
+    // The second run takes the mask from the cache instead of the model
+    tracing::subscriber::with_default(recorder.clone(), || remove_background_detailed(&photo, None, &options)).unwrap();
+    let (spans, events) = recorder.take();
+    assert!(spans.iter().all(|span| span.name != "inference"), "{:#?}", spans);
+    let hit = find(&events, "mask_cache_hit");
+    assert_eq!((hit.parent, hit.fields["model"].as_str()), (Some("process"), "u2netp"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
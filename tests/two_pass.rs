@@ -0,0 +1,81 @@
+//! Two-pass inference (`RemoveBgOptions::two_pass`, `--two-pass`) on a small
+//! subject in a large scene, with a model that only sees a coarse grid.
+
+use image::imageops::FilterType;
+use image::{DynamicImage, GrayImage, Luma, Rgb, RgbImage};
+use removebg::core::Gray16Image;
+use removebg::{BackgroundRemover, RemoveBgOptions, Result, SegmentationModel};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Finds red pixels on a 24x24 copy of the image, like a model whose input
+/// is far smaller than the photo, so small subjects come out as blobs.
+struct CoarseModel;
+
+impl SegmentationModel for CoarseModel {
+    fn predict_mask(&self, image: &DynamicImage) -> Result<Gray16Image> {
+        let small = image.resize_exact(24, 24, FilterType::Triangle).to_rgb8();
+        let mask = GrayImage::from_fn(24, 24, |x, y| {
+            let [r, g, _] = small.get_pixel(x, y).0;
+            Luma([(r.saturating_sub(g) as u32 * 255 / 160).min(255) as u8])
+        });
+        let mask = image::imageops::resize(&mask, image.width(), image.height(), FilterType::Triangle);
+        Ok(Gray16Image::from_fn(image.width(), image.height(), |x, y| Luma([mask.get_pixel(x, y)[0] as u16 * 257])))
+    }
+}
+
+/// A gray `width`x`height` scene with a red disc of `radius` at (`cx`, `cy`),
+/// and the disc's true mask.
+fn scene(width: u32, height: u32, (cx, cy): (f32, f32), radius: f32) -> (DynamicImage, GrayImage) {
+    let inside = |x: u32, y: u32| (x as f32 + 0.5 - cx).hypot(y as f32 + 0.5 - cy) <= radius;
+    let image = RgbImage::from_fn(width, height, |x, y| if inside(x, y) { Rgb([220, 40, 40]) } else { Rgb([90, 90, 90]) });
+    let truth = GrayImage::from_fn(width, height, |x, y| Luma([if inside(x, y) { 255 } else { 0 }]));
+    (DynamicImage::ImageRgb8(image), truth)
+}
+
+/// Intersection over union of the pixels at or above half alpha.
+fn iou(mask: &GrayImage, truth: &GrayImage) -> f32 {
+    let (mut both, mut either) = (0u32, 0u32);
+    for (a, b) in mask.pixels().zip(truth.pixels()) {
+        let (a, b) = (a[0] >= 128, b[0] >= 128);
+        both += (a && b) as u32;
+        either += (a || b) as u32;
+    }
+    both as f32 / either.max(1) as f32
+}
+
+fn remover(two_pass: bool) -> BackgroundRemover {
+    BackgroundRemover::with_model(CoarseModel, RemoveBgOptions { two_pass, ..Default::default() })
+}
+
+#[test]
+fn small_subjects_match_their_shape_better() {
+    let (image, truth) = scene(480, 360, (300.0, 120.0), 14.0);
+    let single = iou(&remover(false).mask(&image).unwrap(), &truth);
+    let double = iou(&remover(true).mask(&image).unwrap(), &truth);
+    assert!(double > single + 0.1, "two-pass IoU {} vs single-pass {}", double, single);
+    assert!(double > 0.8, "{}", double);
+}
+
+#[test]
+fn second_pass_is_timed_and_skipped_when_it_cannot_help() {
+    let dir = std::env::temp_dir().join(format!("removebg-two-pass-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let run = |name: &str, image: DynamicImage, two_pass: bool| -> Duration {
+        let input: PathBuf = dir.join(format!("{}.png", name));
+        image.save(&input).unwrap();
+        let report = remover(two_pass).process_file(&input, Some(&dir.join(format!("{}_out.png", name)))).unwrap();
+        report.durations.second_pass
+    };
+
+    let (small, _) = scene(480, 360, (300.0, 120.0), 14.0);
+    assert!(run("small", small.clone(), true) > Duration::ZERO);
+    assert_eq!(run("single", small, false), Duration::ZERO);
+    // Nothing to crop to, and a subject filling the frame
+    let (empty, _) = scene(480, 360, (0.0, 0.0), 0.0);
+    assert_eq!(run("empty", empty, true), Duration::ZERO);
+    let (large, _) = scene(480, 360, (240.0, 180.0), 170.0);
+    assert_eq!(run("large", large, true), Duration::ZERO);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
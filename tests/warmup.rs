@@ -0,0 +1,56 @@
+//! Warm-up runs after loading the model (`RemoveBgOptions::warmup`): the
+//! first image is about as fast as the second, and the warm-up time is
+//! reported on its own. Runs use the stub model in `tests/fixtures/models`.
+
+use removebg::{remove_background_detailed, testing::{self, TestModel}, BackgroundRemover, RemoveBgOptions};
+use std::path::PathBuf;
+use std::time::Duration;
+
+fn options() -> RemoveBgOptions {
+    testing::deterministic(TestModel::Brightness.options(RemoveBgOptions { warmup: true, overwrite: true, ..Default::default() }))
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("removebg-warmup-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn first_image_is_about_as_fast_as_the_second() {
+    let dir = temp_dir("latency");
+    let remover = BackgroundRemover::new(options()).expect("stub model loads");
+    assert!(remover.warmup_duration() > Duration::ZERO);
+
+    let mut reports = Vec::new();
+    for seed in 0..2 {
+        let input = dir.join(format!("{}.png", seed));
+        testing::synthetic_image(seed, 640, 480).save(&input).unwrap();
+        reports.push(remover.process_file(&input, None).unwrap());
+    }
+    let (first, second) = (reports[0].durations, reports[1].durations);
+    // The model was loaded before either call
+    assert_eq!(first.warmup, Duration::ZERO);
+    // A few milliseconds of slack keep scheduling noise from failing the run
+    let limit = second.inference.mul_f64(1.5) + Duration::from_millis(5);
+    assert!(first.inference <= limit, "first {:?}, second {:?}", first.inference, second.inference);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn warmup_is_reported_by_the_call_that_loads_the_model() {
+    let dir = temp_dir("report");
+    let input = dir.join("a.png");
+    testing::synthetic_image(3, 160, 120).save(&input).unwrap();
+
+    let first = remove_background_detailed(&input, None, &options()).unwrap();
+    assert!(first.durations.warmup > Duration::ZERO);
+    assert!(first.durations.model_load + first.durations.warmup <= first.durations.total);
+    let second = remove_background_detailed(&input, None, &options()).unwrap();
+    assert_eq!(second.durations.warmup, Duration::ZERO);
+
+    let cold = RemoveBgOptions { warmup: false, intra_threads: Some(1), ..options() };
+    let report = remove_background_detailed(&input, None, &cold).unwrap();
+    assert_eq!(report.durations.warmup, Duration::ZERO);
+    std::fs::remove_dir_all(&dir).unwrap();
+}